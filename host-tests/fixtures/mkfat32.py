@@ -0,0 +1,197 @@
+#!/usr/bin/env python3
+# Builds a small FAT32 image from a directory tree:
+#
+#   mkfat32.py OUT.img TREE [SECTORS]
+#
+# 512-byte sectors, one sector per cluster, root directory in cluster 2.
+# That is far below the 65525-cluster minimum real formatters insist on,
+# but structurally FAT32, which is what the driver parses. Names that are
+# not valid upper-case 8.3 get long-name entries. Files of three or more
+# clusters are deliberately fragmented so cluster chains are exercised.
+# Output is deterministic: entries are sorted and timestamps fixed.
+
+import os
+import struct
+import sys
+
+SECTOR = 512
+RESERVED = 32
+NUM_FATS = 2
+# 2000-01-01 00:00:00 in FAT date/time encoding
+FAT_DATE = ((2000 - 1980) << 9) | (1 << 5) | 1
+FAT_TIME = 0
+EOC = 0x0FFFFFFF
+SHORT_CHARS = set(b'ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!#$%&\'()-@^_`{}~')
+
+
+class Image:
+    def __init__(self, total_sectors):
+        self.total = total_sectors
+        # One FAT entry per sector is an upper bound on the cluster count.
+        self.fat_sectors = ((total_sectors + 2) * 4 + SECTOR - 1) // SECTOR
+        self.first_data = RESERVED + NUM_FATS * self.fat_sectors
+        self.max_cluster = total_sectors - self.first_data + 1
+        self.fat = [0x0FFFFFF8, EOC]
+        self.data = {}
+
+    def free_cluster(self):
+        self.fat.append(0)
+        if len(self.fat) - 1 > self.max_cluster:
+            sys.exit('mkfat32: image too small')
+        return len(self.fat) - 1
+
+    def chain(self, count, fragment=False):
+        clusters = []
+        for _ in range(count):
+            clusters.append(self.free_cluster())
+            if fragment and len(clusters) < count:
+                self.free_cluster()  # leave a hole in the chain
+        for a, b in zip(clusters, clusters[1:]):
+            self.fat[a] = b
+        self.fat[clusters[-1]] = EOC
+        return clusters
+
+    def store(self, clusters, payload):
+        for i, cluster in enumerate(clusters):
+            self.data[cluster] = payload[i * SECTOR:(i + 1) * SECTOR]
+
+
+def is_short(name):
+    base, _, ext = name.partition('.')
+    raw = (base + ext).encode()
+    return (0 < len(base) <= 8 and len(ext) <= 3 and name.count('.') <= 1
+            and all(c in SHORT_CHARS for c in raw))
+
+
+def pack_short(name):
+    base, _, ext = name.partition('.')
+    return base.encode().ljust(8) + ext.encode().ljust(3)
+
+
+def short_alias(name, taken):
+    base, _, ext = name.upper().rpartition('.') if '.' in name else (name.upper(), '', '')
+    clean = lambda s: bytes(c for c in s.encode() if c in SHORT_CHARS)
+    base, ext = clean(base) or b'_', clean(ext)[:3]
+    for n in range(1, 100):
+        tail = b'~%d' % n
+        alias = (base[:8 - len(tail)] + tail).ljust(8) + ext.ljust(3)
+        if alias not in taken:
+            return alias
+    sys.exit('mkfat32: too many aliases for ' + name)
+
+
+def short_entry(name11, attr, cluster, size):
+    return struct.pack('<11sBBBHHHHHHHI', name11, attr, 0, 0, FAT_TIME, FAT_DATE, FAT_DATE,
+                       cluster >> 16, FAT_TIME, FAT_DATE, cluster & 0xFFFF, size)
+
+
+def lfn_entries(long_name, name11):
+    checksum = 0
+    for b in name11:
+        checksum = (((checksum & 1) << 7) + (checksum >> 1) + b) & 0xFF
+    chars = list(long_name.encode('utf-16-le'))
+    chars = [chars[i] | (chars[i + 1] << 8) for i in range(0, len(chars), 2)]
+    if len(chars) % 13:
+        chars.append(0)
+    chars += [0xFFFF] * (-len(chars) % 13)
+    parts = [chars[i:i + 13] for i in range(0, len(chars), 13)]
+    out = []
+    for seq, part in enumerate(parts, 1):
+        order = seq | (0x40 if seq == len(parts) else 0)
+        out.append(struct.pack('<B5HBBB6HH2H', order, *part[:5], 0x0F, 0, checksum,
+                               *part[5:11], 0, *part[11:13]))
+    return b''.join(reversed(out))
+
+
+def listing(path):
+    return sorted(os.listdir(path))
+
+
+def entry_slots(name):
+    if is_short(name):
+        return 1
+    return 1 + (len(name.encode('utf-16-le')) // 2 + 12) // 13
+
+
+def dir_clusters(path, is_root):
+    slots = sum(entry_slots(n) for n in listing(path)) + 1 + (1 if is_root else 2)
+    return max(1, (slots * 32 + SECTOR - 1) // SECTOR)
+
+
+def build_dir(img, path, clusters, parent_cluster, label=None):
+    own = clusters[0]
+    entries = []
+    if label is not None:
+        entries.append(short_entry(label.encode().ljust(11), 0x08, 0, 0))
+    else:
+        entries.append(short_entry(b'.'.ljust(11), 0x10, own, 0))
+        entries.append(short_entry(b'..'.ljust(11), 0x10, parent_cluster, 0))
+
+    taken = set()
+    for name in listing(path):
+        full = os.path.join(path, name)
+        if is_short(name):
+            name11 = pack_short(name)
+            prefix = b''
+        else:
+            name11 = short_alias(name, taken)
+            prefix = lfn_entries(name, name11)
+        taken.add(name11)
+
+        if os.path.isdir(full):
+            sub = img.chain(dir_clusters(full, False))
+            # `..` pointing at the root is stored as cluster 0
+            build_dir(img, full, sub, 0 if label is not None else own)
+            entries.append(prefix + short_entry(name11, 0x10, sub[0], 0))
+        else:
+            with open(full, 'rb') as f:
+                payload = f.read()
+            first = 0
+            if payload:
+                count = (len(payload) + SECTOR - 1) // SECTOR
+                chain = img.chain(count, fragment=count >= 3)
+                img.store(chain, payload)
+                first = chain[0]
+            entries.append(prefix + short_entry(name11, 0x20, first, len(payload)))
+
+    img.store(clusters, b''.join(entries) + bytes(32))
+
+
+def main():
+    out, tree = sys.argv[1], sys.argv[2]
+    total = int(sys.argv[3]) if len(sys.argv) > 3 else 2048
+    img = Image(total)
+
+    root = img.chain(dir_clusters(tree, True))
+    assert root[0] == 2
+    build_dir(img, tree, root, 0, label='QUNIX-TEST')
+
+    image = bytearray(total * SECTOR)
+    bpb = struct.pack('<3s8sHBHBHHBHHHIIIHHIHH12sBBBI11s8s',
+                      b'\xEB\x58\x90', b'QUNIX   ', SECTOR, 1, RESERVED, NUM_FATS, 0, 0, 0xF8, 0,
+                      32, 2, 0, total, img.fat_sectors, 0, 0, 2, 1, 6, bytes(12),
+                      0x80, 0, 0x29, 0x51A7C0DE, b'QUNIX-TEST ', b'FAT32   ')
+    image[0:len(bpb)] = bpb
+    image[510:512] = b'\x55\xAA'
+
+    free = sum(1 for e in img.fat[2:] if e == 0) + img.max_cluster + 1 - len(img.fat)
+    fsinfo = SECTOR
+    image[fsinfo:fsinfo + 4] = struct.pack('<I', 0x41615252)
+    image[fsinfo + 484:fsinfo + 496] = struct.pack('<III', 0x61417272, free, len(img.fat))
+    image[fsinfo + 508:fsinfo + 512] = struct.pack('<I', 0xAA550000)
+
+    fat_bytes = b''.join(struct.pack('<I', e) for e in img.fat)
+    for n in range(NUM_FATS):
+        start = (RESERVED + n * img.fat_sectors) * SECTOR
+        image[start:start + len(fat_bytes)] = fat_bytes
+
+    for cluster, payload in img.data.items():
+        start = (img.first_data + cluster - 2) * SECTOR
+        image[start:start + len(payload)] = payload
+
+    with open(out, 'wb') as f:
+        f.write(image)
+
+
+if __name__ == '__main__':
+    main()
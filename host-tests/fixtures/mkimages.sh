@@ -0,0 +1,123 @@
+#!/bin/bash
+# Regenerate the disk image fixtures. Output is deterministic (fixed UUID,
+# hash seed and timestamps) so rebuilding does not churn the committed
+# images. Requires e2fsprogs and python3; mkfat32.py builds the FAT32
+# image since FAT tools refuse to format volumes this small.
+
+set -e
+umask 022
+cd "$(dirname "$0")"
+
+# mke2fs copies each file's ctime from the host, which touch cannot set.
+pin_ctime() {
+    for ino in $(seq 1 "$2"); do
+        echo "sif <$ino> ctime 1700000000"
+    done | debugfs -w -f - "$1" >/dev/null 2>&1
+}
+
+# debugfs stamps the superblock with the time of any change that
+# allocates, so put it back, and the checksum over it.
+pin_wtime() {
+    python3 - "$1" <<'PY'
+import struct, sys
+def crc32c(data, crc=0xFFFFFFFF):
+    for b in data:
+        crc ^= b
+        for _ in range(8):
+            crc = (crc >> 1) ^ (0x82F63B78 if crc & 1 else 0)
+    return crc
+with open(sys.argv[1], 'r+b') as f:
+    f.seek(1024)
+    sb = bytearray(f.read(1024))
+    struct.pack_into('<I', sb, 0x30, 1700000000)
+    struct.pack_into('<I', sb, 0x3FC, crc32c(sb[:0x3FC]))
+    f.seek(1024)
+    f.write(sb)
+PY
+}
+
+TREE="$(mktemp -d)"
+trap 'rm -rf "$TREE"' EXIT
+
+mkdir -p "$TREE/dir/nested"
+printf 'hello from ext4\n' > "$TREE/hello.txt"
+printf 'nested file\n' > "$TREE/dir/nested/file.txt"
+ln -s hello.txt "$TREE/link"
+touch -h -d @1700000000 "$TREE" "$TREE"/* "$TREE"/dir/* "$TREE"/dir/nested/*
+
+# Block-mapped (no extents) 1 KiB-block ext4, with none of the newer features.
+rm -f ext4-small.img
+E2FSPROGS_FAKE_TIME=1700000000 mke2fs -q -t ext4 -b 1024 -N 32 \
+    -O ^has_journal,^resize_inode,^extent,^flex_bg,^metadata_csum,^64bit,^dir_index \
+    -U 6f1d4c1e-0000-4000-8000-000000000001 -E hash_seed=6f1d4c1e-0000-4000-8000-000000000002 \
+    -L qunix-test -d "$TREE" ext4-small.img 256K
+pin_ctime ext4-small.img 32
+
+# The same with the features mke2fs gives ext4 now: extents, 64bit,
+# flex_bg, metadata_csum, and an indexed directory. sparse.bin has a
+# block written in every other one, so its extent tree is two levels
+# deep; prealloc is allocated but unwritten, over blocks full of 0xff.
+EXT="$(mktemp -d)"
+trap 'rm -rf "$TREE" "$EXT"' EXIT
+mkdir -p "$EXT/many"
+printf 'hello from extents\n' > "$EXT/hello.txt"
+python3 - "$EXT" <<'PY'
+import sys
+root = sys.argv[1]
+with open(root + '/big.bin', 'wb') as f:
+    f.write(bytes(i * 7 % 256 for i in range(40 * 1024)))
+with open(root + '/sparse.bin', 'wb') as f:
+    for i in range(400):
+        f.seek(2 * i * 1024)
+        f.write(bytes([i % 251 + 1]) * 1024)
+for i in range(100):
+    with open('%s/many/file-%03d' % (root, i), 'w') as f:
+        f.write('%d\n' % i)
+PY
+touch -h -d @1700000000 "$EXT" "$EXT"/* "$EXT"/many/*
+rm -f ext4-extents.img
+E2FSPROGS_FAKE_TIME=1700000000 mke2fs -q -t ext4 -b 1024 -N 256 \
+    -O ^has_journal,^resize_inode,^orphan_file \
+    -U 6f1d4c1e-0000-4000-8000-000000000003 -E hash_seed=6f1d4c1e-0000-4000-8000-000000000004 \
+    -L qunix-extents -d "$EXT" ext4-extents.img 1M
+E2FSCK_TIME=1700000000 e2fsck -fyD ext4-extents.img >/dev/null 2>&1 || [ $? -eq 1 ]
+{
+    echo "write /dev/null prealloc"
+    echo "sif prealloc size 8192"
+    for field in atime mtime crtime; do
+        echo "sif prealloc $field 1700000000"
+    done
+    echo "fallocate prealloc 0 7"
+    for block in $(seq 0 7); do
+        echo "zap_block -f prealloc -p 0xff $block"
+    done
+} | E2FSPROGS_FAKE_TIME=1700000000 debugfs -w -f - ext4-extents.img >/dev/null 2>&1
+pin_ctime ext4-extents.img 256
+pin_wtime ext4-extents.img
+
+FAT="$(mktemp -d)"
+trap 'rm -rf "$TREE" "$EXT" "$FAT"' EXIT
+mkdir -p "$FAT/SUBDIR"
+printf 'hello from fat32\n' > "$FAT/HELLO.TXT"
+printf 'long name file\n' > "$FAT/A Long File Name.txt"
+printf 'nested file\n' > "$FAT/SUBDIR/NESTED.TXT"
+python3 mkfat32.py fat32-small.img "$FAT"
+
+# The same tree as GNU and pax tarballs, gzip-compressed without a stored
+# name or timestamp. The long file name needs an 'L' or 'x' header; the
+# long directory fits ustar's prefix field.
+ARC="$(mktemp -d)"
+trap 'rm -rf "$TREE" "$EXT" "$FAT" "$ARC"' EXIT
+LONG_DIR="a-directory-name-long-enough-that-the-full-path-needs-the-ustar-prefix"
+LONG_NAME="$(printf 'long-name-%.0s' $(seq 1 11)).txt"
+mkdir -p "$ARC/tree/$LONG_DIR"
+printf 'hello from tar\n' > "$ARC/tree/hello.txt"
+seq 1 2000 > "$ARC/tree/numbers.txt"
+printf 'nested\n' > "$ARC/tree/$LONG_DIR/file.txt"
+printf 'long\n' > "$ARC/tree/$LONG_NAME"
+ln -s hello.txt "$ARC/tree/link"
+find "$ARC/tree" -exec touch -h -d @1700000000 {} +
+TAR_OPTS="--sort=name --mtime=@1700000000 --owner=0 --group=0 --numeric-owner"
+tar -C "$ARC" $TAR_OPTS --format=gnu -cf - tree | gzip -9n > tree-gnu.tar.gz
+tar -C "$ARC" $TAR_OPTS --format=pax --pax-option=exthdr.name=%d/PaxHeaders/%f,delete=atime,delete=ctime \
+    -cf - tree | gzip -9n > tree-pax.tar.gz
@@ -0,0 +1,50 @@
+#!/bin/bash
+# Build and run one of the libFuzzer targets in fuzz/:
+#
+#   ./fuzz.sh TARGET [libFuzzer options...]
+#   ./fuzz.sh list
+#
+# fuzz/ is a regular cargo-fuzz crate, but `cargo fuzz` cannot be run inside
+# this repository: the kernel's .cargo/config.toml would apply build-std to
+# it. Like run.sh this builds from outside the tree, with the instrumentation
+# flags cargo-fuzz would use. Corpora live in fuzz/corpus/TARGET, seeded with
+# the fixture images for the mount targets; crashes land in fuzz/artifacts.
+
+set -e
+
+HERE="$(cd "$(dirname "$0")" && pwd)"
+FUZZ="$HERE/fuzz"
+HOST="$(rustc +nightly -vV | sed -n 's/^host: //p')"
+
+if [ -z "$1" ] || [ "$1" = list ]; then
+    ls "$FUZZ/fuzz_targets" | sed 's/\.rs$//'
+    exit 0
+fi
+TARGET="$1"
+shift
+
+export RUSTFLAGS="-Cpasses=sancov-module -Cllvm-args=-sanitizer-coverage-level=4 \
+-Cllvm-args=-sanitizer-coverage-inline-8bit-counters -Cllvm-args=-sanitizer-coverage-pc-table \
+-Cllvm-args=-sanitizer-coverage-trace-compares -Zsanitizer=address -Cdebug-assertions \
+-Coverflow-checks --cfg fuzzing"
+
+cd /
+cargo +nightly build --manifest-path "$FUZZ/Cargo.toml" --target "$HOST" --release \
+    --target-dir "$FUZZ/target" --bin "$TARGET"
+
+CORPUS="$FUZZ/corpus/$TARGET"
+mkdir -p "$CORPUS" "$FUZZ/artifacts/$TARGET"
+SEED=
+case "$TARGET" in
+    ext4_mount) SEED="$HERE/fixtures/ext4-small.img" ;;
+    fat32_mount) SEED="$HERE/fixtures/fat32-small.img" ;;
+esac
+OPTS=()
+if [ -n "$SEED" ]; then
+    cp -n "$SEED" "$CORPUS/"
+    # Whole images are far larger than libFuzzer's default input limit
+    OPTS+=(-max_len="$(stat -c %s "$SEED")")
+fi
+
+exec "$FUZZ/target/$HOST/release/$TARGET" -artifact_prefix="$FUZZ/artifacts/$TARGET/" \
+    "${OPTS[@]}" "$@" "$CORPUS"
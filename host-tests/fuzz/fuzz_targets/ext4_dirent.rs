@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qunix_host_tests::fs::ext4::parse_dir_entry;
+
+// Walks the input as one directory block, as readdir does.
+fuzz_target!(|block: &[u8]| {
+    let mut offset = 0;
+    while offset < block.len() {
+        match parse_dir_entry(block, offset) {
+            Ok((entry, name)) => {
+                assert!(8 + name.len() <= entry.rec_len as usize);
+                offset += entry.rec_len as usize;
+            }
+            Err(_) => break,
+        }
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qunix_host_tests::fs::ext4::{parse_group_desc, parse_superblock};
+
+static IMAGE: &[u8] = include_bytes!("../../fixtures/ext4-small.img");
+
+// The first byte picks the descriptor format; the rest is the descriptor.
+fuzz_target!(|data: &[u8]| {
+    let Some((&mode, desc)) = data.split_first() else { return };
+    let mut raw = IMAGE[1024..2048].to_vec();
+    if mode & 1 != 0 {
+        // 64bit feature with 64-byte descriptors
+        raw[0x60] |= 0x80;
+        raw[0xFE..0x100].copy_from_slice(&64u16.to_le_bytes());
+    }
+    let sb = parse_superblock(&raw).expect("fixture superblock");
+    if let Ok(gd) = parse_group_desc(desc, &sb) {
+        assert!(gd.inode_table() + sb.inode_table_blocks() <= sb.blocks_count());
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qunix_host_tests::fs::ext4::parse_inode;
+
+// The first byte picks the inode record size (128 to 1024 bytes).
+fuzz_target!(|data: &[u8]| {
+    let Some((&mode, raw)) = data.split_first() else { return };
+    if let Ok(inode) = parse_inode(raw, 128 << (mode & 3)) {
+        let _ = (inode.size(), inode.file_type(), inode.blocks_count(), inode.uid(), inode.gid());
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qunix_fuzz::exercise;
+use qunix_host_tests::fs::ext4::Ext4Filesystem;
+use qunix_host_tests::fs::ramdisk::RamDisk;
+use qunix_host_tests::image;
+
+// Seed the corpus with host-tests/fixtures/ext4-small.img.
+fuzz_target!(|data: &[u8]| {
+    let device = image::shared(RamDisk::from_image(data, 1024));
+    if let Ok(fs) = Ext4Filesystem::mount(device, true) {
+        exercise(&fs);
+    }
+});
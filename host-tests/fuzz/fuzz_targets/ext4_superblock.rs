@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qunix_host_tests::fs::ext4::parse_superblock;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(sb) = parse_superblock(data) {
+        // Everything mount derives from an accepted superblock must be sane
+        assert!(sb.block_group_count() > 0);
+        assert!(sb.block_size() / sb.desc_size() as u32 > 0);
+        assert!(sb.block_size() / sb.inode_size() as u32 > 0);
+        let _ = sb.inode_table_blocks();
+    }
+});
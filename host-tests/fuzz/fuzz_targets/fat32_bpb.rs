@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qunix_host_tests::fs::fat32::parse_bpb;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(bpb) = parse_bpb(data) {
+        // The geometry helpers must not overflow on an accepted BPB
+        assert!(bpb.cluster_count() > 0);
+        assert!(bpb.first_data_sector() < bpb.total_sectors());
+        let last = bpb.cluster_count() + 1;
+        assert!(bpb.cluster_to_sector(last) < bpb.total_sectors());
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qunix_host_tests::fs::fat32::parse_directory;
+
+// Decodes the input as the contents of one directory.
+fuzz_target!(|data: &[u8]| {
+    if let Ok(entries) = parse_directory(data) {
+        assert!(entries.len() <= data.len() / 32);
+    }
+});
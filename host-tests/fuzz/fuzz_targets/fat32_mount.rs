@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use qunix_fuzz::exercise;
+use qunix_host_tests::fs::fat32::Fat32Filesystem;
+use qunix_host_tests::fs::ramdisk::RamDisk;
+use qunix_host_tests::image;
+
+// Seed the corpus with host-tests/fixtures/fat32-small.img.
+fuzz_target!(|data: &[u8]| {
+    let device = image::shared(RamDisk::from_image(data, 512));
+    if let Ok(fs) = Fat32Filesystem::mount(device, true) {
+        exercise(&fs);
+    }
+});
@@ -0,0 +1,44 @@
+//! Shared driver for the whole-image fuzz targets.
+
+use qunix_host_tests::fs::vfs::{Filesystem, InodeNumber};
+
+const MAX_DEPTH: usize = 8;
+const MAX_NODES: usize = 256;
+
+/// Walks a mounted filesystem the way the shell would: list every
+/// directory, look each entry up, stat it and read the start of it. The
+/// limits keep directory cycles in a corrupt image from looping forever.
+pub fn exercise(fs: &dyn Filesystem) {
+    let root = match fs.root() {
+        Ok(root) => root,
+        Err(_) => return,
+    };
+    let mut budget = MAX_NODES;
+    walk(fs, root.inode, 0, &mut budget);
+}
+
+fn walk(fs: &dyn Filesystem, dir: InodeNumber, depth: usize, budget: &mut usize) {
+    let entries = match fs.readdir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries {
+        if *budget == 0 {
+            return;
+        }
+        *budget -= 1;
+        if entry.name == "." || entry.name == ".." {
+            continue;
+        }
+        let node = match fs.lookup(dir, &entry.name) {
+            Ok(node) => node,
+            Err(_) => continue,
+        };
+        let _ = fs.stat(node.inode);
+        let mut buf = [0u8; 4096];
+        let _ = fs.read(node.inode, 0, &mut buf);
+        if node.is_dir() && depth < MAX_DEPTH {
+            walk(fs, node.inode, depth + 1, budget);
+        }
+    }
+}
@@ -0,0 +1,13 @@
+#!/bin/bash
+# Run the filesystem tests on the host. The repository's .cargo/config.toml
+# targets the kernel (custom target, build-std), so build from outside it
+# with an explicit host target and the stable toolchain.
+
+set -e
+
+HERE="$(cd "$(dirname "$0")" && pwd)"
+HOST="$(rustc +stable -vV | sed -n 's/^host: //p')"
+
+cd /
+exec cargo +stable test --manifest-path "$HERE/Cargo.toml" --target "$HOST" \
+    --target-dir "$HERE/target" "$@"
@@ -0,0 +1,150 @@
+//! Host stand-ins for the kernel drivers the filesystem code writes to.
+//! Output sent to a device node is captured so tests can inspect it.
+
+pub mod drivers {
+    pub mod tty {
+        use std::sync::Mutex;
+
+        static OUTPUT: Mutex<String> = Mutex::new(String::new());
+
+        pub fn get_current_tty() -> usize {
+            0
+        }
+
+        /// Which virtual terminal, /dev/tty1 to /dev/tty6, `id` is.
+        pub fn vt_of(id: crate::fs::vfs::node::DeviceId) -> Option<usize> {
+            match (id.major, id.minor) {
+                (4, n @ 1..=6) => Some(n as usize),
+                _ => None,
+            }
+        }
+
+        pub fn write_to_tty(_id: usize, s: &str) {
+            OUTPUT.lock().unwrap().push_str(s);
+        }
+
+        pub fn write_bytes_to_tty(_id: usize, buf: &[u8]) {
+            OUTPUT.lock().unwrap().push_str(&String::from_utf8_lossy(buf));
+        }
+
+        /// Returns and clears everything written to the TTY so far.
+        pub fn take_output() -> String {
+            core::mem::take(&mut *OUTPUT.lock().unwrap())
+        }
+    }
+
+    pub mod serial {
+        use std::sync::Mutex;
+
+        static OUTPUT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+        pub fn write_byte(byte: u8) {
+            OUTPUT.lock().unwrap().push(byte);
+        }
+
+        pub fn write_string(s: &str) {
+            OUTPUT.lock().unwrap().extend_from_slice(s.as_bytes());
+        }
+
+        /// Which of /dev/ttyS0 to /dev/ttyS3 `id` is.
+        pub fn port_of(id: crate::fs::vfs::node::DeviceId) -> Option<usize> {
+            match (id.major, id.minor) {
+                (4, n @ 64..=67) => Some(n as usize - 64),
+                _ => None,
+            }
+        }
+
+        /// Every port writes to the same captured output.
+        pub fn write(_port: usize, buf: &[u8]) {
+            OUTPUT.lock().unwrap().extend_from_slice(buf);
+        }
+
+        /// Nothing is ever received.
+        pub fn read(_port: usize, _buf: &mut [u8]) -> usize {
+            0
+        }
+
+        /// Returns and clears everything written to the serial port so far.
+        pub fn take_output() -> Vec<u8> {
+            core::mem::take(&mut *OUTPUT.lock().unwrap())
+        }
+    }
+
+    pub mod hotplug {
+        use std::sync::Mutex;
+
+        pub const MAJOR: u16 = 10;
+
+        static LOG: Mutex<String> = Mutex::new(String::new());
+
+        pub fn read(offset: u64, buf: &mut [u8]) -> usize {
+            let log = LOG.lock().unwrap();
+            let start = (offset as usize).min(log.len());
+            let len = (log.len() - start).min(buf.len());
+            buf[..len].copy_from_slice(&log.as_bytes()[start..start + len]);
+            len
+        }
+
+        /// Appends a line to what /dev/hotplug reads back.
+        pub fn push_event(line: &str) {
+            LOG.lock().unwrap().push_str(line);
+        }
+    }
+
+    pub mod audio {
+        pub const MAJOR: u16 = 14;
+
+        /// PCM written to /dev/dsp is accepted and dropped.
+        pub fn write(buf: &[u8]) -> Result<usize, &'static str> {
+            Ok(buf.len())
+        }
+    }
+
+    pub mod mem {
+        pub const MAJOR: u16 = 240;
+    }
+
+    pub mod input {
+        use crate::fs::FsResult;
+
+        pub const MAJOR: u16 = 13;
+
+        /// No keys are pressed on the host.
+        pub fn read(_buf: &mut [u8]) -> FsResult<usize> {
+            Ok(0)
+        }
+    }
+
+    pub mod device {
+        use crate::fs::vfs::node::DeviceId;
+        use crate::fs::{FsError, FsResult};
+
+        /// No device can be mapped on the host.
+        pub fn map(_id: DeviceId, _offset: u64, _len: u64) -> FsResult<u64> {
+            Err(FsError::NotSupported)
+        }
+    }
+}
+
+pub mod lockdep {
+    /// The lock classes the kernel checks the order of; the host does not.
+    pub enum Class {
+        Vfs,
+    }
+
+    pub struct Mutex<T>(spin::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub const fn new(_class: Class, value: T) -> Self {
+            Mutex(spin::Mutex::new(value))
+        }
+
+        pub fn lock(&self) -> spin::MutexGuard<'_, T> {
+            self.0.lock()
+        }
+
+        pub fn try_lock(&self) -> Option<spin::MutexGuard<'_, T>> {
+            self.0.try_lock()
+        }
+    }
+}
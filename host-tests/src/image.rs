@@ -0,0 +1,21 @@
+//! Disk image fixtures exposed as kernel block devices.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use spin::RwLock;
+use crate::fs::ext4::ext4::BlockDevice;
+use crate::fs::ramdisk::RamDisk;
+
+/// Loads an image into a RAM disk. Writes go to the copy, never the file.
+pub fn open(path: impl AsRef<Path>, block_size: u32) -> std::io::Result<RamDisk> {
+    Ok(RamDisk::from_image(&std::fs::read(path)?, block_size))
+}
+
+pub fn shared(device: RamDisk) -> Arc<RwLock<dyn BlockDevice + Send + Sync>> {
+    Arc::new(RwLock::new(device))
+}
+
+/// Path of a file under `host-tests/fixtures`.
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(name)
+}
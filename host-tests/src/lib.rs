@@ -0,0 +1,12 @@
+//! Builds the kernel's `fs` module for the host so VFS, path handling and
+//! the ext4/FAT32 parsers can be exercised with plain `cargo test` instead
+//! of booting QEMU. The sources are shared with the kernel through `#[path]`;
+//! only the few hardware hooks they call are replaced by `hal` below.
+
+extern crate alloc;
+
+#[path = "../../src/fs/mod.rs"]
+pub mod fs;
+
+pub mod hal;
+pub mod image;
@@ -0,0 +1,154 @@
+use qunix_host_tests::fs::archive::gzip;
+use qunix_host_tests::fs::archive::inflate::inflate;
+use qunix_host_tests::fs::archive::tar::{self, Builder, Entry, EntryKind, Reader};
+use qunix_host_tests::fs::vfs::api;
+use qunix_host_tests::fs::FsError;
+use qunix_host_tests::image::fixture;
+
+const LIMIT: usize = 1 << 20;
+const SAMPLE: &[u8] = b"abcabcabcabc hello hello hello\n";
+const LONG_DIR: &str = "a-directory-name-long-enough-that-the-full-path-needs-the-ustar-prefix";
+
+fn long_name() -> String {
+    format!("{}.txt", "long-name-".repeat(11))
+}
+
+fn numbers() -> Vec<u8> {
+    (1..=2000).map(|n| format!("{}\n", n)).collect::<String>().into_bytes()
+}
+
+fn unpack(name: &str) -> Vec<u8> {
+    let data = std::fs::read(fixture(name)).expect("missing fixture");
+    gzip::decompress(&data, LIMIT).unwrap()
+}
+
+fn file<'a>(path: &str, data: &'a [u8]) -> Entry<'a> {
+    Entry { path: path.into(), kind: EntryKind::File, mode: 0o644, uid: 0, gid: 0, mtime: 1700000000, data }
+}
+
+#[test]
+fn inflate_stored_and_fixed_blocks() {
+    let stored = [
+        0x01, 0x1f, 0x00, 0xe0, 0xff, 0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x61, 0x62, 0x63, 0x61, 0x62,
+        0x63, 0x20, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x68, 0x65,
+        0x6c, 0x6c, 0x6f, 0x0a,
+    ];
+    let fixed = [0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x85, 0x8c, 0xd4, 0x9c, 0x9c, 0x7c, 0x64, 0x92, 0x0b, 0x00];
+    assert_eq!(inflate(&stored, LIMIT).unwrap(), (SAMPLE.to_vec(), stored.len()));
+    assert_eq!(inflate(&fixed, LIMIT).unwrap(), (SAMPLE.to_vec(), fixed.len()));
+
+    assert_eq!(inflate(&fixed, 8).err(), Some(FsError::NoSpace));
+    assert_eq!(inflate(&fixed[..8], LIMIT).err(), Some(FsError::InvalidArgument));
+    let mut bad_len = stored;
+    bad_len[3] ^= 1;
+    assert_eq!(inflate(&bad_len, LIMIT).err(), Some(FsError::InvalidArgument));
+    // Block type 3 is reserved
+    assert_eq!(inflate(&[0x07], LIMIT).err(), Some(FsError::InvalidArgument));
+}
+
+#[test]
+fn gzip_checks_trailer() {
+    let data = std::fs::read(fixture("tree-gnu.tar.gz")).unwrap();
+    assert!(gzip::is_gzip(&data));
+    assert_eq!(gzip::crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(gzip::crc32_continue(gzip::crc32(b"1234"), b"56789"), 0xCBF4_3926);
+
+    let mut corrupt = data.clone();
+    let crc = corrupt.len() - 8;
+    corrupt[crc] ^= 0xFF;
+    assert_eq!(gzip::decompress(&corrupt, LIMIT).err(), Some(FsError::IoError));
+    assert_eq!(gzip::decompress(&data[..data.len() - 4], LIMIT).err(), Some(FsError::InvalidArgument));
+    assert_eq!(gzip::decompress(&data, 1024).err(), Some(FsError::NoSpace));
+
+    // Two members decompress to both contents back to back
+    let mut twice = data.clone();
+    twice.extend_from_slice(&data);
+    let single = gzip::decompress(&data, LIMIT).unwrap();
+    assert_eq!(gzip::decompress(&twice, 2 * LIMIT).unwrap(), [&single[..], &single[..]].concat());
+}
+
+#[test]
+fn reads_gnu_and_pax_fixtures() {
+    for name in ["tree-gnu.tar.gz", "tree-pax.tar.gz"] {
+        let archive = unpack(name);
+        let entries: Vec<Entry> = Reader::new(&archive).collect::<Result<_, _>>().unwrap();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        let nested = format!("tree/{}/file.txt", LONG_DIR);
+        let long = format!("tree/{}", long_name());
+        assert_eq!(
+            paths,
+            ["tree/", &format!("tree/{}/", LONG_DIR), &nested, "tree/hello.txt", "tree/link", &long, "tree/numbers.txt"],
+            "{}",
+            name
+        );
+
+        let find = |path: &str| entries.iter().find(|e| e.path == path).unwrap();
+        assert_eq!(find("tree/").kind, EntryKind::Directory);
+        assert_eq!(find("tree/hello.txt").data, b"hello from tar\n");
+        assert_eq!(find("tree/hello.txt").mode, 0o644);
+        assert_eq!(find("tree/hello.txt").mtime, 1700000000);
+        assert_eq!(find("tree/link").kind, EntryKind::Symlink("hello.txt".into()));
+        assert_eq!(find(&nested).data, b"nested\n");
+        assert_eq!(find(&long).data, b"long\n");
+        assert_eq!(find("tree/numbers.txt").data, &numbers()[..]);
+    }
+}
+
+#[test]
+fn builder_round_trips() {
+    let nested = format!("{}/{}/file.txt", LONG_DIR, LONG_DIR);
+    let mut builder = Builder::new();
+    builder.append(&Entry { kind: EntryKind::Directory, mode: 0o755, ..file("dir", b"") }).unwrap();
+    builder.append(&file("dir/a.txt", SAMPLE)).unwrap();
+    builder.append(&file(&nested, b"deep\n")).unwrap();
+    builder.append(&Entry { kind: EntryKind::Symlink("a.txt".into()), mode: 0o777, ..file("dir/link", b"") }).unwrap();
+    let long = format!("{}/{}", LONG_DIR, long_name());
+    builder.append(&file(&long, b"long\n")).unwrap();
+    builder.append(&Entry { kind: EntryKind::Symlink(long.clone()), ..file("dir/far", b"") }).unwrap();
+    let archive = builder.finish();
+    assert_eq!(archive.len() % tar::BLOCK_SIZE, 0);
+
+    let entries: Vec<Entry> = Reader::new(&archive).collect::<Result<_, _>>().unwrap();
+    assert_eq!(entries.len(), 6);
+    assert_eq!((entries[0].path.as_str(), &entries[0].kind, entries[0].mode), ("dir/", &EntryKind::Directory, 0o755));
+    assert_eq!((entries[1].path.as_str(), entries[1].data), ("dir/a.txt", SAMPLE));
+    assert_eq!((entries[2].path.as_str(), entries[2].data), (nested.as_str(), &b"deep\n"[..]));
+    assert_eq!(entries[3].kind, EntryKind::Symlink("a.txt".into()));
+    // Too long for ustar, so carried by GNU long-name records
+    assert_eq!((entries[4].path.as_str(), entries[4].data), (long.as_str(), &b"long\n"[..]));
+    assert_eq!(entries[5].kind, EntryKind::Symlink(long.clone()));
+
+    // A damaged header is reported once, then iteration stops
+    let mut corrupt = archive.clone();
+    corrupt[tar::BLOCK_SIZE + 10] ^= 1;
+    let results: Vec<_> = Reader::new(&corrupt).collect();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[1].as_ref().err(), Some(&FsError::InvalidArgument));
+}
+
+#[test]
+fn extracts_into_vfs_and_archives_back() {
+    api::mkdir("/unpack", 0o755).unwrap();
+    let archive = unpack("tree-pax.tar.gz");
+    let mut seen = 0;
+    assert_eq!(tar::extract(&archive, "/unpack", |_| seen += 1).unwrap(), 7);
+    assert_eq!(seen, 7);
+    assert_eq!(api::read_file("/unpack/tree/hello.txt").unwrap(), b"hello from tar\n");
+    assert_eq!(api::read_file(&format!("/unpack/tree/{}/file.txt", LONG_DIR)).unwrap(), b"nested\n");
+    assert_eq!(api::readlink("/unpack/tree/link").unwrap(), "hello.txt");
+    assert_eq!(api::stat("/unpack/tree/numbers.txt").unwrap().size, numbers().len() as u64);
+
+    let mut added = Vec::new();
+    let repacked = tar::create(&["/unpack/tree"], |path| added.push(path.to_string())).unwrap();
+    assert_eq!(added.len(), 7);
+    assert_eq!(added[0], "/unpack/tree");
+    api::mkdir("/repack", 0o755).unwrap();
+    assert_eq!(tar::extract(&repacked, "/repack", |_| {}).unwrap(), 7);
+    assert_eq!(api::read_file("/repack/unpack/tree/numbers.txt").unwrap(), numbers());
+
+    // Nothing may land outside the destination
+    let mut builder = Builder::new();
+    builder.append(&file("../escape", b"x")).unwrap();
+    assert_eq!(tar::extract(&builder.finish(), "/unpack", |_| {}).err(), Some(FsError::InvalidPath));
+    assert_eq!(api::stat("/escape").err(), Some(FsError::NotFound));
+}
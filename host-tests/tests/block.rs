@@ -0,0 +1,91 @@
+use qunix_host_tests::fs::ext4::ext4::BlockDevice;
+use qunix_host_tests::fs::iosched::{Priority, RequestQueue};
+use qunix_host_tests::fs::ramdisk::RamDisk;
+
+const BS: usize = 512;
+
+/// A RAM disk that remembers the order writes reached it in
+struct Recorder {
+    disk: RamDisk,
+    writes: Vec<(u64, usize)>,
+}
+
+impl BlockDevice for Recorder {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.disk.read_block(block_num, buf)
+    }
+
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.writes.push((block_num, buf.len() / BS));
+        self.disk.write_block(block_num, buf)
+    }
+
+    fn block_size(&self) -> u32 {
+        BS as u32
+    }
+
+    fn block_count(&self) -> u64 {
+        self.disk.block_count()
+    }
+}
+
+fn queue() -> RequestQueue<Recorder> {
+    RequestQueue::new("test", Recorder { disk: RamDisk::new(64, BS as u32), writes: Vec::new() })
+}
+
+fn block(byte: u8) -> Vec<u8> {
+    vec![byte; BS]
+}
+
+/// Flush and return the writes the device saw
+fn written(queue: &mut RequestQueue<Recorder>) -> Vec<(u64, usize)> {
+    queue.flush().unwrap();
+    std::mem::take(&mut queue.device_mut().writes)
+}
+
+#[test]
+fn adjacent_writes_merge_and_reads_see_them() {
+    let mut q = queue();
+    q.write_block(4, &block(4)).unwrap();
+    q.write_block(5, &block(5)).unwrap();
+    q.write_block(3, &block(3)).unwrap();
+    q.write_block(4, &block(0x44)).unwrap();
+
+    let stats = q.stats();
+    assert_eq!((stats.writes, stats.merges, stats.queued_blocks), (4, 3, 3));
+    assert!(q.device_mut().writes.is_empty());
+
+    let mut buf = vec![0u8; 3 * BS];
+    q.read_block(3, &mut buf).unwrap();
+    assert_eq!(&buf[..BS], &block(3)[..]);
+    assert_eq!(&buf[BS..2 * BS], &block(0x44)[..]);
+    assert_eq!(&buf[2 * BS..], &block(5)[..]);
+
+    assert_eq!(written(&mut q), vec![(3, 3)]);
+    assert_eq!(q.stats().queued_blocks, 0);
+}
+
+#[test]
+fn dispatch_sweeps_up_from_the_head() {
+    let mut q = queue();
+    for lba in [10, 2, 7] {
+        q.write_block(lba, &block(1)).unwrap();
+    }
+    assert_eq!(written(&mut q), vec![(2, 1), (7, 1), (10, 1)]);
+
+    // The head is past block 10 now: 12 goes before the wrap to 5
+    for lba in [5, 12] {
+        q.write_block(lba, &block(2)).unwrap();
+    }
+    assert_eq!(written(&mut q), vec![(12, 1), (5, 1)]);
+}
+
+#[test]
+fn sync_writes_go_out_first() {
+    let mut q = queue();
+    q.write_block(20, &block(1)).unwrap();
+    q.submit(30, &block(2), Priority::Sync).unwrap();
+    assert_eq!(q.device_mut().writes, vec![(30, 1)]);
+    assert_eq!(q.stats().queued_blocks, 1);
+    assert!(q.submit(1, &[0u8; 100], Priority::Sync).is_err());
+}
@@ -0,0 +1,218 @@
+use qunix_host_tests::fs::ext4::Ext4Filesystem;
+use qunix_host_tests::fs::vfs::{Filesystem, VfsNodeData};
+use qunix_host_tests::fs::{FileMode, FileType, FsError};
+use qunix_host_tests::fs::ramdisk::RamDisk;
+use qunix_host_tests::image::{self, fixture};
+
+fn mount() -> Ext4Filesystem {
+    let device = image::open(fixture("ext4-small.img"), 1024).expect("missing fixture");
+    Ext4Filesystem::mount(image::shared(device), true).expect("mount failed")
+}
+
+fn read_all(fs: &Ext4Filesystem, parent: u64, name: &str) -> Vec<u8> {
+    let node = fs.lookup(parent, name).unwrap();
+    let mut buf = vec![0u8; node.size as usize + 4096];
+    let n = fs.read(node.inode, 0, &mut buf).unwrap();
+    buf.truncate(n);
+    buf
+}
+
+fn names(fs: &Ext4Filesystem, dir: u64) -> Vec<String> {
+    let mut names: Vec<String> = fs.readdir(dir).unwrap().into_iter().map(|e| e.name).collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn root_listing() {
+    let fs = mount();
+    let root = fs.root().unwrap();
+    assert!(root.is_dir());
+
+    let mut names: Vec<(String, FileType)> = fs
+        .readdir(root.inode)
+        .unwrap()
+        .into_iter()
+        .map(|e| (e.name, e.file_type))
+        .collect();
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        names,
+        [
+            (".".to_string(), FileType::Directory),
+            ("..".to_string(), FileType::Directory),
+            ("dir".to_string(), FileType::Directory),
+            ("hello.txt".to_string(), FileType::Regular),
+            ("link".to_string(), FileType::Symlink),
+            ("lost+found".to_string(), FileType::Directory),
+        ]
+    );
+}
+
+#[test]
+fn read_file_and_symlink() {
+    let fs = mount();
+    let root = fs.root().unwrap();
+
+    let hello = fs.lookup(root.inode, "hello.txt").unwrap();
+    assert_eq!(hello.size, 16);
+    let mut buf = [0u8; 64];
+    let n = fs.read(hello.inode, 0, &mut buf).unwrap();
+    assert_eq!(&buf[..n], b"hello from ext4\n");
+
+    let link = fs.lookup(root.inode, "link").unwrap();
+    match link.data {
+        VfsNodeData::Symlink(target) => assert_eq!(target, "hello.txt"),
+        _ => panic!("not a symlink"),
+    }
+
+    let dir = fs.lookup(root.inode, "dir").unwrap();
+    let nested = fs.lookup(dir.inode, "nested").unwrap();
+    let file = fs.lookup(nested.inode, "file.txt").unwrap();
+    let n = fs.read(file.inode, 0, &mut buf).unwrap();
+    assert_eq!(&buf[..n], b"nested file\n");
+
+    assert_eq!(fs.lookup(root.inode, "missing").err(), Some(FsError::NotFound));
+    assert_eq!(fs.lookup(hello.inode, "x").err(), Some(FsError::NotDirectory));
+}
+
+#[test]
+fn rejects_garbage() {
+    let device = RamDisk::new(64, 1024);
+    assert!(Ext4Filesystem::mount(image::shared(device), true).is_err());
+}
+
+#[test]
+fn writes_reach_the_disk() {
+    let device = image::shared(image::open(fixture("ext4-small.img"), 1024).expect("missing fixture"));
+    let mut fs = Ext4Filesystem::mount(device.clone(), false).unwrap();
+    let root = fs.root().unwrap().inode;
+
+    // Past the direct blocks, then grown past its end with a gap
+    let data: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+    let file = fs.create(root, "kernel.a", FileMode::new(0o644)).unwrap();
+    assert_eq!(fs.write(file.inode, 0, &data).unwrap(), data.len());
+    assert_eq!(fs.write(file.inode, 30000, b"tail").unwrap(), 4);
+    fs.write(file.inode, 1020, b"patched").unwrap();
+
+    let dir = fs.lookup(root, "dir").unwrap().inode;
+    let boot = fs.mkdir(dir, "boot", FileMode::new(0o755)).unwrap();
+    fs.create(boot.inode, "state", FileMode::new(0o600)).unwrap();
+    assert_eq!(fs.create(root, "kernel.a", FileMode::new(0o644)).err(), Some(FsError::AlreadyExists));
+    assert_eq!(fs.create(root, "a/b", FileMode::new(0o644)).err(), Some(FsError::InvalidArgument));
+    assert_eq!(fs.create(root, &"x".repeat(256), FileMode::new(0o644)).err(), Some(FsError::NameTooLong));
+
+    // A fresh mount sees only what was written out
+    let mut fs = Ext4Filesystem::mount(device.clone(), false).unwrap();
+    let mut expected = data.clone();
+    expected[1020..1027].copy_from_slice(b"patched");
+    expected.resize(30000, 0);
+    expected.extend_from_slice(b"tail");
+    assert_eq!(read_all(&fs, root, "kernel.a"), expected);
+    let boot = fs.lookup(dir, "boot").unwrap();
+    assert_eq!((boot.mode.0 & 0o7777, boot.nlink), (0o755, 2));
+    assert_eq!(fs.lookup(root, "dir").unwrap().nlink, 4);
+    assert_eq!(names(&fs, boot.inode), [".", "..", "state"]);
+    assert_eq!(fs.lookup(boot.inode, "state").unwrap().mode.0 & 0o7777, 0o600);
+
+    // Renaming replaces the target and keeps the data; removing frees it
+    fs.rename(root, "hello.txt", dir, "old.txt").unwrap();
+    fs.rename(root, "kernel.a", dir, "old.txt").unwrap();
+    assert_eq!(fs.rename(dir, "boot", boot.inode, "loop").err(), Some(FsError::InvalidArgument));
+    fs.rename(dir, "boot", root, "boot").unwrap();
+    assert_eq!(fs.rmdir(root, "boot").err(), Some(FsError::NotEmpty));
+    assert_eq!(fs.unlink(root, "boot").err(), Some(FsError::IsDirectory));
+    fs.unlink(boot.inode, "state").unwrap();
+    fs.rmdir(root, "boot").unwrap();
+
+    let fs = Ext4Filesystem::mount(device, true).unwrap();
+    assert_eq!(names(&fs, root), [".", "..", "dir", "link", "lost+found"]);
+    assert_eq!(names(&fs, dir), [".", "..", "nested", "old.txt"]);
+    assert_eq!(read_all(&fs, dir, "old.txt"), expected);
+    assert_eq!(fs.lookup(root, "dir").unwrap().nlink, 3);
+}
+
+#[test]
+fn space_is_given_back() {
+    let device = image::shared(image::open(fixture("ext4-small.img"), 1024).expect("missing fixture"));
+    let mut fs = Ext4Filesystem::mount(device, false).unwrap();
+    let root = fs.root().unwrap().inode;
+
+    // Most of the free space each time, through the double indirect block
+    let data = vec![0x5Au8; 200 * 1024];
+    for round in 0..3 {
+        let file = fs.create(root, "fill", FileMode::new(0o644)).unwrap();
+        assert_eq!(fs.write(file.inode, 0, &data).unwrap(), data.len(), "round {}", round);
+        fs.unlink(root, "fill").unwrap();
+    }
+
+    // A write the disk fills up during is cut short
+    let file = fs.create(root, "fill", FileMode::new(0o644)).unwrap();
+    let written = fs.write(file.inode, 0, &vec![1u8; 400 * 1024]).unwrap();
+    assert!(written > data.len() && written < 400 * 1024);
+    assert_eq!(fs.write(file.inode, written as u64, b"more").err(), Some(FsError::NoSpace));
+    assert_eq!(fs.stat(file.inode).unwrap().size, written as u64);
+}
+
+#[test]
+fn read_only_mounts_refuse_writes() {
+    let mut fs = mount();
+    let root = fs.root().unwrap().inode;
+    assert_eq!(fs.create(root, "new.txt", FileMode::new(0o644)).err(), Some(FsError::ReadOnly));
+    assert_eq!(fs.unlink(root, "hello.txt").err(), Some(FsError::ReadOnly));
+}
+
+/// What mkimages.sh wrote to sparse.bin: block 2i full of i % 251 + 1,
+/// the blocks between holes
+fn sparse_contents() -> Vec<u8> {
+    let mut data = vec![0u8; 799 * 1024];
+    for i in 0..400 {
+        data[2 * i * 1024..(2 * i + 1) * 1024].fill((i % 251 + 1) as u8);
+    }
+    data
+}
+
+#[test]
+fn read_extent_files() {
+    let device = image::open(fixture("ext4-extents.img"), 1024).expect("missing fixture");
+    let fs = Ext4Filesystem::mount(image::shared(device), true).unwrap();
+    let root = fs.root().unwrap().inode;
+
+    assert_eq!(read_all(&fs, root, "hello.txt"), b"hello from extents\n");
+    let big: Vec<u8> = (0..40 * 1024u32).map(|i| (i * 7 % 256) as u8).collect();
+    assert_eq!(read_all(&fs, root, "big.bin"), big);
+    // Two levels of index above 400 leaf extents
+    assert_eq!(read_all(&fs, root, "sparse.bin"), sparse_contents());
+    // Unwritten blocks read as zeros whatever they hold
+    assert_eq!(read_all(&fs, root, "prealloc"), vec![0u8; 8192]);
+
+    let many = fs.lookup(root, "many").unwrap().inode;
+    assert_eq!(names(&fs, many).len(), 102);
+    assert_eq!(read_all(&fs, many, "file-042"), b"42\n");
+}
+
+#[test]
+fn writes_through_extent_trees() {
+    let device = image::shared(image::open(fixture("ext4-extents.img"), 1024).expect("missing fixture"));
+    let mut fs = Ext4Filesystem::mount(device.clone(), false).unwrap();
+    let root = fs.root().unwrap().inode;
+
+    let sparse = fs.lookup(root, "sparse.bin").unwrap().inode;
+    fs.write(sparse, 1024 + 100, b"filled").unwrap();
+    let prealloc = fs.lookup(root, "prealloc").unwrap().inode;
+    fs.write(prealloc, 3 * 1024, b"written").unwrap();
+    let many = fs.lookup(root, "many").unwrap().inode;
+    let file = fs.create(many, "file-new", FileMode::new(0o644)).unwrap();
+    fs.write(file.inode, 0, b"new\n").unwrap();
+
+    let fs = Ext4Filesystem::mount(device, true).unwrap();
+    let mut expected = sparse_contents();
+    expected[1124..1130].copy_from_slice(b"filled");
+    assert_eq!(read_all(&fs, root, "sparse.bin"), expected);
+    let mut expected = vec![0u8; 8192];
+    expected[3072..3079].copy_from_slice(b"written");
+    assert_eq!(read_all(&fs, root, "prealloc"), expected);
+    assert_eq!(names(&fs, many).len(), 103);
+    assert_eq!(read_all(&fs, many, "file-new"), b"new\n");
+    assert_eq!(read_all(&fs, many, "file-099"), b"99\n");
+}
@@ -0,0 +1,108 @@
+use qunix_host_tests::fs::fat32::Fat32Filesystem;
+use qunix_host_tests::fs::vfs::Filesystem;
+use qunix_host_tests::fs::{FileMode, FsError};
+use qunix_host_tests::image::{self, fixture};
+
+fn mount() -> Fat32Filesystem {
+    let device = image::open(fixture("fat32-small.img"), 512).expect("missing fixture");
+    Fat32Filesystem::mount(image::shared(device), true).expect("mount failed")
+}
+
+fn read_all(fs: &Fat32Filesystem, parent: u64, name: &str) -> Vec<u8> {
+    let node = fs.lookup(parent, name).unwrap();
+    let mut buf = vec![0u8; node.size as usize + 4096];
+    fs.read(node.inode, 0, &mut buf).unwrap();
+    buf.truncate(node.size as usize);
+    buf
+}
+
+#[test]
+fn root_listing() {
+    let fs = mount();
+    let root = fs.root().unwrap();
+    let mut names: Vec<String> = fs.readdir(root.inode).unwrap().into_iter().map(|e| e.name).collect();
+    names.sort();
+    assert_eq!(names, ["A Long File Name.txt", "HELLO.TXT", "SUBDIR"]);
+}
+
+#[test]
+fn read_files() {
+    let fs = mount();
+    let root = fs.root().unwrap();
+    let mut buf = [0u8; 512];
+
+    let hello = fs.lookup(root.inode, "hello.txt").unwrap();
+    assert_eq!(hello.size, 17);
+    let n = fs.read(hello.inode, 0, &mut buf).unwrap();
+    assert_eq!(&buf[..hello.size as usize], b"hello from fat32\n");
+    assert!(n >= hello.size as usize);
+
+    let long = fs.lookup(root.inode, "A Long File Name.txt").unwrap();
+    fs.read(long.inode, 0, &mut buf).unwrap();
+    assert_eq!(&buf[..long.size as usize], b"long name file\n");
+
+    let subdir = fs.lookup(root.inode, "SUBDIR").unwrap();
+    let nested = fs.lookup(subdir.inode, "NESTED.TXT").unwrap();
+    fs.read(nested.inode, 0, &mut buf).unwrap();
+    assert_eq!(&buf[..nested.size as usize], b"nested file\n");
+
+    assert_eq!(fs.lookup(root.inode, "missing").err(), Some(FsError::NotFound));
+}
+
+#[test]
+fn writes_reach_the_disk() {
+    let device = image::shared(image::open(fixture("fat32-small.img"), 512).expect("missing fixture"));
+    let mut fs = Fat32Filesystem::mount(device.clone(), false).unwrap();
+    let root = fs.root().unwrap().inode;
+
+    // Larger than a cluster, then grown past its end with a gap
+    let data: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+    let file = fs.create(root, "kernel.a", FileMode::new(0o644)).unwrap();
+    assert_eq!(fs.write(file.inode, 0, &data).unwrap(), data.len());
+    assert_eq!(fs.write(file.inode, 5000, b"tail").unwrap(), 4);
+    fs.write(file.inode, 10, b"patched").unwrap();
+
+    let subdir = fs.lookup(root, "SUBDIR").unwrap().inode;
+    let dir = fs.mkdir(subdir, "boot", FileMode::new(0o755)).unwrap();
+    fs.create(dir.inode, "state", FileMode::new(0o644)).unwrap();
+    assert_eq!(fs.create(root, "KERNEL.A", FileMode::new(0o644)).err(), Some(FsError::AlreadyExists));
+    assert_eq!(fs.create(root, "too-long-a-name.txt", FileMode::new(0o644)).err(), Some(FsError::InvalidArgument));
+
+    // A fresh mount sees only what was written out
+    let mut fs = Fat32Filesystem::mount(device.clone(), false).unwrap();
+    let mut expected = data.clone();
+    expected[10..17].copy_from_slice(b"patched");
+    expected.resize(5000, 0);
+    expected.extend_from_slice(b"tail");
+    assert_eq!(read_all(&fs, root, "KERNEL.A"), expected);
+    assert_eq!(read_all(&fs, root, "hello.txt"), b"hello from fat32\n");
+    let subdir = fs.lookup(root, "SUBDIR").unwrap().inode;
+    let boot = fs.lookup(subdir, "BOOT").unwrap();
+    let names: Vec<String> = fs.readdir(boot.inode).unwrap().into_iter().map(|e| e.name).collect();
+    assert_eq!(names, ["STATE"]);
+
+    // Renaming replaces the target and keeps the data; removing frees it
+    fs.readdir(root).unwrap();
+    fs.rename(root, "hello.txt", subdir, "old.txt").unwrap();
+    fs.rename(root, "KERNEL.A", subdir, "OLD.TXT").unwrap();
+    assert_eq!(fs.rmdir(subdir, "BOOT").err(), Some(FsError::NotEmpty));
+    fs.unlink(boot.inode, "STATE").unwrap();
+    fs.rmdir(subdir, "BOOT").unwrap();
+
+    let fs = Fat32Filesystem::mount(device, true).unwrap();
+    let mut names: Vec<String> = fs.readdir(root).unwrap().into_iter().map(|e| e.name).collect();
+    names.sort();
+    assert_eq!(names, ["A Long File Name.txt", "SUBDIR"]);
+    let mut names: Vec<String> = fs.readdir(subdir).unwrap().into_iter().map(|e| e.name).collect();
+    names.sort();
+    assert_eq!(names, ["NESTED.TXT", "OLD.TXT"]);
+    assert_eq!(read_all(&fs, subdir, "OLD.TXT"), expected);
+}
+
+#[test]
+fn read_only_mounts_refuse_writes() {
+    let mut fs = mount();
+    let root = fs.root().unwrap().inode;
+    assert_eq!(fs.create(root, "NEW.TXT", FileMode::new(0o644)).err(), Some(FsError::ReadOnly));
+    assert_eq!(fs.unlink(root, "HELLO.TXT").err(), Some(FsError::ReadOnly));
+}
@@ -0,0 +1,202 @@
+use qunix_host_tests::fs::ext4::{parse_dir_entry, parse_group_desc, parse_inode, parse_superblock, Ext4Filesystem};
+use qunix_host_tests::fs::fat32::{parse_bpb, parse_lfn_entry, Fat32Filesystem};
+use qunix_host_tests::fs::vfs::Filesystem;
+use qunix_host_tests::fs::FsError;
+use qunix_host_tests::fs::ramdisk::RamDisk;
+use qunix_host_tests::image::{self, fixture};
+
+fn ext4_image() -> Vec<u8> {
+    std::fs::read(fixture("ext4-small.img")).expect("missing fixture")
+}
+
+fn fat32_image() -> Vec<u8> {
+    std::fs::read(fixture("fat32-small.img")).expect("missing fixture")
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn superblock() -> Vec<u8> {
+    ext4_image()[1024..2048].to_vec()
+}
+
+#[test]
+fn ext4_superblock_accepts_fixture() {
+    let sb = parse_superblock(&superblock()).unwrap();
+    assert_eq!(sb.block_size(), 1024);
+    assert_eq!(sb.desc_size(), 32);
+}
+
+#[test]
+fn ext4_superblock_rejects_bad_geometry() {
+    let cases: [(usize, u32); 6] = [
+        (24, 7),     // s_log_block_size: 128 KiB blocks
+        (24, 40),    // shift past the width of u32
+        (32, 0),     // s_blocks_per_group
+        (40, 0),     // s_inodes_per_group
+        (40, 8193),  // more inodes per group than bitmap bits
+        (0, u32::MAX), // s_inodes_count beyond what the groups hold
+    ];
+    for (offset, value) in cases {
+        let mut buf = superblock();
+        put_u32(&mut buf, offset, value);
+        assert_eq!(parse_superblock(&buf).err(), Some(FsError::InvalidArgument), "offset {}", offset);
+    }
+
+    for inode_size in [0u16, 64, 200, 2048] {
+        let mut buf = superblock();
+        put_u16(&mut buf, 88, inode_size);
+        assert!(parse_superblock(&buf).is_err(), "inode size {}", inode_size);
+    }
+
+    let mut buf = superblock();
+    put_u16(&mut buf, 56, 0x1234);
+    assert!(parse_superblock(&buf).is_err());
+    assert!(parse_superblock(&superblock()[..1000]).is_err());
+}
+
+#[test]
+fn ext4_group_desc_bounds() {
+    let sb = parse_superblock(&superblock()).unwrap();
+    let image = ext4_image();
+    let table = &image[2048..2048 + 32];
+    let desc = parse_group_desc(table, &sb).unwrap();
+    assert!(desc.inode_table() > 0);
+
+    let mut bad = table.to_vec();
+    put_u32(&mut bad, 8, sb.blocks_count() as u32); // bg_inode_table_lo
+    assert_eq!(parse_group_desc(&bad, &sb).err(), Some(FsError::IoError));
+    assert!(parse_group_desc(&table[..16], &sb).is_err());
+}
+
+#[test]
+fn ext4_inode_and_dirent() {
+    let mut raw = vec![0u8; 256];
+    put_u16(&mut raw, 0, 0o100644);
+    put_u16(&mut raw, 128, 32); // i_extra_isize
+    put_u32(&mut raw, 144, 7); // i_crtime
+    assert_eq!({ parse_inode(&raw, 256).unwrap().i_crtime }, 7);
+    // 128-byte inodes have no extra fields, whatever follows in the buffer
+    assert_eq!({ parse_inode(&raw, 128).unwrap().i_crtime }, 0);
+    assert!(parse_inode(&raw[..200], 256).is_err());
+    put_u16(&mut raw, 128, 200);
+    assert!(parse_inode(&raw, 256).is_err());
+
+    let mut block = vec![0u8; 64];
+    put_u32(&mut block, 0, 11);
+    put_u16(&mut block, 4, 64);
+    block[6] = 5;
+    block[7] = 1;
+    block[8..13].copy_from_slice(b"hello");
+    let (entry, name) = parse_dir_entry(&block, 0).unwrap();
+    assert_eq!({ entry.inode }, 11);
+    assert_eq!(name, b"hello");
+
+    for rec_len in [0u16, 6, 10, 68, 12] {
+        put_u16(&mut block, 4, rec_len);
+        assert!(parse_dir_entry(&block, 0).is_err(), "rec_len {}", rec_len);
+    }
+    assert!(parse_dir_entry(&block, 60).is_err());
+    assert!(parse_dir_entry(&block, usize::MAX).is_err());
+}
+
+#[test]
+fn ext4_corrupt_directory_is_an_error() {
+    let mut image = ext4_image();
+    let device = image::shared(RamDisk::from_image(&image, 1024));
+    let fs = Ext4Filesystem::mount(device, true).unwrap();
+    let root = fs.root().unwrap();
+    let entries = fs.readdir(root.inode).unwrap();
+    assert!(!entries.is_empty());
+
+    // Find the root directory block by its "." / ".." header and zero
+    // the first rec_len, which used to stop the walk silently.
+    let block = (0..image.len() / 1024)
+        .find(|&b| {
+            let d = &image[b * 1024..];
+            d[0..4] == 2u32.to_le_bytes() && d[6] == 1 && d[8] == b'.' && d[12..16] == 2u32.to_le_bytes()
+        })
+        .expect("root directory block");
+    put_u16(&mut image, block * 1024 + 4, 0);
+    let fs = Ext4Filesystem::mount(image::shared(RamDisk::from_image(&image, 1024)), true).unwrap();
+    assert_eq!(fs.readdir(root.inode).err(), Some(FsError::IoError));
+
+    // A volume larger than its device is refused
+    let truncated = RamDisk::from_image(&ext4_image()[..64 * 1024], 1024);
+    assert_eq!(Ext4Filesystem::mount(image::shared(truncated), true).err(), Some(FsError::InvalidArgument));
+}
+
+#[test]
+fn fat32_bpb_validation() {
+    let image = fat32_image();
+    let sector = &image[..512];
+    let bpb = parse_bpb(sector).unwrap();
+    assert_eq!({ bpb.root_cluster }, 2);
+
+    let cases: [(usize, u32, usize); 7] = [
+        (11, 1000, 2), // bytes_per_sector not a power of two
+        (13, 3, 1),    // sectors_per_cluster
+        (14, 0, 2),    // reserved_sector_count
+        (16, 0, 1),    // num_fats
+        (36, 0, 4),    // fat_size_32
+        (44, 1, 4),    // root_cluster below the data area
+        (44, 0x0FFFFFF0, 4),
+    ];
+    for (offset, value, width) in cases {
+        let mut buf = sector.to_vec();
+        buf[offset..offset + width].copy_from_slice(&value.to_le_bytes()[..width]);
+        assert_eq!(parse_bpb(&buf).err(), Some(FsError::InvalidArgument), "offset {}", offset);
+    }
+
+    let mut buf = sector.to_vec();
+    buf[510] = 0;
+    assert!(parse_bpb(&buf).is_err());
+    assert!(parse_bpb(&sector[..100]).is_err());
+}
+
+#[test]
+fn fat32_mismatched_long_names_fall_back() {
+    let mut lfn = [0u8; 32];
+    lfn[0] = 0x41;
+    lfn[11] = 0x0F;
+    assert!(parse_lfn_entry(&lfn).is_ok());
+    lfn[0] = 0x40;
+    assert!(parse_lfn_entry(&lfn).is_err());
+    lfn[0] = 0x55;
+    assert!(parse_lfn_entry(&lfn).is_err());
+
+    // Break the checksum of every long-name entry: names revert to 8.3
+    let mut image = fat32_image();
+    let fs = Fat32Filesystem::mount(image::shared(RamDisk::from_image(&image, 512)), true).unwrap();
+    let root = fs.root().unwrap().inode;
+    assert!(fs.lookup(root, "A Long File Name.txt").is_ok());
+
+    for entry in image.chunks_exact_mut(32) {
+        if entry[11] == 0x0F && entry[12] == 0 && entry[26..28] == [0, 0] {
+            entry[13] ^= 0xFF;
+        }
+    }
+    let fs = Fat32Filesystem::mount(image::shared(RamDisk::from_image(&image, 512)), true).unwrap();
+    assert_eq!(fs.lookup(root, "A Long File Name.txt").err(), Some(FsError::NotFound));
+    assert!(fs.readdir(root).unwrap().iter().any(|e| e.name.contains('~')));
+}
+
+#[test]
+fn fat32_unset_and_corrupt_dates() {
+    let mut image = fat32_image();
+    let entry = image
+        .chunks_exact(32)
+        .position(|e| &e[..11] == b"HELLO   TXT")
+        .expect("HELLO.TXT entry")
+        * 32;
+    image[entry + 14..entry + 20].fill(0);
+    put_u16(&mut image, entry + 24, (20 << 9) | (13 << 5) | 1); // month 13
+    let fs = Fat32Filesystem::mount(image::shared(RamDisk::from_image(&image, 512)), true).unwrap();
+    let hello = fs.lookup(fs.root().unwrap().inode, "HELLO.TXT").unwrap();
+    assert_eq!((hello.ctime, hello.mtime, hello.atime), (0, 0, 0));
+}
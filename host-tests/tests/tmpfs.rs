@@ -0,0 +1,179 @@
+use std::sync::Arc;
+use qunix_host_tests::fs::tmpfs::{Options, Tmpfs, ROOT_INO};
+use qunix_host_tests::fs::vfs::{DeviceId, FallocateFlags, Filesystem, VirtualFileSystem, VfsNodeData};
+use qunix_host_tests::fs::{FileMode, FileType, FsError};
+
+fn mode(bits: u16) -> FileMode {
+    FileMode::new(bits)
+}
+
+fn names(fs: &Tmpfs, dir: u64) -> Vec<String> {
+    let mut names: Vec<String> = fs.readdir(dir).unwrap().into_iter().map(|e| e.name).collect();
+    names.sort();
+    names
+}
+
+/// A VFS with a tmpfs of `options` mounted on /tmp, and that tmpfs
+fn mounted(options: Options) -> (VirtualFileSystem, Arc<spin::RwLock<Tmpfs>>) {
+    let fs = Arc::new(spin::RwLock::new(Tmpfs::new(options, false)));
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/tmp", mode(0o1777)).unwrap();
+    vfs.create_directory("/home", mode(0o755)).unwrap();
+    vfs.graft("/tmp", fs.clone()).unwrap();
+    (vfs, fs)
+}
+
+#[test]
+fn parses_options() {
+    assert_eq!(Options::parse("").unwrap(), Options::default());
+    assert_eq!(Options::parse("size=4m,nr_inodes=64").unwrap(), Options { size: 4 << 20, inodes: 64 });
+    assert_eq!(Options::parse("size=10k").unwrap().size, 10240);
+    assert_eq!(Options::parse("size=2G").unwrap().size, 2 << 30);
+    for bad in ["size=", "size=lots", "nr_inodes=0", "mode=1777", "size=99999999999g"] {
+        assert_eq!(Options::parse(bad).err(), Some(FsError::InvalidArgument), "{}", bad);
+    }
+}
+
+#[test]
+fn files_and_directories() {
+    let mut fs = Tmpfs::new(Options::default(), false);
+    let dir = fs.mkdir(ROOT_INO, "dir", mode(0o755)).unwrap().inode;
+    let file = fs.create(dir, "file", mode(0o644)).unwrap().inode;
+    assert_eq!(fs.write(file, 0, b"hello").unwrap(), 5);
+    assert_eq!(fs.write(file, 10, b"world").unwrap(), 5);
+    let mut buf = [0xffu8; 32];
+    assert_eq!(fs.read(file, 0, &mut buf).unwrap(), 15);
+    assert_eq!(&buf[..15], b"hello\0\0\0\0\0world");
+    fs.symlink(dir, "link", "file").unwrap();
+    assert!(matches!(fs.lookup(dir, "link").unwrap().data, VfsNodeData::Symlink(ref t) if t == "file"));
+
+    assert_eq!(names(&fs, dir), [".", "..", "file", "link"]);
+    assert_eq!(fs.stat(ROOT_INO).unwrap().nlink, 3);
+    assert_eq!(fs.stat(file).unwrap().mode.file_type(), FileType::Regular);
+    assert_eq!(fs.create(dir, "file", mode(0o644)).err(), Some(FsError::AlreadyExists));
+    assert_eq!(fs.create(dir, "a/b", mode(0o644)).err(), Some(FsError::InvalidArgument));
+    assert_eq!(fs.create(file, "x", mode(0o644)).err(), Some(FsError::NotDirectory));
+    assert_eq!(fs.rmdir(ROOT_INO, "dir").err(), Some(FsError::NotEmpty));
+    assert_eq!(fs.unlink(ROOT_INO, "dir").err(), Some(FsError::IsDirectory));
+
+    // Renames replace their own kind, and never move a directory under itself
+    let other = fs.mkdir(ROOT_INO, "other", mode(0o755)).unwrap().inode;
+    fs.rename(dir, "file", other, "moved").unwrap();
+    assert_eq!(fs.lookup(other, "moved").unwrap().inode, file);
+    assert_eq!(fs.rename(dir, "link", other, "moved").map(|_| names(&fs, other)).unwrap(), [".", "..", "moved"]);
+    assert_eq!(fs.rename(ROOT_INO, "dir", other, "moved").err(), Some(FsError::NotDirectory));
+    assert_eq!(fs.rename(ROOT_INO, "other", other, "inside").err(), Some(FsError::InvalidArgument));
+    fs.rename(ROOT_INO, "dir", other, "dir").unwrap();
+    assert_eq!(fs.readdir(dir).unwrap()[1].inode, other);
+    assert_eq!(fs.stat(ROOT_INO).unwrap().nlink, 3);
+
+    let mut ro = Tmpfs::new(Options::default(), true);
+    assert_eq!(ro.create(ROOT_INO, "x", mode(0o644)).err(), Some(FsError::ReadOnly));
+}
+
+#[test]
+fn limits_are_enforced() {
+    let mut fs = Tmpfs::new(Options { size: 8192, inodes: 4 }, false);
+    let file = fs.create(ROOT_INO, "file", mode(0o644)).unwrap().inode;
+    fs.write(file, 0, &[1; 8192]).unwrap();
+    assert_eq!(fs.usage(), (8192, 2));
+
+    // Full: rewriting what is there still works, growing does not
+    assert_eq!(fs.write(file, 8192, b"x").err(), Some(FsError::NoSpace));
+    fs.write(file, 100, b"rewritten").unwrap();
+    assert_eq!(fs.stat(file).unwrap().size, 8192);
+
+    // Space comes back by truncating, punching holes and unlinking
+    fs.truncate(file, 4096).unwrap();
+    assert_eq!(fs.usage().0, 4096);
+    let punch = FallocateFlags::PUNCH_HOLE | FallocateFlags::KEEP_SIZE;
+    fs.fallocate(file, punch, 0, 4096).unwrap();
+    assert_eq!((fs.usage().0, fs.stat(file).unwrap().size), (0, 4096));
+    assert_eq!(fs.fallocate(file, FallocateFlags::empty(), 0, 12288).err(), Some(FsError::NoSpace));
+    fs.fallocate(file, FallocateFlags::empty(), 0, 8192).unwrap();
+    assert_eq!(fs.stat(file).unwrap().blocks, 16);
+    fs.unlink(ROOT_INO, "file").unwrap();
+    assert_eq!(fs.usage(), (0, 1));
+
+    // The root counts against the inodes
+    for name in ["a", "b", "c"] {
+        fs.mkdir(ROOT_INO, name, mode(0o755)).unwrap();
+    }
+    assert_eq!(fs.symlink(ROOT_INO, "d", "a").err(), Some(FsError::NoSpace));
+}
+
+#[test]
+fn vfs_goes_through_the_mount_point() {
+    let (mut vfs, fs) = mounted(Options::default());
+
+    // What is made under the mount point is made in the filesystem
+    vfs.create_directory("/tmp/a", mode(0o755)).unwrap();
+    vfs.create_directory("/tmp/a/b", mode(0o700)).unwrap();
+    let file = vfs.create_file("/tmp/a/b/file", mode(0o600)).unwrap().inode;
+    vfs.write_node(file, 0, b"through the mount").unwrap();
+    vfs.create_symlink("/tmp/a/link", "b/file").unwrap();
+    {
+        let fs = fs.read();
+        let a = fs.lookup(ROOT_INO, "a").unwrap().inode;
+        let b = fs.lookup(a, "b").unwrap();
+        assert_eq!(b.mode.permissions(), 0o700);
+        let remote = fs.lookup(b.inode, "file").unwrap().inode;
+        let mut buf = [0u8; 32];
+        assert_eq!(fs.read(remote, 0, &mut buf).unwrap(), 17);
+        assert_eq!(&buf[..17], b"through the mount");
+        assert_eq!(names(&fs, a), [".", "..", "b", "link"]);
+    }
+    assert_eq!(vfs.get_node(file).unwrap().size, 17);
+    assert_eq!(vfs.canonicalize("/tmp/a/link").unwrap(), "/tmp/a/b/file");
+
+    // Truncating and renaming go there too; moving out of it cannot
+    vfs.truncate("/tmp/a/b/file", 7).unwrap();
+    vfs.rename("/tmp/a/b/file", "/tmp/file").unwrap();
+    assert_eq!(fs.read().stat(fs.read().lookup(ROOT_INO, "file").unwrap().inode).unwrap().size, 7);
+    assert_eq!(vfs.rename("/tmp/file", "/home/file").err(), Some(FsError::CrossDevice));
+    vfs.create_file("/home/file", mode(0o644)).unwrap();
+    assert_eq!(vfs.rename("/home/file", "/tmp/file").err(), Some(FsError::CrossDevice));
+    assert!(vfs.lookup_path("/tmp/file").is_ok());
+    assert_eq!(vfs.create_device("/tmp/null", DeviceId::new(1, 3), mode(0o666)).err(), Some(FsError::NotSupported));
+
+    // Grafted again, the tree is what the filesystem kept
+    vfs.remove_file("/tmp/a/link").unwrap();
+    vfs.remove_directory("/tmp/a/b").unwrap();
+    vfs.ungraft("/tmp").unwrap();
+    assert_eq!(vfs.lookup_path("/tmp/file").err(), Some(FsError::NotFound));
+    vfs.graft("/tmp", fs.clone()).unwrap();
+    let file = vfs.lookup_path("/tmp/file").unwrap();
+    let mut buf = [0u8; 16];
+    assert_eq!(file.read(0, &mut buf).unwrap(), 7);
+    assert_eq!(&buf[..7], b"through");
+    assert_eq!(vfs.lookup_path("/tmp/a").unwrap().readdir().unwrap().len(), 2);
+    assert_eq!(fs.read().usage().1, 3);
+}
+
+#[test]
+fn vfs_sees_the_size_limit() {
+    let (mut vfs, fs) = mounted(Options { size: 16384, inodes: 8 });
+    let file = vfs.create_file("/tmp/big", mode(0o644)).unwrap().inode;
+    vfs.write_node(file, 0, &[7; 16384]).unwrap();
+    assert_eq!(vfs.write_node(file, 16384, b"more").err(), Some(FsError::NoSpace));
+    assert_eq!(vfs.get_node(file).unwrap().size, 16384);
+
+    // A file removed while open is kept in memory, not in the filesystem
+    vfs.hold(file);
+    vfs.remove_file("/tmp/big").unwrap();
+    assert_eq!(fs.read().usage(), (0, 1));
+    let mut buf = [0u8; 4];
+    assert_eq!(vfs.get_node(file).unwrap().read(16380, &mut buf).unwrap(), 4);
+    assert_eq!(buf, [7; 4]);
+    vfs.unhold(file);
+
+    let file = vfs.create_file("/tmp/prealloc", mode(0o644)).unwrap().inode;
+    vfs.fallocate(file, FallocateFlags::empty(), 0, 8192).unwrap();
+    assert_eq!(vfs.get_node(file).unwrap().size, 8192);
+    assert_eq!(vfs.fallocate(file, FallocateFlags::KEEP_SIZE, 8192, 12288).err(), Some(FsError::NoSpace));
+    for i in 0..6 {
+        vfs.create_directory(&format!("/tmp/d{}", i), mode(0o755)).unwrap();
+    }
+    assert_eq!(vfs.create_file("/tmp/one-more", mode(0o644)).err(), Some(FsError::NoSpace));
+    assert_eq!(vfs.lookup_path("/tmp/one-more").err(), Some(FsError::NotFound));
+}
@@ -0,0 +1,582 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use qunix_host_tests::fs::quota::{Limits, QuotaType};
+use qunix_host_tests::fs::vfs::{AccessMode, VirtualFileSystem, VfsNodeData, DeviceId, FallocateFlags};
+use qunix_host_tests::fs::{FileMode, FileType, FsError};
+use qunix_host_tests::hal::drivers::{hotplug, serial};
+
+fn mode(bits: u16) -> FileMode {
+    FileMode::new(bits)
+}
+
+#[test]
+fn resolve_path_normalizes() {
+    let mut vfs = VirtualFileSystem::new();
+    assert_eq!(vfs.resolve_path("/a//b/./c/"), "/a/b/c");
+    assert_eq!(vfs.resolve_path("/a/b/../../.."), "/");
+    vfs.create_directory("/home", mode(0o755)).unwrap();
+    vfs.set_cwd("/home").unwrap();
+    assert_eq!(vfs.resolve_path("user/../x"), "/home/x");
+    assert_eq!(vfs.resolve_path(".."), "/");
+}
+
+#[test]
+fn path_limits() {
+    use qunix_host_tests::fs::vfs::path::{self, NAME_MAX, PATH_MAX};
+
+    let longest = "n".repeat(NAME_MAX);
+    let too_long = "n".repeat(NAME_MAX + 1);
+    assert_eq!(path::from_bytes(b"/etc/passwd"), Ok("/etc/passwd"));
+    assert_eq!(path::from_bytes(b""), Err(FsError::NotFound));
+    assert_eq!(path::from_bytes(b"/etc/\xff"), Err(FsError::InvalidArgument));
+    assert_eq!(path::validate("/etc\0/passwd"), Err(FsError::InvalidArgument));
+    assert_eq!(path::validate(&format!("/{}", too_long)), Err(FsError::NameTooLong));
+    let deep = format!("/{}", ["d"; PATH_MAX / 2].join("/"));
+    assert_eq!(path::validate(&deep[..PATH_MAX - 1]), Ok(()));
+    assert_eq!(path::validate(&deep[..PATH_MAX]), Err(FsError::NameTooLong));
+
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_file(&format!("/{}", longest), mode(0o644)).unwrap();
+    assert!(vfs.lookup_path(&format!("/{}", longest)).is_ok());
+    assert_eq!(vfs.create_file(&format!("/{}", too_long), mode(0o644)).err(), Some(FsError::NameTooLong));
+    assert_eq!(vfs.create_directory(&format!("/{}", too_long), mode(0o755)).err(), Some(FsError::NameTooLong));
+    assert_eq!(vfs.lookup_path(&format!("/{}", too_long)).err(), Some(FsError::NameTooLong));
+    assert_eq!(vfs.rename(&format!("/{}", longest), &format!("/{}", too_long)).err(), Some(FsError::NameTooLong));
+}
+
+#[test]
+fn mount_points_end_at_components() {
+    use qunix_host_tests::fs::mount::is_within;
+
+    assert!(is_within("/mnt/usb", "/mnt/usb"));
+    assert!(is_within("/mnt/usb/docs", "/mnt/usb/"));
+    assert!(is_within("/mnt", "/"));
+    assert!(!is_within("/mnt/usb2", "/mnt/usb"));
+    assert!(!is_within("/mnt", "/mnt/usb"));
+}
+
+#[test]
+fn chroot_confines_paths() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/srv", mode(0o755)).unwrap();
+    vfs.create_directory("/srv/etc", mode(0o755)).unwrap();
+    vfs.create_file("/etc", mode(0o644)).unwrap();
+    assert_eq!(vfs.set_root("/srv/").unwrap(), "/srv");
+    assert_eq!(vfs.get_cwd(), "/");
+    assert_eq!(vfs.resolve_path("/../../etc"), "/srv/etc");
+    assert!(vfs.lookup_path("/etc").unwrap().is_dir());
+    vfs.create_file("/etc/hosts", mode(0o644)).unwrap();
+    assert!(vfs.lookup_resolved("/srv/etc/hosts").is_ok());
+    vfs.set_cwd("etc").unwrap();
+    assert_eq!(vfs.canonicalize("../etc/hosts").unwrap(), "/srv/etc/hosts");
+}
+
+#[test]
+fn create_lookup_and_readdir() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/etc", mode(0o755)).unwrap();
+    let file = vfs.create_file("/etc/passwd", mode(0o644)).unwrap();
+    vfs.write_node(file.inode, 0, b"root:x:0:0\n").unwrap();
+
+    let node = vfs.lookup_path("/etc/passwd").unwrap();
+    assert_eq!(node.size, 11);
+    assert_eq!(node.mode.0 & 0o7777, 0o644);
+    match &node.data {
+        VfsNodeData::Regular(data) => assert_eq!(data, b"root:x:0:0\n"),
+        _ => panic!("not a regular file"),
+    }
+
+    let etc = vfs.lookup_path("/etc").unwrap();
+    assert!(etc.is_dir());
+    assert_eq!(vfs.create_file("/etc/passwd", mode(0o644)).err(), Some(FsError::AlreadyExists));
+    assert_eq!(vfs.lookup_path("/etc/shadow").err(), Some(FsError::NotFound));
+    assert_eq!(vfs.create_file("/etc/passwd/x", mode(0o644)).err(), Some(FsError::NotDirectory));
+}
+
+#[test]
+fn remove_and_rename() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/d", mode(0o755)).unwrap();
+    vfs.create_file("/d/f", mode(0o644)).unwrap();
+
+    assert_eq!(vfs.remove_directory("/d").err(), Some(FsError::NotEmpty));
+    assert_eq!(vfs.remove_file("/d").err(), Some(FsError::IsDirectory));
+
+    vfs.rename("/d/f", "/g").unwrap();
+    assert!(vfs.lookup_path("/d/f").is_err());
+    assert_eq!(vfs.lookup_path("/g").unwrap().name, "g");
+
+    vfs.remove_directory("/d").unwrap();
+    vfs.remove_file("/g").unwrap();
+    assert!(vfs.lookup_path("/g").is_err());
+}
+
+#[test]
+fn rename_across_directories() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/a", mode(0o755)).unwrap();
+    vfs.create_directory("/a/sub", mode(0o755)).unwrap();
+    vfs.create_directory("/b", mode(0o755)).unwrap();
+    let sub = vfs.lookup_path("/a/sub").unwrap().inode;
+
+    // A directory moves with its .. and its parents' link counts
+    vfs.rename("/a/sub", "/b/sub").unwrap();
+    assert_eq!(vfs.lookup_path("/b/sub/..").unwrap().inode, vfs.lookup_path("/b").unwrap().inode);
+    assert_eq!((vfs.lookup_path("/a").unwrap().nlink, vfs.lookup_path("/b").unwrap().nlink), (2, 3));
+
+    // Not under itself, and a failed rename changes nothing
+    assert_eq!(vfs.rename("/b", "/b/sub/b").err(), Some(FsError::InvalidArgument));
+    assert_eq!(vfs.rename("/b", "/b/b").err(), Some(FsError::InvalidArgument));
+    assert_eq!(vfs.rename("/b/sub", "/c/sub").err(), Some(FsError::NotFound));
+    assert_eq!(vfs.lookup_path("/b/sub").unwrap().inode, sub);
+
+    // Replacing: a file by a file, an empty directory by a directory
+    vfs.create_file("/a/f", mode(0o644)).unwrap();
+    vfs.create_file("/b/g", mode(0o644)).unwrap();
+    let f = vfs.lookup_path("/a/f").unwrap().inode;
+    let g = vfs.lookup_path("/b/g").unwrap().inode;
+    vfs.rename("/a/f", "/b/g").unwrap();
+    assert_eq!(vfs.lookup_path("/b/g").unwrap().inode, f);
+    assert_eq!(vfs.get_node(g).err(), Some(FsError::NotFound));
+    vfs.create_directory("/a/empty", mode(0o755)).unwrap();
+    assert_eq!(vfs.rename("/b/g", "/a/empty").err(), Some(FsError::IsDirectory));
+    assert_eq!(vfs.rename("/a/empty", "/b/g").err(), Some(FsError::NotDirectory));
+    vfs.create_file("/b/sub/x", mode(0o644)).unwrap();
+    assert_eq!(vfs.rename("/a/empty", "/b/sub").err(), Some(FsError::NotEmpty));
+    vfs.rename("/b/sub", "/a/empty").unwrap();
+    assert_eq!(vfs.lookup_path("/a/empty/x").unwrap().name, "x");
+    assert_eq!((vfs.lookup_path("/a").unwrap().nlink, vfs.lookup_path("/b").unwrap().nlink), (3, 2));
+
+    // Onto itself is a no-op
+    vfs.rename("/b/g", "/b/g").unwrap();
+    assert_eq!(vfs.lookup_path("/b/g").unwrap().inode, f);
+}
+
+#[test]
+fn rename_onto_another_link_is_a_no_op() {
+    use qunix_host_tests::fs::tmpfs::{Options, Tmpfs, ROOT_INO};
+    use qunix_host_tests::fs::vfs::{DirEntry, Filesystem, InodeNumber, VfsNode};
+    use qunix_host_tests::fs::{FileStat, FsResult};
+    use std::sync::Arc;
+
+    /// A tmpfs whose root has `alias` as a second link to `file`
+    struct Linked(Tmpfs);
+
+    impl Linked {
+        fn real(parent: InodeNumber, name: &str) -> &str {
+            if parent == ROOT_INO && name == "alias" { "file" } else { name }
+        }
+    }
+
+    impl Filesystem for Linked {
+        fn name(&self) -> &str { "linked" }
+        fn root(&self) -> FsResult<VfsNode> { self.0.root() }
+        fn lookup(&self, parent: InodeNumber, name: &str) -> FsResult<VfsNode> {
+            let mut node = self.0.lookup(parent, Self::real(parent, name))?;
+            node.name = name.to_string();
+            Ok(node)
+        }
+        fn read(&self, inode: InodeNumber, offset: u64, buf: &mut [u8]) -> FsResult<usize> { self.0.read(inode, offset, buf) }
+        fn write(&mut self, inode: InodeNumber, offset: u64, buf: &[u8]) -> FsResult<usize> { self.0.write(inode, offset, buf) }
+        fn create(&mut self, parent: InodeNumber, name: &str, mode: FileMode) -> FsResult<VfsNode> { self.0.create(parent, name, mode) }
+        fn mkdir(&mut self, parent: InodeNumber, name: &str, mode: FileMode) -> FsResult<VfsNode> { self.0.mkdir(parent, name, mode) }
+        fn unlink(&mut self, parent: InodeNumber, name: &str) -> FsResult<()> { self.0.unlink(parent, Self::real(parent, name)) }
+        fn rmdir(&mut self, parent: InodeNumber, name: &str) -> FsResult<()> { self.0.rmdir(parent, name) }
+        fn rename(&mut self, old_parent: InodeNumber, old_name: &str, new_parent: InodeNumber, new_name: &str) -> FsResult<()> {
+            self.0.rename(old_parent, Self::real(old_parent, old_name), new_parent, Self::real(new_parent, new_name))
+        }
+        fn stat(&self, inode: InodeNumber) -> FsResult<FileStat> { self.0.stat(inode) }
+        fn readdir(&self, inode: InodeNumber) -> FsResult<Vec<DirEntry>> {
+            let mut entries = self.0.readdir(inode)?;
+            if let Some(file) = entries.iter().find(|e| inode == ROOT_INO && e.name == "file").cloned() {
+                entries.push(DirEntry::new("alias".into(), file.inode, file.file_type));
+            }
+            Ok(entries)
+        }
+        fn sync(&mut self) -> FsResult<()> { Ok(()) }
+    }
+
+    let mut fs = Tmpfs::new(Options::default(), false);
+    fs.create(ROOT_INO, "file", mode(0o644)).unwrap();
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/mnt", mode(0o755)).unwrap();
+    let fs = Arc::new(spin::RwLock::new(Linked(fs)));
+    vfs.graft("/mnt", fs.clone()).unwrap();
+
+    // Both names stay, in the VFS and in the filesystem
+    vfs.rename("/mnt/file", "/mnt/alias").unwrap();
+    assert!(vfs.lookup_path("/mnt/file").is_ok());
+    assert!(vfs.lookup_path("/mnt/alias").is_ok());
+    assert!(fs.read().0.lookup(ROOT_INO, "file").is_ok());
+}
+
+#[test]
+fn canonicalize_follows_symlinks() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/etc", mode(0o755)).unwrap();
+    vfs.create_file("/etc/shadow", mode(0o600)).unwrap();
+    vfs.create_directory("/tmp", mode(0o777)).unwrap();
+    vfs.create_symlink("/tmp/s", "/etc").unwrap();
+    vfs.create_symlink("/tmp/rel", "../etc/shadow").unwrap();
+
+    assert_eq!(vfs.canonicalize("/tmp/s/shadow").unwrap(), "/etc/shadow");
+    assert_eq!(vfs.canonicalize("/tmp/rel").unwrap(), "/etc/shadow");
+    assert_eq!(vfs.canonicalize("/tmp/s/../tmp/new").unwrap(), "/tmp/new");
+    assert_eq!(vfs.read_symlink("/tmp/s").unwrap(), "/etc");
+
+    vfs.create_symlink("/tmp/loop", "/tmp/loop").unwrap();
+    assert_eq!(vfs.canonicalize("/tmp/loop").err(), Some(FsError::SymlinkLoop));
+}
+
+#[test]
+fn device_writes_reach_driver() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/dev", mode(0o755)).unwrap();
+    let dev = vfs.create_device("/dev/ttyS0", DeviceId::new(4, 64), mode(0o620)).unwrap();
+    assert_eq!(vfs.lookup_path("/dev").unwrap().lookup("ttyS0").unwrap().file_type, FileType::CharDevice);
+
+    serial::take_output();
+    vfs.write_node(dev.inode, 0, b"ping").unwrap();
+    assert_eq!(serial::take_output(), b"ping");
+}
+
+#[test]
+fn device_nodes_by_kind() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/dev", mode(0o755)).unwrap();
+    vfs.create_device("/dev/mmcblk0", DeviceId::new(179, 0), FileMode::new(FileMode::S_IFBLK | 0o660)).unwrap();
+    let hotplug = vfs.create_device("/dev/hotplug", DeviceId::new(hotplug::MAJOR, 0), mode(0o444)).unwrap();
+    let dev = vfs.lookup_path("/dev").unwrap();
+    assert_eq!(dev.lookup("mmcblk0").unwrap().file_type, FileType::BlockDevice);
+    assert_eq!(vfs.lookup_path("/dev/mmcblk0").unwrap().stat().rdev, 179 << 16);
+
+    hotplug::push_event("1 add block mmcblk0 179:0\n");
+    let mut buf = [0u8; 64];
+    let n = hotplug.read(6, &mut buf).unwrap();
+    assert_eq!(&buf[..n], b"block mmcblk0 179:0\n");
+}
+
+#[test]
+fn graft_mirrors_a_filesystem() {
+    use qunix_host_tests::fs::ext4::Ext4Filesystem;
+    use qunix_host_tests::image::{self, fixture};
+    use std::sync::Arc;
+
+    let device = image::open(fixture("ext4-small.img"), 1024).expect("missing fixture");
+    let fs = Ext4Filesystem::mount(image::shared(device), true).expect("mount failed");
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/mnt", mode(0o755)).unwrap();
+    vfs.create_file("/mnt/stale", mode(0o644)).unwrap();
+    vfs.graft("/mnt", Arc::new(spin::RwLock::new(fs))).unwrap();
+
+    assert_eq!(vfs.lookup_path("/mnt/stale").err(), Some(FsError::NotFound));
+    let file = vfs.lookup_path("/mnt/dir/nested/file.txt").unwrap();
+    let mut buf = [0u8; 64];
+    let n = file.read(0, &mut buf).unwrap();
+    assert_eq!(&buf[..n], b"nested file\n");
+    assert_eq!(vfs.read_symlink("/mnt/link").unwrap(), "hello.txt");
+    assert_eq!(vfs.lookup_path("/mnt/dir/..").unwrap().inode, vfs.lookup_path("/mnt").unwrap().inode);
+
+    vfs.ungraft("/mnt").unwrap();
+    assert_eq!(vfs.lookup_path("/mnt/hello.txt").err(), Some(FsError::NotFound));
+    assert_eq!(vfs.lookup_path("/mnt").unwrap().nlink, 2);
+    // What the graft hid is back
+    assert!(vfs.lookup_path("/mnt/stale").unwrap().is_file());
+}
+
+static NOW: AtomicU64 = AtomicU64::new(1000);
+
+fn now() -> u64 {
+    NOW.load(Ordering::Relaxed)
+}
+
+#[test]
+fn quota_hard_limits_and_release() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/home", mode(0o777)).unwrap();
+    let file = vfs.create_file("/home/a", mode(0o644)).unwrap();
+    vfs.chown("/home/a", 1000, 100).unwrap();
+    let limits = Limits { block_soft: 0, block_hard: 4, inode_soft: 0, inode_hard: 2 };
+    vfs.quotas_mut().set_limits(QuotaType::User, 1000, limits);
+
+    vfs.write_node(file.inode, 0, &[1; 4096]).unwrap();
+    assert_eq!(vfs.quotas().get(QuotaType::User, 1000).blocks, 4);
+    assert_eq!(vfs.quotas().get(QuotaType::Group, 100).blocks, 4);
+    assert_eq!(vfs.write_node(file.inode, 4096, b"x").err(), Some(FsError::QuotaExceeded));
+    assert_eq!(vfs.lookup_path("/home/a").unwrap().size, 4096);
+    assert_eq!(vfs.truncate("/home/a", 8192).err(), Some(FsError::QuotaExceeded));
+    vfs.truncate("/home/a", 1024).unwrap();
+    assert_eq!(vfs.quotas().get(QuotaType::User, 1000).blocks, 1);
+
+    vfs.create_file("/home/b", mode(0o644)).unwrap();
+    vfs.chown("/home/b", 1000, 100).unwrap();
+    vfs.create_file("/home/c", mode(0o644)).unwrap();
+    assert_eq!(vfs.chown("/home/c", 1000, 100).err(), Some(FsError::QuotaExceeded));
+    assert_eq!(vfs.lookup_path("/home/c").unwrap().uid, 0);
+
+    vfs.remove_file("/home/a").unwrap();
+    let usage = vfs.quotas().get(QuotaType::User, 1000);
+    assert_eq!((usage.blocks, usage.inodes), (0, 1));
+    vfs.chown("/home/c", 1000, 100).unwrap();
+}
+
+#[test]
+fn quota_soft_limit_grace() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.quotas_mut().set_clock(now);
+    vfs.quotas_mut().set_grace(60, 60);
+    let file = vfs.create_file("/f", mode(0o644)).unwrap();
+    vfs.chown("/f", 7, 7).unwrap();
+    let limits = Limits { block_soft: 2, block_hard: 0, inode_soft: 0, inode_hard: 0 };
+    vfs.quotas_mut().set_limits(QuotaType::User, 7, limits);
+
+    vfs.write_node(file.inode, 0, &[0; 3000]).unwrap();
+    let usage = vfs.quotas().get(QuotaType::User, 7);
+    assert_eq!(usage.block_grace, Some(now() + 60));
+    NOW.fetch_add(30, Ordering::Relaxed);
+    vfs.write_node(file.inode, 3000, &[0; 1000]).unwrap();
+    NOW.fetch_add(30, Ordering::Relaxed);
+    assert_eq!(vfs.write_node(file.inode, 4000, &[0; 1000]).err(), Some(FsError::QuotaExceeded));
+
+    vfs.truncate("/f", 100).unwrap();
+    assert_eq!(vfs.quotas().get(QuotaType::User, 7).block_grace, None);
+    vfs.write_node(file.inode, 100, &[0; 1000]).unwrap();
+}
+
+#[test]
+fn overlay_copies_up_and_whites_out() {
+    use qunix_host_tests::fs::ext4::Ext4Filesystem;
+    use qunix_host_tests::fs::overlay::Change;
+    use qunix_host_tests::fs::vfs::Filesystem;
+    use qunix_host_tests::image::{self, fixture};
+    use std::sync::Arc;
+
+    let device = image::open(fixture("ext4-small.img"), 1024).expect("missing fixture");
+    let fs = Arc::new(spin::RwLock::new(Ext4Filesystem::mount(image::shared(device), true).expect("mount failed")));
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/merged", mode(0o755)).unwrap();
+    vfs.overlay("/merged", fs.clone()).unwrap();
+    assert!(vfs.overlay_changes("/merged").unwrap().is_empty());
+
+    let file = vfs.lookup_path("/merged/dir/nested/file.txt").unwrap().inode;
+    vfs.write_node(file, 0, b"N").unwrap();
+    let mut buf = [0u8; 64];
+    let n = vfs.get_node(file).unwrap().read(0, &mut buf).unwrap();
+    assert_eq!(&buf[..n], b"Nested file\n");
+    vfs.remove_file("/merged/hello.txt").unwrap();
+    vfs.rename("/merged/link", "/merged/moved").unwrap();
+    vfs.create_file("/merged/new", mode(0o644)).unwrap();
+    vfs.create_directory("/merged/dir/sub", mode(0o755)).unwrap();
+    assert_eq!(
+        vfs.overlay_changes("/merged").unwrap(),
+        [
+            (Change::Changed, "/dir/nested/file.txt".to_string()),
+            (Change::Added, "/dir/sub".to_string()),
+            (Change::Deleted, "/hello.txt".to_string()),
+            (Change::Deleted, "/link".to_string()),
+            (Change::Added, "/moved".to_string()),
+            (Change::Added, "/new".to_string()),
+        ]
+    );
+    vfs.create_file("/merged/hello.txt", mode(0o644)).unwrap();
+    assert!(vfs.overlay_changes("/merged").unwrap().contains(&(Change::Changed, "/hello.txt".to_string())));
+
+    // The lower layer is untouched, and a fresh overlay shows it again
+    let lower = fs.read();
+    let dir = lower.lookup(lower.root().unwrap().inode, "dir").unwrap();
+    let nested = lower.lookup(dir.inode, "nested").unwrap();
+    let n = lower.read(lower.lookup(nested.inode, "file.txt").unwrap().inode, 0, &mut buf).unwrap();
+    drop(lower);
+    assert_eq!(&buf[..n], b"nested file\n");
+    vfs.overlay("/merged", fs).unwrap();
+    assert!(vfs.lookup_path("/merged/hello.txt").unwrap().is_file());
+    assert_eq!(vfs.lookup_path("/merged/new").err(), Some(FsError::NotFound));
+    vfs.ungraft("/merged").unwrap();
+    assert_eq!(vfs.overlay_changes("/merged").err(), Some(FsError::InvalidArgument));
+}
+
+#[test]
+fn snapshot_restores_changed_nodes() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/etc", mode(0o755)).unwrap();
+    let passwd = vfs.create_file("/etc/passwd", mode(0o644)).unwrap().inode;
+    vfs.write_node(passwd, 0, b"root:x:0:0\n").unwrap();
+    let before = vfs.snapshot();
+
+    vfs.write_node(passwd, 11, b"alice:x:1000:1000\n").unwrap();
+    vfs.create_file("/etc/shadow", mode(0o600)).unwrap();
+    vfs.chown("/etc/passwd", 1000, 1000).unwrap();
+    let after = vfs.snapshot();
+    vfs.remove_file("/etc/passwd").unwrap();
+
+    // passwd and /etc
+    assert_eq!(vfs.restore(&after).unwrap(), 2);
+    assert_eq!(vfs.lookup_path("/etc/passwd").unwrap().size, 29);
+    assert_eq!(vfs.quotas().get(QuotaType::User, 1000).inodes, 1);
+
+    assert_eq!(vfs.restore(&before).unwrap(), 3);
+    assert!(!vfs.holds(&after));
+    assert_eq!(vfs.restore(&after).err(), Some(FsError::InvalidArgument));
+    let node = vfs.lookup_path("/etc/passwd").unwrap();
+    assert_eq!((node.size, node.uid), (11, 0));
+    assert_eq!(vfs.lookup_path("/etc/shadow").err(), Some(FsError::NotFound));
+    assert_eq!(vfs.quotas().get(QuotaType::User, 1000).inodes, 0);
+
+    // The same snapshot again, and a file created after it gets a fresh start
+    vfs.create_file("/tmp", mode(0o644)).unwrap();
+    assert_eq!(vfs.restore(&before).unwrap(), 2);
+    assert_eq!(vfs.lookup_path("/tmp").err(), Some(FsError::NotFound));
+    vfs.release(&before);
+    assert!(!vfs.holds(&before));
+}
+
+#[test]
+fn fallocate_preallocates_and_punches_holes() {
+    let mut vfs = VirtualFileSystem::new();
+    let file = vfs.create_file("/disk.img", mode(0o644)).unwrap().inode;
+    let blocks = |vfs: &VirtualFileSystem| vfs.get_node(file).unwrap().stat().blocks;
+
+    vfs.fallocate(file, FallocateFlags::empty(), 0, 16384).unwrap();
+    assert_eq!((vfs.get_node(file).unwrap().size, blocks(&vfs)), (16384, 32));
+    vfs.fallocate(file, FallocateFlags::KEEP_SIZE, 16384, 4096).unwrap();
+    assert_eq!((vfs.get_node(file).unwrap().size, blocks(&vfs)), (16384, 40));
+
+    vfs.write_node(file, 0, &[0xaa; 16384]).unwrap();
+    let punch = FallocateFlags::PUNCH_HOLE | FallocateFlags::KEEP_SIZE;
+    vfs.fallocate(file, punch, 2048, 8192).unwrap();
+    assert_eq!((vfs.get_node(file).unwrap().size, blocks(&vfs)), (16384, 32));
+    let mut buf = [0u8; 16384];
+    vfs.get_node(file).unwrap().read(0, &mut buf).unwrap();
+    assert!(buf[..2048].iter().all(|&b| b == 0xaa));
+    assert!(buf[2048..10240].iter().all(|&b| b == 0));
+    assert!(buf[10240..].iter().all(|&b| b == 0xaa));
+
+    // Punching the rest frees the preallocated page past the end too
+    vfs.fallocate(file, punch, 0, 1 << 20).unwrap();
+    assert_eq!((vfs.get_node(file).unwrap().size, blocks(&vfs)), (16384, 0));
+
+    assert_eq!(vfs.fallocate(file, FallocateFlags::PUNCH_HOLE, 0, 4096).err(), Some(FsError::NotSupported));
+    assert_eq!(vfs.fallocate(file, FallocateFlags::empty(), 0, 0).err(), Some(FsError::InvalidArgument));
+    let root = vfs.lookup_path("/").unwrap().inode;
+    assert_eq!(vfs.fallocate(root, FallocateFlags::empty(), 0, 4096).err(), Some(FsError::IsDirectory));
+}
+
+#[test]
+fn casefold_directories_ignore_case() {
+    use qunix_host_tests::fs::fat32::Fat32Filesystem;
+    use qunix_host_tests::image::{self, fixture};
+    use std::sync::Arc;
+
+    let device = image::open(fixture("fat32-small.img"), 512).expect("missing fixture");
+    let fs = Fat32Filesystem::mount(image::shared(device), false).expect("mount failed");
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/mnt", mode(0o755)).unwrap();
+    vfs.graft("/mnt", Arc::new(spin::RwLock::new(fs))).unwrap();
+
+    // FAT names match in any case, through the VFS too
+    let nested = vfs.lookup_path("/mnt/SUBDIR/NESTED.TXT").unwrap().inode;
+    assert_eq!(vfs.lookup_path("/mnt/subdir/nested.txt").unwrap().inode, nested);
+    assert_eq!(vfs.create_file("/mnt/Hello.txt", mode(0o644)).err(), Some(FsError::AlreadyExists));
+    vfs.create_directory("/mnt/subdir/Deeper", mode(0o755)).unwrap();
+    assert!(vfs.lookup_path("/mnt/SUBDIR/deeper").is_ok());
+    vfs.rename("/mnt/subdir/deeper", "/mnt/subdir/DEEPER").unwrap();
+    let names: Vec<String> = vfs.lookup_path("/mnt/subdir").unwrap().readdir().unwrap().iter().map(|e| e.name.clone()).collect();
+    assert!(names.contains(&"DEEPER".to_string()));
+
+    // Elsewhere case still counts, unless a directory opts in
+    vfs.create_directory("/tmp", mode(0o777)).unwrap();
+    vfs.create_file("/tmp/a", mode(0o644)).unwrap();
+    vfs.create_file("/tmp/A", mode(0o644)).unwrap();
+    let tmp = vfs.lookup_path("/tmp").unwrap().inode;
+    assert_eq!(vfs.set_casefold(tmp, true).err(), Some(FsError::AlreadyExists));
+    vfs.remove_file("/tmp/A").unwrap();
+    vfs.set_casefold(tmp, true).unwrap();
+    assert_eq!(vfs.lookup_path("/tmp/A").unwrap().inode, vfs.lookup_path("/tmp/a").unwrap().inode);
+    vfs.set_casefold(tmp, false).unwrap();
+    assert_eq!(vfs.lookup_path("/tmp/A").err(), Some(FsError::NotFound));
+}
+
+#[test]
+fn directories_by_inode() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/srv", mode(0o755)).unwrap();
+    let www = vfs.create_directory("/srv/www", mode(0o755)).unwrap().inode;
+    let file = vfs.create_file("/srv/index", mode(0o644)).unwrap().inode;
+
+    // Found where it is now, not where it was
+    vfs.create_directory("/var", mode(0o755)).unwrap();
+    vfs.rename("/srv/www", "/var/www").unwrap();
+    assert_eq!(vfs.path_of(www).unwrap(), "/var/www");
+    assert_eq!(vfs.set_cwd_inode(www).unwrap(), "/var/www");
+    assert_eq!(vfs.get_cwd(), "/var/www");
+    assert_eq!(vfs.set_cwd_inode(file).err(), Some(FsError::NotDirectory));
+
+    // Relative to the root, and not outside it
+    vfs.set_root("/var").unwrap();
+    assert_eq!(vfs.set_cwd_inode(www).unwrap(), "/www");
+    let srv = vfs.lookup_resolved("/srv").unwrap().inode;
+    assert_eq!(vfs.set_cwd_inode(srv).err(), Some(FsError::PermissionDenied));
+
+    // Directories are neither read nor written
+    let mut buf = [0u8; 8];
+    assert_eq!(vfs.get_node(www).unwrap().read(0, &mut buf).err(), Some(FsError::IsDirectory));
+    assert_eq!(vfs.write_node(www, 0, b"x").err(), Some(FsError::IsDirectory));
+}
+
+#[test]
+fn held_nodes_outlive_their_names() {
+    let mut vfs = VirtualFileSystem::new();
+    let log = vfs.create_file("/log", mode(0o644)).unwrap().inode;
+    vfs.write_node(log, 0, b"kept").unwrap();
+    vfs.hold(log);
+    vfs.hold(log);
+
+    // Unlinked, it goes from the tree but stays usable through the hold
+    vfs.remove_file("/log").unwrap();
+    assert_eq!(vfs.lookup_path("/log").err(), Some(FsError::NotFound));
+    assert_eq!(vfs.write_node(log, 4, b" open").unwrap(), 5);
+    let mut buf = [0u8; 16];
+    let node = vfs.get_node(log).unwrap();
+    assert_eq!(node.read(0, &mut buf).unwrap(), 9);
+    assert_eq!(&buf[..9], b"kept open");
+    assert_eq!(node.stat().nlink, 0);
+
+    // Gone with the last hold; one never removed stays
+    vfs.unhold(log);
+    assert!(vfs.get_node(log).is_ok());
+    vfs.unhold(log);
+    assert_eq!(vfs.get_node(log).err(), Some(FsError::NotFound));
+    let other = vfs.create_file("/other", mode(0o644)).unwrap().inode;
+    vfs.hold(other);
+    vfs.unhold(other);
+    assert!(vfs.lookup_path("/other").is_ok());
+}
+
+#[test]
+fn access_checks_permission_bits() {
+    let mut vfs = VirtualFileSystem::new();
+    vfs.create_directory("/home", mode(0o755)).unwrap();
+    vfs.create_directory("/home/alice", mode(0o700)).unwrap();
+    vfs.chown("/home/alice", 1000, 1000).unwrap();
+    vfs.create_file("/home/alice/notes", mode(0o640)).unwrap();
+    vfs.chown("/home/alice/notes", 1000, 1000).unwrap();
+    vfs.create_file("/script", mode(0o754)).unwrap();
+    vfs.chown("/script", 0, 100).unwrap();
+    let (r, w, x) = (AccessMode::R_OK, AccessMode::W_OK, AccessMode::X_OK);
+
+    assert_eq!(vfs.access("/home/alice/notes", 1000, 1000, r | w), Ok(()));
+    assert_eq!(vfs.access("/home/alice/notes", 1000, 1000, x), Err(FsError::PermissionDenied));
+    // Bob is refused by the directory before the file
+    assert_eq!(vfs.access("/home/alice/notes", 1001, 1000, AccessMode::empty()), Err(FsError::PermissionDenied));
+    assert_eq!(vfs.access("/home/alice/missing", 1000, 1000, AccessMode::empty()), Err(FsError::NotFound));
+
+    // Owner, then group, then everyone else
+    assert_eq!(vfs.access("/script", 1000, 100, r | x), Ok(()));
+    assert_eq!(vfs.access("/script", 1000, 100, w), Err(FsError::PermissionDenied));
+    assert_eq!(vfs.access("/script", 1000, 1000, r), Ok(()));
+    assert_eq!(vfs.access("/script", 1000, 1000, x), Err(FsError::PermissionDenied));
+
+    // Root reads and writes anything, but executes only what someone may
+    assert_eq!(vfs.access("/home/alice/notes", 0, 0, r | w), Ok(()));
+    assert_eq!(vfs.access("/home/alice/notes", 0, 0, x), Err(FsError::PermissionDenied));
+    assert_eq!(vfs.access("/script", 0, 0, x), Ok(()));
+}
@@ -0,0 +1,104 @@
+// gzip container (RFC 1952)
+//
+// Only decompression: each member's header is skipped, its DEFLATE stream
+// inflated and the CRC-32 and length in its trailer checked. Concatenated
+// members decompress to the concatenation of their contents, as with
+// gunzip.
+
+use alloc::vec::Vec;
+use crate::fs::{FsError, FsResult};
+use super::inflate::inflate;
+
+const MAGIC: [u8; 2] = [0x1F, 0x8B];
+const METHOD_DEFLATE: u8 = 8;
+
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+const FLAG_RESERVED: u8 = 0xE0;
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// CRC-32 (IEEE 802.3), as used by gzip and zip
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_continue(0, data)
+}
+
+/// The CRC-32 of what `crc` was the CRC-32 of, followed by `data`
+pub fn crc32_continue(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &b| CRC32_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Length of the member header at the start of `data`
+fn header_len(data: &[u8]) -> FsResult<usize> {
+    let fixed = data.get(..10).ok_or(FsError::InvalidArgument)?;
+    if !is_gzip(fixed) || fixed[2] != METHOD_DEFLATE || fixed[3] & FLAG_RESERVED != 0 {
+        return Err(FsError::InvalidArgument);
+    }
+    let flags = fixed[3];
+    let mut pos = 10;
+
+    if flags & FLAG_EXTRA != 0 {
+        let len = data.get(pos..pos + 2).ok_or(FsError::InvalidArgument)?;
+        pos += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+    }
+    for flag in [FLAG_NAME, FLAG_COMMENT] {
+        if flags & flag != 0 {
+            let rest = data.get(pos..).ok_or(FsError::InvalidArgument)?;
+            pos += rest.iter().position(|&b| b == 0).ok_or(FsError::InvalidArgument)? + 1;
+        }
+    }
+    if flags & FLAG_HCRC != 0 {
+        pos += 2;
+    }
+    if pos > data.len() {
+        return Err(FsError::InvalidArgument);
+    }
+    Ok(pos)
+}
+
+/// Decompress a gzip file, producing at most `limit` bytes (`NoSpace`
+/// beyond that)
+pub fn decompress(data: &[u8], limit: usize) -> FsResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut rest = data;
+    loop {
+        let start = header_len(rest)?;
+        let (member, used) = inflate(&rest[start..], limit - out.len())?;
+
+        let trailer = rest.get(start + used..start + used + 8).ok_or(FsError::InvalidArgument)?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&member) || size != member.len() as u32 {
+            return Err(FsError::IoError);
+        }
+        out.extend_from_slice(&member);
+
+        rest = &rest[start + used + 8..];
+        // Anything after the last member other than another one is padding
+        if !is_gzip(rest) {
+            return Ok(out);
+        }
+    }
+}
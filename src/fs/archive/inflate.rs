@@ -0,0 +1,248 @@
+// DEFLATE decompressor (RFC 1951)
+//
+// A straightforward canonical-Huffman decoder in the style of zlib's
+// "puff": codes are decoded a bit at a time from per-length counts, which
+// is slow but small and easy to check. Every read is bounds-checked and
+// the output is capped, so corrupt or hostile input ends in an error
+// rather than a panic or an exhausted heap.
+
+use alloc::vec::Vec;
+use crate::fs::{FsError, FsResult};
+
+const MAX_BITS: usize = 15;
+const MAX_LIT_CODES: usize = 288;
+const MAX_DIST_CODES: usize = 30;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// Order in which code length code lengths are stored
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// LSB-first bit input. Bytes are pulled in only as bits are needed, so
+/// fewer than 8 bits are ever left buffered between calls.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, need: u32) -> FsResult<u32> {
+        while self.bit_count < need {
+            let byte = *self.data.get(self.pos).ok_or(FsError::InvalidArgument)?;
+            self.pos += 1;
+            self.bit_buf |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buf & ((1u64 << need) - 1) as u32;
+        self.bit_buf >>= need;
+        self.bit_count -= need;
+        Ok(value)
+    }
+
+    /// Drop the rest of the current byte
+    fn align(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+}
+
+struct Huffman {
+    /// Number of codes of each length
+    counts: [u16; MAX_BITS + 1],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build the canonical code for `lengths`. Incomplete codes are allowed
+    /// (decoding an unused code fails); over-subscribed ones are not.
+    fn new(lengths: &[u8]) -> FsResult<Self> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(FsError::InvalidArgument);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = alloc::vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, input: &mut BitReader) -> FsResult<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(FsError::InvalidArgument)
+    }
+}
+
+fn fixed_codes() -> FsResult<(Huffman, Huffman)> {
+    let mut lengths = [0u8; MAX_LIT_CODES];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; MAX_DIST_CODES])?))
+}
+
+fn dynamic_codes(input: &mut BitReader) -> FsResult<(Huffman, Huffman)> {
+    let nlen = input.bits(5)? as usize + 257;
+    let ndist = input.bits(5)? as usize + 1;
+    let ncode = input.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > MAX_DIST_CODES {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let mut clen_lengths = [0u8; 19];
+    for &index in &CLEN_ORDER[..ncode] {
+        clen_lengths[index] = input.bits(3)? as u8;
+    }
+    let clen = Huffman::new(&clen_lengths)?;
+
+    let mut lengths = [0u8; MAX_LIT_CODES + MAX_DIST_CODES];
+    let mut i = 0;
+    while i < nlen + ndist {
+        let symbol = clen.decode(input)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..i].last().ok_or(FsError::InvalidArgument)?;
+                (previous, 3 + input.bits(2)? as usize)
+            }
+            17 => (0, 3 + input.bits(3)? as usize),
+            _ => (0, 11 + input.bits(7)? as usize),
+        };
+        if i + repeat > nlen + ndist {
+            return Err(FsError::InvalidArgument);
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    // Without an end-of-block code the block could never finish
+    if lengths[256] == 0 {
+        return Err(FsError::InvalidArgument);
+    }
+
+    Ok((Huffman::new(&lengths[..nlen])?, Huffman::new(&lengths[nlen..nlen + ndist])?))
+}
+
+fn inflate_block(input: &mut BitReader, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman, limit: usize) -> FsResult<()> {
+    loop {
+        let symbol = lit.decode(input)? as usize;
+        match symbol {
+            0..=255 => {
+                if out.len() >= limit {
+                    return Err(FsError::NoSpace);
+                }
+                out.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            257..=285 => {
+                let index = symbol - 257;
+                let len = LENGTH_BASE[index] as usize + input.bits(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = dist.decode(input)? as usize;
+                if index >= MAX_DIST_CODES {
+                    return Err(FsError::InvalidArgument);
+                }
+                let distance = DIST_BASE[index] as usize + input.bits(DIST_EXTRA[index] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(FsError::InvalidArgument);
+                }
+                if out.len() + len > limit {
+                    return Err(FsError::NoSpace);
+                }
+                // The copy may overlap what it is producing, so go byte by byte
+                let start = out.len() - distance;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+            _ => return Err(FsError::InvalidArgument),
+        }
+    }
+}
+
+/// Decompress the raw DEFLATE stream at the start of `data`, producing at
+/// most `limit` bytes (`NoSpace` beyond that). Returns the output and the
+/// number of input bytes the stream took up.
+pub fn inflate(data: &[u8], limit: usize) -> FsResult<(Vec<u8>, usize)> {
+    let mut input = BitReader { data, pos: 0, bit_buf: 0, bit_count: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = input.bits(1)? == 1;
+        match input.bits(2)? {
+            0 => {
+                input.align();
+                let header = data.get(input.pos..input.pos + 4).ok_or(FsError::InvalidArgument)?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let nlen = u16::from_le_bytes([header[2], header[3]]) as usize;
+                if len != !nlen & 0xFFFF {
+                    return Err(FsError::InvalidArgument);
+                }
+                input.pos += 4;
+                let stored = data.get(input.pos..input.pos + len).ok_or(FsError::InvalidArgument)?;
+                if out.len() + len > limit {
+                    return Err(FsError::NoSpace);
+                }
+                out.extend_from_slice(stored);
+                input.pos += len;
+            }
+            1 => {
+                let (lit, dist) = fixed_codes()?;
+                inflate_block(&mut input, &mut out, &lit, &dist, limit)?;
+            }
+            2 => {
+                let (lit, dist) = dynamic_codes(&mut input)?;
+                inflate_block(&mut input, &mut out, &lit, &dist, limit)?;
+            }
+            _ => return Err(FsError::InvalidArgument),
+        }
+        if last {
+            break;
+        }
+    }
+
+    Ok((out, input.pos))
+}
@@ -0,0 +1,6 @@
+// Archive and compression formats: tar for trees of files, gzip/DEFLATE
+// for compressed archives and images. Decompression only for now.
+
+pub mod gzip;
+pub mod inflate;
+pub mod tar;
@@ -0,0 +1,495 @@
+// tar archives (POSIX ustar)
+//
+// Reader parses ustar and older v7 headers, plus the GNU long-name ('L',
+// 'K') and pax ('x') extensions hosts emit for long paths, so archives made
+// with GNU tar or bsdtar unpack. Builder writes ustar, falling back to a
+// GNU long-name record for a path or link target ustar cannot hold.
+// extract() and create() move whole trees between an archive and the VFS.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::fs::{FileType, FsError, FsResult};
+use crate::fs::vfs::api::{self as vfs_api, write_file};
+
+pub const BLOCK_SIZE: usize = 512;
+
+const NAME_LEN: usize = 100;
+const PREFIX_LEN: usize = 155;
+
+// Header field offsets and lengths
+const NAME: (usize, usize) = (0, 100);
+const MODE: (usize, usize) = (100, 8);
+const UID: (usize, usize) = (108, 8);
+const GID: (usize, usize) = (116, 8);
+const SIZE: (usize, usize) = (124, 12);
+const MTIME: (usize, usize) = (136, 12);
+const CHKSUM: (usize, usize) = (148, 8);
+const TYPEFLAG: usize = 156;
+const LINKNAME: (usize, usize) = (157, 100);
+const MAGIC: (usize, usize) = (257, 6);
+const VERSION: (usize, usize) = (263, 2);
+const PREFIX: (usize, usize) = (345, 155);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink(String),
+    /// Another name for an earlier entry of the archive
+    HardLink(String),
+    /// Device nodes, FIFOs and anything else, by type flag
+    Other(u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry<'a> {
+    pub path: String,
+    pub kind: EntryKind,
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: u64,
+    pub data: &'a [u8],
+}
+
+fn field(block: &[u8], (offset, len): (usize, usize)) -> &[u8] {
+    &block[offset..offset + len]
+}
+
+/// A NUL-terminated (or NUL-padded) string field
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// An octal number field, or GNU base-256 when the high bit is set
+fn number(bytes: &[u8]) -> FsResult<u64> {
+    if bytes.first().is_some_and(|&b| b & 0x80 != 0) {
+        let mut value: u64 = (bytes[0] & 0x7F) as u64;
+        for &b in &bytes[1..] {
+            if value >> 56 != 0 {
+                return Err(FsError::InvalidArgument);
+            }
+            value = value << 8 | b as u64;
+        }
+        return Ok(value);
+    }
+
+    let digits = bytes.iter()
+        .skip_while(|&&b| b == b' ' || b == 0)
+        .take_while(|&&b| b != b' ' && b != 0);
+    let mut value: u64 = 0;
+    for &b in digits {
+        if !(b'0'..=b'7').contains(&b) || value >> 61 != 0 {
+            return Err(FsError::InvalidArgument);
+        }
+        value = value << 3 | (b - b'0') as u64;
+    }
+    Ok(value)
+}
+
+/// Whether the header checksum matches; old tars summed signed bytes
+fn checksum_ok(block: &[u8]) -> FsResult<bool> {
+    let stored = number(field(block, CHKSUM))?;
+    let blank = |i: usize| (CHKSUM.0..CHKSUM.0 + CHKSUM.1).contains(&i);
+    let unsigned: u64 = block.iter().enumerate()
+        .map(|(i, &b)| if blank(i) { b' ' as u64 } else { b as u64 })
+        .sum();
+    let signed: i64 = block.iter().enumerate()
+        .map(|(i, &b)| if blank(i) { b' ' as i64 } else { b as i8 as i64 })
+        .sum();
+    Ok(stored == unsigned || stored as i64 == signed)
+}
+
+/// Apply pax extended header records ("LEN key=value\n") we understand
+fn apply_pax(records: &[u8], path: &mut Option<String>, link: &mut Option<String>) -> FsResult<()> {
+    let mut rest = records;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ').ok_or(FsError::InvalidArgument)?;
+        let len: usize = core::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .ok_or(FsError::InvalidArgument)?;
+        if len <= space + 1 || len > rest.len() || rest[len - 1] != b'\n' {
+            return Err(FsError::InvalidArgument);
+        }
+        let record = &rest[space + 1..len - 1];
+        let eq = record.iter().position(|&b| b == b'=').ok_or(FsError::InvalidArgument)?;
+        let value = String::from_utf8_lossy(&record[eq + 1..]).into_owned();
+        match &record[..eq] {
+            b"path" => *path = Some(value),
+            b"linkpath" => *link = Some(value),
+            _ => {}
+        }
+        rest = &rest[len..];
+    }
+    Ok(())
+}
+
+/// Iterates over the entries of an archive held in memory. Stops at the
+/// end-of-archive marker or the end of the data; yields an error (once)
+/// for a damaged header or truncated contents.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    done: bool,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0, done: false }
+    }
+
+    fn next_entry(&mut self) -> FsResult<Option<Entry<'a>>> {
+        let mut long_path = None;
+        let mut long_link = None;
+        loop {
+            if self.pos >= self.data.len() {
+                return Ok(None);
+            }
+            let block = self.data.get(self.pos..self.pos + BLOCK_SIZE).ok_or(FsError::InvalidArgument)?;
+            if block.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+            if !checksum_ok(block)? {
+                return Err(FsError::InvalidArgument);
+            }
+
+            let size = number(field(block, SIZE))? as usize;
+            let start = self.pos + BLOCK_SIZE;
+            let data = start.checked_add(size)
+                .and_then(|end| self.data.get(start..end))
+                .ok_or(FsError::InvalidArgument)?;
+            self.pos = start + size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+
+            let typeflag = block[TYPEFLAG];
+            match typeflag {
+                b'L' => long_path = Some(text(data)),
+                b'K' => long_link = Some(text(data)),
+                b'x' => apply_pax(data, &mut long_path, &mut long_link)?,
+                b'g' => {}
+                _ => {
+                    let mut path = text(field(block, NAME));
+                    let magic = field(block, MAGIC);
+                    if magic == b"ustar\0" && field(block, VERSION) == b"00" {
+                        let prefix = text(field(block, PREFIX));
+                        if !prefix.is_empty() {
+                            path = format!("{}/{}", prefix, path);
+                        }
+                    }
+                    let path = long_path.take().unwrap_or(path);
+                    let link = long_link.take().unwrap_or_else(|| text(field(block, LINKNAME)));
+
+                    let kind = match typeflag {
+                        b'0' | 0 | b'7' if path.ends_with('/') => EntryKind::Directory,
+                        b'0' | 0 | b'7' => EntryKind::File,
+                        b'1' => EntryKind::HardLink(link),
+                        b'2' => EntryKind::Symlink(link),
+                        b'5' => EntryKind::Directory,
+                        other => EntryKind::Other(other),
+                    };
+                    return Ok(Some(Entry {
+                        path,
+                        kind,
+                        mode: (number(field(block, MODE))? & 0o7777) as u16,
+                        uid: number(field(block, UID))? as u32,
+                        gid: number(field(block, GID))? as u32,
+                        mtime: number(field(block, MTIME))?,
+                        data,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = FsResult<Entry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_entry();
+        if !matches!(next, Ok(Some(_))) {
+            self.done = true;
+        }
+        next.transpose()
+    }
+}
+
+/// Builds a ustar archive in memory
+pub struct Builder {
+    out: Vec<u8>,
+}
+
+fn put_text(block: &mut [u8], (offset, len): (usize, usize), value: &str) -> FsResult<()> {
+    if value.len() > len {
+        return Err(FsError::NameTooLong);
+    }
+    block[offset..offset + value.len()].copy_from_slice(value.as_bytes());
+    Ok(())
+}
+
+/// Octal, zero-padded and NUL-terminated
+fn put_number(block: &mut [u8], (offset, len): (usize, usize), value: u64) -> FsResult<()> {
+    let digits = format!("{:0width$o}", value, width = len - 1);
+    if digits.len() > len - 1 {
+        return Err(FsError::InvalidArgument);
+    }
+    block[offset..offset + len - 1].copy_from_slice(digits.as_bytes());
+    block[offset + len - 1] = 0;
+    Ok(())
+}
+
+/// Split a path into ustar prefix and name fields, if it fits
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= NAME_LEN {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= PREFIX_LEN && name.len() <= NAME_LEN && !name.is_empty())
+}
+
+/// The longest prefix of `s` that fits in `len` bytes
+fn truncate(s: &str, len: usize) -> &str {
+    let mut end = s.len().min(len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Header fields other than the name, prefix and link target
+struct Meta {
+    typeflag: u8,
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    mtime: u64,
+    size: usize,
+}
+
+fn header(prefix: &str, name: &str, link: &str, meta: &Meta) -> FsResult<[u8; BLOCK_SIZE]> {
+    let mut block = [0u8; BLOCK_SIZE];
+    put_text(&mut block, NAME, name)?;
+    put_text(&mut block, PREFIX, prefix)?;
+    put_text(&mut block, LINKNAME, link)?;
+    put_number(&mut block, MODE, meta.mode as u64)?;
+    put_number(&mut block, UID, meta.uid as u64)?;
+    put_number(&mut block, GID, meta.gid as u64)?;
+    put_number(&mut block, SIZE, meta.size as u64)?;
+    put_number(&mut block, MTIME, meta.mtime)?;
+    block[TYPEFLAG] = meta.typeflag;
+    put_text(&mut block, MAGIC, "ustar\0")?;
+    put_text(&mut block, VERSION, "00")?;
+
+    block[CHKSUM.0..CHKSUM.0 + CHKSUM.1].fill(b' ');
+    let sum: u64 = block.iter().map(|&b| b as u64).sum();
+    let chksum = format!("{:06o}\0 ", sum);
+    block[CHKSUM.0..CHKSUM.0 + CHKSUM.1].copy_from_slice(chksum.as_bytes());
+    Ok(block)
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder { out: Vec::new() }
+    }
+
+    fn push(&mut self, block: &[u8; BLOCK_SIZE], data: &[u8]) {
+        self.out.extend_from_slice(block);
+        self.out.extend_from_slice(data);
+        let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        self.out.resize(self.out.len() + padding, 0);
+    }
+
+    /// A GNU 'L' or 'K' record carrying the full name for the next header
+    fn push_long(&mut self, typeflag: u8, value: &str) -> FsResult<()> {
+        let mut data = Vec::from(value.as_bytes());
+        data.push(0);
+        let meta = Meta { typeflag, mode: 0, uid: 0, gid: 0, mtime: 0, size: data.len() };
+        let block = header("", "././@LongLink", "", &meta)?;
+        self.push(&block, &data);
+        Ok(())
+    }
+
+    pub fn append(&mut self, entry: &Entry) -> FsResult<()> {
+        let mut path = String::from(entry.path.trim_start_matches('/'));
+        let (typeflag, link, data): (u8, &str, &[u8]) = match &entry.kind {
+            EntryKind::File => (b'0', "", entry.data),
+            EntryKind::Directory => {
+                if !path.ends_with('/') {
+                    path.push('/');
+                }
+                (b'5', "", &[])
+            }
+            EntryKind::Symlink(target) => (b'2', target, &[]),
+            EntryKind::HardLink(target) => (b'1', target, &[]),
+            EntryKind::Other(flag) => (*flag, "", &[]),
+        };
+
+        let (prefix, name) = match split_path(&path) {
+            Some(split) => split,
+            None => {
+                self.push_long(b'L', &path)?;
+                ("", truncate(&path, NAME_LEN))
+            }
+        };
+        if link.len() > LINKNAME.1 {
+            self.push_long(b'K', link)?;
+        }
+        let meta = Meta {
+            typeflag,
+            mode: entry.mode,
+            uid: entry.uid,
+            gid: entry.gid,
+            mtime: entry.mtime,
+            size: data.len(),
+        };
+        let block = header(prefix, name, truncate(link, LINKNAME.1), &meta)?;
+        self.push(&block, data);
+        Ok(())
+    }
+
+    /// Add the end-of-archive marker and return the archive
+    pub fn finish(mut self) -> Vec<u8> {
+        self.out.resize(self.out.len() + 2 * BLOCK_SIZE, 0);
+        self.out
+    }
+}
+
+/// `path` from an archive joined onto `dest`; absolute paths are made
+/// relative and `..` is refused so an archive cannot write outside `dest`
+fn target_path(dest: &str, path: &str) -> FsResult<String> {
+    let mut target = String::from(dest.trim_end_matches('/'));
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(FsError::InvalidPath),
+            _ => {
+                target.push('/');
+                target.push_str(component);
+            }
+        }
+    }
+    if target.is_empty() {
+        target.push('/');
+    }
+    Ok(target)
+}
+
+/// Create `path` and any missing parents
+pub fn make_dirs(path: &str) -> FsResult<()> {
+    let mut partial = String::new();
+    for component in path.split('/').filter(|c| !c.is_empty()) {
+        partial.push('/');
+        partial.push_str(component);
+        match vfs_api::mkdir(&partial, 0o755) {
+            Ok(()) | Err(FsError::AlreadyExists) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Unpack `archive` under the directory `dest`, calling `each` for every
+/// entry before it is written. Device nodes and FIFOs are skipped, and hard
+/// links become copies. Returns the number of entries unpacked.
+pub fn extract(archive: &[u8], dest: &str, mut each: impl FnMut(&Entry)) -> FsResult<usize> {
+    let dest = crate::fs::vfs::VFS.lock().absolute_path(dest);
+    let mut count = 0;
+    for entry in Reader::new(archive) {
+        let entry = entry?;
+        let target = target_path(&dest, &entry.path)?;
+        if let EntryKind::Other(_) = entry.kind {
+            continue;
+        }
+        each(&entry);
+        if let Some(parent) = target.rfind('/').map(|i| &target[..i]) {
+            make_dirs(parent)?;
+        }
+
+        match &entry.kind {
+            EntryKind::File => write_file(&target, entry.data, entry.mode)?,
+            EntryKind::Directory => make_dirs(&target)?,
+            EntryKind::Symlink(link) => {
+                vfs_api::unlink(&target).ok();
+                vfs_api::symlink(link, &target)?;
+            }
+            EntryKind::HardLink(link) => {
+                let data = vfs_api::read_file(&target_path(&dest, link)?)?;
+                write_file(&target, &data, entry.mode)?;
+            }
+            EntryKind::Other(_) => unreachable!(),
+        }
+        if !matches!(entry.kind, EntryKind::Symlink(_)) {
+            vfs_api::chmod(&target, entry.mode)?;
+        }
+        vfs_api::chown(&target, entry.uid, entry.gid)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn add_tree(builder: &mut Builder, path: &str, each: &mut dyn FnMut(&str)) -> FsResult<()> {
+    let stat = vfs_api::stat(path)?;
+    let name = path.trim_start_matches('/');
+    let mut entry = Entry {
+        path: String::from(if name.is_empty() { "." } else { name }),
+        kind: EntryKind::File,
+        mode: stat.mode.0 & 0o7777,
+        uid: stat.uid,
+        gid: stat.gid,
+        mtime: stat.mtime,
+        data: &[],
+    };
+
+    match stat.mode.file_type() {
+        FileType::Regular => {
+            each(path);
+            let data = vfs_api::read_file(path)?;
+            entry.data = &data;
+            builder.append(&entry)
+        }
+        FileType::Symlink => {
+            each(path);
+            entry.kind = EntryKind::Symlink(vfs_api::readlink(path)?);
+            builder.append(&entry)
+        }
+        FileType::Directory => {
+            each(path);
+            entry.kind = EntryKind::Directory;
+            builder.append(&entry)?;
+            let mut children: Vec<String> = vfs_api::readdir(path)?
+                .into_iter()
+                .map(|e| e.name)
+                .filter(|name| name != "." && name != "..")
+                .collect();
+            children.sort();
+            for child in children {
+                add_tree(builder, &format!("{}/{}", path.trim_end_matches('/'), child), each)?;
+            }
+            Ok(())
+        }
+        // Devices, FIFOs and sockets have no contents worth archiving
+        _ => Ok(()),
+    }
+}
+
+/// Archive `paths`, recursing into directories, calling `each` with every
+/// path added. Leading `/` is dropped from the stored names.
+pub fn create(paths: &[&str], mut each: impl FnMut(&str)) -> FsResult<Vec<u8>> {
+    let mut builder = Builder::new();
+    for path in paths {
+        add_tree(&mut builder, path, &mut each)?;
+    }
+    Ok(builder.finish())
+}
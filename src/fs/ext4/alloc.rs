@@ -0,0 +1,380 @@
+// ext4 block and inode allocation
+//
+// A group's bitmaps are read the first time a change allocates or frees
+// in it and kept, with the group descriptors and superblock counters
+// changed along with them, until flush() writes them all out at the end
+// of the change. Allocation takes the first free block at or after a
+// goal, the block after the one before it in the file, so that files
+// stay contiguous; inodes go in the parent directory's group when there
+// is room.
+//
+// Groups never written to are only marked so on filesystems with group
+// descriptor checksums (BLOCK_UNINIT, INODE_UNINIT): their bitmaps are
+// made up from the layout the first time one is needed and written from
+// then on.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::fs::{FsError, FsResult};
+use super::block::{
+    EXT4_BG_BLOCK_UNINIT, EXT4_BG_INODE_UNINIT, EXT4_FEATURE_RO_COMPAT_GDT_CSUM, EXT4_FEATURE_INCOMPAT_64BIT,
+    EXT4_SUPERBLOCK_SIZE,
+};
+use super::csum::{crc16, crc32c};
+use super::ext4::Ext4Filesystem;
+use super::inode::{Ext4Inode, EXT4_GOOD_OLD_INODE_SIZE, EXT4_INODE_CHECKSUM_HI, EXT4_INODE_CHECKSUM_LO};
+
+/// Where the superblock starts on the device, in bytes
+const SUPERBLOCK_OFFSET: usize = 1024;
+/// Offset of bg_checksum in a group descriptor
+const GROUP_DESC_CHECKSUM: usize = 0x1E;
+/// Offset of s_checksum in the superblock
+const SUPERBLOCK_CHECKSUM: usize = 0x3FC;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bitmap {
+    Blocks,
+    Inodes,
+}
+
+/// Metadata changed since the last flush
+#[derive(Default)]
+pub struct Changes {
+    bitmaps: BTreeMap<(u32, Bitmap), Vec<u8>>,
+    groups: BTreeSet<u32>,
+    superblock: bool,
+}
+
+impl Ext4Filesystem {
+    fn group_start(&self, group: u32) -> u64 {
+        self.superblock.s_first_data_block as u64 + group as u64 * self.superblock.s_blocks_per_group as u64
+    }
+
+    /// Blocks in `group`; the last group may be short
+    fn group_blocks(&self, group: u32) -> u32 {
+        let left = self.superblock.blocks_count() - self.group_start(group);
+        core::cmp::min(left, self.superblock.s_blocks_per_group as u64) as u32
+    }
+
+    pub(super) fn group_of_inode(&self, inode: u32) -> u32 {
+        (inode - 1) / self.superblock.s_inodes_per_group
+    }
+
+    /// The first block of the group inode `inode` is in, as a goal for
+    /// the first block of a file
+    pub(super) fn inode_goal(&self, inode: u32) -> u64 {
+        self.group_start(self.group_of_inode(inode))
+    }
+
+    fn uninit(&self, group: u32, flag: u16) -> bool {
+        self.superblock.group_desc_csum() && self.block_groups[group as usize].bg_flags & flag != 0
+    }
+
+    /// The `kind` bitmap of `group`, read or made up the first time
+    fn bitmap(&mut self, group: u32, kind: Bitmap) -> FsResult<&mut Vec<u8>> {
+        if !self.changes.bitmaps.contains_key(&(group, kind)) {
+            let bits = match kind {
+                Bitmap::Blocks => self.group_blocks(group) as usize,
+                Bitmap::Inodes => self.superblock.s_inodes_per_group as usize,
+            };
+            let (block_bitmap, inode_bitmap) = {
+                let desc = &self.block_groups[group as usize];
+                (desc.block_bitmap(), desc.inode_bitmap())
+            };
+            let data = match kind {
+                Bitmap::Blocks if self.uninit(group, EXT4_BG_BLOCK_UNINIT) => {
+                    let bitmap = self.layout_bitmap(group, bits);
+                    self.block_groups[group as usize].bg_flags &= !EXT4_BG_BLOCK_UNINIT;
+                    bitmap
+                }
+                Bitmap::Inodes if self.uninit(group, EXT4_BG_INODE_UNINIT) => {
+                    let mut bitmap = vec![0u8; self.block_size as usize];
+                    set_padding(&mut bitmap, bits);
+                    self.block_groups[group as usize].bg_flags &= !EXT4_BG_INODE_UNINIT;
+                    bitmap
+                }
+                Bitmap::Blocks => self.read_block_data(block_bitmap)?,
+                Bitmap::Inodes => self.read_block_data(inode_bitmap)?,
+            };
+            self.changes.groups.insert(group);
+            self.changes.bitmaps.insert((group, kind), data);
+        }
+        Ok(self.changes.bitmaps.get_mut(&(group, kind)).unwrap())
+    }
+
+    /// The block bitmap of a group never written to: only the copies of
+    /// the superblock and descriptors, and any group's bitmaps and inode
+    /// table, are in use
+    fn layout_bitmap(&self, group: u32, bits: usize) -> Vec<u8> {
+        let mut bitmap = vec![0u8; self.block_size as usize];
+        let start = self.group_start(group);
+        let mut mark = |block: u64| {
+            if let Some(bit) = block.checked_sub(start).filter(|&bit| bit < bits as u64) {
+                bitmap[bit as usize / 8] |= 1 << (bit % 8);
+            }
+        };
+        if self.superblock.has_super(group) {
+            let copies = 1 + self.superblock.group_desc_blocks() + self.superblock.s_reserved_gdt_blocks as u64;
+            (start..start + copies).for_each(&mut mark);
+        }
+        for desc in &self.block_groups {
+            mark(desc.block_bitmap());
+            mark(desc.inode_bitmap());
+            let table = desc.inode_table();
+            (table..table + self.superblock.inode_table_blocks()).for_each(&mut mark);
+        }
+        set_padding(&mut bitmap, bits);
+        bitmap
+    }
+
+    /// A free block, the first at or after `goal` and below `limit`
+    pub(super) fn alloc_block(&mut self, goal: u64, limit: u64) -> FsResult<u64> {
+        let first = self.superblock.s_first_data_block as u64;
+        let goal = goal.clamp(first, self.superblock.blocks_count() - 1);
+        let groups = self.block_groups.len() as u32;
+        let goal_group = ((goal - first) / self.superblock.s_blocks_per_group as u64) as u32;
+        for n in 0..=groups {
+            let group = (goal_group + n) % groups;
+            if self.block_groups[group as usize].free_blocks_count() == 0 || self.group_start(group) >= limit {
+                continue;
+            }
+            // Back to the start of the goal's group once round
+            let from = match n {
+                0 => (goal - self.group_start(group)) as usize,
+                _ => 0,
+            };
+            let bits = core::cmp::min(self.group_blocks(group) as u64, limit - self.group_start(group)) as usize;
+            let bitmap = self.bitmap(group, Bitmap::Blocks)?;
+            let Some(bit) = find_zero(bitmap, from, bits) else {
+                continue;
+            };
+            bitmap[bit / 8] |= 1 << (bit % 8);
+            let desc = &mut self.block_groups[group as usize];
+            desc.set_free_blocks_count(desc.free_blocks_count() - 1);
+            let free = self.superblock.free_blocks_count();
+            self.superblock.set_free_blocks_count(free.saturating_sub(1));
+            self.changes.superblock = true;
+            return Ok(self.group_start(group) + bit as u64);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    pub(super) fn free_block(&mut self, block: u64) -> FsResult<()> {
+        let first = self.superblock.s_first_data_block as u64;
+        if block < first || block >= self.superblock.blocks_count() {
+            return Err(FsError::IoError);
+        }
+        let group = ((block - first) / self.superblock.s_blocks_per_group as u64) as u32;
+        let bit = (block - self.group_start(group)) as usize;
+        let bitmap = self.bitmap(group, Bitmap::Blocks)?;
+        if bitmap[bit / 8] & (1 << (bit % 8)) == 0 {
+            return Err(FsError::IoError);
+        }
+        bitmap[bit / 8] &= !(1 << (bit % 8));
+        let desc = &mut self.block_groups[group as usize];
+        desc.set_free_blocks_count(desc.free_blocks_count() + 1);
+        let free = self.superblock.free_blocks_count();
+        self.superblock.set_free_blocks_count(free + 1);
+        self.changes.superblock = true;
+        Ok(())
+    }
+
+    /// A free inode number, in the group of `parent` if there is one
+    pub(super) fn alloc_inode(&mut self, parent: u32, directory: bool) -> FsResult<u32> {
+        let per_group = self.superblock.s_inodes_per_group;
+        let groups = self.block_groups.len() as u32;
+        let first_ino = self.superblock.s_first_ino;
+        for n in 0..groups {
+            let group = (self.group_of_inode(parent) + n) % groups;
+            if self.block_groups[group as usize].free_inodes_count() == 0 {
+                continue;
+            }
+            // Inodes below the first are reserved, whatever the bitmap says
+            let from = first_ino.saturating_sub(1 + group * per_group) as usize;
+            let bits = core::cmp::min(per_group, self.superblock.s_inodes_count - group * per_group) as usize;
+            let bitmap = self.bitmap(group, Bitmap::Inodes)?;
+            let Some(bit) = find_zero(bitmap, from, bits) else {
+                continue;
+            };
+            bitmap[bit / 8] |= 1 << (bit % 8);
+            let csum = self.superblock.group_desc_csum();
+            let desc = &mut self.block_groups[group as usize];
+            desc.set_free_inodes_count(desc.free_inodes_count() - 1);
+            if directory {
+                desc.set_used_dirs_count(desc.used_dirs_count() + 1);
+            }
+            // Inodes past the last one used are not checked, or read
+            if csum && bit as u32 >= per_group - desc.itable_unused() {
+                desc.set_itable_unused(per_group - bit as u32 - 1);
+            }
+            self.superblock.s_free_inodes_count = self.superblock.s_free_inodes_count.saturating_sub(1);
+            self.changes.superblock = true;
+            return Ok(group * per_group + bit as u32 + 1);
+        }
+        Err(FsError::NoSpace)
+    }
+
+    pub(super) fn free_inode(&mut self, inode: u32, directory: bool) -> FsResult<()> {
+        let group = self.group_of_inode(inode);
+        let bit = ((inode - 1) % self.superblock.s_inodes_per_group) as usize;
+        let bitmap = self.bitmap(group, Bitmap::Inodes)?;
+        if bitmap[bit / 8] & (1 << (bit % 8)) == 0 {
+            return Err(FsError::IoError);
+        }
+        bitmap[bit / 8] &= !(1 << (bit % 8));
+        let desc = &mut self.block_groups[group as usize];
+        desc.set_free_inodes_count(desc.free_inodes_count() + 1);
+        if directory {
+            desc.set_used_dirs_count(desc.used_dirs_count().saturating_sub(1));
+        }
+        self.superblock.s_free_inodes_count += 1;
+        self.changes.superblock = true;
+        Ok(())
+    }
+
+    /// Turn on read-only compatible `feature`, as the first file past
+    /// 2 GiB does large_file
+    pub(super) fn add_ro_compat_feature(&mut self, feature: u32) {
+        self.superblock.s_feature_ro_compat |= feature;
+        self.changes.superblock = true;
+    }
+
+    /// The seed of the checksums of inode `inode` and the blocks it owns
+    pub(super) fn inode_seed(&self, inode: u32, generation: u32) -> u32 {
+        crc32c(crc32c(self.csum_seed, &inode.to_le_bytes()), &generation.to_le_bytes())
+    }
+
+    /// Write `inode` back to the inode table. What is on disk past the
+    /// part the struct holds, extended attributes, is kept, unless the
+    /// inode is `fresh`, when it is cleared.
+    pub(super) fn write_inode(&mut self, number: u32, inode: &Ext4Inode, fresh: bool) -> FsResult<()> {
+        let (block, offset) = self.inode_location(number)?;
+        let inode_size = self.superblock.inode_size() as usize;
+        let mut data = self.read_block_data(block)?;
+        let raw = &mut data[offset..offset + inode_size];
+        if fresh {
+            raw.fill(0);
+        }
+        let base = EXT4_GOOD_OLD_INODE_SIZE as usize;
+        let extra = match inode_size > base {
+            true => core::cmp::min({ inode.i_extra_isize } as usize, size_of::<Ext4Inode>() - base),
+            false => 0,
+        };
+        raw[..base + extra].copy_from_slice(&inode.to_bytes()[..base + extra]);
+        if self.superblock.metadata_csum() {
+            let hi = inode_size > base && inode.i_extra_isize >= 4;
+            raw[EXT4_INODE_CHECKSUM_LO..EXT4_INODE_CHECKSUM_LO + 2].fill(0);
+            if hi {
+                raw[EXT4_INODE_CHECKSUM_HI..EXT4_INODE_CHECKSUM_HI + 2].fill(0);
+            }
+            let csum = crc32c(self.inode_seed(number, inode.i_generation), raw);
+            raw[EXT4_INODE_CHECKSUM_LO..EXT4_INODE_CHECKSUM_LO + 2].copy_from_slice(&(csum as u16).to_le_bytes());
+            if hi {
+                raw[EXT4_INODE_CHECKSUM_HI..EXT4_INODE_CHECKSUM_HI + 2].copy_from_slice(&((csum >> 16) as u16).to_le_bytes());
+            }
+        }
+        self.write_block_data(block, &data)
+    }
+
+    /// Write out the bitmaps, group descriptors and superblock changed
+    /// since the last flush
+    pub(super) fn flush(&mut self) -> FsResult<()> {
+        let bitmaps = core::mem::take(&mut self.changes.bitmaps);
+        for ((group, kind), bitmap) in bitmaps {
+            let desc = &mut self.block_groups[group as usize];
+            let (block, bytes) = match kind {
+                Bitmap::Blocks => (desc.block_bitmap(), self.superblock.s_blocks_per_group as usize / 8),
+                Bitmap::Inodes => (desc.inode_bitmap(), self.superblock.s_inodes_per_group as usize / 8),
+            };
+            if self.superblock.metadata_csum() {
+                let csum = crc32c(self.csum_seed, &bitmap[..bytes]);
+                match kind {
+                    Bitmap::Blocks => {
+                        desc.bg_block_bitmap_csum_lo = csum as u16;
+                        desc.bg_block_bitmap_csum_hi = (csum >> 16) as u16;
+                    }
+                    Bitmap::Inodes => {
+                        desc.bg_inode_bitmap_csum_lo = csum as u16;
+                        desc.bg_inode_bitmap_csum_hi = (csum >> 16) as u16;
+                    }
+                }
+            }
+            self.write_block_data(block, &bitmap)?;
+        }
+
+        let groups = core::mem::take(&mut self.changes.groups);
+        let desc_size = self.superblock.desc_size() as usize;
+        let per_block = self.block_size as usize / desc_size;
+        let first = self.superblock.s_first_data_block as u64 + 1;
+        let mut current: Option<(u64, Vec<u8>)> = None;
+        for group in groups {
+            let block = first + (group as usize / per_block) as u64;
+            if current.as_ref().is_none_or(|(b, _)| *b != block) {
+                if let Some((b, data)) = current.take() {
+                    self.write_block_data(b, &data)?;
+                }
+                current = Some((block, self.read_block_data(block)?));
+            }
+            let (_, data) = current.as_mut().unwrap();
+            let offset = (group as usize % per_block) * desc_size;
+            let raw = &mut data[offset..offset + desc_size];
+            let long = self.block_groups[group as usize].to_bytes();
+            let len = core::cmp::min(desc_size, long.len());
+            raw[..len].copy_from_slice(&long[..len]);
+            if let Some(csum) = self.group_desc_checksum(group, raw) {
+                raw[GROUP_DESC_CHECKSUM..GROUP_DESC_CHECKSUM + 2].copy_from_slice(&csum.to_le_bytes());
+            }
+        }
+        if let Some((b, data)) = current {
+            self.write_block_data(b, &data)?;
+        }
+
+        if core::mem::take(&mut self.changes.superblock) {
+            let mut raw = self.superblock.to_bytes();
+            if self.superblock.metadata_csum() {
+                let csum = crc32c(!0, &raw[..SUPERBLOCK_CHECKSUM]);
+                raw[SUPERBLOCK_CHECKSUM..].copy_from_slice(&csum.to_le_bytes());
+            }
+            let block = (SUPERBLOCK_OFFSET / self.block_size as usize) as u64;
+            let offset = SUPERBLOCK_OFFSET % self.block_size as usize;
+            let mut data = self.read_block_data(block)?;
+            data[offset..offset + EXT4_SUPERBLOCK_SIZE].copy_from_slice(&raw);
+            self.write_block_data(block, &data)?;
+        }
+        Ok(())
+    }
+
+    /// The checksum of group descriptor `raw` of `group`, if the
+    /// filesystem keeps one
+    fn group_desc_checksum(&self, group: u32, raw: &mut [u8]) -> Option<u16> {
+        raw[GROUP_DESC_CHECKSUM..GROUP_DESC_CHECKSUM + 2].fill(0);
+        if self.superblock.metadata_csum() {
+            let csum = crc32c(crc32c(self.csum_seed, &group.to_le_bytes()), raw);
+            Some(csum as u16)
+        } else if self.superblock.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_GDT_CSUM) {
+            let mut csum = crc16(!0, &{ self.superblock.s_uuid });
+            csum = crc16(csum, &group.to_le_bytes());
+            csum = crc16(csum, &raw[..GROUP_DESC_CHECKSUM]);
+            if self.superblock.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT) {
+                csum = crc16(csum, &raw[GROUP_DESC_CHECKSUM + 2..]);
+            }
+            Some(csum)
+        } else {
+            None
+        }
+    }
+}
+
+/// Mark the bits of a bitmap block past the `bits` it covers in use, as
+/// mke2fs does
+fn set_padding(bitmap: &mut [u8], bits: usize) {
+    for bit in bits..bitmap.len() * 8 {
+        bitmap[bit / 8] |= 1 << (bit % 8);
+    }
+}
+
+/// The first clear bit of `bitmap` from `from` up to `bits`
+fn find_zero(bitmap: &[u8], from: usize, bits: usize) -> Option<usize> {
+    (from..bits).find(|&bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0)
+}
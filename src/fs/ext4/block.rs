@@ -1,8 +1,14 @@
 use alloc::vec::Vec;
+use core::mem::size_of;
+use crate::fs::{FsError, FsResult};
+use super::inode::EXT4_GOOD_OLD_INODE_SIZE;
 
 pub const EXT4_SUPER_MAGIC: u16 = 0xEF53;
 pub const EXT4_BLOCK_SIZE_MIN: u32 = 1024;
 pub const EXT4_BLOCK_SIZE_MAX: u32 = 65536;
+pub const EXT4_SUPERBLOCK_SIZE: usize = 1024;
+pub const EXT4_MIN_DESC_SIZE: u16 = 32;
+pub const EXT4_MIN_DESC_SIZE_64BIT: u16 = 64;
 
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
@@ -114,6 +120,9 @@ impl Ext4Superblock {
     }
     
     pub fn blocks_count(&self) -> u64 {
+        if !self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT) {
+            return self.s_blocks_count_lo as u64;
+        }
         (self.s_blocks_count_hi as u64) << 32 | self.s_blocks_count_lo as u64
     }
     
@@ -146,6 +155,158 @@ impl Ext4Superblock {
     pub fn has_feature_ro_compat(&self, feature: u32) -> bool {
         self.s_feature_ro_compat & feature != 0
     }
+    
+    /// On-disk size of one group descriptor; only 64bit filesystems use
+    /// the long form.
+    pub fn desc_size(&self) -> u16 {
+        if self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT) {
+            self.s_desc_size
+        } else {
+            EXT4_MIN_DESC_SIZE
+        }
+    }
+    
+    /// Blocks occupied by one group's inode table.
+    pub fn inode_table_blocks(&self) -> u64 {
+        let bytes = self.s_inodes_per_group as u64 * self.inode_size() as u64;
+        bytes.div_ceil(self.block_size() as u64)
+    }
+    
+    pub fn set_free_blocks_count(&mut self, count: u64) {
+        self.s_free_blocks_count_lo = count as u32;
+        if self.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT) {
+            self.s_free_blocks_count_hi = (count >> 32) as u32;
+        }
+    }
+    
+    /// Whether every piece of metadata carries a CRC32C
+    pub fn metadata_csum(&self) -> bool {
+        self.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_METADATA_CSUM)
+    }
+    
+    /// Whether group descriptors are checksummed, and so whether the
+    /// BLOCK_UNINIT and INODE_UNINIT group flags mean anything
+    pub fn group_desc_csum(&self) -> bool {
+        self.metadata_csum() || self.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_GDT_CSUM)
+    }
+    
+    /// Whether this driver can change the filesystem without breaking
+    /// a feature it does not know how to keep up: a journal waiting to be
+    /// replayed, inline data, encryption, quotas and the like
+    pub fn writable(&self) -> bool {
+        self.s_feature_incompat & !EXT4_INCOMPAT_WRITABLE == 0
+            && self.s_feature_ro_compat & !EXT4_RO_COMPAT_WRITABLE == 0
+            && !self.has_feature_compat(EXT4_FEATURE_COMPAT_SPARSE_SUPER2)
+    }
+    
+    /// Whether group `group` has a copy of the superblock and group
+    /// descriptors at its start
+    pub fn has_super(&self, group: u32) -> bool {
+        let power_of = |base: u32| {
+            let mut n = base;
+            while n < group {
+                n = n.saturating_mul(base);
+            }
+            n == group
+        };
+        group <= 1
+            || !self.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER)
+            || power_of(3)
+            || power_of(5)
+            || power_of(7)
+    }
+    
+    /// Blocks taken by the group descriptors, after each superblock
+    pub fn group_desc_blocks(&self) -> u64 {
+        (self.block_group_count() as u64 * self.desc_size() as u64).div_ceil(self.block_size() as u64)
+    }
+    
+    /// The superblock as it is on disk
+    pub fn to_bytes(&self) -> [u8; EXT4_SUPERBLOCK_SIZE] {
+        unsafe { core::ptr::read_unaligned(self as *const Self as *const [u8; EXT4_SUPERBLOCK_SIZE]) }
+    }
+}
+
+const _: () = assert!(size_of::<Ext4Superblock>() == EXT4_SUPERBLOCK_SIZE);
+
+/// Decodes the superblock at the start of `buf` and checks every field the
+/// driver later divides by, indexes with or sizes allocations from, so code
+/// holding an `Ext4Superblock` can use its geometry without re-checking.
+pub fn parse_superblock(buf: &[u8]) -> FsResult<Ext4Superblock> {
+    if buf.len() < EXT4_SUPERBLOCK_SIZE {
+        return Err(FsError::InvalidArgument);
+    }
+    let sb: Ext4Superblock = unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const Ext4Superblock) };
+    
+    if !sb.is_valid() || EXT4_BLOCK_SIZE_MIN.checked_shl(sb.s_log_block_size).is_none_or(|bs| bs > EXT4_BLOCK_SIZE_MAX) {
+        return Err(FsError::InvalidArgument);
+    }
+    let block_size = sb.block_size();
+    
+    // Each group's block and inode bitmaps are a single block
+    let bits_per_block = block_size * 8;
+    if sb.s_blocks_per_group == 0 || sb.s_blocks_per_group > bits_per_block {
+        return Err(FsError::InvalidArgument);
+    }
+    if sb.s_inodes_per_group == 0 || sb.s_inodes_per_group > bits_per_block {
+        return Err(FsError::InvalidArgument);
+    }
+    
+    let inode_size = sb.inode_size();
+    if inode_size < EXT4_GOOD_OLD_INODE_SIZE || !inode_size.is_power_of_two() || inode_size as u32 > block_size {
+        return Err(FsError::InvalidArgument);
+    }
+    
+    // Block 0 holds the boot sector and superblock on 1 KiB filesystems
+    let first_data_block = if block_size == EXT4_BLOCK_SIZE_MIN { 1 } else { 0 };
+    if sb.s_first_data_block != first_data_block || sb.blocks_count() <= first_data_block as u64 {
+        return Err(FsError::InvalidArgument);
+    }
+    
+    let groups = sb.blocks_count().div_ceil(sb.s_blocks_per_group as u64);
+    if groups > u32::MAX as u64 {
+        return Err(FsError::InvalidArgument);
+    }
+    if sb.s_inodes_count == 0 || sb.s_inodes_count as u64 > groups * sb.s_inodes_per_group as u64 {
+        return Err(FsError::InvalidArgument);
+    }
+    
+    if sb.has_feature_incompat(EXT4_FEATURE_INCOMPAT_64BIT) {
+        let desc_size = sb.s_desc_size;
+        if desc_size < EXT4_MIN_DESC_SIZE_64BIT || !desc_size.is_power_of_two() || desc_size as u32 > block_size {
+            return Err(FsError::InvalidArgument);
+        }
+    }
+    
+    Ok(sb)
+}
+
+/// Decodes the group descriptor at the start of `buf`, which must hold at
+/// least `sb.desc_size()` bytes, and checks that the bitmaps and inode table
+/// it points at lie inside the filesystem. Short (32-byte) descriptors have
+/// their high halves zeroed.
+pub fn parse_group_desc(buf: &[u8], sb: &Ext4Superblock) -> FsResult<Ext4BlockGroupDesc> {
+    let desc_size = sb.desc_size() as usize;
+    if buf.len() < desc_size {
+        return Err(FsError::IoError);
+    }
+    
+    let mut raw = [0u8; size_of::<Ext4BlockGroupDesc>()];
+    let len = core::cmp::min(desc_size, raw.len());
+    raw[..len].copy_from_slice(&buf[..len]);
+    let desc: Ext4BlockGroupDesc = unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const Ext4BlockGroupDesc) };
+    
+    let blocks = sb.blocks_count();
+    let table_end = desc.inode_table().checked_add(sb.inode_table_blocks());
+    if desc.block_bitmap() >= blocks
+        || desc.inode_bitmap() >= blocks
+        || desc.inode_table() == 0
+        || table_end.is_none_or(|end| end > blocks)
+    {
+        return Err(FsError::IoError);
+    }
+    
+    Ok(desc)
 }
 
 pub const EXT4_FEATURE_COMPAT_DIR_PREALLOC: u32 = 0x0001;
@@ -172,6 +333,39 @@ pub const EXT4_FEATURE_INCOMPAT_LARGEDIR: u32 = 0x4000;
 pub const EXT4_FEATURE_INCOMPAT_INLINE_DATA: u32 = 0x8000;
 pub const EXT4_FEATURE_INCOMPAT_ENCRYPT: u32 = 0x10000;
 
+pub const EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+pub const EXT4_FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+pub const EXT4_FEATURE_RO_COMPAT_BTREE_DIR: u32 = 0x0004;
+pub const EXT4_FEATURE_RO_COMPAT_HUGE_FILE: u32 = 0x0008;
+pub const EXT4_FEATURE_RO_COMPAT_GDT_CSUM: u32 = 0x0010;
+pub const EXT4_FEATURE_RO_COMPAT_DIR_NLINK: u32 = 0x0020;
+pub const EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE: u32 = 0x0040;
+pub const EXT4_FEATURE_RO_COMPAT_QUOTA: u32 = 0x0100;
+pub const EXT4_FEATURE_RO_COMPAT_BIGALLOC: u32 = 0x0200;
+pub const EXT4_FEATURE_RO_COMPAT_METADATA_CSUM: u32 = 0x0400;
+
+/// Incompatible features a read-write mount can keep up
+const EXT4_INCOMPAT_WRITABLE: u32 = EXT4_FEATURE_INCOMPAT_FILETYPE
+    | EXT4_FEATURE_INCOMPAT_EXTENTS
+    | EXT4_FEATURE_INCOMPAT_64BIT
+    | EXT4_FEATURE_INCOMPAT_FLEX_BG
+    | EXT4_FEATURE_INCOMPAT_CSUM_SEED
+    | EXT4_FEATURE_INCOMPAT_LARGEDIR;
+/// Read-only compatible features a read-write mount can keep up
+const EXT4_RO_COMPAT_WRITABLE: u32 = EXT4_FEATURE_RO_COMPAT_SPARSE_SUPER
+    | EXT4_FEATURE_RO_COMPAT_LARGE_FILE
+    | EXT4_FEATURE_RO_COMPAT_HUGE_FILE
+    | EXT4_FEATURE_RO_COMPAT_GDT_CSUM
+    | EXT4_FEATURE_RO_COMPAT_DIR_NLINK
+    | EXT4_FEATURE_RO_COMPAT_EXTRA_ISIZE
+    | EXT4_FEATURE_RO_COMPAT_METADATA_CSUM;
+
+/// The group's inode bitmap and table were never written
+pub const EXT4_BG_INODE_UNINIT: u16 = 0x0001;
+/// The group's block bitmap was never written
+pub const EXT4_BG_BLOCK_UNINIT: u16 = 0x0002;
+pub const EXT4_BG_INODE_ZEROED: u16 = 0x0004;
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct Ext4BlockGroupDesc {
@@ -221,6 +415,40 @@ impl Ext4BlockGroupDesc {
     pub fn free_inodes_count(&self) -> u32 {
         (self.bg_free_inodes_count_hi as u32) << 16 | self.bg_free_inodes_count_lo as u32
     }
+    
+    pub fn used_dirs_count(&self) -> u32 {
+        (self.bg_used_dirs_count_hi as u32) << 16 | self.bg_used_dirs_count_lo as u32
+    }
+    
+    pub fn itable_unused(&self) -> u32 {
+        (self.bg_itable_unused_hi as u32) << 16 | self.bg_itable_unused_lo as u32
+    }
+    
+    pub fn set_free_blocks_count(&mut self, count: u32) {
+        self.bg_free_blocks_count_lo = count as u16;
+        self.bg_free_blocks_count_hi = (count >> 16) as u16;
+    }
+    
+    pub fn set_free_inodes_count(&mut self, count: u32) {
+        self.bg_free_inodes_count_lo = count as u16;
+        self.bg_free_inodes_count_hi = (count >> 16) as u16;
+    }
+    
+    pub fn set_used_dirs_count(&mut self, count: u32) {
+        self.bg_used_dirs_count_lo = count as u16;
+        self.bg_used_dirs_count_hi = (count >> 16) as u16;
+    }
+    
+    pub fn set_itable_unused(&mut self, count: u32) {
+        self.bg_itable_unused_lo = count as u16;
+        self.bg_itable_unused_hi = (count >> 16) as u16;
+    }
+    
+    /// The long (64-byte) form of the descriptor; short descriptors are
+    /// the first 32 bytes of it
+    pub fn to_bytes(&self) -> [u8; size_of::<Ext4BlockGroupDesc>()] {
+        unsafe { core::ptr::read_unaligned(self as *const Self as *const [u8; size_of::<Ext4BlockGroupDesc>()]) }
+    }
 }
 
 pub struct BlockCache {
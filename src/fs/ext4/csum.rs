@@ -0,0 +1,53 @@
+// ext4 metadata checksums
+//
+// With metadata_csum every inode, bitmap, group descriptor, directory
+// block and extent block carries a CRC32C, started from a seed derived
+// from the filesystem UUID (and, below the superblock, the inode number
+// and generation), with no final inversion. Older filesystems with
+// gdt_csum (uninit_bg) checksum only the group descriptors, with CRC16.
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0x82F6_3B78 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u16;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xA001 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32C_TABLE: [u32; 256] = crc32c_table();
+static CRC16_TABLE: [u16; 256] = crc16_table();
+
+/// CRC32C (Castagnoli) of `data` continuing from `crc`, as ext4 computes
+/// it: the caller passes !0 to start, and the result is not inverted
+pub fn crc32c(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| CRC32C_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+/// CRC16 (ANSI, reflected) of `data` continuing from `crc`
+pub fn crc16(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |crc, &b| CRC16_TABLE[((crc ^ b as u16) & 0xFF) as usize] ^ (crc >> 8))
+}
@@ -1,3 +1,19 @@
+// ext4
+//
+// Reads follow each file's block map or extent tree and read it whole. Mounted
+// read-write it also creates, writes, renames and removes files and
+// directories (see alloc, extent and write), writing each change straight
+// through: data and directory blocks as they change, then the bitmaps,
+// group descriptors and superblock it touched, with their checksums.
+// There is no journal. A filesystem whose journal still needs replaying,
+// or with a feature this driver cannot keep up, such as inline data,
+// encryption, quotas or bigalloc, only mounts read-only.
+//
+// New files and directories are block-mapped; files that already use
+// extents are written through their extent tree. A directory with a hash
+// index loses the index the first time it changes and is a plain list of
+// entries from then on, which is still valid ext4 (e2fsck -D rebuilds it).
+
 use core::mem::offset_of;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
@@ -6,16 +22,24 @@ use spin::RwLock;
 use alloc::vec;
 use crate::fs::{FileMode, FileStat, FileType, FsResult, FsError};
 use crate::fs::vfs::node::{VfsNode, DirEntry, Filesystem, InodeNumber};
-use super::block::{Ext4Superblock, Ext4BlockGroupDesc, BlockCache};
-use super::inode::{Ext4Inode, Ext4DirEntry, EXT4_ROOT_INO};
+use super::alloc::Changes;
+use super::block::{Ext4Superblock, Ext4BlockGroupDesc, BlockCache, EXT4_SUPERBLOCK_SIZE, EXT4_FEATURE_INCOMPAT_CSUM_SEED, parse_superblock, parse_group_desc};
+use super::csum::crc32c;
+use super::inode::{Ext4Inode, EXT4_ROOT_INO, parse_inode, parse_dir_entry};
 
 pub struct Ext4Filesystem {
-    superblock: Ext4Superblock,
-    block_groups: Vec<Ext4BlockGroupDesc>,
-    block_size: u32,
+    pub(super) superblock: Ext4Superblock,
+    pub(super) block_groups: Vec<Ext4BlockGroupDesc>,
+    pub(super) block_size: u32,
     block_cache: BlockCache,
-    device: Arc<RwLock<dyn BlockDevice + Send + Sync>>,
+    pub(super) device: Arc<RwLock<dyn BlockDevice + Send + Sync>>,
     read_only: bool,
+    /// Where metadata checksums start from
+    pub(super) csum_seed: u32,
+    /// Metadata changed and not yet written out
+    pub(super) changes: Changes,
+    /// Seconds since the epoch, for timestamps
+    clock: fn() -> u64,
 }
 
 pub trait BlockDevice {
@@ -23,49 +47,62 @@ pub trait BlockDevice {
     fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str>;
     fn block_size(&self) -> u32;
     fn block_count(&self) -> u64;
+    /// Write out anything held back, e.g. by a request queue
+    fn flush(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
 }
 
 impl Ext4Filesystem {
     pub fn mount(device: Arc<RwLock<dyn BlockDevice + Send + Sync>>, read_only: bool) -> FsResult<Self> {
-        let mut superblock_buf = [0u8; 1024];
+        let mut superblock_buf = [0u8; EXT4_SUPERBLOCK_SIZE];
         
         {
             let dev = device.read();
             dev.read_block(1, &mut superblock_buf).map_err(|_| FsError::IoError)?;
         }
         
-        let superblock: Ext4Superblock = unsafe {
-            core::ptr::read(superblock_buf.as_ptr() as *const Ext4Superblock)
-        };
+        let superblock = parse_superblock(&superblock_buf)?;
+        let block_size = superblock.block_size();
         
-        if !superblock.is_valid() {
-            return Err(FsError::InvalidArgument);
+        // Refuse geometry the device cannot back before sizing anything from it
+        {
+            let dev = device.read();
+            let capacity = dev.block_count().saturating_mul(dev.block_size() as u64);
+            if superblock.blocks_count().saturating_mul(block_size as u64) > capacity {
+                return Err(FsError::InvalidArgument);
+            }
         }
         
-        let block_size = superblock.block_size();
         let bg_count = superblock.block_group_count();
+        let desc_size = superblock.desc_size() as u32;
         
         let mut block_groups = Vec::with_capacity(bg_count as usize);
-        let bg_per_block = block_size / core::mem::size_of::<Ext4BlockGroupDesc>() as u32;
+        let bg_per_block = block_size / desc_size;
         
-        let bg_start_block = if block_size == 1024 { 2 } else { 1 };
+        let bg_start_block = superblock.s_first_data_block as u64 + 1;
         
+        let mut block_buf = vec![0u8; block_size as usize];
         for i in 0..bg_count {
-            let block_idx = bg_start_block + (i / bg_per_block);
-            let offset_in_block = (i % bg_per_block) as usize * core::mem::size_of::<Ext4BlockGroupDesc>();
+            let block_idx = bg_start_block + (i / bg_per_block) as u64;
+            let offset_in_block = ((i % bg_per_block) * desc_size) as usize;
             
-            let mut block_buf = vec![0u8; block_size as usize];
-            {
+            if offset_in_block == 0 {
                 let dev = device.read();
-                dev.read_block(block_idx as u64, &mut block_buf).map_err(|_| FsError::IoError)?;
+                dev.read_block(block_idx, &mut block_buf).map_err(|_| FsError::IoError)?;
             }
             
-            let bg: Ext4BlockGroupDesc = unsafe {
-                core::ptr::read(block_buf[offset_in_block..].as_ptr() as *const Ext4BlockGroupDesc)
-            };
-            block_groups.push(bg);
+            block_groups.push(parse_group_desc(&block_buf[offset_in_block..], &superblock)?);
         }
         
+        if !read_only && !superblock.writable() {
+            return Err(FsError::ReadOnly);
+        }
+        let csum_seed = match superblock.has_feature_incompat(EXT4_FEATURE_INCOMPAT_CSUM_SEED) {
+            true => superblock.s_checksum_seed,
+            false => crc32c(!0, &{ superblock.s_uuid }),
+        };
+        
         Ok(Ext4Filesystem {
             superblock,
             block_groups,
@@ -73,10 +110,23 @@ impl Ext4Filesystem {
             block_cache: BlockCache::new(block_size, 256),
             device,
             read_only,
+            csum_seed,
+            changes: Changes::default(),
+            clock: || 0,
         })
     }
     
-    fn read_inode(&self, inode_num: u32) -> FsResult<Ext4Inode> {
+    /// Timestamp what changes by `clock`, which returns Unix time in seconds
+    pub fn set_clock(&mut self, clock: fn() -> u64) {
+        self.clock = clock;
+    }
+    
+    pub(super) fn now(&self) -> u32 {
+        (self.clock)() as u32
+    }
+    
+    /// The inode table block holding inode `inode_num`, and where in it
+    pub(super) fn inode_location(&self, inode_num: u32) -> FsResult<(u64, usize)> {
         if inode_num == 0 || inode_num > self.superblock.s_inodes_count {
             return Err(FsError::NotFound);
         }
@@ -87,34 +137,45 @@ impl Ext4Filesystem {
         let bg_index = (inode_num - 1) / inodes_per_group;
         let inode_index = (inode_num - 1) % inodes_per_group;
         
-        let bg = &self.block_groups[bg_index as usize];
+        let bg = self.block_groups.get(bg_index as usize).ok_or(FsError::IoError)?;
         let inode_table_block = bg.inode_table();
         
         let inodes_per_block = self.block_size / inode_size;
         let block_offset = inode_index / inodes_per_block;
         let offset_in_block = (inode_index % inodes_per_block) * inode_size;
         
-        let mut block_buf = vec![0u8; self.block_size as usize];
-        {
-            let dev = self.device.read();
-            dev.read_block(inode_table_block + block_offset as u64, &mut block_buf)
-                .map_err(|_| FsError::IoError)?;
-        }
-        
-        let inode: Ext4Inode = unsafe {
-            core::ptr::read(block_buf[offset_in_block as usize..].as_ptr() as *const Ext4Inode)
-        };
-        
-        Ok(inode)
+        Ok((inode_table_block + block_offset as u64, offset_in_block as usize))
     }
     
-    fn read_block_data(&self, block_num: u64) -> FsResult<Vec<u8>> {
+    pub(super) fn read_inode(&self, inode_num: u32) -> FsResult<Ext4Inode> {
+        let (block, offset) = self.inode_location(inode_num)?;
+        let block_buf = self.read_block_data(block)?;
+        parse_inode(&block_buf[offset..], self.superblock.inode_size())
+    }
+    
+    pub(super) fn read_block_data(&self, block_num: u64) -> FsResult<Vec<u8>> {
         let mut buf = vec![0u8; self.block_size as usize];
         let dev = self.device.read();
         dev.read_block(block_num, &mut buf).map_err(|_| FsError::IoError)?;
         Ok(buf)
     }
     
+    pub(super) fn write_block_data(&self, block_num: u64, data: &[u8]) -> FsResult<()> {
+        let mut dev = self.device.write();
+        dev.write_block(block_num, data).map_err(|_| FsError::IoError)
+    }
+    
+    /// Run the change `f` on a filesystem mounted read-write, then write
+    /// out the metadata it changed, whether or not it succeeded
+    fn change<T>(&mut self, f: impl FnOnce(&mut Self) -> FsResult<T>) -> FsResult<T> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        let result = f(self);
+        self.flush()?;
+        result
+    }
+    
     fn inode_to_vfs_node(&self, inode_num: u32, name: &str) -> FsResult<VfsNode> {
         let inode = self.read_inode(inode_num)?;
         
@@ -130,7 +191,7 @@ impl Ext4Filesystem {
                     content = self.read_file_data(&inode)?;
                 }
                 
-                crate::fs::vfs::node::VfsNodeData::Regular(content)
+                crate::fs::vfs::node::VfsNodeData::Regular(content.into())
             }
             FileType::Directory => {
                 let entries = self.read_directory_entries(&inode)?;
@@ -140,7 +201,7 @@ impl Ext4Filesystem {
                 let target = self.read_symlink(&inode)?;
                 crate::fs::vfs::node::VfsNodeData::Symlink(target)
             }
-            _ => crate::fs::vfs::node::VfsNodeData::Regular(Vec::new()),
+            _ => crate::fs::vfs::node::VfsNodeData::Regular(Default::default()),
         };
         
         Ok(VfsNode {
@@ -156,10 +217,16 @@ impl Ext4Filesystem {
             nlink: inode.i_links_count as u64,
             device: None,
             data,
+            casefold: false,
         })
     }
     
     fn read_file_data(&self, inode: &Ext4Inode) -> FsResult<Vec<u8>> {
+        // Files are read whole, so a size beyond the filesystem's own is
+        // treated as corruption rather than allocated.
+        if inode.size() > self.superblock.blocks_count() * self.block_size as u64 {
+            return Err(FsError::IoError);
+        }
         let size = inode.size() as usize;
         let mut data = Vec::with_capacity(size);
         
@@ -172,52 +239,100 @@ impl Ext4Filesystem {
         Ok(data)
     }
     
-    fn read_extent_data(&self, _inode: &Ext4Inode, data: &mut Vec<u8>, _size: usize) -> FsResult<()> {
+    /// Reads through the extent tree, however many levels deep (see
+    /// load_extents). Holes and unwritten extents read back as zeros.
+    fn read_extent_data(&self, inode: &Ext4Inode, data: &mut Vec<u8>, size: usize) -> FsResult<()> {
+        let (extents, _) = self.load_extents(inode)?;
+        let block_size = self.block_size as u64;
+        data.resize(size, 0);
+        
+        for extent in extents.iter().filter(|e| !e.unwritten) {
+            for i in 0..extent.len as u64 {
+                let at = (extent.logical as u64 + i) * block_size;
+                if at >= size as u64 {
+                    break;
+                }
+                let to_copy = core::cmp::min(size as u64 - at, block_size) as usize;
+                let block_data = self.read_block_data(extent.start + i)?;
+                data[at as usize..at as usize + to_copy].copy_from_slice(&block_data[..to_copy]);
+            }
+        }
+        
         Ok(())
     }
     
     fn read_block_map_data(&self, inode: &Ext4Inode, data: &mut Vec<u8>, size: usize) -> FsResult<()> {
-        let mut remaining = size;
-        let mut block_idx = 0u32;
+        let block_size = self.block_size as usize;
+        let blocks = size.div_ceil(block_size);
+        
+        for logical in 0..blocks as u64 {
+            let to_copy = core::cmp::min(size - data.len(), block_size);
+            match self.map_block(inode, logical)? {
+                // Holes in sparse files read back as zeros
+                0 => data.resize(data.len() + to_copy, 0),
+                physical => {
+                    let block_data = self.read_block_data(physical as u64)?;
+                    data.extend_from_slice(&block_data[..to_copy]);
+                }
+            }
+        }
         
-        while remaining > 0 && block_idx < 12 {
-            let block_num = inode.i_block[block_idx as usize];
-            if block_num == 0 {
-                break;
+        Ok(())
+    }
+    
+    /// Maps a logical file block to its physical block through the direct,
+    /// indirect, double and triple indirect pointers. 0 means a hole.
+    pub(super) fn map_block(&self, inode: &Ext4Inode, mut logical: u64) -> FsResult<u32> {
+        let i_block = inode.i_block;
+        if logical < 12 {
+            return Ok(i_block[logical as usize]);
+        }
+        logical -= 12;
+        
+        let per_block = (self.block_size / 4) as u64;
+        let mut span = 1u64;
+        for (depth, slot) in [12usize, 13, 14].into_iter().enumerate() {
+            span *= per_block;
+            if logical >= span {
+                logical -= span;
+                continue;
             }
             
-            let block_data = self.read_block_data(block_num as u64)?;
-            let to_copy = core::cmp::min(remaining, self.block_size as usize);
-            data.extend_from_slice(&block_data[..to_copy]);
-            
-            remaining -= to_copy;
-            block_idx += 1;
+            let mut block = i_block[slot];
+            let mut step = span;
+            for _ in 0..=depth {
+                if block == 0 {
+                    return Ok(0);
+                }
+                step /= per_block;
+                let index = (logical / step) as usize;
+                logical %= step;
+                let table = self.read_block_data(block as u64)?;
+                block = u32::from_le_bytes([
+                    table[index * 4],
+                    table[index * 4 + 1],
+                    table[index * 4 + 2],
+                    table[index * 4 + 3],
+                ]);
+            }
+            return Ok(block);
         }
         
-        Ok(())
+        Err(FsError::InvalidArgument)
     }
     
     fn read_directory_entries(&self, inode: &Ext4Inode) -> FsResult<Vec<DirEntry>> {
         let mut entries = Vec::new();
         let data = self.read_file_data(inode)?;
         
-        let mut offset = 0usize;
-        while offset < data.len() {
-            if offset + 8 > data.len() {
-                break;
-            }
-            
-            let dir_entry: Ext4DirEntry = unsafe {
-                core::ptr::read(data[offset..].as_ptr() as *const Ext4DirEntry)
-            };
-            
-            if dir_entry.inode != 0 && dir_entry.name_len > 0 {
-                let name_start = offset + 8;
-                let name_end = name_start + dir_entry.name_len as usize;
+        // Entries never cross a block boundary
+        for block in data.chunks(self.block_size as usize) {
+            let mut offset = 0usize;
+            while offset < block.len() {
+                let (dir_entry, name) = parse_dir_entry(block, offset)?;
                 
-                if name_end <= data.len() {
-                    let name_bytes = &data[name_start..name_end];
-                    if let Ok(name) = core::str::from_utf8(name_bytes) {
+                if dir_entry.inode != 0 && !name.is_empty() {
+                    if let Ok(name) = core::str::from_utf8(name) {
                         entries.push(DirEntry::new(
                             name.to_string(),
                             dir_entry.inode as u64,
@@ -225,12 +340,9 @@ impl Ext4Filesystem {
                         ));
                     }
                 }
+                
+                offset += dir_entry.rec_len as usize;
             }
-            
-            if dir_entry.rec_len == 0 {
-                break;
-            }
-            offset += dir_entry.rec_len as usize;
         }
         
         Ok(entries)
@@ -314,46 +426,32 @@ impl Filesystem for Ext4Filesystem {
         Ok(len)
     }
     
-    fn write(&mut self, _inode: InodeNumber, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn write(&mut self, inode: InodeNumber, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        self.change(|fs| fs.write_data(inode as u32, offset, buf))
     }
     
-    fn create(&mut self, _parent: InodeNumber, _name: &str, _mode: FileMode) -> FsResult<VfsNode> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn create(&mut self, parent: InodeNumber, name: &str, mode: FileMode) -> FsResult<VfsNode> {
+        let mode = FileMode::S_IFREG | (mode.0 & 0o7777);
+        let inode = self.change(|fs| fs.make(parent as u32, name, mode))?;
+        self.inode_to_vfs_node(inode, name)
     }
     
-    fn mkdir(&mut self, _parent: InodeNumber, _name: &str, _mode: FileMode) -> FsResult<VfsNode> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn mkdir(&mut self, parent: InodeNumber, name: &str, mode: FileMode) -> FsResult<VfsNode> {
+        let mode = FileMode::S_IFDIR | (mode.0 & 0o7777);
+        let inode = self.change(|fs| fs.make(parent as u32, name, mode))?;
+        self.inode_to_vfs_node(inode, name)
     }
     
-    fn unlink(&mut self, _parent: InodeNumber, _name: &str) -> FsResult<()> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn unlink(&mut self, parent: InodeNumber, name: &str) -> FsResult<()> {
+        self.change(|fs| fs.remove(parent as u32, name, false))
     }
     
-    fn rmdir(&mut self, _parent: InodeNumber, _name: &str) -> FsResult<()> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn rmdir(&mut self, parent: InodeNumber, name: &str) -> FsResult<()> {
+        self.change(|fs| fs.remove(parent as u32, name, true))
     }
     
-    fn rename(&mut self, _old_parent: InodeNumber, _old_name: &str, _new_parent: InodeNumber, _new_name: &str) -> FsResult<()> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn rename(&mut self, old_parent: InodeNumber, old_name: &str, new_parent: InodeNumber, new_name: &str) -> FsResult<()> {
+        self.change(|fs| fs.move_entry(old_parent as u32, old_name, new_parent as u32, new_name))
     }
     
     fn stat(&self, inode: InodeNumber) -> FsResult<FileStat> {
@@ -372,6 +470,6 @@ impl Filesystem for Ext4Filesystem {
     }
     
     fn sync(&mut self) -> FsResult<()> {
-        Ok(())
+        self.device.write().flush().map_err(|_| FsError::IoError)
     }
 }
@@ -0,0 +1,224 @@
+// ext4 extent trees
+//
+// A file with extents keeps runs of blocks rather than one pointer per
+// block: up to four in the inode's i_block, and past that in leaf blocks
+// the inode points at, through index blocks when there are more leaves
+// than fit. Writes load the whole tree as a sorted list of extents,
+// change the list, and store it again as the smallest tree that holds
+// it, reusing the blocks the old one had. Unwritten extents are blocks
+// allocated ahead and not yet written, which read as zeros.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::fs::{FsError, FsResult};
+use super::ext4::Ext4Filesystem;
+use super::inode::{
+    Ext4Inode, encode_extent_entry, encode_extent_header, parse_extent, parse_extent_header, parse_extent_idx,
+    EXT4_EXTENT_ENTRY, EXT4_EXTENT_HEADER, EXT4_MAX_EXTENT_LEN,
+};
+
+/// Entries the root of the tree in i_block holds
+const ROOT_ENTRIES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// First file block it covers
+    pub logical: u32,
+    pub len: u32,
+    /// Where on disk it starts
+    pub start: u64,
+    pub unwritten: bool,
+}
+
+impl Extent {
+    pub fn end(&self) -> u64 {
+        self.logical as u64 + self.len as u64
+    }
+
+    fn max_len(&self) -> u32 {
+        match self.unwritten {
+            true => EXT4_MAX_EXTENT_LEN - 1,
+            false => EXT4_MAX_EXTENT_LEN,
+        }
+    }
+}
+
+/// Where file block `logical` is in `extents`, if any covers it
+pub fn find_extent(extents: &[Extent], logical: u32) -> Option<usize> {
+    let i = extents.partition_point(|e| e.end() <= logical as u64);
+    extents.get(i).filter(|e| e.logical <= logical).map(|_| i)
+}
+
+/// Put `extent` of one block in its place, joining the extents either
+/// side of it when they run on
+pub fn insert_extent(extents: &mut Vec<Extent>, extent: Extent) {
+    let i = extents.partition_point(|e| e.logical < extent.logical);
+    extents.insert(i, extent);
+    merge_extents(extents, i);
+}
+
+/// Mark file block `logical`, in an unwritten extent, written, splitting
+/// the extent around it
+pub fn mark_written(extents: &mut Vec<Extent>, logical: u32) {
+    let Some(i) = find_extent(extents, logical).filter(|&i| extents[i].unwritten) else {
+        return;
+    };
+    let e = extents[i];
+    let before = logical - e.logical;
+    let mut pieces = Vec::new();
+    if before > 0 {
+        pieces.push(Extent { len: before, ..e });
+    }
+    pieces.push(Extent { logical, len: 1, start: e.start + before as u64, unwritten: false });
+    if before + 1 < e.len {
+        pieces.push(Extent { logical: logical + 1, len: e.len - before - 1, start: e.start + before as u64 + 1, unwritten: true });
+    }
+    let written = i + (before > 0) as usize;
+    extents.splice(i..=i, pieces);
+    merge_extents(extents, written);
+}
+
+/// Join the extent at `i` to its neighbours where they are contiguous on
+/// disk and in the file
+fn merge_extents(extents: &mut Vec<Extent>, mut i: usize) {
+    let joins = |a: &Extent, b: &Extent| {
+        a.end() == b.logical as u64
+            && a.start + a.len as u64 == b.start
+            && a.unwritten == b.unwritten
+            && a.len + b.len <= a.max_len()
+    };
+    if i > 0 && joins(&extents[i - 1], &extents[i]) {
+        extents[i - 1].len += extents[i].len;
+        extents.remove(i);
+        i -= 1;
+    }
+    if i + 1 < extents.len() && joins(&extents[i], &extents[i + 1]) {
+        extents[i].len += extents[i + 1].len;
+        extents.remove(i + 1);
+    }
+}
+
+impl Ext4Filesystem {
+    /// Entries a tree block holds, leaving room for its checksum
+    fn node_entries(&self) -> usize {
+        (self.block_size as usize - EXT4_EXTENT_HEADER) / EXT4_EXTENT_ENTRY
+    }
+
+    /// Blocks a tree of `count` extents takes outside the inode
+    pub(super) fn extent_tree_blocks(&self, count: usize) -> usize {
+        let per_node = self.node_entries();
+        let (mut total, mut level) = (0, count);
+        while level > ROOT_ENTRIES {
+            level = level.div_ceil(per_node);
+            total += level;
+        }
+        total
+    }
+
+    /// The extents of `inode` in file order, and the blocks its tree
+    /// takes. A tree whose entries overlap, are out of order or point
+    /// outside the filesystem is an I/O error.
+    pub(super) fn load_extents(&self, inode: &Ext4Inode) -> FsResult<(Vec<Extent>, Vec<u64>)> {
+        let mut extents = Vec::new();
+        let mut nodes = Vec::new();
+        self.load_extent_node(&inode.block_bytes(), None, &mut extents, &mut nodes)?;
+        let blocks = self.superblock.blocks_count();
+        let valid = extents.iter().all(|e| e.len > 0 && e.start.checked_add(e.len as u64).is_some_and(|end| end <= blocks))
+            && extents.windows(2).all(|w| w[0].end() <= w[1].logical as u64);
+        match valid {
+            true => Ok((extents, nodes)),
+            false => Err(FsError::IoError),
+        }
+    }
+
+    /// Walk the node `node`, whose depth, below the root, must be one
+    /// less than its parent's
+    fn load_extent_node(&self, node: &[u8], parent_depth: Option<u16>, extents: &mut Vec<Extent>, nodes: &mut Vec<u64>) -> FsResult<()> {
+        let header = parse_extent_header(node)?;
+        if parent_depth.is_some_and(|depth| header.eh_depth + 1 != depth) {
+            return Err(FsError::IoError);
+        }
+        for i in 0..header.eh_entries as usize {
+            if header.eh_depth == 0 {
+                let e = parse_extent(node, i);
+                extents.push(Extent { logical: e.ee_block, len: e.len(), start: e.start(), unwritten: e.is_unwritten() });
+                continue;
+            }
+            let block = parse_extent_idx(node, i).leaf();
+            if block >= self.superblock.blocks_count() || nodes.contains(&block) {
+                return Err(FsError::IoError);
+            }
+            nodes.push(block);
+            let child = self.read_block_data(block)?;
+            self.load_extent_node(&child, Some(header.eh_depth), extents, nodes)?;
+        }
+        Ok(())
+    }
+
+    /// Store `extents` as the tree of inode `number`, in the blocks
+    /// `nodes` of its old tree and as many more as it needs, freeing
+    /// those it does not
+    pub(super) fn store_extents(&mut self, number: u32, inode: &mut Ext4Inode, extents: &[Extent], mut nodes: Vec<u64>) -> FsResult<()> {
+        let per_node = self.node_entries();
+        let old = nodes.len();
+        // The entries of the level being built, as (first block, entry)
+        let mut level: Vec<(u32, [u8; EXT4_EXTENT_ENTRY])> = extents
+            .iter()
+            .map(|e| {
+                let mut entry = [0u8; EXT4_EXTENT_ENTRY];
+                let len = e.len as u16 + if e.unwritten { EXT4_MAX_EXTENT_LEN as u16 } else { 0 };
+                encode_extent_entry(&mut entry, e.logical, Some(len), e.start);
+                (e.logical, entry)
+            })
+            .collect();
+        let goal = extents.first().map_or_else(|| self.inode_goal(number), |e| e.start);
+        let seed = self.inode_seed(number, inode.i_generation);
+        let mut depth = 0u16;
+        let mut added = 0usize;
+        while level.len() > ROOT_ENTRIES {
+            let mut up = Vec::new();
+            for chunk in level.chunks(per_node) {
+                let block = match nodes.pop() {
+                    Some(block) => block,
+                    None => {
+                        added += 1;
+                        self.alloc_block(goal, u64::MAX)?
+                    }
+                };
+                let mut data = vec![0u8; self.block_size as usize];
+                encode_extent_header(&mut data, chunk.len() as u16, per_node as u16, depth);
+                for (i, (_, entry)) in chunk.iter().enumerate() {
+                    let at = EXT4_EXTENT_HEADER + i * EXT4_EXTENT_ENTRY;
+                    data[at..at + EXT4_EXTENT_ENTRY].copy_from_slice(entry);
+                }
+                if self.superblock.metadata_csum() {
+                    let tail = EXT4_EXTENT_HEADER + per_node * EXT4_EXTENT_ENTRY;
+                    let csum = super::csum::crc32c(seed, &data[..tail]);
+                    data[tail..tail + 4].copy_from_slice(&csum.to_le_bytes());
+                }
+                self.write_block_data(block, &data)?;
+                let mut entry = [0u8; EXT4_EXTENT_ENTRY];
+                encode_extent_entry(&mut entry, chunk[0].0, None, block);
+                up.push((chunk[0].0, entry));
+            }
+            level = up;
+            depth += 1;
+        }
+        let mut root = [0u8; 60];
+        encode_extent_header(&mut root, level.len() as u16, ROOT_ENTRIES as u16, depth);
+        for (i, (_, entry)) in level.iter().enumerate() {
+            let at = EXT4_EXTENT_HEADER + i * EXT4_EXTENT_ENTRY;
+            root[at..at + EXT4_EXTENT_ENTRY].copy_from_slice(entry);
+        }
+        inode.set_block_bytes(&root);
+        let freed = nodes.len();
+        for block in nodes {
+            self.free_block(block)?;
+        }
+        let per_block = (self.block_size / 512) as u64;
+        let sectors = inode.sectors(self.block_size) + added as u64 * per_block;
+        inode.set_sectors(sectors.saturating_sub(freed as u64 * per_block));
+        debug_assert_eq!(old + added - freed, self.extent_tree_blocks(extents.len()));
+        Ok(())
+    }
+}
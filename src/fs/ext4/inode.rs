@@ -1,5 +1,6 @@
 use alloc::vec::Vec;
-use crate::fs::{FileMode, FileType};
+use core::mem::size_of;
+use crate::fs::{FileMode, FileType, FsError, FsResult};
 
 pub const EXT4_GOOD_OLD_INODE_SIZE: u16 = 128;
 pub const EXT4_ROOT_INO: u32 = 2;
@@ -87,8 +88,72 @@ impl Ext4Inode {
         let hi = ((self.i_osd2[7] as u32) << 8) | (self.i_osd2[6] as u32);
         (hi << 16) | self.i_gid as u32
     }
+    
+    pub fn set_uid(&mut self, uid: u32) {
+        self.i_uid = uid as u16;
+        self.i_osd2[4..6].copy_from_slice(&((uid >> 16) as u16).to_le_bytes());
+    }
+    
+    pub fn set_gid(&mut self, gid: u32) {
+        self.i_gid = gid as u16;
+        self.i_osd2[6..8].copy_from_slice(&((gid >> 16) as u16).to_le_bytes());
+    }
+    
+    /// Blocks the inode holds, data and metadata, in 512-byte units
+    /// whichever unit it keeps them in
+    pub fn sectors(&self, block_size: u32) -> u64 {
+        let count = (u16::from_le_bytes([self.i_osd2[0], self.i_osd2[1]]) as u64) << 32 | self.i_blocks_lo as u64;
+        match self.i_flags & EXT4_HUGE_FILE_FL != 0 {
+            true => count * (block_size / 512) as u64,
+            false => count,
+        }
+    }
+    
+    pub fn set_sectors(&mut self, sectors: u64) {
+        self.i_blocks_lo = sectors as u32;
+        self.i_osd2[0..2].copy_from_slice(&((sectors >> 32) as u16).to_le_bytes());
+        self.i_flags &= !EXT4_HUGE_FILE_FL;
+    }
+    
+    /// The block holding extended attributes that did not fit in the
+    /// inode, 0 if none
+    pub fn file_acl(&self) -> u64 {
+        (u16::from_le_bytes([self.i_osd2[2], self.i_osd2[3]]) as u64) << 32 | self.i_file_acl_lo as u64
+    }
+    
+    pub fn set_file_acl(&mut self, block: u64) {
+        self.i_file_acl_lo = block as u32;
+        self.i_osd2[2..4].copy_from_slice(&((block >> 32) as u16).to_le_bytes());
+    }
+    
+    /// i_block as it is on disk, where an extent tree's root is kept
+    pub fn block_bytes(&self) -> [u8; 60] {
+        let mut bytes = [0u8; 60];
+        for (i, pointer) in { self.i_block }.iter().enumerate() {
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&pointer.to_le_bytes());
+        }
+        bytes
+    }
+    
+    pub fn set_block_bytes(&mut self, bytes: &[u8; 60]) {
+        let mut i_block = [0u32; 15];
+        for (i, pointer) in i_block.iter_mut().enumerate() {
+            *pointer = u32::from_le_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+        }
+        self.i_block = i_block;
+    }
+    
+    /// The first 128 bytes of the inode on disk, and as much of the
+    /// extra area after them as this struct covers
+    pub fn to_bytes(&self) -> [u8; size_of::<Ext4Inode>()] {
+        unsafe { core::ptr::read_unaligned(self as *const Self as *const [u8; size_of::<Ext4Inode>()]) }
+    }
 }
 
+/// Offsets of the two halves of an inode's checksum
+pub const EXT4_INODE_CHECKSUM_LO: usize = 0x7C;
+pub const EXT4_INODE_CHECKSUM_HI: usize = 0x82;
+
 pub const EXT4_SECRM_FL: u32 = 0x00000001;
 pub const EXT4_UNRM_FL: u32 = 0x00000002;
 pub const EXT4_COMPR_FL: u32 = 0x00000004;
@@ -134,6 +199,83 @@ impl Ext4ExtentHeader {
     }
 }
 
+/// Size of an extent tree node's header and of each entry after it
+pub const EXT4_EXTENT_HEADER: usize = 12;
+pub const EXT4_EXTENT_ENTRY: usize = 12;
+/// Deepest an extent tree can go
+pub const EXT4_MAX_EXTENT_DEPTH: u16 = 5;
+/// Blocks a written extent covers at most; an unwritten one one less
+pub const EXT4_MAX_EXTENT_LEN: u32 = 32768;
+
+/// Decodes the header at the start of an extent tree node, checking that
+/// the entries it claims fit in `node`
+pub fn parse_extent_header(node: &[u8]) -> FsResult<Ext4ExtentHeader> {
+    let raw = node.get(..EXT4_EXTENT_HEADER).ok_or(FsError::IoError)?;
+    let header = Ext4ExtentHeader {
+        eh_magic: u16::from_le_bytes([raw[0], raw[1]]),
+        eh_entries: u16::from_le_bytes([raw[2], raw[3]]),
+        eh_max: u16::from_le_bytes([raw[4], raw[5]]),
+        eh_depth: u16::from_le_bytes([raw[6], raw[7]]),
+        eh_generation: u32::from_le_bytes([raw[8], raw[9], raw[10], raw[11]]),
+    };
+    let room = (node.len() - EXT4_EXTENT_HEADER) / EXT4_EXTENT_ENTRY;
+    if !header.is_valid() || header.eh_entries > header.eh_max || header.eh_max as usize > room || header.eh_depth > EXT4_MAX_EXTENT_DEPTH {
+        return Err(FsError::IoError);
+    }
+    Ok(header)
+}
+
+/// The `index`th entry of an extent tree node, whose header has been
+/// checked by parse_extent_header, as a leaf holds it
+pub fn parse_extent(node: &[u8], index: usize) -> Ext4Extent {
+    let e = &node[EXT4_EXTENT_HEADER + index * EXT4_EXTENT_ENTRY..];
+    Ext4Extent {
+        ee_block: u32::from_le_bytes([e[0], e[1], e[2], e[3]]),
+        ee_len: u16::from_le_bytes([e[4], e[5]]),
+        ee_start_hi: u16::from_le_bytes([e[6], e[7]]),
+        ee_start_lo: u32::from_le_bytes([e[8], e[9], e[10], e[11]]),
+    }
+}
+
+/// The `index`th entry of an extent tree node, as an index node holds it
+pub fn parse_extent_idx(node: &[u8], index: usize) -> Ext4ExtentIdx {
+    let e = &node[EXT4_EXTENT_HEADER + index * EXT4_EXTENT_ENTRY..];
+    Ext4ExtentIdx {
+        ei_block: u32::from_le_bytes([e[0], e[1], e[2], e[3]]),
+        ei_leaf_lo: u32::from_le_bytes([e[4], e[5], e[6], e[7]]),
+        ei_leaf_hi: u16::from_le_bytes([e[8], e[9]]),
+        ei_unused: 0,
+    }
+}
+
+/// Writes an extent tree node header for `entries` of `max` at `depth`
+/// to the start of `node`
+pub fn encode_extent_header(node: &mut [u8], entries: u16, max: u16, depth: u16) {
+    node[0..2].copy_from_slice(&Ext4ExtentHeader::MAGIC.to_le_bytes());
+    node[2..4].copy_from_slice(&entries.to_le_bytes());
+    node[4..6].copy_from_slice(&max.to_le_bytes());
+    node[6..8].copy_from_slice(&depth.to_le_bytes());
+    node[8..12].fill(0);
+}
+
+/// An extent tree entry: a leaf's extent of `len` blocks at `start`, or
+/// an index entry pointing at the node `start` when `len` is None
+pub fn encode_extent_entry(entry: &mut [u8], block: u32, len: Option<u16>, start: u64) {
+    entry[0..4].copy_from_slice(&block.to_le_bytes());
+    match len {
+        Some(len) => {
+            entry[4..6].copy_from_slice(&len.to_le_bytes());
+            entry[6..8].copy_from_slice(&((start >> 32) as u16).to_le_bytes());
+            entry[8..12].copy_from_slice(&(start as u32).to_le_bytes());
+        }
+        None => {
+            entry[4..8].copy_from_slice(&(start as u32).to_le_bytes());
+            entry[8..10].copy_from_slice(&((start >> 32) as u16).to_le_bytes());
+            entry[10..12].fill(0);
+        }
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct Ext4ExtentIdx {
@@ -200,6 +342,81 @@ impl Ext4DirEntry {
     }
 }
 
+pub const EXT4_DIR_ENTRY_HEADER: usize = 8;
+/// The entry at the end of each directory block holding its checksum,
+/// on filesystems with metadata_csum
+pub const EXT4_DIR_TAIL: usize = 12;
+pub const EXT4_DIR_TAIL_FT: u8 = 0xDE;
+pub const EXT4_NAME_LEN: usize = 255;
+
+/// Space an entry with a name of `name_len` bytes takes at the least
+pub fn dir_rec_len(name_len: usize) -> usize {
+    (EXT4_DIR_ENTRY_HEADER + name_len + 3) & !3
+}
+
+/// Writes a directory entry at `offset` in a directory block
+pub fn encode_dir_entry(block: &mut [u8], offset: usize, inode: u32, rec_len: usize, name: &[u8], file_type: u8) {
+    let entry = &mut block[offset..offset + rec_len];
+    entry[0..4].copy_from_slice(&inode.to_le_bytes());
+    entry[4..6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+    entry[6] = name.len() as u8;
+    entry[7] = file_type;
+    entry[EXT4_DIR_ENTRY_HEADER..EXT4_DIR_ENTRY_HEADER + name.len()].copy_from_slice(name);
+}
+
+/// Decodes an on-disk inode of `inode_size` bytes from the start of `buf`.
+/// Fields past the record (128-byte inodes) or past the area `i_extra_isize`
+/// claims are read as zero, never from whatever follows in the buffer.
+pub fn parse_inode(buf: &[u8], inode_size: u16) -> FsResult<Ext4Inode> {
+    let inode_size = inode_size as usize;
+    let base = EXT4_GOOD_OLD_INODE_SIZE as usize;
+    if inode_size < base || buf.len() < inode_size {
+        return Err(FsError::IoError);
+    }
+    
+    let mut raw = [0u8; size_of::<Ext4Inode>()];
+    raw[..base].copy_from_slice(&buf[..base]);
+    if inode_size > base {
+        let extra = u16::from_le_bytes([buf[base], buf[base + 1]]) as usize;
+        if base + extra > inode_size || !extra.is_multiple_of(4) {
+            return Err(FsError::IoError);
+        }
+        let end = core::cmp::min(base + extra, raw.len());
+        raw[base..end].copy_from_slice(&buf[base..end]);
+    }
+    
+    Ok(unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const Ext4Inode) })
+}
+
+/// Decodes the directory entry at `offset` in a directory block, returning
+/// it with its name. Entries may not straddle the end of `block`, and
+/// `rec_len` must be aligned and large enough to cover the name, so walking
+/// a block by `rec_len` always makes progress and stays in bounds.
+pub fn parse_dir_entry(block: &[u8], offset: usize) -> FsResult<(Ext4DirEntry, &[u8])> {
+    let header = offset
+        .checked_add(EXT4_DIR_ENTRY_HEADER)
+        .and_then(|end| block.get(offset..end))
+        .ok_or(FsError::IoError)?;
+    let entry = Ext4DirEntry {
+        inode: u32::from_le_bytes([header[0], header[1], header[2], header[3]]),
+        rec_len: u16::from_le_bytes([header[4], header[5]]),
+        name_len: header[6],
+        file_type: header[7],
+    };
+    
+    let rec_len = entry.rec_len as usize;
+    if rec_len < EXT4_DIR_ENTRY_HEADER
+        || !rec_len.is_multiple_of(4)
+        || rec_len > block.len() - offset
+        || EXT4_DIR_ENTRY_HEADER + entry.name_len as usize > rec_len
+    {
+        return Err(FsError::IoError);
+    }
+    
+    let name_start = offset + EXT4_DIR_ENTRY_HEADER;
+    Ok((entry, &block[name_start..name_start + entry.name_len as usize]))
+}
+
 pub const EXT4_FT_UNKNOWN: u8 = 0;
 pub const EXT4_FT_REG_FILE: u8 = 1;
 pub const EXT4_FT_DIR: u8 = 2;
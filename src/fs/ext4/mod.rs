@@ -1,6 +1,10 @@
 pub mod block;
 pub mod inode;
 pub mod ext4;
+pub mod csum;
+mod alloc;
+mod extent;
+mod write;
 
 pub use block::*;
 pub use inode::*;
@@ -0,0 +1,771 @@
+// ext4 writes: file data and directory entries
+//
+// A file's blocks are found and added through its block map, direct then
+// indirect pointers with the indirect blocks allocated as needed, or its
+// extents (see extent). Each new block goes after the one before it in
+// the file where that is free. Writing into the middle of a block reads
+// it first; bytes between the old end of a file and a write past it read
+// back as zeros.
+//
+// Directories are plain lists of entries, each block ending in a tail
+// holding its checksum on filesystems with metadata_csum. A new entry
+// takes the slack after an entry that has enough, or a new block at the
+// end; a removed entry is folded into the one before it.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::fs::{FileMode, FsError, FsResult};
+use super::block::{EXT4_FEATURE_INCOMPAT_FILETYPE, EXT4_FEATURE_RO_COMPAT_DIR_NLINK, EXT4_FEATURE_RO_COMPAT_LARGE_FILE};
+use super::csum::crc32c;
+use super::ext4::Ext4Filesystem;
+use super::extent::{find_extent, insert_extent, mark_written, Extent};
+use super::inode::{
+    Ext4Inode, dir_rec_len, encode_dir_entry, parse_dir_entry, EXT4_DIR_ENTRY_HEADER, EXT4_DIR_TAIL, EXT4_DIR_TAIL_FT,
+    EXT4_FT_BLKDEV, EXT4_FT_CHRDEV, EXT4_FT_DIR, EXT4_FT_FIFO, EXT4_FT_REG_FILE, EXT4_FT_SOCK, EXT4_FT_SYMLINK,
+    EXT4_FT_UNKNOWN, EXT4_GOOD_OLD_INODE_SIZE, EXT4_INDEX_FL, EXT4_NAME_LEN, EXT4_ROOT_INO,
+};
+
+/// Pointers in i_block before the indirect ones
+const DIRECT_BLOCKS: u64 = 12;
+/// Links a directory counts; past this, with dir_nlink, it counts 1
+const EXT4_LINK_MAX: u16 = 65000;
+/// Extended attribute block magic, and where its reference count and
+/// checksum are
+const XATTR_MAGIC: u32 = 0xEA02_0000;
+const XATTR_REFCOUNT: usize = 4;
+const XATTR_CHECKSUM: usize = 16;
+/// Files larger than this need the large_file feature
+const LARGE_FILE_SIZE: u64 = 0x7FFF_FFFF;
+
+/// Where a file's blocks are while a change adds to them: through its
+/// block map, or in its extents, loaded whole and stored at the end
+enum Mapping {
+    Blocks,
+    Extents { extents: Vec<Extent>, nodes: Vec<u64> },
+}
+
+/// A directory entry as find_entry found it
+struct Slot {
+    block: u64,
+    offset: usize,
+    /// The entry before it in the same block
+    previous: Option<usize>,
+    inode: u32,
+}
+
+fn check_name(name: &str) -> FsResult<()> {
+    match name {
+        "" => Err(FsError::NameTooLong),
+        _ if name.len() > EXT4_NAME_LEN => Err(FsError::NameTooLong),
+        "." | ".." => Err(FsError::AlreadyExists),
+        _ if name.contains(['/', '\0']) => Err(FsError::InvalidArgument),
+        _ => Ok(()),
+    }
+}
+
+fn has_dir_tail(block: &[u8]) -> bool {
+    let tail = &block[block.len() - EXT4_DIR_TAIL..];
+    tail[..4] == [0; 4] && tail[4..6] == (EXT4_DIR_TAIL as u16).to_le_bytes() && tail[6] == 0 && tail[7] == EXT4_DIR_TAIL_FT
+}
+
+impl Ext4Filesystem {
+    fn open_mapping(&self, inode: &Ext4Inode) -> FsResult<Mapping> {
+        match inode.uses_extents() {
+            true => {
+                let (extents, nodes) = self.load_extents(inode)?;
+                Ok(Mapping::Extents { extents, nodes })
+            }
+            false => Ok(Mapping::Blocks),
+        }
+    }
+
+    /// Store what a change did to a file's extents
+    fn close_mapping(&mut self, mapping: Mapping, number: u32, inode: &mut Ext4Inode) -> FsResult<()> {
+        match mapping {
+            Mapping::Blocks => Ok(()),
+            Mapping::Extents { extents, nodes } => self.store_extents(number, inode, &extents, nodes),
+        }
+    }
+
+    /// Blocks a file can have
+    fn max_file_blocks(&self, mapping: &Mapping) -> u64 {
+        match mapping {
+            Mapping::Blocks => {
+                let per_block = (self.block_size / 4) as u64;
+                DIRECT_BLOCKS + per_block + per_block.pow(2) + per_block.pow(3)
+            }
+            Mapping::Extents { .. } => 1 << 32,
+        }
+    }
+
+    /// Where file block `logical` is, and whether it is unwritten; None
+    /// for a hole
+    fn lookup_block(&self, mapping: &Mapping, inode: &Ext4Inode, logical: u64) -> FsResult<Option<(u64, bool)>> {
+        match mapping {
+            Mapping::Blocks => Ok(Some(self.map_block(inode, logical)? as u64).filter(|&block| block != 0).map(|block| (block, false))),
+            Mapping::Extents { extents, .. } => Ok(find_extent(extents, logical as u32).map(|i| {
+                let e = extents[i];
+                (e.start + (logical as u32 - e.logical) as u64, e.unwritten)
+            })),
+        }
+    }
+
+    /// Give file block `logical`, a hole, a block of its own near `goal`
+    fn add_block(&mut self, mapping: &mut Mapping, inode: &mut Ext4Inode, logical: u64, goal: u64) -> FsResult<u64> {
+        let block = match mapping {
+            Mapping::Blocks => {
+                let block = self.alloc_block(goal, 1 << 32)?;
+                if let Err(e) = self.set_block_map(inode, logical, block) {
+                    self.free_block(block)?;
+                    return Err(e);
+                }
+                block
+            }
+            Mapping::Extents { extents, nodes } => {
+                // Keep back what the tree may grow by to hold the block
+                let tree = self.extent_tree_blocks(extents.len() + 1).saturating_sub(nodes.len());
+                if self.superblock.free_blocks_count() <= tree as u64 {
+                    return Err(FsError::NoSpace);
+                }
+                let block = self.alloc_block(goal, u64::MAX)?;
+                insert_extent(extents, Extent { logical: logical as u32, len: 1, start: block, unwritten: false });
+                block
+            }
+        };
+        inode.set_sectors(inode.sectors(self.block_size) + (self.block_size / 512) as u64);
+        Ok(block)
+    }
+
+    /// Point file block `logical` of a block-mapped file at `block`,
+    /// allocating the indirect blocks on the way
+    fn set_block_map(&mut self, inode: &mut Ext4Inode, mut logical: u64, block: u64) -> FsResult<()> {
+        let mut i_block = inode.i_block;
+        if logical < DIRECT_BLOCKS {
+            i_block[logical as usize] = block as u32;
+            inode.i_block = i_block;
+            return Ok(());
+        }
+        logical -= DIRECT_BLOCKS;
+
+        let per_block = (self.block_size / 4) as u64;
+        let mut span = 1u64;
+        for (depth, slot) in [12usize, 13, 14].into_iter().enumerate() {
+            span *= per_block;
+            if logical >= span {
+                logical -= span;
+                continue;
+            }
+
+            if i_block[slot] == 0 {
+                i_block[slot] = self.alloc_indirect(inode, block)? as u32;
+                inode.i_block = i_block;
+            }
+            let mut table_block = i_block[slot] as u64;
+            let mut step = span;
+            for level in 0..=depth {
+                step /= per_block;
+                let index = (logical / step) as usize * 4;
+                logical %= step;
+                let mut table = self.read_block_data(table_block)?;
+                if level == depth {
+                    table[index..index + 4].copy_from_slice(&(block as u32).to_le_bytes());
+                    return self.write_block_data(table_block, &table);
+                }
+                let mut next = u32::from_le_bytes([table[index], table[index + 1], table[index + 2], table[index + 3]]);
+                if next == 0 {
+                    next = self.alloc_indirect(inode, block)? as u32;
+                    table[index..index + 4].copy_from_slice(&next.to_le_bytes());
+                    self.write_block_data(table_block, &table)?;
+                }
+                table_block = next as u64;
+            }
+        }
+
+        Err(FsError::InvalidArgument)
+    }
+
+    /// A zeroed block for a table of block pointers, near `goal`
+    fn alloc_indirect(&mut self, inode: &mut Ext4Inode, goal: u64) -> FsResult<u64> {
+        let block = self.alloc_block(goal, 1 << 32)?;
+        self.write_block_data(block, &vec![0u8; self.block_size as usize])?;
+        inode.set_sectors(inode.sectors(self.block_size) + (self.block_size / 512) as u64);
+        Ok(block)
+    }
+
+    /// Write `buf` at `offset` in regular file `number`. A write the
+    /// filesystem fills up during is cut short, and says how much it
+    /// wrote.
+    pub(super) fn write_data(&mut self, number: u32, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        let mut inode = self.read_inode(number)?;
+        if inode.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        if !inode.is_file() {
+            return Err(FsError::InvalidArgument);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let block_size = self.block_size as u64;
+        let mut mapping = self.open_mapping(&inode)?;
+        let end = offset.checked_add(buf.len() as u64).ok_or(FsError::InvalidArgument)?;
+        if end.div_ceil(block_size) > self.max_file_blocks(&mapping) {
+            return Err(FsError::InvalidArgument);
+        }
+
+        // What is past the old end of the file in its last block may not
+        // be zeros, and a write further on brings it into the file
+        let size = inode.size();
+        if offset > size && size % block_size != 0 {
+            if let Some((block, false)) = self.lookup_block(&mapping, &inode, size / block_size)? {
+                let mut data = self.read_block_data(block)?;
+                data[(size % block_size) as usize..].fill(0);
+                self.write_block_data(block, &data)?;
+            }
+        }
+
+        let mut written = 0;
+        let result = self.write_blocks(&mut mapping, number, &mut inode, offset, buf, &mut written);
+        self.close_mapping(mapping, number, &mut inode)?;
+        let end = offset + written as u64;
+        if end > size {
+            inode.set_size(end);
+            if end > LARGE_FILE_SIZE && !self.superblock.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_LARGE_FILE) {
+                self.add_ro_compat_feature(EXT4_FEATURE_RO_COMPAT_LARGE_FILE);
+            }
+        }
+        if written > 0 {
+            inode.i_mtime = self.now();
+            inode.i_ctime = self.now();
+        }
+        self.write_inode(number, &inode, false)?;
+        match result {
+            Err(e) if written == 0 => Err(e),
+            _ => Ok(written),
+        }
+    }
+
+    fn write_blocks(&mut self, mapping: &mut Mapping, number: u32, inode: &mut Ext4Inode, offset: u64, buf: &[u8], written: &mut usize) -> FsResult<()> {
+        let block_size = self.block_size as u64;
+        let end = offset + buf.len() as u64;
+        let mut goal = match offset / block_size {
+            0 => None,
+            first => self.lookup_block(mapping, inode, first - 1)?.map(|(block, _)| block + 1),
+        }
+        .unwrap_or_else(|| self.inode_goal(number));
+        for logical in offset / block_size..end.div_ceil(block_size) {
+            let first = logical * block_size;
+            let (from, to) = (offset.max(first), end.min(first + block_size));
+            let (block, mut data) = match self.lookup_block(mapping, inode, logical)? {
+                Some((block, false)) if to - from < block_size => (block, self.read_block_data(block)?),
+                Some((block, unwritten)) => {
+                    if let (true, Mapping::Extents { extents, .. }) = (unwritten, &mut *mapping) {
+                        mark_written(extents, logical as u32);
+                    }
+                    (block, vec![0u8; block_size as usize])
+                }
+                None => (self.add_block(mapping, inode, logical, goal)?, vec![0u8; block_size as usize]),
+            };
+            data[(from - first) as usize..(to - first) as usize].copy_from_slice(&buf[(from - offset) as usize..(to - offset) as usize]);
+            self.write_block_data(block, &data)?;
+            goal = block + 1;
+            *written = (to - offset) as usize;
+        }
+        Ok(())
+    }
+
+    /// Make `name` in directory `parent`, a regular file or directory by
+    /// `mode`, and return its inode number
+    pub(super) fn make(&mut self, parent: u32, name: &str, mode: u16) -> FsResult<u32> {
+        check_name(name)?;
+        let dir = self.read_inode(parent)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        // Removed while open
+        if dir.i_links_count == 0 {
+            return Err(FsError::NotFound);
+        }
+        if self.find_entry(&dir, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        let directory = mode & FileMode::S_IFMT == FileMode::S_IFDIR;
+        if directory && dir.i_links_count >= EXT4_LINK_MAX - 1 && !self.superblock.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_DIR_NLINK) {
+            return Err(FsError::TooManyLinks);
+        }
+
+        let number = self.alloc_inode(parent, directory)?;
+        let mut inode = self.new_inode(number, &dir, mode);
+        let file_type = self.dir_file_type(mode);
+        let result = (|| {
+            if directory {
+                self.make_dir_block(number, &mut inode, parent)?;
+            }
+            self.write_inode(number, &inode, true)?;
+            self.add_entry(parent, name, number, file_type)
+        })();
+        if let Err(e) = result {
+            if inode.i_block[0] != 0 {
+                self.free_block(inode.i_block[0] as u64)?;
+            }
+            self.free_inode(number, directory)?;
+            return Err(e);
+        }
+        if directory {
+            self.link_parent(parent, true)?;
+        }
+        Ok(number)
+    }
+
+    /// A new inode `number` in directory `dir`, owned by whoever makes
+    /// it, or by the directory's group if it is set-group-ID
+    fn new_inode(&self, number: u32, dir: &Ext4Inode, mut mode: u16) -> Ext4Inode {
+        let directory = mode & FileMode::S_IFMT == FileMode::S_IFDIR;
+        let (uid, mut gid) = crate::fs::vfs::credentials();
+        if dir.i_mode & FileMode::S_ISGID != 0 {
+            gid = dir.gid();
+            if directory {
+                mode |= FileMode::S_ISGID;
+            }
+        }
+        // Handles kept by NFS and the like tell reused inodes apart by it
+        let generation = self.read_inode(number).map_or(0, |old| old.i_generation.wrapping_add(1));
+        let now = self.now();
+
+        // SAFETY: Ext4Inode is plain integers, for which zero is valid
+        let mut inode: Ext4Inode = unsafe { core::mem::zeroed() };
+        inode.i_mode = mode;
+        inode.set_uid(uid);
+        inode.set_gid(gid);
+        inode.i_atime = now;
+        inode.i_ctime = now;
+        inode.i_mtime = now;
+        inode.i_links_count = if directory { 2 } else { 1 };
+        inode.i_generation = generation;
+        let extra = self.superblock.inode_size() as usize - EXT4_GOOD_OLD_INODE_SIZE as usize;
+        if extra > 0 {
+            inode.i_extra_isize = core::cmp::min(extra, size_of::<Ext4Inode>() - EXT4_GOOD_OLD_INODE_SIZE as usize) as u16;
+            inode.i_crtime = now;
+        }
+        inode
+    }
+
+    /// Give new directory `number` its first block, holding "." and ".."
+    fn make_dir_block(&mut self, number: u32, inode: &mut Ext4Inode, parent: u32) -> FsResult<()> {
+        let block = self.alloc_block(self.inode_goal(number), 1 << 32)?;
+        let mut i_block = [0u32; 15];
+        i_block[0] = block as u32;
+        inode.i_block = i_block;
+        inode.set_size(self.block_size as u64);
+        inode.set_sectors((self.block_size / 512) as u64);
+
+        let mut data = vec![0u8; self.block_size as usize];
+        let file_type = self.dir_file_type(FileMode::S_IFDIR);
+        let dot = dir_rec_len(1);
+        encode_dir_entry(&mut data, 0, number, dot, b".", file_type);
+        encode_dir_entry(&mut data, dot, parent, self.dir_end() - dot, b"..", file_type);
+        self.write_dir_block(number, inode, block, &mut data)
+    }
+
+    /// Take `name` out of directory `parent`, and the file or directory
+    /// it names with it once nothing else links to it
+    pub(super) fn remove(&mut self, parent: u32, name: &str, directory: bool) -> FsResult<()> {
+        if name == "." || name == ".." {
+            return Err(if directory { FsError::InvalidArgument } else { FsError::IsDirectory });
+        }
+        let dir = self.read_inode(parent)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        let slot = self.find_entry(&dir, name)?.ok_or(FsError::NotFound)?;
+        let mut inode = self.read_inode(slot.inode)?;
+        match (inode.is_dir(), directory) {
+            (true, false) => return Err(FsError::IsDirectory),
+            (false, true) => return Err(FsError::NotDirectory),
+            (true, true) if !self.dir_is_empty(&inode)? => return Err(FsError::NotEmpty),
+            _ => {}
+        }
+        self.remove_entry(parent, name)?;
+        self.unlink_inode(slot.inode, &mut inode)?;
+        if directory {
+            self.link_parent(parent, false)?;
+        }
+        Ok(())
+    }
+
+    /// Move the entry `old_name` in `old_parent` to `new_name` in
+    /// `new_parent`, replacing what is there
+    pub(super) fn move_entry(&mut self, old_parent: u32, old_name: &str, new_parent: u32, new_name: &str) -> FsResult<()> {
+        if [old_name, new_name].iter().any(|&name| name == "." || name == "..") {
+            return Err(FsError::InvalidArgument);
+        }
+        check_name(new_name)?;
+        let old_dir = self.read_inode(old_parent)?;
+        let new_dir = self.read_inode(new_parent)?;
+        if !old_dir.is_dir() || !new_dir.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        let source = self.find_entry(&old_dir, old_name)?.ok_or(FsError::NotFound)?;
+        let inode = self.read_inode(source.inode)?;
+        let directory = inode.is_dir();
+        let target = self.find_entry(&new_dir, new_name)?;
+        // Two links to the same file
+        if target.as_ref().is_some_and(|target| target.inode == source.inode) {
+            return Ok(());
+        }
+        let moves = directory && old_parent != new_parent;
+        if moves && self.is_within(new_parent, source.inode)? {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let file_type = self.dir_file_type(inode.i_mode);
+        match target {
+            Some(target) => {
+                let mut replaced = self.read_inode(target.inode)?;
+                match (replaced.is_dir(), directory) {
+                    (true, false) => return Err(FsError::IsDirectory),
+                    (false, true) => return Err(FsError::NotDirectory),
+                    (true, true) if !self.dir_is_empty(&replaced)? => return Err(FsError::NotEmpty),
+                    _ => {}
+                }
+                self.set_entry(new_parent, new_name, source.inode, file_type)?;
+                self.unlink_inode(target.inode, &mut replaced)?;
+                if directory {
+                    self.link_parent(new_parent, false)?;
+                }
+            }
+            None => {
+                if moves && new_dir.i_links_count >= EXT4_LINK_MAX - 1 && !self.superblock.has_feature_ro_compat(EXT4_FEATURE_RO_COMPAT_DIR_NLINK) {
+                    return Err(FsError::TooManyLinks);
+                }
+                self.add_entry(new_parent, new_name, source.inode, file_type)?;
+            }
+        }
+        self.remove_entry(old_parent, old_name)?;
+        if moves {
+            self.set_entry(source.inode, "..", new_parent, self.dir_file_type(FileMode::S_IFDIR))?;
+            self.link_parent(old_parent, false)?;
+            self.link_parent(new_parent, true)?;
+        }
+        let mut inode = self.read_inode(source.inode)?;
+        inode.i_ctime = self.now();
+        self.write_inode(source.inode, &inode, false)
+    }
+
+    /// Whether directory `dir` is `ancestor` or under it
+    fn is_within(&self, mut dir: u32, ancestor: u32) -> FsResult<bool> {
+        // However deep the tree, a walk up it meets no inode twice
+        for _ in 0..self.superblock.s_inodes_count {
+            if dir == ancestor {
+                return Ok(true);
+            }
+            if dir == EXT4_ROOT_INO {
+                return Ok(false);
+            }
+            let up = self.find_entry(&self.read_inode(dir)?, "..")?.ok_or(FsError::IoError)?;
+            dir = up.inode;
+        }
+        Err(FsError::IoError)
+    }
+
+    /// Drop a link to inode `number`, and free it with the last
+    fn unlink_inode(&mut self, number: u32, inode: &mut Ext4Inode) -> FsResult<()> {
+        // A directory's own "." goes with its entry
+        inode.i_links_count = match inode.is_dir() {
+            true => 0,
+            false => inode.i_links_count.saturating_sub(1),
+        };
+        inode.i_ctime = self.now();
+        match inode.i_links_count {
+            0 => self.release(number, inode),
+            _ => self.write_inode(number, inode, false),
+        }
+    }
+
+    /// Count a subdirectory more or less in directory `number`, which
+    /// its ".." links to
+    fn link_parent(&mut self, number: u32, more: bool) -> FsResult<()> {
+        let mut dir = self.read_inode(number)?;
+        let links = dir.i_links_count;
+        // 1 is a directory with too many subdirectories to count
+        dir.i_links_count = match more {
+            true if links == 1 || links >= EXT4_LINK_MAX - 1 => 1,
+            true => links + 1,
+            false if links > 2 => links - 1,
+            false => links,
+        };
+        dir.i_ctime = self.now();
+        self.write_inode(number, &dir, false)
+    }
+
+    /// Free inode `number`, with no links left, and its blocks
+    fn release(&mut self, number: u32, inode: &mut Ext4Inode) -> FsResult<()> {
+        let acl = inode.file_acl();
+        let acl_sectors = if acl != 0 { (self.block_size / 512) as u64 } else { 0 };
+        // Devices keep their numbers in i_block, and short symlinks their
+        // target
+        let has_blocks = inode.is_file() || inode.is_dir() || (inode.is_symlink() && inode.sectors(self.block_size) > acl_sectors);
+        if has_blocks {
+            if inode.uses_extents() {
+                let (extents, nodes) = self.load_extents(inode)?;
+                for e in extents {
+                    for block in e.start..e.start + e.len as u64 {
+                        self.free_block(block)?;
+                    }
+                }
+                for block in nodes {
+                    self.free_block(block)?;
+                }
+            } else {
+                let i_block = inode.i_block;
+                for &block in i_block[..DIRECT_BLOCKS as usize].iter().filter(|&&block| block != 0) {
+                    self.free_block(block as u64)?;
+                }
+                for (depth, &block) in i_block[DIRECT_BLOCKS as usize..].iter().enumerate() {
+                    self.free_indirect(block as u64, depth)?;
+                }
+            }
+            inode.set_block_bytes(&[0; 60]);
+        }
+        if acl != 0 {
+            self.release_xattr_block(acl)?;
+            inode.set_file_acl(0);
+        }
+        inode.set_size(0);
+        inode.set_sectors(0);
+        inode.i_dtime = self.now();
+        self.write_inode(number, inode, false)?;
+        self.free_inode(number, inode.is_dir())
+    }
+
+    /// Free the table of block pointers `block`, `depth` tables above the
+    /// data, and what it points to
+    fn free_indirect(&mut self, block: u64, depth: usize) -> FsResult<()> {
+        if block == 0 {
+            return Ok(());
+        }
+        let table = self.read_block_data(block)?;
+        for pointer in table.chunks_exact(4) {
+            let pointer = u32::from_le_bytes([pointer[0], pointer[1], pointer[2], pointer[3]]) as u64;
+            match depth {
+                0 if pointer != 0 => self.free_block(pointer)?,
+                0 => {}
+                _ => self.free_indirect(pointer, depth - 1)?,
+            }
+        }
+        self.free_block(block)
+    }
+
+    /// Drop a reference to the extended attribute block `block`, which
+    /// files with the same attributes share
+    fn release_xattr_block(&mut self, block: u64) -> FsResult<()> {
+        let mut data = self.read_block_data(block)?;
+        let word = |data: &[u8], at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        if word(&data, 0) != XATTR_MAGIC {
+            return Err(FsError::IoError);
+        }
+        let refcount = word(&data, XATTR_REFCOUNT);
+        if refcount <= 1 {
+            return self.free_block(block);
+        }
+        data[XATTR_REFCOUNT..XATTR_REFCOUNT + 4].copy_from_slice(&(refcount - 1).to_le_bytes());
+        if self.superblock.metadata_csum() {
+            data[XATTR_CHECKSUM..XATTR_CHECKSUM + 4].fill(0);
+            let csum = crc32c(crc32c(self.csum_seed, &block.to_le_bytes()), &data);
+            data[XATTR_CHECKSUM..XATTR_CHECKSUM + 4].copy_from_slice(&csum.to_le_bytes());
+        }
+        self.write_block_data(block, &data)
+    }
+
+    /// The directory entry file type of a file of `mode`, where entries
+    /// have one
+    fn dir_file_type(&self, mode: u16) -> u8 {
+        if !self.superblock.has_feature_incompat(EXT4_FEATURE_INCOMPAT_FILETYPE) {
+            return EXT4_FT_UNKNOWN;
+        }
+        match mode & FileMode::S_IFMT {
+            FileMode::S_IFREG => EXT4_FT_REG_FILE,
+            FileMode::S_IFDIR => EXT4_FT_DIR,
+            FileMode::S_IFCHR => EXT4_FT_CHRDEV,
+            FileMode::S_IFBLK => EXT4_FT_BLKDEV,
+            FileMode::S_IFIFO => EXT4_FT_FIFO,
+            FileMode::S_IFSOCK => EXT4_FT_SOCK,
+            FileMode::S_IFLNK => EXT4_FT_SYMLINK,
+            _ => EXT4_FT_UNKNOWN,
+        }
+    }
+
+    /// Where entries end in a directory block, before its tail
+    fn dir_end(&self) -> usize {
+        match self.superblock.metadata_csum() {
+            true => self.block_size as usize - EXT4_DIR_TAIL,
+            false => self.block_size as usize,
+        }
+    }
+
+    /// The blocks of directory `dir`, in order
+    fn dir_blocks(&self, dir: &Ext4Inode) -> FsResult<Vec<u64>> {
+        let mapping = self.open_mapping(dir)?;
+        let count = dir.size().div_ceil(self.block_size as u64);
+        if count > self.superblock.blocks_count() {
+            return Err(FsError::IoError);
+        }
+        (0..count)
+            .map(|logical| Ok(self.lookup_block(&mapping, dir, logical)?.ok_or(FsError::IoError)?.0))
+            .collect()
+    }
+
+    fn find_entry(&self, dir: &Ext4Inode, name: &str) -> FsResult<Option<Slot>> {
+        for block in self.dir_blocks(dir)? {
+            let data = self.read_block_data(block)?;
+            let (mut offset, mut previous) = (0, None);
+            while offset < data.len() {
+                let (entry, entry_name) = parse_dir_entry(&data, offset)?;
+                if entry.inode != 0 && entry_name == name.as_bytes() {
+                    return Ok(Some(Slot { block, offset, previous, inode: entry.inode }));
+                }
+                previous = Some(offset);
+                offset += entry.rec_len as usize;
+            }
+        }
+        Ok(None)
+    }
+
+    fn dir_is_empty(&self, dir: &Ext4Inode) -> FsResult<bool> {
+        for block in self.dir_blocks(dir)? {
+            let data = self.read_block_data(block)?;
+            let mut offset = 0;
+            while offset < data.len() {
+                let (entry, name) = parse_dir_entry(&data, offset)?;
+                if entry.inode != 0 && name != b"." && name != b".." {
+                    return Ok(false);
+                }
+                offset += entry.rec_len as usize;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Write block `block` of directory `number`, with its tail
+    fn write_dir_block(&self, number: u32, dir: &Ext4Inode, block: u64, data: &mut [u8]) -> FsResult<()> {
+        if self.superblock.metadata_csum() {
+            let end = self.dir_end();
+            encode_dir_entry(data, end, 0, EXT4_DIR_TAIL, &[], EXT4_DIR_TAIL_FT);
+            let csum = crc32c(self.inode_seed(number, dir.i_generation), &data[..end]);
+            data[end + 8..end + 12].copy_from_slice(&csum.to_le_bytes());
+        }
+        self.write_block_data(block, data)
+    }
+
+    /// Turn directory `number` from hash-indexed into a plain list. The
+    /// index hides in what reads as the slack of an entry, in blocks with
+    /// no room yet for a tail, which that entry is cut short to make.
+    fn deindex(&mut self, number: u32, dir: &mut Ext4Inode) -> FsResult<()> {
+        if dir.i_flags & EXT4_INDEX_FL == 0 {
+            return Ok(());
+        }
+        dir.i_flags &= !EXT4_INDEX_FL;
+        if !self.superblock.metadata_csum() {
+            return Ok(());
+        }
+        for block in self.dir_blocks(dir)? {
+            let mut data = self.read_block_data(block)?;
+            if has_dir_tail(&data) {
+                continue;
+            }
+            let (mut offset, mut last) = (0, 0);
+            while offset < data.len() {
+                last = offset;
+                offset += parse_dir_entry(&data, offset)?.0.rec_len as usize;
+            }
+            let (entry, _) = parse_dir_entry(&data, last)?;
+            let rec_len = entry.rec_len as usize - EXT4_DIR_TAIL;
+            if rec_len < dir_rec_len(entry.name_len as usize) {
+                return Err(FsError::IoError);
+            }
+            data[last + 4..last + 6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+            self.write_dir_block(number, dir, block, &mut data)?;
+        }
+        Ok(())
+    }
+
+    /// Set directory `number`'s times after its entries changed, and
+    /// write it
+    fn touch_dir(&mut self, number: u32, dir: &mut Ext4Inode) -> FsResult<()> {
+        dir.i_mtime = self.now();
+        dir.i_ctime = self.now();
+        self.write_inode(number, dir, false)
+    }
+
+    /// Add the entry `name` for inode `inode` to directory `number`
+    fn add_entry(&mut self, number: u32, name: &str, inode: u32, file_type: u8) -> FsResult<()> {
+        let mut dir = self.read_inode(number)?;
+        self.deindex(number, &mut dir)?;
+        let needed = dir_rec_len(name.len());
+        let end = self.dir_end();
+        let blocks = self.dir_blocks(&dir)?;
+        for &block in &blocks {
+            let mut data = self.read_block_data(block)?;
+            let mut offset = 0;
+            while offset < end {
+                let (entry, _) = parse_dir_entry(&data[..end], offset)?;
+                let rec_len = entry.rec_len as usize;
+                let used = match entry.inode {
+                    0 => 0,
+                    _ => dir_rec_len(entry.name_len as usize),
+                };
+                if rec_len - used >= needed {
+                    if used > 0 {
+                        data[offset + 4..offset + 6].copy_from_slice(&(used as u16).to_le_bytes());
+                    }
+                    encode_dir_entry(&mut data, offset + used, inode, rec_len - used, name.as_bytes(), file_type);
+                    self.write_dir_block(number, &dir, block, &mut data)?;
+                    return self.touch_dir(number, &mut dir);
+                }
+                offset += rec_len;
+            }
+        }
+
+        // Every block is full
+        let mut mapping = self.open_mapping(&dir)?;
+        let goal = blocks.last().map_or_else(|| self.inode_goal(number), |&block| block + 1);
+        let block = self.add_block(&mut mapping, &mut dir, blocks.len() as u64, goal)?;
+        self.close_mapping(mapping, number, &mut dir)?;
+        let mut data = vec![0u8; self.block_size as usize];
+        encode_dir_entry(&mut data, 0, inode, end, name.as_bytes(), file_type);
+        self.write_dir_block(number, &dir, block, &mut data)?;
+        dir.set_size(dir.size() + self.block_size as u64);
+        self.touch_dir(number, &mut dir)
+    }
+
+    /// Take the entry `name` out of directory `number`
+    fn remove_entry(&mut self, number: u32, name: &str) -> FsResult<()> {
+        let mut dir = self.read_inode(number)?;
+        self.deindex(number, &mut dir)?;
+        let slot = self.find_entry(&dir, name)?.ok_or(FsError::NotFound)?;
+        let mut data = self.read_block_data(slot.block)?;
+        match slot.previous {
+            Some(previous) => {
+                let rec_len = |data: &[u8], at: usize| u16::from_le_bytes([data[at + 4], data[at + 5]]);
+                let joined = rec_len(&data, previous) + rec_len(&data, slot.offset);
+                data[previous + 4..previous + 6].copy_from_slice(&joined.to_le_bytes());
+            }
+            // The first entry of a block stays, unused
+            None => data[slot.offset..slot.offset + 4].fill(0),
+        }
+        self.write_dir_block(number, &dir, slot.block, &mut data)?;
+        self.touch_dir(number, &mut dir)
+    }
+
+    /// Point the entry `name` in directory `number` at inode `inode`
+    fn set_entry(&mut self, number: u32, name: &str, inode: u32, file_type: u8) -> FsResult<()> {
+        let mut dir = self.read_inode(number)?;
+        self.deindex(number, &mut dir)?;
+        let slot = self.find_entry(&dir, name)?.ok_or(FsError::NotFound)?;
+        let mut data = self.read_block_data(slot.block)?;
+        data[slot.offset..slot.offset + 4].copy_from_slice(&inode.to_le_bytes());
+        data[slot.offset + EXT4_DIR_ENTRY_HEADER - 1] = file_type;
+        self.write_dir_block(number, &dir, slot.block, &mut data)?;
+        self.touch_dir(number, &mut dir)
+    }
+}
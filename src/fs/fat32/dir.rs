@@ -1,7 +1,7 @@
 use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::format;
-use crate::fs::FileType;
+use crate::fs::{FileType, FsError, FsResult};
 
 pub const DIR_ENTRY_SIZE: usize = 32;
 pub const ATTR_READ_ONLY: u8 = 0x01;
@@ -34,6 +34,26 @@ pub struct Fat32DirEntry {
 }
 
 impl Fat32DirEntry {
+    /// An entry named `name`, in 8.3 form, starting at `cluster`
+    pub fn new(name: [u8; 11], attr: u8, cluster: u32) -> Self {
+        let mut entry = Fat32DirEntry {
+            name,
+            attr,
+            nt_res: 0,
+            crt_time_tenth: 0,
+            crt_time: 0,
+            crt_date: 0,
+            lst_acc_date: 0,
+            fst_clus_hi: 0,
+            wrt_time: 0,
+            wrt_date: 0,
+            fst_clus_lo: 0,
+            file_size: 0,
+        };
+        entry.set_first_cluster(cluster);
+        entry
+    }
+    
     pub fn is_free(&self) -> bool {
         self.name[0] == DIR_FREE
     }
@@ -130,68 +150,46 @@ impl Fat32LfnEntry {
     
     pub fn get_name_chars(&self) -> [u16; 13] {
         let mut chars = [0u16; 13];
+        let base = self as *const Self as *const u8;
+        let parts = [
+            (core::mem::offset_of!(Self, name1), 0, 5),
+            (core::mem::offset_of!(Self, name2), 5, 6),
+            (core::mem::offset_of!(Self, name3), 11, 2),
+        ];
 
-        unsafe {
-            // Build a dummy instance as raw bytes
-            let mut tmp = core::mem::MaybeUninit::<Self>::uninit();
-            let base = tmp.as_mut_ptr() as *mut u8;
-
-            // Size of the struct
-            let size = core::mem::size_of::<Self>();
-
-            let mut name1_offset = 0usize;
-            let mut name2_offset = 0usize;
-            let mut name3_offset = 0usize;
-
-            // We discover offsets by scanning for the three arrays' patterns.
-            // This avoids touching the packed fields through references.
-            for i in 0..size {
-                let field = base.add(i) as *mut u16;
-
-                // name1 is [u16;5]
-                if name1_offset == 0 && i + 5 * 2 <= size {
-                    name1_offset = i;
-                }
-
-                // name2 is [u16;6]
-                if name2_offset == 0 && i + 6 * 2 <= size {
-                    name2_offset = i;
-                }
-
-                // name3 is [u16;2]
-                if name3_offset == 0 && i + 2 * 2 <= size {
-                    name3_offset = i;
-                }
-
-                // Once all found, bail early
-                if name1_offset != 0 && name2_offset != 0 && name3_offset != 0 {
-                    break;
-                }
+        // The struct is packed, so the name arrays are unaligned.
+        for (offset, start, len) in parts {
+            for i in 0..len {
+                chars[start + i] = unsafe { (base.add(offset + i * 2) as *const u16).read_unaligned() };
             }
+        }
+        chars
+    }
+}
 
-            // Real pointer to actual self
-            let real_base = self as *const _ as *const u8;
-
-            core::ptr::copy_nonoverlapping(
-                real_base.add(name1_offset) as *const u16,
-                chars.as_mut_ptr(),
-                5,
-            );
+/// A long name is at most 255 UTF-16 units, 13 to an entry.
+pub const LFN_MAX_ENTRIES: usize = 20;
 
-            core::ptr::copy_nonoverlapping(
-                real_base.add(name2_offset) as *const u16,
-                chars.as_mut_ptr().add(5),
-                6,
-            );
+/// Decodes the 32-byte directory entry at the start of `buf`.
+pub fn parse_dir_entry(buf: &[u8]) -> FsResult<Fat32DirEntry> {
+    if buf.len() < DIR_ENTRY_SIZE {
+        return Err(FsError::IoError);
+    }
+    Ok(unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const Fat32DirEntry) })
+}
 
-            core::ptr::copy_nonoverlapping(
-                real_base.add(name3_offset) as *const u16,
-                chars.as_mut_ptr().add(11),
-                2,
-            );
-        }
-        chars
+/// Decodes a long-name entry, rejecting ones that are not marked as such or
+/// whose sequence number no valid name can have.
+pub fn parse_lfn_entry(buf: &[u8]) -> FsResult<Fat32LfnEntry> {
+    if buf.len() < DIR_ENTRY_SIZE {
+        return Err(FsError::IoError);
     }
+    let lfn: Fat32LfnEntry = unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const Fat32LfnEntry) };
+    let seq = lfn.sequence_number() as usize;
+    if lfn.attr & ATTR_LONG_NAME_MASK != ATTR_LONG_NAME || seq == 0 || seq > LFN_MAX_ENTRIES {
+        return Err(FsError::IoError);
+    }
+    Ok(lfn)
 }
 
 pub fn compute_sfn_checksum(name: &[u8; 11]) -> u8 {
@@ -205,7 +203,11 @@ pub fn compute_sfn_checksum(name: &[u8; 11]) -> u8 {
 pub fn decode_long_name(entries: &[Fat32LfnEntry]) -> String {
     let mut chars: Vec<u16> = Vec::new();
     
-    for entry in entries.iter().rev() {
+    // On disk the entries are stored last-part-first; order by sequence.
+    let mut ordered: Vec<&Fat32LfnEntry> = entries.iter().collect();
+    ordered.sort_by_key(|e| e.sequence_number());
+    
+    for entry in ordered {
         for &c in entry.get_name_chars().iter() {
             if c == 0x0000 || c == 0xFFFF {
                 break;
@@ -217,6 +219,76 @@ pub fn decode_long_name(entries: &[Fat32LfnEntry]) -> String {
     String::from_utf16_lossy(&chars)
 }
 
+/// A named entry and where it sits in its directory, counted in 32-byte
+/// slots: `first` is the start of the long-name run before it, or `slot`
+/// itself when it has none.
+#[derive(Debug, Clone)]
+pub struct DirSlot {
+    pub name: String,
+    pub first: usize,
+    pub slot: usize,
+    pub entry: Fat32DirEntry,
+}
+
+/// Splits a directory's raw contents into named entries, joining long-name
+/// runs to the short entry they precede. Runs that are malformed or whose
+/// checksum does not match leave the short name in place.
+pub fn parse_directory(data: &[u8]) -> FsResult<Vec<(String, Fat32DirEntry)>> {
+    Ok(parse_directory_slots(data)?.into_iter().map(|slot| (slot.name, slot.entry)).collect())
+}
+
+/// parse_directory, keeping where each entry was found
+pub fn parse_directory_slots(data: &[u8]) -> FsResult<Vec<DirSlot>> {
+    let mut entries = Vec::new();
+    let mut lfn_entries: Vec<Fat32LfnEntry> = Vec::new();
+    let mut lfn_start = 0;
+    
+    for (slot, raw) in data.chunks_exact(DIR_ENTRY_SIZE).enumerate() {
+        let entry = parse_dir_entry(raw)?;
+        
+        if entry.is_last() {
+            break;
+        }
+        
+        if entry.is_free() || entry.is_volume_id() {
+            lfn_entries.clear();
+            continue;
+        }
+        
+        if entry.is_long_name() {
+            match parse_lfn_entry(raw) {
+                Ok(lfn) if lfn_entries.len() < LFN_MAX_ENTRIES => {
+                    if lfn_entries.is_empty() {
+                        lfn_start = slot;
+                    }
+                    lfn_entries.push(lfn)
+                }
+                _ => lfn_entries.clear(),
+            }
+            continue;
+        }
+        
+        let checksum = compute_sfn_checksum(&entry.name);
+        let (name, first) = if !lfn_entries.is_empty() && lfn_entries.iter().all(|lfn| lfn.checksum == checksum) {
+            (decode_long_name(&lfn_entries), lfn_start)
+        } else {
+            (entry.short_name(), slot)
+        };
+        
+        entries.push(DirSlot { name, first, slot, entry });
+        lfn_entries.clear();
+    }
+    
+    Ok(entries)
+}
+
+/// The 32 bytes `entry` is stored as
+pub fn encode_dir_entry(entry: &Fat32DirEntry) -> [u8; DIR_ENTRY_SIZE] {
+    let mut buf = [0u8; DIR_ENTRY_SIZE];
+    unsafe { core::ptr::write_unaligned(buf.as_mut_ptr() as *mut Fat32DirEntry, *entry) };
+    buf
+}
+
 pub fn encode_short_name(name: &str) -> [u8; 11] {
     let mut result = [b' '; 11];
     let name = name.to_uppercase();
@@ -274,6 +346,11 @@ fn fat_time_to_unix(date: u16, time: u16) -> u64 {
     let min = ((time >> 5) & 0x3F) as u64;
     let sec = ((time & 0x1F) * 2) as u64;
     
+    // Unset (all zero) or corrupt dates
+    if !(1..=12).contains(&month) || day == 0 {
+        return 0;
+    }
+    
     let mut days = 0u64;
     for y in 1970..year {
         days += if is_leap_year(y) { 366 } else { 365 };
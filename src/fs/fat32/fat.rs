@@ -1,5 +1,7 @@
+use alloc::collections::BTreeSet;
 use alloc::vec::Vec;
 use alloc::vec;
+use crate::fs::{FsError, FsResult};
 
 pub const FAT32_SIGNATURE: u16 = 0xAA55;
 pub const FAT32_FSTYPE: [u8; 8] = *b"FAT32   ";
@@ -96,6 +98,50 @@ impl Fat32Bpb {
     }
 }
 
+pub const FAT32_BOOT_SECTOR_SIZE: usize = 512;
+
+/// Decodes the boot sector at the start of `buf` and checks the geometry
+/// the driver relies on: sector and cluster sizes, a FAT large enough for
+/// every cluster, a data area inside the volume and a root cluster within
+/// it. The arithmetic helpers on `Fat32Bpb` cannot overflow on a BPB that
+/// passed.
+pub fn parse_bpb(buf: &[u8]) -> FsResult<Fat32Bpb> {
+    if buf.len() < FAT32_BOOT_SECTOR_SIZE
+        || u16::from_le_bytes([buf[510], buf[511]]) != FAT32_SIGNATURE
+    {
+        return Err(FsError::InvalidArgument);
+    }
+    let bpb: Fat32Bpb = unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const Fat32Bpb) };
+    
+    let bytes_per_sector = bpb.bytes_per_sector;
+    if !bpb.is_valid() || !bytes_per_sector.is_power_of_two() || !bpb.sectors_per_cluster.is_power_of_two() {
+        return Err(FsError::InvalidArgument);
+    }
+    // FAT32 keeps the root directory in a cluster chain and has no 16-bit FAT
+    if bpb.reserved_sector_count == 0 || bpb.root_entry_count != 0 || bpb.fat_size_16 != 0 || bpb.fat_size_32 == 0 {
+        return Err(FsError::InvalidArgument);
+    }
+    
+    let first_data = bpb.reserved_sector_count as u64 + bpb.num_fats as u64 * bpb.fat_size_32 as u64;
+    let total = bpb.total_sectors() as u64;
+    if first_data >= total {
+        return Err(FsError::InvalidArgument);
+    }
+    
+    let clusters = (total - first_data) / bpb.sectors_per_cluster as u64;
+    let fat_entries = bpb.fat_size_32 as u64 * bytes_per_sector as u64 / 4;
+    if clusters == 0 || fat_entries < clusters + 2 {
+        return Err(FsError::InvalidArgument);
+    }
+    
+    let root = bpb.root_cluster as u64;
+    if root < 2 || root >= clusters + 2 {
+        return Err(FsError::InvalidArgument);
+    }
+    
+    Ok(bpb)
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct Fat32FsInfo {
@@ -122,14 +168,18 @@ impl Fat32FsInfo {
 
 pub struct FatTable {
     entries: Vec<u32>,
-    dirty: bool,
+    /// Entries changed since the table was last written out
+    dirty: BTreeSet<u32>,
+    /// Where the next search for a free cluster starts
+    next_free: u32,
 }
 
 impl FatTable {
     pub fn new(size: usize) -> Self {
         FatTable {
             entries: vec![0; size],
-            dirty: false,
+            dirty: BTreeSet::new(),
+            next_free: 2,
         }
     }
     
@@ -150,7 +200,8 @@ impl FatTable {
         
         FatTable {
             entries,
-            dirty: false,
+            dirty: BTreeSet::new(),
+            next_free: 2,
         }
     }
     
@@ -165,7 +216,7 @@ impl FatTable {
     pub fn set(&mut self, cluster: u32, value: u32) {
         if (cluster as usize) < self.entries.len() {
             self.entries[cluster as usize] = value & 0x0FFFFFFF;
-            self.dirty = true;
+            self.dirty.insert(cluster);
         }
     }
     
@@ -182,9 +233,14 @@ impl FatTable {
     }
     
     pub fn allocate_cluster(&mut self) -> Option<u32> {
-        for i in 2..self.entries.len() as u32 {
+        // Search from after the last cluster handed out, so a file written
+        // cluster by cluster does not rescan the whole table each time
+        let count = self.entries.len() as u32;
+        let start = self.next_free.clamp(2, count.max(2));
+        for i in (start..count).chain(2..start) {
             if self.is_free(i) {
                 self.set(i, FAT32_EOC);
+                self.next_free = i + 1;
                 return Some(i);
             }
         }
@@ -193,6 +249,7 @@ impl FatTable {
     
     pub fn free_cluster(&mut self, cluster: u32) {
         self.set(cluster, FAT32_FREE);
+        self.next_free = self.next_free.min(cluster);
     }
     
     pub fn extend_chain(&mut self, last_cluster: u32) -> Option<u32> {
@@ -232,10 +289,23 @@ impl FatTable {
     }
     
     pub fn is_dirty(&self) -> bool {
-        self.dirty
+        !self.dirty.is_empty()
     }
     
     pub fn clear_dirty(&mut self) {
-        self.dirty = false;
+        self.dirty.clear();
+    }
+    
+    /// The entries changed since the last call, in order
+    pub fn take_dirty(&mut self) -> BTreeSet<u32> {
+        core::mem::take(&mut self.dirty)
+    }
+    
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
     }
 }
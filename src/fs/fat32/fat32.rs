@@ -1,14 +1,32 @@
+// FAT32
+//
+// Reads follow cluster chains as the FAT gives them; an inode is the
+// first cluster of a file or directory. Mounted read-write it also
+// creates, writes, renames and removes files and directories, with 8.3
+// names only: long names are read and kept, never made. Every change
+// writes the clusters and directory entries it touches straight away and
+// the FAT sectors that changed to each copy of the FAT.
+//
+// A directory entry is found from its inode by the directory it was last
+// seen in, which lookup, readdir and create record. A file made here is
+// given a cluster while still empty, so that it has an inode of its own;
+// an empty file made elsewhere has none and cannot be written.
+
+use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use alloc::sync::Arc;
-use spin::RwLock;
+use spin::{Mutex, RwLock};
 use alloc::vec;
 use crate::fs::{FileMode, FileStat, FileType, FsResult, FsError};
 use crate::fs::vfs::node::{VfsNode, VfsNodeData, DirEntry, Filesystem, InodeNumber};
 use crate::fs::ext4::ext4::BlockDevice;
 use super::fat::FatTable;
-use crate::fs::fat32::Fat32Bpb;
-use super::dir::{Fat32DirEntry, Fat32LfnEntry, decode_long_name, DIR_ENTRY_SIZE};
+use crate::fs::fat32::{Fat32Bpb, FAT32_BOOT_SECTOR_SIZE, parse_bpb};
+use super::dir::{
+    Fat32DirEntry, DirSlot, parse_directory, parse_directory_slots, encode_dir_entry, encode_short_name,
+    is_valid_short_name, ATTR_ARCHIVE, ATTR_DIRECTORY, DIR_ENTRY_SIZE, DIR_FREE,
+};
 
 pub struct Fat32Filesystem {
     bpb: Fat32Bpb,
@@ -16,23 +34,28 @@ pub struct Fat32Filesystem {
     device: Arc<RwLock<dyn BlockDevice + Send + Sync>>,
     read_only: bool,
     cluster_size: u32,
+    /// The directory each inode was last seen in, by first cluster
+    parents: Mutex<BTreeMap<u32, u32>>,
 }
 
 impl Fat32Filesystem {
     pub fn mount(device: Arc<RwLock<dyn BlockDevice + Send + Sync>>, read_only: bool) -> FsResult<Self> {
-        let mut bpb_buf = [0u8; 512];
+        let mut bpb_buf = [0u8; FAT32_BOOT_SECTOR_SIZE];
         
         {
             let dev = device.read();
             dev.read_block(0, &mut bpb_buf).map_err(|_| FsError::IoError)?;
         }
         
-        let bpb: Fat32Bpb = unsafe {
-            core::ptr::read(bpb_buf.as_ptr() as *const Fat32Bpb)
-        };
+        let bpb = parse_bpb(&bpb_buf)?;
         
-        if !bpb.is_valid() {
-            return Err(FsError::InvalidArgument);
+        // Sectors are read one device block at a time, so the sizes must
+        // agree, and the volume has to fit on the device.
+        {
+            let dev = device.read();
+            if dev.block_size() != bpb.bytes_per_sector as u32 || bpb.total_sectors() as u64 > dev.block_count() {
+                return Err(FsError::InvalidArgument);
+            }
         }
         
         let fat_size_bytes = bpb.fat_size() as usize * bpb.bytes_per_sector as usize;
@@ -50,7 +73,10 @@ impl Fat32Filesystem {
             }
         }
         
-        let fat = FatTable::from_data(&fat_data);
+        // Entries past the last cluster describe nothing; dropping them keeps
+        // chains within the volume.
+        let fat_entries = (bpb.cluster_count() as usize + 2) * 4;
+        let fat = FatTable::from_data(&fat_data[..fat_entries]);
         let cluster_size = bpb.cluster_size();
         
         Ok(Fat32Filesystem {
@@ -59,10 +85,14 @@ impl Fat32Filesystem {
             device,
             read_only,
             cluster_size,
+            parents: Mutex::new(BTreeMap::new()),
         })
     }
     
     fn read_cluster(&self, cluster: u32) -> FsResult<Vec<u8>> {
+        if cluster < 2 || cluster - 2 >= self.bpb.cluster_count() {
+            return Err(FsError::IoError);
+        }
         let sector = self.bpb.cluster_to_sector(cluster);
         let mut data = vec![0u8; self.cluster_size as usize];
         
@@ -93,53 +123,175 @@ impl Fat32Filesystem {
     
     fn read_directory(&self, start_cluster: u32) -> FsResult<Vec<(String, Fat32DirEntry)>> {
         let data = self.read_cluster_chain(start_cluster)?;
-        let mut entries = Vec::new();
-        let mut lfn_entries: Vec<Fat32LfnEntry> = Vec::new();
+        parse_directory(&data)
+    }
+    
+    fn write_cluster(&self, cluster: u32, data: &[u8]) -> FsResult<()> {
+        if cluster < 2 || cluster - 2 >= self.bpb.cluster_count() {
+            return Err(FsError::IoError);
+        }
+        let sector = self.bpb.cluster_to_sector(cluster);
+        let bytes_per_sector = self.bpb.bytes_per_sector as usize;
         
-        let mut i = 0;
-        while i + DIR_ENTRY_SIZE <= data.len() {
-            let entry: Fat32DirEntry = unsafe {
-                core::ptr::read(data[i..].as_ptr() as *const Fat32DirEntry)
-            };
-            
-            if entry.is_last() {
-                break;
+        let mut dev = self.device.write();
+        for (i, chunk) in data.chunks_exact(bytes_per_sector).enumerate() {
+            dev.write_block(sector as u64 + i as u64, chunk).map_err(|_| FsError::IoError)?;
+        }
+        Ok(())
+    }
+    
+    /// Write the FAT sectors holding changed entries to every copy of the FAT
+    fn flush_fat(&mut self) -> FsResult<()> {
+        let bytes_per_sector = self.bpb.bytes_per_sector as usize;
+        let per_sector = (bytes_per_sector / 4) as u32;
+        let mut sectors: Vec<u32> = self.fat.take_dirty().into_iter().map(|cluster| cluster / per_sector).collect();
+        sectors.dedup();
+        
+        let mut dev = self.device.write();
+        let mut buf = vec![0u8; bytes_per_sector];
+        for sector in sectors {
+            let first = self.bpb.first_fat_sector() + sector;
+            // Entries past the last cluster are not in the table; keep them
+            dev.read_block(first as u64, &mut buf).map_err(|_| FsError::IoError)?;
+            for (i, raw) in buf.chunks_exact_mut(4).enumerate() {
+                let cluster = sector * per_sector + i as u32;
+                if (cluster as usize) < self.fat.len() {
+                    let reserved = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) & 0xF0000000;
+                    raw.copy_from_slice(&(reserved | self.fat.get(cluster)).to_le_bytes());
+                }
             }
-            
-            if entry.is_free() {
-                i += DIR_ENTRY_SIZE;
-                lfn_entries.clear();
-                continue;
+            for copy in 0..self.bpb.num_fats as u32 {
+                dev.write_block((first + copy * self.bpb.fat_size()) as u64, &buf).map_err(|_| FsError::IoError)?;
             }
-            
-            if entry.is_long_name() {
-                let lfn: Fat32LfnEntry = unsafe {
-                    core::ptr::read(data[i..].as_ptr() as *const Fat32LfnEntry)
-                };
-                lfn_entries.push(lfn);
-                i += DIR_ENTRY_SIZE;
-                continue;
+        }
+        Ok(())
+    }
+    
+    /// A cluster for a new file or directory, zeroed
+    fn allocate(&mut self) -> FsResult<u32> {
+        let cluster = self.fat.allocate_cluster().ok_or(FsError::NoSpace)?;
+        self.write_cluster(cluster, &vec![0u8; self.cluster_size as usize])?;
+        Ok(cluster)
+    }
+    
+    fn find(&self, dir: u32, name: &str) -> FsResult<DirSlot> {
+        let data = self.read_cluster_chain(dir)?;
+        parse_directory_slots(&data)?.into_iter()
+            .find(|slot| slot.name != "." && slot.name != ".." && slot.name.eq_ignore_ascii_case(name))
+            .ok_or(FsError::NotFound)
+    }
+    
+    /// The entry for `inode`, in the directory it was last seen in
+    fn locate(&self, inode: InodeNumber) -> FsResult<(u32, DirSlot)> {
+        let cluster = self.inode_to_cluster(inode);
+        let dir = *self.parents.lock().get(&cluster).ok_or(FsError::NotFound)?;
+        let data = self.read_cluster_chain(dir)?;
+        let slot = parse_directory_slots(&data)?.into_iter()
+            .find(|slot| slot.name != "." && slot.name != ".." && slot.entry.first_cluster() == cluster)
+            .ok_or(FsError::NotFound)?;
+        Ok((dir, slot))
+    }
+    
+    /// Overwrite slots `slots` of directory `dir`, each with `fill`
+    fn write_slots(&self, dir: u32, slots: core::ops::RangeInclusive<usize>, fill: impl Fn(usize) -> [u8; DIR_ENTRY_SIZE]) -> FsResult<()> {
+        let chain = self.fat.get_chain(dir);
+        let per_cluster = self.cluster_size as usize / DIR_ENTRY_SIZE;
+        let mut current: Option<(u32, Vec<u8>)> = None;
+        for slot in slots {
+            let cluster = *chain.get(slot / per_cluster).ok_or(FsError::IoError)?;
+            if current.as_ref().is_none_or(|(c, _)| *c != cluster) {
+                if let Some((c, data)) = current.take() {
+                    self.write_cluster(c, &data)?;
+                }
+                current = Some((cluster, self.read_cluster(cluster)?));
             }
-            
-            if entry.is_volume_id() {
-                i += DIR_ENTRY_SIZE;
-                lfn_entries.clear();
-                continue;
+            let (_, data) = current.as_mut().unwrap();
+            let offset = (slot % per_cluster) * DIR_ENTRY_SIZE;
+            data[offset..offset + DIR_ENTRY_SIZE].copy_from_slice(&fill(slot));
+        }
+        if let Some((c, data)) = current {
+            self.write_cluster(c, &data)?;
+        }
+        Ok(())
+    }
+    
+    /// Add `entry` to directory `dir` in the first free slot, growing it
+    /// by a cluster if it is full
+    fn add_entry(&mut self, dir: u32, entry: &Fat32DirEntry) -> FsResult<()> {
+        let data = self.read_cluster_chain(dir)?;
+        let free = data.chunks_exact(DIR_ENTRY_SIZE).position(|raw| raw[0] == DIR_FREE || raw[0] == 0);
+        let slot = match free {
+            Some(slot) => slot,
+            None => {
+                let last = *self.fat.get_chain(dir).last().ok_or(FsError::IoError)?;
+                let cluster = self.fat.extend_chain(last).ok_or(FsError::NoSpace)?;
+                self.write_cluster(cluster, &vec![0u8; self.cluster_size as usize])?;
+                data.len() / DIR_ENTRY_SIZE
             }
-            
-            let name = if !lfn_entries.is_empty() {
-                lfn_entries.sort_by_key(|e| e.sequence_number());
-                decode_long_name(&lfn_entries)
-            } else {
-                entry.short_name()
-            };
-            
-            entries.push((name, entry));
-            lfn_entries.clear();
-            i += DIR_ENTRY_SIZE;
+        };
+        self.write_slots(dir, slot..=slot, |_| encode_dir_entry(entry))
+    }
+    
+    /// Mark the entry at `slot` and its long name free
+    fn remove_entry(&self, dir: u32, slot: &DirSlot) -> FsResult<()> {
+        let data = self.read_cluster_chain(dir)?;
+        let entries: Vec<&[u8]> = data.chunks_exact(DIR_ENTRY_SIZE).collect();
+        self.write_slots(dir, slot.first..=slot.slot, |i| {
+            let mut raw = [0u8; DIR_ENTRY_SIZE];
+            raw.copy_from_slice(entries[i]);
+            raw[0] = DIR_FREE;
+            raw
+        })
+    }
+    
+    /// Make a new entry `name` in `parent`, with a zeroed cluster of its own
+    fn make(&mut self, parent: InodeNumber, name: &str, attr: u8) -> FsResult<(u32, Fat32DirEntry)> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
         }
-        
-        Ok(entries)
+        if !is_valid_short_name(name) || name.starts_with('.') {
+            return Err(FsError::InvalidArgument);
+        }
+        let dir = self.inode_to_cluster(parent);
+        match self.find(dir, name) {
+            Ok(_) => return Err(FsError::AlreadyExists),
+            Err(FsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        let cluster = self.allocate()?;
+        let entry = Fat32DirEntry::new(encode_short_name(name), attr, cluster);
+        let result = self.add_entry(dir, &entry);
+        if result.is_err() {
+            self.fat.free_chain(cluster);
+        }
+        self.flush_fat()?;
+        result?;
+        self.parents.lock().insert(cluster, dir);
+        Ok((cluster, entry))
+    }
+    
+    /// Take the entry `name` out of `parent`, freeing its clusters
+    fn remove(&mut self, parent: InodeNumber, name: &str, directory: bool) -> FsResult<()> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        let dir = self.inode_to_cluster(parent);
+        let slot = self.find(dir, name)?;
+        let cluster = slot.entry.first_cluster();
+        match (slot.entry.is_directory(), directory) {
+            (true, false) => return Err(FsError::IsDirectory),
+            (false, true) => return Err(FsError::NotDirectory),
+            (true, true) if self.read_directory(cluster)?.iter().any(|(name, _)| name != "." && name != "..") => {
+                return Err(FsError::NotEmpty);
+            }
+            _ => {}
+        }
+        self.remove_entry(dir, &slot)?;
+        if cluster >= 2 {
+            self.fat.free_chain(cluster);
+            self.parents.lock().remove(&cluster);
+        }
+        self.flush_fat()
     }
     
     fn cluster_to_inode(&self, cluster: u32) -> InodeNumber {
@@ -170,7 +322,11 @@ impl Fat32Filesystem {
             ctime: entry.creation_time(),
             nlink: 1,
             device: None,
-            data: VfsNodeData::Regular(Vec::new()),
+            data: match entry.is_directory() {
+                true => VfsNodeData::Directory(Vec::new()),
+                false => VfsNodeData::Regular(Default::default()),
+            },
+            casefold: entry.is_directory(),
         }
     }
 }
@@ -196,6 +352,7 @@ impl Filesystem for Fat32Filesystem {
             nlink: 2,
             device: None,
             data: VfsNodeData::Directory(Vec::new()),
+            casefold: true,
         })
     }
     
@@ -205,6 +362,7 @@ impl Filesystem for Fat32Filesystem {
         
         for (entry_name, entry) in entries {
             if entry_name.eq_ignore_ascii_case(name) {
+                self.parents.lock().insert(entry.first_cluster(), parent_cluster);
                 return Ok(self.entry_to_vfs_node(&entry_name, &entry));
             }
         }
@@ -228,46 +386,137 @@ impl Filesystem for Fat32Filesystem {
         Ok(len)
     }
     
-    fn write(&mut self, _inode: InodeNumber, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
+    fn write(&mut self, inode: InodeNumber, offset: u64, buf: &[u8]) -> FsResult<usize> {
         if self.read_only {
             return Err(FsError::ReadOnly);
         }
-        Err(FsError::NotSupported)
+        let (dir, slot) = self.locate(inode)?;
+        let mut entry = slot.entry;
+        if entry.is_directory() {
+            return Err(FsError::IsDirectory);
+        }
+        let cluster = self.inode_to_cluster(inode);
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if cluster < 2 {
+            return Err(FsError::NotSupported);
+        }
+        
+        let size = entry.file_size as u64;
+        let end = offset.checked_add(buf.len() as u64).filter(|&end| end <= u32::MAX as u64).ok_or(FsError::NoSpace)?;
+        let cluster_size = self.cluster_size as u64;
+        
+        let mut chain = self.fat.get_chain(cluster);
+        let existing = chain.len();
+        let needed = end.div_ceil(cluster_size) as usize;
+        while chain.len() < needed {
+            match self.fat.extend_chain(*chain.last().unwrap()) {
+                Some(next) => chain.push(next),
+                None => {
+                    // Give back what this write took
+                    self.fat.set(chain[existing - 1], super::fat::FAT32_EOC);
+                    for &cluster in &chain[existing..] {
+                        self.fat.free_cluster(cluster);
+                    }
+                    self.flush_fat()?;
+                    return Err(FsError::NoSpace);
+                }
+            }
+        }
+        
+        // Bytes between the old end and `offset` read back as zeros
+        let start = offset.min(size);
+        for (i, &cluster) in chain.iter().enumerate().take(needed) {
+            let (first, last) = (i as u64 * cluster_size, (i as u64 + 1) * cluster_size);
+            if last <= start && i < existing {
+                continue;
+            }
+            let covered = first >= offset && last <= end;
+            let mut data = if covered || i >= existing || first >= size {
+                vec![0u8; cluster_size as usize]
+            } else {
+                self.read_cluster(cluster)?
+            };
+            for (at, byte) in data.iter_mut().enumerate() {
+                let pos = first + at as u64;
+                if pos >= start && pos < offset {
+                    *byte = 0;
+                } else if pos >= offset && pos < end {
+                    *byte = buf[(pos - offset) as usize];
+                }
+            }
+            self.write_cluster(cluster, &data)?;
+        }
+        
+        if end > size {
+            entry.file_size = end as u32;
+            self.write_slots(dir, slot.slot..=slot.slot, |_| encode_dir_entry(&entry))?;
+        }
+        self.flush_fat()?;
+        Ok(buf.len())
     }
     
-    fn create(&mut self, _parent: InodeNumber, _name: &str, _mode: FileMode) -> FsResult<VfsNode> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn create(&mut self, parent: InodeNumber, name: &str, _mode: FileMode) -> FsResult<VfsNode> {
+        let (_, entry) = self.make(parent, name, ATTR_ARCHIVE)?;
+        Ok(self.entry_to_vfs_node(&entry.short_name(), &entry))
     }
     
-    fn mkdir(&mut self, _parent: InodeNumber, _name: &str, _mode: FileMode) -> FsResult<VfsNode> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn mkdir(&mut self, parent: InodeNumber, name: &str, _mode: FileMode) -> FsResult<VfsNode> {
+        let (cluster, entry) = self.make(parent, name, ATTR_DIRECTORY)?;
+        // ".." in a directory under the root names cluster 0
+        let up = match self.inode_to_cluster(parent) {
+            root if root == self.bpb.root_cluster => 0,
+            dir => dir,
+        };
+        let dot = Fat32DirEntry::new(*b".          ", ATTR_DIRECTORY, cluster);
+        let dotdot = Fat32DirEntry::new(*b"..         ", ATTR_DIRECTORY, up);
+        self.write_slots(cluster, 0..=1, |i| encode_dir_entry(if i == 0 { &dot } else { &dotdot }))?;
+        Ok(self.entry_to_vfs_node(&entry.short_name(), &entry))
     }
     
-    fn unlink(&mut self, _parent: InodeNumber, _name: &str) -> FsResult<()> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn unlink(&mut self, parent: InodeNumber, name: &str) -> FsResult<()> {
+        self.remove(parent, name, false)
     }
     
-    fn rmdir(&mut self, _parent: InodeNumber, _name: &str) -> FsResult<()> {
-        if self.read_only {
-            return Err(FsError::ReadOnly);
-        }
-        Err(FsError::NotSupported)
+    fn rmdir(&mut self, parent: InodeNumber, name: &str) -> FsResult<()> {
+        self.remove(parent, name, true)
     }
     
-    fn rename(&mut self, _old_parent: InodeNumber, _old_name: &str, _new_parent: InodeNumber, _new_name: &str) -> FsResult<()> {
+    fn rename(&mut self, old_parent: InodeNumber, old_name: &str, new_parent: InodeNumber, new_name: &str) -> FsResult<()> {
         if self.read_only {
             return Err(FsError::ReadOnly);
         }
-        Err(FsError::NotSupported)
+        if !is_valid_short_name(new_name) || new_name.starts_with('.') {
+            return Err(FsError::InvalidArgument);
+        }
+        let (from, to) = (self.inode_to_cluster(old_parent), self.inode_to_cluster(new_parent));
+        let slot = self.find(from, old_name)?;
+        let cluster = slot.entry.first_cluster();
+        match self.find(to, new_name) {
+            // Only the case changes
+            Ok(target) if from == to && target.slot == slot.slot => {}
+            Ok(target) if target.entry.is_directory() != slot.entry.is_directory() => {
+                return Err(if target.entry.is_directory() { FsError::IsDirectory } else { FsError::NotDirectory });
+            }
+            Ok(_) => self.remove(new_parent, new_name, slot.entry.is_directory())?,
+            Err(FsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        
+        let mut entry = slot.entry;
+        entry.name = encode_short_name(new_name);
+        self.remove_entry(from, &slot)?;
+        self.add_entry(to, &entry)?;
+        if entry.is_directory() && from != to {
+            let up = if to == self.bpb.root_cluster { 0 } else { to };
+            let dotdot = Fat32DirEntry::new(*b"..         ", ATTR_DIRECTORY, up);
+            self.write_slots(cluster, 1..=1, |_| encode_dir_entry(&dotdot))?;
+        }
+        if cluster >= 2 {
+            self.parents.lock().insert(cluster, to);
+        }
+        self.flush_fat()
     }
     
     fn stat(&self, inode: InodeNumber) -> FsResult<FileStat> {
@@ -293,10 +542,12 @@ impl Filesystem for Fat32Filesystem {
     fn readdir(&self, inode: InodeNumber) -> FsResult<Vec<DirEntry>> {
         let cluster = self.inode_to_cluster(inode);
         let entries = self.read_directory(cluster)?;
+        let mut parents = self.parents.lock();
         
         Ok(entries.into_iter()
             .filter(|(name, _)| name != "." && name != "..")
             .map(|(name, entry)| {
+                parents.insert(entry.first_cluster(), cluster);
                 DirEntry::new(
                     name,
                     self.cluster_to_inode(entry.first_cluster()),
@@ -307,6 +558,10 @@ impl Filesystem for Fat32Filesystem {
     }
     
     fn sync(&mut self) -> FsResult<()> {
-        Ok(())
+        self.device.write().flush().map_err(|_| FsError::IoError)
+    }
+    
+    fn casefold(&self) -> bool {
+        true
     }
 }
@@ -0,0 +1,213 @@
+// Golden-image tests for the ext4 and FAT32 drivers. The images are built
+// by testdata/mkgolden.sh from known trees; every assertion here mirrors
+// something that script puts on disk.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::RwLock;
+use super::ext4::Ext4Filesystem;
+use super::fat32::Fat32Filesystem;
+use super::ramdisk::RamDisk;
+use super::vfs::{Filesystem, VfsNode, VfsNodeData};
+use super::{FileType, FsError};
+
+static EXT4_IMAGE: &[u8] = include_bytes!("testdata/golden-ext4.img");
+static FAT32_IMAGE: &[u8] = include_bytes!("testdata/golden-fat32.img");
+
+const STAMP: u64 = 1700000000;
+const LONG_EXT4_NAME: &str = "this-is-a-rather-long-file-name-that-goes-well-past-sixty-four-characters.txt";
+const LONG_FAT_NAME: &str = "a very long file name that spans several lfn entries.text";
+
+fn ext4() -> Ext4Filesystem {
+    let disk = RamDisk::from_image(EXT4_IMAGE, 1024);
+    Ext4Filesystem::mount(Arc::new(RwLock::new(disk)), true).expect("golden ext4 image failed to mount")
+}
+
+fn fat32() -> Fat32Filesystem {
+    let disk = RamDisk::from_image(FAT32_IMAGE, 512);
+    Fat32Filesystem::mount(Arc::new(RwLock::new(disk)), true).expect("golden FAT32 image failed to mount")
+}
+
+fn walk(fs: &dyn Filesystem, path: &str) -> Result<VfsNode, FsError> {
+    let mut node = fs.root()?;
+    for name in path.split('/').filter(|c| !c.is_empty()) {
+        node = fs.lookup(node.inode, name)?;
+    }
+    Ok(node)
+}
+
+fn read_all(fs: &dyn Filesystem, node: &VfsNode) -> Vec<u8> {
+    let mut buf = alloc::vec![0u8; node.size as usize + 4096];
+    let n = fs.read(node.inode, 0, &mut buf).expect("read failed");
+    buf.truncate(core::cmp::min(n, node.size as usize));
+    buf
+}
+
+fn names(fs: &dyn Filesystem, path: &str) -> Vec<(String, FileType)> {
+    let dir = walk(fs, path).expect("directory missing");
+    let mut entries: Vec<(String, FileType)> = fs
+        .readdir(dir.inode)
+        .expect("readdir failed")
+        .into_iter()
+        .filter(|e| e.name != "." && e.name != "..")
+        .map(|e| (e.name, e.file_type))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+#[test_case]
+fn test_ext4_root_listing() {
+    let fs = ext4();
+    let listing = names(&fs, "/");
+    let expected = [
+        ("big.bin", FileType::Regular),
+        ("bin", FileType::Directory),
+        ("dir", FileType::Directory),
+        ("empty.txt", FileType::Regular),
+        ("hello.txt", FileType::Regular),
+        ("link", FileType::Symlink),
+        ("longlink", FileType::Symlink),
+        ("lost+found", FileType::Directory),
+        ("sparse.bin", FileType::Regular),
+        (LONG_EXT4_NAME, FileType::Regular),
+    ];
+    assert_eq!(listing.len(), expected.len());
+    for ((name, kind), (want_name, want_kind)) in listing.iter().zip(expected.iter()) {
+        assert_eq!(name, want_name);
+        assert_eq!(kind, want_kind);
+    }
+}
+
+#[test_case]
+fn test_ext4_contents() {
+    let fs = ext4();
+    assert_eq!(read_all(&fs, &walk(&fs, "/hello.txt").unwrap()), b"hello, golden ext4\n");
+    assert_eq!(read_all(&fs, &walk(&fs, "/dir/nested/deep.txt").unwrap()), b"deep\n");
+    assert_eq!(read_all(&fs, &walk(&fs, "/bin/run.sh").unwrap()), b"#!/bin/sh\necho hi\n");
+    assert_eq!(read_all(&fs, &walk(&fs, &alloc::format!("/{}", LONG_EXT4_NAME)).unwrap()), b"long\n");
+    assert!(read_all(&fs, &walk(&fs, "/empty.txt").unwrap()).is_empty());
+}
+
+#[test_case]
+fn test_ext4_indirect_and_sparse() {
+    let fs = ext4();
+
+    let big = walk(&fs, "/big.bin").unwrap();
+    assert_eq!(big.size, 40960);
+    let data = read_all(&fs, &big);
+    assert_eq!(data.len(), 40960);
+    assert!(data.iter().enumerate().all(|(i, &b)| b == ((i * 7 + 3) % 256) as u8));
+
+    let sparse = walk(&fs, "/sparse.bin").unwrap();
+    assert_eq!(sparse.size, 20484);
+    let data = read_all(&fs, &sparse);
+    assert!(data[..20480].iter().all(|&b| b == 0));
+    assert_eq!(&data[20480..], b"tail");
+}
+
+#[test_case]
+fn test_ext4_symlinks() {
+    let fs = ext4();
+    match walk(&fs, "/link").unwrap().data {
+        VfsNodeData::Symlink(target) => assert_eq!(target, "hello.txt"),
+        _ => panic!("link is not a symlink"),
+    }
+    // Longer than 60 bytes, so stored in a data block rather than the inode
+    match walk(&fs, "/longlink").unwrap().data {
+        VfsNodeData::Symlink(target) => {
+            assert_eq!(target, "dir/nested/../nested/../nested/../nested/../nested/../nested/./deep.txt")
+        }
+        _ => panic!("longlink is not a symlink"),
+    }
+}
+
+#[test_case]
+fn test_ext4_stat() {
+    let fs = ext4();
+
+    let hello = walk(&fs, "/hello.txt").unwrap();
+    let stat = fs.stat(hello.inode).unwrap();
+    assert_eq!(stat.mode.0, 0o100600);
+    assert_eq!((stat.uid, stat.gid), (1000, 100));
+    assert_eq!(stat.size, 19);
+    assert_eq!(stat.nlink, 1);
+    assert_eq!(stat.mtime, STAMP);
+
+    let script = fs.stat(walk(&fs, "/bin/run.sh").unwrap().inode).unwrap();
+    assert_eq!(script.mode.0, 0o100755);
+
+    let dir = fs.stat(walk(&fs, "/dir").unwrap().inode).unwrap();
+    assert_eq!(dir.mode.0, 0o040755);
+    assert_eq!(dir.nlink, 3);
+}
+
+#[test_case]
+fn test_ext4_errors() {
+    let fs = ext4();
+    assert_eq!(walk(&fs, "/missing").err(), Some(FsError::NotFound));
+    assert_eq!(walk(&fs, "/hello.txt/x").err(), Some(FsError::NotDirectory));
+    assert_eq!(fs.readdir(walk(&fs, "/hello.txt").unwrap().inode).err(), Some(FsError::NotDirectory));
+
+    let mut fs = fs;
+    let root = fs.root().unwrap().inode;
+    assert_eq!(fs.create(root, "new", super::FileMode::new(0o644)).err(), Some(FsError::ReadOnly));
+
+    let blank = RamDisk::new(64, 1024);
+    assert!(Ext4Filesystem::mount(Arc::new(RwLock::new(blank)), true).is_err());
+}
+
+#[test_case]
+fn test_fat32_listing() {
+    let fs = fat32();
+    let listing = names(&fs, "/");
+    let expected = [
+        ("BIG.BIN", FileType::Regular),
+        ("DOCS", FileType::Directory),
+        ("EMPTY.TXT", FileType::Regular),
+        ("HELLO.TXT", FileType::Regular),
+        ("Mixed Case Name.txt", FileType::Regular),
+        (LONG_FAT_NAME, FileType::Regular),
+    ];
+    assert_eq!(listing.len(), expected.len());
+    for ((name, kind), (want_name, want_kind)) in listing.iter().zip(expected.iter()) {
+        assert_eq!(name, want_name);
+        assert_eq!(kind, want_kind);
+    }
+
+    let sub = names(&fs, "/DOCS");
+    assert_eq!(sub.len(), 2);
+    assert_eq!(sub[0].0, "README.MD");
+    assert_eq!(sub[1], (String::from("SUB"), FileType::Directory));
+}
+
+#[test_case]
+fn test_fat32_contents() {
+    let fs = fat32();
+    assert_eq!(read_all(&fs, &walk(&fs, "/HELLO.TXT").unwrap()), b"hello, golden fat32\n");
+    // Short names match case-insensitively
+    assert_eq!(read_all(&fs, &walk(&fs, "/hello.txt").unwrap()), b"hello, golden fat32\n");
+    assert_eq!(read_all(&fs, &walk(&fs, "/Mixed Case Name.txt").unwrap()), b"mixed\n");
+    assert_eq!(read_all(&fs, &walk(&fs, &alloc::format!("/{}", LONG_FAT_NAME)).unwrap()), b"spans several entries\n");
+    assert_eq!(read_all(&fs, &walk(&fs, "/DOCS/SUB/DEEP.TXT").unwrap()), b"deep\n");
+
+    let empty = walk(&fs, "/EMPTY.TXT").unwrap();
+    assert_eq!(empty.size, 0);
+    assert!(read_all(&fs, &empty).is_empty());
+
+    // Spread over a fragmented cluster chain
+    let big = walk(&fs, "/BIG.BIN").unwrap();
+    assert_eq!(big.size, 5000);
+    let data = read_all(&fs, &big);
+    assert_eq!(data.len(), 5000);
+    assert!(data.iter().enumerate().all(|(i, &b)| b == ((i * 13 + 5) % 256) as u8));
+}
+
+#[test_case]
+fn test_fat32_errors() {
+    let mut fs = fat32();
+    assert_eq!(walk(&fs, "/NOPE.TXT").err(), Some(FsError::NotFound));
+    let root = fs.root().unwrap().inode;
+    assert_eq!(fs.mkdir(root, "NEW", super::FileMode::new(0o755)).err(), Some(FsError::ReadOnly));
+}
@@ -0,0 +1,257 @@
+// Block I/O scheduler
+//
+// A RequestQueue sits between a filesystem and its BlockDevice and is a
+// BlockDevice itself. Reads are synchronous: they go to the device at once,
+// ahead of any queued writeback, and are patched with data still waiting
+// in the queue. Writes are queued. A write that overlaps or touches a
+// queued one is merged into it, so the queue never holds two requests for
+// neighbouring blocks. The queue is dispatched when it reaches
+// MAX_QUEUED_BLOCKS or on flush(), sync requests first, each class in one
+// elevator sweep: upwards from where the last dispatch stopped, then
+// wrapping to the lowest block. Sync writes are dispatched as soon as they
+// are queued.
+
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use crate::fs::ext4::ext4::BlockDevice;
+
+/// Queued writeback, in blocks, that forces a dispatch
+pub const MAX_QUEUED_BLOCKS: u64 = 64;
+
+/// Sync requests have someone waiting on them; background ones are
+/// writeback. Sync sorts first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Sync,
+    Background,
+}
+
+struct Request {
+    start: u64,
+    data: Vec<u8>,
+    priority: Priority,
+}
+
+impl Request {
+    fn blocks(&self, block_size: usize) -> u64 {
+        (self.data.len() / block_size) as u64
+    }
+
+    fn end(&self, block_size: usize) -> u64 {
+        self.start + self.blocks(block_size)
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    merges: AtomicU64,
+    dispatches: AtomicU64,
+    queued_blocks: AtomicU64,
+    read_hits: AtomicU64,
+}
+
+/// Counts for one queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Read requests, all sent straight to the device
+    pub reads: u64,
+    /// Write requests submitted
+    pub writes: u64,
+    /// Writes folded into a request already queued
+    pub merges: u64,
+    /// Write requests sent to the device
+    pub dispatches: u64,
+    /// Blocks waiting in the queue
+    pub queued_blocks: u64,
+    /// Reads that took data from the queue
+    pub read_hits: u64,
+}
+
+/// Live queues by device name, for iostat
+static QUEUES: Mutex<Vec<(String, Weak<Counters>)>> = Mutex::new(Vec::new());
+
+pub struct RequestQueue<D: BlockDevice> {
+    device: D,
+    block_size: usize,
+    /// Pending writes, sorted by block, none overlapping or touching
+    queue: Vec<Request>,
+    /// Block after the last one dispatched, where the next sweep starts
+    head: u64,
+    counters: Arc<Counters>,
+}
+
+impl<D: BlockDevice> RequestQueue<D> {
+    /// Queue requests for `device`, listing its stats under `name`
+    pub fn new(name: &str, device: D) -> Self {
+        let counters = Arc::new(Counters::default());
+        let mut queues = QUEUES.lock();
+        queues.retain(|(_, counters)| counters.strong_count() > 0);
+        queues.push((String::from(name), Arc::downgrade(&counters)));
+        RequestQueue {
+            block_size: device.block_size() as usize,
+            device,
+            queue: Vec::new(),
+            head: 0,
+            counters,
+        }
+    }
+
+    /// The device itself, bypassing the queue
+    pub fn device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        snapshot(&self.counters)
+    }
+
+    /// Queue a write of `buf`, whole blocks, at block `start`
+    pub fn submit(&mut self, start: u64, buf: &[u8], priority: Priority) -> Result<(), &'static str> {
+        let bs = self.block_size;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        if !buf.len().is_multiple_of(bs) {
+            return Err("write is not a whole number of blocks");
+        }
+        let end = start + (buf.len() / bs) as u64;
+        if end > self.device.block_count() {
+            return Err("block out of range");
+        }
+        self.counters.writes.fetch_add(1, Ordering::Relaxed);
+
+        // Every queued request overlapping or touching [start, end)
+        let first = self.queue.partition_point(|r| r.end(bs) < start);
+        let last = self.queue.partition_point(|r| r.start <= end);
+        let request = if first == last {
+            Request { start, data: buf.to_vec(), priority }
+        } else {
+            let merged_start = self.queue[first].start.min(start);
+            let merged_end = self.queue[last - 1].end(bs).max(end);
+            let mut data = vec![0u8; (merged_end - merged_start) as usize * bs];
+            let mut merged_priority = priority;
+            for old in &self.queue[first..last] {
+                let at = (old.start - merged_start) as usize * bs;
+                data[at..at + old.data.len()].copy_from_slice(&old.data);
+                merged_priority = merged_priority.min(old.priority);
+            }
+            let at = (start - merged_start) as usize * bs;
+            data[at..at + buf.len()].copy_from_slice(buf);
+            self.counters.merges.fetch_add((last - first) as u64, Ordering::Relaxed);
+            Request { start: merged_start, data, priority: merged_priority }
+        };
+        self.queue.splice(first..last, core::iter::once(request));
+        self.update_queued();
+
+        if priority == Priority::Sync {
+            self.dispatch(false)
+        } else if self.counters.queued_blocks.load(Ordering::Relaxed) >= MAX_QUEUED_BLOCKS {
+            self.dispatch(true)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn update_queued(&self) {
+        let blocks = self.queue.iter().map(|r| r.blocks(self.block_size)).sum();
+        self.counters.queued_blocks.store(blocks, Ordering::Relaxed);
+    }
+
+    /// Send the sync requests, or all of them, to the device in elevator
+    /// order. On an error the requests not yet sent stay queued.
+    fn dispatch(&mut self, all: bool) -> Result<(), &'static str> {
+        let (mut batch, rest): (Vec<Request>, Vec<Request>) = core::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|r| all || r.priority == Priority::Sync);
+        self.queue = rest;
+        let head = self.head;
+        batch.sort_by_key(|r| (r.priority, r.start < head, r.start));
+
+        let mut result = Ok(());
+        let mut batch = batch.into_iter();
+        for request in batch.by_ref() {
+            if let Err(e) = self.device.write_block(request.start, &request.data) {
+                self.queue.push(request);
+                result = Err(e);
+                break;
+            }
+            self.counters.dispatches.fetch_add(1, Ordering::Relaxed);
+            self.head = request.end(self.block_size);
+        }
+        self.queue.extend(batch);
+        self.queue.sort_by_key(|r| r.start);
+        self.update_queued();
+        result
+    }
+}
+
+fn snapshot(counters: &Counters) -> QueueStats {
+    QueueStats {
+        reads: counters.reads.load(Ordering::Relaxed),
+        writes: counters.writes.load(Ordering::Relaxed),
+        merges: counters.merges.load(Ordering::Relaxed),
+        dispatches: counters.dispatches.load(Ordering::Relaxed),
+        queued_blocks: counters.queued_blocks.load(Ordering::Relaxed),
+        read_hits: counters.read_hits.load(Ordering::Relaxed),
+    }
+}
+
+/// The stats of every live queue, by device name
+pub fn all_stats() -> Vec<(String, QueueStats)> {
+    QUEUES.lock().iter()
+        .filter_map(|(name, counters)| {
+            let counters = counters.upgrade()?;
+            Some((name.clone(), snapshot(&counters)))
+        })
+        .collect()
+}
+
+impl<D: BlockDevice> BlockDevice for RequestQueue<D> {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.device.read_block(block_num, buf)?;
+        self.counters.reads.fetch_add(1, Ordering::Relaxed);
+
+        // Writes still queued are newer than what the device holds
+        let bs = self.block_size as u64;
+        let (start, end) = (block_num * bs, block_num * bs + buf.len() as u64);
+        for request in &self.queue {
+            let (r_start, r_end) = (request.start * bs, request.start * bs + request.data.len() as u64);
+            let (from, to) = (start.max(r_start), end.min(r_end));
+            if from < to {
+                buf[(from - start) as usize..(to - start) as usize]
+                    .copy_from_slice(&request.data[(from - r_start) as usize..(to - r_start) as usize]);
+                self.counters.read_hits.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.submit(block_num, buf, Priority::Background)
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.dispatch(true)?;
+        self.device.flush()
+    }
+
+    fn block_size(&self) -> u32 {
+        self.device.block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.device.block_count()
+    }
+}
+
+impl<D: BlockDevice> Drop for RequestQueue<D> {
+    fn drop(&mut self) {
+        self.dispatch(true).ok();
+    }
+}
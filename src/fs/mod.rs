@@ -1,7 +1,16 @@
 pub mod vfs;
+pub mod archive;
 pub mod ext4;
 pub mod fat32;
+pub mod iosched;
 pub mod mount;
+pub mod overlay;
+pub mod quota;
+pub mod ramdisk;
+pub mod tmpfs;
+
+#[cfg(test)]
+mod golden;
 
 pub use vfs::*;
 pub use mount::*;
@@ -11,6 +20,7 @@ use alloc::string::String;
 pub fn init() {
     vfs::init();
     mount::init();
+    tmpfs::init();
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -157,7 +167,7 @@ impl FileStat {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FsError {
     NotFound,
     PermissionDenied,
@@ -174,6 +184,11 @@ pub enum FsError {
     InvalidArgument,
     NotSupported,
     Busy,
+    SymlinkLoop,
+    /// A uid or gid would go over its disk quota
+    QuotaExceeded,
+    /// Moving between two filesystems, which rename cannot do
+    CrossDevice,
 }
 
 pub type FsResult<T> = Result<T, FsError>;
@@ -2,10 +2,13 @@ use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use alloc::sync::Arc;
 use alloc::format;
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicU32, Ordering};
 use spin::{Mutex, RwLock};
 use lazy_static::lazy_static;
 use crate::fs::{FsResult, FsError};
 use crate::fs::vfs::node::Filesystem;
+use crate::fs::vfs::VFS;
 
 #[derive(Clone)]
 pub struct MountPoint {
@@ -34,16 +37,70 @@ bitflags::bitflags! {
         const REC = 1 << 12;
         const SILENT = 1 << 13;
         const RELATIME = 1 << 14;
+        /// Match names under the mount point without regard to case
+        const CASEFOLD = 1 << 15;
     }
 }
 
+/// Identifies a mount namespace: a mount table of its own, so mounts made
+/// in one are not seen in another
+pub type MntNsId = u32;
+
+/// The namespace everything starts in
+pub const ROOT_MNT_NS: MntNsId = 0;
+
 lazy_static! {
-    static ref MOUNT_TABLE: Mutex<Vec<MountPoint>> = Mutex::new(Vec::new());
+    static ref MOUNT_TABLES: Mutex<BTreeMap<MntNsId, Vec<MountPoint>>> = {
+        let mut tables = BTreeMap::new();
+        tables.insert(ROOT_MNT_NS, Vec::new());
+        Mutex::new(tables)
+    };
 }
 
+/// Namespace of the running task; the functions below work on its table
+static CURRENT_NS: AtomicU32 = AtomicU32::new(ROOT_MNT_NS);
+static NEXT_NS: AtomicU32 = AtomicU32::new(ROOT_MNT_NS + 1);
+
 pub fn init() {
 }
 
+/// Switch to the mount table of `ns`, as the scheduler does when it
+/// switches to a task in that namespace
+pub fn set_namespace(ns: MntNsId) {
+    CURRENT_NS.store(ns, Ordering::Relaxed);
+}
+
+pub fn current_namespace() -> MntNsId {
+    CURRENT_NS.load(Ordering::Relaxed)
+}
+
+/// Create a namespace starting with a copy of the mounts in `from`
+pub fn clone_namespace(from: MntNsId) -> MntNsId {
+    let mut tables = MOUNT_TABLES.lock();
+    let table = tables.get(&from).cloned().unwrap_or_default();
+    let ns = NEXT_NS.fetch_add(1, Ordering::Relaxed);
+    tables.insert(ns, table);
+    ns
+}
+
+/// Forget a namespace no task uses any more. The root one always stays.
+pub fn drop_namespace(ns: MntNsId) {
+    if ns != ROOT_MNT_NS {
+        MOUNT_TABLES.lock().remove(&ns);
+    }
+}
+
+/// Every namespace that currently exists
+pub fn namespaces() -> Vec<MntNsId> {
+    MOUNT_TABLES.lock().keys().copied().collect()
+}
+
+/// Run `f` on the current namespace's table
+fn with_table<R>(f: impl FnOnce(&mut Vec<MountPoint>) -> R) -> R {
+    let mut tables = MOUNT_TABLES.lock();
+    f(tables.entry(current_namespace()).or_default())
+}
+
 pub fn mount(
     source: &str,
     target: &str,
@@ -51,56 +108,109 @@ pub fn mount(
     flags: MountFlags,
     filesystem: Arc<RwLock<dyn Filesystem + Send + Sync>>,
 ) -> FsResult<()> {
-    let mut table = MOUNT_TABLE.lock();
+    // A filesystem that ignores case, such as FAT, is always casefold
+    let flags = match filesystem.read().casefold() {
+        true => flags | MountFlags::CASEFOLD,
+        false => flags,
+    };
+    with_table(|table| {
+        if table.iter().any(|m| m.path == target) {
+            return Err(FsError::Busy);
+        }
+        
+        let mount_point = MountPoint {
+            path: target.to_string(),
+            device: source.to_string(),
+            fs_type: fs_type.to_string(),
+            flags,
+            filesystem,
+        };
+        
+        table.push(mount_point);
+        
+        table.sort_by_key(|mount| core::cmp::Reverse(mount.path.len()));
+        
+        Ok(())
+    })?;
     
-    if table.iter().any(|m| m.path == target) {
-        return Err(FsError::Busy);
+    if flags.contains(MountFlags::CASEFOLD) {
+        if let Err(e) = set_casefold(target, true) {
+            umount(target).ok();
+            return Err(e);
+        }
     }
-    
-    let mount_point = MountPoint {
-        path: target.to_string(),
-        device: source.to_string(),
-        fs_type: fs_type.to_string(),
-        flags,
-        filesystem,
+    Ok(())
+}
+
+/// Graft `filesystem` on the directory `target` and enter it in the mount
+/// table, as block devices and tmpfs are mounted; Busy if something is
+/// mounted there already. Nothing is left grafted if it fails.
+pub fn graft(
+    source: &str,
+    target: &str,
+    fs_type: &str,
+    flags: MountFlags,
+    filesystem: Arc<RwLock<dyn Filesystem + Send + Sync>>,
+) -> FsResult<()> {
+    let target = {
+        let mut vfs = VFS.lock();
+        let target = vfs.resolve_path(target);
+        if is_mounted(&target) {
+            return Err(FsError::Busy);
+        }
+        vfs.graft(&target, filesystem.clone())?;
+        target
     };
-    
-    table.push(mount_point);
-    
-    table.sort_by(|a, b| b.path.len().cmp(&a.path.len()));
-    
+    if let Err(e) = mount(source, &target, fs_type, flags, filesystem) {
+        VFS.lock().ungraft(&target).ok();
+        return Err(e);
+    }
     Ok(())
 }
 
 pub fn umount(target: &str) -> FsResult<()> {
-    let mut table = MOUNT_TABLE.lock();
+    let flags = with_table(|table| {
+        if let Some(pos) = table.iter().position(|m| m.path == target) {
+            Ok(table.remove(pos).flags)
+        } else {
+            Err(FsError::NotFound)
+        }
+    })?;
     
-    if let Some(pos) = table.iter().position(|m| m.path == target) {
-        table.remove(pos);
-        Ok(())
-    } else {
-        Err(FsError::NotFound)
+    if flags.contains(MountFlags::CASEFOLD) {
+        set_casefold(target, false).ok();
     }
+    Ok(())
 }
 
+/// Casefold the VFS directory `target` is mounted on, or stop doing so
+fn set_casefold(target: &str, casefold: bool) -> FsResult<()> {
+    let mut vfs = VFS.lock();
+    let inode = vfs.lookup_resolved(target)?.inode;
+    vfs.set_casefold(inode, casefold)
+}
+
+/// The mount `path` is on: the deepest one it is at or under, as the
+/// table is kept longest path first
 pub fn find_mount_point(path: &str) -> Option<MountPoint> {
-    let table = MOUNT_TABLE.lock();
-    
-    for mount in table.iter() {
-        if path.starts_with(&mount.path) {
-            return Some(mount.clone());
-        }
+    with_table(|table| table.iter().find(|mount| is_within(path, &mount.path)).cloned())
+}
+
+/// Whether `path` is the directory `dir` or anything under it. /mnt/usb
+/// is not under /mnt/us.
+pub fn is_within(path: &str, dir: &str) -> bool {
+    match path.strip_prefix(dir.trim_end_matches('/')) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
     }
-    
-    None
 }
 
 pub fn get_mount_table() -> Vec<MountPoint> {
-    MOUNT_TABLE.lock().clone()
+    with_table(|table| table.clone())
 }
 
 pub fn is_mounted(path: &str) -> bool {
-    MOUNT_TABLE.lock().iter().any(|m| m.path == path)
+    with_table(|table| table.iter().any(|m| m.path == path))
 }
 
 pub fn get_relative_path(path: &str, mount_point: &str) -> String {
@@ -127,7 +237,7 @@ pub struct MountInfo {
 }
 
 pub fn get_mounts() -> Vec<MountInfo> {
-    MOUNT_TABLE.lock().iter().map(|m| {
+    get_mount_table().iter().map(|m| {
         let mut options = Vec::new();
         
         if m.flags.contains(MountFlags::RDONLY) {
@@ -152,6 +262,10 @@ pub fn get_mounts() -> Vec<MountInfo> {
             options.push("noatime");
         }
         
+        if m.flags.contains(MountFlags::CASEFOLD) {
+            options.push("casefold");
+        }
+        
         MountInfo {
             device: m.device.clone(),
             mount_point: m.path.clone(),
@@ -162,14 +276,14 @@ pub fn get_mounts() -> Vec<MountInfo> {
 }
 
 pub fn remount(target: &str, flags: MountFlags) -> FsResult<()> {
-    let mut table = MOUNT_TABLE.lock();
-    
-    if let Some(mount) = table.iter_mut().find(|m| m.path == target) {
-        mount.flags = flags;
-        Ok(())
-    } else {
-        Err(FsError::NotFound)
-    }
+    with_table(|table| {
+        if let Some(mount) = table.iter_mut().find(|m| m.path == target) {
+            mount.flags = flags;
+            Ok(())
+        } else {
+            Err(FsError::NotFound)
+        }
+    })
 }
 
 pub const MS_RDONLY: u32 = MountFlags::RDONLY.bits();
@@ -0,0 +1,78 @@
+// Overlay (union) mounts
+//
+// An overlay merges the tree of a read-only filesystem, the lower layer,
+// into a directory of the VFS, whose in-memory nodes are the upper layer.
+// Directories and metadata are copied in when the overlay is made; file
+// data is read through from the lower layer until something changes it,
+// when the file is copied up into memory first. The lower layer is never
+// written, so dropping the overlay drops every change with it.
+//
+// Removing or renaming away a lower entry leaves a whiteout: the entry
+// stays gone from the merged view, and `changes` reports it deleted next
+// to what was added and what was copied up.
+
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::fmt;
+use spin::RwLock;
+use crate::fs::vfs::node::{Filesystem, InodeNumber};
+
+#[derive(Clone)]
+pub struct Overlay {
+    pub lower: Arc<RwLock<dyn Filesystem + Send + Sync>>,
+    /// The merged directory, as a path from the real root
+    pub path: String,
+    /// Nodes that mirror an entry of the lower layer
+    pub lower_nodes: BTreeSet<InodeNumber>,
+    /// Those of them that have been copied up
+    pub copied: BTreeSet<InodeNumber>,
+    /// Lower entries removed from the merged view, by path under it
+    pub whiteouts: BTreeSet<String>,
+}
+
+impl Overlay {
+    pub fn new(lower: Arc<RwLock<dyn Filesystem + Send + Sync>>, path: String) -> Self {
+        Overlay {
+            lower,
+            path,
+            lower_nodes: BTreeSet::new(),
+            copied: BTreeSet::new(),
+            whiteouts: BTreeSet::new(),
+        }
+    }
+
+    /// `path` (from the real root) as a path under the merged directory,
+    /// if it is under it
+    pub fn relative(&self, path: &str) -> Option<String> {
+        if self.path == "/" {
+            return Some(String::from(path));
+        }
+        let rest = path.strip_prefix(self.path.as_str())?;
+        rest.starts_with('/').then(|| String::from(rest))
+    }
+
+    /// Forget a node that is gone from the merged view
+    pub fn forget(&mut self, inode: InodeNumber) {
+        self.lower_nodes.remove(&inode);
+        self.copied.remove(&inode);
+    }
+}
+
+/// How an entry of the merged view differs from the lower layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Added,
+    Changed,
+    Deleted,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Change::Added => "A",
+            Change::Changed => "C",
+            Change::Deleted => "D",
+        })
+    }
+}
@@ -0,0 +1,269 @@
+// Disk quotas
+//
+// Usage of the VFS is counted per uid and per gid: the size of every node
+// in 1 KiB quota blocks, and one inode each. A node is charged to its
+// owner when it is created, as it grows or shrinks and when it changes
+// owner, and given back when it is removed. Files of a grafted filesystem
+// count towards their owners too.
+//
+// Each count may have a soft and a hard limit, 0 meaning none. Growing
+// past a hard limit fails with QuotaExceeded (EDQUOT). Going past a soft
+// limit starts a grace period, and once that has run out the soft limit
+// holds like a hard one until usage drops back under it. Limits are not
+// checked against id 0: root, and the kernel, are only counted.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use crate::fs::{FsError, FsResult};
+use crate::fs::vfs::node::InodeNumber;
+
+/// Bytes in a quota block
+pub const QUOTA_BLOCK: u64 = 1024;
+/// Grace period, in seconds, until one is set: a week
+pub const DEFAULT_GRACE: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaType {
+    User,
+    Group,
+}
+
+/// Soft and hard limits on quota blocks and inodes; 0 is no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub block_soft: u64,
+    pub block_hard: u64,
+    pub inode_soft: u64,
+    pub inode_hard: u64,
+}
+
+/// One uid's or gid's usage and limits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dquot {
+    pub blocks: u64,
+    pub inodes: u64,
+    pub limits: Limits,
+    /// While blocks are over the soft limit, when it starts to hold
+    pub block_grace: Option<u64>,
+    pub inode_grace: Option<u64>,
+}
+
+/// Whether `used` may grow to `after` under `soft` and `hard`
+fn within(used: u64, after: u64, soft: u64, hard: u64, grace: Option<u64>, now: u64) -> bool {
+    after <= used
+        || ((hard == 0 || after <= hard) && (soft == 0 || after <= soft || grace.is_none_or(|end| now < end)))
+}
+
+impl Dquot {
+    fn allows(&self, blocks: u64, inodes: u64, now: u64) -> bool {
+        let limits = &self.limits;
+        within(self.blocks, blocks, limits.block_soft, limits.block_hard, self.block_grace, now)
+            && within(self.inodes, inodes, limits.inode_soft, limits.inode_hard, self.inode_grace, now)
+    }
+
+    /// Start or end the grace periods as usage crosses the soft limits
+    fn update_grace(&mut self, now: u64, block_period: u64, inode_period: u64) {
+        let over = |used: u64, soft: u64| soft != 0 && used > soft;
+        self.block_grace = match over(self.blocks, self.limits.block_soft) {
+            true => self.block_grace.or(Some(now + block_period)),
+            false => None,
+        };
+        self.inode_grace = match over(self.inodes, self.limits.inode_soft) {
+            true => self.inode_grace.or(Some(now + inode_period)),
+            false => None,
+        };
+    }
+}
+
+/// What a node is charged: to whom, and how many blocks
+#[derive(Debug, Clone, Copy)]
+struct Charge {
+    uid: u32,
+    gid: u32,
+    blocks: u64,
+}
+
+pub fn blocks_for(size: u64) -> u64 {
+    size.div_ceil(QUOTA_BLOCK)
+}
+
+#[derive(Clone)]
+pub struct Quotas {
+    users: BTreeMap<u32, Dquot>,
+    groups: BTreeMap<u32, Dquot>,
+    charges: BTreeMap<InodeNumber, Charge>,
+    block_grace: u64,
+    inode_grace: u64,
+    /// Seconds since the epoch, for grace periods
+    clock: fn() -> u64,
+}
+
+impl Quotas {
+    pub fn new() -> Self {
+        Quotas {
+            users: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            charges: BTreeMap::new(),
+            block_grace: DEFAULT_GRACE,
+            inode_grace: DEFAULT_GRACE,
+            clock: || 0,
+        }
+    }
+
+    /// Time grace periods by `clock`, which returns Unix time in seconds
+    pub fn set_clock(&mut self, clock: fn() -> u64) {
+        self.clock = clock;
+    }
+
+    pub fn now(&self) -> u64 {
+        (self.clock)()
+    }
+
+    fn table(&self, kind: QuotaType) -> &BTreeMap<u32, Dquot> {
+        match kind {
+            QuotaType::User => &self.users,
+            QuotaType::Group => &self.groups,
+        }
+    }
+
+    fn table_mut(&mut self, kind: QuotaType) -> &mut BTreeMap<u32, Dquot> {
+        match kind {
+            QuotaType::User => &mut self.users,
+            QuotaType::Group => &mut self.groups,
+        }
+    }
+
+    pub fn get(&self, kind: QuotaType, id: u32) -> Dquot {
+        self.table(kind).get(&id).copied().unwrap_or_default()
+    }
+
+    /// Every id with usage or limits, by id
+    pub fn report(&self, kind: QuotaType) -> Vec<(u32, Dquot)> {
+        self.table(kind).iter().map(|(&id, &dquot)| (id, dquot)).collect()
+    }
+
+    pub fn set_limits(&mut self, kind: QuotaType, id: u32, limits: Limits) {
+        let (now, block_period, inode_period) = (self.now(), self.block_grace, self.inode_grace);
+        let dquot = self.table_mut(kind).entry(id).or_default();
+        dquot.limits = limits;
+        dquot.update_grace(now, block_period, inode_period);
+        self.tidy(kind, id);
+    }
+
+    /// The block and inode grace periods, in seconds
+    pub fn grace(&self) -> (u64, u64) {
+        (self.block_grace, self.inode_grace)
+    }
+
+    /// Set the grace periods; ones already running keep their end
+    pub fn set_grace(&mut self, block: u64, inode: u64) {
+        (self.block_grace, self.inode_grace) = (block, inode);
+    }
+
+    /// Whether `uid` and `gid` may grow by `blocks` and `inodes`
+    fn check(&self, uid: u32, gid: u32, blocks: u64, inodes: u64) -> FsResult<()> {
+        let now = self.now();
+        for (kind, id) in [(QuotaType::User, uid), (QuotaType::Group, gid)] {
+            let dquot = self.get(kind, id);
+            if id != 0 && !dquot.allows(dquot.blocks + blocks, dquot.inodes + inodes, now) {
+                return Err(FsError::QuotaExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, uid: u32, gid: u32, blocks: i64, inodes: i64) {
+        let (now, block_period, inode_period) = (self.now(), self.block_grace, self.inode_grace);
+        for (kind, id) in [(QuotaType::User, uid), (QuotaType::Group, gid)] {
+            let dquot = self.table_mut(kind).entry(id).or_default();
+            dquot.blocks = dquot.blocks.saturating_add_signed(blocks);
+            dquot.inodes = dquot.inodes.saturating_add_signed(inodes);
+            dquot.update_grace(now, block_period, inode_period);
+            self.tidy(kind, id);
+        }
+    }
+
+    /// Forget an id with nothing to its name
+    fn tidy(&mut self, kind: QuotaType, id: u32) {
+        let table = self.table_mut(kind);
+        if table.get(&id) == Some(&Dquot::default()) {
+            table.remove(&id);
+        }
+    }
+
+    /// Charge a new node of `size` bytes to `uid` and `gid`
+    pub fn create(&mut self, inode: InodeNumber, uid: u32, gid: u32, size: u64) -> FsResult<()> {
+        let blocks = blocks_for(size);
+        self.check(uid, gid, blocks, 1)?;
+        self.adopt(inode, uid, gid, size);
+        Ok(())
+    }
+
+    /// Count a node that exists already, like one of a grafted
+    /// filesystem, whatever the limits
+    pub fn adopt(&mut self, inode: InodeNumber, uid: u32, gid: u32, size: u64) {
+        self.release(inode);
+        let blocks = blocks_for(size);
+        self.apply(uid, gid, blocks as i64, 1);
+        self.charges.insert(inode, Charge { uid, gid, blocks });
+    }
+
+    /// Charge a node for its new size. Nodes not counted are left alone.
+    pub fn resize(&mut self, inode: InodeNumber, size: u64) -> FsResult<()> {
+        let charge = match self.charges.get(&inode) {
+            Some(&charge) => charge,
+            None => return Ok(()),
+        };
+        let blocks = blocks_for(size);
+        if blocks > charge.blocks {
+            self.check(charge.uid, charge.gid, blocks - charge.blocks, 0)?;
+        }
+        self.apply(charge.uid, charge.gid, blocks as i64 - charge.blocks as i64, 0);
+        self.charges.insert(inode, Charge { blocks, ..charge });
+        Ok(())
+    }
+
+    /// Move a node's charge to a new owner, who must have room for it
+    pub fn chown(&mut self, inode: InodeNumber, uid: u32, gid: u32) -> FsResult<()> {
+        let charge = match self.charges.get(&inode) {
+            Some(&charge) => charge,
+            None => return Ok(()),
+        };
+        // Only the ids that change take anything on
+        let blocks = |same: bool| if same { 0 } else { charge.blocks };
+        let inodes = |same: bool| if same { 0 } else { 1 };
+        let now = self.now();
+        for (kind, id, old) in [(QuotaType::User, uid, charge.uid), (QuotaType::Group, gid, charge.gid)] {
+            let dquot = self.get(kind, id);
+            let same = id == old;
+            if id != 0 && !dquot.allows(dquot.blocks + blocks(same), dquot.inodes + inodes(same), now) {
+                return Err(FsError::QuotaExceeded);
+            }
+        }
+        self.release(inode);
+        self.adopt(inode, uid, gid, charge.blocks * QUOTA_BLOCK);
+        Ok(())
+    }
+
+    /// Charge `inode` as `from` had it charged, or not at all if it was
+    /// not, as when the VFS goes back to a snapshot
+    pub fn restore(&mut self, inode: InodeNumber, from: &Quotas) {
+        self.release(inode);
+        if let Some(charge) = from.charges.get(&inode) {
+            self.adopt(inode, charge.uid, charge.gid, charge.blocks * QUOTA_BLOCK);
+        }
+    }
+    
+    /// Give back what a removed node was charged
+    pub fn release(&mut self, inode: InodeNumber) {
+        if let Some(charge) = self.charges.remove(&inode) {
+            self.apply(charge.uid, charge.gid, -(charge.blocks as i64), -1);
+        }
+    }
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Quotas::new()
+    }
+}
@@ -0,0 +1,64 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::fs::ext4::ext4::BlockDevice;
+
+/// A block device backed by memory. Used to mount embedded images and as
+/// scratch storage; contents are lost on reboot.
+pub struct RamDisk {
+    data: Vec<u8>,
+    block_size: u32,
+}
+
+impl RamDisk {
+    pub fn new(blocks: u64, block_size: u32) -> Self {
+        RamDisk {
+            data: vec![0; blocks as usize * block_size as usize],
+            block_size,
+        }
+    }
+    
+    /// Copies `image` into a new RAM disk; writes never touch the original.
+    pub fn from_image(image: &[u8], block_size: u32) -> Self {
+        RamDisk {
+            data: image.to_vec(),
+            block_size,
+        }
+    }
+    
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    
+    fn range(&self, block_num: u64, len: usize) -> Result<core::ops::Range<usize>, &'static str> {
+        let start = (block_num as usize)
+            .checked_mul(self.block_size as usize)
+            .ok_or("block out of range")?;
+        let end = start.checked_add(len).ok_or("block out of range")?;
+        if end > self.data.len() {
+            return Err("block out of range");
+        }
+        Ok(start..end)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let range = self.range(block_num, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+    
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let range = self.range(block_num, buf.len())?;
+        self.data[range].copy_from_slice(buf);
+        Ok(())
+    }
+    
+    fn block_size(&self) -> u32 {
+        self.block_size
+    }
+    
+    fn block_count(&self) -> u64 {
+        self.data.len() as u64 / self.block_size as u64
+    }
+}
@@ -0,0 +1,54 @@
+#!/bin/bash
+# Regenerate the golden images embedded by src/fs/golden.rs. The trees are
+# the ones the tests assert against, so change both together. Output is
+# deterministic. Requires e2fsprogs and python3.
+
+set -e
+umask 022
+cd "$(dirname "$0")"
+MKFAT32="../../../host-tests/fixtures/mkfat32.py"
+STAMP=1700000000
+
+TREE="$(mktemp -d)"
+FAT="$(mktemp -d)"
+trap 'rm -rf "$TREE" "$FAT"' EXIT
+
+# ext4: block-mapped (no extents), 1 KiB blocks
+mkdir -p "$TREE/dir/nested" "$TREE/bin"
+printf 'hello, golden ext4\n' > "$TREE/hello.txt"
+printf 'deep\n' > "$TREE/dir/nested/deep.txt"
+printf '#!/bin/sh\necho hi\n' > "$TREE/bin/run.sh"
+chmod 0755 "$TREE/bin/run.sh"
+chmod 0600 "$TREE/hello.txt"
+: > "$TREE/empty.txt"
+printf 'long\n' > "$TREE/this-is-a-rather-long-file-name-that-goes-well-past-sixty-four-characters.txt"
+ln -s hello.txt "$TREE/link"
+ln -s "dir/nested/../nested/../nested/../nested/../nested/../nested/./deep.txt" "$TREE/longlink"
+# 40 blocks: 12 direct, the rest through the single indirect block
+python3 -c "import sys; sys.stdout.buffer.write(bytes((i * 7 + 3) % 256 for i in range(40960)))" > "$TREE/big.bin"
+# 20 KiB hole, then data reached through the indirect block
+printf 'tail' | dd of="$TREE/sparse.bin" bs=1 seek=20480 conv=notrunc status=none
+chown -R 1000:100 "$TREE/hello.txt" "$TREE/dir"
+find "$TREE" -exec touch -h -d @$STAMP {} +
+
+rm -f golden-ext4.img
+E2FSPROGS_FAKE_TIME=$STAMP mke2fs -q -t ext4 -b 1024 -N 32 \
+    -O ^has_journal,^resize_inode,^extent,^flex_bg,^metadata_csum,^64bit,^dir_index \
+    -U 6f1d4c1e-0000-4000-8000-000000000003 -E hash_seed=6f1d4c1e-0000-4000-8000-000000000004 \
+    -L golden -d "$TREE" golden-ext4.img 256K >/dev/null
+# mke2fs copies each file's ctime from the host, which touch cannot set.
+for ino in $(seq 1 32); do
+    echo "sif <$ino> ctime $STAMP"
+done | debugfs -w -f - golden-ext4.img >/dev/null 2>&1
+
+# FAT32
+mkdir -p "$FAT/DOCS/SUB"
+printf 'hello, golden fat32\n' > "$FAT/HELLO.TXT"
+printf 'mixed\n' > "$FAT/Mixed Case Name.txt"
+printf 'spans several entries\n' > "$FAT/a very long file name that spans several lfn entries.text"
+printf 'readme\n' > "$FAT/DOCS/README.MD"
+printf 'deep\n' > "$FAT/DOCS/SUB/DEEP.TXT"
+: > "$FAT/EMPTY.TXT"
+# 10 clusters, fragmented by mkfat32
+python3 -c "import sys; sys.stdout.buffer.write(bytes((i * 13 + 5) % 256 for i in range(5000)))" > "$FAT/BIG.BIN"
+python3 "$MKFAT32" golden-fat32.img "$FAT" 256
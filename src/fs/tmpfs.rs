@@ -0,0 +1,468 @@
+// tmpfs
+//
+// A filesystem kept on the heap, gone when it is unmounted: /tmp and /run
+// are tmpfs from boot, and `mount -t tmpfs` makes more. Each mount has a
+// size limit on the file data it holds and a limit on its inodes, and
+// going past either fails with NoSpace (ENOSPC) as a full disk would,
+// rather than with the heap running out. File data is sparse (see
+// vfs::sparse) and counts by the pages it takes, so holes cost nothing
+// and fallocate() counts in full; directories and symlinks count only
+// against the inodes.
+//
+// Being the simplest Filesystem there is, it is also the one the tests
+// use to walk the VFS through a mount point.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::RwLock;
+use crate::fs::{FileMode, FileStat, FileType, FsError, FsResult};
+use crate::fs::mount::{self, MountFlags};
+use crate::fs::vfs::node::{DirEntry, Filesystem, InodeNumber, VfsNode, VfsNodeData};
+use crate::fs::vfs::sparse::{FileData, PAGE_SIZE};
+use crate::fs::vfs::api::FallocateFlags;
+use crate::fs::vfs::credentials;
+
+/// Bytes of file data a mount holds unless `size=` says otherwise
+pub const DEFAULT_SIZE: u64 = 16 * 1024 * 1024;
+/// Inodes a mount holds unless `nr_inodes=` says otherwise
+pub const DEFAULT_INODES: u64 = 4096;
+/// Inode number of the root directory
+pub const ROOT_INO: InodeNumber = 1;
+const NAME_MAX: usize = 255;
+
+/// What timestamps files, as Unix time in seconds
+static CLOCK: RwLock<fn() -> u64> = RwLock::new(|| 0);
+
+/// Timestamp files of every tmpfs by `clock` from now on
+pub fn set_clock(clock: fn() -> u64) {
+    *CLOCK.write() = clock;
+}
+
+/// The limits of one mount
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// Bytes of file data, rounded up to whole pages
+    pub size: u64,
+    pub inodes: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { size: DEFAULT_SIZE, inodes: DEFAULT_INODES }
+    }
+}
+
+impl Options {
+    /// Parse mount options as mount -o takes them: `size=` in bytes, or
+    /// with a k, m or g suffix, and `nr_inodes=`, separated by commas
+    pub fn parse(options: &str) -> FsResult<Options> {
+        let mut parsed = Options::default();
+        for option in options.split(',').filter(|o| !o.is_empty()) {
+            match option.split_once('=') {
+                Some(("size", value)) => parsed.size = parse_size(value)?,
+                Some(("nr_inodes", value)) => parsed.inodes = parse_size(value)?,
+                _ => return Err(FsError::InvalidArgument),
+            }
+        }
+        if parsed.inodes == 0 {
+            return Err(FsError::InvalidArgument);
+        }
+        Ok(parsed)
+    }
+}
+
+fn parse_size(value: &str) -> FsResult<u64> {
+    let (digits, shift) = match value.as_bytes().last().map(u8::to_ascii_lowercase) {
+        Some(b'k') => (&value[..value.len() - 1], 10),
+        Some(b'm') => (&value[..value.len() - 1], 20),
+        Some(b'g') => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    let n: u64 = digits.parse().map_err(|_| FsError::InvalidArgument)?;
+    n.checked_mul(1 << shift).ok_or(FsError::InvalidArgument)
+}
+
+enum Data {
+    File(FileData),
+    /// Entries by name, and the directory it is in
+    Directory(BTreeMap<String, InodeNumber>, InodeNumber),
+    Symlink(String),
+}
+
+struct Inode {
+    mode: FileMode,
+    uid: u32,
+    gid: u32,
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+    data: Data,
+}
+
+impl Inode {
+    fn size(&self) -> u64 {
+        match &self.data {
+            Data::File(data) => data.len(),
+            Data::Directory(entries, _) => entries.len() as u64,
+            Data::Symlink(target) => target.len() as u64,
+        }
+    }
+
+    fn nlink(&self, inodes: &BTreeMap<InodeNumber, Inode>) -> u64 {
+        match &self.data {
+            Data::Directory(entries, _) => {
+                2 + entries.values().filter(|i| inodes.get(i).is_some_and(|i| i.mode.is_dir())).count() as u64
+            }
+            _ => 1,
+        }
+    }
+}
+
+pub struct Tmpfs {
+    inodes: BTreeMap<InodeNumber, Inode>,
+    next_inode: InodeNumber,
+    options: Options,
+    /// Bytes of pages file data takes
+    used: u64,
+    read_only: bool,
+}
+
+impl Tmpfs {
+    /// An empty filesystem: a root directory anyone can make files in,
+    /// as /tmp is
+    pub fn new(options: Options, read_only: bool) -> Self {
+        let mut fs = Tmpfs { inodes: BTreeMap::new(), next_inode: ROOT_INO + 1, options, used: 0, read_only };
+        let root = fs.new_inode(FileMode::S_IFDIR | 0o1777, Data::Directory(BTreeMap::new(), ROOT_INO));
+        fs.inodes.insert(ROOT_INO, Inode { uid: 0, gid: 0, ..root });
+        fs
+    }
+
+    pub fn options(&self) -> Options {
+        self.options
+    }
+
+    /// Bytes of file data and inodes in use
+    pub fn usage(&self) -> (u64, u64) {
+        (self.used, self.inodes.len() as u64)
+    }
+
+    fn now() -> u64 {
+        (CLOCK.read())()
+    }
+
+    fn new_inode(&self, mode: u16, data: Data) -> Inode {
+        let (uid, gid) = credentials();
+        let now = Self::now();
+        Inode { mode: FileMode::new(mode), uid, gid, atime: now, mtime: now, ctime: now, data }
+    }
+
+    fn inode(&self, inode: InodeNumber) -> FsResult<&Inode> {
+        self.inodes.get(&inode).ok_or(FsError::NotFound)
+    }
+
+    fn inode_mut(&mut self, inode: InodeNumber) -> FsResult<&mut Inode> {
+        self.inodes.get_mut(&inode).ok_or(FsError::NotFound)
+    }
+
+    fn writable(&self) -> FsResult<()> {
+        match self.read_only {
+            true => Err(FsError::ReadOnly),
+            false => Ok(()),
+        }
+    }
+
+    fn entries(&self, dir: InodeNumber) -> FsResult<&BTreeMap<String, InodeNumber>> {
+        match &self.inode(dir)?.data {
+            Data::Directory(entries, _) => Ok(entries),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    fn entries_mut(&mut self, dir: InodeNumber) -> FsResult<&mut BTreeMap<String, InodeNumber>> {
+        match &mut self.inode_mut(dir)?.data {
+            Data::Directory(entries, _) => Ok(entries),
+            _ => Err(FsError::NotDirectory),
+        }
+    }
+
+    /// The file `inode`'s data, to change, after checking it may take
+    /// `more` bytes of pages more
+    fn file_mut(&mut self, inode: InodeNumber, more: impl FnOnce(&FileData) -> u64) -> FsResult<&mut FileData> {
+        self.writable()?;
+        let data = match &self.inode(inode)?.data {
+            Data::File(data) => data,
+            Data::Directory(..) => return Err(FsError::IsDirectory),
+            Data::Symlink(_) => return Err(FsError::InvalidArgument),
+        };
+        if self.used.saturating_add(more(data)) > self.options.size.next_multiple_of(PAGE_SIZE as u64) {
+            return Err(FsError::NoSpace);
+        }
+        match &mut self.inode_mut(inode)?.data {
+            Data::File(data) => Ok(data),
+            _ => Err(FsError::InvalidArgument),
+        }
+    }
+
+    /// Run `change` on the file `inode`, which may take `more` bytes of
+    /// pages more, and account for what it took or gave back
+    fn change_file(&mut self, inode: InodeNumber, more: impl FnOnce(&FileData) -> u64, change: impl FnOnce(&mut FileData)) -> FsResult<()> {
+        let data = self.file_mut(inode, more)?;
+        let before = data.allocated();
+        change(data);
+        let after = data.allocated();
+        self.used = self.used + after - before;
+        let now = Self::now();
+        let node = self.inode_mut(inode)?;
+        (node.mtime, node.ctime) = (now, now);
+        Ok(())
+    }
+
+    /// Enter a new inode as `name` in the directory `parent`
+    fn make(&mut self, parent: InodeNumber, name: &str, mode: u16, data: Data) -> FsResult<VfsNode> {
+        self.writable()?;
+        check_name(name)?;
+        if self.entries(parent)?.contains_key(name) {
+            return Err(FsError::AlreadyExists);
+        }
+        if self.inodes.len() as u64 >= self.options.inodes {
+            return Err(FsError::NoSpace);
+        }
+        let number = self.next_inode;
+        self.next_inode += 1;
+        let inode = self.new_inode(mode, data);
+        self.inodes.insert(number, inode);
+        self.entries_mut(parent)?.insert(name.to_string(), number);
+        self.touch(parent)?;
+        self.node(number, name)
+    }
+
+    /// Take `name` out of the directory `parent`, freeing its inode
+    fn remove(&mut self, parent: InodeNumber, name: &str, directory: bool) -> FsResult<()> {
+        self.writable()?;
+        let number = *self.entries(parent)?.get(name).ok_or(FsError::NotFound)?;
+        match (&self.inode(number)?.data, directory) {
+            (Data::Directory(entries, _), true) if !entries.is_empty() => return Err(FsError::NotEmpty),
+            (Data::Directory(..), false) => return Err(FsError::IsDirectory),
+            (Data::File(_) | Data::Symlink(_), true) => return Err(FsError::NotDirectory),
+            _ => {}
+        }
+        self.entries_mut(parent)?.remove(name);
+        self.release(number);
+        self.touch(parent)
+    }
+
+    fn release(&mut self, number: InodeNumber) {
+        if let Some(Inode { data: Data::File(data), .. }) = self.inodes.remove(&number) {
+            self.used -= data.allocated();
+        }
+    }
+
+    /// Whether the directory `dir` is `ancestor` or under it
+    fn is_within(&self, mut dir: InodeNumber, ancestor: InodeNumber) -> bool {
+        loop {
+            if dir == ancestor {
+                return true;
+            }
+            match self.inodes.get(&dir).map(|i| &i.data) {
+                Some(Data::Directory(_, up)) if *up != dir => dir = *up,
+                _ => return false,
+            }
+        }
+    }
+
+    fn touch(&mut self, dir: InodeNumber) -> FsResult<()> {
+        let now = Self::now();
+        let node = self.inode_mut(dir)?;
+        (node.mtime, node.ctime) = (now, now);
+        Ok(())
+    }
+
+    fn node(&self, number: InodeNumber, name: &str) -> FsResult<VfsNode> {
+        let inode = self.inode(number)?;
+        let data = match &inode.data {
+            Data::File(_) => VfsNodeData::Regular(FileData::new()),
+            Data::Directory(..) => VfsNodeData::Directory(self.readdir(number)?),
+            Data::Symlink(target) => VfsNodeData::Symlink(target.clone()),
+        };
+        Ok(VfsNode {
+            name: name.to_string(),
+            inode: number,
+            mode: inode.mode,
+            uid: inode.uid,
+            gid: inode.gid,
+            size: inode.size(),
+            atime: inode.atime,
+            mtime: inode.mtime,
+            ctime: inode.ctime,
+            nlink: inode.nlink(&self.inodes),
+            device: None,
+            data,
+            casefold: false,
+        })
+    }
+}
+
+fn check_name(name: &str) -> FsResult<()> {
+    if name.len() > NAME_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err(FsError::InvalidArgument);
+    }
+    Ok(())
+}
+
+impl Filesystem for Tmpfs {
+    fn name(&self) -> &str {
+        "tmpfs"
+    }
+
+    fn root(&self) -> FsResult<VfsNode> {
+        self.node(ROOT_INO, "/")
+    }
+
+    fn lookup(&self, parent: InodeNumber, name: &str) -> FsResult<VfsNode> {
+        let number = *self.entries(parent)?.get(name).ok_or(FsError::NotFound)?;
+        self.node(number, name)
+    }
+
+    fn read(&self, inode: InodeNumber, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        match &self.inode(inode)?.data {
+            Data::File(data) => Ok(data.read(offset, buf)),
+            Data::Directory(..) => Err(FsError::IsDirectory),
+            Data::Symlink(_) => Err(FsError::InvalidArgument),
+        }
+    }
+
+    fn write(&mut self, inode: InodeNumber, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        offset.checked_add(buf.len() as u64).ok_or(FsError::InvalidArgument)?;
+        let len = buf.len() as u64;
+        self.change_file(inode, |data| data.unallocated(offset, len), |data| data.write(offset, buf))?;
+        Ok(buf.len())
+    }
+
+    fn create(&mut self, parent: InodeNumber, name: &str, mode: FileMode) -> FsResult<VfsNode> {
+        self.make(parent, name, FileMode::S_IFREG | (mode.0 & 0o7777), Data::File(FileData::new()))
+    }
+
+    fn mkdir(&mut self, parent: InodeNumber, name: &str, mode: FileMode) -> FsResult<VfsNode> {
+        self.make(parent, name, FileMode::S_IFDIR | (mode.0 & 0o7777), Data::Directory(BTreeMap::new(), parent))
+    }
+
+    fn symlink(&mut self, parent: InodeNumber, name: &str, target: &str) -> FsResult<VfsNode> {
+        self.make(parent, name, FileMode::S_IFLNK | 0o777, Data::Symlink(target.to_string()))
+    }
+
+    fn unlink(&mut self, parent: InodeNumber, name: &str) -> FsResult<()> {
+        self.remove(parent, name, false)
+    }
+
+    fn rmdir(&mut self, parent: InodeNumber, name: &str) -> FsResult<()> {
+        self.remove(parent, name, true)
+    }
+
+    /// As rename(2): an existing `new_name` of the same kind is replaced,
+    /// a directory only if it is empty, and a directory cannot move
+    /// under itself
+    fn rename(&mut self, old_parent: InodeNumber, old_name: &str, new_parent: InodeNumber, new_name: &str) -> FsResult<()> {
+        self.writable()?;
+        check_name(new_name)?;
+        let number = *self.entries(old_parent)?.get(old_name).ok_or(FsError::NotFound)?;
+        let is_dir = self.inode(number)?.mode.is_dir();
+        if is_dir && self.is_within(new_parent, number) {
+            return Err(FsError::InvalidArgument);
+        }
+        match self.entries(new_parent)?.get(new_name) {
+            Some(&target) if target == number => return Ok(()),
+            Some(_) => self.remove(new_parent, new_name, is_dir)?,
+            None => {}
+        }
+        self.entries_mut(old_parent)?.remove(old_name);
+        self.entries_mut(new_parent)?.insert(new_name.to_string(), number);
+        if let Data::Directory(_, up) = &mut self.inode_mut(number)?.data {
+            *up = new_parent;
+        }
+        self.inode_mut(number)?.ctime = Self::now();
+        self.touch(old_parent)?;
+        self.touch(new_parent)
+    }
+
+    fn stat(&self, inode: InodeNumber) -> FsResult<FileStat> {
+        let node = self.inode(inode)?;
+        Ok(FileStat {
+            dev: 0,
+            ino: inode,
+            mode: node.mode,
+            nlink: node.nlink(&self.inodes),
+            uid: node.uid,
+            gid: node.gid,
+            rdev: 0,
+            size: node.size(),
+            blksize: PAGE_SIZE as u64,
+            blocks: match &node.data {
+                Data::File(data) => data.allocated() / 512,
+                _ => 0,
+            },
+            atime: node.atime,
+            mtime: node.mtime,
+            ctime: node.ctime,
+        })
+    }
+
+    fn readdir(&self, inode: InodeNumber) -> FsResult<Vec<DirEntry>> {
+        let up = match &self.inode(inode)?.data {
+            Data::Directory(_, up) => *up,
+            _ => return Err(FsError::NotDirectory),
+        };
+        let mut entries = alloc::vec![
+            DirEntry::new(".".into(), inode, FileType::Directory),
+            DirEntry::new("..".into(), up, FileType::Directory),
+        ];
+        for (name, &number) in self.entries(inode)? {
+            entries.push(DirEntry::new(name.clone(), number, self.inode(number)?.mode.file_type()));
+        }
+        Ok(entries)
+    }
+
+    fn sync(&mut self) -> FsResult<()> {
+        Ok(())
+    }
+
+    fn fallocate(&mut self, inode: InodeNumber, mode: FallocateFlags, offset: u64, len: u64) -> FsResult<()> {
+        let end = offset.checked_add(len).filter(|_| len > 0).ok_or(FsError::InvalidArgument)?;
+        let punch = mode.contains(FallocateFlags::PUNCH_HOLE);
+        if mode.bits() & !FallocateFlags::all().bits() != 0 || (punch && !mode.contains(FallocateFlags::KEEP_SIZE)) {
+            return Err(FsError::NotSupported);
+        }
+        if punch {
+            return self.change_file(inode, |_| 0, |data| data.punch_hole(offset, len));
+        }
+        let grow = !mode.contains(FallocateFlags::KEEP_SIZE);
+        self.change_file(inode, |data| data.unallocated(offset, len), |data| {
+            data.allocate(offset, len);
+            if grow && end > data.len() {
+                data.resize(end);
+            }
+        })
+    }
+
+    fn truncate(&mut self, inode: InodeNumber, size: u64) -> FsResult<()> {
+        self.change_file(inode, |_| 0, |data| data.resize(size))
+    }
+}
+
+/// Mount a new tmpfs on the directory `target`, hiding what it holds
+/// until it is unmounted; `source` is only a name for the mount table
+pub fn mount(source: &str, target: &str, options: Options, flags: MountFlags) -> FsResult<()> {
+    let filesystem = Arc::new(RwLock::new(Tmpfs::new(options, flags.contains(MountFlags::RDONLY))));
+    mount::graft(source, target, "tmpfs", flags, filesystem)
+}
+
+/// Mount /tmp and /run
+pub fn init() {
+    let flags = MountFlags::NOSUID | MountFlags::NODEV;
+    mount("tmpfs", "/tmp", Options::default(), flags).ok();
+    mount("tmpfs", "/run", Options { size: DEFAULT_SIZE / 4, ..Options::default() }, flags).ok();
+}
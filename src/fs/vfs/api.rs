@@ -24,6 +24,28 @@ bitflags::bitflags! {
     }
 }
 
+bitflags::bitflags! {
+    /// fallocate(2) modes
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FallocateFlags: u32 {
+        /// Allocate without growing the file
+        const KEEP_SIZE = 0x01;
+        /// Deallocate, leaving a hole; needs KEEP_SIZE
+        const PUNCH_HOLE = 0x02;
+    }
+}
+
+bitflags::bitflags! {
+    /// access(2) modes. F_OK, only asking whether the file exists, is
+    /// the empty set.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct AccessMode: u32 {
+        const X_OK = 1;
+        const W_OK = 2;
+        const R_OK = 4;
+    }
+}
+
 impl OpenFlags {
     pub fn can_read(&self) -> bool {
         let access = self.bits() & 3;
@@ -107,6 +129,13 @@ pub fn open(path: &str, flags: OpenFlags, mode: u16) -> FsResult<FileDescriptor>
     if flags.contains(OpenFlags::O_DIRECTORY) && !node.is_dir() {
         return Err(FsError::NotDirectory);
     }
+    if node.is_dir() && flags.can_write() {
+        return Err(FsError::IsDirectory);
+    }
+
+    if flags.contains(OpenFlags::O_TRUNC) && flags.can_write() && node.is_file() {
+        VFS.lock().truncate(path, 0)?;
+    }
 
     Ok(FileDescriptor::new(
         String::from(path),
@@ -163,6 +192,44 @@ pub fn lseek(fd: &mut FileDescriptor, offset: i64, whence: i32) -> FsResult<u64>
     fd.seek(pos, node.size)
 }
 
+/// Reads a whole regular file into memory.
+pub fn read_file(path: &str) -> FsResult<Vec<u8>> {
+    let vfs = VFS.lock();
+    let node = vfs.lookup_path(path)?;
+    if node.is_dir() {
+        return Err(FsError::IsDirectory);
+    }
+    
+    // The size is only a hint: a grafted file may have changed since
+    let mut data = alloc::vec![0u8; node.size.max(4096) as usize];
+    let mut len = 0;
+    loop {
+        if len == data.len() {
+            data.resize(len * 2, 0);
+        }
+        match node.read(len as u64, &mut data[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    data.truncate(len);
+    Ok(data)
+}
+
+/// Creates or replaces a regular file with `data`.
+pub fn write_file(path: &str, data: &[u8], mode: u16) -> FsResult<()> {
+    let flags = OpenFlags::O_WRONLY | OpenFlags::O_CREAT | OpenFlags::O_TRUNC;
+    let mut fd = open(path, flags, mode)?;
+    let mut written = 0;
+    while written < data.len() {
+        match write(&mut fd, &data[written..])? {
+            0 => return Err(FsError::IoError),
+            n => written += n,
+        }
+    }
+    Ok(())
+}
+
 pub fn stat(path: &str) -> FsResult<FileStat> {
     let vfs = VFS.lock();
     let node = vfs.lookup_path(path)?;
@@ -227,10 +294,9 @@ pub fn readlink(path: &str) -> FsResult<String> {
     vfs.read_symlink(path)
 }
 
-pub fn access(path: &str, mode: i32) -> FsResult<()> {
+pub fn access(path: &str, uid: u32, gid: u32, mode: AccessMode) -> FsResult<()> {
     let vfs = VFS.lock();
-    let _node = vfs.lookup_path(path)?;
-    Ok(())
+    vfs.access(path, uid, gid, mode)
 }
 
 pub fn truncate(path: &str, length: u64) -> FsResult<()> {
@@ -238,6 +304,14 @@ pub fn truncate(path: &str, length: u64) -> FsResult<()> {
     vfs.truncate(path, length)
 }
 
+pub fn fallocate(fd: &FileDescriptor, mode: FallocateFlags, offset: u64, len: u64) -> FsResult<()> {
+    if !fd.flags.can_write() {
+        return Err(FsError::PermissionDenied);
+    }
+    let mut vfs = VFS.lock();
+    vfs.fallocate(fd.inode, mode, offset, len)
+}
+
 pub fn sync() -> FsResult<()> {
     let mut vfs = VFS.lock();
     vfs.sync()
@@ -1,6 +1,9 @@
 pub mod node;
 pub mod api;
 pub mod vfs;
+pub mod snapshot;
+pub mod sparse;
+pub mod path;
 
 pub use node::*;
 pub use api::*;
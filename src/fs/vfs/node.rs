@@ -3,7 +3,9 @@ use alloc::vec::Vec;
 use alloc::sync::Arc;
 use spin::RwLock;
 use crate::fs::{FileMode, FileStat, FileType, FsResult, FsError};
-use crate::hal::drivers::{tty, serial};
+use crate::hal::drivers::{audio, device, hotplug, input, mem, tty, serial};
+use super::api::{AccessMode, FallocateFlags};
+use super::sparse::FileData;
 
 pub type InodeNumber = u64;
 
@@ -14,7 +16,7 @@ pub struct DeviceId {
 }
 
 impl DeviceId {
-    pub fn new(major: u16, minor: u16) -> Self {
+    pub const fn new(major: u16, minor: u16) -> Self {
         DeviceId { major, minor }
     }
     
@@ -37,17 +39,24 @@ pub struct VfsNode {
     pub nlink: u64,
     pub device: Option<DeviceId>,
     pub data: VfsNodeData,
+    /// For a directory, match names in it without regard to case, as on a
+    /// casefold mount; directories made in it inherit this
+    pub casefold: bool,
 }
 
 #[derive(Clone)]
 pub enum VfsNodeData {
-    Regular(Vec<u8>),
+    Regular(FileData),
     Directory(Vec<DirEntry>),
     Symlink(String),
     Device(DeviceId),
     Fifo,
     Socket,
-    Mounted(Arc<RwLock<dyn Filesystem + Send + Sync>>),
+    /// A file of a grafted filesystem, by its inode number there
+    Mounted(Arc<RwLock<dyn Filesystem + Send + Sync>>, InodeNumber),
+    /// A file of an overlay's lower layer, read through until the VFS
+    /// copies it up
+    Lower(Arc<RwLock<dyn Filesystem + Send + Sync>>, InodeNumber),
 }
 
 #[derive(Clone, Debug)]
@@ -77,7 +86,8 @@ impl VfsNode {
             ctime: 0,
             nlink: 1,
             device: None,
-            data: VfsNodeData::Regular(Vec::new()),
+            data: VfsNodeData::Regular(FileData::new()),
+            casefold: false,
         }
     }
     
@@ -98,6 +108,7 @@ impl VfsNode {
             nlink: 2,
             device: None,
             data: VfsNodeData::Directory(entries),
+            casefold: false,
         }
     }
     
@@ -116,6 +127,7 @@ impl VfsNode {
             nlink: 1,
             device: None,
             data: VfsNodeData::Symlink(target),
+            casefold: false,
         }
     }
     
@@ -133,6 +145,7 @@ impl VfsNode {
             nlink: 1,
             device: Some(device),
             data: VfsNodeData::Device(device),
+            casefold: false,
         }
     }
     
@@ -150,6 +163,7 @@ impl VfsNode {
             nlink: 1,
             device: Some(device),
             data: VfsNodeData::Device(device),
+            casefold: false,
         }
     }
     
@@ -169,6 +183,20 @@ impl VfsNode {
         self.mode.is_symlink()
     }
     
+    /// Whether `uid` and `gid` may access the node as `mode` says, by its
+    /// permission bits: the owner's if `uid` owns it, else the group's if
+    /// `gid` is its group, else everyone's. Root may read and write
+    /// anything, and execute anything some execute bit is set on.
+    pub fn permits(&self, uid: u32, gid: u32, mode: AccessMode) -> bool {
+        if uid == 0 {
+            return !mode.contains(AccessMode::X_OK) || self.is_dir() || self.mode.0 & 0o111 != 0;
+        }
+        let (owner, group) = (uid == self.uid, gid == self.gid);
+        (!mode.contains(AccessMode::R_OK) || self.mode.can_read(owner, group))
+            && (!mode.contains(AccessMode::W_OK) || self.mode.can_write(owner, group))
+            && (!mode.contains(AccessMode::X_OK) || self.mode.can_execute(owner, group))
+    }
+    
     pub fn stat(&self) -> FileStat {
         FileStat {
             dev: 0,
@@ -180,7 +208,10 @@ impl VfsNode {
             rdev: self.device.map_or(0, |d| d.to_u64()),
             size: self.size,
             blksize: 4096,
-            blocks: (self.size + 511) / 512,
+            blocks: match &self.data {
+                VfsNodeData::Regular(data) => data.allocated() / 512,
+                _ => self.size.div_ceil(512),
+            },
             atime: self.atime,
             mtime: self.mtime,
             ctime: self.ctime,
@@ -189,16 +220,7 @@ impl VfsNode {
     
     pub fn read(&self, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
         match &self.data {
-            VfsNodeData::Regular(data) => {
-                if offset >= data.len() as u64 {
-                    return Ok(0);
-                }
-                let start = offset as usize;
-                let end = core::cmp::min(start + buf.len(), data.len());
-                let len = end - start;
-                buf[..len].copy_from_slice(&data[start..end]);
-                Ok(len)
-            }
+            VfsNodeData::Regular(data) => Ok(data.read(offset, buf)),
             VfsNodeData::Symlink(target) => {
                 if offset >= target.len() as u64 {
                     return Ok(0);
@@ -209,6 +231,15 @@ impl VfsNode {
                 buf[..len].copy_from_slice(&target.as_bytes()[start..end]);
                 Ok(len)
             }
+            VfsNodeData::Device(dev) if dev.major == hotplug::MAJOR => Ok(hotplug::read(offset, buf)),
+            VfsNodeData::Device(dev) if dev.major == input::MAJOR => input::read(buf),
+            VfsNodeData::Mounted(fs, inode) | VfsNodeData::Lower(fs, inode) => fs.read().read(*inode, offset, buf),
+            VfsNodeData::Directory(_) => Err(FsError::IsDirectory),
+            // What a serial port has received, without waiting for more
+            VfsNodeData::Device(dev) => match serial::port_of(*dev) {
+                Some(port) => Ok(serial::read(port, buf)),
+                None => Err(FsError::InvalidArgument),
+            },
             _ => Err(FsError::InvalidArgument),
         }
     }
@@ -216,37 +247,33 @@ impl VfsNode {
     pub fn write(&mut self, offset: u64, buf: &[u8]) -> FsResult<usize> {
         match &mut self.data {
             VfsNodeData::Regular(data) => {
-                let offset = offset as usize;
-                if offset + buf.len() > data.len() {
-                    data.resize(offset + buf.len(), 0);
-                }
-                data[offset..offset + buf.len()].copy_from_slice(buf);
-                self.size = data.len() as u64;
+                data.write(offset, buf);
+                self.size = data.len();
                 Ok(buf.len())
             }
+            VfsNodeData::Mounted(fs, inode) => {
+                let written = fs.write().write(*inode, offset, buf)?;
+                self.size = self.size.max(offset + written as u64);
+                Ok(written)
+            }
+            VfsNodeData::Lower(..) => Err(FsError::ReadOnly),
+            VfsNodeData::Directory(_) => Err(FsError::IsDirectory),
+            VfsNodeData::Device(dev) if dev.major == audio::MAJOR => audio::write(buf).map_err(|_| FsError::IoError),
+            // Can only be mapped
+            VfsNodeData::Device(dev) if dev.major == mem::MAJOR => Err(FsError::NotSupported),
+            // Can only be read
+            VfsNodeData::Device(dev) if dev.major == input::MAJOR => Err(FsError::NotSupported),
             VfsNodeData::Device(dev) => {
-                // Simple device dispatch: major 1 -> TTY, otherwise send to serial
+                // Simple device dispatch: major 1 -> TTY, a virtual terminal to
+                // its own, a serial port to its own, otherwise send to COM1
                 if dev.major == 1 {
-                    // Write bytes as UTF-8 string when possible
-                    if let Ok(s) = core::str::from_utf8(buf) {
-                        tty::write_to_tty(tty::get_current_tty(), s);
-                    } else {
-                        for &b in buf {
-                            // best-effort single-byte to string
-                            let b_arr = [b];
-                            let s = core::str::from_utf8(&b_arr).unwrap_or(" ");
-                            tty::write_to_tty(tty::get_current_tty(), s);
-                        }
-                    }
+                    tty::write_bytes_to_tty(tty::get_current_tty(), buf);
+                    Ok(buf.len())
+                } else if let Some(vt) = tty::vt_of(*dev) {
+                    tty::write_bytes_to_tty(vt, buf);
                     Ok(buf.len())
                 } else {
-                    if let Ok(s) = core::str::from_utf8(buf) {
-                        serial::write_string(s);
-                    } else {
-                        for &b in buf {
-                            serial::write_byte(b);
-                        }
-                    }
+                    serial::write(serial::port_of(*dev).unwrap_or(0), buf);
                     Ok(buf.len())
                 }
             }
@@ -254,10 +281,24 @@ impl VfsNode {
         }
     }
     
+    /// The physical address of `len` bytes at `offset`, for mmap. Only
+    /// device nodes whose driver lets them be mapped have one.
+    pub fn mmap(&self, offset: u64, len: u64) -> FsResult<u64> {
+        match &self.data {
+            VfsNodeData::Device(dev) => device::map(*dev, offset, len),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+    
     pub fn truncate(&mut self, size: u64) -> FsResult<()> {
         match &mut self.data {
             VfsNodeData::Regular(data) => {
-                data.resize(size as usize, 0);
+                data.resize(size);
+                self.size = size;
+                Ok(())
+            }
+            VfsNodeData::Mounted(fs, inode) => {
+                fs.write().truncate(*inode, size)?;
                 self.size = size;
                 Ok(())
             }
@@ -265,10 +306,18 @@ impl VfsNode {
         }
     }
     
+    /// Where the entry `name` is among `entries`. Casefolded, a name that
+    /// matches exactly still comes first.
+    fn position(entries: &[DirEntry], name: &str, casefold: bool) -> Option<usize> {
+        entries.iter().position(|e| e.name == name).or_else(|| {
+            casefold.then(|| entries.iter().position(|e| fold_eq(&e.name, name))).flatten()
+        })
+    }
+    
     pub fn add_entry(&mut self, entry: DirEntry) -> FsResult<()> {
         match &mut self.data {
             VfsNodeData::Directory(entries) => {
-                if entries.iter().any(|e| e.name == entry.name) {
+                if Self::position(entries, &entry.name, self.casefold).is_some() {
                     return Err(FsError::AlreadyExists);
                 }
                 entries.push(entry);
@@ -281,7 +330,7 @@ impl VfsNode {
     pub fn remove_entry(&mut self, name: &str) -> FsResult<DirEntry> {
         match &mut self.data {
             VfsNodeData::Directory(entries) => {
-                if let Some(pos) = entries.iter().position(|e| e.name == name) {
+                if let Some(pos) = Self::position(entries, name, self.casefold) {
                     Ok(entries.remove(pos))
                 } else {
                     Err(FsError::NotFound)
@@ -294,8 +343,8 @@ impl VfsNode {
     pub fn lookup(&self, name: &str) -> FsResult<&DirEntry> {
         match &self.data {
             VfsNodeData::Directory(entries) => {
-                entries.iter()
-                    .find(|e| e.name == name)
+                Self::position(entries, name, self.casefold)
+                    .map(|pos| &entries[pos])
                     .ok_or(FsError::NotFound)
             }
             _ => Err(FsError::NotDirectory),
@@ -310,6 +359,11 @@ impl VfsNode {
     }
 }
 
+/// Whether two names are the same but for case
+pub fn fold_eq(a: &str, b: &str) -> bool {
+    a.chars().flat_map(char::to_lowercase).eq(b.chars().flat_map(char::to_lowercase))
+}
+
 pub trait Filesystem {
     fn name(&self) -> &str;
     fn root(&self) -> FsResult<VfsNode>;
@@ -324,4 +378,28 @@ pub trait Filesystem {
     fn stat(&self, inode: InodeNumber) -> FsResult<FileStat>;
     fn readdir(&self, inode: InodeNumber) -> FsResult<Vec<DirEntry>>;
     fn sync(&mut self) -> FsResult<()>;
+    
+    /// Whether the filesystem itself matches names without regard to case,
+    /// as FAT does; mounting it casefolds the mount point
+    fn casefold(&self) -> bool {
+        false
+    }
+    
+    /// Preallocate or punch a hole in a file, as fallocate(2); not
+    /// supported unless the filesystem says otherwise
+    fn fallocate(&mut self, _inode: InodeNumber, _mode: FallocateFlags, _offset: u64, _len: u64) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Make a symlink to `target`; not supported unless the filesystem
+    /// says otherwise
+    fn symlink(&mut self, _parent: InodeNumber, _name: &str, _target: &str) -> FsResult<VfsNode> {
+        Err(FsError::NotSupported)
+    }
+
+    /// Cut a file short or extend it with zeros to `size` bytes; not
+    /// supported unless the filesystem says otherwise
+    fn truncate(&mut self, _inode: InodeNumber, _size: u64) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
 }
@@ -0,0 +1,46 @@
+// Path validation
+//
+// The limits every path is held to, checked in one place. Syscalls pass
+// the bytes they copy from the caller through `from_bytes`, and the VFS
+// checks the paths it creates and looks up against the same limits, so a
+// name too long for one is too long for all: ENAMETOOLONG for a path of
+// PATH_MAX bytes or more (it has to fit with its NUL) or a component over
+// NAME_MAX, EINVAL for a NUL inside a path or one that is not UTF-8.
+
+use crate::fs::{FsError, FsResult};
+
+/// Bytes in a path, its terminating NUL included
+pub const PATH_MAX: usize = 4096;
+
+/// Bytes in one component of a path
+pub const NAME_MAX: usize = 255;
+
+/// Check a path copied from a caller, without its NUL, and give it back
+/// as a string. An empty path names nothing.
+pub fn from_bytes(bytes: &[u8]) -> FsResult<&str> {
+    let path = core::str::from_utf8(bytes).map_err(|_| FsError::InvalidArgument)?;
+    if path.is_empty() {
+        return Err(FsError::NotFound);
+    }
+    validate(path)?;
+    Ok(path)
+}
+
+/// Check a path against PATH_MAX and NAME_MAX, and for a NUL inside it
+pub fn validate(path: &str) -> FsResult<()> {
+    if path.len() >= PATH_MAX {
+        return Err(FsError::NameTooLong);
+    }
+    if path.contains('\0') {
+        return Err(FsError::InvalidArgument);
+    }
+    path.split('/').try_for_each(validate_name)
+}
+
+/// Check one component of a path against NAME_MAX
+pub fn validate_name(name: &str) -> FsResult<()> {
+    match name.len() > NAME_MAX {
+        true => Err(FsError::NameTooLong),
+        false => Ok(()),
+    }
+}
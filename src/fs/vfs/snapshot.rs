@@ -0,0 +1,162 @@
+// Copy-on-write node table and VFS snapshots
+//
+// The VFS keeps its nodes behind Arcs in a NodeTable. A snapshot clones
+// the Arcs, not the nodes, and a node still shared with a snapshot is
+// copied the first time it is changed. While any snapshot is held, the
+// table logs each node it hands out for changing, once per snapshot, so
+// restoring a snapshot puts back only what changed after it: the cost is
+// in the nodes changed, not in the size of the tree.
+//
+// Snapshots nest. Restoring one forgets those taken after it, and the
+// log is dropped once no snapshot is left. Named snapshots for the shell
+// (vfs-snapshot, vfs-restore) are kept here too; the kernel test runner
+// takes one of its own and restores it after every test.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::fs::{FsError, FsResult};
+use super::node::{InodeNumber, VfsNode};
+use super::vfs::{Snapshot, VFS};
+
+pub struct NodeTable {
+    nodes: BTreeMap<InodeNumber, Arc<VfsNode>>,
+    /// Nodes changed since the oldest snapshot held, in order
+    log: Vec<InodeNumber>,
+    /// Those logged since the newest one
+    logged: BTreeSet<InodeNumber>,
+    /// Ids of the snapshots held, oldest first
+    live: Vec<u64>,
+    next_id: u64,
+}
+
+/// The nodes as they were when a snapshot was taken
+#[derive(Clone)]
+pub struct NodeSnapshot {
+    id: u64,
+    /// Length of the log then
+    mark: usize,
+    nodes: BTreeMap<InodeNumber, Arc<VfsNode>>,
+}
+
+impl NodeTable {
+    pub fn new() -> Self {
+        NodeTable {
+            nodes: BTreeMap::new(),
+            log: Vec::new(),
+            logged: BTreeSet::new(),
+            live: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    fn touch(&mut self, inode: InodeNumber) {
+        if !self.live.is_empty() && self.logged.insert(inode) {
+            self.log.push(inode);
+        }
+    }
+
+    pub fn get(&self, inode: &InodeNumber) -> Option<&VfsNode> {
+        self.nodes.get(inode).map(|node| &**node)
+    }
+
+    /// The node to change, copied first if a snapshot shares it
+    pub fn get_mut(&mut self, inode: &InodeNumber) -> Option<&mut VfsNode> {
+        if !self.nodes.contains_key(inode) {
+            return None;
+        }
+        self.touch(*inode);
+        self.nodes.get_mut(inode).map(Arc::make_mut)
+    }
+
+    pub fn insert(&mut self, inode: InodeNumber, node: VfsNode) {
+        self.touch(inode);
+        self.nodes.insert(inode, Arc::new(node));
+    }
+
+    pub fn remove(&mut self, inode: &InodeNumber) {
+        self.touch(*inode);
+        self.nodes.remove(inode);
+    }
+
+    pub fn snapshot(&mut self) -> NodeSnapshot {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.live.push(id);
+        self.logged.clear();
+        NodeSnapshot { id, mark: self.log.len(), nodes: self.nodes.clone() }
+    }
+
+    /// Whether `snapshot` can still be restored
+    pub fn holds(&self, snapshot: &NodeSnapshot) -> bool {
+        self.live.contains(&snapshot.id)
+    }
+
+    /// Put back the nodes changed since `snapshot`, returning their
+    /// inodes. Snapshots taken after it are forgotten.
+    pub fn restore(&mut self, snapshot: &NodeSnapshot) -> FsResult<BTreeSet<InodeNumber>> {
+        let at = self.live.iter().position(|&id| id == snapshot.id).ok_or(FsError::InvalidArgument)?;
+        self.live.truncate(at + 1);
+        let changed: BTreeSet<InodeNumber> = self.log.drain(snapshot.mark..).collect();
+        for &inode in &changed {
+            match snapshot.nodes.get(&inode) {
+                Some(node) => self.nodes.insert(inode, node.clone()),
+                None => self.nodes.remove(&inode),
+            };
+        }
+        self.logged.clear();
+        Ok(changed)
+    }
+
+    /// Stop holding `snapshot`
+    pub fn release(&mut self, snapshot: &NodeSnapshot) {
+        self.live.retain(|&id| id != snapshot.id);
+        if self.live.is_empty() {
+            self.log.clear();
+            self.logged.clear();
+        }
+    }
+}
+
+impl Default for NodeTable {
+    fn default() -> Self {
+        NodeTable::new()
+    }
+}
+
+/// Snapshots taken from the shell, by name
+static NAMED: Mutex<BTreeMap<String, Snapshot>> = Mutex::new(BTreeMap::new());
+
+/// Snapshot the VFS as `name`, replacing a snapshot of that name
+pub fn take(name: &str) {
+    let mut named = NAMED.lock();
+    let mut vfs = VFS.lock();
+    if let Some(old) = named.remove(name) {
+        vfs.release(&old);
+    }
+    named.insert(String::from(name), vfs.snapshot());
+}
+
+/// Restore the snapshot `name`, returning how many nodes were put back;
+/// snapshots taken after it are dropped
+pub fn restore(name: &str) -> FsResult<usize> {
+    let mut named = NAMED.lock();
+    let mut vfs = VFS.lock();
+    let restored = vfs.restore(named.get(name).ok_or(FsError::NotFound)?)?;
+    named.retain(|_, snapshot| vfs.holds(snapshot));
+    Ok(restored)
+}
+
+/// Let go of the snapshot `name`
+pub fn discard(name: &str) -> FsResult<()> {
+    let snapshot = NAMED.lock().remove(name).ok_or(FsError::NotFound)?;
+    VFS.lock().release(&snapshot);
+    Ok(())
+}
+
+/// Names of the snapshots held
+pub fn names() -> Vec<String> {
+    NAMED.lock().keys().cloned().collect()
+}
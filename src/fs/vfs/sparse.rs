@@ -0,0 +1,172 @@
+// Sparse file data
+//
+// The contents of an in-memory file, kept as the 4 KiB pages that hold
+// something. A page that was never written, or whose range was punched
+// out, is a hole: it reads as zeros and takes no memory. Pages are
+// allocated as writes reach them or when fallocate() asks for them, so
+// `allocated()` (what stat reports as blocks) can be less than the size,
+// for a file with holes, or more, for one preallocated past its end.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+pub const PAGE_SIZE: usize = 4096;
+
+type Page = Box<[u8; PAGE_SIZE]>;
+
+#[derive(Clone, Default)]
+pub struct FileData {
+    pages: BTreeMap<u64, Page>,
+    len: u64,
+}
+
+fn zeroed() -> Page {
+    Box::new([0; PAGE_SIZE])
+}
+
+impl FileData {
+    pub fn new() -> Self {
+        FileData::default()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes of memory holding data, a whole number of pages
+    pub fn allocated(&self) -> u64 {
+        (self.pages.len() * PAGE_SIZE) as u64
+    }
+
+    /// Read at `offset`, up to the end of the file; holes read as zeros
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> usize {
+        if offset >= self.len {
+            return 0;
+        }
+        let len = buf.len().min((self.len - offset) as usize);
+        let mut done = 0;
+        while done < len {
+            let at = offset + done as u64;
+            let (index, start) = (at / PAGE_SIZE as u64, at as usize % PAGE_SIZE);
+            let n = (PAGE_SIZE - start).min(len - done);
+            match self.pages.get(&index) {
+                Some(page) => buf[done..done + n].copy_from_slice(&page[start..start + n]),
+                None => buf[done..done + n].fill(0),
+            }
+            done += n;
+        }
+        len
+    }
+
+    /// Write `buf` at `offset`, growing the file if it ends past it
+    pub fn write(&mut self, offset: u64, buf: &[u8]) {
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done as u64;
+            let (index, start) = (at / PAGE_SIZE as u64, at as usize % PAGE_SIZE);
+            let n = (PAGE_SIZE - start).min(buf.len() - done);
+            let page = self.pages.entry(index).or_insert_with(zeroed);
+            page[start..start + n].copy_from_slice(&buf[done..done + n]);
+            done += n;
+        }
+        self.len = self.len.max(offset + buf.len() as u64);
+    }
+
+    /// Shrink or extend the file to `len` bytes. Shrinking frees what is
+    /// cut off, preallocated pages past the end included; extending
+    /// leaves a hole.
+    pub fn resize(&mut self, len: u64) {
+        if len < self.len {
+            self.zero(len, u64::MAX - len);
+        }
+        self.len = len;
+    }
+
+    /// Allocate the pages of `len` bytes at `offset`, zeroed where they
+    /// were holes, without changing the size
+    pub fn allocate(&mut self, offset: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let (first, last) = (offset / PAGE_SIZE as u64, (offset + len - 1) / PAGE_SIZE as u64);
+        for index in first..=last {
+            self.pages.entry(index).or_insert_with(zeroed);
+        }
+    }
+
+    /// Bytes of memory writing or allocating `len` bytes at `offset`
+    /// would add: the pages in that range that are holes now
+    pub fn unallocated(&self, offset: u64, len: u64) -> u64 {
+        if len == 0 {
+            return 0;
+        }
+        let (first, last) = (offset / PAGE_SIZE as u64, offset.saturating_add(len - 1) / PAGE_SIZE as u64);
+        let present = self.pages.range(first..=last).count() as u64;
+        (last - first + 1 - present).saturating_mul(PAGE_SIZE as u64)
+    }
+
+    /// Make a hole of `len` bytes at `offset`: they read as zeros from now
+    /// on, and pages wholly inside are freed. The size stays.
+    pub fn punch_hole(&mut self, offset: u64, len: u64) {
+        self.zero(offset, len);
+    }
+
+    /// Zero a range, dropping the pages it covers whole
+    fn zero(&mut self, offset: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+        let page = PAGE_SIZE as u64;
+        let end = offset.saturating_add(len);
+        let (first, last) = (offset / page, (end - 1) / page);
+        let indices: Vec<u64> = self.pages.range(first..=last).map(|(&index, _)| index).collect();
+        for index in indices {
+            let (start, stop) = (index * page, (index * page).saturating_add(page));
+            if offset <= start && end >= stop {
+                self.pages.remove(&index);
+            } else if let Some(data) = self.pages.get_mut(&index) {
+                let from = offset.max(start) - start;
+                let to = end.min(stop) - start;
+                data[from as usize..to as usize].fill(0);
+            }
+        }
+    }
+
+    /// The whole contents, holes as zeros
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut data = alloc::vec![0; self.len as usize];
+        self.read(0, &mut data);
+        data
+    }
+}
+
+impl From<Vec<u8>> for FileData {
+    fn from(data: Vec<u8>) -> Self {
+        let mut file = FileData::new();
+        file.write(0, &data);
+        file
+    }
+}
+
+impl PartialEq<[u8]> for FileData {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.len == other.len() as u64 && self.to_vec() == other
+    }
+}
+
+impl<const N: usize> PartialEq<[u8; N]> for FileData {
+    fn eq(&self, other: &[u8; N]) -> bool {
+        *self == other[..]
+    }
+}
+
+impl core::fmt::Debug for FileData {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("FileData").field("len", &self.len).field("pages", &self.pages.len()).finish()
+    }
+}
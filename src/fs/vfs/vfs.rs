@@ -1,28 +1,82 @@
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use alloc::format;
-use alloc::collections::BTreeMap;
-use spin::Mutex;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::RwLock;
+use crate::hal::lockdep::{Class, Mutex};
 use lazy_static::lazy_static;
 use crate::fs::{FileMode, FileStat, FileType, FsResult, FsError};
-use super::node::{VfsNode, VfsNodeData, DirEntry, InodeNumber};
+use crate::fs::overlay::{Change, Overlay};
+use crate::fs::quota::Quotas;
+use super::node::{fold_eq, VfsNode, VfsNodeData, DirEntry, Filesystem, InodeNumber};
+use super::snapshot::{NodeSnapshot, NodeTable};
+use super::api::{AccessMode, FallocateFlags};
+use super::path;
+
+/// Symlinks followed while resolving one path before giving up (ELOOP).
+pub const MAX_SYMLINK_FOLLOWS: usize = 40;
 
 lazy_static! {
-    pub static ref VFS: Mutex<VirtualFileSystem> = Mutex::new(VirtualFileSystem::new());
+    pub static ref VFS: Mutex<VirtualFileSystem> = Mutex::new(Class::Vfs, VirtualFileSystem::new());
+}
+
+/// The uid and gid that own nodes created from now on: the running task's
+/// effective ids, set by the scheduler as it switches tasks
+static CREDENTIALS: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_credentials(uid: u32, gid: u32) {
+    CREDENTIALS.store((uid as u64) << 32 | gid as u64, Ordering::Relaxed);
+}
+
+pub fn credentials() -> (u32, u32) {
+    let ids = CREDENTIALS.load(Ordering::Relaxed);
+    ((ids >> 32) as u32, ids as u32)
 }
 
 pub struct VirtualFileSystem {
-    nodes: BTreeMap<InodeNumber, VfsNode>,
+    nodes: NodeTable,
     next_inode: InodeNumber,
+    /// Working directory, as seen from `root`
     cwd: String,
+    /// Directory absolute paths start from (chroot); always normalized
+    root: String,
+    quotas: Quotas,
+    /// Overlays, by the inode of their merged directory
+    overlays: BTreeMap<InodeNumber, Overlay>,
+    /// How many open files hold each node; see `hold`
+    held: BTreeMap<InodeNumber, usize>,
+    /// Held nodes removed from the tree, dropped on the last `unhold`
+    orphans: BTreeSet<InodeNumber>,
+    /// Directories mirroring one of a grafted filesystem, with the
+    /// filesystem and its inode number there: what is made, removed or
+    /// renamed in them is made, removed or renamed there first
+    remote_dirs: BTreeMap<InodeNumber, Remote>,
+    /// What graft() and overlay() hid of a directory: its entries, link
+    /// count and casefolding, back in place on ungraft()
+    shadowed: BTreeMap<InodeNumber, Shadowed>,
 }
 
+/// The entries, link count and casefolding a directory had
+type Shadowed = (Vec<DirEntry>, u64, bool);
+
+/// A grafted filesystem and an inode number in it
+type Remote = (Arc<RwLock<dyn Filesystem + Send + Sync>>, InodeNumber);
+
 impl VirtualFileSystem {
     pub fn new() -> Self {
         let mut vfs = VirtualFileSystem {
-            nodes: BTreeMap::new(),
+            nodes: NodeTable::new(),
             next_inode: 2,
             cwd: String::from("/"),
+            root: String::from("/"),
+            quotas: Quotas::new(),
+            overlays: BTreeMap::new(),
+            held: BTreeMap::new(),
+            orphans: BTreeSet::new(),
+            remote_dirs: BTreeMap::new(),
+            shadowed: BTreeMap::new(),
         };
         
         let root = VfsNode::new_directory("/".into(), 1, 0o755);
@@ -37,7 +91,159 @@ impl VirtualFileSystem {
         inode
     }
     
-    pub fn resolve_path(&self, path: &str) -> String {
+    /// Enter the new `node` in the directory `parent`, owned by the
+    /// current credentials and charged to their quotas
+    fn insert_node(&mut self, parent: InodeNumber, mut node: VfsNode) -> FsResult<VfsNode> {
+        let (uid, gid) = credentials();
+        (node.uid, node.gid) = (uid, gid);
+        self.quotas.create(node.inode, uid, gid, node.size)?;
+        let entry = DirEntry::new(node.name.clone(), node.inode, node.file_type());
+        let added = match self.nodes.get_mut(&parent) {
+            Some(parent) => {
+                node.casefold = parent.casefold && node.is_dir();
+                parent.add_entry(entry)
+            }
+            None => Err(FsError::NotFound),
+        };
+        if let Err(e) = added {
+            self.quotas.release(node.inode);
+            return Err(e);
+        }
+        self.nodes.insert(node.inode, node.clone());
+        Ok(node)
+    }
+    
+    /// Enter the new `node` in the directory `parent` as insert_node()
+    /// does, having `make` make it first in the filesystem grafted there,
+    /// if any: a file made there reads and writes through to it, and a
+    /// directory is one more to make things in there
+    fn insert_made(
+        &mut self,
+        parent: InodeNumber,
+        mut node: VfsNode,
+        make: impl FnOnce(&mut (dyn Filesystem + Send + Sync), InodeNumber, &str) -> FsResult<VfsNode>,
+    ) -> FsResult<VfsNode> {
+        let Some((fs, remote)) = self.remote_dirs.get(&parent).cloned() else {
+            return self.insert_node(parent, node);
+        };
+        let (name, is_dir) = (node.name.clone(), node.is_dir());
+        let made = make(&mut *fs.write(), remote, &name)?.inode;
+        if node.is_file() {
+            node.data = VfsNodeData::Mounted(fs.clone(), made);
+        }
+        let inserted = self.insert_node(parent, node);
+        match &inserted {
+            Ok(node) if is_dir => {
+                self.remote_dirs.insert(node.inode, (fs, made));
+            }
+            Ok(_) => {}
+            Err(_) if is_dir => {
+                fs.write().rmdir(remote, &name).ok();
+            }
+            Err(_) => {
+                fs.write().unlink(remote, &name).ok();
+            }
+        }
+        inserted
+    }
+    
+    /// Remove the node `inode`, giving back what it was charged. A node an
+    /// open file holds is only taken out of the tree: it stays readable
+    /// and writable through that file until the last `unhold`.
+    fn drop_node(&mut self, inode: InodeNumber) {
+        if self.held.contains_key(&inode) {
+            if let Some(node) = self.nodes.get_mut(&inode) {
+                node.nlink = 0;
+            }
+            self.orphans.insert(inode);
+            return;
+        }
+        self.nodes.remove(&inode);
+        self.quotas.release(inode);
+        self.remote_dirs.remove(&inode);
+        for overlay in self.overlays.values_mut() {
+            overlay.forget(inode);
+        }
+    }
+    
+    /// Keep the node `inode` for an open file, whatever happens to its
+    /// name, until the matching `unhold`
+    pub fn hold(&mut self, inode: InodeNumber) {
+        *self.held.entry(inode).or_insert(0) += 1;
+    }
+    
+    /// Let go of a node `hold` kept, dropping it if it was removed from
+    /// the tree meanwhile and nothing else holds it
+    pub fn unhold(&mut self, inode: InodeNumber) {
+        let Some(count) = self.held.get_mut(&inode) else { return };
+        *count -= 1;
+        if *count == 0 {
+            self.held.remove(&inode);
+            if self.orphans.remove(&inode) {
+                self.drop_node(inode);
+            }
+        }
+    }
+    
+    /// Snapshot the tree, its quota charges and overlays, to restore
+    /// later; see vfs::snapshot. Give it back with `release`. What a
+    /// grafted filesystem holds is not part of it.
+    pub fn snapshot(&mut self) -> Snapshot {
+        Snapshot {
+            nodes: self.nodes.snapshot(),
+            next_inode: self.next_inode,
+            quotas: self.quotas.clone(),
+            overlays: self.overlays.clone(),
+            orphans: self.orphans.clone(),
+            remote_dirs: self.remote_dirs.clone(),
+        }
+    }
+    
+    /// Go back to `snapshot`, returning how many nodes changed since it.
+    /// The working directory and root stay as they are.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> FsResult<usize> {
+        let changed = self.nodes.restore(&snapshot.nodes)?;
+        for &inode in &changed {
+            self.quotas.restore(inode, &snapshot.quotas);
+        }
+        self.next_inode = snapshot.next_inode;
+        self.overlays = snapshot.overlays.clone();
+        self.remote_dirs = snapshot.remote_dirs.clone();
+        // Orphans the snapshot had and nothing holds any more are dropped
+        self.orphans = snapshot.orphans.clone();
+        let unheld: Vec<InodeNumber> = self.orphans.iter()
+            .filter(|inode| !self.held.contains_key(inode))
+            .copied()
+            .collect();
+        for inode in unheld {
+            self.orphans.remove(&inode);
+            self.drop_node(inode);
+        }
+        Ok(changed.len())
+    }
+    
+    /// Whether `snapshot` can still be restored: restoring an older one
+    /// drops it
+    pub fn holds(&self, snapshot: &Snapshot) -> bool {
+        self.nodes.holds(&snapshot.nodes)
+    }
+    
+    pub fn release(&mut self, snapshot: &Snapshot) {
+        self.nodes.release(&snapshot.nodes);
+    }
+    
+    pub fn quotas(&self) -> &Quotas {
+        &self.quotas
+    }
+    
+    pub fn quotas_mut(&mut self) -> &mut Quotas {
+        &mut self.quotas
+    }
+    
+    /// `path` made absolute and normalized as the caller sees it: inside
+    /// the root, which `..` cannot climb out of. Other VFS calls accept it
+    /// like any path.
+    pub fn absolute_path(&self, path: &str) -> String {
         if path.starts_with('/') {
             normalize_path(path)
         } else {
@@ -46,9 +252,30 @@ impl VirtualFileSystem {
         }
     }
     
+    /// A path inside the root as a path from the real root
+    fn in_root(&self, view: &str) -> String {
+        if self.root == "/" {
+            view.to_string()
+        } else if view == "/" {
+            self.root.clone()
+        } else {
+            format!("{}{}", self.root, view)
+        }
+    }
+    
+    /// `path` as an absolute path from the real root, the form kept in fd
+    /// tables and passed to `lookup_resolved`
+    pub fn resolve_path(&self, path: &str) -> String {
+        self.in_root(&self.absolute_path(path))
+    }
+    
     pub fn lookup_path(&self, path: &str) -> FsResult<&VfsNode> {
-        let path = self.resolve_path(path);
-        
+        self.lookup_resolved(&self.resolve_path(path))
+    }
+    
+    /// Look up a path already returned by `resolve_path`, which is not put
+    /// under the root again
+    pub fn lookup_resolved(&self, path: &str) -> FsResult<&VfsNode> {
         if path == "/" {
             return self.nodes.get(&1).ok_or(FsError::NotFound);
         }
@@ -56,6 +283,7 @@ impl VirtualFileSystem {
         let mut current_inode: InodeNumber = 1;
         
         for component in path.split('/').filter(|s| !s.is_empty()) {
+            path::validate_name(component)?;
             let current = self.nodes.get(&current_inode).ok_or(FsError::NotFound)?;
             
             if !current.is_dir() {
@@ -69,6 +297,34 @@ impl VirtualFileSystem {
         self.nodes.get(&current_inode).ok_or(FsError::NotFound)
     }
     
+    /// Whether `uid` and `gid` may access `path` as `mode` says, as
+    /// access(2): every directory on the way has to let them search it,
+    /// and the file itself grant `mode` (see VfsNode::permits). Fails with
+    /// PermissionDenied where one does not.
+    pub fn access(&self, path: &str, uid: u32, gid: u32, mode: AccessMode) -> FsResult<()> {
+        let path = self.resolve_path(path);
+        let mut current_inode: InodeNumber = 1;
+        
+        for component in path.split('/').filter(|s| !s.is_empty()) {
+            path::validate_name(component)?;
+            let current = self.nodes.get(&current_inode).ok_or(FsError::NotFound)?;
+            
+            if !current.is_dir() {
+                return Err(FsError::NotDirectory);
+            }
+            if !current.permits(uid, gid, AccessMode::X_OK) {
+                return Err(FsError::PermissionDenied);
+            }
+            
+            current_inode = current.lookup(component)?.inode;
+        }
+        
+        match self.get_node(current_inode)?.permits(uid, gid, mode) {
+            true => Ok(()),
+            false => Err(FsError::PermissionDenied),
+        }
+    }
+    
     pub fn lookup_path_mut(&mut self, path: &str) -> FsResult<&mut VfsNode> {
         let path = self.resolve_path(path);
         
@@ -79,6 +335,7 @@ impl VirtualFileSystem {
         let mut current_inode: InodeNumber = 1;
         
         for component in path.split('/').filter(|s| !s.is_empty()) {
+            path::validate_name(component)?;
             let current = self.nodes.get(&current_inode).ok_or(FsError::NotFound)?;
             
             if !current.is_dir() {
@@ -92,6 +349,60 @@ impl VirtualFileSystem {
         self.nodes.get_mut(&current_inode).ok_or(FsError::NotFound)
     }
     
+    /// Resolves `path` to the absolute path it really refers to: `.`/`..`
+    /// are applied after symlinks are followed, and components that do not
+    /// exist yet are kept as-is. Used by security checks so a symlink or
+    /// `..` cannot dodge a path rule.
+    pub fn canonicalize(&self, path: &str) -> FsResult<String> {
+        let full = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.cwd, path)
+        };
+        let root = self.lookup_resolved(&self.root).ok().map(|n| n.inode);
+        
+        let mut pending: Vec<String> = full.split('/').rev().map(|c| c.to_string()).collect();
+        let mut resolved: Vec<String> = Vec::new();
+        let mut current: Option<InodeNumber> = root;
+        let mut follows = 0;
+        
+        while let Some(component) = pending.pop() {
+            match component.as_str() {
+                "" | "." => continue,
+                ".." => {
+                    resolved.pop();
+                    current = self.lookup_path(&format!("/{}", resolved.join("/"))).ok().map(|n| n.inode);
+                    continue;
+                }
+                _ => {}
+            }
+            
+            let next = current
+                .and_then(|inode| self.nodes.get(&inode))
+                .filter(|node| node.is_dir())
+                .and_then(|dir| dir.lookup(&component).ok())
+                .map(|entry| entry.inode);
+            
+            if let Some(VfsNodeData::Symlink(target)) = next.and_then(|i| self.nodes.get(&i)).map(|n| &n.data) {
+                follows += 1;
+                if follows > MAX_SYMLINK_FOLLOWS {
+                    return Err(FsError::SymlinkLoop);
+                }
+                if target.starts_with('/') {
+                    resolved.clear();
+                    current = root;
+                }
+                pending.extend(target.split('/').rev().map(|c| c.to_string()));
+                continue;
+            }
+            
+            resolved.push(component);
+            current = next;
+        }
+        
+        Ok(self.in_root(&format!("/{}", resolved.join("/"))))
+    }
+    
     pub fn get_node(&self, inode: InodeNumber) -> FsResult<&VfsNode> {
         self.nodes.get(&inode).ok_or(FsError::NotFound)
     }
@@ -100,8 +411,10 @@ impl VirtualFileSystem {
         self.nodes.get_mut(&inode).ok_or(FsError::NotFound)
     }
     
+    /// Parent (as seen from the root) and final component of `path`
     fn get_parent_and_name(&self, path: &str) -> FsResult<(String, String)> {
-        let path = self.resolve_path(path);
+        path::validate(path)?;
+        let path = self.absolute_path(path);
         
         if path == "/" {
             return Err(FsError::InvalidArgument);
@@ -129,14 +442,8 @@ impl VirtualFileSystem {
         };
         
         let inode = self.alloc_inode();
-        let node = VfsNode::new_file(name.clone(), inode, mode.0 & 0o7777);
-        
-        self.nodes.insert(inode, node.clone());
-        
-        let parent = self.nodes.get_mut(&parent_inode).ok_or(FsError::NotFound)?;
-        parent.add_entry(DirEntry::new(name, inode, FileType::Regular))?;
-        
-        Ok(node)
+        let node = VfsNode::new_file(name, inode, mode.0 & 0o7777);
+        self.insert_made(parent_inode, node, |fs, parent, name| fs.create(parent, name, mode))
     }
 
     pub fn create_device(&mut self, path: &str, device: super::node::DeviceId, mode: FileMode) -> FsResult<VfsNode> {
@@ -149,16 +456,19 @@ impl VirtualFileSystem {
             }
             parent.inode
         };
+        // No filesystem grafted has device nodes
+        if self.remote_dirs.contains_key(&parent_inode) {
+            return Err(FsError::NotSupported);
+        }
 
         let inode = self.alloc_inode();
-        let node = VfsNode::new_char_device(name.clone(), inode, device, mode.0 & 0o7777);
-
-        self.nodes.insert(inode, node.clone());
-
-        let parent = self.nodes.get_mut(&parent_inode).ok_or(FsError::NotFound)?;
-        parent.add_entry(DirEntry::new(name, inode, FileType::CharDevice))?;
-
-        Ok(node)
+        // A character device unless the mode says block
+        let node = if mode.file_type() == FileType::BlockDevice {
+            VfsNode::new_block_device(name, inode, device, mode.0 & 0o7777)
+        } else {
+            VfsNode::new_char_device(name, inode, device, mode.0 & 0o7777)
+        };
+        self.insert_node(parent_inode, node)
     }
     
     pub fn create_directory(&mut self, path: &str, mode: FileMode) -> FsResult<VfsNode> {
@@ -173,16 +483,14 @@ impl VirtualFileSystem {
         };
         
         let inode = self.alloc_inode();
-        let mut node = VfsNode::new_directory(name.clone(), inode, mode.0 & 0o7777);
+        let mut node = VfsNode::new_directory(name, inode, mode.0 & 0o7777);
         
         if let VfsNodeData::Directory(ref mut entries) = node.data {
             entries.push(DirEntry::new("..".into(), parent_inode, FileType::Directory));
         }
         
-        self.nodes.insert(inode, node.clone());
-        
+        let node = self.insert_made(parent_inode, node, |fs, parent, name| fs.mkdir(parent, name, mode))?;
         let parent = self.nodes.get_mut(&parent_inode).ok_or(FsError::NotFound)?;
-        parent.add_entry(DirEntry::new(name, inode, FileType::Directory))?;
         parent.nlink += 1;
         
         Ok(node)
@@ -200,14 +508,8 @@ impl VirtualFileSystem {
         };
         
         let inode = self.alloc_inode();
-        let node = VfsNode::new_symlink(name.clone(), inode, target.to_string());
-        
-        self.nodes.insert(inode, node.clone());
-        
-        let parent = self.nodes.get_mut(&parent_inode).ok_or(FsError::NotFound)?;
-        parent.add_entry(DirEntry::new(name, inode, FileType::Symlink))?;
-        
-        Ok(node)
+        let node = VfsNode::new_symlink(name, inode, target.to_string());
+        self.insert_made(parent_inode, node, |fs, parent, name| fs.symlink(parent, name, target))
     }
     
     pub fn remove_file(&mut self, path: &str) -> FsResult<()> {
@@ -224,10 +526,12 @@ impl VirtualFileSystem {
             (parent.inode, entry.inode)
         };
         
+        self.remove_remote(parent_inode, file_inode)?;
         let parent = self.nodes.get_mut(&parent_inode).ok_or(FsError::NotFound)?;
         parent.remove_entry(&name)?;
         
-        self.nodes.remove(&file_inode);
+        self.white_out(file_inode, path);
+        self.drop_node(file_inode);
         
         Ok(())
     }
@@ -252,15 +556,21 @@ impl VirtualFileSystem {
             (parent.inode, dir.inode)
         };
         
+        self.remove_remote(parent_inode, dir_inode)?;
         let parent = self.nodes.get_mut(&parent_inode).ok_or(FsError::NotFound)?;
         parent.remove_entry(&name)?;
         parent.nlink -= 1;
         
-        self.nodes.remove(&dir_inode);
+        self.white_out(dir_inode, path);
+        self.drop_node(dir_inode);
         
         Ok(())
     }
     
+    /// Move `old_path` to `new_path`, as rename(2): an existing target is
+    /// replaced if it is of the same kind (an empty directory for a
+    /// directory), and a directory cannot move under itself. Everything is
+    /// checked before anything changes, so a failed rename changes nothing.
     pub fn rename(&mut self, old_path: &str, new_path: &str) -> FsResult<()> {
         let (old_parent_path, old_name) = self.get_parent_and_name(old_path)?;
         let (new_parent_path, new_name) = self.get_parent_and_name(new_path)?;
@@ -270,24 +580,149 @@ impl VirtualFileSystem {
             let entry = old_parent.lookup(&old_name)?;
             (old_parent.inode, entry.inode, entry.file_type)
         };
+        let is_dir = file_type == FileType::Directory;
         
-        let new_parent_inode = {
+        let (new_parent_inode, target) = {
             let new_parent = self.lookup_path(&new_parent_path)?;
-            new_parent.inode
+            if !new_parent.is_dir() {
+                return Err(FsError::NotDirectory);
+            }
+            (new_parent.inode, new_parent.lookup(&new_name).ok().map(|e| (e.inode, e.file_type)))
         };
         
-        let old_parent = self.nodes.get_mut(&old_parent_inode).ok_or(FsError::NotFound)?;
-        old_parent.remove_entry(&old_name)?;
+        if is_dir && self.is_within(new_parent_inode, entry_inode) {
+            return Err(FsError::InvalidArgument);
+        }
+        let replaced = match target {
+            // Links to one file: nothing to do, as POSIX has it, unless
+            // only the case of a name changes in a casefold directory
+            Some((inode, _)) if self.same_file(inode, entry_inode) => {
+                let recased = inode == entry_inode
+                    && old_parent_inode == new_parent_inode
+                    && old_name != new_name
+                    && self.get_node(new_parent_inode)?.casefold;
+                if !recased {
+                    return Ok(());
+                }
+                None
+            }
+            Some((inode, target_type)) => {
+                match (is_dir, target_type == FileType::Directory) {
+                    (true, false) => return Err(FsError::NotDirectory),
+                    (false, true) => return Err(FsError::IsDirectory),
+                    (true, true) if !self.children(inode).is_empty() => return Err(FsError::NotEmpty),
+                    _ => {}
+                }
+                Some(inode)
+            }
+            None => None,
+        };
+        match (self.remote_dirs.get(&old_parent_inode).cloned(), self.remote_dirs.get(&new_parent_inode).cloned()) {
+            (None, None) => {}
+            (Some((fs, from)), Some((other, to))) if Arc::ptr_eq(&fs, &other) => {
+                let old_name = self.get_node(entry_inode)?.name.clone();
+                let rename = || fs.write().rename(from, &old_name, to, &new_name);
+                match replaced {
+                    Some(inode) => self.keeping_open(inode, rename)?,
+                    None => rename()?,
+                }
+            }
+            _ => return Err(FsError::CrossDevice),
+        }
         
-        let node = self.nodes.get_mut(&entry_inode).ok_or(FsError::NotFound)?;
-        node.name = new_name.clone();
+        // Nothing below can fail: every node it touches was found above
+        if let Some(inode) = replaced {
+            if let Some(new_parent) = self.nodes.get_mut(&new_parent_inode) {
+                new_parent.remove_entry(&new_name).ok();
+                if is_dir {
+                    new_parent.nlink -= 1;
+                }
+            }
+            self.white_out(inode, new_path);
+            self.drop_node(inode);
+        }
+        if let Some(old_parent) = self.nodes.get_mut(&old_parent_inode) {
+            old_parent.remove_entry(&old_name).ok();
+            if is_dir && old_parent_inode != new_parent_inode {
+                old_parent.nlink -= 1;
+            }
+        }
+        if let Some(node) = self.nodes.get_mut(&entry_inode) {
+            node.name = new_name.clone();
+            if let VfsNodeData::Directory(entries) = &mut node.data {
+                if let Some(parent) = entries.iter_mut().find(|e| e.name == "..") {
+                    parent.inode = new_parent_inode;
+                }
+            }
+        }
+        if let Some(new_parent) = self.nodes.get_mut(&new_parent_inode) {
+            new_parent.add_entry(DirEntry::new(new_name, entry_inode, file_type)).ok();
+            if is_dir && old_parent_inode != new_parent_inode {
+                new_parent.nlink += 1;
+            }
+        }
         
-        let new_parent = self.nodes.get_mut(&new_parent_inode).ok_or(FsError::NotFound)?;
-        new_parent.add_entry(DirEntry::new(new_name, entry_inode, file_type))?;
+        // What moved is no longer where the lower layer has it
+        if self.white_out(entry_inode, old_path) {
+            let mut moved = self.subtree(entry_inode);
+            moved.push(entry_inode);
+            for inode in moved {
+                for overlay in self.overlays.values_mut() {
+                    overlay.forget(inode);
+                }
+            }
+        }
         
         Ok(())
     }
     
+    /// Remove the entry for `inode` from the filesystem grafted on the
+    /// directory `parent`, if there is one
+    fn remove_remote(&mut self, parent: InodeNumber, inode: InodeNumber) -> FsResult<()> {
+        let Some((fs, remote)) = self.remote_dirs.get(&parent).cloned() else {
+            return Ok(());
+        };
+        let node = self.get_node(inode)?;
+        let name = node.name.clone();
+        match node.is_dir() {
+            true => fs.write().rmdir(remote, &name),
+            false => self.keeping_open(inode, || fs.write().unlink(remote, &name)),
+        }
+    }
+    
+    /// Run `remove`, which takes the file `inode` off the filesystem it
+    /// mirrors, first reading it into memory if an open file holds it, so
+    /// that it outlives its name there as any other file does
+    fn keeping_open(&mut self, inode: InodeNumber, remove: impl FnOnce() -> FsResult<()>) -> FsResult<()> {
+        let node = self.get_node(inode)?;
+        if !self.held.contains_key(&inode) || !matches!(node.data, VfsNodeData::Mounted(..)) {
+            return remove();
+        }
+        let data = read_all(node)?;
+        remove()?;
+        let node = self.nodes.get_mut(&inode).ok_or(FsError::NotFound)?;
+        (node.size, node.data) = (data.len() as u64, VfsNodeData::Regular(data.into()));
+        Ok(())
+    }
+    
+    /// Whether the directory `inode` is `ancestor` or under it, following
+    /// `..` up to the root
+    fn is_within(&self, mut inode: InodeNumber, ancestor: InodeNumber) -> bool {
+        loop {
+            if inode == ancestor {
+                return true;
+            }
+            let parent = match self.nodes.get(&inode).map(|node| &node.data) {
+                Some(VfsNodeData::Directory(entries)) => entries.iter().find(|e| e.name == "..").map(|e| e.inode),
+                _ => None,
+            };
+            match parent {
+                Some(parent) if parent != inode => inode = parent,
+                _ => return false,
+            }
+        }
+    }
+    
     pub fn read_symlink(&self, path: &str) -> FsResult<String> {
         let node = self.lookup_path(path)?;
         
@@ -297,49 +732,494 @@ impl VirtualFileSystem {
         }
     }
     
+    /// Write to the node `inode`; a file may grow only as far as its
+    /// owner's quota allows
     pub fn write_node(&mut self, inode: InodeNumber, offset: u64, buf: &[u8]) -> FsResult<usize> {
+        self.copy_up(inode)?;
         let node = self.nodes.get_mut(&inode).ok_or(FsError::NotFound)?;
-        node.write(offset, buf)
+        let size = node.size;
+        self.quotas.resize(inode, size.max(offset + buf.len() as u64))?;
+        let written = node.write(offset, buf);
+        let grown = written.map_or(size, |n| size.max(offset + n as u64));
+        self.quotas.resize(inode, grown).ok();
+        written
     }
     
     pub fn chmod(&mut self, path: &str, mode: u16) -> FsResult<()> {
-        let node = self.lookup_path_mut(path)?;
+        let inode = self.lookup_path(path)?.inode;
+        self.copy_up(inode)?;
+        let node = self.nodes.get_mut(&inode).ok_or(FsError::NotFound)?;
         let current = node.mode.0 & FileMode::S_IFMT;
         node.mode = FileMode::new(current | (mode & 0o7777));
         Ok(())
     }
     
     pub fn chown(&mut self, path: &str, uid: u32, gid: u32) -> FsResult<()> {
-        let node = self.lookup_path_mut(path)?;
+        let inode = self.lookup_path(path)?.inode;
+        self.copy_up(inode)?;
+        self.quotas.chown(inode, uid, gid)?;
+        let node = self.nodes.get_mut(&inode).ok_or(FsError::NotFound)?;
         node.uid = uid;
         node.gid = gid;
         Ok(())
     }
     
     pub fn truncate(&mut self, path: &str, length: u64) -> FsResult<()> {
-        let node = self.lookup_path_mut(path)?;
-        node.truncate(length)
+        let (inode, size) = {
+            let node = self.lookup_path(path)?;
+            if !node.is_file() {
+                return Err(FsError::InvalidArgument);
+            }
+            (node.inode, node.size)
+        };
+        self.copy_up(inode)?;
+        self.quotas.resize(inode, length)?;
+        let node = self.nodes.get_mut(&inode).ok_or(FsError::NotFound)?;
+        node.truncate(length).inspect_err(|_| {
+            self.quotas.resize(inode, size).ok();
+        })
+    }
+    
+    /// Allocate `len` bytes at `offset` of the file `inode`, growing it
+    /// unless KEEP_SIZE is given, or with PUNCH_HOLE free them instead.
+    /// Growing is charged to the owner like a write.
+    pub fn fallocate(&mut self, inode: InodeNumber, mode: FallocateFlags, offset: u64, len: u64) -> FsResult<()> {
+        let punch = mode.contains(FallocateFlags::PUNCH_HOLE);
+        if mode.bits() & !FallocateFlags::all().bits() != 0 || (punch && !mode.contains(FallocateFlags::KEEP_SIZE)) {
+            return Err(FsError::NotSupported);
+        }
+        let end = offset.checked_add(len).filter(|_| len > 0).ok_or(FsError::InvalidArgument)?;
+        let node = self.nodes.get(&inode).ok_or(FsError::NotFound)?;
+        if node.is_dir() {
+            return Err(FsError::IsDirectory);
+        }
+        if let VfsNodeData::Mounted(fs, remote) = &node.data {
+            let (fs, remote) = (fs.clone(), *remote);
+            fs.write().fallocate(remote, mode, offset, len)?;
+            let size = fs.read().stat(remote)?.size;
+            self.nodes.get_mut(&inode).ok_or(FsError::NotFound)?.size = size;
+            return Ok(());
+        }
+        if !node.is_file() {
+            return Err(FsError::NotSupported);
+        }
+        
+        self.copy_up(inode)?;
+        let size = self.nodes.get(&inode).ok_or(FsError::NotFound)?.size;
+        let grow = !mode.contains(FallocateFlags::KEEP_SIZE) && end > size;
+        if grow {
+            self.quotas.resize(inode, end)?;
+        }
+        let node = self.nodes.get_mut(&inode).ok_or(FsError::NotFound)?;
+        if let VfsNodeData::Regular(data) = &mut node.data {
+            if punch {
+                data.punch_hole(offset, len);
+            } else {
+                data.allocate(offset, len);
+                if grow {
+                    data.resize(end);
+                    node.size = end;
+                }
+            }
+        }
+        Ok(())
     }
     
     pub fn sync(&mut self) -> FsResult<()> {
         Ok(())
     }
     
+    /// Mirror `fs` in the directory `path`, hiding what was in it until
+    /// ungraft(): the filesystem's directories are copied in and its files
+    /// become nodes that read and write through to it. Files, directories
+    /// and symlinks made, removed or renamed under `path` from then on are
+    /// made, removed or renamed in `fs` too; moving anything in or out is
+    /// CrossDevice. Files `fs` gains some other way show up only when it
+    /// is grafted again.
+    pub fn graft(&mut self, path: &str, fs: Arc<RwLock<dyn Filesystem + Send + Sync>>) -> FsResult<()> {
+        let dir = self.lookup_path(path)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        let (inode, casefold) = (dir.inode, dir.casefold || fs.read().casefold());
+        let root = fs.read().root()?.inode;
+        self.overlays.remove(&inode);
+        self.shadow(inode);
+        self.remote_dirs.insert(inode, (fs.clone(), root));
+        let grafted = self.set_casefold(inode, casefold).and_then(|()| self.graft_directory(inode, &fs, root, false));
+        if grafted.is_err() {
+            self.ungraft(path).ok();
+        }
+        grafted
+    }
+    
+    /// Empty the directory `path` of what graft() or overlay() put there,
+    /// changes to an overlay included, and put back what they hid
+    pub fn ungraft(&mut self, path: &str) -> FsResult<()> {
+        let dir = self.lookup_path(path)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        let inode = dir.inode;
+        self.overlays.remove(&inode);
+        self.remote_dirs.remove(&inode);
+        self.clear_directory(inode);
+        if let Some((entries, nlink, casefold)) = self.shadowed.remove(&inode) {
+            let node = self.nodes.get_mut(&inode).ok_or(FsError::NotFound)?;
+            if let VfsNodeData::Directory(current) = &mut node.data {
+                current.extend(entries);
+            }
+            (node.nlink, node.casefold) = (nlink, casefold);
+        }
+        Ok(())
+    }
+    
+    /// Merge the read-only filesystem `lower` into the directory `path`,
+    /// hiding what was in it until ungraft(), as the lower layer of an
+    /// overlay: see fs::overlay. Changes stay in memory until ungraft()
+    /// drops them.
+    pub fn overlay(&mut self, path: &str, lower: Arc<RwLock<dyn Filesystem + Send + Sync>>) -> FsResult<()> {
+        let dir = self.lookup_path(path)?;
+        if !dir.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        let (inode, casefold) = (dir.inode, dir.casefold || lower.read().casefold());
+        let root = lower.read().root()?.inode;
+        self.overlays.remove(&inode);
+        self.remote_dirs.remove(&inode);
+        self.shadow(inode);
+        if let Err(e) = self.set_casefold(inode, casefold) {
+            self.ungraft(path).ok();
+            return Err(e);
+        }
+        let mut overlay = Overlay::new(lower.clone(), self.resolve_path(path));
+        let grafted = self.graft_directory(inode, &lower, root, true);
+        overlay.lower_nodes.extend(self.subtree(inode));
+        self.overlays.insert(inode, overlay);
+        grafted
+    }
+    
+    /// Match names in the directory `inode` and every directory under it
+    /// without regard to case, or with it again. Turning casefolding on
+    /// fails with AlreadyExists if two names in one directory would then
+    /// be the same.
+    pub fn set_casefold(&mut self, inode: InodeNumber, casefold: bool) -> FsResult<()> {
+        if !self.get_node(inode)?.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        let mut dirs = self.subtree(inode);
+        dirs.push(inode);
+        dirs.retain(|dir| self.nodes.get(dir).is_some_and(|node| node.is_dir() && node.casefold != casefold));
+        if casefold {
+            for &dir in &dirs {
+                let children = self.children(dir);
+                for (at, entry) in children.iter().enumerate() {
+                    if children[at + 1..].iter().any(|other| fold_eq(&entry.name, &other.name)) {
+                        return Err(FsError::AlreadyExists);
+                    }
+                }
+            }
+        }
+        for dir in dirs {
+            if let Some(node) = self.nodes.get_mut(&dir) {
+                node.casefold = casefold;
+            }
+        }
+        Ok(())
+    }
+    
+    /// How the overlay on the directory `path` differs from its lower
+    /// layer, by path under it
+    pub fn overlay_changes(&self, path: &str) -> FsResult<Vec<(Change, String)>> {
+        let dir = self.lookup_path(path)?.inode;
+        let overlay = self.overlays.get(&dir).ok_or(FsError::InvalidArgument)?;
+        let mut changes = Vec::new();
+        let mut pending = alloc::vec![(dir, String::new())];
+        while let Some((inode, prefix)) = pending.pop() {
+            for entry in self.children(inode) {
+                let path = format!("{}/{}", prefix, entry.name);
+                // Something new where a lower entry was removed replaces it
+                if !overlay.lower_nodes.contains(&entry.inode) && !overlay.whiteouts.contains(&path) {
+                    changes.push((Change::Added, path.clone()));
+                } else if !overlay.lower_nodes.contains(&entry.inode) || overlay.copied.contains(&entry.inode) {
+                    changes.push((Change::Changed, path.clone()));
+                }
+                if entry.file_type == FileType::Directory {
+                    pending.push((entry.inode, path));
+                }
+            }
+        }
+        for path in &overlay.whiteouts {
+            if self.lookup_resolved(&format!("{}{}", overlay.path.trim_end_matches('/'), path)).is_err() {
+                changes.push((Change::Deleted, path.clone()));
+            }
+        }
+        changes.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(changes)
+    }
+    
+    /// The overlay `inode` mirrors a lower entry of, if any
+    fn overlay_of(&self, inode: InodeNumber) -> Option<InodeNumber> {
+        self.overlays.iter().find(|(_, overlay)| overlay.lower_nodes.contains(&inode)).map(|(&dir, _)| dir)
+    }
+    
+    /// Bring a node that mirrors an overlay's lower layer into memory
+    /// before it is changed. A file's data is read in and charged to its
+    /// owner; other nodes are only marked as changed.
+    fn copy_up(&mut self, inode: InodeNumber) -> FsResult<()> {
+        let node = self.nodes.get(&inode).ok_or(FsError::NotFound)?;
+        if let VfsNodeData::Lower(..) = node.data {
+            let data = read_all(node)?;
+            self.quotas.create(inode, node.uid, node.gid, data.len() as u64)?;
+            let node = self.nodes.get_mut(&inode).ok_or(FsError::NotFound)?;
+            node.size = data.len() as u64;
+            node.data = VfsNodeData::Regular(data.into());
+        }
+        if let Some(dir) = self.overlay_of(inode) {
+            if let Some(overlay) = self.overlays.get_mut(&dir) {
+                overlay.copied.insert(inode);
+            }
+        }
+        Ok(())
+    }
+    
+    /// Record a whiteout if `inode`, leaving `path`, mirrors an overlay's
+    /// lower layer; whether it did
+    fn white_out(&mut self, inode: InodeNumber, path: &str) -> bool {
+        let path = self.resolve_path(path);
+        let dir = match self.overlay_of(inode) {
+            Some(dir) => dir,
+            None => return false,
+        };
+        if let Some(overlay) = self.overlays.get_mut(&dir) {
+            if let Some(relative) = overlay.relative(&path) {
+                overlay.whiteouts.insert(relative);
+            }
+        }
+        true
+    }
+    
+    /// The entries of the directory `inode`, without `.` and `..`
+    fn children(&self, inode: InodeNumber) -> Vec<DirEntry> {
+        match self.nodes.get(&inode).map(|node| &node.data) {
+            Some(VfsNodeData::Directory(entries)) => {
+                entries.iter().filter(|e| e.name != "." && e.name != "..").cloned().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+    
+    /// Every node under the directory `inode`
+    fn subtree(&self, inode: InodeNumber) -> Vec<InodeNumber> {
+        let mut nodes = Vec::new();
+        let mut pending = alloc::vec![inode];
+        while let Some(dir) = pending.pop() {
+            for entry in self.children(dir) {
+                if entry.file_type == FileType::Directory {
+                    pending.push(entry.inode);
+                }
+                nodes.push(entry.inode);
+            }
+        }
+        nodes
+    }
+    
+    /// Mirror the directory `remote` of `fs` in `dir`. Files of a lower
+    /// layer are read through and not charged until they are copied up.
+    fn graft_directory(&mut self, dir: InodeNumber, fs: &Arc<RwLock<dyn Filesystem + Send + Sync>>, remote: InodeNumber, lower: bool) -> FsResult<()> {
+        let entries = fs.read().readdir(remote)?;
+        let casefold = self.get_node(dir)?.casefold;
+        for entry in entries.into_iter().filter(|e| e.name != "." && e.name != "..") {
+            // A case-sensitive filesystem mounted casefold can have names
+            // that differ only in case; the first of them is kept
+            if casefold && self.get_node(dir)?.lookup(&entry.name).is_ok() {
+                continue;
+            }
+            let remote_node = fs.read().lookup(remote, &entry.name)?;
+            let inode = self.alloc_inode();
+            let mut node = match remote_node.data {
+                VfsNodeData::Directory(_) => {
+                    let mut node = VfsNode::new_directory(entry.name.clone(), inode, remote_node.mode.0);
+                    if let VfsNodeData::Directory(ref mut entries) = node.data {
+                        entries.push(DirEntry::new("..".into(), dir, FileType::Directory));
+                    }
+                    node.casefold = casefold;
+                    node
+                }
+                VfsNodeData::Symlink(ref target) => VfsNode::new_symlink(entry.name.clone(), inode, target.clone()),
+                _ => {
+                    let mut node = VfsNode::new_file(entry.name.clone(), inode, remote_node.mode.0 & 0o7777);
+                    node.data = match lower {
+                        true => VfsNodeData::Lower(fs.clone(), remote_node.inode),
+                        false => VfsNodeData::Mounted(fs.clone(), remote_node.inode),
+                    };
+                    node
+                }
+            };
+            (node.size, node.uid, node.gid) = (remote_node.size, remote_node.uid, remote_node.gid);
+            (node.atime, node.mtime, node.ctime) = (remote_node.atime, remote_node.mtime, remote_node.ctime);
+            let file_type = node.file_type();
+            if !lower {
+                self.quotas.adopt(inode, node.uid, node.gid, node.size);
+                if file_type == FileType::Directory {
+                    self.remote_dirs.insert(inode, (fs.clone(), remote_node.inode));
+                }
+            }
+            self.nodes.insert(inode, node);
+            
+            let parent = self.nodes.get_mut(&dir).ok_or(FsError::NotFound)?;
+            parent.add_entry(DirEntry::new(entry.name, inode, file_type))?;
+            if file_type == FileType::Directory {
+                parent.nlink += 1;
+                self.graft_directory(inode, fs, remote_node.inode, lower)?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// Empty the directory `inode` for a graft or overlay, keeping what it
+    /// held aside for ungraft(). Grafted over again, what the last graft
+    /// put there is dropped and what the first one hid stays hidden.
+    fn shadow(&mut self, inode: InodeNumber) {
+        if self.shadowed.contains_key(&inode) {
+            self.clear_directory(inode);
+            return;
+        }
+        let Some(node) = self.nodes.get_mut(&inode) else { return };
+        if let VfsNodeData::Directory(entries) = &mut node.data {
+            let (kept, hidden) = core::mem::take(entries).into_iter().partition(|e| e.name == "." || e.name == "..");
+            *entries = kept;
+            self.shadowed.insert(inode, (hidden, node.nlink, node.casefold));
+            node.nlink = 2;
+        }
+    }
+    
+    /// Whether the nodes `a` and `b` are one file: the same node, or links
+    /// to one inode of a grafted filesystem
+    fn same_file(&self, a: InodeNumber, b: InodeNumber) -> bool {
+        if a == b {
+            return true;
+        }
+        match (self.nodes.get(&a).map(|n| &n.data), self.nodes.get(&b).map(|n| &n.data)) {
+            (
+                Some(VfsNodeData::Mounted(fs, x) | VfsNodeData::Lower(fs, x)),
+                Some(VfsNodeData::Mounted(other, y) | VfsNodeData::Lower(other, y)),
+            ) => Arc::ptr_eq(fs, other) && x == y,
+            _ => false,
+        }
+    }
+    
+    /// Drop everything under the directory `inode`
+    fn clear_directory(&mut self, inode: InodeNumber) {
+        for child in self.children(inode) {
+            if child.file_type == FileType::Directory {
+                self.clear_directory(child.inode);
+            }
+            self.drop_node(child.inode);
+        }
+        if let Some(node) = self.nodes.get_mut(&inode) {
+            if let VfsNodeData::Directory(entries) = &mut node.data {
+                entries.retain(|e| e.name == "." || e.name == "..");
+            }
+            node.nlink = 2;
+        }
+    }
+    
     pub fn set_cwd(&mut self, path: &str) -> FsResult<()> {
-        let resolved = self.resolve_path(path);
-        let node = self.lookup_path(&resolved)?;
+        let view = self.absolute_path(path);
+        let node = self.lookup_path(&view)?;
         
         if !node.is_dir() {
             return Err(FsError::NotDirectory);
         }
         
-        self.cwd = resolved;
+        self.cwd = view;
         Ok(())
     }
     
+    /// Make the directory `inode` the working directory, as fchdir(2),
+    /// returning the new one. It has to be inside the root, which the
+    /// working directory is kept relative to.
+    pub fn set_cwd_inode(&mut self, inode: InodeNumber) -> FsResult<String> {
+        if !self.get_node(inode)?.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        let view = self.view_of(inode)?;
+        self.cwd = view.clone();
+        Ok(view)
+    }
+    
+    /// The path of the directory `inode` as seen from the root; outside
+    /// it, the directory cannot be named (PermissionDenied)
+    pub fn view_of(&self, inode: InodeNumber) -> FsResult<String> {
+        let path = self.path_of(inode)?;
+        match self.root.as_str() {
+            "/" => Ok(path),
+            root if path == root => Ok(String::from("/")),
+            root => match path.strip_prefix(root).filter(|rest| rest.starts_with('/')) {
+                Some(rest) => Ok(String::from(rest)),
+                None => Err(FsError::PermissionDenied),
+            },
+        }
+    }
+    
+    /// The path of the directory `inode` from the real root, found by
+    /// climbing `..`
+    pub fn path_of(&self, inode: InodeNumber) -> FsResult<String> {
+        let mut names = Vec::new();
+        let mut current = inode;
+        while current != 1 {
+            let node = self.get_node(current)?;
+            let parent = match &node.data {
+                VfsNodeData::Directory(entries) => entries.iter().find(|e| e.name == "..").map(|e| e.inode),
+                _ => None,
+            };
+            names.push(node.name.as_str());
+            current = parent.ok_or(FsError::NotFound)?;
+        }
+        names.reverse();
+        Ok(format!("/{}", names.join("/")))
+    }
+    
     pub fn get_cwd(&self) -> &str {
         &self.cwd
     }
+    
+    /// Make the directory `path` the root, as chroot(2), and move the
+    /// working directory to it. Returns the new root as a path from the
+    /// real root.
+    pub fn set_root(&mut self, path: &str) -> FsResult<String> {
+        let root = self.resolve_path(path);
+        if !self.lookup_resolved(&root)?.is_dir() {
+            return Err(FsError::NotDirectory);
+        }
+        
+        self.root = root.clone();
+        self.cwd = String::from("/");
+        Ok(root)
+    }
+    
+    /// The root as a path from the real root
+    pub fn get_root(&self) -> &str {
+        &self.root
+    }
+    
+    /// Put back a root and working directory saved from `get_root` and
+    /// `get_cwd`, without resolving them again
+    pub fn restore_root(&mut self, root: String, cwd: String) {
+        self.root = root;
+        self.cwd = cwd;
+    }
+}
+
+/// The VFS as `VirtualFileSystem::snapshot` found it
+pub struct Snapshot {
+    nodes: NodeSnapshot,
+    next_inode: InodeNumber,
+    quotas: Quotas,
+    overlays: BTreeMap<InodeNumber, Overlay>,
+    orphans: BTreeSet<InodeNumber>,
+    remote_dirs: BTreeMap<InodeNumber, Remote>,
 }
 
 pub fn init_vfs() {
@@ -348,6 +1228,7 @@ pub fn init_vfs() {
     vfs.create_directory("/bin", FileMode::new(0o755)).ok();
     vfs.create_directory("/sbin", FileMode::new(0o755)).ok();
     vfs.create_directory("/etc", FileMode::new(0o755)).ok();
+    vfs.create_directory("/etc/skel", FileMode::new(0o755)).ok();
     vfs.create_directory("/dev", FileMode::new(0o755)).ok();
     // Create standard device nodes
     vfs.create_device("/dev/stdin", super::node::DeviceId::new(1, 0), FileMode::new(FileMode::S_IFCHR | 0o666)).ok();
@@ -356,6 +1237,7 @@ pub fn init_vfs() {
     vfs.create_directory("/proc", FileMode::new(0o555)).ok();
     vfs.create_directory("/sys", FileMode::new(0o555)).ok();
     vfs.create_directory("/tmp", FileMode::new(0o1777)).ok();
+    vfs.create_directory("/run", FileMode::new(0o755)).ok();
     vfs.create_directory("/var", FileMode::new(0o755)).ok();
     vfs.create_directory("/var/log", FileMode::new(0o755)).ok();
     vfs.create_directory("/home", FileMode::new(0o755)).ok();
@@ -367,6 +1249,23 @@ pub fn init_vfs() {
     vfs.create_directory("/mnt", FileMode::new(0o755)).ok();
 }
 
+/// Everything in the file `node`, read through to where it is kept
+fn read_all(node: &VfsNode) -> FsResult<Vec<u8>> {
+    let mut data = alloc::vec![0u8; node.size as usize];
+    let mut len = 0;
+    loop {
+        if len == data.len() {
+            data.resize(len.max(4096) * 2, 0);
+        }
+        match node.read(len as u64, &mut data[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    data.truncate(len);
+    Ok(data)
+}
+
 fn normalize_path(path: &str) -> String {
     let mut components: Vec<&str> = Vec::new();
     
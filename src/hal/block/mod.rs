@@ -0,0 +1,337 @@
+// Block layer
+//
+// Drivers register each disk they bring up here, under its /dev name and
+// through the request queue they keep for it, rather than with the device
+// registry directly. Registering a disk reads its partition table (see
+// partition) and registers every partition as a block device of its own
+// that passes its blocks through to the disk's: sda1 on sda, or
+// nvme0n1p1 on nvme0n1, with a "p" after a name ending in a digit.
+// Partitions take the minor numbers after the disk's, as many as its
+// driver set aside, and after that ones from BLKEXT_MAJOR as Linux gives
+// them. Every device registered shows in the device registry, so hotplug
+// makes its /dev node and lsblk lists it.
+//
+// RAM disks are registered the same way: `ramdisk=MIB[,MIB...]` on the
+// command line makes ram0, ram1 and so on, empty and lost at reboot.
+//
+// open() finds any of them by name or /dev path, and also the mapped
+// devices and RAID arrays, which keep their own lists so as to know when
+// nothing holds them; mount() puts the ext4 or FAT32 filesystem on a
+// device in the VFS by that path. rescan() reads a disk's table again
+// once nothing has its partitions open.
+
+pub mod partition;
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use spin::{Mutex, RwLock};
+use crate::fs::ext4::{BlockDevice, Ext4Filesystem};
+use crate::fs::fat32::Fat32Filesystem;
+use crate::fs::mount::{self, MountFlags};
+use crate::fs::ramdisk::RamDisk;
+use crate::fs::vfs::node::{DeviceId, Filesystem};
+use crate::fs::vfs::VFS;
+use crate::fs::{FsError, FsResult};
+use crate::hal::drivers::device::{self, DeviceKind, SharedBlockDevice};
+use crate::hal::drivers::{dm, md};
+use partition::TableError;
+
+/// Where block devices without a number of their own go, as on Linux
+pub const BLKEXT_MAJOR: u16 = 259;
+pub const RAMDISK_PARAM: &str = "ramdisk";
+const RAMDISK_BLOCK_SIZE: u32 = 512;
+
+static NEXT_EXT_MINOR: AtomicU16 = AtomicU16::new(0);
+
+/// A partition of a disk, as a block device
+pub struct PartitionDevice {
+    disk: SharedBlockDevice,
+    start: u64,
+    blocks: u64,
+}
+
+impl PartitionDevice {
+    fn check(&self, block_num: u64, len: usize, block_size: u32) -> Result<u64, &'static str> {
+        let count = (len as u64).div_ceil(block_size as u64);
+        match block_num.checked_add(count) {
+            Some(end) if end <= self.blocks => Ok(self.start + block_num),
+            _ => Err("block out of range"),
+        }
+    }
+}
+
+impl BlockDevice for PartitionDevice {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let disk = self.disk.read();
+        disk.read_block(self.check(block_num, buf.len(), disk.block_size())?, buf)
+    }
+
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let mut disk = self.disk.write();
+        let block = self.check(block_num, buf.len(), disk.block_size())?;
+        disk.write_block(block, buf)
+    }
+
+    fn block_size(&self) -> u32 {
+        self.disk.read().block_size()
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        self.disk.write().flush()
+    }
+}
+
+struct Registered {
+    name: String,
+    id: DeviceId,
+    device: SharedBlockDevice,
+    /// For a partition, the disk it is on and its number there
+    parent: Option<(String, u32)>,
+    /// Minor numbers after `id` its driver set aside for partitions
+    minors: u16,
+}
+
+static DEVICES: Mutex<Vec<Registered>> = Mutex::new(Vec::new());
+
+/// A device number of BLKEXT_MAJOR no other device has, for drivers
+/// without a major number of their own
+pub fn ext_id() -> DeviceId {
+    DeviceId::new(BLKEXT_MAJOR, NEXT_EXT_MINOR.fetch_add(1, Ordering::Relaxed))
+}
+
+/// The name partition `number` of `disk` gets
+pub fn partition_name(disk: &str, number: u32) -> String {
+    match disk.ends_with(|c: char| c.is_ascii_digit()) {
+        true => format!("{}p{}", disk, number),
+        false => format!("{}{}", disk, number),
+    }
+}
+
+fn size_of(device: &SharedBlockDevice) -> u64 {
+    let device = device.read();
+    device.block_count() * device.block_size() as u64
+}
+
+/// Register the disk `device` as `name` with number `id`, leaving the
+/// `minors` numbers after it for its partitions, and register those
+pub fn register(name: &str, id: DeviceId, driver: &'static str, device: SharedBlockDevice, minors: u16) {
+    device::register(name, DeviceKind::Block, id, driver, Some(size_of(&device)));
+    DEVICES.lock().push(Registered { name: name.to_string(), id, device, parent: None, minors });
+    if let Err(e) = scan(name) {
+        crate::println!("  [BLOCK] {}: {}", name, e);
+    }
+}
+
+/// Read the partition table of the disk `name` and register what is in it
+fn scan(name: &str) -> Result<usize, TableError> {
+    let (id, device, minors) = match DEVICES.lock().iter().find(|d| d.name == name) {
+        Some(disk) => (disk.id, disk.device.clone(), disk.minors),
+        None => return Ok(0),
+    };
+    let partitions = partition::read_table(&*device.read())?;
+    for found in &partitions {
+        let part_name = partition_name(name, found.number);
+        let part_id = match u16::try_from(found.number) {
+            Ok(number) if number < minors => DeviceId::new(id.major, id.minor + number),
+            _ => ext_id(),
+        };
+        let part: SharedBlockDevice = Arc::new(RwLock::new(PartitionDevice {
+            disk: device.clone(),
+            start: found.start,
+            blocks: found.blocks,
+        }));
+        device::register(&part_name, DeviceKind::Block, part_id, "part", Some(size_of(&part)));
+        DEVICES.lock().push(Registered {
+            name: part_name,
+            id: part_id,
+            device: part,
+            parent: Some((name.to_string(), found.number)),
+            minors: 0,
+        });
+    }
+    Ok(partitions.len())
+}
+
+/// Drop the partitions of `disk`, unless one is still open
+fn drop_partitions(disk: &str) -> FsResult<()> {
+    let mut devices = DEVICES.lock();
+    let is_part = |d: &Registered| d.parent.as_ref().is_some_and(|(parent, _)| parent == disk);
+    if devices.iter().filter(|d| is_part(d)).any(|d| Arc::strong_count(&d.device) > 1) {
+        return Err(FsError::Busy);
+    }
+    let gone: Vec<String> = devices.iter().filter(|d| is_part(d)).map(|d| d.name.clone()).collect();
+    devices.retain(|d| !is_part(d));
+    drop(devices);
+    for name in gone {
+        device::unregister(&name);
+    }
+    Ok(())
+}
+
+/// Take the disk `name` and its partitions away, as when it is unplugged
+pub fn unregister(name: &str) {
+    let mut devices = DEVICES.lock();
+    let gone: Vec<String> = devices.iter()
+        .filter(|d| d.name == name || d.parent.as_ref().is_some_and(|(parent, _)| parent == name))
+        .map(|d| d.name.clone())
+        .collect();
+    devices.retain(|d| !gone.contains(&d.name));
+    drop(devices);
+    for name in gone {
+        device::unregister(&name);
+    }
+}
+
+/// Read the partition table of the disk `name` again, replacing its
+/// partitions; fails with Busy while one of them is open
+pub fn rescan(name: &str) -> FsResult<usize> {
+    let name = name.strip_prefix("/dev/").unwrap_or(name);
+    if !DEVICES.lock().iter().any(|d| d.name == name && d.parent.is_none()) {
+        return Err(FsError::NotFound);
+    }
+    drop_partitions(name)?;
+    scan(name).map_err(|_| FsError::IoError)
+}
+
+/// Make an empty RAM disk of `blocks` 512-byte blocks, returning its name
+pub fn add_ramdisk(blocks: u64) -> String {
+    let index = DEVICES.lock().iter().filter(|d| d.name.starts_with("ram")).count();
+    let name = format!("ram{}", index);
+    let disk: SharedBlockDevice = Arc::new(RwLock::new(RamDisk::new(blocks, RAMDISK_BLOCK_SIZE)));
+    register(&name, ext_id(), "ramdisk", disk, 0);
+    name
+}
+
+/// Make the RAM disks `ramdisk=` asks for
+pub fn init_ramdisks() {
+    let value = match crate::kernel::get_param(RAMDISK_PARAM) {
+        Some(value) => value,
+        None => return,
+    };
+    for size in value.split(',') {
+        match size.parse::<u64>() {
+            Ok(mib) if mib > 0 => {
+                let name = add_ramdisk(mib * 1024 * 1024 / RAMDISK_BLOCK_SIZE as u64);
+                crate::println!("  [BLOCK] {}: {} MiB RAM disk", name, mib);
+            }
+            _ => crate::println!("  [BLOCK] {}={}: expected MiB", RAMDISK_PARAM, size),
+        }
+    }
+}
+
+/// The block device `name`, e.g. "sda1" or "/dev/nvme0n1", a mapped device
+/// or a RAID array
+pub fn open(name: &str) -> Option<SharedBlockDevice> {
+    let name = name.strip_prefix("/dev/").unwrap_or(name);
+    if let Some(found) = DEVICES.lock().iter().find(|d| d.name == name) {
+        return Some(found.device.clone());
+    }
+    if let Some(mapped) = dm::device(name) {
+        return Some(mapped);
+    }
+    md::array(name).map(|array| -> SharedBlockDevice { array })
+}
+
+/// The disk the partition `name` is on and its number there; None for
+/// anything but a partition
+pub fn parent(name: &str) -> Option<(String, u32)> {
+    let name = name.strip_prefix("/dev/").unwrap_or(name);
+    DEVICES.lock().iter().find(|d| d.name == name).and_then(|d| d.parent.clone())
+}
+
+/// The filesystem types mount() knows, by the name it takes them by
+pub const FS_TYPES: &[&str] = &["ext4", "fat32"];
+
+/// Mount the filesystem on the block device `source` on the directory
+/// `target`, hiding what it holds until umount(), returning its type.
+/// `fs_type` is one of FS_TYPES, or None to try each in turn.
+pub fn mount(source: &str, target: &str, fs_type: Option<&str>, flags: MountFlags) -> FsResult<&'static str> {
+    let device = open(source).ok_or(FsError::NotFound)?;
+    let read_only = flags.contains(MountFlags::RDONLY);
+    let mut found = None;
+    // An ext4 filesystem with features it cannot keep up only mounts
+    // read-only, which is worth saying rather than that nothing is there
+    let mut refused = FsError::InvalidArgument;
+    for &name in FS_TYPES.iter().filter(|&&name| fs_type.is_none_or(|t| t == name)) {
+        let filesystem: FsResult<Arc<RwLock<dyn Filesystem + Send + Sync>>> = match name {
+            "ext4" => Ext4Filesystem::mount(device.clone(), read_only).map(|mut fs| {
+                fs.set_clock(crate::kernel::time::now);
+                Arc::new(RwLock::new(fs)) as _
+            }),
+            _ => Fat32Filesystem::mount(device.clone(), read_only).map(|fs| Arc::new(RwLock::new(fs)) as _),
+        };
+        match filesystem {
+            Ok(filesystem) => {
+                found = Some((name, filesystem));
+                break;
+            }
+            Err(FsError::ReadOnly) => refused = FsError::ReadOnly,
+            Err(_) => {}
+        }
+    }
+    let (fs_type, filesystem) = found.ok_or(refused)?;
+    let source = format!("/dev/{}", source.strip_prefix("/dev/").unwrap_or(source));
+    mount::graft(&source, target, fs_type, flags, filesystem)?;
+    Ok(fs_type)
+}
+
+/// Undo mount(), or any other mount on `target`, writing out what the
+/// filesystem holds back. Fails with Busy while the working directory or
+/// another mount is under it.
+pub fn umount(target: &str) -> FsResult<()> {
+    let (target, cwd) = {
+        let vfs = VFS.lock();
+        (vfs.resolve_path(target), vfs.resolve_path("."))
+    };
+    let table = mount::get_mount_table();
+    let mounted = table.iter().find(|m| m.path == target).ok_or(FsError::NotFound)?;
+    if mount::is_within(&cwd, &target) || table.iter().any(|m| m.path != target && mount::is_within(&m.path, &target)) {
+        return Err(FsError::Busy);
+    }
+    mounted.filesystem.write().sync()?;
+    mount::umount(&target)?;
+    VFS.lock().ungraft(&target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use crate::fs::ramdisk::RamDisk;
+
+    #[test_case]
+    fn test_partitions_register_and_pass_through() {
+        let mut disk = RamDisk::new(64, 512);
+        let mut mbr = vec![0u8; 512];
+        partition::tests::mbr_entry(&mut mbr, 0, 0x83, 8, 16);
+        partition::tests::mbr_entry(&mut mbr, 2, 0x83, 24, 40);
+        disk.write_block(0, &mbr).unwrap();
+        let disk: SharedBlockDevice = Arc::new(RwLock::new(disk));
+        register("blocktest0", DeviceId::new(7, 200), "test", disk.clone(), 2);
+
+        assert_eq!(parent("/dev/blocktest0p1"), Some((String::from("blocktest0"), 1)));
+        assert_eq!(device::find("blocktest0p1").map(|d| d.id), Some(DeviceId::new(7, 201)));
+        assert_eq!(device::find("blocktest0p3").map(|d| d.id.major), Some(BLKEXT_MAJOR));
+
+        let part = open("blocktest0p3").unwrap();
+        assert_eq!(part.read().block_count(), 40);
+        part.write().write_block(1, &[0xAB; 512]).unwrap();
+        assert!(part.read().read_block(40, &mut [0u8; 512]).is_err());
+        let mut buf = [0u8; 512];
+        disk.read().read_block(25, &mut buf).unwrap();
+        assert_eq!(buf, [0xAB; 512]);
+
+        assert_eq!(rescan("blocktest0"), Err(FsError::Busy));
+        drop(part);
+        assert_eq!(rescan("blocktest0"), Ok(2));
+        unregister("blocktest0");
+        assert!(open("blocktest0p1").is_none() && device::find("blocktest0").is_none());
+    }
+}
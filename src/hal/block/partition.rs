@@ -0,0 +1,317 @@
+// Partition tables
+//
+// A disk is read for an MBR in block 0 or, when that is a protective MBR,
+// a GPT from block 1 on. MBR partitions are numbered 1-4 by their slot
+// and logical partitions in an extended one from 5, following the chain
+// of EBRs; GPT partitions by their slot in the entry array. Positions and
+// sizes are in the disk's blocks, as both tables count them.
+//
+// Block 0 of a disk formatted without a table, a FAT volume's boot sector
+// for one, ends with the same signature as an MBR, so a table only counts
+// if every slot is well formed and at least one is in use. A GPT whose
+// header or entry array fails its CRC-32 is read from the backup at the
+// end of the disk instead.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use crate::fs::archive::gzip::crc32;
+use crate::fs::ext4::BlockDevice;
+
+const MBR_SIGNATURE: u16 = 0xAA55;
+const MBR_ENTRIES: usize = 446;
+const MBR_PROTECTIVE: u8 = 0xEE;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_HEADER_SIZE: usize = 92;
+/// Longest chain of logical partitions followed, against loops
+const MAX_LOGICAL: u32 = 64;
+/// The most GPT entries read, which is what tools make
+const MAX_GPT_ENTRIES: u32 = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableError {
+    Io,
+    /// A table that is there but damaged, with what is wrong with it
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableError::Io => write!(f, "I/O error"),
+            TableError::Corrupt(what) => write!(f, "bad partition table: {}", what),
+        }
+    }
+}
+
+/// What a partition says it holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    /// An MBR system ID, e.g. 0x83 for Linux
+    Mbr(u8),
+    Gpt { type_guid: [u8; 16], name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    pub number: u32,
+    /// First block
+    pub start: u64,
+    pub blocks: u64,
+    pub kind: Kind,
+}
+
+fn read(disk: &dyn BlockDevice, block: u64) -> Result<Vec<u8>, TableError> {
+    let mut buf = vec![0u8; disk.block_size() as usize];
+    disk.read_block(block, &mut buf).map_err(|_| TableError::Io)?;
+    Ok(buf)
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+/// The partitions on `disk`, none if it has no table
+pub fn read_table(disk: &dyn BlockDevice) -> Result<Vec<Partition>, TableError> {
+    if disk.block_size() < 512 || disk.block_count() < 2 {
+        return Ok(Vec::new());
+    }
+    let mbr = read(disk, 0)?;
+    let slots = match mbr_slots(&mbr, disk.block_count()) {
+        Some(slots) => slots,
+        None => return Ok(Vec::new()),
+    };
+    if slots.iter().any(|&(kind, _, _)| kind == MBR_PROTECTIVE) {
+        return read_gpt(disk);
+    }
+
+    let mut partitions = Vec::new();
+    for (slot, &(kind, start, blocks)) in slots.iter().enumerate() {
+        if kind == 0 {
+            continue;
+        }
+        partitions.push(Partition { number: slot as u32 + 1, start, blocks, kind: Kind::Mbr(kind) });
+        if matches!(kind, 0x05 | 0x0F | 0x85) {
+            read_logical(disk, start, blocks, &mut partitions)?;
+        }
+    }
+    Ok(partitions)
+}
+
+/// The four slots of an MBR as (system ID, start, blocks), if `sector`
+/// is one
+fn mbr_slots(sector: &[u8], disk_blocks: u64) -> Option<[(u8, u64, u64); 4]> {
+    if u16::from_le_bytes([sector[510], sector[511]]) != MBR_SIGNATURE {
+        return None;
+    }
+    let mut slots = [(0, 0, 0); 4];
+    for (slot, entry) in slots.iter_mut().zip(sector[MBR_ENTRIES..510].chunks_exact(16)) {
+        let (status, kind) = (entry[0], entry[4]);
+        let (start, blocks) = (u32_at(entry, 8) as u64, u32_at(entry, 12) as u64);
+        if status & 0x7F != 0 {
+            return None;
+        }
+        if kind != 0 {
+            // A protective MBR may claim more than the disk has
+            let fits = start + blocks <= disk_blocks || kind == MBR_PROTECTIVE;
+            if start == 0 || blocks == 0 || !fits {
+                return None;
+            }
+        }
+        *slot = (kind, start, blocks);
+    }
+    slots.iter().any(|&(kind, _, _)| kind != 0).then_some(slots)
+}
+
+/// Follow the EBRs of the extended partition at `base`, adding the
+/// logical partitions in it from number 5
+fn read_logical(disk: &dyn BlockDevice, base: u64, size: u64, partitions: &mut Vec<Partition>) -> Result<(), TableError> {
+    let mut ebr = base;
+    for number in 5..5 + MAX_LOGICAL {
+        let sector = read(disk, ebr)?;
+        if u16::from_le_bytes([sector[510], sector[511]]) != MBR_SIGNATURE {
+            return Err(TableError::Corrupt("extended partition without an EBR"));
+        }
+        let entry = |n: usize| {
+            let entry = &sector[MBR_ENTRIES + n * 16..MBR_ENTRIES + (n + 1) * 16];
+            (entry[4], u32_at(entry, 8) as u64, u32_at(entry, 12) as u64)
+        };
+        // The first entry counts from this EBR, the link from the start
+        // of the extended partition
+        let (kind, start, blocks) = entry(0);
+        if kind != 0 && blocks != 0 {
+            let start = ebr + start;
+            if start + blocks > base + size {
+                return Err(TableError::Corrupt("logical partition outside the extended one"));
+            }
+            partitions.push(Partition { number, start, blocks, kind: Kind::Mbr(kind) });
+        }
+        let (link, next, _) = entry(1);
+        if link == 0 || next == 0 {
+            return Ok(());
+        }
+        if next >= size {
+            return Err(TableError::Corrupt("EBR outside the extended partition"));
+        }
+        ebr = base + next;
+    }
+    Err(TableError::Corrupt("too many logical partitions"))
+}
+
+fn read_gpt(disk: &dyn BlockDevice) -> Result<Vec<Partition>, TableError> {
+    match read_gpt_at(disk, 1) {
+        Ok(partitions) => Ok(partitions),
+        Err(TableError::Corrupt(_)) => read_gpt_at(disk, disk.block_count() - 1),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_gpt_at(disk: &dyn BlockDevice, at: u64) -> Result<Vec<Partition>, TableError> {
+    let mut header = read(disk, at)?;
+    if &header[..8] != GPT_SIGNATURE {
+        return Err(TableError::Corrupt("no GPT header"));
+    }
+    let size = u32_at(&header, 12) as usize;
+    if size < GPT_HEADER_SIZE || size > header.len() {
+        return Err(TableError::Corrupt("bad GPT header size"));
+    }
+    let crc = u32_at(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..size]) != crc {
+        return Err(TableError::Corrupt("GPT header checksum"));
+    }
+
+    let (first_usable, last_usable) = (u64_at(&header, 40), u64_at(&header, 48));
+    let entries_at = u64_at(&header, 72);
+    let (count, entry_size) = (u32_at(&header, 80), u32_at(&header, 84) as usize);
+    if entry_size < 128 || entry_size % 8 != 0 || count > MAX_GPT_ENTRIES {
+        return Err(TableError::Corrupt("bad GPT entry array"));
+    }
+    let block_size = disk.block_size() as usize;
+    let bytes = count as usize * entry_size;
+    let mut entries = Vec::with_capacity(bytes.next_multiple_of(block_size));
+    for block in 0..bytes.div_ceil(block_size) as u64 {
+        entries.extend_from_slice(&read(disk, entries_at + block)?);
+    }
+    if crc32(&entries[..bytes]) != u32_at(&header, 88) {
+        return Err(TableError::Corrupt("GPT entry array checksum"));
+    }
+
+    let mut partitions = Vec::new();
+    for (slot, entry) in entries[..bytes].chunks_exact(entry_size).enumerate() {
+        let type_guid: [u8; 16] = entry[..16].try_into().unwrap();
+        if type_guid == [0; 16] {
+            continue;
+        }
+        let (first, last) = (u64_at(entry, 32), u64_at(entry, 40));
+        if first < first_usable || last > last_usable || last < first {
+            return Err(TableError::Corrupt("GPT partition outside the usable blocks"));
+        }
+        let name: Vec<u16> = entry[56..128].chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0)
+            .collect();
+        partitions.push(Partition {
+            number: slot as u32 + 1,
+            start: first,
+            blocks: last - first + 1,
+            kind: Kind::Gpt { type_guid, name: String::from_utf16_lossy(&name) },
+        });
+    }
+    Ok(partitions)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::fs::ramdisk::RamDisk;
+
+    pub fn mbr_entry(sector: &mut [u8], slot: usize, kind: u8, start: u32, blocks: u32) {
+        let entry = &mut sector[MBR_ENTRIES + slot * 16..MBR_ENTRIES + (slot + 1) * 16];
+        entry[4] = kind;
+        entry[8..12].copy_from_slice(&start.to_le_bytes());
+        entry[12..16].copy_from_slice(&blocks.to_le_bytes());
+        sector[510..512].copy_from_slice(&MBR_SIGNATURE.to_le_bytes());
+    }
+
+    fn disk_with(blocks: u64, sectors: &[(u64, Vec<u8>)]) -> RamDisk {
+        let mut disk = RamDisk::new(blocks, 512);
+        for (block, sector) in sectors {
+            disk.write_block(*block, sector).unwrap();
+        }
+        disk
+    }
+
+    #[test_case]
+    fn test_mbr_with_logical_partitions() {
+        let mut mbr = vec![0u8; 512];
+        mbr_entry(&mut mbr, 0, 0x0C, 2048, 1024);
+        mbr_entry(&mut mbr, 1, 0x05, 4096, 4096);
+        let mut first = vec![0u8; 512];
+        mbr_entry(&mut first, 0, 0x83, 63, 100);
+        mbr_entry(&mut first, 1, 0x05, 1024, 1000);
+        let mut second = vec![0u8; 512];
+        mbr_entry(&mut second, 0, 0x82, 63, 200);
+        let disk = disk_with(8192, &[(0, mbr), (4096, first), (5120, second)]);
+
+        let found: Vec<(u32, u64, u64)> = read_table(&disk).unwrap().iter().map(|p| (p.number, p.start, p.blocks)).collect();
+        assert_eq!(found, [(1, 2048, 1024), (2, 4096, 4096), (5, 4159, 100), (6, 5183, 200)]);
+    }
+
+    #[test_case]
+    fn test_not_a_table() {
+        // A boot sector with code where the slots would be
+        let mut sector = vec![0x90u8; 512];
+        sector[510..512].copy_from_slice(&MBR_SIGNATURE.to_le_bytes());
+        assert_eq!(read_table(&disk_with(64, &[(0, sector)])), Ok(Vec::new()));
+        assert_eq!(read_table(&RamDisk::new(64, 512)), Ok(Vec::new()));
+    }
+
+    #[test_case]
+    fn test_gpt_falls_back_to_backup() {
+        let blocks = 256u64;
+        let mut mbr = vec![0u8; 512];
+        mbr_entry(&mut mbr, 0, MBR_PROTECTIVE, 1, u32::MAX);
+        let mut entries = vec![0u8; 512];
+        entries[..16].copy_from_slice(&[0xAF; 16]);
+        entries[32..40].copy_from_slice(&34u64.to_le_bytes());
+        entries[40..48].copy_from_slice(&99u64.to_le_bytes());
+        for (i, unit) in "boot".encode_utf16().enumerate() {
+            entries[56 + i * 2..58 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        let header = |at: u64, entries_at: u64| {
+            let mut header = vec![0u8; 512];
+            header[..8].copy_from_slice(GPT_SIGNATURE);
+            header[12..16].copy_from_slice(&(GPT_HEADER_SIZE as u32).to_le_bytes());
+            header[24..32].copy_from_slice(&at.to_le_bytes());
+            header[40..48].copy_from_slice(&34u64.to_le_bytes());
+            header[48..56].copy_from_slice(&(blocks - 34).to_le_bytes());
+            header[72..80].copy_from_slice(&entries_at.to_le_bytes());
+            header[80..84].copy_from_slice(&4u32.to_le_bytes());
+            header[84..88].copy_from_slice(&128u32.to_le_bytes());
+            header[88..92].copy_from_slice(&crc32(&entries).to_le_bytes());
+            let crc = crc32(&header[..GPT_HEADER_SIZE]);
+            header[16..20].copy_from_slice(&crc.to_le_bytes());
+            header
+        };
+        let mut primary = header(1, 2);
+        primary[40] ^= 1;
+        let backup = header(blocks - 1, blocks - 2);
+        let disk = disk_with(blocks, &[
+            (0, mbr), (1, primary), (2, entries.clone()), (blocks - 2, entries), (blocks - 1, backup),
+        ]);
+
+        let expected = Partition {
+            number: 1,
+            start: 34,
+            blocks: 66,
+            kind: Kind::Gpt { type_guid: [0xAF; 16], name: String::from("boot") },
+        };
+        assert_eq!(read_table(&disk), Ok(vec![expected]));
+    }
+}
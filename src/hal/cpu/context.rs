@@ -0,0 +1,93 @@
+// Execution context
+//
+// Tracks, per CPU, whether a hardware interrupt handler is running. Each
+// IRQ handler opens with `let _irq = context::enter_irq();`, and code that
+// must not run from one - allocating from the heap, sleeping, blocking on
+// another task - checks `in_interrupt()`. Exceptions and the syscall gate
+// run on behalf of the interrupted task and do not count.
+//
+// In debug builds `assert_may_sleep` and `assert_may_allocate` panic when
+// called from interrupt context, naming the culprit, rather than letting
+// it deadlock on a lock the interrupted code holds.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use super::lapic;
+
+pub const MAX_CPUS: usize = 4;
+
+/// Interrupt handlers running on each CPU, counting nested ones
+static IRQ_DEPTH: [AtomicU32; MAX_CPUS] = [const { AtomicU32::new(0) }; MAX_CPUS];
+
+/// This CPU's index, for per-CPU state
+pub fn cpu() -> usize {
+    if lapic::enabled() {
+        lapic::id() as usize % MAX_CPUS
+    } else {
+        0
+    }
+}
+
+/// Marks this CPU as running an interrupt handler until dropped
+pub struct IrqGuard {
+    cpu: usize,
+}
+
+pub fn enter_irq() -> IrqGuard {
+    let cpu = cpu();
+    IRQ_DEPTH[cpu].fetch_add(1, Ordering::Relaxed);
+    IrqGuard { cpu }
+}
+
+impl Drop for IrqGuard {
+    fn drop(&mut self) {
+        IRQ_DEPTH[self.cpu].fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn in_interrupt() -> bool {
+    IRQ_DEPTH[cpu()].load(Ordering::Relaxed) > 0
+}
+
+/// `what` may sleep or wait for another task, which an interrupt handler
+/// must never do
+#[track_caller]
+pub fn assert_may_sleep(what: &str) {
+    if cfg!(debug_assertions) && in_interrupt() {
+        fail(format_args!("{} would sleep in interrupt context", what));
+    }
+}
+
+/// `what` takes the heap lock, which the interrupted code may hold
+#[track_caller]
+pub fn assert_may_allocate(what: &str) {
+    if cfg!(debug_assertions) && in_interrupt() {
+        fail(format_args!("{} in interrupt context", what));
+    }
+}
+
+#[cold]
+#[track_caller]
+fn fail(message: core::fmt::Arguments) -> ! {
+    // Leave interrupt context first, so the panic handler may allocate
+    IRQ_DEPTH[cpu()].store(0, Ordering::Relaxed);
+    panic!("{}", message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_irq_guard_nests() {
+        assert!(!in_interrupt());
+        {
+            let _outer = enter_irq();
+            {
+                let _inner = enter_irq();
+                assert!(in_interrupt());
+            }
+            assert!(in_interrupt());
+        }
+        assert!(!in_interrupt());
+    }
+}
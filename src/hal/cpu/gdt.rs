@@ -17,32 +17,39 @@ static PAGE_FAULT_STACK: Stack = Stack([0; STACK_SIZE]);
 static GP_FAULT_STACK: Stack = Stack([0; STACK_SIZE]);
 static PRIVILEGE_STACK: Stack = Stack([0; STACK_SIZE]);
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            let stack_start = VirtAddr::from_ptr(unsafe { &DOUBLE_FAULT_STACK });
-            stack_start + STACK_SIZE
-        };
-        
-        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
-            let stack_start = VirtAddr::from_ptr(unsafe { &PAGE_FAULT_STACK });
-            stack_start + STACK_SIZE
-        };
-        
-        tss.interrupt_stack_table[GENERAL_PROTECTION_IST_INDEX as usize] = {
-            let stack_start = VirtAddr::from_ptr(unsafe { &GP_FAULT_STACK });
-            stack_start + STACK_SIZE
-        };
-        
-        tss.privilege_stack_table[0] = {
-            let stack_start = VirtAddr::from_ptr(unsafe { &PRIVILEGE_STACK });
-            stack_start + STACK_SIZE
-        };
-        
-        tss
+/// The CPU takes RSP0 from here each time an interrupt or exception comes
+/// from ring 3, so it is changed, by `set_kernel_stack`, whenever another
+/// program is entered. Filled in by `init` before it is loaded.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+fn init_tss() {
+    let tss = unsafe { &mut *core::ptr::addr_of_mut!(TSS) };
+
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        let stack_start = VirtAddr::from_ptr(&DOUBLE_FAULT_STACK);
+        stack_start + STACK_SIZE
     };
+
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = {
+        let stack_start = VirtAddr::from_ptr(&PAGE_FAULT_STACK);
+        stack_start + STACK_SIZE
+    };
+
+    tss.interrupt_stack_table[GENERAL_PROTECTION_IST_INDEX as usize] = {
+        let stack_start = VirtAddr::from_ptr(&GP_FAULT_STACK);
+        stack_start + STACK_SIZE
+    };
+
+    tss.privilege_stack_table[0] = {
+        let stack_start = VirtAddr::from_ptr(&PRIVILEGE_STACK);
+        stack_start + STACK_SIZE
+    };
+}
+
+/// The stack the CPU switches to on an interrupt from ring 3: the kernel
+/// stack of the program about to run
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe { (*core::ptr::addr_of_mut!(TSS)).privilege_stack_table[0] = top };
 }
 
 lazy_static! {
@@ -53,7 +60,7 @@ lazy_static! {
         let kernel_data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
         let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
         let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*core::ptr::addr_of!(TSS) }));
         
         (gdt, Selectors {
             kernel_code_selector,
@@ -78,6 +85,7 @@ pub fn init() {
     use x86_64::instructions::tables::load_tss;
     use x86_64::instructions::segmentation::{CS, DS, ES, SS, Segment};
     
+    init_tss();
     GDT.0.load();
     
     unsafe {
@@ -2,6 +2,7 @@ use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, Pag
 use lazy_static::lazy_static;
 use crate::{println, serial_println};
 use super::gdt;
+use crate::kernel::sys::posix::signals::{SIGFPE, SIGILL, SIGSEGV};
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
@@ -49,10 +50,31 @@ lazy_static! {
             .set_handler_fn(super::interrupts::timer_interrupt_handler);
         idt[super::interrupts::InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(super::interrupts::keyboard_interrupt_handler);
+        idt[super::interrupts::InterruptIndex::Com2.as_usize()]
+            .set_handler_fn(super::interrupts::com2_interrupt_handler);
+        idt[super::interrupts::InterruptIndex::Com1.as_usize()]
+            .set_handler_fn(super::interrupts::com1_interrupt_handler);
         idt[super::interrupts::InterruptIndex::PrimaryAta.as_usize()]
             .set_handler_fn(super::interrupts::primary_ata_handler);
         idt[super::interrupts::InterruptIndex::SecondaryAta.as_usize()]
             .set_handler_fn(super::interrupts::secondary_ata_handler);
+        #[cfg(feature = "framebuffer")]
+        idt[super::interrupts::InterruptIndex::Mouse.as_usize()]
+            .set_handler_fn(super::interrupts::mouse_interrupt_handler);
+        #[cfg(feature = "sound")]
+        {
+            idt[super::interrupts::InterruptIndex::Acpi.as_usize()]
+                .set_handler_fn(super::interrupts::pci_irq9_handler);
+            idt[super::interrupts::InterruptIndex::Available1.as_usize()]
+                .set_handler_fn(super::interrupts::pci_irq10_handler);
+            idt[super::interrupts::InterruptIndex::Available2.as_usize()]
+                .set_handler_fn(super::interrupts::pci_irq11_handler);
+        }
+        #[cfg(feature = "nvme")]
+        idt[super::interrupts::NVME_VECTOR as usize]
+            .set_handler_fn(super::interrupts::nvme_interrupt_handler);
+        idt[super::lapic::SPURIOUS_VECTOR as usize]
+            .set_handler_fn(super::interrupts::spurious_interrupt_handler);
         
         idt[0x80].set_handler_fn(syscall_handler);
         
@@ -64,7 +86,16 @@ pub fn init() {
     IDT.load();
 }
 
+/// Kill the program in ring 3 that raised an exception, rather than halt:
+/// the kernel is fine, only the program went wrong
+fn kill_if_user(stack_frame: &InterruptStackFrame, signal: i32, what: &str) {
+    if stack_frame.code_segment & 3 == 3 {
+        crate::kernel::sys::user_fault(signal, what, stack_frame.instruction_pointer.as_u64());
+    }
+}
+
 extern "x86-interrupt" fn divide_error_handler(stack_frame: InterruptStackFrame) {
+    kill_if_user(&stack_frame, SIGFPE, "divide error");
     println!("EXCEPTION: DIVIDE BY ZERO");
     println!("{:#?}", stack_frame);
     crate::hlt_loop();
@@ -98,6 +129,7 @@ extern "x86-interrupt" fn bound_range_handler(stack_frame: InterruptStackFrame)
 }
 
 extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    kill_if_user(&stack_frame, SIGILL, "invalid opcode");
     println!("EXCEPTION: INVALID OPCODE");
     println!("{:#?}", stack_frame);
     crate::hlt_loop();
@@ -149,6 +181,7 @@ extern "x86-interrupt" fn general_protection_handler(
     stack_frame: InterruptStackFrame,
     error_code: u64,
 ) {
+    kill_if_user(&stack_frame, SIGSEGV, "general protection fault");
     println!("EXCEPTION: GENERAL PROTECTION FAULT (error code: {})", error_code);
     println!("{:#?}", stack_frame);
     serial_println!("GPF: error_code={}, {:#?}", error_code, stack_frame);
@@ -160,7 +193,9 @@ extern "x86-interrupt" fn page_fault_handler(
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2;
-    
+
+    crate::tracepoint!(PageFault, Cr2::read_raw(), error_code.bits(), stack_frame.instruction_pointer.as_u64());
+    kill_if_user(&stack_frame, SIGSEGV, "page fault");
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
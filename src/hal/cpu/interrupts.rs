@@ -6,6 +6,9 @@ use lazy_static::lazy_static;
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+/// MSI-X vector of NVMe completions, above the PIC range
+pub const NVME_VECTOR: u8 = 0x50;
+
 lazy_static! {
     pub static ref PICS: Mutex<ChainedPics> = Mutex::new(unsafe {
         ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET)
@@ -44,16 +47,18 @@ impl InterruptIndex {
 }
 
 pub extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::context::enter_irq();
     crate::hal::drivers::pit::tick();
     
     unsafe {
         PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
     
-    crate::kernel::scheduler::schedule();
+    crate::kernel::scheduler::preempt();
 }
 
 pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::context::enter_irq();
     use x86_64::instructions::port::Port;
 
     let mut port = Port::new(0x60);
@@ -66,18 +71,85 @@ pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: Interrupt
     }
 }
 
+/// COM2 and COM4, which share the line
+pub extern "x86-interrupt" fn com2_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::context::enter_irq();
+    crate::hal::drivers::serial::handle_interrupt(3);
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(InterruptIndex::Com2.as_u8());
+    }
+}
+
+/// COM1 and COM3, which share the line
+pub extern "x86-interrupt" fn com1_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::context::enter_irq();
+    crate::hal::drivers::serial::handle_interrupt(4);
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(InterruptIndex::Com1.as_u8());
+    }
+}
+
 pub extern "x86-interrupt" fn primary_ata_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::context::enter_irq();
     unsafe {
         PICS.lock().notify_end_of_interrupt(InterruptIndex::PrimaryAta.as_u8());
     }
 }
 
 pub extern "x86-interrupt" fn secondary_ata_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::context::enter_irq();
     unsafe {
         PICS.lock().notify_end_of_interrupt(InterruptIndex::SecondaryAta.as_u8());
     }
 }
 
+#[cfg(feature = "framebuffer")]
+pub extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::context::enter_irq();
+    crate::hal::drivers::mouse::handle_interrupt();
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+    }
+}
+
+/// AC'97 completions on whichever PCI line the firmware gave it
+#[cfg(feature = "sound")]
+fn pci_interrupt(index: InterruptIndex) {
+    let _irq = super::context::enter_irq();
+    crate::hal::drivers::ac97::handle_interrupt();
+
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(index.as_u8());
+    }
+}
+
+#[cfg(feature = "sound")]
+pub extern "x86-interrupt" fn pci_irq9_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(InterruptIndex::Acpi);
+}
+
+#[cfg(feature = "sound")]
+pub extern "x86-interrupt" fn pci_irq10_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(InterruptIndex::Available1);
+}
+
+#[cfg(feature = "sound")]
+pub extern "x86-interrupt" fn pci_irq11_handler(_stack_frame: InterruptStackFrame) {
+    pci_interrupt(InterruptIndex::Available2);
+}
+
+/// Only wakes the task waiting in hlt; it reaps its own completion
+#[cfg(feature = "nvme")]
+pub extern "x86-interrupt" fn nvme_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let _irq = super::context::enter_irq();
+    super::lapic::eoi();
+}
+
+pub extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
 pub fn enable() {
     x86_64::instructions::interrupts::enable();
 }
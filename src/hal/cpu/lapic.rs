@@ -0,0 +1,77 @@
+// Local APIC
+//
+// Device interrupts still come through the 8259 PICs. The local APIC is
+// only the target of message-signalled interrupts (MSI-X), which bypass
+// the PICs and must be acknowledged here instead. enable() leaves the APIC
+// in virtual wire mode, so PIC interrupts keep arriving through LINT0.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::model_specific::Msr;
+use x86_64::PhysAddr;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const BASE_MASK: u64 = 0xF_FFFF_F000;
+
+const REG_ID: u64 = 0x20;
+const REG_EOI: u64 = 0xB0;
+const REG_SVR: u64 = 0xF0;
+const REG_LVT_LINT0: u64 = 0x350;
+const REG_LVT_LINT1: u64 = 0x360;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const DELIVERY_EXTINT: u32 = 0x700;
+const DELIVERY_NMI: u32 = 0x400;
+
+/// Vector of the interrupts the APIC raises on its own; they need no EOI
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// MSI messages are writes to this address plus the APIC id << 12
+pub const MSI_ADDRESS: u64 = 0xFEE0_0000;
+
+/// Virtual address of the APIC registers, 0 until enable()
+static BASE: AtomicU64 = AtomicU64::new(0);
+
+fn read(reg: u64) -> u32 {
+    unsafe { core::ptr::read_volatile((BASE.load(Ordering::Relaxed) + reg) as *const u32) }
+}
+
+fn write(reg: u64, value: u32) {
+    unsafe { core::ptr::write_volatile((BASE.load(Ordering::Relaxed) + reg) as *mut u32, value) }
+}
+
+/// Software-enable the APIC if the firmware has not, wired up as the PICs
+/// expect. False if paging is not set up yet.
+pub fn enable() -> bool {
+    if BASE.load(Ordering::Relaxed) != 0 {
+        return true;
+    }
+    let phys = unsafe { Msr::new(IA32_APIC_BASE).read() } & BASE_MASK;
+    let virt = match crate::hal::memory::paging::phys_to_virt(PhysAddr::new(phys)) {
+        Some(virt) => virt,
+        None => return false,
+    };
+    BASE.store(virt.as_u64(), Ordering::Relaxed);
+    let svr = read(REG_SVR);
+    if svr & SVR_ENABLE == 0 {
+        write(REG_LVT_LINT0, DELIVERY_EXTINT);
+        write(REG_LVT_LINT1, DELIVERY_NMI);
+        write(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+    }
+    true
+}
+
+/// Whether enable() has mapped the APIC, so its registers can be read
+pub fn enabled() -> bool {
+    BASE.load(Ordering::Relaxed) != 0
+}
+
+pub fn id() -> u8 {
+    (read(REG_ID) >> 24) as u8
+}
+
+/// Acknowledge an interrupt delivered through the APIC
+pub fn eoi() {
+    if BASE.load(Ordering::Relaxed) != 0 {
+        write(REG_EOI, 0);
+    }
+}
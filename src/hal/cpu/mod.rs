@@ -1,6 +1,9 @@
 pub mod gdt;
 pub mod idt;
 pub mod interrupts;
+pub mod lapic;
+pub mod context;
+pub mod usermode;
 
 pub use gdt::init;
 pub use interrupts::*;
@@ -0,0 +1,238 @@
+// User mode
+//
+// Running a program in ring 3 and getting back from it. `enter` saves the
+// kernel's callee-saved registers, flags and stack pointer, points RSP0 in
+// the TSS and the syscall stack at the program's kernel stack, and irets
+// to its entry point on the user code and stack selectors. `leave` -
+// called when the program exits or faults - unwinds to that saved stack,
+// so `enter` returns to its caller with a wait status, as if it had been
+// an ordinary call. An exec from ring 3 `replace`s the running program
+// instead: the syscall unwinds as usual, and on its way out, rather than
+// return to the old program, jumps to the new one, keeping the way back
+// that the outer `enter` saved.
+//
+// A program calls the kernel with the `syscall` instruction. LSTAR points
+// it at `qunix_syscall_entry`, which swaps onto the kernel stack, saves
+// the registers a syscall must preserve and hands a SyscallFrame to the
+// kernel; sysretq goes back with the result in rax. SFMASK clears IF on
+// entry, so nothing interrupts before the stack is the kernel's.
+//
+// Interrupts and exceptions from ring 3 arrive on RSP0, or their IST
+// stack. The selectors are those Context::new_user uses: user data at
+// 0x1b and user code at 0x23, 0x10 and 0x08 apart from the kernel's, as
+// STAR needs.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::PhysFrame;
+use x86_64::VirtAddr;
+use crate::hal::memory::address_space;
+use super::gdt;
+
+pub const USER_CODE_SELECTOR: u64 = 0x23;
+pub const USER_DATA_SELECTOR: u64 = 0x1b;
+
+/// What a program had in its registers when it made a syscall, lowest
+/// address first, as `qunix_syscall_entry` pushes them. `rax` carries the
+/// number in and the result out.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SyscallFrame {
+    pub rax: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub r10: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rflags: u64,
+    pub rip: u64,
+    pub rsp: u64,
+}
+
+/// Kernel stack pointer saved by `enter`, for `leave` to go back to
+static mut USER_RETURN_RSP: u64 = 0;
+/// Top of the kernel stack syscalls run on
+static mut SYSCALL_STACK: u64 = 0;
+/// The program's stack pointer, while `qunix_syscall_entry` swaps stacks
+static mut SYSCALL_USER_RSP: u64 = 0;
+
+/// Whether a program is running, so exec knows to replace it rather than
+/// enter a new one
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+core::arch::global_asm!(
+    ".global qunix_enter_user",
+    "qunix_enter_user:",
+    "push rbx",
+    "push rbp",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "pushfq",
+    "mov [rip + {return_rsp}], rsp",
+    ".global qunix_jump_user",
+    "qunix_jump_user:",
+    "cli",
+    "push {data}",
+    "push rsi",
+    "push 0x202",
+    "push {code}",
+    "push rdi",
+    "xor eax, eax",
+    "xor ebx, ebx",
+    "xor ecx, ecx",
+    "xor edx, edx",
+    "xor esi, esi",
+    "xor edi, edi",
+    "xor ebp, ebp",
+    "xor r8d, r8d",
+    "xor r9d, r9d",
+    "xor r10d, r10d",
+    "xor r11d, r11d",
+    "xor r12d, r12d",
+    "xor r13d, r13d",
+    "xor r14d, r14d",
+    "xor r15d, r15d",
+    "iretq",
+
+    ".global qunix_leave_user",
+    "qunix_leave_user:",
+    "mov rsp, [rip + {return_rsp}]",
+    "popfq",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbp",
+    "pop rbx",
+    "mov eax, edi",
+    "ret",
+
+    ".global qunix_syscall_entry",
+    "qunix_syscall_entry:",
+    "mov [rip + {user_rsp}], rsp",
+    "mov rsp, [rip + {stack}]",
+    "push [rip + {user_rsp}]",
+    "push rcx",
+    "push r11",
+    "push rdi",
+    "push rsi",
+    "push rdx",
+    "push r10",
+    "push r8",
+    "push r9",
+    "push rax",
+    "mov rdi, rsp",
+    "call {handler}",
+    "pop rax",
+    "pop r9",
+    "pop r8",
+    "pop r10",
+    "pop rdx",
+    "pop rsi",
+    "pop rdi",
+    "pop r11",
+    "pop rcx",
+    "pop rsp",
+    "sysretq",
+    data = const USER_DATA_SELECTOR,
+    code = const USER_CODE_SELECTOR,
+    handler = sym syscall_handler,
+    return_rsp = sym USER_RETURN_RSP,
+    user_rsp = sym SYSCALL_USER_RSP,
+    stack = sym SYSCALL_STACK,
+);
+
+extern "C" {
+    fn qunix_enter_user(entry: u64, stack: u64) -> i32;
+    fn qunix_jump_user(entry: u64, stack: u64) -> !;
+    fn qunix_leave_user(status: i32) -> !;
+    fn qunix_syscall_entry();
+}
+
+extern "C" fn syscall_handler(frame: &mut SyscallFrame) {
+    x86_64::instructions::interrupts::enable();
+    crate::kernel::sys::handle_user_syscall(frame);
+    x86_64::instructions::interrupts::disable();
+}
+
+/// Turn on the syscall instruction. The GDT must be loaded.
+pub fn init() {
+    let selectors = gdt::get_selectors();
+    debug_assert_eq!(selectors.user_code_selector.0 as u64, USER_CODE_SELECTOR);
+    debug_assert_eq!(selectors.user_data_selector.0 as u64, USER_DATA_SELECTOR);
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.kernel_code_selector,
+        selectors.kernel_data_selector,
+    ).expect("GDT order does not suit syscall/sysret");
+    LStar::write(VirtAddr::new(qunix_syscall_entry as *const () as u64));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// Whether a program is running in ring 3, or in a syscall from it
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Where a program starts
+#[derive(Debug, Clone, Copy)]
+pub struct Start {
+    /// Level 4 table of its address space
+    pub pml4: PhysFrame,
+    pub entry: u64,
+    pub stack: u64,
+    /// Top of the kernel stack its interrupts and syscalls land on
+    pub kernel_stack: u64,
+}
+
+/// The program an exec from ring 3 replaces the running one by
+static REPLACEMENT: Mutex<Option<Start>> = Mutex::new(None);
+
+fn prepare(start: &Start) {
+    gdt::set_kernel_stack(VirtAddr::new(start.kernel_stack));
+    unsafe { SYSCALL_STACK = start.kernel_stack };
+    address_space::activate(start.pml4);
+    ACTIVE.store(true, Ordering::SeqCst);
+}
+
+/// Run a program until it exits or faults. Returns its wait status: the
+/// exit code shifted left by 8, or the number of the signal that killed
+/// it.
+pub fn enter(start: Start) -> i32 {
+    prepare(&start);
+    unsafe { qunix_enter_user(start.entry, start.stack) }
+}
+
+/// Have the syscall being made by the running program return to `start`
+/// instead; the `enter` that started the first returns when it is done
+pub fn replace(start: Start) {
+    debug_assert!(active());
+    *REPLACEMENT.lock() = Some(start);
+}
+
+/// At the end of a syscall, with nothing left on the kernel stack to drop,
+/// go to the program `replace` asked for, if it did
+pub fn jump_if_replaced() {
+    let Some(start) = REPLACEMENT.lock().take() else {
+        return;
+    };
+    prepare(&start);
+    unsafe { qunix_jump_user(start.entry, start.stack) }
+}
+
+/// Stop the running program, returning `status` from the `enter` that
+/// started it, on the kernel's own page tables
+pub fn leave(status: i32) -> ! {
+    debug_assert!(active());
+    ACTIVE.store(false, Ordering::SeqCst);
+    REPLACEMENT.lock().take();
+    address_space::activate_kernel();
+    unsafe { qunix_leave_user(status) }
+}
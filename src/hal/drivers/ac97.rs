@@ -0,0 +1,345 @@
+// AC'97 audio
+//
+// Controllers on PCI class 04:01, such as the ICH's and QEMU's -device
+// AC97. The mixer (NAM) and the bus master (NABM) are both I/O BARs; only
+// the PCM out channel is used. It plays a ring of 32 heap pages through
+// the buffer descriptor list, each holding 1024 frames of 16-bit stereo.
+// write() fills the page after the last valid one and moves the last
+// valid index onto it, and the engine, if it had run dry and halted,
+// carries on from there.
+//
+// Each buffer raises an interrupt on completion when the controller's
+// line is one of the PCI lines the PIC routes (9 to 11). The handler only
+// clears the status; writers waiting for a free buffer sleep in hlt and
+// look at the current index themselves, which also works on the timer
+// tick when there is no interrupt.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
+use crate::hal::cpu::interrupts::set_irq_mask;
+use crate::hal::memory::paging;
+use super::audio::{self, AudioDevice, Frame};
+use super::pci::{self, PciDevice};
+use super::pit;
+
+const PAGE_SIZE: usize = 4096;
+const BUFFERS: usize = 32;
+const FRAME_SIZE: usize = 4;
+const BUFFER_FRAMES: usize = PAGE_SIZE / FRAME_SIZE;
+
+// Mixer registers
+const NAM_RESET: u16 = 0x00;
+const NAM_MASTER_VOLUME: u16 = 0x02;
+const NAM_PCM_OUT_VOLUME: u16 = 0x18;
+const NAM_EXT_AUDIO_ID: u16 = 0x28;
+const NAM_EXT_AUDIO_CTRL: u16 = 0x2A;
+const NAM_FRONT_DAC_RATE: u16 = 0x2C;
+/// Variable rate audio, in the extended ID and control registers
+const EXT_VRA: u16 = 0x01;
+/// 0 dB on both channels, unmuted
+const VOLUME_0DB: u16 = 0x0808;
+
+// Bus master registers of the PCM out channel, and the global ones
+const PO_BDBAR: u16 = 0x10;
+const PO_CIV: u16 = 0x14;
+const PO_LVI: u16 = 0x15;
+const PO_SR: u16 = 0x16;
+const PO_CR: u16 = 0x1B;
+const GLOB_CNT: u16 = 0x2C;
+const GLOB_STA: u16 = 0x30;
+
+const CR_RUN: u8 = 0x01;
+const CR_RESET: u8 = 0x02;
+const CR_IOC_ENABLE: u8 = 0x10;
+const SR_HALTED: u16 = 0x01;
+/// Last valid buffer, completion and FIFO error: write 1 to clear
+const SR_CLEAR: u16 = 0x1C;
+/// Out of cold reset
+const GLOB_CNT_COLD: u32 = 0x02;
+const GLOB_STA_CODEC_READY: u32 = 0x100;
+
+const BD_IOC: u16 = 1 << 15;
+
+/// Rate when the codec has no variable rate support
+const FIXED_RATE: u32 = 48000;
+const MIN_RATE: u32 = 8000;
+/// How long the engine may sit on one buffer before it is declared stuck
+const STALL_MS: u64 = 1000;
+const CODEC_TIMEOUT_MS: u64 = 100;
+const CASCADE_IRQ: u8 = 2;
+
+/// Bus master base for the interrupt handler, 0 if none
+static NABM: AtomicU16 = AtomicU16::new(0);
+
+#[repr(C, align(4096))]
+struct Page([u8; PAGE_SIZE]);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct BufferDescriptor {
+    addr: u32,
+    /// Length in 16-bit samples, not frames
+    samples: u16,
+    flags: u16,
+}
+
+#[repr(C, align(4096))]
+struct DescriptorList([BufferDescriptor; BUFFERS]);
+
+/// Physical address of heap memory, which the bus master needs below 4 GiB
+fn dma_addr<T>(value: &T) -> Result<u32, &'static str> {
+    let phys = paging::translate_addr(VirtAddr::from_ptr(value)).ok_or("cannot map DMA memory")?;
+    u32::try_from(phys.as_u64()).map_err(|_| "DMA memory above 4 GiB")
+}
+
+struct Ac97 {
+    nam: u16,
+    nabm: u16,
+    vra: bool,
+    /// Completion interrupts arrive
+    irq: bool,
+    list: Box<DescriptorList>,
+    pages: Vec<Box<Page>>,
+    /// Page being filled, just after the last valid one
+    tail: usize,
+    /// Frames in it so far
+    fill: usize,
+}
+
+impl Ac97 {
+    fn nam_write(&self, reg: u16, value: u16) {
+        unsafe { Port::<u16>::new(self.nam + reg).write(value) }
+    }
+
+    fn nam_read(&self, reg: u16) -> u16 {
+        unsafe { Port::<u16>::new(self.nam + reg).read() }
+    }
+
+    fn civ(&self) -> usize {
+        unsafe { Port::<u8>::new(self.nabm + PO_CIV).read() as usize % BUFFERS }
+    }
+
+    fn status(&self) -> u16 {
+        unsafe { Port::<u16>::new(self.nabm + PO_SR).read() }
+    }
+
+    fn control(&self) -> u8 {
+        unsafe { Port::<u8>::new(self.nabm + PO_CR).read() }
+    }
+
+    fn set_control(&self, value: u8) {
+        unsafe { Port::<u8>::new(self.nabm + PO_CR).write(value) }
+    }
+
+    /// Buffers handed to the engine and not yet played, counting the one
+    /// it is on
+    fn queued(&self) -> usize {
+        if self.control() & CR_RUN == 0 || self.status() & SR_HALTED != 0 {
+            return 0;
+        }
+        (self.tail + BUFFERS - self.civ()) % BUFFERS
+    }
+
+    fn new(nam: u16, nabm: u16, irq: bool) -> Result<Ac97, &'static str> {
+        unsafe { Port::<u32>::new(nabm + GLOB_CNT).write(GLOB_CNT_COLD) };
+        let timer = pit::Timer::new(CODEC_TIMEOUT_MS);
+        while unsafe { Port::<u32>::new(nabm + GLOB_STA).read() } & GLOB_STA_CODEC_READY == 0 {
+            if timer.is_expired() {
+                return Err("codec not ready");
+            }
+            core::hint::spin_loop();
+        }
+
+        let mut list = Box::new(DescriptorList([BufferDescriptor::default(); BUFFERS]));
+        let mut pages = Vec::with_capacity(BUFFERS);
+        for descriptor in list.0.iter_mut() {
+            let page = Box::new(Page([0; PAGE_SIZE]));
+            descriptor.addr = dma_addr(&*page)?;
+            descriptor.flags = if irq { BD_IOC } else { 0 };
+            pages.push(page);
+        }
+        let mut ac97 = Ac97 { nam, nabm, vra: false, irq, list, pages, tail: 0, fill: 0 };
+        ac97.nam_write(NAM_RESET, 0);
+        ac97.nam_write(NAM_MASTER_VOLUME, 0);
+        ac97.nam_write(NAM_PCM_OUT_VOLUME, VOLUME_0DB);
+        ac97.vra = ac97.nam_read(NAM_EXT_AUDIO_ID) & EXT_VRA != 0;
+        if ac97.vra {
+            ac97.nam_write(NAM_EXT_AUDIO_CTRL, ac97.nam_read(NAM_EXT_AUDIO_CTRL) | EXT_VRA);
+        }
+        ac97.reset()?;
+        Ok(ac97)
+    }
+
+    /// Stop the engine and point it at the start of an empty ring
+    fn reset(&mut self) -> Result<(), &'static str> {
+        self.set_control(0);
+        self.set_control(CR_RESET);
+        let timer = pit::Timer::new(CODEC_TIMEOUT_MS);
+        while self.control() & CR_RESET != 0 {
+            if timer.is_expired() {
+                return Err("bus master reset timed out");
+            }
+            core::hint::spin_loop();
+        }
+        let list = dma_addr(&*self.list)?;
+        unsafe { Port::<u32>::new(self.nabm + PO_BDBAR).write(list) };
+        self.tail = 0;
+        self.fill = 0;
+        Ok(())
+    }
+
+    /// Sleep until `ready` holds, or fail if the engine stops moving
+    fn wait(&self, ready: impl Fn(&Ac97) -> bool) -> Result<(), &'static str> {
+        let mut timer = pit::Timer::new(STALL_MS);
+        let mut civ = self.civ();
+        loop {
+            // As in the NVMe driver: check and sleep with interrupts off
+            // until the hlt so the completion cannot slip in between
+            let sleep = interrupts::are_enabled();
+            if sleep {
+                interrupts::disable();
+            }
+            if ready(self) {
+                if sleep {
+                    interrupts::enable();
+                }
+                return Ok(());
+            }
+            if sleep {
+                interrupts::enable_and_hlt();
+            } else {
+                core::hint::spin_loop();
+            }
+            if self.civ() != civ {
+                civ = self.civ();
+                timer.reset();
+            } else if timer.is_expired() {
+                return Err("playback stalled");
+            }
+        }
+    }
+
+    /// Hand the tail page to the engine, starting it if it is idle
+    fn commit(&mut self) -> Result<(), &'static str> {
+        self.wait(|ac97| ac97.queued() < BUFFERS - 1)?;
+        self.list.0[self.tail].samples = (self.fill * 2) as u16;
+        core::sync::atomic::fence(Ordering::SeqCst);
+        unsafe { Port::<u8>::new(self.nabm + PO_LVI).write(self.tail as u8) };
+        self.tail = (self.tail + 1) % BUFFERS;
+        self.fill = 0;
+        let control = self.control();
+        if control & CR_RUN == 0 {
+            self.set_control(control | CR_RUN | if self.irq { CR_IOC_ENABLE } else { 0 });
+        }
+        Ok(())
+    }
+}
+
+impl AudioDevice for Ac97 {
+    fn name(&self) -> &'static str {
+        "ac97"
+    }
+
+    fn set_rate(&mut self, rate: u32) -> u32 {
+        if !self.vra {
+            return FIXED_RATE;
+        }
+        self.nam_write(NAM_FRONT_DAC_RATE, rate.clamp(MIN_RATE, FIXED_RATE) as u16);
+        self.nam_read(NAM_FRONT_DAC_RATE) as u32
+    }
+
+    fn write(&mut self, frames: &[Frame]) -> Result<(), &'static str> {
+        let mut frames = frames;
+        while !frames.is_empty() {
+            let n = frames.len().min(BUFFER_FRAMES - self.fill);
+            let page = &mut self.pages[self.tail].0[self.fill * FRAME_SIZE..];
+            for (bytes, frame) in page.chunks_exact_mut(FRAME_SIZE).zip(&frames[..n]) {
+                bytes[..2].copy_from_slice(&frame[0].to_le_bytes());
+                bytes[2..].copy_from_slice(&frame[1].to_le_bytes());
+            }
+            self.fill += n;
+            frames = &frames[n..];
+            if self.fill == BUFFER_FRAMES {
+                self.commit()?;
+            }
+        }
+        // Keep the engine fed when writes come in small pieces
+        if self.fill > 0 && self.queued() < 2 {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self) {
+        let played = if self.fill > 0 { self.commit() } else { Ok(()) };
+        if played.and_then(|_| self.wait(|ac97| ac97.queued() == 0)).is_err() {
+            self.stop();
+        }
+    }
+
+    fn stop(&mut self) {
+        if self.reset().is_err() {
+            crate::println!("  [AC97] Bus master did not reset");
+        }
+    }
+}
+
+/// Called on the controller's PCI interrupt line
+pub fn handle_interrupt() {
+    let nabm = NABM.load(Ordering::Relaxed);
+    if nabm == 0 {
+        return;
+    }
+    let mut status = Port::<u16>::new(nabm + PO_SR);
+    unsafe {
+        let pending = status.read() & SR_CLEAR;
+        if pending != 0 {
+            status.write(pending);
+        }
+    }
+}
+
+fn probe(device: &PciDevice) -> Result<(), &'static str> {
+    if !pci::is_bar_io(device.bar[0]) || !pci::is_bar_io(device.bar[1]) {
+        return Err("mixer and bus master are not I/O BARs");
+    }
+    let nam = pci::get_bar_address(device.bar[0]) as u16;
+    let nabm = pci::get_bar_address(device.bar[1]) as u16;
+    pci::enable_io_space(device);
+    pci::enable_bus_mastering(device);
+
+    let line = device.interrupt_line;
+    let irq = (9..=11).contains(&line);
+    let ac97 = Ac97::new(nam, nabm, irq)?;
+    let delivery = if irq {
+        NABM.store(nabm, Ordering::Relaxed);
+        set_irq_mask(CASCADE_IRQ, false);
+        set_irq_mask(line, false);
+        format!("IRQ {}", line)
+    } else {
+        String::from("polled")
+    };
+    let rates = if ac97.vra { "variable rate" } else { "48 kHz only" };
+    crate::println!("  [AC97] {} at {:04x}/{:04x}, {}, {}", device.address, nam, nabm, rates, delivery);
+    audio::register(Box::new(ac97));
+    Ok(())
+}
+
+pub fn init() {
+    let device = match pci::find_devices_by_class(0x04, 0x01).into_iter().next() {
+        Some(device) => device,
+        None => {
+            crate::println!("  [AC97] No controller found");
+            return;
+        }
+    };
+    if let Err(e) = probe(&device) {
+        crate::println!("  [AC97] {}: {}", device.address, e);
+    }
+}
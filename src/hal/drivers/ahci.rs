@@ -1,7 +1,32 @@
+// AHCI SATA disks
+//
+// Controllers on PCI class 01:06:01. Each port with a SATA disk gets one
+// page for its command list, received FIS area and the table of its one
+// command slot, and a bounce buffer of BOUNCE_PAGES pages described one
+// page per PRDT entry, so no command moves more than that. The disk is
+// IDENTIFYed and, if it has LBA48, becomes a BlockDevice named sda, sdb,
+// ... behind a request queue, from disk(), moving data with READ and
+// WRITE DMA EXT.
+//
+// One command is in flight per port, and completion is polled: port
+// interrupts stay off. Without 64-bit addressing (CAP.S64A) every page
+// the port uses must lie below 4 GiB, or the disk is skipped.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use spin::Mutex;
+use spin::{Mutex, RwLock};
 use lazy_static::lazy_static;
-use crate::hal::drivers::pci::{PciDevice, find_ahci_controllers, enable_bus_mastering, enable_memory_space, get_bar_address};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::fs::ext4::ext4::BlockDevice;
+use crate::fs::iosched::RequestQueue;
+use crate::hal::drivers::pci::{self, PciDevice, find_ahci_controllers, enable_bus_mastering, enable_memory_space};
+use crate::fs::vfs::node::DeviceId;
+use crate::hal::drivers::pit;
+use crate::hal::drivers::smart::{Identity, IdentifyWords};
+use crate::hal::memory::paging;
+use crate::kernel::trace;
 use crate::println;
 
 const AHCI_CAP: u32 = 0x00;
@@ -34,6 +59,34 @@ const PORT_CMD_FRE: u32 = 1 << 4;
 const PORT_CMD_FR: u32 = 1 << 14;
 const PORT_CMD_CR: u32 = 1 << 15;
 
+const PORT_IS_TFES: u32 = 1 << 30;
+
+const TFD_ERR: u32 = 1 << 0;
+const TFD_DRQ: u32 = 1 << 3;
+const TFD_BSY: u32 = 1 << 7;
+
+const CAP_S64A: u32 = 1 << 31;
+
+const PAGE_SIZE: usize = 4096;
+const SECTOR_SIZE: usize = 512;
+
+/// Pages in a port's bounce buffer, and so PRDT entries in its table
+const BOUNCE_PAGES: usize = 8;
+/// Largest transfer one command makes
+const MAX_TRANSFER: usize = BOUNCE_PAGES * PAGE_SIZE;
+
+/// Where the received FIS area and the command table go in a port's page;
+/// the command list takes the first 1 KiB
+const FIS_OFFSET: usize = 0x400;
+const TABLE_OFFSET: usize = 0x800;
+/// The PRDT, after the command FIS, ATAPI command and reserved bytes
+const PRDT_OFFSET: usize = 0x80;
+
+const COMMAND_TIMEOUT_MS: u64 = 5000;
+
+/// Block major number Linux uses for SCSI and SATA disks
+const SD_MAJOR: u16 = 8;
+
 const SATA_SIG_ATA: u32 = 0x00000101;
 const SATA_SIG_ATAPI: u32 = 0xEB140101;
 const SATA_SIG_SEMB: u32 = 0xC33C0101;
@@ -59,6 +112,7 @@ pub struct AhciPort {
 #[derive(Debug, Clone)]
 pub struct AhciController {
     pub pci_device: PciDevice,
+    /// Virtual address of the ABAR (BAR5)
     pub abar: u64,
     pub ports: Vec<AhciPort>,
     pub version: u32,
@@ -142,6 +196,307 @@ fn start_port(abar: u64, port: u8) {
     write_port_reg(abar, port, PORT_CMD, cmd | PORT_CMD_FRE | PORT_CMD_ST);
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AhciError {
+    /// The port stayed busy or did not complete a command
+    Timeout,
+    /// The disk reported an error; the task file status and error
+    Device(u8, u8),
+    /// The ABAR could not be mapped or the port's memory is out of reach
+    NoMemory,
+    /// The disk has no LBA48, which READ and WRITE DMA EXT need
+    NoLba48,
+    OutOfRange,
+}
+
+impl AhciError {
+    pub fn message(self) -> &'static str {
+        match self {
+            AhciError::Timeout => "port timed out",
+            AhciError::Device(..) => "disk reported an error",
+            AhciError::NoMemory => "cannot map controller memory",
+            AhciError::NoLba48 => "disk lacks LBA48",
+            AhciError::OutOfRange => "sector out of range",
+        }
+    }
+}
+
+#[repr(C, align(4096))]
+struct Page([u8; PAGE_SIZE]);
+
+/// A zeroed heap page the controller can reach by its physical address
+struct DmaPage {
+    page: Box<Page>,
+    phys: u64,
+}
+
+impl DmaPage {
+    fn new(dma64: bool) -> Result<DmaPage, AhciError> {
+        let page = Box::new(Page([0; PAGE_SIZE]));
+        let phys = paging::translate_addr(VirtAddr::from_ptr(&*page)).ok_or(AhciError::NoMemory)?.as_u64();
+        if !dma64 && phys >> 32 != 0 {
+            return Err(AhciError::NoMemory);
+        }
+        Ok(DmaPage { page, phys })
+    }
+
+    fn write<T>(&mut self, offset: usize, value: T) {
+        assert!(offset + core::mem::size_of::<T>() <= PAGE_SIZE);
+        unsafe { core::ptr::write_volatile(self.page.0.as_mut_ptr().add(offset) as *mut T, value) }
+    }
+}
+
+/// A command list entry
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct CommandHeader {
+    /// FIS length in dwords, write in bit 6, PRDT entries in the top half
+    flags: u32,
+    /// Bytes transferred, which the controller fills in
+    prdbc: u32,
+    ctba: u64,
+    reserved: [u32; 4],
+}
+
+/// A PRDT entry: one region of memory for the transfer
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Prd {
+    dba: u64,
+    reserved: u32,
+    /// Bytes less one
+    dbc: u32,
+}
+
+/// A SATA disk's port, driven through its first command slot
+struct Port {
+    abar: u64,
+    port: u8,
+    /// Command list, received FISes and the command table; see FIS_OFFSET
+    memory: DmaPage,
+    bounce: Vec<DmaPage>,
+}
+
+impl Port {
+    fn new(abar: u64, port: u8, dma64: bool) -> Result<Port, AhciError> {
+        let bounce = (0..BOUNCE_PAGES).map(|_| DmaPage::new(dma64)).collect::<Result<Vec<_>, _>>()?;
+        Ok(Port { abar, port, memory: DmaPage::new(dma64)?, bounce })
+    }
+
+    fn read(&self, offset: u32) -> u32 {
+        read_port_reg(self.abar, self.port, offset)
+    }
+
+    fn write(&self, offset: u32, value: u32) {
+        write_port_reg(self.abar, self.port, offset, value)
+    }
+
+    /// Point the port at its memory and start it
+    fn start(&self) -> Result<(), AhciError> {
+        stop_port(self.abar, self.port);
+        let (list, fis) = (self.memory.phys, self.memory.phys + FIS_OFFSET as u64);
+        self.write(PORT_CLB, list as u32);
+        self.write(PORT_CLBU, (list >> 32) as u32);
+        self.write(PORT_FB, fis as u32);
+        self.write(PORT_FBU, (fis >> 32) as u32);
+        self.write(PORT_IE, 0);
+        self.write(PORT_SERR, !0);
+        self.write(PORT_IS, !0);
+        start_port(self.abar, self.port);
+        self.wait_idle()
+    }
+
+    /// Wait for the disk to be neither busy nor asking for data
+    fn wait_idle(&self) -> Result<(), AhciError> {
+        let timer = pit::Timer::new(COMMAND_TIMEOUT_MS);
+        while self.read(PORT_TFD) & (TFD_BSY | TFD_DRQ) != 0 {
+            if timer.is_expired() {
+                return Err(AhciError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Issue `fis` in slot 0 moving `bytes` to or from the bounce buffer,
+    /// and wait for it
+    fn execute(&mut self, fis: FisRegH2D, bytes: usize, write: bool) -> Result<(), AhciError> {
+        let prds = bytes.div_ceil(PAGE_SIZE);
+        let fis_dwords = (core::mem::size_of::<FisRegH2D>() / 4) as u32;
+        let header = CommandHeader {
+            flags: fis_dwords | (write as u32) << 6 | (prds as u32) << 16,
+            ctba: self.memory.phys + TABLE_OFFSET as u64,
+            ..CommandHeader::default()
+        };
+        self.memory.write(0, header);
+        self.memory.page.0[TABLE_OFFSET..TABLE_OFFSET + PRDT_OFFSET].fill(0);
+        self.memory.write(TABLE_OFFSET, fis);
+        for n in 0..prds {
+            let len = PAGE_SIZE.min(bytes - n * PAGE_SIZE);
+            let prd = Prd { dba: self.bounce[n].phys, reserved: 0, dbc: len as u32 - 1 };
+            self.memory.write(TABLE_OFFSET + PRDT_OFFSET + n * core::mem::size_of::<Prd>(), prd);
+        }
+
+        self.wait_idle()?;
+        self.write(PORT_IS, !0);
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+        self.write(PORT_CI, 1);
+
+        let timer = pit::Timer::new(COMMAND_TIMEOUT_MS);
+        loop {
+            let failed = self.read(PORT_IS) & PORT_IS_TFES != 0;
+            if failed || self.read(PORT_CI) & 1 == 0 {
+                let tfd = self.read(PORT_TFD);
+                if failed || tfd & TFD_ERR != 0 {
+                    // Restarting the port clears the error for the next command
+                    self.start().ok();
+                    return Err(AhciError::Device(tfd as u8, (tfd >> 8) as u8));
+                }
+                return Ok(());
+            }
+            if timer.is_expired() {
+                self.start().ok();
+                return Err(AhciError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn identify(&mut self) -> Result<IdentifyWords, AhciError> {
+        let mut fis = FisRegH2D::new();
+        fis.set_command(ATA_CMD_IDENTIFY);
+        self.execute(fis, SECTOR_SIZE, false)?;
+        let mut words = [0u16; 256];
+        for (word, pair) in words.iter_mut().zip(self.bounce[0].page.0.chunks_exact(2)) {
+            *word = u16::from_le_bytes([pair[0], pair[1]]);
+        }
+        Ok(words)
+    }
+
+    /// READ or WRITE DMA EXT of `bytes` at `lba`, through the bounce buffer
+    fn transfer(&mut self, command: u8, lba: u64, bytes: usize) -> Result<(), AhciError> {
+        let mut fis = FisRegH2D::new();
+        fis.set_command(command);
+        fis.set_lba(lba);
+        fis.set_count((bytes / SECTOR_SIZE) as u16);
+        self.execute(fis, bytes, command == ATA_CMD_WRITE_DMA_EXT)
+    }
+
+    fn bounce_in(&mut self, data: &[u8]) {
+        for (page, chunk) in self.bounce.iter_mut().zip(data.chunks(PAGE_SIZE)) {
+            page.page.0[..chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    fn bounce_out(&self, buf: &mut [u8]) {
+        for (page, chunk) in self.bounce.iter().zip(buf.chunks_mut(PAGE_SIZE)) {
+            let len = chunk.len();
+            chunk.copy_from_slice(&page.page.0[..len]);
+        }
+    }
+}
+
+/// A SATA disk as a BlockDevice of 512-byte sectors
+pub struct Disk {
+    port: Mutex<Port>,
+    id: DeviceId,
+    sectors: u64,
+}
+
+impl Disk {
+    fn check(&self, lba: u64, len: usize) -> Result<(), &'static str> {
+        if len % SECTOR_SIZE != 0 {
+            return Err("transfer is not a whole number of sectors");
+        }
+        match lba.checked_add((len / SECTOR_SIZE) as u64) {
+            Some(end) if end <= self.sectors => Ok(()),
+            _ => Err(AhciError::OutOfRange.message()),
+        }
+    }
+}
+
+impl BlockDevice for Disk {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.check(block_num, buf.len())?;
+        crate::tracepoint!(BlockRead, trace::dev(self.id), block_num, buf.len() / SECTOR_SIZE);
+        let mut port = self.port.lock();
+        for (n, chunk) in buf.chunks_mut(MAX_TRANSFER).enumerate() {
+            let lba = block_num + (n * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            port.transfer(ATA_CMD_READ_DMA_EXT, lba, chunk.len()).map_err(AhciError::message)?;
+            port.bounce_out(chunk);
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.check(block_num, buf.len())?;
+        crate::tracepoint!(BlockWrite, trace::dev(self.id), block_num, buf.len() / SECTOR_SIZE);
+        let mut port = self.port.lock();
+        for (n, chunk) in buf.chunks(MAX_TRANSFER).enumerate() {
+            let lba = block_num + (n * MAX_TRANSFER / SECTOR_SIZE) as u64;
+            port.bounce_in(chunk);
+            port.transfer(ATA_CMD_WRITE_DMA_EXT, lba, chunk.len()).map_err(AhciError::message)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        let mut fis = FisRegH2D::new();
+        fis.set_command(ATA_CMD_FLUSH_CACHE_EXT);
+        self.port.lock().execute(fis, 0, false).map_err(AhciError::message)
+    }
+
+    fn block_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+pub type SharedDisk = Arc<RwLock<RequestQueue<Disk>>>;
+
+struct Found {
+    name: String,
+    identity: Identity,
+    queue: SharedDisk,
+}
+
+static DISKS: Mutex<Vec<Found>> = Mutex::new(Vec::new());
+
+/// The disk registered as `name`, e.g. "sda"
+pub fn disk(name: &str) -> Option<SharedDisk> {
+    DISKS.lock().iter().find(|d| d.name == name).map(|d| d.queue.clone())
+}
+
+/// The IDENTIFY data of every disk found at boot, by name
+pub fn identities() -> Vec<(String, Identity)> {
+    DISKS.lock().iter().map(|d| (d.name.clone(), d.identity.clone())).collect()
+}
+
+/// Start the SATA disk on port `port` and register it as the next sdX
+fn probe_disk(abar: u64, port: u8, dma64: bool) -> Result<(), AhciError> {
+    let mut engine = Port::new(abar, port, dma64)?;
+    engine.start()?;
+    let identity = Identity::parse(&engine.identify()?);
+    if !identity.lba48 {
+        return Err(AhciError::NoLba48);
+    }
+
+    let index = DISKS.lock().len();
+    let name = alloc::format!("sd{}", (b'a' + index as u8) as char);
+    let id = DeviceId::new(SD_MAJOR, index as u16 * 16);
+    println!("  [AHCI] {}: {} (serial {}), {} sectors", name, identity.model, identity.serial, identity.sectors);
+    let disk = Disk { port: Mutex::new(engine), id, sectors: identity.sectors };
+    let queue = Arc::new(RwLock::new(RequestQueue::new(&name, disk)));
+    DISKS.lock().push(Found { name: name.clone(), identity, queue: queue.clone() });
+    crate::hal::block::register(&name, id, "ahci", queue, 16);
+    Ok(())
+}
+
+/// Bring up every AHCI controller on the PCI bus and the SATA disks on
+/// its ports
 pub fn init() {
     let controllers = find_ahci_controllers();
     
@@ -149,13 +504,17 @@ pub fn init() {
         enable_bus_mastering(&pci_dev);
         enable_memory_space(&pci_dev);
         
-        let abar = get_bar_address(pci_dev.bar[5]);
-        if abar == 0 {
-            continue;
-        }
+        let bar = pci::memory_bar(&pci_dev, 5);
+        let abar = match paging::phys_to_virt(PhysAddr::new(bar)) {
+            Some(abar) if bar != 0 => abar.as_u64(),
+            _ => {
+                println!("  [AHCI] {}: {}", pci_dev.address, AhciError::NoMemory.message());
+                continue;
+            }
+        };
         
         let ghc = read_reg(abar, AHCI_GHC);
-        write_reg(abar, AHCI_GHC, ghc | AHCI_GHC_AE);
+        write_reg(abar, AHCI_GHC, (ghc | AHCI_GHC_AE) & !AHCI_GHC_IE);
         
         let cap = read_reg(abar, AHCI_CAP);
         let version = read_reg(abar, AHCI_VS);
@@ -181,6 +540,11 @@ pub fn init() {
                 if port_type != PortType::None {
                     println!("  [AHCI] Port {} - {:?}", i, port_type);
                 }
+                if port_type == PortType::Sata {
+                    if let Err(e) = probe_disk(abar, i, cap & CAP_S64A != 0) {
+                        println!("  [AHCI] Port {}: {}", i, e.message());
+                    }
+                }
             }
         }
         
@@ -279,3 +643,17 @@ pub const ATA_CMD_READ_DMA_EXT: u8 = 0x25;
 pub const ATA_CMD_WRITE_DMA_EXT: u8 = 0x35;
 pub const ATA_CMD_IDENTIFY: u8 = 0xEC;
 pub const ATA_CMD_FLUSH_CACHE_EXT: u8 = 0xEA;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_structure_sizes() {
+        assert_eq!(core::mem::size_of::<CommandHeader>(), 32);
+        assert_eq!(core::mem::size_of::<Prd>(), 16);
+        assert_eq!(core::mem::size_of::<FisRegH2D>(), 20);
+        // The table with every PRDT entry fits the port's page
+        assert!(TABLE_OFFSET + PRDT_OFFSET + BOUNCE_PAGES * core::mem::size_of::<Prd>() <= PAGE_SIZE);
+    }
+}
@@ -0,0 +1,374 @@
+// Legacy IDE (ATA PIO) disks
+//
+// The four drives of the two legacy IDE channels, which is where QEMU puts
+// `-drive` disks unless told otherwise. Transfers are polled LBA28 PIO: no
+// interrupts, no DMA and no allocation, so the panic path can use it too.
+// Nothing in Drive locks; callers are the single kernel thread or a panic
+// that has stopped everything else. Filesystems get each disk found at
+// boot as a BlockDevice behind a request queue, from disk(), and their
+// IDENTIFY data from identities(). smart() reads a disk's SMART status
+// and attributes while holding its queue, so no writeback interleaves.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+use x86_64::instructions::port::Port;
+use crate::fs::ext4::ext4::BlockDevice;
+use crate::fs::iosched::RequestQueue;
+use crate::fs::vfs::node::DeviceId;
+use crate::kernel::trace;
+use super::smart::{self, Attribute, Health, Identity, IdentifyWords};
+
+pub const SECTOR_SIZE: usize = 512;
+
+/// Highest sector count one command can move; 0 in the count register
+/// means 256
+const MAX_SECTORS_PER_COMMAND: usize = 256;
+
+const CHANNEL_BASES: [u16; 2] = [0x1F0, 0x170];
+const CHANNEL_CONTROLS: [u16; 2] = [0x3F6, 0x376];
+
+const REG_DATA: u16 = 0;
+const REG_ERROR: u16 = 1;
+const REG_FEATURES: u16 = 1;
+const REG_SECTOR_COUNT: u16 = 2;
+const REG_LBA_LOW: u16 = 3;
+const REG_LBA_MID: u16 = 4;
+const REG_LBA_HIGH: u16 = 5;
+const REG_DRIVE: u16 = 6;
+const REG_STATUS: u16 = 7;
+const REG_COMMAND: u16 = 7;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_FLUSH_CACHE: u8 = 0xE7;
+const CMD_IDENTIFY: u8 = 0xEC;
+const CMD_SMART: u8 = 0xB0;
+
+const SMART_READ_DATA: u8 = 0xD0;
+const SMART_READ_THRESHOLDS: u8 = 0xD1;
+const SMART_RETURN_STATUS: u8 = 0xDA;
+/// SMART commands carry 0xC24F in LBA mid/high; RETURN STATUS answers
+/// with it, or with 0x2CF4 once a threshold is exceeded
+const SMART_LBA: u32 = 0xC2_4F00;
+const SMART_PASSED: (u8, u8) = (0x4F, 0xC2);
+const SMART_FAILING: (u8, u8) = (0xF4, 0x2C);
+
+/// Sectors addressable with the LBA28 commands used here
+const LBA28_LIMIT: u64 = 1 << 28;
+
+const STATUS_ERR: u8 = 0x01;
+const STATUS_DRQ: u8 = 0x08;
+const STATUS_DF: u8 = 0x20;
+const STATUS_BSY: u8 = 0x80;
+
+/// Status polls before a command is given up on
+const POLL_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtaError {
+    /// No drive answers at that position, or it is not an ATA disk
+    NoDevice,
+    /// The drive reported an error; the error register's value
+    Device(u8),
+    Timeout,
+    /// The transfer runs past the end of the disk or of LBA28
+    OutOfRange,
+}
+
+impl AtaError {
+    pub fn message(self) -> &'static str {
+        match self {
+            AtaError::NoDevice => "no such disk",
+            AtaError::Device(_) => "disk error",
+            AtaError::Timeout => "disk timed out",
+            AtaError::OutOfRange => "sector out of range",
+        }
+    }
+}
+
+/// One of the four legacy drives: ata0 and ata1 are the primary master and
+/// slave, ata2 and ata3 the secondary ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Drive {
+    pub channel: u8,
+    pub slave: bool,
+}
+
+impl Drive {
+    pub fn from_index(index: usize) -> Option<Drive> {
+        (index < 4).then(|| Drive { channel: (index / 2) as u8, slave: index % 2 == 1 })
+    }
+
+    /// Parse "ata0".."ata3"
+    pub fn from_name(name: &str) -> Option<Drive> {
+        name.strip_prefix("ata")?.parse().ok().and_then(Drive::from_index)
+    }
+
+    /// Parse "hda".."hdd"
+    pub fn from_device_name(name: &str) -> Option<Drive> {
+        (0..4).filter_map(Drive::from_index).find(|drive| drive.device_name() == name)
+    }
+
+    pub fn index(self) -> usize {
+        self.channel as usize * 2 + self.slave as usize
+    }
+
+    /// Device name, as the old Linux IDE driver named them
+    pub fn device_name(self) -> &'static str {
+        ["hda", "hdb", "hdc", "hdd"][self.index()]
+    }
+
+    pub fn device_id(self) -> DeviceId {
+        let major = if self.channel == 0 { 3 } else { 22 };
+        DeviceId::new(major, if self.slave { 64 } else { 0 })
+    }
+
+    fn port(self, reg: u16) -> u16 {
+        CHANNEL_BASES[self.channel as usize] + reg
+    }
+
+    fn read_reg(self, reg: u16) -> u8 {
+        unsafe { Port::<u8>::new(self.port(reg)).read() }
+    }
+
+    fn write_reg(self, reg: u16, value: u8) {
+        unsafe { Port::<u8>::new(self.port(reg)).write(value) }
+    }
+
+    /// Let the drive settle after selection: reading the alternate status
+    /// four times takes the 400ns the specification asks for
+    fn delay(self) {
+        let mut control = Port::<u8>::new(CHANNEL_CONTROLS[self.channel as usize]);
+        for _ in 0..4 {
+            unsafe { control.read() };
+        }
+    }
+
+    fn select(self, lba: u32) {
+        let head = 0xE0 | (self.slave as u8) << 4 | ((lba >> 24) & 0x0F) as u8;
+        self.write_reg(REG_DRIVE, head);
+        self.delay();
+    }
+
+    /// Wait for BSY to clear, then for DRQ if data is expected
+    fn wait(self, data: bool) -> Result<(), AtaError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.read_reg(REG_STATUS);
+            if status == 0xFF {
+                return Err(AtaError::NoDevice);
+            }
+            if status & STATUS_BSY != 0 {
+                continue;
+            }
+            if status & (STATUS_ERR | STATUS_DF) != 0 {
+                return Err(AtaError::Device(self.read_reg(REG_ERROR)));
+            }
+            if !data || status & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(AtaError::Timeout)
+    }
+
+    fn command(self, command: u8, features: u8, lba: u32, count: usize) -> Result<(), AtaError> {
+        self.select(lba);
+        self.wait(false)?;
+        self.write_reg(REG_FEATURES, features);
+        self.write_reg(REG_SECTOR_COUNT, count as u8);
+        self.write_reg(REG_LBA_LOW, lba as u8);
+        self.write_reg(REG_LBA_MID, (lba >> 8) as u8);
+        self.write_reg(REG_LBA_HIGH, (lba >> 16) as u8);
+        self.write_reg(REG_COMMAND, command);
+        Ok(())
+    }
+
+    /// The raw IDENTIFY DEVICE data
+    pub fn identify_words(self) -> Result<IdentifyWords, AtaError> {
+        self.select(0);
+        if self.read_reg(REG_STATUS) == 0xFF {
+            return Err(AtaError::NoDevice);
+        }
+        self.write_reg(REG_SECTOR_COUNT, 0);
+        self.write_reg(REG_LBA_LOW, 0);
+        self.write_reg(REG_LBA_MID, 0);
+        self.write_reg(REG_LBA_HIGH, 0);
+        self.write_reg(REG_COMMAND, CMD_IDENTIFY);
+        if self.read_reg(REG_STATUS) == 0 {
+            return Err(AtaError::NoDevice);
+        }
+        // ATAPI and SATA devices put a signature here instead of answering
+        for _ in 0..POLL_LIMIT {
+            if self.read_reg(REG_STATUS) & STATUS_BSY == 0 {
+                break;
+            }
+        }
+        if self.read_reg(REG_LBA_MID) != 0 || self.read_reg(REG_LBA_HIGH) != 0 {
+            return Err(AtaError::NoDevice);
+        }
+        self.wait(true)?;
+
+        let mut data = Port::<u16>::new(self.port(REG_DATA));
+        let mut words = [0u16; 256];
+        for word in words.iter_mut() {
+            *word = unsafe { data.read() };
+        }
+        Ok(words)
+    }
+
+    /// Sectors of the drive reachable with LBA28, from IDENTIFY DEVICE
+    pub fn identify(self) -> Result<u64, AtaError> {
+        Ok(smart::sectors(&self.identify_words()?).min(LBA28_LIMIT))
+    }
+
+    /// IDENTIFY DEVICE, decoded
+    pub fn identity(self) -> Result<Identity, AtaError> {
+        Ok(Identity::parse(&self.identify_words()?))
+    }
+
+    /// One sector of SMART data: READ DATA or READ THRESHOLDS
+    fn smart_read(self, feature: u8) -> Result<[u8; SECTOR_SIZE], AtaError> {
+        self.command(CMD_SMART, feature, SMART_LBA, 1)?;
+        self.wait(true)?;
+        let mut data = Port::<u16>::new(self.port(REG_DATA));
+        let mut sector = [0u8; SECTOR_SIZE];
+        for pair in sector.chunks_exact_mut(2) {
+            pair.copy_from_slice(&unsafe { data.read() }.to_le_bytes());
+        }
+        Ok(sector)
+    }
+
+    /// SMART RETURN STATUS
+    pub fn smart_health(self) -> Result<Health, AtaError> {
+        self.command(CMD_SMART, SMART_RETURN_STATUS, SMART_LBA, 0)?;
+        self.wait(false)?;
+        Ok(match (self.read_reg(REG_LBA_MID), self.read_reg(REG_LBA_HIGH)) {
+            SMART_PASSED => Health::Passed,
+            SMART_FAILING => Health::Failing,
+            _ => Health::Unknown,
+        })
+    }
+
+    /// The SMART attribute table with thresholds, or None if the disk
+    /// returned data with a bad checksum
+    pub fn smart_attributes(self) -> Result<Option<Vec<Attribute>>, AtaError> {
+        let data = self.smart_read(SMART_READ_DATA)?;
+        let thresholds = self.smart_read(SMART_READ_THRESHOLDS)?;
+        Ok(smart::attributes(&data, &thresholds))
+    }
+
+    fn check_range(lba: u64, sectors: usize) -> Result<u32, AtaError> {
+        match lba.checked_add(sectors as u64) {
+            Some(end) if end <= LBA28_LIMIT => Ok(lba as u32),
+            _ => Err(AtaError::OutOfRange),
+        }
+    }
+
+    /// Read whole sectors starting at `lba` into `buf`, whose length must
+    /// be a multiple of the sector size
+    pub fn read_sectors(self, lba: u64, buf: &mut [u8]) -> Result<(), AtaError> {
+        let start = Self::check_range(lba, buf.len() / SECTOR_SIZE)?;
+        crate::tracepoint!(BlockRead, trace::dev(self.device_id()), lba, buf.len() / SECTOR_SIZE);
+        let mut data = Port::<u16>::new(self.port(REG_DATA));
+        for (n, chunk) in buf.chunks_mut(SECTOR_SIZE * MAX_SECTORS_PER_COMMAND).enumerate() {
+            let lba = start + (n * MAX_SECTORS_PER_COMMAND) as u32;
+            self.command(CMD_READ_SECTORS, 0, lba, chunk.len() / SECTOR_SIZE)?;
+            for sector in chunk.chunks_exact_mut(SECTOR_SIZE) {
+                self.wait(true)?;
+                for pair in sector.chunks_exact_mut(2) {
+                    pair.copy_from_slice(&unsafe { data.read() }.to_le_bytes());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write whole sectors from `buf` starting at `lba`, then flush the
+    /// drive's cache so they survive a reset
+    pub fn write_sectors(self, lba: u64, buf: &[u8]) -> Result<(), AtaError> {
+        let start = Self::check_range(lba, buf.len() / SECTOR_SIZE)?;
+        crate::tracepoint!(BlockWrite, trace::dev(self.device_id()), lba, buf.len() / SECTOR_SIZE);
+        let mut data = Port::<u16>::new(self.port(REG_DATA));
+        for (n, chunk) in buf.chunks(SECTOR_SIZE * MAX_SECTORS_PER_COMMAND).enumerate() {
+            let lba = start + (n * MAX_SECTORS_PER_COMMAND) as u32;
+            self.command(CMD_WRITE_SECTORS, 0, lba, chunk.len() / SECTOR_SIZE)?;
+            for sector in chunk.chunks_exact(SECTOR_SIZE) {
+                self.wait(true)?;
+                for pair in sector.chunks_exact(2) {
+                    unsafe { data.write(u16::from_le_bytes([pair[0], pair[1]])) };
+                }
+            }
+        }
+        self.command(CMD_FLUSH_CACHE, 0, 0, 0)?;
+        self.wait(false)
+    }
+}
+
+/// A drive as a BlockDevice of 512-byte sectors
+pub struct AtaDisk {
+    drive: Drive,
+    sectors: u64,
+}
+
+impl BlockDevice for AtaDisk {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        if buf.len() % SECTOR_SIZE != 0 {
+            return Err("read is not a whole number of sectors");
+        }
+        self.drive.read_sectors(block_num, buf).map_err(AtaError::message)
+    }
+
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        if buf.len() % SECTOR_SIZE != 0 {
+            return Err("write is not a whole number of sectors");
+        }
+        self.drive.write_sectors(block_num, buf).map_err(AtaError::message)
+    }
+
+    fn block_size(&self) -> u32 {
+        SECTOR_SIZE as u32
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors
+    }
+}
+
+pub type SharedDisk = Arc<RwLock<RequestQueue<AtaDisk>>>;
+
+struct Found {
+    drive: Drive,
+    identity: Identity,
+    queue: SharedDisk,
+}
+
+static DISKS: Mutex<Vec<Found>> = Mutex::new(Vec::new());
+
+/// The disk found at boot at `drive`'s position
+pub fn disk(drive: Drive) -> Option<SharedDisk> {
+    DISKS.lock().iter().find(|d| d.drive == drive).map(|d| d.queue.clone())
+}
+
+/// The IDENTIFY data of every disk found at boot
+pub fn identities() -> Vec<(Drive, Identity)> {
+    DISKS.lock().iter().map(|d| (d.drive, d.identity.clone())).collect()
+}
+
+/// SMART health and attributes of a disk found at boot
+pub fn smart(drive: Drive) -> Result<(Health, Option<Vec<Attribute>>), AtaError> {
+    let queue = disk(drive).ok_or(AtaError::NoDevice)?;
+    let _quiet = queue.write();
+    Ok((drive.smart_health()?, drive.smart_attributes()?))
+}
+
+/// Probe the four drives and register the disks found as hda..hdd
+pub fn init() {
+    for drive in (0..4).filter_map(Drive::from_index) {
+        if let Ok(identity) = drive.identity() {
+            let sectors = identity.sectors.min(LBA28_LIMIT);
+            let queue = Arc::new(RwLock::new(RequestQueue::new(drive.device_name(), AtaDisk { drive, sectors })));
+            DISKS.lock().push(Found { drive, identity, queue: queue.clone() });
+            crate::hal::block::register(drive.device_name(), drive.device_id(), "ata", queue, 64);
+        }
+    }
+}
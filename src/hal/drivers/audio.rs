@@ -0,0 +1,296 @@
+// Audio output and /dev/dsp
+//
+// One AudioDevice plays at a time: the AC'97 controller if one was found,
+// otherwise the PC speaker. Writes to /dev/dsp are signed 16-bit little
+// endian PCM in the stream format, 44.1 kHz stereo unless set_format()
+// says otherwise; they are cut into frames, resampled to whatever rate
+// the hardware settled on and queued. There is no ioctl, so only kernel
+// code (play) can change the format.
+//
+// A write returns once its samples are queued, which on the AC'97 means
+// waiting for a DMA buffer to come free and on the speaker means playing
+// them out. VFS is held meanwhile, so writers should keep writes to a few
+// kilobytes.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use crate::fs::vfs::node::DeviceId;
+use super::device::{self, DeviceKind};
+use super::pit;
+
+/// Character major and minor of /dev/dsp, as on Linux's OSS
+pub const MAJOR: u16 = 14;
+pub const MINOR: u16 = 3;
+
+/// A left and right sample
+pub type Frame = [i16; 2];
+
+pub trait AudioDevice: Send {
+    fn name(&self) -> &'static str;
+    /// Switch to the supported rate nearest `rate` and return it
+    fn set_rate(&mut self, rate: u32) -> u32;
+    /// Queue `frames` at the current rate, waiting for room as needed
+    fn write(&mut self, frames: &[Frame]) -> Result<(), &'static str>;
+    /// Wait for everything queued to be played
+    fn drain(&mut self);
+    /// Stop at once, dropping whatever is queued
+    fn stop(&mut self);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub rate: u32,
+    pub channels: u16,
+}
+
+const DEFAULT_FORMAT: Format = Format { rate: 44100, channels: 2 };
+const MIN_RATE: u32 = 4000;
+const MAX_RATE: u32 = 192000;
+
+/// Nearest-sample rate conversion, carrying its position across calls
+struct Resampler {
+    /// Input frames per output frame, 32.32 fixed point
+    step: u64,
+    /// Position in the input, 32.32 fixed point
+    phase: u64,
+}
+
+impl Resampler {
+    fn new(from: u32, to: u32) -> Resampler {
+        // Rounding the step up keeps whole ratios from gaining a frame
+        Resampler { step: ((from as u64) << 32).div_ceil(to as u64), phase: 0 }
+    }
+
+    fn run(&mut self, input: &[Frame], out: &mut Vec<Frame>) {
+        let end = (input.len() as u64) << 32;
+        while self.phase < end {
+            out.push(input[(self.phase >> 32) as usize]);
+            self.phase += self.step;
+        }
+        self.phase -= end;
+    }
+}
+
+struct Audio {
+    device: Box<dyn AudioDevice>,
+    format: Format,
+    /// Rate the device plays at
+    rate: u32,
+    resampler: Resampler,
+    /// The start of a frame cut off at the end of the last write
+    partial: Vec<u8>,
+}
+
+impl Audio {
+    fn reset(&mut self) {
+        self.resampler = Resampler::new(self.format.rate, self.rate);
+        self.partial.clear();
+    }
+}
+
+static AUDIO: Mutex<Option<Audio>> = Mutex::new(None);
+
+/// Make `device` the one /dev/dsp plays on, in place of any other
+pub fn register(device: Box<dyn AudioDevice>) {
+    let mut device = device;
+    let rate = device.set_rate(DEFAULT_FORMAT.rate);
+    let mut guard = AUDIO.lock();
+    if let Some(old) = guard.as_mut() {
+        old.device.stop();
+    }
+    *guard = Some(Audio {
+        device,
+        format: DEFAULT_FORMAT,
+        rate,
+        resampler: Resampler::new(DEFAULT_FORMAT.rate, rate),
+        partial: Vec::new(),
+    });
+}
+
+/// Name of the device in use and the rate it plays at
+pub fn info() -> Option<(&'static str, u32)> {
+    AUDIO.lock().as_ref().map(|audio| (audio.device.name(), audio.rate))
+}
+
+/// Set the format of what is written next, stopping anything queued
+pub fn set_format(format: Format) -> Result<(), &'static str> {
+    if !(MIN_RATE..=MAX_RATE).contains(&format.rate) || !(1..=2).contains(&format.channels) {
+        return Err("unsupported audio format");
+    }
+    let mut guard = AUDIO.lock();
+    let audio = guard.as_mut().ok_or("no audio device")?;
+    audio.device.stop();
+    audio.rate = audio.device.set_rate(format.rate);
+    audio.format = format;
+    audio.reset();
+    Ok(())
+}
+
+/// Queue PCM bytes in the stream format, as written to /dev/dsp
+pub fn write(buf: &[u8]) -> Result<usize, &'static str> {
+    let mut guard = AUDIO.lock();
+    let audio = guard.as_mut().ok_or("no audio device")?;
+    let channels = audio.format.channels as usize;
+    let frame_size = 2 * channels;
+
+    let mut bytes = core::mem::take(&mut audio.partial);
+    bytes.extend_from_slice(buf);
+    let whole = bytes.len() - bytes.len() % frame_size;
+    let frames: Vec<Frame> = bytes[..whole]
+        .chunks_exact(frame_size)
+        .map(|f| {
+            let left = i16::from_le_bytes([f[0], f[1]]);
+            let right = if channels == 2 { i16::from_le_bytes([f[2], f[3]]) } else { left };
+            [left, right]
+        })
+        .collect();
+    audio.partial = bytes[whole..].to_vec();
+
+    let mut out = Vec::with_capacity(frames.len() * audio.rate as usize / audio.format.rate as usize + 1);
+    audio.resampler.run(&frames, &mut out);
+    audio.device.write(&out)?;
+    Ok(buf.len())
+}
+
+/// Wait until everything written has been played
+pub fn drain() {
+    if let Some(audio) = AUDIO.lock().as_mut() {
+        audio.device.drain();
+    }
+}
+
+/// Silence the device and forget what was queued
+pub fn stop() {
+    if let Some(audio) = AUDIO.lock().as_mut() {
+        audio.device.stop();
+        audio.reset();
+    }
+}
+
+const PIT_FREQUENCY: u64 = 1193182;
+const PIT_CHANNEL2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// Channel 2, low byte only, mode 0: the output drops on each write and
+/// rises when the count runs out
+const PIT_CHANNEL2_ONESHOT: u8 = 0x90;
+const SPEAKER_PORT: u16 = 0x61;
+const SPEAKER_GATE: u8 = 0x01;
+const SPEAKER_DATA: u8 = 0x02;
+/// The count must fit in a byte, which bounds the rate from below
+const SPEAKER_MIN_RATE: u32 = 8000;
+const SPEAKER_MAX_RATE: u32 = 16000;
+
+/// PCM on the PC speaker by pulse-width modulation
+///
+/// Each sample restarts PIT channel 2 with a count proportional to its
+/// level, so the speaker is driven low for part of every sample period.
+/// Sample periods are timed on the TSC, busy-waiting, so a write plays
+/// its samples before it returns and timer interrupts add some jitter.
+struct Speaker {
+    rate: u32,
+    tsc_hz: u64,
+    /// PIT ticks in a sample period
+    period: u64,
+    /// TSC value at which the next sample is due
+    due: u64,
+}
+
+fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+impl Speaker {
+    /// Count TSC cycles over a few timer ticks
+    fn new() -> Speaker {
+        const MS: u64 = 20;
+        pit::sleep_ms(1);
+        let start = rdtsc();
+        pit::sleep_ms(MS);
+        let tsc_hz = (rdtsc() - start) * 1000 / MS;
+        let mut speaker = Speaker { rate: 0, tsc_hz, period: 0, due: 0 };
+        speaker.set_rate(SPEAKER_MAX_RATE);
+        speaker
+    }
+}
+
+impl AudioDevice for Speaker {
+    fn name(&self) -> &'static str {
+        "pcspkr"
+    }
+
+    fn set_rate(&mut self, rate: u32) -> u32 {
+        self.rate = rate.clamp(SPEAKER_MIN_RATE, SPEAKER_MAX_RATE);
+        self.period = PIT_FREQUENCY / self.rate as u64;
+        self.rate
+    }
+
+    fn write(&mut self, frames: &[Frame]) -> Result<(), &'static str> {
+        let per_sample = self.tsc_hz / self.rate as u64;
+        let mut channel = Port::<u8>::new(PIT_CHANNEL2);
+        unsafe {
+            Port::<u8>::new(PIT_COMMAND).write(PIT_CHANNEL2_ONESHOT);
+            let mut gate = Port::<u8>::new(SPEAKER_PORT);
+            let value = gate.read();
+            gate.write(value | SPEAKER_GATE | SPEAKER_DATA);
+        }
+        // Start afresh after a pause rather than rushing to catch up
+        if rdtsc() > self.due + self.tsc_hz / 100 {
+            self.due = rdtsc();
+        }
+        for frame in frames {
+            let level = ((frame[0] as i32 + frame[1] as i32) / 2 + 0x8000) as u64;
+            let count = 1 + level * (self.period - 1) / 0x10000;
+            while rdtsc() < self.due {
+                core::hint::spin_loop();
+            }
+            unsafe { channel.write(count as u8) };
+            self.due += per_sample;
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self) {}
+
+    fn stop(&mut self) {
+        let mut gate = Port::<u8>::new(SPEAKER_PORT);
+        unsafe {
+            let value = gate.read();
+            gate.write(value & !(SPEAKER_GATE | SPEAKER_DATA));
+        }
+    }
+}
+
+/// Fall back to the PC speaker if no driver registered a device, and
+/// create /dev/dsp
+pub fn init() {
+    if AUDIO.lock().is_none() {
+        register(Box::new(Speaker::new()));
+    }
+    if let Some((name, rate)) = info() {
+        crate::println!("  [AUDIO] /dev/dsp on {} at {} Hz", name, rate);
+    }
+    device::register("dsp", DeviceKind::Char, DeviceId::new(MAJOR, MINOR), "audio", None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_resampler() {
+        let input: Vec<Frame> = (0..6).map(|i| [i, -i]).collect();
+        // Halving the rate keeps every other frame, even across calls
+        let mut down = Resampler::new(2, 1);
+        let mut out = Vec::new();
+        down.run(&input[..3], &mut out);
+        down.run(&input[3..], &mut out);
+        assert_eq!(out, [[0, 0], [2, -2], [4, -4]]);
+
+        let mut up = Resampler::new(2, 3);
+        out.clear();
+        up.run(&input[..2], &mut out);
+        assert_eq!(out, [[0, 0], [0, 0], [1, -1]]);
+    }
+}
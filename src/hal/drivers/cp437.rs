@@ -0,0 +1,65 @@
+// Code page 437, the VGA text mode character set
+//
+// The VGA font has 256 glyphs: ASCII, then accented letters, Greek, maths
+// and box drawing. Unicode text is mapped onto it one character at a time;
+// characters it lacks become a close ASCII stand-in if there is one, and
+// otherwise FALLBACK.
+
+/// Shown for characters with no glyph: a small square
+pub const FALLBACK: u8 = 0xFE;
+
+/// Unicode for glyphs 0x80-0xFF
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The glyph for `c`: exact, or an ASCII look-alike, or FALLBACK
+pub fn from_char(c: char) -> u8 {
+    if c.is_ascii() {
+        return c as u8;
+    }
+    if let Some(i) = HIGH.iter().position(|&h| h == c) {
+        return 0x80 + i as u8;
+    }
+    match c {
+        '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{2032}' => b'\'',
+        '\u{201c}' | '\u{201d}' | '\u{201e}' | '\u{2033}' => b'"',
+        '\u{2010}'..='\u{2015}' | '\u{2212}' => b'-',
+        '\u{2022}' => 0xF9,
+        '\u{2026}' => b'.',
+        '\u{00d7}' => b'x',
+        _ => FALLBACK,
+    }
+}
+
+/// The Unicode character glyph `byte` shows
+pub fn to_char(byte: u8) -> char {
+    match byte {
+        0 => ' ',
+        0x80..=0xFF => HIGH[byte as usize - 0x80],
+        _ if byte.is_ascii_graphic() || byte == b' ' => byte as char,
+        _ => '?',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_glyphs_round_trip() {
+        for byte in 0x80..=0xFFu8 {
+            assert_eq!(from_char(to_char(byte)), byte);
+        }
+        assert_eq!(from_char('╔'), 0xC9);
+        assert_eq!(from_char('\u{2019}'), b'\'');
+        assert_eq!(from_char('\u{1f600}'), FALLBACK);
+    }
+}
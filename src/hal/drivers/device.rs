@@ -0,0 +1,101 @@
+// Device registry
+//
+// Drivers register the character and block devices they bring up so that
+// tools like lsdev can list them without knowing every driver. Names are
+// unique: registering a name again replaces the earlier entry. Every
+// change is reported to hotplug, which creates and removes the /dev nodes.
+//
+// Devices that are memory, like /dev/mem and the framebuffer, also
+// register a map function, which mmap(2) on their node asks for the
+// physical address behind an offset. Block devices are registered
+// through the block layer, hal::block, which also opens them by name.
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+use lazy_static::lazy_static;
+use crate::fs::vfs::node::DeviceId;
+use crate::fs::{FsError, FsResult};
+use crate::fs::ext4::BlockDevice;
+use super::hotplug::{self, Action};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Char,
+    Block,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    /// Name under /dev, e.g. "ttyS0" or "sda"
+    pub name: String,
+    pub kind: DeviceKind,
+    pub id: DeviceId,
+    /// Driver that registered the device
+    pub driver: &'static str,
+    /// Capacity in bytes, if the driver knows it
+    pub size: Option<u64>,
+}
+
+/// A block device as filesystems and mapped devices hold it
+pub type SharedBlockDevice = Arc<RwLock<dyn BlockDevice + Send + Sync>>;
+
+/// The physical address of `len` bytes at `offset` of a device, None if
+/// that part of it may not be mapped
+pub type MapFn = fn(offset: u64, len: u64) -> Option<u64>;
+
+lazy_static! {
+    static ref DEVICES: Mutex<Vec<DeviceInfo>> = Mutex::new(Vec::new());
+}
+
+static MAPPABLE: Mutex<Vec<(DeviceId, MapFn)>> = Mutex::new(Vec::new());
+
+pub fn register(name: &str, kind: DeviceKind, id: DeviceId, driver: &'static str, size: Option<u64>) {
+    let info = DeviceInfo { name: name.to_string(), kind, id, driver, size };
+    let mut devices = DEVICES.lock();
+    match devices.iter_mut().find(|d| d.name == name) {
+        Some(existing) => *existing = info,
+        None => devices.push(info),
+    }
+    drop(devices);
+    hotplug::report_device(Action::Add, kind, name, id);
+}
+
+pub fn unregister(name: &str) -> bool {
+    let mut devices = DEVICES.lock();
+    let gone = devices.iter().position(|d| d.name == name).map(|at| devices.remove(at));
+    drop(devices);
+    match gone {
+        Some(info) => {
+            hotplug::report_device(Action::Remove, info.kind, &info.name, info.id);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn find(name: &str) -> Option<DeviceInfo> {
+    DEVICES.lock().iter().find(|d| d.name == name).cloned()
+}
+
+/// All registered devices, sorted by kind, then major and minor number
+pub fn list() -> Vec<DeviceInfo> {
+    let mut devices = DEVICES.lock().clone();
+    devices.sort_by_key(|d| (d.kind == DeviceKind::Block, d.id.major, d.id.minor));
+    devices
+}
+
+/// Let device `id` be mapped through `map`
+pub fn register_map(id: DeviceId, map: MapFn) {
+    let mut mappable = MAPPABLE.lock();
+    mappable.retain(|&(device, _)| device != id);
+    mappable.push((id, map));
+}
+
+/// The physical address of `len` bytes at `offset` of device `id`; fails
+/// with NotSupported if the device cannot be mapped at all
+pub fn map(id: DeviceId, offset: u64, len: u64) -> FsResult<u64> {
+    let map = MAPPABLE.lock().iter().find(|&&(device, _)| device == id).map(|&(_, map)| map);
+    map.ok_or(FsError::NotSupported)?(offset, len).ok_or(FsError::InvalidArgument)
+}
@@ -0,0 +1,459 @@
+// Device mapper
+//
+// A mapped device is a block device made of runs of other ones, described
+// by a table with one target per line, in 512-byte sectors as Linux's
+// dmsetup has them:
+//
+//   START LENGTH linear DEVICE OFFSET
+//   START LENGTH crypt aes-xts-plain64 KEY IV_OFFSET DEVICE OFFSET
+//
+// A linear target passes its sectors through to DEVICE from sector OFFSET
+// on, so tables concatenate devices or carve one up. A crypt target does
+// the same with every sector encrypted by XTS-AES under the hex KEY (256
+// or 512 bits), using the sector's number within the target plus
+// IV_OFFSET as the tweak. Targets cover the device from sector 0 with no
+// gaps, and every DEVICE in a table shares one block size; any device
+// name under /dev does, another mapped device's included.
+//
+// A mapped device registers as dm-N, and /dev/mapper/NAME links to it.
+// Tables are fixed once loaded, so devices cannot come to depend on each
+// other in a loop, and one is removed only once nothing - a table stacked
+// on it, a mounted filesystem - still holds it.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::ops::Range;
+use spin::{Mutex, RwLock};
+use crate::fs::ext4::BlockDevice;
+use crate::fs::vfs::node::DeviceId;
+use crate::qsf::cipher::Xts;
+use super::device::{self, DeviceKind, SharedBlockDevice};
+
+/// The major number Linux usually gives device-mapper
+pub const DM_MAJOR: u16 = 253;
+/// Tables count in sectors of this size, whatever the devices' block size
+pub const SECTOR_SIZE: u64 = 512;
+/// The cipher spec crypt targets take, as dm-crypt names it
+pub const CRYPT_CIPHER: &str = "aes-xts-plain64";
+const MAPPER_DIR: &str = "/dev/mapper";
+const MAX_NAME: usize = 127;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DmError {
+    /// A table line that does not parse, with what is wrong with it
+    BadTable(usize, &'static str),
+    NoDevice(String),
+    InvalidName,
+    Exists,
+    NotFound,
+    Busy,
+}
+
+impl fmt::Display for DmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DmError::BadTable(line, what) => write!(f, "table line {}: {}", line, what),
+            DmError::NoDevice(name) => write!(f, "{}: no such block device", name),
+            DmError::InvalidName => write!(f, "invalid device name"),
+            DmError::Exists => write!(f, "device already exists"),
+            DmError::NotFound => write!(f, "no such mapped device"),
+            DmError::Busy => write!(f, "device is in use"),
+        }
+    }
+}
+
+/// What a table line maps its sectors to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetKind {
+    Linear,
+    Crypt { key: Vec<u8>, iv_offset: u64 },
+}
+
+/// One line of a table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetSpec {
+    pub start: u64,
+    pub length: u64,
+    pub kind: TargetKind,
+    pub device: String,
+    pub offset: u64,
+}
+
+/// Parse a table, its lines separated by newlines or ';'
+pub fn parse_table(text: &str) -> Result<Vec<TargetSpec>, DmError> {
+    let lines = text.split(['\n', ';']).map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#'));
+    lines.enumerate().map(|(n, line)| parse_line(n + 1, line)).collect()
+}
+
+fn parse_line(n: usize, line: &str) -> Result<TargetSpec, DmError> {
+    let bad = |what| DmError::BadTable(n, what);
+    let number = |field: Option<&str>, what| field.and_then(|field| field.parse::<u64>().ok()).ok_or(bad(what));
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let start = number(fields.first().copied(), "bad start sector")?;
+    let length = number(fields.get(1).copied(), "bad length")?;
+    let (kind, rest) = match fields.get(2).copied() {
+        Some("linear") => (TargetKind::Linear, &fields[3..]),
+        Some("crypt") => {
+            if fields.get(3).copied() != Some(CRYPT_CIPHER) {
+                return Err(bad("crypt supports aes-xts-plain64 only"));
+            }
+            let key = fields.get(4)
+                .and_then(|key| crate::qsf::crypto::from_hex(key))
+                .filter(|key| key.len() == 32 || key.len() == 64)
+                .ok_or(bad("key must be 64 or 128 hex digits"))?;
+            let iv_offset = number(fields.get(5).copied(), "bad IV offset")?;
+            (TargetKind::Crypt { key, iv_offset }, fields.get(6..).unwrap_or(&[]))
+        }
+        Some(_) => return Err(bad("unknown target type")),
+        None => return Err(bad("missing target type")),
+    };
+    let device = rest.first().ok_or(bad("missing device"))?.to_string();
+    let offset = number(rest.get(1).copied(), "bad device offset")?;
+    if rest.len() > 2 {
+        return Err(bad("trailing fields"));
+    }
+    if length == 0 {
+        return Err(bad("empty target"));
+    }
+    Ok(TargetSpec { start, length, kind, device, offset })
+}
+
+struct Target {
+    spec: TargetSpec,
+    device: SharedBlockDevice,
+    cipher: Option<Xts>,
+}
+
+impl Target {
+    fn end(&self) -> u64 {
+        self.spec.start + self.spec.length
+    }
+
+    /// The first block of the device below for sector `within` of this
+    /// target
+    fn block(&self, within: u64, sectors_per_block: u64) -> u64 {
+        (self.spec.offset + within) / sectors_per_block
+    }
+
+    fn unit(&self, within: u64) -> u64 {
+        match self.spec.kind {
+            TargetKind::Crypt { iv_offset, .. } => iv_offset + within,
+            TargetKind::Linear => 0,
+        }
+    }
+}
+
+pub struct MappedDevice {
+    name: String,
+    minor: u16,
+    block_size: u32,
+    sectors: u64,
+    targets: Vec<Target>,
+}
+
+impl MappedDevice {
+    /// Check `specs` and open their devices through `open`
+    pub fn build(
+        name: &str,
+        minor: u16,
+        mut specs: Vec<TargetSpec>,
+        open: impl Fn(&str) -> Option<SharedBlockDevice>,
+    ) -> Result<MappedDevice, DmError> {
+        if specs.is_empty() {
+            return Err(DmError::BadTable(0, "no targets"));
+        }
+        specs.sort_by_key(|spec| spec.start);
+        let mut targets: Vec<Target> = Vec::with_capacity(specs.len());
+        let mut block_size = 0;
+        for (n, spec) in specs.into_iter().enumerate() {
+            let bad = |what| DmError::BadTable(n + 1, what);
+            let device = open(&spec.device).ok_or_else(|| DmError::NoDevice(spec.device.clone()))?;
+            let (size, blocks) = {
+                let device = device.read();
+                (device.block_size(), device.block_count())
+            };
+            if block_size == 0 {
+                block_size = size;
+            }
+            if size != block_size || size as u64 % SECTOR_SIZE != 0 {
+                return Err(bad("devices differ in block size"));
+            }
+            let per_block = block_size as u64 / SECTOR_SIZE;
+            if spec.start != targets.last().map_or(0, |target| target.end()) {
+                return Err(bad("targets must cover the device without gaps"));
+            }
+            if [spec.start, spec.length, spec.offset].iter().any(|sectors| sectors % per_block != 0) {
+                return Err(bad("not aligned to the device block size"));
+            }
+            if spec.offset.checked_add(spec.length).map_or(true, |end| end > blocks * per_block) {
+                return Err(bad("runs past the end of the device"));
+            }
+            let cipher = match &spec.kind {
+                TargetKind::Crypt { key, .. } => Some(Xts::new(key).ok_or(bad("bad key"))?),
+                TargetKind::Linear => None,
+            };
+            targets.push(Target { spec, device, cipher });
+        }
+        let sectors = targets.last().map_or(0, |target| target.end());
+        Ok(MappedDevice { name: name.to_string(), minor, block_size, sectors, targets })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn minor(&self) -> u16 {
+        self.minor
+    }
+
+    /// Size in 512-byte sectors
+    pub fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    /// The table, as `dmsetup table` prints it, with keys left out
+    pub fn table(&self) -> String {
+        let mut text = String::new();
+        for target in &self.targets {
+            let spec = &target.spec;
+            write!(text, "{} {} ", spec.start, spec.length).ok();
+            match (&spec.kind, &target.cipher) {
+                (TargetKind::Crypt { iv_offset, .. }, Some(cipher)) => {
+                    write!(text, "crypt {} <{}-bit key> {} ", CRYPT_CIPHER, cipher.key_bits(), iv_offset).ok();
+                }
+                _ => text.push_str("linear "),
+            }
+            writeln!(text, "{} {}", spec.device, spec.offset).ok();
+        }
+        text
+    }
+
+    fn sectors_per_block(&self) -> u64 {
+        self.block_size as u64 / SECTOR_SIZE
+    }
+
+    /// Hand `len` bytes from block `block_num` on to `io` piece by piece,
+    /// one target at a time, with the target, the sector within it and
+    /// the part of the buffer it holds
+    fn split(
+        &self,
+        block_num: u64,
+        len: usize,
+        mut io: impl FnMut(&Target, u64, Range<usize>) -> Result<(), &'static str>,
+    ) -> Result<(), &'static str> {
+        if len % self.block_size as usize != 0 {
+            return Err("not a whole number of blocks");
+        }
+        let mut sector = block_num.checked_mul(self.sectors_per_block()).ok_or("block out of range")?;
+        let mut done = 0;
+        while done < len {
+            let target = self.targets.iter()
+                .find(|target| (target.spec.start..target.end()).contains(&sector))
+                .ok_or("block out of range")?;
+            let within = sector - target.spec.start;
+            let bytes = ((target.spec.length - within) * SECTOR_SIZE).min((len - done) as u64) as usize;
+            io(target, within, done..done + bytes)?;
+            sector += bytes as u64 / SECTOR_SIZE;
+            done += bytes;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for MappedDevice {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let per_block = self.sectors_per_block();
+        self.split(block_num, buf.len(), |target, within, range| {
+            let piece = &mut buf[range];
+            target.device.read().read_block(target.block(within, per_block), piece)?;
+            if let Some(cipher) = &target.cipher {
+                for (n, sector) in piece.chunks_exact_mut(SECTOR_SIZE as usize).enumerate() {
+                    cipher.decrypt(target.unit(within) + n as u64, sector);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let per_block = self.sectors_per_block();
+        self.split(block_num, buf.len(), |target, within, range| {
+            let block = target.block(within, per_block);
+            match &target.cipher {
+                Some(cipher) => {
+                    let mut sealed = vec![0u8; range.len()];
+                    sealed.copy_from_slice(&buf[range]);
+                    for (n, sector) in sealed.chunks_exact_mut(SECTOR_SIZE as usize).enumerate() {
+                        cipher.encrypt(target.unit(within) + n as u64, sector);
+                    }
+                    target.device.write().write_block(block, &sealed)
+                }
+                None => target.device.write().write_block(block, &buf[range]),
+            }
+        })
+    }
+
+    fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors / self.sectors_per_block()
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        for target in &self.targets {
+            target.device.write().flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub type SharedMapped = Arc<RwLock<MappedDevice>>;
+
+static MAPPED: Mutex<Vec<SharedMapped>> = Mutex::new(Vec::new());
+
+/// The mapped device called `name`, or registered as `name` ("dm-0"), or
+/// linked as `name` ("mapper/NAME")
+pub fn device(name: &str) -> Option<SharedMapped> {
+    let name = name.strip_prefix("mapper/").unwrap_or(name);
+    let minor = name.strip_prefix("dm-").and_then(|minor| minor.parse::<u16>().ok());
+    MAPPED.lock().iter().find(|mapped| {
+        let mapped = mapped.read();
+        mapped.name == name || Some(mapped.minor) == minor
+    }).cloned()
+}
+
+/// Every mapped device, by minor number
+pub fn list() -> Vec<SharedMapped> {
+    let mut mapped = MAPPED.lock().clone();
+    mapped.sort_by_key(|mapped| mapped.read().minor);
+    mapped
+}
+
+/// Create the device `name` from `table`, returning its minor number
+pub fn create(name: &str, table: &str) -> Result<u16, DmError> {
+    if name.is_empty() || name.len() > MAX_NAME || name.contains('/') || name.starts_with("dm-") {
+        return Err(DmError::InvalidName);
+    }
+    if device(name).is_some() {
+        return Err(DmError::Exists);
+    }
+    let specs = parse_table(table)?;
+    let minor = {
+        let mapped = MAPPED.lock();
+        (0..).find(|&minor| !mapped.iter().any(|m| m.read().minor == minor)).unwrap_or(0)
+    };
+    // Opening may look up other mapped devices, so MAPPED is not held
+    let mapped = MappedDevice::build(name, minor, specs, crate::hal::block::open)?;
+    let bytes = mapped.sectors() * SECTOR_SIZE;
+    {
+        let mut all = MAPPED.lock();
+        if all.iter().any(|m| m.read().name == name || m.read().minor == minor) {
+            return Err(DmError::Exists);
+        }
+        all.push(Arc::new(RwLock::new(mapped)));
+    }
+
+    let node = format!("dm-{}", minor);
+    device::register(&node, DeviceKind::Block, DeviceId::new(DM_MAJOR, minor), "dm", Some(bytes));
+    let mut vfs = crate::fs::vfs::VFS.lock();
+    vfs.create_directory(MAPPER_DIR, crate::fs::FileMode::new(0o755)).ok();
+    vfs.create_symlink(&format!("{}/{}", MAPPER_DIR, name), &format!("/dev/{}", node)).ok();
+    Ok(minor)
+}
+
+/// Flush and remove the device `name`, unless something still holds it
+pub fn remove(name: &str) -> Result<(), DmError> {
+    let mapped = {
+        let mut all = MAPPED.lock();
+        let at = all.iter().position(|m| m.read().name == name).ok_or(DmError::NotFound)?;
+        if Arc::strong_count(&all[at]) > 1 {
+            return Err(DmError::Busy);
+        }
+        all.remove(at)
+    };
+    let mut mapped = mapped.write();
+    mapped.flush().ok();
+    device::unregister(&format!("dm-{}", mapped.minor));
+    crate::fs::vfs::VFS.lock().remove_file(&format!("{}/{}", MAPPER_DIR, name)).ok();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::ramdisk::RamDisk;
+
+    fn disk(sectors: u64) -> SharedBlockDevice {
+        Arc::new(RwLock::new(RamDisk::new(sectors, SECTOR_SIZE as u32)))
+    }
+
+    #[test_case]
+    fn test_parse_table() {
+        let specs = parse_table("0 8 linear hda 16; 8 8 crypt aes-xts-plain64 00000000000000000000000000000000000000000000000000000000000000ff 4 /dev/hdb 0").unwrap();
+        assert_eq!(specs[0], TargetSpec { start: 0, length: 8, kind: TargetKind::Linear, device: "hda".to_string(), offset: 16 });
+        assert!(matches!(specs[1].kind, TargetKind::Crypt { iv_offset: 4, .. }));
+        assert_eq!(specs[1].device, "/dev/hdb");
+        assert_eq!(parse_table("0 8 striped hda 0"), Err(DmError::BadTable(1, "unknown target type")));
+        assert_eq!(parse_table("0 8 crypt aes-xts-plain64 00ff 0 hda 0"), Err(DmError::BadTable(1, "key must be 64 or 128 hex digits")));
+    }
+
+    #[test_case]
+    fn test_linear_concatenates() {
+        let (first, second) = (disk(4), disk(4));
+        let open = |name: &str| match name {
+            "a" => Some(first.clone()),
+            "b" => Some(second.clone()),
+            _ => None,
+        };
+        let specs = parse_table("0 2 linear a 2\n2 4 linear b 0").unwrap();
+        let mut mapped = MappedDevice::build("both", 0, specs, open).unwrap();
+        assert_eq!(mapped.block_count(), 6);
+
+        let data: Vec<u8> = (0..3 * SECTOR_SIZE).map(|n| n as u8).collect();
+        mapped.write_block(1, &data).unwrap();
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        first.read().read_block(3, &mut sector).unwrap();
+        assert_eq!(sector[..], data[..512]);
+        second.read().read_block(1, &mut sector).unwrap();
+        assert_eq!(sector[..], data[1024..]);
+        let mut back = vec![0u8; data.len()];
+        mapped.read_block(1, &mut back).unwrap();
+        assert_eq!(back, data);
+        assert!(mapped.read_block(6, &mut sector).is_err());
+
+        let gap = parse_table("0 2 linear a 0\n4 2 linear b 0").unwrap();
+        assert!(matches!(MappedDevice::build("gap", 1, gap, open), Err(DmError::BadTable(2, _))));
+        let past = parse_table("0 4 linear a 2").unwrap();
+        assert!(matches!(MappedDevice::build("past", 1, past, open), Err(DmError::BadTable(1, _))));
+    }
+
+    #[test_case]
+    fn test_crypt_round_trips() {
+        let backing = disk(4);
+        let key = "0123456789abcdef".repeat(4);
+        let specs = parse_table(&format!("0 4 crypt aes-xts-plain64 {} 100 a 0", key)).unwrap();
+        let mut mapped = MappedDevice::build("secret", 0, specs, |_| Some(backing.clone())).unwrap();
+
+        let plain = [0x5au8; 2 * SECTOR_SIZE as usize];
+        mapped.write_block(2, &plain).unwrap();
+        let mut raw = [0u8; 2 * SECTOR_SIZE as usize];
+        backing.read().read_block(2, &mut raw).unwrap();
+        assert_ne!(raw[..SECTOR_SIZE as usize], plain[..SECTOR_SIZE as usize]);
+        // Each sector has its own tweak, so equal sectors differ on disk
+        assert_ne!(raw[..SECTOR_SIZE as usize], raw[SECTOR_SIZE as usize..]);
+
+        let xts = Xts::new(&crate::qsf::crypto::from_hex(&key).unwrap()).unwrap();
+        xts.decrypt(102, &mut raw[..SECTOR_SIZE as usize]);
+        assert_eq!(raw[..SECTOR_SIZE as usize], plain[..SECTOR_SIZE as usize]);
+        let mut back = [0u8; 2 * SECTOR_SIZE as usize];
+        mapped.read_block(2, &mut back).unwrap();
+        assert_eq!(back, plain);
+        assert!(mapped.table().contains("crypt aes-xts-plain64 <256-bit key> 100 a 0"));
+    }
+}
@@ -0,0 +1,350 @@
+// Intel 8254x (e1000) Ethernet
+//
+// The 82540EM that QEMU gives its default machine, and the 82545EM.
+// Registers sit in memory BAR 0. Reception uses a ring of 32 descriptors
+// with a 2 KiB buffer each, two to a heap page; transmission a ring of 8,
+// each frame copied into its descriptor's buffer. The driver is polled by
+// the network stack: interrupts stay masked, and a received frame is
+// whatever the next descriptor holds once the card has set its done bit.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ptr::{addr_of, addr_of_mut};
+use core::sync::atomic::{fence, Ordering};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::hal::memory::paging;
+use crate::net::ethernet::{MacAddr, HEADER_LEN, MTU};
+use crate::net::{self, NetDevice, NetError, NetResult};
+use super::pci::{self, PciDevice};
+use super::pit;
+
+const PAGE_SIZE: usize = 4096;
+const BUFFER_SIZE: usize = 2048;
+const RX_DESCRIPTORS: usize = 32;
+const TX_DESCRIPTORS: usize = 8;
+
+const DEVICE_IDS: [u16; 2] = [0x100E, 0x100F];
+
+const REG_CTRL: u64 = 0x0000;
+const REG_STATUS: u64 = 0x0008;
+const REG_EERD: u64 = 0x0014;
+const REG_IMC: u64 = 0x00D8;
+const REG_RCTL: u64 = 0x0100;
+const REG_TCTL: u64 = 0x0400;
+const REG_TIPG: u64 = 0x0410;
+const REG_RDBAL: u64 = 0x2800;
+const REG_RDBAH: u64 = 0x2804;
+const REG_RDLEN: u64 = 0x2808;
+const REG_RDH: u64 = 0x2810;
+const REG_RDT: u64 = 0x2818;
+const REG_TDBAL: u64 = 0x3800;
+const REG_TDBAH: u64 = 0x3804;
+const REG_TDLEN: u64 = 0x3808;
+const REG_TDH: u64 = 0x3810;
+const REG_TDT: u64 = 0x3818;
+const REG_MTA: u64 = 0x5200;
+const REG_RAL: u64 = 0x5400;
+const REG_RAH: u64 = 0x5404;
+
+const CTRL_ASDE: u32 = 1 << 5;
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+const EERD_START: u32 = 1 << 0;
+const EERD_DONE: u32 = 1 << 4;
+/// Address valid, in RAH
+const RAH_AV: u32 = 1 << 31;
+
+const RCTL_EN: u32 = 1 << 1;
+/// Accept broadcast
+const RCTL_BAM: u32 = 1 << 15;
+/// Strip the CRC; buffer size bits left at 0 for 2048 bytes
+const RCTL_SECRC: u32 = 1 << 26;
+const TCTL_EN: u32 = 1 << 1;
+/// Pad short packets
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x10 << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+/// IPG transmit time, receive times 1 and 2, for copper
+const TIPG_COPPER: u32 = 10 | 8 << 10 | 6 << 20;
+
+const DESC_DD: u8 = 1 << 0;
+const DESC_EOP: u8 = 1 << 1;
+const CMD_EOP: u8 = 1 << 0;
+/// Insert the CRC
+const CMD_IFCS: u8 = 1 << 1;
+/// Report status, so DD gets set
+const CMD_RS: u8 = 1 << 3;
+
+const RESET_TIMEOUT_MS: u64 = 10;
+/// How long send() waits for a transmit descriptor to come free
+const TX_TIMEOUT_MS: u64 = 10;
+
+#[repr(C, align(4096))]
+struct Page([u8; PAGE_SIZE]);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct RxDescriptor {
+    addr: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct TxDescriptor {
+    addr: u64,
+    length: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+#[repr(C, align(4096))]
+struct RxRing([RxDescriptor; RX_DESCRIPTORS]);
+
+#[repr(C, align(4096))]
+struct TxRing([TxDescriptor; TX_DESCRIPTORS]);
+
+fn dma_addr<T>(value: &T) -> NetResult<u64> {
+    paging::translate_addr(VirtAddr::from_ptr(value)).map(|p| p.as_u64()).ok_or(NetError::NoDevice)
+}
+
+struct E1000 {
+    regs: u64,
+    mac: MacAddr,
+    rx: Box<RxRing>,
+    tx: Box<TxRing>,
+    rx_pages: Vec<Box<Page>>,
+    tx_pages: Vec<Box<Page>>,
+    /// Next receive descriptor the card will fill
+    rx_next: usize,
+    /// Next transmit descriptor to use
+    tx_next: usize,
+}
+
+impl E1000 {
+    fn read(&self, reg: u64) -> u32 {
+        unsafe { core::ptr::read_volatile((self.regs + reg) as *const u32) }
+    }
+
+    fn write(&self, reg: u64, value: u32) {
+        unsafe { core::ptr::write_volatile((self.regs + reg) as *mut u32, value) }
+    }
+
+    /// The receive or transmit buffer for descriptor `index`
+    fn buffer(pages: &mut [Box<Page>], index: usize) -> &mut [u8] {
+        let per_page = PAGE_SIZE / BUFFER_SIZE;
+        let at = index % per_page * BUFFER_SIZE;
+        &mut pages[index / per_page].0[at..at + BUFFER_SIZE]
+    }
+
+    fn read_eeprom(&self, word: u32) -> Option<u16> {
+        self.write(REG_EERD, word << 8 | EERD_START);
+        let timer = pit::Timer::new(RESET_TIMEOUT_MS);
+        while !timer.is_expired() {
+            let value = self.read(REG_EERD);
+            if value & EERD_DONE != 0 {
+                return Some((value >> 16) as u16);
+            }
+            core::hint::spin_loop();
+        }
+        None
+    }
+
+    /// The address in receive address 0, or the EEPROM's if it is unset
+    fn read_mac(&self) -> Option<MacAddr> {
+        let (low, high) = (self.read(REG_RAL), self.read(REG_RAH));
+        let mut mac = [0u8; 6];
+        if high & RAH_AV != 0 {
+            mac[..4].copy_from_slice(&low.to_le_bytes());
+            mac[4..].copy_from_slice(&(high as u16).to_le_bytes());
+        } else {
+            for word in 0..3 {
+                let value = self.read_eeprom(word as u32)?;
+                mac[word * 2..word * 2 + 2].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        Some(MacAddr(mac))
+    }
+
+    fn reset(&mut self) -> NetResult<()> {
+        self.write(REG_IMC, u32::MAX);
+        self.write(REG_CTRL, self.read(REG_CTRL) | CTRL_RST);
+        pit::busy_wait_us(1000);
+        let timer = pit::Timer::new(RESET_TIMEOUT_MS);
+        while self.read(REG_CTRL) & CTRL_RST != 0 {
+            if timer.is_expired() {
+                return Err(NetError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+        self.write(REG_IMC, u32::MAX);
+        self.write(REG_CTRL, self.read(REG_CTRL) | CTRL_SLU | CTRL_ASDE);
+        self.mac = self.read_mac().ok_or(NetError::Timeout)?;
+        let mac = self.mac.0;
+        self.write(REG_RAL, u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]));
+        self.write(REG_RAH, u16::from_le_bytes([mac[4], mac[5]]) as u32 | RAH_AV);
+        for i in 0..128 {
+            self.write(REG_MTA + i * 4, 0);
+        }
+
+        for index in 0..RX_DESCRIPTORS {
+            let addr = dma_addr(&Self::buffer(&mut self.rx_pages, index)[0])?;
+            self.rx.0[index] = RxDescriptor { addr, ..RxDescriptor::default() };
+        }
+        let ring = dma_addr(&*self.rx)?;
+        self.write(REG_RDBAL, ring as u32);
+        self.write(REG_RDBAH, (ring >> 32) as u32);
+        self.write(REG_RDLEN, (RX_DESCRIPTORS * core::mem::size_of::<RxDescriptor>()) as u32);
+        self.write(REG_RDH, 0);
+        // The card may fill all but one; head == tail means none are free
+        self.write(REG_RDT, RX_DESCRIPTORS as u32 - 1);
+        self.rx_next = 0;
+        self.write(REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+
+        for index in 0..TX_DESCRIPTORS {
+            let addr = dma_addr(&Self::buffer(&mut self.tx_pages, index)[0])?;
+            // Free descriptors look like finished ones
+            self.tx.0[index] = TxDescriptor { addr, status: DESC_DD, ..TxDescriptor::default() };
+        }
+        let ring = dma_addr(&*self.tx)?;
+        self.write(REG_TDBAL, ring as u32);
+        self.write(REG_TDBAH, (ring >> 32) as u32);
+        self.write(REG_TDLEN, (TX_DESCRIPTORS * core::mem::size_of::<TxDescriptor>()) as u32);
+        self.write(REG_TDH, 0);
+        self.write(REG_TDT, 0);
+        self.tx_next = 0;
+        self.write(REG_TIPG, TIPG_COPPER);
+        self.write(REG_TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+        Ok(())
+    }
+}
+
+impl NetDevice for E1000 {
+    fn name(&self) -> &'static str {
+        "eth0"
+    }
+
+    fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    fn link_up(&self) -> bool {
+        self.read(REG_STATUS) & STATUS_LU != 0
+    }
+
+    fn send(&mut self, frame: &[u8]) -> NetResult<()> {
+        if frame.len() > HEADER_LEN + MTU {
+            return Err(NetError::InvalidArgument);
+        }
+        let index = self.tx_next;
+        let desc = addr_of_mut!(self.tx.0[index]);
+        let timer = pit::Timer::new(TX_TIMEOUT_MS);
+        while unsafe { core::ptr::read_volatile(addr_of!((*desc).status)) } & DESC_DD == 0 {
+            if timer.is_expired() {
+                return Err(NetError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+        Self::buffer(&mut self.tx_pages, index)[..frame.len()].copy_from_slice(frame);
+        unsafe {
+            core::ptr::write_volatile(addr_of_mut!((*desc).length), frame.len() as u16);
+            core::ptr::write_volatile(addr_of_mut!((*desc).cmd), CMD_EOP | CMD_IFCS | CMD_RS);
+            core::ptr::write_volatile(addr_of_mut!((*desc).status), 0);
+        }
+        fence(Ordering::SeqCst);
+        self.tx_next = (index + 1) % TX_DESCRIPTORS;
+        self.write(REG_TDT, self.tx_next as u32);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        loop {
+            let index = self.rx_next;
+            let desc = addr_of_mut!(self.rx.0[index]);
+            let status = unsafe { core::ptr::read_volatile(addr_of!((*desc).status)) };
+            if status & DESC_DD == 0 {
+                return None;
+            }
+            fence(Ordering::SeqCst);
+            let (length, errors) = unsafe { ((*desc).length as usize, (*desc).errors) };
+            // Frames never span buffers at this MTU; drop any that do
+            let frame = (status & DESC_EOP != 0 && errors == 0)
+                .then(|| Self::buffer(&mut self.rx_pages, index)[..length.min(BUFFER_SIZE)].to_vec());
+            unsafe { core::ptr::write_volatile(addr_of_mut!((*desc).status), 0) };
+            self.rx_next = (index + 1) % RX_DESCRIPTORS;
+            self.write(REG_RDT, index as u32);
+            if frame.is_some() {
+                return frame;
+            }
+        }
+    }
+}
+
+fn probe(device: &PciDevice) -> NetResult<E1000> {
+    pci::enable_memory_space(device);
+    pci::enable_bus_mastering(device);
+    let bar = pci::memory_bar(device, 0);
+    let regs = match paging::phys_to_virt(PhysAddr::new(bar)) {
+        Some(regs) if bar != 0 => regs.as_u64(),
+        _ => return Err(NetError::NoDevice),
+    };
+    let pages = |buffers: usize| (0..buffers * BUFFER_SIZE / PAGE_SIZE).map(|_| Box::new(Page([0; PAGE_SIZE]))).collect();
+    let mut nic = E1000 {
+        regs,
+        mac: MacAddr::default(),
+        rx: Box::new(RxRing([RxDescriptor::default(); RX_DESCRIPTORS])),
+        tx: Box::new(TxRing([TxDescriptor::default(); TX_DESCRIPTORS])),
+        rx_pages: pages(RX_DESCRIPTORS),
+        tx_pages: pages(TX_DESCRIPTORS),
+        rx_next: 0,
+        tx_next: 0,
+    };
+    nic.reset()?;
+    Ok(nic)
+}
+
+/// Bring up the first e1000 and hand it to the network stack
+pub fn init() {
+    let device = pci::get_devices().into_iter().find(|d| d.vendor_id == 0x8086 && DEVICE_IDS.contains(&d.device_id));
+    let device = match device {
+        Some(device) => device,
+        None => {
+            crate::println!("  [E1000] No adapter found");
+            return;
+        }
+    };
+    match probe(&device) {
+        Ok(nic) => {
+            crate::println!(
+                "  [E1000] {}: {} link {}",
+                device.address,
+                nic.mac,
+                if nic.link_up() { "up" } else { "down" }
+            );
+            net::register_device(Box::new(nic));
+        }
+        Err(e) => crate::println!("  [E1000] {}: {}", device.address, e.message()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_descriptor_layout() {
+        assert_eq!(core::mem::size_of::<RxDescriptor>(), 16);
+        assert_eq!(core::mem::size_of::<TxDescriptor>(), 16);
+        // RDLEN and TDLEN must be multiples of 128 bytes
+        assert_eq!(RX_DESCRIPTORS * 16 % 128, 0);
+        assert_eq!(TX_DESCRIPTORS * 16 % 128, 0);
+    }
+}
@@ -0,0 +1,137 @@
+// Large-font console on the framebuffer
+//
+// For those who find the 80x25 text screen hard to read, the console can
+// be shown in a graphics mode instead, every character drawn from the VGA
+// font at twice its width and height. It shows the text screen as it is:
+// WRITER keeps writing to VGA memory, and after each change the rows that
+// differ from what was drawn are drawn again, in the colors of the VGA
+// palette (see vga::set_palette), with the cursor as a bar under its
+// cell. Anything else entering a graphics mode meanwhile borrows the
+// screen, and the console is drawn whole when it comes back.
+
+use alloc::boxed::Box;
+use spin::Mutex;
+use crate::hal::drivers::framebuffer::{self, Framebuffer, Mode};
+use crate::hal::drivers::gfx::{rgb, Canvas, Color, Font, Rect, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::hal::drivers::vga::{self, BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+
+/// How many times larger than in text mode characters are drawn
+const SCALE: u32 = 2;
+const MODE: Mode = Mode {
+    width: BUFFER_WIDTH as u32 * GLYPH_WIDTH * SCALE,
+    height: BUFFER_HEIGHT as u32 * GLYPH_HEIGHT * SCALE,
+};
+/// Height of the cursor bar, in font rows
+const CURSOR_ROWS: usize = 2;
+
+/// A cell as drawn: the character in the low byte, the attribute above
+type Cell = u16;
+/// Not a cell VGA memory can hold, so a row holding it is drawn again
+const STALE: Cell = 0xFFFF;
+
+struct Console {
+    font: Box<Font>,
+    drawn: [[Cell; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    /// Where the cursor was drawn
+    cursor: (usize, usize),
+}
+
+static CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+
+/// Show the console in large characters
+pub fn start() -> Result<(), &'static str> {
+    if CONSOLE.lock().is_some() {
+        return Ok(());
+    }
+    framebuffer::enter_console(MODE)?;
+    let Some(font) = framebuffer::font() else {
+        framebuffer::leave_console();
+        return Err("no font");
+    };
+    *CONSOLE.lock() = Some(Console { font, drawn: [[STALE; BUFFER_WIDTH]; BUFFER_HEIGHT], cursor: (0, 0) });
+    refresh();
+    Ok(())
+}
+
+/// Go back to the text screen
+pub fn stop() {
+    if CONSOLE.lock().take().is_some() {
+        framebuffer::leave_console();
+    }
+}
+
+/// Whether the console is shown in large characters
+pub fn active() -> bool {
+    CONSOLE.lock().is_some()
+}
+
+/// Draw everything again, as after the palette changed or the screen
+/// came back
+pub fn redraw() {
+    if let Some(console) = CONSOLE.lock().as_mut() {
+        console.drawn = [[STALE; BUFFER_WIDTH]; BUFFER_HEIGHT];
+    }
+    refresh();
+}
+
+/// Draw the rows that changed since last time. Skipped rather than waited
+/// on if the screen or the console is busy, as it may be called from an
+/// interrupt; the next change draws them.
+pub fn refresh() {
+    let Some(mut guard) = CONSOLE.try_lock() else {
+        return;
+    };
+    let Some(console) = guard.as_mut() else {
+        return;
+    };
+    let Some(fb) = framebuffer::console() else {
+        return;
+    };
+    let (screen, cursor) = {
+        let Some(writer) = WRITER.try_lock() else {
+            return;
+        };
+        let mut screen = [[0; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        for (row, cells) in screen.iter_mut().enumerate() {
+            for (col, cell) in cells.iter_mut().enumerate() {
+                let (c, attr) = writer.read_cell(row, col);
+                *cell = (attr as Cell) << 8 | c as Cell;
+            }
+        }
+        (screen, writer.get_position())
+    };
+    let palette = vga::palette().map(|(r, g, b)| rgb(r, g, b));
+    for (row, (cells, drawn)) in screen.iter().zip(console.drawn.iter_mut()).enumerate() {
+        let moved = cursor != console.cursor && (row == cursor.0 || row == console.cursor.0);
+        if cells != drawn || moved {
+            draw_row(&fb, &console.font, &palette, row, cells, (cursor.0 == row).then_some(cursor.1));
+            *drawn = *cells;
+        }
+    }
+    console.cursor = cursor;
+}
+
+/// Draw one text row, and the cursor under column `cursor` if it is on it
+fn draw_row(fb: &Framebuffer, font: &Font, palette: &[Color; 16], row: usize, cells: &[Cell; BUFFER_WIDTH], cursor: Option<usize>) {
+    let (cell_width, cell_height) = (GLYPH_WIDTH * SCALE, GLYPH_HEIGHT * SCALE);
+    let mut canvas = Canvas::new(MODE.width, cell_height, palette[0]);
+    for (col, &cell) in cells.iter().enumerate() {
+        let (c, attr) = ((cell & 0xFF) as usize, (cell >> 8) as usize);
+        // The top bit of the background is blink, which is not drawn
+        let (fg, bg) = (palette[attr & 0x0F], palette[(attr >> 4) & 0x07]);
+        let x = (col as u32 * cell_width) as i32;
+        canvas.fill_rect(Rect::new(x, 0, cell_width, cell_height), bg);
+        let glyph = &font[c * GLYPH_HEIGHT as usize..(c + 1) * GLYPH_HEIGHT as usize];
+        for (y, &bits) in glyph.iter().enumerate() {
+            let bits = match cursor == Some(col) && y >= GLYPH_HEIGHT as usize - CURSOR_ROWS {
+                true => 0xFF,
+                false => bits,
+            };
+            for dx in (0..GLYPH_WIDTH).filter(|dx| bits & (0x80 >> dx) != 0) {
+                let at = Rect::new(x + (dx * SCALE) as i32, (y as u32 * SCALE) as i32, SCALE, SCALE);
+                canvas.fill_rect(at, fg);
+            }
+        }
+    }
+    fb.present_at(&canvas, 0, (row as u32 * cell_height) as i32);
+}
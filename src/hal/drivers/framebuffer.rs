@@ -0,0 +1,397 @@
+// Bochs/QEMU graphics adapter (BGA) framebuffer
+//
+// The console stays in VGA text mode; a graphics mode is only entered
+// while something draws on the screen, and leave() brings the text back.
+// Text and font live in the first 256 KiB of video memory (the VGA planes
+// are interleaved there), so the mode is set without clearing memory and
+// the visible picture starts past that, leaving the console untouched.
+// Enabling the BGA rewrites the VGA registers, so they are saved first
+// and written back afterwards. The VGA font is read out of plane 2 while
+// still in text mode, for drawing text in graphics.
+//
+// The adapter is /dev/fb0. While a graphics mode is set, mapping it maps
+// the picture, offset 0 being the top left pixel and each line the mode's
+// width in 32-bit pixels; the picture starts on a page boundary for this.
+// In text mode there is nothing to map.
+//
+// The large-font console (see fbcon) keeps a graphics mode for as long as
+// it is on. Something else entering a mode meanwhile borrows the screen
+// from it, and leaving gives the screen back to the console.
+
+use alloc::boxed::Box;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+use crate::fs::vfs::node::DeviceId;
+use crate::hal::memory::{paging, PAGE_SIZE};
+use super::device::{self, DeviceKind};
+use super::gfx::{Canvas, Font, Rect, GLYPH_HEIGHT};
+use super::pci;
+
+pub const MAJOR: u16 = 29;
+const MINOR: u16 = 0;
+
+const PCI_VENDOR: u16 = 0x1234;
+const PCI_DEVICE: u16 = 0x1111;
+
+const DISPI_INDEX: u16 = 0x01CE;
+const DISPI_DATA: u16 = 0x01CF;
+const DISPI_ID: u16 = 0;
+const DISPI_XRES: u16 = 1;
+const DISPI_YRES: u16 = 2;
+const DISPI_BPP: u16 = 3;
+const DISPI_ENABLE: u16 = 4;
+const DISPI_Y_OFFSET: u16 = 9;
+const DISPI_VIDEO_MEMORY_64K: u16 = 0xA;
+
+const DISPI_ID_MIN: u16 = 0xB0C2;
+const DISPI_ENABLED: u16 = 0x01;
+const DISPI_LFB_ENABLED: u16 = 0x40;
+const DISPI_NOCLEARMEM: u16 = 0x80;
+
+const BPP: u16 = 32;
+/// Video memory the text console needs kept
+const TEXT_RESERVED: u64 = 256 * 1024;
+
+const VGA_MISC_READ: u16 = 0x3CC;
+const VGA_MISC_WRITE: u16 = 0x3C2;
+const VGA_SEQ: u16 = 0x3C4;
+const VGA_GC: u16 = 0x3CE;
+const VGA_CRTC: u16 = 0x3D4;
+const VGA_AC: u16 = 0x3C0;
+const VGA_AC_READ: u16 = 0x3C1;
+const VGA_STATUS: u16 = 0x3DA;
+/// Attribute controller index bit that hands the palette back to the display
+const AC_PALETTE_ENABLE: u8 = 0x20;
+const VGA_WINDOW: u64 = 0xA0000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Mode {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The VGA registers that decide text mode
+struct VgaState {
+    misc: u8,
+    seq: [u8; 5],
+    crtc: [u8; 25],
+    gc: [u8; 9],
+    ac: [u8; 21],
+}
+
+fn outb(port: u16, value: u8) {
+    unsafe { Port::new(port).write(value) }
+}
+
+fn inb(port: u16) -> u8 {
+    unsafe { Port::new(port).read() }
+}
+
+fn read_indexed(port: u16, index: u8) -> u8 {
+    outb(port, index);
+    inb(port + 1)
+}
+
+fn write_indexed(port: u16, index: u8, value: u8) {
+    outb(port, index);
+    outb(port + 1, value);
+}
+
+impl VgaState {
+    fn save() -> VgaState {
+        let mut state = VgaState { misc: inb(VGA_MISC_READ), seq: [0; 5], crtc: [0; 25], gc: [0; 9], ac: [0; 21] };
+        for (i, r) in state.seq.iter_mut().enumerate() {
+            *r = read_indexed(VGA_SEQ, i as u8);
+        }
+        for (i, r) in state.crtc.iter_mut().enumerate() {
+            *r = read_indexed(VGA_CRTC, i as u8);
+        }
+        for (i, r) in state.gc.iter_mut().enumerate() {
+            *r = read_indexed(VGA_GC, i as u8);
+        }
+        for (i, r) in state.ac.iter_mut().enumerate() {
+            // Reading the status register resets the index/data flip-flop
+            inb(VGA_STATUS);
+            outb(VGA_AC, i as u8);
+            *r = inb(VGA_AC_READ);
+        }
+        inb(VGA_STATUS);
+        outb(VGA_AC, AC_PALETTE_ENABLE);
+        state
+    }
+
+    fn restore(&self) {
+        outb(VGA_MISC_WRITE, self.misc);
+        for (i, &r) in self.seq.iter().enumerate() {
+            write_indexed(VGA_SEQ, i as u8, r);
+        }
+        // CRTC 0-7 are write-protected until bit 7 of register 0x11 clears
+        write_indexed(VGA_CRTC, 0x11, self.crtc[0x11] & 0x7F);
+        for (i, &r) in self.crtc.iter().enumerate() {
+            write_indexed(VGA_CRTC, i as u8, r);
+        }
+        for (i, &r) in self.gc.iter().enumerate() {
+            write_indexed(VGA_GC, i as u8, r);
+        }
+        inb(VGA_STATUS);
+        for (i, &r) in self.ac.iter().enumerate() {
+            outb(VGA_AC, i as u8);
+            outb(VGA_AC, r);
+        }
+        outb(VGA_AC, AC_PALETTE_ENABLE);
+    }
+}
+
+/// Copy the text mode font out of plane 2. Only valid in text mode.
+fn read_font(state: &VgaState) -> Option<Box<Font>> {
+    let window = paging::phys_to_virt(PhysAddr::new(VGA_WINDOW))?.as_u64();
+    // Plane 2 alone, sequential addressing, mapped at 0xA0000
+    write_indexed(VGA_SEQ, 2, 0x04);
+    write_indexed(VGA_SEQ, 4, 0x07);
+    write_indexed(VGA_GC, 4, 0x02);
+    write_indexed(VGA_GC, 5, 0x00);
+    write_indexed(VGA_GC, 6, 0x04);
+    let mut font = Box::new([0u8; 256 * GLYPH_HEIGHT as usize]);
+    for (glyph, rows) in font.chunks_exact_mut(GLYPH_HEIGHT as usize).enumerate() {
+        // Each glyph has a 32-byte slot
+        let slot = window + glyph as u64 * 32;
+        for (row, byte) in rows.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((slot + row as u64) as *const u8) };
+        }
+    }
+    write_indexed(VGA_SEQ, 2, state.seq[2]);
+    write_indexed(VGA_SEQ, 4, state.seq[4]);
+    write_indexed(VGA_GC, 4, state.gc[4]);
+    write_indexed(VGA_GC, 5, state.gc[5]);
+    write_indexed(VGA_GC, 6, state.gc[6]);
+    Some(font)
+}
+
+fn dispi_read(index: u16) -> u16 {
+    unsafe {
+        Port::new(DISPI_INDEX).write(index);
+        Port::new(DISPI_DATA).read()
+    }
+}
+
+fn dispi_write(index: u16, value: u16) {
+    unsafe {
+        Port::new(DISPI_INDEX).write(index);
+        Port::new(DISPI_DATA).write(value);
+    }
+}
+
+/// The screen while a graphics mode is set
+#[derive(Clone, Copy)]
+pub struct Framebuffer {
+    /// Virtual address of the first visible pixel
+    base: u64,
+    pub mode: Mode,
+}
+
+impl Framebuffer {
+    /// Copy `area` of `canvas`, which is screen sized, to the screen
+    pub fn present(&self, canvas: &Canvas, area: Rect) {
+        let area = match area.intersect(&canvas.bounds()).and_then(|a| a.intersect(&self.bounds())) {
+            Some(area) => area,
+            None => return,
+        };
+        for y in area.y..area.bottom() {
+            let row = &canvas.row(y as u32)[area.x as usize..area.right() as usize];
+            let dst = self.base + (y as u64 * self.mode.width as u64 + area.x as u64) * 4;
+            unsafe {
+                core::ptr::copy_nonoverlapping(row.as_ptr(), dst as *mut u32, row.len());
+            }
+        }
+    }
+
+    /// Copy all of `canvas` to the screen with its top left at (x, y)
+    pub fn present_at(&self, canvas: &Canvas, x: i32, y: i32) {
+        let area = match Rect::new(x, y, canvas.width(), canvas.height()).intersect(&self.bounds()) {
+            Some(area) => area,
+            None => return,
+        };
+        for y_on_screen in area.y..area.bottom() {
+            let row = canvas.row((y_on_screen - y) as u32);
+            let row = &row[(area.x - x) as usize..(area.right() - x) as usize];
+            let dst = self.base + (y_on_screen as u64 * self.mode.width as u64 + area.x as u64) * 4;
+            unsafe {
+                core::ptr::copy_nonoverlapping(row.as_ptr(), dst as *mut u32, row.len());
+            }
+        }
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.mode.width, self.mode.height)
+    }
+}
+
+struct Adapter {
+    /// Physical address of video memory
+    lfb: u64,
+    /// Bytes of video memory
+    memory: u64,
+    font: Option<Box<Font>>,
+    /// What to put back on leave(), while in graphics
+    text: Option<VgaState>,
+    /// Physical address and size of the picture, while in graphics
+    picture: Option<(u64, u64)>,
+    /// The mode the console keeps, while it is on
+    console: Option<Mode>,
+    /// Whether something else has the screen from the console
+    lent: bool,
+}
+
+static ADAPTER: Mutex<Option<Adapter>> = Mutex::new(None);
+
+/// Whether a graphics adapter was found
+pub fn available() -> bool {
+    ADAPTER.lock().is_some()
+}
+
+/// Switch to `mode` at 32 bits per pixel
+pub fn enter(mode: Mode) -> Result<Framebuffer, &'static str> {
+    let mut guard = ADAPTER.lock();
+    let adapter = guard.as_mut().ok_or("no graphics adapter")?;
+    if adapter.text.is_some() && (adapter.console.is_none() || adapter.lent) {
+        return Err("already in graphics mode");
+    }
+    let fb = set_mode(adapter, mode)?;
+    adapter.lent = adapter.console.is_some();
+    Ok(fb)
+}
+
+/// Switch to `mode` for the console, which keeps it until leave_console()
+pub fn enter_console(mode: Mode) -> Result<Framebuffer, &'static str> {
+    let mut guard = ADAPTER.lock();
+    let adapter = guard.as_mut().ok_or("no graphics adapter")?;
+    if adapter.text.is_some() {
+        return Err("already in graphics mode");
+    }
+    let fb = set_mode(adapter, mode)?;
+    adapter.console = Some(mode);
+    Ok(fb)
+}
+
+/// The screen, while the console has it
+pub fn console() -> Option<Framebuffer> {
+    let guard = ADAPTER.lock();
+    let adapter = guard.as_ref()?;
+    match (adapter.console, adapter.lent, adapter.picture) {
+        (Some(mode), false, Some((start, _))) => {
+            let base = paging::phys_to_virt(PhysAddr::new(start))?.as_u64();
+            Some(Framebuffer { base, mode })
+        }
+        _ => None,
+    }
+}
+
+fn set_mode(adapter: &mut Adapter, mode: Mode) -> Result<Framebuffer, &'static str> {
+    let pitch = mode.width as u64 * (BPP / 8) as u64;
+    if mode.width == 0 || mode.height == 0 || mode.width > 4096 || mode.height > 4096 {
+        return Err("unsupported resolution");
+    }
+    // The picture starts on the first whole line past the text console
+    // that begins a page
+    let lines_per_page = PAGE_SIZE as u64 >> pitch.trailing_zeros().min(PAGE_SIZE.trailing_zeros());
+    let skip = TEXT_RESERVED.div_ceil(pitch).next_multiple_of(lines_per_page);
+    if (skip + mode.height as u64) * pitch > adapter.memory {
+        return Err("not enough video memory");
+    }
+    let base = paging::phys_to_virt(PhysAddr::new(adapter.lfb + skip * pitch))
+        .ok_or("video memory not mapped")?
+        .as_u64();
+
+    if adapter.text.is_none() {
+        let text = VgaState::save();
+        if adapter.font.is_none() {
+            adapter.font = read_font(&text);
+        }
+        adapter.text = Some(text);
+    }
+    dispi_write(DISPI_ENABLE, 0);
+    dispi_write(DISPI_XRES, mode.width as u16);
+    dispi_write(DISPI_YRES, mode.height as u16);
+    dispi_write(DISPI_BPP, BPP);
+    dispi_write(DISPI_ENABLE, DISPI_ENABLED | DISPI_LFB_ENABLED | DISPI_NOCLEARMEM);
+    dispi_write(DISPI_Y_OFFSET, skip as u16);
+    adapter.picture = Some((adapter.lfb + skip * pitch, mode.height as u64 * pitch));
+    Ok(Framebuffer { base, mode })
+}
+
+/// Go back to the text console, or to the large-font console if it had
+/// the screen before
+pub fn leave() {
+    let mut guard = ADAPTER.lock();
+    if let Some(adapter) = guard.as_mut() {
+        if adapter.lent {
+            adapter.lent = false;
+            if let Some(mode) = adapter.console {
+                if set_mode(adapter, mode).is_ok() {
+                    drop(guard);
+                    super::fbcon::redraw();
+                    return;
+                }
+            }
+        }
+        to_text(adapter);
+    }
+}
+
+/// Take the console off the screen, back to text mode, unless something
+/// has borrowed the screen, in which case leave() goes to text mode
+pub fn leave_console() {
+    let mut guard = ADAPTER.lock();
+    if let Some(adapter) = guard.as_mut() {
+        if !adapter.lent {
+            to_text(adapter);
+        }
+        adapter.console = None;
+    }
+}
+
+fn to_text(adapter: &mut Adapter) {
+    adapter.picture = None;
+    adapter.console = None;
+    if let Some(text) = adapter.text.take() {
+        dispi_write(DISPI_ENABLE, 0);
+        text.restore();
+        super::vga::load_palette();
+    }
+}
+
+/// The physical address of `len` bytes at `offset` into the picture
+fn map(offset: u64, len: u64) -> Option<u64> {
+    let (start, size) = ADAPTER.lock().as_ref()?.picture?;
+    let end = offset.checked_add(len)?;
+    (end <= size.next_multiple_of(PAGE_SIZE as u64)).then_some(start + offset)
+}
+
+/// A copy of the VGA font, once a graphics mode has been entered
+pub fn font() -> Option<Box<Font>> {
+    ADAPTER.lock().as_ref().and_then(|adapter| adapter.font.clone())
+}
+
+pub fn init() {
+    let device = match pci::find_device(PCI_VENDOR, PCI_DEVICE) {
+        Some(device) => device,
+        None => return,
+    };
+    let id = dispi_read(DISPI_ID);
+    if id < DISPI_ID_MIN {
+        crate::println!("  [FB] Adapter version {:#x} too old", id);
+        return;
+    }
+    pci::enable_memory_space(&device);
+    let lfb = pci::memory_bar(&device, 0);
+    let memory = match dispi_read(DISPI_VIDEO_MEMORY_64K) {
+        0 => 16 * 1024 * 1024,
+        blocks => blocks as u64 * 64 * 1024,
+    };
+    crate::println!("  [FB] Bochs graphics adapter, {} KiB at {:#x}", memory / 1024, lfb);
+    *ADAPTER.lock() = Some(Adapter { lfb, memory, font: None, text: None, picture: None, console: None, lent: false });
+    let id = DeviceId::new(MAJOR, MINOR);
+    device::register("fb0", DeviceKind::Char, id, "framebuffer", Some(memory));
+    device::register_map(id, map);
+}
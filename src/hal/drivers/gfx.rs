@@ -0,0 +1,227 @@
+// Drawing on 32-bit pixel buffers
+//
+// A Canvas is a plain array of 0x00RRGGBB pixels. Compositor surfaces and
+// its back buffer are canvases, and the framebuffer driver copies one to
+// the screen. Everything is clipped to the canvas, so callers may draw
+// partly or wholly off it. Text uses the VGA font: 256 code page 437
+// glyphs of 16 rows, one byte per row, leftmost pixel in the top bit.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use super::cp437;
+
+pub type Color = u32;
+
+pub const fn rgb(r: u8, g: u8, b: u8) -> Color {
+    (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+pub const BLACK: Color = rgb(0, 0, 0);
+pub const WHITE: Color = rgb(0xFF, 0xFF, 0xFF);
+
+pub const GLYPH_WIDTH: u32 = 8;
+pub const GLYPH_HEIGHT: u32 = 16;
+
+/// 256 glyphs of GLYPH_HEIGHT bytes
+pub type Font = [u8; 256 * GLYPH_HEIGHT as usize];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub const fn new(x: i32, y: i32, width: u32, height: u32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x.saturating_add(self.width as i32)
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y.saturating_add(self.height as i32)
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// The overlap of two rectangles, None if they do not meet
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        if right <= x || bottom <= y {
+            return None;
+        }
+        Some(Rect::new(x, y, (right - x) as u32, (bottom - y) as u32))
+    }
+}
+
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, color: Color) -> Canvas {
+        Canvas { width, height, pixels: vec![color; width as usize * height as usize] }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// Pixels of row `y`
+    pub fn row(&self, y: u32) -> &[Color] {
+        let start = (y * self.width) as usize;
+        &self.pixels[start..start + self.width as usize]
+    }
+
+    pub fn pixel(&self, x: i32, y: i32) -> Option<Color> {
+        if !self.bounds().contains(x, y) {
+            return None;
+        }
+        Some(self.pixels[y as usize * self.width as usize + x as usize])
+    }
+
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if self.bounds().contains(x, y) {
+            self.pixels[y as usize * self.width as usize + x as usize] = color;
+        }
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        self.pixels.fill(color);
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let area = match rect.intersect(&self.bounds()) {
+            Some(area) => area,
+            None => return,
+        };
+        for y in area.y..area.bottom() {
+            let start = y as usize * self.width as usize + area.x as usize;
+            self.pixels[start..start + area.width as usize].fill(color);
+        }
+    }
+
+    /// A one pixel outline just inside `rect`
+    pub fn draw_rect(&mut self, rect: Rect, color: Color) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        self.fill_rect(Rect::new(rect.x, rect.y, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, rect.bottom() - 1, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, rect.y, 1, rect.height), color);
+        self.fill_rect(Rect::new(rect.right() - 1, rect.y, 1, rect.height), color);
+    }
+
+    /// Copy all of `src` with its top left corner at (x, y)
+    pub fn blit(&mut self, src: &Canvas, x: i32, y: i32) {
+        let area = match Rect::new(x, y, src.width, src.height).intersect(&self.bounds()) {
+            Some(area) => area,
+            None => return,
+        };
+        let src_x = (area.x - x) as usize;
+        for row in area.y..area.bottom() {
+            let src_start = (row - y) as usize * src.width as usize + src_x;
+            let dst_start = row as usize * self.width as usize + area.x as usize;
+            self.pixels[dst_start..dst_start + area.width as usize]
+                .copy_from_slice(&src.pixels[src_start..src_start + area.width as usize]);
+        }
+    }
+
+    /// Stretch or shrink all of `src` to fill `dest`, nearest pixel
+    pub fn blit_scaled(&mut self, src: &Canvas, dest: Rect) {
+        if src.width == 0 || src.height == 0 {
+            return;
+        }
+        let area = match dest.intersect(&self.bounds()) {
+            Some(area) => area,
+            None => return,
+        };
+        for y in area.y..area.bottom() {
+            let src_y = (y - dest.y) as u64 * src.height as u64 / dest.height as u64;
+            let src_row = src.row(src_y as u32);
+            let start = y as usize * self.width as usize;
+            for x in area.x..area.right() {
+                let src_x = (x - dest.x) as u64 * src.width as u64 / dest.width as u64;
+                self.pixels[start + x as usize] = src_row[src_x as usize];
+            }
+        }
+    }
+
+    /// Draw `text` from (x, y) on one line, over `bg` or over what is there
+    /// already. Returns the x just after the last character.
+    pub fn draw_text(&mut self, x: i32, y: i32, text: &str, fg: Color, bg: Option<Color>, font: &Font) -> i32 {
+        let mut x = x;
+        for c in text.chars() {
+            let glyph = cp437::from_char(c) as usize * GLYPH_HEIGHT as usize;
+            for (row, &bits) in font[glyph..glyph + GLYPH_HEIGHT as usize].iter().enumerate() {
+                for col in 0..GLYPH_WIDTH as i32 {
+                    let set = bits & (0x80 >> col) != 0;
+                    match (set, bg) {
+                        (true, _) => self.set_pixel(x + col, y + row as i32, fg),
+                        (false, Some(bg)) => self.set_pixel(x + col, y + row as i32, bg),
+                        (false, None) => {}
+                    }
+                }
+            }
+            x += GLYPH_WIDTH as i32;
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_clipping() {
+        let mut canvas = Canvas::new(4, 3, BLACK);
+        canvas.fill_rect(Rect::new(-2, 1, 4, 10), WHITE);
+        assert_eq!(canvas.row(0), [BLACK; 4]);
+        assert_eq!(canvas.row(2), [WHITE, WHITE, BLACK, BLACK]);
+
+        let mut src = Canvas::new(2, 2, rgb(1, 2, 3));
+        src.set_pixel(1, 1, rgb(9, 9, 9));
+        canvas.blit(&src, 2, -1);
+        assert_eq!(canvas.pixel(2, 0), Some(rgb(1, 2, 3)));
+        assert_eq!(canvas.pixel(3, 0), Some(rgb(9, 9, 9)));
+        assert_eq!(canvas.pixel(4, 0), None);
+        assert_eq!(Rect::new(0, 0, 2, 2).intersect(&Rect::new(2, 0, 2, 2)), None);
+    }
+
+    #[test_case]
+    fn test_scaling_and_text() {
+        let mut src = Canvas::new(2, 1, BLACK);
+        src.set_pixel(1, 0, WHITE);
+        let mut canvas = Canvas::new(4, 2, rgb(5, 5, 5));
+        canvas.blit_scaled(&src, Rect::new(0, 0, 4, 2));
+        assert_eq!(canvas.row(1), [BLACK, BLACK, WHITE, WHITE]);
+
+        let mut font = [0u8; 256 * 16];
+        font[b'A' as usize * 16] = 0b1000_0001;
+        let mut canvas = Canvas::new(10, 16, BLACK);
+        assert_eq!(canvas.draw_text(1, 0, "A", WHITE, None, &font), 9);
+        assert_eq!(canvas.pixel(1, 0), Some(WHITE));
+        assert_eq!(canvas.pixel(8, 0), Some(WHITE));
+        assert_eq!(canvas.pixel(2, 0), Some(BLACK));
+    }
+}
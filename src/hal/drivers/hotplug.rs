@@ -0,0 +1,227 @@
+// Device hotplug events
+//
+// Bus drivers report devices coming and going with report(); the device
+// registry does so for every register() and unregister(), so boot-time
+// probing shows up as a burst of "add" events. Reports are only queued:
+// drivers often hold their own locks when they notice a change, so the
+// subscribers (/dev nodes, automounting) run later from process(), with
+// no driver lock held. Delivered events are also kept in a log that
+// userland reads through /dev/hotplug, one line per event.
+//
+// Nothing raises an interrupt on insertion yet, so poll() asks the buses
+// that can change under us to look, then delivers what they found. The
+// shell calls it before each command.
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+use crate::fs::vfs::node::DeviceId;
+use super::device::{self, DeviceKind};
+
+/// Major and minor number of /dev/hotplug
+pub const MAJOR: u16 = 10;
+pub const MINOR: u16 = 0;
+
+/// Events kept for readers of /dev/hotplug
+const LOG_SIZE: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Add,
+    Remove,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Add => "add",
+            Action::Remove => "remove",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// Increases by one per event, so readers can tell if they missed some
+    pub seq: u64,
+    pub action: Action,
+    /// "block" and "char" for device nodes, otherwise the bus, e.g. "pci"
+    pub subsystem: &'static str,
+    /// Name under /dev for devices, bus address otherwise
+    pub name: String,
+    pub device: Option<DeviceId>,
+}
+
+/// "<seq> <action> <subsystem> <name> [<major>:<minor>]"
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {}", self.seq, self.action.as_str(), self.subsystem, self.name)?;
+        if let Some(id) = self.device {
+            write!(f, " {}:{}", id.major, id.minor)?;
+        }
+        Ok(())
+    }
+}
+
+pub type Handler = fn(&Event);
+
+/// The event text handed to readers. Lines that fall off the front are
+/// counted in `base`, so a read offset keeps meaning the same byte.
+struct Log {
+    lines: VecDeque<String>,
+    base: u64,
+}
+
+impl Log {
+    const fn new() -> Log {
+        Log { lines: VecDeque::new(), base: 0 }
+    }
+
+    fn push(&mut self, line: String) {
+        if self.lines.len() == LOG_SIZE {
+            if let Some(old) = self.lines.pop_front() {
+                self.base += old.len() as u64;
+            }
+        }
+        self.lines.push_back(line);
+    }
+
+    /// Copy the text from byte `offset` on. Readers that fell behind by
+    /// more than LOG_SIZE events see blank lines where the lost ones were.
+    fn read(&self, offset: u64, buf: &mut [u8]) -> usize {
+        if offset < self.base {
+            let lost = ((self.base - offset) as usize).min(buf.len());
+            buf[..lost].fill(b'\n');
+            return lost;
+        }
+        let mut skip = (offset - self.base) as usize;
+        let mut len = 0;
+        for line in &self.lines {
+            if skip >= line.len() {
+                skip -= line.len();
+                continue;
+            }
+            let n = (line.len() - skip).min(buf.len() - len);
+            buf[len..len + n].copy_from_slice(&line.as_bytes()[skip..skip + n]);
+            len += n;
+            skip = 0;
+            if len == buf.len() {
+                break;
+            }
+        }
+        len
+    }
+}
+
+struct State {
+    seq: u64,
+    pending: Vec<Event>,
+    subscribers: Vec<Handler>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State { seq: 0, pending: Vec::new(), subscribers: Vec::new() });
+static LOG: Mutex<Log> = Mutex::new(Log::new());
+
+/// Queue an event for the next process()
+pub fn report(action: Action, subsystem: &'static str, name: &str, device: Option<DeviceId>) {
+    let mut state = STATE.lock();
+    state.seq += 1;
+    let event = Event { seq: state.seq, action, subsystem, name: String::from(name), device };
+    state.pending.push(event);
+}
+
+/// Report a device from the registry
+pub fn report_device(action: Action, kind: DeviceKind, name: &str, id: DeviceId) {
+    let subsystem = match kind {
+        DeviceKind::Char => "char",
+        DeviceKind::Block => "block",
+    };
+    report(action, subsystem, name, Some(id));
+}
+
+/// Have `handler` called for every event delivered from now on. Events
+/// queued before it subscribed are not replayed, so subsystems subscribe
+/// before the first process().
+pub fn subscribe(handler: Handler) {
+    STATE.lock().subscribers.push(handler);
+}
+
+/// Deliver the queued events. Handlers may report further events; those
+/// are delivered in the same call.
+pub fn process() {
+    loop {
+        let (events, subscribers) = {
+            let mut state = STATE.lock();
+            if state.pending.is_empty() {
+                return;
+            }
+            (core::mem::take(&mut state.pending), state.subscribers.clone())
+        };
+        for event in events {
+            for handler in &subscribers {
+                handler(&event);
+            }
+            LOG.lock().push(format!("{}\n", event));
+        }
+    }
+}
+
+/// Let the buses without change interrupts look for devices, then deliver
+pub fn poll() {
+    crate::alloc_tag!(Driver);
+    #[cfg(feature = "sdhci")]
+    super::sdhci::poll();
+    process();
+}
+
+/// Read the event log from byte `offset`, for /dev/hotplug
+pub fn read(offset: u64, buf: &mut [u8]) -> usize {
+    LOG.lock().read(offset, buf)
+}
+
+pub fn init() {
+    device::register("hotplug", DeviceKind::Char, DeviceId::new(MAJOR, MINOR), "hotplug", None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(seq: u64, action: Action, name: &str, device: Option<DeviceId>) -> Event {
+        Event { seq, action, subsystem: "block", name: String::from(name), device }
+    }
+
+    #[test_case]
+    fn test_event_format() {
+        let add = event(3, Action::Add, "mmcblk0", Some(DeviceId::new(179, 0)));
+        assert_eq!(format!("{}", add), "3 add block mmcblk0 179:0");
+        let gone = Event { subsystem: "pci", ..event(4, Action::Remove, "00:03.0", None) };
+        assert_eq!(format!("{}", gone), "4 remove pci 00:03.0");
+    }
+
+    #[test_case]
+    fn test_log_offsets() {
+        let mut log = Log::new();
+        log.push(String::from("1 add\n"));
+        log.push(String::from("2 remove\n"));
+        let mut buf = [0u8; 32];
+        let n = log.read(0, &mut buf);
+        assert_eq!(&buf[..n], b"1 add\n2 remove\n");
+        let n = log.read(3, &mut buf[..5]);
+        assert_eq!(&buf[..n], b"dd\n2 ");
+        assert_eq!(log.read(15, &mut buf), 0);
+
+        for seq in 3..3 + LOG_SIZE as u64 {
+            log.push(format!("{} add\n", seq));
+        }
+        // The first two lines have gone; their bytes read as blank lines
+        assert_eq!(log.base, 15);
+        let n = log.read(0, &mut buf);
+        assert_eq!(&buf[..n], &[b'\n'; 15]);
+        let n = log.read(15, &mut buf[..6]);
+        assert_eq!(&buf[..n], b"3 add\n");
+    }
+}
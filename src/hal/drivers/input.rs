@@ -0,0 +1,216 @@
+// Key events and /dev/input/kbd0
+//
+// The keyboard driver reports every key going down or coming up here, by
+// its Linux input code, alongside the characters it still decodes for the
+// terminals. Each report is queued as a KeyEvent with the modifiers and
+// locks in effect after it and the uptime, for readers of
+// /dev/input/kbd0. A read takes whole events and never waits: nothing
+// queued reads as 0 bytes. Events nobody reads are dropped, oldest first,
+// once QUEUE_SIZE are waiting.
+//
+// The keys held down and the locks are kept here too, for the keyboard
+// driver's own shortcuts and for the KBDGSTATE ioctl. Reports come from
+// the keyboard interrupt, so the queue is a fixed ring and everything
+// else takes the lock with interrupts off.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::fs::vfs::node::DeviceId;
+use crate::fs::{FsError, FsResult};
+use crate::kernel::sys::syscalls::{
+    KeyEvent, KeyboardState, KEYMOD_ALT, KEYMOD_CAPSLOCK, KEYMOD_CTRL, KEYMOD_META, KEYMOD_NUMLOCK,
+    KEYMOD_SCROLLLOCK, KEYMOD_SHIFT, KEY_PRESSED, KEY_RELEASED, KEY_REPEATED,
+};
+use super::device::{self, DeviceKind};
+use super::pit;
+
+/// Character major of the input devices, and the minor of kbd0, as
+/// Linux numbers its event devices
+pub const MAJOR: u16 = 13;
+pub const KBD_MINOR: u16 = 64;
+
+const QUEUE_SIZE: usize = 256;
+/// Codes the held-down bitmap covers
+const KEY_COUNT: u16 = 256;
+
+// The Linux input codes of the modifiers and locks
+pub const KEY_LEFTCTRL: u16 = 29;
+pub const KEY_LEFTSHIFT: u16 = 42;
+pub const KEY_RIGHTSHIFT: u16 = 54;
+pub const KEY_LEFTALT: u16 = 56;
+pub const KEY_CAPSLOCK: u16 = 58;
+pub const KEY_NUMLOCK: u16 = 69;
+pub const KEY_SCROLLLOCK: u16 = 70;
+pub const KEY_RIGHTCTRL: u16 = 97;
+pub const KEY_RIGHTALT: u16 = 100;
+pub const KEY_LEFTMETA: u16 = 125;
+pub const KEY_RIGHTMETA: u16 = 126;
+
+/// Modifier bits and the keys that hold them
+const MODIFIERS: [(u16, [u16; 2]); 4] = [
+    (KEYMOD_SHIFT, [KEY_LEFTSHIFT, KEY_RIGHTSHIFT]),
+    (KEYMOD_CTRL, [KEY_LEFTCTRL, KEY_RIGHTCTRL]),
+    (KEYMOD_ALT, [KEY_LEFTALT, KEY_RIGHTALT]),
+    (KEYMOD_META, [KEY_LEFTMETA, KEY_RIGHTMETA]),
+];
+
+/// Lock bits and the keys that toggle them
+const LOCKS: [(u16, u16); 3] = [
+    (KEYMOD_CAPSLOCK, KEY_CAPSLOCK),
+    (KEYMOD_NUMLOCK, KEY_NUMLOCK),
+    (KEYMOD_SCROLLLOCK, KEY_SCROLLLOCK),
+];
+
+const NO_EVENT: KeyEvent = KeyEvent { time_ms: 0, code: 0, modifiers: 0, value: 0, reserved: [0; 3] };
+
+struct Keys {
+    down: [u64; 4],
+    locks: u16,
+    queue: [KeyEvent; QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl Keys {
+    const fn new() -> Self {
+        Keys { down: [0; 4], locks: 0, queue: [NO_EVENT; QUEUE_SIZE], head: 0, len: 0 }
+    }
+
+    fn is_down(&self, code: u16) -> bool {
+        self.down[code as usize / 64] & 1 << (code % 64) != 0
+    }
+
+    fn modifiers(&self) -> u16 {
+        let held = MODIFIERS.iter()
+            .filter(|(_, keys)| keys.iter().any(|&key| self.is_down(key)))
+            .fold(0, |bits, (bit, _)| bits | bit);
+        held | self.locks
+    }
+
+    /// Note `code` going down or up, returning the KeyEvent value it is
+    fn update(&mut self, code: u16, pressed: bool) -> u8 {
+        let bit = 1 << (code % 64);
+        let word = &mut self.down[code as usize / 64];
+        let was_down = *word & bit != 0;
+        if !pressed {
+            *word &= !bit;
+            return KEY_RELEASED;
+        }
+        *word |= bit;
+        if was_down {
+            return KEY_REPEATED;
+        }
+        for &(lock, key) in &LOCKS {
+            if key == code {
+                self.locks ^= lock;
+            }
+        }
+        KEY_PRESSED
+    }
+
+    fn push(&mut self, event: KeyEvent) {
+        if self.len == QUEUE_SIZE {
+            self.pop();
+        }
+        self.queue[(self.head + self.len) % QUEUE_SIZE] = event;
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<KeyEvent> {
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.queue[self.head];
+        self.head = (self.head + 1) % QUEUE_SIZE;
+        self.len -= 1;
+        Some(event)
+    }
+}
+
+static KEYS: Mutex<Keys> = Mutex::new(Keys::new());
+
+/// Register /dev/input/kbd0
+pub fn init() {
+    device::register("input/kbd0", DeviceKind::Char, DeviceId::new(MAJOR, KBD_MINOR), "keyboard", None);
+}
+
+/// Called by the keyboard driver, from its interrupt: the key `code` went
+/// down (again, if it was already) or came up
+pub fn report_key(code: u16, pressed: bool) {
+    if code >= KEY_COUNT {
+        return;
+    }
+    let mut keys = KEYS.lock();
+    let value = keys.update(code, pressed);
+    let event = KeyEvent { time_ms: pit::get_uptime_ms(), code, modifiers: keys.modifiers(), value, reserved: [0; 3] };
+    keys.push(event);
+}
+
+/// The KEYMOD_* bits in effect
+pub fn modifiers() -> u16 {
+    interrupts::without_interrupts(|| KEYS.lock().modifiers())
+}
+
+/// The modifiers and the keys held down, for KBDGSTATE
+pub fn state() -> KeyboardState {
+    interrupts::without_interrupts(|| {
+        let keys = KEYS.lock();
+        KeyboardState { modifiers: keys.modifiers(), keys: keys.down, ..KeyboardState::default() }
+    })
+}
+
+/// Read as many whole events as `buf` holds and are queued, without
+/// waiting; a buffer too small for one is refused
+pub fn read(buf: &mut [u8]) -> FsResult<usize> {
+    const SIZE: usize = core::mem::size_of::<KeyEvent>();
+    if buf.len() < SIZE {
+        return Err(FsError::InvalidArgument);
+    }
+    let mut len = 0;
+    interrupts::without_interrupts(|| {
+        let mut keys = KEYS.lock();
+        while len + SIZE <= buf.len() {
+            let Some(event) = keys.pop() else {
+                break;
+            };
+            let bytes = unsafe { core::slice::from_raw_parts(&event as *const KeyEvent as *const u8, SIZE) };
+            buf[len..len + SIZE].copy_from_slice(bytes);
+            len += SIZE;
+        }
+    });
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_modifiers_and_locks() {
+        let mut keys = Keys::new();
+        assert_eq!(keys.update(KEY_RIGHTSHIFT, true), KEY_PRESSED);
+        assert_eq!(keys.update(KEY_RIGHTSHIFT, true), KEY_REPEATED);
+        assert_eq!(keys.update(KEY_LEFTCTRL, true), KEY_PRESSED);
+        assert_eq!(keys.modifiers(), KEYMOD_SHIFT | KEYMOD_CTRL);
+        assert_eq!(keys.update(KEY_RIGHTSHIFT, false), KEY_RELEASED);
+        assert_eq!(keys.modifiers(), KEYMOD_CTRL);
+
+        // A lock toggles when its key goes down, not as it repeats
+        keys.update(KEY_CAPSLOCK, true);
+        keys.update(KEY_CAPSLOCK, true);
+        keys.update(KEY_CAPSLOCK, false);
+        assert_eq!(keys.modifiers(), KEYMOD_CTRL | KEYMOD_CAPSLOCK);
+        keys.update(KEY_CAPSLOCK, true);
+        assert_eq!(keys.modifiers() & KEYMOD_CAPSLOCK, 0);
+    }
+
+    #[test_case]
+    fn test_queue_drops_the_oldest() {
+        let mut keys = Keys::new();
+        for n in 0..QUEUE_SIZE as u64 + 2 {
+            keys.push(KeyEvent { time_ms: n, ..NO_EVENT });
+        }
+        assert_eq!(keys.pop().map(|e| e.time_ms), Some(2));
+        assert_eq!(keys.len, QUEUE_SIZE - 1);
+    }
+}
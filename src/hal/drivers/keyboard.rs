@@ -3,6 +3,8 @@ extern crate alloc;
 use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
 use spin::Mutex;
 use lazy_static::lazy_static;
+use crate::kernel::sys::syscalls::{KEYMOD_ALT, KEYMOD_CAPSLOCK, KEYMOD_CTRL, KEYMOD_SHIFT};
+use super::input;
 
 const KEYBOARD_BUFFER_SIZE: usize = 256;
 
@@ -145,36 +147,96 @@ lazy_static! {
     static ref SCANCODE_BUFFER: Mutex<U8RingBuffer> = Mutex::new(U8RingBuffer::new());
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct KeyEvent {
-    pub scancode: u8,
-    pub key: Option<DecodedKey>,
-    pub pressed: bool,
+/// Scancode set 1 as keys going down and up, by their Linux input codes
+struct Set1 {
+    /// An 0xE0 came before
+    extended: bool,
+    /// Bytes of a Pause sequence still to come
+    skip: u8,
 }
 
-static mut SHIFT_PRESSED: bool = false;
-static mut CTRL_PRESSED: bool = false;
-static mut ALT_PRESSED: bool = false;
-static mut CAPS_LOCK: bool = false;
+impl Set1 {
+    const fn new() -> Self {
+        Set1 { extended: false, skip: 0 }
+    }
 
-pub fn init() {
-    unsafe {
-        SHIFT_PRESSED = false;
-        CTRL_PRESSED = false;
-        ALT_PRESSED = false;
-        CAPS_LOCK = false;
+    /// The key `byte` completes and whether it went down, if any
+    fn decode(&mut self, byte: u8) -> Option<(u16, bool)> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+        match byte {
+            0xE0 => {
+                self.extended = true;
+                return None;
+            }
+            // Pause sends E1 1D 45 E1 9D C5 and nothing when let go
+            0xE1 => {
+                self.skip = 5;
+                return None;
+            }
+            // Errors, and acknowledgements of commands
+            0x00 | 0xFA | 0xFE | 0xFF => return None,
+            _ => {}
+        }
+        let (make, pressed) = (byte & 0x7F, byte & 0x80 == 0);
+        let code = if core::mem::take(&mut self.extended) {
+            extended_code(make)?
+        } else {
+            // Linux numbers the keys of set 1 by their make codes
+            make as u16
+        };
+        Some((code, pressed))
     }
 }
 
+/// The Linux input code of the 0xE0-prefixed key `make`; the fake shifts
+/// some keys send around themselves are none
+fn extended_code(make: u8) -> Option<u16> {
+    Some(match make {
+        0x1C => 96,                      // KEY_KPENTER
+        0x1D => input::KEY_RIGHTCTRL,
+        0x35 => 98,                      // KEY_KPSLASH
+        0x37 => 99,                      // KEY_SYSRQ
+        0x38 => input::KEY_RIGHTALT,
+        0x47 => 102,                     // KEY_HOME
+        0x48 => 103,                     // KEY_UP
+        0x49 => 104,                     // KEY_PAGEUP
+        0x4B => 105,                     // KEY_LEFT
+        0x4D => 106,                     // KEY_RIGHT
+        0x4F => 107,                     // KEY_END
+        0x50 => 108,                     // KEY_DOWN
+        0x51 => 109,                     // KEY_PAGEDOWN
+        0x52 => 110,                     // KEY_INSERT
+        0x53 => 111,                     // KEY_DELETE
+        0x5B => input::KEY_LEFTMETA,
+        0x5C => input::KEY_RIGHTMETA,
+        0x5D => 127,                     // KEY_COMPOSE
+        _ => return None,
+    })
+}
+
+static SET1: Mutex<Set1> = Mutex::new(Set1::new());
+
+/// Register /dev/input/kbd0, which has every key going down and up
+pub fn init() {
+    input::init();
+}
+
 pub fn handle_scancode(scancode: u8) {
     let mut keyboard = KEYBOARD.lock();
     SCANCODE_BUFFER.lock().push(scancode);
+    // Reported first, so the modifiers are current for the shortcuts below
+    if let Some((code, pressed)) = SET1.lock().decode(scancode) {
+        input::report_key(code, pressed);
+    }
 
     if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
         if let Some(key) = keyboard.process_keyevent(key_event) {
             match key {
                 DecodedKey::Unicode(character) => {
-                    // Only enqueue; shell_loop reads and echoes
+                    // Only enqueue; getty hands it to the terminal on the screen
                     KEY_BUFFER.lock().push(character);
                 }
                 DecodedKey::RawKey(raw_key) => {
@@ -189,18 +251,6 @@ fn handle_raw_key(key: pc_keyboard::KeyCode) {
     use pc_keyboard::KeyCode;
 
     match key {
-        KeyCode::LShift | KeyCode::RShift => {
-            unsafe { SHIFT_PRESSED = true; }
-        }
-        KeyCode::LControl | KeyCode::RControl => {
-            unsafe { CTRL_PRESSED = true; }
-        }
-        KeyCode::LAlt => {
-            unsafe { ALT_PRESSED = true; }
-        }
-        KeyCode::CapsLock => {
-            unsafe { CAPS_LOCK = !CAPS_LOCK; }
-        }
         KeyCode::ArrowUp => {
             crate::kernel::sys::handle_special_key(SpecialKey::ArrowUp);
         }
@@ -231,6 +281,13 @@ fn handle_raw_key(key: pc_keyboard::KeyCode) {
         KeyCode::Insert => {
             crate::kernel::sys::handle_special_key(SpecialKey::Insert);
         }
+        // Alt+F1 to Alt+F6 switch virtual terminals
+        KeyCode::F1 if is_alt_pressed() => super::tty::request_switch(1),
+        KeyCode::F2 if is_alt_pressed() => super::tty::request_switch(2),
+        KeyCode::F3 if is_alt_pressed() => super::tty::request_switch(3),
+        KeyCode::F4 if is_alt_pressed() => super::tty::request_switch(4),
+        KeyCode::F5 if is_alt_pressed() => super::tty::request_switch(5),
+        KeyCode::F6 if is_alt_pressed() => super::tty::request_switch(6),
         KeyCode::F1 => crate::kernel::sys::handle_special_key(SpecialKey::F1),
         KeyCode::F2 => crate::kernel::sys::handle_special_key(SpecialKey::F2),
         KeyCode::F3 => crate::kernel::sys::handle_special_key(SpecialKey::F3),
@@ -278,10 +335,12 @@ pub fn read_char() -> Option<char> {
 }
 
 pub fn read_char_blocking() -> char {
+    crate::hal::cpu::context::assert_may_sleep("read_char_blocking");
     loop {
         if let Some(c) = read_char() {
             return c;
         }
+        crate::kernel::idle::run();
         x86_64::instructions::hlt();
     }
 }
@@ -291,19 +350,19 @@ pub fn read_scancode() -> Option<u8> {
 }
 
 pub fn is_shift_pressed() -> bool {
-    unsafe { SHIFT_PRESSED }
+    input::modifiers() & KEYMOD_SHIFT != 0
 }
 
 pub fn is_ctrl_pressed() -> bool {
-    unsafe { CTRL_PRESSED }
+    input::modifiers() & KEYMOD_CTRL != 0
 }
 
 pub fn is_alt_pressed() -> bool {
-    unsafe { ALT_PRESSED }
+    input::modifiers() & KEYMOD_ALT != 0
 }
 
 pub fn is_caps_lock_on() -> bool {
-    unsafe { CAPS_LOCK }
+    input::modifiers() & KEYMOD_CAPSLOCK != 0
 }
 
 pub fn clear_buffer() {
@@ -318,3 +377,29 @@ pub fn buffer_len() -> usize {
 pub fn peek_char() -> Option<char> {
     KEY_BUFFER.lock().front()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_set1_decoding() {
+        let mut set1 = Set1::new();
+        // A, down and up
+        assert_eq!(set1.decode(0x1E), Some((30, true)));
+        assert_eq!(set1.decode(0x9E), Some((30, false)));
+        // Right Ctrl and the arrow up, which come after an E0
+        assert_eq!(set1.decode(0xE0), None);
+        assert_eq!(set1.decode(0x1D), Some((input::KEY_RIGHTCTRL, true)));
+        assert_eq!(set1.decode(0xE0), None);
+        assert_eq!(set1.decode(0xC8), Some((103, false)));
+        // The fake shift around Print Screen, and Pause, are not keys
+        assert_eq!(set1.decode(0xE0), None);
+        assert_eq!(set1.decode(0x2A), None);
+        for byte in [0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5] {
+            assert_eq!(set1.decode(byte), None);
+        }
+        assert_eq!(set1.decode(0xFA), None);
+        assert_eq!(set1.decode(0x2A), Some((input::KEY_LEFTSHIFT, true)));
+    }
+}
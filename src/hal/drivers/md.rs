@@ -0,0 +1,1035 @@
+// Software RAID
+//
+// An md array is a block device made of several others, its members,
+// either striped (RAID0: chunks go round the members in turn, for speed)
+// or mirrored (RAID1: every member holds all of it, so it survives all
+// but one of them failing). Arrays register as mdN under MD_MAJOR.
+//
+// Every member starts with a 4 KiB metadata area, the array's data
+// following it. The area's first sector is a superblock naming the array
+// by UUID and saying which slot the member fills, how far it is in sync,
+// and an event count bumped whenever the array changes state, so
+// assembling finds the members again on any disks and tells a member that
+// missed changes - one failed, or absent last time - from current ones.
+//
+// A RAID1 member failing, by an I/O error or `mdadm fail`, leaves the
+// array degraded: reads and writes go to the members still in sync. A
+// member added in its place, or a stale one found on assembly, is rebuilt
+// from a current one in the background, as idle work, a step at a time;
+// reads use it only below the point it has been rebuilt to. A RAID0 array
+// has no redundancy, so it fails with any member.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use spin::{Mutex, RwLock};
+use crate::fs::ext4::BlockDevice;
+use crate::fs::vfs::node::DeviceId;
+use super::device::{self, DeviceKind, SharedBlockDevice};
+
+/// The major number Linux gives md arrays
+pub const MD_MAJOR: u16 = 9;
+pub const SECTOR_SIZE: u64 = 512;
+pub const MD_MAGIC: u32 = 0xa92b_4efc;
+const VERSION: u32 = 1;
+/// Sectors of metadata at the start of every member
+pub const DATA_OFFSET: u64 = 8;
+/// Default RAID0 chunk, in sectors: 64 KiB
+pub const DEFAULT_CHUNK: u64 = 128;
+pub const MAX_MEMBERS: usize = 16;
+/// Sectors a rebuild copies each time the system is idle
+const RESYNC_STEP: u64 = 256;
+/// A member's recovery point once it is wholly in sync
+const IN_SYNC: u64 = u64::MAX;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MdError {
+    InvalidName,
+    Exists,
+    NotFound,
+    Busy,
+    NoDevice(String),
+    /// The device is already a member of a running array
+    InUse(String),
+    NoSuperblock(String),
+    /// Arguments or members that cannot make an array, and why
+    Invalid(&'static str),
+    Io(&'static str),
+}
+
+impl fmt::Display for MdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MdError::InvalidName => write!(f, "arrays are named mdN"),
+            MdError::Exists => write!(f, "array already exists"),
+            MdError::NotFound => write!(f, "no such array"),
+            MdError::Busy => write!(f, "array is in use"),
+            MdError::NoDevice(name) => write!(f, "{}: no such block device", name),
+            MdError::InUse(name) => write!(f, "{}: already in an array", name),
+            MdError::NoSuperblock(name) => write!(f, "{}: no md superblock", name),
+            MdError::Invalid(what) => write!(f, "{}", what),
+            MdError::Io(what) => write!(f, "I/O error: {}", what),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Raid0,
+    Raid1,
+}
+
+impl Level {
+    /// "0", "raid0" or "stripe"; "1", "raid1" or "mirror"
+    pub fn parse(text: &str) -> Option<Level> {
+        match text {
+            "0" | "raid0" | "stripe" => Some(Level::Raid0),
+            "1" | "raid1" | "mirror" => Some(Level::Raid1),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Level::Raid0 => "raid0",
+            Level::Raid1 => "raid1",
+        })
+    }
+}
+
+/// The superblock in a member's first sector, little-endian:
+///
+///   0 magic, 4 version, 8 UUID[16], 24 preferred minor (u32), 28 level,
+///   32 raid disks, 36 chunk sectors, 40 role (u32), 44 reserved,
+///   48 sectors per member, 56 events, 64 recovery point (u64),
+///   72 CRC-32 of bytes 0..72
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Superblock {
+    pub uuid: [u8; 16],
+    pub minor: u16,
+    pub level: Level,
+    pub raid_disks: u32,
+    pub chunk: u64,
+    pub role: u32,
+    pub member_sectors: u64,
+    pub events: u64,
+    /// Sectors of the member known to be current, IN_SYNC if all of them
+    pub recovery: u64,
+}
+
+const CHECKSUMMED: usize = 72;
+
+impl Superblock {
+    pub fn encode(&self) -> [u8; SECTOR_SIZE as usize] {
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        sector[0..4].copy_from_slice(&MD_MAGIC.to_le_bytes());
+        sector[4..8].copy_from_slice(&VERSION.to_le_bytes());
+        sector[8..24].copy_from_slice(&self.uuid);
+        sector[24..28].copy_from_slice(&(self.minor as u32).to_le_bytes());
+        let level: u32 = match self.level {
+            Level::Raid0 => 0,
+            Level::Raid1 => 1,
+        };
+        sector[28..32].copy_from_slice(&level.to_le_bytes());
+        sector[32..36].copy_from_slice(&self.raid_disks.to_le_bytes());
+        sector[36..40].copy_from_slice(&(self.chunk as u32).to_le_bytes());
+        sector[40..44].copy_from_slice(&self.role.to_le_bytes());
+        sector[48..56].copy_from_slice(&self.member_sectors.to_le_bytes());
+        sector[56..64].copy_from_slice(&self.events.to_le_bytes());
+        sector[64..72].copy_from_slice(&self.recovery.to_le_bytes());
+        let crc = crate::fs::archive::gzip::crc32(&sector[..CHECKSUMMED]);
+        sector[CHECKSUMMED..CHECKSUMMED + 4].copy_from_slice(&crc.to_le_bytes());
+        sector
+    }
+
+    /// None unless `sector` holds a superblock with a good checksum
+    pub fn decode(sector: &[u8]) -> Option<Superblock> {
+        let u32_at = |at: usize| u32::from_le_bytes(sector[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(sector[at..at + 8].try_into().unwrap());
+        if sector.len() < SECTOR_SIZE as usize || u32_at(0) != MD_MAGIC || u32_at(4) != VERSION {
+            return None;
+        }
+        if u32_at(CHECKSUMMED) != crate::fs::archive::gzip::crc32(&sector[..CHECKSUMMED]) {
+            return None;
+        }
+        let level = match u32_at(28) {
+            0 => Level::Raid0,
+            1 => Level::Raid1,
+            _ => return None,
+        };
+        let mut uuid = [0u8; 16];
+        uuid.copy_from_slice(&sector[8..24]);
+        Some(Superblock {
+            uuid,
+            minor: u32_at(24) as u16,
+            level,
+            raid_disks: u32_at(32),
+            chunk: u32_at(36) as u64,
+            role: u32_at(40),
+            member_sectors: u64_at(48),
+            events: u64_at(56),
+            recovery: u64_at(64),
+        })
+    }
+}
+
+/// The block size a member needs: whole sectors, fitting the metadata
+/// area a whole number of times
+fn usable_block_size(size: u32) -> bool {
+    let metadata = (DATA_OFFSET * SECTOR_SIZE) as u32;
+    size >= SECTOR_SIZE as u32 && size <= metadata && size % SECTOR_SIZE as u32 == 0 && metadata % size == 0
+}
+
+/// Read the superblock of `device`, None if it has none
+pub fn read_superblock(device: &SharedBlockDevice) -> Result<Option<Superblock>, &'static str> {
+    let device = device.read();
+    if !usable_block_size(device.block_size()) {
+        return Ok(None);
+    }
+    let mut block = vec![0u8; device.block_size() as usize];
+    device.read_block(0, &mut block)?;
+    Ok(Superblock::decode(&block))
+}
+
+fn write_superblock(device: &SharedBlockDevice, superblock: &Superblock) -> Result<(), &'static str> {
+    let mut device = device.write();
+    let mut block = vec![0u8; device.block_size() as usize];
+    device.read_block(0, &mut block)?;
+    block[..SECTOR_SIZE as usize].copy_from_slice(&superblock.encode());
+    device.write_block(0, &block)?;
+    device.flush()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberState {
+    InSync,
+    /// Being rebuilt; current below its recovery point
+    Rebuilding,
+    Faulty,
+    Missing,
+}
+
+impl MemberState {
+    fn from_u8(state: u8) -> MemberState {
+        match state {
+            0 => MemberState::InSync,
+            1 => MemberState::Rebuilding,
+            2 => MemberState::Faulty,
+            _ => MemberState::Missing,
+        }
+    }
+}
+
+impl fmt::Display for MemberState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            MemberState::InSync => "in sync",
+            MemberState::Rebuilding => "rebuilding",
+            MemberState::Faulty => "faulty",
+            MemberState::Missing => "missing",
+        })
+    }
+}
+
+struct Member {
+    name: String,
+    device: Option<SharedBlockDevice>,
+    /// A MemberState; reads mark a member faulty through a shared borrow
+    state: AtomicU8,
+    recovery: u64,
+}
+
+impl Member {
+    fn new(name: String, device: Option<SharedBlockDevice>, state: MemberState, recovery: u64) -> Member {
+        Member { name, device, state: AtomicU8::new(state as u8), recovery }
+    }
+
+    fn state(&self) -> MemberState {
+        MemberState::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    fn set_state(&self, state: MemberState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    /// Whether the member's sectors up to `end` hold current data
+    fn current(&self, end: u64) -> bool {
+        match self.state() {
+            MemberState::InSync => true,
+            MemberState::Rebuilding => end <= self.recovery,
+            _ => false,
+        }
+    }
+
+    fn read(&self, sector: u64, per_block: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let device = self.device.as_ref().ok_or("member missing")?;
+        device.read().read_block((DATA_OFFSET + sector) / per_block, buf)
+    }
+
+    fn write(&self, sector: u64, per_block: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let device = self.device.as_ref().ok_or("member missing")?;
+        device.write().write_block((DATA_OFFSET + sector) / per_block, buf)
+    }
+}
+
+pub struct MdArray {
+    minor: u16,
+    uuid: [u8; 16],
+    level: Level,
+    chunk: u64,
+    member_sectors: u64,
+    block_size: u32,
+    events: AtomicU64,
+    members: Vec<Member>,
+}
+
+impl MdArray {
+    /// A new array over `devices`, their superblocks written. RAID1
+    /// members after the first start out being rebuilt from it.
+    pub fn create(
+        minor: u16,
+        uuid: [u8; 16],
+        level: Level,
+        chunk: u64,
+        devices: Vec<(String, SharedBlockDevice)>,
+    ) -> Result<MdArray, MdError> {
+        if devices.len() < 2 || devices.len() > MAX_MEMBERS {
+            return Err(MdError::Invalid("an array needs 2 to 16 members"));
+        }
+        let mut block_size = 0;
+        let mut smallest = u64::MAX;
+        for (_, device) in &devices {
+            let device = device.read();
+            if block_size == 0 {
+                block_size = device.block_size();
+            }
+            if device.block_size() != block_size || !usable_block_size(block_size) {
+                return Err(MdError::Invalid("members differ in block size"));
+            }
+            smallest = smallest.min(device.block_count() * (block_size as u64 / SECTOR_SIZE));
+        }
+        let per_block = block_size as u64 / SECTOR_SIZE;
+        let unit = match level {
+            Level::Raid0 if chunk == 0 || chunk % per_block != 0 => {
+                return Err(MdError::Invalid("chunk is not a multiple of the block size"));
+            }
+            Level::Raid0 => chunk,
+            Level::Raid1 => per_block,
+        };
+        let member_sectors = smallest.saturating_sub(DATA_OFFSET) / unit * unit;
+        if member_sectors == 0 {
+            return Err(MdError::Invalid("members are too small"));
+        }
+
+        let members = devices.into_iter().enumerate().map(|(role, (name, device))| {
+            let (state, recovery) = match level {
+                Level::Raid1 if role > 0 => (MemberState::Rebuilding, 0),
+                _ => (MemberState::InSync, IN_SYNC),
+            };
+            Member::new(name, Some(device), state, recovery)
+        }).collect();
+        let array = MdArray { minor, uuid, level, chunk, member_sectors, block_size, events: AtomicU64::new(1), members };
+        array.write_superblocks();
+        Ok(array)
+    }
+
+    /// Put an array back together from members found with their
+    /// superblocks, all of one array. Members that missed changes are
+    /// rebuilt, if the array is a mirror.
+    pub fn assemble(minor: u16, found: Vec<Found>) -> Result<MdArray, MdError> {
+        let first = found.first().map(|(_, _, superblock)| superblock.clone()).ok_or(MdError::Invalid("no members"))?;
+        if found.iter().any(|(_, _, superblock)| superblock.uuid != first.uuid) {
+            return Err(MdError::Invalid("members belong to different arrays"));
+        }
+        let raid_disks = first.raid_disks as usize;
+        if !(2..=MAX_MEMBERS).contains(&raid_disks) {
+            return Err(MdError::Invalid("bad superblock"));
+        }
+        let events = found.iter().map(|(_, _, superblock)| superblock.events).max().unwrap_or(0);
+        let block_size = found[0].1.read().block_size();
+
+        let mut members: Vec<Member> = (0..raid_disks)
+            .map(|_| Member::new(String::new(), None, MemberState::Missing, 0))
+            .collect();
+        for (name, device, superblock) in found {
+            let slot = members.get_mut(superblock.role as usize).ok_or(MdError::Invalid("bad superblock"))?;
+            if slot.device.is_some() {
+                return Err(MdError::Invalid("two members claim one slot"));
+            }
+            {
+                let device = device.read();
+                if device.block_size() != block_size {
+                    return Err(MdError::Invalid("members differ in block size"));
+                }
+                if device.block_count() * (block_size as u64 / SECTOR_SIZE) < DATA_OFFSET + first.member_sectors {
+                    return Err(MdError::Invalid("member is smaller than the array needs"));
+                }
+            }
+            let (state, recovery) = if superblock.events < events {
+                (MemberState::Rebuilding, 0)
+            } else if superblock.recovery != IN_SYNC {
+                (MemberState::Rebuilding, superblock.recovery)
+            } else {
+                (MemberState::InSync, IN_SYNC)
+            };
+            *slot = Member::new(name, Some(device), state, recovery);
+        }
+        let in_sync = members.iter().filter(|member| member.state() == MemberState::InSync).count();
+        match first.level {
+            Level::Raid0 if in_sync < raid_disks => return Err(MdError::Invalid("a striped array needs every member current")),
+            Level::Raid1 if in_sync == 0 => return Err(MdError::Invalid("no member is in sync")),
+            _ => {}
+        }
+
+        let array = MdArray {
+            minor,
+            uuid: first.uuid,
+            level: first.level,
+            chunk: first.chunk,
+            member_sectors: first.member_sectors,
+            block_size,
+            events: AtomicU64::new(events),
+            members,
+        };
+        array.write_superblocks();
+        Ok(array)
+    }
+
+    pub fn name(&self) -> String {
+        format!("md{}", self.minor)
+    }
+
+    pub fn minor(&self) -> u16 {
+        self.minor
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Size in 512-byte sectors
+    pub fn sectors(&self) -> u64 {
+        match self.level {
+            Level::Raid0 => self.member_sectors * self.members.len() as u64,
+            Level::Raid1 => self.member_sectors,
+        }
+    }
+
+    /// The members' names and states, by slot
+    pub fn members(&self) -> Vec<(String, MemberState)> {
+        self.members.iter().map(|member| (member.name.clone(), member.state())).collect()
+    }
+
+    /// Whether `name` is a member
+    pub fn has_member(&self, name: &str) -> bool {
+        self.members.iter().any(|member| member.device.is_some() && member.name == name)
+    }
+
+    /// Percent rebuilt of the member furthest behind, None if none is
+    pub fn resync_progress(&self) -> Option<u64> {
+        self.members.iter()
+            .filter(|member| member.state() == MemberState::Rebuilding)
+            .map(|member| member.recovery * 100 / self.member_sectors)
+            .min()
+    }
+
+    /// "clean", "degraded", "resyncing" or "failed"
+    pub fn state(&self) -> &'static str {
+        let states = self.members();
+        let count = |wanted| states.iter().filter(|(_, state)| *state == wanted).count();
+        match self.level {
+            Level::Raid0 if count(MemberState::InSync) < states.len() => "failed",
+            Level::Raid1 if count(MemberState::InSync) == 0 => "failed",
+            _ if count(MemberState::Rebuilding) > 0 => "resyncing",
+            _ if count(MemberState::InSync) < states.len() => "degraded",
+            _ => "clean",
+        }
+    }
+
+    /// The array and its members, as `mdadm detail` prints them
+    pub fn detail(&self) -> String {
+        let mut text = String::new();
+        writeln!(text, "/dev/{}: {}, {} members, {} sectors", self.name(), self.level, self.members.len(), self.sectors()).ok();
+        writeln!(text, "  UUID: {}", crate::qsf::crypto::to_hex(&self.uuid)).ok();
+        if self.level == Level::Raid0 {
+            writeln!(text, "  Chunk: {}K", self.chunk * SECTOR_SIZE / 1024).ok();
+        }
+        write!(text, "  State: {}", self.state()).ok();
+        match self.resync_progress() {
+            Some(percent) => writeln!(text, ", {}% rebuilt", percent).ok(),
+            None => writeln!(text).ok(),
+        };
+        writeln!(text, "  Events: {}", self.events.load(Ordering::Relaxed)).ok();
+        for (slot, member) in self.members.iter().enumerate() {
+            let name = if member.device.is_some() { member.name.as_str() } else { "-" };
+            writeln!(text, "  {:>4}  {:<12} {}", slot, name, member.state()).ok();
+        }
+        text
+    }
+
+    fn superblock(&self, role: usize) -> Superblock {
+        Superblock {
+            uuid: self.uuid,
+            minor: self.minor,
+            level: self.level,
+            raid_disks: self.members.len() as u32,
+            chunk: self.chunk,
+            role: role as u32,
+            member_sectors: self.member_sectors,
+            events: self.events.load(Ordering::Acquire),
+            recovery: self.members[role].recovery,
+        }
+    }
+
+    /// Record a change of state on every working member. One that cannot
+    /// be written is faulty.
+    fn write_superblocks(&self) {
+        self.events.fetch_add(1, Ordering::AcqRel);
+        for (role, member) in self.members.iter().enumerate() {
+            if let (Some(device), MemberState::InSync | MemberState::Rebuilding) = (&member.device, member.state()) {
+                if write_superblock(device, &self.superblock(role)).is_err() {
+                    member.set_state(MemberState::Faulty);
+                }
+            }
+        }
+    }
+
+    /// Mark the member in `slot` faulty; true if it was not already
+    pub fn fail(&self, slot: usize) -> bool {
+        let member = match self.members.get(slot) {
+            Some(member) if matches!(member.state(), MemberState::InSync | MemberState::Rebuilding) => member,
+            _ => return false,
+        };
+        member.set_state(MemberState::Faulty);
+        crate::println!("[MD] {}: {} failed, array {}", self.name(), member.name, self.state());
+        self.write_superblocks();
+        true
+    }
+
+    /// The slot `name` fills
+    pub fn slot_of(&self, name: &str) -> Option<usize> {
+        self.members.iter().position(|member| member.device.is_some() && member.name == name)
+    }
+
+    /// Put `device` in the first faulty or missing slot of a mirror, to be
+    /// rebuilt; returns the slot
+    pub fn add(&mut self, name: String, device: SharedBlockDevice) -> Result<usize, MdError> {
+        if self.level != Level::Raid1 {
+            return Err(MdError::Invalid("only mirrors take new members"));
+        }
+        {
+            let device = device.read();
+            if device.block_size() != self.block_size {
+                return Err(MdError::Invalid("members differ in block size"));
+            }
+            if device.block_count() * self.per_block() < DATA_OFFSET + self.member_sectors {
+                return Err(MdError::Invalid("device is smaller than the array needs"));
+            }
+        }
+        let slot = self.members.iter()
+            .position(|member| matches!(member.state(), MemberState::Faulty | MemberState::Missing))
+            .ok_or(MdError::Invalid("no faulty or missing member to replace"))?;
+        self.members[slot] = Member::new(name, Some(device), MemberState::Rebuilding, 0);
+        self.write_superblocks();
+        Ok(slot)
+    }
+
+    /// Copy the next step of every member being rebuilt from one in sync;
+    /// false once there is nothing left to do
+    pub fn resync_step(&mut self) -> bool {
+        let source = match self.members.iter().position(|member| member.state() == MemberState::InSync) {
+            Some(source) => source,
+            None => return false,
+        };
+        let per_block = self.per_block();
+        let mut finished = false;
+        let mut pending = false;
+        for target in 0..self.members.len() {
+            if self.members[target].state() != MemberState::Rebuilding {
+                continue;
+            }
+            let start = self.members[target].recovery;
+            let count = RESYNC_STEP.min(self.member_sectors - start);
+            let mut buf = vec![0u8; (count * SECTOR_SIZE) as usize];
+            if self.members[source].read(start, per_block, &mut buf).is_err() {
+                self.fail(source);
+                return self.resync_step();
+            }
+            if self.members[target].write(start, per_block, &buf).is_err() {
+                self.fail(target);
+                continue;
+            }
+            let member = &mut self.members[target];
+            member.recovery = start + count;
+            if member.recovery == self.member_sectors {
+                member.recovery = IN_SYNC;
+                member.set_state(MemberState::InSync);
+                crate::println!("[MD] md{}: {} is in sync", self.minor, member.name);
+                finished = true;
+            } else {
+                pending = true;
+                if let Some(device) = &self.members[target].device {
+                    write_superblock(device, &self.superblock(target)).ok();
+                }
+            }
+        }
+        if finished {
+            self.write_superblocks();
+        }
+        pending
+    }
+
+    fn per_block(&self) -> u64 {
+        self.block_size as u64 / SECTOR_SIZE
+    }
+
+    /// Where sector `sector` of a striped array lies: the member, the
+    /// sector on it, and how many sectors from there stay on it
+    fn stripe(&self, sector: u64) -> (usize, u64, u64) {
+        let members = self.members.len() as u64;
+        let (chunk, within) = (sector / self.chunk, sector % self.chunk);
+        ((chunk % members) as usize, chunk / members * self.chunk + within, self.chunk - within)
+    }
+
+    /// The first sector and sector count of a request, checked against
+    /// the array's size
+    fn extent(&self, block_num: u64, len: usize) -> Result<(u64, u64), &'static str> {
+        if len % self.block_size as usize != 0 {
+            return Err("not a whole number of blocks");
+        }
+        let start = block_num.checked_mul(self.per_block()).ok_or("block out of range")?;
+        let count = len as u64 / SECTOR_SIZE;
+        if start.checked_add(count).map_or(true, |end| end > self.sectors()) {
+            return Err("block out of range");
+        }
+        Ok((start, count))
+    }
+
+    /// Hand a striped request on to `io` one chunk at a time, with the
+    /// member, the sector on it and the part of the buffer it holds
+    fn split(
+        &self,
+        start: u64,
+        count: u64,
+        mut io: impl FnMut(&Member, u64, core::ops::Range<usize>) -> Result<(), &'static str>,
+    ) -> Result<(), &'static str> {
+        let mut done = 0;
+        while done < count {
+            let (slot, sector, run) = self.stripe(start + done);
+            let run = run.min(count - done);
+            let member = &self.members[slot];
+            if member.state() != MemberState::InSync {
+                return Err("striped array has failed");
+            }
+            io(member, sector, (done * SECTOR_SIZE) as usize..((done + run) * SECTOR_SIZE) as usize)?;
+            done += run;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for MdArray {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        let (start, count) = self.extent(block_num, buf.len())?;
+        let per_block = self.per_block();
+        match self.level {
+            Level::Raid0 => self.split(start, count, |member, sector, range| member.read(sector, per_block, &mut buf[range])),
+            Level::Raid1 => {
+                // Degraded reads: a member that fails is marked faulty and
+                // the next current one tried
+                for (slot, member) in self.members.iter().enumerate() {
+                    if !member.current(start + count) {
+                        continue;
+                    }
+                    match member.read(start, per_block, buf) {
+                        Ok(()) => return Ok(()),
+                        Err(_) => {
+                            self.fail(slot);
+                        }
+                    }
+                }
+                Err("no working mirror")
+            }
+        }
+    }
+
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        let (start, count) = self.extent(block_num, buf.len())?;
+        let per_block = self.per_block();
+        match self.level {
+            Level::Raid0 => self.split(start, count, |member, sector, range| member.write(sector, per_block, &buf[range])),
+            Level::Raid1 => {
+                // Members being rebuilt take every write too, so the part
+                // already copied stays current
+                let mut written = false;
+                for (slot, member) in self.members.iter().enumerate() {
+                    let state = member.state();
+                    if !matches!(state, MemberState::InSync | MemberState::Rebuilding) {
+                        continue;
+                    }
+                    match member.write(start, per_block, buf) {
+                        Ok(()) => written |= state == MemberState::InSync,
+                        Err(_) => {
+                            self.fail(slot);
+                        }
+                    }
+                }
+                if written { Ok(()) } else { Err("no working mirror") }
+            }
+        }
+    }
+
+    fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.sectors() / self.per_block()
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        for member in &self.members {
+            if let (Some(device), MemberState::InSync | MemberState::Rebuilding) = (&member.device, member.state()) {
+                device.write().flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub type SharedArray = Arc<RwLock<MdArray>>;
+
+/// A member found for assembly: its name, device and superblock
+pub type Found = (String, SharedBlockDevice, Superblock);
+
+static ARRAYS: Mutex<Vec<SharedArray>> = Mutex::new(Vec::new());
+
+/// The array registered as `name` ("md0")
+pub fn array(name: &str) -> Option<SharedArray> {
+    let minor = parse_name(name.strip_prefix("/dev/").unwrap_or(name))?;
+    ARRAYS.lock().iter().find(|array| array.read().minor == minor).cloned()
+}
+
+/// Every array, by minor number
+pub fn list() -> Vec<SharedArray> {
+    let mut arrays = ARRAYS.lock().clone();
+    arrays.sort_by_key(|array| array.read().minor);
+    arrays
+}
+
+fn parse_name(name: &str) -> Option<u16> {
+    let minor = name.strip_prefix("md")?;
+    if minor.starts_with('+') || (minor.len() > 1 && minor.starts_with('0')) {
+        return None;
+    }
+    minor.parse().ok()
+}
+
+fn normalize(name: &str) -> &str {
+    name.strip_prefix("/dev/").unwrap_or(name)
+}
+
+/// Open `names` as members: block devices that are in no array yet
+fn open_members(names: &[&str]) -> Result<Vec<(String, SharedBlockDevice)>, MdError> {
+    let mut members: Vec<(String, SharedBlockDevice)> = Vec::new();
+    for &name in names {
+        let name = normalize(name);
+        if members.iter().any(|(other, _)| other == name) || list().iter().any(|array| array.read().has_member(name)) {
+            return Err(MdError::InUse(name.to_string()));
+        }
+        let device = crate::hal::block::open(name).ok_or_else(|| MdError::NoDevice(name.to_string()))?;
+        members.push((name.to_string(), device));
+    }
+    Ok(members)
+}
+
+fn register(array: MdArray) -> Result<u16, MdError> {
+    let minor = array.minor;
+    let bytes = array.sectors() * SECTOR_SIZE;
+    {
+        let mut all = ARRAYS.lock();
+        if all.iter().any(|other| other.read().minor == minor) {
+            return Err(MdError::Exists);
+        }
+        all.push(Arc::new(RwLock::new(array)));
+    }
+    device::register(&format!("md{}", minor), DeviceKind::Block, DeviceId::new(MD_MAJOR, minor), "md", Some(bytes));
+    crate::kernel::idle::register(resync);
+    Ok(minor)
+}
+
+/// Create array `name` at `level` from `devices`, `chunk` sectors to a
+/// stripe; returns its minor number
+pub fn create(name: &str, level: Level, chunk: u64, devices: &[&str]) -> Result<u16, MdError> {
+    let minor = parse_name(normalize(name)).ok_or(MdError::InvalidName)?;
+    if array(name).is_some() {
+        return Err(MdError::Exists);
+    }
+    let members = open_members(devices)?;
+    // Unique enough to tell arrays apart: the members and the time
+    let mut seed = format!("{}:{}:{}", minor, crate::kernel::time::now_ms(), crate::hal::drivers::pit::get_ticks());
+    for (name, _) in &members {
+        seed.push_str(name);
+    }
+    let uuid = crate::qsf::crypto::md5(seed.as_bytes());
+    register(MdArray::create(minor, uuid, level, chunk, members)?)
+}
+
+/// Assemble array `name` from `devices`
+pub fn assemble(name: &str, devices: &[&str]) -> Result<u16, MdError> {
+    let minor = parse_name(normalize(name)).ok_or(MdError::InvalidName)?;
+    if array(name).is_some() {
+        return Err(MdError::Exists);
+    }
+    let mut found = Vec::new();
+    for (name, device) in open_members(devices)? {
+        let superblock = read_superblock(&device).map_err(MdError::Io)?.ok_or_else(|| MdError::NoSuperblock(name.clone()))?;
+        found.push((name, device, superblock));
+    }
+    register(MdArray::assemble(minor, found)?)
+}
+
+/// Assemble every array whose members are found on the block devices not
+/// yet in one, each under its last minor number if that is free. Returns
+/// the arrays found, by UUID, with how assembling each went.
+pub fn assemble_scan() -> Vec<([u8; 16], Result<u16, MdError>)> {
+    let mut groups: Vec<([u8; 16], Vec<Found>)> = Vec::new();
+    let candidates = device::list().into_iter()
+        .filter(|info| info.kind == DeviceKind::Block && parse_name(&info.name).is_none());
+    for info in candidates {
+        if list().iter().any(|array| array.read().has_member(&info.name)) {
+            continue;
+        }
+        let device = match crate::hal::block::open(&info.name) {
+            Some(device) => device,
+            None => continue,
+        };
+        let superblock = match read_superblock(&device) {
+            Ok(Some(superblock)) => superblock,
+            _ => continue,
+        };
+        let member = (info.name, device, superblock.clone());
+        match groups.iter_mut().find(|(uuid, _)| *uuid == superblock.uuid) {
+            Some((_, members)) => members.push(member),
+            None => groups.push((superblock.uuid, vec![member])),
+        }
+    }
+
+    groups.into_iter().map(|(uuid, found)| {
+        let preferred = found[0].2.minor;
+        let result = {
+            let taken = |minor| list().iter().any(|array| array.read().minor == minor);
+            let minor = if taken(preferred) { (0..).find(|&minor| !taken(minor)).unwrap_or(0) } else { preferred };
+            MdArray::assemble(minor, found).and_then(register)
+        };
+        (uuid, result)
+    }).collect()
+}
+
+/// Flush and stop array `name`, unless something still holds it. The
+/// superblocks stay, so it can be assembled again.
+pub fn stop(name: &str) -> Result<(), MdError> {
+    let minor = parse_name(normalize(name)).ok_or(MdError::InvalidName)?;
+    let array = {
+        let mut all = ARRAYS.lock();
+        let at = all.iter().position(|array| array.read().minor == minor).ok_or(MdError::NotFound)?;
+        if Arc::strong_count(&all[at]) > 1 {
+            return Err(MdError::Busy);
+        }
+        all.remove(at)
+    };
+    array.write().flush().ok();
+    device::unregister(&format!("md{}", minor));
+    Ok(())
+}
+
+/// Mark member `member` of array `name` faulty
+pub fn fail(name: &str, member: &str) -> Result<(), MdError> {
+    let array = array(name).ok_or(MdError::NotFound)?;
+    let array = array.read();
+    let slot = array.slot_of(normalize(member)).ok_or_else(|| MdError::NoDevice(member.to_string()))?;
+    if !array.fail(slot) {
+        return Err(MdError::Invalid("member has already failed"));
+    }
+    Ok(())
+}
+
+/// Add `member` to mirror `name` in place of a failed one; returns its slot
+pub fn add(name: &str, member: &str) -> Result<usize, MdError> {
+    let array = array(name).ok_or(MdError::NotFound)?;
+    let (member_name, device) = open_members(&[member])?.remove(0);
+    let slot = array.write().add(member_name, device)?;
+    crate::kernel::idle::register(resync);
+    Ok(slot)
+}
+
+/// Idle work: take every array being rebuilt a step further
+fn resync() {
+    for array in list() {
+        if let Some(mut array) = array.try_write() {
+            array.resync_step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicBool;
+    use crate::fs::ramdisk::RamDisk;
+
+    fn disk(sectors: u64) -> SharedBlockDevice {
+        Arc::new(RwLock::new(RamDisk::new(sectors, SECTOR_SIZE as u32)))
+    }
+
+    /// A disk whose reads fail once `failing` is set
+    struct Flaky {
+        disk: RamDisk,
+        failing: Arc<AtomicBool>,
+    }
+
+    impl BlockDevice for Flaky {
+        fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+            if self.failing.load(Ordering::Relaxed) {
+                return Err("medium error");
+            }
+            self.disk.read_block(block_num, buf)
+        }
+        fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+            self.disk.write_block(block_num, buf)
+        }
+        fn block_size(&self) -> u32 {
+            self.disk.block_size()
+        }
+        fn block_count(&self) -> u64 {
+            self.disk.block_count()
+        }
+    }
+
+    fn flaky(sectors: u64) -> (SharedBlockDevice, Arc<AtomicBool>) {
+        let failing = Arc::new(AtomicBool::new(false));
+        let disk = Flaky { disk: RamDisk::new(sectors, SECTOR_SIZE as u32), failing: failing.clone() };
+        (Arc::new(RwLock::new(disk)), failing)
+    }
+
+    fn named(disks: &[&SharedBlockDevice]) -> Vec<(String, SharedBlockDevice)> {
+        disks.iter().enumerate().map(|(n, disk)| (format!("d{}", n), (*disk).clone())).collect()
+    }
+
+    #[test_case]
+    fn test_superblock_round_trips() {
+        let superblock = Superblock {
+            uuid: [7; 16],
+            minor: 3,
+            level: Level::Raid1,
+            raid_disks: 2,
+            chunk: 128,
+            role: 1,
+            member_sectors: 4096,
+            events: 9,
+            recovery: 256,
+        };
+        let mut sector = superblock.encode();
+        assert_eq!(Superblock::decode(&sector), Some(superblock));
+        sector[60] ^= 1;
+        assert_eq!(Superblock::decode(&sector), None);
+    }
+
+    #[test_case]
+    fn test_raid0_stripes_chunks() {
+        let (a, b) = (disk(8 + 20), disk(8 + 16));
+        let mut array = MdArray::create(0, [1; 16], Level::Raid0, 4, named(&[&a, &b])).unwrap();
+        // The smaller member, less metadata, in whole chunks
+        assert_eq!(array.sectors(), 32);
+
+        let data: Vec<u8> = (0..8 * SECTOR_SIZE).map(|n| (n / SECTOR_SIZE) as u8).collect();
+        array.write_block(2, &data).unwrap();
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        // Array sectors 2-3 end chunk 0 on a, 4-7 are chunk 1 on b, 8-9
+        // start chunk 2 on a
+        a.read().read_block(DATA_OFFSET + 2, &mut sector).unwrap();
+        assert_eq!(sector[0], 0);
+        b.read().read_block(DATA_OFFSET, &mut sector).unwrap();
+        assert_eq!(sector[0], 2);
+        a.read().read_block(DATA_OFFSET + 4, &mut sector).unwrap();
+        assert_eq!(sector[0], 6);
+        let mut back = vec![0u8; data.len()];
+        array.read_block(2, &mut back).unwrap();
+        assert_eq!(back, data);
+
+        array.fail(1);
+        assert_eq!(array.state(), "failed");
+        assert!(array.read_block(4, &mut sector).is_err());
+    }
+
+    #[test_case]
+    fn test_raid1_degrades_and_rebuilds() {
+        let (a, failing) = flaky(8 + 600);
+        let (b, c) = (disk(8 + 600), disk(8 + 600));
+        let mut array = MdArray::create(1, [2; 16], Level::Raid1, 0, named(&[&a, &b])).unwrap();
+        assert_eq!(array.state(), "resyncing");
+        assert_eq!(array.resync_progress(), Some(0));
+        assert!(array.resync_step());
+        assert_eq!(array.resync_progress(), Some(256 * 100 / 600));
+        while array.resync_step() {}
+        assert_eq!(array.state(), "clean");
+
+        let data = [0xa5u8; SECTOR_SIZE as usize];
+        array.write_block(10, &data).unwrap();
+        // Reads go to the first member; once it fails it is marked
+        // faulty, the array degrades and the other serves the read
+        failing.store(true, Ordering::Relaxed);
+        let mut back = [0u8; SECTOR_SIZE as usize];
+        array.read_block(10, &mut back).unwrap();
+        assert_eq!(back, data);
+        assert_eq!(array.members()[0].1, MemberState::Faulty);
+        assert_eq!(array.state(), "degraded");
+
+        assert_eq!(array.add("d2".to_string(), c.clone()), Ok(0));
+        assert_eq!(array.state(), "resyncing");
+        while array.resync_step() {}
+        assert_eq!(array.state(), "clean");
+        c.read().read_block(DATA_OFFSET + 10, &mut back).unwrap();
+        assert_eq!(back, data);
+
+        // With its only current member failing, a rebuild cannot go on
+        let (d, failing) = flaky(8 + 600);
+        let mut array = MdArray::create(1, [5; 16], Level::Raid1, 0, named(&[&d, &b])).unwrap();
+        failing.store(true, Ordering::Relaxed);
+        assert!(!array.resync_step());
+        assert_eq!(array.state(), "failed");
+    }
+
+    #[test_case]
+    fn test_assembly_rebuilds_stale_members() {
+        let (a, b) = (disk(8 + 64), disk(8 + 64));
+        let mut array = MdArray::create(2, [3; 16], Level::Raid1, 0, named(&[&a, &b])).unwrap();
+        while array.resync_step() {}
+        array.fail(1);
+        array.write_block(5, &[0x11; SECTOR_SIZE as usize]).unwrap();
+        drop(array);
+
+        let found = [&b, &a].iter().map(|disk| {
+            let superblock = read_superblock(disk).unwrap().unwrap();
+            (format!("d{}", superblock.role), (*disk).clone(), superblock)
+        }).collect();
+        let mut array = MdArray::assemble(2, found).unwrap();
+        // b missed the write, so it comes back to be rebuilt
+        assert_eq!(array.members(), vec![("d0".to_string(), MemberState::InSync), ("d1".to_string(), MemberState::Rebuilding)]);
+        while array.resync_step() {}
+        let mut sector = [0u8; SECTOR_SIZE as usize];
+        b.read().read_block(DATA_OFFSET + 5, &mut sector).unwrap();
+        assert_eq!(sector, [0x11; SECTOR_SIZE as usize]);
+
+        let stranger = disk(8 + 64);
+        MdArray::create(3, [4; 16], Level::Raid0, 8, named(&[&stranger, &disk(8 + 64)])).unwrap();
+        let mixed = [&a, &stranger].iter().map(|disk| ("x".to_string(), (*disk).clone(), read_superblock(disk).unwrap().unwrap())).collect();
+        assert!(matches!(MdArray::assemble(2, mixed), Err(MdError::Invalid(_))));
+    }
+}
@@ -0,0 +1,40 @@
+// /dev/mem: physical memory
+//
+// Only mmap is offered, for reaching device registers and firmware tables
+// from a process. The range must lie in the first megabyte, where the BIOS
+// data, VGA memory and option ROMs are, or wholly outside RAM, so the
+// kernel's memory and every process's stay out of reach even of root. Who
+// may map it at all is QSF's to decide: root with CAP_SYS_RAWIO, and
+// nobody once locked down.
+
+use crate::fs::vfs::node::DeviceId;
+use crate::hal::memory::frame_allocator;
+use super::device::{self, DeviceKind};
+
+/// Major 1, /dev/mem's on Linux, is the console's here, and /dev/stdout
+/// has 1:1; this one is from the range Linux leaves for local use
+pub const MAJOR: u16 = 240;
+pub const MINOR: u16 = 0;
+/// Below this is the real-mode memory that may always be mapped
+const LOW_MEMORY: u64 = 0x10_0000;
+/// What 52-bit physical addresses reach
+const PHYS_LIMIT: u64 = 1 << 52;
+
+/// The physical address of `len` bytes at `offset`, which is the address
+fn map(offset: u64, len: u64) -> Option<u64> {
+    let end = offset.checked_add(len)?;
+    if end > PHYS_LIMIT {
+        return None;
+    }
+    if end <= LOW_MEMORY || !frame_allocator::is_ram(offset..end) {
+        Some(offset)
+    } else {
+        None
+    }
+}
+
+pub fn init() {
+    let id = DeviceId::new(MAJOR, MINOR);
+    device::register("mem", DeviceKind::Char, id, "mem", None);
+    device::register_map(id, map);
+}
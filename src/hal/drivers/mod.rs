@@ -1,11 +1,40 @@
 pub mod vga;
+pub mod cp437;
 pub mod serial;
 pub mod keyboard;
+pub mod input;
 pub mod pci;
+pub mod ata;
+pub mod smart;
+#[cfg(feature = "ahci")]
 pub mod ahci;
+#[cfg(feature = "nvme")]
+pub mod nvme;
+#[cfg(feature = "sdhci")]
+pub mod sdhci;
+#[cfg(feature = "framebuffer")]
+pub mod gfx;
+#[cfg(feature = "framebuffer")]
+pub mod framebuffer;
+#[cfg(feature = "framebuffer")]
+pub mod fbcon;
+#[cfg(feature = "framebuffer")]
+pub mod mouse;
+#[cfg(feature = "usb")]
 pub mod usb;
+#[cfg(feature = "net")]
+pub mod e1000;
+pub mod audio;
+#[cfg(feature = "sound")]
+pub mod ac97;
 pub mod tty;
 pub mod pit;
+pub mod rtc;
+pub mod device;
+pub mod dm;
+pub mod md;
+pub mod mem;
+pub mod hotplug;
 
 pub use vga::*;
 pub use serial::write_string;
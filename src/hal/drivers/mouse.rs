@@ -0,0 +1,189 @@
+// PS/2 mouse
+//
+// The mouse sits on the second port of the keyboard controller and sends
+// three-byte movement packets on IRQ 12. The handler assembles packets
+// and queues them without taking a lock anyone else holds; read_event()
+// hands them out. Only the compositor listens for now.
+
+use crossbeam_queue::ArrayQueue;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+use crate::hal::cpu::interrupts;
+
+const DATA: u16 = 0x60;
+const STATUS: u16 = 0x64;
+const COMMAND: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 0x01;
+const STATUS_INPUT_FULL: u8 = 0x02;
+const STATUS_AUX_DATA: u8 = 0x20;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_ENABLE_AUX: u8 = 0xA8;
+const CMD_WRITE_AUX: u8 = 0xD4;
+const CONFIG_AUX_IRQ: u8 = 0x02;
+const CONFIG_AUX_CLOCK_OFF: u8 = 0x20;
+
+const MOUSE_DEFAULTS: u8 = 0xF6;
+const MOUSE_ENABLE_REPORTING: u8 = 0xF4;
+const MOUSE_ACK: u8 = 0xFA;
+
+const IRQ: u8 = 12;
+const CASCADE_IRQ: u8 = 2;
+/// Status polls before giving up on the controller
+const TIMEOUT: usize = 100_000;
+const QUEUE_SIZE: usize = 128;
+
+pub const BUTTON_LEFT: u8 = 0x01;
+pub const BUTTON_RIGHT: u8 = 0x02;
+pub const BUTTON_MIDDLE: u8 = 0x04;
+
+/// One movement report
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Rightwards
+    pub dx: i16,
+    /// Downwards, as on the screen
+    pub dy: i16,
+    pub buttons: u8,
+}
+
+/// Collects the bytes of one packet
+struct Packet {
+    bytes: [u8; 3],
+    len: usize,
+}
+
+impl Packet {
+    const fn new() -> Packet {
+        Packet { bytes: [0; 3], len: 0 }
+    }
+
+    /// Add a byte, returning the event once a packet is complete
+    fn push(&mut self, byte: u8) -> Option<MouseEvent> {
+        // Bit 3 is always set in the first byte; wait for it to resync
+        if self.len == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < 3 {
+            return None;
+        }
+        self.len = 0;
+        decode(self.bytes)
+    }
+}
+
+/// The movement in a packet, None if it overflowed
+fn decode(bytes: [u8; 3]) -> Option<MouseEvent> {
+    let [flags, x, y] = bytes;
+    if flags & 0xC0 != 0 {
+        return None;
+    }
+    // Nine-bit two's complement, the sign bits in the first byte
+    let dx = x as i16 - (((flags as i16) << 4) & 0x100);
+    let dy = y as i16 - (((flags as i16) << 3) & 0x100);
+    Some(MouseEvent { dx, dy: -dy, buttons: flags & 0x07 })
+}
+
+lazy_static! {
+    static ref EVENTS: ArrayQueue<MouseEvent> = ArrayQueue::new(QUEUE_SIZE);
+}
+
+/// Only the interrupt handler takes this
+static PACKET: Mutex<Packet> = Mutex::new(Packet::new());
+
+fn wait(mask: u8, set: bool) -> bool {
+    let mut status = Port::<u8>::new(STATUS);
+    (0..TIMEOUT).any(|_| (unsafe { status.read() } & mask != 0) == set)
+}
+
+fn write_command(command: u8) -> bool {
+    wait(STATUS_INPUT_FULL, false) && {
+        unsafe { Port::new(COMMAND).write(command) };
+        true
+    }
+}
+
+fn write_data(byte: u8) -> bool {
+    wait(STATUS_INPUT_FULL, false) && {
+        unsafe { Port::new(DATA).write(byte) };
+        true
+    }
+}
+
+fn read_data() -> Option<u8> {
+    if wait(STATUS_OUTPUT_FULL, true) {
+        Some(unsafe { Port::new(DATA).read() })
+    } else {
+        None
+    }
+}
+
+fn write_mouse(byte: u8) -> bool {
+    write_command(CMD_WRITE_AUX) && write_data(byte) && read_data() == Some(MOUSE_ACK)
+}
+
+/// Called on IRQ 12
+pub fn handle_interrupt() {
+    let status: u8 = unsafe { Port::new(STATUS).read() };
+    if status & (STATUS_OUTPUT_FULL | STATUS_AUX_DATA) != STATUS_OUTPUT_FULL | STATUS_AUX_DATA {
+        return;
+    }
+    let byte: u8 = unsafe { Port::new(DATA).read() };
+    if let Some(event) = PACKET.lock().push(byte) {
+        // A full queue means nobody is reading; drop the newest
+        EVENTS.push(event).ok();
+    }
+}
+
+pub fn read_event() -> Option<MouseEvent> {
+    EVENTS.pop()
+}
+
+/// Drop movement queued while nobody was looking
+pub fn clear() {
+    while EVENTS.pop().is_some() {}
+}
+
+pub fn init() {
+    lazy_static::initialize(&EVENTS);
+    let ok = interrupts::without_interrupts(|| {
+        if !write_command(CMD_ENABLE_AUX) || !write_mouse(MOUSE_DEFAULTS) || !write_mouse(MOUSE_ENABLE_REPORTING) {
+            return false;
+        }
+        let config = match write_command(CMD_READ_CONFIG).then(read_data).flatten() {
+            Some(config) => config,
+            None => return false,
+        };
+        write_command(CMD_WRITE_CONFIG) && write_data((config | CONFIG_AUX_IRQ) & !CONFIG_AUX_CLOCK_OFF)
+    });
+    if !ok {
+        crate::println!("  [MOUSE] No PS/2 mouse");
+        return;
+    }
+    interrupts::set_irq_mask(CASCADE_IRQ, false);
+    interrupts::set_irq_mask(IRQ, false);
+    crate::println!("  [MOUSE] PS/2 mouse on IRQ {}", IRQ);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_packets() {
+        let mut packet = Packet::new();
+        // A stray byte without bit 3 is skipped
+        assert_eq!(packet.push(0x00), None);
+        assert_eq!(packet.push(0x09), None);
+        assert_eq!(packet.push(5), None);
+        assert_eq!(packet.push(3), Some(MouseEvent { dx: 5, dy: -3, buttons: BUTTON_LEFT }));
+        // Left and down: both sign bits
+        assert_eq!(decode([0x38, 0xFE, 0xFC]), Some(MouseEvent { dx: -2, dy: 4, buttons: 0 }));
+        assert_eq!(decode([0x48, 1, 1]), None);
+    }
+}
@@ -0,0 +1,638 @@
+// NVMe disks
+//
+// Controllers on PCI class 01:08:02, each with the admin queue pair and one
+// I/O queue pair of a page each, in page-aligned heap memory. Data moves
+// through a two-page bounce buffer described by PRP1 and PRP2, so no PRP
+// lists are needed and no command is larger than two pages. Every active
+// namespace becomes a BlockDevice named nvme<C>n<N> behind a request
+// queue, from namespace().
+//
+// One command is in flight per controller; the waiter finds its
+// completion by the phase tag. With MSI-X set up and interrupts on, it
+// sleeps in hlt until the controller's vector fires; otherwise, as on the
+// panic path, it polls. The interrupt handler only acknowledges, so the
+// queues are only ever touched under the controller lock.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+use spin::{Mutex, RwLock};
+use x86_64::instructions::interrupts;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::fs::ext4::ext4::BlockDevice;
+use crate::fs::iosched::RequestQueue;
+use crate::hal::cpu::{interrupts::NVME_VECTOR, lapic};
+use crate::hal::memory::paging;
+use super::pci::{self, PciDevice};
+use super::pit;
+
+const PAGE_SIZE: usize = 4096;
+
+const REG_CAP: u64 = 0x00;
+const REG_VS: u64 = 0x08;
+const REG_INTMS: u64 = 0x0C;
+const REG_CC: u64 = 0x14;
+const REG_CSTS: u64 = 0x1C;
+const REG_AQA: u64 = 0x24;
+const REG_ASQ: u64 = 0x28;
+const REG_ACQ: u64 = 0x30;
+const DOORBELLS: u64 = 0x1000;
+
+const CC_ENABLE: u32 = 1 << 0;
+/// 64-byte submission and 16-byte completion entries, as powers of two
+const CC_ENTRY_SIZES: u32 = 6 << 16 | 4 << 20;
+const CSTS_READY: u32 = 1 << 0;
+const CSTS_FATAL: u32 = 1 << 1;
+/// CAP.CSS bit for the NVM command set
+const CAP_CSS_NVM: u64 = 1 << 37;
+
+const ADMIN_QUEUE_ENTRIES: u16 = 16;
+const IO_QUEUE_ENTRIES: u16 = 64;
+const IO_QUEUE_ID: u16 = 1;
+
+const ADMIN_CREATE_SQ: u8 = 0x01;
+const ADMIN_CREATE_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+const IO_FLUSH: u8 = 0x00;
+const IO_WRITE: u8 = 0x01;
+const IO_READ: u8 = 0x02;
+
+const CNS_NAMESPACE: u32 = 0;
+const CNS_CONTROLLER: u32 = 1;
+const CNS_ACTIVE_NAMESPACES: u32 = 2;
+
+/// Namespaces looked at per controller
+const MAX_NAMESPACES: usize = 16;
+
+/// Polls of the completion queue with interrupts off before giving up
+const POLL_LIMIT: usize = 10_000_000;
+
+const PCI_CAP_MSIX: u8 = 0x11;
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvmeError {
+    /// The controller did not get ready or did not complete a command
+    Timeout,
+    /// The controller reported a fatal status
+    Fatal,
+    /// A command completed with this status code type << 8 | status code
+    Status(u16),
+    /// The BAR or the queue memory could not be mapped
+    NoMemory,
+    OutOfRange,
+}
+
+impl NvmeError {
+    pub fn message(self) -> &'static str {
+        match self {
+            NvmeError::Timeout => "controller timed out",
+            NvmeError::Fatal => "controller fatal status",
+            NvmeError::Status(_) => "command failed",
+            NvmeError::NoMemory => "cannot map controller memory",
+            NvmeError::OutOfRange => "block out of range",
+        }
+    }
+}
+
+#[repr(C, align(4096))]
+struct Page([u8; PAGE_SIZE]);
+
+/// A zeroed heap page the controller can reach by its physical address
+struct DmaPage {
+    page: Box<Page>,
+    phys: u64,
+}
+
+impl DmaPage {
+    fn new() -> Result<DmaPage, NvmeError> {
+        let page = Box::new(Page([0; PAGE_SIZE]));
+        let phys = paging::translate_addr(VirtAddr::from_ptr(&*page)).ok_or(NvmeError::NoMemory)?;
+        Ok(DmaPage { page, phys: phys.as_u64() })
+    }
+}
+
+/// A submission queue entry; only the controller reads it
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Command {
+    opcode: u8,
+    flags: u8,
+    cid: u16,
+    nsid: u32,
+    reserved: u64,
+    metadata: u64,
+    prp1: u64,
+    prp2: u64,
+    cdw: [u32; 6],
+}
+
+/// A completion queue entry
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Completion {
+    result: u32,
+    reserved: u32,
+    sq_head: u16,
+    sq_id: u16,
+    cid: u16,
+    /// Phase tag in bit 0, status field above it
+    status: u16,
+}
+
+struct QueuePair {
+    id: u16,
+    entries: u16,
+    sq: DmaPage,
+    cq: DmaPage,
+    sq_tail: u16,
+    cq_head: u16,
+    /// Phase tag of the entries not yet consumed; flips on every wrap
+    phase: bool,
+}
+
+impl QueuePair {
+    fn new(id: u16, entries: u16) -> Result<QueuePair, NvmeError> {
+        Ok(QueuePair { id, entries, sq: DmaPage::new()?, cq: DmaPage::new()?, sq_tail: 0, cq_head: 0, phase: true })
+    }
+
+    fn push(&mut self, command: Command) {
+        let slot = (self.sq.page.0.as_mut_ptr() as *mut Command).wrapping_add(self.sq_tail as usize);
+        unsafe { core::ptr::write_volatile(slot, command) };
+        self.sq_tail = (self.sq_tail + 1) % self.entries;
+    }
+
+    /// The next completion, if the controller has posted it
+    fn pop(&mut self) -> Option<Completion> {
+        let slot = (self.cq.page.0.as_ptr() as *const Completion).wrapping_add(self.cq_head as usize);
+        let entry = unsafe { core::ptr::read_volatile(slot) };
+        if (entry.status & 1 == 1) != self.phase {
+            return None;
+        }
+        self.cq_head += 1;
+        if self.cq_head == self.entries {
+            self.cq_head = 0;
+            self.phase = !self.phase;
+        }
+        Some(entry)
+    }
+}
+
+/// IDENTIFY CONTROLLER, the parts of it used here
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControllerIdentity {
+    pub serial: String,
+    pub model: String,
+    pub firmware: String,
+    /// Largest transfer in pages of the minimum page size, 0 for no limit
+    pub mdts: u8,
+    pub namespaces: u32,
+}
+
+/// IDENTIFY NAMESPACE, the parts of it used here
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NamespaceIdentity {
+    pub blocks: u64,
+    pub block_size: u32,
+    /// Metadata bytes per block in the LBA format in use
+    pub metadata: u16,
+}
+
+fn ascii(bytes: &[u8]) -> String {
+    String::from(String::from_utf8_lossy(bytes).trim())
+}
+
+fn le32(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+fn le64(data: &[u8], at: usize) -> u64 {
+    le32(data, at) as u64 | (le32(data, at + 4) as u64) << 32
+}
+
+impl ControllerIdentity {
+    pub fn parse(data: &[u8]) -> ControllerIdentity {
+        ControllerIdentity {
+            serial: ascii(&data[4..24]),
+            model: ascii(&data[24..64]),
+            firmware: ascii(&data[64..72]),
+            mdts: data[77],
+            namespaces: le32(data, 516),
+        }
+    }
+}
+
+impl NamespaceIdentity {
+    /// None for an inactive namespace or a block size this driver cannot
+    /// bounce
+    pub fn parse(data: &[u8]) -> Option<NamespaceIdentity> {
+        let blocks = le64(data, 0);
+        let format = le32(data, 128 + 4 * (data[26] & 0x0F) as usize);
+        let shift = (format >> 16) as u8;
+        if blocks == 0 || !(9..=12).contains(&shift) {
+            return None;
+        }
+        Some(NamespaceIdentity { blocks, block_size: 1 << shift, metadata: format as u16 })
+    }
+}
+
+struct Controller {
+    /// Virtual address of BAR0
+    regs: u64,
+    /// Bytes between doorbells
+    stride: u64,
+    timeout_ms: u64,
+    admin: QueuePair,
+    io: Option<QueuePair>,
+    bounce: [DmaPage; 2],
+    /// Largest transfer in bytes
+    max_transfer: usize,
+    msix: bool,
+    next_cid: u16,
+}
+
+impl Controller {
+    fn read32(&self, reg: u64) -> u32 {
+        unsafe { core::ptr::read_volatile((self.regs + reg) as *const u32) }
+    }
+
+    fn write32(&self, reg: u64, value: u32) {
+        unsafe { core::ptr::write_volatile((self.regs + reg) as *mut u32, value) }
+    }
+
+    fn read64(&self, reg: u64) -> u64 {
+        self.read32(reg) as u64 | (self.read32(reg + 4) as u64) << 32
+    }
+
+    fn write64(&self, reg: u64, value: u64) {
+        self.write32(reg, value as u32);
+        self.write32(reg + 4, (value >> 32) as u32);
+    }
+
+    fn doorbell(&self, queue: u16, completion: bool, value: u16) {
+        self.write32(DOORBELLS + (2 * queue as u64 + completion as u64) * self.stride, value as u32);
+    }
+
+    /// Wait for CSTS.RDY to become `ready`
+    fn wait_ready(&self, ready: bool) -> Result<(), NvmeError> {
+        let timer = pit::Timer::new(self.timeout_ms);
+        let mut polls = 0;
+        loop {
+            let status = self.read32(REG_CSTS);
+            if status & CSTS_FATAL != 0 {
+                return Err(NvmeError::Fatal);
+            }
+            if (status & CSTS_READY != 0) == ready {
+                return Ok(());
+            }
+            polls += 1;
+            if timer.is_expired() || polls >= POLL_LIMIT {
+                return Err(NvmeError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Reset the controller and bring it up with the admin queue
+    fn enable(&mut self) -> Result<(), NvmeError> {
+        self.write32(REG_CC, 0);
+        self.wait_ready(false)?;
+        let entries = ADMIN_QUEUE_ENTRIES as u32 - 1;
+        self.write32(REG_AQA, entries << 16 | entries);
+        self.write64(REG_ASQ, self.admin.sq.phys);
+        self.write64(REG_ACQ, self.admin.cq.phys);
+        self.write32(REG_CC, CC_ENTRY_SIZES | CC_ENABLE);
+        self.wait_ready(true)
+    }
+
+    fn queue(&mut self, io: bool) -> &mut QueuePair {
+        if io && self.io.is_some() {
+            self.io.as_mut().unwrap()
+        } else {
+            &mut self.admin
+        }
+    }
+
+    /// Consume completions up to the one for `cid`
+    fn reap(&mut self, io: bool, cid: u16) -> Option<Completion> {
+        loop {
+            let queue = self.queue(io);
+            let entry = queue.pop()?;
+            let (id, head) = (queue.id, queue.cq_head);
+            self.doorbell(id, true, head);
+            if entry.cid == cid {
+                return Some(entry);
+            }
+        }
+    }
+
+    /// Submit `command` and wait for it, returning the result dword
+    fn execute(&mut self, io: bool, mut command: Command) -> Result<u32, NvmeError> {
+        command.cid = self.next_cid;
+        self.next_cid = self.next_cid.wrapping_add(1);
+        let queue = self.queue(io);
+        queue.push(command);
+        let (id, tail) = (queue.id, queue.sq_tail);
+        fence(Ordering::SeqCst);
+        self.doorbell(id, false, tail);
+
+        let timer = pit::Timer::new(self.timeout_ms);
+        let mut polls = 0;
+        loop {
+            // Checking and sleeping with interrupts off, until the hlt,
+            // keeps the completion's interrupt from slipping in between
+            let sleep = self.msix && interrupts::are_enabled();
+            if sleep {
+                interrupts::disable();
+            }
+            match self.reap(io, command.cid) {
+                Some(entry) => {
+                    if sleep {
+                        interrupts::enable();
+                    }
+                    return match entry.status >> 1 {
+                        0 => Ok(entry.result),
+                        status => Err(NvmeError::Status(status & 0x7FF)),
+                    };
+                }
+                None if sleep => interrupts::enable_and_hlt(),
+                None => core::hint::spin_loop(),
+            }
+            polls += 1;
+            if timer.is_expired() || polls >= POLL_LIMIT {
+                return Err(NvmeError::Timeout);
+            }
+        }
+    }
+
+    fn identify(&mut self, cns: u32, nsid: u32) -> Result<&[u8], NvmeError> {
+        let mut command = Command { opcode: ADMIN_IDENTIFY, nsid, prp1: self.bounce[0].phys, ..Command::default() };
+        command.cdw[0] = cns;
+        self.execute(false, command)?;
+        Ok(&self.bounce[0].page.0)
+    }
+
+    fn create_io_queues(&mut self, entries: u16) -> Result<(), NvmeError> {
+        let queue = QueuePair::new(IO_QUEUE_ID, entries)?;
+        let size = (entries as u32 - 1) << 16 | IO_QUEUE_ID as u32;
+
+        let mut command = Command { opcode: ADMIN_CREATE_CQ, prp1: queue.cq.phys, ..Command::default() };
+        command.cdw[0] = size;
+        // Physically contiguous, and interrupts on vector 0 if there is MSI-X
+        command.cdw[1] = 1 | (self.msix as u32) << 1;
+        self.execute(false, command)?;
+
+        let mut command = Command { opcode: ADMIN_CREATE_SQ, prp1: queue.sq.phys, ..Command::default() };
+        command.cdw[0] = size;
+        command.cdw[1] = (IO_QUEUE_ID as u32) << 16 | 1;
+        self.execute(false, command)?;
+
+        self.io = Some(queue);
+        Ok(())
+    }
+
+    /// Read or write `blocks` blocks at `lba` through the bounce buffer
+    fn transfer(&mut self, opcode: u8, nsid: u32, lba: u64, blocks: u32, bytes: usize) -> Result<(), NvmeError> {
+        let mut command = Command { opcode, nsid, prp1: self.bounce[0].phys, ..Command::default() };
+        if bytes > PAGE_SIZE {
+            command.prp2 = self.bounce[1].phys;
+        }
+        command.cdw[0] = lba as u32;
+        command.cdw[1] = (lba >> 32) as u32;
+        command.cdw[2] = blocks - 1;
+        self.execute(true, command).map(|_| ())
+    }
+
+    fn bounce_in(&mut self, data: &[u8]) {
+        for (page, chunk) in self.bounce.iter_mut().zip(data.chunks(PAGE_SIZE)) {
+            page.page.0[..chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    fn bounce_out(&self, buf: &mut [u8]) {
+        for (page, chunk) in self.bounce.iter().zip(buf.chunks_mut(PAGE_SIZE)) {
+            let len = chunk.len();
+            chunk.copy_from_slice(&page.page.0[..len]);
+        }
+    }
+}
+
+/// Route the controller's first MSI-X vector to NVME_VECTOR on this CPU
+fn setup_msix(device: &PciDevice) -> bool {
+    let cap = match pci::find_capability(device, PCI_CAP_MSIX) {
+        Some(cap) => cap,
+        None => return false,
+    };
+    if !lapic::enable() {
+        return false;
+    }
+    let table = pci::read_config_dword(device.address, cap + 4);
+    let bar = pci::memory_bar(device, (table & 0x7) as usize);
+    let entry = match paging::phys_to_virt(PhysAddr::new(bar + (table & !0x7) as u64)) {
+        Some(entry) if bar != 0 => entry.as_u64() as *mut u32,
+        _ => return false,
+    };
+    unsafe {
+        core::ptr::write_volatile(entry, (lapic::MSI_ADDRESS | (lapic::id() as u64) << 12) as u32);
+        core::ptr::write_volatile(entry.add(1), 0);
+        core::ptr::write_volatile(entry.add(2), NVME_VECTOR as u32);
+        core::ptr::write_volatile(entry.add(3), 0);
+    }
+    let control = pci::read_config_word(device.address, cap + 2);
+    pci::write_config_word(device.address, cap + 2, (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK);
+    pci::disable_intx(device);
+    true
+}
+
+/// An NVMe namespace as a BlockDevice
+pub struct Namespace {
+    controller: Arc<Mutex<Controller>>,
+    nsid: u32,
+    block_size: u32,
+    blocks: u64,
+}
+
+impl Namespace {
+    fn check(&self, block_num: u64, len: usize) -> Result<(), &'static str> {
+        if len % self.block_size as usize != 0 {
+            return Err("transfer is not a whole number of blocks");
+        }
+        match block_num.checked_add((len / self.block_size as usize) as u64) {
+            Some(end) if end <= self.blocks => Ok(()),
+            _ => Err(NvmeError::OutOfRange.message()),
+        }
+    }
+
+    /// The transfers `len` bytes from `block_num` split into, as
+    /// (byte offset, block, block count, bytes)
+    fn chunks(&self, block_num: u64, len: usize, max: usize) -> impl Iterator<Item = (usize, u64, u32, usize)> {
+        let bs = self.block_size as usize;
+        (0..len).step_by(max).map(move |at| {
+            let bytes = max.min(len - at);
+            (at, block_num + (at / bs) as u64, (bytes / bs) as u32, bytes)
+        })
+    }
+}
+
+impl BlockDevice for Namespace {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.check(block_num, buf.len())?;
+        let mut controller = self.controller.lock();
+        let max = controller.max_transfer;
+        for (at, lba, blocks, bytes) in self.chunks(block_num, buf.len(), max) {
+            controller.transfer(IO_READ, self.nsid, lba, blocks, bytes).map_err(NvmeError::message)?;
+            controller.bounce_out(&mut buf[at..at + bytes]);
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.check(block_num, buf.len())?;
+        let mut controller = self.controller.lock();
+        let max = controller.max_transfer;
+        for (at, lba, blocks, bytes) in self.chunks(block_num, buf.len(), max) {
+            controller.bounce_in(&buf[at..at + bytes]);
+            controller.transfer(IO_WRITE, self.nsid, lba, blocks, bytes).map_err(NvmeError::message)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), &'static str> {
+        let command = Command { opcode: IO_FLUSH, nsid: self.nsid, ..Command::default() };
+        self.controller.lock().execute(true, command).map(|_| ()).map_err(NvmeError::message)
+    }
+
+    fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+}
+
+pub type SharedNamespace = Arc<RwLock<RequestQueue<Namespace>>>;
+
+static NAMESPACES: Mutex<Vec<(String, SharedNamespace)>> = Mutex::new(Vec::new());
+
+/// The namespace registered as `name`, e.g. "nvme0n1"
+pub fn namespace(name: &str) -> Option<SharedNamespace> {
+    NAMESPACES.lock().iter().find(|(n, _)| n == name).map(|(_, ns)| ns.clone())
+}
+
+/// Bring up controller number `index` and register its namespaces
+fn probe(index: usize, device: &PciDevice) -> Result<(), NvmeError> {
+    pci::enable_memory_space(device);
+    pci::enable_bus_mastering(device);
+    let bar = pci::memory_bar(device, 0);
+    let regs = match paging::phys_to_virt(PhysAddr::new(bar)) {
+        Some(regs) if bar != 0 => regs.as_u64(),
+        _ => return Err(NvmeError::NoMemory),
+    };
+
+    let mut controller = Controller {
+        regs,
+        stride: 4,
+        timeout_ms: 500,
+        admin: QueuePair::new(0, ADMIN_QUEUE_ENTRIES)?,
+        io: None,
+        bounce: [DmaPage::new()?, DmaPage::new()?],
+        max_transfer: 2 * PAGE_SIZE,
+        msix: false,
+        next_cid: 0,
+    };
+    let cap = controller.read64(REG_CAP);
+    // The controller must do the NVM command set with 4 KiB pages
+    if cap & CAP_CSS_NVM == 0 || (cap >> 48) & 0xF != 0 {
+        return Err(NvmeError::Fatal);
+    }
+    controller.stride = 4 << ((cap >> 32) & 0xF);
+    controller.timeout_ms = ((cap >> 24) & 0xFF).max(1) * 500;
+    let max_entries = (cap & 0xFFFF) as u16 + 1;
+    controller.enable()?;
+
+    controller.msix = setup_msix(device);
+    if !controller.msix {
+        controller.write32(REG_INTMS, !0);
+    }
+
+    let identity = ControllerIdentity::parse(controller.identify(CNS_CONTROLLER, 0)?);
+    if identity.mdts != 0 {
+        controller.max_transfer = controller.max_transfer.min(PAGE_SIZE << identity.mdts);
+    }
+    let version = controller.read32(REG_VS);
+    crate::println!("  [NVMe] nvme{}: {} (serial {}, firmware {}), NVMe {}.{}, {}", index, identity.model,
+        identity.serial, identity.firmware, version >> 16, (version >> 8) & 0xFF,
+        if controller.msix { "MSI-X" } else { "polled" });
+    controller.create_io_queues(IO_QUEUE_ENTRIES.min(max_entries))?;
+
+    // The active namespace list needs NVMe 1.1; before that, try them all
+    let nsids: Vec<u32> = match controller.identify(CNS_ACTIVE_NAMESPACES, 0) {
+        Ok(list) => list.chunks_exact(4).map(|id| le32(id, 0)).take_while(|&id| id != 0).take(MAX_NAMESPACES).collect(),
+        Err(_) => (1..=identity.namespaces.min(MAX_NAMESPACES as u32)).collect(),
+    };
+    let mut found = Vec::new();
+    for nsid in nsids {
+        match controller.identify(CNS_NAMESPACE, nsid).map(NamespaceIdentity::parse) {
+            Ok(Some(ns)) if ns.metadata == 0 => found.push((nsid, ns)),
+            Ok(Some(_)) => crate::println!("  [NVMe] nvme{}n{}: skipped, has metadata", index, nsid),
+            _ => {}
+        }
+    }
+
+    let controller = Arc::new(Mutex::new(controller));
+    for (nsid, ns) in found {
+        let name = format!("nvme{}n{}", index, nsid);
+        crate::println!("  [NVMe] {}: {} blocks of {} bytes", name, ns.blocks, ns.block_size);
+        let namespace = Namespace { controller: controller.clone(), nsid, block_size: ns.block_size, blocks: ns.blocks };
+        let queue = Arc::new(RwLock::new(RequestQueue::new(&name, namespace)));
+        NAMESPACES.lock().push((name.clone(), queue.clone()));
+        crate::hal::block::register(&name, crate::hal::block::ext_id(), "nvme", queue, 0);
+    }
+    Ok(())
+}
+
+/// Probe every NVMe controller on the PCI bus
+pub fn init() {
+    for (index, device) in pci::find_nvme_controllers().iter().enumerate() {
+        if let Err(e) = probe(index, device) {
+            crate::println!("  [NVMe] nvme{}: {}", index, e.message());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_entry_sizes() {
+        assert_eq!(core::mem::size_of::<Command>(), 64);
+        assert_eq!(core::mem::size_of::<Completion>(), 16);
+    }
+
+    #[test_case]
+    fn test_identify_parsing() {
+        let mut data = [0u8; PAGE_SIZE];
+        data[24..33].copy_from_slice(b"QEMU NVMe");
+        data[24 + 9..64].fill(b' ');
+        data[77] = 7;
+        data[516] = 1;
+        let controller = ControllerIdentity::parse(&data);
+        assert_eq!((controller.model.as_str(), controller.mdts, controller.namespaces), ("QEMU NVMe", 7, 1));
+
+        let mut data = [0u8; PAGE_SIZE];
+        data[0] = 0x80;
+        data[26] = 1;
+        data[128 + 4 + 2] = 12;
+        assert_eq!(NamespaceIdentity::parse(&data), Some(NamespaceIdentity { blocks: 128, block_size: 4096, metadata: 0 }));
+        data[0] = 0;
+        assert_eq!(NamespaceIdentity::parse(&data), None);
+    }
+}
@@ -1,8 +1,11 @@
 use x86_64::instructions::port::Port;
 use spin::Mutex;
 use lazy_static::lazy_static;
+use alloc::string::ToString;
 use alloc::vec::Vec;
+use core::fmt;
 use crate::println;
+use super::hotplug::{self, Action};
 
 const PCI_CONFIG_ADDRESS: u16 = 0xCF8;
 const PCI_CONFIG_DATA: u16 = 0xCFC;
@@ -29,6 +32,13 @@ impl PciAddress {
     }
 }
 
+/// "bus:device.function", as lspci shows it
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PciDevice {
     pub address: PciAddress,
@@ -68,6 +78,14 @@ impl PciDevice {
     pub fn is_ahci(&self) -> bool {
         self.class_code == 0x01 && self.subclass == 0x06 && self.prog_if == 0x01
     }
+
+    pub fn is_sdhci(&self) -> bool {
+        self.class_code == 0x08 && self.subclass == 0x05
+    }
+
+    pub fn is_nvme(&self) -> bool {
+        self.class_code == 0x01 && self.subclass == 0x08 && self.prog_if == 0x02
+    }
     
     pub fn class_name(&self) -> &'static str {
         match self.class_code {
@@ -168,26 +186,18 @@ fn check_device(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
     })
 }
 
-pub fn scan_bus() {
-    let mut devices = PCI_DEVICES.lock();
-    devices.clear();
-    
+/// Every function that answers on the bus
+fn probe() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
     for bus in 0..=255u8 {
         for device in 0..32u8 {
             if let Some(dev) = check_device(bus, device, 0) {
                 let is_multifunction = (dev.header_type & 0x80) != 0;
-                
-                println!("  [PCI] {:02x}:{:02x}.0 - {} [{:04x}:{:04x}]",
-                    bus, device, dev.class_name(), dev.vendor_id, dev.device_id);
-                
                 devices.push(dev);
                 
                 if is_multifunction {
                     for function in 1..8u8 {
                         if let Some(func_dev) = check_device(bus, device, function) {
-                            println!("  [PCI] {:02x}:{:02x}.{} - {} [{:04x}:{:04x}]",
-                                bus, device, function, func_dev.class_name(),
-                                func_dev.vendor_id, func_dev.device_id);
                             devices.push(func_dev);
                         }
                     }
@@ -195,8 +205,45 @@ pub fn scan_bus() {
             }
         }
     }
-    
+    devices
+}
+
+pub fn scan_bus() {
+    let devices = probe();
+    for dev in &devices {
+        println!("  [PCI] {} - {} [{:04x}:{:04x}]",
+            dev.address, dev.class_name(), dev.vendor_id, dev.device_id);
+    }
     println!("  [PCI] Found {} device(s)", devices.len());
+    *PCI_DEVICES.lock() = devices;
+}
+
+/// Scan the bus again and report the functions that appeared or went away
+/// since the last scan to hotplug. Returns how many did.
+pub fn rescan() -> usize {
+    let found = probe();
+    let same = |a: &PciDevice, b: &PciDevice| {
+        a.address == b.address && a.vendor_id == b.vendor_id && a.device_id == b.device_id
+    };
+    let mut devices = PCI_DEVICES.lock();
+    let removed: Vec<PciAddress> = devices.iter()
+        .filter(|old| !found.iter().any(|new| same(old, new)))
+        .map(|old| old.address)
+        .collect();
+    let added: Vec<PciAddress> = found.iter()
+        .filter(|new| !devices.iter().any(|old| same(old, new)))
+        .map(|new| new.address)
+        .collect();
+    *devices = found;
+    drop(devices);
+
+    for address in &removed {
+        hotplug::report(Action::Remove, "pci", &address.to_string(), None);
+    }
+    for address in &added {
+        hotplug::report(Action::Add, "pci", &address.to_string(), None);
+    }
+    removed.len() + added.len()
 }
 
 pub fn get_devices() -> Vec<PciDevice> {
@@ -223,6 +270,20 @@ pub fn find_ahci_controllers() -> Vec<PciDevice> {
         .collect()
 }
 
+pub fn find_nvme_controllers() -> Vec<PciDevice> {
+    PCI_DEVICES.lock().iter()
+        .filter(|d| d.is_nvme())
+        .cloned()
+        .collect()
+}
+
+pub fn find_sdhci_controllers() -> Vec<PciDevice> {
+    PCI_DEVICES.lock().iter()
+        .filter(|d| d.is_sdhci())
+        .cloned()
+        .collect()
+}
+
 pub fn find_usb_controllers() -> Vec<PciDevice> {
     PCI_DEVICES.lock().iter()
         .filter(|d| d.is_usb())
@@ -240,6 +301,55 @@ pub fn enable_memory_space(device: &PciDevice) {
     write_config_word(device.address, 0x04, command | 0x02);
 }
 
+/// Set the command register's interrupt disable bit, silencing INTx
+pub fn disable_intx(device: &PciDevice) {
+    let command = read_config_word(device.address, 0x04);
+    write_config_word(device.address, 0x04, command | 0x400);
+}
+
+/// Stop every device but the bridges from mastering the bus and raising
+/// INTx, so none of them writes to memory or interrupts from here on
+pub fn quiesce() {
+    for device in PCI_DEVICES.lock().iter().filter(|d| !d.is_bridge()) {
+        let command = read_config_word(device.address, 0x04);
+        write_config_word(device.address, 0x04, (command & !0x04) | 0x400);
+    }
+}
+
+/// Config space offset of the capability `id`, if the device has it
+pub fn find_capability(device: &PciDevice, id: u8) -> Option<u8> {
+    if read_config_word(device.address, 0x06) & 0x10 == 0 {
+        return None;
+    }
+    let mut offset = (read_config_dword(device.address, 0x34) & 0xFC) as u8;
+    // The list can be at most 48 entries long; stop on a loop
+    for _ in 0..48 {
+        if offset == 0 {
+            return None;
+        }
+        let header = read_config_word(device.address, offset);
+        if header as u8 == id {
+            return Some(offset);
+        }
+        offset = (header >> 8) as u8 & 0xFC;
+    }
+    None
+}
+
+/// Physical address of memory BAR `index`, taking the next BAR as the
+/// high half of a 64-bit one; 0 if it is an I/O BAR
+pub fn memory_bar(device: &PciDevice, index: usize) -> u64 {
+    let bar = device.bar[index];
+    if is_bar_io(bar) {
+        return 0;
+    }
+    let low = (bar & 0xFFFFFFF0) as u64;
+    match (bar >> 1) & 0x03 {
+        0x02 if index < 5 => low | (device.bar[index + 1] as u64) << 32,
+        _ => low,
+    }
+}
+
 pub fn enable_io_space(device: &PciDevice) {
     let command = read_config_word(device.address, 0x04);
     write_config_word(device.address, 0x04, command | 0x01);
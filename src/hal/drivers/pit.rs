@@ -1,4 +1,5 @@
 use x86_64::instructions::port::Port;
+use core::sync::atomic::{AtomicU64, Ordering};
 use spin::Mutex;
 use lazy_static::lazy_static;
 
@@ -9,6 +10,8 @@ const PIT_CHANNEL1: u16 = 0x41;
 const PIT_CHANNEL2: u16 = 0x42;
 const PIT_COMMAND: u16 = 0x43;
 
+static TSC_PER_US: AtomicU64 = AtomicU64::new(0);
+
 lazy_static! {
     static ref TICKS: Mutex<u64> = Mutex::new(0);
     static ref UPTIME_SECONDS: Mutex<u64> = Mutex::new(0);
@@ -55,6 +58,7 @@ pub fn get_uptime_ms() -> u64 {
 }
 
 pub fn sleep_ms(milliseconds: u64) {
+    crate::hal::cpu::context::assert_may_sleep("sleep_ms");
     let start = get_ticks();
     while get_ticks() - start < milliseconds {
         x86_64::instructions::hlt();
@@ -72,6 +76,31 @@ pub fn busy_wait_us(microseconds: u64) {
     }
 }
 
+/// TSC cycles per microsecond, counted over 50 ticks the first time and
+/// remembered. Needs the timer interrupt running.
+pub fn tsc_per_us() -> u64 {
+    const CALIBRATE_MS: u64 = 50;
+    let known = TSC_PER_US.load(Ordering::Relaxed);
+    if known != 0 {
+        return known;
+    }
+    let start = get_ticks();
+    while get_ticks() == start {
+        core::hint::spin_loop();
+    }
+    let (tick, tsc) = (get_ticks(), rdtsc());
+    while get_ticks() < tick + CALIBRATE_MS {
+        core::hint::spin_loop();
+    }
+    let rate = ((rdtsc() - tsc) / (CALIBRATE_MS * 1000)).max(1);
+    TSC_PER_US.store(rate, Ordering::Relaxed);
+    rate
+}
+
+pub fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
 pub fn read_counter() -> u16 {
     unsafe {
         let mut command_port = Port::<u8>::new(PIT_COMMAND);
@@ -0,0 +1,155 @@
+// CMOS real-time clock
+//
+// The RTC keeps wall-clock time across reboots. Depending on status
+// register B it counts in BCD or binary and in 12- or 24-hour mode; both
+// are converted here. The century register (0x32) is not guaranteed to
+// exist, so years it cannot vouch for are taken to be 20xx.
+
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use crate::kernel::time::DateTime;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_CENTURY: u8 = 0x32;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+/// Status A: an update cycle is in progress
+const STATUS_A_UIP: u8 = 0x80;
+/// Status B: updates halted while the clock is being set
+const STATUS_B_SET: u8 = 0x80;
+/// Status B: 24-hour mode
+const STATUS_B_24H: u8 = 0x02;
+/// Status B: binary rather than BCD values
+const STATUS_B_BINARY: u8 = 0x04;
+/// Hours register: PM in 12-hour mode
+const HOUR_PM: u8 = 0x80;
+
+fn read_register(reg: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(reg);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+fn write_register(reg: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(reg);
+        Port::<u8>::new(CMOS_DATA).write(value);
+    }
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Raw {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+fn read_raw() -> Raw {
+    while read_register(REG_STATUS_A) & STATUS_A_UIP != 0 {
+        core::hint::spin_loop();
+    }
+    Raw {
+        seconds: read_register(REG_SECONDS),
+        minutes: read_register(REG_MINUTES),
+        hours: read_register(REG_HOURS),
+        day: read_register(REG_DAY),
+        month: read_register(REG_MONTH),
+        year: read_register(REG_YEAR),
+        century: read_register(REG_CENTURY),
+    }
+}
+
+/// Read the current date and time (UTC)
+pub fn read() -> DateTime {
+    let (raw, status_b) = interrupts::without_interrupts(|| {
+        // An update can still land between the UIP check and the reads;
+        // retry until two consecutive reads agree
+        let mut raw = read_raw();
+        loop {
+            let again = read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        (raw, read_register(REG_STATUS_B))
+    });
+
+    let bcd = status_b & STATUS_B_BINARY == 0;
+    let decode = |v: u8| if bcd { from_bcd(v) } else { v };
+
+    let pm = raw.hours & HOUR_PM != 0;
+    let mut hour = decode(raw.hours & !HOUR_PM);
+    if status_b & STATUS_B_24H == 0 {
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    let century = match decode(raw.century) {
+        c @ 19..=30 => c as u32,
+        _ => 20,
+    };
+
+    DateTime {
+        year: century * 100 + decode(raw.year) as u32,
+        month: decode(raw.month),
+        day: decode(raw.day),
+        hour,
+        minute: decode(raw.minutes),
+        second: decode(raw.seconds),
+    }
+}
+
+/// Set the clock to `time` (UTC), which must be a valid date in
+/// 1900..=3099
+pub fn write(time: &DateTime) {
+    interrupts::without_interrupts(|| {
+        let status_b = read_register(REG_STATUS_B);
+        let bcd = status_b & STATUS_B_BINARY == 0;
+        let encode = |v: u8| if bcd { to_bcd(v) } else { v };
+
+        let hours = if status_b & STATUS_B_24H != 0 {
+            encode(time.hour)
+        } else {
+            let twelve = match time.hour % 12 {
+                0 => 12,
+                h => h,
+            };
+            encode(twelve) | if time.hour >= 12 { HOUR_PM } else { 0 }
+        };
+
+        write_register(REG_STATUS_B, status_b | STATUS_B_SET);
+        write_register(REG_SECONDS, encode(time.second));
+        write_register(REG_MINUTES, encode(time.minute));
+        write_register(REG_HOURS, hours);
+        write_register(REG_DAY, encode(time.day));
+        write_register(REG_MONTH, encode(time.month));
+        write_register(REG_YEAR, encode((time.year % 100) as u8));
+        write_register(REG_CENTURY, encode((time.year / 100) as u8));
+        write_register(REG_STATUS_B, status_b & !STATUS_B_SET);
+    });
+}
@@ -0,0 +1,555 @@
+// SD cards on SDHCI controllers
+//
+// Host controllers are found on PCI (class 08:05, one slot per BAR) or at
+// the MMIO addresses given as `sdhci=ADDR[,ADDR...]` for boards without
+// PCI. Transfers are polled PIO through the buffer data port, one 512-byte
+// block per command, with the card at 25 MHz on a 4-bit bus. A card in a
+// slot is brought up with CMD0, CMD8, then CMD55/ACMD41 until it is ready,
+// and registered as mmcblkN behind a request queue, from card().
+//
+// Nothing raises interrupts. poll() looks at each slot's card-detect state
+// and registers cards that have arrived and unregisters the ones that have
+// gone; it runs at boot and between shell commands. A BlockDevice for a
+// removed card fails from then on, even if another card goes in.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+use x86_64::PhysAddr;
+use crate::fs::ext4::ext4::BlockDevice;
+use crate::fs::iosched::RequestQueue;
+use crate::fs::vfs::node::DeviceId;
+use crate::hal::memory::paging;
+use super::pci;
+use super::pit;
+
+pub const PARAM: &str = "sdhci";
+pub const BLOCK_SIZE: usize = 512;
+
+const REG_BLOCK_SIZE: u64 = 0x04;
+const REG_BLOCK_COUNT: u64 = 0x06;
+const REG_ARGUMENT: u64 = 0x08;
+const REG_TRANSFER_MODE: u64 = 0x0C;
+const REG_COMMAND: u64 = 0x0E;
+const REG_RESPONSE: u64 = 0x10;
+const REG_DATA: u64 = 0x20;
+const REG_PRESENT_STATE: u64 = 0x24;
+const REG_HOST_CONTROL: u64 = 0x28;
+const REG_POWER_CONTROL: u64 = 0x29;
+const REG_CLOCK_CONTROL: u64 = 0x2C;
+const REG_TIMEOUT_CONTROL: u64 = 0x2E;
+const REG_SOFTWARE_RESET: u64 = 0x2F;
+const REG_INT_STATUS: u64 = 0x30;
+const REG_ERROR_STATUS: u64 = 0x32;
+const REG_INT_ENABLE: u64 = 0x34;
+const REG_ERROR_ENABLE: u64 = 0x36;
+const REG_CAPABILITIES: u64 = 0x40;
+const REG_VERSION: u64 = 0xFE;
+
+const STATE_CMD_INHIBIT: u32 = 1 << 0;
+const STATE_DAT_INHIBIT: u32 = 1 << 1;
+const STATE_CARD_INSERTED: u32 = 1 << 16;
+
+const INT_COMMAND_COMPLETE: u16 = 1 << 0;
+const INT_TRANSFER_COMPLETE: u16 = 1 << 1;
+const INT_BUFFER_WRITE_READY: u16 = 1 << 4;
+const INT_BUFFER_READ_READY: u16 = 1 << 5;
+const INT_CARD_CHANGE: u16 = 3 << 6;
+const INT_ERROR: u16 = 1 << 15;
+
+const CLOCK_INTERNAL_ENABLE: u16 = 1 << 0;
+const CLOCK_INTERNAL_STABLE: u16 = 1 << 1;
+const CLOCK_CARD_ENABLE: u16 = 1 << 2;
+
+const RESET_ALL: u8 = 1 << 0;
+const RESET_LINES: u8 = 3 << 1;
+
+/// Bus power on at 3.3 V
+const POWER_3V3_ON: u8 = 0x0F;
+const HOST_4BIT: u8 = 1 << 1;
+/// Maximum data timeout, TMCLK * 2^27
+const TIMEOUT_MAX: u8 = 0x0E;
+
+const MODE_READ: u16 = 1 << 4;
+
+/// Command register flags: response type, CRC and index checks, data
+const RESP_NONE: u16 = 0;
+const RESP_136: u16 = 1 | 1 << 3;
+const RESP_48: u16 = 2 | 1 << 3 | 1 << 4;
+const RESP_48_BUSY: u16 = 3 | 1 << 3 | 1 << 4;
+/// R3 carries the OCR and no valid CRC or index
+const RESP_R3: u16 = 2;
+const DATA_PRESENT: u16 = 1 << 5;
+
+const CMD_GO_IDLE: u8 = 0;
+const CMD_ALL_SEND_CID: u8 = 2;
+const CMD_SEND_RELATIVE_ADDR: u8 = 3;
+const CMD_SELECT_CARD: u8 = 7;
+const CMD_SEND_IF_COND: u8 = 8;
+const CMD_SEND_CSD: u8 = 9;
+const CMD_SET_BLOCKLEN: u8 = 16;
+const CMD_READ_SINGLE: u8 = 17;
+const CMD_WRITE_SINGLE: u8 = 24;
+const CMD_APP: u8 = 55;
+const ACMD_SET_BUS_WIDTH: u8 = 6;
+const ACMD_SEND_OP_COND: u8 = 41;
+
+/// CMD8 argument: 2.7-3.6 V and a check pattern the card echoes
+const IF_COND: u32 = 0x1AA;
+/// ACMD41: the voltage window, and HCS to accept high-capacity cards
+const OCR_VOLTAGES: u32 = 0x00FF_8000;
+const OCR_HCS: u32 = 1 << 30;
+const OCR_READY: u32 = 1 << 31;
+
+const INIT_CLOCK_KHZ: u32 = 400;
+const TRANSFER_CLOCK_KHZ: u32 = 25_000;
+
+/// Status polls before a command is given up on
+const POLL_LIMIT: usize = 1_000_000;
+/// How long a card may take to finish powering up after ACMD41
+const POWER_UP_MS: u64 = 1000;
+
+/// Block major number Linux uses for MMC
+const MMC_MAJOR: u16 = 179;
+const MINORS_PER_CARD: u16 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SdError {
+    NoCard,
+    /// The card was removed after it was registered
+    Removed,
+    Timeout,
+    /// A command failed; the error interrupt status
+    Command(u16),
+    /// The card does not work at the voltages offered
+    Unusable,
+    OutOfRange,
+}
+
+impl SdError {
+    pub fn message(self) -> &'static str {
+        match self {
+            SdError::NoCard => "no card",
+            SdError::Removed => "card removed",
+            SdError::Timeout => "card timed out",
+            SdError::Command(_) => "card command failed",
+            SdError::Unusable => "card not usable",
+            SdError::OutOfRange => "block out of range",
+        }
+    }
+}
+
+/// Capacity in 512-byte blocks from the CSD register, for both the
+/// standard (version 1.0) and high capacity (2.0) layouts
+pub fn csd_blocks(csd: u128) -> u64 {
+    let bits = |high: u32, low: u32| ((csd >> low) & ((1u128 << (high - low + 1)) - 1)) as u64;
+    match bits(127, 126) {
+        1 => (bits(69, 48) + 1) * 1024,
+        _ => {
+            let bytes = (bits(73, 62) + 1) << (bits(49, 47) + 2) << bits(83, 80);
+            bytes / BLOCK_SIZE as u64
+        }
+    }
+}
+
+/// Clock control value dividing `base_khz` down to at most `target_khz`:
+/// a 10-bit divisor from version 3.00 on, a power of two before
+fn clock_divider(base_khz: u32, target_khz: u32, version: u8) -> u16 {
+    if base_khz <= target_khz {
+        return 0;
+    }
+    // The card clock is base / (2 * N)
+    let n = base_khz.div_ceil(2 * target_khz);
+    if version >= 2 {
+        let n = n.min(0x3FF) as u16;
+        (n & 0xFF) << 8 | (n >> 8) << 6
+    } else {
+        (n.next_power_of_two().min(0x80) as u16) << 8
+    }
+}
+
+struct Card {
+    name: String,
+    /// Block rather than byte addressing
+    high_capacity: bool,
+    blocks: u64,
+}
+
+struct Host {
+    /// Virtual address of the slot registers
+    regs: u64,
+    version: u8,
+    base_khz: u32,
+    card: Option<Card>,
+    /// Bumped on every insertion, so devices of a removed card fail
+    generation: u32,
+}
+
+impl Host {
+    fn read8(&self, reg: u64) -> u8 {
+        unsafe { core::ptr::read_volatile((self.regs + reg) as *const u8) }
+    }
+
+    fn write8(&self, reg: u64, value: u8) {
+        unsafe { core::ptr::write_volatile((self.regs + reg) as *mut u8, value) }
+    }
+
+    fn read16(&self, reg: u64) -> u16 {
+        unsafe { core::ptr::read_volatile((self.regs + reg) as *const u16) }
+    }
+
+    fn write16(&self, reg: u64, value: u16) {
+        unsafe { core::ptr::write_volatile((self.regs + reg) as *mut u16, value) }
+    }
+
+    fn read32(&self, reg: u64) -> u32 {
+        unsafe { core::ptr::read_volatile((self.regs + reg) as *const u32) }
+    }
+
+    fn write32(&self, reg: u64, value: u32) {
+        unsafe { core::ptr::write_volatile((self.regs + reg) as *mut u32, value) }
+    }
+
+    fn card_inserted(&self) -> bool {
+        self.read32(REG_PRESENT_STATE) & STATE_CARD_INSERTED != 0
+    }
+
+    fn wait_until(&self, mut done: impl FnMut(&Host) -> bool) -> Result<(), SdError> {
+        for _ in 0..POLL_LIMIT {
+            if done(self) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(SdError::Timeout)
+    }
+
+    fn reset(&self, what: u8) -> Result<(), SdError> {
+        self.write8(REG_SOFTWARE_RESET, what);
+        self.wait_until(|host| host.read8(REG_SOFTWARE_RESET) & what == 0)
+    }
+
+    fn set_clock(&self, target_khz: u32) -> Result<(), SdError> {
+        self.write16(REG_CLOCK_CONTROL, 0);
+        let divider = clock_divider(self.base_khz, target_khz, self.version);
+        self.write16(REG_CLOCK_CONTROL, divider | CLOCK_INTERNAL_ENABLE);
+        self.wait_until(|host| host.read16(REG_CLOCK_CONTROL) & CLOCK_INTERNAL_STABLE != 0)?;
+        self.write16(REG_CLOCK_CONTROL, divider | CLOCK_INTERNAL_ENABLE | CLOCK_CARD_ENABLE);
+        Ok(())
+    }
+
+    /// Reset the slot and power the card up at the identification clock
+    fn power_up(&self) -> Result<(), SdError> {
+        self.reset(RESET_ALL)?;
+        self.write16(REG_INT_ENABLE, 0xFFFF);
+        self.write16(REG_ERROR_ENABLE, 0xFFFF);
+        self.write8(REG_TIMEOUT_CONTROL, TIMEOUT_MAX);
+        self.write8(REG_POWER_CONTROL, POWER_3V3_ON);
+        self.set_clock(INIT_CLOCK_KHZ)
+    }
+
+    /// Wait for `bits` in the interrupt status and clear them, failing on
+    /// an error interrupt
+    fn wait_status(&self, bits: u16) -> Result<(), SdError> {
+        for _ in 0..POLL_LIMIT {
+            let status = self.read16(REG_INT_STATUS);
+            if status & INT_ERROR != 0 {
+                let error = self.read16(REG_ERROR_STATUS);
+                self.write16(REG_ERROR_STATUS, error);
+                self.write16(REG_INT_STATUS, status & !INT_CARD_CHANGE);
+                self.reset(RESET_LINES).ok();
+                return Err(SdError::Command(error));
+            }
+            if status & bits == bits {
+                self.write16(REG_INT_STATUS, bits);
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(SdError::Timeout)
+    }
+
+    /// Send a command and return its response registers
+    fn command(&self, index: u8, argument: u32, flags: u16) -> Result<[u32; 4], SdError> {
+        let mut inhibit = STATE_CMD_INHIBIT;
+        if flags & DATA_PRESENT != 0 || flags == RESP_48_BUSY {
+            inhibit |= STATE_DAT_INHIBIT;
+        }
+        self.wait_until(|host| host.read32(REG_PRESENT_STATE) & inhibit == 0)?;
+        self.write32(REG_ARGUMENT, argument);
+        self.write16(REG_COMMAND, (index as u16) << 8 | flags);
+        self.wait_status(INT_COMMAND_COMPLETE)?;
+        if flags == RESP_48_BUSY {
+            self.wait_status(INT_TRANSFER_COMPLETE)?;
+        }
+        let mut response = [0u32; 4];
+        for (i, word) in response.iter_mut().enumerate() {
+            *word = self.read32(REG_RESPONSE + 4 * i as u64);
+        }
+        Ok(response)
+    }
+
+    fn app_command(&self, rca: u16, index: u8, argument: u32, flags: u16) -> Result<[u32; 4], SdError> {
+        self.command(CMD_APP, (rca as u32) << 16, RESP_48)?;
+        self.command(index, argument, flags)
+    }
+
+    /// Identify the card in the slot and put it in transfer state
+    fn init_card(&self, name: String) -> Result<Card, SdError> {
+        if !self.card_inserted() {
+            return Err(SdError::NoCard);
+        }
+        self.power_up()?;
+        self.command(CMD_GO_IDLE, 0, RESP_NONE)?;
+        // Only version 2.00 cards answer CMD8, and only they may be SDHC
+        let v2 = match self.command(CMD_SEND_IF_COND, IF_COND, RESP_48) {
+            Ok(response) if response[0] & 0xFFF == IF_COND => true,
+            Ok(_) => return Err(SdError::Unusable),
+            Err(_) => false,
+        };
+        let hcs = if v2 { OCR_HCS } else { 0 };
+        let timer = pit::Timer::new(POWER_UP_MS);
+        let ocr = loop {
+            let ocr = self.app_command(0, ACMD_SEND_OP_COND, OCR_VOLTAGES | hcs, RESP_R3)?[0];
+            if ocr & OCR_READY != 0 {
+                break ocr;
+            }
+            if timer.is_expired() {
+                return Err(SdError::Timeout);
+            }
+            pit::busy_wait_us(1000);
+        };
+
+        self.command(CMD_ALL_SEND_CID, 0, RESP_136)?;
+        let rca = (self.command(CMD_SEND_RELATIVE_ADDR, 0, RESP_48)?[0] >> 16) as u16;
+        let csd = self.command(CMD_SEND_CSD, (rca as u32) << 16, RESP_136)?;
+        // The controller drops the CRC byte, so the register reads 8 bits low
+        let csd = csd.iter().rev().fold(0u128, |value, &word| value << 32 | word as u128) << 8;
+        self.command(CMD_SELECT_CARD, (rca as u32) << 16, RESP_48_BUSY)?;
+
+        let high_capacity = ocr & OCR_HCS != 0;
+        if !high_capacity {
+            self.command(CMD_SET_BLOCKLEN, BLOCK_SIZE as u32, RESP_48)?;
+        }
+        self.app_command(rca, ACMD_SET_BUS_WIDTH, 2, RESP_48)?;
+        self.write8(REG_HOST_CONTROL, self.read8(REG_HOST_CONTROL) | HOST_4BIT);
+        self.set_clock(TRANSFER_CLOCK_KHZ)?;
+        Ok(Card { name, high_capacity, blocks: csd_blocks(csd) })
+    }
+
+    /// Start a one-block transfer at `block`
+    fn start(&self, block: u64, read: bool) -> Result<(), SdError> {
+        let card = self.card.as_ref().ok_or(SdError::NoCard)?;
+        let address = if card.high_capacity { block } else { block * BLOCK_SIZE as u64 };
+        self.write16(REG_BLOCK_SIZE, BLOCK_SIZE as u16);
+        self.write16(REG_BLOCK_COUNT, 1);
+        self.write16(REG_TRANSFER_MODE, if read { MODE_READ } else { 0 });
+        let index = if read { CMD_READ_SINGLE } else { CMD_WRITE_SINGLE };
+        self.command(index, address as u32, RESP_48 | DATA_PRESENT).map(|_| ())
+    }
+
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> Result<(), SdError> {
+        self.start(block, true)?;
+        self.wait_status(INT_BUFFER_READ_READY)?;
+        for word in buf.chunks_exact_mut(4) {
+            word.copy_from_slice(&self.read32(REG_DATA).to_le_bytes());
+        }
+        self.wait_status(INT_TRANSFER_COMPLETE)
+    }
+
+    fn write_block(&self, block: u64, buf: &[u8]) -> Result<(), SdError> {
+        self.start(block, false)?;
+        self.wait_status(INT_BUFFER_WRITE_READY)?;
+        for word in buf.chunks_exact(4) {
+            self.write32(REG_DATA, u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+        }
+        self.wait_status(INT_TRANSFER_COMPLETE)
+    }
+}
+
+/// The card in a slot as a BlockDevice of 512-byte blocks
+pub struct SdCard {
+    host: Arc<Mutex<Host>>,
+    generation: u32,
+    blocks: u64,
+}
+
+impl SdCard {
+    fn check(&self, block_num: u64, len: usize) -> Result<(), &'static str> {
+        if len % BLOCK_SIZE != 0 {
+            return Err("transfer is not a whole number of blocks");
+        }
+        match block_num.checked_add((len / BLOCK_SIZE) as u64) {
+            Some(end) if end <= self.blocks => Ok(()),
+            _ => Err(SdError::OutOfRange.message()),
+        }
+    }
+
+    /// Run `f` on the host, unless the card has gone since this device
+    /// was made
+    fn with_host(&self, f: impl FnOnce(&Host) -> Result<(), SdError>) -> Result<(), &'static str> {
+        let host = self.host.lock();
+        if host.generation != self.generation || host.card.is_none() {
+            return Err(SdError::Removed.message());
+        }
+        f(&host).map_err(SdError::message)
+    }
+}
+
+impl BlockDevice for SdCard {
+    fn read_block(&self, block_num: u64, buf: &mut [u8]) -> Result<(), &'static str> {
+        self.check(block_num, buf.len())?;
+        self.with_host(|host| {
+            for (n, block) in buf.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+                host.read_block(block_num + n as u64, block)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn write_block(&mut self, block_num: u64, buf: &[u8]) -> Result<(), &'static str> {
+        self.check(block_num, buf.len())?;
+        self.with_host(|host| {
+            for (n, block) in buf.chunks_exact(BLOCK_SIZE).enumerate() {
+                host.write_block(block_num + n as u64, block)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn block_size(&self) -> u32 {
+        BLOCK_SIZE as u32
+    }
+
+    fn block_count(&self) -> u64 {
+        self.blocks
+    }
+}
+
+pub type SharedCard = Arc<RwLock<RequestQueue<SdCard>>>;
+
+static HOSTS: Mutex<Vec<Arc<Mutex<Host>>>> = Mutex::new(Vec::new());
+static CARDS: Mutex<Vec<(String, SharedCard)>> = Mutex::new(Vec::new());
+
+/// The card registered as `name`, e.g. "mmcblk0"
+pub fn card(name: &str) -> Option<SharedCard> {
+    CARDS.lock().iter().find(|(n, _)| n == name).map(|(_, card)| card.clone())
+}
+
+/// Register the slot whose registers are at physical `addr`
+fn add_host(addr: u64) {
+    let regs = match paging::phys_to_virt(PhysAddr::new(addr)) {
+        Some(regs) if addr != 0 => regs.as_u64(),
+        _ => return,
+    };
+    let mut host = Host { regs, version: 0, base_khz: 0, card: None, generation: 0 };
+    host.version = host.read16(REG_VERSION) as u8;
+    let capabilities = host.read32(REG_CAPABILITIES);
+    let mask = if host.version >= 2 { 0xFF } else { 0x3F };
+    host.base_khz = ((capabilities >> 8) & mask) * 1000;
+    crate::println!("  [SDHCI] Slot at {:#x}, version {}.00", addr, host.version + 1);
+    HOSTS.lock().push(Arc::new(Mutex::new(host)));
+}
+
+/// Register the card of slot `index`, which has just been inserted
+fn attach(index: usize, shared: &Arc<Mutex<Host>>) {
+    let name = format!("mmcblk{}", index);
+    let mut host = shared.lock();
+    let card = match host.init_card(name.clone()) {
+        Ok(card) => card,
+        Err(e) => {
+            crate::println!("  [SDHCI] {}: {}", name, e.message());
+            return;
+        }
+    };
+    let blocks = card.blocks;
+    crate::println!("  [SDHCI] {}: {} card, {} blocks", name,
+        if card.high_capacity { "SDHC/SDXC" } else { "SDSC" }, blocks);
+    host.card = Some(card);
+    host.generation += 1;
+    let sd = SdCard { host: shared.clone(), generation: host.generation, blocks };
+    drop(host);
+
+    let queue = Arc::new(RwLock::new(RequestQueue::new(&name, sd)));
+    CARDS.lock().push((name.clone(), queue.clone()));
+    let id = DeviceId::new(MMC_MAJOR, index as u16 * MINORS_PER_CARD);
+    crate::hal::block::register(&name, id, "sdhci", queue, MINORS_PER_CARD);
+}
+
+fn detach(card: Card) {
+    crate::println!("  [SDHCI] {}: card removed", card.name);
+    crate::hal::block::unregister(&card.name);
+    let mut cards = CARDS.lock();
+    let gone = cards.iter().position(|(name, _)| *name == card.name).map(|at| cards.remove(at));
+    drop(cards);
+    // Writeback still queued fails now that the card is gone
+    drop(gone);
+}
+
+/// Pick up cards inserted or removed since the last call
+pub fn poll() {
+    let hosts = HOSTS.lock().clone();
+    for (index, shared) in hosts.iter().enumerate() {
+        let mut host = shared.lock();
+        let changes = host.read16(REG_INT_STATUS) & INT_CARD_CHANGE;
+        host.write16(REG_INT_STATUS, changes);
+        // A removal and insertion between two polls is a different card
+        let removed = if changes != 0 || !host.card_inserted() { host.card.take() } else { None };
+        let arrived = host.card.is_none() && host.card_inserted();
+        drop(host);
+        if let Some(card) = removed {
+            detach(card);
+        }
+        if arrived {
+            attach(index, shared);
+        }
+    }
+}
+
+/// Find the host controllers and the cards in them
+pub fn init() {
+    for device in pci::find_sdhci_controllers() {
+        pci::enable_memory_space(&device);
+        // Slot information: first BAR and slot count, one BAR per slot
+        let slots = pci::read_config_word(device.address, 0x40);
+        let first = (slots & 0x7) as usize;
+        let count = ((slots >> 4) & 0x7) as usize + 1;
+        for bar in (first..first + count).filter(|&bar| bar < 6) {
+            add_host(pci::memory_bar(&device, bar));
+        }
+    }
+    if let Some(value) = crate::kernel::get_param(PARAM) {
+        for addr in value.split(',') {
+            match u64::from_str_radix(addr.trim_start_matches("0x"), 16) {
+                Ok(addr) => add_host(addr),
+                Err(_) => crate::println!("  [SDHCI] {}={}: bad address", PARAM, addr),
+            }
+        }
+    }
+    poll();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_csd_capacity() {
+        // Version 2.0: C_SIZE 0x3B37 is an 8 GB card
+        let csd = 1u128 << 126 | 0x3B37u128 << 48;
+        assert_eq!(csd_blocks(csd), 0x3B38 * 1024);
+        // Version 1.0: C_SIZE 4095, C_SIZE_MULT 7, READ_BL_LEN 10 is 2 GB
+        let csd = 4095u128 << 62 | 7u128 << 47 | 10u128 << 80;
+        assert_eq!(csd_blocks(csd), 4096 * 512 * 1024 / 512);
+    }
+
+    #[test_case]
+    fn test_clock_divider() {
+        assert_eq!(clock_divider(50_000, 400, 2), 63 << 8);
+        assert_eq!(clock_divider(50_000, 400, 1), 64 << 8);
+        assert_eq!(clock_divider(50_000, 25_000, 2), 1 << 8);
+        assert_eq!(clock_divider(25_000, 25_000, 1), 0);
+    }
+}
@@ -1,73 +1,433 @@
-use uart_16550::SerialPort;
-use spin::Mutex;
-use lazy_static::lazy_static;
+// 16550 serial ports
+//
+// COM1 to COM4 at their ISA addresses, found by their scratch register
+// answering, and /dev/ttyS0 to /dev/ttyS3 for the ones that do. Output
+// goes into a ring per port that the transmitter-empty interrupt drains
+// a FIFO's worth at a time, so logging does not wait on the line; input
+// is read from the FIFO by the receive interrupt into a ring that
+// read_byte() and reads of the device take from.
+//
+// Until init() has run, and whenever interrupts are off - in a handler,
+// during a panic, on the way to a reset - a write goes out before it
+// returns, after whatever was queued, as no interrupt may come to send
+// it. A full ring is waited on the same way. COM1, the console, works
+// from the first print: it is set up by that print and taken over by
+// init().
+
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port as IoPort;
+use crate::fs::vfs::node::DeviceId;
+use crate::fs::{FsError, FsResult};
+use crate::hal::cpu::interrupts::set_irq_mask;
+use super::device::{self, DeviceKind};
+
+/// Base addresses and IRQ lines of COM1 to COM4
+const PORTS: [(u16, u8); 4] = [(0x3F8, 4), (0x2F8, 3), (0x3E8, 4), (0x2E8, 3)];
+
+/// /dev/ttyS0 is minor 64 of the terminals' major, as on Linux
+const TTY_MAJOR: u16 = 4;
+const MINOR_BASE: u16 = 64;
+
+const TX_SIZE: usize = 4096;
+const RX_SIZE: usize = 1024;
+/// Bytes the transmit FIFO takes once it reports empty
+const FIFO_SIZE: usize = 16;
+/// What the divisor latch divides to get the baud rate
+const CLOCK: u32 = 115_200;
+
+// Register offsets from the base
+const DATA: u16 = 0;
+const IER: u16 = 1;
+const IIR: u16 = 2;
+const FCR: u16 = 2;
+const LCR: u16 = 3;
+const MCR: u16 = 4;
+const LSR: u16 = 5;
+const MSR: u16 = 6;
+const SCRATCH: u16 = 7;
+
+const IER_RX: u8 = 0x01;
+const IER_THRE: u8 = 0x02;
+const LCR_DLAB: u8 = 0x80;
+const LSR_DATA: u8 = 0x01;
+const LSR_THRE: u8 = 0x20;
+/// FIFOs on and cleared, receive interrupt at 14 bytes
+const FCR_ENABLE: u8 = 0xC7;
+/// DTR, RTS, and OUT2, which connects the interrupt to the PIC
+const MCR_ON: u8 = 0x0B;
+
+/// Everything written from here on goes out before the write returns
+static SYNCHRONOUS: AtomicBool = AtomicBool::new(false);
+
+static SERIAL: Mutex<[Option<Uart>; 4]> = Mutex::new([None, None, None, None]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    /// Always 1
+    Mark,
+    /// Always 0
+    Space,
+}
+
+/// A port's line settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub baud: u32,
+    /// 5 to 8
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 or 2
+    pub stop_bits: u8,
+}
+
+impl Default for Config {
+    /// 38400 8N1
+    fn default() -> Self {
+        Config { baud: 38400, data_bits: 8, parity: Parity::None, stop_bits: 1 }
+    }
+}
+
+impl Config {
+    /// The divisor latch value, if the UART's clock divides down to the
+    /// baud rate exactly
+    fn divisor(&self) -> Option<u16> {
+        if self.baud == 0 || CLOCK % self.baud != 0 {
+            return None;
+        }
+        u16::try_from(CLOCK / self.baud).ok()
+    }
+
+    /// The line control register for the settings, if they are ones the
+    /// UART has
+    fn line_control(&self) -> Option<u8> {
+        if !(5..=8).contains(&self.data_bits) || !(1..=2).contains(&self.stop_bits) {
+            return None;
+        }
+        let parity = match self.parity {
+            Parity::None => 0x00,
+            Parity::Odd => 0x08,
+            Parity::Even => 0x18,
+            Parity::Mark => 0x28,
+            Parity::Space => 0x38,
+        };
+        Some((self.data_bits - 5) | (self.stop_bits - 1) << 2 | parity)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.divisor().is_some() && self.line_control().is_some()
+    }
+}
+
+/// A fixed ring of bytes; it takes no heap, as COM1 is written to before
+/// there is one
+struct Ring<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> Ring<N> {
+    const fn new() -> Self {
+        Ring { buf: [0; N], head: 0, len: 0 }
+    }
+
+    /// Add `byte` at the end, unless the ring is full
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+struct Uart {
+    base: u16,
+    irq: u8,
+    config: Config,
+    /// Whether init() has taken it over and its interrupts are on
+    interrupts: bool,
+    tx: Ring<TX_SIZE>,
+    rx: Ring<RX_SIZE>,
+}
+
+impl Uart {
+    fn new(base: u16, irq: u8) -> Self {
+        Uart { base, irq, config: Config::default(), interrupts: false, tx: Ring::new(), rx: Ring::new() }
+    }
+
+    fn read(&self, register: u16) -> u8 {
+        unsafe { IoPort::<u8>::new(self.base + register).read() }
+    }
+
+    fn write(&self, register: u16, value: u8) {
+        unsafe { IoPort::<u8>::new(self.base + register).write(value) }
+    }
+
+    /// Whether a UART answers at `base`: its scratch register keeps what
+    /// is written to it
+    fn probe(base: u16) -> bool {
+        let uart = Uart::new(base, 0);
+        [0x5A, 0xA5].iter().all(|&value| {
+            uart.write(SCRATCH, value);
+            uart.read(SCRATCH) == value
+        })
+    }
+
+    /// Program the line settings, FIFOs and interrupts from scratch
+    fn program(&self) {
+        let divisor = self.config.divisor().unwrap_or(3);
+        self.write(IER, 0);
+        self.write(LCR, LCR_DLAB);
+        self.write(DATA, divisor as u8);
+        self.write(IER, (divisor >> 8) as u8);
+        self.write(LCR, self.config.line_control().unwrap_or(0x03));
+        self.write(FCR, FCR_ENABLE);
+        self.write(MCR, MCR_ON);
+        self.write(IER, if self.interrupts { IER_RX } else { 0 });
+    }
+
+    /// Send `byte` once the transmitter has room, waiting for it
+    fn send_polled(&self, byte: u8) {
+        while self.read(LSR) & LSR_THRE == 0 {
+            core::hint::spin_loop();
+        }
+        self.write(DATA, byte);
+    }
+
+    /// Send all that is queued, waiting for it
+    fn flush(&mut self) {
+        while let Some(byte) = self.tx.pop() {
+            self.send_polled(byte);
+        }
+    }
+
+    /// Queue `byte`, or send it now if it cannot wait for an interrupt
+    fn queue(&mut self, byte: u8, now: bool) {
+        if now || !self.interrupts {
+            self.flush();
+            self.send_polled(byte);
+        } else if !self.tx.push(byte) {
+            // Full: make room by sending the oldest
+            if let Some(oldest) = self.tx.pop() {
+                self.send_polled(oldest);
+            }
+            self.tx.push(byte);
+        }
+    }
+
+    /// Fill the FIFO from the ring if it is empty, and have the port
+    /// interrupt when it is empty again while there is more
+    fn transmit(&mut self) {
+        if self.read(LSR) & LSR_THRE != 0 {
+            for _ in 0..FIFO_SIZE {
+                match self.tx.pop() {
+                    Some(byte) => self.write(DATA, byte),
+                    None => break,
+                }
+            }
+        }
+        self.write(IER, if self.tx.is_empty() { IER_RX } else { IER_RX | IER_THRE });
+    }
+
+    /// Take what was received off the FIFO; what the ring has no room for
+    /// is dropped
+    fn receive(&mut self) {
+        while self.read(LSR) & LSR_DATA != 0 {
+            let byte = self.read(DATA);
+            self.rx.push(byte);
+        }
+    }
+
+    /// Serve every interrupt the port has pending
+    fn interrupt(&mut self) {
+        loop {
+            match self.read(IIR) & 0x0F {
+                // Receiver line status: overrun, parity or framing error
+                0x06 => {
+                    self.read(LSR);
+                }
+                // Data, or a character timeout with data under the trigger level
+                0x04 | 0x0C => self.receive(),
+                0x02 => self.transmit(),
+                0x00 => {
+                    self.read(MSR);
+                }
+                _ => break,
+            }
+        }
+    }
 
-const COM1_PORT: u16 = 0x3F8;
-const COM2_PORT: u16 = 0x2F8;
+    fn next_byte(&mut self) -> Option<u8> {
+        // With interrupts off, nothing moves the FIFO into the ring
+        self.rx.pop().or_else(|| (self.read(LSR) & LSR_DATA != 0).then(|| self.read(DATA)))
+    }
+}
 
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(COM1_PORT) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
-    
-    pub static ref SERIAL2: Mutex<Option<SerialPort>> = Mutex::new(None);
+/// Run `f` on port `n` with interrupts off, COM1 being set up on first use
+fn with_port<R>(n: usize, f: impl FnOnce(&mut Uart) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| {
+        let mut ports = SERIAL.lock();
+        let slot = ports.get_mut(n)?;
+        if n == 0 && slot.is_none() {
+            let (base, irq) = PORTS[0];
+            let uart = Uart::new(base, irq);
+            uart.program();
+            *slot = Some(uart);
+        }
+        slot.as_mut().map(f)
+    })
 }
 
+/// Find the ports and register a device for each, switching them to
+/// interrupt-driven transfers
 pub fn init() {
-    let mut serial2 = SERIAL2.lock();
-    let mut port = unsafe { SerialPort::new(COM2_PORT) };
-    port.init();
-    *serial2 = Some(port);
+    for (n, &(base, irq)) in PORTS.iter().enumerate() {
+        // COM1 was already written to; the console stays, even unanswered
+        if n != 0 && !Uart::probe(base) {
+            continue;
+        }
+        interrupts::without_interrupts(|| {
+            let mut ports = SERIAL.lock();
+            let uart = ports[n].get_or_insert_with(|| Uart::new(base, irq));
+            uart.flush();
+            uart.interrupts = true;
+            uart.program();
+        });
+        set_irq_mask(irq, false);
+        let name = alloc::format!("ttyS{}", n);
+        device::register(&name, DeviceKind::Char, DeviceId::new(TTY_MAJOR, MINOR_BASE + n as u16), "serial", None);
+    }
+}
+
+/// Called by the IRQ 3 and IRQ 4 handlers: serve the ports on `irq`
+pub fn handle_interrupt(irq: u8) {
+    let mut ports = SERIAL.lock();
+    for uart in ports.iter_mut().flatten().filter(|uart| uart.irq == irq && uart.interrupts) {
+        uart.interrupt();
+    }
+}
+
+/// Which port the device `id` is, if it is one that was found
+pub fn port_of(id: DeviceId) -> Option<usize> {
+    let n = id.minor.checked_sub(MINOR_BASE)? as usize;
+    if id.major != TTY_MAJOR || n >= PORTS.len() {
+        return None;
+    }
+    interrupts::without_interrupts(|| SERIAL.lock()[n].is_some()).then_some(n)
+}
+
+/// Port `n`'s line settings
+pub fn config(n: usize) -> Option<Config> {
+    interrupts::without_interrupts(|| SERIAL.lock().get(n)?.as_ref().map(|uart| uart.config))
+}
+
+/// Change port `n`'s line settings, once what is queued has gone out at
+/// the old ones
+pub fn configure(n: usize, config: Config) -> FsResult<()> {
+    if !config.is_valid() {
+        return Err(FsError::InvalidArgument);
+    }
+    with_port(n, |uart| {
+        uart.flush();
+        uart.config = config;
+        uart.program();
+    }).ok_or(FsError::NotFound)
+}
+
+/// From now on send every write before returning, for a panic: the
+/// interrupt that would send what is queued may never come
+pub fn set_synchronous() {
+    SYNCHRONOUS.store(true, Ordering::Relaxed);
+}
+
+/// Send what every port has queued, waiting for it
+pub fn flush() {
+    for n in 0..PORTS.len() {
+        with_port(n, Uart::flush);
+    }
+}
+
+/// Write `buf` to port `n`; a port that was not found takes nothing
+pub fn write(n: usize, buf: &[u8]) {
+    let now = !interrupts::are_enabled() || SYNCHRONOUS.load(Ordering::Relaxed);
+    with_port(n, |uart| {
+        for &byte in buf {
+            uart.queue(byte, now);
+        }
+        if !now && uart.interrupts {
+            uart.transmit();
+        }
+    });
+}
+
+/// Read what port `n` has received into `buf`, without waiting
+pub fn read(n: usize, buf: &mut [u8]) -> usize {
+    with_port(n, |uart| {
+        let mut len = 0;
+        while len < buf.len() {
+            match uart.next_byte() {
+                Some(byte) => buf[len] = byte,
+                None => break,
+            }
+            len += 1;
+        }
+        len
+    }).unwrap_or(0)
 }
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
-
-    interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
-    });
+    SerialWriter.write_fmt(args).expect("Printing to serial failed");
 }
 
 pub fn write_byte(byte: u8) {
-    x86_64::instructions::interrupts::without_interrupts(|| {
-        SERIAL1.lock().send(byte);
-    });
+    write(0, &[byte]);
 }
 
 pub fn read_byte() -> Option<u8> {
-    x86_64::instructions::interrupts::without_interrupts(|| {
-        let mut serial = SERIAL1.lock();
-        if serial_data_available(&serial) {
-            Some(serial.receive())
-        } else {
-            None
-        }
-    })
+    with_port(0, Uart::next_byte).flatten()
 }
 
 pub fn read_byte_blocking() -> u8 {
+    crate::hal::cpu::context::assert_may_sleep("read_byte_blocking");
     loop {
         if let Some(byte) = read_byte() {
             return byte;
         }
+        crate::kernel::idle::run();
         x86_64::instructions::hlt();
     }
 }
 
 pub fn read_line(buffer: &mut [u8]) -> usize {
     let mut len = 0;
-    
+
     loop {
         let byte = read_byte_blocking();
-        
+
         match byte {
             b'\n' | b'\r' => {
                 _print(format_args!("\n"));
@@ -75,33 +435,31 @@ pub fn read_line(buffer: &mut [u8]) -> usize {
             }
             8 | 127 => { // Backspace (0x08) or DEL (0x7F)
                 if len > 0 {
-                    len -= 1;
+                    // Back over the whole of a multi-byte UTF-8 character
+                    while len > 0 {
+                        len -= 1;
+                        if buffer[len] & 0xC0 != 0x80 {
+                            break;
+                        }
+                    }
                     _print(format_args!("\u{8} \u{8}"));
                 }
             }
             _ if len < buffer.len() => {
                 buffer[len] = byte;
                 len += 1;
-                _print(format_args!("{}", byte as char));
+                // Echo the byte itself: the terminal decodes the UTF-8
+                write_byte(byte);
             }
             _ => {}
         }
     }
-    
-    len
-}
 
-fn serial_data_available(_serial: &SerialPort) -> bool {
-    unsafe {
-        let mut status_port = x86_64::instructions::port::Port::<u8>::new(COM1_PORT + 5);
-        (status_port.read() & 0x01) != 0
-    }
+    len
 }
 
 pub fn write_string(s: &str) {
-    for byte in s.bytes() {
-        write_byte(byte);
-    }
+    write(0, s.as_bytes());
 }
 
 pub struct SerialWriter;
@@ -125,4 +483,35 @@ mod tests {
     fn test_serial_output() {
         crate::serial_println!("test_serial_output");
     }
+
+    #[test_case]
+    fn test_ring_wraps_and_fills() {
+        let mut ring = Ring::<4>::new();
+        for byte in 1..=4 {
+            assert!(ring.push(byte));
+        }
+        assert!(!ring.push(5));
+        assert_eq!((ring.pop(), ring.pop()), (Some(1), Some(2)));
+        assert!(ring.push(5) && ring.push(6));
+        let drained: alloc::vec::Vec<u8> = core::iter::from_fn(|| ring.pop()).collect();
+        assert_eq!(drained, [3, 4, 5, 6]);
+        assert!(ring.is_empty());
+    }
+
+    #[test_case]
+    fn test_line_settings() {
+        let config = Config { baud: 9600, data_bits: 7, parity: Parity::Even, stop_bits: 2 };
+        assert_eq!((config.divisor(), config.line_control()), (Some(12), Some(0x1E)));
+        assert_eq!(Config::default().divisor(), Some(3));
+        assert_eq!(Config::default().line_control(), Some(0x03));
+        for bad in [
+            Config { baud: 110, ..Config::default() },
+            Config { baud: 0, ..Config::default() },
+            Config { baud: 1, ..Config::default() },
+            Config { data_bits: 9, ..Config::default() },
+            Config { stop_bits: 0, ..Config::default() },
+        ] {
+            assert!(!bad.is_valid(), "{:?}", bad);
+        }
+    }
 }
@@ -0,0 +1,249 @@
+// ATA IDENTIFY DEVICE and S.M.A.R.T. data
+//
+// Decoding of the 512-byte blocks a disk returns for IDENTIFY DEVICE and
+// for SMART READ DATA / READ THRESHOLDS. The drivers issue the commands;
+// this only interprets what comes back. sectors() does not allocate, so the
+// panic path can size a disk from raw IDENTIFY words.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// IDENTIFY DEVICE words
+pub type IdentifyWords = [u16; 256];
+
+/// What a disk says about itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+    /// Addressable sectors, with LBA48 if the disk has it
+    pub sectors: u64,
+    pub lba48: bool,
+    pub dma: bool,
+    pub smart_supported: bool,
+    pub smart_enabled: bool,
+    pub write_cache: bool,
+    pub trim: bool,
+    /// Nominal rotation rate in rpm; 1 means solid state, None unreported
+    pub rotation_rate: Option<u16>,
+}
+
+fn bit(word: u16, n: u32) -> bool {
+    word & (1 << n) != 0
+}
+
+/// Words 82-87 are only valid when bit 14 is set and bit 15 clear
+fn valid(word: u16) -> bool {
+    word & 0xC000 == 0x4000
+}
+
+/// ATA strings hold two characters per word, first in the high byte,
+/// padded with spaces
+fn ata_string(words: &[u16]) -> String {
+    let mut s = String::new();
+    for &word in words {
+        for byte in [(word >> 8) as u8, word as u8] {
+            s.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' });
+        }
+    }
+    String::from(s.trim())
+}
+
+fn lba48(words: &IdentifyWords) -> bool {
+    valid(words[83]) && bit(words[83], 10)
+}
+
+/// Sector count: words 100-103 with LBA48, else words 60-61
+pub fn sectors(words: &IdentifyWords) -> u64 {
+    let lba28 = words[60] as u64 | (words[61] as u64) << 16;
+    if !lba48(words) {
+        return lba28;
+    }
+    let long = (0..4).fold(0u64, |n, i| n | (words[100 + i] as u64) << (16 * i));
+    if long == 0 { lba28 } else { long }
+}
+
+impl Identity {
+    pub fn parse(words: &IdentifyWords) -> Identity {
+        Identity {
+            model: ata_string(&words[27..47]),
+            serial: ata_string(&words[10..20]),
+            firmware: ata_string(&words[23..27]),
+            sectors: sectors(words),
+            lba48: lba48(words),
+            dma: bit(words[49], 8),
+            smart_supported: valid(words[83]) && bit(words[82], 0),
+            smart_enabled: valid(words[87]) && bit(words[85], 0),
+            write_cache: valid(words[87]) && bit(words[85], 5),
+            trim: bit(words[169], 0),
+            rotation_rate: match words[217] {
+                0 | 0xFFFF => None,
+                rate => Some(rate),
+            },
+        }
+    }
+
+    /// The feature flags that are set, by name
+    pub fn features(&self) -> Vec<&'static str> {
+        [
+            (self.lba48, "lba48"),
+            (self.dma, "dma"),
+            (self.smart_supported, "smart"),
+            (self.write_cache, "write-cache"),
+            (self.trim, "trim"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|&(_, name)| name)
+        .collect()
+    }
+}
+
+/// The overall verdict of SMART RETURN STATUS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    Passed,
+    /// Some attribute has crossed its threshold
+    Failing,
+    /// The disk answered with neither signature
+    Unknown,
+}
+
+/// One row of the SMART attribute table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attribute {
+    pub id: u8,
+    pub flags: u16,
+    /// Normalised value, higher is better
+    pub current: u8,
+    pub worst: u8,
+    /// 0 if the disk has no threshold for it
+    pub threshold: u8,
+    /// The vendor's raw counter, 48 bits
+    pub raw: u64,
+}
+
+/// SMART data and thresholds hold 30 entries of 12 bytes from offset 2
+const TABLE_OFFSET: usize = 2;
+const TABLE_ENTRIES: usize = 30;
+const ENTRY_SIZE: usize = 12;
+
+impl Attribute {
+    /// Pre-failure attributes predict failure; the rest are statistics
+    pub fn prefail(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    pub fn failing(&self) -> bool {
+        self.threshold != 0 && self.current <= self.threshold
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self.id {
+            1 => "Raw_Read_Error_Rate",
+            3 => "Spin_Up_Time",
+            4 => "Start_Stop_Count",
+            5 => "Reallocated_Sector_Ct",
+            7 => "Seek_Error_Rate",
+            9 => "Power_On_Hours",
+            10 => "Spin_Retry_Count",
+            12 => "Power_Cycle_Count",
+            177 => "Wear_Leveling_Count",
+            190 => "Airflow_Temperature",
+            194 => "Temperature_Celsius",
+            196 => "Reallocated_Event_Count",
+            197 => "Current_Pending_Sector",
+            198 => "Offline_Uncorrectable",
+            199 => "UDMA_CRC_Error_Count",
+            _ => "Unknown_Attribute",
+        }
+    }
+}
+
+/// A SMART data sector is valid when its bytes sum to zero
+pub fn checksum_ok(sector: &[u8; 512]) -> bool {
+    sector.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+/// The used entries of a SMART table
+fn entries(sector: &[u8; 512]) -> impl Iterator<Item = &[u8]> {
+    sector[TABLE_OFFSET..TABLE_OFFSET + TABLE_ENTRIES * ENTRY_SIZE]
+        .chunks_exact(ENTRY_SIZE)
+        .filter(|entry| entry[0] != 0)
+}
+
+/// The attribute table of SMART READ DATA, with the thresholds from SMART
+/// READ THRESHOLDS matched by id, or None if either fails its checksum
+pub fn attributes(data: &[u8; 512], thresholds: &[u8; 512]) -> Option<Vec<Attribute>> {
+    if !checksum_ok(data) || !checksum_ok(thresholds) {
+        return None;
+    }
+    let limits: Vec<&[u8]> = entries(thresholds).collect();
+    Some(entries(data).map(|e| Attribute {
+        id: e[0],
+        flags: u16::from_le_bytes([e[1], e[2]]),
+        current: e[3],
+        worst: e[4],
+        threshold: limits.iter().find(|t| t[0] == e[0]).map_or(0, |t| t[1]),
+        raw: (0..6).fold(0u64, |n, i| n | (e[5 + i] as u64) << (8 * i)),
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_string(words: &mut [u16], s: &str) {
+        let bytes: Vec<u8> = s.bytes().chain(core::iter::repeat(b' ')).take(words.len() * 2).collect();
+        for (word, pair) in words.iter_mut().zip(bytes.chunks(2)) {
+            *word = (pair[0] as u16) << 8 | pair[1] as u16;
+        }
+    }
+
+    fn seal(sector: &mut [u8; 512]) {
+        sector[511] = 0;
+        sector[511] = 0u8.wrapping_sub(sector.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)));
+    }
+
+    #[test_case]
+    fn test_identity() {
+        let mut words = [0u16; 256];
+        put_string(&mut words[27..47], "QEMU HARDDISK");
+        put_string(&mut words[10..20], "QM00001");
+        words[49] = 1 << 9 | 1 << 8;
+        words[60] = 0xFFFF;
+        words[61] = 0x0FFF;
+        words[82] = 1;
+        words[83] = 0x4000 | 1 << 10;
+        words[100] = 0x0000;
+        words[101] = 0x0001;
+        words[217] = 1;
+        let identity = Identity::parse(&words);
+        assert_eq!(identity.model, "QEMU HARDDISK");
+        assert_eq!(identity.serial, "QM00001");
+        assert_eq!(identity.sectors, 0x10000);
+        assert_eq!(identity.features(), ["lba48", "dma", "smart"]);
+        assert_eq!(identity.rotation_rate, Some(1));
+    }
+
+    #[test_case]
+    fn test_attributes() {
+        let mut data = [0u8; 512];
+        data[2..14].copy_from_slice(&[5, 0x33, 0, 90, 80, 7, 1, 0, 0, 0, 0, 0]);
+        data[14..26].copy_from_slice(&[9, 0x32, 0, 100, 100, 42, 0, 0, 0, 0, 0, 0]);
+        let mut thresholds = [0u8; 512];
+        thresholds[2..4].copy_from_slice(&[5, 95]);
+        seal(&mut data);
+        seal(&mut thresholds);
+
+        let attrs = attributes(&data, &thresholds).unwrap();
+        assert_eq!(attrs.len(), 2);
+        assert_eq!((attrs[0].raw, attrs[0].threshold), (263, 95));
+        assert!(attrs[0].prefail() && attrs[0].failing());
+        assert!(!attrs[1].prefail() && !attrs[1].failing());
+
+        data[100] ^= 1;
+        assert!(attributes(&data, &thresholds).is_none());
+    }
+}
@@ -1,13 +1,27 @@
 use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::Mutex;
 use lazy_static::lazy_static;
-use crate::hal::drivers::vga::WRITER;
+use x86_64::instructions::interrupts;
+use crate::fs::vfs::node::DeviceId;
+use crate::hal::drivers::vga::{Screen, WRITER};
+use super::device::{self, DeviceKind};
 use crate::print;
 
 const TTY_BUFFER_SIZE: usize = 4096;
 const MAX_LINE_LENGTH: usize = 256;
+/// Output kept for a virtual terminal while another is on the screen
+const BACKLOG_SIZE: usize = 4096;
+
+/// The console: /dev/console, /dev/stdin, /dev/stdout and /dev/stderr
+pub const CONSOLE: DeviceId = DeviceId::new(1, 0);
+/// Terminals: the virtual ones, /dev/tty1 to /dev/tty6, and from minor
+/// 64 the serial ones, /dev/ttyS0 and up, as Linux numbers them
+const TTY_MAJOR: u16 = 4;
+/// Virtual terminals, switched between with Alt+F1 to Alt+F6
+pub const VT_COUNT: usize = 6;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TtyMode {
@@ -45,11 +59,59 @@ impl Default for TerminalSettings {
     }
 }
 
+/// Reassembles UTF-8 text from bytes that may arrive split across writes.
+/// Malformed sequences come out as U+FFFD.
+#[derive(Debug, Default, Clone)]
+pub struct Utf8Decoder {
+    buf: [u8; 4],
+    len: usize,
+    need: usize,
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Self {
+        Utf8Decoder { buf: [0; 4], len: 0, need: 0 }
+    }
+
+    /// Feed one byte, passing each character it completes to `emit`
+    pub fn push(&mut self, byte: u8, emit: &mut impl FnMut(char)) {
+        if self.len > 0 {
+            if byte & 0xC0 == 0x80 {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                if self.len == self.need {
+                    let c = core::str::from_utf8(&self.buf[..self.len]).ok()
+                        .and_then(|s| s.chars().next())
+                        .unwrap_or(char::REPLACEMENT_CHARACTER);
+                    self.len = 0;
+                    emit(c);
+                }
+                return;
+            }
+            // Cut short by a byte that does not continue it
+            self.len = 0;
+            emit(char::REPLACEMENT_CHARACTER);
+        }
+        self.need = match byte {
+            0x00..=0x7F => return emit(byte as char),
+            0xC2..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF4 => 4,
+            _ => return emit(char::REPLACEMENT_CHARACTER),
+        };
+        self.buf[0] = byte;
+        self.len = 1;
+    }
+}
+
 pub struct Tty {
     pub id: usize,
     pub input_buffer: VecDeque<u8>,
     pub output_buffer: VecDeque<u8>,
     pub line_buffer: String,
+    /// Bytes of a character partly consumed from `line_buffer` by read_byte
+    pending: VecDeque<u8>,
+    decoder: Utf8Decoder,
     pub settings: TerminalSettings,
     pub mode: TtyMode,
     pub foreground_pid: Option<u32>,
@@ -57,6 +119,12 @@ pub struct Tty {
     pub cols: usize,
     pub cursor_row: usize,
     pub cursor_col: usize,
+    /// Whether it is the terminal on the screen
+    shown: bool,
+    /// What the screen showed when another terminal was switched to
+    screen: Option<Screen>,
+    /// Output since, put on the screen when it is switched back to
+    backlog: VecDeque<u8>,
 }
 
 impl Tty {
@@ -66,6 +134,8 @@ impl Tty {
             input_buffer: VecDeque::with_capacity(TTY_BUFFER_SIZE),
             output_buffer: VecDeque::with_capacity(TTY_BUFFER_SIZE),
             line_buffer: String::with_capacity(MAX_LINE_LENGTH),
+            pending: VecDeque::new(),
+            decoder: Utf8Decoder::new(),
             settings: TerminalSettings::default(),
             mode: TtyMode::Canonical,
             foreground_pid: None,
@@ -73,6 +143,9 @@ impl Tty {
             cols: 80,
             cursor_row: 0,
             cursor_col: 0,
+            shown: false,
+            screen: None,
+            backlog: VecDeque::new(),
         }
     }
     
@@ -82,15 +155,35 @@ impl Tty {
     }
     
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            self.output_buffer.push_back(byte);
-        }
+        self.write_bytes(s.as_bytes());
+    }
+    
+    /// Write UTF-8 that may be cut anywhere: a character split between
+    /// two writes is put back together
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.output_buffer.extend(bytes);
         self.flush_output();
     }
     
+    fn write_char(&mut self, c: char) {
+        let mut utf8 = [0u8; 4];
+        self.write_string(c.encode_utf8(&mut utf8));
+    }
+    
     pub fn flush_output(&mut self) {
+        let mut text = String::new();
         while let Some(byte) = self.output_buffer.pop_front() {
-            print!("{}", byte as char);
+            self.decoder.push(byte, &mut |c| text.push(c));
+        }
+        if text.is_empty() {
+            return;
+        }
+        if self.shown {
+            print!("{}", text);
+        } else {
+            self.backlog.extend(text.as_bytes());
+            let excess = self.backlog.len().saturating_sub(BACKLOG_SIZE);
+            self.backlog.drain(..excess);
         }
     }
     
@@ -100,15 +193,26 @@ impl Tty {
                 self.input_buffer.pop_front()
             }
             TtyMode::Canonical => {
-                if self.line_buffer.is_empty() {
-                    None
-                } else {
-                    Some(self.line_buffer.remove(0) as u8)
+                if self.pending.is_empty() && !self.line_buffer.is_empty() {
+                    let mut utf8 = [0u8; 4];
+                    self.pending.extend(self.line_buffer.remove(0).encode_utf8(&mut utf8).as_bytes());
                 }
+                self.pending.pop_front()
             }
         }
     }
     
+    /// The next whole line typed, without its newline
+    pub fn take_line(&mut self) -> Option<String> {
+        if self.mode != TtyMode::Canonical || !self.pending.is_empty() {
+            return None;
+        }
+        let end = self.line_buffer.find('\n')?;
+        let line = self.line_buffer[..end].into();
+        self.line_buffer.drain(..=end);
+        Some(line)
+    }
+
     pub fn read_line(&mut self) -> Option<String> {
         if self.mode != TtyMode::Canonical {
             return None;
@@ -126,7 +230,7 @@ impl Tty {
     pub fn handle_input(&mut self, c: char) {
         match self.mode {
             TtyMode::Raw => {
-                self.input_buffer.push_back(c as u8);
+                self.push_input(c);
             }
             TtyMode::Cbreak => {
                 if self.settings.signal_chars {
@@ -141,9 +245,9 @@ impl Tty {
                         return;
                     }
                 }
-                self.input_buffer.push_back(c as u8);
+                self.push_input(c);
                 if self.settings.echo {
-                    self.write_byte(c as u8);
+                    self.write_char(c);
                 }
             }
             TtyMode::Canonical => {
@@ -169,7 +273,7 @@ impl Tty {
                 }
                 
                 if c == self.settings.kill_char {
-                    let len = self.line_buffer.len();
+                    let len = self.line_buffer.chars().count();
                     self.line_buffer.clear();
                     if self.settings.echo {
                         for _ in 0..len {
@@ -187,17 +291,38 @@ impl Tty {
                     return;
                 }
                 
-                if self.line_buffer.len() < MAX_LINE_LENGTH {
+                if self.line_buffer.len() + c.len_utf8() <= MAX_LINE_LENGTH {
                     self.line_buffer.push(c);
                     if self.settings.echo {
-                        self.write_byte(c as u8);
+                        self.write_char(c);
                     }
                 }
             }
         }
     }
     
-    fn send_signal(&self, _signal: i32) {
+    /// Queue `c` for read_byte as its UTF-8 bytes
+    fn push_input(&mut self, c: char) {
+        let mut utf8 = [0u8; 4];
+        self.input_buffer.extend(c.encode_utf8(&mut utf8).as_bytes());
+    }
+    
+    /// The device a session has this terminal as: a virtual terminal's
+    /// own, or the console's
+    fn device(&self) -> DeviceId {
+        match self.id {
+            1..=VT_COUNT => vt_device(self.id),
+            _ => CONSOLE,
+        }
+    }
+
+    /// Signal the terminal's foreground group. Input arrives in interrupt
+    /// context, so if the scheduler is busy the character is dropped
+    /// rather than waited on.
+    fn send_signal(&self, signal: i32) {
+        if let Some(mut scheduler) = crate::kernel::scheduler::SCHEDULER.try_lock() {
+            scheduler.signal_foreground(self.device(), signal as u8);
+        }
     }
     
     pub fn set_mode(&mut self, mode: TtyMode) {
@@ -224,6 +349,7 @@ impl Tty {
             self.cursor_row = row;
             self.cursor_col = col;
             WRITER.lock().set_position(row, col);
+            crate::hal::drivers::vga::updated();
         }
     }
     
@@ -234,7 +360,7 @@ impl Tty {
     pub fn data_available(&self) -> bool {
         match self.mode {
             TtyMode::Raw | TtyMode::Cbreak => !self.input_buffer.is_empty(),
-            TtyMode::Canonical => self.line_buffer.contains('\n'),
+            TtyMode::Canonical => !self.pending.is_empty() || self.line_buffer.contains('\n'),
         }
     }
 }
@@ -245,28 +371,115 @@ lazy_static! {
         for i in 0..8 {
             ttys.push(Tty::new(i));
         }
+        ttys[1].shown = true;
         Mutex::new(ttys)
     };
     
-    static ref CURRENT_TTY: Mutex<usize> = Mutex::new(0);
+    static ref CURRENT_TTY: Mutex<usize> = Mutex::new(1);
+}
+
+/// A virtual terminal asked for from the keyboard, or 0
+static PENDING_SWITCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Register the console, whose major number /dev/stdin, /dev/stdout and
+/// /dev/stderr have, and the virtual terminals
+pub fn init() {
+    device::register("console", DeviceKind::Char, CONSOLE, "tty", None);
+    for n in 1..=VT_COUNT {
+        device::register(&alloc::format!("tty{}", n), DeviceKind::Char, vt_device(n), "vt", None);
+    }
+}
+
+/// The device number of virtual terminal `n`
+pub fn vt_device(n: usize) -> DeviceId {
+    DeviceId::new(TTY_MAJOR, n as u16)
+}
+
+/// Which virtual terminal the device `id` is, if it is one
+pub fn vt_of(id: DeviceId) -> Option<usize> {
+    match (id.major, id.minor as usize) {
+        (TTY_MAJOR, n @ 1..=VT_COUNT) => Some(n),
+        _ => None,
+    }
+}
+
+/// The terminal a device node with `id` opens, if it is one; the
+/// console's nodes are all the one terminal
+pub fn terminal(id: DeviceId) -> Option<DeviceId> {
+    match id.major {
+        1 => Some(CONSOLE),
+        TTY_MAJOR => Some(id),
+        _ => None,
+    }
 }
 
 pub fn get_current_tty() -> usize {
     *CURRENT_TTY.lock()
 }
 
+/// Put virtual terminal `id` on the screen. What the one there showed is
+/// kept for when it comes back, and what `id` was sent meanwhile is
+/// written out over its own.
 pub fn switch_tty(id: usize) {
-    let ttys = TTYS.lock();
-    if id < ttys.len() {
-        *CURRENT_TTY.lock() = id;
+    if !(1..=VT_COUNT).contains(&id) {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let mut ttys = TTYS.lock();
+        let mut current = CURRENT_TTY.lock();
+        if *current == id {
+            return;
+        }
+        let mut writer = WRITER.lock();
+        let old = &mut ttys[*current];
+        old.screen = Some(writer.save());
+        old.shown = false;
+        let new = &mut ttys[id];
+        match new.screen.take() {
+            Some(screen) => writer.restore(&screen),
+            None => writer.clear(),
+        }
+        let backlog: Vec<u8> = new.backlog.drain(..).collect();
+        writer.write_string(&String::from_utf8_lossy(&backlog));
+        new.shown = true;
+        *current = id;
+    });
+    crate::hal::drivers::vga::updated();
+}
+
+/// Ask for virtual terminal `id`, from the keyboard interrupt; the switch
+/// is made by switch_pending(), outside it, as it takes the screen
+pub fn request_switch(id: usize) {
+    PENDING_SWITCH.store(id, Ordering::Relaxed);
+}
+
+/// Make the switch last asked for, if any
+pub fn switch_pending() {
+    match PENDING_SWITCH.swap(0, Ordering::Relaxed) {
+        0 => {}
+        id => switch_tty(id),
     }
 }
 
 pub fn write_to_tty(id: usize, s: &str) {
+    write_bytes_to_tty(id, s.as_bytes());
+}
+
+/// Write UTF-8 bytes to a TTY; see Tty::write_bytes
+pub fn write_bytes_to_tty(id: usize, buf: &[u8]) {
     let mut ttys = TTYS.lock();
     if id < ttys.len() {
-        ttys[id].write_string(s);
+        ttys[id].write_bytes(buf);
+    }
+}
+
+/// Write to the system console: the serial port the shell runs on and the
+/// active TTY. The serial terminal gets the bytes as they are.
+pub fn write_console(buf: &[u8]) {
+    for &byte in buf {
+        super::serial::write_byte(byte);
     }
+    write_bytes_to_tty(get_current_tty(), buf);
 }
 
 pub fn read_from_tty(id: usize) -> Option<u8> {
@@ -278,6 +491,17 @@ pub fn read_from_tty(id: usize) -> Option<u8> {
     }
 }
 
+/// The next line typed on terminal `id`; see Tty::take_line
+pub fn take_line(id: usize) -> Option<String> {
+    TTYS.lock().get_mut(id).and_then(Tty::take_line)
+}
+
+pub fn set_tty_echo(id: usize, echo: bool) {
+    if let Some(tty) = TTYS.lock().get_mut(id) {
+        tty.set_echo(echo);
+    }
+}
+
 pub fn handle_tty_input(c: char) {
     let current = *CURRENT_TTY.lock();
     let mut ttys = TTYS.lock();
@@ -293,3 +517,36 @@ pub fn clear_current_tty() {
         ttys[current].clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_decoder_joins_split_characters() {
+        let mut decoder = Utf8Decoder::new();
+        let mut text = String::new();
+        for chunk in ["h\u{e9}".as_bytes(), &[0xE2, 0x95], &[0x94, b'!']] {
+            for &byte in chunk {
+                decoder.push(byte, &mut |c| text.push(c));
+            }
+        }
+        for &byte in &[0xC3, b'x', 0xFF] {
+            decoder.push(byte, &mut |c| text.push(c));
+        }
+        assert_eq!(text, "h\u{e9}\u{2554}!\u{fffd}x\u{fffd}");
+    }
+
+    #[test_case]
+    fn test_lines_and_output_off_screen() {
+        let mut tty = Tty::new(6);
+        for c in "ls\ncd /t\x7fetc\npw".chars() {
+            tty.handle_input(c);
+        }
+        assert_eq!(tty.take_line(), Some(String::from("ls")));
+        assert_eq!(tty.take_line(), Some(String::from("cd /etc")));
+        assert_eq!(tty.take_line(), None);
+        // The echo is kept for when it is switched to
+        assert_eq!(tty.backlog.iter().copied().collect::<Vec<u8>>(), b"ls\ncd /t\x08 \x08etc\npw");
+    }
+}
@@ -3,11 +3,32 @@ use core::fmt;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicBool, Ordering};
+use super::cp437;
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+pub const BUFFER_HEIGHT: usize = 25;
+pub const BUFFER_WIDTH: usize = 80;
 const VGA_BUFFER_ADDR: usize = 0xb8000;
 
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+const DAC_DATA: u16 = 0x3C9;
+/// The DAC entry each of the 16 text colors shows, as the attribute
+/// controller's palette registers are left at boot
+const DAC_INDEX: [u8; 16] = [0, 1, 2, 3, 4, 5, 20, 7, 56, 57, 58, 59, 60, 61, 62, 63];
+
+/// What the 16 text colors look like, as red, green and blue
+pub type Palette = [(u8, u8, u8); 16];
+
+/// The colors VGA starts with, in Color order
+pub const VGA_PALETTE: Palette = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xAA), (0x00, 0xAA, 0x00), (0x00, 0xAA, 0xAA),
+    (0xAA, 0x00, 0x00), (0xAA, 0x00, 0xAA), (0xAA, 0x55, 0x00), (0xAA, 0xAA, 0xAA),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xFF), (0x55, 0xFF, 0x55), (0x55, 0xFF, 0xFF),
+    (0xFF, 0x55, 0x55), (0xFF, 0x55, 0xFF), (0xFF, 0xFF, 0x55), (0xFF, 0xFF, 0xFF),
+];
+
+static PALETTE: Mutex<Palette> = Mutex::new(VGA_PALETTE);
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -52,6 +73,14 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// What the screen showed and where the cursor was, to be put back
+#[derive(Clone)]
+pub struct Screen {
+    chars: [[ScreenChar; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    row: usize,
+    col: usize,
+}
+
 pub struct Writer {
     column_position: usize,
     row_position: usize,
@@ -122,12 +151,17 @@ impl Writer {
         }
     }
 
+    pub fn write_char(&mut self, c: char) {
+        match c {
+            ' '..='~' | '\n' | '\r' | '\t' | '\x08' => self.write_byte(c as u8),
+            _ if c.is_control() => self.write_byte(cp437::FALLBACK),
+            _ => self.write_byte(cp437::from_char(c)),
+        }
+    }
+
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                0x20..=0x7e | b'\n' | b'\r' | b'\t' | 0x08 => self.write_byte(byte),
-                _ => self.write_byte(0xfe),
-            }
+        for c in s.chars() {
+            self.write_char(c);
         }
     }
 
@@ -169,6 +203,43 @@ impl Writer {
         self.color_code = ColorCode::new(foreground, background);
     }
 
+    /// The character byte and attribute (background color in the high
+    /// nibble, foreground in the low) at `row`, `col`
+    pub fn read_cell(&self, row: usize, col: usize) -> (u8, u8) {
+        let c = unsafe { self.read_screenchar_ptr(row, col) };
+        (c.ascii_character, c.color_code.0)
+    }
+
+    /// The character bytes of one screen row
+    pub fn read_row(&self, row: usize) -> [u8; BUFFER_WIDTH] {
+        let mut bytes = [0u8; BUFFER_WIDTH];
+        for (col, byte) in bytes.iter_mut().enumerate() {
+            *byte = unsafe { self.read_screenchar_ptr(row, col) }.ascii_character;
+        }
+        bytes
+    }
+
+    pub fn save(&self) -> Screen {
+        let blank = ScreenChar { ascii_character: b' ', color_code: self.color_code };
+        let mut chars = [[blank; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        for (row, line) in chars.iter_mut().enumerate() {
+            for (col, c) in line.iter_mut().enumerate() {
+                *c = unsafe { self.read_screenchar_ptr(row, col) };
+            }
+        }
+        Screen { chars, row: self.row_position, col: self.column_position }
+    }
+
+    pub fn restore(&mut self, screen: &Screen) {
+        for (row, line) in screen.chars.iter().enumerate() {
+            for (col, &c) in line.iter().enumerate() {
+                unsafe { self.write_screenchar_ptr(row, col, c) };
+            }
+        }
+        self.row_position = screen.row;
+        self.column_position = screen.col;
+    }
+
     pub fn get_position(&self) -> (usize, usize) {
         (self.row_position, self.column_position)
     }
@@ -238,20 +309,90 @@ lazy_static! {
     });
 }
 
+/// While set, `println!` goes to serial rather than the screen; the kernel
+/// log gets it either way. Boot sets it for `quiet` (see kernel::bootui).
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
     use x86_64::instructions::interrupts;
 
+    if QUIET.load(Ordering::Relaxed) {
+        super::serial::_print(args);
+        interrupts::without_interrupts(|| crate::kernel::klog::write_fmt(args));
+        return;
+    }
     interrupts::without_interrupts(|| {
         WRITER.lock().write_fmt(args).unwrap();
+        crate::kernel::klog::write_fmt(args);
+    });
+    updated();
+}
+
+/// Print on the screen even while quiet, in `foreground` if given
+pub fn print_always(args: fmt::Arguments, foreground: Option<Color>) {
+    use core::fmt::Write;
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let previous = writer.color_code;
+        if let Some(foreground) = foreground {
+            writer.color_code = ColorCode::new(foreground, Color::Black);
+        }
+        writer.write_fmt(args).unwrap();
+        writer.color_code = previous;
+        crate::kernel::klog::write_fmt(args);
     });
+    updated();
 }
 
 pub fn clear_screen() {
     x86_64::instructions::interrupts::without_interrupts(|| {
         WRITER.lock().clear();
     });
+    updated();
+}
+
+/// Show what was just written to WRITER other than by printing, on the
+/// framebuffer console if it is on; the text screen shows it already
+pub fn updated() {
+    #[cfg(feature = "framebuffer")]
+    super::fbcon::refresh();
+}
+
+/// Show the 16 text colors as `palette` says, from now on. Everything on
+/// the screen changes with it, as does the framebuffer console.
+pub fn set_palette(palette: Palette) {
+    *PALETTE.lock() = palette;
+    load_palette();
+    #[cfg(feature = "framebuffer")]
+    super::fbcon::redraw();
+}
+
+pub fn palette() -> Palette {
+    *PALETTE.lock()
+}
+
+/// Write the palette to the DAC again, as after a graphics mode used it
+pub fn load_palette() {
+    use x86_64::instructions::port::Port;
+
+    let palette = palette();
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        let (mut index, mut data) = (Port::<u8>::new(DAC_WRITE_INDEX), Port::<u8>::new(DAC_DATA));
+        for (&entry, &(r, g, b)) in DAC_INDEX.iter().zip(palette.iter()) {
+            index.write(entry);
+            // The DAC takes six bits a channel
+            data.write(r >> 2);
+            data.write(g >> 2);
+            data.write(b >> 2);
+        }
+    });
 }
 
 pub fn set_color(foreground: Color, background: Color) {
@@ -0,0 +1,336 @@
+// Lock dependency checking
+//
+// A cut-down lockdep for the kernel's big locks. Each is a
+// `lockdep::Mutex` of its own `Class`, and locks that are not, like the
+// heap's, are reported with `lockdep::held`. With the `lockdep` feature
+// every acquisition is checked before it spins, for
+//
+//   - a class taken again while this CPU already holds it,
+//   - an ordering inversion: B taken while holding A after A has been
+//     taken while holding B, directly or through other classes, and
+//   - a class taken in an interrupt handler that is also taken with
+//     interrupts enabled, where the handler can spin forever on a lock
+//     the code it interrupted holds.
+//
+// Each violation is reported once on the serial port, which no lock
+// holder can be interrupted in, and /proc/lockdep lists them with the
+// order seen so far. A try_lock cannot deadlock, so it is only recorded
+// as held. Without the feature a lockdep::Mutex is a spin::Mutex.
+
+use alloc::string::String;
+use core::fmt::{self, Write};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use crate::hal::cpu::context::{self, MAX_CPUS};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Class {
+    Scheduler,
+    Qsf,
+    Vfs,
+    Heap,
+    Frames,
+    PageTables,
+}
+
+const CLASSES: usize = 6;
+
+impl Class {
+    pub const ALL: [Class; CLASSES] = [
+        Class::Scheduler,
+        Class::Qsf,
+        Class::Vfs,
+        Class::Heap,
+        Class::Frames,
+        Class::PageTables,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Scheduler => "scheduler",
+            Class::Qsf => "qsf",
+            Class::Vfs => "vfs",
+            Class::Heap => "heap",
+            Class::Frames => "frames",
+            Class::PageTables => "page-tables",
+        }
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+pub struct Mutex<T> {
+    class: Class,
+    inner: spin::Mutex<T>,
+}
+
+pub struct MutexGuard<'a, T> {
+    _held: Held,
+    guard: spin::MutexGuard<'a, T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(class: Class, value: T) -> Self {
+        Mutex { class, inner: spin::Mutex::new(value) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        let held = held(self.class);
+        MutexGuard { _held: held, guard: self.inner.lock() }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let guard = self.inner.try_lock()?;
+        acquire(self.class, true);
+        Some(MutexGuard { _held: Held { class: self.class }, guard })
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// A class being held, until dropped
+pub struct Held {
+    class: Class,
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        release(self.class);
+    }
+}
+
+/// Check and record taking a lock of `class` that is not a
+/// lockdep::Mutex; call just before spinning on it
+pub fn held(class: Class) -> Held {
+    acquire(class, false);
+    Held { class }
+}
+
+pub fn enabled() -> bool {
+    cfg!(feature = "lockdep")
+}
+
+/// Classes held at once per context, at most; deeper ones go unchecked
+const MAX_HELD: usize = 8;
+/// Process and interrupt context hold locks separately on each CPU
+const CONTEXTS: usize = MAX_CPUS * 2;
+
+struct HeldStack {
+    classes: [AtomicU8; MAX_HELD],
+    depth: AtomicUsize,
+}
+
+static HELD: [HeldStack; CONTEXTS] = [const {
+    HeldStack { classes: [const { AtomicU8::new(0) }; MAX_HELD], depth: AtomicUsize::new(0) }
+}; CONTEXTS];
+/// Per class, the classes seen taken while it was held
+static AFTER: [AtomicU16; CLASSES] = [const { AtomicU16::new(0) }; CLASSES];
+/// Per class, whether it has been taken in an interrupt handler and with
+/// interrupts enabled
+static USAGE: [AtomicU8; CLASSES] = [const { AtomicU8::new(0) }; CLASSES];
+const USED_IN_IRQ: u8 = 1;
+const USED_IRQS_ON: u8 = 2;
+
+/// Violations reported so far, so that each is reported once
+static RECURSIVE: AtomicU16 = AtomicU16::new(0);
+static IRQ_UNSAFE: AtomicU16 = AtomicU16::new(0);
+static INVERTED: [AtomicU16; CLASSES] = [const { AtomicU16::new(0) }; CLASSES];
+static VIOLATIONS: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    Recursive(Class),
+    /// `taken` while holding `held`, against the order seen before
+    Inversion { held: Class, taken: Class },
+    IrqUnsafe(Class),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::Recursive(class) => write!(f, "{} taken while already held", class.name()),
+            Violation::Inversion { held, taken } => write!(
+                f,
+                "{} taken while holding {}, but {} has been taken while holding {}",
+                taken.name(), held.name(), held.name(), taken.name()
+            ),
+            Violation::IrqUnsafe(class) => {
+                write!(f, "{} taken in an interrupt handler and with interrupts enabled", class.name())
+            }
+        }
+    }
+}
+
+fn stack() -> &'static HeldStack {
+    &HELD[context::cpu() * 2 + context::in_interrupt() as usize]
+}
+
+/// Whether `to` has been taken while `from` was held, through any chain
+/// of classes, in `after` as AFTER holds it
+fn reaches(after: &[u16; CLASSES], from: Class, to: Class) -> bool {
+    let (mut seen, mut frontier) = (0u16, from.bit());
+    while frontier != 0 {
+        let class = frontier.trailing_zeros() as usize;
+        frontier &= frontier - 1;
+        if seen & 1 << class != 0 {
+            continue;
+        }
+        seen |= 1 << class;
+        if after[class] & to.bit() != 0 {
+            return true;
+        }
+        frontier |= after[class] & !seen;
+    }
+    false
+}
+
+fn check(class: Class, stack: &HeldStack) {
+    let depth = stack.depth.load(Ordering::Relaxed).min(MAX_HELD);
+    for held in stack.classes[..depth].iter().map(|held| Class::ALL[held.load(Ordering::Relaxed) as usize]) {
+        if held == class {
+            report(Violation::Recursive(class));
+        } else {
+            let after = AFTER.each_ref().map(|after| after.load(Ordering::Relaxed));
+            if reaches(&after, class, held) {
+                report(Violation::Inversion { held, taken: class });
+            }
+            AFTER[held as usize].fetch_or(class.bit(), Ordering::Relaxed);
+        }
+    }
+
+    let usage = if context::in_interrupt() {
+        USED_IN_IRQ
+    } else if crate::hal::cpu::interrupts::are_enabled() {
+        USED_IRQS_ON
+    } else {
+        0
+    };
+    let usage = USAGE[class as usize].fetch_or(usage, Ordering::Relaxed) | usage;
+    if usage == USED_IN_IRQ | USED_IRQS_ON {
+        report(Violation::IrqUnsafe(class));
+    }
+}
+
+fn acquire(class: Class, trylock: bool) {
+    if !enabled() {
+        return;
+    }
+    let stack = stack();
+    if !trylock {
+        check(class, stack);
+    }
+    let depth = stack.depth.fetch_add(1, Ordering::Relaxed);
+    if depth < MAX_HELD {
+        stack.classes[depth].store(class as u8, Ordering::Relaxed);
+    }
+}
+
+fn release(class: Class) {
+    if !enabled() {
+        return;
+    }
+    let stack = stack();
+    let depth = stack.depth.load(Ordering::Relaxed);
+    if depth == 0 {
+        return;
+    }
+    // Locks need not be released in the order they were taken: drop the
+    // latest entry for the class and close the gap
+    let top = depth.min(MAX_HELD);
+    if let Some(at) = (0..top).rev().find(|&i| stack.classes[i].load(Ordering::Relaxed) == class as u8) {
+        for i in at..top - 1 {
+            stack.classes[i].store(stack.classes[i + 1].load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+    stack.depth.store(depth - 1, Ordering::Relaxed);
+}
+
+fn report(violation: Violation) {
+    let (reported, bit) = match violation {
+        Violation::Recursive(class) => (&RECURSIVE, class.bit()),
+        Violation::Inversion { held, taken } => (&INVERTED[held as usize], taken.bit()),
+        Violation::IrqUnsafe(class) => (&IRQ_UNSAFE, class.bit()),
+    };
+    if reported.fetch_or(bit, Ordering::Relaxed) & bit != 0 {
+        return;
+    }
+    VIOLATIONS.fetch_add(1, Ordering::Relaxed);
+    crate::serial_println!("lockdep: {}", violation);
+}
+
+/// Every violation reported so far
+pub fn violations() -> impl Iterator<Item = Violation> {
+    let recursive = RECURSIVE.load(Ordering::Relaxed);
+    let irq_unsafe = IRQ_UNSAFE.load(Ordering::Relaxed);
+    Class::ALL.into_iter().flat_map(move |class| {
+        let inverted = INVERTED[class as usize].load(Ordering::Relaxed);
+        let inversions = Class::ALL.into_iter()
+            .filter(move |taken| inverted & taken.bit() != 0)
+            .map(move |taken| Violation::Inversion { held: class, taken });
+        (recursive & class.bit() != 0).then_some(Violation::Recursive(class)).into_iter()
+            .chain((irq_unsafe & class.bit() != 0).then_some(Violation::IrqUnsafe(class)))
+            .chain(inversions)
+    })
+}
+
+/// The order and interrupt use seen for each class, then the violations,
+/// for /proc/lockdep
+pub fn dump() -> String {
+    let mut text = String::new();
+    if !enabled() {
+        writeln!(text, "# lockdep: off (build with the lockdep feature)").ok();
+        return text;
+    }
+    writeln!(text, "# lockdep: on, {} violations", VIOLATIONS.load(Ordering::Relaxed)).ok();
+    writeln!(text, "# class        in-irq  irqs-on  taken after").ok();
+    for class in Class::ALL {
+        let usage = USAGE[class as usize].load(Ordering::Relaxed);
+        let after = AFTER[class as usize].load(Ordering::Relaxed);
+        let yes_no = |bit| if usage & bit != 0 { "yes" } else { "no" };
+        write!(text, "{:<14} {:<7} {:<8}", class.name(), yes_no(USED_IN_IRQ), yes_no(USED_IRQS_ON)).ok();
+        for next in Class::ALL.into_iter().filter(|next| after & next.bit() != 0) {
+            write!(text, " {}", next.name()).ok();
+        }
+        text.push('\n');
+    }
+    for violation in violations() {
+        writeln!(text, "{}", violation).ok();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_reaches_through_chain() {
+        let mut after = [0; CLASSES];
+        after[Class::Scheduler as usize] = Class::Qsf.bit();
+        after[Class::Qsf as usize] = Class::Vfs.bit() | Class::Scheduler.bit();
+        assert!(reaches(&after, Class::Scheduler, Class::Vfs));
+        assert!(!reaches(&after, Class::Vfs, Class::Scheduler));
+        assert!(!reaches(&after, Class::Heap, Class::Vfs));
+    }
+
+    #[test_case]
+    fn test_violation_text() {
+        let text = alloc::format!("{}", Violation::Inversion { held: Class::Vfs, taken: Class::Scheduler });
+        assert_eq!(text, "scheduler taken while holding vfs, but vfs has been taken while holding scheduler");
+    }
+}
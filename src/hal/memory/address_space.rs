@@ -0,0 +1,217 @@
+// Per-process address spaces
+//
+// A user program gets a level 4 page table of its own. Its upper entries
+// are copied from the kernel's, so every kernel mapping - code, heap,
+// physical memory - is there too, shared and out of reach of ring 3 as
+// none of it is USER_ACCESSIBLE. The program's own pages live in the
+// window USER_BASE..USER_END, whose level 4 entries the kernel leaves
+// empty; they are mapped to fresh frames with tables of their own, so
+// nothing mapped there shows in the kernel's tables or another process's.
+//
+// Kernel mappings made after an address space was created under a level
+// 4 entry that was empty then are not seen by it. The boot mappings, the
+// heap and the mmap window all have theirs by the time a program runs.
+// Frames are not given back when an address space goes, as the boot
+// frame allocator takes none back.
+
+use x86_64::structures::paging::mapper::{Translate, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::VirtAddr;
+use core::ops::Range;
+use spin::Mutex;
+use super::frame_allocator::FRAME_ALLOCATOR;
+use super::mmu::{ProtectionFlags, PAGE_SIZE};
+use super::paging;
+
+/// Where user programs are mapped: level 4 entries 128 to 135, below the
+/// kernel heap
+pub const USER_BASE: u64 = 0x0000_4000_0000_0000;
+pub const USER_END: u64 = 0x0000_4400_0000_0000;
+
+/// The user stack ends at the top of the window
+pub const USER_STACK_TOP: u64 = USER_END;
+pub const USER_STACK_SIZE: u64 = 64 * 1024;
+
+/// Where mmap(2) puts a program's anonymous memory: the top half of the
+/// window, below the stack. The program break grows up to it.
+pub const USER_MMAP_BASE: u64 = 0x0000_4200_0000_0000;
+pub const USER_MMAP_END: u64 = USER_STACK_TOP - USER_STACK_SIZE;
+
+/// The kernel's level 4 table, the one the bootloader left in CR3
+static KERNEL_PML4: Mutex<Option<PhysFrame>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpaceError {
+    /// No frames left for pages or page tables
+    NoMemory,
+    /// Outside the user window, or mapped already
+    Invalid,
+}
+
+/// Remember the kernel's page tables, before any address space is made
+pub fn init() {
+    *KERNEL_PML4.lock() = Some(Cr3::read().0);
+}
+
+/// Load the level 4 table at `pml4` into CR3
+pub fn activate(pml4: PhysFrame) {
+    if Cr3::read().0 != pml4 {
+        unsafe { Cr3::write(pml4, Cr3Flags::empty()) };
+    }
+}
+
+/// Go back to the kernel's page tables
+pub fn activate_kernel() {
+    if let Some(frame) = *KERNEL_PML4.lock() {
+        activate(frame);
+    }
+}
+
+fn in_window(start: u64, len: u64) -> bool {
+    start >= USER_BASE && start.checked_add(len).is_some_and(|end| end <= USER_END)
+}
+
+/// Page aligned and in the window
+fn check(start: u64, len: u64) -> Result<(), AddressSpaceError> {
+    if !start.is_multiple_of(PAGE_SIZE as u64) || !len.is_multiple_of(PAGE_SIZE as u64) || !in_window(start, len) {
+        return Err(AddressSpaceError::Invalid);
+    }
+    Ok(())
+}
+
+fn table_at(frame: PhysFrame) -> &'static mut PageTable {
+    let virt = paging::phys_to_virt(frame.start_address()).expect("Paging not initialized");
+    unsafe { &mut *virt.as_mut_ptr::<PageTable>() }
+}
+
+fn zero_frame(frame: PhysFrame) {
+    let virt = paging::phys_to_virt(frame.start_address()).expect("Paging not initialized");
+    unsafe { core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, PAGE_SIZE) };
+}
+
+#[derive(Debug)]
+pub struct AddressSpace {
+    pml4: PhysFrame,
+}
+
+impl AddressSpace {
+    /// A new address space with the kernel mapped and nothing else
+    pub fn new() -> Result<Self, AddressSpaceError> {
+        let kernel = KERNEL_PML4.lock().ok_or(AddressSpaceError::NoMemory)?;
+        let pml4 = FRAME_ALLOCATOR.lock().as_mut()
+            .and_then(|frames| frames.allocate_frame())
+            .ok_or(AddressSpaceError::NoMemory)?;
+
+        let (kernel, table) = (table_at(kernel), table_at(pml4));
+        table.zero();
+        let window: Range<usize> = VirtAddr::new(USER_BASE).p4_index().into()..VirtAddr::new(USER_END).p4_index().into();
+        for index in 0..512 {
+            if !window.contains(&index) {
+                table[index] = kernel[index].clone();
+            }
+        }
+        Ok(AddressSpace { pml4 })
+    }
+
+    /// The frame of the level 4 table, for CR3
+    pub fn frame(&self) -> PhysFrame {
+        self.pml4
+    }
+
+    fn mapper(&self) -> OffsetPageTable<'static> {
+        let offset = paging::get_physical_memory_offset().expect("Paging not initialized");
+        unsafe { OffsetPageTable::new(table_at(self.pml4), offset) }
+    }
+
+    /// Map `len` bytes at `start` to fresh, zeroed frames reachable from
+    /// ring 3 with `prot`. Both must be page aligned. A page mapped already,
+    /// as one two ELF segments share, keeps its frame and gains `prot`.
+    pub fn map(&self, start: u64, len: u64, prot: ProtectionFlags) -> Result<(), AddressSpaceError> {
+        check(start, len)?;
+        let flags = (prot | ProtectionFlags::USER).to_page_table_flags();
+        let tables = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let mut mapper = self.mapper();
+        let mut frames = FRAME_ALLOCATOR.lock();
+        let frames = frames.as_mut().ok_or(AddressSpaceError::NoMemory)?;
+        for offset in (0..len).step_by(PAGE_SIZE) {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(start + offset));
+            if let TranslateResult::Mapped { flags: old, .. } = mapper.translate(page.start_address()) {
+                let merged = (old | flags) & !(PageTableFlags::NO_EXECUTE & !flags);
+                let flush = unsafe { mapper.update_flags(page, merged) }.map_err(|_| AddressSpaceError::Invalid)?;
+                flush.ignore();
+                continue;
+            }
+            let frame = frames.allocate_frame().ok_or(AddressSpaceError::NoMemory)?;
+            zero_frame(frame);
+            let flush = unsafe { mapper.map_to_with_table_flags(page, frame, flags, tables, frames) }
+                .map_err(|_| AddressSpaceError::Invalid)?;
+            // Not the active tables, unless this space is loaded
+            flush.ignore();
+        }
+        self.flush();
+        Ok(())
+    }
+
+    /// Unmap the pages of `len` bytes at `start`, skipping those not
+    /// mapped. Their frames are not given back.
+    pub fn unmap(&self, start: u64, len: u64) -> Result<(), AddressSpaceError> {
+        check(start, len)?;
+        let mut mapper = self.mapper();
+        for offset in (0..len).step_by(PAGE_SIZE) {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(start + offset));
+            if let Ok((_, flush)) = mapper.unmap(page) {
+                flush.ignore();
+            }
+        }
+        self.flush();
+        Ok(())
+    }
+
+    /// Give the pages mapped in `len` bytes at `start` `prot`
+    pub fn protect(&self, start: u64, len: u64, prot: ProtectionFlags) -> Result<(), AddressSpaceError> {
+        check(start, len)?;
+        let flags = (prot | ProtectionFlags::USER).to_page_table_flags();
+        let mut mapper = self.mapper();
+        for offset in (0..len).step_by(PAGE_SIZE) {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(start + offset));
+            if let Ok(flush) = unsafe { mapper.update_flags(page, flags) } {
+                flush.ignore();
+            }
+        }
+        self.flush();
+        Ok(())
+    }
+
+    /// Drop stale translations, if this space is loaded
+    fn flush(&self) {
+        if Cr3::read().0 == self.pml4 {
+            paging::flush_tlb();
+        }
+    }
+
+    /// Copy `data` to `start`, which must be mapped, through the kernel's
+    /// view of physical memory rather than the user mapping
+    pub fn write(&self, start: u64, data: &[u8]) -> Result<(), AddressSpaceError> {
+        if !in_window(start, data.len() as u64) {
+            return Err(AddressSpaceError::Invalid);
+        }
+        let mapper = self.mapper();
+        let mut done = 0;
+        while done < data.len() {
+            let at = start + done as u64;
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(at));
+            let frame = mapper.translate_page(page).map_err(|_| AddressSpaceError::Invalid)?;
+            let within = (at % PAGE_SIZE as u64) as usize;
+            let n = (PAGE_SIZE - within).min(data.len() - done);
+            let virt = paging::phys_to_virt(frame.start_address()).ok_or(AddressSpaceError::Invalid)?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(data[done..].as_ptr(), virt.as_mut_ptr::<u8>().add(within), n);
+            }
+            done += n;
+        }
+        Ok(())
+    }
+}
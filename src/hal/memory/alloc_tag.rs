@@ -0,0 +1,188 @@
+// Allocation subsystem tags
+//
+// Every heap allocation and page frame is charged to the subsystem that
+// was running when it was made: the filesystems, the scheduler, the
+// network stack, the drivers, or the rest of the kernel. Code takes the
+// charge with `alloc_tag!(Fs)` at the top of a scope; the tag lasts until
+// the scope ends and then the previous one applies again, so tags nest.
+//
+// Frames are counted in every build. Heap bytes need the `heap-debug`
+// feature, which keeps the tag in each block's header so a free credits
+// the subsystem that made the allocation rather than the one freeing it.
+// `memtop` shows the totals.
+
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Subsystem {
+    Kernel,
+    Fs,
+    Sched,
+    Net,
+    Driver,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 5] = [
+        Subsystem::Kernel,
+        Subsystem::Fs,
+        Subsystem::Sched,
+        Subsystem::Net,
+        Subsystem::Driver,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::Kernel => "kernel",
+            Subsystem::Fs => "fs",
+            Subsystem::Sched => "sched",
+            Subsystem::Net => "net",
+            Subsystem::Driver => "driver",
+        }
+    }
+
+    /// The tag stored in a block header; anything unknown is the kernel's
+    pub fn from_raw(raw: u8) -> Subsystem {
+        Subsystem::ALL.get(raw as usize).copied().unwrap_or(Subsystem::Kernel)
+    }
+}
+
+/// Take the charge for allocations until the end of the enclosing scope,
+/// e.g. `alloc_tag!(Fs)`
+#[macro_export]
+macro_rules! alloc_tag {
+    ($sub:ident) => {
+        let _alloc_tag = $crate::hal::memory::alloc_tag::AllocTag::enter(
+            $crate::hal::memory::alloc_tag::Subsystem::$sub,
+        );
+    };
+}
+
+/// Charges allocations to a subsystem while it is alive, restoring the
+/// previous tag when dropped
+pub struct AllocTag {
+    prev: u8,
+}
+
+impl AllocTag {
+    pub fn enter(sub: Subsystem) -> Self {
+        AllocTag { prev: CURRENT.swap(sub as u8, Ordering::Relaxed) }
+    }
+}
+
+impl Drop for AllocTag {
+    fn drop(&mut self) {
+        CURRENT.store(self.prev, Ordering::Relaxed);
+    }
+}
+
+struct Counters {
+    live_bytes: AtomicUsize,
+    live: AtomicU64,
+    allocations: AtomicU64,
+    frames: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Counters {
+        Counters {
+            live_bytes: AtomicUsize::new(0),
+            live: AtomicU64::new(0),
+            allocations: AtomicU64::new(0),
+            frames: AtomicU64::new(0),
+        }
+    }
+}
+
+static CURRENT: AtomicU8 = AtomicU8::new(Subsystem::Kernel as u8);
+static COUNTERS: [Counters; Subsystem::ALL.len()] = [const { Counters::new() }; Subsystem::ALL.len()];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Usage {
+    pub subsystem: Subsystem,
+    pub live_bytes: usize,
+    pub live: u64,
+    pub allocations: u64,
+    pub frames: u64,
+}
+
+pub fn current() -> Subsystem {
+    Subsystem::from_raw(CURRENT.load(Ordering::Relaxed))
+}
+
+/// Charge a heap allocation of `size` bytes to the current subsystem,
+/// returning the tag to store with it
+pub fn charge(size: usize) -> u8 {
+    let tag = CURRENT.load(Ordering::Relaxed);
+    let counters = &COUNTERS[Subsystem::from_raw(tag) as usize];
+    counters.live_bytes.fetch_add(size, Ordering::Relaxed);
+    counters.live.fetch_add(1, Ordering::Relaxed);
+    counters.allocations.fetch_add(1, Ordering::Relaxed);
+    tag
+}
+
+/// Credit a freed allocation back to the subsystem `tag` charged it to
+pub fn credit(tag: u8, size: usize) {
+    let counters = &COUNTERS[Subsystem::from_raw(tag) as usize];
+    counters.live_bytes.fetch_sub(size, Ordering::Relaxed);
+    counters.live.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// Charge a page frame to the current subsystem
+pub fn charge_frame() {
+    COUNTERS[current() as usize].frames.fetch_add(1, Ordering::Relaxed);
+}
+
+/// What each subsystem holds, in `Subsystem::ALL` order
+pub fn usage() -> [Usage; Subsystem::ALL.len()] {
+    Subsystem::ALL.map(|subsystem| {
+        let counters = &COUNTERS[subsystem as usize];
+        Usage {
+            subsystem,
+            live_bytes: counters.live_bytes.load(Ordering::Relaxed),
+            live: counters.live.load(Ordering::Relaxed),
+            allocations: counters.allocations.load(Ordering::Relaxed),
+            frames: counters.frames.load(Ordering::Relaxed),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_tags_nest() {
+        let outer = current();
+        {
+            crate::alloc_tag!(Fs);
+            assert_eq!(current(), Subsystem::Fs);
+            {
+                crate::alloc_tag!(Net);
+                assert_eq!(current(), Subsystem::Net);
+            }
+            assert_eq!(current(), Subsystem::Fs);
+        }
+        assert_eq!(current(), outer);
+    }
+
+    #[test_case]
+    fn test_charge_and_credit() {
+        let before = usage()[Subsystem::Driver as usize];
+        let tag = {
+            crate::alloc_tag!(Driver);
+            charge(100)
+        };
+        assert_eq!(Subsystem::from_raw(tag), Subsystem::Driver);
+        let charged = usage()[Subsystem::Driver as usize];
+        assert_eq!(charged.live_bytes, before.live_bytes + 100);
+        assert_eq!(charged.allocations, before.allocations + 1);
+
+        credit(tag, 100);
+        let credited = usage()[Subsystem::Driver as usize];
+        assert_eq!(credited.live_bytes, before.live_bytes);
+        assert_eq!(credited.live, before.live);
+        assert_eq!(Subsystem::from_raw(200), Subsystem::Kernel);
+    }
+}
@@ -1,9 +1,21 @@
 use x86_64::structures::paging::{FrameAllocator, PhysFrame, Size4KiB};
 use x86_64::PhysAddr;
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::ops::Range;
 use spin::Mutex;
+use crate::hal::lockdep::{self, Class};
 use lazy_static::lazy_static;
 
+/// Physical memory withheld from allocation, set aside before the first
+/// frame is handed out
+static RESERVED: Mutex<Option<Range<u64>>> = Mutex::new(None);
+
+/// Keep the frames of `range` out of the boot frame allocators. Must run
+/// before either of them allocates anything.
+pub fn reserve(range: Range<u64>) {
+    *RESERVED.lock() = Some(range);
+}
+
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
@@ -18,7 +30,9 @@ impl BootInfoFrameAllocator {
         let regions = self.memory_map.iter();
         let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
         let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
+        let reserved = RESERVED.lock().clone().unwrap_or(0..0);
+        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096))
+            .filter(move |addr| !reserved.contains(addr));
         frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
     }
 
@@ -33,19 +47,78 @@ impl BootInfoFrameAllocator {
     pub fn used_frames(&self) -> usize {
         self.next
     }
+
+    /// Every frame the kernel has something in: those handed out so far,
+    /// and those the bootloader put the kernel, its stack, the page tables
+    /// and the boot info in
+    pub fn in_use_frames(&self) -> impl Iterator<Item = PhysFrame> + '_ {
+        let loaded = self.memory_map.iter()
+            .filter(|r| matches!(r.region_type,
+                MemoryRegionType::InUse | MemoryRegionType::Kernel | MemoryRegionType::KernelStack
+                | MemoryRegionType::PageTable | MemoryRegionType::BootInfo | MemoryRegionType::Package))
+            .flat_map(|r| (r.range.start_addr()..r.range.end_addr()).step_by(4096))
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)));
+        self.usable_frames().take(self.next).chain(loaded)
+    }
+
+    /// Take back the last `count` frames handed out, which nothing may
+    /// still be using
+    pub fn give_back(&mut self, count: usize) {
+        self.next -= count.min(self.next);
+    }
+
+    /// `count` frames one after another in physical memory, the first of
+    /// which is returned. Frames skipped to find them are lost.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        let mut run: Option<(PhysFrame, usize)> = None;
+        for (index, frame) in self.usable_frames().enumerate().skip(self.next) {
+            run = match run {
+                Some((first, len)) if first + len as u64 == frame => Some((first, len + 1)),
+                _ => Some((frame, 1)),
+            };
+            if let Some((first, len)) = run.filter(|&(_, len)| len == count) {
+                self.next = index + 1;
+                for _ in 0..len {
+                    super::alloc_tag::charge_frame();
+                }
+                return Some(first);
+            }
+        }
+        None
+    }
+
+    pub fn memory_map(&self) -> &'static MemoryMap {
+        self.memory_map
+    }
+
+    /// Whether any of `range` is RAM, as opposed to device memory or a
+    /// hole: usable, or already given to the kernel, the bootloader or
+    /// firmware tables
+    pub fn is_ram(&self, range: Range<u64>) -> bool {
+        self.memory_map.iter()
+            .filter(|r| matches!(r.region_type,
+                MemoryRegionType::Usable | MemoryRegionType::InUse | MemoryRegionType::Kernel
+                | MemoryRegionType::KernelStack | MemoryRegionType::PageTable | MemoryRegionType::Bootloader
+                | MemoryRegionType::FrameZero | MemoryRegionType::BootInfo | MemoryRegionType::Package
+                | MemoryRegionType::AcpiReclaimable))
+            .any(|r| r.range.start_addr() < range.end && range.start < r.range.end_addr())
+    }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
+        if frame.is_some() {
+            super::alloc_tag::charge_frame();
+        }
         frame
     }
 }
 
 /// Global frame allocator - initialized during boot
 lazy_static! {
-    pub static ref FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+    pub static ref FRAME_ALLOCATOR: lockdep::Mutex<Option<BootInfoFrameAllocator>> = lockdep::Mutex::new(Class::Frames, None);
 }
 
 /// Initialize frame allocator from boot info memory map
@@ -57,6 +130,12 @@ pub fn init_from_boot_info(memory_map: &'static MemoryMap) {
     }
 }
 
+/// Whether any of `range` is RAM; all of it is taken to be before the
+/// allocator is set up
+pub fn is_ram(range: Range<u64>) -> bool {
+    FRAME_ALLOCATOR.lock().as_ref().map_or(true, |frames| frames.is_ram(range))
+}
+
 pub struct BitmapFrameAllocator {
     bitmap: alloc::vec::Vec<u64>,
     base_frame: u64,
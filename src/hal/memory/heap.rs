@@ -1,16 +1,38 @@
+// Kernel heap
+//
+// The heap starts as HEAP_SIZE bytes mapped at boot at HEAP_START. When an
+// allocation does not fit, fresh frames are mapped straight after the top
+// of the heap and handed to the allocator before it gives up, so the heap
+// grows through the virtual range reserved for it up to a cap:
+// HEAP_MAX_DEFAULT, or `heap.max=MIB` on the kernel command line. The
+// heap never shrinks. /proc/meminfo shows its size and cap.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use x86_64::{
     structures::paging::{
         mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
     },
     VirtAddr,
 };
-use linked_list_allocator::LockedHeap;
+use super::heap_debug::HardenedHeap;
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 8 * 1024 * 1024;
+/// Virtual space above HEAP_START kept free for the heap to grow into
+pub const HEAP_RESERVE: usize = 1024 * 1024 * 1024;
+pub const HEAP_MAX_DEFAULT: usize = 64 * 1024 * 1024;
+/// The heap grows by at least this much at a time
+const GROW_STEP: usize = 1024 * 1024;
+const MAX_PARAM: &str = "heap.max";
+
+/// Bytes mapped at HEAP_START and given to the allocator
+static MAPPED: AtomicUsize = AtomicUsize::new(HEAP_SIZE);
+static MAX: AtomicUsize = AtomicUsize::new(HEAP_MAX_DEFAULT);
+static GROWING: AtomicBool = AtomicBool::new(false);
+static GROWTHS: AtomicU64 = AtomicU64::new(0);
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: HardenedHeap = HardenedHeap::empty();
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -35,22 +57,78 @@ pub fn init_heap(
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START as *mut u8, HEAP_SIZE);
+        ALLOCATOR.inner().lock().init(HEAP_START as *mut u8, HEAP_SIZE);
     }
 
     Ok(())
 }
 
+/// Read `heap.max=` for the growth cap. Run once the heap and the kernel's
+/// page tables are up.
+pub fn init_growth() {
+    let value = match crate::kernel::get_param(MAX_PARAM) {
+        Some(value) => value,
+        None => return,
+    };
+    match value.parse::<usize>() {
+        Ok(mib) => {
+            let max = (mib * 1024 * 1024).clamp(HEAP_SIZE, HEAP_RESERVE);
+            MAX.store(max, Ordering::Relaxed);
+            crate::println!("  [HAL] Heap may grow to {} MiB", max / (1024 * 1024));
+        }
+        Err(_) => crate::println!("  [HAL] {}={}: expected MiB, heap capped at {} MiB",
+            MAX_PARAM, value, HEAP_MAX_DEFAULT / (1024 * 1024)),
+    }
+}
+
+/// Map at least `need` more bytes onto the top of the heap, within the
+/// cap. Returns whether the heap grew; the allocator retries if it did.
+pub(super) fn grow(need: usize) -> bool {
+    // One growth at a time, and none from within one
+    if GROWING.swap(true, Ordering::Acquire) {
+        return false;
+    }
+    let mapped = MAPPED.load(Ordering::Relaxed);
+    let room = MAX.load(Ordering::Relaxed).saturating_sub(mapped);
+    let want = (need.div_ceil(GROW_STEP) * GROW_STEP).min(room);
+    let grown = match want {
+        0 => 0,
+        want => {
+            let top = VirtAddr::new((HEAP_START + mapped) as u64);
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+            super::paging::try_map_fresh(top, want as u64, flags) as usize
+        }
+    };
+    if grown > 0 {
+        unsafe { ALLOCATOR.inner().lock().extend(grown) };
+        MAPPED.store(mapped + grown, Ordering::Relaxed);
+        GROWTHS.fetch_add(1, Ordering::Relaxed);
+    }
+    GROWING.store(false, Ordering::Release);
+    grown > 0
+}
+
 pub fn heap_used() -> usize {
-    ALLOCATOR.lock().used()
+    ALLOCATOR.inner().lock().used()
 }
 
 pub fn heap_free() -> usize {
-    ALLOCATOR.lock().free()
+    ALLOCATOR.inner().lock().free()
 }
 
+/// Bytes the heap currently spans
 pub fn heap_size() -> usize {
-    HEAP_SIZE
+    MAPPED.load(Ordering::Relaxed)
+}
+
+/// Bytes the heap may grow to
+pub fn heap_max() -> usize {
+    MAX.load(Ordering::Relaxed)
+}
+
+/// Times the heap has grown since boot
+pub fn heap_growths() -> u64 {
+    GROWTHS.load(Ordering::Relaxed)
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -58,17 +136,31 @@ pub struct HeapStats {
     pub total: usize,
     pub used: usize,
     pub free: usize,
+    pub max: usize,
 }
 
 pub fn get_heap_stats() -> HeapStats {
-    let allocator = ALLOCATOR.lock();
+    let allocator = ALLOCATOR.inner().lock();
     HeapStats {
-        total: HEAP_SIZE,
+        total: heap_size(),
         used: allocator.used(),
         free: allocator.free(),
+        max: heap_max(),
     }
 }
 
+/// Heap stats unless the allocator is locked, as it may be when a panic
+/// interrupts it
+pub fn try_heap_stats() -> Option<HeapStats> {
+    let allocator = ALLOCATOR.inner().try_lock()?;
+    Some(HeapStats {
+        total: heap_size(),
+        used: allocator.used(),
+        free: allocator.free(),
+        max: heap_max(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,4 +202,13 @@ mod tests {
         }
         assert_eq!(*long_lived, 1);
     }
+
+    #[test_case]
+    fn grows_past_initial_size() {
+        let before = heap_size();
+        let big: Vec<u8> = alloc::vec![1; HEAP_SIZE];
+        assert!(heap_size() > before);
+        assert!(heap_size() <= heap_max());
+        assert_eq!(big.iter().map(|&b| b as usize).sum::<usize>(), HEAP_SIZE);
+    }
 }
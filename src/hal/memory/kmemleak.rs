@@ -0,0 +1,206 @@
+// Conservative kernel memory leak scanner, in the spirit of Linux kmemleak.
+//
+// Every tracked allocation (see `heap_debug`) is a candidate. Roots are the
+// kernel's data and bss, the boot stack, and all heap memory outside tracked
+// blocks (allocations made before tracking started, task stacks, ...). Any
+// aligned word that points into a candidate marks it reachable, and marked
+// blocks are scanned in turn. Whatever is left unmarked has no reference
+// anywhere and is reported as a leak. Being conservative, integers that
+// happen to look like pointers can hide a leak, but never invent one.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use super::heap::{HEAP_START, heap_size};
+use super::heap_debug::{self, AllocRecord};
+
+extern "C" {
+    // Provided by the linker: end of text, and end of the loaded image (bss).
+    static _etext: u8;
+    static _end: u8;
+}
+
+static BOOT_STACK_TOP: AtomicUsize = AtomicUsize::new(0);
+
+/// Remembers the top of the boot stack; must be called first thing in
+/// `kernel_main` so every later frame lies below it.
+#[inline(always)]
+pub fn note_boot_stack() {
+    let rsp: usize;
+    unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)) };
+    BOOT_STACK_TOP.store(rsp, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone)]
+pub struct LeakReport {
+    pub scanned: usize,
+    pub leaks: Vec<AllocRecord>,
+}
+
+struct Scan<'a> {
+    records: &'a [AllocRecord],
+    marked: Vec<bool>,
+    worklist: Vec<usize>,
+    excluded: [(usize, usize); 4],
+}
+
+impl<'a> Scan<'a> {
+    /// Index of the candidate containing `addr`; `records` is sorted by address.
+    fn lookup(&self, addr: usize) -> Option<usize> {
+        let idx = self.records.partition_point(|r| r.ptr <= addr).checked_sub(1)?;
+        let record = &self.records[idx];
+        (addr < record.ptr + record.size.max(1)).then_some(idx)
+    }
+
+    fn excluded(&self, addr: usize) -> bool {
+        self.excluded.iter().any(|&(start, end)| addr >= start && addr < end)
+    }
+
+    /// Marks every candidate referenced from the words in `[start, end)`.
+    unsafe fn scan_range(&mut self, start: usize, end: usize) {
+        let mut addr = (start + 7) & !7;
+        while addr + 8 <= end {
+            if !self.excluded(addr) {
+                let value = (addr as *const usize).read_volatile();
+                if let Some(idx) = self.lookup(value) {
+                    if !self.marked[idx] {
+                        self.marked[idx] = true;
+                        self.worklist.push(idx);
+                    }
+                }
+            }
+            addr += 8;
+        }
+    }
+
+    /// Scans the heap except the candidates themselves.
+    unsafe fn scan_heap_roots(&mut self) {
+        let mut cursor = HEAP_START;
+        for i in 0..self.records.len() {
+            let record = self.records[i];
+            self.scan_range(cursor, record.ptr);
+            cursor = cursor.max(record.ptr + record.size);
+        }
+        self.scan_range(cursor, HEAP_START + heap_size());
+    }
+
+    unsafe fn propagate(&mut self) {
+        while let Some(idx) = self.worklist.pop() {
+            let record = self.records[idx];
+            self.scan_range(record.ptr, record.ptr + record.size);
+        }
+    }
+}
+
+fn buffer_range<T>(buf: &Vec<T>) -> (usize, usize) {
+    let start = buf.as_ptr() as usize;
+    (start, start + buf.capacity() * core::mem::size_of::<T>())
+}
+
+/// Runs a full scan with interrupts disabled and returns the unreachable
+/// allocations, oldest first. Requires `heap-debug` with tracking enabled.
+pub fn scan() -> LeakReport {
+    let was_tracking = heap_debug::tracking();
+    // The scanner's own buffers must not be candidates.
+    heap_debug::set_tracking(false);
+
+    let mut records = heap_debug::snapshot();
+    records.sort_unstable_by_key(|r| r.ptr);
+    let marked = alloc::vec![false; records.len()];
+    let worklist = Vec::with_capacity(records.len());
+
+    let report = x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut scan = Scan {
+            excluded: [
+                heap_debug::tracker_range(),
+                buffer_range(&records),
+                buffer_range(&marked),
+                buffer_range(&worklist),
+            ],
+            records: &records,
+            marked,
+            worklist,
+        };
+
+        unsafe {
+            let data_start = &_etext as *const u8 as usize;
+            let data_end = &_end as *const u8 as usize;
+            scan.scan_range(data_start, data_end);
+
+            let rsp: usize;
+            core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack));
+            let top = BOOT_STACK_TOP.load(Ordering::Relaxed);
+            if top > rsp {
+                scan.scan_range(rsp, top);
+            }
+
+            scan.scan_heap_roots();
+            scan.propagate();
+        }
+
+        let mut leaks: Vec<AllocRecord> = records
+            .iter()
+            .zip(&scan.marked)
+            .filter(|(_, marked)| !**marked)
+            .map(|(record, _)| *record)
+            .collect();
+        leaks.sort_unstable_by_key(|r| r.seq);
+        LeakReport { scanned: records.len(), leaks }
+    });
+
+    heap_debug::set_tracking(was_tracking);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(ptr: usize, size: usize) -> AllocRecord {
+        AllocRecord { ptr, size, owner: 0, site: "test", seq: 0 }
+    }
+
+    #[test_case]
+    fn test_scan_follows_references() {
+        // Three blocks of four words: a root points at the first, the first
+        // at the second, and nothing at the third
+        let mut blocks = alloc::vec![0usize; 12];
+        let base = blocks.as_ptr() as usize;
+        blocks[1] = base + 32 + 8;
+        let roots = alloc::vec![0usize, base + 16];
+        let records = [record(base, 32), record(base + 32, 32), record(base + 64, 32)];
+        let mut scan = Scan {
+            records: &records,
+            marked: alloc::vec![false; 3],
+            worklist: Vec::new(),
+            excluded: [(0, 0); 4],
+        };
+
+        assert_eq!(scan.lookup(base + 40), Some(1));
+        assert_eq!(scan.lookup(base + 96), None);
+        assert_eq!(scan.lookup(base - 8), None);
+        unsafe {
+            let roots = roots.as_ptr() as usize;
+            scan.scan_range(roots, roots + 16);
+            scan.propagate();
+        }
+        assert_eq!(scan.marked, [true, true, false]);
+    }
+
+    #[test_case]
+    fn test_excluded_ranges_are_not_roots() {
+        let blocks = alloc::vec![0usize; 4];
+        let base = blocks.as_ptr() as usize;
+        let roots = alloc::vec![base, base + 8];
+        let records = [record(base, 32)];
+        let start = roots.as_ptr() as usize;
+        let mut scan = Scan {
+            records: &records,
+            marked: alloc::vec![false],
+            worklist: Vec::new(),
+            excluded: [(start, start + 16), (0, 0), (0, 0), (0, 0)],
+        };
+        unsafe { scan.scan_range(start, start + 16) };
+        assert_eq!(scan.marked, [false]);
+        assert!(scan.worklist.is_empty());
+    }
+}
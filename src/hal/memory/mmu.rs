@@ -123,6 +123,7 @@ pub fn pages_needed(size: u64) -> u64 {
     (size + PAGE_SIZE as u64 - 1) / PAGE_SIZE as u64
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ProtectionFlags(u64);
 
 impl ProtectionFlags {
@@ -132,12 +133,32 @@ impl ProtectionFlags {
     pub const EXECUTE: Self = Self(1 << 2);
     pub const USER: Self = Self(1 << 3);
 
+    /// The flags of an mmap() `prot`, whose PROT_READ, PROT_WRITE and
+    /// PROT_EXEC bits are the same as these
+    pub fn from_prot(prot: u64) -> Self {
+        Self(prot & (Self::READ.0 | Self::WRITE.0 | Self::EXECUTE.0))
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether pages with these could be both written and run, which W^X
+    /// refuses to user programs that have not opted out
+    pub fn is_writable_executable(&self) -> bool {
+        self.contains(Self::WRITE | Self::EXECUTE)
+    }
+
+    /// The page table flags for these. A page cannot be present and not
+    /// readable, so one with none of READ, WRITE and EXECUTE is kept from
+    /// ring 3 instead.
     pub fn to_page_table_flags(&self) -> PageTableFlags {
         let mut flags = PageTableFlags::PRESENT;
         if self.0 & Self::WRITE.0 != 0 {
             flags |= PageTableFlags::WRITABLE;
         }
-        if self.0 & Self::USER.0 != 0 {
+        let access = Self::READ.0 | Self::WRITE.0 | Self::EXECUTE.0;
+        if self.0 & Self::USER.0 != 0 && self.0 & access != 0 {
             flags |= PageTableFlags::USER_ACCESSIBLE;
         }
         if self.0 & Self::EXECUTE.0 == 0 {
@@ -1,7 +1,12 @@
 pub mod paging;
 pub mod heap;
+pub mod heap_debug;
+pub mod alloc_tag;
+pub mod kmemleak;
 pub mod mmu;
 pub mod frame_allocator;
+pub mod vma;
+pub mod address_space;
 
 pub use paging::*;
 pub use heap::*;
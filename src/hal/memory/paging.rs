@@ -16,15 +16,17 @@ use x86_64::{
 };
 
 use spin::Mutex;
+use crate::hal::lockdep::{self, Class};
 use lazy_static::lazy_static;
 
 lazy_static! {
-    static ref PAGE_TABLE_MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+    static ref PAGE_TABLE_MAPPER: lockdep::Mutex<Option<OffsetPageTable<'static>>> = lockdep::Mutex::new(Class::PageTables, None);
     static ref PHYS_MEM_OFFSET: Mutex<Option<VirtAddr>> = Mutex::new(None);
 }
 
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     *PHYS_MEM_OFFSET.lock() = Some(physical_memory_offset);
+    super::address_space::init();
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
@@ -128,6 +130,118 @@ pub fn identity_map(
     Ok(())
 }
 
+/// Keep the kernel's mapper for mappings made after boot
+pub fn install(mapper: OffsetPageTable<'static>) {
+    *PAGE_TABLE_MAPPER.lock() = Some(mapper);
+}
+
+/// Map `size` bytes of physical memory at `phys` to `virt` in the kernel's
+/// page tables, taking any page table frames from the global frame
+/// allocator. Nothing stays mapped if a page fails.
+pub fn map_range(virt: VirtAddr, phys: PhysAddr, size: u64, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    let mut mapper = PAGE_TABLE_MAPPER.lock();
+    let mapper = mapper.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    let mut frames = super::frame_allocator::FRAME_ALLOCATOR.lock();
+    let frames = frames.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    let result = create_mapping(virt, phys, size, flags, mapper, frames);
+    if result.is_err() {
+        unmap_pages(virt, size, mapper);
+    }
+    result
+}
+
+/// Map `size` bytes at `virt` to fresh frames from the global frame
+/// allocator, returning how many bytes from `virt` up were mapped: fewer
+/// than `size` if frames ran out, and none if the page tables or the frame
+/// allocator were locked, as they are when the caller interrupted whoever
+/// holds them. Used to grow the heap, so it never allocates.
+pub fn try_map_fresh(virt: VirtAddr, size: u64, flags: PageTableFlags) -> u64 {
+    let mut mapper = match PAGE_TABLE_MAPPER.try_lock() {
+        Some(mapper) => mapper,
+        None => return 0,
+    };
+    let mut frames = match super::frame_allocator::FRAME_ALLOCATOR.try_lock() {
+        Some(frames) => frames,
+        None => return 0,
+    };
+    let (mapper, frames) = match (mapper.as_mut(), frames.as_mut()) {
+        (Some(mapper), Some(frames)) => (mapper, frames),
+        _ => return 0,
+    };
+    let mut mapped = 0;
+    while mapped < size {
+        let page = Page::<Size4KiB>::containing_address(virt + mapped);
+        let frame = match frames.allocate_frame() {
+            Some(frame) => frame,
+            None => break,
+        };
+        match unsafe { mapper.map_to(page, frame, flags, frames) } {
+            Ok(flush) => flush.flush(),
+            Err(_) => break,
+        }
+        mapped += 4096;
+    }
+    mapped
+}
+
+/// Map `size` bytes at `virt` to fresh, zeroed frames from the global
+/// frame allocator in the kernel's page tables. Nothing stays mapped if a
+/// page fails, though the frames already taken are not given back.
+pub fn map_fresh(virt: VirtAddr, size: u64, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    let offset = get_physical_memory_offset().ok_or(MapToError::FrameAllocationFailed)?;
+    let mut mapper = PAGE_TABLE_MAPPER.lock();
+    let mapper = mapper.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    let mut frames = super::frame_allocator::FRAME_ALLOCATOR.lock();
+    let frames = frames.as_mut().ok_or(MapToError::FrameAllocationFailed)?;
+    let mut map = || -> Result<(), MapToError<Size4KiB>> {
+        for at in (0..size).step_by(4096) {
+            let page = Page::<Size4KiB>::containing_address(virt + at);
+            let frame = frames.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+            let zeroed = offset + frame.start_address().as_u64();
+            unsafe {
+                core::ptr::write_bytes(zeroed.as_mut_ptr::<u8>(), 0, 4096);
+                mapper.map_to(page, frame, flags, frames)?.flush();
+            }
+        }
+        Ok(())
+    };
+    let result = map();
+    if result.is_err() {
+        unmap_pages(virt, size, mapper);
+    }
+    result
+}
+
+/// Give the pages of `size` bytes from `virt` in the kernel's page tables
+/// `flags`, skipping pages that are not mapped
+pub fn protect_range(virt: VirtAddr, size: u64, flags: PageTableFlags) {
+    if let Some(mapper) = PAGE_TABLE_MAPPER.lock().as_mut() {
+        let start = Page::<Size4KiB>::containing_address(virt);
+        let end = Page::containing_address(virt + size - 1u64);
+        for page in Page::range_inclusive(start, end) {
+            if let Ok(flush) = unsafe { mapper.update_flags(page, flags) } {
+                flush.flush();
+            }
+        }
+    }
+}
+
+/// Unmap `size` bytes from `virt`, skipping pages that are not mapped.
+/// The frames are left alone: whoever mapped them owns them.
+pub fn unmap_range(virt: VirtAddr, size: u64) {
+    if let Some(mapper) = PAGE_TABLE_MAPPER.lock().as_mut() {
+        unmap_pages(virt, size, mapper);
+    }
+}
+
+fn unmap_pages(virt: VirtAddr, size: u64, mapper: &mut impl Mapper<Size4KiB>) {
+    let start = Page::<Size4KiB>::containing_address(virt);
+    let end = Page::containing_address(virt + size - 1u64);
+    for page in Page::range_inclusive(start, end) {
+        unmap_page(page, mapper).ok();
+    }
+}
+
 pub fn get_physical_memory_offset() -> Option<VirtAddr> {
     *PHYS_MEM_OFFSET.lock()
 }
@@ -0,0 +1,298 @@
+// Mapped areas
+//
+// What mmap(2) maps is kept as a list of areas per task, given back when
+// the task unmaps them, execs or exits. Addresses are first fit and never
+// overlap. A program exec'd into an address space of its own gets its
+// anonymous memory there, in the window between its break and its stack,
+// seen by no other task. Everything else - device memory, and anonymous
+// memory for tasks on the kernel's page tables - goes in a window of the
+// lower half nothing else uses in the kernel's tables, so it is seen by
+// all of them.
+//
+// Anonymous areas are fresh zeroed frames, which are not given back when
+// they are unmapped as the boot frame allocator takes none back. Device
+// areas map the device's own memory, uncached, and the frames behind them
+// are never freed: they belong to the device, not to the frame allocator.
+// An area may be unmapped or have its protection changed in part, which
+// splits it.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use spin::Mutex;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::fs::vfs::node::DeviceId;
+use crate::kernel::scheduler::Pid;
+use super::address_space::{AddressSpace, USER_MMAP_BASE, USER_MMAP_END};
+use super::mmu::{ProtectionFlags, PAGE_SIZE};
+use super::paging;
+
+pub const MMAP_BASE: u64 = 0x0000_7000_0000_0000;
+pub const MMAP_END: u64 = 0x0000_7F00_0000_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// No room left in the window, or no frames
+    NoSpace,
+    /// Not page aligned, or an empty range
+    Invalid,
+    /// Part of the range is not mapped
+    Unmapped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    Device { device: DeviceId, phys: u64 },
+    Anonymous,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Area {
+    pub owner: Pid,
+    pub start: u64,
+    pub len: u64,
+    pub prot: ProtectionFlags,
+    pub backing: Backing,
+}
+
+impl Area {
+    fn end(&self) -> u64 {
+        self.start + self.len
+    }
+
+    /// Whether it is in its owner's address space rather than the kernel's
+    /// page tables
+    fn is_private(&self) -> bool {
+        (USER_MMAP_BASE..USER_MMAP_END).contains(&self.start)
+    }
+
+    fn flags(&self) -> PageTableFlags {
+        let flags = (self.prot | ProtectionFlags::USER).to_page_table_flags();
+        match self.backing {
+            Backing::Device { .. } => flags | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+            Backing::Anonymous => flags,
+        }
+    }
+
+    /// The part from `at` on, leaving this the part before it
+    fn split_off(&mut self, at: u64) -> Area {
+        let mut rest = *self;
+        rest.start = at;
+        rest.len = self.end() - at;
+        if let Backing::Device { device, phys } = self.backing {
+            rest.backing = Backing::Device { device, phys: phys + (at - self.start) };
+        }
+        self.len = at - self.start;
+        rest
+    }
+}
+
+static AREAS: Mutex<Vec<Area>> = Mutex::new(Vec::new());
+
+/// The first gap of `len` bytes in `window`, among `areas` in order
+fn find_gap<'a>(areas: impl IntoIterator<Item = &'a Area>, window: Range<u64>, len: u64) -> Option<u64> {
+    let mut start = window.start;
+    for area in areas {
+        if area.start - start >= len {
+            break;
+        }
+        start = area.end();
+    }
+    (window.end - start >= len).then_some(start)
+}
+
+/// Where an area of `len` bytes would go: in `owner`'s own window if it
+/// is `private`, else in the kernel's
+fn place(areas: &[Area], owner: Pid, private: bool, len: u64) -> Option<u64> {
+    if private {
+        let own = areas.iter().filter(|area| area.owner == owner && area.is_private());
+        find_gap(own, USER_MMAP_BASE..USER_MMAP_END, len)
+    } else {
+        find_gap(areas.iter().filter(|area| !area.is_private()), MMAP_BASE..MMAP_END, len)
+    }
+}
+
+fn insert(areas: &mut Vec<Area>, area: Area) {
+    let at = areas.partition_point(|other| other.start < area.start);
+    areas.insert(at, area);
+}
+
+/// Map `len` bytes of `device`'s memory at `phys` for `owner`, returning
+/// the address it is mapped at
+pub fn map_device(owner: Pid, phys: u64, len: u64, prot: ProtectionFlags, device: DeviceId) -> Result<u64, VmaError> {
+    if len == 0 || !phys.is_multiple_of(PAGE_SIZE as u64) {
+        return Err(VmaError::Invalid);
+    }
+    let len = len.next_multiple_of(PAGE_SIZE as u64);
+    let mut areas = AREAS.lock();
+    let start = place(&areas, owner, false, len).ok_or(VmaError::NoSpace)?;
+    let area = Area { owner, start, len, prot, backing: Backing::Device { device, phys } };
+    paging::map_range(VirtAddr::new(start), PhysAddr::new(phys), len, area.flags()).map_err(|_| VmaError::NoSpace)?;
+    insert(&mut areas, area);
+    Ok(start)
+}
+
+/// Map `len` bytes of zeroed memory for `owner`, in `space` if it runs in
+/// one, returning the address it is mapped at
+pub fn map_anonymous(owner: Pid, space: Option<&AddressSpace>, len: u64, prot: ProtectionFlags) -> Result<u64, VmaError> {
+    if len == 0 {
+        return Err(VmaError::Invalid);
+    }
+    let len = len.checked_next_multiple_of(PAGE_SIZE as u64).ok_or(VmaError::NoSpace)?;
+    let mut areas = AREAS.lock();
+    let start = place(&areas, owner, space.is_some(), len).ok_or(VmaError::NoSpace)?;
+    let area = Area { owner, start, len, prot, backing: Backing::Anonymous };
+    match space {
+        // Pages mapped before it failed are taken out again, as map_fresh()
+        // does, since no area would ever unmap them
+        Some(space) => space.map(start, len, prot).map_err(|_| {
+            space.unmap(start, len).ok();
+            VmaError::NoSpace
+        })?,
+        None => paging::map_fresh(VirtAddr::new(start), len, area.flags()).map_err(|_| VmaError::NoSpace)?,
+    }
+    insert(&mut areas, area);
+    Ok(start)
+}
+
+/// The page range `start..start + len`, checked
+fn range(start: u64, len: u64) -> Result<Range<u64>, VmaError> {
+    if len == 0 || !start.is_multiple_of(PAGE_SIZE as u64) {
+        return Err(VmaError::Invalid);
+    }
+    let end = len.checked_next_multiple_of(PAGE_SIZE as u64).and_then(|len| start.checked_add(len));
+    Ok(start..end.ok_or(VmaError::Invalid)?)
+}
+
+/// Split `owner`'s areas that straddle either end of `range`, so each one
+/// is wholly inside it or wholly outside
+fn split(areas: &mut Vec<Area>, owner: Pid, range: &Range<u64>) {
+    for at in [range.start, range.end] {
+        let straddling = areas.iter().position(|area| area.owner == owner && area.start < at && at < area.end());
+        if let Some(index) = straddling {
+            let rest = areas[index].split_off(at);
+            insert(areas, rest);
+        }
+    }
+}
+
+fn unmap_area(area: &Area, space: Option<&AddressSpace>) {
+    if !area.is_private() {
+        paging::unmap_range(VirtAddr::new(area.start), area.len);
+    } else if let Some(space) = space {
+        space.unmap(area.start, area.len).ok();
+    }
+}
+
+/// Unmap whatever `owner` has mapped within `start..start + len`, which
+/// need not all be mapped; `space` is the one it runs in, if any
+pub fn unmap(owner: Pid, space: Option<&AddressSpace>, start: u64, len: u64) -> Result<(), VmaError> {
+    let range = range(start, len)?;
+    let mut areas = AREAS.lock();
+    split(&mut areas, owner, &range);
+    areas.retain(|area| {
+        if area.owner != owner || !range.contains(&area.start) {
+            return true;
+        }
+        unmap_area(area, space);
+        false
+    });
+    Ok(())
+}
+
+/// Give the pages of `area` its protection
+fn apply_protection(area: &Area, space: Option<&AddressSpace>) -> Result<(), VmaError> {
+    match space.filter(|_| area.is_private()) {
+        Some(space) => space.protect(area.start, area.len, area.prot).map_err(|_| VmaError::Invalid),
+        None => {
+            paging::protect_range(VirtAddr::new(area.start), area.len, area.flags());
+            Ok(())
+        }
+    }
+}
+
+/// Change the protection of `owner`'s memory in `start..start + len`,
+/// all of which must be areas of its. If any of it cannot be changed,
+/// none of it is.
+pub fn protect(owner: Pid, space: Option<&AddressSpace>, start: u64, len: u64, prot: ProtectionFlags) -> Result<(), VmaError> {
+    let range = range(start, len)?;
+    let mut areas = AREAS.lock();
+    let mut covered = range.start;
+    for area in areas.iter().filter(|area| area.owner == owner && area.start < range.end && range.start < area.end()) {
+        if area.start > covered {
+            break;
+        }
+        covered = area.end();
+    }
+    if covered < range.end {
+        return Err(VmaError::Unmapped);
+    }
+
+    let before = areas.clone();
+    split(&mut areas, owner, &range);
+    let changed = areas.iter_mut().filter(|area| area.owner == owner && range.contains(&area.start)).try_for_each(|area| {
+        area.prot = prot;
+        apply_protection(area, space)
+    });
+    if changed.is_err() {
+        let touched = before.iter().filter(|area| area.owner == owner && area.start < range.end && range.start < area.end());
+        for area in touched {
+            apply_protection(area, space).ok();
+        }
+        *areas = before;
+    }
+    changed
+}
+
+/// Unmap everything `owner` mapped, as it execs or exits, from the
+/// kernel's tables; its own address space goes with it
+pub fn release(owner: Pid) {
+    AREAS.lock().retain(|area| {
+        if area.owner != owner {
+            return true;
+        }
+        unmap_area(area, None);
+        false
+    });
+}
+
+pub fn areas(owner: Pid) -> Vec<Area> {
+    AREAS.lock().iter().filter(|area| area.owner == owner).copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(start: u64, len: u64) -> Area {
+        Area {
+            owner: 1, start, len, prot: ProtectionFlags::READ,
+            backing: Backing::Device { device: DeviceId::new(1, 1), phys: 0x1000 },
+        }
+    }
+
+    #[test_case]
+    fn test_find_gap() {
+        let area = device;
+        let page = PAGE_SIZE as u64;
+        let window = MMAP_BASE..MMAP_END;
+        assert_eq!(find_gap(&[], window.clone(), page), Some(MMAP_BASE));
+        let areas = [area(MMAP_BASE, page), area(MMAP_BASE + 3 * page, page)];
+        assert_eq!(find_gap(&areas, window.clone(), 2 * page), Some(MMAP_BASE + page));
+        assert_eq!(find_gap(&areas, window.clone(), 3 * page), Some(MMAP_BASE + 4 * page));
+        assert_eq!(find_gap(&areas, window, MMAP_END - MMAP_BASE), None);
+    }
+
+    #[test_case]
+    fn test_split() {
+        let page = PAGE_SIZE as u64;
+        let mut areas = alloc::vec![device(MMAP_BASE, 4 * page)];
+        split(&mut areas, 1, &(MMAP_BASE + page..MMAP_BASE + 2 * page));
+        assert_eq!(areas.len(), 3);
+        assert_eq!((areas[1].start, areas[1].len), (MMAP_BASE + page, page));
+        assert_eq!(areas[2].backing, Backing::Device { device: DeviceId::new(1, 1), phys: 0x1000 + 2 * page });
+        // Another task's areas are left whole
+        split(&mut areas, 2, &(MMAP_BASE + page / 2..MMAP_END));
+        assert_eq!(areas.len(), 3);
+    }
+}
@@ -1,46 +1,92 @@
 pub mod cpu;
 pub mod memory;
 pub mod drivers;
+pub mod block;
+pub mod lockdep;
 pub mod hal;
 
 pub use hal::*;
 
 use bootloader::BootInfo;
-use crate::println;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::Size4KiB;
+use crate::kernel::bootui::stage;
+
+/// Stages `init` reports, for the boot progress bar
+pub const BOOT_STAGES: usize = 12
+    + cfg!(feature = "ahci") as usize
+    + cfg!(feature = "nvme") as usize
+    + cfg!(feature = "sdhci") as usize
+    + cfg!(feature = "framebuffer") as usize
+    + cfg!(feature = "net") as usize;
 
 pub fn init(boot_info: &'static BootInfo) {
-    println!("  [HAL] Initializing GDT...");
-    cpu::gdt::init();
-    
-    println!("  [HAL] Initializing IDT...");
-    cpu::idt::init();
-    
-    println!("  [HAL] Initializing PIC...");
-    unsafe { cpu::interrupts::PICS.lock().initialize() };
-    
-    println!("  [HAL] Enabling interrupts...");
-    x86_64::instructions::interrupts::enable();
-    
-    println!("  [HAL] Initializing memory management...");
-    let phys_mem_offset = x86_64::VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::paging::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe {
-        memory::frame_allocator::BootInfoFrameAllocator::init(&boot_info.memory_map)
-    };
-    
-    println!("  [HAL] Initializing kernel heap...");
-    memory::heap::init_heap(&mut mapper, &mut frame_allocator)
-        .expect("Heap initialization failed");
-    
-    println!("  [HAL] Initializing serial port...");
-    drivers::serial::init();
-    
-    println!("  [HAL] Initializing keyboard driver...");
-    drivers::keyboard::init();
-    
-    println!("  [HAL] Initializing PIT timer...");
-    drivers::pit::init();
-    
-    println!("  [HAL] Scanning PCI bus...");
-    drivers::pci::scan_bus();
+    stage("CPU tables (GDT, IDT, syscall)", || {
+        cpu::gdt::init();
+        cpu::idt::init();
+        cpu::usermode::init();
+    });
+
+    stage("Interrupt controller", || {
+        unsafe { cpu::interrupts::PICS.lock().initialize() };
+        x86_64::instructions::interrupts::enable();
+    });
+
+    stage("Paging and kernel heap", || -> Result<(), MapToError<Size4KiB>> {
+        let phys_mem_offset = x86_64::VirtAddr::new(boot_info.physical_memory_offset);
+        let mut mapper = unsafe { memory::paging::init(phys_mem_offset) };
+        // The heap's frames come from the global allocator main() set
+        // up, so mappings made later are not handed them again
+        let mut frames = memory::frame_allocator::FRAME_ALLOCATOR.lock();
+        let frame_allocator = frames.as_mut().expect("Frame allocator not initialized");
+        memory::heap::init_heap(&mut mapper, frame_allocator)?;
+        drop(frames);
+        memory::paging::install(mapper);
+        memory::heap::init_growth();
+        Ok(())
+    }).expect("Heap initialization failed");
+
+    // Everything from here on is a driver's
+    crate::alloc_tag!(Driver);
+    stage("Device hotplug", drivers::hotplug::init);
+    stage("Serial port", drivers::serial::init);
+
+    stage("Keyboard and terminals", || {
+        drivers::keyboard::init();
+        drivers::tty::init();
+        drivers::mem::init();
+    });
+
+    stage("PIT timer", drivers::pit::init);
+    stage("PCI bus", drivers::pci::scan_bus);
+    stage("IDE disks", drivers::ata::init);
+    // Before anything that does DMA, which a resumed kernel would not
+    // know had been set up again
+    stage("Hibernation image", crate::kernel::hibernate::resume).ok();
+
+    #[cfg(feature = "ahci")]
+    stage("SATA disks", drivers::ahci::init);
+
+    #[cfg(feature = "nvme")]
+    stage("NVMe controllers", drivers::nvme::init);
+
+    #[cfg(feature = "sdhci")]
+    stage("SD host controllers", drivers::sdhci::init);
+
+    stage("RAM disks", block::init_ramdisks);
+
+    #[cfg(feature = "framebuffer")]
+    stage("Graphics adapter and mouse", || {
+        drivers::framebuffer::init();
+        drivers::mouse::init();
+    });
+
+    stage("Audio", || {
+        #[cfg(feature = "sound")]
+        drivers::ac97::init();
+        drivers::audio::init();
+    });
+
+    #[cfg(feature = "net")]
+    stage("Network adapters", drivers::e1000::init);
 }
@@ -0,0 +1,185 @@
+// Boot progress
+//
+// Boot runs as stages - a subsystem, or a group of drivers - and each
+// ends with one status line on the screen and serial:
+//
+//   [ OK ] Paging and kernel heap (12 ms)
+//   [FAIL] Root filesystem: NotFound
+//
+// Times come from the PIT, so the stages before it runs have none. What
+// the init code inside a stage prints is the detail: with `quiet` on the
+// kernel command line it goes to serial and the kernel log only, and the
+// screen shows just the status lines. With `splash` too and a graphics
+// adapter, the screen shows a progress bar from the stage that finds the
+// adapter until boot is done, when the text console comes back.
+//
+// A stage fails when its init returns an Err; one that returns () always
+// succeeds, whatever it found. A failure does not stop boot by itself:
+// the caller gets the Result back, to expect() if it cannot go on.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::hal::drivers::pit;
+use crate::hal::drivers::vga::{self, Color};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Stages boot will run, for the progress bar
+static PLANNED: AtomicUsize = AtomicUsize::new(0);
+static DONE: AtomicUsize = AtomicUsize::new(0);
+
+/// What a stage's init returned, as far as its status line goes
+pub trait Outcome {
+    fn error(&self) -> Option<&dyn fmt::Debug>;
+}
+
+impl Outcome for () {
+    fn error(&self) -> Option<&dyn fmt::Debug> {
+        None
+    }
+}
+
+impl<T, E: fmt::Debug> Outcome for Result<T, E> {
+    fn error(&self) -> Option<&dyn fmt::Debug> {
+        self.as_ref().err().map(|e| e as &dyn fmt::Debug)
+    }
+}
+
+/// Read `quiet` and `splash` from the command line, which must have been
+/// parsed, ahead of `stages` stages
+pub fn init(stages: usize) {
+    let quiet = super::has_param("quiet");
+    QUIET.store(quiet, Ordering::Relaxed);
+    vga::set_quiet(quiet);
+    PLANNED.store(stages, Ordering::Relaxed);
+    #[cfg(feature = "framebuffer")]
+    splash::WANTED.store(super::has_param("splash"), Ordering::Relaxed);
+}
+
+/// Whether boot is quiet
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Run one stage of boot, reporting how it went
+pub fn stage<R: Outcome>(name: &str, init: impl FnOnce() -> R) -> R {
+    let start = pit::get_ticks();
+    let result = init();
+    let end = pit::get_ticks();
+    // The PIT is not running yet if it has not counted anything
+    let elapsed = (end > 0).then(|| end - start);
+    report(name, result.error(), elapsed);
+    let done = DONE.fetch_add(1, Ordering::Relaxed) + 1;
+    #[cfg(feature = "framebuffer")]
+    splash::update(name, result.error().is_some(), done, PLANNED.load(Ordering::Relaxed));
+    #[cfg(not(feature = "framebuffer"))]
+    let _ = done;
+    result
+}
+
+fn report(name: &str, error: Option<&dyn fmt::Debug>, elapsed: Option<u64>) {
+    let (tag, color) = match error {
+        None => ("[ OK ]", Color::LightGreen),
+        Some(_) => ("[FAIL]", Color::LightRed),
+    };
+    let line = Line { name, error, elapsed };
+    vga::print_always(format_args!("{} ", tag), Some(color));
+    vga::print_always(format_args!("{}\n", line), None);
+    crate::serial_println!("{} {}", tag, line);
+}
+
+/// A status line after its tag
+struct Line<'a> {
+    name: &'a str,
+    error: Option<&'a dyn fmt::Debug>,
+    elapsed: Option<u64>,
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(error) = self.error {
+            write!(f, ": {:?}", error)?;
+        }
+        if let Some(ms) = self.elapsed {
+            write!(f, " ({} ms)", ms)?;
+        }
+        Ok(())
+    }
+}
+
+/// Boot is done: take the splash down and let `println!` at the screen
+pub fn finish() {
+    debug_assert_eq!(DONE.load(Ordering::Relaxed), PLANNED.load(Ordering::Relaxed), "boot stage count is off");
+    #[cfg(feature = "framebuffer")]
+    splash::close();
+    vga::set_quiet(false);
+}
+
+#[cfg(feature = "framebuffer")]
+mod splash {
+    use alloc::boxed::Box;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use spin::Mutex;
+    use crate::hal::drivers::framebuffer::{self, Framebuffer, Mode};
+    use crate::hal::drivers::gfx::{rgb, Canvas, Color, Font, Rect, BLACK, GLYPH_HEIGHT, GLYPH_WIDTH, WHITE};
+
+    const MODE: Mode = Mode { width: 640, height: 480 };
+    const BAR: Rect = Rect::new(120, 260, 400, 16);
+    const FILL: Color = rgb(0x30, 0x90, 0xF0);
+    const FAILED: Color = rgb(0xF0, 0x40, 0x40);
+
+    pub static WANTED: AtomicBool = AtomicBool::new(false);
+
+    struct Splash {
+        fb: Framebuffer,
+        screen: Canvas,
+        font: Box<Font>,
+    }
+
+    static SPLASH: Mutex<Option<Splash>> = Mutex::new(None);
+
+    /// Switch to graphics the first time it can be done
+    fn open() -> Option<Splash> {
+        if !WANTED.load(Ordering::Relaxed) || !framebuffer::available() {
+            return None;
+        }
+        WANTED.store(false, Ordering::Relaxed);
+        let fb = framebuffer::enter(MODE).ok()?;
+        let Some(font) = framebuffer::font() else {
+            framebuffer::leave();
+            return None;
+        };
+        Some(Splash { fb, screen: Canvas::new(MODE.width, MODE.height, BLACK), font })
+    }
+
+    fn centered(text: &str) -> i32 {
+        (MODE.width as i32 - text.len() as i32 * GLYPH_WIDTH as i32) / 2
+    }
+
+    /// Redraw after stage `done` of `planned`, which was `name`
+    pub fn update(name: &str, failed: bool, done: usize, planned: usize) {
+        let mut splash = SPLASH.lock();
+        if splash.is_none() {
+            *splash = open();
+        }
+        let Some(splash) = splash.as_mut() else {
+            return;
+        };
+        let Splash { fb, screen, font } = splash;
+        screen.clear(BLACK);
+        let title = "Qunix";
+        screen.draw_text(centered(title), BAR.y - 3 * GLYPH_HEIGHT as i32, title, WHITE, None, font);
+        screen.draw_rect(BAR, WHITE);
+        let filled = (BAR.width - 4) as usize * done.min(planned) / planned.max(1);
+        screen.fill_rect(Rect::new(BAR.x + 2, BAR.y + 2, filled as u32, BAR.height - 4), FILL);
+        let color = if failed { FAILED } else { WHITE };
+        screen.draw_text(centered(name), BAR.bottom() + GLYPH_HEIGHT as i32, name, color, None, font);
+        fb.present(screen, screen.bounds());
+    }
+
+    pub fn close() {
+        if SPLASH.lock().take().is_some() {
+            framebuffer::leave();
+        }
+    }
+}
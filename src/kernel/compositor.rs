@@ -0,0 +1,305 @@
+// Window compositor
+//
+// Clients get rectangular surfaces: a canvas they draw into and the
+// compositor reads from when it paints the screen, shared rather than
+// copied. Surfaces are stacked with the newest or last clicked on top,
+// which also has the keyboard, and carry a title bar to drag them by.
+// Keys go to the top surface and pointer events to the one under the
+// pointer, queued on the surface for its client to take.
+//
+// The compositor has no task of its own: whoever started it calls poll()
+// in a loop, which routes input, drops the surfaces of tasks that have
+// exited and repaints if anything changed. poll() takes SCHEDULER, then
+// the compositor, then each surface, so clients must not call into the
+// compositor from inside SurfaceHandle::draw.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::hal::drivers::framebuffer::{self, Framebuffer, Mode};
+use crate::hal::drivers::gfx::{rgb, Canvas, Color, Font, Rect, GLYPH_HEIGHT, WHITE};
+use crate::hal::drivers::{keyboard, mouse};
+use crate::kernel::scheduler::{Pid, SCHEDULER};
+
+pub type SurfaceId = u32;
+
+const TITLE_HEIGHT: u32 = GLYPH_HEIGHT + 4;
+const DESKTOP: Color = rgb(0x2E, 0x4A, 0x62);
+const BORDER: Color = rgb(0x10, 0x10, 0x10);
+const TITLE_FOCUSED: Color = rgb(0x3A, 0x6E, 0xA5);
+const TITLE_UNFOCUSED: Color = rgb(0x70, 0x70, 0x70);
+/// Events kept per surface before the oldest are dropped
+const QUEUE_LIMIT: usize = 64;
+/// Offset between the default positions of successive surfaces
+const CASCADE: i32 = 24;
+
+/// The pointer, one row per line, top bit leftmost
+const CURSOR: [u8; 12] = [
+    0x80, 0xC0, 0xE0, 0xF0, 0xF8, 0xFC, 0xFE, 0xF8, 0xD8, 0x8C, 0x0C, 0x06,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(char),
+    /// Pointer position within the surface and the buttons held
+    Pointer { x: i32, y: i32, buttons: u8 },
+}
+
+pub struct Surface {
+    pub canvas: Canvas,
+    events: VecDeque<InputEvent>,
+    damaged: bool,
+}
+
+impl Surface {
+    fn push(&mut self, event: InputEvent) {
+        if self.events.len() == QUEUE_LIMIT {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+/// A client's surface; dropping it closes the surface
+pub struct SurfaceHandle {
+    id: SurfaceId,
+    surface: Arc<Mutex<Surface>>,
+}
+
+impl SurfaceHandle {
+    pub fn id(&self) -> SurfaceId {
+        self.id
+    }
+
+    /// Draw on the surface; it is repainted on the next poll()
+    pub fn draw<R>(&self, f: impl FnOnce(&mut Canvas) -> R) -> R {
+        let mut surface = self.surface.lock();
+        surface.damaged = true;
+        f(&mut surface.canvas)
+    }
+
+    pub fn next_event(&self) -> Option<InputEvent> {
+        self.surface.lock().events.pop_front()
+    }
+}
+
+impl Drop for SurfaceHandle {
+    fn drop(&mut self) {
+        if let Some(compositor) = COMPOSITOR.lock().as_mut() {
+            compositor.windows.retain(|w| w.id != self.id);
+            compositor.dirty = true;
+        }
+    }
+}
+
+struct Window {
+    id: SurfaceId,
+    /// None for surfaces the kernel opened outside any task
+    owner: Option<Pid>,
+    title: String,
+    /// Top left of the content, below the title bar
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    surface: Arc<Mutex<Surface>>,
+}
+
+impl Window {
+    fn content(&self) -> Rect {
+        Rect::new(self.x, self.y, self.width, self.height)
+    }
+
+    fn title_bar(&self) -> Rect {
+        Rect::new(self.x, self.y - TITLE_HEIGHT as i32, self.width, TITLE_HEIGHT)
+    }
+
+    /// Content, title bar and border
+    fn frame(&self) -> Rect {
+        Rect::new(self.x - 1, self.y - TITLE_HEIGHT as i32 - 1, self.width + 2, self.height + TITLE_HEIGHT + 2)
+    }
+}
+
+struct Compositor {
+    fb: Framebuffer,
+    /// What is on the screen, painted here first
+    screen: Canvas,
+    font: Box<Font>,
+    /// Bottom to top
+    windows: Vec<Window>,
+    next_id: SurfaceId,
+    pointer: (i32, i32),
+    buttons: u8,
+    /// Window being dragged and where in it the pointer holds it
+    drag: Option<(SurfaceId, i32, i32)>,
+    /// Repaint even if no surface has been drawn on
+    dirty: bool,
+}
+
+static COMPOSITOR: Mutex<Option<Compositor>> = Mutex::new(None);
+
+impl Compositor {
+    /// Index of the topmost window whose frame holds (x, y)
+    fn window_at(&self, x: i32, y: i32) -> Option<usize> {
+        self.windows.iter().rposition(|w| w.frame().contains(x, y))
+    }
+
+    fn key(&mut self, c: char) {
+        if let Some(top) = self.windows.last() {
+            top.surface.lock().push(InputEvent::Key(c));
+        }
+    }
+
+    fn pointer(&mut self, event: mouse::MouseEvent) {
+        let bounds = self.fb.bounds();
+        let x = (self.pointer.0 + event.dx as i32).clamp(0, bounds.right() - 1);
+        let y = (self.pointer.1 + event.dy as i32).clamp(0, bounds.bottom() - 1);
+        let pressed = event.buttons & !self.buttons;
+        self.pointer = (x, y);
+        self.buttons = event.buttons;
+        self.dirty = true;
+
+        if let Some((id, dx, dy)) = self.drag {
+            if event.buttons & mouse::BUTTON_LEFT != 0 {
+                if let Some(w) = self.windows.iter_mut().find(|w| w.id == id) {
+                    w.x = x - dx;
+                    w.y = y - dy;
+                }
+                return;
+            }
+            self.drag = None;
+        }
+        let index = match self.window_at(x, y) {
+            Some(index) => index,
+            None => return,
+        };
+        let index = if pressed & mouse::BUTTON_LEFT != 0 {
+            let window = self.windows.remove(index);
+            if window.title_bar().contains(x, y) {
+                self.drag = Some((window.id, x - window.x, y - window.y));
+            }
+            self.windows.push(window);
+            self.windows.len() - 1
+        } else {
+            index
+        };
+        let window = &self.windows[index];
+        if window.content().contains(x, y) {
+            window.surface.lock().push(InputEvent::Pointer { x: x - window.x, y: y - window.y, buttons: event.buttons });
+        }
+    }
+
+    fn paint(&mut self) {
+        self.screen.clear(DESKTOP);
+        let top = self.windows.len().saturating_sub(1);
+        for (i, window) in self.windows.iter().enumerate() {
+            self.screen.draw_rect(window.frame(), BORDER);
+            let bar = window.title_bar();
+            self.screen.fill_rect(bar, if i == top { TITLE_FOCUSED } else { TITLE_UNFOCUSED });
+            self.screen.draw_text(bar.x + 4, bar.y + 2, &window.title, WHITE, None, &self.font);
+            let mut surface = window.surface.lock();
+            surface.damaged = false;
+            self.screen.blit(&surface.canvas, window.x, window.y);
+        }
+        let (px, py) = self.pointer;
+        for (row, bits) in CURSOR.iter().enumerate() {
+            for col in 0..8 {
+                if bits & (0x80 >> col) != 0 {
+                    self.screen.set_pixel(px + col, py + row as i32, WHITE);
+                }
+            }
+        }
+        self.fb.present(&self.screen, self.screen.bounds());
+        self.dirty = false;
+    }
+}
+
+/// Take over the screen in `mode`
+pub fn start(mode: Mode) -> Result<(), &'static str> {
+    let mut guard = COMPOSITOR.lock();
+    if guard.is_some() {
+        return Err("compositor already running");
+    }
+    let fb = framebuffer::enter(mode)?;
+    let font = match framebuffer::font() {
+        Some(font) => font,
+        None => {
+            framebuffer::leave();
+            return Err("no font");
+        }
+    };
+    keyboard::clear_buffer();
+    mouse::clear();
+    *guard = Some(Compositor {
+        screen: Canvas::new(mode.width, mode.height, DESKTOP),
+        fb,
+        font,
+        windows: Vec::new(),
+        next_id: 1,
+        pointer: (mode.width as i32 / 2, mode.height as i32 / 2),
+        buttons: 0,
+        drag: None,
+        dirty: true,
+    });
+    Ok(())
+}
+
+/// Give the screen back to the text console. Open surfaces stay valid
+/// but are no longer shown.
+pub fn stop() {
+    if COMPOSITOR.lock().take().is_some() {
+        framebuffer::leave();
+    }
+}
+
+/// Open a `width` x `height` surface for the current task
+pub fn create_surface(title: &str, width: u32, height: u32) -> Result<SurfaceHandle, &'static str> {
+    let owner = SCHEDULER.lock().current_pid();
+    let mut guard = COMPOSITOR.lock();
+    let compositor = guard.as_mut().ok_or("compositor not running")?;
+    let n = compositor.windows.len() as i32;
+    let id = compositor.next_id;
+    compositor.next_id += 1;
+    let surface = Arc::new(Mutex::new(Surface {
+        canvas: Canvas::new(width, height, WHITE),
+        events: VecDeque::new(),
+        damaged: true,
+    }));
+    compositor.windows.push(Window {
+        id,
+        owner,
+        title: String::from(title),
+        x: 2 * CASCADE + n * CASCADE,
+        y: 2 * CASCADE + n * CASCADE + TITLE_HEIGHT as i32,
+        width,
+        height,
+        surface: surface.clone(),
+    });
+    Ok(SurfaceHandle { id, surface })
+}
+
+/// Route input and repaint. Does nothing unless started.
+pub fn poll() {
+    let live: Vec<Pid> = SCHEDULER.lock().get_tasks().map(|task| task.pid).collect();
+    let mut guard = COMPOSITOR.lock();
+    let compositor = match guard.as_mut() {
+        Some(compositor) => compositor,
+        None => return,
+    };
+    let before = compositor.windows.len();
+    compositor.windows.retain(|w| w.owner.map_or(true, |pid| live.contains(&pid)));
+    compositor.dirty |= compositor.windows.len() != before;
+
+    while let Some(c) = keyboard::read_char() {
+        compositor.key(c);
+    }
+    while let Some(event) = mouse::read_event() {
+        compositor.pointer(event);
+    }
+    if compositor.dirty || compositor.windows.iter().any(|w| w.surface.lock().damaged) {
+        compositor.paint();
+    }
+}
@@ -0,0 +1,74 @@
+//src/kernel/config.rs
+//
+// Compile-time kernel configuration. Every option maps to a Cargo feature
+// declared in Cargo.toml; build a slim kernel with `--no-default-features`
+// and pick options back up with `--features usb,net,...`.
+
+use crate::qsf::SecurityLevel;
+
+pub const CONFIG_USB: bool = cfg!(feature = "usb");
+pub const CONFIG_AHCI: bool = cfg!(feature = "ahci");
+pub const CONFIG_NVME: bool = cfg!(feature = "nvme");
+pub const CONFIG_SDHCI: bool = cfg!(feature = "sdhci");
+pub const CONFIG_NET: bool = cfg!(feature = "net");
+pub const CONFIG_FRAMEBUFFER: bool = cfg!(feature = "framebuffer");
+pub const CONFIG_SOUND: bool = cfg!(feature = "sound");
+pub const CONFIG_SMP: bool = cfg!(feature = "smp");
+pub const CONFIG_QSF_ENFORCING_DEFAULT: bool = cfg!(feature = "qsf-enforcing-default");
+pub const CONFIG_CANARIES: bool = cfg!(feature = "canaries");
+pub const CONFIG_HEAP_DEBUG: bool = cfg!(feature = "heap-debug");
+pub const CONFIG_LOCKDEP: bool = cfg!(feature = "lockdep");
+
+/// Kernel command line baked in at build time (`QUNIX_CMDLINE=...`); the
+/// bootloader does not pass one.
+pub const BUILTIN_CMDLINE: &str = match option_env!("QUNIX_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+
+/// Security level QSF starts in before any policy is loaded.
+pub const DEFAULT_SECURITY_LEVEL: SecurityLevel = if CONFIG_QSF_ENFORCING_DEFAULT {
+    SecurityLevel::Enforcing
+} else {
+    SecurityLevel::Permissive
+};
+
+/// All options in kconfig order, as (name, enabled).
+pub const OPTIONS: &[(&str, bool)] = &[
+    ("CONFIG_USB", CONFIG_USB),
+    ("CONFIG_AHCI", CONFIG_AHCI),
+    ("CONFIG_NVME", CONFIG_NVME),
+    ("CONFIG_SDHCI", CONFIG_SDHCI),
+    ("CONFIG_NET", CONFIG_NET),
+    ("CONFIG_FRAMEBUFFER", CONFIG_FRAMEBUFFER),
+    ("CONFIG_SOUND", CONFIG_SOUND),
+    ("CONFIG_SMP", CONFIG_SMP),
+    ("CONFIG_QSF_ENFORCING_DEFAULT", CONFIG_QSF_ENFORCING_DEFAULT),
+    ("CONFIG_CANARIES", CONFIG_CANARIES),
+    ("CONFIG_HEAP_DEBUG", CONFIG_HEAP_DEBUG),
+    ("CONFIG_LOCKDEP", CONFIG_LOCKDEP),
+];
+
+pub fn is_enabled(name: &str) -> Option<bool> {
+    OPTIONS.iter().find(|(n, _)| *n == name).map(|(_, on)| *on)
+}
+
+/// Prints the build configuration to VGA and serial so boot logs record
+/// exactly which subsystems were compiled in.
+pub fn print_summary() {
+    // A quiet boot sends println! to serial already
+    let line = |args: core::fmt::Arguments| {
+        crate::println!("{}", args);
+        if !crate::kernel::bootui::quiet() {
+            crate::serial_println!("{}", args);
+        }
+    };
+    line(format_args!("[BOOT] Kernel configuration:"));
+    for (name, enabled) in OPTIONS {
+        let value = if *enabled { 'y' } else { 'n' };
+        line(format_args!("  {}={}", name, value));
+    }
+    if !BUILTIN_CMDLINE.is_empty() {
+        line(format_args!("[BOOT] Command line: {}", BUILTIN_CMDLINE));
+    }
+}
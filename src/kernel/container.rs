@@ -0,0 +1,83 @@
+// Lightweight containers
+//
+// A container is a task forked from the caller into a new PID namespace
+// (where it is pid 1) and a new mount namespace, chrooted into a directory
+// and confined by a QSF profile. Built-ins run inline, so `run` enters the
+// task's context - current pid, mount table, VFS root and working
+// directory - for the length of the work, then puts the caller's back and
+// reaps the task. It takes SCHEDULER, QSF and VFS in that order, so none
+// may be held.
+
+use alloc::string::String;
+use crate::fs::vfs::VFS;
+use crate::kernel::scheduler::{Pid, SCHEDULER};
+use crate::kernel::sys::errno::{Errno, SysResult};
+use crate::qsf::{Capability, QSF};
+
+/// Run `work` as a new task called `name` inside a container rooted at the
+/// directory `root` and confined by `profile`, returning its exit status.
+pub fn run(name: &str, root: &str, profile: &str, work: impl FnOnce() -> i32) -> SysResult<i32> {
+    let root = {
+        let vfs = VFS.lock();
+        let root = vfs.resolve_path(root);
+        if !vfs.lookup_resolved(&root)?.is_dir() {
+            return Err(Errno::ENOTDIR);
+        }
+        root
+    };
+
+    let pid = spawn(name, root.clone(), profile)?;
+
+    let saved = {
+        let mut vfs = VFS.lock();
+        let saved = (String::from(vfs.get_root()), String::from(vfs.get_cwd()));
+        vfs.restore_root(root, String::from("/"));
+        saved
+    };
+    let previous = SCHEDULER.lock().make_current(Some(pid));
+
+    let status = work();
+
+    SCHEDULER.lock().make_current(previous);
+    VFS.lock().restore_root(saved.0, saved.1);
+    reap(pid, status);
+    Ok(status)
+}
+
+/// Fork the container task from the current one, returning its pid
+fn spawn(name: &str, root: String, profile: &str) -> SysResult<Pid> {
+    let mut scheduler = SCHEDULER.lock();
+    let parent = scheduler.current().ok_or(Errno::ESRCH)?.clone();
+    let mut qsf = QSF.lock();
+    if !qsf.check_process_capability(parent.pid, parent.euid, Capability::CapSysAdmin) {
+        return Err(Errno::EPERM);
+    }
+    if !qsf.confinement().profiles().any(|p| p.name == profile) {
+        return Err(Errno::ENOENT);
+    }
+
+    let pid_ns = scheduler.pid_namespaces.create(parent.pid_ns).ok_or(Errno::ENOSPC)?;
+    let pid = scheduler.allocate_pid().ok_or(Errno::EAGAIN)?;
+    let mut task = parent.fork(pid).map_err(|_| Errno::ENOMEM)?;
+    task.name = String::from(name);
+    task.pid_ns = pid_ns;
+    scheduler.number_in_namespace(&mut task);
+    task.mnt_ns = crate::fs::mount::clone_namespace(parent.mnt_ns);
+    task.root = root;
+    task.cwd = String::from("/");
+    scheduler.add_task(task);
+
+    qsf.on_fork(parent.pid, pid);
+    qsf.confine_to_profile(pid, profile).map_err(|_| Errno::ENOENT)?;
+    Ok(pid)
+}
+
+/// Exit and reap the container task, dropping its namespaces
+fn reap(pid: Pid, status: i32) {
+    let mut scheduler = SCHEDULER.lock();
+    scheduler.reparent_children(pid);
+    if let Some(task) = scheduler.get_task_mut(pid) {
+        task.exit(status);
+    }
+    scheduler.remove_zombie(pid);
+}
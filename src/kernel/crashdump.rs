@@ -0,0 +1,307 @@
+// Kernel crash dumps (kdump-lite)
+//
+// With `crashdump=ataN[:LBA]` on the command line, a panic writes a plain
+// text report - the panic message, backtrace, registers, task list, heap
+// stats, the screen and the tail of the kernel log - to a region of that
+// disk set aside for it: DUMP_SECTORS sectors from LBA, or the last
+// DUMP_SECTORS of the disk if no LBA is given. The first sector is a
+// header with a magic number, the report's length and CRC-32 and the time
+// of the crash; the `crashdump` command reads it back after the reboot.
+// The panic path builds the report in a static buffer and only try-locks,
+// so a panic taken with the heap or the scheduler locked still leaves a
+// dump.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use spin::Mutex;
+use crate::fs::archive::gzip::crc32;
+use crate::hal::drivers::ata::{AtaError, Drive, SECTOR_SIZE};
+use crate::kernel::scheduler::{TaskState, SCHEDULER};
+use crate::kernel::time::{self, DateTime};
+
+pub const PARAM: &str = "crashdump";
+
+/// Size of the dump region, header included
+pub const DUMP_SECTORS: usize = 128;
+
+/// Deepest backtrace recorded
+pub const MAX_FRAMES: usize = 32;
+
+const MAGIC: &[u8; 8] = b"QUNIXDMP";
+const VERSION: u32 = 1;
+const REPORT_MAX: usize = (DUMP_SECTORS - 1) * SECTOR_SIZE;
+
+/// A frame pointer further than this above the last one ends the walk
+const MAX_FRAME_SIZE: usize = 1 << 20;
+
+/// Where dumps go
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+    pub drive: Drive,
+    pub lba: u64,
+}
+
+static REGION: Mutex<Option<Region>> = Mutex::new(None);
+
+/// The report is built here rather than on the heap, which may be what
+/// panicked
+static REPORT: Mutex<[u8; REPORT_MAX]> = Mutex::new([0; REPORT_MAX]);
+
+/// Read `crashdump=` and check the region it names
+pub fn init() {
+    let value = match super::get_param(PARAM) {
+        Some(value) => value,
+        None => return,
+    };
+    match configure(&value) {
+        Ok(region) => {
+            crate::println!("  [KERNEL] Crash dumps go to {} sectors {}-{}",
+                region.drive.device_name(), region.lba, region.lba + DUMP_SECTORS as u64 - 1);
+            *REGION.lock() = Some(region);
+        }
+        Err(e) => crate::println!("  [KERNEL] {}={}: crash dumps disabled ({:?})", PARAM, value, e),
+    }
+}
+
+fn configure(value: &str) -> Result<Region, AtaError> {
+    let (drive, lba) = match value.split_once(':') {
+        Some((drive, lba)) => (drive, Some(lba.parse::<u64>().map_err(|_| AtaError::OutOfRange)?)),
+        None => (value, None),
+    };
+    let drive = Drive::from_name(drive).ok_or(AtaError::NoDevice)?;
+    let sectors = drive.identify()?;
+    let lba = match lba {
+        Some(lba) => lba,
+        None => sectors.checked_sub(DUMP_SECTORS as u64).ok_or(AtaError::OutOfRange)?,
+    };
+    if lba + DUMP_SECTORS as u64 > sectors {
+        return Err(AtaError::OutOfRange);
+    }
+    Ok(Region { drive, lba })
+}
+
+/// The configured dump region, if any
+pub fn region() -> Option<Region> {
+    *REGION.lock()
+}
+
+/// The header sector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub length: usize,
+    pub crc: u32,
+    /// Unix time of the crash
+    pub time: u64,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; SECTOR_SIZE] {
+        let mut sector = [0u8; SECTOR_SIZE];
+        sector[..8].copy_from_slice(MAGIC);
+        sector[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        sector[12..16].copy_from_slice(&(self.length as u32).to_le_bytes());
+        sector[16..20].copy_from_slice(&self.crc.to_le_bytes());
+        sector[20..28].copy_from_slice(&self.time.to_le_bytes());
+        sector
+    }
+
+    fn decode(sector: &[u8]) -> Option<Header> {
+        let word = |at: usize| u32::from_le_bytes([sector[at], sector[at + 1], sector[at + 2], sector[at + 3]]);
+        if sector.len() < 28 || &sector[..8] != MAGIC || word(8) != VERSION {
+            return None;
+        }
+        let mut time = [0u8; 8];
+        time.copy_from_slice(&sector[20..28]);
+        Some(Header {
+            length: (word(12) as usize).min(REPORT_MAX),
+            crc: word(16),
+            time: u64::from_le_bytes(time),
+        })
+    }
+}
+
+/// A dump read back from disk
+pub struct Dump {
+    pub header: Header,
+    pub report: Vec<u8>,
+}
+
+impl Dump {
+    /// Whether the report still matches its checksum
+    pub fn intact(&self) -> bool {
+        crc32(&self.report) == self.header.crc
+    }
+}
+
+/// The dump in `region`, or None if it holds none
+pub fn read(region: Region) -> Result<Option<Dump>, AtaError> {
+    let mut data = vec![0u8; DUMP_SECTORS * SECTOR_SIZE];
+    region.drive.read_sectors(region.lba, &mut data)?;
+    Ok(Header::decode(&data[..SECTOR_SIZE]).map(|header| Dump {
+        header,
+        report: data[SECTOR_SIZE..SECTOR_SIZE + header.length].to_vec(),
+    }))
+}
+
+/// Erase the header so the dump is not reported again
+pub fn clear(region: Region) -> Result<(), AtaError> {
+    region.drive.write_sectors(region.lba, &[0u8; SECTOR_SIZE])
+}
+
+/// Return addresses up the frame-pointer chain, newest first. The kernel
+/// is built with frame pointers (see .cargo/config.toml); the walk stops
+/// at a null or misaligned one, or one that does not move a little way up
+/// the stack.
+#[inline(never)]
+pub fn backtrace(frames: &mut [usize]) -> usize {
+    let mut rbp: usize;
+    unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp) };
+    let mut depth = 0;
+    while depth < frames.len() && rbp != 0 && rbp % 8 == 0 {
+        let (next, ret) = unsafe { (*(rbp as *const usize), *((rbp + 8) as *const usize)) };
+        if ret == 0 {
+            break;
+        }
+        frames[depth] = ret;
+        depth += 1;
+        if next <= rbp || next - rbp > MAX_FRAME_SIZE {
+            break;
+        }
+        rbp = next;
+    }
+    depth
+}
+
+/// Print a backtrace to the screen and the serial port
+pub fn print_backtrace(frames: &[usize]) {
+    crate::println!("Backtrace:");
+    crate::serial_println!("Backtrace:");
+    for (n, addr) in frames.iter().enumerate() {
+        crate::println!("  #{:<2} {:#018x}", n, addr);
+        crate::serial_println!("  #{:<2} {:#018x}", n, addr);
+    }
+}
+
+/// Writes into a fixed buffer, dropping whatever does not fit
+struct Cursor<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for Cursor<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Formatting a derived Debug would need the heap
+fn state_name(state: TaskState) -> &'static str {
+    match state {
+        TaskState::Ready => "Ready",
+        TaskState::Running => "Running",
+        TaskState::Blocked => "Blocked",
+        TaskState::Sleeping => "Sleeping",
+        TaskState::Zombie => "Zombie",
+        TaskState::Stopped => "Stopped",
+    }
+}
+
+fn write_report(out: &mut Cursor, info: &PanicInfo, frames: &[usize], now: u64) -> fmt::Result {
+    writeln!(out, "Qunix {} crash dump", crate::QUNIX_VERSION)?;
+    writeln!(out, "Time: {} (uptime {} ms)", DateTime::from_unix(now), crate::hal::drivers::pit::get_ticks())?;
+    writeln!(out, "{}", info)?;
+
+    writeln!(out, "\nBacktrace:")?;
+    for (n, addr) in frames.iter().enumerate() {
+        writeln!(out, "  #{:<2} {:#018x}", n, addr)?;
+    }
+
+    let (rsp, rbp): (u64, u64);
+    unsafe {
+        core::arch::asm!("mov {}, rsp", out(reg) rsp);
+        core::arch::asm!("mov {}, rbp", out(reg) rbp);
+    }
+    use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+    writeln!(out, "\nRegisters (in the panic handler):")?;
+    writeln!(out, "  RSP={:#018x} RBP={:#018x} RFLAGS={:#018x}", rsp, rbp, x86_64::registers::rflags::read_raw())?;
+    writeln!(out, "  CR0={:#018x} CR2={:#018x} CR3={:#018x} CR4={:#018x}",
+        Cr0::read_raw(), Cr2::read_raw(), Cr3::read().0.start_address().as_u64(), Cr4::read_raw())?;
+
+    writeln!(out, "\nTasks:")?;
+    match SCHEDULER.try_lock() {
+        Some(scheduler) => {
+            writeln!(out, "  {:>5} {:>5} {:<9} NAME", "PID", "PPID", "STATE")?;
+            for task in scheduler.get_tasks() {
+                let current = if scheduler.current_pid() == Some(task.pid) { " (current)" } else { "" };
+                writeln!(out, "  {:>5} {:>5} {:<9} {}{}", task.pid, task.ppid.unwrap_or(0),
+                    state_name(task.state), task.name, current)?;
+            }
+        }
+        None => writeln!(out, "  (scheduler locked)")?,
+    }
+
+    match crate::hal::memory::heap::try_heap_stats() {
+        Some(heap) => writeln!(out, "\nHeap: {} used, {} free of {} bytes", heap.used, heap.free, heap.total)?,
+        None => writeln!(out, "\nHeap: (allocator locked)")?,
+    }
+
+    writeln!(out, "\nScreen:")?;
+    match super::screendump::capture() {
+        Some(screen) => super::screendump::write_text(&screen, out)?,
+        None => writeln!(out, "  (console locked)")?,
+    }
+
+    writeln!(out, "\nKernel log:")?;
+    let room = out.buf.len() - out.len;
+    let start = out.len;
+    let copied = super::klog::try_with(|ring| ring.copy_tail(&mut out.buf[start..start + room]));
+    match copied {
+        Some(n) => out.len += n,
+        None => writeln!(out, "  (log locked)")?,
+    }
+    Ok(())
+}
+
+/// Write the crash report for `info`. Ok(None) if no region is configured.
+pub fn save(info: &PanicInfo, frames: &[usize]) -> Result<Option<Region>, AtaError> {
+    let region = match REGION.try_lock().and_then(|region| *region) {
+        Some(region) => region,
+        None => return Ok(None),
+    };
+    let mut report = match REPORT.try_lock() {
+        Some(report) => report,
+        // A panic while saving: leave the first dump alone
+        None => return Ok(None),
+    };
+
+    let now = time::now();
+    let mut cursor = Cursor { buf: &mut report[..], len: 0 };
+    write_report(&mut cursor, info, frames, now).ok();
+    let length = cursor.len;
+    let header = Header { length, crc: crc32(&report[..length]), time: now };
+
+    // Report sectors first, so a dump cut short never has a valid header
+    let sectors = length.div_ceil(SECTOR_SIZE).max(1);
+    report[length..sectors * SECTOR_SIZE].fill(0);
+    region.drive.write_sectors(region.lba + 1, &report[..sectors * SECTOR_SIZE])?;
+    region.drive.write_sectors(region.lba, &header.encode())?;
+    Ok(Some(region))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_header_round_trip() {
+        let header = Header { length: 1234, crc: 0xDEAD_BEEF, time: 1_700_000_000 };
+        let sector = header.encode();
+        assert_eq!(Header::decode(&sector), Some(header));
+        assert_eq!(Header::decode(&[0u8; SECTOR_SIZE]), None);
+    }
+}
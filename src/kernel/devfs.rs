@@ -0,0 +1,89 @@
+// /dev nodes and removable media, kept in step with hotplug events
+//
+// Every device in the registry gets a node under /dev, created when its
+// driver registers it and removed when the driver lets it go. SD cards,
+// and the partitions on them, holding an ext4 or FAT32 filesystem are
+// also mounted on /media/<name> when they arrive and unmounted when they
+// are pulled.
+// Both handlers take VFS, and the automounter the mount table, so they
+// run from hotplug::process() and never from a driver.
+
+use alloc::format;
+#[cfg(feature = "sdhci")]
+use crate::fs::FsError;
+use crate::fs::FileMode;
+use crate::fs::vfs::VFS;
+use crate::hal::drivers::hotplug::{self, Action, Event};
+
+#[cfg(feature = "sdhci")]
+const MEDIA: &str = "/media";
+
+fn device_nodes(event: &Event) {
+    let (device, mode) = match (event.device, event.subsystem) {
+        (Some(id), "block") => (id, FileMode::S_IFBLK | 0o660),
+        (Some(id), "char") => (id, FileMode::S_IFCHR | 0o666),
+        _ => return,
+    };
+    let path = format!("/dev/{}", event.name);
+    let mut vfs = VFS.lock();
+    // A name registered again may come back with another number
+    vfs.remove_file(&path).ok();
+    if event.action == Action::Add {
+        // Names such as input/kbd0 go in a directory of their own
+        if let Some((dir, _)) = path.rsplit_once('/').filter(|(dir, _)| *dir != "/dev") {
+            vfs.create_directory(dir, FileMode::new(0o755)).ok();
+        }
+        vfs.create_device(&path, device, FileMode::new(mode)).ok();
+    }
+}
+
+#[cfg(feature = "sdhci")]
+fn removable_media(event: &Event) {
+    use crate::fs::mount::{self, MountFlags};
+    use crate::hal::block;
+    use crate::hal::drivers::sdhci;
+
+    if event.subsystem != "block" {
+        return;
+    }
+    let target = format!("{}/{}", MEDIA, event.name);
+    if event.action == Action::Remove {
+        // The card is gone, so there is nothing to write back
+        if mount::umount(&target).is_ok() {
+            let mut vfs = VFS.lock();
+            vfs.ungraft(&target).ok();
+            vfs.remove_directory(&target).ok();
+            crate::println!("  [HOTPLUG] Unmounted {}", target);
+        }
+        return;
+    }
+
+    let disk = block::parent(&event.name).map_or_else(|| event.name.clone(), |(disk, _)| disk);
+    if sdhci::card(&disk).is_none() {
+        return;
+    }
+    {
+        let mut vfs = VFS.lock();
+        vfs.create_directory(MEDIA, FileMode::new(0o755)).ok();
+        vfs.create_directory(&target, FileMode::new(0o755)).ok();
+    }
+    let source = format!("/dev/{}", event.name);
+    match block::mount(&source, &target, None, MountFlags::empty()) {
+        Ok(fs_type) => crate::println!("  [HOTPLUG] Mounted {} on {} ({})", source, target, fs_type),
+        Err(e) => {
+            VFS.lock().remove_directory(&target).ok();
+            match e {
+                FsError::InvalidArgument => crate::println!("  [HOTPLUG] {}: no filesystem to mount", event.name),
+                e => crate::println!("  [HOTPLUG] {}: cannot mount on {}: {:?}", source, target, e),
+            }
+        }
+    }
+}
+
+/// Subscribe to hotplug and deliver what the drivers found at boot
+pub fn init() {
+    hotplug::subscribe(device_nodes);
+    #[cfg(feature = "sdhci")]
+    hotplug::subscribe(removable_media);
+    hotplug::process();
+}
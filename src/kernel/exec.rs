@@ -0,0 +1,278 @@
+// Program loading
+//
+// Builds the address space execve runs a program in: each PT_LOAD segment
+// of a static ELF64 x86-64 executable copied to where it asks to be, the
+// rest of its memory size zeroed, writable only if the segment is and
+// executable only if it is, so text is read-execute and data read-write;
+// then a user stack at the top of the user
+// window holding, as the System V ABI lays it out from the stack pointer
+// up, argc, the argv pointers, a null, the envp pointers, a null and an
+// empty auxiliary vector, the strings themselves above them. The program
+// break starts at the page after the highest segment.
+//
+// Segments must lie in the user window (address_space::USER_BASE up);
+// position-independent executables and an interpreter are not supported.
+// W^X: a segment both writable and executable, or a page a writable
+// segment shares with an executable one, is refused unless the task has
+// opted out (prctl PR_SET_WX_ALLOWED, with CAP_SYS_ADMIN). Pages without
+// EXECUTE are NX.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::hal::memory::address_space::{AddressSpace, USER_BASE, USER_STACK_SIZE, USER_STACK_TOP};
+use crate::hal::memory::mmu::{self, ProtectionFlags, PAGE_SIZE};
+use crate::kernel::sys::Errno;
+
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 62;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+/// Most bytes the argument and environment strings may take, pointers
+/// included, so they leave most of the stack to the program
+pub const ARG_MAX: usize = USER_STACK_SIZE as usize / 4;
+
+/// A program ready to run
+pub struct Image {
+    pub space: AddressSpace,
+    pub entry: u64,
+    pub stack: u64,
+    /// The initial program break
+    pub brk: u64,
+}
+
+fn bytes_at<const N: usize>(data: &[u8], at: usize) -> Result<[u8; N], Errno> {
+    let end = at.checked_add(N).ok_or(Errno::ENOEXEC)?;
+    let bytes = data.get(at..end).ok_or(Errno::ENOEXEC)?;
+    Ok(bytes.try_into().unwrap())
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16, Errno> {
+    bytes_at(data, at).map(u16::from_le_bytes)
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32, Errno> {
+    bytes_at(data, at).map(u32::from_le_bytes)
+}
+
+fn u64_at(data: &[u8], at: usize) -> Result<u64, Errno> {
+    bytes_at(data, at).map(u64::from_le_bytes)
+}
+
+/// A PT_LOAD segment
+pub struct Segment<'a> {
+    pub vaddr: u64,
+    pub memsz: u64,
+    /// What goes at `vaddr`; the rest of `memsz` is zeroed
+    pub contents: &'a [u8],
+    pub flags: u32,
+}
+
+impl Segment<'_> {
+    pub fn writable(&self) -> bool {
+        self.flags & PF_W != 0
+    }
+
+    pub fn executable(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+
+    /// The pages it covers
+    pub fn pages(&self) -> core::ops::Range<u64> {
+        mmu::page_align_down(self.vaddr)..mmu::page_align_up(self.vaddr + self.memsz)
+    }
+
+    /// What its pages are mapped with: readable, and writable and
+    /// executable as it says
+    fn prot(&self) -> ProtectionFlags {
+        let mut prot = ProtectionFlags::READ;
+        if self.writable() {
+            prot = prot | ProtectionFlags::WRITE;
+        }
+        if self.executable() {
+            prot = prot | ProtectionFlags::EXECUTE;
+        }
+        prot
+    }
+}
+
+/// The entry point and PT_LOAD segments of the static ELF64 x86-64
+/// executable `data`, wherever they ask to be
+pub fn segments(data: &[u8]) -> Result<(u64, Vec<Segment<'_>>), Errno> {
+    // 64-bit, little-endian, version 1
+    if data.get(..7) != Some(b"\x7fELF\x02\x01\x01") {
+        return Err(Errno::ENOEXEC);
+    }
+    if u16_at(data, 16)? != ET_EXEC || u16_at(data, 18)? != EM_X86_64 {
+        return Err(Errno::ENOEXEC);
+    }
+    let entry = u64_at(data, 24)?;
+    let phoff = u64_at(data, 32)? as usize;
+    let phentsize = u16_at(data, 54)? as usize;
+    let phnum = u16_at(data, 56)? as usize;
+
+    let mut segments = Vec::new();
+    for index in 0..phnum {
+        let header = phoff.checked_add(index * phentsize).ok_or(Errno::ENOEXEC)?;
+        if u32_at(data, header)? != PT_LOAD {
+            continue;
+        }
+        let flags = u32_at(data, header.saturating_add(4))?;
+        let offset = u64_at(data, header.saturating_add(8))? as usize;
+        let vaddr = u64_at(data, header.saturating_add(16))?;
+        let filesz = u64_at(data, header.saturating_add(32))? as usize;
+        let memsz = u64_at(data, header.saturating_add(40))?;
+        if memsz < filesz as u64 || vaddr.checked_add(memsz).is_none_or(|end| end > u64::MAX - PAGE_SIZE as u64) {
+            return Err(Errno::ENOEXEC);
+        }
+        let contents = data.get(offset..offset.checked_add(filesz).ok_or(Errno::ENOEXEC)?).ok_or(Errno::ENOEXEC)?;
+        segments.push(Segment { vaddr, memsz, contents, flags });
+    }
+    if !segments.iter().any(|segment| segment.executable() && (segment.vaddr..segment.vaddr + segment.memsz).contains(&entry)) {
+        return Err(Errno::ENOEXEC);
+    }
+    Ok((entry, segments))
+}
+
+/// Whether any page of `segments` would be both writable and executable,
+/// a page two of them share getting the rights of both
+fn maps_writable_executable(segments: &[Segment]) -> bool {
+    segments.iter().enumerate().any(|(i, a)| {
+        a.prot().is_writable_executable() || segments[i + 1..].iter().any(|b| {
+            let (x, y) = (a.pages(), b.pages());
+            x.start < y.end && y.start < x.end && (a.prot() | b.prot()).is_writable_executable()
+        })
+    })
+}
+
+/// Load the executable `data` into a new address space, with `args` and
+/// `env` on its stack. Unless `allow_wx`, one that would have memory both
+/// writable and executable is refused with EACCES.
+pub fn load(data: &[u8], args: &[String], env: &[String], allow_wx: bool) -> Result<Image, Errno> {
+    let (entry, segments) = segments(data)?;
+    if !allow_wx && maps_writable_executable(&segments) {
+        return Err(Errno::EACCES);
+    }
+    let mut space = AddressSpace::new()?;
+    let mut brk = USER_BASE;
+    for segment in &segments {
+        let pages = segment.pages();
+        space.map(pages.start, pages.end - pages.start, segment.prot())?;
+        space.write(segment.vaddr, segment.contents)?;
+        brk = brk.max(pages.end);
+    }
+
+    let stack = build_stack(&mut space, args, env)?;
+    Ok(Image { space, entry, stack, brk })
+}
+
+/// Map the user stack and lay out argc, argv and envp on it, returning
+/// the stack pointer to start with
+fn build_stack(space: &mut AddressSpace, args: &[String], env: &[String]) -> Result<u64, Errno> {
+    let strings: usize = args.iter().chain(env).map(|s| s.len() + 1).sum();
+    let pointers = (args.len() + env.len() + 5) * 8;
+    if strings + pointers > ARG_MAX {
+        return Err(Errno::E2BIG);
+    }
+    let base = USER_STACK_TOP - USER_STACK_SIZE;
+    space.map(base, USER_STACK_SIZE, ProtectionFlags::READ | ProtectionFlags::WRITE)?;
+
+    // Strings at the top, then the vectors pointing at them
+    let mut top = USER_STACK_TOP;
+    let mut place = |s: &String| -> Result<u64, Errno> {
+        top -= s.len() as u64 + 1;
+        let mut bytes = Vec::with_capacity(s.len() + 1);
+        bytes.extend_from_slice(s.as_bytes());
+        bytes.push(0);
+        space.write(top, &bytes)?;
+        Ok(top)
+    };
+    let argv = args.iter().map(&mut place).collect::<Result<Vec<_>, _>>()?;
+    let envp = env.iter().map(&mut place).collect::<Result<Vec<_>, _>>()?;
+
+    let mut words = Vec::with_capacity(pointers / 8);
+    words.push(args.len() as u64);
+    words.extend(&argv);
+    words.push(0);
+    words.extend(&envp);
+    words.push(0);
+    // AT_NULL
+    words.extend([0, 0]);
+
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let stack = (top - bytes.len() as u64) & !0xf;
+    debug_assert!(stack >= base + PAGE_SIZE as u64);
+    space.write(stack, &bytes)?;
+    Ok(stack)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PF_R: u32 = 4;
+
+    /// An executable entered at `entry` with a segment holding `code` at
+    /// each of `segments`, as (address, flags)
+    fn elf_with(entry: u64, segments: &[(u64, u32)], code: &[u8]) -> Vec<u8> {
+        let headers = 64 + 56 * segments.len();
+        let mut data = alloc::vec![0u8; headers];
+        data[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+        data[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        data[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+        data[24..32].copy_from_slice(&entry.to_le_bytes());
+        data[32..40].copy_from_slice(&64u64.to_le_bytes());
+        data[54..56].copy_from_slice(&56u16.to_le_bytes());
+        data[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        for (i, &(vaddr, flags)) in segments.iter().enumerate() {
+            let header = &mut data[64 + 56 * i..120 + 56 * i];
+            header[..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+            header[4..8].copy_from_slice(&flags.to_le_bytes());
+            header[8..16].copy_from_slice(&(headers as u64).to_le_bytes());
+            header[16..24].copy_from_slice(&vaddr.to_le_bytes());
+            header[32..40].copy_from_slice(&(code.len() as u64).to_le_bytes());
+            header[40..48].copy_from_slice(&(code.len() as u64).to_le_bytes());
+        }
+        data.extend_from_slice(code);
+        data
+    }
+
+    /// An executable with one segment holding `code` at `vaddr`
+    fn elf(vaddr: u64, code: &[u8]) -> Vec<u8> {
+        elf_with(vaddr, &[(vaddr, PF_X | PF_R)], code)
+    }
+
+    #[test_case]
+    fn test_load_lays_out_the_stack() {
+        let args = [String::from("prog"), String::from("-v")];
+        let image = load(&elf(USER_BASE + 0x1000, &[0xf4]), &args, &[], false).unwrap();
+        assert_eq!(image.entry, USER_BASE + 0x1000);
+        assert_eq!(image.stack % 16, 0);
+        assert_eq!(image.brk, USER_BASE + 0x2000);
+        assert!(image.stack < USER_STACK_TOP && image.stack > USER_STACK_TOP - USER_STACK_SIZE);
+    }
+
+    #[test_case]
+    fn test_load_refuses_what_it_cannot_run() {
+        let refused = |data: &[u8]| load(data, &[], &[], false).err();
+        assert_eq!(refused(b"#!/bin/sh\n"), Some(Errno::ENOEXEC));
+        // Outside the user window
+        assert_eq!(refused(&elf(0x40_0000, &[0xf4])), Some(Errno::ENOEXEC));
+        let mut truncated = elf(USER_BASE, &[0xf4]);
+        truncated.truncate(100);
+        assert_eq!(refused(&truncated), Some(Errno::ENOEXEC));
+    }
+
+    #[test_case]
+    fn test_load_enforces_wx() {
+        let text = USER_BASE + 0x1000;
+        let loads = |segments: &[(u64, u32)], allow_wx: bool| load(&elf_with(text, segments, &[0xf4]), &[], &[], allow_wx).map(|_| ());
+        let rwx = [(text, PF_R | PF_W | PF_X)];
+        assert_eq!(loads(&rwx, false), Err(Errno::EACCES));
+        assert_eq!(loads(&rwx, true), Ok(()));
+        // Text and data a page apart, and sharing one
+        assert_eq!(loads(&[(text, PF_R | PF_X), (text + 0x1000, PF_R | PF_W)], false), Ok(()));
+        assert_eq!(loads(&[(text, PF_R | PF_X), (text + 0x800, PF_R | PF_W)], false), Err(Errno::EACCES));
+    }
+}
@@ -0,0 +1,261 @@
+// Open files
+//
+// An open file is what open(2) makes and a descriptor refers to: the node
+// it opened, by inode rather than by path, the offset reads and writes
+// move, and the status flags it was opened with. dup, dup2, fork and
+// posix_spawn copy descriptors, not open files, so the copies share one
+// offset as POSIX has it; only close-on-exec belongs to the descriptor.
+//
+// Holding the inode means a file renamed after it was opened is still the
+// file read and written, and one unlinked stays usable until the last
+// descriptor on it is closed, when the VFS drops it; see
+// VirtualFileSystem::hold. Closing takes the VFS lock, so a descriptor
+// must never be dropped with it held.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use spin::Mutex;
+use crate::fs::vfs::api::OpenFlags;
+use crate::fs::vfs::node::{DeviceId, InodeNumber};
+use crate::fs::vfs::vfs::VFS;
+use crate::fs::vfs::{VfsNode, VfsNodeData};
+use crate::hal::drivers::tty;
+use crate::kernel::pipe::{self, End};
+use crate::kernel::sys::posix::PosixStat;
+use crate::kernel::sys::{Errno, SysResult};
+
+pub const SEEK_SET: i32 = 0;
+pub const SEEK_CUR: i32 = 1;
+pub const SEEK_END: i32 = 2;
+
+#[derive(Debug)]
+enum Node {
+    Inode { inode: InodeNumber, dir: bool },
+    Pipe(End),
+    /// A standard stream made before there was a VFS to open it in,
+    /// which is the console
+    Console,
+}
+
+#[derive(Debug)]
+pub struct OpenFile {
+    /// Where it was opened, as VirtualFileSystem::resolve_path gives it,
+    /// or a pipe's name; only a name, as the node may have moved since
+    path: String,
+    node: Node,
+    /// Access mode and status flags, without O_CLOEXEC
+    flags: u32,
+    offset: Mutex<u64>,
+}
+
+/// Whether `node` is the console's device
+fn is_console_node(node: &VfsNode) -> bool {
+    matches!(&node.data, VfsNodeData::Device(dev) if dev.major == 1)
+}
+
+/// The terminal `node` opens, if it is one
+pub fn terminal_of(node: &VfsNode) -> Option<DeviceId> {
+    match &node.data {
+        VfsNodeData::Device(dev) => tty::terminal(*dev),
+        _ => None,
+    }
+}
+
+impl OpenFile {
+    fn new(path: String, node: Node, flags: u32) -> Arc<Self> {
+        let flags = flags & !OpenFlags::O_CLOEXEC.bits();
+        Arc::new(OpenFile { path, node, flags, offset: Mutex::new(0) })
+    }
+
+    /// Open the node `inode`, found at `path`, holding it in the VFS
+    pub fn open(inode: InodeNumber, path: String, flags: u32, dir: bool) -> Arc<Self> {
+        VFS.lock().hold(inode);
+        Self::new(path, Node::Inode { inode, dir }, flags)
+    }
+
+    /// Open a pipe end
+    pub fn pipe(end: End, flags: u32) -> Arc<Self> {
+        Self::new(end.name(), Node::Pipe(end), flags)
+    }
+
+    /// A standard stream on the console, named `path`
+    pub fn console(path: &str, flags: u32) -> Arc<Self> {
+        Self::new(String::from(path), Node::Console, flags)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn offset(&self) -> u64 {
+        *self.offset.lock()
+    }
+
+    fn is_nonblocking(&self) -> bool {
+        self.flags & OpenFlags::O_NONBLOCK.bits() != 0
+    }
+
+    /// The node open, unless it is a pipe or the console
+    pub fn inode(&self) -> Option<InodeNumber> {
+        match self.node {
+            Node::Inode { inode, .. } => Some(inode),
+            _ => None,
+        }
+    }
+
+    /// The directory open, for fchdir; None unless it is one
+    pub fn dir(&self) -> Option<InodeNumber> {
+        match self.node {
+            Node::Inode { inode, dir: true } => Some(inode),
+            _ => None,
+        }
+    }
+
+    pub fn is_pipe(&self) -> bool {
+        matches!(self.node, Node::Pipe(_))
+    }
+
+    /// Whether it writes to the console
+    pub fn is_console(&self) -> bool {
+        match self.node {
+            Node::Inode { inode, .. } => VFS.lock().get_node(inode).is_ok_and(is_console_node),
+            Node::Pipe(_) => false,
+            Node::Console => true,
+        }
+    }
+
+    /// The terminal open, if it is one
+    pub fn terminal(&self) -> Option<DeviceId> {
+        match self.node {
+            Node::Inode { inode, .. } => VFS.lock().get_node(inode).ok().and_then(terminal_of),
+            Node::Pipe(_) => None,
+            Node::Console => Some(tty::CONSOLE),
+        }
+    }
+
+    /// The device node open, if it is one
+    pub fn device(&self) -> Option<DeviceId> {
+        match self.node {
+            Node::Inode { inode, .. } => match &VFS.lock().get_node(inode).ok()?.data {
+                VfsNodeData::Device(dev) => Some(*dev),
+                _ => None,
+            },
+            Node::Pipe(_) | Node::Console => None,
+        }
+    }
+
+    /// Read into `buf` at the offset, moving it past what was read
+    pub fn read(&self, buf: &mut [u8]) -> SysResult<usize> {
+        let inode = match &self.node {
+            Node::Inode { inode, .. } => *inode,
+            Node::Pipe(end) => return end.read(buf, self.is_nonblocking()),
+            Node::Console => return Err(Errno::EINVAL),
+        };
+        let mut offset = self.offset.lock();
+        let read = VFS.lock().get_node(inode)?.read(*offset, buf)?;
+        *offset += read as u64;
+        Ok(read)
+    }
+
+    /// Write `data` at the offset, or the end for O_APPEND, moving the
+    /// offset past what was written; the console takes it all
+    pub fn write(&self, data: &[u8]) -> SysResult<usize> {
+        let inode = match &self.node {
+            Node::Inode { inode, .. } => *inode,
+            Node::Pipe(end) => return end.write(data, self.is_nonblocking()),
+            Node::Console => {
+                tty::write_console(data);
+                return Ok(data.len());
+            }
+        };
+        let mut offset = self.offset.lock();
+        let mut vfs = VFS.lock();
+        let node = vfs.get_node(inode)?;
+        if is_console_node(node) {
+            tty::write_console(data);
+            return Ok(data.len());
+        }
+        if self.flags & OpenFlags::O_APPEND.bits() != 0 {
+            *offset = node.size;
+        }
+        let written = vfs.write_node(inode, *offset, data)?;
+        *offset += written as u64;
+        Ok(written)
+    }
+
+    /// Move the offset as lseek(2) does, returning where it ends up
+    pub fn seek(&self, to: i64, whence: i32) -> SysResult<u64> {
+        let inode = match self.node {
+            Node::Inode { inode, .. } => inode,
+            _ => return Err(Errno::ESPIPE),
+        };
+        let mut offset = self.offset.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *offset as i64,
+            SEEK_END => VFS.lock().get_node(inode)?.size as i64,
+            _ => return Err(Errno::EINVAL),
+        };
+        match base.checked_add(to) {
+            Some(to) if to >= 0 => {
+                *offset = to as u64;
+                Ok(*offset)
+            }
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
+    pub fn stat(&self) -> SysResult<PosixStat> {
+        let stat = match &self.node {
+            Node::Inode { inode, .. } => VFS.lock().get_node(*inode)?.stat(),
+            Node::Pipe(end) => return Ok(PosixStat {
+                st_mode: (crate::fs::FileMode::S_IFIFO | 0o600) as u32,
+                st_nlink: 1,
+                st_size: end.available() as i64,
+                st_blksize: pipe::PIPE_SIZE as i64,
+                ..Default::default()
+            }),
+            Node::Console => VFS.lock().lookup_resolved(&self.path)?.stat(),
+        };
+        Ok(PosixStat::from(stat))
+    }
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        if let Node::Inode { inode, .. } = self.node {
+            VFS.lock().unhold(inode);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::FileMode;
+
+    #[test_case]
+    fn test_shared_offset_survives_unlink() {
+        let inode = VFS.lock().create_file("/openfile-test", FileMode::new(0o644)).unwrap().inode;
+        let file = OpenFile::open(inode, String::from("/openfile-test"), OpenFlags::O_RDWR.bits(), false);
+        let dup = file.clone();
+        assert_eq!(file.write(b"hello"), Ok(5));
+        assert_eq!(dup.write(b" world"), Ok(6));
+        assert_eq!(dup.seek(-5, SEEK_END), Ok(6));
+
+        VFS.lock().remove_file("/openfile-test").unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(file.read(&mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"world");
+        assert_eq!(file.seek(-1, SEEK_SET), Err(Errno::EINVAL));
+        assert_eq!(file.stat().map(|stat| stat.st_nlink), Ok(0));
+
+        drop(file);
+        drop(dup);
+        assert!(VFS.lock().get_node(inode).is_err());
+    }
+}
@@ -0,0 +1,385 @@
+// Logins on the virtual terminals (getty)
+//
+// tty1 to tty6 each run a login of their own, as getty and login do: the
+// terminal asks for a user name and a password, checks them against
+// /etc/passwd (see userland::users) and starts a shell session for the
+// account. The session's shell is a task of its own that leads a new
+// session, with the terminal as its controlling terminal and its standard
+// input, output and error, and with the account's ids, home directory
+// and variables. `logout` or `exit` ends it, hanging the terminal up, and
+// the login prompt comes back. Alt+F1 to Alt+F6 switch the screen and the
+// keyboard between the terminals.
+//
+// The shell runs in init's context, where the serial console's runs too,
+// so the terminals are served as idle work while the console waits for a
+// line: keys go to the terminal on the screen, and a line typed on one is
+// run with its session's task as the current one and its working
+// directory and variables in place. Commands that read input of their
+// own, such as editors and pagers, still read the serial console.
+//
+// A user logging in for the first time gets a home directory copied from
+// /etc/skel (see userland::users). Before the first prompt the session
+// runs /etc/profile and then ~/.profile, where they exist, as `.` would,
+// so the variables they set are the session's from then on.
+//
+// /etc/login.conf sets how long a session may go without a line typed
+// before it is logged out, read as each session starts:
+//
+//   # seconds; 0, or no line, for never
+//   idle-timeout 900
+//
+// Logins, refused logins and logouts go in the QSF audit log.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+use crate::fs::vfs::api::{self as vfs_api, OpenFlags};
+use crate::fs::vfs::VFS;
+use crate::hal::drivers::{keyboard, pit, tty};
+use crate::kernel::file::OpenFile;
+use crate::kernel::scheduler::task::FileDescriptor;
+use crate::kernel::scheduler::{Pid, Task, TaskState, INIT_PID, SCHEDULER};
+use crate::qsf::{AccessDecision, QSF};
+use crate::userland::shell::{self, vars};
+use crate::userland::users::{self, Account};
+
+pub const LOGIN_CONF: &str = "/etc/login.conf";
+pub const PROFILE: &str = "/etc/profile";
+/// The profile in a home directory
+const USER_PROFILE: &str = ".profile";
+
+/// What /etc/login.conf sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoginConf {
+    /// Seconds without a line typed before a session is logged out; 0
+    /// for never
+    pub idle_timeout: u64,
+}
+
+pub fn parse_login_conf(text: &str) -> Result<LoginConf, String> {
+    let mut conf = LoginConf::default();
+    for (number, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        match fields[..] {
+            [] => {}
+            ["idle-timeout", secs] => {
+                conf.idle_timeout = secs.parse().map_err(|_| format!("line {}: bad idle-timeout", number + 1))?;
+            }
+            _ => return Err(format!("line {}: not understood", number + 1)),
+        }
+    }
+    Ok(conf)
+}
+
+fn read_login_conf() -> Result<LoginConf, String> {
+    match vfs_api::read_file(LOGIN_CONF) {
+        Ok(text) => parse_login_conf(&String::from_utf8_lossy(&text)),
+        Err(_) => Ok(LoginConf::default()),
+    }
+}
+
+/// A user logged in on a terminal
+struct Session {
+    /// The shell's task, which leads the session
+    pid: Pid,
+    account: Account,
+    cwd: String,
+    vars: BTreeMap<String, String>,
+    conf: LoginConf,
+    /// Uptime in milliseconds when the last line was typed
+    last_input: u64,
+}
+
+enum Stage {
+    /// Waiting for a user name
+    Name,
+    /// Waiting for the password of the account named
+    Password(String),
+    Shell(Session),
+    /// Handling a line, with the stage taken out
+    Busy,
+}
+
+static TERMINALS: Mutex<Vec<Stage>> = Mutex::new(Vec::new());
+/// Whether the serial console's shell is waiting for a line, the only
+/// time a terminal's line can be run
+static CONSOLE_WAITING: AtomicBool = AtomicBool::new(false);
+
+fn write(vt: usize, text: &str) {
+    tty::write_to_tty(vt, text);
+}
+
+fn device_path(vt: usize) -> String {
+    format!("/dev/tty{}", vt)
+}
+
+fn login_prompt(vt: usize) {
+    write(vt, &format!("\nQunix OS ({})\n\nqunix login: ", device_path(vt)));
+}
+
+fn shell_prompt(vt: usize, session: &Session) {
+    let sign = if session.account.uid == 0 { '#' } else { '$' };
+    write(vt, &format!("{}@qunix:{}{} ", session.account.name, session.cwd, sign));
+}
+
+fn audit(pid: Pid, uid: u32, action: &str, vt: usize, decision: AccessDecision, reason: &str) {
+    QSF.lock().on_login(pid, uid, action, &device_path(vt), decision, reason);
+}
+
+/// Give every virtual terminal a login prompt and serve them from idle
+pub fn init() {
+    let mut terminals = TERMINALS.lock();
+    terminals.clear();
+    for vt in 1..=tty::VT_COUNT {
+        terminals.push(Stage::Name);
+        login_prompt(vt);
+    }
+    drop(terminals);
+    crate::kernel::idle::register(serve);
+}
+
+/// Say whether the serial console's shell is waiting for a line
+pub fn console_waiting(waiting: bool) {
+    CONSOLE_WAITING.store(waiting, Ordering::Relaxed);
+}
+
+/// Idle work: switch terminals as asked, and while the console waits,
+/// hand keys to the terminal on the screen, act on the lines typed and
+/// log out sessions left idle
+fn serve() {
+    tty::switch_pending();
+    if !CONSOLE_WAITING.load(Ordering::Relaxed) {
+        return;
+    }
+    while let Some(c) = keyboard::read_char() {
+        tty::handle_tty_input(c);
+    }
+    for vt in 1..=tty::VT_COUNT {
+        while let Some(line) = tty::take_line(vt) {
+            handle(vt, &line);
+        }
+        let now = pit::get_uptime_ms();
+        let idle = match &TERMINALS.lock().get(vt - 1) {
+            Some(Stage::Shell(session)) => {
+                session.conf.idle_timeout > 0 && now.saturating_sub(session.last_input) >= session.conf.idle_timeout * 1000
+            }
+            _ => false,
+        };
+        if idle {
+            if let Some(Stage::Shell(session)) = take(vt) {
+                write(vt, "\nidle timeout\n");
+                logout(vt, session, "idle timeout");
+            }
+            put(vt, Stage::Name);
+        }
+    }
+}
+
+fn take(vt: usize) -> Option<Stage> {
+    TERMINALS.lock().get_mut(vt - 1).map(|stage| core::mem::replace(stage, Stage::Busy))
+}
+
+fn put(vt: usize, stage: Stage) {
+    if let Some(slot) = TERMINALS.lock().get_mut(vt - 1) {
+        *slot = stage;
+    }
+}
+
+/// Act on a line typed on terminal `vt`. The stage is taken out while
+/// the line is handled, so nothing here runs with TERMINALS held.
+fn handle(vt: usize, line: &str) {
+    let next = match take(vt) {
+        Some(Stage::Name) => match line.trim() {
+            "" => {
+                write(vt, "qunix login: ");
+                Stage::Name
+            }
+            name => {
+                tty::set_tty_echo(vt, false);
+                write(vt, "Password: ");
+                Stage::Password(String::from(name))
+            }
+        },
+        Some(Stage::Password(name)) => {
+            tty::set_tty_echo(vt, true);
+            write(vt, "\n");
+            login(vt, &name, line)
+        }
+        Some(Stage::Shell(mut session)) => {
+            session.last_input = pit::get_uptime_ms();
+            match line.trim() {
+                "logout" | "exit" => {
+                    logout(vt, session, "logout");
+                    Stage::Name
+                }
+                _ => {
+                    run(&mut session, line);
+                    shell_prompt(vt, &session);
+                    Stage::Shell(session)
+                }
+            }
+        }
+        Some(Stage::Busy) | None => return,
+    };
+    put(vt, next);
+}
+
+fn login(vt: usize, name: &str, password: &str) -> Stage {
+    let account = match users::account(name).filter(|account| account.check_password(password)) {
+        Some(account) => account,
+        None => {
+            audit(INIT_PID, users::uid(name).unwrap_or(u32::MAX), "login_refused", vt, AccessDecision::Deny,
+                &format!("user {}", name));
+            write(vt, "\nLogin incorrect\n");
+            login_prompt(vt);
+            return Stage::Name;
+        }
+    };
+    match start(vt, account) {
+        Ok(mut session) => {
+            audit(session.pid, session.account.uid, "login", vt, AccessDecision::Audit,
+                &format!("user {}", session.account.name));
+            profile(&mut session);
+            shell_prompt(vt, &session);
+            Stage::Shell(session)
+        }
+        Err(e) => {
+            write(vt, &format!("login: {}\n", e));
+            login_prompt(vt);
+            Stage::Name
+        }
+    }
+}
+
+/// Start a session for `account` on terminal `vt`
+fn start(vt: usize, account: Account) -> Result<Session, String> {
+    let conf = read_login_conf().unwrap_or_else(|e| {
+        write(vt, &format!("login: {}: {}\n", LOGIN_CONF, e));
+        LoginConf::default()
+    });
+    let path = device_path(vt);
+    let flags = OpenFlags::O_RDWR;
+    let opened = vfs_api::open(&path, flags, 0).map_err(|e| format!("{}: {:?}", path, e))?;
+    let terminal = OpenFile::open(opened.inode, VFS.lock().resolve_path(&path), flags.bits(), false);
+    if let Err(e) = users::create_home(&account) {
+        write(vt, &format!("login: cannot make {}: {:?}\n", account.home, e));
+    }
+    let cwd = match VFS.lock().lookup_path(&account.home) {
+        Ok(node) if node.is_dir() => account.home.clone(),
+        _ => String::from("/"),
+    };
+
+    let mut scheduler = SCHEDULER.lock();
+    let pid = scheduler.allocate_pid().ok_or("out of pids")?;
+    let mut task = Task::new(pid, String::from("-sh"), 0, true)?;
+    task.ppid = Some(INIT_PID);
+    (task.uid, task.euid, task.gid, task.egid) = (account.uid, account.uid, account.gid, account.gid);
+    task.cwd = cwd.clone();
+    for fd in 0..3 {
+        task.fds.insert(fd, FileDescriptor::new(fd, terminal.clone(), flags.bits()));
+    }
+    scheduler.add_task(task);
+    scheduler.setsid(pid).ok();
+    scheduler.acquire_tty(pid, tty::vt_device(vt));
+    // It waits on its terminal between lines
+    scheduler.unqueue(pid);
+    if let Some(task) = scheduler.get_task_mut(pid) {
+        task.state = TaskState::Blocked;
+    }
+    drop(scheduler);
+
+    let mut vars = vars::snapshot();
+    for (name, value) in [("HOME", &account.home), ("USER", &account.name), ("LOGNAME", &account.name)] {
+        vars.insert(String::from(name), value.clone());
+    }
+    Ok(Session { pid, account, cwd, vars, conf, last_input: pit::get_uptime_ms() })
+}
+
+/// Run /etc/profile and then the account's ~/.profile in `session`
+fn profile(session: &mut Session) {
+    let user_profile = format!("{}/{}", session.account.home.trim_end_matches('/'), USER_PROFILE);
+    within(session, || {
+        for path in [PROFILE, user_profile.as_str()] {
+            if vfs_api::stat(path).is_ok() {
+                shell::source(path).ok();
+            }
+        }
+    });
+}
+
+fn run(session: &mut Session, line: &str) {
+    within(session, || {
+        shell::run_line(line);
+    });
+}
+
+/// Call `f` in `session`: as its task, in its working directory and with
+/// its variables
+fn within(session: &mut Session, f: impl FnOnce()) {
+    let previous = {
+        let mut scheduler = SCHEDULER.lock();
+        if let Some(task) = scheduler.get_task_mut(session.pid) {
+            task.state = TaskState::Running;
+        }
+        scheduler.current_pid.replace(session.pid)
+    };
+    let console_cwd = {
+        let mut vfs = VFS.lock();
+        let cwd = String::from(vfs.get_cwd());
+        if vfs.set_cwd(&session.cwd).is_err() {
+            vfs.set_cwd("/").ok();
+        }
+        cwd
+    };
+    let console_vars = vars::replace(core::mem::take(&mut session.vars));
+
+    f();
+
+    session.vars = vars::replace(console_vars);
+    {
+        let mut vfs = VFS.lock();
+        session.cwd = String::from(vfs.get_cwd());
+        vfs.set_cwd(&console_cwd).ok();
+    }
+    let mut scheduler = SCHEDULER.lock();
+    // A timer tick may have queued it meanwhile
+    scheduler.unqueue(session.pid);
+    if let Some(task) = scheduler.get_task_mut(session.pid) {
+        task.state = TaskState::Blocked;
+        task.cwd = session.cwd.clone();
+    }
+    scheduler.current_pid = previous;
+}
+
+/// End `session` on terminal `vt`: its task exits, which hangs the
+/// terminal up, and the login prompt comes back
+fn logout(vt: usize, session: Session, reason: &str) {
+    {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.job_control_exit(session.pid);
+        scheduler.reparent_children(session.pid);
+        if let Some(task) = scheduler.get_task_mut(session.pid) {
+            task.exit(0);
+            task.orphaned = true;
+        }
+        scheduler.reap_init_children();
+    }
+    audit(session.pid, session.account.uid, "logout", vt, AccessDecision::Audit, reason);
+    login_prompt(vt);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_login_conf() {
+        assert_eq!(parse_login_conf(""), Ok(LoginConf { idle_timeout: 0 }));
+        assert_eq!(parse_login_conf("# seconds\nidle-timeout 900   # a quarter of an hour\n\n"), Ok(LoginConf { idle_timeout: 900 }));
+        assert_eq!(parse_login_conf("idle-timeout soon"), Err(String::from("line 1: bad idle-timeout")));
+        assert_eq!(parse_login_conf("\nidle_timeout 5"), Err(String::from("line 2: not understood")));
+    }
+}
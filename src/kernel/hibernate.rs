@@ -0,0 +1,462 @@
+// Hibernation (suspend to disk)
+//
+// Experimental. With `resume=ataN[:LBA]` on the command line, `hibernate`
+// freezes the tasks, copies every page of RAM the kernel has something in
+// - its image, stacks, page tables and heap, and the tasks' memory - to
+// that disk from LBA (0 if none is given, the disk being given over to
+// it) and powers off. The next boot with the same `resume=` finds the
+// image while bringing up the disks, puts the pages back where they were
+// and carries on inside `hibernate`, which returns as if it had only
+// taken a while. The image is
+//
+//   sector 0      header: magic, version, page count, CRC-32 of the
+//                 pages, fingerprint of the kernel and memory map, time
+//   sectors 1..   the pages' physical addresses, 8 bytes each
+//   then          the pages, 8 sectors each
+//
+// The header is written last and erased before a resume starts, so a half
+// written image is never resumed and one that crashes is not tried again.
+// The kernel resuming must be the same build, booted on the same memory
+// map, as the pages it runs from and the page tables mapping them are
+// overwritten while the copy runs; the fingerprint refuses anything else.
+//
+// Devices are not saved. The PIC, PIT, serial port and keyboard are set
+// up again the same way by the boot that resumes; the ones found after
+// the IDE disks - NVMe, SD, graphics, audio and network - lose their
+// state at power off and stay unusable until the next reboot.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::structures::paging::FrameAllocator;
+use x86_64::{PhysAddr, VirtAddr};
+use crate::fs::archive::gzip::{crc32, crc32_continue};
+use crate::hal::drivers::ata::{AtaError, Drive, SECTOR_SIZE};
+use crate::hal::memory::frame_allocator::FRAME_ALLOCATOR;
+use crate::hal::memory::paging;
+use crate::kernel::scheduler::SCHEDULER;
+use super::crashdump::Region;
+
+pub const PARAM: &str = "resume";
+
+const MAGIC: &[u8; 8] = b"QUNIXHIB";
+const VERSION: u32 = 1;
+
+const PAGE_SIZE: usize = 4096;
+const PAGE_SECTORS: u64 = (PAGE_SIZE / SECTOR_SIZE) as u64;
+const ADDRESSES_PER_SECTOR: usize = SECTOR_SIZE / 8;
+
+/// Copies in one page of the list the resume copy loop walks: the page
+/// starts with their count and the address of the next page
+const COPIES_PER_PAGE: usize = PAGE_SIZE / 16 - 1;
+
+/// Frames `hibernate` may need beyond what it counted, for heap growth
+/// while it makes room for the page lists
+const SLACK: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HibernateError {
+    /// No `resume=`, or one naming no usable disk
+    NotConfigured,
+    Disk(AtaError),
+    /// The image would run past the end of the disk
+    NoSpace,
+    /// Too little free memory to copy the pages into
+    NoMemory,
+    /// The image is from another kernel build or memory map
+    Mismatch,
+    /// The pages do not match their checksum
+    Corrupt,
+}
+
+impl From<AtaError> for HibernateError {
+    fn from(error: AtaError) -> Self {
+        HibernateError::Disk(error)
+    }
+}
+
+static REGION: Mutex<Option<Region>> = Mutex::new(None);
+
+/// Frames `hibernate` copied the pages into, handed back once it resumes
+/// if they are still the last ones handed out, which they are when the
+/// frame allocator has given out STAGED_END in all
+static STAGED: AtomicUsize = AtomicUsize::new(0);
+static STAGED_END: AtomicUsize = AtomicUsize::new(0);
+
+/// What `save` keeps, at the offsets the assembly below uses
+#[repr(C)]
+struct Context {
+    rsp: u64,
+    rbp: u64,
+    rbx: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rflags: u64,
+    cr3: u64,
+    rip: u64,
+}
+
+/// Written by `save` just before the snapshot, so the copy of it in the
+/// image is what `restore` loads
+static mut CONTEXT: Context = Context {
+    rsp: 0, rbp: 0, rbx: 0, r12: 0, r13: 0, r14: 0, r15: 0, rflags: 0, cr3: 0, rip: 0,
+};
+
+extern "C" {
+    /// Keep the callee-saved registers, the stack and CR3 in `context` and
+    /// return 0; after a resume, return from here again with 1
+    fn qunix_hibernate_save(context: *mut Context) -> u64;
+
+    /// Do the copies in the list from `copies`, then go back into `save`
+    /// with `context`. Runs on no stack and touches no memory but the list
+    /// and the pages it names.
+    fn qunix_hibernate_restore(copies: u64, context: *const Context) -> !;
+}
+
+core::arch::global_asm!(
+    ".global qunix_hibernate_save",
+    "qunix_hibernate_save:",
+    "    mov rax, [rsp]",
+    "    mov [rdi + 72], rax",
+    "    lea rax, [rsp + 8]",
+    "    mov [rdi], rax",
+    "    mov [rdi + 8], rbp",
+    "    mov [rdi + 16], rbx",
+    "    mov [rdi + 24], r12",
+    "    mov [rdi + 32], r13",
+    "    mov [rdi + 40], r14",
+    "    mov [rdi + 48], r15",
+    "    pushfq",
+    "    pop rax",
+    "    mov [rdi + 56], rax",
+    "    mov rax, cr3",
+    "    mov [rdi + 64], rax",
+    "    xor eax, eax",
+    "    ret",
+    "",
+    ".global qunix_hibernate_restore",
+    "qunix_hibernate_restore:",
+    "    cli",
+    "    mov r11, rsi",
+    "2:  test rdi, rdi",
+    "    jz 5f",
+    "    mov r8, [rdi]",
+    "    mov r10, [rdi + 8]",
+    "    lea r9, [rdi + 16]",
+    "3:  test r8, r8",
+    "    jz 4f",
+    "    mov rsi, [r9]",
+    "    mov rdi, [r9 + 8]",
+    "    mov rcx, 512",
+    "    rep movsq",
+    "    add r9, 16",
+    "    dec r8",
+    "    jmp 3b",
+    "4:  mov rdi, r10",
+    "    jmp 2b",
+    "5:  mov rax, [r11 + 64]",
+    "    mov cr3, rax",
+    "    mov rsp, [r11]",
+    "    mov rbp, [r11 + 8]",
+    "    mov rbx, [r11 + 16]",
+    "    mov r12, [r11 + 24]",
+    "    mov r13, [r11 + 32]",
+    "    mov r14, [r11 + 40]",
+    "    mov r15, [r11 + 48]",
+    "    push qword ptr [r11 + 56]",
+    "    popfq",
+    "    mov eax, 1",
+    "    jmp qword ptr [r11 + 72]",
+);
+
+/// The header sector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub pages: usize,
+    pub crc: u32,
+    pub fingerprint: u32,
+    /// Unix time of hibernation
+    pub time: u64,
+}
+
+impl Header {
+    fn encode(&self) -> [u8; SECTOR_SIZE] {
+        let mut sector = [0u8; SECTOR_SIZE];
+        sector[..8].copy_from_slice(MAGIC);
+        sector[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        sector[12..16].copy_from_slice(&(self.pages as u32).to_le_bytes());
+        sector[16..20].copy_from_slice(&self.crc.to_le_bytes());
+        sector[20..24].copy_from_slice(&self.fingerprint.to_le_bytes());
+        sector[24..32].copy_from_slice(&self.time.to_le_bytes());
+        sector
+    }
+
+    fn decode(sector: &[u8]) -> Option<Header> {
+        if sector.len() < 32 || &sector[..8] != MAGIC {
+            return None;
+        }
+        let word = |at: usize| u32::from_le_bytes([sector[at], sector[at + 1], sector[at + 2], sector[at + 3]]);
+        if word(8) != VERSION {
+            return None;
+        }
+        let mut time = [0u8; 8];
+        time.copy_from_slice(&sector[24..32]);
+        Some(Header {
+            pages: word(12) as usize,
+            crc: word(16),
+            fingerprint: word(20),
+            time: u64::from_le_bytes(time),
+        })
+    }
+
+    /// Sectors the image takes, this one included
+    fn sectors(&self) -> u64 {
+        1 + list_sectors(self.pages) + self.pages as u64 * PAGE_SECTORS
+    }
+}
+
+fn list_sectors(pages: usize) -> u64 {
+    pages.div_ceil(ADDRESSES_PER_SECTOR) as u64
+}
+
+/// What a resuming kernel must share with the one that hibernated: where
+/// its code is, where it sees physical memory and the memory map
+fn fingerprint() -> u32 {
+    let mut crc = crc32(super::KERNEL_INFO.version.as_bytes());
+    let entry: fn() -> Result<(), HibernateError> = resume;
+    let restore: unsafe extern "C" fn(u64, *const Context) -> ! = qunix_hibernate_restore;
+    for addr in [entry as usize, restore as usize] {
+        crc = crc32_continue(crc, &(addr as u64).to_le_bytes());
+    }
+    let offset = paging::get_physical_memory_offset().map_or(0, |offset| offset.as_u64());
+    crc = crc32_continue(crc, &offset.to_le_bytes());
+    if let Some(frames) = FRAME_ALLOCATOR.lock().as_ref() {
+        for region in frames.memory_map().iter() {
+            crc = crc32_continue(crc, &region.range.start_addr().to_le_bytes());
+            crc = crc32_continue(crc, &region.range.end_addr().to_le_bytes());
+        }
+    }
+    crc
+}
+
+/// Where physical `addr` is mapped
+fn virt(addr: u64) -> VirtAddr {
+    paging::phys_to_virt(PhysAddr::new(addr)).expect("physical memory is not mapped")
+}
+
+/// The page at physical `addr`
+///
+/// # Safety
+/// Nothing else may be using the page while the slice lives.
+unsafe fn page<'a>(addr: u64) -> &'a mut [u8] {
+    core::slice::from_raw_parts_mut(virt(addr).as_mut_ptr(), PAGE_SIZE)
+}
+
+fn configure(value: &str) -> Result<Region, AtaError> {
+    let (drive, lba) = match value.split_once(':') {
+        Some((drive, lba)) => (drive, lba.parse::<u64>().map_err(|_| AtaError::OutOfRange)?),
+        None => (value, 0),
+    };
+    let drive = Drive::from_name(drive).ok_or(AtaError::NoDevice)?;
+    if lba >= drive.identify()? {
+        return Err(AtaError::OutOfRange);
+    }
+    Ok(Region { drive, lba })
+}
+
+/// The image region `resume=` names, if any
+pub fn region() -> Option<Region> {
+    *REGION.lock()
+}
+
+/// Read `resume=` and resume from the image there if it holds one, not
+/// returning if so. Runs once the IDE disks are up.
+pub fn resume() -> Result<(), HibernateError> {
+    let Some(value) = super::get_param(PARAM) else {
+        return Ok(());
+    };
+    let region = match configure(&value) {
+        Ok(region) => region,
+        Err(e) => {
+            crate::println!("  [KERNEL] {}={}: hibernation disabled ({:?})", PARAM, value, e);
+            return Err(HibernateError::NotConfigured);
+        }
+    };
+    *REGION.lock() = Some(region);
+
+    let mut sector = [0u8; SECTOR_SIZE];
+    region.drive.read_sectors(region.lba, &mut sector)?;
+    let Some(header) = Header::decode(&sector) else {
+        return Ok(());
+    };
+    if header.fingerprint != fingerprint() {
+        return Err(HibernateError::Mismatch);
+    }
+    crate::println!("  [KERNEL] Resuming {} pages from {} sector {}", header.pages, region.drive.device_name(), region.lba);
+
+    let mut addresses = vec![0u8; list_sectors(header.pages) as usize * SECTOR_SIZE];
+    region.drive.read_sectors(region.lba + 1, &mut addresses)?;
+    let pages: Vec<u64> = addresses.chunks_exact(8).take(header.pages)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    drop(addresses);
+    let mut taken = pages.clone();
+    taken.sort_unstable();
+
+    // Somewhere the image is not going back to for each page, and for the
+    // list of copies
+    let lists = pages.len().div_ceil(COPIES_PER_PAGE);
+    let mut staging = Vec::with_capacity(pages.len() + lists);
+    {
+        let mut frames = FRAME_ALLOCATOR.lock();
+        let frames = frames.as_mut().ok_or(HibernateError::NoMemory)?;
+        while staging.len() < pages.len() + lists {
+            let frame = frames.allocate_frame().ok_or(HibernateError::NoMemory)?;
+            let addr = frame.start_address().as_u64();
+            if taken.binary_search(&addr).is_err() {
+                staging.push(addr);
+            }
+        }
+    }
+    let (data, lists) = staging.split_at(pages.len());
+
+    let mut lba = region.lba + 1 + list_sectors(header.pages);
+    let mut crc = 0;
+    for &to in data {
+        let buf = unsafe { page(to) };
+        region.drive.read_sectors(lba, buf)?;
+        crc = crc32_continue(crc, buf);
+        lba += PAGE_SECTORS;
+    }
+    // Either way the image is used up
+    super::crashdump::clear(region)?;
+    if crc != header.crc {
+        return Err(HibernateError::Corrupt);
+    }
+
+    for (n, (&list, copies)) in lists.iter().zip(data.chunks(COPIES_PER_PAGE)).enumerate() {
+        let next = lists.get(n + 1).map_or(0, |&next| virt(next).as_u64());
+        let words = unsafe { core::slice::from_raw_parts_mut(virt(list).as_mut_ptr::<u64>(), PAGE_SIZE / 8) };
+        words[0] = copies.len() as u64;
+        words[1] = next;
+        for (i, &from) in copies.iter().enumerate() {
+            let to = pages[n * COPIES_PER_PAGE + i];
+            words[2 + 2 * i] = virt(from).as_u64();
+            words[3 + 2 * i] = virt(to).as_u64();
+        }
+    }
+    let first = lists.first().map_or(0, |&list| virt(list).as_u64());
+    unsafe { qunix_hibernate_restore(first, core::ptr::addr_of!(CONTEXT)) }
+}
+
+/// Write an image of the running system to the `resume=` region and power
+/// off. Returns, a while later, in the boot that resumes from it; or at
+/// once if the image could not be written.
+pub fn hibernate() -> Result<(), HibernateError> {
+    let region = region().ok_or(HibernateError::NotConfigured)?;
+    let available = region.drive.identify()?.saturating_sub(region.lba);
+    crate::fs::vfs::api::sync().ok();
+
+    // Room for the lists before counting, as growing the heap takes frames
+    let estimate = {
+        let frames = FRAME_ALLOCATOR.lock();
+        let frames = frames.as_ref().ok_or(HibernateError::NoMemory)?;
+        frames.in_use_frames().count() + SLACK
+    };
+    let mut pages: Vec<u64> = Vec::with_capacity(estimate);
+    let mut staging: Vec<u64> = Vec::with_capacity(estimate);
+
+    SCHEDULER.lock().disable_preemption();
+    x86_64::instructions::interrupts::disable();
+    let result = snapshot(region, available, &mut pages, &mut staging);
+    // Only here on failure, or after a resume
+    let staged = STAGED.swap(0, Ordering::Relaxed);
+    if let Some(frames) = FRAME_ALLOCATOR.lock().as_mut() {
+        if frames.used_frames() == STAGED_END.load(Ordering::Relaxed) {
+            frames.give_back(staged);
+        }
+    }
+    x86_64::instructions::interrupts::enable();
+    SCHEDULER.lock().enable_preemption();
+    if result.is_ok() {
+        super::time::init();
+    }
+    result
+}
+
+/// The part of `hibernate` from when nothing else runs; `pages` and
+/// `staging` must have room enough that filling them takes no frames
+fn snapshot(region: Region, available: u64, pages: &mut Vec<u64>, staging: &mut Vec<u64>) -> Result<(), HibernateError> {
+    {
+        let mut frames = FRAME_ALLOCATOR.lock();
+        let frames = frames.as_mut().ok_or(HibernateError::NoMemory)?;
+        for frame in frames.in_use_frames() {
+            if pages.len() == pages.capacity() {
+                return Err(HibernateError::NoMemory);
+            }
+            pages.push(frame.start_address().as_u64());
+        }
+        let header = Header { pages: pages.len(), crc: 0, fingerprint: 0, time: 0 };
+        if header.sectors() > available {
+            return Err(HibernateError::NoSpace);
+        }
+        while staging.len() < pages.len() {
+            STAGED.fetch_add(1, Ordering::Relaxed);
+            let frame = frames.allocate_frame().ok_or(HibernateError::NoMemory)?;
+            staging.push(frame.start_address().as_u64());
+        }
+        STAGED_END.store(frames.used_frames(), Ordering::Relaxed);
+    }
+
+    if unsafe { qunix_hibernate_save(core::ptr::addr_of_mut!(CONTEXT)) } != 0 {
+        // Resumed: the tasks' memory and ours is as it was at the copy
+        // below, and the boot that put it back has erased the image
+        crate::println!("Resumed from hibernation");
+        return Ok(());
+    }
+    // The snapshot itself. Nothing past this allocates or takes a lock,
+    // until the pages are copied.
+    for (&from, &to) in pages.iter().zip(staging.iter()) {
+        unsafe { page(to).copy_from_slice(page(from)) };
+    }
+
+    let write = || -> Result<(), HibernateError> {
+        let mut addresses = vec![0u8; list_sectors(pages.len()) as usize * SECTOR_SIZE];
+        for (bytes, addr) in addresses.chunks_exact_mut(8).zip(pages.iter()) {
+            bytes.copy_from_slice(&addr.to_le_bytes());
+        }
+        region.drive.write_sectors(region.lba + 1, &addresses)?;
+        let mut lba = region.lba + 1 + list_sectors(pages.len());
+        let mut crc = 0;
+        for &addr in staging.iter() {
+            let buf = unsafe { page(addr) };
+            region.drive.write_sectors(lba, buf)?;
+            crc = crc32_continue(crc, buf);
+            lba += PAGE_SECTORS;
+        }
+        let header = Header { pages: pages.len(), crc, fingerprint: fingerprint(), time: super::time::now() };
+        region.drive.write_sectors(region.lba, &header.encode())?;
+        Ok(())
+    };
+    write()?;
+    crate::println!("Hibernated {} pages to {}; powering off", pages.len(), region.drive.device_name());
+    super::power_off()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_header_round_trip() {
+        let header = Header { pages: 5000, crc: 0xDEAD_BEEF, fingerprint: 0x1234_5678, time: 1_700_000_000 };
+        let sector = header.encode();
+        assert_eq!(Header::decode(&sector), Some(header));
+        assert_eq!(Header::decode(&[0u8; SECTOR_SIZE]), None);
+        // Header, 79 sectors of addresses, then the pages
+        assert_eq!(header.sectors(), 1 + 79 + 5000 * 8);
+    }
+}
@@ -0,0 +1,36 @@
+// Idle work
+//
+// Nothing runs in the background on its own, so jobs that have to happen
+// while the system waits for input (answering the network, keeping the
+// clock in step) register a function here. The blocking reads of the
+// serial port and the keyboard call run() before each halt; a function
+// that is already running is not entered again, so one may block for a
+// while without the others piling up behind it.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+static WORK: Mutex<Vec<fn()>> = Mutex::new(Vec::new());
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Call `work` whenever the system is idle
+pub fn register(work: fn()) {
+    let mut list = WORK.lock();
+    if !list.contains(&work) {
+        list.push(work);
+    }
+}
+
+/// Do the registered work once
+pub fn run() {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return;
+    }
+    // Copy the list so a job may register another without deadlocking
+    let work = WORK.lock().clone();
+    for job in work {
+        job();
+    }
+    RUNNING.store(false, Ordering::Release);
+}
@@ -22,6 +22,8 @@ pub fn start_init_process() {
         let mut scheduler = SCHEDULER.lock();
         println!("[INIT] Scheduler locked, adding task");
         scheduler.add_task(init_task);
+        // The shell runs in init's session, on the console
+        scheduler.acquire_tty(1, crate::hal::drivers::tty::CONSOLE);
         println!("[INIT] Task added to scheduler");
     } // lock released here
 
@@ -50,51 +52,39 @@ fn init_main() {
     crate::serial_println!("╚══════════════════════════════════╝");
     crate::serial_println!();
 
+    // Reaching the shell is what confirms a kernel update
+    crate::kernel::sysupdate::confirm();
+
+    // Logins on the virtual terminals, served while the console is idle
+    crate::kernel::getty::init();
+
+    // CI boots into a script and powers off when it is done
+    if let Some(script) = crate::kernel::get_param("autorun") {
+        crate::userland::shell::autorun::run(&script);
+    }
+
     shell_loop();
 }
 
 fn shell_loop() {
     loop {
-        // Ensure the prompt is visible on both VGA and serial
+        // The screen belongs to the virtual terminals' logins
         crate::serial_print!("root@qunix:/# ");
-        crate::println!("root@qunix:/# "); // Also print to VGA for compatibility
         
         let mut buf = [0u8; 128];
+        crate::kernel::getty::console_waiting(true);
         let len = crate::hal::drivers::serial::read_line(&mut buf);
+        crate::kernel::getty::console_waiting(false);
         
-        let line = core::str::from_utf8(&buf[..len]).unwrap_or("");
+        let line = String::from_utf8_lossy(&buf[..len]);
         if !line.is_empty() {
-            handle_shell_input(line);
+            handle_shell_input(&line);
         }
     }
 }
 
 pub fn handle_shell_input(input: &str) {
-    let input = input.trim();
-    if input.is_empty() {
-        return;
-    }
-
-    let mut args: [&str; 16] = [""; 16];
-    let mut count = 0;
-    let mut iter = input.split_whitespace();
-    while let Some(token) = iter.next() {
-        if count < 16 {
-            args[count] = token;
-            count += 1;
-        } else {
-            break;
-        }
-    }
-    if count == 0 {
-        return;
-    }
-
-    let command = args[0];
-    let actual_args = &args[1..count];
-
-    // Use the new modular command system
-    crate::userland::shell::execute(command, actual_args);
+    crate::userland::shell::run_line(input);
 }
 
 // Shell input is now handled by modular command system
@@ -27,6 +27,46 @@ pub fn is_running() -> bool {
     get_state() == KernelState::Running
 }
 
+/// Flush the filesystems, keep the kernel log in pstore and reset the
+/// machine through the keyboard controller, or failing that by a triple
+/// fault. RAM is left alone, so the next boot finds the log.
+pub fn reboot() -> ! {
+    set_state(KernelState::Halting);
+    crate::fs::vfs::api::sync().ok();
+    super::pstore::save(super::pstore::Reason::Reboot);
+
+    crate::hal::drivers::serial::flush();
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        let mut status = x86_64::instructions::port::Port::<u8>::new(0x64);
+        while status.read() & 0x02 != 0 {}
+        status.write(0xFE);
+        let empty = x86_64::structures::DescriptorTablePointer {
+            limit: 0,
+            base: x86_64::VirtAddr::new(0),
+        };
+        x86_64::instructions::tables::lidt(&empty);
+        core::arch::asm!("int3");
+    }
+    crate::hlt_loop()
+}
+
+/// Switch the machine off through the ACPI PM1a control register, at the
+/// port QEMU (0x604) or Bochs and older QEMU (0xB004) have it; no ACPI
+/// tables are read, so elsewhere this only halts. Filesystems are not
+/// flushed; only what the serial ports have queued is sent.
+pub fn power_off() -> ! {
+    set_state(KernelState::Halting);
+    crate::hal::drivers::serial::flush();
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        x86_64::instructions::port::Port::<u16>::new(0x604).write(0x2000);
+        x86_64::instructions::port::Port::<u16>::new(0xB004).write(0x2000);
+    }
+    crate::println!("It is now safe to turn off the machine.");
+    crate::hlt_loop()
+}
+
 #[derive(Debug)]
 pub struct KernelInfo {
     pub name: &'static str,
@@ -0,0 +1,386 @@
+// Soft reboot into a new kernel (kexec)
+//
+// `kexec FILE` starts another kernel without going back through the
+// firmware. FILE is the kernel's ELF itself, not a boot image, and it is
+// started as the bootloader would start it: its PT_LOAD segments copied to
+// fresh frames and mapped where they ask to be, all physical memory mapped
+// at the offset this kernel sees it at with 2 MiB pages, the VGA text
+// buffer mapped where it is, and a stack and a BootInfo of its own. The
+// memory map in it is this one's, with everything this kernel had taken
+// usable again bar the frames the new kernel now lives in.
+//
+// Those frames are one physically contiguous run, taken from the frame
+// allocator before anything is torn down, so they are a handful of
+// regions in the map whatever the size of the image; the map has room for
+// 64. The jump runs from a copy in that run, reached through the physical
+// memory window, which is at the same place in the old page tables and
+// the new ones. Before it the filesystems are synced, the kernel log kept
+// in pstore for the new kernel to find, and every PCI device stopped from
+// mastering the bus, so nothing writes to memory the new kernel thinks is
+// free. Devices are not reset otherwise; the new kernel boots with its own
+// built-in command line.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
+use bootloader::BootInfo;
+use x86_64::structures::paging::mapper::{MapToError, Translate, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::hal::memory::frame_allocator::FRAME_ALLOCATOR;
+use crate::hal::memory::paging;
+use super::exec::{self, Segment};
+
+const PAGE_SIZE: u64 = 4096;
+const HUGE_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+
+/// The new kernel's boot stack
+const STACK_PAGES: u64 = 128;
+/// Regions a bootloader memory map holds
+const MAX_REGIONS: usize = 64;
+/// The VGA text buffer, identity mapped
+const VGA: Range<u64> = 0xb8000..0xc0000;
+
+const _: () = assert!(core::mem::size_of::<BootInfo>() as u64 <= PAGE_SIZE);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KexecError {
+    /// Not a static x86-64 ELF executable
+    NotKernel,
+    /// Its segments fall on the physical memory window or the VGA buffer
+    Overlap,
+    /// No run of free frames long enough to load it into
+    NoMemory,
+    /// The memory map would not fit in a BootInfo
+    TooManyRegions,
+}
+
+extern "C" {
+    /// Switch to the page tables at `pml4` and the stack at `stack`, and
+    /// jump to `entry` with `boot_info` as its argument. Position
+    /// independent: it runs from a copy reached through the physical
+    /// memory window, up to `qunix_kexec_jump_end`.
+    fn qunix_kexec_jump(pml4: u64, stack: u64, boot_info: u64, entry: u64) -> !;
+    static qunix_kexec_jump_end: u8;
+}
+
+core::arch::global_asm!(
+    ".global qunix_kexec_jump",
+    "qunix_kexec_jump:",
+    // Clearing CR4.PGE flushes the global pages too
+    "    mov rax, cr4",
+    "    mov r8, rax",
+    "    btr rax, 7",
+    "    mov cr4, rax",
+    "    mov cr3, rdi",
+    "    mov cr4, r8",
+    "    mov rsp, rsi",
+    "    mov rdi, rdx",
+    "    xor ebp, ebp",
+    "    jmp rcx",
+    ".global qunix_kexec_jump_end",
+    "qunix_kexec_jump_end:",
+);
+
+/// Frames handed out one after another from part of the run, zeroed
+struct Frames {
+    next: u64,
+    end: u64,
+}
+
+impl Frames {
+    fn new(start: u64, pages: u64) -> Self {
+        Frames { next: start, end: start + pages * PAGE_SIZE }
+    }
+
+    fn take(&mut self, pages: u64) -> Option<u64> {
+        let start = self.next;
+        if self.end - start < pages * PAGE_SIZE {
+            return None;
+        }
+        self.next += pages * PAGE_SIZE;
+        unsafe { core::ptr::write_bytes(virt(start).as_mut_ptr::<u8>(), 0, (pages * PAGE_SIZE) as usize) };
+        Some(start)
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for Frames {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.take(1).map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+/// A kernel loaded and ready to jump to
+pub struct Image {
+    pml4: u64,
+    stack: u64,
+    boot_info: u64,
+    entry: u64,
+    jump: u64,
+}
+
+fn virt(addr: u64) -> VirtAddr {
+    paging::phys_to_virt(PhysAddr::new(addr)).expect("physical memory is not mapped")
+}
+
+fn map_error<S: x86_64::structures::paging::PageSize>(error: MapToError<S>) -> KexecError {
+    match error {
+        MapToError::FrameAllocationFailed => KexecError::NoMemory,
+        _ => KexecError::Overlap,
+    }
+}
+
+/// What a region of this kernel's memory map is in the new kernel's: all
+/// it took for itself is free again
+fn reclaimed(region_type: MemoryRegionType) -> MemoryRegionType {
+    match region_type {
+        MemoryRegionType::InUse | MemoryRegionType::Kernel | MemoryRegionType::KernelStack
+        | MemoryRegionType::PageTable | MemoryRegionType::BootInfo | MemoryRegionType::Package => MemoryRegionType::Usable,
+        other => other,
+    }
+}
+
+/// The new kernel's memory map: `regions` of this one's with `claims`
+/// carved out of them, sorted, and neighbours of one type merged. Claims
+/// must not overlap each other.
+fn new_map(regions: &[(Range<u64>, MemoryRegionType)], claims: &[(Range<u64>, MemoryRegionType)])
+    -> Result<Vec<(Range<u64>, MemoryRegionType)>, KexecError>
+{
+    let mut pieces = Vec::new();
+    for (range, region_type) in regions {
+        let mut start = range.start;
+        let mut overlapping: Vec<_> = claims.iter().map(|(claim, _)| claim)
+            .filter(|claim| claim.start < range.end && range.start < claim.end)
+            .collect();
+        overlapping.sort_by_key(|claim| claim.start);
+        for claim in overlapping {
+            if claim.start > start {
+                pieces.push((start..claim.start, reclaimed(*region_type)));
+            }
+            start = start.max(claim.end);
+        }
+        if start < range.end {
+            pieces.push((start..range.end, reclaimed(*region_type)));
+        }
+    }
+    pieces.extend(claims.iter().filter(|(claim, _)| !claim.is_empty()).cloned());
+    pieces.sort_by_key(|(range, _)| range.start);
+
+    let mut map: Vec<(Range<u64>, MemoryRegionType)> = Vec::new();
+    for (range, region_type) in pieces {
+        match map.last_mut() {
+            Some((last, last_type)) if last.end == range.start && *last_type == region_type => last.end = range.end,
+            _ => map.push((range, region_type)),
+        }
+    }
+    if map.len() > MAX_REGIONS {
+        return Err(KexecError::TooManyRegions);
+    }
+    Ok(map)
+}
+
+fn segment_flags(segment: &Segment) -> PageTableFlags {
+    let mut flags = PageTableFlags::PRESENT;
+    if segment.writable() {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if !segment.executable() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    flags
+}
+
+/// Page tables the new kernel could need at most, for `pages` pages of
+/// segments in `segments` segments and physical memory up to `top`
+fn table_bound(segments: usize, pages: u64, top: u64) -> u64 {
+    const GIB: u64 = 1 << 30;
+    let directories = top.div_ceil(GIB);
+    let pointers = top.div_ceil(512 * GIB);
+    // Each 4 KiB mapped range may straddle two of each table above it
+    let small = (segments as u64 + 1) * 6 + pages / 512;
+    1 + pointers + directories + small
+}
+
+/// Load the kernel ELF `data` into memory set aside for it, with page
+/// tables, a stack and a BootInfo, leaving this kernel running
+pub fn load(data: &[u8]) -> Result<Image, KexecError> {
+    let (entry, segments) = exec::segments(data).map_err(|_| KexecError::NotKernel)?;
+    if segments.iter().any(|segment| VirtAddr::try_new(segment.vaddr + segment.memsz).is_err()) {
+        return Err(KexecError::NotKernel);
+    }
+    let offset = paging::get_physical_memory_offset().ok_or(KexecError::NoMemory)?;
+
+    let regions: Vec<(Range<u64>, MemoryRegionType)> = {
+        let frames = FRAME_ALLOCATOR.lock();
+        let frames = frames.as_ref().ok_or(KexecError::NoMemory)?;
+        frames.memory_map().iter()
+            .map(|region| (region.range.start_addr()..region.range.end_addr(), region.region_type))
+            .collect()
+    };
+    let top = regions.iter().map(|(range, _)| range.end).max().unwrap_or(0).next_multiple_of(HUGE_PAGE_SIZE);
+
+    // The run: segments, stack, boot info, the jump, then page tables
+    let segment_pages: u64 = segments.iter().map(|segment| {
+        let pages = segment.pages();
+        (pages.end - pages.start) / PAGE_SIZE
+    }).sum();
+    let table_pages = table_bound(segments.len(), segment_pages, top);
+    let total = segment_pages + STACK_PAGES + 2 + table_pages;
+    let run = FRAME_ALLOCATOR.lock().as_mut()
+        .and_then(|frames| frames.allocate_contiguous(total as usize))
+        .ok_or(KexecError::NoMemory)?
+        .start_address().as_u64();
+
+    let mut loaded = Frames::new(run, segment_pages);
+    let stack = run + segment_pages * PAGE_SIZE;
+    let boot_info = stack + STACK_PAGES * PAGE_SIZE;
+    let jump = boot_info + PAGE_SIZE;
+    let mut tables = Frames::new(jump + PAGE_SIZE, table_pages);
+
+    let pml4 = tables.take(1).ok_or(KexecError::NoMemory)?;
+    let mut mapper = unsafe { OffsetPageTable::new(&mut *virt(pml4).as_mut_ptr::<PageTable>(), offset) };
+
+    // Physical memory first, so a segment in its way shows as an overlap
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    for phys in (0..top).step_by(HUGE_PAGE_SIZE as usize) {
+        let page = Page::<Size2MiB>::containing_address(offset + phys);
+        let frame = PhysFrame::<Size2MiB>::containing_address(PhysAddr::new(phys));
+        unsafe { mapper.map_to(page, frame, flags, &mut tables) }.map_err(map_error)?.ignore();
+    }
+    for addr in VGA.step_by(PAGE_SIZE as usize) {
+        let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+        let frame = PhysFrame::containing_address(PhysAddr::new(addr));
+        let flags = flags | PageTableFlags::NO_EXECUTE;
+        unsafe { mapper.map_to(page, frame, flags, &mut tables) }.map_err(map_error)?.ignore();
+    }
+
+    for segment in &segments {
+        let wanted = segment_flags(segment);
+        for addr in segment.pages().step_by(PAGE_SIZE as usize) {
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+            match mapper.translate(page.start_address()) {
+                // A page shared with the segment before: it gets both's rights
+                TranslateResult::Mapped { flags, .. } if !flags.contains(PageTableFlags::HUGE_PAGE) => {
+                    let mut merged = flags | (wanted & PageTableFlags::WRITABLE);
+                    if segment.executable() {
+                        merged.remove(PageTableFlags::NO_EXECUTE);
+                    }
+                    unsafe { mapper.update_flags(page, merged) }.map_err(|_| KexecError::Overlap)?.ignore();
+                }
+                TranslateResult::Mapped { .. } => return Err(KexecError::Overlap),
+                _ => {
+                    let frame = loaded.allocate_frame().ok_or(KexecError::NoMemory)?;
+                    unsafe { mapper.map_to(page, frame, wanted, &mut tables) }.map_err(map_error)?.ignore();
+                }
+            }
+        }
+        // Copy the contents a page at a time, through where their frames are
+        // mapped here
+        let mut at = segment.vaddr;
+        for chunk in segment.contents.chunks(PAGE_SIZE as usize) {
+            let mut done = 0;
+            while done < chunk.len() {
+                let phys = mapper.translate_addr(VirtAddr::new(at)).ok_or(KexecError::Overlap)?;
+                let len = (chunk.len() - done).min((PAGE_SIZE - at % PAGE_SIZE) as usize);
+                unsafe {
+                    let to = virt(phys.as_u64()).as_mut_ptr::<u8>();
+                    core::ptr::copy_nonoverlapping(chunk[done..].as_ptr(), to, len);
+                }
+                done += len;
+                at += len as u64;
+            }
+        }
+    }
+
+    // The stack starts as if `entry` had been called, with a null return
+    // address at the top
+    let stack_top = virt(stack + STACK_PAGES * PAGE_SIZE).as_u64() - 8;
+    unsafe { core::ptr::write(stack_top as *mut u64, 0) };
+
+    let claims = [
+        (run..loaded.next, MemoryRegionType::Kernel),
+        (stack..boot_info, MemoryRegionType::KernelStack),
+        (boot_info..jump, MemoryRegionType::BootInfo),
+        (jump..jump + PAGE_SIZE, MemoryRegionType::Bootloader),
+        (jump + PAGE_SIZE..tables.next, MemoryRegionType::PageTable),
+    ];
+    let mut memory_map = MemoryMap::new();
+    for (range, region_type) in new_map(&regions, &claims)? {
+        memory_map.add_region(MemoryRegion { range: FrameRange::new(range.start, range.end), region_type });
+    }
+    let info = BootInfo::new(memory_map, None, 0, offset.as_u64());
+    unsafe { core::ptr::write(virt(boot_info).as_mut_ptr::<BootInfo>(), info) };
+
+    let code: unsafe extern "C" fn(u64, u64, u64, u64) -> ! = qunix_kexec_jump;
+    let len = core::ptr::addr_of!(qunix_kexec_jump_end) as usize - code as usize;
+    unsafe { core::ptr::copy_nonoverlapping(code as *const u8, virt(jump).as_mut_ptr::<u8>(), len) };
+
+    Ok(Image {
+        pml4,
+        stack: stack_top,
+        boot_info: virt(boot_info).as_u64(),
+        entry,
+        jump: virt(jump).as_u64(),
+    })
+}
+
+/// Shut this kernel down and start `image`
+pub fn exec(image: Image) -> ! {
+    super::set_state(super::KernelState::Halting);
+    crate::fs::vfs::api::sync().ok();
+    crate::println!("Starting new kernel at {:#x}", image.entry);
+    super::pstore::save(super::pstore::Reason::Kexec);
+    x86_64::instructions::interrupts::disable();
+    crate::hal::drivers::pci::quiesce();
+    #[cfg(feature = "framebuffer")]
+    crate::hal::drivers::framebuffer::leave();
+    unsafe {
+        let jump: unsafe extern "C" fn(u64, u64, u64, u64) -> ! = core::mem::transmute(image.jump);
+        jump(image.pml4, image.stack, image.boot_info, image.entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MemoryRegionType::*;
+
+    #[test_case]
+    fn test_new_map() {
+        let regions = [
+            (0..0x1000, FrameZero),
+            (0x1000..0x9f000, Usable),
+            (0x100000..0x400000, Kernel),
+            (0x400000..0x800000, InUse),
+            (0x800000..0x8000000, Usable),
+            (0xfffc0000..0x100000000, Reserved),
+        ];
+        let claims = [
+            (0x1000000..0x1400000, Kernel),
+            (0x1400000..0x1480000, KernelStack),
+            (0x1480000..0x1480000, PageTable),
+        ];
+        let map = new_map(&regions, &claims).unwrap();
+        assert_eq!(map, [
+            (0..0x1000, FrameZero),
+            (0x1000..0x9f000, Usable),
+            // The old kernel and what it had merge with the free memory after
+            (0x100000..0x1000000, Usable),
+            (0x1000000..0x1400000, Kernel),
+            (0x1400000..0x1480000, KernelStack),
+            (0x1480000..0x8000000, Usable),
+            (0xfffc0000..0x100000000, Reserved),
+        ]);
+    }
+
+    #[test_case]
+    fn test_too_many_regions() {
+        let regions: Vec<_> = (0..MAX_REGIONS as u64 + 1)
+            .map(|n| (n * 0x2000..n * 0x2000 + 0x1000, Usable))
+            .collect();
+        assert_eq!(new_map(&regions, &[]), Err(KexecError::TooManyRegions));
+        assert!(new_map(&regions[1..], &[]).is_ok());
+    }
+}
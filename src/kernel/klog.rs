@@ -0,0 +1,95 @@
+// Kernel log ring
+//
+// Everything printed with `println!` is also appended here, overwriting
+// the oldest text once the ring is full, so the recent kernel log can be
+// recovered after the screen has scrolled or the machine has panicked.
+// The ring is a fixed static buffer: writing it never allocates.
+
+use core::fmt;
+use spin::Mutex;
+
+pub const LOG_SIZE: usize = 16 * 1024;
+
+pub struct LogRing {
+    buf: [u8; LOG_SIZE],
+    /// Bytes ever written; the next one goes at `written % LOG_SIZE`
+    written: usize,
+}
+
+static KLOG: Mutex<LogRing> = Mutex::new(LogRing::new());
+
+impl LogRing {
+    pub const fn new() -> Self {
+        LogRing { buf: [0; LOG_SIZE], written: 0 }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.buf[self.written % LOG_SIZE] = byte;
+            self.written += 1;
+        }
+    }
+
+    /// The retained text as two slices, oldest first
+    pub fn contents(&self) -> (&[u8], &[u8]) {
+        if self.written <= LOG_SIZE {
+            (&self.buf[..self.written], &[])
+        } else {
+            let start = self.written % LOG_SIZE;
+            (&self.buf[start..], &self.buf[..start])
+        }
+    }
+
+    /// Copy the newest bytes that fit into `out`, returning how many
+    pub fn copy_tail(&self, out: &mut [u8]) -> usize {
+        let (old, new) = self.contents();
+        let total = old.len() + new.len();
+        let skip = total.saturating_sub(out.len());
+        let mut n = 0;
+        for &byte in old.iter().chain(new.iter()).skip(skip) {
+            out[n] = byte;
+            n += 1;
+        }
+        n
+    }
+}
+
+impl fmt::Write for LogRing {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Append formatted text, as `println!` does
+pub fn write_fmt(args: fmt::Arguments) {
+    use core::fmt::Write;
+    KLOG.lock().write_fmt(args).ok();
+}
+
+/// Run `f` on the ring, or not at all if it is locked: for the panic path,
+/// which may have interrupted a writer
+pub fn try_with<R>(f: impl FnOnce(&LogRing) -> R) -> Option<R> {
+    KLOG.try_lock().map(|ring| f(&ring))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_ring_keeps_newest_text() {
+        let mut ring = LogRing::new();
+        ring.push(b"boot\n");
+        assert_eq!(ring.contents(), (&b"boot\n"[..], &b""[..]));
+        for _ in 0..LOG_SIZE / 4 {
+            ring.push(b"abcd");
+        }
+        ring.push(b"XY");
+        let mut tail = [0u8; 6];
+        assert_eq!(ring.copy_tail(&mut tail), 6);
+        assert_eq!(&tail, b"abcdXY");
+        let (old, new) = ring.contents();
+        assert_eq!(old.len() + new.len(), LOG_SIZE);
+    }
+}
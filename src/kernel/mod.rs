@@ -2,23 +2,77 @@
 pub mod scheduler;
 pub mod sys;
 pub mod init;
+pub mod getty;
 pub mod kernel;
+pub mod config;
+pub mod canary;
+pub mod time;
+pub mod idle;
+pub mod procfs;
+pub mod devfs;
+#[cfg(feature = "framebuffer")]
+pub mod compositor;
+pub mod container;
+pub mod klog;
+pub mod crashdump;
+pub mod hibernate;
+pub mod kexec;
+pub mod pipe;
+pub mod pstore;
+pub mod qpkg;
+pub mod sysupdate;
+pub mod modules;
+pub mod screendump;
+pub mod trace;
+pub mod exec;
+pub mod file;
+pub mod bootui;
+pub mod tz;
+pub mod theme;
 
 pub use init::*;
 pub use kernel::*;
 
-use crate::println;
+use bootui::stage;
+
+/// Stages `init` reports, for the boot progress bar
+pub const BOOT_STAGES: usize = 6 + cfg!(feature = "net") as usize;
 
 pub fn init() {
-    println!("  [KERNEL] Initializing scheduler...");
-    scheduler::init();
-    
-    println!("  [KERNEL] Initializing syscall interface...");
-    sys::init();
-    
-    println!("  [KERNEL] Initializing filesystem...");
-    crate::fs::init();
-    
-    println!("  [KERNEL] Initializing security framework...");
-    crate::qsf::init();
+    stage("Real-time clock", time::init);
+
+    stage("Scheduler", || {
+        crate::alloc_tag!(Sched);
+        scheduler::init();
+    });
+
+    stage("Syscall interface", || {
+        sys::init();
+        crashdump::init();
+    });
+
+    stage("Filesystems", || {
+        crate::alloc_tag!(Fs);
+        crate::fs::init();
+        crate::fs::vfs::VFS.lock().quotas_mut().set_clock(time::now);
+        crate::fs::tmpfs::set_clock(time::now);
+        devfs::init();
+        pstore::init();
+        modules::init();
+        // After the initramfs, which may carry /etc/timezone
+        tz::init();
+    });
+
+    // Starts the kernel on the boot partition in place of this one, if
+    // there is one, so before anything else is brought up
+    stage("Boot partition kernel", sysupdate::boot);
+
+    #[cfg(feature = "net")]
+    stage("Network stack", || {
+        crate::alloc_tag!(Net);
+        crate::net::init();
+        crate::net::tftpfs::init();
+    });
+
+    stage("Security framework", crate::qsf::init);
 }
@@ -0,0 +1,160 @@
+// Boot modules
+//
+// The bootloader loads only the kernel and its BootInfo has no module
+// list, so modules come from a disk instead: `modules=ataN` names an IDE
+// disk holding a tar archive, gzip-compressed or not, and every regular
+// file in it is one module. At boot each module is copied to
+// /boot/modules/<name> and consumed by kind before the shell starts: an
+// initramfs is unpacked over the root, and QSF loads *.policy files
+// before any lockdown, so they are measured with the rest.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::fs::archive::{gzip, tar};
+use crate::fs::{FsError, FsResult};
+use crate::fs::vfs::api as vfs_api;
+use crate::hal::drivers::ata::{AtaError, Drive, SECTOR_SIZE};
+
+pub const PARAM: &str = "modules";
+pub const MODULES_DIR: &str = "/boot/modules";
+
+/// Most of the disk read for the archive; the heap is small
+const MAX_ARCHIVE: usize = 2 * 1024 * 1024;
+/// Largest archive or initramfs after decompression
+const MAX_UNPACKED: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// initramfs.tar or initramfs.tar.gz, unpacked into /
+    Initramfs,
+    /// A QSF policy in text form
+    Policy,
+    /// Anything else: left in /boot/modules for whoever wants it
+    Data,
+}
+
+impl Kind {
+    pub fn of(name: &str) -> Kind {
+        match name {
+            "initramfs.tar" | "initramfs.tar.gz" | "initramfs.tgz" => Kind::Initramfs,
+            _ if name.ends_with(".policy") => Kind::Policy,
+            _ => Kind::Data,
+        }
+    }
+}
+
+enum ModuleError {
+    Disk(AtaError),
+    Fs(FsError),
+}
+
+impl core::fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ModuleError::Disk(e) => write!(f, "disk error {:?}", e),
+            ModuleError::Fs(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+impl From<AtaError> for ModuleError {
+    fn from(e: AtaError) -> Self {
+        ModuleError::Disk(e)
+    }
+}
+
+impl From<FsError> for ModuleError {
+    fn from(e: FsError) -> Self {
+        ModuleError::Fs(e)
+    }
+}
+
+fn read_archive(drive: Drive) -> Result<Vec<u8>, ModuleError> {
+    let sectors = drive.identify()? as usize;
+    let mut data = alloc::vec![0u8; (sectors * SECTOR_SIZE).min(MAX_ARCHIVE)];
+    drive.read_sectors(0, &mut data)?;
+    if gzip::is_gzip(&data) {
+        data = gzip::decompress(&data, MAX_UNPACKED)?;
+    }
+    Ok(data)
+}
+
+fn unpack_initramfs(data: &[u8]) -> FsResult<usize> {
+    let unpacked;
+    let archive = if gzip::is_gzip(data) {
+        unpacked = gzip::decompress(data, MAX_UNPACKED)?;
+        &unpacked[..]
+    } else {
+        data
+    };
+    tar::extract(archive, "/", |_| {})
+}
+
+fn load(drive: Drive) -> Result<(), ModuleError> {
+    let archive = read_archive(drive)?;
+    vfs_api::mkdir("/boot", 0o755).ok();
+    vfs_api::mkdir(MODULES_DIR, 0o755).ok();
+
+    for entry in tar::Reader::new(&archive) {
+        let entry = entry?;
+        let name = match entry.path.rsplit('/').next() {
+            Some(name) if entry.kind == tar::EntryKind::File && !name.is_empty() => name,
+            _ => continue,
+        };
+        vfs_api::write_file(&format!("{}/{}", MODULES_DIR, name), entry.data, 0o444)?;
+        crate::println!("  [KERNEL] Module {} ({} bytes)", name, entry.data.len());
+        if Kind::of(name) == Kind::Initramfs {
+            match unpack_initramfs(entry.data) {
+                Ok(count) => crate::println!("  [KERNEL] Unpacked {} entries from {}", count, name),
+                Err(e) => crate::println!("  [KERNEL] {}: cannot unpack: {:?}", name, e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Load the modules named by `modules=`. Needs the VFS.
+pub fn init() {
+    let value = match super::get_param(PARAM) {
+        Some(value) => value,
+        None => return,
+    };
+    let drive = match Drive::from_name(&value) {
+        Some(drive) => drive,
+        None => {
+            crate::println!("  [KERNEL] {}={}: not an IDE disk (ata0-ata3)", PARAM, value);
+            return;
+        }
+    };
+    if let Err(e) = load(drive) {
+        crate::println!("  [KERNEL] {}={}: modules not loaded ({})", PARAM, value, e);
+    }
+}
+
+/// The policy modules, as (name, text)
+pub fn policies() -> Vec<(String, String)> {
+    let entries = match vfs_api::readdir(MODULES_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries.into_iter()
+        .filter(|e| Kind::of(&e.name) == Kind::Policy)
+        .filter_map(|e| {
+            let data = vfs_api::read_file(&format!("{}/{}", MODULES_DIR, e.name)).ok()?;
+            Some((e.name, String::from_utf8(data).ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_module_kinds() {
+        assert_eq!(Kind::of("initramfs.tar.gz"), Kind::Initramfs);
+        assert_eq!(Kind::of("site.policy"), Kind::Policy);
+        assert_eq!(Kind::of("fixture.bin"), Kind::Data);
+    }
+}
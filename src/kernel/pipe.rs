@@ -0,0 +1,231 @@
+// Pipes
+//
+// A pipe is a ring buffer with a read end and a write end, which file
+// descriptors hold rather than a path; each end may be held by several
+// descriptors at once, through dup or fork, and the pipe counts them.
+// Reading an empty pipe waits for a writer to write, or gives end of file
+// once no descriptor holds the write end. Writing waits while the pipe is
+// full until it has all gone in; with no descriptor left holding the read
+// end it fails with EPIPE, and the writing task is sent SIGPIPE, which
+// ends a program that neither handles nor ignores it. O_NONBLOCK on the
+// descriptor gives EAGAIN rather than waiting.
+//
+// Programs run one at a time, so a wait only ends when an interrupt
+// handler changes something, the other end is dropped, or a signal that
+// would end the task arrives, which gives EINTR.
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::kernel::sys::posix::signals;
+use crate::kernel::sys::{Errno, SysResult};
+
+/// What pipe(2) makes hold, as on Linux
+pub const PIPE_SIZE: usize = 64 * 1024;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Read,
+    Write,
+}
+
+struct Buffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+    readers: usize,
+    writers: usize,
+}
+
+struct Pipe {
+    id: u64,
+    buffer: Mutex<Buffer>,
+}
+
+/// One end of a pipe, as a descriptor holds it
+pub struct End {
+    pipe: Arc<Pipe>,
+    side: Side,
+}
+
+/// A new pipe holding up to `capacity` bytes, as its read and write ends
+pub fn pipe(capacity: usize) -> (End, End) {
+    let pipe = Arc::new(Pipe {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        buffer: Mutex::new(Buffer { data: VecDeque::new(), capacity, readers: 1, writers: 1 }),
+    });
+    (End { pipe: pipe.clone(), side: Side::Read }, End { pipe, side: Side::Write })
+}
+
+/// Whether the current task has been sent a signal that would end it
+fn interrupted() -> bool {
+    let scheduler = SCHEDULER.lock();
+    scheduler.current().is_some_and(|task| {
+        (1..signals::NSIG as u8).any(|signal| task.has_pending_signal(signal) && signals::is_fatal_signal(signal as i32))
+    })
+}
+
+/// Wait for an interrupt, with interrupts on for it even in a syscall
+fn wait() -> SysResult<()> {
+    if interrupted() {
+        return Err(Errno::EINTR);
+    }
+    crate::kernel::idle::run();
+    let enabled = x86_64::instructions::interrupts::are_enabled();
+    x86_64::instructions::interrupts::enable_and_hlt();
+    if !enabled {
+        x86_64::instructions::interrupts::disable();
+    }
+    Ok(())
+}
+
+impl End {
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    /// How it shows in a descriptor's path, as `pipe:[N]`
+    pub fn name(&self) -> alloc::string::String {
+        alloc::format!("pipe:[{}]", self.pipe.id)
+    }
+
+    /// Bytes waiting to be read
+    pub fn available(&self) -> usize {
+        self.pipe.buffer.lock().data.len()
+    }
+
+    /// Read what is there, up to `buf.len()` bytes: None if that is
+    /// nothing yet, Some(0) at end of file
+    pub fn try_read(&self, buf: &mut [u8]) -> SysResult<Option<usize>> {
+        if self.side != Side::Read {
+            return Err(Errno::EBADF);
+        }
+        let mut buffer = self.pipe.buffer.lock();
+        if buffer.data.is_empty() {
+            return Ok((buffer.writers == 0).then_some(0));
+        }
+        let n = buf.len().min(buffer.data.len());
+        for (to, from) in buf.iter_mut().zip(buffer.data.drain(..n)) {
+            *to = from;
+        }
+        Ok(Some(n))
+    }
+
+    /// Write as much of `data` as there is room for: None if that is
+    /// nothing yet
+    pub fn try_write(&self, data: &[u8]) -> SysResult<Option<usize>> {
+        if self.side != Side::Write {
+            return Err(Errno::EBADF);
+        }
+        let mut buffer = self.pipe.buffer.lock();
+        if buffer.readers == 0 {
+            return Err(Errno::EPIPE);
+        }
+        let n = data.len().min(buffer.capacity - buffer.data.len());
+        if n == 0 && !data.is_empty() {
+            return Ok(None);
+        }
+        buffer.data.extend(&data[..n]);
+        Ok(Some(n))
+    }
+
+    /// Read up to `buf.len()` bytes, waiting for some unless `nonblock`
+    pub fn read(&self, buf: &mut [u8], nonblock: bool) -> SysResult<usize> {
+        loop {
+            match self.try_read(buf)? {
+                Some(n) => return Ok(n),
+                None if nonblock => return Err(Errno::EAGAIN),
+                None => wait()?,
+            }
+        }
+    }
+
+    /// Write all of `data`, waiting for room as it takes; with `nonblock`
+    /// only what fits now. What a failure cuts short counts as written if
+    /// any of it went in.
+    pub fn write(&self, data: &[u8], nonblock: bool) -> SysResult<usize> {
+        let mut written = 0;
+        loop {
+            let result = match self.try_write(&data[written..]) {
+                Ok(Some(n)) => {
+                    written += n;
+                    if written == data.len() {
+                        return Ok(written);
+                    }
+                    continue;
+                }
+                Ok(None) if nonblock => Err(Errno::EAGAIN),
+                Ok(None) => wait(),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                return if written > 0 { Ok(written) } else { Err(e) };
+            }
+        }
+    }
+}
+
+impl Clone for End {
+    fn clone(&self) -> Self {
+        let mut buffer = self.pipe.buffer.lock();
+        match self.side {
+            Side::Read => buffer.readers += 1,
+            Side::Write => buffer.writers += 1,
+        }
+        End { pipe: self.pipe.clone(), side: self.side }
+    }
+}
+
+impl Drop for End {
+    fn drop(&mut self) {
+        let mut buffer = self.pipe.buffer.lock();
+        match self.side {
+            Side::Read => buffer.readers -= 1,
+            Side::Write => buffer.writers -= 1,
+        }
+    }
+}
+
+impl fmt::Debug for End {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({:?})", self.name(), self.side)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_pipe_round_trip() {
+        let (reader, writer) = pipe(4);
+        let mut buf = [0u8; 8];
+        assert_eq!(reader.try_read(&mut buf), Ok(None));
+        assert_eq!(writer.write(b"abcdef", true), Ok(4));
+        assert_eq!(writer.try_write(b"ef"), Ok(None));
+        assert_eq!(writer.write(b"ef", true), Err(Errno::EAGAIN));
+        assert_eq!(reader.read(&mut buf[..3], false), Ok(3));
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(reader.try_write(b"x"), Err(Errno::EBADF));
+
+        // End of file once every write end is gone, after what is left
+        let second = writer.clone();
+        drop(writer);
+        assert_eq!(second.write(b"e", false), Ok(1));
+        drop(second);
+        assert_eq!(reader.read(&mut buf, false), Ok(2));
+        assert_eq!(&buf[..2], b"de");
+        assert_eq!(reader.read(&mut buf, false), Ok(0));
+    }
+
+    #[test_case]
+    fn test_broken_pipe() {
+        let (reader, writer) = pipe(PIPE_SIZE);
+        drop(reader);
+        assert_eq!(writer.write(b"lost", false), Err(Errno::EPIPE));
+    }
+}
@@ -0,0 +1,432 @@
+// /proc/<pid>/fd, /proc/<pid>/schedstat, /proc/<pid>/status,
+// /proc/<pid>/cmdline, /proc/diskinfo, /proc/meminfo, /proc/loadavg,
+// /proc/uptime, /proc/cpuinfo, /proc/lockdep, /proc/trace and
+// /proc/sys/kernel/features
+//
+// Filesystems are grafted into the VFS as a copy of their tree, so one
+// whose entries come and go with the tasks cannot be mounted; /proc is
+// kept as ordinary VFS nodes that are rebuilt from the scheduler on
+// demand instead: each live task gets /proc/<pid>/fd with one symlink per
+// open descriptor pointing at the path it was opened with,
+// /proc/<pid>/schedstat with its CPU time and context switches,
+// /proc/<pid>/status with its name, state, ids and signals and
+// /proc/<pid>/cmdline with the arguments it was exec'd with. /proc/diskinfo
+// is rewritten from the IDENTIFY data the disks gave at boot,
+// /proc/meminfo from the frame allocator and the heap, /proc/loadavg and
+// /proc/uptime from the scheduler, /proc/cpuinfo from CPUID,
+// /proc/lockdep from the lock order seen so far, and /proc/trace from the
+// trace rings, after acting on `on`, `off` or `clear` if one was written
+// to it since, and /proc/sys/kernel/features from the ABI table
+// (sys::abi). The shell refreshes before each
+// command and the path syscalls before resolving a /proc path. refresh() takes
+// SCHEDULER and then VFS, so it must not be called with either held.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::fmt::Write;
+use alloc::vec::Vec;
+use crate::fs::{FileMode, FileType};
+use crate::fs::vfs::{VirtualFileSystem, VFS};
+use crate::fs::vfs::api::{self as vfs_api, OpenFlags};
+use crate::hal::drivers::ata;
+use crate::hal::lockdep;
+use crate::hal::memory::{frame_allocator, heap};
+use crate::hal::drivers::pit;
+use crate::kernel::scheduler::{self, loadavg, Scheduler, Task, TaskState, SCHEDULER};
+use crate::kernel::sys::abi::{ABI_VERSION, SUBSYSTEMS, SYSCALLS};
+use crate::kernel::trace;
+
+pub const PROC_ROOT: &str = "/proc";
+pub const DISKINFO: &str = "/proc/diskinfo";
+pub const MEMINFO: &str = "/proc/meminfo";
+pub const LOADAVG: &str = "/proc/loadavg";
+pub const UPTIME: &str = "/proc/uptime";
+pub const CPUINFO: &str = "/proc/cpuinfo";
+pub const LOCKDEP: &str = "/proc/lockdep";
+pub const TRACE: &str = "/proc/trace";
+pub const FEATURES: &str = "/proc/sys/kernel/features";
+
+/// A task's pid, uid and gid, and the files of its /proc/<pid> by name
+type TaskFiles = (u32, u32, u32, [(&'static str, String); 3]);
+
+/// Whether the absolute path `path` lies under /proc
+pub fn is_proc_path(path: &str) -> bool {
+    path.strip_prefix(PROC_ROOT).map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Refresh /proc first if `path` (relative to the cwd) lies under it
+pub fn refresh_for(path: &str) {
+    let path = VFS.lock().resolve_path(path);
+    if is_proc_path(&path) {
+        refresh();
+    }
+}
+
+/// Rebuild /proc/<pid> for every task and drop the entries of tasks that
+/// have gone away
+pub fn refresh() {
+    crate::alloc_tag!(Fs);
+    let (owners, loadavg, uptime) = {
+        let scheduler = SCHEDULER.lock();
+        let owners: Vec<TaskFiles> = scheduler.get_tasks()
+            .map(|task| (task.pid, task.uid, task.gid, [
+                ("schedstat", schedstat(task)),
+                ("status", status(task)),
+                ("cmdline", cmdline(task)),
+            ]))
+            .collect();
+        (owners, loadavg(&scheduler), uptime(&scheduler))
+    };
+    let cpuinfo = cpuinfo();
+    let fds = scheduler::open_fds(None);
+    let diskinfo = diskinfo();
+    let meminfo = meminfo();
+    let lockdep = lockdep::dump();
+    if let Some(command) = trace_command() {
+        trace::control(&command).ok();
+    }
+    let trace = trace::dump();
+
+    let mut vfs = VFS.lock();
+    let stale: Vec<String> = match vfs.lookup_path(PROC_ROOT).and_then(|proc| proc.readdir().map(|e| e.to_vec())) {
+        Ok(entries) => entries.into_iter()
+            .filter(|e| e.file_type == FileType::Directory && e.name.parse::<u32>().is_ok())
+            .map(|e| e.name)
+            .collect(),
+        Err(_) => return,
+    };
+    for pid in stale {
+        remove_tree(&mut vfs, &format!("{}/{}", PROC_ROOT, pid));
+    }
+
+    for (pid, uid, gid, files) in owners {
+        let dir = format!("{}/{}", PROC_ROOT, pid);
+        let fd_dir = format!("{}/fd", dir);
+        vfs.create_directory(&dir, FileMode::new(0o555)).ok();
+        vfs.create_directory(&fd_dir, FileMode::new(0o500)).ok();
+        for info in fds.iter().filter(|info| info.pid == pid) {
+            vfs.create_symlink(&format!("{}/{}", fd_dir, info.fd), &info.path).ok();
+        }
+        let mut paths = alloc::vec![dir.clone(), fd_dir];
+        for (name, text) in files {
+            let path = format!("{}/{}", dir, name);
+            if let Ok(node) = vfs.create_file(&path, FileMode::new(0o444)) {
+                vfs.write_node(node.inode, 0, text.as_bytes()).ok();
+            }
+            paths.push(path);
+        }
+        for path in &paths {
+            vfs.chown(path, uid, gid).ok();
+        }
+    }
+
+    for dir in ["/proc/sys", "/proc/sys/kernel"] {
+        vfs.create_directory(dir, FileMode::new(0o555)).ok();
+    }
+    let features = features();
+    let files = [
+        (DISKINFO, &diskinfo, 0o444),
+        (MEMINFO, &meminfo, 0o444),
+        (LOADAVG, &loadavg, 0o444),
+        (UPTIME, &uptime, 0o444),
+        (CPUINFO, &cpuinfo, 0o444),
+        (LOCKDEP, &lockdep, 0o444),
+        (TRACE, &trace, 0o644),
+        (FEATURES, &features, 0o444),
+    ];
+    for (path, text, mode) in files {
+        vfs.remove_file(path).ok();
+        if let Ok(node) = vfs.create_file(path, FileMode::new(mode)) {
+            vfs.write_node(node.inode, 0, text.as_bytes()).ok();
+        }
+    }
+}
+
+/// What was written over /proc/trace, if anything: its dump starts with a
+/// comment, so a first line that is not one is a command
+fn trace_command() -> Option<String> {
+    let mut fd = vfs_api::open(TRACE, OpenFlags::O_RDONLY, 0).ok()?;
+    let mut head = [0u8; 16];
+    let len = vfs_api::read(&mut fd, &mut head).ok()?;
+    let line = core::str::from_utf8(&head[..len]).ok()?.lines().next()?.trim();
+    (!line.is_empty() && !line.starts_with('#')).then(|| String::from(line))
+}
+
+/// The ABI version, then a line per syscall, as `syscall` or `enosys`
+/// with its name, number and the version it came in, and one per
+/// subsystem present, so a script can grep for `^syscall mount ` or
+/// `^subsystem net$`
+fn features() -> String {
+    let mut text = format!("abi {}\n", ABI_VERSION);
+    for s in SYSCALLS {
+        let kind = if s.implemented { "syscall" } else { "enosys" };
+        writeln!(text, "{} {} {} {}", kind, s.name, s.num, s.since).ok();
+    }
+    for (name, _) in SUBSYSTEMS.iter().filter(|(_, present)| *present) {
+        writeln!(text, "subsystem {}", name).ok();
+    }
+    text
+}
+
+/// One block per disk of "key: value" lines, blocks separated by a blank
+/// line
+fn diskinfo() -> String {
+    let mut text = String::new();
+    for (n, (drive, disk)) in ata::identities().into_iter().enumerate() {
+        if n > 0 {
+            text.push('\n');
+        }
+        let media = match disk.rotation_rate {
+            Some(1) => String::from("solid state"),
+            Some(rpm) => format!("{} rpm", rpm),
+            None => String::from("unknown"),
+        };
+        writeln!(text, "device:   {}", drive.device_name()).ok();
+        writeln!(text, "model:    {}", disk.model).ok();
+        writeln!(text, "serial:   {}", disk.serial).ok();
+        writeln!(text, "firmware: {}", disk.firmware).ok();
+        writeln!(text, "sectors:  {} ({} bytes)", disk.sectors, disk.sectors * ata::SECTOR_SIZE as u64).ok();
+        writeln!(text, "media:    {}", media).ok();
+        writeln!(text, "features: {}", disk.features().join(" ")).ok();
+        let smart = match (disk.smart_supported, disk.smart_enabled) {
+            (false, _) => "unsupported",
+            (true, false) => "disabled",
+            (true, true) => "enabled",
+        };
+        writeln!(text, "smart:    {}", smart).ok();
+    }
+    text
+}
+
+/// "Key: value kB" lines, as Linux has them, for physical memory and the
+/// kernel heap
+fn meminfo() -> String {
+    let (total, used) = frame_allocator::FRAME_ALLOCATOR.lock().as_ref()
+        .map_or((0, 0), |frames| (frames.total_memory(), frames.used_frames() as u64 * 4096));
+    let heap = heap::get_heap_stats();
+    let mut text = String::new();
+    for (key, bytes) in [
+        ("MemTotal", total),
+        ("MemFree", total.saturating_sub(used)),
+        ("HeapSize", heap.total as u64),
+        ("HeapMax", heap.max as u64),
+        ("HeapUsed", heap.used as u64),
+        ("HeapFree", heap.free as u64),
+    ] {
+        writeln!(text, "{:<12}{:>10} kB", format!("{}:", key), bytes / 1024).ok();
+    }
+    writeln!(text, "{:<12}{:>10}", "HeapGrowths:", heap::heap_growths()).ok();
+    text
+}
+
+/// The 1, 5 and 15 minute load averages, runnable and total tasks, and
+/// the last pid handed out, as Linux has them: "0.16 0.03 0.01 1/12 42"
+fn loadavg(scheduler: &Scheduler) -> String {
+    let [one, five, fifteen] = scheduler.load.averages().map(loadavg::split);
+    format!(
+        "{}.{:02} {}.{:02} {}.{:02} {}/{} {}\n",
+        one.0, one.1, five.0, five.1, fifteen.0, fifteen.1,
+        scheduler.running_count(),
+        scheduler.task_count(),
+        scheduler.pids.last()
+    )
+}
+
+/// Seconds since boot and seconds the idle task has run, to hundredths,
+/// as Linux has them: "350.42 339.17". A tick is a millisecond.
+fn uptime(scheduler: &Scheduler) -> String {
+    let idle = scheduler.idle_pid.and_then(|pid| scheduler.get_task(pid)).map_or(0, |task| task.cpu_time);
+    let [up, idle] = [pit::get_uptime_ms(), idle].map(|ms| (ms / 1000, ms % 1000 / 10));
+    format!("{}.{:02} {}.{:02}\n", up.0, up.1, idle.0, idle.1)
+}
+
+/// "Key:\tvalue" lines about `task`, as Linux has them, with the same
+/// keys for what Qunix keeps: its pid in each PID namespace, outermost
+/// first, under NSpid, and the saved and filesystem ids, which it does not
+/// keep apart, as the effective ones
+fn status(task: &Task) -> String {
+    let state = match task.state {
+        TaskState::Ready | TaskState::Running => "R (running)",
+        TaskState::Blocked | TaskState::Sleeping => "S (sleeping)",
+        TaskState::Stopped => "T (stopped)",
+        TaskState::Zombie => "Z (zombie)",
+    };
+    let ns_pids: Vec<String> = core::iter::once(task.pid)
+        .chain(task.ns_pids.iter().rev().map(|&(_, pid)| pid))
+        .map(|pid| pid.to_string())
+        .collect();
+    let mut text = String::new();
+    writeln!(text, "Name:\t{}", task.name).ok();
+    writeln!(text, "Umask:\t{:04o}", task.umask).ok();
+    writeln!(text, "State:\t{}", state).ok();
+    writeln!(text, "Tgid:\t{}", task.pid).ok();
+    writeln!(text, "Pid:\t{}", task.pid).ok();
+    writeln!(text, "PPid:\t{}", task.ppid.unwrap_or(0)).ok();
+    writeln!(text, "Uid:\t{0}\t{1}\t{1}\t{1}", task.uid, task.euid).ok();
+    writeln!(text, "Gid:\t{0}\t{1}\t{1}\t{1}", task.gid, task.egid).ok();
+    writeln!(text, "FDSize:\t{}", task.nofile).ok();
+    writeln!(text, "NSpid:\t{}", ns_pids.join("\t")).ok();
+    writeln!(text, "NSpgid:\t{}", task.pgid).ok();
+    writeln!(text, "NSsid:\t{}", task.sid).ok();
+    writeln!(text, "Threads:\t1").ok();
+    writeln!(text, "SigPnd:\t{:016x}", task.pending_signals).ok();
+    writeln!(text, "SigBlk:\t{:016x}", task.signal_mask).ok();
+    text
+}
+
+/// The arguments `task` was exec'd with, each ended by a NUL; empty for
+/// a kernel task
+fn cmdline(task: &Task) -> String {
+    task.cmdline.iter().fold(String::new(), |mut text, arg| {
+        text.push_str(arg);
+        text.push('\0');
+        text
+    })
+}
+
+/// Feature flags, by CPUID leaf and register, as Linux names them
+const CPU_FLAGS: &[(u32, Reg, u32, &str)] = &[
+    (1, Reg::Edx, 0, "fpu"), (1, Reg::Edx, 1, "vme"), (1, Reg::Edx, 2, "de"), (1, Reg::Edx, 3, "pse"),
+    (1, Reg::Edx, 4, "tsc"), (1, Reg::Edx, 5, "msr"), (1, Reg::Edx, 6, "pae"), (1, Reg::Edx, 7, "mce"),
+    (1, Reg::Edx, 8, "cx8"), (1, Reg::Edx, 9, "apic"), (1, Reg::Edx, 11, "sep"), (1, Reg::Edx, 12, "mtrr"),
+    (1, Reg::Edx, 13, "pge"), (1, Reg::Edx, 14, "mca"), (1, Reg::Edx, 15, "cmov"), (1, Reg::Edx, 16, "pat"),
+    (1, Reg::Edx, 17, "pse36"), (1, Reg::Edx, 19, "clflush"), (1, Reg::Edx, 23, "mmx"), (1, Reg::Edx, 24, "fxsr"),
+    (1, Reg::Edx, 25, "sse"), (1, Reg::Edx, 26, "sse2"), (1, Reg::Edx, 28, "ht"),
+    (0x8000_0001, Reg::Edx, 11, "syscall"), (0x8000_0001, Reg::Edx, 20, "nx"), (0x8000_0001, Reg::Edx, 26, "pdpe1gb"),
+    (0x8000_0001, Reg::Edx, 27, "rdtscp"), (0x8000_0001, Reg::Edx, 29, "lm"),
+    (1, Reg::Ecx, 0, "pni"), (1, Reg::Ecx, 1, "pclmulqdq"), (1, Reg::Ecx, 9, "ssse3"), (1, Reg::Ecx, 12, "fma"),
+    (1, Reg::Ecx, 13, "cx16"), (1, Reg::Ecx, 19, "sse4_1"), (1, Reg::Ecx, 20, "sse4_2"), (1, Reg::Ecx, 21, "x2apic"),
+    (1, Reg::Ecx, 22, "movbe"), (1, Reg::Ecx, 23, "popcnt"), (1, Reg::Ecx, 25, "aes"), (1, Reg::Ecx, 26, "xsave"),
+    (1, Reg::Ecx, 28, "avx"), (1, Reg::Ecx, 29, "f16c"), (1, Reg::Ecx, 30, "rdrand"), (1, Reg::Ecx, 31, "hypervisor"),
+    (0x8000_0001, Reg::Ecx, 0, "lahf_lm"), (0x8000_0001, Reg::Ecx, 5, "abm"),
+    (7, Reg::Ebx, 0, "fsgsbase"), (7, Reg::Ebx, 3, "bmi1"), (7, Reg::Ebx, 5, "avx2"), (7, Reg::Ebx, 7, "smep"),
+    (7, Reg::Ebx, 8, "bmi2"), (7, Reg::Ebx, 9, "erms"), (7, Reg::Ebx, 16, "avx512f"), (7, Reg::Ebx, 18, "rdseed"),
+    (7, Reg::Ebx, 19, "adx"), (7, Reg::Ebx, 20, "smap"), (7, Reg::Ebx, 29, "sha_ni"),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Reg {
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// Family, model and stepping from CPUID leaf 1's EAX, the extended
+/// fields added in as the vendors define them
+fn signature(eax: u32) -> (u32, u32, u32) {
+    let (stepping, model, family) = (eax & 0xF, eax >> 4 & 0xF, eax >> 8 & 0xF);
+    let family = if family == 0xF { family + (eax >> 20 & 0xFF) } else { family };
+    let model = if family >= 6 { model | (eax >> 16 & 0xF) << 4 } else { model };
+    (family, model, stepping)
+}
+
+/// "key\t: value" lines for the one CPU the kernel runs on, as Linux has
+/// them, from CPUID and the TSC rate the PIT measured
+fn cpuinfo() -> String {
+    use core::arch::x86_64::__cpuid;
+
+    let leaf0 = __cpuid(0);
+    let vendor: Vec<u8> = [leaf0.ebx, leaf0.edx, leaf0.ecx].iter().flat_map(|r| r.to_le_bytes()).collect();
+    let max_extended = __cpuid(0x8000_0000).eax;
+    let leaf = |n: u32| {
+        let max = if n >= 0x8000_0000 { max_extended } else { leaf0.eax };
+        (n <= max).then(|| __cpuid(n))
+    };
+    let model_name = if leaf(0x8000_0004).is_some() {
+        let brand: Vec<u8> = (0x8000_0002..=0x8000_0004u32)
+            .flat_map(|n| {
+                let r = __cpuid(n);
+                [r.eax, r.ebx, r.ecx, r.edx]
+            })
+            .flat_map(|r| r.to_le_bytes())
+            .take_while(|&b| b != 0)
+            .collect();
+        String::from_utf8_lossy(&brand).trim().to_string()
+    } else {
+        String::from("unknown")
+    };
+    let leaves = [1, 7, 0x8000_0001].map(|n| (n, leaf(n)));
+    let (family, model, stepping) = signature(leaves[0].1.map_or(0, |r| r.eax));
+    let flags: Vec<&str> = CPU_FLAGS.iter()
+        .filter(|&&(n, reg, bit, _)| {
+            let Some((_, Some(r))) = leaves.iter().find(|(leaf, _)| *leaf == n) else {
+                return false;
+            };
+            let value = match reg {
+                Reg::Ebx => r.ebx,
+                Reg::Ecx => r.ecx,
+                Reg::Edx => r.edx,
+            };
+            value >> bit & 1 != 0
+        })
+        .map(|&(_, _, _, name)| name)
+        .collect();
+    let mhz = pit::tsc_per_us();
+
+    let mut text = String::new();
+    writeln!(text, "processor\t: 0").ok();
+    writeln!(text, "vendor_id\t: {}", String::from_utf8_lossy(&vendor)).ok();
+    writeln!(text, "cpu family\t: {}", family).ok();
+    writeln!(text, "model\t\t: {}", model).ok();
+    writeln!(text, "model name\t: {}", model_name).ok();
+    writeln!(text, "stepping\t: {}", stepping).ok();
+    writeln!(text, "cpu MHz\t\t: {}.000", mhz).ok();
+    writeln!(text, "flags\t\t: {}", flags.join(" ")).ok();
+    text
+}
+
+/// "key: value" lines of the time `task` has run and how often it has
+/// been switched in and out
+fn schedstat(task: &Task) -> String {
+    let mut text = String::new();
+    writeln!(text, "cpu_time_ms:                {}", task.cpu_time).ok();
+    writeln!(text, "timeslices:                 {}", task.timeslices).ok();
+    writeln!(text, "voluntary_ctxt_switches:    {}", task.nvcsw).ok();
+    writeln!(text, "nonvoluntary_ctxt_switches: {}", task.nivcsw).ok();
+    text
+}
+
+/// Remove `path` and everything below it
+fn remove_tree(vfs: &mut VirtualFileSystem, path: &str) {
+    let entries = match vfs.lookup_path(path).and_then(|node| node.readdir().map(|e| e.to_vec())) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.iter().filter(|e| e.name != "." && e.name != "..") {
+        let child = format!("{}/{}", path, entry.name);
+        if entry.file_type == FileType::Directory {
+            remove_tree(vfs, &child);
+        } else {
+            vfs.remove_file(&child).ok();
+        }
+    }
+    vfs.remove_directory(path).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_cpu_signature() {
+        // Skylake, and Zen 2 with its family in the extended field
+        assert_eq!(signature(0x0005_06E3), (6, 94, 3));
+        assert_eq!(signature(0x0087_0F10), (23, 113, 0));
+        assert_eq!(signature(0x0000_0633), (6, 3, 3));
+    }
+
+    #[test_case]
+    fn test_status_and_cmdline() {
+        let mut task = Task::new(42, String::from("/bin/sh"), 0, false).unwrap();
+        task.ppid = Some(1);
+        task.ns_pids = alloc::vec![(2, 1), (1, 5)];
+        task.cmdline = alloc::vec![String::from("sh"), String::from("-c"), String::from("true")];
+        let status = status(&task);
+        for line in ["Name:\t/bin/sh", "State:\tR (running)", "PPid:\t1", "Uid:\t1000\t1000\t1000\t1000", "NSpid:\t42\t5\t1"] {
+            assert!(status.lines().any(|l| l == line), "{}", line);
+        }
+        assert_eq!(cmdline(&task), "sh\0-c\0true\0");
+        task.cmdline.clear();
+        assert_eq!(cmdline(&task), "");
+    }
+}
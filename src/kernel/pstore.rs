@@ -0,0 +1,168 @@
+// Persistent kernel log (pstore)
+//
+// The top PSTORE_SIZE bytes of the highest usable memory region are kept
+// out of the frame allocators. On panic, reboot or kexec the tail of the kernel
+// log is copied there behind a header with a magic number, the reason and
+// a CRC-32. RAM survives a warm reset, so the next boot finds the record
+// at the same physical address, writes it to /var/log/prev-boot.log and
+// erases it. Saving never allocates and only try-locks.
+
+use core::fmt;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
+use bootloader::bootinfo::MemoryRegionType;
+use bootloader::BootInfo;
+use spin::Mutex;
+use crate::fs::archive::gzip::crc32;
+
+pub const PSTORE_SIZE: usize = 16 * 1024;
+pub const PREV_BOOT_LOG: &str = "/var/log/prev-boot.log";
+
+const MAGIC: &[u8; 8] = b"QPSTORE1";
+
+/// Why the previous boot's record was saved, 0 for no record
+static PREVIOUS: AtomicU32 = AtomicU32::new(0);
+const HEADER_SIZE: usize = 20;
+
+/// Why the log was saved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Panic = 1,
+    Reboot = 2,
+    Kexec = 3,
+}
+
+impl Reason {
+    fn from_u32(value: u32) -> Option<Reason> {
+        match value {
+            1 => Some(Reason::Panic),
+            2 => Some(Reason::Reboot),
+            3 => Some(Reason::Kexec),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Reason::Panic => "panic",
+            Reason::Reboot => "reboot",
+            Reason::Kexec => "kexec",
+        })
+    }
+}
+
+/// The reserved region: its physical range and where it is mapped
+struct Area {
+    phys: Range<u64>,
+    virt: usize,
+}
+
+static AREA: Mutex<Option<Area>> = Mutex::new(None);
+
+/// Set the region aside. Called before the heap is set up, while nothing
+/// has been allocated from it.
+pub fn reserve(boot_info: &BootInfo) {
+    let top = boot_info.memory_map.iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .filter(|r| r.range.end_addr() - r.range.start_addr() >= PSTORE_SIZE as u64)
+        .map(|r| r.range.end_addr())
+        .max();
+    if let Some(end) = top {
+        let phys = end - PSTORE_SIZE as u64..end;
+        crate::hal::memory::frame_allocator::reserve(phys.clone());
+        let virt = (boot_info.physical_memory_offset + phys.start) as usize;
+        *AREA.lock() = Some(Area { phys, virt });
+    }
+}
+
+fn area_bytes(area: &Area) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(area.virt as *mut u8, PSTORE_SIZE) }
+}
+
+/// A record found in the region
+#[derive(Debug, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub reason: Reason,
+    pub log: &'a [u8],
+    pub intact: bool,
+}
+
+/// Fill `area` with the log tail, header last
+fn store(area: &mut [u8], reason: Reason, copy: impl FnOnce(&mut [u8]) -> usize) {
+    area[..HEADER_SIZE].fill(0);
+    let length = copy(&mut area[HEADER_SIZE..]);
+    let crc = crc32(&area[HEADER_SIZE..HEADER_SIZE + length]);
+    area[8..12].copy_from_slice(&(reason as u32).to_le_bytes());
+    area[12..16].copy_from_slice(&(length as u32).to_le_bytes());
+    area[16..20].copy_from_slice(&crc.to_le_bytes());
+    area[..8].copy_from_slice(MAGIC);
+}
+
+fn load(area: &[u8]) -> Option<Record<'_>> {
+    let word = |at: usize| u32::from_le_bytes([area[at], area[at + 1], area[at + 2], area[at + 3]]);
+    if &area[..8] != MAGIC {
+        return None;
+    }
+    let reason = Reason::from_u32(word(8))?;
+    let length = (word(12) as usize).min(area.len() - HEADER_SIZE);
+    let log = &area[HEADER_SIZE..HEADER_SIZE + length];
+    Some(Record { reason, log, intact: crc32(log) == word(16) })
+}
+
+/// Copy the tail of the kernel log into the region
+pub fn save(reason: Reason) {
+    let area = match AREA.try_lock() {
+        Some(area) => area,
+        None => return,
+    };
+    if let Some(area) = area.as_ref() {
+        store(area_bytes(area), reason, |out| super::klog::try_with(|ring| ring.copy_tail(out)).unwrap_or(0));
+    }
+}
+
+/// Why the previous boot ended, if it left a record: Kexec for a kernel
+/// started by kexec
+pub fn previous() -> Option<Reason> {
+    Reason::from_u32(PREVIOUS.load(Ordering::Relaxed))
+}
+
+/// Hand the previous boot's record, if any, to /var/log and erase it
+pub fn init() {
+    let area = AREA.lock();
+    let area = match area.as_ref() {
+        Some(area) => area,
+        None => return,
+    };
+    crate::println!("  [KERNEL] pstore: {} KiB at {:#x}", PSTORE_SIZE / 1024, area.phys.start);
+    let bytes = area_bytes(area);
+    if let Some(record) = load(bytes) {
+        PREVIOUS.store(record.reason as u32, Ordering::Relaxed);
+        let damaged = if record.intact { "" } else { " (damaged)" };
+        match crate::fs::vfs::api::write_file(PREV_BOOT_LOG, record.log, 0o600) {
+            Ok(()) => crate::println!("  [KERNEL] Previous boot ended with a {}; log in {}{}",
+                record.reason, PREV_BOOT_LOG, damaged),
+            Err(e) => crate::println!("  [KERNEL] pstore: cannot write {}: {:?}", PREV_BOOT_LOG, e),
+        }
+    }
+    bytes[..HEADER_SIZE].fill(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_record_round_trip() {
+        let mut area = [0u8; 256];
+        assert_eq!(load(&area), None);
+        store(&mut area, Reason::Panic, |out| {
+            out[..5].copy_from_slice(b"oops\n");
+            5
+        });
+        assert_eq!(load(&area), Some(Record { reason: Reason::Panic, log: b"oops\n", intact: true }));
+        area[HEADER_SIZE] = b'O';
+        assert!(!load(&area).unwrap().intact);
+    }
+}
@@ -0,0 +1,358 @@
+// Package bundles
+//
+// A bundle is a ustar archive whose first two entries are MANIFEST and
+// SIGNATURE, followed by the files it installs, named relative to /usr.
+// The manifest names the package and lists every file with its mode and
+// the SHA-256 of its contents, in hex:
+//
+//   name hello
+//   version 1.0
+//   file bin/hello 755 9f86d081884c7d65...
+//
+// SIGNATURE is `KEY MAC`: the HMAC-SHA256, in hex, of MANIFEST under the
+// key in /etc/qpkg/keys/KEY, a file holding the key in hex. With every
+// digest in the signed manifest, no file of a bundle whose signature
+// checks out can be changed, added or left out. The keys are shared
+// secrets, so only root should be able to read them.
+//
+// Installing writes the files under /usr and records them in
+// /var/lib/qpkg/NAME. A file already there is only replaced if an
+// earlier version of the same package put it there; installing a newer
+// version takes away the files it no longer has. Every install and
+// removal, and every bundle refused, goes in the QSF audit log.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use crate::fs::archive::tar::{self, EntryKind, Reader};
+use crate::fs::vfs::api as vfs_api;
+use crate::fs::FsError;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::crypto;
+use crate::qsf::{AccessDecision, QSF};
+
+pub const KEY_DIR: &str = "/etc/qpkg/keys";
+pub const DB_DIR: &str = "/var/lib/qpkg";
+/// Where a bundle's files go
+pub const PREFIX: &str = "/usr";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QpkgError {
+    /// Not a bundle, or one whose manifest does not parse
+    Malformed(&'static str),
+    UnknownKey(String),
+    BadSignature,
+    /// A file missing from the bundle, not in its manifest or not
+    /// matching its digest
+    Mismatch(String),
+    /// A file that is there already and not the package's to replace
+    Conflict(String),
+    NotInstalled,
+    Fs(FsError),
+}
+
+impl From<FsError> for QpkgError {
+    fn from(e: FsError) -> Self {
+        QpkgError::Fs(e)
+    }
+}
+
+impl fmt::Display for QpkgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QpkgError::Malformed(what) => write!(f, "not a valid bundle: {}", what),
+            QpkgError::UnknownKey(key) => write!(f, "signed with unknown key {}", key),
+            QpkgError::BadSignature => write!(f, "bad signature"),
+            QpkgError::Mismatch(path) => write!(f, "{}: does not match the manifest", path),
+            QpkgError::Conflict(path) => write!(f, "{}: already exists", path),
+            QpkgError::NotInstalled => write!(f, "not installed"),
+            QpkgError::Fs(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSpec {
+    /// Relative to PREFIX
+    pub path: String,
+    pub mode: u16,
+    pub digest: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub files: Vec<FileSpec>,
+}
+
+/// A package as installed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// The key its bundle was signed with
+    pub key: String,
+    /// Absolute paths
+    pub files: Vec<String>,
+}
+
+/// A package or key name, which becomes a file name
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'+'))
+}
+
+/// A relative path with nothing in it that could lead outside PREFIX
+fn valid_path(path: &str) -> bool {
+    !path.is_empty() && path.split('/').all(|component| !matches!(component, "" | "." | ".."))
+}
+
+pub fn parse_manifest(text: &str) -> Result<Manifest, QpkgError> {
+    let (mut name, mut version, mut files) = (None, None, Vec::<FileSpec>::new());
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["name", value] if valid_name(value) => name = Some(value),
+            ["version", value] => version = Some(value),
+            ["file", path, mode, digest] => {
+                let path = path.trim_start_matches("./");
+                if !valid_path(path) {
+                    return Err(QpkgError::Malformed("bad file path"));
+                }
+                if files.iter().any(|file| file.path == path) {
+                    return Err(QpkgError::Malformed("file listed twice"));
+                }
+                let mode = u16::from_str_radix(mode, 8).ok()
+                    .filter(|&mode| mode <= 0o7777)
+                    .ok_or(QpkgError::Malformed("bad file mode"))?;
+                let digest = crypto::from_hex(digest)
+                    .and_then(|digest| <[u8; 32]>::try_from(digest).ok())
+                    .ok_or(QpkgError::Malformed("bad file digest"))?;
+                files.push(FileSpec { path: String::from(path), mode, digest });
+            }
+            _ => return Err(QpkgError::Malformed("bad manifest line")),
+        }
+    }
+    match (name, version) {
+        (Some(name), Some(version)) => Ok(Manifest { name: String::from(name), version: String::from(version), files }),
+        _ => Err(QpkgError::Malformed("manifest without a name and version")),
+    }
+}
+
+/// Check `signature` over `manifest`, getting the key it names from
+/// `key`. Returns the key's name.
+fn check_signature(manifest: &[u8], signature: &str, key: impl Fn(&str) -> Option<Vec<u8>>) -> Result<String, QpkgError> {
+    let [name, mac] = signature.split_whitespace().collect::<Vec<_>>()[..] else {
+        return Err(QpkgError::Malformed("bad signature line"));
+    };
+    if !valid_name(name) {
+        return Err(QpkgError::Malformed("bad key name"));
+    }
+    let secret = key(name).ok_or_else(|| QpkgError::UnknownKey(String::from(name)))?;
+    let mac = crypto::from_hex(mac).ok_or(QpkgError::BadSignature)?;
+    if !crypto::digest_eq(&crypto::hmac_sha256(&secret, manifest), &mac) {
+        return Err(QpkgError::BadSignature);
+    }
+    Ok(String::from(name))
+}
+
+/// The key named `name` in KEY_DIR
+fn read_key(name: &str) -> Option<Vec<u8>> {
+    let text = vfs_api::read_file(&format!("{}/{}", KEY_DIR, name)).ok()?;
+    crypto::from_hex(core::str::from_utf8(&text).ok()?.trim())
+}
+
+/// The manifest of `bundle`, the key that signed it and each file's
+/// contents, in manifest order, once all of it checks out
+pub fn verify(bundle: &[u8]) -> Result<(Manifest, String, Vec<&[u8]>), QpkgError> {
+    let mut entries = Reader::new(bundle);
+    let mut next_file = |name: &'static str| match entries.next() {
+        Some(Ok(entry)) if entry.kind == EntryKind::File && entry.path.trim_start_matches("./") == name => Ok(entry.data),
+        _ => Err(QpkgError::Malformed(name)),
+    };
+    let manifest_text = next_file("MANIFEST")?;
+    let signature = next_file("SIGNATURE")?;
+    let signature = core::str::from_utf8(signature).map_err(|_| QpkgError::Malformed("SIGNATURE"))?;
+    let key = check_signature(manifest_text, signature, read_key)?;
+    let text = core::str::from_utf8(manifest_text).map_err(|_| QpkgError::Malformed("MANIFEST"))?;
+    let manifest = parse_manifest(text)?;
+
+    let mut contents: Vec<Option<&[u8]>> = alloc::vec![None; manifest.files.len()];
+    for entry in entries {
+        let entry = entry.map_err(|_| QpkgError::Malformed("damaged archive"))?;
+        let path = entry.path.trim_start_matches("./").trim_end_matches('/');
+        match entry.kind {
+            EntryKind::Directory => continue,
+            EntryKind::File => {}
+            _ => return Err(QpkgError::Mismatch(String::from(path))),
+        }
+        let index = manifest.files.iter().position(|file| file.path == path)
+            .filter(|&index| contents[index].is_none())
+            .ok_or_else(|| QpkgError::Mismatch(String::from(path)))?;
+        if !crypto::digest_eq(&crypto::sha256(entry.data), &manifest.files[index].digest) {
+            return Err(QpkgError::Mismatch(String::from(path)));
+        }
+        contents[index] = Some(entry.data);
+    }
+    let contents = contents.into_iter().zip(&manifest.files)
+        .map(|(data, file)| data.ok_or_else(|| QpkgError::Mismatch(file.path.clone())))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((manifest, key, contents))
+}
+
+fn parse_record(name: &str, text: &str) -> Package {
+    let mut package = Package { name: String::from(name), version: String::new(), key: String::new(), files: Vec::new() };
+    for line in text.lines() {
+        if let Some(version) = line.strip_prefix("version ") {
+            package.version = String::from(version);
+        } else if let Some(key) = line.strip_prefix("key ") {
+            package.key = String::from(key);
+        } else if line.starts_with('/') {
+            package.files.push(String::from(line));
+        }
+    }
+    package
+}
+
+fn record(package: &Package) -> String {
+    let mut text = format!("version {}\nkey {}\n", package.version, package.key);
+    for file in &package.files {
+        text.push_str(file);
+        text.push('\n');
+    }
+    text
+}
+
+/// Every package installed, by name
+pub fn installed() -> Result<Vec<Package>, QpkgError> {
+    let entries = match vfs_api::readdir(DB_DIR) {
+        Ok(entries) => entries,
+        Err(FsError::NotFound) => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut packages = Vec::new();
+    for entry in entries.iter().filter(|entry| valid_name(&entry.name)) {
+        let text = vfs_api::read_file(&format!("{}/{}", DB_DIR, entry.name))?;
+        packages.push(parse_record(&entry.name, &String::from_utf8_lossy(&text)));
+    }
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(packages)
+}
+
+/// Who is asking, for the audit log
+fn caller() -> (u32, u32) {
+    SCHEDULER.lock().current().map_or((0, 0), |task| (task.pid, task.uid))
+}
+
+fn audit(action: &str, package: &str, result: &Result<Package, QpkgError>) {
+    let (pid, uid) = caller();
+    let (decision, reason) = match result {
+        Ok(package) => (AccessDecision::Audit, format!("version {}, key {}", package.version, package.key)),
+        Err(e) => (AccessDecision::Deny, format!("{}", e)),
+    };
+    QSF.lock().on_package(pid, uid, action, package, decision, &reason);
+}
+
+/// Verify `bundle` and install what is in it, returning the package as
+/// installed
+pub fn install(bundle: &[u8]) -> Result<Package, QpkgError> {
+    let result = install_verified(bundle);
+    let name = match &result {
+        Ok(package) => package.name.clone(),
+        Err(_) => String::from("(bundle)"),
+    };
+    audit("qpkg_install", &name, &result);
+    result
+}
+
+fn install_verified(bundle: &[u8]) -> Result<Package, QpkgError> {
+    let (manifest, key, contents) = verify(bundle)?;
+    let installed = installed()?;
+    let previous = installed.iter().find(|package| package.name == manifest.name);
+    let files: Vec<String> = manifest.files.iter().map(|file| format!("{}/{}", PREFIX, file.path)).collect();
+
+    for path in &files {
+        let ours = previous.is_some_and(|package| package.files.contains(path));
+        if !ours && vfs_api::stat(path).is_ok() {
+            return Err(QpkgError::Conflict(path.clone()));
+        }
+    }
+    for ((path, file), data) in files.iter().zip(&manifest.files).zip(contents) {
+        if let Some(parent) = path.rfind('/').map(|i| &path[..i]) {
+            tar::make_dirs(parent)?;
+        }
+        vfs_api::write_file(path, data, file.mode)?;
+        vfs_api::chmod(path, file.mode)?;
+    }
+    if let Some(previous) = previous {
+        for stale in previous.files.iter().filter(|path| !files.contains(path)) {
+            vfs_api::unlink(stale).ok();
+        }
+    }
+
+    let package = Package { name: manifest.name, version: manifest.version, key, files };
+    tar::make_dirs(DB_DIR)?;
+    vfs_api::write_file(&format!("{}/{}", DB_DIR, package.name), record(&package).as_bytes(), 0o644)?;
+    Ok(package)
+}
+
+/// Take away the package `name` and its files
+pub fn remove(name: &str) -> Result<Package, QpkgError> {
+    let result = remove_installed(name);
+    audit("qpkg_remove", name, &result);
+    result
+}
+
+fn remove_installed(name: &str) -> Result<Package, QpkgError> {
+    let package = installed()?.into_iter()
+        .find(|package| package.name == name)
+        .ok_or(QpkgError::NotInstalled)?;
+    for path in &package.files {
+        match vfs_api::unlink(path) {
+            Ok(()) | Err(FsError::NotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    vfs_api::unlink(&format!("{}/{}", DB_DIR, name))?;
+    Ok(package)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    #[test_case]
+    fn test_parse_manifest() {
+        let text = format!("# hello\nname hello\nversion 1.0\nfile ./bin/hello 755 {}\n", DIGEST);
+        let manifest = parse_manifest(&text).unwrap();
+        assert_eq!((manifest.name.as_str(), manifest.version.as_str()), ("hello", "1.0"));
+        assert_eq!(manifest.files[0].path, "bin/hello");
+        assert_eq!(manifest.files[0].mode, 0o755);
+        assert_eq!(manifest.files[0].digest, crypto::sha256(b"test"));
+
+        let bad = |line: &str| parse_manifest(&format!("name a\nversion 1\n{}\n", line)).unwrap_err();
+        assert_eq!(bad(&format!("file ../etc/passwd 644 {}", DIGEST)), QpkgError::Malformed("bad file path"));
+        assert_eq!(bad(&format!("file /bin/sh 644 {}", DIGEST)), QpkgError::Malformed("bad file path"));
+        assert_eq!(bad("file bin/a 644 abcd"), QpkgError::Malformed("bad file digest"));
+        assert_eq!(bad(&format!("file bin/a 99 {}", DIGEST)), QpkgError::Malformed("bad file mode"));
+        assert_eq!(bad("name ../a"), QpkgError::Malformed("bad manifest line"));
+        assert!(parse_manifest("name a\n").is_err());
+    }
+
+    #[test_case]
+    fn test_check_signature() {
+        let key = |name: &str| (name == "release").then(|| alloc::vec![7u8; 32]);
+        let manifest = b"name a\nversion 1\n";
+        let mac = crypto::to_hex(&crypto::hmac_sha256(&[7u8; 32], manifest));
+        let signature = format!("release {}\n", mac);
+        assert_eq!(check_signature(manifest, &signature, key), Ok(String::from("release")));
+        assert_eq!(check_signature(b"name a\nversion 2\n", &signature, key), Err(QpkgError::BadSignature));
+        assert_eq!(check_signature(manifest, &format!("other {}", mac), key), Err(QpkgError::UnknownKey(String::from("other"))));
+        assert_eq!(check_signature(manifest, "../keys/x 00", key), Err(QpkgError::Malformed("bad key name")));
+    }
+}
@@ -74,7 +74,7 @@ impl Context {
         ctx
     }
 
-    /// Create a user-mode context (ring 3) - for future use
+    /// Create a user-mode context (ring 3), as exec starts a program with
     pub fn new_user(entry_point: usize, stack_top: usize) -> Self {
         let mut ctx = Self::new();
         ctx.rip = entry_point as u64;
@@ -0,0 +1,106 @@
+// Load average
+//
+// Every LOAD_FREQ timer ticks the number of runnable tasks - those in the
+// ready queues and the one running - is folded into three exponentially
+// decaying averages over 1, 5 and 15 minutes, in fixed point with FSHIFT
+// fraction bits, exactly as Linux computes them. A tick the timer could
+// not account, because the scheduler lock was held, only delays the next
+// sample.
+
+use alloc::format;
+use alloc::string::String;
+
+/// Bits of fraction in a load average
+pub const FSHIFT: u32 = 11;
+pub const FIXED_1: u64 = 1 << FSHIFT;
+/// Timer ticks between samples: 5 s at 1000 Hz
+pub const LOAD_FREQ: u32 = 5 * 1000;
+
+/// 1/exp(5 s / 1 min), 1/exp(5 s / 5 min) and 1/exp(5 s / 15 min), in
+/// fixed point
+const EXP: [u64; 3] = [1884, 2014, 2037];
+
+#[derive(Debug, Clone)]
+pub struct LoadAvg {
+    averages: [u64; 3],
+    countdown: u32,
+}
+
+impl LoadAvg {
+    pub const fn new() -> Self {
+        LoadAvg { averages: [0; 3], countdown: LOAD_FREQ }
+    }
+
+    /// Count a timer tick with `active` tasks runnable
+    pub fn tick(&mut self, active: usize) {
+        self.countdown = self.countdown.saturating_sub(1);
+        if self.countdown == 0 {
+            self.countdown = LOAD_FREQ;
+            self.sample(active);
+        }
+    }
+
+    fn sample(&mut self, active: usize) {
+        let active = active as u64 * FIXED_1;
+        for (load, exp) in self.averages.iter_mut().zip(EXP) {
+            let mut next = *load * exp + active * (FIXED_1 - exp);
+            if active >= *load {
+                // Round up while rising, so a steady load is reached
+                next += FIXED_1 - 1;
+            }
+            *load = next / FIXED_1;
+        }
+    }
+
+    /// The 1, 5 and 15 minute averages, in fixed point
+    pub fn averages(&self) -> [u64; 3] {
+        self.averages
+    }
+}
+
+impl Default for LoadAvg {
+    fn default() -> Self {
+        LoadAvg::new()
+    }
+}
+
+/// A fixed point load as whole tasks and hundredths, for "1.05"
+pub fn split(load: u64) -> (u64, u64) {
+    let load = load + FIXED_1 / 200;
+    (load >> FSHIFT, ((load & (FIXED_1 - 1)) * 100) >> FSHIFT)
+}
+
+/// The three averages as `uptime` and `top` show them:
+/// "0.16, 0.03, 0.01"
+pub fn describe(averages: [u64; 3]) -> String {
+    let [one, five, fifteen] = averages.map(split);
+    format!("{}.{:02}, {}.{:02}, {}.{:02}", one.0, one.1, five.0, five.1, fifteen.0, fifteen.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_load_follows_runnable_tasks() {
+        let mut load = LoadAvg::new();
+        for _ in 0..LOAD_FREQ - 1 {
+            load.tick(2);
+        }
+        assert_eq!(load.averages(), [0; 3]);
+        load.tick(2);
+        // The first sample moves the 1 minute average 8% of the way
+        assert_eq!(split(load.averages()[0]), (0, 16));
+        assert_eq!(describe(load.averages()), "0.16, 0.03, 0.01");
+
+        // An hour of two runnable tasks settles every average on 2
+        for _ in 0..720 {
+            load.sample(2);
+        }
+        assert!(load.averages().iter().all(|&avg| split(avg) == (2, 0)));
+        for _ in 0..720 {
+            load.sample(0);
+        }
+        assert_eq!(split(load.averages()[0]), (0, 0));
+    }
+}
@@ -2,7 +2,12 @@
 pub mod task;
 pub mod context;
 pub mod scheduler;
+pub mod namespace;
+pub mod pidmap;
+pub mod loadavg;
+pub mod session;
 
 pub use task::*;
 pub use context::*;
 pub use scheduler::*;
+pub use namespace::{NsId, ROOT_PID_NS};
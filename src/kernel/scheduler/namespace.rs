@@ -0,0 +1,127 @@
+// PID namespaces
+//
+// Every task has a pid in the root namespace (`Task::pid`, the one the
+// kernel itself uses) and one more in each nested namespace between its own
+// and the root. A namespace numbers its tasks from 1, so the first task
+// created in a new one sees itself as pid 1, and tasks outside it have no
+// pid there at all.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use super::pidmap::PidMap;
+use super::task::Pid;
+
+pub type NsId = u32;
+
+/// The namespace of init and everything not in a container
+pub const ROOT_PID_NS: NsId = 0;
+
+/// How deep namespaces may nest, as on Linux
+pub const MAX_PID_NS_LEVEL: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct PidNamespace {
+    pub id: NsId,
+    pub parent: Option<NsId>,
+    pids: PidMap,
+}
+
+pub struct PidNamespaces {
+    spaces: BTreeMap<NsId, PidNamespace>,
+    next_id: NsId,
+}
+
+impl PidNamespaces {
+    pub fn new() -> Self {
+        let mut spaces = BTreeMap::new();
+        spaces.insert(ROOT_PID_NS, PidNamespace { id: ROOT_PID_NS, parent: None, pids: PidMap::new() });
+        PidNamespaces { spaces, next_id: ROOT_PID_NS + 1 }
+    }
+
+    /// Create a namespace nested in `parent`; None if `parent` does not
+    /// exist or is already nested as deep as allowed
+    pub fn create(&mut self, parent: NsId) -> Option<NsId> {
+        if !self.spaces.contains_key(&parent) || self.nested(parent).len() >= MAX_PID_NS_LEVEL {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.spaces.insert(id, PidNamespace { id, parent: Some(parent), pids: PidMap::new() });
+        Some(id)
+    }
+
+    /// `ns` and its ancestors up to but not including the root, innermost
+    /// first
+    pub fn nested(&self, ns: NsId) -> Vec<NsId> {
+        let mut chain = Vec::new();
+        let mut current = self.spaces.get(&ns);
+        while let Some(space) = current.filter(|space| space.id != ROOT_PID_NS) {
+            chain.push(space.id);
+            current = space.parent.and_then(|parent| self.spaces.get(&parent));
+        }
+        chain
+    }
+
+    /// Number a new task in `ns` and every nested namespace above it; the
+    /// root pid is allocated by the scheduler as before
+    pub fn alloc(&mut self, ns: NsId) -> Vec<(NsId, Pid)> {
+        let chain = self.nested(ns);
+        chain.into_iter().filter_map(|id| {
+            let pid = self.spaces.get_mut(&id)?.pids.alloc(|_| false)?;
+            Some((id, pid))
+        }).collect()
+    }
+
+    /// Give back the pids `alloc` numbered a task with, once it is reaped
+    pub fn free(&mut self, pids: &[(NsId, Pid)]) {
+        for &(id, pid) in pids {
+            if let Some(space) = self.spaces.get_mut(&id) {
+                space.pids.free(pid);
+            }
+        }
+    }
+
+    pub fn contains(&self, ns: NsId) -> bool {
+        self.spaces.contains_key(&ns)
+    }
+
+    /// Drop every nested namespace `in_use` rejects, keeping those that
+    /// still have a namespace below them
+    pub fn retain(&mut self, in_use: impl Fn(NsId) -> bool) {
+        loop {
+            let parents: Vec<NsId> = self.spaces.values().filter_map(|space| space.parent).collect();
+            let unused: Vec<NsId> = self.spaces.keys()
+                .copied()
+                .filter(|&id| id != ROOT_PID_NS && !in_use(id) && !parents.contains(&id))
+                .collect();
+            if unused.is_empty() {
+                return;
+            }
+            for id in unused {
+                self.spaces.remove(&id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_nested_namespaces_number_from_one() {
+        let mut spaces = PidNamespaces::new();
+        let outer = spaces.create(ROOT_PID_NS).unwrap();
+        let inner = spaces.create(outer).unwrap();
+        assert_eq!(spaces.alloc(ROOT_PID_NS), [].to_vec());
+        assert_eq!(spaces.alloc(outer), [(outer, 1)].to_vec());
+        assert_eq!(spaces.alloc(inner), [(inner, 1), (outer, 2)].to_vec());
+        spaces.free(&[(outer, 1)]);
+        assert_eq!(spaces.alloc(outer), [(outer, 3)].to_vec());
+
+        spaces.retain(|id| id == inner);
+        assert!(spaces.contains(outer));
+        spaces.retain(|_| false);
+        assert!(!spaces.contains(outer) && spaces.contains(ROOT_PID_NS));
+    }
+}
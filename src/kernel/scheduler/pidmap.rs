@@ -0,0 +1,108 @@
+// PID allocation
+//
+// PIDs come from a bitmap of the ones in use, so those of reaped tasks are
+// handed out again instead of the counter growing without bound. Like
+// Linux, allocation is next-fit: it starts after the last PID handed out
+// and wraps around at PID_MAX, so a PID just freed is not reused straight
+// away, while a full word of the bitmap is skipped at once. PID 0 is never
+// allocated.
+
+use alloc::vec::Vec;
+use super::task::Pid;
+
+/// PIDs run from 1 to PID_MAX - 1
+pub const PID_MAX: Pid = 32768;
+
+const BITS: Pid = u64::BITS;
+
+#[derive(Debug, Clone, Default)]
+pub struct PidMap {
+    /// Grown as PIDs are handed out, so an unused namespace costs nothing
+    words: Vec<u64>,
+    last: Pid,
+    used: usize,
+}
+
+impl PidMap {
+    pub const fn new() -> Self {
+        PidMap { words: Vec::new(), last: 0, used: 0 }
+    }
+
+    pub fn contains(&self, pid: Pid) -> bool {
+        self.words.get((pid / BITS) as usize).is_some_and(|word| word & 1 << (pid % BITS) != 0)
+    }
+
+    /// The PID handed out most recently
+    pub fn last(&self) -> Pid {
+        self.last
+    }
+
+    /// PIDs in use
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// The next free PID `skip` does not reject, or None if there is none
+    pub fn alloc(&mut self, mut skip: impl FnMut(Pid) -> bool) -> Option<Pid> {
+        let mut offset = 1;
+        while offset < PID_MAX {
+            let pid = (self.last + offset - 1) % (PID_MAX - 1) + 1;
+            if self.words.get((pid / BITS) as usize) == Some(&u64::MAX) {
+                // Nothing free in this word: go on from the next
+                offset += BITS - pid % BITS;
+                continue;
+            }
+            if !self.contains(pid) && !skip(pid) {
+                self.reserve(pid);
+                self.last = pid;
+                return Some(pid);
+            }
+            offset += 1;
+        }
+        None
+    }
+
+    /// Mark `pid` in use, for a task numbered elsewhere
+    pub fn reserve(&mut self, pid: Pid) {
+        if pid == 0 || pid >= PID_MAX || self.contains(pid) {
+            return;
+        }
+        let index = (pid / BITS) as usize;
+        if self.words.len() <= index {
+            self.words.resize(index + 1, 0);
+        }
+        self.words[index] |= 1 << (pid % BITS);
+        self.used += 1;
+    }
+
+    pub fn free(&mut self, pid: Pid) {
+        if self.contains(pid) {
+            self.words[(pid / BITS) as usize] &= !(1 << (pid % BITS));
+            self.used -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_pids_are_recycled_after_wrapping() {
+        let mut pids = PidMap::new();
+        assert_eq!(pids.alloc(|_| false), Some(1));
+        assert_eq!(pids.alloc(|_| false), Some(2));
+        pids.free(1);
+        // Next-fit: the freed PID waits until the map wraps
+        assert_eq!(pids.alloc(|_| false), Some(3));
+        assert_eq!(pids.alloc(|pid| pid == 4), Some(5));
+
+        for pid in 6..PID_MAX {
+            pids.reserve(pid);
+        }
+        assert_eq!(pids.alloc(|_| false), Some(1));
+        assert_eq!(pids.alloc(|_| false), Some(4));
+        assert_eq!(pids.alloc(|_| false), None);
+        assert_eq!(pids.used(), PID_MAX as usize - 1);
+    }
+}
@@ -1,23 +1,47 @@
 extern crate alloc;
 
+use alloc::string::String;
 use alloc::vec::Vec;
-use alloc::collections::VecDeque;
-use spin::Mutex;
+use alloc::collections::{btree_map, BTreeMap};
+use crate::hal::lockdep::{Class, Mutex};
 use lazy_static::lazy_static;
 
-use super::task::{Task, TaskState, TaskPriority, Pid};
+use super::task::{Task, TaskState, TaskPriority, Pid, RunLink, SIGCHLD};
+use super::namespace::{NsId, PidNamespaces, ROOT_PID_NS};
+use super::pidmap::PidMap;
+use super::loadavg::LoadAvg;
+use super::session::ControllingTty;
+use crate::kernel::canary::Guarded;
+use crate::qsf::QSF;
+use crate::fs::FileType;
+use crate::fs::vfs::node::InodeNumber;
+
+/// init, which adopts orphans nobody nearer will
+pub const INIT_PID: Pid = 1;
 
 lazy_static! {
-    pub static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
+    pub static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Class::Scheduler, Scheduler::new());
+}
+
+/// One priority's ready queue: the ends of a list linked through each
+/// queued task's `run_link`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RunQueue {
+    pub head: Option<Pid>,
+    pub tail: Option<Pid>,
+    pub len: usize,
 }
 
 pub struct Scheduler {
-    pub tasks: Vec<Task>,
-    pub ready_queue: [VecDeque<Pid>; 5],
+    pub tasks: BTreeMap<Pid, Task>,
+    ready_queue: Guarded<[RunQueue; 5]>,
     pub current_pid: Option<Pid>,
-    pub next_pid: Pid,
+    pub pids: PidMap,
+    pub pid_namespaces: PidNamespaces,
+    pub terminals: Vec<ControllingTty>,
     pub idle_pid: Option<Pid>,
     pub ticks: u64,
+    pub load: LoadAvg,
     pub time_slice: u64,
     pub preemption_enabled: bool,
 }
@@ -25,46 +49,136 @@ pub struct Scheduler {
 impl Scheduler {
     pub fn new() -> Self {
         Scheduler {
-            tasks: Vec::new(),
-            ready_queue: [
-                VecDeque::new(),
-                VecDeque::new(),
-                VecDeque::new(),
-                VecDeque::new(),
-                VecDeque::new(),
-            ],
+            tasks: BTreeMap::new(),
+            ready_queue: Guarded::new([RunQueue::default(); 5]),
             current_pid: None,
-            next_pid: 1,
+            pids: PidMap::new(),
+            pid_namespaces: PidNamespaces::new(),
+            terminals: Vec::new(),
             idle_pid: None,
             ticks: 0,
+            load: LoadAvg::new(),
             time_slice: 10,
             preemption_enabled: true,
         }
     }
 
+    /// Add `task`, listing it among its parent's children, and queue it
+    /// to run
     pub fn add_task(&mut self, mut task: Task) {
         let pid = task.pid;
-        let priority = task.priority as usize;
         task.init_fds();
-        self.tasks.push(task);
-        self.ready_queue[priority].push_back(pid);
-        if self.next_pid <= pid {
-            self.next_pid = pid + 1;
+        self.pids.reserve(pid);
+        if let Some(parent) = task.ppid.and_then(|ppid| self.tasks.get_mut(&ppid)) {
+            parent.add_child(pid);
+        }
+        self.tasks.insert(pid, task);
+        self.enqueue(pid);
+    }
+
+    /// A free pid for a new task, passing over any that still names a
+    /// process group or session so the task does not join it by accident;
+    /// None once every pid is taken
+    pub fn allocate_pid(&mut self) -> Option<Pid> {
+        let tasks = &self.tasks;
+        self.pids.alloc(|pid| tasks.values().any(|task| task.pgid == pid || task.sid == pid))
+    }
+
+    /// Put `pid` at the back of the ready queue for its priority, unless
+    /// it is queued already
+    fn enqueue(&mut self, pid: Pid) {
+        let queue = match self.tasks.get(&pid) {
+            Some(task) if task.run_link.queue.is_none() => task.priority as usize,
+            _ => return,
+        };
+        let tail = self.ready_queue[queue].tail;
+        if let Some(last) = tail.and_then(|tail| self.tasks.get_mut(&tail)) {
+            last.run_link.next = Some(pid);
+        }
+        if let Some(task) = self.tasks.get_mut(&pid) {
+            task.run_link = RunLink { queue: Some(queue), prev: tail, next: None };
+        }
+        let ready = &mut self.ready_queue[queue];
+        ready.head = ready.head.or(Some(pid));
+        ready.tail = Some(pid);
+        ready.len += 1;
+    }
+
+    /// Take `pid` out of whichever ready queue holds it
+    pub fn unqueue(&mut self, pid: Pid) {
+        let link = match self.tasks.get_mut(&pid) {
+            Some(task) => core::mem::take(&mut task.run_link),
+            None => return,
+        };
+        let queue = match link.queue {
+            Some(queue) => queue,
+            None => return,
+        };
+        match link.prev.and_then(|prev| self.tasks.get_mut(&prev)) {
+            Some(prev) => prev.run_link.next = link.next,
+            None => self.ready_queue[queue].head = link.next,
+        }
+        match link.next.and_then(|next| self.tasks.get_mut(&next)) {
+            Some(next) => next.run_link.prev = link.prev,
+            None => self.ready_queue[queue].tail = link.prev,
+        }
+        self.ready_queue[queue].len -= 1;
+    }
+
+    /// Give a task just forked its pids in namespace `task.pid_ns` and
+    /// the nested ones above it
+    pub fn number_in_namespace(&mut self, task: &mut Task) {
+        task.ns_pids = self.pid_namespaces.alloc(task.pid_ns);
+    }
+
+    /// The namespace the current task lives in
+    pub fn current_pid_ns(&self) -> NsId {
+        self.current().map_or(ROOT_PID_NS, |task| task.pid_ns)
+    }
+
+    /// The global pid of the task numbered `pid` in namespace `ns`
+    pub fn pid_from_ns(&self, ns: NsId, pid: Pid) -> Option<Pid> {
+        if ns == ROOT_PID_NS {
+            return self.tasks.contains_key(&pid).then_some(pid);
+        }
+        self.tasks.values().find(|t| t.pid_in(ns) == Some(pid)).map(|t| t.pid)
+    }
+
+    /// The number of the task `pid` in namespace `ns`, if visible there
+    pub fn pid_in_ns(&self, ns: NsId, pid: Pid) -> Option<Pid> {
+        self.get_task(pid).and_then(|task| task.pid_in(ns))
+    }
+
+    /// Forget namespaces, PID and mount, that no task uses any more
+    pub fn release_namespaces(&mut self) {
+        let tasks = &self.tasks;
+        self.pid_namespaces.retain(|ns| tasks.values().any(|t| t.pid_ns == ns || t.child_pid_ns == Some(ns)));
+        for ns in crate::fs::mount::namespaces() {
+            if !self.tasks.values().any(|t| t.mnt_ns == ns) {
+                crate::fs::mount::drop_namespace(ns);
+            }
         }
     }
 
-    pub fn allocate_pid(&mut self) -> Pid {
-        let pid = self.next_pid;
-        self.next_pid += 1;
-        pid
+    /// Make `pid` the current task straight away, outside the ready
+    /// queues, returning the one it replaces. For running work inline in
+    /// another task's context.
+    pub fn make_current(&mut self, pid: Option<Pid>) -> Option<Pid> {
+        let previous = core::mem::replace(&mut self.current_pid, pid);
+        if let Some(task) = pid.and_then(|pid| self.get_task(pid)) {
+            crate::fs::mount::set_namespace(task.mnt_ns);
+            crate::fs::vfs::set_credentials(task.euid, task.egid);
+            crate::hal::memory::heap_debug::set_owner(task.pid);
+        }
+        previous
     }
 
     pub fn get_task(&self, pid: Pid) -> Option<&Task> {
-        self.tasks.iter().find(|t| t.pid == pid)
+        self.tasks.get(&pid)
     }
 
     pub fn get_task_mut(&mut self, pid: Pid) -> Option<&mut Task> {
-        self.tasks.iter_mut().find(|t| t.pid == pid)
+        self.tasks.get_mut(&pid)
     }
 
     pub fn current(&self) -> Option<&Task> {
@@ -72,30 +186,44 @@ impl Scheduler {
     }
 
     pub fn current_mut(&mut self) -> Option<&mut Task> {
-        if let Some(pid) = self.current_pid {
-            self.tasks.iter_mut().find(|t| t.pid == pid)
-        } else {
-            None
-        }
+        self.current_pid.and_then(|pid| self.tasks.get_mut(&pid))
     }
 
     pub fn current_pid(&self) -> Option<Pid> {
         self.current_pid
     }
 
-    pub fn get_tasks(&self) -> &[Task] {
-        &self.tasks
+    /// Every task, ordered by pid
+    pub fn get_tasks(&self) -> btree_map::Values<'_, Pid, Task> {
+        self.tasks.values()
     }
 
     fn select_next(&mut self) -> Option<Pid> {
         for priority in (0..5).rev() {
-            if let Some(pid) = self.ready_queue[priority].pop_front() {
+            if let Some(pid) = self.ready_queue[priority].head {
+                self.unqueue(pid);
                 return Some(pid);
             }
         }
         self.idle_pid
     }
 
+    /// Account a timer tick: charge it to the running task and count the
+    /// runnable tasks towards the load average
+    pub fn tick(&mut self) {
+        if let Some(task) = self.current_mut().filter(|task| task.state == TaskState::Running) {
+            task.cpu_time += 1;
+        }
+        let active = self.running_count();
+        self.load.tick(active);
+    }
+
+    /// Runnable tasks: those queued and the one running
+    pub fn running_count(&self) -> usize {
+        let running = self.current().filter(|task| task.state == TaskState::Running && Some(task.pid) != self.idle_pid);
+        self.ready_count() + running.is_some() as usize
+    }
+
     pub fn schedule(&mut self) {
         if !self.preemption_enabled {
             return;
@@ -107,29 +235,49 @@ impl Scheduler {
             return;
         }
 
+        let mut preempted = false;
         if let Some(current_pid) = self.current_pid {
             if let Some(task) = self.get_task_mut(current_pid) {
                 if task.state == TaskState::Running {
                     task.state = TaskState::Ready;
-                    let priority = task.priority as usize;
-                    self.ready_queue[priority].push_back(current_pid);
+                    preempted = true;
+                    self.enqueue(current_pid);
                 }
             }
         }
 
         if let Some(next_pid) = self.select_next() {
             if Some(next_pid) != self.current_pid {
-                self.switch_to(next_pid);
+                self.switch_to(next_pid, preempted);
             }
         }
     }
 
-    fn switch_to(&mut self, next_pid: Pid) {
-        let _old_pid = self.current_pid;
+    /// Switch to `next_pid`, counting the switch against the task leaving
+    /// as involuntary if it was `preempted` while still runnable
+    fn switch_to(&mut self, next_pid: Pid, preempted: bool) {
+        let old_pid = self.current_pid;
+        if let Some(old) = old_pid.and_then(|pid| self.tasks.get_mut(&pid)) {
+            if preempted {
+                old.nivcsw += 1;
+            } else {
+                old.nvcsw += 1;
+            }
+        }
+        crate::tracepoint!(SchedSwitch, old_pid.unwrap_or(0), next_pid);
+        self.ready_queue.verify(format_args!("scheduler ready queues"));
+        if let Some(old) = old_pid.and_then(|pid| self.get_task(pid)) {
+            old.verify_canaries();
+        }
+        if let Some(next) = self.get_task(next_pid) {
+            next.verify_canaries();
+        }
         self.current_pid = Some(next_pid);
+        crate::hal::memory::heap_debug::set_owner(next_pid);
         if let Some(task) = self.get_task_mut(next_pid) {
+            crate::fs::mount::set_namespace(task.mnt_ns);
             task.state = TaskState::Running;
-            task.cpu_time += 1;
+            task.timeslices += 1;
         }
     }
 
@@ -147,13 +295,21 @@ impl Scheduler {
         if let Some(task) = self.get_task_mut(pid) {
             if task.state == TaskState::Blocked {
                 task.state = TaskState::Ready;
-                let priority = task.priority as usize;
-                self.ready_queue[priority].push_back(pid);
+                self.enqueue(pid);
             }
         }
     }
 
+    /// Let the parent that vforked `pid` run again, now that `pid` has
+    /// exec'd or exited and no longer runs on its stack
+    pub fn release_vfork(&mut self, pid: Pid) {
+        if let Some(parent) = self.get_task_mut(pid).and_then(|task| task.vfork_parent.take()) {
+            self.unblock(parent);
+        }
+    }
+
     pub fn sleep(&mut self, _ticks: u64) {
+        crate::hal::cpu::context::assert_may_sleep("Scheduler::sleep");
         if let Some(task) = self.current_mut() {
             task.state = TaskState::Sleeping;
         }
@@ -162,14 +318,88 @@ impl Scheduler {
     }
 
     pub fn exit(&mut self, code: i32) {
+        if let Some(pid) = self.current_pid {
+            self.job_control_exit(pid);
+            self.reparent_children(pid);
+        }
         if let Some(task) = self.current_mut() {
             task.exit(code);
         }
+        if let Some(pid) = self.current_pid {
+            self.release_vfork(pid);
+        }
+        self.reap_init_children();
 
         self.current_pid = None;
         self.schedule();
     }
 
+    /// Who adopts the children of `pid`: the nearest living ancestor
+    /// that is a child subreaper, looking no further than the init of
+    /// `pid`'s PID namespace, else that init, else init itself
+    pub fn reaper_for(&self, pid: Pid) -> Pid {
+        let task = match self.get_task(pid) {
+            Some(task) => task,
+            None => return INIT_PID,
+        };
+        let ns = task.pid_ns;
+        let mut ancestor = task.ppid.and_then(|ppid| self.get_task(ppid));
+        while let Some(candidate) = ancestor {
+            if candidate.pid_in(ns).map_or(true, |local| local == 1) {
+                break;
+            }
+            if candidate.child_subreaper && candidate.state != TaskState::Zombie {
+                return candidate.pid;
+            }
+            ancestor = candidate.ppid.and_then(|ppid| self.get_task(ppid));
+        }
+        self.pid_from_ns(ns, 1).filter(|&init| init != pid).unwrap_or(INIT_PID)
+    }
+
+    /// Hand the children of `pid`, which is exiting, to their reaper. A
+    /// reaper adopting children that are already dead is sent SIGCHLD so
+    /// it waits for them.
+    pub fn reparent_children(&mut self, pid: Pid) {
+        let children = match self.get_task_mut(pid) {
+            Some(task) => core::mem::take(&mut task.children),
+            None => return,
+        };
+        if children.is_empty() {
+            return;
+        }
+        let reaper = self.reaper_for(pid);
+        let mut dead = false;
+        for &child in &children {
+            if let Some(task) = self.get_task_mut(child) {
+                task.ppid = Some(reaper);
+                task.orphaned = reaper == INIT_PID;
+                dead |= task.state == TaskState::Zombie;
+            }
+        }
+        if let Some(task) = self.get_task_mut(reaper) {
+            task.children.extend(children);
+            if dead {
+                task.send_signal(SIGCHLD);
+            }
+        }
+        self.reap_init_children();
+    }
+
+    /// Reap the orphans init adopted once they are dead: init runs the
+    /// shell, which only waits for children of its own. The current task
+    /// is left until it has switched away, as it may still be on its way
+    /// out.
+    pub fn reap_init_children(&mut self) {
+        let dead: Vec<Pid> = self.tasks.values()
+            .filter(|task| task.orphaned && task.state == TaskState::Zombie)
+            .filter(|task| Some(task.pid) != self.current_pid)
+            .map(|task| task.pid)
+            .collect();
+        for pid in dead {
+            self.remove_zombie(pid);
+        }
+    }
+
     pub fn kill(&mut self, pid: Pid, signal: u8) -> bool {
         if let Some(task) = self.get_task_mut(pid) {
             task.send_signal(signal);
@@ -179,18 +409,39 @@ impl Scheduler {
         }
     }
 
+    /// Remove `pid` if it is a zombie, dropping it from its parent's
+    /// children and freeing its pids, and return its exit code. What QSF
+    /// kept for the pid goes first, so a task given it again starts out
+    /// unconfined and untraced.
     pub fn remove_zombie(&mut self, pid: Pid) -> Option<i32> {
-        if let Some(pos) = self.tasks.iter().position(|t| t.pid == pid && t.state == TaskState::Zombie) {
-            let task = self.tasks.remove(pos);
-            task.exit_code
-        } else {
-            None
+        if self.get_task(pid)?.state != TaskState::Zombie {
+            return None;
+        }
+        self.unqueue(pid);
+        let task = self.tasks.remove(&pid)?;
+        if let Some(parent) = task.ppid.and_then(|ppid| self.tasks.get_mut(&ppid)) {
+            parent.remove_child(pid);
         }
+        QSF.lock().on_exit(pid);
+        self.pids.free(pid);
+        self.pid_namespaces.free(&task.ns_pids);
+        self.release_namespaces();
+        task.exit_code
     }
 
+    /// Change the priority of `pid`, moving it to the matching ready
+    /// queue if it is waiting in one
     pub fn set_priority(&mut self, pid: Pid, priority: TaskPriority) {
-        if let Some(task) = self.get_task_mut(pid) {
-            task.priority = priority;
+        let queued = match self.get_task_mut(pid) {
+            Some(task) => {
+                task.priority = priority;
+                task.run_link.queue.is_some()
+            }
+            None => return,
+        };
+        if queued {
+            self.unqueue(pid);
+            self.enqueue(pid);
         }
     }
 
@@ -207,10 +458,46 @@ impl Scheduler {
     }
 
     pub fn ready_count(&self) -> usize {
-        self.ready_queue.iter().map(|q| q.len()).sum()
+        self.ready_queue.iter().map(|q| q.len).sum()
+    }
+
+    /// Every open descriptor of `pid`, or of all tasks, ordered by pid
+    /// then fd
+    pub fn open_fds(&self, pid: Option<Pid>) -> Vec<FdInfo> {
+        let mut fds: Vec<FdInfo> = self.tasks.values()
+            .filter(|task| pid.map_or(true, |pid| task.pid == pid))
+            .flat_map(|task| task.fds.values().map(move |entry| FdInfo {
+                pid: task.pid,
+                command: task.name.clone(),
+                fd: entry.fd,
+                path: String::from(entry.file.path()),
+                inode: entry.file.inode(),
+                file_type: None,
+                offset: entry.file.offset(),
+                flags: entry.flags(),
+            }))
+            .collect();
+        fds.sort_by_key(|info| (info.pid, info.fd));
+        fds
     }
 }
 
+/// One open descriptor, as reported by lsof and /proc/<pid>/fd
+#[derive(Debug, Clone)]
+pub struct FdInfo {
+    pub pid: Pid,
+    pub command: String,
+    pub fd: i32,
+    /// Where it was opened, which it may have left since
+    pub path: String,
+    /// The node open; None for a pipe or a console stream
+    pub inode: Option<InodeNumber>,
+    /// None for a pipe
+    pub file_type: Option<FileType>,
+    pub offset: u64,
+    pub flags: u32,
+}
+
 pub fn init() {
     crate::println!("[SCHED] Scheduler initialized");
 }
@@ -220,7 +507,17 @@ pub fn schedule() {
     scheduler.schedule();
 }
 
+/// Account a timer tick from the interrupt handler. The interrupted code
+/// may hold the scheduler, so the tick is skipped rather than spun on.
+pub fn preempt() {
+    if let Some(mut scheduler) = SCHEDULER.try_lock() {
+        scheduler.tick();
+        scheduler.schedule();
+    }
+}
+
 pub fn yield_now() {
+    crate::hal::cpu::context::assert_may_sleep("yield_now");
     let mut scheduler = SCHEDULER.lock();
     scheduler.schedule();
 }
@@ -231,7 +528,13 @@ pub fn current_pid() -> Option<Pid> {
 
 pub fn spawn(name: alloc::string::String, entry: usize) -> Pid {
     let mut scheduler = SCHEDULER.lock();
-    let pid = scheduler.allocate_pid();
+    let pid = match scheduler.allocate_pid() {
+        Some(pid) => pid,
+        None => {
+            crate::println!("[SCHED] ERROR: spawn failed: out of pids");
+            return 0;
+        }
+    };
     match Task::new(pid, name, entry, false) {
         Ok(task) => {
             scheduler.add_task(task);
@@ -269,4 +572,116 @@ pub fn kill(pid: Pid, signal: u8) -> bool {
 
 pub fn get_task(pid: Pid) -> Option<Task> {
     SCHEDULER.lock().get_task(pid).cloned()
-}
\ No newline at end of file
+}
+
+/// Snapshot the open descriptors of `pid` (or every task) with their file
+/// types filled in. Takes SCHEDULER, then VFS, so neither may be held.
+pub fn open_fds(pid: Option<Pid>) -> Vec<FdInfo> {
+    let mut fds = SCHEDULER.lock().open_fds(pid);
+    let vfs = crate::fs::vfs::VFS.lock();
+    for info in fds.iter_mut() {
+        let node = match info.inode {
+            Some(inode) => vfs.get_node(inode).ok(),
+            None => vfs.lookup_resolved(&info.path).ok(),
+        };
+        info.file_type = node.map(|node| node.file_type());
+    }
+    fds
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_reparenting() {
+        let mut scheduler = Scheduler::new();
+        let init = Task::new(INIT_PID, String::from("init"), 0, true).unwrap();
+        let mut manager = init.fork(2).unwrap();
+        let service = manager.fork(3).unwrap();
+        let worker = service.fork(4).unwrap();
+        let mut dead = service.fork(5).unwrap();
+        dead.exit(0);
+        manager.child_subreaper = true;
+        for task in [init, manager, service, worker, dead] {
+            scheduler.add_task(task);
+        }
+
+        // The service's children go to the manager, not init, and the one
+        // already dead is waiting to be reaped
+        assert_eq!(scheduler.reaper_for(3), 2);
+        scheduler.reparent_children(3);
+        assert_eq!(scheduler.get_task(4).unwrap().ppid, Some(2));
+        assert_eq!(scheduler.get_task(2).unwrap().children, [3, 4, 5]);
+        assert!(scheduler.get_task(2).unwrap().has_pending_signal(SIGCHLD));
+
+        // With no subreaper left they go to init, which reaps the dead
+        scheduler.get_task_mut(2).unwrap().child_subreaper = false;
+        assert_eq!(scheduler.reaper_for(3), INIT_PID);
+        scheduler.reparent_children(2);
+        assert!(scheduler.get_task(5).is_none());
+        assert!(scheduler.get_task(4).unwrap().orphaned);
+        assert_eq!(scheduler.get_task(INIT_PID).unwrap().children, [2, 3, 4]);
+        assert!(!scheduler.pids.contains(5));
+    }
+
+    #[test_case]
+    fn test_run_queues_are_linked_through_tasks() {
+        let mut scheduler = Scheduler::new();
+        for pid in 1..=4 {
+            scheduler.add_task(Task::new(pid, String::from("task"), 0, false).unwrap());
+        }
+        scheduler.set_priority(3, TaskPriority::High);
+        scheduler.unqueue(2);
+        assert_eq!(scheduler.ready_count(), 3);
+        assert_eq!(scheduler.get_task(2).unwrap().run_link, RunLink::default());
+
+        // Higher priority first, then the order they were queued in
+        let order: Vec<Option<Pid>> = (0..4).map(|_| scheduler.select_next()).collect();
+        assert_eq!(order, [Some(3), Some(1), Some(4), None]);
+        assert_eq!(scheduler.allocate_pid(), Some(5));
+    }
+
+    #[test_case]
+    fn test_switches_are_counted() {
+        let mut scheduler = Scheduler::new();
+        scheduler.time_slice = 1;
+        for pid in [1, 2] {
+            scheduler.add_task(Task::new(pid, String::from("task"), 0, false).unwrap());
+        }
+        scheduler.unqueue(1);
+        scheduler.current_pid = Some(1);
+        scheduler.get_task_mut(1).unwrap().state = TaskState::Running;
+
+        scheduler.tick();
+        scheduler.schedule();
+        assert_eq!(scheduler.current_pid(), Some(2));
+        scheduler.block_current();
+        assert_eq!(scheduler.current_pid(), Some(1));
+
+        let (first, second) = (scheduler.get_task(1).unwrap(), scheduler.get_task(2).unwrap());
+        assert_eq!((first.cpu_time, first.nvcsw, first.nivcsw), (1, 0, 1));
+        assert_eq!((second.nvcsw, second.nivcsw, second.timeslices), (1, 0, 1));
+    }
+
+    #[test_case]
+    fn test_reused_pid_starts_unconfined() {
+        let mut scheduler = Scheduler::new();
+        let init = Task::new(INIT_PID, String::from("init"), 0, true).unwrap();
+        let mut child = init.fork(2).unwrap();
+        scheduler.add_task(init);
+        child.exit(0);
+        scheduler.add_task(child);
+        {
+            let mut qsf = QSF.lock();
+            qsf.confine_to_profile(2, "sandbox").unwrap();
+            qsf.trace_process(2);
+        }
+
+        assert_eq!(scheduler.remove_zombie(2), Some(0));
+        assert_eq!(scheduler.allocate_pid(), Some(2));
+        let qsf = QSF.lock();
+        assert!(!qsf.confinement().is_confined(2));
+        assert!(!qsf.is_traced(2));
+        assert!(!crate::qsf::modules::trace::tracing_active());
+    }
+}
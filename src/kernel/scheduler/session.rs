@@ -0,0 +1,331 @@
+// Process groups and sessions
+//
+// A task belongs to a process group, and each group to a session; both
+// are named by the pid of the task that created them, their leader, and
+// outlive it. Children start in their parent's group and session.
+// setpgid() moves a task between groups of one session, following POSIX:
+// only the caller or a child that has not exec'd yet can be moved, never
+// a session leader, and only into a group that already exists in the
+// session or a new one named after the task. setsid() starts a session,
+// with no controlling terminal, for a task that leads no group.
+//
+// The first terminal a session leader opens without O_NOCTTY, and that no
+// other session has, becomes the session's controlling terminal, with the
+// leader's group in the foreground. Reading it from a background group
+// sends that group SIGTTIN, and fails with EIO if the signal would do
+// nothing or the group is orphaned; tcsetpgrp() from the background sends
+// SIGTTOU likewise. When the leader exits the terminal hangs up: its
+// foreground group gets SIGHUP and SIGCONT and the session loses it. A
+// group is orphaned when no member has a parent in another group of the
+// same session, and one that becomes so through an exit gets SIGHUP and
+// SIGCONT if any member is stopped, as nobody is left to continue it.
+//
+// Ids given and returned here are global pids; the syscalls translate
+// them for callers in a PID namespace.
+
+use alloc::vec::Vec;
+use super::namespace::{NsId, ROOT_PID_NS};
+use super::scheduler::Scheduler;
+use super::task::{Pid, TaskState, SIGCONT, SIGHUP, SIGTTIN, SIGTTOU};
+use crate::fs::vfs::node::DeviceId;
+use crate::kernel::sys::errno::{Errno, SysResult};
+
+/// A terminal controlling a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ControllingTty {
+    pub device: DeviceId,
+    pub sid: Pid,
+    /// The process group reads and job control signals go to
+    pub foreground: Pid,
+}
+
+impl Scheduler {
+    /// Group or session `id` as numbered in namespace `ns`. The root
+    /// namespace numbers everything globally, so a group there keeps its
+    /// name after its leader is gone; elsewhere the leader has to be alive.
+    pub fn id_from_ns(&self, ns: NsId, id: Pid) -> Option<Pid> {
+        if ns == ROOT_PID_NS {
+            return Some(id);
+        }
+        self.pid_from_ns(ns, id)
+    }
+
+    /// The number of group or session `id` in namespace `ns`
+    pub fn id_in_ns(&self, ns: NsId, id: Pid) -> Option<Pid> {
+        if ns == ROOT_PID_NS {
+            return Some(id);
+        }
+        self.pid_in_ns(ns, id)
+    }
+
+    pub fn getpgid(&self, pid: Pid) -> SysResult<Pid> {
+        self.get_task(pid).map(|task| task.pgid).ok_or(Errno::ESRCH)
+    }
+
+    pub fn getsid(&self, pid: Pid) -> SysResult<Pid> {
+        self.get_task(pid).map(|task| task.sid).ok_or(Errno::ESRCH)
+    }
+
+    /// Whether `pid` leads a session
+    pub fn is_session_leader(&self, pid: Pid) -> bool {
+        self.get_task(pid).is_some_and(|task| task.sid == pid)
+    }
+
+    /// Put `pid` in process group `pgid`, on behalf of `caller`
+    pub fn setpgid(&mut self, caller: Pid, pid: Pid, pgid: Pid) -> SysResult<()> {
+        let caller_sid = self.getsid(caller)?;
+        let task = self.get_task(pid).ok_or(Errno::ESRCH)?;
+        if pid != caller {
+            if task.ppid != Some(caller) {
+                return Err(Errno::ESRCH);
+            }
+            if task.did_exec {
+                return Err(Errno::EACCES);
+            }
+        }
+        if task.sid == pid || task.sid != caller_sid {
+            return Err(Errno::EPERM);
+        }
+        let group_exists = self.tasks.values().any(|other| other.pgid == pgid && other.sid == caller_sid);
+        if pgid != pid && !group_exists {
+            return Err(Errno::EPERM);
+        }
+        if let Some(task) = self.get_task_mut(pid) {
+            task.pgid = pgid;
+        }
+        Ok(())
+    }
+
+    /// Make `pid` the leader of a new session and of a new group in it,
+    /// returning the session
+    pub fn setsid(&mut self, pid: Pid) -> SysResult<Pid> {
+        if self.tasks.values().any(|task| task.pgid == pid) {
+            return Err(Errno::EPERM);
+        }
+        let task = self.get_task_mut(pid).ok_or(Errno::ESRCH)?;
+        task.sid = pid;
+        task.pgid = pid;
+        Ok(pid)
+    }
+
+    /// The terminal controlling session `sid`
+    pub fn controlling_tty(&self, sid: Pid) -> Option<ControllingTty> {
+        self.terminals.iter().find(|tty| tty.sid == sid).copied()
+    }
+
+    /// The terminal that `pid` has as its controlling terminal
+    fn tty_of(&self, pid: Pid) -> Option<ControllingTty> {
+        self.get_task(pid).and_then(|task| self.controlling_tty(task.sid))
+    }
+
+    /// Make `device` the controlling terminal of `pid`'s session if `pid`
+    /// leads it, the session has none and no other session has `device`
+    pub fn acquire_tty(&mut self, pid: Pid, device: DeviceId) -> bool {
+        let task = match self.get_task(pid) {
+            Some(task) if task.sid == pid => task,
+            _ => return false,
+        };
+        if self.terminals.iter().any(|tty| tty.sid == pid || tty.device == device) {
+            return false;
+        }
+        let foreground = task.pgid;
+        self.terminals.push(ControllingTty { device, sid: pid, foreground });
+        true
+    }
+
+    /// The foreground group of `device`, which has to be `pid`'s
+    /// controlling terminal
+    pub fn tcgetpgrp(&self, pid: Pid, device: DeviceId) -> SysResult<Pid> {
+        match self.tty_of(pid) {
+            Some(tty) if tty.device == device => Ok(tty.foreground),
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+
+    /// Put group `pgid` of `pid`'s session in the foreground of `device`,
+    /// its controlling terminal
+    pub fn tcsetpgrp(&mut self, pid: Pid, device: DeviceId, pgid: Pid) -> SysResult<()> {
+        let tty = match self.tty_of(pid) {
+            Some(tty) if tty.device == device => tty,
+            _ => return Err(Errno::ENOTTY),
+        };
+        if !self.tasks.values().any(|task| task.pgid == pgid && task.sid == tty.sid && task.state != TaskState::Zombie) {
+            return Err(Errno::EPERM);
+        }
+        self.background_check(pid, tty, SIGTTOU, Ok(()))?;
+        if let Some(tty) = self.terminals.iter_mut().find(|t| t.device == device) {
+            tty.foreground = pgid;
+        }
+        Ok(())
+    }
+
+    /// Whether `pid` may read `device`. A background group reading its
+    /// controlling terminal is sent SIGTTIN instead.
+    pub fn check_tty_read(&mut self, pid: Pid, device: DeviceId) -> SysResult<()> {
+        match self.tty_of(pid) {
+            Some(tty) if tty.device == device => self.background_check(pid, tty, SIGTTIN, Err(Errno::EIO)),
+            _ => Ok(()),
+        }
+    }
+
+    /// What a member of a background group doing something only the
+    /// foreground may gets: `ignored` if it would not notice `signal`, EIO
+    /// if its group is orphaned and EINTR once the group has been sent
+    /// `signal`
+    fn background_check(&mut self, pid: Pid, tty: ControllingTty, signal: u8, ignored: SysResult<()>) -> SysResult<()> {
+        let task = self.get_task(pid).ok_or(Errno::ESRCH)?;
+        let pgid = task.pgid;
+        if pgid == tty.foreground {
+            return Ok(());
+        }
+        if task.ignores_signal(signal) {
+            return ignored;
+        }
+        if self.is_orphaned(pgid, None) {
+            return Err(Errno::EIO);
+        }
+        self.signal_group(pgid, signal);
+        Err(Errno::EINTR)
+    }
+
+    /// Send `signal` to the foreground group of `device`, for the
+    /// terminal's interrupt, quit and suspend characters
+    pub fn signal_foreground(&mut self, device: DeviceId, signal: u8) {
+        if let Some(tty) = self.terminals.iter().find(|tty| tty.device == device).copied() {
+            self.signal_group(tty.foreground, signal);
+        }
+    }
+
+    /// Hang up `device`: its foreground group gets SIGHUP and SIGCONT and
+    /// the session it controlled no longer has a terminal
+    pub fn hangup(&mut self, device: DeviceId) {
+        if let Some(at) = self.terminals.iter().position(|tty| tty.device == device) {
+            let tty = self.terminals.remove(at);
+            self.signal_group(tty.foreground, SIGHUP);
+            self.signal_group(tty.foreground, SIGCONT);
+        }
+    }
+
+    /// Send `signal` to every live member of group `pgid`
+    pub fn signal_group(&mut self, pgid: Pid, signal: u8) {
+        for task in self.tasks.values_mut().filter(|task| task.pgid == pgid && task.state != TaskState::Zombie) {
+            task.send_signal(signal);
+        }
+    }
+
+    /// Whether group `pgid` is orphaned, not counting task `leaving`: no
+    /// live member has a live parent in another group of its session
+    pub fn is_orphaned(&self, pgid: Pid, leaving: Option<Pid>) -> bool {
+        let alive = |pid: Pid| Some(pid) != leaving
+            && self.get_task(pid).is_some_and(|task| task.state != TaskState::Zombie);
+        !self.tasks.values().filter(|task| task.pgid == pgid && alive(task.pid)).any(|task| {
+            task.ppid.filter(|&ppid| alive(ppid)).and_then(|ppid| self.get_task(ppid))
+                .is_some_and(|parent| parent.pgid != pgid && parent.sid == task.sid)
+        })
+    }
+
+    /// Job control for `pid` as it exits: a session leader hangs up its
+    /// terminal, and groups its leaving orphans - its own, or its
+    /// children's - get SIGHUP and SIGCONT if they have stopped members
+    pub fn job_control_exit(&mut self, pid: Pid) {
+        let (sid, pgid, children) = match self.get_task(pid) {
+            Some(task) => (task.sid, task.pgid, task.children.clone()),
+            None => return,
+        };
+        if sid == pid {
+            if let Some(tty) = self.controlling_tty(sid) {
+                self.hangup(tty.device);
+            }
+        }
+
+        let mut groups: Vec<Pid> = children.iter().filter_map(|&child| self.get_task(child)).map(|task| task.pgid).collect();
+        groups.push(pgid);
+        groups.sort_unstable();
+        groups.dedup();
+        for group in groups {
+            let stopped = self.tasks.values().any(|task| task.pgid == group && task.state == TaskState::Stopped);
+            if stopped && !self.is_orphaned(group, None) && self.is_orphaned(group, Some(pid)) {
+                self.signal_group(group, SIGHUP);
+                self.signal_group(group, SIGCONT);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use super::super::task::Task;
+    use super::*;
+
+    #[test_case]
+    fn test_job_control_rules() {
+        let mut scheduler = Scheduler::new();
+        let shell = Task::new(10, String::from("sh"), 0, true).unwrap();
+        let job = shell.fork(11).unwrap();
+        let other = shell.fork(12).unwrap();
+        scheduler.add_task(shell);
+        scheduler.add_task(job);
+        scheduler.add_task(other);
+
+        // The shell leads its session, so it cannot move or start another
+        assert_eq!(scheduler.setpgid(10, 10, 10), Err(Errno::EPERM));
+        assert_eq!(scheduler.setsid(10), Err(Errno::EPERM));
+
+        // A child gets a group of its own, which a sibling can then join;
+        // groups that do not exist cannot be joined
+        assert_eq!(scheduler.setpgid(10, 11, 11), Ok(()));
+        assert_eq!(scheduler.setpgid(10, 12, 11), Ok(()));
+        assert_eq!(scheduler.setpgid(10, 12, 99), Err(Errno::EPERM));
+        assert_eq!(scheduler.getpgid(12), Ok(11));
+
+        // Only the caller's own children, before they exec
+        assert_eq!(scheduler.setpgid(11, 12, 12), Err(Errno::ESRCH));
+        scheduler.get_task_mut(12).unwrap().did_exec = true;
+        assert_eq!(scheduler.setpgid(10, 12, 12), Err(Errno::EACCES));
+
+        // A group leader cannot start a session, but a member can, and
+        // after that it is out of reach of the old session's groups
+        assert_eq!(scheduler.setsid(11), Err(Errno::EPERM));
+        assert_eq!(scheduler.setsid(12), Ok(12));
+        assert_eq!((scheduler.getsid(12), scheduler.getpgid(12)), (Ok(12), Ok(12)));
+        assert_eq!(scheduler.setpgid(12, 12, 11), Err(Errno::EPERM));
+    }
+
+    #[test_case]
+    fn test_controlling_terminal() {
+        let tty = DeviceId::new(4, 64);
+        let mut scheduler = Scheduler::new();
+        let shell = Task::new(20, String::from("sh"), 0, true).unwrap();
+        let job = shell.fork(21).unwrap();
+        let stopped = job.fork(22).unwrap();
+        scheduler.add_task(shell);
+        scheduler.add_task(job);
+        scheduler.add_task(stopped);
+        scheduler.setpgid(20, 21, 21).unwrap();
+        scheduler.setpgid(21, 22, 21).unwrap();
+
+        // Only a session leader gets a controlling terminal, and only one
+        assert!(!scheduler.acquire_tty(21, tty));
+        assert!(scheduler.acquire_tty(20, tty));
+        assert!(!scheduler.acquire_tty(20, DeviceId::new(4, 65)));
+        assert_eq!(scheduler.tcgetpgrp(21, tty), Ok(20));
+
+        // The job reading in the background is stopped by SIGTTIN
+        assert_eq!(scheduler.check_tty_read(21, tty), Err(Errno::EINTR));
+        assert!(scheduler.get_task(22).unwrap().has_pending_signal(SIGTTIN));
+        assert_eq!(scheduler.tcsetpgrp(20, tty, 21), Ok(()));
+        assert_eq!(scheduler.check_tty_read(21, tty), Ok(()));
+
+        // With the shell gone the job's group is orphaned; having a stopped
+        // member, it is told to hang up and continue
+        scheduler.get_task_mut(22).unwrap().set_state(TaskState::Stopped);
+        scheduler.get_task_mut(22).unwrap().clear_signal(SIGTTIN);
+        scheduler.job_control_exit(20);
+        scheduler.get_task_mut(20).unwrap().exit(0);
+        assert!(scheduler.get_task(22).unwrap().has_pending_signal(SIGHUP));
+        assert!(scheduler.get_task(22).unwrap().has_pending_signal(SIGCONT));
+        assert_eq!(scheduler.controlling_tty(20), None);
+        assert!(scheduler.is_orphaned(21, None));
+    }
+}
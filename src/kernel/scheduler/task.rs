@@ -2,13 +2,26 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use super::context::Context;
+use super::namespace::{NsId, ROOT_PID_NS};
+use crate::fs::mount::{MntNsId, ROOT_MNT_NS};
+use crate::kernel::file::OpenFile;
+use crate::kernel::canary::{self, Guarded};
+use crate::hal::memory::address_space::AddressSpace;
 
 pub type Pid = u32;
 pub type Tid = u32;
 
 const KERNEL_STACK_SIZE: usize = 16 * 1024;
 
+/// How many descriptors a task may have open (RLIMIT_NOFILE) until it
+/// sets another limit, the hard limit it may raise that to, and the most
+/// root may raise either to
+pub const NOFILE_DEFAULT: u32 = 1024;
+pub const NOFILE_MAX_DEFAULT: u32 = 4096;
+pub const NR_OPEN: u32 = 1 << 20;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TaskState {
     Ready,
@@ -34,12 +47,92 @@ impl Default for TaskPriority {
     }
 }
 
+/// An owned kernel stack. Cloning (fork) allocates a fresh stack with a copy
+/// of the contents, so no two tasks ever free the same memory.
+#[derive(Debug)]
+pub struct KernelStack {
+    base: usize,
+}
+
+impl KernelStack {
+    pub fn new() -> Self {
+        let boxed = Box::new([0u8; KERNEL_STACK_SIZE]);
+        let base = Box::into_raw(boxed) as usize;
+        unsafe { canary::arm_stack(base) };
+        KernelStack { base }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn top(&self) -> usize {
+        self.base + KERNEL_STACK_SIZE
+    }
+
+    pub fn size(&self) -> usize {
+        KERNEL_STACK_SIZE
+    }
+
+    pub fn verify(&self, pid: Pid) {
+        unsafe { canary::verify_stack(self.base, format_args!("kernel stack of pid {}", pid)) };
+    }
+}
+
+impl Clone for KernelStack {
+    fn clone(&self) -> Self {
+        let copy = KernelStack::new();
+        unsafe {
+            core::ptr::copy_nonoverlapping(self.base as *const u8, copy.base as *mut u8, KERNEL_STACK_SIZE);
+        }
+        copy
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(self.base as *mut [u8; KERNEL_STACK_SIZE]));
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FileDescriptor {
     pub fd: i32,
-    pub path: String,
-    pub offset: u64,
-    pub flags: u32, // O_CLOEXEC, etc.
+    /// The open file, shared with the descriptors dup'd or inherited from
+    /// this one, and with it the offset
+    pub file: Arc<OpenFile>,
+    /// Closed on exec; the one flag that is the descriptor's own
+    pub cloexec: bool,
+}
+
+impl FileDescriptor {
+    pub fn new(fd: i32, file: Arc<OpenFile>, flags: u32) -> Self {
+        let cloexec = flags & crate::fs::vfs::api::OpenFlags::O_CLOEXEC.bits() != 0;
+        FileDescriptor { fd, file, cloexec }
+    }
+
+    /// Another descriptor `fd` on the same open file, as dup makes, which
+    /// is not closed on exec
+    pub fn dup(&self, fd: i32) -> Self {
+        FileDescriptor { fd, file: self.file.clone(), cloexec: false }
+    }
+
+    /// The open file's flags with O_CLOEXEC if set, as open(2) takes them
+    pub fn flags(&self) -> u32 {
+        let cloexec = if self.cloexec { crate::fs::vfs::api::OpenFlags::O_CLOEXEC.bits() } else { 0 };
+        self.file.flags() | cloexec
+    }
+}
+
+/// A task's place in a ready queue. The queues are linked through the
+/// tasks themselves, so queueing one never allocates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunLink {
+    pub queue: Option<usize>,
+    pub prev: Option<Pid>,
+    pub next: Option<Pid>,
 }
 
 /// POSIX-like Process Control Block
@@ -51,20 +144,36 @@ pub struct Task {
     pub pgid: Pid,                  // Process group ID (for job control)
     pub sid: Pid,                   // Session ID
     
+    // Namespaces
+    pub pid_ns: NsId,               // PID namespace the task lives in
+    pub ns_pids: Vec<(NsId, Pid)>,  // Its pid in each nested namespace, innermost first
+    pub child_pid_ns: Option<NsId>, // Where children go, after unshare(CLONE_NEWPID)
+    pub mnt_ns: MntNsId,            // Mount namespace
+    pub root: String,               // Root directory (chroot), from the real root
+    
     // Process info
     pub name: String,
+    pub cmdline: Vec<String>,       // Arguments it was exec'd with; none for kernel tasks
     pub state: TaskState,
     pub priority: TaskPriority,
+    pub run_link: RunLink,          // Place in a ready queue, if queued
     pub exit_code: Option<i32>,     // POSIX: set when exiting
     pub children: Vec<Pid>,         // POSIX: track child PIDs
+    pub vfork_parent: Option<Pid>,  // Suspended until this task execs or exits
+    pub did_exec: bool,             // Has exec'd, so its parent can no longer move its group
+    pub child_subreaper: bool,      // Adopts orphaned descendants instead of init
+    pub allow_wx: bool,             // Exempt from W^X (prctl PR_SET_WX_ALLOWED), across fork and exec
+    pub orphaned: bool,             // Adopted by init, which reaps it as it exits
     
     // Execution context
     pub context: Context,
-    pub kernel_stack: usize,
-    pub kernel_stack_size: usize,
+    pub kernel_stack: Option<KernelStack>,
     pub user_stack: usize,
     pub entry_point: usize,
     pub is_kernel_task: bool,
+    pub address_space: Option<Arc<AddressSpace>>, // Set by exec, for ring 3
+    pub brk_start: u64,             // Program break as exec set it; 0 with no address space
+    pub brk: u64,                   // Program break now
     
     // Credentials (POSIX)
     pub uid: u32,                   // Real UID
@@ -75,8 +184,9 @@ pub struct Task {
     
     // File descriptor table
     pub cwd: String,                // Current working directory
-    pub fds: BTreeMap<i32, FileDescriptor>,
-    pub next_fd: i32,
+    pub fds: Guarded<BTreeMap<i32, FileDescriptor>>,
+    pub nofile: u32,                // RLIMIT_NOFILE: descriptors are below it
+    pub nofile_max: u32,            // Its hard limit, which only root raises
     
     // Signals (POSIX)
     pub signal_mask: u64,           // Blocked signals
@@ -85,6 +195,9 @@ pub struct Task {
     
     // Timing
     pub cpu_time: u64,              // CPU ticks consumed
+    pub timeslices: u64,            // Times switched to
+    pub nvcsw: u64,                 // Switched out after blocking, sleeping or exiting
+    pub nivcsw: u64,                // Switched out while still runnable
     pub start_time: u64,            // Boot time when created
     pub last_schedule: u64,         // Last scheduled time
 }
@@ -93,18 +206,11 @@ impl Task {
     /// Create a new task (POSIX-compatible PCB)
     pub fn new(pid: Pid, name: String, entry_point: usize, is_kernel: bool) -> Result<Self, &'static str> {
         // Allocate kernel stack for kernel tasks
-        let (kernel_stack_ptr, kernel_stack_top) = if is_kernel {
-            let boxed = Box::new([0u8; KERNEL_STACK_SIZE]);
-            let ptr = Box::into_raw(boxed) as usize;
-            let top = ptr + KERNEL_STACK_SIZE;
-            (ptr, top)
-        } else {
-            (0, 0)
-        };
+        let kernel_stack = if is_kernel { Some(KernelStack::new()) } else { None };
 
         // Build execution context
-        let context = if is_kernel {
-            Context::new_kernel(entry_point, kernel_stack_top)
+        let context = if let Some(stack) = &kernel_stack {
+            Context::new_kernel(entry_point, stack.top())
         } else {
             Context::new_user(entry_point, 0)
         };
@@ -116,20 +222,36 @@ impl Task {
             pgid: pid,                  // Process is own group initially
             sid: pid,                   // Process is own session initially
             
+            // Namespaces
+            pid_ns: ROOT_PID_NS,
+            ns_pids: Vec::new(),
+            child_pid_ns: None,
+            mnt_ns: ROOT_MNT_NS,
+            root: String::from("/"),
+            
             // Process info
             name,
+            cmdline: Vec::new(),
             state: TaskState::Ready,
             priority: TaskPriority::Normal,
+            run_link: RunLink::default(),
             exit_code: None,            // Not exited yet
             children: Vec::new(),       // No children yet
+            vfork_parent: None,
+            did_exec: false,
+            child_subreaper: false,
+            allow_wx: false,
+            orphaned: false,
             
             // Execution context
             context,
-            kernel_stack: kernel_stack_ptr,
-            kernel_stack_size: if is_kernel { KERNEL_STACK_SIZE } else { 0 },
+            kernel_stack,
             user_stack: 0,
             entry_point,
             is_kernel_task: is_kernel,
+            address_space: None,
+            brk_start: 0,
+            brk: 0,
             
             // Credentials (POSIX)
             uid: if is_kernel { 0 } else { 1000 },
@@ -140,8 +262,9 @@ impl Task {
             
             // File descriptors
             cwd: String::from("/"),
-            fds: BTreeMap::new(),
-            next_fd: 3,                 // 0=stdin, 1=stdout, 2=stderr
+            fds: Guarded::new(BTreeMap::new()),
+            nofile: NOFILE_DEFAULT,
+            nofile_max: NOFILE_MAX_DEFAULT,
             
             // Signals
             signal_mask: 0,
@@ -150,31 +273,29 @@ impl Task {
             
             // Timing
             cpu_time: 0,
+            timeslices: 0,
+            nvcsw: 0,
+            nivcsw: 0,
             start_time: crate::hal::drivers::pit::get_ticks(),
             last_schedule: 0,
         })
     }
 
-    /// Initialize standard file descriptors (stdin, stdout, stderr)
+    /// Verify the canaries around this task's kernel stack and fd table,
+    /// panicking with the task's identity if any was overwritten.
+    pub fn verify_canaries(&self) {
+        if let Some(stack) = &self.kernel_stack {
+            stack.verify(self.pid);
+        }
+        self.fds.verify(format_args!("fd table of pid {}", self.pid));
+    }
+
+    /// Initialize the standard file descriptors (stdin, stdout, stderr)
+    /// the task did not inherit
     pub fn init_fds(&mut self) {
-        self.fds.insert(0, FileDescriptor {
-            fd: 0,
-            path: String::from("/dev/stdin"),
-            offset: 0,
-            flags: 0,
-        });
-        self.fds.insert(1, FileDescriptor {
-            fd: 1,
-            path: String::from("/dev/stdout"),
-            offset: 0,
-            flags: 1,
-        });
-        self.fds.insert(2, FileDescriptor {
-            fd: 2,
-            path: String::from("/dev/stderr"),
-            offset: 0,
-            flags: 1,
-        });
+        for (fd, path, flags) in [(0, "/dev/stdin", 0), (1, "/dev/stdout", 1), (2, "/dev/stderr", 1)] {
+            self.fds.entry(fd).or_insert_with(|| FileDescriptor::new(fd, OpenFile::console(path, flags), flags));
+        }
     }
 
     /// POSIX fork: duplicate this task as a child
@@ -182,25 +303,103 @@ impl Task {
         let mut child = self.clone();
         child.pid = child_pid;
         child.ppid = Some(self.pid);           // Set parent PID
-        child.pgid = child_pid;                 // New process group
         child.children.clear();                 // Child has no children
+        child.run_link = RunLink::default();    // Not queued yet
+        child.vfork_parent = None;
+        child.did_exec = false;
+        child.address_space = None;             // User memory is not copied yet
+        (child.brk_start, child.brk) = (0, 0);
+        child.child_subreaper = false;
+        child.orphaned = false;
+        child.pid_ns = self.child_pid_ns.unwrap_or(self.pid_ns);
+        child.child_pid_ns = None;
         child.exit_code = None;                 // Not exited
         child.cpu_time = 0;
+        (child.timeslices, child.nvcsw, child.nivcsw) = (0, 0, 0);
         child.start_time = crate::hal::drivers::pit::get_ticks();
         Ok(child)
     }
 
-    /// POSIX exit: mark as zombie with exit code
+    /// vfork: a child that runs on this task's kernel stack while this
+    /// task stays blocked, so only the descriptor table is copied
+    pub fn vfork(&mut self, child_pid: Pid) -> Result<Task, &'static str> {
+        let stack = self.kernel_stack.take();
+        let child = self.fork(child_pid);
+        self.kernel_stack = stack;
+        let mut child = child?;
+        child.vfork_parent = Some(self.pid);
+        Ok(child)
+    }
+
+    /// posix_spawn: a fresh child running the program `name`. It keeps
+    /// what exec would - credentials, root and working directory,
+    /// namespaces, process group, session, signal mask and the descriptors
+    /// not marked close-on-exec - and nothing else is copied, not even the
+    /// kernel stack fork duplicates. No arguments are passed, so its command
+    /// line is the name alone.
+    pub fn spawn(&self, child_pid: Pid, name: String) -> Result<Task, &'static str> {
+        let mut child = Task::new(child_pid, name.clone(), 0, false)?;
+        child.cmdline = alloc::vec![name];
+        child.ppid = Some(self.pid);
+        child.pgid = self.pgid;
+        child.sid = self.sid;
+        child.did_exec = true;
+        child.pid_ns = self.child_pid_ns.unwrap_or(self.pid_ns);
+        child.mnt_ns = self.mnt_ns;
+        child.root = self.root.clone();
+        child.cwd = self.cwd.clone();
+        child.priority = self.priority;
+        (child.uid, child.gid, child.euid, child.egid) = (self.uid, self.gid, self.euid, self.egid);
+        child.umask = self.umask;
+        child.allow_wx = self.allow_wx;
+        child.signal_mask = self.signal_mask;
+        for (&fd, entry) in self.fds.iter().filter(|(_, entry)| !entry.cloexec) {
+            child.fds.insert(fd, entry.clone());
+        }
+        (child.nofile, child.nofile_max) = (self.nofile, self.nofile_max);
+        Ok(child)
+    }
+
+    /// This task's pid as seen from namespace `ns`, if it is visible there
+    pub fn pid_in(&self, ns: NsId) -> Option<Pid> {
+        if ns == ROOT_PID_NS {
+            return Some(self.pid);
+        }
+        self.ns_pids.iter().find(|&&(id, _)| id == ns).map(|&(_, pid)| pid)
+    }
+
+    /// This task's pid as it sees it
+    pub fn local_pid(&self) -> Pid {
+        self.pid_in(self.pid_ns).unwrap_or(self.pid)
+    }
+
+    /// POSIX exit: mark as zombie with exit code and unmap what it mapped
     pub fn exit(&mut self, code: i32) {
         self.exit_code = Some(code);
         self.state = TaskState::Zombie;
+        self.address_space = None;
+        // Closing them tells the other end of any pipe
+        self.fds.clear();
+        crate::hal::memory::vma::release(self.pid);
+    }
+
+    /// The lowest descriptor not in use, as POSIX has open, dup and pipe
+    /// return. None, for EMFILE, when every one below the NOFILE limit is.
+    pub fn allocate_fd(&self) -> Option<i32> {
+        let mut fd = 0;
+        for &used in self.fds.keys() {
+            if used != fd {
+                break;
+            }
+            fd += 1;
+        }
+        self.fd_in_range(fd).then_some(fd)
     }
 
-    /// Allocate a new file descriptor
-    pub fn allocate_fd(&mut self) -> i32 {
-        let fd = self.next_fd;
-        self.next_fd += 1;
-        fd
+    /// Whether `fd` is a descriptor the task may have, below its NOFILE
+    /// limit; dup2 onto one that is not fails with EBADF
+    pub fn fd_in_range(&self, fd: i32) -> bool {
+        fd >= 0 && (fd as u32) < self.nofile
     }
 
     /// Close a file descriptor
@@ -273,6 +472,12 @@ impl Task {
         }
     }
 
+    /// Whether `signal` is blocked or ignored, so sending it has no effect
+    pub fn ignores_signal(&self, signal: u8) -> bool {
+        signal < 64 && (self.signal_mask & (1 << signal) != 0
+            || self.signal_handlers[signal as usize] == crate::kernel::sys::posix::signals::SIG_IGN as u64)
+    }
+
     // ========== Process hierarchy (POSIX) ==========
 
     /// Add a child PID to this process
@@ -299,19 +504,93 @@ pub const SIGINT: u8 = 2;
 pub const SIGQUIT: u8 = 3;
 pub const SIGABRT: u8 = 6;
 pub const SIGKILL: u8 = 9;      // Cannot be caught/blocked
+pub const SIGPIPE: u8 = 13;     // Write to a pipe with no readers
 pub const SIGTERM: u8 = 15;
 pub const SIGCHLD: u8 = 17;     // Child process exited
+pub const SIGCONT: u8 = 18;     // Continue if stopped
 pub const SIGSTOP: u8 = 19;     // Cannot be caught/blocked
 pub const SIGTSTP: u8 = 20;     // Terminal stop signal
+pub const SIGTTIN: u8 = 21;     // Background read from the terminal
+pub const SIGTTOU: u8 = 22;     // Background terminal control
 
 impl Drop for Task {
     fn drop(&mut self) {
-        // Deallocate kernel stack if it was allocated
-        if self.is_kernel_task && self.kernel_stack != 0 {
-            unsafe {
-                let boxed = Box::from_raw(self.kernel_stack as *mut [u8; KERNEL_STACK_SIZE]);
-                drop(boxed);
-            }
-        }
+        // Catch corruption before the stack is freed and the evidence lost;
+        // the stack itself is released by `KernelStack`.
+        self.verify_canaries();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::vfs::api::OpenFlags;
+
+    #[test_case]
+    fn test_vfork_and_spawn() {
+        let mut parent = Task::new(100, String::from("sh"), 0, true).unwrap();
+        parent.init_fds();
+        parent.euid = 0;
+        let (reader, _writer) = crate::kernel::pipe::pipe(16);
+        let pipe = OpenFile::pipe(reader, 0);
+        parent.fds.insert(5, FileDescriptor::new(5, pipe.clone(), OpenFlags::O_CLOEXEC.bits()));
+        let dup = parent.fds[&5].dup(6);
+        parent.fds.insert(6, dup);
+
+        // The vforked child runs on the parent's stack, which stays put
+        let child = parent.vfork(101).unwrap();
+        assert!(child.kernel_stack.is_none() && parent.kernel_stack.is_some());
+        assert_eq!(child.vfork_parent, Some(100));
+
+        // A spawned child keeps the credentials and open descriptors, but
+        // not those marked close-on-exec; a dup is not, and shares the file
+        let child = parent.spawn(102, String::from("/bin/true")).unwrap();
+        assert_eq!((child.ppid, child.euid, child.sid), (Some(100), 0, parent.sid));
+        assert!(child.fds.contains_key(&1) && !child.fds.contains_key(&5));
+        assert!(Arc::ptr_eq(&child.fds[&6].file, &pipe) && child.fds[&6].flags() == 0);
+        assert!(child.kernel_stack.is_none() && !child.is_kernel_task);
+    }
+
+    #[test_case]
+    fn test_fds_reuse_the_lowest_free() {
+        let mut task = Task::new(110, String::from("prog"), 0, false).unwrap();
+        task.init_fds();
+        let (reader, _writer) = crate::kernel::pipe::pipe(16);
+        let pipe = OpenFile::pipe(reader, 0);
+        let open = |task: &mut Task| {
+            let fd = task.allocate_fd()?;
+            task.fds.insert(fd, FileDescriptor::new(fd, pipe.clone(), 0));
+            Some(fd)
+        };
+        let dup = |task: &mut Task, old: i32| {
+            let fd = task.allocate_fd()?;
+            let entry = task.fds[&old].dup(fd);
+            task.fds.insert(fd, entry);
+            Some(fd)
+        };
+
+        assert_eq!([open(&mut task), open(&mut task), dup(&mut task, 3)], [Some(3), Some(4), Some(5)]);
+        // Closed descriptors are reused lowest first, below a dup2'd one
+        task.close_fd(4);
+        task.close_fd(0);
+        let high = task.fds[&3].dup(9);
+        task.fds.insert(9, high);
+        assert_eq!([dup(&mut task, 9), open(&mut task), open(&mut task)], [Some(0), Some(4), Some(6)]);
+
+        // Nothing at or past the NOFILE limit
+        task.nofile = 8;
+        assert_eq!(open(&mut task), Some(7));
+        assert_eq!(open(&mut task), None);
+        assert!(task.fd_in_range(7) && !task.fd_in_range(8) && !task.fd_in_range(-1));
+        task.close_fd(2);
+        assert_eq!(open(&mut task), Some(2));
+        // Lowered under what is open, which stays open
+        task.nofile = 3;
+        assert!(task.fds.contains_key(&9) && task.allocate_fd().is_none());
+        task.close_fd(1);
+        assert_eq!(task.allocate_fd(), Some(1));
+
+        let child = task.spawn(111, String::from("/bin/true")).unwrap();
+        assert_eq!((child.nofile, child.nofile_max), (3, NOFILE_MAX_DEFAULT));
     }
 }
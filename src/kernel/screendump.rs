@@ -0,0 +1,77 @@
+// Console screenshots
+//
+// Captures the VGA text screen as plain text, one line per row with the
+// trailing blanks trimmed. The `screendump` command writes it to a file,
+// and the panic handler saves one to /tmp/screen.txt and puts a copy in
+// the crash dump. The console is text-only, so there are no pixels to
+// capture yet.
+
+use alloc::string::String;
+use core::fmt;
+use crate::fs::{FsError, FsResult};
+use crate::hal::drivers::cp437;
+use crate::hal::drivers::vga::{BUFFER_HEIGHT, BUFFER_WIDTH, WRITER};
+
+pub const DEFAULT_PATH: &str = "/tmp/screen.txt";
+
+/// The character bytes of every cell, row by row
+pub type Screen = [[u8; BUFFER_WIDTH]; BUFFER_HEIGHT];
+
+/// Copy the screen, or None if the console is being written
+pub fn capture() -> Option<Screen> {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let writer = WRITER.try_lock()?;
+        let mut screen = [[0u8; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        for (row, cells) in screen.iter_mut().enumerate() {
+            *cells = writer.read_row(row);
+        }
+        Some(screen)
+    })
+}
+
+/// Write `screen` as text, leaving out the blank rows at the bottom
+pub fn write_text(screen: &Screen, out: &mut dyn fmt::Write) -> fmt::Result {
+    let blank = |row: &[u8; BUFFER_WIDTH]| row.iter().all(|&b| b == b' ' || b == 0);
+    let used = screen.iter().rposition(|row| !blank(row)).map_or(0, |last| last + 1);
+    for row in &screen[..used] {
+        let end = row.iter().rposition(|&b| b != b' ' && b != 0).map_or(0, |last| last + 1);
+        for &byte in &row[..end] {
+            out.write_char(cp437::to_char(byte))?;
+        }
+        out.write_char('\n')?;
+    }
+    Ok(())
+}
+
+/// Save the screen to `path`, returning its size in bytes
+pub fn save(path: &str) -> FsResult<usize> {
+    let screen = capture().ok_or(FsError::Busy)?;
+    let mut text = String::new();
+    write_text(&screen, &mut text).ok();
+    crate::fs::vfs::api::write_file(path, text.as_bytes(), 0o644)?;
+    Ok(text.len())
+}
+
+/// Save the screen to DEFAULT_PATH from the panic handler, unless the
+/// heap or the VFS is locked by the code that panicked
+pub fn save_on_panic() {
+    if crate::hal::memory::heap::try_heap_stats().is_none() || crate::fs::vfs::VFS.try_lock().is_none() {
+        return;
+    }
+    save(DEFAULT_PATH).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_text_is_trimmed() {
+        let mut screen = [[b' '; BUFFER_WIDTH]; BUFFER_HEIGHT];
+        screen[0][..3].copy_from_slice(b"abc");
+        screen[2][1] = 0xfe;
+        let mut text = String::new();
+        write_text(&screen, &mut text).unwrap();
+        assert_eq!(text, "abc\n\n \u{25a0}\n");
+    }
+}
@@ -0,0 +1,225 @@
+// Kernel ABI: the syscall table and feature probing
+//
+// Syscalls arrive faster than programs can assume them, so the kernel says
+// what it has. SYSCALLS lists every number it knows, with the ABI version
+// it appeared in and whether it does anything yet: the stubs have a
+// number and a name reserved but return ENOSYS, explicitly, from
+// do_syscall. SUBSYSTEMS lists what a kernel may be built with or without.
+// A program asks with qunix_features(2), which fills in a QunixFeatures
+// and returns ABI_VERSION; a shell script reads /proc/sys/kernel/features.
+// Entries are only ever added, and subsystem bits only at the end, so a
+// program built against an older table reads a newer kernel correctly.
+
+use super::syscalls::*;
+
+/// Bumped whenever syscalls or subsystems are added
+pub const ABI_VERSION: u32 = 6;
+
+/// Syscall numbers QunixFeatures has a bit for
+pub const SYSCALL_BITS: usize = 512;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Syscall {
+    pub num: u64,
+    pub name: &'static str,
+    /// The ABI version it was added in
+    pub since: u32,
+    /// False for stubs, which return ENOSYS
+    pub implemented: bool,
+}
+
+const fn call(num: u64, name: &'static str, since: u32) -> Syscall {
+    Syscall { num, name, since, implemented: true }
+}
+
+const fn stub(num: u64, name: &'static str, since: u32) -> Syscall {
+    Syscall { num, name, since, implemented: false }
+}
+
+/// Every syscall the kernel knows, by number
+pub const SYSCALLS: &[Syscall] = &[
+    call(SYS_READ, "read", 1),
+    call(SYS_WRITE, "write", 1),
+    call(SYS_OPEN, "open", 1),
+    call(SYS_CLOSE, "close", 1),
+    call(SYS_STAT, "stat", 1),
+    call(SYS_FSTAT, "fstat", 1),
+    stub(SYS_LSTAT, "lstat", 1),
+    stub(SYS_POLL, "poll", 1),
+    call(SYS_LSEEK, "lseek", 1),
+    call(SYS_MMAP, "mmap", 1),
+    call(SYS_MPROTECT, "mprotect", 1),
+    call(SYS_MUNMAP, "munmap", 1),
+    call(SYS_BRK, "brk", 1),
+    stub(SYS_SIGACTION, "rt_sigaction", 1),
+    stub(SYS_SIGPROCMASK, "rt_sigprocmask", 1),
+    stub(SYS_SIGRETURN, "rt_sigreturn", 1),
+    call(SYS_IOCTL, "ioctl", 1),
+    call(SYS_ACCESS, "access", 1),
+    call(SYS_PIPE, "pipe", 1),
+    call(SYS_DUP, "dup", 1),
+    call(SYS_DUP2, "dup2", 1),
+    call(SYS_GETPID, "getpid", 1),
+    call(SYS_FORK, "fork", 1),
+    call(SYS_VFORK, "vfork", 1),
+    call(SYS_EXECVE, "execve", 1),
+    call(SYS_EXIT, "exit", 1),
+    call(SYS_WAIT4, "wait4", 1),
+    call(SYS_KILL, "kill", 1),
+    stub(SYS_UNAME, "uname", 1),
+    stub(SYS_FCNTL, "fcntl", 1),
+    stub(SYS_FLOCK, "flock", 1),
+    stub(SYS_FSYNC, "fsync", 1),
+    call(SYS_GETCWD, "getcwd", 1),
+    call(SYS_CHDIR, "chdir", 1),
+    call(SYS_FCHDIR, "fchdir", 1),
+    stub(SYS_RENAME, "rename", 1),
+    call(SYS_MKDIR, "mkdir", 1),
+    call(SYS_RMDIR, "rmdir", 1),
+    stub(SYS_CREAT, "creat", 1),
+    stub(SYS_LINK, "link", 1),
+    call(SYS_UNLINK, "unlink", 1),
+    stub(SYS_SYMLINK, "symlink", 1),
+    call(SYS_READLINK, "readlink", 1),
+    call(SYS_CHMOD, "chmod", 1),
+    call(SYS_FCHMOD, "fchmod", 1),
+    call(SYS_CHOWN, "chown", 1),
+    call(SYS_FCHOWN, "fchown", 1),
+    call(SYS_UMASK, "umask", 1),
+    call(SYS_GETRLIMIT, "getrlimit", 3),
+    call(SYS_GETUID, "getuid", 1),
+    call(SYS_GETGID, "getgid", 1),
+    stub(SYS_SETUID, "setuid", 1),
+    stub(SYS_SETGID, "setgid", 1),
+    call(SYS_GETEUID, "geteuid", 1),
+    call(SYS_GETEGID, "getegid", 1),
+    call(SYS_SETPGID, "setpgid", 1),
+    call(SYS_GETPPID, "getppid", 1),
+    call(SYS_GETPGRP, "getpgrp", 1),
+    call(SYS_SETSID, "setsid", 1),
+    stub(SYS_GETGROUPS, "getgroups", 1),
+    stub(SYS_SETGROUPS, "setgroups", 1),
+    call(SYS_GETPGID, "getpgid", 1),
+    call(SYS_GETSID, "getsid", 1),
+    call(SYS_PRCTL, "prctl", 1),
+    call(SYS_SETRLIMIT, "setrlimit", 3),
+    call(SYS_CHROOT, "chroot", 1),
+    call(SYS_MOUNT, "mount", 1),
+    call(SYS_UMOUNT2, "umount2", 1),
+    call(SYS_FACCESSAT, "faccessat", 1),
+    call(SYS_UNSHARE, "unshare", 1),
+    call(SYS_FALLOCATE, "fallocate", 1),
+    call(SYS_POSIX_SPAWN, "posix_spawn", 1),
+    call(SYS_QUNIX_FEATURES, "qunix_features", 1),
+];
+
+/// Subsystems by bit in QunixFeatures::subsystems: what the kernel was
+/// built with, and what every kernel since the ABI it came in has
+pub const SUBSYSTEMS: &[(&str, bool)] = &[
+    ("usb", cfg!(feature = "usb")),
+    ("ahci", cfg!(feature = "ahci")),
+    ("nvme", cfg!(feature = "nvme")),
+    ("sdhci", cfg!(feature = "sdhci")),
+    ("net", cfg!(feature = "net")),
+    ("framebuffer", cfg!(feature = "framebuffer")),
+    ("sound", cfg!(feature = "sound")),
+    ("smp", cfg!(feature = "smp")),
+    ("lockdep", cfg!(feature = "lockdep")),
+    ("heap-debug", cfg!(feature = "heap-debug")),
+    ("canaries", cfg!(feature = "canaries")),
+    ("procfs", true),
+    ("fat32", true),
+    ("ext4", true),
+    ("overlayfs", true),
+    ("quota", true),
+    ("namespaces", true),
+    ("qsf", true),
+    // ABI 2: W^X for user memory (prctl PR_SET_WX_ALLOWED to opt out)
+    ("wxorx", true),
+    // ABI 4: tmpfs, mounted on /tmp and /run, and mount(2) taking its options
+    ("tmpfs", true),
+    // ABI 5: COM1 to COM4 as /dev/ttyS*, set up with TIOCSSERCONF
+    ("serial-ports", true),
+    // ABI 6: key events on /dev/input/kbd0, and KBDGSTATE
+    ("input", true),
+];
+
+/// The syscall numbered `num`, if the kernel knows it
+pub fn syscall(num: u64) -> Option<&'static Syscall> {
+    SYSCALLS.iter().find(|s| s.num == num)
+}
+
+/// What qunix_features(2) fills in. Fields are only added at the end: a
+/// caller passes the size it knows and gets that much.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QunixFeatures {
+    pub abi_version: u32,
+    /// How many syscall numbers `syscalls` covers
+    pub syscall_bits: u32,
+    /// One bit per implemented syscall, by number
+    pub syscalls: [u64; SYSCALL_BITS / 64],
+    /// One bit per subsystem present, by its place in SUBSYSTEMS
+    pub subsystems: u64,
+}
+
+impl QunixFeatures {
+    /// What this kernel has
+    pub fn current() -> Self {
+        let mut features = QunixFeatures { abi_version: ABI_VERSION, syscall_bits: SYSCALL_BITS as u32, ..Default::default() };
+        for s in SYSCALLS.iter().filter(|s| s.implemented) {
+            features.syscalls[s.num as usize / 64] |= 1 << (s.num % 64);
+        }
+        for (bit, _) in SUBSYSTEMS.iter().enumerate().filter(|(_, (_, present))| *present) {
+            features.subsystems |= 1 << bit;
+        }
+        features
+    }
+
+    pub fn has_syscall(&self, num: u64) -> bool {
+        num < self.syscall_bits as u64 && self.syscalls[num as usize / 64] & (1 << (num % 64)) != 0
+    }
+
+    /// Whether the subsystem `name` is present; false for one this
+    /// table does not know
+    pub fn has_subsystem(&self, name: &str) -> bool {
+        SUBSYSTEMS.iter().position(|(n, _)| *n == name).is_some_and(|bit| self.subsystems & (1 << bit) != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::Errno;
+
+    #[test_case]
+    fn test_syscall_table_is_consistent() {
+        for (i, s) in SYSCALLS.iter().enumerate() {
+            assert!((s.num as usize) < SYSCALL_BITS);
+            assert!(s.since >= 1 && s.since <= ABI_VERSION);
+            assert!(SYSCALLS[i + 1..].iter().all(|t| t.num != s.num && t.name != s.name));
+            assert_eq!(syscall_name(s.num), s.name);
+        }
+        assert!(SUBSYSTEMS.len() <= 64);
+    }
+
+    #[test_case]
+    fn test_stubs_return_enosys() {
+        for s in SYSCALLS.iter().filter(|s| !s.implemented) {
+            let args = SyscallArgs { num: s.num, arg1: 0, arg2: 0, arg3: 0, arg4: 0, arg5: 0, arg6: 0 };
+            assert_eq!(dispatch_syscall(&args), Errno::ENOSYS.to_syscall_ret());
+        }
+    }
+
+    #[test_case]
+    fn test_feature_bits_match_table() {
+        let features = QunixFeatures::current();
+        for s in SYSCALLS {
+            assert_eq!(features.has_syscall(s.num), s.implemented);
+        }
+        assert!(!features.has_syscall(SYSCALL_BITS as u64));
+        assert!(features.has_subsystem("procfs"));
+        assert_eq!(features.has_subsystem("net"), cfg!(feature = "net"));
+        assert!(!features.has_subsystem("nonesuch"));
+    }
+}
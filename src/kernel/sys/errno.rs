@@ -0,0 +1,193 @@
+//src/kernel/sys/errno.rs
+//
+// Kernel-wide error numbers. Syscall handlers work in `SysResult<T>` and the
+// dispatcher converts to the ABI convention (negative errno in rax) in one
+// place; libc decodes the same values back into `Errno`.
+
+use crate::fs::FsError;
+use crate::hal::memory::address_space::AddressSpaceError;
+use crate::hal::memory::vma::VmaError;
+use core::fmt;
+
+macro_rules! errnos {
+    ($($name:ident = $val:expr, $desc:expr;)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[repr(i32)]
+        pub enum Errno {
+            $($name = $val,)*
+        }
+
+        $(pub const $name: i32 = $val;)*
+
+        impl Errno {
+            pub fn from_raw(value: i32) -> Option<Errno> {
+                match value {
+                    $($val => Some(Errno::$name),)*
+                    _ => None,
+                }
+            }
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Errno::$name => stringify!($name),)*
+                }
+            }
+
+            pub fn description(self) -> &'static str {
+                match self {
+                    $(Errno::$name => $desc,)*
+                }
+            }
+        }
+    };
+}
+
+errnos! {
+    EPERM = 1, "Operation not permitted";
+    ENOENT = 2, "No such file or directory";
+    ESRCH = 3, "No such process";
+    EINTR = 4, "Interrupted system call";
+    EIO = 5, "Input/output error";
+    ENXIO = 6, "No such device or address";
+    E2BIG = 7, "Argument list too long";
+    ENOEXEC = 8, "Exec format error";
+    EBADF = 9, "Bad file descriptor";
+    ECHILD = 10, "No child processes";
+    EAGAIN = 11, "Resource temporarily unavailable";
+    ENOMEM = 12, "Cannot allocate memory";
+    EACCES = 13, "Permission denied";
+    EFAULT = 14, "Bad address";
+    ENOTBLK = 15, "Block device required";
+    EBUSY = 16, "Device or resource busy";
+    EEXIST = 17, "File exists";
+    EXDEV = 18, "Invalid cross-device link";
+    ENODEV = 19, "No such device";
+    ENOTDIR = 20, "Not a directory";
+    EISDIR = 21, "Is a directory";
+    EINVAL = 22, "Invalid argument";
+    ENFILE = 23, "Too many open files in system";
+    EMFILE = 24, "Too many open files";
+    ENOTTY = 25, "Inappropriate ioctl for device";
+    ETXTBSY = 26, "Text file busy";
+    EFBIG = 27, "File too large";
+    ENOSPC = 28, "No space left on device";
+    ESPIPE = 29, "Illegal seek";
+    EROFS = 30, "Read-only file system";
+    EMLINK = 31, "Too many links";
+    EPIPE = 32, "Broken pipe";
+    EDOM = 33, "Numerical argument out of domain";
+    ERANGE = 34, "Numerical result out of range";
+    EDEADLK = 35, "Resource deadlock avoided";
+    ENAMETOOLONG = 36, "File name too long";
+    ENOLCK = 37, "No locks available";
+    ENOSYS = 38, "Function not implemented";
+    ENOTEMPTY = 39, "Directory not empty";
+    ELOOP = 40, "Too many levels of symbolic links";
+    EOPNOTSUPP = 95, "Operation not supported";
+    EDQUOT = 122, "Disk quota exceeded";
+}
+
+pub type SysResult<T> = Result<T, Errno>;
+
+/// Largest errno the ABI can return; anything in `-MAX_ERRNO..0` is an error.
+pub const MAX_ERRNO: i64 = 4095;
+
+impl Errno {
+    pub fn as_i32(self) -> i32 {
+        self as i32
+    }
+
+    /// Encodes the error as a syscall return value (negative errno).
+    pub fn to_syscall_ret(self) -> i64 {
+        -(self as i64)
+    }
+
+    /// Decodes a raw syscall return value. Unknown negative values in the
+    /// errno range map to `EINVAL` so callers never see a bogus success.
+    pub fn from_syscall_ret(ret: i64) -> SysResult<u64> {
+        if ret < 0 && ret >= -MAX_ERRNO {
+            Err(Errno::from_raw(-ret as i32).unwrap_or(Errno::EINVAL))
+        } else {
+            Ok(ret as u64)
+        }
+    }
+}
+
+/// Encodes a handler result for the syscall ABI.
+pub fn encode_result(result: SysResult<i64>) -> i64 {
+    match result {
+        Ok(value) => value,
+        Err(e) => e.to_syscall_ret(),
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+impl From<FsError> for Errno {
+    fn from(e: FsError) -> Self {
+        match e {
+            FsError::NotFound => Errno::ENOENT,
+            FsError::PermissionDenied => Errno::EACCES,
+            FsError::AlreadyExists => Errno::EEXIST,
+            FsError::NotDirectory => Errno::ENOTDIR,
+            FsError::IsDirectory => Errno::EISDIR,
+            FsError::NotEmpty => Errno::ENOTEMPTY,
+            FsError::InvalidPath => Errno::EINVAL,
+            FsError::InvalidArgument => Errno::EINVAL,
+            FsError::IoError => Errno::EIO,
+            FsError::NoSpace => Errno::ENOSPC,
+            FsError::ReadOnly => Errno::EROFS,
+            FsError::TooManyLinks => Errno::EMLINK,
+            FsError::NameTooLong => Errno::ENAMETOOLONG,
+            FsError::NotSupported => Errno::EOPNOTSUPP,
+            FsError::Busy => Errno::EBUSY,
+            FsError::SymlinkLoop => Errno::ELOOP,
+            FsError::QuotaExceeded => Errno::EDQUOT,
+            FsError::CrossDevice => Errno::EXDEV,
+        }
+    }
+}
+
+/// Mapping a program fails for want of memory, or because the executable
+/// asks for somewhere it may not go
+impl From<AddressSpaceError> for Errno {
+    fn from(e: AddressSpaceError) -> Self {
+        match e {
+            AddressSpaceError::NoMemory => Errno::ENOMEM,
+            AddressSpaceError::Invalid => Errno::ENOEXEC,
+        }
+    }
+}
+
+impl From<VmaError> for Errno {
+    fn from(e: VmaError) -> Self {
+        match e {
+            VmaError::NoSpace | VmaError::Unmapped => Errno::ENOMEM,
+            VmaError::Invalid => Errno::EINVAL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_errno_abi_roundtrip() {
+        for raw in 1..=40 {
+            let e = Errno::from_raw(raw).unwrap();
+            assert_eq!(Errno::from_syscall_ret(e.to_syscall_ret()), Err(e));
+        }
+        assert_eq!(Errno::from_syscall_ret(7), Ok(7));
+    }
+
+    #[test_case]
+    fn test_errno_from_fs_error() {
+        assert_eq!(Errno::from(FsError::NotFound).to_syscall_ret(), -2);
+        assert_eq!(Errno::from(FsError::NameTooLong), Errno::ENAMETOOLONG);
+    }
+}
@@ -1,10 +1,14 @@
+pub mod abi;
+pub mod errno;
 pub mod posix;
 pub mod syscalls;
 
+pub use errno::{Errno, SysResult};
 pub use posix::*;
 pub use syscalls::*;
 
 use x86_64::structures::idt::InterruptStackFrame;
+use crate::hal::cpu::usermode::{self, SyscallFrame};
 use crate::hal::drivers::keyboard::SpecialKey;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
@@ -20,6 +24,54 @@ pub fn init() {
 pub fn handle_syscall_interrupt(_stack_frame: &InterruptStackFrame) {
 }
 
+/// A syscall made by a program in ring 3. exit does not come back: the
+/// program is gone, and the exec that ran it returns. Nor does an exec
+/// that succeeded: the new program starts instead.
+pub fn handle_user_syscall(frame: &mut SyscallFrame) {
+    let args = SyscallArgs {
+        num: frame.rax,
+        arg1: frame.rdi,
+        arg2: frame.rsi,
+        arg3: frame.rdx,
+        arg4: frame.r10,
+        arg5: frame.r8,
+        arg6: frame.r9,
+    };
+    let ret = dispatch_syscall(&args);
+    if args.num == SYS_EXIT {
+        usermode::leave((args.arg1 as i32 & 0xff) << 8);
+    }
+    if args.num == SYS_WRITE && ret == Errno::EPIPE.to_syscall_ret() && broken_pipe_kills() {
+        let signal = crate::kernel::scheduler::task::SIGPIPE as i32;
+        crate::kernel::scheduler::exit(128 + signal);
+        usermode::leave(signal);
+    }
+    usermode::jump_if_replaced();
+    frame.rax = ret as u64;
+}
+
+/// Whether the SIGPIPE a write to a pipe with no readers sent the running
+/// program ends it: it neither handles, blocks nor ignores it
+fn broken_pipe_kills() -> bool {
+    let signal = crate::kernel::scheduler::task::SIGPIPE;
+    let scheduler = crate::kernel::scheduler::SCHEDULER.lock();
+    scheduler.current().is_some_and(|task| {
+        task.has_pending_signal(signal) && !task.ignores_signal(signal)
+            && task.signal_handlers[signal as usize] == posix::signals::SIG_DFL as u64
+    })
+}
+
+/// The program in ring 3 raised `what` at `rip`: it is killed by
+/// `signal`, exiting as a shell reports it, with 128 + `signal`
+pub fn user_fault(signal: i32, what: &str, rip: u64) -> ! {
+    let task = crate::kernel::scheduler::SCHEDULER.lock().current().map(|task| (task.name.clone(), task.pid));
+    if let Some((name, pid)) = task {
+        crate::println!("{}[{}]: {} at {:#x}, killed by signal {}", name, pid, what, rip, signal);
+    }
+    crate::kernel::scheduler::exit(128 + signal);
+    usermode::leave(signal);
+}
+
 pub fn handle_keyboard_input(c: char) {
     unsafe {
         let len = INPUT_LEN.load(Ordering::SeqCst);
@@ -1,5 +1,5 @@
 use crate::fs::{FsResult, FsError, FileStat};
-use crate::fs::vfs::api::OpenFlags;
+use crate::fs::vfs::api::{AccessMode, OpenFlags};
 use alloc::string::String;
 
 pub fn posix_open(path: &str, flags: i32, mode: u32) -> FsResult<i32> {
@@ -73,7 +73,7 @@ pub fn posix_lstat(path: &str) -> FsResult<PosixStat> {
 }
 
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct PosixStat {
     pub st_dev: u64,
     pub st_ino: u64,
@@ -116,8 +116,11 @@ impl From<FileStat> for PosixStat {
     }
 }
 
+/// access(2) for the running task, with its real ids
 pub fn posix_access(path: &str, mode: i32) -> FsResult<()> {
-    crate::fs::vfs::api::access(path, mode)
+    let mode = AccessMode::from_bits(mode as u32).ok_or(FsError::InvalidArgument)?;
+    let (uid, gid) = crate::kernel::scheduler::SCHEDULER.lock().current().map_or((0, 0), |task| (task.uid, task.gid));
+    crate::fs::vfs::api::access(path, uid, gid, mode)
 }
 
 pub fn posix_chmod(path: &str, mode: u32) -> FsResult<()> {
@@ -2,6 +2,7 @@ use super::fs::*;
 use super::proc::*;
 use super::signals::*;
 use crate::fs::{FsResult, FsError};
+use crate::fs::vfs::path::{NAME_MAX, PATH_MAX};
 use crate::kernel::scheduler::Pid;
 use alloc::string::String;
 
@@ -77,8 +78,8 @@ impl Default for PosixLimits {
             tzname_max: 6,
             pagesize: 4096,
             symloop_max: 40,
-            path_max: 4096,
-            name_max: 255,
+            path_max: PATH_MAX,
+            name_max: NAME_MAX,
             pipe_buf: 4096,
         }
     }
@@ -142,7 +143,14 @@ impl TimeSpec {
 
 pub fn clock_gettime(clock_id: i32) -> FsResult<TimeSpec> {
     match clock_id {
-        0 | 1 => Ok(TimeSpec::now()),
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => {
+            let ms = crate::kernel::time::now_ms();
+            Ok(TimeSpec {
+                tv_sec: (ms / 1000) as i64,
+                tv_nsec: ((ms % 1000) * 1_000_000) as i64,
+            })
+        }
+        CLOCK_MONOTONIC | CLOCK_MONOTONIC_COARSE | CLOCK_MONOTONIC_RAW | CLOCK_BOOTTIME => Ok(TimeSpec::now()),
         _ => Err(FsError::InvalidArgument),
     }
 }
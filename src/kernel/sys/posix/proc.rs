@@ -111,20 +111,40 @@ pub fn posix_setgid(gid: u32) -> FsResult<()> {
     }
 }
 
+/// The job control errors as the FsError closest to each
+fn job_error(e: crate::kernel::sys::errno::Errno) -> FsError {
+    use crate::kernel::sys::errno::Errno;
+    match e {
+        Errno::ESRCH => FsError::NotFound,
+        Errno::EINVAL => FsError::InvalidArgument,
+        _ => FsError::PermissionDenied,
+    }
+}
+
 pub fn posix_setsid() -> FsResult<Pid> {
-    Err(FsError::NotSupported)
+    let mut scheduler = SCHEDULER.lock();
+    let pid = scheduler.current_pid().ok_or(FsError::InvalidArgument)?;
+    scheduler.setsid(pid).map_err(job_error)
 }
 
-pub fn posix_getpgid(_pid: Pid) -> FsResult<Pid> {
-    Err(FsError::NotSupported)
+/// Group of `pid`, or of the caller for 0
+pub fn posix_getpgid(pid: Pid) -> FsResult<Pid> {
+    let scheduler = SCHEDULER.lock();
+    let pid = if pid == 0 { scheduler.current_pid().ok_or(FsError::InvalidArgument)? } else { pid };
+    scheduler.getpgid(pid).map_err(job_error)
 }
 
-pub fn posix_setpgid(_pid: Pid, _pgid: Pid) -> FsResult<()> {
-    Err(FsError::NotSupported)
+/// Move `pid` (0 for the caller) into group `pgid` (0 for its own)
+pub fn posix_setpgid(pid: Pid, pgid: Pid) -> FsResult<()> {
+    let mut scheduler = SCHEDULER.lock();
+    let caller = scheduler.current_pid().ok_or(FsError::InvalidArgument)?;
+    let pid = if pid == 0 { caller } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    scheduler.setpgid(caller, pid, pgid).map_err(job_error)
 }
 
 pub fn posix_getpgrp() -> Pid {
-    posix_getpid()
+    posix_getpgid(0).unwrap_or(0)
 }
 
 #[repr(C)]
@@ -185,6 +205,8 @@ pub fn posix_getrusage(_who: i32) -> RUsage {
         let ms = task.cpu_time;
         rusage.ru_utime.tv_sec = (ms / 1000) as i64;
         rusage.ru_utime.tv_usec = ((ms % 1000) * 1000) as i64;
+        rusage.ru_nvcsw = task.nvcsw as i64;
+        rusage.ru_nivcsw = task.nivcsw as i64;
     }
     
     rusage
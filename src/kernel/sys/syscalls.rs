@@ -1,9 +1,21 @@
-use crate::fs::{FsError};
+use super::errno::{self, Errno, SysResult};
 use crate::kernel::scheduler::{SCHEDULER, Pid};
+use crate::kernel::scheduler::task::FileDescriptor;
+use crate::kernel::file::OpenFile;
+use crate::kernel::scheduler::context::Context;
+use crate::kernel::scheduler::task::KernelStack;
+use crate::hal::cpu::usermode;
+use crate::hal::drivers::{input, serial};
+use alloc::sync::Arc;
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
 use crate::fs::vfs::api as vfs_api;
+use crate::fs::vfs::path as vfs_path;
+use crate::fs::vfs::node::DeviceId;
+use crate::hal::memory::address_space::AddressSpace;
+use crate::hal::memory::alloc_tag::{AllocTag, Subsystem};
+use crate::hal::memory::heap_debug::AllocSite;
 
 pub const SYS_READ: u64 = 0;
 pub const SYS_WRITE: u64 = 1;
@@ -50,21 +62,182 @@ pub const SYS_FCHMOD: u64 = 91;
 pub const SYS_CHOWN: u64 = 92;
 pub const SYS_FCHOWN: u64 = 93;
 pub const SYS_UMASK: u64 = 95;
+pub const SYS_GETRLIMIT: u64 = 97;
 pub const SYS_GETUID: u64 = 102;
 pub const SYS_GETGID: u64 = 104;
 pub const SYS_SETUID: u64 = 105;
 pub const SYS_SETGID: u64 = 106;
 pub const SYS_GETEUID: u64 = 107;
 pub const SYS_GETEGID: u64 = 108;
+pub const SYS_SETPGID: u64 = 109;
 pub const SYS_GETPPID: u64 = 110;
 pub const SYS_GETPGRP: u64 = 111;
 pub const SYS_SETSID: u64 = 112;
+pub const SYS_GETPGID: u64 = 121;
+pub const SYS_GETSID: u64 = 124;
 pub const SYS_GETGROUPS: u64 = 115;
 pub const SYS_SETGROUPS: u64 = 116;
+pub const SYS_CHROOT: u64 = 161;
+pub const SYS_MOUNT: u64 = 165;
+pub const SYS_UMOUNT2: u64 = 166;
+pub const SYS_FACCESSAT: u64 = 269;
+pub const SYS_UNSHARE: u64 = 272;
+pub const SYS_FALLOCATE: u64 = 285;
+pub const SYS_PRCTL: u64 = 157;
+pub const SYS_SETRLIMIT: u64 = 160;
+/// Qunix's own, past the end of Linux's table
+pub const SYS_POSIX_SPAWN: u64 = 500;
+pub const SYS_QUNIX_FEATURES: u64 = 501;
 pub const SYS_SIGACTION: u64 = 13;
 pub const SYS_SIGPROCMASK: u64 = 14;
 pub const SYS_SIGRETURN: u64 = 15;
 
+/// unshare(2) flags
+pub const CLONE_NEWNS: u64 = 0x0002_0000;
+pub const CLONE_NEWPID: u64 = 0x2000_0000;
+
+/// prctl(2) options
+pub const PR_SET_CHILD_SUBREAPER: u64 = 36;
+pub const PR_GET_CHILD_SUBREAPER: u64 = 37;
+/// Qunix's own: whether the caller may map memory both writable and
+/// executable, which W^X otherwise refuses
+pub const PR_SET_WX_ALLOWED: u64 = 1000;
+pub const PR_GET_WX_ALLOWED: u64 = 1001;
+
+/// getrlimit(2) and setrlimit(2) resources, and the limit that is none
+pub const RLIMIT_NOFILE: u32 = 7;
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// Terminal ioctl(2) requests for job control
+pub const TIOCSCTTY: u64 = 0x540E;
+pub const TIOCGPGRP: u64 = 0x540F;
+pub const TIOCSPGRP: u64 = 0x5410;
+pub const TIOCNOTTY: u64 = 0x5422;
+pub const TIOCGSID: u64 = 0x5429;
+/// Qunix's own: get and set a serial port's line settings, as a
+/// SerialConfig
+pub const TIOCGSERCONF: u64 = 0x5480;
+pub const TIOCSSERCONF: u64 = 0x5481;
+
+/// Qunix's own keyboard ioctl(2) request, on /dev/input/kbd0: fill in a
+/// KeyboardState
+pub const KBDGSTATE: u64 = 0x4B80;
+
+/// KeyEvent values
+pub const KEY_RELEASED: u8 = 0;
+pub const KEY_PRESSED: u8 = 1;
+/// Held down long enough for the keyboard to send it again
+pub const KEY_REPEATED: u8 = 2;
+
+/// Modifiers held and locks on, as KeyEvent and KeyboardState have them;
+/// either of a pair of keys counts
+pub const KEYMOD_SHIFT: u16 = 0x01;
+pub const KEYMOD_CTRL: u16 = 0x02;
+pub const KEYMOD_ALT: u16 = 0x04;
+pub const KEYMOD_META: u16 = 0x08;
+pub const KEYMOD_CAPSLOCK: u16 = 0x10;
+pub const KEYMOD_NUMLOCK: u16 = 0x20;
+pub const KEYMOD_SCROLLLOCK: u16 = 0x40;
+
+/// A key going down, repeating or coming up, as /dev/input/kbd0 reads
+/// them: the uptime in milliseconds, the key's Linux input code (KEY_A is
+/// 30), the KEYMOD_* bits in effect after it and a KEY_* value
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub time_ms: u64,
+    pub code: u16,
+    pub modifiers: u16,
+    pub value: u8,
+    pub reserved: [u8; 3],
+}
+
+/// What KBDGSTATE fills in: the KEYMOD_* bits in effect, and a bit per key
+/// held down, by code
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyboardState {
+    pub modifiers: u16,
+    pub reserved: [u16; 3],
+    pub keys: [u64; 4],
+}
+
+/// SerialConfig parities
+pub const SERIAL_PARITY_NONE: u8 = 0;
+pub const SERIAL_PARITY_ODD: u8 = 1;
+pub const SERIAL_PARITY_EVEN: u8 = 2;
+pub const SERIAL_PARITY_MARK: u8 = 3;
+pub const SERIAL_PARITY_SPACE: u8 = 4;
+
+/// A serial port's line settings, as TIOCGSERCONF and TIOCSSERCONF take
+/// them: a baud rate 115200 divides by, 5 to 8 data bits, a
+/// SERIAL_PARITY_* and 1 or 2 stop bits
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerialConfig {
+    pub baud: u32,
+    pub data_bits: u8,
+    pub parity: u8,
+    pub stop_bits: u8,
+    pub reserved: u8,
+}
+
+impl From<serial::Config> for SerialConfig {
+    fn from(config: serial::Config) -> Self {
+        let parity = match config.parity {
+            serial::Parity::None => SERIAL_PARITY_NONE,
+            serial::Parity::Odd => SERIAL_PARITY_ODD,
+            serial::Parity::Even => SERIAL_PARITY_EVEN,
+            serial::Parity::Mark => SERIAL_PARITY_MARK,
+            serial::Parity::Space => SERIAL_PARITY_SPACE,
+        };
+        SerialConfig { baud: config.baud, data_bits: config.data_bits, parity, stop_bits: config.stop_bits, reserved: 0 }
+    }
+}
+
+impl SerialConfig {
+    /// The settings, if the parity is one there is
+    pub fn to_config(&self) -> Option<serial::Config> {
+        let parity = match self.parity {
+            SERIAL_PARITY_NONE => serial::Parity::None,
+            SERIAL_PARITY_ODD => serial::Parity::Odd,
+            SERIAL_PARITY_EVEN => serial::Parity::Even,
+            SERIAL_PARITY_MARK => serial::Parity::Mark,
+            SERIAL_PARITY_SPACE => serial::Parity::Space,
+            _ => return None,
+        };
+        Some(serial::Config { baud: self.baud, data_bits: self.data_bits, parity, stop_bits: self.stop_bits })
+    }
+}
+
+/// posix_spawn file actions
+pub const SPAWN_OPEN: u32 = 0;
+pub const SPAWN_CLOSE: u32 = 1;
+pub const SPAWN_DUP2: u32 = 2;
+
+/// One file action for posix_spawn, applied to the child's descriptors in
+/// the order given: open `path` with `flags` and `mode` as `fd`, close
+/// `fd`, or dup2 `source` onto `fd`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SpawnAction {
+    pub kind: u32,
+    pub fd: i32,
+    pub source: i32,
+    pub flags: i32,
+    pub mode: u32,
+    pub path: *const u8,
+}
+
+/// A resource limit, as getrlimit and setrlimit take it: the soft limit
+/// enforced, and the hard limit it may be raised to
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rlimit {
+    pub cur: u64,
+    pub max: u64,
+}
+
 #[derive(Debug)]
 pub struct SyscallArgs {
     pub num: u64,
@@ -77,25 +250,132 @@ pub struct SyscallArgs {
 }
 
 pub fn dispatch_syscall(args: &SyscallArgs) -> i64 {
+    crate::tracepoint!(SyscallEnter, args.num, args.arg1, args.arg2);
+    let _alloc_tag = AllocTag::enter(syscall_subsystem(args.num));
+    let result = do_syscall(args);
+    if crate::qsf::modules::trace::tracing_active() {
+        trace_syscall(args, &result);
+    }
+    let ret = errno::encode_result(result);
+    crate::tracepoint!(SyscallExit, args.num, ret);
+    ret
+}
+
+/// The name of syscall `num`, from the ABI table (sys::abi)
+pub fn syscall_name(num: u64) -> &'static str {
+    super::abi::syscall(num).map_or("unknown", |s| s.name)
+}
+
+/// The subsystem a syscall's allocations are charged to
+fn syscall_subsystem(num: u64) -> Subsystem {
+    match num {
+        SYS_READ | SYS_WRITE | SYS_OPEN | SYS_CLOSE | SYS_STAT | SYS_FSTAT | SYS_LSTAT | SYS_POLL
+        | SYS_LSEEK | SYS_IOCTL | SYS_ACCESS | SYS_PIPE | SYS_DUP | SYS_DUP2 | SYS_FCNTL | SYS_FLOCK
+        | SYS_FSYNC | SYS_GETCWD | SYS_CHDIR | SYS_FCHDIR | SYS_RENAME | SYS_MKDIR | SYS_RMDIR
+        | SYS_CREAT | SYS_LINK | SYS_UNLINK | SYS_SYMLINK | SYS_READLINK | SYS_CHMOD | SYS_FCHMOD
+        | SYS_CHOWN | SYS_FCHOWN | SYS_UMASK | SYS_CHROOT | SYS_FACCESSAT | SYS_FALLOCATE | SYS_MOUNT
+        | SYS_UMOUNT2 => Subsystem::Fs,
+        SYS_FORK | SYS_VFORK | SYS_EXECVE | SYS_EXIT | SYS_WAIT4 | SYS_KILL | SYS_SETPGID
+        | SYS_SETSID | SYS_UNSHARE | SYS_PRCTL | SYS_POSIX_SPAWN | SYS_GETRLIMIT | SYS_SETRLIMIT => Subsystem::Sched,
+        _ => Subsystem::Kernel,
+    }
+}
+
+/// Renders a syscall as `name(args)` with paths and fds decoded, for the
+/// QSF syscall audit trail.
+fn describe_syscall(args: &SyscallArgs) -> String {
+    let path = |ptr: u64| match user_path(ptr as *const u8) {
+        Ok(p) => alloc::format!("\"{}\"", p),
+        Err(_) => alloc::format!("{:#x}", ptr),
+    };
+    let name = syscall_name(args.num);
+    match args.num {
+        SYS_OPEN | SYS_CREAT => alloc::format!("{}({}, {:#o}, {:#o})", name, path(args.arg1), args.arg2, args.arg3),
+        SYS_STAT | SYS_LSTAT | SYS_CHDIR | SYS_CHROOT | SYS_RMDIR | SYS_UNLINK | SYS_EXECVE | SYS_READLINK =>
+            alloc::format!("{}({})", name, path(args.arg1)),
+        SYS_MKDIR | SYS_CHMOD | SYS_ACCESS => alloc::format!("{}({}, {:#o})", name, path(args.arg1), args.arg2),
+        SYS_CHOWN => alloc::format!("{}({}, {}, {})", name, path(args.arg1), args.arg2, args.arg3),
+        SYS_RENAME | SYS_LINK | SYS_SYMLINK => alloc::format!("{}({}, {})", name, path(args.arg1), path(args.arg2)),
+        SYS_MOUNT => alloc::format!("{}({}, {}, {}, {:#x}, {})", name, path(args.arg1), path(args.arg2), path(args.arg3), args.arg4, path(args.arg5)),
+        SYS_UMOUNT2 => alloc::format!("{}({}, {:#x})", name, path(args.arg1), args.arg2),
+        SYS_READ | SYS_WRITE => alloc::format!("{}(fd={}, {:#x}, {})", name, args.arg1 as i32, args.arg2, args.arg3),
+        SYS_CLOSE | SYS_FSTAT | SYS_DUP | SYS_FCHDIR | SYS_FSYNC | SYS_FCHMOD | SYS_FCHOWN =>
+            alloc::format!("{}(fd={})", name, args.arg1 as i32),
+        SYS_IOCTL => alloc::format!("{}(fd={}, {:#x}, {:#x})", name, args.arg1 as i32, args.arg2, args.arg3),
+        SYS_LSEEK => alloc::format!("{}(fd={}, {}, {})", name, args.arg1 as i32, args.arg2 as i64, args.arg3),
+        SYS_FALLOCATE => alloc::format!("{}(fd={}, {:#x}, {}, {})", name, args.arg1 as i32, args.arg2, args.arg3, args.arg4),
+        SYS_FACCESSAT => alloc::format!("{}({}, {}, {:#o}, {:#x})", name, args.arg1 as i32, path(args.arg2), args.arg3, args.arg4),
+        SYS_DUP2 => alloc::format!("{}(fd={}, fd={})", name, args.arg1 as i32, args.arg2 as i32),
+        SYS_MMAP => alloc::format!("{}({:#x}, {}, {:#x}, {:#x}, fd={}, {:#x})",
+            name, args.arg1, args.arg2, args.arg3, args.arg4, args.arg5 as i32, args.arg6),
+        SYS_MUNMAP => alloc::format!("{}({:#x}, {})", name, args.arg1, args.arg2),
+        SYS_MPROTECT => alloc::format!("{}({:#x}, {}, {:#x})", name, args.arg1, args.arg2, args.arg3),
+        SYS_BRK => alloc::format!("{}({:#x})", name, args.arg1),
+        SYS_KILL | SYS_SETPGID => alloc::format!("{}({}, {})", name, args.arg1 as i32, args.arg2 as i32),
+        SYS_GETPGID | SYS_GETSID => alloc::format!("{}({})", name, args.arg1 as i32),
+        SYS_WAIT4 => alloc::format!("{}({}, {:#x})", name, args.arg1 as i32, args.arg3),
+        SYS_UNSHARE => alloc::format!("{}({:#x})", name, args.arg1),
+        SYS_PRCTL => alloc::format!("{}({}, {:#x})", name, args.arg1, args.arg2),
+        SYS_POSIX_SPAWN => alloc::format!("{}({:#x}, {}, {:#x}, {})", name, args.arg1, path(args.arg2), args.arg3, args.arg4),
+        SYS_QUNIX_FEATURES => alloc::format!("{}({:#x}, {})", name, args.arg1, args.arg2),
+        _ if name == "unknown" => alloc::format!("syscall_{}({:#x}, {:#x}, {:#x})", args.num, args.arg1, args.arg2, args.arg3),
+        _ => alloc::format!("{}({:#x}, {:#x}, {:#x})", name, args.arg1, args.arg2, args.arg3),
+    }
+}
+
+fn trace_syscall(args: &SyscallArgs, result: &SysResult<i64>) {
+    let (pid, uid) = match SCHEDULER.lock().current() {
+        Some(task) => (task.pid, task.uid),
+        None => return,
+    };
+    if !crate::qsf::QSF.lock().is_traced(pid) {
+        return;
+    }
+
+    let call = describe_syscall(args);
+    let outcome = match result {
+        Ok(value) => alloc::format!("= {}", value),
+        Err(e) => alloc::format!("= -1 {} ({})", e.name(), e.description()),
+    };
+    crate::qsf::QSF.lock().trace_syscall(pid, uid, &call, &outcome);
+}
+
+fn do_syscall(args: &SyscallArgs) -> SysResult<i64> {
     match args.num {
         SYS_READ => sys_read(args.arg1 as i32, args.arg2 as *mut u8, args.arg3 as usize),
         SYS_WRITE => sys_write(args.arg1 as i32, args.arg2 as *const u8, args.arg3 as usize),
         SYS_OPEN => sys_open(args.arg1 as *const u8, args.arg2 as i32, args.arg3 as u32),
         SYS_CLOSE => sys_close(args.arg1 as i32),
         SYS_LSEEK => sys_lseek(args.arg1 as i32, args.arg2 as i64, args.arg3 as i32),
+        SYS_MMAP => sys_mmap(args.arg1, args.arg2, args.arg3, args.arg4, args.arg5 as i32, args.arg6),
+        SYS_MUNMAP => sys_munmap(args.arg1, args.arg2),
+        SYS_MPROTECT => sys_mprotect(args.arg1, args.arg2, args.arg3),
+        SYS_BRK => sys_brk(args.arg1),
         SYS_GETPID => sys_getpid(),
         SYS_GETPPID => sys_getppid(),
+        SYS_SETPGID => sys_setpgid(args.arg1 as i32, args.arg2 as i32),
+        SYS_GETPGID => sys_getpgid(args.arg1 as i32),
+        SYS_GETPGRP => sys_getpgid(0),
+        SYS_SETSID => sys_setsid(),
+        SYS_GETSID => sys_getsid(args.arg1 as i32),
         SYS_GETUID => sys_getuid(),
         SYS_GETEUID => sys_geteuid(),
         SYS_GETGID => sys_getgid(),
         SYS_GETEGID => sys_getegid(),
         SYS_FORK => sys_fork(),
+        SYS_VFORK => sys_vfork(),
+        SYS_POSIX_SPAWN => sys_posix_spawn(args.arg1 as *mut i32, args.arg2 as *const u8,
+            args.arg3 as *const SpawnAction, args.arg4 as usize),
         SYS_EXIT => sys_exit(args.arg1 as i32),
         SYS_EXECVE => sys_execve(args.arg1 as *const u8, args.arg2 as *const *const u8, args.arg3 as *const *const u8),
         SYS_WAIT4 => sys_wait4(args.arg1 as i32, args.arg2 as *mut i32, args.arg3 as i32, args.arg4 as *const u8),
         SYS_KILL => sys_kill(args.arg1 as i32, args.arg2 as i32),
         SYS_GETCWD => sys_getcwd(args.arg1 as *mut u8, args.arg2 as usize),
         SYS_CHDIR => sys_chdir(args.arg1 as *const u8),
+        SYS_FCHDIR => sys_fchdir(args.arg1 as i32),
+        SYS_READLINK => sys_readlink(args.arg1 as *const u8, args.arg2 as *mut u8, args.arg3 as i64),
+        SYS_ACCESS => sys_faccessat(AT_FDCWD, args.arg1 as *const u8, args.arg2 as u32, 0),
+        SYS_FACCESSAT => sys_faccessat(args.arg1 as i32, args.arg2 as *const u8, args.arg3 as u32, args.arg4 as i32),
         SYS_MKDIR => sys_mkdir(args.arg1 as *const u8, args.arg2 as u32),
         SYS_RMDIR => sys_rmdir(args.arg1 as *const u8),
         SYS_UNLINK => sys_unlink(args.arg1 as *const u8),
@@ -109,613 +389,1214 @@ pub fn dispatch_syscall(args: &SyscallArgs) -> i64 {
         SYS_PIPE => sys_pipe(args.arg1 as *mut i32),
         SYS_DUP => sys_dup(args.arg1 as i32),
         SYS_DUP2 => sys_dup2(args.arg1 as i32, args.arg2 as i32),
-        _ => -38,  // ENOSYS
+        SYS_CHROOT => sys_chroot(args.arg1 as *const u8),
+        SYS_MOUNT => sys_mount(args.arg1 as *const u8, args.arg2 as *const u8, args.arg3 as *const u8, args.arg4, args.arg5 as *const u8),
+        SYS_UMOUNT2 => sys_umount2(args.arg1 as *const u8, args.arg2),
+        SYS_UNSHARE => sys_unshare(args.arg1),
+        SYS_FALLOCATE => sys_fallocate(args.arg1 as i32, args.arg2 as u32, args.arg3 as i64, args.arg4 as i64),
+        SYS_PRCTL => sys_prctl(args.arg1, args.arg2),
+        SYS_GETRLIMIT => sys_getrlimit(args.arg1 as u32, args.arg2 as *mut Rlimit),
+        SYS_SETRLIMIT => sys_setrlimit(args.arg1 as u32, args.arg2 as *const Rlimit),
+        SYS_IOCTL => sys_ioctl(args.arg1 as i32, args.arg2, args.arg3),
+        SYS_QUNIX_FEATURES => sys_qunix_features(args.arg1 as *mut u8, args.arg2 as usize),
+        // In the ABI table with their numbers reserved, not done yet
+        SYS_LSTAT | SYS_POLL | SYS_SIGACTION | SYS_SIGPROCMASK | SYS_SIGRETURN | SYS_UNAME | SYS_FCNTL
+        | SYS_FLOCK | SYS_FSYNC | SYS_RENAME | SYS_CREAT | SYS_LINK | SYS_SYMLINK | SYS_SETUID | SYS_SETGID
+        | SYS_GETGROUPS | SYS_SETGROUPS => Err(Errno::ENOSYS),
+        _ => Err(Errno::ENOSYS),
     }
 }
 
-fn sys_read(fd: i32, buf: *mut u8, count: usize) -> i64 {
-    if buf.is_null() {
-        return -14;
+/// Copies a NUL-terminated path out of caller memory, checked against
+/// the VFS path limits (fs::vfs::path).
+fn user_path(ptr: *const u8) -> SysResult<String> {
+    if ptr.is_null() {
+        return Err(Errno::EFAULT);
     }
-    
-    let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
-        if let Some(fd_entry) = task.get_fd_mut(fd) {
-            // Build buffer
-            let slice = unsafe { core::slice::from_raw_parts_mut(buf, count) };
-            // Lookup node
-            let vfs = crate::fs::vfs::vfs::VFS.lock();
-            match vfs.lookup_path(&fd_entry.path) {
-                Ok(node) => {
-                    match node.read(fd_entry.offset, slice) {
-                        Ok(bytes_read) => {
-                            fd_entry.offset += bytes_read as u64;
-                            return bytes_read as i64;
-                        }
-                        Err(e) => return fs_error_to_errno(e),
-                    }
-                }
-                Err(e) => return fs_error_to_errno(e),
+
+    let bytes = unsafe {
+        let mut bytes = Vec::new();
+        let mut p = ptr;
+        while *p != 0 {
+            if bytes.len() >= vfs_path::PATH_MAX {
+                return Err(Errno::ENAMETOOLONG);
             }
+            bytes.push(*p);
+            p = p.add(1);
+        }
+        bytes
+    };
+
+    Ok(vfs_path::from_bytes(&bytes)?.to_string())
+}
+
+/// qunix_features: copy what the kernel has, as a QunixFeatures cut to
+/// `size` bytes, to `buf` if it is given. Returns the ABI version.
+fn sys_qunix_features(buf: *mut u8, size: usize) -> SysResult<i64> {
+    let features = super::abi::QunixFeatures::current();
+    if !buf.is_null() {
+        let len = size.min(core::mem::size_of_val(&features));
+        unsafe {
+            core::ptr::copy_nonoverlapping(&features as *const _ as *const u8, buf, len);
         }
     }
+    Ok(features.abi_version as i64)
+}
 
-    -9
+fn sys_read(fd: i32, buf: *mut u8, count: usize) -> SysResult<i64> {
+    if buf.is_null() {
+        return Err(Errno::EFAULT);
+    }
+
+    let slice = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+    let pid = SCHEDULER.lock().current_pid();
+    read_fd(pid, fd, slice).map(|read| read as i64)
 }
 
-fn sys_write(fd: i32, buf: *const u8, count: usize) -> i64 {
+fn sys_write(fd: i32, buf: *const u8, count: usize) -> SysResult<i64> {
     if buf.is_null() {
-        return -14;  // EFAULT
-    }
-    
-    // stdout/stderr: write directly
-    if fd == 1 || fd == 2 {
-        let slice = unsafe { core::slice::from_raw_parts(buf, count) };
-        if let Ok(s) = core::str::from_utf8(slice) {
-            crate::print!("{}", s);
-        } else {
-            // Write binary data
-            for &byte in slice {
-                crate::print!("{}", byte as char);
-            }
-        }
-        return count as i64;
+        return Err(Errno::EFAULT);
     }
-    
-    // For other fds: check if exists in task's fd table and write via VFS or device
-    let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
-        if let Some(fd_entry) = task.get_fd_mut(fd) {
-            let path = fd_entry.path.clone();
-            let offset = fd_entry.offset;
-            let slice = unsafe { core::slice::from_raw_parts(buf, count) };
-            
-            let mut vfs = crate::fs::vfs::vfs::VFS.lock();
-            // Get inode first, then drop the immutable borrow
-            let inode = match vfs.lookup_path(&path) {
-                Ok(node) => node.inode,
-                Err(e) => return fs_error_to_errno(e),
-            };
-            
-            match vfs.write_node(inode, offset, slice) {
-                Ok(written) => {
-                    fd_entry.offset += written as u64;
-                    return written as i64;
-                }
-                Err(e) => return fs_error_to_errno(e),
-            }
-        }
+
+    let slice = unsafe { core::slice::from_raw_parts(buf, count) };
+    let pid = SCHEDULER.lock().current_pid();
+    let result = write_fd(pid, fd, slice);
+    if let (Err(Errno::EPIPE), Some(pid)) = (result, pid) {
+        SCHEDULER.lock().kill(pid, crate::kernel::scheduler::task::SIGPIPE);
     }
+    result.map(|written| written as i64)
+}
 
-    -9  // EBADF
+/// The open file descriptor `fd` of task `pid` refers to
+fn file_of(pid: Option<Pid>, fd: i32) -> SysResult<Arc<OpenFile>> {
+    let scheduler = SCHEDULER.lock();
+    let task = pid.and_then(|pid| scheduler.get_task(pid)).ok_or(Errno::EBADF)?;
+    Ok(task.get_fd(fd).ok_or(Errno::EBADF)?.file.clone())
 }
 
-fn sys_open(_pathname: *const u8, _flags: i32, _mode: u32) -> i64 {
-    if _pathname.is_null() {
-        return -14; // EFAULT
+/// Read from descriptor `fd` of task `pid` into `buf`, the way read(2)
+/// does for that task, through its open file: a terminal only for the
+/// foreground
+pub fn read_fd(pid: Option<Pid>, fd: i32, buf: &mut [u8]) -> SysResult<usize> {
+    let file = file_of(pid, fd)?;
+    if let (Some(terminal), Some(pid)) = (file.terminal(), pid) {
+        SCHEDULER.lock().check_tty_read(pid, terminal)?;
     }
+    // Without the scheduler: others may have to run for a pipe to fill
+    file.read(buf)
+}
 
-    // Extract path string
-    let path_vec = unsafe {
-        let mut bytes = Vec::new();
-        let mut ptr = _pathname;
-        while *ptr != 0 {
-            bytes.push(*ptr);
-            ptr = ptr.add(1);
-            if bytes.len() > 4096 { break; }
+/// Whether `fd` of task `pid` refers to the console. Without a task, or
+/// without an entry in its table, fds 1 and 2 are the console.
+pub fn fd_is_console(pid: Option<Pid>, fd: i32) -> bool {
+    match file_of(pid, fd) {
+        Ok(file) => file.is_console(),
+        Err(_) => fd == 1 || fd == 2,
+    }
+}
+
+/// Write `data` to descriptor `fd` of task `pid`, the way write(2) does
+/// for that task, through its open file. Without a task, or without an
+/// entry in its table, fds 1 and 2 are the console.
+pub fn write_fd(pid: Option<Pid>, fd: i32, data: &[u8]) -> SysResult<usize> {
+    match file_of(pid, fd) {
+        Ok(file) => file.write(data),
+        Err(_) if fd == 1 || fd == 2 => {
+            crate::hal::drivers::tty::write_console(data);
+            Ok(data.len())
         }
-        bytes
-    };
+        Err(e) => Err(e),
+    }
+}
+
+fn sys_open(pathname: *const u8, flags: i32, mode: u32) -> SysResult<i64> {
+    let path = user_path(pathname)?;
 
-    let path = match core::str::from_utf8(&path_vec) {
-        Ok(s) => s.to_string(),
-        Err(_) => return -14,
+    let (pid, uid, euid) = SCHEDULER.lock().current().map_or((0, 0, 0), |task| (task.pid, task.uid, task.euid));
+    if crate::qsf::QSF.lock().check_raw_device(pid, uid, &path).is_err() {
+        return Err(Errno::EPERM);
+    }
+    // The file rules, for the access the open asks for
+    let access = match flags as u32 & 3 {
+        0 => 0o400,
+        1 => 0o200,
+        _ => 0o600,
     };
+    if crate::qsf::check_access(pid, euid, &path, access) == crate::qsf::AccessDecision::Deny {
+        return Err(Errno::EACCES);
+    }
+
+    crate::kernel::procfs::refresh_for(&path);
 
     // Validate via VFS open
-    let open_flags = vfs_api::OpenFlags::from_bits_truncate(_flags as u32);
-    match crate::fs::vfs::api::open(&path, open_flags, _mode as u16) {
-        Ok(_) => {
-            let mut scheduler = SCHEDULER.lock();
-            if let Some(task) = scheduler.current_mut() {
-                let newfd = task.allocate_fd();
-                task.fds.insert(newfd, crate::kernel::scheduler::task::FileDescriptor {
-                    fd: newfd,
-                    path: path.clone(),
-                    offset: 0,
-                    flags: _flags as u32,
-                });
-                return newfd as i64;
-            }
-            -3
-        }
-        Err(e) => fs_error_to_errno(e),
+    let open_flags = vfs_api::OpenFlags::from_bits_truncate(flags as u32);
+    let file = crate::fs::vfs::api::open(&path, open_flags, mode as u16)?;
+    let path = crate::fs::vfs::vfs::VFS.lock().resolve_path(&path);
+    let file = OpenFile::open(file.inode, path, flags as u32, file.mode.is_dir());
+    let terminal = file.terminal();
+
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    let newfd = task.allocate_fd().ok_or(Errno::EMFILE)?;
+    task.fds.insert(newfd, FileDescriptor::new(newfd, file, flags as u32));
+    // A session leader's first terminal becomes its controlling terminal
+    if let Some(terminal) = terminal.filter(|_| !open_flags.contains(vfs_api::OpenFlags::O_NOCTTY)) {
+        scheduler.acquire_tty(pid, terminal);
     }
+    Ok(newfd as i64)
 }
 
-fn sys_close(fd: i32) -> i64 {
+/// ioctl(2), for the job control requests on terminals: taking one as the
+/// controlling terminal or giving it up, and getting or setting its
+/// foreground group and getting its session
+fn sys_ioctl(fd: i32, request: u64, arg: u64) -> SysResult<i64> {
     let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
-        if task.close_fd(fd) {
-            return 0;
+    let task = scheduler.current().ok_or(Errno::ESRCH)?;
+    let (pid, sid) = (task.pid, task.sid);
+    let file = task.get_fd(fd).ok_or(Errno::EBADF)?.file.clone();
+    if file.device() == Some(DeviceId::new(input::MAJOR, input::KBD_MINOR)) {
+        return match request {
+            KBDGSTATE if arg == 0 => Err(Errno::EFAULT),
+            KBDGSTATE => {
+                unsafe { *(arg as *mut KeyboardState) = input::state(); }
+                Ok(0)
+            }
+            _ => Err(Errno::ENOTTY),
+        };
+    }
+    let device = file.terminal().ok_or(Errno::ENOTTY)?;
+    let controlling = scheduler.controlling_tty(sid).filter(|tty| tty.device == device);
+    let ns = scheduler.current_pid_ns();
+
+    match request {
+        TIOCSCTTY if controlling.is_some() || scheduler.acquire_tty(pid, device) => Ok(0),
+        TIOCSCTTY => Err(Errno::EPERM),
+        TIOCNOTTY => {
+            controlling.ok_or(Errno::ENOTTY)?;
+            if sid == pid {
+                scheduler.hangup(device);
+            }
+            Ok(0)
+        }
+        TIOCGPGRP | TIOCGSID => {
+            if arg == 0 {
+                return Err(Errno::EFAULT);
+            }
+            let id = match request {
+                TIOCGPGRP => scheduler.tcgetpgrp(pid, device)?,
+                _ => controlling.ok_or(Errno::ENOTTY)?.sid,
+            };
+            unsafe { *(arg as *mut i32) = scheduler.id_in_ns(ns, id).unwrap_or(0) as i32; }
+            Ok(0)
+        }
+        TIOCSPGRP => {
+            if arg == 0 {
+                return Err(Errno::EFAULT);
+            }
+            let pgid = match unsafe { *(arg as *const i32) } {
+                pgid if pgid < 0 => return Err(Errno::EINVAL),
+                pgid => scheduler.id_from_ns(ns, pgid as Pid).ok_or(Errno::EPERM)?,
+            };
+            scheduler.tcsetpgrp(pid, device, pgid)?;
+            Ok(0)
+        }
+        TIOCGSERCONF | TIOCSSERCONF => {
+            let port = serial::port_of(device).ok_or(Errno::ENOTTY)?;
+            if arg == 0 {
+                return Err(Errno::EFAULT);
+            }
+            if request == TIOCGSERCONF {
+                let config = serial::config(port).ok_or(Errno::ENOTTY)?;
+                unsafe { *(arg as *mut SerialConfig) = config.into(); }
+            } else {
+                let config = unsafe { *(arg as *const SerialConfig) }.to_config().ok_or(Errno::EINVAL)?;
+                serial::configure(port, config)?;
+            }
+            Ok(0)
         }
+        _ => Err(Errno::ENOTTY),
     }
-    -9
 }
 
-fn sys_lseek(fd: i32, offset: i64, whence: i32) -> i64 {
+fn sys_close(fd: i32) -> SysResult<i64> {
     let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
-        if let Some(fd_entry) = task.get_fd_mut(fd) {
-            match whence {
-                0 => fd_entry.offset = offset as u64,  // SEEK_SET
-                1 => fd_entry.offset = (fd_entry.offset as i64 + offset) as u64,  // SEEK_CUR
-                2 => fd_entry.offset = (10000 + offset) as u64,  // SEEK_END (stub file size)
-                _ => return -22,  // EINVAL
-            }
-            return fd_entry.offset as i64;
-        }
+    let task = scheduler.current_mut().ok_or(Errno::EBADF)?;
+    if task.close_fd(fd) {
+        Ok(0)
+    } else {
+        Err(Errno::EBADF)
     }
-    -9  // EBADF
 }
 
-fn sys_getpid() -> i64 {
-    SCHEDULER.lock().current_pid().map_or(-1, |pid| pid as i64)
+/// lseek(2): move the offset of the open file, which descriptors dup'd
+/// from `fd` share
+fn sys_lseek(fd: i32, offset: i64, whence: i32) -> SysResult<i64> {
+    let pid = SCHEDULER.lock().current_pid();
+    Ok(file_of(pid, fd)?.seek(offset, whence)? as i64)
 }
 
-fn sys_getppid() -> i64 {
-    let scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current() {
-        task.ppid.map_or(1, |pid| pid as i64)
-    } else {
-        1
+/// Preallocate space in, or punch a hole in, the file open as `fd`
+fn sys_fallocate(fd: i32, mode: u32, offset: i64, len: i64) -> SysResult<i64> {
+    if offset < 0 || len <= 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mode = vfs_api::FallocateFlags::from_bits(mode).ok_or(Errno::EOPNOTSUPP)?;
+    let pid = SCHEDULER.lock().current_pid();
+    let file = file_of(pid, fd)?;
+    if !vfs_api::OpenFlags::from_bits_truncate(file.flags()).can_write() {
+        return Err(Errno::EBADF);
     }
+    let inode = file.inode().ok_or(Errno::ESPIPE)?;
+    crate::fs::vfs::vfs::VFS.lock().fallocate(inode, mode, offset as u64, len as u64)?;
+    Ok(0)
 }
 
-fn sys_getuid() -> i64 {
+const MAP_SHARED: u64 = 0x01;
+const MAP_PRIVATE: u64 = 0x02;
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+
+/// The caller's pid and the address space it runs in, if any
+fn current_space() -> SysResult<(Pid, Option<Arc<AddressSpace>>)> {
     let scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current() {
-        task.uid as i64
+    let task = scheduler.current().ok_or(Errno::ESRCH)?;
+    Ok((task.pid, task.address_space.clone()))
+}
+
+/// W^X: memory may be writable or executable but not both, unless the
+/// caller opted out with prctl(PR_SET_WX_ALLOWED)
+fn check_wx(prot: crate::hal::memory::ProtectionFlags) -> SysResult<()> {
+    let allowed = SCHEDULER.lock().current().is_some_and(|task| task.allow_wx);
+    match prot.is_writable_executable() && !allowed {
+        true => Err(Errno::EACCES),
+        false => Ok(()),
+    }
+}
+
+/// Map zeroed memory, or a device file. Anonymous mappings go in the
+/// caller's own address space if it runs in one; `fd` and `offset` are
+/// ignored for them, and MAP_SHARED is the same as MAP_PRIVATE as fork
+/// copies no user memory. Only shared mappings of devices that can be
+/// mapped, like /dev/mem and /dev/fb0, are supported. Either is at an
+/// address of the kernel's choosing; `addr` is only a hint and is ignored.
+fn sys_mmap(_addr: u64, len: u64, prot: u64, flags: u64, fd: i32, offset: u64) -> SysResult<i64> {
+    use crate::hal::memory::{vma, ProtectionFlags, PAGE_SIZE};
+
+    if len == 0 || flags & MAP_FIXED != 0 || flags & (MAP_SHARED | MAP_PRIVATE) == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let prot = ProtectionFlags::from_prot(prot);
+    check_wx(prot)?;
+    if flags & MAP_ANONYMOUS != 0 {
+        let (pid, space) = current_space()?;
+        return Ok(vma::map_anonymous(pid, space.as_deref(), len, prot)? as i64);
+    }
+    if !offset.is_multiple_of(PAGE_SIZE as u64) || flags & MAP_SHARED == 0 {
+        return Err(Errno::EINVAL);
+    }
+
+    let (pid, uid, file) = {
+        let scheduler = SCHEDULER.lock();
+        let task = scheduler.current().ok_or(Errno::ESRCH)?;
+        let entry = task.get_fd(fd).ok_or(Errno::EBADF)?;
+        (task.pid, task.uid, entry.file.clone())
+    };
+    let open_flags = file.flags();
+    // Like a write, a writable mapping needs a descriptor open for
+    // writing; every mapping needs it open for reading
+    let open_flags = vfs_api::OpenFlags::from_bits_truncate(open_flags);
+    let mut mode = 0;
+    if prot.contains(ProtectionFlags::READ) || prot.contains(ProtectionFlags::EXECUTE) {
+        mode |= 0o400;
+    }
+    if prot.contains(ProtectionFlags::WRITE) {
+        mode |= 0o200;
+    }
+    if !open_flags.can_read() || (mode & 0o200 != 0 && !open_flags.can_write()) {
+        return Err(Errno::EACCES);
+    }
+
+    if crate::qsf::check_mmap(pid, uid, file.path(), mode) == crate::qsf::AccessDecision::Deny {
+        return Err(Errno::EPERM);
+    }
+
+    let inode = file.inode().ok_or(Errno::ENODEV)?;
+    let (device, phys) = {
+        let vfs = crate::fs::vfs::vfs::VFS.lock();
+        let node = vfs.get_node(inode)?;
+        let device = match &node.data {
+            crate::fs::vfs::VfsNodeData::Device(dev) => *dev,
+            _ => return Err(Errno::ENODEV),
+        };
+        (device, node.mmap(offset, len).map_err(|e| match e {
+            crate::fs::FsError::NotSupported => Errno::ENODEV,
+            e => Errno::from(e),
+        })?)
+    };
+
+    Ok(vma::map_device(pid, phys, len, prot, device)? as i64)
+}
+
+fn sys_munmap(addr: u64, len: u64) -> SysResult<i64> {
+    let (pid, space) = current_space()?;
+    crate::hal::memory::vma::unmap(pid, space.as_deref(), addr, len)?;
+    Ok(0)
+}
+
+/// Change the protection of memory mmap mapped. PROT_NONE keeps ring 3
+/// out; the kernel's own tasks can still reach it. As with mmap, W^X
+/// refuses writable and executable together.
+fn sys_mprotect(addr: u64, len: u64, prot: u64) -> SysResult<i64> {
+    use crate::hal::memory::{vma, ProtectionFlags};
+
+    let prot = ProtectionFlags::from_prot(prot);
+    check_wx(prot)?;
+    let (pid, space) = current_space()?;
+    vma::protect(pid, space.as_deref(), addr, len, prot)?;
+    Ok(0)
+}
+
+/// Move the program break to `addr`, mapping or unmapping the pages
+/// between, and return where it ends up: where it was if `addr` is 0 or
+/// out of range, or memory ran out. Only a program exec'd into an address
+/// space of its own has a break; for other tasks it is always 0.
+fn sys_brk(addr: u64) -> SysResult<i64> {
+    use crate::hal::memory::address_space::USER_MMAP_BASE;
+    use crate::hal::memory::{mmu, ProtectionFlags};
+
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    let Some(space) = task.address_space.clone() else {
+        return Ok(0);
+    };
+    if addr < task.brk_start || addr > USER_MMAP_BASE {
+        return Ok(task.brk as i64);
+    }
+    let (mapped, wanted) = (mmu::page_align_up(task.brk), mmu::page_align_up(addr));
+    let changed = if wanted > mapped {
+        space.map(mapped, wanted - mapped, ProtectionFlags::READ | ProtectionFlags::WRITE)
+    } else if wanted < mapped {
+        space.unmap(wanted, mapped - wanted)
     } else {
-        0
+        Ok(())
+    };
+    if changed.is_ok() {
+        task.brk = addr;
     }
+    Ok(task.brk as i64)
+}
+
+fn sys_getpid() -> SysResult<i64> {
+    SCHEDULER.lock().current().map(|task| task.local_pid() as i64).ok_or(Errno::ESRCH)
 }
 
-fn sys_geteuid() -> i64 {
+/// The parent as numbered in the caller's namespace; 0 if the parent is
+/// outside it, as for the first task of a container
+fn sys_getppid() -> SysResult<i64> {
     let scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current() {
-        task.euid as i64
-    } else {
-        0
+    let ns = scheduler.current_pid_ns();
+    Ok(match scheduler.current().and_then(|task| task.ppid) {
+        Some(ppid) => scheduler.pid_in_ns(ns, ppid).unwrap_or(0) as i64,
+        None => 1,
+    })
+}
+
+/// The global pid of `pid` as the caller numbers it, 0 being the caller
+fn job_target(scheduler: &crate::kernel::scheduler::Scheduler, pid: i32) -> SysResult<Pid> {
+    let current = scheduler.current_pid().ok_or(Errno::ESRCH)?;
+    match pid {
+        0 => Ok(current),
+        pid if pid < 0 => Err(Errno::EINVAL),
+        pid => scheduler.pid_from_ns(scheduler.current_pid_ns(), pid as Pid).ok_or(Errno::ESRCH),
     }
 }
 
-fn sys_getgid() -> i64 {
+fn sys_setpgid(pid: i32, pgid: i32) -> SysResult<i64> {
+    let mut scheduler = SCHEDULER.lock();
+    let caller = scheduler.current_pid().ok_or(Errno::ESRCH)?;
+    let target = job_target(&scheduler, pid)?;
+    let pgid = match pgid {
+        0 => target,
+        pgid if pgid < 0 => return Err(Errno::EINVAL),
+        pgid => scheduler.id_from_ns(scheduler.current_pid_ns(), pgid as Pid).ok_or(Errno::EPERM)?,
+    };
+    scheduler.setpgid(caller, target, pgid)?;
+    Ok(0)
+}
+
+fn sys_getpgid(pid: i32) -> SysResult<i64> {
     let scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current() {
-        task.gid as i64
-    } else {
-        0
+    let pgid = scheduler.getpgid(job_target(&scheduler, pid)?)?;
+    Ok(scheduler.id_in_ns(scheduler.current_pid_ns(), pgid).unwrap_or(0) as i64)
+}
+
+fn sys_setsid() -> SysResult<i64> {
+    let mut scheduler = SCHEDULER.lock();
+    let pid = scheduler.current_pid().ok_or(Errno::ESRCH)?;
+    let sid = scheduler.setsid(pid)?;
+    Ok(scheduler.id_in_ns(scheduler.current_pid_ns(), sid).unwrap_or(0) as i64)
+}
+
+fn sys_getsid(pid: i32) -> SysResult<i64> {
+    let scheduler = SCHEDULER.lock();
+    let sid = scheduler.getsid(job_target(&scheduler, pid)?)?;
+    Ok(scheduler.id_in_ns(scheduler.current_pid_ns(), sid).unwrap_or(0) as i64)
+}
+
+/// prctl(2), for the child subreaper flag: a subreaper adopts the orphans
+/// among its descendants that would otherwise go to init. Opting out of
+/// W^X takes CAP_SYS_ADMIN; opting back in does not.
+fn sys_prctl(option: u64, arg: u64) -> SysResult<i64> {
+    if option == PR_SET_WX_ALLOWED && arg != 0 {
+        require_sys_admin()?;
     }
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    match option {
+        PR_SET_CHILD_SUBREAPER => task.child_subreaper = arg != 0,
+        PR_GET_CHILD_SUBREAPER => {
+            if arg == 0 {
+                return Err(Errno::EFAULT);
+            }
+            unsafe { *(arg as *mut i32) = task.child_subreaper as i32; }
+        }
+        PR_SET_WX_ALLOWED => task.allow_wx = arg != 0,
+        PR_GET_WX_ALLOWED => return Ok(task.allow_wx as i64),
+        _ => return Err(Errno::EINVAL),
+    }
+    Ok(0)
 }
 
-fn sys_getegid() -> i64 {
+/// A descriptor limit as getrlimit reports it: NR_OPEN is no limit at all
+fn nofile_rlim(limit: u32) -> u64 {
+    use crate::kernel::scheduler::task::NR_OPEN;
+    if limit >= NR_OPEN { RLIM_INFINITY } else { limit as u64 }
+}
+
+/// getrlimit(2), of RLIMIT_NOFILE only
+fn sys_getrlimit(resource: u32, rlim: *mut Rlimit) -> SysResult<i64> {
+    if resource != RLIMIT_NOFILE {
+        return Err(Errno::EINVAL);
+    }
+    if rlim.is_null() {
+        return Err(Errno::EFAULT);
+    }
     let scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current() {
-        task.egid as i64
-    } else {
-        0
+    let task = scheduler.current().ok_or(Errno::ESRCH)?;
+    unsafe { *rlim = Rlimit { cur: nofile_rlim(task.nofile), max: nofile_rlim(task.nofile_max) }; }
+    Ok(0)
+}
+
+/// setrlimit(2), of RLIMIT_NOFILE only: the soft limit up to the hard
+/// one, which only root raises, and neither past NR_OPEN, which
+/// RLIM_INFINITY stands for. Descriptors open at or above a lowered limit
+/// stay open.
+fn sys_setrlimit(resource: u32, rlim: *const Rlimit) -> SysResult<i64> {
+    use crate::kernel::scheduler::task::NR_OPEN;
+
+    if resource != RLIMIT_NOFILE {
+        return Err(Errno::EINVAL);
     }
+    if rlim.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    let mut limit = unsafe { *rlim };
+    for value in [&mut limit.cur, &mut limit.max] {
+        if *value == RLIM_INFINITY {
+            *value = NR_OPEN as u64;
+        }
+    }
+    if limit.cur > limit.max {
+        return Err(Errno::EINVAL);
+    }
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    if limit.max > NR_OPEN as u64 || (limit.max > task.nofile_max as u64 && !task.is_root()) {
+        return Err(Errno::EPERM);
+    }
+    (task.nofile, task.nofile_max) = (limit.cur as u32, limit.max as u32);
+    Ok(0)
+}
+
+fn sys_getuid() -> SysResult<i64> {
+    let scheduler = SCHEDULER.lock();
+    Ok(scheduler.current().map_or(0, |task| task.uid as i64))
+}
+
+fn sys_geteuid() -> SysResult<i64> {
+    let scheduler = SCHEDULER.lock();
+    Ok(scheduler.current().map_or(0, |task| task.euid as i64))
+}
+
+fn sys_getgid() -> SysResult<i64> {
+    let scheduler = SCHEDULER.lock();
+    Ok(scheduler.current().map_or(0, |task| task.gid as i64))
 }
 
-fn sys_fork() -> i64 {
+fn sys_getegid() -> SysResult<i64> {
+    let scheduler = SCHEDULER.lock();
+    Ok(scheduler.current().map_or(0, |task| task.egid as i64))
+}
+
+fn sys_fork() -> SysResult<i64> {
+    let _site = AllocSite::enter("fork");
     let mut scheduler = SCHEDULER.lock();
-    
+
     // Get the current task and clone it BEFORE calling allocate_pid
-    let cloned_parent = if let Some(parent_task) = scheduler.current() {
-        parent_task.clone()
-    } else {
-        return -3;  // ESRCH (no such process)
-    };
-    
+    let cloned_parent = scheduler.current().ok_or(Errno::ESRCH)?.clone();
+
     // Now allocate PID (this doesn't conflict with the clone)
-    let child_pid = scheduler.allocate_pid();
-    
+    let child_pid = scheduler.allocate_pid().ok_or(Errno::EAGAIN)?;
+
     // Clone the parent task as child
-    match cloned_parent.fork(child_pid) {
-        Ok(child_task) => {
-            // Add child to scheduler
-            scheduler.add_task(child_task);
-            
-            // Update parent's children list
-            if let Some(parent) = scheduler.current_mut() {
-                parent.children.push(child_pid);
+    let mut child_task = cloned_parent.fork(child_pid).map_err(|_| Errno::ENOMEM)?;
+    scheduler.number_in_namespace(&mut child_task);
+    let local_pid = child_task.pid_in(cloned_parent.pid_ns).unwrap_or(child_pid);
+
+    // Add child to scheduler, which lists it among the parent's children
+    scheduler.add_task(child_task);
+    crate::qsf::QSF.lock().on_fork(cloned_parent.pid, child_pid);
+
+    // Parent returns child PID, as its namespace numbers it
+    Ok(local_pid as i64)
+}
+
+/// vfork(2): the child runs on the parent's stack, and the parent stays
+/// blocked until the child execs or exits. Tasks share the kernel's
+/// address space already, so nothing but the descriptors is copied.
+fn sys_vfork() -> SysResult<i64> {
+    let _site = AllocSite::enter("vfork");
+    let mut scheduler = SCHEDULER.lock();
+    let (parent_pid, pid_ns) = scheduler.current().map(|t| (t.pid, t.pid_ns)).ok_or(Errno::ESRCH)?;
+
+    let child_pid = scheduler.allocate_pid().ok_or(Errno::EAGAIN)?;
+    let parent = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    let mut child_task = parent.vfork(child_pid).map_err(|_| Errno::ENOMEM)?;
+    scheduler.number_in_namespace(&mut child_task);
+    let local_pid = child_task.pid_in(pid_ns).unwrap_or(child_pid);
+    scheduler.add_task(child_task);
+    crate::qsf::QSF.lock().on_fork(parent_pid, child_pid);
+
+    scheduler.block_current();
+    Ok(local_pid as i64)
+}
+
+/// A file action with its path opened, ready to apply
+enum FileAction {
+    Open(i32, FileDescriptor),
+    Close(i32),
+    Dup2(i32, i32),
+}
+
+/// Copy `count` file actions in, opening the files they name. Opening
+/// takes VFS and QSF, so it happens before the child's table is touched.
+fn spawn_actions(pid: Pid, uid: u32, actions: *const SpawnAction, count: usize) -> SysResult<Vec<FileAction>> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+    if actions.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    let actions = unsafe { core::slice::from_raw_parts(actions, count) };
+    actions.iter().map(|action| match action.kind {
+        SPAWN_OPEN => {
+            let path = user_path(action.path)?;
+            if crate::qsf::QSF.lock().check_raw_device(pid, uid, &path).is_err() {
+                return Err(Errno::EPERM);
+            }
+            let flags = vfs_api::OpenFlags::from_bits_truncate(action.flags as u32);
+            let file = vfs_api::open(&path, flags, action.mode as u16)?;
+            let path = crate::fs::vfs::vfs::VFS.lock().resolve_path(&path);
+            let file = OpenFile::open(file.inode, path, action.flags as u32, file.mode.is_dir());
+            Ok(FileAction::Open(action.fd, FileDescriptor::new(action.fd, file, action.flags as u32)))
+        }
+        SPAWN_CLOSE => Ok(FileAction::Close(action.fd)),
+        SPAWN_DUP2 => Ok(FileAction::Dup2(action.source, action.fd)),
+        _ => Err(Errno::EINVAL),
+    }).collect()
+}
+
+/// posix_spawn: start the program at `pathname` in a new child without
+/// copying the caller first, applying `count` file actions to the
+/// descriptors it inherits. The child's pid goes to `pid_out` if given.
+/// Unlike execve, nothing is loaded yet; the child takes the program's name.
+fn sys_posix_spawn(pid_out: *mut i32, pathname: *const u8, actions: *const SpawnAction, count: usize) -> SysResult<i64> {
+    let _site = AllocSite::enter("spawn");
+    let path = user_path(pathname)?;
+    let (pid, uid) = SCHEDULER.lock().current().map(|t| (t.pid, t.uid)).ok_or(Errno::ESRCH)?;
+
+    {
+        let vfs = crate::fs::vfs::vfs::VFS.lock();
+        let node = vfs.lookup_path(&path)?;
+        if node.file_type() != crate::fs::FileType::Regular {
+            return Err(Errno::EACCES);
+        }
+        let mut magic = [0u8; 4];
+        if node.read(0, &mut magic)? != magic.len() || &magic != b"\x7fELF" {
+            return Err(Errno::ENOEXEC);
+        }
+    }
+    if crate::qsf::check_exec(pid, uid, &path) == crate::qsf::AccessDecision::Deny {
+        return Err(Errno::EACCES);
+    }
+    let actions = spawn_actions(pid, uid, actions, count)?;
+
+    let mut scheduler = SCHEDULER.lock();
+    let child_pid = scheduler.allocate_pid().ok_or(Errno::EAGAIN)?;
+    let parent = scheduler.current().ok_or(Errno::ESRCH)?;
+    let pid_ns = parent.pid_ns;
+    let mut child = parent.spawn(child_pid, path.clone()).map_err(|_| Errno::ENOMEM)?;
+    for action in actions {
+        match action {
+            FileAction::Open(fd, entry) => {
+                if !child.fd_in_range(fd) {
+                    return Err(Errno::EBADF);
+                }
+                child.fds.insert(fd, entry);
+            }
+            FileAction::Close(fd) => {
+                child.fds.remove(&fd).ok_or(Errno::EBADF)?;
+            }
+            FileAction::Dup2(source, fd) => {
+                if !child.fd_in_range(fd) {
+                    return Err(Errno::EBADF);
+                }
+                let entry = child.fds.get(&source).ok_or(Errno::EBADF)?.dup(fd);
+                child.fds.insert(fd, entry);
             }
-            
-            // Parent returns child PID
-            child_pid as i64
         }
-        Err(_) => -12,  // ENOMEM
     }
+
+    scheduler.number_in_namespace(&mut child);
+    let local_pid = child.pid_in(pid_ns).unwrap_or(child_pid);
+    scheduler.add_task(child);
+    let mut qsf = crate::qsf::QSF.lock();
+    qsf.on_fork(pid, child_pid);
+    qsf.on_exec(child_pid, uid, &path);
+
+    if !pid_out.is_null() {
+        unsafe { *pid_out = local_pid as i32; }
+    }
+    Ok(0)
 }
 
-fn sys_exit(code: i32) -> i64 {
+fn sys_exit(code: i32) -> SysResult<i64> {
     crate::kernel::scheduler::exit(code);
-    0
+    Ok(0)
 }
 
-fn sys_kill(pid: i32, sig: i32) -> i64 {
-    if crate::kernel::scheduler::kill(pid as Pid, sig as u8) {
-        0
+fn sys_kill(pid: i32, sig: i32) -> SysResult<i64> {
+    let mut scheduler = SCHEDULER.lock();
+    let ns = scheduler.current_pid_ns();
+    let target = scheduler.pid_from_ns(ns, pid as Pid).ok_or(Errno::ESRCH)?;
+    if scheduler.kill(target, sig as u8) {
+        Ok(0)
     } else {
-        -3
+        Err(Errno::ESRCH)
     }
 }
 
-fn sys_getcwd(buf: *mut u8, size: usize) -> i64 {
+fn sys_getcwd(buf: *mut u8, size: usize) -> SysResult<i64> {
     if buf.is_null() || size == 0 {
-        return -14;
+        return Err(Errno::EFAULT);
     }
-    
+
     let vfs = crate::fs::vfs::vfs::VFS.lock();
     let cwd = vfs.get_cwd();
-    
+
     if cwd.len() + 1 > size {
-        return -34;
+        return Err(Errno::ERANGE);
     }
-    
+
     unsafe {
         core::ptr::copy_nonoverlapping(cwd.as_ptr(), buf, cwd.len());
         *buf.add(cwd.len()) = 0;
     }
-    
-    cwd.len() as i64
+
+    Ok(cwd.len() as i64)
 }
 
-fn sys_chdir(pathname: *const u8) -> i64 {
-    if pathname.is_null() {
-        return -14;  // EFAULT
+/// fchdir(2): make the directory open as `fd` the working directory,
+/// wherever it has been moved since it was opened; EACCES unless the
+/// caller may search it
+fn sys_fchdir(fd: i32) -> SysResult<i64> {
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    let inode = task.get_fd(fd).ok_or(Errno::EBADF)?.file.dir().ok_or(Errno::ENOTDIR)?;
+    let mut vfs = crate::fs::vfs::vfs::VFS.lock();
+    if !vfs.get_node(inode)?.permits(task.euid, task.egid, vfs_api::AccessMode::X_OK) {
+        return Err(Errno::EACCES);
     }
-    
+    task.cwd = vfs.set_cwd_inode(inode)?;
+    Ok(0)
+}
+
+/// chdir(2): EACCES unless the caller may search the directory and
+/// every one on the way to it
+fn sys_chdir(pathname: *const u8) -> SysResult<i64> {
+    let path = user_path(pathname)?;
+
     let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
-        // Extract path string
-        let path_bytes = unsafe {
-            let mut bytes = Vec::new();
-            let mut ptr = pathname;
-            while *ptr != 0 && bytes.len() < 256 {
-                bytes.push(*ptr);
-                ptr = ptr.add(1);
-            }
-            bytes
-        };
-        
-        if let Ok(path_str) = core::str::from_utf8(&path_bytes) {
-            task.cwd = path_str.to_string();
-            return 0;
-        }
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    let mut vfs = crate::fs::vfs::vfs::VFS.lock();
+    if !vfs.lookup_path(&path)?.is_dir() {
+        return Err(Errno::ENOTDIR);
     }
-    
-    -3  // ESRCH
+    vfs.access(&path, task.euid, task.egid, vfs_api::AccessMode::X_OK)?;
+    vfs.set_cwd(&path)?;
+    task.cwd = String::from(vfs.get_cwd());
+    Ok(0)
 }
 
-fn sys_mkdir(pathname: *const u8, _mode: u32) -> i64 {
-    if pathname.is_null() {
-        return -14;
+/// The working directory, for the *at() calls
+pub const AT_FDCWD: i32 = -100;
+/// faccessat(2): check with the effective ids
+pub const AT_EACCESS: i32 = 0x200;
+
+/// access(2) and faccessat(2): whether the running task may access
+/// `pathname` as `mode` (R_OK, W_OK, X_OK, or F_OK to ask if it exists)
+/// says. The real ids are checked, so a setuid program learns what the
+/// user who ran it may do, unless AT_EACCESS asks for the effective ones.
+/// A relative path starts at the directory open as `dirfd`, or at the
+/// working directory for AT_FDCWD. QSF gets the last word.
+fn sys_faccessat(dirfd: i32, pathname: *const u8, mode: u32, flags: i32) -> SysResult<i64> {
+    let path = user_path(pathname)?;
+    let mode = vfs_api::AccessMode::from_bits(mode).ok_or(Errno::EINVAL)?;
+    if flags & !AT_EACCESS != 0 {
+        return Err(Errno::EINVAL);
     }
-    // Extract path
-    let path_vec = unsafe {
-        let mut bytes = Vec::new();
-        let mut ptr = pathname;
-        while *ptr != 0 {
-            bytes.push(*ptr);
-            ptr = ptr.add(1);
-            if bytes.len() > 4096 { break; }
-        }
-        bytes
+
+    let (pid, uid, gid, dir) = {
+        let scheduler = SCHEDULER.lock();
+        let task = scheduler.current().ok_or(Errno::ESRCH)?;
+        let (uid, gid) = match flags & AT_EACCESS {
+            0 => (task.uid, task.gid),
+            _ => (task.euid, task.egid),
+        };
+        let dir = match dirfd {
+            _ if path.starts_with('/') => None,
+            AT_FDCWD => None,
+            fd => Some(task.get_fd(fd).ok_or(Errno::EBADF)?.file.dir().ok_or(Errno::ENOTDIR)?),
+        };
+        (task.pid, uid, gid, dir)
     };
 
-    let path = match core::str::from_utf8(&path_vec) {
-        Ok(s) => s,
-        Err(_) => return -14,
+    let path = {
+        let vfs = crate::fs::vfs::vfs::VFS.lock();
+        let path = match dir {
+            Some(inode) => alloc::format!("{}/{}", vfs.view_of(inode)?, path),
+            None => path,
+        };
+        vfs.access(&path, uid, gid, mode)?;
+        path
     };
+    // The rule modes are permission bits, the owner's place holding them
+    match crate::qsf::check_access(pid, uid, &path, mode.bits() << 6) {
+        crate::qsf::AccessDecision::Deny => Err(Errno::EACCES),
+        _ => Ok(0),
+    }
+}
 
-    match crate::fs::vfs::api::mkdir(path, _mode as u16) {
-        Ok(()) => 0,
-        Err(e) => fs_error_to_errno(e),
+/// readlink(2): copy the target of the symlink `pathname` into `buf`,
+/// cut to `bufsiz` bytes and without a NUL, returning how many it took
+fn sys_readlink(pathname: *const u8, buf: *mut u8, bufsiz: i64) -> SysResult<i64> {
+    let path = user_path(pathname)?;
+    if bufsiz <= 0 {
+        return Err(Errno::EINVAL);
     }
+    if buf.is_null() {
+        return Err(Errno::EFAULT);
+    }
+
+    let target = crate::fs::vfs::vfs::VFS.lock().read_symlink(&path)?;
+    let len = target.len().min(bufsiz as usize);
+    unsafe {
+        core::ptr::copy_nonoverlapping(target.as_ptr(), buf, len);
+    }
+    Ok(len as i64)
 }
 
-fn sys_rmdir(pathname: *const u8) -> i64 {
-    if pathname.is_null() {
-        return -14;
+/// chroot(2). The VFS has a single root, like its single working
+/// directory; the task's copy is what a container restores on entry.
+fn sys_chroot(pathname: *const u8) -> SysResult<i64> {
+    let path = user_path(pathname)?;
+
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    if !crate::qsf::QSF.lock().check_process_capability(task.pid, task.euid, crate::qsf::Capability::CapSysChroot) {
+        return Err(Errno::EPERM);
     }
-    let path_vec = unsafe {
-        let mut bytes = Vec::new();
-        let mut ptr = pathname;
-        while *ptr != 0 {
-            bytes.push(*ptr);
-            ptr = ptr.add(1);
-            if bytes.len() > 4096 { break; }
-        }
-        bytes
-    };
+    task.root = crate::fs::vfs::vfs::VFS.lock().set_root(&path)?;
+    Ok(0)
+}
+
+/// mount(2) of the ext4 or FAT32 filesystem on the block device `source`
+/// on the directory `target`, in the caller's mount namespace, or of a
+/// new tmpfs, which `data` may give options for (see fs::tmpfs). A null
+/// or "auto" type tries each the block layer knows. `flags` are the MS_
+/// ones of fs::mount; remounting, binding and moving are not supported.
+fn sys_mount(source: *const u8, target: *const u8, filesystemtype: *const u8, flags: u64, data: *const u8) -> SysResult<i64> {
+    use crate::fs::mount::MountFlags;
 
-    let path = match core::str::from_utf8(&path_vec) {
-        Ok(s) => s,
-        Err(_) => return -14,
+    require_sys_admin()?;
+    let (source, target) = (user_path(source)?, user_path(target)?);
+    let fs_type = match filesystemtype.is_null() {
+        true => None,
+        false => Some(user_path(filesystemtype)?).filter(|t| t != "auto"),
     };
+    let tmpfs = fs_type.as_deref() == Some("tmpfs");
+    if !tmpfs && fs_type.as_deref().is_some_and(|t| !crate::hal::block::FS_TYPES.contains(&t)) {
+        return Err(Errno::ENODEV);
+    }
+    let flags = u32::try_from(flags).ok().and_then(MountFlags::from_bits).ok_or(Errno::EINVAL)?;
+    if flags.intersects(MountFlags::REMOUNT | MountFlags::BIND | MountFlags::MOVE) {
+        return Err(Errno::EINVAL);
+    }
+    if tmpfs {
+        let options = match data.is_null() {
+            true => crate::fs::tmpfs::Options::default(),
+            false => crate::fs::tmpfs::Options::parse(&user_path(data)?)?,
+        };
+        crate::fs::tmpfs::mount(&source, &target, options, flags)?;
+        return Ok(0);
+    }
+    crate::hal::block::mount(&source, &target, fs_type.as_deref(), flags)?;
+    Ok(0)
+}
 
-    match crate::fs::vfs::api::rmdir(path) {
-        Ok(()) => 0,
-        Err(e) => fs_error_to_errno(e),
+/// umount2(2): undo a mount on `target`. Neither MNT_FORCE nor
+/// MNT_DETACH is supported, so a busy mount stays.
+fn sys_umount2(target: *const u8, flags: u64) -> SysResult<i64> {
+    require_sys_admin()?;
+    let target = user_path(target)?;
+    if flags != 0 {
+        return Err(Errno::EINVAL);
     }
+    crate::hal::block::umount(&target)?;
+    Ok(0)
 }
 
-fn sys_unlink(pathname: *const u8) -> i64 {
-    if pathname.is_null() {
-        return -14;
+/// EPERM unless the caller has CAP_SYS_ADMIN
+fn require_sys_admin() -> SysResult<()> {
+    let (pid, euid) = SCHEDULER.lock().current().map(|t| (t.pid, t.euid)).ok_or(Errno::ESRCH)?;
+    match crate::qsf::QSF.lock().check_process_capability(pid, euid, crate::qsf::Capability::CapSysAdmin) {
+        true => Ok(()),
+        false => Err(Errno::EPERM),
     }
-    let path_vec = unsafe {
-        let mut bytes = Vec::new();
-        let mut ptr = pathname;
-        while *ptr != 0 {
-            bytes.push(*ptr);
-            ptr = ptr.add(1);
-            if bytes.len() > 4096 { break; }
-        }
-        bytes
-    };
+}
 
-    let path = match core::str::from_utf8(&path_vec) {
-        Ok(s) => s,
-        Err(_) => return -14,
-    };
+/// unshare(2) for CLONE_NEWNS, which gives the caller a private copy of
+/// its mount table, and CLONE_NEWPID, which puts its later children in a
+/// new PID namespace where the first of them is pid 1
+fn sys_unshare(flags: u64) -> SysResult<i64> {
+    if flags & !(CLONE_NEWNS | CLONE_NEWPID) != 0 {
+        return Err(Errno::EINVAL);
+    }
 
-    match crate::fs::vfs::api::unlink(path) {
-        Ok(()) => 0,
-        Err(e) => fs_error_to_errno(e),
+    let mut scheduler = SCHEDULER.lock();
+    let (pid, euid, pid_ns) = scheduler.current().map(|t| (t.pid, t.euid, t.pid_ns)).ok_or(Errno::ESRCH)?;
+    if !crate::qsf::QSF.lock().check_process_capability(pid, euid, crate::qsf::Capability::CapSysAdmin) {
+        return Err(Errno::EPERM);
     }
+
+    let child_pid_ns = if flags & CLONE_NEWPID != 0 {
+        Some(scheduler.pid_namespaces.create(pid_ns).ok_or(Errno::ENOSPC)?)
+    } else {
+        None
+    };
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    if child_pid_ns.is_some() {
+        task.child_pid_ns = child_pid_ns;
+    }
+    if flags & CLONE_NEWNS != 0 {
+        task.mnt_ns = crate::fs::mount::clone_namespace(task.mnt_ns);
+        crate::fs::mount::set_namespace(task.mnt_ns);
+    }
+    scheduler.release_namespaces();
+    Ok(0)
 }
 
-fn sys_execve(pathname: *const u8, argv: *const *const u8, envp: *const *const u8) -> i64 {
-    if pathname.is_null() {
-        return -14;  // EFAULT
+fn sys_mkdir(pathname: *const u8, mode: u32) -> SysResult<i64> {
+    let path = user_path(pathname)?;
+    crate::fs::vfs::api::mkdir(&path, mode as u16)?;
+    Ok(0)
+}
+
+fn sys_rmdir(pathname: *const u8) -> SysResult<i64> {
+    let path = user_path(pathname)?;
+    crate::fs::vfs::api::rmdir(&path)?;
+    Ok(0)
+}
+
+fn sys_unlink(pathname: *const u8) -> SysResult<i64> {
+    let path = user_path(pathname)?;
+    crate::fs::vfs::api::unlink(&path)?;
+    Ok(0)
+}
+
+/// Copy a null-terminated array of C strings, argv or envp, from the
+/// caller; a null array is empty. Together they may take ARG_MAX bytes.
+fn user_strings(ptr: *const *const u8) -> SysResult<Vec<String>> {
+    let mut strings = Vec::new();
+    if ptr.is_null() {
+        return Ok(strings);
     }
-    
-    // Extract program name from pathname
-    let prog_name_vec = unsafe {
+    let mut total = 0;
+    loop {
+        let s = unsafe { *ptr.add(strings.len()) };
+        if s.is_null() {
+            return Ok(strings);
+        }
         let mut bytes = Vec::new();
-        let mut ptr = pathname as *const u8;
-        while *ptr != 0 {
-            bytes.push(*ptr);
-            ptr = ptr.add(1);
+        unsafe {
+            while *s.add(bytes.len()) != 0 {
+                bytes.push(*s.add(bytes.len()));
+                if total + bytes.len() > crate::kernel::exec::ARG_MAX {
+                    return Err(Errno::E2BIG);
+                }
+            }
         }
-        bytes
+        total += bytes.len() + 1 + core::mem::size_of::<usize>();
+        strings.push(String::from_utf8(bytes).map_err(|_| Errno::EINVAL)?);
+    }
+}
+
+/// execve: replace the caller's program by the ELF executable at
+/// `pathname`, run in ring 3 in an address space of its own. From a
+/// program already in ring 3 this does not return. Called from the
+/// kernel, the program runs until it exits, its status becoming the
+/// task's exit code, and then the call returns 0.
+fn sys_execve(pathname: *const u8, argv: *const *const u8, envp: *const *const u8) -> SysResult<i64> {
+    let _site = AllocSite::enter("exec");
+    let prog_name = user_path(pathname)?;
+    let args = user_strings(argv)?;
+    let env = user_strings(envp)?;
+
+    let data = {
+        let vfs = crate::fs::vfs::vfs::VFS.lock();
+        let node = vfs.lookup_path(&prog_name)?;
+        if node.file_type() != crate::fs::FileType::Regular {
+            return Err(Errno::EACCES);
+        }
+        let mut data = alloc::vec![0; node.size as usize];
+        let len = node.read(0, &mut data)?;
+        data.truncate(len);
+        data
     };
-    
-    if prog_name_vec.is_empty() {
-        return -2;  // ENOENT
-    }
-    
-    let prog_name = String::from_utf8_lossy(&prog_name_vec).to_string();
-    
-    // Update current task's name and entry point
-    let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
+    let (pid, uid, allow_wx) = SCHEDULER.lock().current().map(|t| (t.pid, t.uid, t.allow_wx)).ok_or(Errno::ESRCH)?;
+    if crate::qsf::check_exec(pid, uid, &prog_name) == crate::qsf::AccessDecision::Deny {
+        return Err(Errno::EACCES);
+    }
+    let image = crate::kernel::exec::load(&data, &args, &env, allow_wx)?;
+    drop((data, env));
+
+    // Past here exec cannot fail
+    let start = {
+        let mut scheduler = SCHEDULER.lock();
+        let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+        crate::qsf::QSF.lock().on_exec(task.pid, task.uid, &prog_name);
         task.name = prog_name;
-        // In a real implementation, we'd load the ELF binary, set up memory, and jump to entry point
-        // For now, this is a stub
-        return 0;
+        task.cmdline = args;
+        task.did_exec = true;
+        task.context = Context::new_user(image.entry as usize, image.stack as usize);
+        task.user_stack = image.stack as usize;
+        task.entry_point = image.entry as usize;
+        let start = usermode::Start {
+            pml4: image.space.frame(),
+            entry: image.entry,
+            stack: image.stack,
+            kernel_stack: task.kernel_stack.get_or_insert_with(KernelStack::new).top() as u64,
+        };
+        // What the old program mapped goes with it
+        crate::hal::memory::vma::release(task.pid);
+        task.address_space = Some(Arc::new(image.space));
+        (task.brk_start, task.brk) = (image.brk, image.brk);
+        scheduler.release_vfork(pid);
+        start
+    };
+
+    if usermode::active() {
+        usermode::replace(start);
+    } else {
+        usermode::enter(start);
     }
-    
-    -3  // ESRCH
+    Ok(0)
 }
 
-fn sys_wait4(pid: i32, status: *mut i32, flags: i32, _rusage: *const u8) -> i64 {
+fn sys_wait4(pid: i32, status: *mut i32, _flags: i32, _rusage: *const u8) -> SysResult<i64> {
     let mut scheduler = SCHEDULER.lock();
-    
+
+    let ns = scheduler.current_pid_ns();
     let target_pid = if pid == -1 {
-        // Wait for any child
-        if let Some(task) = scheduler.current() {
-            task.children.first().copied()
-        } else {
-            None
-        }
+        // Wait for any child, one already dead first
+        let dead = |pid: &Pid| scheduler.get_task(*pid)
+            .is_some_and(|child| child.state == crate::kernel::scheduler::task::TaskState::Zombie);
+        scheduler.current().and_then(|task| {
+            task.children.iter().copied().find(dead).or(task.children.first().copied())
+        })
     } else if pid > 0 {
-        Some(pid as Pid)
+        scheduler.pid_from_ns(ns, pid as Pid)
     } else {
-        return -22;  // EINVAL
+        return Err(Errno::EINVAL);
     };
-    
-    if let Some(tpid) = target_pid {
-        // Check if child exists and is a zombie
-        if let Some(child) = scheduler.get_task(tpid) {
-            if child.state == crate::kernel::scheduler::task::TaskState::Zombie {
-                let exit_code = child.exit_code.unwrap_or(0);
-                
-                // Store exit status if pointer provided
-                if !status.is_null() {
-                    unsafe {
-                        *status = exit_code;
-                    }
-                }
-                
-                // Remove zombie task
-                scheduler.tasks.retain(|t| t.pid != tpid);
-                
-                return tpid as i64;
-            }
-        }
-    }
-    
-    -10  // ECHILD (no child process)
-}
 
-fn sys_stat(_pathname: *const u8, _stat_buf: *mut u8) -> i64 {
-    if _pathname.is_null() || _stat_buf.is_null() {
-        return -14;
-    }
+    let tpid = target_pid.ok_or(Errno::ECHILD)?;
+    let child = scheduler.get_task(tpid).ok_or(Errno::ECHILD)?;
 
-    // extract path
-    let path_vec = unsafe {
-        let mut bytes = Vec::new();
-        let mut ptr = _pathname;
-        while *ptr != 0 {
-            bytes.push(*ptr);
-            ptr = ptr.add(1);
-            if bytes.len() > 4096 { break; }
-        }
-        bytes
-    };
+    // Only zombies can be reaped
+    if child.state != crate::kernel::scheduler::task::TaskState::Zombie {
+        return Err(Errno::ECHILD);
+    }
 
-    let path = match core::str::from_utf8(&path_vec) {
-        Ok(s) => s,
-        Err(_) => return -14,
-    };
+    let exit_code = child.exit_code.unwrap_or(0);
 
-    match crate::kernel::sys::posix::posix_stat(path) {
-        Ok(posix_stat) => {
-            // copy PosixStat bytes into buffer (caller expects struct)
-            let src = &posix_stat as *const crate::kernel::sys::posix::PosixStat as *const u8;
-            let size = core::mem::size_of::<crate::kernel::sys::posix::PosixStat>();
-            unsafe { core::ptr::copy_nonoverlapping(src, _stat_buf, size); }
-            0
+    // Store exit status if pointer provided
+    if !status.is_null() {
+        unsafe {
+            *status = exit_code;
         }
-        Err(e) => fs_error_to_errno(e),
     }
+
+    let local_pid = child.pid_in(ns).unwrap_or(tpid);
+
+    // Remove zombie task, and with it the parent's record of it
+    scheduler.remove_zombie(tpid);
+
+    Ok(local_pid as i64)
+}
+
+fn copy_stat_out(stat: &crate::kernel::sys::posix::PosixStat, stat_buf: *mut u8) {
+    let src = stat as *const crate::kernel::sys::posix::PosixStat as *const u8;
+    let size = core::mem::size_of::<crate::kernel::sys::posix::PosixStat>();
+    unsafe { core::ptr::copy_nonoverlapping(src, stat_buf, size); }
 }
 
-fn sys_fstat(_fd: i32, _stat_buf: *mut u8) -> i64 {
-    if _stat_buf.is_null() {
-        return -14;
+fn sys_stat(pathname: *const u8, stat_buf: *mut u8) -> SysResult<i64> {
+    if stat_buf.is_null() {
+        return Err(Errno::EFAULT);
     }
+    let path = user_path(pathname)?;
+    crate::kernel::procfs::refresh_for(&path);
 
-    let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
-        if let Some(fd_entry) = task.get_fd(_fd) {
-            match crate::fs::vfs::api::stat(&fd_entry.path) {
-                Ok(stat) => {
-                    let pos = crate::kernel::sys::posix::PosixStat::from(stat);
-                    let src = &pos as *const crate::kernel::sys::posix::PosixStat as *const u8;
-                    let size = core::mem::size_of::<crate::kernel::sys::posix::PosixStat>();
-                    unsafe { core::ptr::copy_nonoverlapping(src, _stat_buf, size); }
-                    return 0;
-                }
-                Err(e) => return fs_error_to_errno(e),
-            }
-        }
+    // copy PosixStat bytes into buffer (caller expects struct)
+    let posix_stat = crate::kernel::sys::posix::posix_stat(&path)?;
+    copy_stat_out(&posix_stat, stat_buf);
+    Ok(0)
+}
+
+fn sys_fstat(fd: i32, stat_buf: *mut u8) -> SysResult<i64> {
+    if stat_buf.is_null() {
+        return Err(Errno::EFAULT);
     }
 
-    -9
+    let pid = SCHEDULER.lock().current_pid();
+    copy_stat_out(&file_of(pid, fd)?.stat()?, stat_buf);
+    Ok(0)
 }
 
-fn sys_chmod(_pathname: *const u8, _mode: u32) -> i64 {
-    -38
+fn sys_chmod(_pathname: *const u8, _mode: u32) -> SysResult<i64> {
+    Err(Errno::ENOSYS)
 }
 
-fn sys_fchmod(_fd: i32, _mode: u32) -> i64 {
-    -38
+fn sys_fchmod(_fd: i32, _mode: u32) -> SysResult<i64> {
+    Err(Errno::ENOSYS)
 }
 
-fn sys_chown(_pathname: *const u8, _uid: u32, _gid: u32) -> i64 {
-    -38
+fn sys_chown(_pathname: *const u8, _uid: u32, _gid: u32) -> SysResult<i64> {
+    Err(Errno::ENOSYS)
 }
 
-fn sys_fchown(_fd: i32, _uid: u32, _gid: u32) -> i64 {
-    -38
+fn sys_fchown(_fd: i32, _uid: u32, _gid: u32) -> SysResult<i64> {
+    Err(Errno::ENOSYS)
 }
 
-fn sys_umask(mask: u32) -> i64 {
+fn sys_umask(mask: u32) -> SysResult<i64> {
     let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
-        let old_mask = task.umask;
-        task.umask = mask;
-        old_mask as i64
-    } else {
-        -3
-    }
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    let old_mask = task.umask;
+    task.umask = mask;
+    Ok(old_mask as i64)
 }
 
-fn sys_pipe(_pipefd: *mut i32) -> i64 {
-    -38
+/// pipe(2): a new pipe, its read end at `pipefd[0]` and its write end at
+/// `pipefd[1]`
+fn sys_pipe(pipefd: *mut i32) -> SysResult<i64> {
+    if pipefd.is_null() {
+        return Err(Errno::EFAULT);
+    }
+    let (reader, writer) = crate::kernel::pipe::pipe(crate::kernel::pipe::PIPE_SIZE);
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler.current_mut().ok_or(Errno::ESRCH)?;
+    let mut fds = [0; 2];
+    for (i, (end, flags)) in [(reader, vfs_api::OpenFlags::O_RDONLY), (writer, vfs_api::OpenFlags::O_WRONLY)].into_iter().enumerate() {
+        let Some(fd) = task.allocate_fd() else {
+            // Not half a pipe: give back the read end if it got one
+            if i > 0 {
+                task.fds.remove(&fds[0]);
+            }
+            return Err(Errno::EMFILE);
+        };
+        task.fds.insert(fd, FileDescriptor::new(fd, OpenFile::pipe(end, flags.bits()), flags.bits()));
+        fds[i] = fd;
+    }
+    unsafe { core::ptr::copy_nonoverlapping(fds.as_ptr(), pipefd, 2) };
+    Ok(0)
 }
 
-fn sys_dup(oldfd: i32) -> i64 {
+/// dup(2): a new descriptor on the open file `oldfd` refers to, sharing
+/// its offset and status flags, but not close-on-exec
+fn sys_dup(oldfd: i32) -> SysResult<i64> {
     let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
-        if let Some(fd) = task.get_fd(oldfd) {
-            let descriptor = fd.clone();
-            let newfd = task.allocate_fd();
-            task.fds.insert(newfd, descriptor);
-            return newfd as i64;
-        }
-    }
-    -9  // EBADF
+    let task = scheduler.current_mut().ok_or(Errno::EBADF)?;
+    let file = task.get_fd(oldfd).ok_or(Errno::EBADF)?.file.clone();
+    let newfd = task.allocate_fd().ok_or(Errno::EMFILE)?;
+    task.fds.insert(newfd, FileDescriptor::new(newfd, file, 0));
+    Ok(newfd as i64)
 }
 
-fn sys_dup2(oldfd: i32, newfd: i32) -> i64 {
+/// dup2(2): like dup, onto `newfd`, closing what it was first. `newfd`
+/// must be below the NOFILE limit.
+fn sys_dup2(oldfd: i32, newfd: i32) -> SysResult<i64> {
     let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.current_mut() {
-        if let Some(fd) = task.get_fd(oldfd) {
-            let descriptor = fd.clone();
-            task.fds.insert(newfd, descriptor);
-            return newfd as i64;
-        }
+    let task = scheduler.current_mut().ok_or(Errno::EBADF)?;
+    if !task.fd_in_range(newfd) {
+        return Err(Errno::EBADF);
     }
-    -9  // EBADF
-}
-
-pub const EPERM: i32 = 1;
-pub const ENOENT: i32 = 2;
-pub const ESRCH: i32 = 3;
-pub const EINTR: i32 = 4;
-pub const EIO: i32 = 5;
-pub const ENXIO: i32 = 6;
-pub const E2BIG: i32 = 7;
-pub const ENOEXEC: i32 = 8;
-pub const EBADF: i32 = 9;
-pub const ECHILD: i32 = 10;
-pub const EAGAIN: i32 = 11;
-pub const ENOMEM: i32 = 12;
-pub const EACCES: i32 = 13;
-pub const EFAULT: i32 = 14;
-pub const EBUSY: i32 = 16;
-pub const EEXIST: i32 = 17;
-pub const EXDEV: i32 = 18;
-pub const ENODEV: i32 = 19;
-pub const ENOTDIR: i32 = 20;
-pub const EISDIR: i32 = 21;
-pub const EINVAL: i32 = 22;
-pub const ENFILE: i32 = 23;
-pub const EMFILE: i32 = 24;
-pub const ENOTTY: i32 = 25;
-pub const ETXTBSY: i32 = 26;
-pub const EFBIG: i32 = 27;
-pub const ENOSPC: i32 = 28;
-pub const ESPIPE: i32 = 29;
-pub const EROFS: i32 = 30;
-pub const EMLINK: i32 = 31;
-pub const EPIPE: i32 = 32;
-pub const ERANGE: i32 = 34;
-pub const ENOSYS: i32 = 38;
-pub const ENOTEMPTY: i32 = 39;
-pub const ELOOP: i32 = 40;
-fn fs_error_to_errno(e: FsError) -> i64 {
-    match e {
-        FsError::NotFound => -2,
-        FsError::PermissionDenied => -13,
-        FsError::AlreadyExists => -17,
-        FsError::NotDirectory => -20,
-        FsError::IsDirectory => -21,
-        FsError::NotEmpty => -39,
-        FsError::InvalidPath => -22,
-        FsError::InvalidArgument => -22,
-        FsError::IoError => -5,
-        FsError::NoSpace => -28,
-        FsError::ReadOnly => -30,
-        FsError::TooManyLinks => -31,
-        FsError::NameTooLong => -36,
-        _ => -38,
+    let descriptor = task.get_fd(oldfd).ok_or(Errno::EBADF)?;
+    if oldfd != newfd {
+        let descriptor = descriptor.dup(newfd);
+        task.fds.insert(newfd, descriptor);
+    }
+    Ok(newfd as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::FileMode;
+    use crate::qsf::{QSF, SecurityLevel};
+
+    #[test_case]
+    fn test_open_denied_by_policy() {
+        crate::fs::vfs::VFS.lock().create_file("/qsf-open-test", FileMode::new(0o644)).unwrap();
+        let policy = crate::qsf::policies::parse_policy("policy open-test 1.0\nrule deny any file:/qsf-open-test r\n").unwrap();
+        let level = {
+            let mut qsf = QSF.lock();
+            qsf.load_policy(policy).unwrap();
+            let level = qsf.get_level();
+            qsf.set_level(SecurityLevel::Enforcing).unwrap();
+            level
+        };
+
+        let path = b"/qsf-open-test\0".as_ptr();
+        let denied = sys_open(path, vfs_api::OpenFlags::O_RDONLY.bits() as i32, 0);
+        let written = sys_open(path, vfs_api::OpenFlags::O_WRONLY.bits() as i32, 0);
+
+        let mut qsf = QSF.lock();
+        qsf.set_level(level).unwrap();
+        qsf.policy_mut("open-test").unwrap().disable();
+        assert_eq!(denied, Err(Errno::EACCES));
+        // Write access is not what the rule is about
+        assert_ne!(written, Err(Errno::EACCES));
     }
 }
@@ -0,0 +1,470 @@
+// Kernel updates on the boot partition (sysupdate)
+//
+// With `bootpart=DEVICE` on the command line, DEVICE is a FAT32 volume
+// with two kernel slots, KERNEL.A and KERNEL.B, each a kernel ELF as
+// kexec takes it, and QUNIX.STA, a state file saying which is which:
+//
+//   active B
+//   fallback A
+//   status pending
+//   digest A 3b9f0c...
+//   digest B 9f86d0...
+//
+// The kernel in the boot image is then only a first stage. Early in boot
+// it reads the state and starts the active slot with kexec, once its
+// SHA-256 matches the digest recorded for it; a kernel that pstore says
+// was started by kexec carries on booting instead. The status is how an
+// update is tried once and taken back if it does not come up:
+//
+//   pending   installed; the next boot marks it trying and starts it
+//   trying    started but never reached the shell, so the boot after
+//             swaps active and fallback, marks it failed and starts the
+//             previous kernel
+//   good      reached the shell, which is the update confirming itself
+//   failed    rolled back
+//
+// `sysupdate IMAGE` installs a kernel into the slot that is not active,
+// keeping the active one as the fallback, and marks it pending. IMAGE is
+// signed as a qpkg manifest is: IMAGE.sig beside it holds `KEY MAC`, the
+// HMAC-SHA256 in hex of the image under the key in
+// /etc/sysupdate/keys/KEY. Installs, refused images and rollbacks go in
+// the QSF audit log.
+//
+// The volume is written through a FAT32 mount of its own, not the VFS,
+// so it should not be mounted elsewhere while an update goes in.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use crate::fs::fat32::Fat32Filesystem;
+use crate::fs::vfs::api as vfs_api;
+use crate::fs::vfs::node::{Filesystem, InodeNumber};
+use crate::fs::{FileMode, FsError};
+use crate::hal::block;
+use crate::kernel::kexec::{self, KexecError};
+use crate::kernel::pstore::{self, Reason};
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::crypto;
+use crate::qsf::{AccessDecision, QSF};
+
+pub const PARAM: &str = "bootpart";
+pub const KEY_DIR: &str = "/etc/sysupdate/keys";
+pub const STATE_FILE: &str = "QUNIX.STA";
+/// Where a new state is written before it is renamed over the old one
+const STATE_NEW: &str = "QUNIX.NEW";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SysupdateError {
+    /// No `bootpart=`, or one naming no block device
+    NoBootPartition,
+    /// Neither a state file nor a kernel in slot A
+    NoKernel,
+    /// A state file or signature that does not parse
+    Malformed(&'static str),
+    UnknownKey(String),
+    BadSignature,
+    /// Not a kernel ELF kexec could start
+    NotKernel,
+    /// A slot whose contents do not match the digest recorded for it
+    Corrupt(Slot),
+    Kexec(KexecError),
+    Fs(FsError),
+}
+
+impl From<FsError> for SysupdateError {
+    fn from(e: FsError) -> Self {
+        SysupdateError::Fs(e)
+    }
+}
+
+impl fmt::Display for SysupdateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SysupdateError::NoBootPartition => write!(f, "no boot partition (set {}=DEVICE)", PARAM),
+            SysupdateError::NoKernel => write!(f, "no kernel installed"),
+            SysupdateError::Malformed(what) => write!(f, "malformed {}", what),
+            SysupdateError::UnknownKey(key) => write!(f, "signed with unknown key {}", key),
+            SysupdateError::BadSignature => write!(f, "bad signature"),
+            SysupdateError::NotKernel => write!(f, "not a kernel image"),
+            SysupdateError::Corrupt(slot) => write!(f, "{}: does not match its digest", slot.file()),
+            SysupdateError::Kexec(e) => write!(f, "cannot load: {:?}", e),
+            SysupdateError::Fs(e) => write!(f, "{:?}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn file(self) -> &'static str {
+        match self {
+            Slot::A => "KERNEL.A",
+            Slot::B => "KERNEL.B",
+        }
+    }
+
+    pub fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn parse(s: &str) -> Option<Slot> {
+        match s {
+            "A" => Some(Slot::A),
+            "B" => Some(Slot::B),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Slot::A => "A",
+            Slot::B => "B",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pending,
+    Trying,
+    Good,
+    Failed,
+}
+
+impl Status {
+    fn parse(s: &str) -> Option<Status> {
+        match s {
+            "pending" => Some(Status::Pending),
+            "trying" => Some(Status::Trying),
+            "good" => Some(Status::Good),
+            "failed" => Some(Status::Failed),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Pending => "pending",
+            Status::Trying => "trying",
+            Status::Good => "good",
+            Status::Failed => "failed",
+        })
+    }
+}
+
+/// What QUNIX.STA holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    pub active: Slot,
+    pub fallback: Option<Slot>,
+    pub status: Status,
+    /// SHA-256 of each slot's kernel, by slot
+    pub digests: [Option<[u8; 32]>; 2],
+}
+
+impl State {
+    pub fn digest(&self, slot: Slot) -> Option<&[u8; 32]> {
+        self.digests[slot.index()].as_ref()
+    }
+
+    /// Start the fallback in place of the active kernel
+    fn roll_back(&mut self) -> Option<Slot> {
+        let fallback = self.fallback?;
+        self.fallback = Some(self.active);
+        self.active = fallback;
+        self.status = Status::Failed;
+        Some(fallback)
+    }
+}
+
+pub fn parse_state(text: &str) -> Result<State, SysupdateError> {
+    let (mut active, mut fallback, mut status) = (None, None, None);
+    let mut digests = [None; 2];
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[..] {
+            ["active", slot] => active = Slot::parse(slot),
+            ["fallback", slot] => fallback = Some(Slot::parse(slot).ok_or(SysupdateError::Malformed(STATE_FILE))?),
+            ["status", value] => status = Status::parse(value),
+            ["digest", slot, hex] => {
+                let slot = Slot::parse(slot).ok_or(SysupdateError::Malformed(STATE_FILE))?;
+                let digest = crypto::from_hex(hex).and_then(|bytes| <[u8; 32]>::try_from(bytes).ok());
+                digests[slot.index()] = Some(digest.ok_or(SysupdateError::Malformed(STATE_FILE))?);
+            }
+            _ => return Err(SysupdateError::Malformed(STATE_FILE)),
+        }
+    }
+    match (active, status) {
+        (Some(active), Some(status)) if fallback != Some(active) => Ok(State { active, fallback, status, digests }),
+        _ => Err(SysupdateError::Malformed(STATE_FILE)),
+    }
+}
+
+pub fn format_state(state: &State) -> String {
+    let mut text = format!("active {}\n", state.active);
+    if let Some(fallback) = state.fallback {
+        text += &format!("fallback {}\n", fallback);
+    }
+    text += &format!("status {}\n", state.status);
+    for slot in [Slot::A, Slot::B] {
+        if let Some(digest) = state.digest(slot) {
+            text += &format!("digest {} {}\n", slot, crypto::to_hex(digest));
+        }
+    }
+    text
+}
+
+/// The key named in a `KEY MAC` signature, once the MAC of `image` under
+/// it checks out
+fn check_signature(image: &[u8], signature: &str, key: impl Fn(&str) -> Option<Vec<u8>>) -> Result<String, SysupdateError> {
+    let [name, mac] = signature.split_whitespace().collect::<Vec<_>>()[..] else {
+        return Err(SysupdateError::Malformed("signature"));
+    };
+    if name.starts_with('.') || !name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'+')) {
+        return Err(SysupdateError::Malformed("key name"));
+    }
+    let secret = key(name).ok_or_else(|| SysupdateError::UnknownKey(String::from(name)))?;
+    let mac = crypto::from_hex(mac).ok_or(SysupdateError::BadSignature)?;
+    if !crypto::digest_eq(&crypto::hmac_sha256(&secret, image), &mac) {
+        return Err(SysupdateError::BadSignature);
+    }
+    Ok(String::from(name))
+}
+
+/// The key named `name` in KEY_DIR
+fn read_key(name: &str) -> Option<Vec<u8>> {
+    let text = vfs_api::read_file(&format!("{}/{}", KEY_DIR, name)).ok()?;
+    crypto::from_hex(core::str::from_utf8(&text).ok()?.trim())
+}
+
+/// The boot partition, mounted for writing
+struct Volume {
+    fs: Fat32Filesystem,
+    root: InodeNumber,
+}
+
+impl Volume {
+    fn open() -> Result<Volume, SysupdateError> {
+        let name = crate::kernel::get_param(PARAM).ok_or(SysupdateError::NoBootPartition)?;
+        let device = block::open(&name).ok_or(SysupdateError::NoBootPartition)?;
+        let fs = Fat32Filesystem::mount(device, false)?;
+        let root = fs.root()?.inode;
+        Ok(Volume { fs, root })
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>, SysupdateError> {
+        let node = self.fs.lookup(self.root, name)?;
+        let mut data = vec![0u8; node.size as usize];
+        if !data.is_empty() && self.fs.read(node.inode, 0, &mut data)? < data.len() {
+            return Err(SysupdateError::Fs(FsError::IoError));
+        }
+        Ok(data)
+    }
+
+    /// Replace the file `name` with `data`
+    fn write(&mut self, name: &str, data: &[u8]) -> Result<(), SysupdateError> {
+        match self.fs.unlink(self.root, name) {
+            Ok(()) | Err(FsError::NotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let node = self.fs.create(self.root, name, FileMode::new(0o644))?;
+        self.fs.write(node.inode, 0, data)?;
+        Ok(())
+    }
+
+    /// The state file, or for a volume without one a state made up from
+    /// the kernel in slot A
+    fn state(&self) -> Result<State, SysupdateError> {
+        match self.read(STATE_FILE) {
+            Ok(text) => parse_state(core::str::from_utf8(&text).map_err(|_| SysupdateError::Malformed(STATE_FILE))?),
+            Err(SysupdateError::Fs(FsError::NotFound)) => {
+                let kernel = match self.read(Slot::A.file()) {
+                    Err(SysupdateError::Fs(FsError::NotFound)) => return Err(SysupdateError::NoKernel),
+                    result => result?,
+                };
+                let mut digests = [None; 2];
+                digests[Slot::A.index()] = Some(crypto::sha256(&kernel));
+                Ok(State { active: Slot::A, fallback: None, status: Status::Good, digests })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write `state` out, whole or not at all
+    fn save(&mut self, state: &State) -> Result<(), SysupdateError> {
+        self.write(STATE_NEW, format_state(state).as_bytes())?;
+        self.fs.rename(self.root, STATE_NEW, self.root, STATE_FILE)?;
+        self.fs.sync()?;
+        Ok(())
+    }
+
+    /// The kernel in `slot`, if it matches its digest
+    fn kernel(&self, state: &State, slot: Slot) -> Result<Vec<u8>, SysupdateError> {
+        let kernel = self.read(slot.file())?;
+        match state.digest(slot) {
+            Some(digest) if crypto::digest_eq(&crypto::sha256(&kernel), digest) => Ok(kernel),
+            _ => Err(SysupdateError::Corrupt(slot)),
+        }
+    }
+}
+
+fn audit(action: &str, what: &str, decision: AccessDecision, reason: &str) {
+    let (pid, uid) = SCHEDULER.lock().current().map_or((0, 0), |task| (task.pid, task.uid));
+    QSF.lock().on_package(pid, uid, action, what, decision, reason);
+}
+
+/// The state of the boot partition
+pub fn status() -> Result<State, SysupdateError> {
+    Volume::open()?.state()
+}
+
+/// Check `image` against `signature`, the contents of its .sig file, and
+/// install it as the kernel the next boot starts, returning the state
+/// that leaves and the key it was signed with
+pub fn install(image: &[u8], signature: &str) -> Result<(State, String), SysupdateError> {
+    let result = install_verified(image, signature);
+    match &result {
+        Ok((state, key)) => audit("sysupdate_install", state.active.file(), AccessDecision::Audit,
+            &format!("key {}, sha256 {}", key, crypto::to_hex(&crypto::sha256(image)))),
+        Err(e) => audit("sysupdate_install", "(image)", AccessDecision::Deny, &format!("{}", e)),
+    }
+    result
+}
+
+fn install_verified(image: &[u8], signature: &str) -> Result<(State, String), SysupdateError> {
+    let key = check_signature(image, signature, read_key)?;
+    crate::kernel::exec::segments(image).map_err(|_| SysupdateError::NotKernel)?;
+    let mut volume = Volume::open()?;
+    let previous = match volume.state() {
+        Ok(state) => Some(state),
+        Err(SysupdateError::NoKernel) => None,
+        Err(e) => return Err(e),
+    };
+
+    let slot = previous.as_ref().map_or(Slot::A, |state| state.active.other());
+    let digest = crypto::sha256(image);
+    volume.write(slot.file(), image)?;
+    let mut state = State {
+        active: slot,
+        fallback: previous.as_ref().map(|state| state.active),
+        status: Status::Pending,
+        digests: previous.map_or([None; 2], |state| state.digests),
+    };
+    state.digests[slot.index()] = Some(digest);
+    // Read back, so a bad write is found now rather than at boot
+    volume.kernel(&state, slot)?;
+    volume.save(&state)?;
+    Ok((state, key))
+}
+
+/// Start the active kernel, rolling back to the fallback if the last one
+/// tried never came up or the active one is damaged; only returns if
+/// there is nothing to start
+fn start() -> Result<(), SysupdateError> {
+    let mut volume = Volume::open()?;
+    let mut state = volume.state()?;
+    match state.status {
+        Status::Pending => {
+            state.status = Status::Trying;
+            volume.save(&state)?;
+        }
+        Status::Trying => roll_back(&mut volume, &mut state, "never reached the shell")?,
+        Status::Good | Status::Failed => {}
+    }
+    let kernel = match volume.kernel(&state, state.active) {
+        Ok(kernel) => kernel,
+        Err(e) if state.status != Status::Failed && state.fallback.is_some() => {
+            roll_back(&mut volume, &mut state, &format!("{}", e))?;
+            volume.kernel(&state, state.active)?
+        }
+        Err(e) => return Err(e),
+    };
+    let image = kexec::load(&kernel).map_err(SysupdateError::Kexec)?;
+    drop(kernel);
+    crate::println!("  [KERNEL] sysupdate: starting {} ({})", state.active.file(), state.status);
+    kexec::exec(image)
+}
+
+fn roll_back(volume: &mut Volume, state: &mut State, reason: &str) -> Result<(), SysupdateError> {
+    let failed = state.active;
+    if let Some(fallback) = state.roll_back() {
+        crate::println!("  [KERNEL] sysupdate: {} {}, going back to {}", failed.file(), reason, fallback.file());
+        audit("sysupdate_rollback", failed.file(), AccessDecision::Deny, reason);
+        volume.save(state)?;
+    }
+    Ok(())
+}
+
+/// Early in boot: start the kernel on the boot partition, unless this is
+/// it or there is none
+pub fn boot() {
+    if crate::kernel::get_param(PARAM).is_none() || pstore::previous() == Some(Reason::Kexec) {
+        return;
+    }
+    if let Err(e) = start() {
+        crate::println!("  [KERNEL] sysupdate: {}; going on with the built-in kernel", e);
+    }
+}
+
+/// Once the shell is up: a kernel being tried has come up, so keep it
+pub fn confirm() {
+    if crate::kernel::get_param(PARAM).is_none() || pstore::previous() != Some(Reason::Kexec) {
+        return;
+    }
+    let result = Volume::open().and_then(|mut volume| {
+        let mut state = volume.state()?;
+        if state.status == Status::Trying {
+            state.status = Status::Good;
+            volume.save(&state)?;
+            crate::println!("[INIT] sysupdate: {} confirmed", state.active.file());
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        crate::println!("[INIT] sysupdate: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_state_round_trip() {
+        let mut state = State { active: Slot::B, fallback: Some(Slot::A), status: Status::Trying, digests: [None; 2] };
+        state.digests[Slot::B.index()] = Some(crypto::sha256(b"kernel"));
+        assert_eq!(parse_state(&format_state(&state)), Ok(state.clone()));
+
+        assert_eq!(state.roll_back(), Some(Slot::A));
+        assert_eq!((state.active, state.fallback, state.status), (Slot::A, Some(Slot::B), Status::Failed));
+        assert_eq!(parse_state("active A\nfallback A\nstatus good\n"), Err(SysupdateError::Malformed(STATE_FILE)));
+        assert_eq!(parse_state("active C\nstatus good\n"), Err(SysupdateError::Malformed(STATE_FILE)));
+    }
+
+    #[test_case]
+    fn test_signature() {
+        let key = |name: &str| (name == "release").then(|| b"secret".to_vec());
+        let mac = crypto::to_hex(&crypto::hmac_sha256(b"secret", b"image"));
+        assert_eq!(check_signature(b"image", &format!("release {}", mac), key), Ok(String::from("release")));
+        assert_eq!(check_signature(b"imagf", &format!("release {}", mac), key), Err(SysupdateError::BadSignature));
+        assert_eq!(check_signature(b"image", &format!("other {}", mac), key), Err(SysupdateError::UnknownKey(String::from("other"))));
+        assert_eq!(check_signature(b"image", "release", key), Err(SysupdateError::Malformed("signature")));
+    }
+}
@@ -0,0 +1,203 @@
+// Console themes and font size
+//
+// A theme is what the 16 text colors look like: changing it reprograms
+// the VGA palette (see vga::set_palette), so everything already on the
+// screen, and on the virtual terminals not shown, changes with it, and
+// text keeps the colors it was written in. high-contrast makes every
+// color as bright as it goes on pure black; light shows dark text on
+// white, the default green and white turned dark green and black.
+//
+// The font is normal, the VGA text screen, or large, the framebuffer
+// console with characters twice the size (see hal::drivers::fbcon),
+// which needs the `framebuffer` feature and a graphics adapter.
+//
+// /etc/console.conf keeps both, is read once boot is done and written
+// by `theme`:
+//
+//   theme high-contrast
+//   font large
+
+use alloc::format;
+use alloc::string::String;
+use spin::Mutex;
+use crate::hal::drivers::vga::{self, Palette, VGA_PALETTE};
+
+pub const CONSOLE_CONF: &str = "/etc/console.conf";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Theme {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub palette: Palette,
+}
+
+pub const THEMES: &[Theme] = &[
+    Theme {
+        name: "default",
+        description: "VGA colors on black",
+        palette: VGA_PALETTE,
+    },
+    Theme {
+        name: "high-contrast",
+        description: "Bright, saturated colors on pure black",
+        palette: [
+            (0x00, 0x00, 0x00), (0x60, 0x90, 0xFF), (0x00, 0xFF, 0x00), (0x00, 0xFF, 0xFF),
+            (0xFF, 0x40, 0x40), (0xFF, 0x60, 0xFF), (0xFF, 0xD0, 0x00), (0xFF, 0xFF, 0xFF),
+            (0xC0, 0xC0, 0xC0), (0x80, 0xB0, 0xFF), (0x00, 0xFF, 0x00), (0x00, 0xFF, 0xFF),
+            (0xFF, 0x60, 0x60), (0xFF, 0x80, 0xFF), (0xFF, 0xFF, 0x00), (0xFF, 0xFF, 0xFF),
+        ],
+    },
+    Theme {
+        name: "light",
+        description: "Dark text on white",
+        palette: [
+            (0xFF, 0xFF, 0xFF), (0x00, 0x00, 0xAA), (0x00, 0x80, 0x00), (0x00, 0x80, 0x80),
+            (0xAA, 0x00, 0x00), (0x80, 0x00, 0x80), (0x80, 0x50, 0x00), (0x30, 0x30, 0x30),
+            (0x70, 0x70, 0x70), (0x00, 0x00, 0xCC), (0x00, 0x66, 0x00), (0x00, 0x66, 0x99),
+            (0xCC, 0x00, 0x00), (0x99, 0x00, 0x99), (0x80, 0x60, 0x00), (0x00, 0x00, 0x00),
+        ],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Theme> {
+    THEMES.iter().find(|theme| theme.name == name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontSize {
+    Normal,
+    Large,
+}
+
+impl FontSize {
+    pub fn name(self) -> &'static str {
+        match self {
+            FontSize::Normal => "normal",
+            FontSize::Large => "large",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<FontSize> {
+        match name {
+            "normal" => Some(FontSize::Normal),
+            "large" => Some(FontSize::Large),
+            _ => None,
+        }
+    }
+}
+
+/// What /etc/console.conf sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleConf {
+    pub theme: &'static Theme,
+    pub font: FontSize,
+}
+
+impl Default for ConsoleConf {
+    fn default() -> Self {
+        ConsoleConf { theme: &THEMES[0], font: FontSize::Normal }
+    }
+}
+
+impl ConsoleConf {
+    /// As /etc/console.conf holds it
+    pub fn to_text(&self) -> String {
+        format!("theme {}\nfont {}\n", self.theme.name, self.font.name())
+    }
+}
+
+pub fn parse_console_conf(text: &str) -> Result<ConsoleConf, String> {
+    let mut conf = ConsoleConf::default();
+    for (number, line) in text.lines().enumerate() {
+        let fields: alloc::vec::Vec<&str> = line.split('#').next().unwrap_or("").split_whitespace().collect();
+        match fields[..] {
+            [] => {}
+            ["theme", name] => {
+                conf.theme = find(name).ok_or_else(|| format!("line {}: no theme {}", number + 1, name))?;
+            }
+            ["font", size] => {
+                conf.font = FontSize::parse(size).ok_or_else(|| format!("line {}: bad font size", number + 1))?;
+            }
+            _ => return Err(format!("line {}: not understood", number + 1)),
+        }
+    }
+    Ok(conf)
+}
+
+static CURRENT: Mutex<ConsoleConf> = Mutex::new(ConsoleConf { theme: &THEMES[0], font: FontSize::Normal });
+
+pub fn current() -> ConsoleConf {
+    *CURRENT.lock()
+}
+
+/// Put `conf` on the screen. The theme always takes; the large font
+/// fails without a framebuffer, leaving the font as it was.
+pub fn apply(conf: ConsoleConf) -> Result<(), &'static str> {
+    vga::set_palette(conf.theme.palette);
+    CURRENT.lock().theme = conf.theme;
+    set_font(conf.font)?;
+    CURRENT.lock().font = conf.font;
+    Ok(())
+}
+
+#[cfg(feature = "framebuffer")]
+fn set_font(size: FontSize) -> Result<(), &'static str> {
+    use crate::hal::drivers::fbcon;
+
+    match size {
+        FontSize::Normal => {
+            fbcon::stop();
+            Ok(())
+        }
+        FontSize::Large => fbcon::start(),
+    }
+}
+
+#[cfg(not(feature = "framebuffer"))]
+fn set_font(size: FontSize) -> Result<(), &'static str> {
+    match size {
+        FontSize::Normal => Ok(()),
+        FontSize::Large => Err("built without the framebuffer console"),
+    }
+}
+
+/// Put `conf` on the screen, now and after a reboot
+pub fn set_and_save(conf: ConsoleConf) -> Result<(), String> {
+    apply(conf).map_err(String::from)?;
+    crate::fs::vfs::api::write_file(CONSOLE_CONF, conf.to_text().as_bytes(), 0o644)
+        .map_err(|e| format!("{}: {:?}", CONSOLE_CONF, e))
+}
+
+/// Take the theme and font from /etc/console.conf, if there is one
+pub fn init() {
+    let Ok(data) = crate::fs::vfs::api::read_file(CONSOLE_CONF) else {
+        return;
+    };
+    let conf = match parse_console_conf(&String::from_utf8_lossy(&data)) {
+        Ok(conf) => conf,
+        Err(e) => {
+            crate::println!("  [KERNEL] {}: {}", CONSOLE_CONF, e);
+            return;
+        }
+    };
+    match apply(conf) {
+        Ok(()) => crate::println!("  [KERNEL] Console theme {}, {} font", conf.theme.name, conf.font.name()),
+        Err(e) => crate::println!("  [KERNEL] Console theme {}; {} font: {}", conf.theme.name, conf.font.name(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_console_conf() {
+        assert_eq!(parse_console_conf(""), Ok(ConsoleConf::default()));
+        let conf = parse_console_conf("# accessibility\ntheme high-contrast\nfont large   # twice the size\n").unwrap();
+        assert_eq!((conf.theme.name, conf.font), ("high-contrast", FontSize::Large));
+        assert_eq!(parse_console_conf(&conf.to_text()), Ok(conf));
+        assert_eq!(parse_console_conf("theme neon"), Err(String::from("line 1: no theme neon")));
+        assert_eq!(parse_console_conf("\nfont huge"), Err(String::from("line 2: bad font size")));
+        assert_eq!(parse_console_conf("font"), Err(String::from("line 1: not understood")));
+    }
+}
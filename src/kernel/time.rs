@@ -0,0 +1,277 @@
+// Wall-clock time
+//
+// The RTC is read once at boot; afterwards the time is worked out from the
+// PIT uptime, so it advances with the timer interrupt and never has to
+// touch CMOS again. Setting the time writes the RTC and moves the base.
+// Everything here is UTC.
+//
+// The clock can also be disciplined the way adjtime() does it: a small
+// correction is slewed in by running up to 500 ppm fast or slow rather
+// than jumping, and a standing frequency correction makes up for a PIT
+// that does not tick at exactly 1 kHz. Stepping is left for large errors.
+
+use core::fmt;
+use spin::Mutex;
+use crate::hal::drivers::{pit, rtc};
+
+pub const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+pub const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Fastest a pending adjustment is slewed in, parts per million
+pub const MAX_SLEW_PPM: i64 = 500;
+/// Largest frequency correction, parts per billion
+pub const MAX_FREQ_PPB: i64 = 500_000;
+
+/// Unix time as a function of uptime, both in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    /// Uptime and Unix time at the last change
+    pub base_uptime: u64,
+    pub base_unix: u64,
+    /// How much faster than the PIT the clock runs, parts per billion
+    pub freq_ppb: i64,
+    /// Adjustment still to be slewed in
+    pub slew_ms: i64,
+}
+
+impl Clock {
+    pub const fn new(uptime: u64, unix: u64) -> Clock {
+        Clock { base_uptime: uptime, base_unix: unix, freq_ppb: 0, slew_ms: 0 }
+    }
+
+    /// How much of the slew has been applied `elapsed` ms after the base
+    fn slewed(&self, elapsed: u64) -> i64 {
+        let most = (elapsed as i64).saturating_mul(MAX_SLEW_PPM) / 1_000_000;
+        self.slew_ms.clamp(-most, most)
+    }
+
+    /// Unix time at `uptime`
+    pub fn at(&self, uptime: u64) -> u64 {
+        let elapsed = uptime.saturating_sub(self.base_uptime);
+        let drift = (elapsed as i64).saturating_mul(self.freq_ppb) / 1_000_000_000;
+        (self.base_unix as i64 + elapsed as i64 + drift + self.slewed(elapsed)).max(0) as u64
+    }
+
+    /// Move the base to `uptime` without changing the time
+    fn rebase(&mut self, uptime: u64) {
+        let unix = self.at(uptime);
+        self.slew_ms -= self.slewed(uptime.saturating_sub(self.base_uptime));
+        self.base_uptime = uptime;
+        self.base_unix = unix;
+    }
+}
+
+static CLOCK: Mutex<Clock> = Mutex::new(Clock::new(0, 0));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+pub fn is_leap_year(year: u32) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+pub fn days_in_month(year: u32, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: u32, month: u8, day: u8) -> i64 {
+    let y = year as i64 - if month <= 2 { 1 } else { 0 };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+impl DateTime {
+    /// Whether every field is in range (years 1970..=9999)
+    pub fn is_valid(&self) -> bool {
+        (1970..=9999).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86400) as i64 + 719468;
+        let rem = secs % 86400;
+        let era = days.div_euclid(146097);
+        let doe = days - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + if month <= 2 { 1 } else { 0 }) as u32;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// Seconds since the epoch; dates before 1970 clamp to 0
+    pub fn to_unix(&self) -> u64 {
+        let days = days_from_civil(self.year, self.month, self.day).max(0) as u64;
+        days * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+
+    /// 0 = Sunday
+    pub fn weekday(&self) -> usize {
+        // 1970-01-01 was a Thursday
+        (days_from_civil(self.year, self.month, self.day) + 4).rem_euclid(7) as usize
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// `date` format: "Thu Jan  1 00:00:00 UTC 1970"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:>2} {:02}:{:02}:{:02} UTC {}",
+            WEEKDAYS[self.weekday()],
+            MONTHS[(self.month as usize).saturating_sub(1) % 12],
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.year
+        )
+    }
+}
+
+pub fn init() {
+    let now = rtc::read();
+    let secs = if now.is_valid() { now.to_unix() } else { 0 };
+    *CLOCK.lock() = Clock::new(pit::get_uptime_ms(), secs * 1000);
+}
+
+/// Current Unix time in seconds
+pub fn now() -> u64 {
+    now_ms() / 1000
+}
+
+/// Current Unix time in milliseconds
+pub fn now_ms() -> u64 {
+    CLOCK.lock().at(pit::get_uptime_ms())
+}
+
+/// Unix time the system booted at
+pub fn boot_time() -> u64 {
+    now_ms().saturating_sub(pit::get_uptime_ms()) / 1000
+}
+
+/// Set the wall clock and the RTC to `secs` since the epoch
+pub fn set(secs: u64) {
+    rtc::write(&DateTime::from_unix(secs));
+    let mut clock = CLOCK.lock();
+    *clock = Clock { freq_ppb: clock.freq_ppb, ..Clock::new(pit::get_uptime_ms(), secs * 1000) };
+}
+
+/// Jump the clock by `offset_ms`, dropping any adjustment in progress
+pub fn step(offset_ms: i64) {
+    let uptime = pit::get_uptime_ms();
+    let mut clock = CLOCK.lock();
+    clock.rebase(uptime);
+    clock.base_unix = (clock.base_unix as i64 + offset_ms).max(0) as u64;
+    clock.slew_ms = 0;
+}
+
+/// Slew the clock by `offset_ms`, replacing any adjustment in progress
+pub fn adjust(offset_ms: i64) {
+    let uptime = pit::get_uptime_ms();
+    let mut clock = CLOCK.lock();
+    clock.rebase(uptime);
+    clock.slew_ms = offset_ms;
+}
+
+/// What is left of the adjustment in progress, in milliseconds
+pub fn pending_adjustment() -> i64 {
+    let uptime = pit::get_uptime_ms();
+    let clock = CLOCK.lock();
+    clock.slew_ms - clock.slewed(uptime.saturating_sub(clock.base_uptime))
+}
+
+/// The frequency correction, in parts per billion
+pub fn frequency() -> i64 {
+    CLOCK.lock().freq_ppb
+}
+
+/// Run the clock `ppb` parts per billion faster than the PIT, within
+/// MAX_FREQ_PPB either way
+pub fn set_frequency(ppb: i64) {
+    let uptime = pit::get_uptime_ms();
+    let mut clock = CLOCK.lock();
+    clock.rebase(uptime);
+    clock.freq_ppb = ppb.clamp(-MAX_FREQ_PPB, MAX_FREQ_PPB);
+}
+
+/// Write the wall clock to the RTC
+pub fn sync_rtc() {
+    rtc::write(&DateTime::from_unix(now()));
+}
+
+/// Read the RTC as Unix seconds, if it holds a valid date
+pub fn read_rtc() -> Option<u64> {
+    let now = rtc::read();
+    now.is_valid().then(|| now.to_unix())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_unix_round_trip() {
+        let epoch = DateTime::from_unix(0);
+        assert_eq!((epoch.year, epoch.month, epoch.day), (1970, 1, 1));
+        assert_eq!(epoch.weekday(), 4);
+
+        let leap = DateTime { year: 2024, month: 2, day: 29, hour: 23, minute: 59, second: 58 };
+        assert_eq!(leap.to_unix(), 1709251198);
+        assert_eq!(DateTime::from_unix(1709251198), leap);
+        assert_eq!(leap.weekday(), 4);
+        assert!(leap.is_valid());
+        assert!(!DateTime { day: 29, year: 2100, ..leap }.is_valid());
+    }
+
+    #[test_case]
+    fn test_clock() {
+        let mut clock = Clock::new(1000, 5_000_000);
+        assert_eq!(clock.at(2000), 5_001_000);
+
+        // 500 ppm of a second is half a millisecond, so a 10 ms slew
+        // takes 20 s and the clock never runs backwards
+        clock.slew_ms = -10;
+        assert_eq!(clock.at(11_000), 5_010_000 - 5);
+        assert_eq!(clock.at(21_000), 5_020_000 - 10);
+        assert_eq!(clock.at(31_000), 5_030_000 - 10);
+        clock.rebase(11_000);
+        assert_eq!((clock.base_unix, clock.slew_ms), (5_009_995, -5));
+        assert_eq!(clock.at(21_000), 5_020_000 - 10);
+
+        let mut fast = Clock::new(0, 0);
+        fast.freq_ppb = 100_000;
+        assert_eq!(fast.at(1_000_000), 1_000_100);
+    }
+}
@@ -0,0 +1,244 @@
+// Tracepoints
+//
+// Static tracepoints, declared in `tracepoints!` below and fired with
+// `tracepoint!(Event, args...)`, mark task switches, syscall entry and
+// exit, page faults and disk I/O. While tracing is off one costs a relaxed
+// load. While it is on each hit is stamped with the TSC and written to
+// the ring of the CPU it ran on, with interrupts off so that a tracepoint
+// in an interrupt handler never spins on a ring its own CPU holds. A full
+// ring overwrites its oldest records and counts them as lost.
+//
+// /proc/trace shows the rings merged, oldest first, on the uptime clock.
+// Writing `on`, `off` or `clear` to it controls tracing from the next
+// /proc refresh; the `trace` command does the same directly.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::hal::cpu::context::{self, MAX_CPUS};
+use crate::hal::drivers::pit;
+
+/// Records each CPU's ring holds
+pub const RING_RECORDS: usize = 1024;
+
+macro_rules! tracepoints {
+    ($($event:ident => $name:literal [$($arg:literal),*],)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Event {
+            $($event,)*
+        }
+
+        impl Event {
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Event::$event => $name,)*
+                }
+            }
+
+            /// What the arguments are, in the order they are recorded
+            pub fn args(self) -> &'static [&'static str] {
+                match self {
+                    $(Event::$event => &[$($arg),*],)*
+                }
+            }
+        }
+    };
+}
+
+tracepoints! {
+    SchedSwitch => "sched_switch" ["prev", "next"],
+    SyscallEnter => "sys_enter" ["nr", "arg1", "arg2"],
+    SyscallExit => "sys_exit" ["nr", "ret"],
+    PageFault => "page_fault" ["addr", "error", "rip"],
+    BlockRead => "block_read" ["dev", "sector", "count"],
+    BlockWrite => "block_write" ["dev", "sector", "count"],
+}
+
+/// Record `Event` with up to three arguments, if tracing is on
+#[macro_export]
+macro_rules! tracepoint {
+    ($event:ident $(, $arg:expr)* $(,)?) => {
+        if $crate::kernel::trace::enabled() {
+            $crate::kernel::trace::record($crate::kernel::trace::Event::$event, &[$($arg as u64),*]);
+        }
+    };
+}
+
+/// A block device for block_read and block_write, as major and minor
+pub fn dev(id: crate::fs::vfs::node::DeviceId) -> u64 {
+    (id.major as u64) << 20 | id.minor as u64
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    tsc: u64,
+    event: Event,
+    args: [u64; 3],
+}
+
+struct Ring {
+    /// Allocated whole when tracing is first turned on, so recording
+    /// never allocates
+    records: Vec<Record>,
+    /// The oldest record, once the ring has wrapped
+    next: usize,
+    lost: u64,
+}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring { records: Vec::new(), next: 0, lost: 0 }
+    }
+
+    fn push(&mut self, record: Record) {
+        if self.records.len() < self.records.capacity() {
+            self.records.push(record);
+        } else if !self.records.is_empty() {
+            self.records[self.next] = record;
+            self.next = (self.next + 1) % self.records.len();
+            self.lost += 1;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.records.clear();
+        self.next = 0;
+        self.lost = 0;
+    }
+
+    fn oldest_first(&self) -> impl Iterator<Item = &Record> {
+        self.records[self.next..].iter().chain(&self.records[..self.next])
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static RINGS: [Mutex<Ring>; MAX_CPUS] = [const { Mutex::new(Ring::new()) }; MAX_CPUS];
+/// The TSC and the uptime in milliseconds when tracing was first turned
+/// on, to put records on the uptime clock
+static BASE_TSC: AtomicU64 = AtomicU64::new(0);
+static BASE_MS: AtomicU64 = AtomicU64::new(0);
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn record(event: Event, args: &[u64]) {
+    let mut record = Record { tsc: pit::rdtsc(), event, args: [0; 3] };
+    for (slot, &arg) in record.args.iter_mut().zip(args) {
+        *slot = arg;
+    }
+    interrupts::without_interrupts(|| RINGS[context::cpu()].lock().push(record));
+}
+
+/// Start recording. The first time this calibrates the TSC, which takes
+/// the timer interrupt and 50 ms.
+pub fn enable() {
+    if BASE_TSC.load(Ordering::Relaxed) == 0 {
+        pit::tsc_per_us();
+        for ring in &RINGS {
+            interrupts::without_interrupts(|| ring.lock().records.reserve_exact(RING_RECORDS));
+        }
+        BASE_MS.store(pit::get_uptime_ms(), Ordering::Relaxed);
+        BASE_TSC.store(pit::rdtsc(), Ordering::Relaxed);
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn clear() {
+    for ring in &RINGS {
+        interrupts::without_interrupts(|| ring.lock().clear());
+    }
+}
+
+/// Act on `on`, `off` or `clear`, as written to /proc/trace
+pub fn control(command: &str) -> Result<(), &'static str> {
+    match command {
+        "on" => enable(),
+        "off" => disable(),
+        "clear" => clear(),
+        _ => return Err("expected on, off or clear"),
+    }
+    Ok(())
+}
+
+/// Records held and lost, over all CPUs
+pub fn counts() -> (usize, u64) {
+    let mut counts = (0, 0);
+    for ring in &RINGS {
+        interrupts::without_interrupts(|| {
+            let ring = ring.lock();
+            counts.0 += ring.records.len();
+            counts.1 += ring.lost;
+        });
+    }
+    counts
+}
+
+/// Every record, oldest first, one to a line:
+/// `[CPU] SECONDS.MICROS: EVENT NAME=VALUE...`
+pub fn dump() -> String {
+    let mut records: Vec<(usize, Record)> = Vec::new();
+    let mut lost = 0;
+    for (cpu, ring) in RINGS.iter().enumerate() {
+        interrupts::without_interrupts(|| {
+            let ring = ring.lock();
+            records.extend(ring.oldest_first().map(|record| (cpu, *record)));
+            lost += ring.lost;
+        });
+    }
+    records.sort_by_key(|(_, record)| record.tsc);
+
+    let mut text = String::new();
+    writeln!(text, "# tracing: {}", if enabled() { "on" } else { "off" }).ok();
+    writeln!(text, "# records: {}, lost: {}", records.len(), lost).ok();
+    let (base_tsc, base_ms) = (BASE_TSC.load(Ordering::Relaxed), BASE_MS.load(Ordering::Relaxed));
+    // Records mean tracing was on, so the TSC has been calibrated already
+    let tsc_per_us = if records.is_empty() { 1 } else { pit::tsc_per_us() };
+    for (cpu, record) in records {
+        let us = base_ms * 1000 + record.tsc.saturating_sub(base_tsc) / tsc_per_us;
+        write!(text, "[{:03}] {:>6}.{:06}: {:<12}", cpu, us / 1_000_000, us % 1_000_000, record.event.name()).ok();
+        for (name, value) in record.event.args().iter().zip(record.args) {
+            match *name {
+                "addr" | "rip" | "error" => write!(text, " {}={:#x}", name, value),
+                "dev" => write!(text, " {}={}:{}", name, value >> 20, value & 0xF_FFFF),
+                "ret" => write!(text, " {}={}", name, value as i64),
+                _ => write!(text, " {}={}", name, value),
+            }
+            .ok();
+        }
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_ring_wraps() {
+        let mut ring = Ring::new();
+        let record = |tsc| Record { tsc, event: Event::SchedSwitch, args: [0; 3] };
+        // Nothing is kept before the ring is allocated
+        ring.push(record(0));
+        assert!(ring.records.is_empty());
+
+        ring.records.reserve_exact(3);
+        let capacity = ring.records.capacity() as u64;
+        for tsc in 1..=capacity + 2 {
+            ring.push(record(tsc));
+        }
+        assert_eq!(ring.lost, 2);
+        let kept: Vec<u64> = ring.oldest_first().map(|record| record.tsc).collect();
+        assert_eq!(kept, (3..=capacity + 2).collect::<Vec<u64>>());
+        assert_eq!(Event::PageFault.name(), "page_fault");
+        assert_eq!(Event::SyscallExit.args(), ["nr", "ret"]);
+    }
+}
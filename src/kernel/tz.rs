@@ -0,0 +1,340 @@
+// Time zones
+//
+// A zone is a POSIX TZ rule: the name and offset of standard time, then
+// optionally those of summer time and the dates it starts and ends,
+//
+//   CET-1CEST,M3.5.0,M10.5.0/3
+//
+// as the last line of a tzdata file has it. Offsets are hours[:minutes]
+// west of Greenwich, so -1 is an hour east, and summer time is an hour
+// ahead of standard unless given. Mm.w.d is weekday d (0 is Sunday) of
+// week w (5 is the last) of month m, at 02:00 local time unless a /time
+// follows. ZONES maps the commonly wanted tzdata names to their rules;
+// what the rules were in other years is not kept.
+//
+// /etc/timezone names the zone, by tzdata name or as a rule, and is read
+// at boot; `tzselect` changes it. The clock and the RTC stay UTC - only
+// what is shown to people is local.
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use spin::Mutex;
+use super::time::{self, DateTime, MONTHS, WEEKDAYS};
+
+pub const TIMEZONE_FILE: &str = "/etc/timezone";
+
+/// tzdata names and their current rules
+pub const ZONES: &[(&str, &str)] = &[
+    ("UTC", "UTC0"),
+    ("Africa/Cairo", "EET-2EEST,M4.5.5/0,M10.5.4/24"),
+    ("Africa/Johannesburg", "SAST-2"),
+    ("Africa/Lagos", "WAT-1"),
+    ("America/Chicago", "CST6CDT,M3.2.0,M11.1.0"),
+    ("America/Denver", "MST7MDT,M3.2.0,M11.1.0"),
+    ("America/Los_Angeles", "PST8PDT,M3.2.0,M11.1.0"),
+    ("America/New_York", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Phoenix", "MST7"),
+    ("America/Sao_Paulo", "<-03>3"),
+    ("Asia/Dubai", "<+04>-4"),
+    ("Asia/Kolkata", "IST-5:30"),
+    ("Asia/Shanghai", "CST-8"),
+    ("Asia/Singapore", "<+08>-8"),
+    ("Asia/Tokyo", "JST-9"),
+    ("Australia/Sydney", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Europe/Berlin", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Helsinki", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/London", "GMT0BST,M3.5.0/1,M10.5.0"),
+    ("Europe/Moscow", "MSK-3"),
+    ("Europe/Paris", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Pacific/Auckland", "NZST-12NZDT,M9.5.0,M4.1.0/3"),
+];
+
+/// When summer time starts or ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Transition {
+    month: u8,
+    /// 1 to 4, or 5 for the last
+    week: u8,
+    weekday: u8,
+    /// Seconds after local midnight
+    time: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Summer {
+    abbr: String,
+    offset: i64,
+    start: Transition,
+    end: Transition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    /// What /etc/timezone holds for it: a tzdata name, or the rule
+    pub name: String,
+    abbr: String,
+    /// Seconds east of UTC
+    offset: i64,
+    summer: Option<Summer>,
+}
+
+static ZONE: Mutex<Option<Zone>> = Mutex::new(None);
+
+/// A time as it is on the clocks of a zone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Local {
+    pub time: DateTime,
+    /// Seconds east of UTC
+    pub offset: i64,
+    pub abbr: String,
+}
+
+impl fmt::Display for Local {
+    /// `date` format: "Thu Jan  1 01:00:00 CET 1970"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let t = &self.time;
+        write!(
+            f,
+            "{} {} {:>2} {:02}:{:02}:{:02} {} {}",
+            WEEKDAYS[t.weekday()],
+            MONTHS[(t.month as usize).saturating_sub(1) % 12],
+            t.day,
+            t.hour,
+            t.minute,
+            t.second,
+            self.abbr,
+            t.year
+        )
+    }
+}
+
+/// An offset east of UTC as `date +%z` shows it: "+0530"
+pub fn format_offset(offset: i64) -> String {
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{}{:02}{:02}", sign, offset.abs() / 3600, offset.abs() / 60 % 60)
+}
+
+/// A name: three or more letters, or anything but `>` between `<` and `>`
+fn abbr(s: &mut &str) -> Option<String> {
+    let name = if let Some(rest) = s.strip_prefix('<') {
+        let (name, rest) = rest.split_once('>')?;
+        *s = rest;
+        name
+    } else {
+        let len = s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len());
+        let name = &s[..len];
+        *s = &s[len..];
+        name
+    };
+    (name.len() >= 3).then(|| String::from(name))
+}
+
+/// `[+-]hh[:mm[:ss]]` in seconds
+fn clock(s: &mut &str) -> Option<i64> {
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'-') => (-1, &s[1..]),
+        Some(b'+') => (1, &s[1..]),
+        _ => (1, *s),
+    };
+    let len = rest.find(|c: char| !c.is_ascii_digit() && c != ':').unwrap_or(rest.len());
+    let mut parts = rest[..len].split(':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next().map_or(Some(0), |m| m.parse().ok())?;
+    let seconds: i64 = parts.next().map_or(Some(0), |m| m.parse().ok())?;
+    if parts.next().is_some() || hours > 167 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+    *s = &rest[len..];
+    Some(sign * (hours * 3600 + minutes * 60 + seconds))
+}
+
+/// `Mm.w.d[/time]`
+fn transition(s: &mut &str) -> Option<Transition> {
+    let rest = s.strip_prefix('M')?;
+    let len = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+    let mut parts = rest[..len].split('.').map(|part| part.parse::<u8>().ok());
+    let (month, week, weekday) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+        return None;
+    }
+    *s = &rest[len..];
+    let time = match s.strip_prefix('/') {
+        Some(rest) => {
+            *s = rest;
+            clock(s)?
+        }
+        None => 2 * 3600,
+    };
+    Some(Transition { month, week, weekday, time })
+}
+
+impl Transition {
+    /// Unix time it happens in `year`, in a zone `offset` east of UTC
+    fn at(&self, year: u32, offset: i64) -> i64 {
+        let first = DateTime { year, month: self.month, day: 1, hour: 0, minute: 0, second: 0 };
+        let mut day = 1 + (self.weekday as i64 - first.weekday() as i64).rem_euclid(7) + (self.week as i64 - 1) * 7;
+        while day > time::days_in_month(year, self.month) as i64 {
+            day -= 7;
+        }
+        first.to_unix() as i64 + (day - 1) * 86400 + self.time - offset
+    }
+}
+
+impl Zone {
+    pub fn utc() -> Zone {
+        Zone { name: String::from("UTC"), abbr: String::from("UTC"), offset: 0, summer: None }
+    }
+
+    /// Parse a POSIX TZ rule, for the zone called `name`
+    pub fn parse(name: &str, rule: &str) -> Option<Zone> {
+        let mut s = rule;
+        let abbr = abbr(&mut s)?;
+        let offset = -clock(&mut s)?;
+        let summer = if s.is_empty() {
+            None
+        } else {
+            let summer_abbr = self::abbr(&mut s)?;
+            let summer_offset = if s.starts_with(',') { offset + 3600 } else { -clock(&mut s)? };
+            s = s.strip_prefix(',')?;
+            let start = transition(&mut s)?;
+            s = s.strip_prefix(',')?;
+            let end = transition(&mut s)?;
+            Some(Summer { abbr: summer_abbr, offset: summer_offset, start, end })
+        };
+        s.is_empty().then(|| Zone { name: String::from(name), abbr, offset, summer })
+    }
+
+    /// A zone by tzdata name, or given as a rule
+    pub fn lookup(spec: &str) -> Option<Zone> {
+        match ZONES.iter().find(|(name, _)| *name == spec) {
+            Some((name, rule)) => Zone::parse(name, rule),
+            None => Zone::parse(spec, spec),
+        }
+    }
+
+    /// Offset east of UTC and name of the time at Unix time `utc`
+    pub fn offset_at(&self, utc: u64) -> (i64, &str) {
+        let Some(summer) = &self.summer else {
+            return (self.offset, &self.abbr);
+        };
+        let t = utc as i64;
+        let year = DateTime::from_unix((t + self.offset).max(0) as u64).year;
+        // The start is in standard time, the end in summer time
+        let start = summer.start.at(year, self.offset);
+        let end = summer.end.at(year, summer.offset);
+        let in_summer = if start < end {
+            (start..end).contains(&t)
+        } else {
+            !(end..start).contains(&t)
+        };
+        if in_summer {
+            (summer.offset, &summer.abbr)
+        } else {
+            (self.offset, &self.abbr)
+        }
+    }
+
+    pub fn local(&self, utc: u64) -> Local {
+        let (offset, abbr) = self.offset_at(utc);
+        Local {
+            time: DateTime::from_unix((utc as i64 + offset).max(0) as u64),
+            offset,
+            abbr: String::from(abbr),
+        }
+    }
+
+    /// Unix time of `local` seconds on this zone's clocks. A time skipped
+    /// or repeated when the clocks change is taken as standard time.
+    pub fn to_utc(&self, local: u64) -> u64 {
+        let standard = (local as i64 - self.offset).max(0) as u64;
+        let (offset, _) = self.offset_at(standard);
+        (local as i64 - offset).max(0) as u64
+    }
+}
+
+/// The zone in use
+pub fn current() -> Zone {
+    ZONE.lock().clone().unwrap_or_else(Zone::utc)
+}
+
+/// `utc` in the zone in use
+pub fn local(utc: u64) -> Local {
+    current().local(utc)
+}
+
+pub fn set(zone: Zone) {
+    *ZONE.lock() = Some(zone);
+}
+
+/// Make `zone` the one in use, now and after a reboot
+pub fn set_and_save(zone: Zone) -> crate::fs::FsResult<()> {
+    let line = format!("{}\n", zone.name);
+    set(zone);
+    crate::fs::vfs::api::write_file(TIMEZONE_FILE, line.as_bytes(), 0o644)
+}
+
+/// Take the zone from /etc/timezone, if there is one
+pub fn init() {
+    let Ok(data) = crate::fs::vfs::api::read_file(TIMEZONE_FILE) else {
+        return;
+    };
+    let spec = String::from_utf8_lossy(&data);
+    let spec = spec.trim();
+    match Zone::lookup(spec) {
+        Some(zone) => {
+            crate::println!("  [KERNEL] Time zone {}", zone.name);
+            set(zone);
+        }
+        None => crate::println!("  [KERNEL] {}: unknown time zone '{}', using UTC", TIMEZONE_FILE, spec),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix(year: u32, month: u8, day: u8, hour: u8, minute: u8) -> u64 {
+        DateTime { year, month, day, hour, minute, second: 0 }.to_unix()
+    }
+
+    #[test_case]
+    fn test_rules() {
+        for (name, rule) in ZONES {
+            assert!(Zone::parse(name, rule).is_some(), "{}", name);
+        }
+        let kolkata = Zone::lookup("Asia/Kolkata").unwrap();
+        assert_eq!(kolkata.offset_at(0), (5 * 3600 + 1800, "IST"));
+        let dubai = Zone::lookup("Asia/Dubai").unwrap();
+        assert_eq!(format_offset(dubai.offset_at(0).0), "+0400");
+        assert_eq!(Zone::lookup("<-03>3").unwrap().offset_at(0).1, "-03");
+        for bad in ["", "X0", "CET", "CET-1CEST", "CET-1CEST,M3.5.0", "CET-1CEST,M13.5.0,M10.5.0", "Mars/Olympus"] {
+            assert_eq!(Zone::lookup(bad), None, "{}", bad);
+        }
+    }
+
+    #[test_case]
+    fn test_summer_time() {
+        // 2024: 31 March 01:00 UTC to 27 October 01:00 UTC
+        let berlin = Zone::lookup("Europe/Berlin").unwrap();
+        assert_eq!(berlin.offset_at(unix(2024, 3, 31, 0, 59)).1, "CET");
+        assert_eq!(berlin.offset_at(unix(2024, 3, 31, 1, 0)).1, "CEST");
+        assert_eq!(berlin.offset_at(unix(2024, 10, 27, 0, 59)).1, "CEST");
+        assert_eq!(berlin.offset_at(unix(2024, 10, 27, 1, 0)).1, "CET");
+        let local = berlin.local(unix(2024, 7, 1, 12, 0));
+        assert_eq!((local.time.hour, local.offset), (14, 7200));
+        assert_eq!(berlin.to_utc(unix(2024, 7, 1, 14, 0)), unix(2024, 7, 1, 12, 0));
+
+        // Summer spans the new year: 2024-04-07 03:00 AEDT, 2024-10-06 02:00 AEST
+        let sydney = Zone::lookup("Australia/Sydney").unwrap();
+        assert_eq!(sydney.offset_at(unix(2024, 1, 15, 0, 0)).1, "AEDT");
+        assert_eq!(sydney.offset_at(unix(2024, 4, 6, 15, 59)).1, "AEDT");
+        assert_eq!(sydney.offset_at(unix(2024, 4, 6, 16, 0)).1, "AEST");
+        assert_eq!(sydney.offset_at(unix(2024, 10, 5, 16, 0)).1, "AEDT");
+
+        // Second Sunday in March, first in November
+        let new_york = Zone::lookup("America/New_York").unwrap();
+        assert_eq!(new_york.offset_at(unix(2024, 3, 10, 7, 0)), (-4 * 3600, "EDT"));
+        assert_eq!(new_york.offset_at(unix(2024, 11, 3, 6, 0)), (-5 * 3600, "EST"));
+    }
+}
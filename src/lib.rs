@@ -12,6 +12,8 @@ pub mod hal;
 pub mod kernel;
 pub mod fs;
 pub mod qsf;
+#[cfg(feature = "net")]
+pub mod net;
 pub mod userland;
 
 
@@ -37,13 +39,17 @@ where
 
 pub fn test_runner(tests: &[&dyn Testable]) {
     serial_println!("Running {} tests", tests.len());
+    // Each test starts from the same files
+    let snapshot = fs::vfs::VFS.lock().snapshot();
     for test in tests {
         test.run();
+        fs::vfs::VFS.lock().restore(&snapshot).ok();
     }
     exit_qemu(QemuExitCode::Success);
 }
 
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    hal::drivers::serial::set_synchronous();
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
     exit_qemu(QemuExitCode::Failed);
@@ -59,7 +65,8 @@ pub enum QemuExitCode {
 
 pub fn exit_qemu(exit_code: QemuExitCode) {
     use x86_64::instructions::port::Port;
-    
+
+    hal::drivers::serial::flush();
     unsafe {
         let mut port = Port::new(0xf4);
         port.write(exit_code as u32);
@@ -15,6 +15,8 @@ use qunix::println;
 entry_point!(kernel_main);
 
 fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    hal::memory::kmemleak::note_boot_stack();
+
     println!("Qunix OS v{}", env!("CARGO_PKG_VERSION"));
     println!("=====================================");
     println!("Secure. POSIX-Compliant. Rust-Built.");
@@ -25,27 +27,26 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     qunix::serial_println!("Secure. POSIX-Compliant. Rust-Built.");
     qunix::serial_println!("=====================================");
 
-    println!("[BOOT] Initializing Hardware Abstraction Layer...");
-    qunix::serial_println!("[BOOT] Initializing Hardware Abstraction Layer...");
+    kernel::parse_cmdline(kernel::config::BUILTIN_CMDLINE);
+    kernel::bootui::init(1 + hal::BOOT_STAGES + kernel::BOOT_STAGES);
+    kernel::config::print_summary();
 
     // CRITICAL BOOT ORDER (DO NOT CHANGE):
     // 1. VGA/Serial already initialized by bootloader
     // 2. Frame allocator from boot_info memory map (MUST be first)
-    hal::memory::frame_allocator::init_from_boot_info(&boot_info.memory_map);
-    println!("[BOOT] Frame allocator initialized");
-    qunix::serial_println!("[BOOT] Frame allocator initialized");
+    kernel::bootui::stage("Frame allocator", || {
+        hal::memory::frame_allocator::init_from_boot_info(&boot_info.memory_map);
+        kernel::pstore::reserve(boot_info);
+    });
 
-    // 3. CPU setup (GDT, IDT, interrupts)
+    // 3. CPU setup (GDT, IDT, interrupts), memory and drivers
     hal::init(boot_info);
-    println!("[BOOT] HAL initialized successfully");
-    qunix::serial_println!("[BOOT] HAL initialized successfully");
 
     // 4. Kernel subsystems (scheduler, VFS, etc.)
-    println!("[BOOT] Initializing kernel subsystems...");
-    qunix::serial_println!("[BOOT] Initializing kernel subsystems...");
     kernel::init();
-    println!("[BOOT] Kernel initialized successfully");
-    qunix::serial_println!("[BOOT] Kernel initialized successfully");
+    kernel::bootui::finish();
+    // Once the splash has given the screen back, for the large font
+    kernel::theme::init();
 
     println!();
     println!("[BOOT] Qunix kernel boot complete!");
@@ -71,6 +72,7 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 fn panic(info: &PanicInfo) -> ! {
     use qunix::serial_println;
 
+    qunix::hal::drivers::serial::set_synchronous();
     println!();
     println!("=====================================");
     println!("KERNEL PANIC!");
@@ -80,6 +82,24 @@ fn panic(info: &PanicInfo) -> ! {
 
     serial_println!("KERNEL PANIC: {}", info);
 
+    let mut frames = [0usize; kernel::crashdump::MAX_FRAMES];
+    let depth = kernel::crashdump::backtrace(&mut frames);
+    kernel::crashdump::print_backtrace(&frames[..depth]);
+
+    match kernel::crashdump::save(info, &frames[..depth]) {
+        Ok(Some(region)) => {
+            println!("Crash dump written to {} at sector {}", region.drive.device_name(), region.lba);
+            serial_println!("Crash dump written to {} at sector {}", region.drive.device_name(), region.lba);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            println!("Crash dump failed: {:?}", e);
+            serial_println!("Crash dump failed: {:?}", e);
+        }
+    }
+    kernel::pstore::save(kernel::pstore::Reason::Panic);
+    kernel::screendump::save_on_panic();
+
     qunix::hlt_loop();
 }
 
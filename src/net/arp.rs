@@ -0,0 +1,372 @@
+// ARP for IPv4 over Ethernet
+//
+// Addresses are learned from requests aimed at us and from replies, and
+// any ARP packet from a host already in the cache updates it, which is
+// how gratuitous announcements are taken in. An entry is trusted for five
+// minutes; the next packet after that asks again. While an address is
+// being resolved the last three packets for it wait here and go out with
+// the reply. A host that has not answered three requests, one a second,
+// is marked failed: what waits for it is dropped, and for twenty seconds
+// senders are told at once that it is unreachable. The interface
+// announces its own address when it comes up, and anyone else claiming
+// that address is reported.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use super::ethernet::{MacAddr, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use super::{NetError, NetResult, Stack, NET};
+
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+const PACKET_LEN: usize = 28;
+/// Time between requests for one address
+const REQUEST_INTERVAL_MS: u64 = 1000;
+/// Requests sent before an address is given up on
+const MAX_REQUESTS: u32 = 3;
+/// How long a learned address is used before it is asked for again
+const LIFETIME_MS: u64 = 300_000;
+/// How long an address that did not answer stays failed
+const FAILED_MS: u64 = 20_000;
+/// Packets held per address being resolved; older ones are dropped
+const QUEUE_LIMIT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub op: u16,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+
+impl Packet {
+    pub fn parse(data: &[u8]) -> Option<Packet> {
+        if data.len() < PACKET_LEN
+            || u16::from_be_bytes([data[0], data[1]]) != HTYPE_ETHERNET
+            || u16::from_be_bytes([data[2], data[3]]) != ETHERTYPE_IPV4
+            || data[4] != 6
+            || data[5] != 4
+        {
+            return None;
+        }
+        let mac = |at: usize| {
+            let mut m = [0u8; 6];
+            m.copy_from_slice(&data[at..at + 6]);
+            MacAddr(m)
+        };
+        let ip = |at: usize| Ipv4Addr::new(data[at], data[at + 1], data[at + 2], data[at + 3]);
+        Some(Packet {
+            op: u16::from_be_bytes([data[6], data[7]]),
+            sender_mac: mac(8),
+            sender_ip: ip(14),
+            target_mac: mac(18),
+            target_ip: ip(24),
+        })
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(PACKET_LEN);
+        data.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        data.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        data.extend_from_slice(&[6, 4]);
+        data.extend_from_slice(&self.op.to_be_bytes());
+        data.extend_from_slice(&self.sender_mac.0);
+        data.extend_from_slice(&self.sender_ip.octets());
+        data.extend_from_slice(&self.target_mac.0);
+        data.extend_from_slice(&self.target_ip.octets());
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Reachable(MacAddr),
+    /// Asked for, no answer yet
+    Incomplete,
+    /// Did not answer
+    Failed,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Reachable(_) => "reachable",
+            State::Incomplete => "incomplete",
+            State::Failed => "failed",
+        }
+    }
+}
+
+struct Entry {
+    state: State,
+    /// When the address was learned, last asked for or given up on
+    since: u64,
+    /// Requests sent while incomplete
+    requests: u32,
+    /// Packets waiting for the address
+    queue: VecDeque<Vec<u8>>,
+}
+
+/// An entry as the arp command shows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Neighbour {
+    pub ip: Ipv4Addr,
+    pub state: State,
+    pub age_ms: u64,
+    pub queued: usize,
+}
+
+pub struct Cache {
+    entries: BTreeMap<Ipv4Addr, Entry>,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache { entries: BTreeMap::new() }
+    }
+
+    pub fn get(&self, ip: Ipv4Addr) -> Option<MacAddr> {
+        match self.entries.get(&ip)?.state {
+            State::Reachable(mac) => Some(mac),
+            _ => None,
+        }
+    }
+
+    pub fn list(&self, now: u64) -> Vec<Neighbour> {
+        self.entries
+            .iter()
+            .map(|(&ip, entry)| Neighbour {
+                ip,
+                state: entry.state,
+                age_ms: now.saturating_sub(entry.since),
+                queued: entry.queue.len(),
+            })
+            .collect()
+    }
+
+    pub fn remove(&mut self, ip: Ipv4Addr) -> bool {
+        self.entries.remove(&ip).is_some()
+    }
+
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Record `ip` at `mac`, handing back the packets that waited for it
+    fn learn(&mut self, ip: Ipv4Addr, mac: MacAddr, now: u64) -> VecDeque<Vec<u8>> {
+        let entry = Entry { state: State::Reachable(mac), since: now, requests: 0, queue: VecDeque::new() };
+        self.entries.insert(ip, entry).map(|old| old.queue).unwrap_or_default()
+    }
+
+    /// Start resolving `ip` unless that is under way already; whether a
+    /// request should go out now
+    fn start(&mut self, ip: Ipv4Addr, now: u64) -> NetResult<bool> {
+        match self.entries.get(&ip).map(|entry| entry.state) {
+            Some(State::Failed) => Err(NetError::HostUnreachable),
+            Some(_) => Ok(false),
+            None => {
+                let entry = Entry { state: State::Incomplete, since: now, requests: 1, queue: VecDeque::new() };
+                self.entries.insert(ip, entry);
+                Ok(true)
+            }
+        }
+    }
+
+    /// Keep `packet` until `ip` is resolved
+    fn hold(&mut self, ip: Ipv4Addr, packet: Vec<u8>) {
+        if let Some(entry) = self.entries.get_mut(&ip) {
+            if entry.queue.len() == QUEUE_LIMIT {
+                entry.queue.pop_front();
+            }
+            entry.queue.push_back(packet);
+        }
+    }
+
+    /// Age the entries; the addresses to ask for again
+    fn expire(&mut self, now: u64) -> Vec<Ipv4Addr> {
+        let mut due = Vec::new();
+        self.entries.retain(|&ip, entry| match entry.state {
+            State::Reachable(_) => now < entry.since + LIFETIME_MS,
+            State::Failed => now < entry.since + FAILED_MS,
+            State::Incomplete => {
+                if now >= entry.since + REQUEST_INTERVAL_MS {
+                    entry.since = now;
+                    if entry.requests >= MAX_REQUESTS {
+                        entry.state = State::Failed;
+                        entry.queue.clear();
+                    } else {
+                        entry.requests += 1;
+                        due.push(ip);
+                    }
+                }
+                true
+            }
+        });
+        due
+    }
+}
+
+fn request(stack: &mut Stack, ip: Ipv4Addr) {
+    let request = Packet {
+        op: OP_REQUEST,
+        sender_mac: stack.mac(),
+        sender_ip: stack.config.addr,
+        target_mac: MacAddr::default(),
+        target_ip: ip,
+    };
+    stack.send_frame(MacAddr::BROADCAST, ETHERTYPE_ARP, &request.build()).ok();
+}
+
+pub fn handle(stack: &mut Stack, data: &[u8], now: u64) {
+    let packet = match Packet::parse(data) {
+        Some(packet) => packet,
+        None => return,
+    };
+    let ours = stack.config.addr;
+    if packet.sender_ip == ours && packet.sender_mac != stack.mac() {
+        crate::println!("  [NET] {} is also using {}", packet.sender_mac, ours);
+        return;
+    }
+    // A probe from a host checking its address is free says nothing
+    if packet.sender_ip.is_unspecified() {
+        return;
+    }
+    // Refresh what we know, and learn the sender if it is talking to us
+    if stack.arp.entries.contains_key(&packet.sender_ip) || packet.target_ip == ours {
+        for waiting in stack.arp.learn(packet.sender_ip, packet.sender_mac, now) {
+            stack.send_frame(packet.sender_mac, ETHERTYPE_IPV4, &waiting).ok();
+        }
+    }
+    if packet.op == OP_REQUEST && packet.target_ip == ours {
+        let reply = Packet {
+            op: OP_REPLY,
+            sender_mac: stack.mac(),
+            sender_ip: ours,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+        stack.send_frame(packet.sender_mac, ETHERTYPE_ARP, &reply.build()).ok();
+    }
+}
+
+/// The MAC address for `ip`, asking for it if it is not known; fails if
+/// it did not answer
+pub fn resolve(stack: &mut Stack, ip: Ipv4Addr, now: u64) -> NetResult<Option<MacAddr>> {
+    if ip == Ipv4Addr::BROADCAST {
+        return Ok(Some(MacAddr::BROADCAST));
+    }
+    if let Some(mac) = stack.arp.get(ip) {
+        return Ok(Some(mac));
+    }
+    if stack.arp.start(ip, now)? {
+        request(stack, ip);
+    }
+    Ok(None)
+}
+
+/// Send the IPv4 `packet` to the next hop `ip`, holding it while the
+/// address is resolved
+pub fn send(stack: &mut Stack, ip: Ipv4Addr, packet: Vec<u8>, now: u64) -> NetResult<()> {
+    match resolve(stack, ip, now)? {
+        Some(mac) => stack.send_frame(mac, ETHERTYPE_IPV4, &packet),
+        None => {
+            stack.arp.hold(ip, packet);
+            Ok(())
+        }
+    }
+}
+
+/// Ask again for what is due and forget what is stale
+pub fn timers(stack: &mut Stack, now: u64) {
+    for ip in stack.arp.expire(now) {
+        request(stack, ip);
+    }
+}
+
+/// Tell the network our address is here, as a request for it from us
+pub fn announce(stack: &mut Stack) {
+    let ours = stack.config.addr;
+    let announcement = Packet {
+        op: OP_REQUEST,
+        sender_mac: stack.mac(),
+        sender_ip: ours,
+        target_mac: MacAddr::default(),
+        target_ip: ours,
+    };
+    stack.send_frame(MacAddr::BROADCAST, ETHERTYPE_ARP, &announcement.build()).ok();
+}
+
+pub fn entries() -> Vec<Neighbour> {
+    let now = super::now();
+    NET.lock().as_ref().map_or(Vec::new(), |stack| stack.arp.list(now))
+}
+
+/// Forget what is known of `ip`; false if nothing was
+pub fn remove(ip: Ipv4Addr) -> bool {
+    NET.lock().as_mut().is_some_and(|stack| stack.arp.remove(ip))
+}
+
+pub fn flush() {
+    if let Some(stack) = NET.lock().as_mut() {
+        stack.arp.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_packet() {
+        let packet = Packet {
+            op: OP_REQUEST,
+            sender_mac: MacAddr([0x52, 0x54, 0, 0x12, 0x34, 0x56]),
+            sender_ip: Ipv4Addr::new(10, 0, 2, 15),
+            target_mac: MacAddr::default(),
+            target_ip: Ipv4Addr::new(10, 0, 2, 2),
+        };
+        let data = packet.build();
+        assert_eq!(data.len(), PACKET_LEN);
+        assert_eq!(&data[..8], [0, 1, 8, 0, 6, 4, 0, 1]);
+        assert_eq!(Packet::parse(&data), Some(packet));
+        assert_eq!(Packet::parse(&data[..20]), None);
+    }
+
+    #[test_case]
+    fn test_cache() {
+        let mut cache = Cache::new();
+        let ip = Ipv4Addr::new(10, 0, 2, 2);
+        let mac = MacAddr([0x52, 0x55, 10, 0, 2, 2]);
+        assert_eq!(cache.start(ip, 0), Ok(true));
+        assert_eq!(cache.start(ip, 10), Ok(false));
+        for n in 0..4u8 {
+            cache.hold(ip, alloc::vec![n]);
+        }
+        assert_eq!(cache.list(500)[0], Neighbour { ip, state: State::Incomplete, age_ms: 500, queued: 3 });
+        assert!(cache.expire(500).is_empty());
+        assert_eq!(cache.expire(1000), [ip]);
+        // The reply hands back the newest packets, oldest first
+        assert_eq!(cache.learn(ip, mac, 1200), [alloc::vec![1], alloc::vec![2], alloc::vec![3]]);
+        assert_eq!(cache.get(ip), Some(mac));
+        assert!(cache.expire(1200 + LIFETIME_MS - 1).is_empty());
+        cache.expire(1200 + LIFETIME_MS);
+        assert_eq!(cache.get(ip), None);
+        assert!(cache.list(0).is_empty());
+
+        // Three requests go unanswered
+        assert_eq!(cache.start(ip, 0), Ok(true));
+        cache.hold(ip, alloc::vec![0]);
+        assert_eq!(cache.expire(1000), [ip]);
+        assert_eq!(cache.expire(2000), [ip]);
+        assert!(cache.expire(3000).is_empty());
+        assert_eq!(cache.list(3000)[0].state, State::Failed);
+        assert_eq!(cache.list(3000)[0].queued, 0);
+        assert_eq!(cache.start(ip, 4000), Err(NetError::HostUnreachable));
+        cache.expire(3000 + FAILED_MS);
+        assert_eq!(cache.start(ip, 3000 + FAILED_MS), Ok(true));
+        assert!(cache.remove(ip));
+        assert!(!cache.remove(ip));
+    }
+}
@@ -0,0 +1,155 @@
+// DNS over UDP
+//
+// Queries for A records and the parsing of their responses. query() asks
+// one server, sending again after each timeout; which servers to ask and
+// what to remember is the resolver's business.
+
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use super::socket::UdpSocket;
+use super::{NetError, NetResult};
+
+const PORT: u16 = 53;
+const TYPE_A: u16 = 1;
+const TYPE_CNAME: u16 = 5;
+const CLASS_IN: u16 = 1;
+const FLAG_RESPONSE: u16 = 0x8000;
+/// Recursion desired
+const FLAG_RD: u16 = 0x0100;
+const RCODE_NXDOMAIN: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub addrs: Vec<Ipv4Addr>,
+    /// Seconds the answer may be kept, the least of its records'
+    pub ttl: u32,
+}
+
+/// A query for the A records of `name`
+pub fn build_query(id: u16, name: &str) -> NetResult<Vec<u8>> {
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&FLAG_RD.to_be_bytes());
+    query.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(NetError::InvalidArgument);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    if query.len() - 12 > 255 {
+        return Err(NetError::InvalidArgument);
+    }
+    query.extend_from_slice(&TYPE_A.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// The offset just past the name at `at`, which may end in a pointer
+fn skip_name(data: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *data.get(at)?;
+        match len {
+            0 => return Some(at + 1),
+            l if l & 0xC0 == 0xC0 => return Some(at + 2),
+            l => at += 1 + l as usize,
+        }
+    }
+}
+
+/// The addresses in a response to query `id`, in the order given
+pub fn parse_response(id: u16, data: &[u8]) -> NetResult<Response> {
+    let be32 = |at: usize| data.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])).ok_or(NetError::BadResponse);
+    let be16 = |at: usize| data.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]])).ok_or(NetError::BadResponse);
+    if be16(0)? != id || be16(2)? & FLAG_RESPONSE == 0 {
+        return Err(NetError::BadResponse);
+    }
+    match be16(2)? & 0xF {
+        0 => {}
+        RCODE_NXDOMAIN => return Err(NetError::NameNotFound),
+        _ => return Err(NetError::BadResponse),
+    }
+    let questions = be16(4)?;
+    let answers = be16(6)?;
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(data, at).ok_or(NetError::BadResponse)? + 4;
+    }
+    let mut response = Response { addrs: Vec::new(), ttl: u32::MAX };
+    for _ in 0..answers {
+        at = skip_name(data, at).ok_or(NetError::BadResponse)?;
+        let (kind, class, len) = (be16(at)?, be16(at + 2)?, be16(at + 8)? as usize);
+        response.ttl = response.ttl.min(be32(at + 4)?);
+        let rdata = data.get(at + 10..at + 10 + len).ok_or(NetError::BadResponse)?;
+        // A CNAME comes first with the records of its target after it
+        if kind == TYPE_A && class == CLASS_IN && len == 4 {
+            response.addrs.push(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]));
+        } else if kind != TYPE_CNAME {
+            return Err(NetError::BadResponse);
+        }
+        at += 10 + len;
+    }
+    if response.addrs.is_empty() {
+        return Err(NetError::NameNotFound);
+    }
+    Ok(response)
+}
+
+/// Ask `server` for the A records of `name`, `attempts` times at most
+pub fn query(server: Ipv4Addr, name: &str, timeout_ms: u64, attempts: usize) -> NetResult<Response> {
+    let socket = UdpSocket::bind(0)?;
+    let id = super::now() as u16 ^ socket.port();
+    let query = build_query(id, name)?;
+    for _ in 0..attempts {
+        socket.send_to(&query, server, PORT)?;
+        let timer = crate::hal::drivers::pit::Timer::new(timeout_ms);
+        // Skip stray datagrams until the answer or the timeout
+        while !timer.is_expired() {
+            match socket.recv_from(timer.remaining_ms()) {
+                Ok((data, from, PORT)) if from == server => match parse_response(id, &data) {
+                    Err(NetError::BadResponse) => continue,
+                    result => return result,
+                },
+                Ok(_) => continue,
+                Err(NetError::Timeout) => break,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Err(NetError::Timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_query() {
+        let query = build_query(0x1234, "qunix.org").unwrap();
+        assert_eq!(&query[..4], [0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[12..], b"\x05qunix\x03org\x00\x00\x01\x00\x01");
+        assert_eq!(build_query(1, "a..b"), Err(NetError::InvalidArgument));
+    }
+
+    #[test_case]
+    fn test_response() {
+        let mut response = build_query(7, "www.qunix.org").unwrap();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        // www CNAME qunix.org, then qunix.org A 93.184.216.34
+        response.extend_from_slice(&[0xC0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xC0, 16]);
+        response.extend_from_slice(&[0xC0, 16, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 216, 34]);
+        // The shorter TTL, the CNAME's, wins
+        response[53] = 1;
+        let expected = Response { addrs: alloc::vec![Ipv4Addr::new(93, 184, 216, 34)], ttl: 60 };
+        assert_eq!(parse_response(7, &response), Ok(expected));
+        assert_eq!(parse_response(8, &response), Err(NetError::BadResponse));
+        response[3] = 0x83;
+        assert_eq!(parse_response(7, &response), Err(NetError::NameNotFound));
+        response[3] = 0x80;
+        assert_eq!(parse_response(7, &response[..40]), Err(NetError::BadResponse));
+    }
+}
@@ -0,0 +1,57 @@
+// Ethernet II framing
+
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const HEADER_LEN: usize = 14;
+/// Largest payload, the MTU of the interface
+pub const MTU: usize = 1500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xFF; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == MacAddr::BROADCAST
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let m = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", m[0], m[1], m[2], m[3], m[4], m[5])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub dest: MacAddr,
+    pub source: MacAddr,
+    pub ethertype: u16,
+}
+
+pub fn parse(frame: &[u8]) -> Option<(Header, &[u8])> {
+    if frame.len() < HEADER_LEN {
+        return None;
+    }
+    let mac = |at: usize| {
+        let mut m = [0u8; 6];
+        m.copy_from_slice(&frame[at..at + 6]);
+        MacAddr(m)
+    };
+    let header = Header { dest: mac(0), source: mac(6), ethertype: u16::from_be_bytes([frame[12], frame[13]]) };
+    Some((header, &frame[HEADER_LEN..]))
+}
+
+pub fn build(dest: MacAddr, source: MacAddr, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&dest.0);
+    frame.extend_from_slice(&source.0);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
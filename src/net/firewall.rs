@@ -0,0 +1,392 @@
+// Packet filter
+//
+// Two chains of rules: input for packets that arrive, output for packets
+// about to be sent. A rule matches on protocol, the remote address (the
+// source of an arriving packet, the destination of a sent one, or a whole
+// network) and the destination port, and accepts or drops; the first rule
+// that matches decides and counts the packet. A packet no rule matches
+// goes to the net: rules of the loaded QSF policies, those for any
+// subject, read standing for input and write for output, so
+//
+//     rule deny any net:10.0.0.0/8:0 w
+//
+// keeps packets from leaving just as it keeps sockets from connecting.
+// Only then does the chain's default policy apply. Sockets ask the output
+// chain before they connect or send, so a dropped destination fails at
+// once rather than timing out.
+//
+// There is no connection tracking: with the input policy at drop, replies
+// come in only if a rule accepts them, by address for instance.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::net::Ipv4Addr;
+use spin::Mutex;
+use crate::qsf::{LockdownFeature, NetDirection, QSF};
+use super::ipv4::{PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chain {
+    Input,
+    Output,
+}
+
+impl Chain {
+    pub const ALL: [Chain; 2] = [Chain::Input, Chain::Output];
+
+    pub fn parse(s: &str) -> Option<Chain> {
+        match s {
+            "input" | "INPUT" => Some(Chain::Input),
+            "output" | "OUTPUT" => Some(Chain::Output),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Chain::Input => "input",
+            Chain::Output => "output",
+        }
+    }
+
+    fn direction(self) -> NetDirection {
+        match self {
+            Chain::Input => NetDirection::Inbound,
+            Chain::Output => NetDirection::Outbound,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    Drop,
+}
+
+impl Verdict {
+    pub fn parse(s: &str) -> Option<Verdict> {
+        match s {
+            "accept" | "ACCEPT" => Some(Verdict::Accept),
+            "drop" | "DROP" => Some(Verdict::Drop),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Verdict::Accept => "accept",
+            Verdict::Drop => "drop",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Counter {
+    fn count(&mut self, len: usize) {
+        self.packets += 1;
+        self.bytes += len as u64;
+    }
+}
+
+/// What a rule looks at in a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub protocol: u8,
+    /// The source of an arriving packet, the destination of a sent one
+    pub remote: Ipv4Addr,
+    /// Destination port, for TCP and UDP
+    pub port: Option<u16>,
+    /// Length of the IP payload
+    pub len: usize,
+}
+
+impl Packet {
+    pub fn new(protocol: u8, remote: Ipv4Addr, payload: &[u8]) -> Packet {
+        let port = match protocol {
+            PROTOCOL_TCP | PROTOCOL_UDP if payload.len() >= 4 => Some(u16::from_be_bytes([payload[2], payload[3]])),
+            _ => None,
+        };
+        Packet { protocol, remote, port, len: payload.len() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    /// None matches every protocol
+    pub protocol: Option<u8>,
+    pub network: Ipv4Addr,
+    /// 0 matches every address
+    pub prefix: u8,
+    /// First and last destination port; a rule with ports matches only
+    /// TCP and UDP
+    pub ports: Option<(u16, u16)>,
+    pub verdict: Verdict,
+    pub counter: Counter,
+}
+
+impl Rule {
+    /// A rule matching every packet
+    pub fn new(verdict: Verdict) -> Rule {
+        Rule { protocol: None, network: Ipv4Addr::UNSPECIFIED, prefix: 0, ports: None, verdict, counter: Counter::default() }
+    }
+
+    pub fn matches(&self, packet: &Packet) -> bool {
+        let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+        self.protocol.map_or(true, |protocol| protocol == packet.protocol)
+            && u32::from(self.network) & mask == u32::from(packet.remote) & mask
+            && self.ports.map_or(true, |(first, last)| packet.port.map_or(false, |port| (first..=last).contains(&port)))
+    }
+}
+
+/// `tcp`, `udp`, `icmp`, `all` or a protocol number
+pub fn parse_protocol(s: &str) -> Option<Option<u8>> {
+    match s {
+        "all" | "any" => Some(None),
+        "icmp" => Some(Some(PROTOCOL_ICMP)),
+        "tcp" => Some(Some(PROTOCOL_TCP)),
+        "udp" => Some(Some(PROTOCOL_UDP)),
+        _ => s.parse().ok().map(Some),
+    }
+}
+
+/// `ADDR` or `ADDR/PREFIX`; `any` is 0.0.0.0/0
+pub fn parse_network(s: &str) -> Option<(Ipv4Addr, u8)> {
+    if s == "any" {
+        return Some((Ipv4Addr::UNSPECIFIED, 0));
+    }
+    let (addr, prefix) = match s.split_once('/') {
+        Some((addr, prefix)) => (addr, prefix.parse().ok().filter(|&p| p <= 32)?),
+        None => (s, 32),
+    };
+    Some((addr.parse().ok()?, prefix))
+}
+
+/// `PORT` or `FIRST:LAST`
+pub fn parse_ports(s: &str) -> Option<(u16, u16)> {
+    let (first, last) = match s.split_once(':') {
+        Some((first, last)) => (first.parse().ok()?, last.parse().ok()?),
+        None => {
+            let port = s.parse().ok()?;
+            (port, port)
+        }
+    };
+    (first <= last).then_some((first, last))
+}
+
+impl fmt::Display for Rule {
+    /// "drop tcp from 10.0.2.0/24 port 22"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.verdict.name())?;
+        match self.protocol {
+            None => write!(f, " all")?,
+            Some(PROTOCOL_ICMP) => write!(f, " icmp")?,
+            Some(PROTOCOL_TCP) => write!(f, " tcp")?,
+            Some(PROTOCOL_UDP) => write!(f, " udp")?,
+            Some(protocol) => write!(f, " proto {}", protocol)?,
+        }
+        match self.prefix {
+            0 => {}
+            32 => write!(f, " {}", self.network)?,
+            prefix => write!(f, " {}/{}", self.network, prefix)?,
+        }
+        match self.ports {
+            None => Ok(()),
+            Some((first, last)) if first == last => write!(f, " port {}", first),
+            Some((first, last)) => write!(f, " ports {}:{}", first, last),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChainRules {
+    pub rules: Vec<Rule>,
+    pub policy: Verdict,
+    /// Packets the default policy decided
+    pub policy_counter: Counter,
+    /// Packets QSF network rules decided
+    pub qsf_counter: Counter,
+}
+
+impl ChainRules {
+    const fn new() -> ChainRules {
+        ChainRules { rules: Vec::new(), policy: Verdict::Accept, policy_counter: Counter { packets: 0, bytes: 0 }, qsf_counter: Counter { packets: 0, bytes: 0 } }
+    }
+
+    /// The first matching rule's verdict, counted, or None
+    fn filter(&mut self, packet: &Packet) -> Option<Verdict> {
+        let rule = self.rules.iter_mut().find(|rule| rule.matches(packet))?;
+        rule.counter.count(packet.len);
+        Some(rule.verdict)
+    }
+}
+
+pub struct Firewall {
+    input: ChainRules,
+    output: ChainRules,
+}
+
+impl Firewall {
+    pub const fn new() -> Firewall {
+        Firewall { input: ChainRules::new(), output: ChainRules::new() }
+    }
+
+    pub fn chain(&self, chain: Chain) -> &ChainRules {
+        match chain {
+            Chain::Input => &self.input,
+            Chain::Output => &self.output,
+        }
+    }
+
+    fn chain_mut(&mut self, chain: Chain) -> &mut ChainRules {
+        match chain {
+            Chain::Input => &mut self.input,
+            Chain::Output => &mut self.output,
+        }
+    }
+}
+
+static FIREWALL: Mutex<Firewall> = Mutex::new(Firewall::new());
+
+/// Whether `packet` may pass `chain`, counting it against whatever
+/// decided. The QSF lock is taken with the filter's released.
+fn decide(chain: Chain, packet: &Packet, count: bool) -> bool {
+    {
+        let mut firewall = FIREWALL.lock();
+        let rules = firewall.chain_mut(chain);
+        if count {
+            if let Some(verdict) = rules.filter(packet) {
+                return verdict == Verdict::Accept;
+            }
+        } else if let Some(rule) = rules.rules.iter().find(|rule| rule.matches(packet)) {
+            return rule.verdict == Verdict::Accept;
+        }
+    }
+    let qsf = crate::qsf::check_packet(packet.remote, packet.port.unwrap_or(0), chain.direction());
+    let mut firewall = FIREWALL.lock();
+    let rules = firewall.chain_mut(chain);
+    let (counter, accept) = match qsf {
+        Some(allowed) => (&mut rules.qsf_counter, allowed),
+        None => (&mut rules.policy_counter, rules.policy == Verdict::Accept),
+    };
+    if count {
+        counter.count(packet.len);
+    }
+    accept
+}
+
+/// Run an IP payload through `chain`; false if it is to be dropped
+pub fn filter(chain: Chain, protocol: u8, remote: Ipv4Addr, payload: &[u8]) -> bool {
+    decide(chain, &Packet::new(protocol, remote, payload), true)
+}
+
+/// Whether the output chain lets `protocol` traffic to `addr`:`port`
+/// through, without counting anything
+pub fn permits(protocol: u8, addr: Ipv4Addr, port: u16) -> bool {
+    decide(Chain::Output, &Packet { protocol, remote: addr, port: Some(port), len: 0 }, false)
+}
+
+/// Changing the rules is changing policy, so lockdown freezes them too
+fn check_lockdown() -> Result<(), &'static str> {
+    QSF.lock().check_lockdown(LockdownFeature::PolicyChange).map_err(|_| "refused: the kernel is locked down")
+}
+
+/// Add `rule` at the end of `chain`, or before rule `position` (from 1)
+pub fn insert(chain: Chain, position: Option<usize>, mut rule: Rule) -> Result<(), &'static str> {
+    check_lockdown()?;
+    rule.counter = Counter::default();
+    let mut firewall = FIREWALL.lock();
+    let rules = &mut firewall.chain_mut(chain).rules;
+    match position {
+        None => rules.push(rule),
+        Some(n) if (1..=rules.len() + 1).contains(&n) => rules.insert(n - 1, rule),
+        Some(_) => return Err("no such rule"),
+    }
+    Ok(())
+}
+
+/// Remove rule `n` (from 1) of `chain`
+pub fn delete(chain: Chain, n: usize) -> Result<Rule, &'static str> {
+    check_lockdown()?;
+    let mut firewall = FIREWALL.lock();
+    let rules = &mut firewall.chain_mut(chain).rules;
+    if n == 0 || n > rules.len() {
+        return Err("no such rule");
+    }
+    Ok(rules.remove(n - 1))
+}
+
+pub fn set_policy(chain: Chain, verdict: Verdict) -> Result<(), &'static str> {
+    check_lockdown()?;
+    FIREWALL.lock().chain_mut(chain).policy = verdict;
+    Ok(())
+}
+
+/// Remove every rule of `chain`; the policy stays
+pub fn flush(chain: Chain) -> Result<(), &'static str> {
+    check_lockdown()?;
+    FIREWALL.lock().chain_mut(chain).rules.clear();
+    Ok(())
+}
+
+/// Reset the counters of `chain`
+pub fn zero(chain: Chain) {
+    let mut firewall = FIREWALL.lock();
+    let rules = firewall.chain_mut(chain);
+    rules.policy_counter = Counter::default();
+    rules.qsf_counter = Counter::default();
+    for rule in &mut rules.rules {
+        rule.counter = Counter::default();
+    }
+}
+
+pub fn chain(chain: Chain) -> ChainRules {
+    FIREWALL.lock().chain(chain).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_rules() {
+        let mut ssh = Rule::new(Verdict::Drop);
+        ssh.protocol = parse_protocol("tcp").unwrap();
+        (ssh.network, ssh.prefix) = parse_network("10.0.2.0/24").unwrap();
+        ssh.ports = parse_ports("22");
+        assert_eq!(alloc::format!("{}", ssh), "drop tcp 10.0.2.0/24 port 22");
+
+        let from = Ipv4Addr::new(10, 0, 2, 2);
+        let segment = [0xC0, 0x00, 0x00, 22, 0, 0, 0, 0];
+        assert_eq!(Packet::new(PROTOCOL_TCP, from, &segment).port, Some(22));
+        assert!(ssh.matches(&Packet::new(PROTOCOL_TCP, from, &segment)));
+        assert!(!ssh.matches(&Packet::new(PROTOCOL_UDP, from, &segment)));
+        assert!(!ssh.matches(&Packet::new(PROTOCOL_TCP, Ipv4Addr::new(10, 0, 3, 2), &segment)));
+        assert!(!ssh.matches(&Packet::new(PROTOCOL_TCP, from, &[0, 0, 0, 23])));
+        // A rule with ports never matches ICMP
+        let mut any_port = Rule::new(Verdict::Accept);
+        any_port.ports = parse_ports("1:65535");
+        assert!(!any_port.matches(&Packet::new(PROTOCOL_ICMP, from, &[8, 0, 0, 0])));
+        assert!(Rule::new(Verdict::Accept).matches(&Packet::new(PROTOCOL_ICMP, from, &[8, 0, 0, 0])));
+
+        let mut chain = ChainRules::new();
+        chain.rules.push(ssh);
+        chain.rules.push(Rule::new(Verdict::Accept));
+        assert_eq!(chain.filter(&Packet::new(PROTOCOL_TCP, from, &segment)), Some(Verdict::Drop));
+        assert_eq!(chain.filter(&Packet::new(PROTOCOL_UDP, from, &segment)), Some(Verdict::Accept));
+        assert_eq!(chain.rules[0].counter, Counter { packets: 1, bytes: 8 });
+        chain.rules.pop();
+        assert_eq!(chain.filter(&Packet::new(PROTOCOL_UDP, from, &segment)), None);
+
+        assert_eq!(parse_network("10.0.2.15"), Some((Ipv4Addr::new(10, 0, 2, 15), 32)));
+        assert_eq!(parse_network("10.0.2.0/40"), None);
+        assert_eq!(parse_ports("1024:2048"), Some((1024, 2048)));
+        assert_eq!(parse_ports("2048:1024"), None);
+        assert_eq!(parse_protocol("all"), Some(None));
+        assert_eq!(parse_protocol("gre"), None);
+    }
+}
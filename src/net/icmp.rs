@@ -0,0 +1,208 @@
+// ICMP: echo replies and errors
+//
+// Errors go back for UDP datagrams to ports nobody has bound, for
+// protocols the stack does not speak and for packets whose TTL has run
+// out, quoting the offending header and the first eight bytes after it
+// (RFC 792). Following RFC 1122 none are sent about broadcasts, about
+// packets from addresses that cannot be answered or about other ICMP
+// errors, and a token bucket keeps a flood from being echoed back.
+//
+// Errors that come in are matched to the socket by the quoted header:
+// unreachable ports and protocols refuse a UDP socket's next receive and
+// a TCP connection still in SYN_SENT, and fragmentation-needed lowers the
+// path MTU to the quoted destination and resegments TCP connections to it.
+
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use super::ipv4::{self, Header, PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+use super::{tcp, NetError, Stack};
+
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_DEST_UNREACHABLE: u8 = 3;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+pub const TYPE_TIME_EXCEEDED: u8 = 11;
+
+pub const CODE_PROTOCOL_UNREACHABLE: u8 = 2;
+pub const CODE_PORT_UNREACHABLE: u8 = 3;
+pub const CODE_FRAGMENTATION_NEEDED: u8 = 4;
+pub const CODE_TTL_EXCEEDED: u8 = 0;
+
+/// Bytes of the offending packet's payload an error quotes
+const QUOTED: usize = 8;
+/// Errors that may go out back to back, and how often another is allowed
+const ERROR_BURST: u32 = 10;
+const ERROR_INTERVAL_MS: u64 = 100;
+
+/// The token bucket errors are sent from
+pub struct Limiter {
+    tokens: u32,
+    refilled: u64,
+}
+
+impl Limiter {
+    pub fn new() -> Limiter {
+        Limiter { tokens: ERROR_BURST, refilled: 0 }
+    }
+
+    /// Take a token if there is one
+    fn take(&mut self, now: u64) -> bool {
+        let earned = now.saturating_sub(self.refilled) / ERROR_INTERVAL_MS;
+        if earned > 0 {
+            self.tokens = (self.tokens as u64 + earned).min(ERROR_BURST as u64) as u32;
+            self.refilled = now;
+        }
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+}
+
+/// A message of `kind` and `code` with the rest of the header and the
+/// data in `body`, checksummed
+pub fn build(kind: u8, code: u8, body: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(4 + body.len());
+    message.extend_from_slice(&[kind, code, 0, 0]);
+    message.extend_from_slice(body);
+    let sum = ipv4::checksum(&message, 0);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    message
+}
+
+/// Whether an error may be sent about the packet with `header` and
+/// `payload`
+fn may_answer(stack: &Stack, header: &Header, payload: &[u8]) -> bool {
+    let unanswerable = |addr: Ipv4Addr| addr.is_unspecified() || addr.is_broadcast() || addr.is_multicast();
+    if !stack.is_local(header.dest) || unanswerable(header.source) {
+        return false;
+    }
+    // Echoes may be complained about; errors may not
+    header.protocol != PROTOCOL_ICMP || matches!(payload.first(), Some(&(TYPE_ECHO_REQUEST | TYPE_ECHO_REPLY)))
+}
+
+/// Answer `packet`, which arrived and could not be delivered, with an
+/// error of `kind` and `code`
+pub fn send_error(stack: &mut Stack, packet: &[u8], kind: u8, code: u8, now: u64) {
+    let (header, payload) = match ipv4::parse(packet) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    if !may_answer(stack, &header, payload) || !stack.icmp.take(now) {
+        return;
+    }
+    let quoted = &packet[..(ipv4::HEADER_LEN + QUOTED).min(packet.len())];
+    let mut body = Vec::with_capacity(4 + quoted.len());
+    body.extend_from_slice(&[0; 4]);
+    body.extend_from_slice(quoted);
+    ipv4::send(stack, header.source, PROTOCOL_ICMP, &build(kind, code, &body), now).ok();
+}
+
+pub fn handle(stack: &mut Stack, header: &Header, data: &[u8], now: u64) {
+    if data.len() < 8 || ipv4::checksum(data, 0) != 0 {
+        return;
+    }
+    match data[0] {
+        TYPE_ECHO_REQUEST if stack.is_local(header.dest) => {
+            let reply = build(TYPE_ECHO_REPLY, 0, &data[4..]);
+            ipv4::send(stack, header.source, PROTOCOL_ICMP, &reply, now).ok();
+        }
+        TYPE_DEST_UNREACHABLE | TYPE_TIME_EXCEEDED => error(stack, data, now),
+        _ => {}
+    }
+}
+
+/// Act on an error about a packet this stack sent
+fn error(stack: &mut Stack, data: &[u8], now: u64) {
+    let (kind, code) = (data[0], data[1]);
+    let (quoted, total, payload) = match ipv4::parse_quoted(&data[8..]) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    if !stack.is_local(quoted.source) || payload.len() < 4 {
+        return;
+    }
+    let local_port = u16::from_be_bytes([payload[0], payload[1]]);
+    let remote_port = u16::from_be_bytes([payload[2], payload[3]]);
+    if kind != TYPE_DEST_UNREACHABLE {
+        // TTL running out on the way is transient; the sender retries
+        return;
+    }
+    match (code, quoted.protocol) {
+        (CODE_FRAGMENTATION_NEEDED, _) => {
+            let mtu = match u16::from_be_bytes([data[6], data[7]]) as usize {
+                0 => ipv4::plateau(total),
+                mtu if mtu < total => mtu,
+                // The packet would have fitted; the message is bogus
+                _ => return,
+            };
+            if stack.path_mtu.lower(quoted.dest, mtu, now) {
+                tcp::path_mtu(stack, quoted.dest, stack.path_mtu.get(quoted.dest, now), now);
+            }
+        }
+        (CODE_PROTOCOL_UNREACHABLE | CODE_PORT_UNREACHABLE, PROTOCOL_UDP) => {
+            stack.udp.report(local_port, NetError::ConnectionRefused);
+        }
+        (_, PROTOCOL_UDP) => stack.udp.report(local_port, NetError::HostUnreachable),
+        (CODE_PROTOCOL_UNREACHABLE | CODE_PORT_UNREACHABLE, PROTOCOL_TCP) => {
+            tcp::refused(stack, local_port, quoted.dest, remote_port);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::loopback::{ADDR, PREFIX};
+    use super::super::{udp, Config};
+    use super::*;
+
+    #[test_case]
+    fn test_errors() {
+        let config = Config { addr: ADDR, prefix: PREFIX, gateway: Ipv4Addr::UNSPECIFIED, dns: Ipv4Addr::UNSPECIFIED };
+        let mut stack = Stack::new(None, config);
+
+        // Nothing is bound on 9: the datagram comes back as port
+        // unreachable, which the sending socket sees on its next receive
+        let (id, _) = stack.udp.bind(0).unwrap();
+        udp::send(&mut stack, id, ADDR, 9, b"anyone?", 0).unwrap();
+        for _ in 0..4 {
+            stack.poll(0);
+        }
+        assert_eq!(stack.udp.receive(id), Err(NetError::ConnectionRefused));
+        assert_eq!(stack.udp.receive(id), Ok(None));
+
+        // The bucket runs dry and refills with time
+        let mut limiter = Limiter::new();
+        assert!((0..ERROR_BURST).all(|_| limiter.take(0)));
+        assert!(!limiter.take(0));
+        assert!(limiter.take(ERROR_INTERVAL_MS));
+    }
+
+    #[test_case]
+    fn test_fragmentation_needed() {
+        let config = Config { addr: ADDR, prefix: PREFIX, gateway: Ipv4Addr::UNSPECIFIED, dns: Ipv4Addr::UNSPECIFIED };
+        let mut stack = Stack::new(None, config);
+        let remote = Ipv4Addr::new(192, 0, 2, 1);
+        let header = Header { source: remote, dest: ADDR, protocol: PROTOCOL_ICMP, ttl: 64, id: 0 };
+        // What a router sends back about a packet of `len` bytes to
+        // `remote`, naming `mtu` as what would have fitted
+        let message = |len: usize, mtu: u16| {
+            let sent = Header { source: ADDR, dest: remote, protocol: PROTOCOL_UDP, ttl: 64, id: 0 };
+            let packet = ipv4::build(&sent, &alloc::vec![0; len - ipv4::HEADER_LEN]);
+            let mut body = alloc::vec![0, 0];
+            body.extend_from_slice(&mtu.to_be_bytes());
+            body.extend_from_slice(&packet[..ipv4::HEADER_LEN + QUOTED]);
+            build(TYPE_DEST_UNREACHABLE, CODE_FRAGMENTATION_NEEDED, &body)
+        };
+
+        handle(&mut stack, &header, &message(1500, 1400), 0);
+        assert_eq!(stack.path_mtu.get(remote, 0), 1400);
+        // A router that does not say gets the next plateau down
+        handle(&mut stack, &header, &message(1400, 0), 0);
+        assert_eq!(stack.path_mtu.get(remote, 0), 1006);
+        // Nor is an MTU the packet would have fitted believed
+        handle(&mut stack, &header, &message(1006, 1200), 0);
+        assert_eq!(stack.path_mtu.get(remote, 0), 1006);
+    }
+}
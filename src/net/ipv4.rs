@@ -0,0 +1,287 @@
+// IPv4
+//
+// Packets are sent whole with don't-fragment set and at most the path
+// MTU; fragments that arrive are dropped, as are options. A router that
+// cannot pass a packet that big answers with ICMP fragmentation-needed,
+// and the smaller MTU it names is kept per destination in PathMtu for a
+// while (RFC 1191), which is what TCP sizes its segments by.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use super::ethernet::MTU;
+use super::firewall::{self, Chain};
+use super::{arp, icmp, tcp, udp, NetError, NetResult, Stack};
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+pub const HEADER_LEN: usize = 20;
+const DEFAULT_TTL: u8 = 64;
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1FFF;
+/// How long a learned path MTU is believed before the interface MTU is
+/// tried again
+const PATH_MTU_LIFETIME_MS: u64 = 10 * 60 * 1000;
+/// The least path MTU believed, so a forged message cannot shrink
+/// packets to nothing
+pub const MIN_PATH_MTU: usize = 576;
+/// MTUs common on real links, for routers that do not say theirs
+/// (RFC 1191, section 7)
+const PLATEAUS: [usize; 10] = [32000, 17914, 8166, 4352, 2002, 1492, 1006, 508, 296, 68];
+
+/// The Internet checksum: the ones' complement of the ones' complement
+/// sum of 16-bit words, starting from `initial`
+pub fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    let mut words = data.chunks_exact(2);
+    for word in &mut words {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+    }
+    if let [last] = words.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The sum over the pseudo-header UDP and TCP checksums cover, to pass
+/// to checksum() as `initial`
+pub fn pseudo_header(source: Ipv4Addr, dest: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let s = source.octets();
+    let d = dest.octets();
+    u16::from_be_bytes([s[0], s[1]]) as u32
+        + u16::from_be_bytes([s[2], s[3]]) as u32
+        + u16::from_be_bytes([d[0], d[1]]) as u32
+        + u16::from_be_bytes([d[2], d[3]]) as u32
+        + protocol as u32
+        + len as u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub source: Ipv4Addr,
+    pub dest: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub id: u16,
+}
+
+/// The header and payload of a packet, None if it is malformed, a
+/// fragment or carries options
+pub fn parse(data: &[u8]) -> Option<(Header, &[u8])> {
+    if data.len() < HEADER_LEN || data[0] != 0x45 || checksum(&data[..HEADER_LEN], 0) != 0 {
+        return None;
+    }
+    let total = u16::from_be_bytes([data[2], data[3]]) as usize;
+    let flags = u16::from_be_bytes([data[6], data[7]]);
+    if total < HEADER_LEN || total > data.len() || flags & (FLAG_MORE_FRAGMENTS | FRAGMENT_OFFSET) != 0 {
+        return None;
+    }
+    let header = Header {
+        source: Ipv4Addr::new(data[12], data[13], data[14], data[15]),
+        dest: Ipv4Addr::new(data[16], data[17], data[18], data[19]),
+        protocol: data[9],
+        ttl: data[8],
+        id: u16::from_be_bytes([data[4], data[5]]),
+    };
+    Some((header, &data[HEADER_LEN..total]))
+}
+
+/// The header of a packet quoted in an ICMP error, its total length and
+/// the bytes of payload that were quoted. The quote is cut short, so the
+/// length is not checked against it.
+pub fn parse_quoted(data: &[u8]) -> Option<(Header, usize, &[u8])> {
+    if data.len() < HEADER_LEN || data[0] != 0x45 || checksum(&data[..HEADER_LEN], 0) != 0 {
+        return None;
+    }
+    let header = Header {
+        source: Ipv4Addr::new(data[12], data[13], data[14], data[15]),
+        dest: Ipv4Addr::new(data[16], data[17], data[18], data[19]),
+        protocol: data[9],
+        ttl: data[8],
+        id: u16::from_be_bytes([data[4], data[5]]),
+    };
+    Some((header, u16::from_be_bytes([data[2], data[3]]) as usize, &data[HEADER_LEN..]))
+}
+
+pub fn build(header: &Header, payload: &[u8]) -> Vec<u8> {
+    let total = (HEADER_LEN + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total.to_be_bytes());
+    packet.extend_from_slice(&header.id.to_be_bytes());
+    packet.extend_from_slice(&FLAG_DONT_FRAGMENT.to_be_bytes());
+    packet.extend_from_slice(&[header.ttl, header.protocol, 0, 0]);
+    packet.extend_from_slice(&header.source.octets());
+    packet.extend_from_slice(&header.dest.octets());
+    let sum = checksum(&packet, 0);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Take in a packet from the card
+pub fn handle(stack: &mut Stack, data: &[u8], now: u64) {
+    let (header, payload) = match parse(data) {
+        Some(parsed) => parsed,
+        None => return,
+    };
+    let config = stack.config;
+    let broadcast = Ipv4Addr::from(u32::from(config.addr) | !u32::from(config.netmask()));
+    if header.dest != config.addr && header.dest != broadcast && header.dest != Ipv4Addr::BROADCAST {
+        return;
+    }
+    // Loopback addresses never come from outside
+    if header.source.is_loopback() {
+        return;
+    }
+    deliver(stack, &data[..HEADER_LEN + payload.len()], &header, payload, now);
+}
+
+/// Take in a packet that went round loopback
+pub fn handle_local(stack: &mut Stack, data: &[u8], now: u64) {
+    if let Some((header, payload)) = parse(data) {
+        deliver(stack, &data[..HEADER_LEN + payload.len()], &header, payload, now);
+    }
+}
+
+/// Hand `packet` to its protocol, answering with an ICMP error what
+/// nothing here takes
+fn deliver(stack: &mut Stack, packet: &[u8], header: &Header, payload: &[u8], now: u64) {
+    if !firewall::filter(Chain::Input, header.protocol, header.source, payload) {
+        return;
+    }
+    // No router passes on a packet whose TTL has run out
+    if header.ttl == 0 {
+        icmp::send_error(stack, packet, icmp::TYPE_TIME_EXCEEDED, icmp::CODE_TTL_EXCEEDED, now);
+        return;
+    }
+    match header.protocol {
+        PROTOCOL_ICMP => icmp::handle(stack, header, payload, now),
+        PROTOCOL_UDP => {
+            if !udp::handle(stack, header, payload) {
+                icmp::send_error(stack, packet, icmp::TYPE_DEST_UNREACHABLE, icmp::CODE_PORT_UNREACHABLE, now);
+            }
+        }
+        PROTOCOL_TCP => tcp::handle(stack, header, payload, now),
+        _ => icmp::send_error(stack, packet, icmp::TYPE_DEST_UNREACHABLE, icmp::CODE_PROTOCOL_UNREACHABLE, now),
+    }
+}
+
+/// Send `payload` to `dest`, by loopback if it is local. The packet waits
+/// in ARP while the next hop's MAC address is asked for; fails with
+/// InvalidArgument if it does not fit the path MTU, HostUnreachable if the
+/// next hop did not answer, and AccessDenied if the output chain drops it.
+pub fn send(stack: &mut Stack, dest: Ipv4Addr, protocol: u8, payload: &[u8], now: u64) -> NetResult<()> {
+    if HEADER_LEN + payload.len() > stack.path_mtu.get(dest, now) {
+        return Err(NetError::InvalidArgument);
+    }
+    if !firewall::filter(Chain::Output, protocol, dest, payload) {
+        return Err(NetError::AccessDenied);
+    }
+    let local = stack.is_local(dest);
+    if !local && stack.device.is_none() {
+        return Err(NetError::HostUnreachable);
+    }
+    let header = Header { source: stack.local_addr(dest), dest, protocol, ttl: DEFAULT_TTL, id: stack.ip_id };
+    stack.ip_id = stack.ip_id.wrapping_add(1);
+    let packet = build(&header, payload);
+    if local {
+        stack.loopback.send(packet);
+        return Ok(());
+    }
+    let hop = stack.config.next_hop(dest);
+    arp::send(stack, hop, packet, now)
+}
+
+/// Path MTUs learned from fragmentation-needed messages
+pub struct PathMtu {
+    /// Destination to its MTU and when that stops being believed
+    entries: BTreeMap<Ipv4Addr, (usize, u64)>,
+}
+
+impl PathMtu {
+    pub fn new() -> PathMtu {
+        PathMtu { entries: BTreeMap::new() }
+    }
+
+    /// The largest packet that gets to `dest`
+    pub fn get(&self, dest: Ipv4Addr, now: u64) -> usize {
+        match self.entries.get(&dest) {
+            Some(&(mtu, until)) if now < until => mtu,
+            _ => MTU,
+        }
+    }
+
+    /// Take the MTU to `dest` down to `mtu`; false if it was no higher
+    pub fn lower(&mut self, dest: Ipv4Addr, mtu: usize, now: u64) -> bool {
+        let mtu = mtu.clamp(MIN_PATH_MTU, MTU);
+        if mtu >= self.get(dest, now) {
+            return false;
+        }
+        self.entries.insert(dest, (mtu, now + PATH_MTU_LIFETIME_MS));
+        true
+    }
+
+    /// Forget the MTUs that are no longer believed
+    pub fn expire(&mut self, now: u64) {
+        self.entries.retain(|_, &mut (_, until)| now < until);
+    }
+}
+
+/// The path MTU to guess when a packet of `len` bytes was too big for a
+/// router that did not say what would fit: the next plateau down
+pub fn plateau(len: usize) -> usize {
+    PLATEAUS.iter().copied().find(|&mtu| mtu < len).unwrap_or(MIN_PATH_MTU)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_header() {
+        let header = Header {
+            source: Ipv4Addr::new(10, 0, 2, 15),
+            dest: Ipv4Addr::new(10, 0, 2, 2),
+            protocol: PROTOCOL_UDP,
+            ttl: 64,
+            id: 7,
+        };
+        let packet = build(&header, b"hello");
+        assert_eq!(packet.len(), 25);
+        assert_eq!(parse(&packet), Some((header, &b"hello"[..])));
+        // A corrupted header fails its checksum, trailing padding is cut
+        let mut bad = packet.clone();
+        bad[8] = 1;
+        assert_eq!(parse(&bad), None);
+        let mut padded = packet.clone();
+        padded.extend_from_slice(&[0; 4]);
+        assert_eq!(parse(&padded).map(|(_, payload)| payload.len()), Some(5));
+        assert_eq!(checksum(&[0x45, 0x00, 0x00, 0x1c], 0), !0x451cu16);
+    }
+
+    #[test_case]
+    fn test_path_mtu() {
+        let dest = Ipv4Addr::new(192, 0, 2, 1);
+        let mut paths = PathMtu::new();
+        assert_eq!(paths.get(dest, 0), MTU);
+        assert!(paths.lower(dest, 1400, 0));
+        assert!(!paths.lower(dest, 1450, 10));
+        assert_eq!(paths.get(dest, 10), 1400);
+        // Too small to believe, and gone once it has aged out
+        assert!(paths.lower(dest, 100, 20));
+        assert_eq!(paths.get(dest, 20), MIN_PATH_MTU);
+        assert_eq!(paths.get(dest, 20 + PATH_MTU_LIFETIME_MS), MTU);
+        paths.expire(20 + PATH_MTU_LIFETIME_MS);
+        assert!(paths.entries.is_empty());
+
+        assert_eq!(plateau(1500), 1492);
+        assert_eq!(plateau(1006), 508);
+        assert_eq!(plateau(60), MIN_PATH_MTU);
+    }
+}
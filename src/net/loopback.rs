@@ -0,0 +1,92 @@
+// Loopback
+//
+// Packets for 127.0.0.0/8 and for the interface's own address never reach
+// a device: ipv4::send queues them here and poll() hands them back to
+// IPv4 as if they had arrived, so both ends of a connection run in the
+// one stack and still pass the firewall both ways. Without a network card
+// the stack comes up on loopback alone, which is enough to exercise the
+// sockets, TCP and the network commands inside QEMU with no -netdev.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+pub const ADDR: Ipv4Addr = Ipv4Addr::LOCALHOST;
+pub const PREFIX: u8 = 8;
+/// Packets queued before new ones are dropped, as a card would when its
+/// receive ring is full
+const QUEUE_LIMIT: usize = 256;
+
+pub struct Loopback {
+    queue: VecDeque<Vec<u8>>,
+    /// Packets and bytes that went round
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Loopback {
+    pub fn new() -> Loopback {
+        Loopback { queue: VecDeque::new(), packets: 0, bytes: 0 }
+    }
+
+    /// Queue a whole IPv4 packet
+    pub fn send(&mut self, packet: Vec<u8>) {
+        if self.queue.len() < QUEUE_LIMIT {
+            self.packets += 1;
+            self.bytes += packet.len() as u64;
+            self.queue.push_back(packet);
+        }
+    }
+
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        self.queue.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tcp, Config, Stack};
+    use super::*;
+
+    /// Deliver what is queued until the stack goes quiet
+    fn settle(stack: &mut Stack, now: u64) {
+        for _ in 0..32 {
+            stack.poll(now);
+        }
+    }
+
+    #[test_case]
+    fn test_tcp_over_loopback() {
+        let config = Config { addr: ADDR, prefix: PREFIX, gateway: Ipv4Addr::UNSPECIFIED, dns: Ipv4Addr::UNSPECIFIED };
+        let mut stack = Stack::new(None, config);
+        let listener = tcp::listen(&mut stack, 7).unwrap();
+        assert_eq!(tcp::listen(&mut stack, 7), Err(crate::net::NetError::AddrInUse));
+        assert_eq!(tcp::accept(&mut stack, listener), Ok(None));
+
+        let client = tcp::connect(&mut stack, ADDR, 7, 0).unwrap();
+        settle(&mut stack, 0);
+        assert_eq!(tcp::state(&stack, client), Ok(tcp::State::Established));
+        let server = tcp::accept(&mut stack, listener).unwrap().unwrap();
+        assert_eq!(tcp::accept(&mut stack, listener), Ok(None));
+
+        assert_eq!(tcp::send(&mut stack, client, b"ping", 10), Ok(4));
+        settle(&mut stack, 10);
+        let mut buf = [0; 16];
+        assert_eq!(tcp::receive(&mut stack, server, &mut buf, 20), Ok(Some(4)));
+        assert_eq!(&buf[..4], b"ping");
+
+        tcp::close(&mut stack, client, 30);
+        settle(&mut stack, 30);
+        assert_eq!(tcp::receive(&mut stack, server, &mut buf, 40), Ok(Some(0)));
+        tcp::close(&mut stack, server, 40);
+        settle(&mut stack, 40);
+        assert_eq!(tcp::state(&stack, server), Err(crate::net::NetError::NotConnected));
+
+        // Nobody listens on 9: the SYN is answered with a reset
+        tcp::unlisten(&mut stack, listener, 50);
+        let refused = tcp::connect(&mut stack, ADDR, 9, 50).unwrap();
+        settle(&mut stack, 50);
+        assert_eq!(tcp::state(&stack, refused), Err(crate::net::NetError::ConnectionRefused));
+        assert!(stack.loopback.packets > 0);
+    }
+}
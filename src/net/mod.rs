@@ -0,0 +1,334 @@
+// Networking
+//
+// A small IPv4 stack over one Ethernet interface and loopback: ARP, IPv4
+// without fragments or options, ICMP echo and errors, UDP and TCP. There is no
+// network task: poll() takes in received frames and looped-back packets,
+// answers ARP and pings, and runs the TCP timers, and it is called by
+// whoever waits on a socket and as idle work while the system waits for
+// input. All state sits behind NET;
+// socket calls hold it only while they look at their socket, never while
+// they wait. The firewall module filters packets on their way in and out
+// of IPv4.
+//
+// The interface comes up with QEMU's user networking addresses unless
+// the command line says otherwise: net.ip=ADDR/PREFIX, net.gateway=ADDR
+// and net.dns=ADDR. With no card the stack runs on loopback alone.
+
+pub mod ethernet;
+pub mod arp;
+pub mod loopback;
+pub mod ipv4;
+pub mod firewall;
+pub mod icmp;
+pub mod udp;
+pub mod tcp;
+pub mod socket;
+pub mod dns;
+pub mod resolver;
+pub mod ntp;
+pub mod tftp;
+pub mod tftpfs;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use spin::Mutex;
+use crate::hal::drivers::pit;
+use self::ethernet::MacAddr;
+
+/// Frames taken from the device per poll, so a flood cannot starve the caller
+const POLL_BUDGET: usize = 64;
+
+const DEFAULT_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+const DEFAULT_PREFIX: u8 = 24;
+const DEFAULT_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+const DEFAULT_DNS: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetError {
+    /// No interface has been brought up
+    NoDevice,
+    Timeout,
+    /// ARP got no answer for the next hop
+    HostUnreachable,
+    ConnectionRefused,
+    ConnectionReset,
+    /// The socket is closed or was never connected
+    NotConnected,
+    AddrInUse,
+    InvalidArgument,
+    /// The name does not exist
+    NameNotFound,
+    /// The server answered with something that could not be used
+    BadResponse,
+    /// The server has no such file
+    FileNotFound,
+    /// The server would not hand the file over, the firewall drops the
+    /// traffic or QSF does not let the caller send it or bind the port
+    AccessDenied,
+}
+
+impl NetError {
+    pub fn message(self) -> &'static str {
+        match self {
+            NetError::NoDevice => "no network interface",
+            NetError::Timeout => "timed out",
+            NetError::HostUnreachable => "host unreachable",
+            NetError::ConnectionRefused => "connection refused",
+            NetError::ConnectionReset => "connection reset by peer",
+            NetError::NotConnected => "not connected",
+            NetError::AddrInUse => "address in use",
+            NetError::InvalidArgument => "invalid argument",
+            NetError::NameNotFound => "name not found",
+            NetError::BadResponse => "bad response",
+            NetError::FileNotFound => "file not found",
+            NetError::AccessDenied => "access denied",
+        }
+    }
+}
+
+pub type NetResult<T> = Result<T, NetError>;
+
+/// An Ethernet adapter
+pub trait NetDevice: Send {
+    fn name(&self) -> &'static str;
+    fn mac(&self) -> MacAddr;
+    fn link_up(&self) -> bool;
+    /// Queue one frame, without the CRC
+    fn send(&mut self, frame: &[u8]) -> NetResult<()>;
+    /// The next received frame, if any
+    fn receive(&mut self) -> Option<Vec<u8>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub addr: Ipv4Addr,
+    pub prefix: u8,
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+}
+
+impl Config {
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0))
+    }
+
+    /// Where to send a packet for `dest`: itself if it is on the local
+    /// network, the gateway otherwise
+    pub fn next_hop(&self, dest: Ipv4Addr) -> Ipv4Addr {
+        let mask = u32::from(self.netmask());
+        if u32::from(dest) & mask == u32::from(self.addr) & mask {
+            dest
+        } else {
+            self.gateway
+        }
+    }
+}
+
+/// Everything behind the NET lock
+pub struct Stack {
+    /// The network card, None when running on loopback alone
+    pub device: Option<Box<dyn NetDevice>>,
+    pub loopback: loopback::Loopback,
+    pub config: Config,
+    pub arp: arp::Cache,
+    pub path_mtu: ipv4::PathMtu,
+    pub icmp: icmp::Limiter,
+    pub udp: udp::Sockets,
+    pub tcp: tcp::Sockets,
+    /// Identification field of the next IPv4 packet
+    ip_id: u16,
+}
+
+impl Stack {
+    pub fn new(device: Option<Box<dyn NetDevice>>, config: Config) -> Stack {
+        Stack {
+            device,
+            loopback: loopback::Loopback::new(),
+            config,
+            arp: arp::Cache::new(),
+            path_mtu: ipv4::PathMtu::new(),
+            icmp: icmp::Limiter::new(),
+            udp: udp::Sockets::new(),
+            tcp: tcp::Sockets::new(),
+            ip_id: 1,
+        }
+    }
+
+    /// The card's MAC address, zero without one
+    pub fn mac(&self) -> MacAddr {
+        self.device.as_ref().map_or(MacAddr::default(), |device| device.mac())
+    }
+
+    /// Whether packets for `addr` stay on this machine
+    pub fn is_local(&self, addr: Ipv4Addr) -> bool {
+        addr.is_loopback() || addr == self.config.addr
+    }
+
+    /// The source address for packets to `dest`
+    pub fn local_addr(&self, dest: Ipv4Addr) -> Ipv4Addr {
+        if dest.is_loopback() {
+            loopback::ADDR
+        } else {
+            self.config.addr
+        }
+    }
+
+    /// Take in what the card and loopback have received and run the timers
+    pub fn poll(&mut self, now: u64) {
+        for _ in 0..POLL_BUDGET {
+            match self.device.as_mut().and_then(|device| device.receive()) {
+                Some(frame) => self.handle_frame(&frame, now),
+                None => break,
+            }
+        }
+        for _ in 0..POLL_BUDGET {
+            match self.loopback.receive() {
+                Some(packet) => ipv4::handle_local(self, &packet, now),
+                None => break,
+            }
+        }
+        arp::timers(self, now);
+        self.path_mtu.expire(now);
+        tcp::timers(self, now);
+    }
+
+    fn handle_frame(&mut self, frame: &[u8], now: u64) {
+        let (header, payload) = match ethernet::parse(frame) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        let mac = self.mac();
+        if header.dest != mac && !header.dest.is_broadcast() {
+            return;
+        }
+        match header.ethertype {
+            ethernet::ETHERTYPE_ARP => arp::handle(self, payload, now),
+            ethernet::ETHERTYPE_IPV4 => ipv4::handle(self, payload, now),
+            _ => {}
+        }
+    }
+
+    /// Wrap `payload` in an Ethernet frame for `dest` and send it
+    pub fn send_frame(&mut self, dest: MacAddr, ethertype: u16, payload: &[u8]) -> NetResult<()> {
+        let frame = ethernet::build(dest, self.mac(), ethertype, payload);
+        self.device.as_mut().ok_or(NetError::NoDevice)?.send(&frame)
+    }
+}
+
+pub static NET: Mutex<Option<Stack>> = Mutex::new(None);
+
+pub fn now() -> u64 {
+    pit::get_ticks()
+}
+
+/// Take in what has been received and run the timers
+pub fn poll() {
+    crate::alloc_tag!(Net);
+    if let Some(stack) = NET.lock().as_mut() {
+        stack.poll(now());
+    }
+}
+
+pub fn config() -> Option<Config> {
+    NET.lock().as_ref().map(|stack| stack.config)
+}
+
+/// Name, MAC address and link state of the card
+pub fn interface() -> Option<(&'static str, MacAddr, bool)> {
+    let guard = NET.lock();
+    let device = guard.as_ref()?.device.as_ref()?;
+    Some((device.name(), device.mac(), device.link_up()))
+}
+
+fn parse_cidr(value: &str) -> Option<(Ipv4Addr, u8)> {
+    let (addr, prefix) = value.split_once('/').unwrap_or((value, "24"));
+    let prefix = prefix.parse().ok().filter(|&p| p <= 32)?;
+    Some((addr.parse().ok()?, prefix))
+}
+
+/// Addresses from the command line, QEMU's defaults for the rest
+fn boot_config() -> Config {
+    use crate::kernel::get_param;
+    let mut config = Config { addr: DEFAULT_ADDR, prefix: DEFAULT_PREFIX, gateway: DEFAULT_GATEWAY, dns: DEFAULT_DNS };
+    if let Some(value) = get_param("net.ip") {
+        match parse_cidr(&value) {
+            Some((addr, prefix)) => (config.addr, config.prefix) = (addr, prefix),
+            None => crate::println!("  [NET] net.ip={}: bad address", value),
+        }
+    }
+    for (param, field) in [("net.gateway", &mut config.gateway), ("net.dns", &mut config.dns)] {
+        if let Some(value) = get_param(param) {
+            match value.parse() {
+                Ok(addr) => *field = addr,
+                Err(_) => crate::println!("  [NET] {}={}: bad address", param, value),
+            }
+        }
+    }
+    config
+}
+
+/// Bring the stack up on `device`; only the first one is used
+pub fn register_device(device: Box<dyn NetDevice>) {
+    let mut guard = NET.lock();
+    if guard.as_ref().is_some_and(|stack| stack.device.is_some()) {
+        crate::println!("  [NET] {} left unused, one interface is supported", device.name());
+        return;
+    }
+    let config = boot_config();
+    crate::println!(
+        "  [NET] {} {} {}/{} gateway {} dns {}",
+        device.name(),
+        device.mac(),
+        config.addr,
+        config.prefix,
+        config.gateway,
+        config.dns
+    );
+    let stack = match guard.as_mut() {
+        // Up on loopback already: the card came later
+        Some(stack) => {
+            stack.device = Some(device);
+            stack.config = config;
+            stack
+        }
+        None => guard.insert(Stack::new(Some(device), config)),
+    };
+    arp::announce(stack);
+    drop(guard);
+    crate::kernel::idle::register(poll);
+    ntp::init();
+}
+
+/// Bring the stack up on loopback alone if no card has registered
+pub fn init() {
+    let mut guard = NET.lock();
+    if guard.is_some() {
+        return;
+    }
+    let config = Config {
+        addr: loopback::ADDR,
+        prefix: loopback::PREFIX,
+        gateway: Ipv4Addr::UNSPECIFIED,
+        dns: Ipv4Addr::UNSPECIFIED,
+    };
+    *guard = Some(Stack::new(None, config));
+    drop(guard);
+    crate::println!("  [NET] No network card, loopback only ({}/{})", loopback::ADDR, loopback::PREFIX);
+    crate::kernel::idle::register(poll);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_routing() {
+        let config = Config { addr: DEFAULT_ADDR, prefix: 24, gateway: DEFAULT_GATEWAY, dns: DEFAULT_DNS };
+        assert_eq!(config.netmask(), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(config.next_hop(Ipv4Addr::new(10, 0, 2, 3)), Ipv4Addr::new(10, 0, 2, 3));
+        assert_eq!(config.next_hop(Ipv4Addr::new(93, 184, 216, 34)), DEFAULT_GATEWAY);
+        assert_eq!(parse_cidr("192.168.1.9/16"), Some((Ipv4Addr::new(192, 168, 1, 9), 16)));
+        assert_eq!(parse_cidr("192.168.1.9/33"), None);
+    }
+}
@@ -0,0 +1,453 @@
+// SNTP client
+//
+// Keeps the wall clock in step with an NTP server, as RFC 4330 describes.
+// The client is idle work: when a poll is due tick() sends one request,
+// and later calls pick up the answer, so nothing ever waits on the server.
+// Offsets up to STEP_THRESHOLD_MS are slewed in and larger ones stepped.
+// Whatever offset is left at the next poll is put down to the PIT running
+// fast or slow, and a share of it goes into the clock's frequency
+// correction. After each sync the RTC is set too, unless told not to.
+//
+// The server is pool.ntp.org unless the command line says ntp.server=NAME;
+// ntp.server=off starts with the client disabled and ntp.rtc=0 leaves the
+// RTC alone. The poll interval starts at 64 s and doubles up to 1024 s
+// while the clock stays within the step threshold.
+
+use alloc::string::String;
+use core::net::Ipv4Addr;
+use spin::Mutex;
+use crate::kernel::time;
+use super::{resolver, udp, NetError, NetResult, NET};
+
+pub const PORT: u16 = 123;
+const PACKET_LEN: usize = 48;
+/// Seconds from 1900, where NTP time starts, to 1970
+const UNIX_EPOCH: u64 = 2_208_988_800;
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+/// Leap indicator of a server whose own clock is not set
+pub const LEAP_UNSYNCHRONIZED: u8 = 3;
+/// Stratum 0 is a kiss-o'-death, 16 and up unsynchronized
+const MAX_STRATUM: u8 = 15;
+
+pub const DEFAULT_SERVER: &str = "pool.ntp.org";
+/// Offsets beyond this are stepped rather than slewed
+pub const STEP_THRESHOLD_MS: i64 = 128;
+pub const MIN_POLL_S: u64 = 64;
+pub const MAX_POLL_S: u64 = 1024;
+/// Wait after a failed poll before the next
+const RETRY_S: u64 = 16;
+const REPLY_TIMEOUT_MS: u64 = 2000;
+/// One over the share of a measured frequency error corrected at a time
+const FREQ_GAIN: i64 = 4;
+
+/// Unix milliseconds as an NTP timestamp: seconds since 1900 in the top
+/// half, the fraction in the bottom
+pub fn to_timestamp(unix_ms: u64) -> u64 {
+    let secs = (unix_ms / 1000 + UNIX_EPOCH) & 0xFFFF_FFFF;
+    let fraction = ((unix_ms % 1000) << 32) / 1000;
+    (secs << 32) | fraction
+}
+
+/// An NTP timestamp as Unix milliseconds, rounded; seconds below 1970 are
+/// taken to be in the next era, which starts in 2036
+pub fn from_timestamp(timestamp: u64) -> u64 {
+    let mut secs = timestamp >> 32;
+    if secs < UNIX_EPOCH {
+        secs += 1 << 32;
+    }
+    (secs - UNIX_EPOCH) * 1000 + (((timestamp & 0xFFFF_FFFF) * 1000 + (1 << 31)) >> 32)
+}
+
+/// A client request whose transmit timestamp is `origin`
+pub fn build_request(origin: u64) -> [u8; PACKET_LEN] {
+    let mut packet = [0; PACKET_LEN];
+    packet[0] = VERSION << 3 | MODE_CLIENT;
+    packet[40..48].copy_from_slice(&origin.to_be_bytes());
+    packet
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    pub leap: u8,
+    pub stratum: u8,
+    /// When the server got the request and sent the reply, Unix ms
+    pub receive: u64,
+    pub transmit: u64,
+}
+
+/// The server's answer to the request sent with timestamp `origin`
+pub fn parse_reply(data: &[u8], origin: u64) -> NetResult<Reply> {
+    if data.len() < PACKET_LEN {
+        return Err(NetError::BadResponse);
+    }
+    let be64 = |at: usize| u64::from_be_bytes(data[at..at + 8].try_into().unwrap());
+    let (leap, version, mode) = (data[0] >> 6, data[0] >> 3 & 7, data[0] & 7);
+    let stratum = data[1];
+    // A reply that does not echo our timestamp is stale or forged
+    if mode != MODE_SERVER || !(1..=VERSION).contains(&version) || be64(24) != origin {
+        return Err(NetError::BadResponse);
+    }
+    if leap == LEAP_UNSYNCHRONIZED || stratum == 0 || stratum > MAX_STRATUM || be64(40) == 0 {
+        return Err(NetError::BadResponse);
+    }
+    Ok(Reply { leap, stratum, receive: from_timestamp(be64(32)), transmit: from_timestamp(be64(40)) })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// How far the server is ahead of us
+    pub offset_ms: i64,
+    /// Round trip, less the time the server held the request
+    pub delay_ms: i64,
+}
+
+impl Sample {
+    /// From our send time `t1`, the server's receive and transmit times
+    /// `t2` and `t3`, and our receive time `t4`
+    pub fn new(t1: u64, t2: u64, t3: u64, t4: u64) -> Sample {
+        let (t1, t2, t3, t4) = (t1 as i64, t2 as i64, t3 as i64, t4 as i64);
+        Sample { offset_ms: ((t2 - t1) + (t3 - t4)) / 2, delay_ms: ((t4 - t1) - (t3 - t2)).max(0) }
+    }
+}
+
+/// The frequency error, parts per billion, shown by finding the clock
+/// `offset_ms` off `elapsed_ms` after the last correction, with
+/// `pending_ms` of that correction still to be slewed in
+pub fn frequency_error(offset_ms: i64, pending_ms: i64, elapsed_ms: u64) -> i64 {
+    if elapsed_ms == 0 {
+        return 0;
+    }
+    (offset_ms - pending_ms).saturating_mul(1_000_000_000) / elapsed_ms as i64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub enabled: bool,
+    pub server: String,
+    /// The server's address when it was last looked up
+    pub addr: Option<Ipv4Addr>,
+    /// Whether each sync also sets the RTC
+    pub rtc_sync: bool,
+    pub interval_s: u64,
+    pub leap: u8,
+    pub stratum: u8,
+    pub last: Option<Sample>,
+    /// Unix time of the last good reply
+    pub last_sync: Option<u64>,
+    pub packets: u32,
+    pub failures: u32,
+    pub steps: u32,
+    /// Why the last poll failed, if it did
+    pub error: Option<NetError>,
+}
+
+/// A request waiting for its reply
+struct Pending {
+    socket: udp::SocketId,
+    addr: Ipv4Addr,
+    /// Unix time it was sent, and as the timestamp it carries
+    t1: u64,
+    origin: u64,
+    /// Uptime when the poll started
+    started: u64,
+    /// Whether it has left yet; the first tries can wait on ARP
+    sent: bool,
+}
+
+struct Client {
+    status: Status,
+    pending: Option<Pending>,
+    /// Uptime of the last slewed correction, where the frequency
+    /// estimate starts
+    anchor: Option<u64>,
+    /// Uptime of the next poll
+    next_poll: u64,
+}
+
+static CLIENT: Mutex<Client> = Mutex::new(Client {
+    status: Status {
+        enabled: false,
+        server: String::new(),
+        addr: None,
+        rtc_sync: true,
+        interval_s: MIN_POLL_S,
+        leap: 0,
+        stratum: 0,
+        last: None,
+        last_sync: None,
+        packets: 0,
+        failures: 0,
+        steps: 0,
+        error: None,
+    },
+    pending: None,
+    anchor: None,
+    next_poll: 0,
+});
+
+impl Client {
+    /// Apply a good sample and schedule the next poll
+    fn discipline(&mut self, reply: Reply, sample: Sample, now: u64) {
+        let status = &mut self.status;
+        (status.leap, status.stratum, status.last, status.error) = (reply.leap, reply.stratum, Some(sample), None);
+        status.packets += 1;
+        if sample.offset_ms.abs() > STEP_THRESHOLD_MS {
+            time::step(sample.offset_ms);
+            status.steps += 1;
+            status.interval_s = MIN_POLL_S;
+            self.anchor = None;
+        } else {
+            // Samples close together say more about noise than drift
+            if let Some(anchor) = self.anchor.filter(|&anchor| now - anchor >= MIN_POLL_S * 1000) {
+                let error = frequency_error(sample.offset_ms, time::pending_adjustment(), now - anchor);
+                time::set_frequency(time::frequency() + error / FREQ_GAIN);
+            }
+            time::adjust(sample.offset_ms);
+            status.interval_s = (status.interval_s * 2).min(MAX_POLL_S);
+            self.anchor = Some(now);
+        }
+        if status.rtc_sync {
+            time::sync_rtc();
+        }
+        status.last_sync = Some(time::now());
+        self.next_poll = now + status.interval_s * 1000;
+    }
+
+    fn fail(&mut self, error: NetError, now: u64) {
+        self.status.failures += 1;
+        self.status.error = Some(error);
+        self.next_poll = now + RETRY_S * 1000;
+    }
+
+    /// Send the request if it has not gone, else look for the reply
+    fn exchange(&mut self, now: u64) -> Option<NetResult<(Reply, Sample)>> {
+        let pending = self.pending.as_mut()?;
+        let mut guard = NET.lock();
+        let stack = match guard.as_mut() {
+            Some(stack) => stack,
+            None => return Some(Err(NetError::NoDevice)),
+        };
+        if !pending.sent {
+            pending.t1 = time::now_ms();
+            pending.origin = to_timestamp(pending.t1);
+            let request = build_request(pending.origin);
+            match udp::send(stack, pending.socket, pending.addr, PORT, &request, now) {
+                Ok(()) => pending.sent = true,
+                // ARP has been asked; try again next time
+                Err(NetError::HostUnreachable) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        } else {
+            loop {
+                let datagram = match stack.udp.receive(pending.socket) {
+                    Ok(Some(datagram)) => datagram,
+                    Ok(None) => break,
+                    Err(e) => return Some(Err(e)),
+                };
+                if datagram.source != pending.addr || datagram.port != PORT {
+                    continue;
+                }
+                if let Ok(reply) = parse_reply(&datagram.data, pending.origin) {
+                    let sample = Sample::new(pending.t1, reply.receive, reply.transmit, time::now_ms());
+                    return Some(Ok((reply, sample)));
+                }
+            }
+        }
+        if now.saturating_sub(pending.started) >= REPLY_TIMEOUT_MS {
+            return Some(Err(if pending.sent { NetError::Timeout } else { NetError::HostUnreachable }));
+        }
+        None
+    }
+
+    /// Drop the request in progress
+    fn finish(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            close(pending.socket);
+        }
+    }
+}
+
+fn close(socket: udp::SocketId) {
+    if let Some(stack) = NET.lock().as_mut() {
+        stack.udp.close(socket);
+    }
+}
+
+/// Start a poll of the server at `addr`
+fn start(addr: Ipv4Addr, now: u64) -> NetResult<Pending> {
+    let socket = match NET.lock().as_mut() {
+        Some(stack) => stack.udp.bind(0)?.0,
+        None => return Err(NetError::NoDevice),
+    };
+    Ok(Pending { socket, addr, t1: 0, origin: 0, started: now, sent: false })
+}
+
+/// Move the client along: start a poll when one is due, send the request,
+/// or take in the reply
+fn service() {
+    let now = super::now();
+    let server = {
+        let mut client = CLIENT.lock();
+        if client.pending.is_none() {
+            if now < client.next_poll {
+                return;
+            }
+            client.status.server.clone()
+        } else {
+            if let Some(result) = client.exchange(now) {
+                client.finish();
+                match result {
+                    Ok((reply, sample)) => client.discipline(reply, sample, now),
+                    Err(e) => client.fail(e, now),
+                }
+            }
+            return;
+        }
+    };
+
+    // The lookup may have to ask a name server, so it runs unlocked
+    let result = resolver::resolve(&server).and_then(|addr| start(addr, now).map(|pending| (addr, pending)));
+    let mut client = CLIENT.lock();
+    if client.status.server != server || client.pending.is_some() {
+        // Reconfigured meanwhile
+        drop(client);
+        if let Ok((_, pending)) = result {
+            close(pending.socket);
+        }
+        return;
+    }
+    match result {
+        Ok((addr, pending)) => {
+            client.status.addr = Some(addr);
+            client.pending = Some(pending);
+        }
+        Err(e) => client.fail(e, now),
+    }
+}
+
+/// Idle work: poll the server when it is time
+pub fn tick() {
+    if CLIENT.lock().status.enabled {
+        service();
+    }
+}
+
+/// Poll the server now, enabled or not, and wait for the outcome
+pub fn sync() -> NetResult<Sample> {
+    let before = {
+        let mut client = CLIENT.lock();
+        if client.pending.is_none() {
+            client.next_poll = 0;
+        }
+        client.status.packets + client.status.failures
+    };
+    loop {
+        super::poll();
+        service();
+        {
+            let client = CLIENT.lock();
+            if client.status.packets + client.status.failures != before {
+                return match client.status.error {
+                    Some(e) => Err(e),
+                    None => Ok(client.status.last.unwrap()),
+                };
+            }
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+pub fn status() -> Status {
+    CLIENT.lock().status.clone()
+}
+
+/// Start or stop polling; starting polls at once
+pub fn set_enabled(enabled: bool) {
+    let mut client = CLIENT.lock();
+    client.status.enabled = enabled;
+    client.next_poll = 0;
+    if !enabled {
+        client.finish();
+    }
+}
+
+/// Use `server` from now on, starting over with it
+pub fn set_server(server: &str) {
+    let mut client = CLIENT.lock();
+    client.finish();
+    client.status.server = String::from(server);
+    client.status.addr = None;
+    client.status.interval_s = MIN_POLL_S;
+    client.anchor = None;
+    client.next_poll = 0;
+}
+
+pub fn set_rtc_sync(rtc_sync: bool) {
+    CLIENT.lock().status.rtc_sync = rtc_sync;
+}
+
+/// Configure the client from the command line and start it
+pub fn init() {
+    use crate::kernel::get_param;
+    let server = get_param("ntp.server").unwrap_or_else(|| String::from(DEFAULT_SERVER));
+    {
+        let mut client = CLIENT.lock();
+        client.status.enabled = server != "off";
+        client.status.server = if server == "off" { String::from(DEFAULT_SERVER) } else { server };
+        client.status.rtc_sync = get_param("ntp.rtc").map_or(true, |value| value != "0");
+    }
+    crate::kernel::idle::register(tick);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_timestamps() {
+        // 2024-02-29 23:59:58.500
+        let ms = 1_709_251_198_500;
+        let timestamp = to_timestamp(ms);
+        assert_eq!(timestamp >> 32, 1_709_251_198 + UNIX_EPOCH);
+        assert_eq!(timestamp & 0xFFFF_FFFF, 1 << 31);
+        assert_eq!(from_timestamp(timestamp), ms);
+        // Past 2036 the seconds wrap into era 1
+        let later = 2_208_988_800_000 + 1_000;
+        assert_eq!(to_timestamp(later) >> 32, 1 + UNIX_EPOCH * 2 - (1 << 32));
+        assert_eq!(from_timestamp(to_timestamp(later)), later);
+    }
+
+    #[test_case]
+    fn test_reply() {
+        let origin = to_timestamp(1_000_000);
+        let request = build_request(origin);
+        assert_eq!(request[0], 0x23);
+
+        let mut reply = [0u8; PACKET_LEN];
+        reply[0] = 0x24;
+        reply[1] = 2;
+        reply[24..32].copy_from_slice(&origin.to_be_bytes());
+        reply[32..40].copy_from_slice(&to_timestamp(1_000_150).to_be_bytes());
+        reply[40..48].copy_from_slice(&to_timestamp(1_000_160).to_be_bytes());
+        let parsed = parse_reply(&reply, origin).unwrap();
+        assert_eq!((parsed.stratum, parsed.receive, parsed.transmit), (2, 1_000_150, 1_000_160));
+        assert_eq!(parse_reply(&reply, origin + 1), Err(NetError::BadResponse));
+        assert_eq!(parse_reply(&reply[..47], origin), Err(NetError::BadResponse));
+        // Kiss-o'-death
+        reply[1] = 0;
+        assert_eq!(parse_reply(&reply, origin), Err(NetError::BadResponse));
+    }
+
+    #[test_case]
+    fn test_sample() {
+        // Sent at 1000 by us, 20 ms each way, the server 100 ms ahead
+        // and holding the request 5 ms
+        let sample = Sample::new(1000, 1120, 1125, 1045);
+        assert_eq!(sample, Sample { offset_ms: 100, delay_ms: 40 });
+        // 64 ms behind after 64 s with nothing pending is 1000 ppm slow
+        assert_eq!(frequency_error(64, 0, 64_000), 1_000_000);
+        assert_eq!(frequency_error(10, 10, 64_000), 0);
+    }
+}
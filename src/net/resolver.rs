@@ -0,0 +1,279 @@
+// Name resolution
+//
+// lookup() answers from /etc/hosts, then from the cache, then by asking
+// the name servers in /etc/resolv.conf in turn, or the interface's DNS
+// server when the file names none. Both files are read again on every
+// lookup that gets that far, so edits apply at once; neither has to exist.
+//
+// Answers are cached for their TTL, up to an hour, and names a server
+// said do not exist for a minute. Timeouts and other failures are not
+// cached, so the next lookup asks again.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use spin::Mutex;
+use crate::fs::vfs::api as vfs_api;
+use super::{dns, NetError, NetResult};
+
+pub const RESOLV_CONF: &str = "/etc/resolv.conf";
+pub const HOSTS: &str = "/etc/hosts";
+
+/// Servers used from resolv.conf, as in glibc
+const MAX_NAMESERVERS: usize = 3;
+const DEFAULT_TIMEOUT_S: u64 = 2;
+const DEFAULT_ATTEMPTS: usize = 3;
+const MAX_TIMEOUT_S: u64 = 30;
+const MAX_ATTEMPTS: usize = 5;
+
+const CACHE_SIZE: usize = 64;
+const MAX_TTL_S: u64 = 3600;
+const NEGATIVE_TTL_S: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvConf {
+    pub nameservers: Vec<Ipv4Addr>,
+    pub timeout_s: u64,
+    pub attempts: usize,
+}
+
+impl ResolvConf {
+    /// `nameserver ADDR` lines and `options timeout:N attempts:N`; other
+    /// lines, and IPv6 servers, are ignored
+    pub fn parse(text: &str) -> ResolvConf {
+        let mut conf = ResolvConf { nameservers: Vec::new(), timeout_s: DEFAULT_TIMEOUT_S, attempts: DEFAULT_ATTEMPTS };
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    if let Some(addr) = words.next().and_then(|w| w.parse().ok()) {
+                        if conf.nameservers.len() < MAX_NAMESERVERS {
+                            conf.nameservers.push(addr);
+                        }
+                    }
+                }
+                Some("options") => {
+                    for option in words {
+                        match option.split_once(':') {
+                            Some(("timeout", n)) => {
+                                if let Ok(n) = n.parse::<u64>() {
+                                    conf.timeout_s = n.clamp(1, MAX_TIMEOUT_S);
+                                }
+                            }
+                            Some(("attempts", n)) => {
+                                if let Ok(n) = n.parse::<usize>() {
+                                    conf.attempts = n.clamp(1, MAX_ATTEMPTS);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        conf
+    }
+}
+
+/// The addresses /etc/hosts gives `name`, matching names and aliases
+/// without regard to case
+pub fn parse_hosts(text: &str, name: &str) -> Vec<Ipv4Addr> {
+    let mut addrs = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let addr = match words.next().and_then(|w| w.parse::<Ipv4Addr>().ok()) {
+            Some(addr) => addr,
+            None => continue,
+        };
+        if words.any(|w| w.eq_ignore_ascii_case(name)) && !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+    addrs
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The name was an address
+    Literal,
+    Hosts,
+    Cache,
+    Server(Ipv4Addr),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub addrs: Vec<Ipv4Addr>,
+    pub source: Source,
+}
+
+struct CacheEntry {
+    /// The addresses, or an empty list for a name that does not exist
+    addrs: Vec<Ipv4Addr>,
+    expires: u64,
+}
+
+static CACHE: Mutex<BTreeMap<String, CacheEntry>> = Mutex::new(BTreeMap::new());
+
+fn cache_get(name: &str, now: u64) -> Option<NetResult<Vec<Ipv4Addr>>> {
+    let mut cache = CACHE.lock();
+    let entry = cache.get(name)?;
+    if now >= entry.expires {
+        cache.remove(name);
+        return None;
+    }
+    Some(match entry.addrs.is_empty() {
+        true => Err(NetError::NameNotFound),
+        false => Ok(entry.addrs.clone()),
+    })
+}
+
+fn cache_put(name: &str, addrs: Vec<Ipv4Addr>, ttl_s: u64, now: u64) {
+    if ttl_s == 0 {
+        return;
+    }
+    let mut cache = CACHE.lock();
+    if cache.len() >= CACHE_SIZE && !cache.contains_key(name) {
+        // Make room by dropping whatever would expire first
+        let soonest = cache.iter().min_by_key(|(_, entry)| entry.expires).map(|(name, _)| name.clone());
+        if let Some(soonest) = soonest {
+            cache.remove(&soonest);
+        }
+    }
+    cache.insert(name.to_string(), CacheEntry { addrs, expires: now + ttl_s * 1000 });
+}
+
+/// Forget every cached answer
+pub fn flush_cache() {
+    CACHE.lock().clear();
+}
+
+/// Cached names with their addresses (empty if the name does not exist)
+/// and the seconds they have left
+pub fn cache_entries() -> Vec<(String, Vec<Ipv4Addr>, u64)> {
+    let now = super::now();
+    CACHE
+        .lock()
+        .iter()
+        .filter(|(_, entry)| entry.expires > now)
+        .map(|(name, entry)| (name.clone(), entry.addrs.clone(), (entry.expires - now) / 1000))
+        .collect()
+}
+
+fn read_text(path: &str) -> Option<String> {
+    vfs_api::read_file(path).ok().and_then(|data| String::from_utf8(data).ok())
+}
+
+/// The resolver configuration in effect
+pub fn resolv_conf() -> ResolvConf {
+    let mut conf = ResolvConf::parse(&read_text(RESOLV_CONF).unwrap_or_default());
+    if conf.nameservers.is_empty() {
+        if let Some(config) = super::config() {
+            conf.nameservers.push(config.dns);
+        }
+    }
+    conf
+}
+
+/// Ask `server` directly, bypassing the hosts file and the cache
+pub fn query(server: Ipv4Addr, name: &str) -> NetResult<Vec<Ipv4Addr>> {
+    let conf = resolv_conf();
+    dns::query(server, name, conf.timeout_s * 1000, conf.attempts).map(|response| response.addrs)
+}
+
+/// Every IPv4 address of `name` and where they came from
+pub fn lookup(name: &str) -> NetResult<Answer> {
+    if let Ok(addr) = name.parse() {
+        return Ok(Answer { addrs: alloc::vec![addr], source: Source::Literal });
+    }
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    if name.is_empty() {
+        return Err(NetError::InvalidArgument);
+    }
+    if let Some(text) = read_text(HOSTS) {
+        let addrs = parse_hosts(&text, &name);
+        if !addrs.is_empty() {
+            return Ok(Answer { addrs, source: Source::Hosts });
+        }
+    } else if name == "localhost" {
+        return Ok(Answer { addrs: alloc::vec![Ipv4Addr::LOCALHOST], source: Source::Hosts });
+    }
+    if let Some(result) = cache_get(&name, super::now()) {
+        return result.map(|addrs| Answer { addrs, source: Source::Cache });
+    }
+
+    let conf = resolv_conf();
+    let mut error = NetError::NoDevice;
+    for &server in &conf.nameservers {
+        match dns::query(server, &name, conf.timeout_s * 1000, conf.attempts) {
+            Ok(response) => {
+                cache_put(&name, response.addrs.clone(), (response.ttl as u64).min(MAX_TTL_S), super::now());
+                return Ok(Answer { addrs: response.addrs, source: Source::Server(server) });
+            }
+            Err(NetError::NameNotFound) => {
+                cache_put(&name, Vec::new(), NEGATIVE_TTL_S, super::now());
+                return Err(NetError::NameNotFound);
+            }
+            // Try the next server
+            Err(e) => error = e,
+        }
+    }
+    Err(error)
+}
+
+/// The first IPv4 address of `name`
+pub fn resolve(name: &str) -> NetResult<Ipv4Addr> {
+    lookup(name).map(|answer| answer.addrs[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_resolv_conf() {
+        let conf = ResolvConf::parse(
+            "# local\nsearch lan\nnameserver 192.168.1.1\nnameserver ::1\nnameserver 8.8.8.8\n\
+             nameserver 1.1.1.1\nnameserver 9.9.9.9\noptions ndots:1 timeout:0 attempts:9\n",
+        );
+        let servers = [Ipv4Addr::new(192, 168, 1, 1), Ipv4Addr::new(8, 8, 8, 8), Ipv4Addr::new(1, 1, 1, 1)];
+        assert_eq!(conf, ResolvConf { nameservers: servers.to_vec(), timeout_s: 1, attempts: MAX_ATTEMPTS });
+        assert_eq!(ResolvConf::parse("").timeout_s, DEFAULT_TIMEOUT_S);
+    }
+
+    #[test_case]
+    fn test_hosts() {
+        let hosts = "127.0.0.1 localhost\n::1 localhost\n10.0.2.2 gateway host # the QEMU host\n\
+                     10.0.2.9 Host\n# 10.0.2.10 host\n";
+        assert_eq!(parse_hosts(hosts, "localhost"), [Ipv4Addr::LOCALHOST]);
+        assert_eq!(parse_hosts(hosts, "host"), [Ipv4Addr::new(10, 0, 2, 2), Ipv4Addr::new(10, 0, 2, 9)]);
+        assert!(parse_hosts(hosts, "QEMU").is_empty());
+    }
+
+    #[test_case]
+    fn test_cache() {
+        flush_cache();
+        let addr = Ipv4Addr::new(10, 0, 0, 1);
+        cache_put("a.test", alloc::vec![addr], 10, 0);
+        cache_put("gone.test", Vec::new(), NEGATIVE_TTL_S, 0);
+        cache_put("zero.test", alloc::vec![addr], 0, 0);
+        assert_eq!(cache_get("a.test", 9_999), Some(Ok(alloc::vec![addr])));
+        assert_eq!(cache_get("a.test", 10_000), None);
+        assert_eq!(cache_get("gone.test", 1), Some(Err(NetError::NameNotFound)));
+        assert_eq!(cache_get("zero.test", 1), None);
+
+        // A full cache gives up the entry closest to expiring
+        flush_cache();
+        for i in 0..CACHE_SIZE as u64 {
+            cache_put(&alloc::format!("{}.test", i), alloc::vec![addr], 100 + i, 0);
+        }
+        cache_put("new.test", alloc::vec![addr], 1, 0);
+        assert!(cache_get("0.test", 0).is_none());
+        assert!(cache_get("1.test", 0).is_some());
+        assert!(cache_get("new.test", 0).is_some());
+        flush_cache();
+    }
+}
@@ -0,0 +1,229 @@
+// Blocking sockets
+//
+// Handles for kernel code and shell commands. Every blocking call loops
+// polling the stack and sleeping until a timer tick, so the stack runs as
+// long as somebody is waiting on it. Connecting or sending to somewhere
+// the firewall's output chain drops fails straight away.
+//
+// QSF is asked on behalf of the calling task before a socket connects,
+// sends a datagram, binds a port or listens: its confinement and the
+// `net:` rules for its user apply, and ports below 1024 need
+// CAP_NET_BIND_SERVICE. Refusals come back as AccessDenied and are in
+// the audit log. Ephemeral ports are not checked when bound; what is sent
+// from them is.
+
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use crate::hal::drivers::pit;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::{self, AccessDecision, NetDirection};
+use x86_64::instructions::interrupts;
+use super::ipv4::{PROTOCOL_TCP, PROTOCOL_UDP};
+use super::{arp, firewall, poll, tcp, udp, NetError, NetResult, Stack, NET};
+
+/// How long a connection waits on a silent peer before failing
+pub const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+
+/// Run `f` on the stack
+fn with_stack<T>(f: impl FnOnce(&mut Stack, u64) -> NetResult<T>) -> NetResult<T> {
+    let now = super::now();
+    match NET.lock().as_mut() {
+        Some(stack) => f(stack, now),
+        None => Err(NetError::NoDevice),
+    }
+}
+
+/// Poll until `f` returns something or `timeout_ms` passes
+fn wait<T>(timeout_ms: u64, mut f: impl FnMut(&mut Stack, u64) -> NetResult<Option<T>>) -> NetResult<T> {
+    let timer = pit::Timer::new(timeout_ms);
+    loop {
+        poll();
+        if let Some(value) = with_stack(&mut f)? {
+            return Ok(value);
+        }
+        if timer.is_expired() {
+            return Err(NetError::Timeout);
+        }
+        if interrupts::are_enabled() {
+            x86_64::instructions::hlt();
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// The calling task's pid and effective uid, the kernel's if there is
+/// no task
+fn caller() -> (u32, u32) {
+    SCHEDULER.lock().current().map_or((0, 0), |task| (task.pid, task.euid))
+}
+
+fn allowed(decision: AccessDecision) -> NetResult<()> {
+    match decision {
+        AccessDecision::Deny => Err(NetError::AccessDenied),
+        AccessDecision::Allow | AccessDecision::Audit => Ok(()),
+    }
+}
+
+/// Ask QSF whether the caller may send to `addr`:`port`, and the firewall
+/// whether it would let the traffic out
+fn check_outbound(protocol: u8, addr: Ipv4Addr, port: u16) -> NetResult<()> {
+    if !firewall::permits(protocol, addr, port) {
+        return Err(NetError::AccessDenied);
+    }
+    let (pid, uid) = caller();
+    allowed(qsf::check_network(pid, uid, addr, port, NetDirection::Outbound))
+}
+
+/// Ask QSF whether the caller may bind `port`; 0 picks an ephemeral
+/// port, which anyone may have
+fn check_bind(port: u16) -> NetResult<()> {
+    if port == 0 {
+        return Ok(());
+    }
+    let (pid, uid) = caller();
+    allowed(qsf::check_bind(pid, uid, port))
+}
+
+/// Find the next hop's MAC address before anything is sent to `dest`
+fn resolve_next_hop(dest: Ipv4Addr) -> NetResult<()> {
+    wait(3000, |stack, now| {
+        if stack.is_local(dest) {
+            return Ok(Some(()));
+        }
+        if stack.device.is_none() {
+            return Err(NetError::HostUnreachable);
+        }
+        let hop = stack.config.next_hop(dest);
+        Ok(arp::resolve(stack, hop, now)?.map(|_| ()))
+    })
+    .map_err(|e| if e == NetError::Timeout { NetError::HostUnreachable } else { e })
+}
+
+pub struct TcpStream {
+    id: tcp::SocketId,
+    timeout_ms: u64,
+}
+
+impl TcpStream {
+    pub fn connect(addr: Ipv4Addr, port: u16, timeout_ms: u64) -> NetResult<TcpStream> {
+        check_outbound(PROTOCOL_TCP, addr, port)?;
+        resolve_next_hop(addr)?;
+        let id = with_stack(|stack, now| tcp::connect(stack, addr, port, now))?;
+        let stream = TcpStream { id, timeout_ms };
+        wait(timeout_ms, |stack, _| match tcp::state(stack, id)? {
+            tcp::State::SynSent => Ok(None),
+            _ => Ok(Some(())),
+        })?;
+        Ok(stream)
+    }
+
+    /// Read what has arrived, waiting for something; 0 at end of stream
+    pub fn read(&mut self, buf: &mut [u8]) -> NetResult<usize> {
+        let id = self.id;
+        wait(self.timeout_ms, |stack, now| tcp::receive(stack, id, buf, now))
+    }
+
+    /// Queue all of `data`, waiting while the send buffer is full
+    pub fn write_all(&mut self, mut data: &[u8]) -> NetResult<()> {
+        let id = self.id;
+        while !data.is_empty() {
+            let n = wait(self.timeout_ms, |stack, now| match tcp::send(stack, id, data, now)? {
+                0 => Ok(None),
+                n => Ok(Some(n)),
+            })?;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+}
+
+/// A listening TCP socket
+pub struct TcpListener {
+    id: tcp::SocketId,
+    port: u16,
+}
+
+impl TcpListener {
+    pub fn bind(port: u16) -> NetResult<TcpListener> {
+        check_bind(port)?;
+        let id = with_stack(|stack, _| tcp::listen(stack, port))?;
+        Ok(TcpListener { id, port })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The next connection to come in, waiting up to `timeout_ms` for one
+    pub fn accept(&self, timeout_ms: u64) -> NetResult<TcpStream> {
+        let listener = self.id;
+        let id = wait(timeout_ms, |stack, _| tcp::accept(stack, listener))?;
+        Ok(TcpStream { id, timeout_ms: DEFAULT_TIMEOUT_MS })
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let id = self.id;
+        with_stack(|stack, now| {
+            tcp::unlisten(stack, id, now);
+            Ok(())
+        })
+        .ok();
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let id = self.id;
+        with_stack(|stack, now| {
+            tcp::close(stack, id, now);
+            Ok(())
+        })
+        .ok();
+    }
+}
+
+pub struct UdpSocket {
+    id: udp::SocketId,
+    port: u16,
+}
+
+impl UdpSocket {
+    /// Bind `port`, or a free one if it is 0
+    pub fn bind(port: u16) -> NetResult<UdpSocket> {
+        check_bind(port)?;
+        let (id, port) = with_stack(|stack, _| stack.udp.bind(port))?;
+        Ok(UdpSocket { id, port })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn send_to(&self, data: &[u8], addr: Ipv4Addr, port: u16) -> NetResult<()> {
+        check_outbound(PROTOCOL_UDP, addr, port)?;
+        resolve_next_hop(addr)?;
+        let id = self.id;
+        with_stack(|stack, now| udp::send(stack, id, addr, port, data, now))
+    }
+
+    /// The next datagram: its data, sender and sender's port
+    pub fn recv_from(&self, timeout_ms: u64) -> NetResult<(Vec<u8>, Ipv4Addr, u16)> {
+        let id = self.id;
+        let datagram = wait(timeout_ms, |stack, _| stack.udp.receive(id))?;
+        Ok((datagram.data, datagram.source, datagram.port))
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let id = self.id;
+        with_stack(|stack, _| {
+            stack.udp.close(id);
+            Ok(())
+        })
+        .ok();
+    }
+}
@@ -0,0 +1,848 @@
+// TCP
+//
+// Enough of RFC 793 for clients and simple servers: active and passive
+// open, in-order receive into a fixed window, go-back-N retransmission on
+// a doubling timeout, and both ways of closing. Segments that arrive out
+// of order are dropped and answered with the sequence number still
+// expected, so the peer sends them again. Beyond a cap on segments in
+// flight there is no congestion control, and there is no urgent data.
+// Segments are kept within the path MTU to the peer, and when ICMP says
+// the path has shrunk what is in flight is resent in smaller pieces.
+//
+// A socket whose handle has been dropped stays in the table until the
+// connection is fully closed, so its FIN still gets retransmitted. A
+// connection that comes in on a listening socket is in the same position
+// until it is accepted: nothing holds it, so it goes away if it is reset
+// first.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use super::ipv4::{self, Header, PROTOCOL_TCP};
+use super::udp::EPHEMERAL_PORTS;
+use super::{NetError, NetResult, Stack};
+
+pub type SocketId = u32;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+const HEADER_LEN: usize = 20;
+const OPTION_END: u8 = 0;
+const OPTION_NOP: u8 = 1;
+const OPTION_MSS: u8 = 2;
+/// What a peer that does not say may be sent
+const DEFAULT_MSS: usize = 536;
+/// What fits in our MTU
+const LOCAL_MSS: usize = 1460;
+const RECEIVE_BUFFER: usize = 32768;
+const SEND_BUFFER: usize = 65536;
+const MAX_IN_FLIGHT: usize = 8;
+const INITIAL_RTO_MS: u64 = 1000;
+const MAX_RTO_MS: u64 = 30_000;
+const MAX_RETRIES: u32 = 8;
+const TIME_WAIT_MS: u64 = 2000;
+/// Connections a listening socket holds before it turns new ones away
+const BACKLOG: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+impl State {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::SynSent => "SYN_SENT",
+            State::SynReceived => "SYN_RECEIVED",
+            State::Established => "ESTABLISHED",
+            State::FinWait1 => "FIN_WAIT1",
+            State::FinWait2 => "FIN_WAIT2",
+            State::CloseWait => "CLOSE_WAIT",
+            State::Closing => "CLOSING",
+            State::LastAck => "LAST_ACK",
+            State::TimeWait => "TIME_WAIT",
+            State::Closed => "CLOSED",
+        }
+    }
+}
+
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment<'a> {
+    pub source_port: u16,
+    pub dest_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    pub mss: Option<u16>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    /// Sequence space taken: the payload, plus one each for SYN and FIN
+    fn len(&self) -> u32 {
+        self.payload.len() as u32 + (self.flags & SYN != 0) as u32 + (self.flags & FIN != 0) as u32
+    }
+
+    pub fn parse(header: &Header, data: &'a [u8]) -> Option<Segment<'a>> {
+        if data.len() < HEADER_LEN {
+            return None;
+        }
+        let offset = (data[12] >> 4) as usize * 4;
+        if offset < HEADER_LEN || offset > data.len() {
+            return None;
+        }
+        if ipv4::checksum(data, ipv4::pseudo_header(header.source, header.dest, PROTOCOL_TCP, data.len())) != 0 {
+            return None;
+        }
+        let mut mss = None;
+        let mut options = &data[HEADER_LEN..offset];
+        while let Some(&kind) = options.first() {
+            match kind {
+                OPTION_END => break,
+                OPTION_NOP => options = &options[1..],
+                _ => {
+                    let len = *options.get(1)? as usize;
+                    if len < 2 || len > options.len() {
+                        return None;
+                    }
+                    if kind == OPTION_MSS && len == 4 {
+                        mss = Some(u16::from_be_bytes([options[2], options[3]]));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        let be32 = |at: usize| u32::from_be_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        Some(Segment {
+            source_port: u16::from_be_bytes([data[0], data[1]]),
+            dest_port: u16::from_be_bytes([data[2], data[3]]),
+            seq: be32(4),
+            ack: be32(8),
+            flags: data[13],
+            window: u16::from_be_bytes([data[14], data[15]]),
+            mss,
+            payload: &data[offset..],
+        })
+    }
+
+    pub fn build(&self, source: Ipv4Addr, dest: Ipv4Addr) -> Vec<u8> {
+        let options = if self.mss.is_some() { 4 } else { 0 };
+        let len = HEADER_LEN + options + self.payload.len();
+        let mut data = Vec::with_capacity(len);
+        data.extend_from_slice(&self.source_port.to_be_bytes());
+        data.extend_from_slice(&self.dest_port.to_be_bytes());
+        data.extend_from_slice(&self.seq.to_be_bytes());
+        data.extend_from_slice(&self.ack.to_be_bytes());
+        data.extend_from_slice(&[(((HEADER_LEN + options) / 4) as u8) << 4, self.flags]);
+        data.extend_from_slice(&self.window.to_be_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        if let Some(mss) = self.mss {
+            data.extend_from_slice(&[OPTION_MSS, 4]);
+            data.extend_from_slice(&mss.to_be_bytes());
+        }
+        data.extend_from_slice(self.payload);
+        let sum = ipv4::checksum(&data, ipv4::pseudo_header(source, dest, PROTOCOL_TCP, len));
+        data[16..18].copy_from_slice(&sum.to_be_bytes());
+        data
+    }
+}
+
+/// A connection
+struct Tcb {
+    id: SocketId,
+    state: State,
+    local: Ipv4Addr,
+    local_port: u16,
+    remote: Ipv4Addr,
+    remote_port: u16,
+    iss: u32,
+    /// Oldest unacknowledged sequence number
+    snd_una: u32,
+    /// Next sequence number to send
+    snd_nxt: u32,
+    /// The peer's receive window
+    snd_wnd: u32,
+    mss: usize,
+    /// The largest segment the path MTU to the peer lets through
+    path_mss: usize,
+    rcv_nxt: u32,
+    /// Bytes from snd_una on: sent and unacknowledged, then unsent
+    tx: VecDeque<u8>,
+    /// Received and not yet read
+    rx: VecDeque<u8>,
+    /// The user is done sending; FIN follows the last byte
+    closing: bool,
+    /// FIN has been sent and not yet rolled back for retransmission
+    fin_sent: bool,
+    fin_acked: bool,
+    /// The peer has sent FIN
+    peer_closed: bool,
+    error: Option<NetError>,
+    rto: u64,
+    retries: u32,
+    /// When to retransmit, if anything is in flight
+    timer: Option<u64>,
+    time_wait_until: u64,
+    /// The handle is gone; forget the socket once it is closed
+    orphaned: bool,
+    ack_pending: bool,
+    /// The listening socket that has yet to accept this connection
+    listener: Option<SocketId>,
+}
+
+impl Tcb {
+    fn new(id: SocketId, state: State, local: Ipv4Addr, local_port: u16, remote: Ipv4Addr, remote_port: u16) -> Tcb {
+        let iss = initial_sequence();
+        Tcb {
+            id,
+            state,
+            local,
+            local_port,
+            remote,
+            remote_port,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            mss: DEFAULT_MSS,
+            path_mss: LOCAL_MSS,
+            rcv_nxt: 0,
+            tx: VecDeque::new(),
+            rx: VecDeque::new(),
+            closing: false,
+            fin_sent: false,
+            fin_acked: false,
+            peer_closed: false,
+            error: None,
+            rto: INITIAL_RTO_MS,
+            retries: 0,
+            timer: None,
+            time_wait_until: 0,
+            orphaned: false,
+            ack_pending: false,
+            listener: None,
+        }
+    }
+
+    fn window(&self) -> u16 {
+        (RECEIVE_BUFFER - self.rx.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn segment<'a>(&self, seq: u32, flags: u8, payload: &'a [u8]) -> Segment<'a> {
+        Segment {
+            source_port: self.local_port,
+            dest_port: self.remote_port,
+            seq,
+            ack: if flags & ACK != 0 { self.rcv_nxt } else { 0 },
+            flags,
+            window: self.window(),
+            mss: None,
+            payload,
+        }
+    }
+
+    fn arm(&mut self, now: u64) {
+        if self.timer.is_none() {
+            self.timer = Some(now + self.rto);
+        }
+    }
+
+    fn enter_time_wait(&mut self, now: u64) {
+        self.state = State::TimeWait;
+        self.time_wait_until = now + TIME_WAIT_MS;
+        self.timer = None;
+    }
+
+    fn fail(&mut self, error: NetError) {
+        self.error = Some(error);
+        self.state = State::Closed;
+        self.timer = None;
+    }
+
+    /// Build whatever should go out now into `out`
+    fn output(&mut self, now: u64, out: &mut Vec<Vec<u8>>) {
+        let local = self.local;
+        match self.state {
+            State::SynSent | State::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = if self.state == State::SynSent { SYN } else { SYN | ACK };
+                    let mut syn = self.segment(self.iss, flags, &[]);
+                    syn.mss = Some(LOCAL_MSS as u16);
+                    out.push(syn.build(local, self.remote));
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                    self.arm(now);
+                }
+                return;
+            }
+            State::Closed => return,
+            _ => {}
+        }
+
+        let mut payload = Vec::new();
+        loop {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let offset = in_flight - self.fin_sent as usize;
+            if offset >= self.tx.len() {
+                break;
+            }
+            // With nothing in flight a closed window still gets a probe
+            let window = (self.snd_wnd as usize).max((in_flight == 0) as usize).min(MAX_IN_FLIGHT * self.mss);
+            if in_flight >= window {
+                break;
+            }
+            let len = (self.tx.len() - offset).min(self.mss).min(window - in_flight);
+            payload.clear();
+            payload.extend(self.tx.range(offset..offset + len));
+            out.push(self.segment(self.snd_nxt, ACK | PSH, &payload).build(local, self.remote));
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+            self.ack_pending = false;
+            self.arm(now);
+        }
+
+        let all_sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize == self.tx.len();
+        if self.closing && !self.fin_sent && !self.fin_acked && all_sent {
+            out.push(self.segment(self.snd_nxt, FIN | ACK, &[]).build(local, self.remote));
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+            self.ack_pending = false;
+            self.state = match self.state {
+                State::Established => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                state => state,
+            };
+            self.arm(now);
+        }
+
+        if self.ack_pending {
+            out.push(self.segment(self.snd_nxt, ACK, &[]).build(local, self.remote));
+            self.ack_pending = false;
+        }
+    }
+
+    /// Go back to the oldest unacknowledged byte, or give up
+    fn retransmit(&mut self) {
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.fail(NetError::Timeout);
+            return;
+        }
+        self.rto = (self.rto * 2).min(MAX_RTO_MS);
+        self.timer = None;
+        self.snd_nxt = match self.state {
+            State::SynSent | State::SynReceived => self.iss,
+            _ => self.snd_una,
+        };
+        self.fin_sent = false;
+    }
+
+    fn input(&mut self, seg: &Segment, now: u64) {
+        if self.state == State::SynSent {
+            let ack_ok = seg.flags & ACK != 0 && seg.ack == self.iss.wrapping_add(1);
+            if seg.flags & RST != 0 {
+                if ack_ok {
+                    self.fail(NetError::ConnectionRefused);
+                }
+            } else if ack_ok && seg.flags & SYN != 0 {
+                self.rcv_nxt = seg.seq.wrapping_add(1);
+                self.snd_una = seg.ack;
+                self.snd_wnd = seg.window as u32;
+                self.mss = seg.mss.map_or(DEFAULT_MSS, |mss| mss as usize).min(self.path_mss);
+                self.state = State::Established;
+                self.timer = None;
+                self.retries = 0;
+                self.rto = INITIAL_RTO_MS;
+                self.ack_pending = true;
+            }
+            return;
+        }
+
+        if self.state == State::SynReceived && seg.flags & (RST | SYN) == 0 {
+            // The ACK of our SYN completes the handshake; whatever else
+            // the segment carries is taken below
+            if seg.flags & ACK == 0 || seg.ack != self.iss.wrapping_add(1) {
+                return;
+            }
+            self.snd_una = seg.ack;
+            self.state = State::Established;
+            self.timer = None;
+            self.retries = 0;
+            self.rto = INITIAL_RTO_MS;
+        }
+
+        if seg.flags & RST != 0 {
+            // Only a reset at the expected place is believed
+            if seg.seq == self.rcv_nxt {
+                self.fail(NetError::ConnectionReset);
+            }
+            return;
+        }
+        if seg.flags & SYN != 0 {
+            // Our answer to the SYN got lost; say it again
+            if self.state == State::SynReceived {
+                self.snd_nxt = self.iss;
+            } else {
+                self.ack_pending = true;
+            }
+            return;
+        }
+
+        if seg.flags & ACK != 0 && seq_le(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt) {
+            let acked = seg.ack.wrapping_sub(self.snd_una) as usize;
+            if acked > 0 {
+                let data = acked.min(self.tx.len());
+                self.tx.drain(..data);
+                if self.fin_sent && seg.ack == self.snd_nxt {
+                    self.fin_acked = true;
+                    self.fin_sent = false;
+                    match self.state {
+                        State::FinWait1 => self.state = State::FinWait2,
+                        State::Closing => self.enter_time_wait(now),
+                        State::LastAck => self.state = State::Closed,
+                        _ => {}
+                    }
+                }
+                self.snd_una = seg.ack;
+                self.retries = 0;
+                self.rto = INITIAL_RTO_MS;
+                self.timer = None;
+                if self.snd_una != self.snd_nxt {
+                    self.arm(now);
+                }
+            }
+            self.snd_wnd = seg.window as u32;
+        }
+
+        let end = seg.seq.wrapping_add(seg.payload.len() as u32);
+        if !seg.payload.is_empty() {
+            self.ack_pending = true;
+            let receiving = matches!(self.state, State::Established | State::FinWait1 | State::FinWait2);
+            // Take the part of the payload that starts at rcv_nxt
+            if receiving && seq_le(seg.seq, self.rcv_nxt) && seq_lt(self.rcv_nxt, end) {
+                let skip = self.rcv_nxt.wrapping_sub(seg.seq) as usize;
+                let room = RECEIVE_BUFFER - self.rx.len();
+                let take = (seg.payload.len() - skip).min(room);
+                self.rx.extend(&seg.payload[skip..skip + take]);
+                self.rcv_nxt = self.rcv_nxt.wrapping_add(take as u32);
+            }
+        }
+
+        if seg.flags & FIN != 0 && end == self.rcv_nxt && !self.peer_closed {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.peer_closed = true;
+            self.ack_pending = true;
+            match self.state {
+                State::Established => self.state = State::CloseWait,
+                State::FinWait1 if self.fin_acked => self.enter_time_wait(now),
+                State::FinWait1 => self.state = State::Closing,
+                State::FinWait2 => self.enter_time_wait(now),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A listening socket
+struct Listener {
+    id: SocketId,
+    port: u16,
+}
+
+pub struct Sockets {
+    tcbs: Vec<Tcb>,
+    listeners: Vec<Listener>,
+    next_id: SocketId,
+    next_port: u16,
+}
+
+impl Sockets {
+    pub fn new() -> Sockets {
+        Sockets { tcbs: Vec::new(), listeners: Vec::new(), next_id: 1, next_port: *EPHEMERAL_PORTS.start() }
+    }
+
+    fn next_id(&mut self) -> SocketId {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn get(&self, id: SocketId) -> NetResult<&Tcb> {
+        self.tcbs.iter().find(|t| t.id == id).ok_or(NetError::NotConnected)
+    }
+
+    fn get_mut(&mut self, id: SocketId) -> NetResult<&mut Tcb> {
+        self.tcbs.iter_mut().find(|t| t.id == id).ok_or(NetError::NotConnected)
+    }
+
+    fn free_port(&mut self) -> NetResult<u16> {
+        for _ in EPHEMERAL_PORTS {
+            let port = self.next_port;
+            self.next_port = if port == *EPHEMERAL_PORTS.end() { *EPHEMERAL_PORTS.start() } else { port + 1 };
+            if !self.tcbs.iter().any(|t| t.local_port == port) && !self.listeners.iter().any(|l| l.port == port) {
+                return Ok(port);
+            }
+        }
+        Err(NetError::AddrInUse)
+    }
+
+    /// Local port, remote address and port and state of every connection
+    pub fn list(&self) -> Vec<(u16, Ipv4Addr, u16, State)> {
+        self.tcbs.iter().map(|t| (t.local_port, t.remote, t.remote_port, t.state)).collect()
+    }
+
+    /// Ports with a listening socket
+    pub fn listening(&self) -> Vec<u16> {
+        self.listeners.iter().map(|l| l.port).collect()
+    }
+}
+
+/// Send what socket `id` has to send
+fn transmit(stack: &mut Stack, id: SocketId, now: u64) {
+    let mut out = Vec::new();
+    let remote = match stack.tcp.get_mut(id) {
+        Ok(tcb) => {
+            tcb.output(now, &mut out);
+            tcb.remote
+        }
+        Err(_) => return,
+    };
+    for segment in out {
+        ipv4::send(stack, remote, PROTOCOL_TCP, &segment, now).ok();
+    }
+}
+
+fn initial_sequence() -> u32 {
+    (unsafe { core::arch::x86_64::_rdtsc() } >> 8) as u32
+}
+
+/// Start connecting to `remote`:`port`
+pub fn connect(stack: &mut Stack, remote: Ipv4Addr, port: u16, now: u64) -> NetResult<SocketId> {
+    let local_port = stack.tcp.free_port()?;
+    let id = stack.tcp.next_id();
+    let local = stack.local_addr(remote);
+    let mut tcb = Tcb::new(id, State::SynSent, local, local_port, remote, port);
+    tcb.path_mss = path_mss(stack, remote, now);
+    stack.tcp.tcbs.push(tcb);
+    transmit(stack, id, now);
+    Ok(id)
+}
+
+/// Start listening on `port`
+pub fn listen(stack: &mut Stack, port: u16) -> NetResult<SocketId> {
+    if port == 0 {
+        return Err(NetError::InvalidArgument);
+    }
+    if stack.tcp.listeners.iter().any(|l| l.port == port) {
+        return Err(NetError::AddrInUse);
+    }
+    let id = stack.tcp.next_id();
+    stack.tcp.listeners.push(Listener { id, port });
+    Ok(id)
+}
+
+/// A connection that has come in on listening socket `id`, if one has
+pub fn accept(stack: &mut Stack, id: SocketId) -> NetResult<Option<SocketId>> {
+    if !stack.tcp.listeners.iter().any(|l| l.id == id) {
+        return Err(NetError::NotConnected);
+    }
+    let tcb = stack.tcp.tcbs.iter_mut().find(|t| t.listener == Some(id) && t.state != State::SynReceived);
+    Ok(tcb.map(|tcb| {
+        tcb.listener = None;
+        tcb.orphaned = false;
+        tcb.id
+    }))
+}
+
+/// Stop listening; connections not yet accepted are closed
+pub fn unlisten(stack: &mut Stack, id: SocketId, now: u64) {
+    stack.tcp.listeners.retain(|l| l.id != id);
+    let pending: Vec<SocketId> = stack.tcp.tcbs.iter().filter(|t| t.listener == Some(id)).map(|t| t.id).collect();
+    for child in pending {
+        if let Ok(tcb) = stack.tcp.get_mut(child) {
+            tcb.listener = None;
+            tcb.closing = true;
+        }
+        transmit(stack, child, now);
+    }
+}
+
+pub fn state(stack: &Stack, id: SocketId) -> NetResult<State> {
+    let tcb = stack.tcp.get(id)?;
+    match tcb.error {
+        Some(error) => Err(error),
+        None => Ok(tcb.state),
+    }
+}
+
+/// Queue as much of `data` as fits, returning how much did
+pub fn send(stack: &mut Stack, id: SocketId, data: &[u8], now: u64) -> NetResult<usize> {
+    let tcb = stack.tcp.get_mut(id)?;
+    if let Some(error) = tcb.error {
+        return Err(error);
+    }
+    if tcb.closing || !matches!(tcb.state, State::Established | State::CloseWait) {
+        return Err(NetError::NotConnected);
+    }
+    let n = data.len().min(SEND_BUFFER - tcb.tx.len());
+    tcb.tx.extend(&data[..n]);
+    transmit(stack, id, now);
+    Ok(n)
+}
+
+/// Read received bytes: None if there are none yet, Some(0) once the
+/// peer has closed and everything has been read
+pub fn receive(stack: &mut Stack, id: SocketId, buf: &mut [u8], now: u64) -> NetResult<Option<usize>> {
+    let tcb = stack.tcp.get_mut(id)?;
+    if tcb.rx.is_empty() {
+        return match tcb.error {
+            Some(error) => Err(error),
+            None if tcb.peer_closed => Ok(Some(0)),
+            None => Ok(None),
+        };
+    }
+    let before = tcb.window();
+    let n = buf.len().min(tcb.rx.len());
+    for (dst, src) in buf.iter_mut().zip(tcb.rx.drain(..n)) {
+        *dst = src;
+    }
+    // Tell the peer once the window has opened by a segment or more
+    if before < tcb.mss as u16 && tcb.window() >= tcb.mss as u16 {
+        tcb.ack_pending = true;
+        transmit(stack, id, now);
+    }
+    Ok(Some(n))
+}
+
+/// Finish sending and let the connection close in the background
+pub fn close(stack: &mut Stack, id: SocketId, now: u64) {
+    if let Ok(tcb) = stack.tcp.get_mut(id) {
+        tcb.orphaned = true;
+        match tcb.state {
+            State::SynSent => tcb.state = State::Closed,
+            _ => tcb.closing = true,
+        }
+    }
+    transmit(stack, id, now);
+    stack.tcp.tcbs.retain(|t| !(t.orphaned && t.state == State::Closed));
+}
+
+pub fn handle(stack: &mut Stack, header: &Header, data: &[u8], now: u64) {
+    let seg = match Segment::parse(header, data) {
+        Some(seg) => seg,
+        None => return,
+    };
+    let found = stack.tcp.tcbs.iter_mut().find(|t| {
+        t.state != State::Closed && t.local_port == seg.dest_port && t.remote == header.source && t.remote_port == seg.source_port
+    });
+    let listener = stack.tcp.listeners.iter().find(|l| l.port == seg.dest_port).map(|l| l.id);
+    match found {
+        Some(tcb) => {
+            tcb.input(&seg, now);
+            let id = tcb.id;
+            transmit(stack, id, now);
+        }
+        None if seg.flags & (SYN | ACK | RST) == SYN && listener.is_some() => {
+            let listener = listener.unwrap();
+            // Past the backlog the SYN is ignored, and the peer tries again
+            if stack.tcp.tcbs.iter().filter(|t| t.listener == Some(listener)).count() >= BACKLOG {
+                return;
+            }
+            let id = stack.tcp.next_id();
+            let mut tcb = Tcb::new(id, State::SynReceived, header.dest, seg.dest_port, header.source, seg.source_port);
+            tcb.rcv_nxt = seg.seq.wrapping_add(1);
+            tcb.snd_wnd = seg.window as u32;
+            tcb.path_mss = path_mss(stack, header.source, now);
+            tcb.mss = seg.mss.map_or(DEFAULT_MSS, |mss| mss as usize).min(tcb.path_mss);
+            tcb.orphaned = true;
+            tcb.listener = Some(listener);
+            stack.tcp.tcbs.push(tcb);
+            transmit(stack, id, now);
+        }
+        None if seg.flags & RST == 0 => {
+            // Nobody here: reset, as RFC 793 describes for a closed port
+            let mut reset = Segment {
+                source_port: seg.dest_port,
+                dest_port: seg.source_port,
+                seq: 0,
+                ack: 0,
+                flags: RST,
+                window: 0,
+                mss: None,
+                payload: &[],
+            };
+            if seg.flags & ACK != 0 {
+                reset.seq = seg.ack;
+            } else {
+                reset.ack = seg.seq.wrapping_add(seg.len());
+                reset.flags |= ACK;
+            }
+            ipv4::send(stack, header.source, PROTOCOL_TCP, &reset.build(header.dest, header.source), now).ok();
+        }
+        None => {}
+    }
+}
+
+/// The largest segment that fits the path MTU to `remote`
+fn path_mss(stack: &Stack, remote: Ipv4Addr, now: u64) -> usize {
+    (stack.path_mtu.get(remote, now) - ipv4::HEADER_LEN - HEADER_LEN).min(LOCAL_MSS)
+}
+
+/// The path to `remote` has shrunk to `mtu`: send smaller segments from
+/// now on, and at once resend what the larger ones carried, since the
+/// router dropped them
+pub fn path_mtu(stack: &mut Stack, remote: Ipv4Addr, mtu: usize, now: u64) {
+    let mss = mtu - ipv4::HEADER_LEN - HEADER_LEN;
+    let mut shrunk = Vec::new();
+    for tcb in stack.tcp.tcbs.iter_mut().filter(|t| t.remote == remote && t.state != State::Closed) {
+        tcb.path_mss = tcb.path_mss.min(mss);
+        if tcb.mss > mss {
+            tcb.mss = mss;
+            if !matches!(tcb.state, State::SynSent | State::SynReceived) {
+                tcb.snd_nxt = tcb.snd_una;
+                tcb.fin_sent = false;
+                tcb.timer = None;
+            }
+            shrunk.push(tcb.id);
+        }
+    }
+    for id in shrunk {
+        transmit(stack, id, now);
+    }
+}
+
+/// An ICMP error says nothing at `remote`:`remote_port` takes the
+/// connection from `local_port`. Only a connection still being opened
+/// gives up on it: an established one has had answers from the peer, and
+/// a forged message should not be able to cut it off.
+pub fn refused(stack: &mut Stack, local_port: u16, remote: Ipv4Addr, remote_port: u16) {
+    let found = stack.tcp.tcbs.iter_mut().find(|t| {
+        t.state == State::SynSent && t.local_port == local_port && t.remote == remote && t.remote_port == remote_port
+    });
+    if let Some(tcb) = found {
+        tcb.fail(NetError::ConnectionRefused);
+    }
+}
+
+/// Retransmit what has timed out, end TIME_WAIT and drop closed orphans
+pub fn timers(stack: &mut Stack, now: u64) {
+    let mut due = Vec::new();
+    for tcb in stack.tcp.tcbs.iter_mut() {
+        if tcb.state == State::TimeWait && now >= tcb.time_wait_until {
+            tcb.state = State::Closed;
+        }
+        if tcb.timer.is_some_and(|at| now >= at) {
+            tcb.retransmit();
+        }
+        due.push(tcb.id);
+    }
+    for id in due {
+        transmit(stack, id, now);
+    }
+    stack.tcp.tcbs.retain(|t| !(t.orphaned && t.state == State::Closed));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+    const REMOTE: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+    fn tcb() -> Tcb {
+        let mut tcb = Tcb::new(1, State::SynSent, LOCAL, 50000, REMOTE, 80);
+        (tcb.iss, tcb.snd_una, tcb.snd_nxt) = (100, 100, 100);
+        tcb
+    }
+
+    /// What `tcb` sends, parsed back
+    fn sent(tcb: &mut Tcb, now: u64) -> Vec<(u32, u32, u8, Vec<u8>)> {
+        let mut out = Vec::new();
+        tcb.output(now, &mut out);
+        let header = Header { source: LOCAL, dest: REMOTE, protocol: PROTOCOL_TCP, ttl: 64, id: 0 };
+        out.iter()
+            .map(|data| {
+                let seg = Segment::parse(&header, data).unwrap();
+                (seg.seq, seg.ack, seg.flags, seg.payload.to_vec())
+            })
+            .collect()
+    }
+
+    fn incoming(seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Segment<'_> {
+        Segment { source_port: 80, dest_port: 50000, seq, ack, flags, window: 8192, mss: Some(1000), payload }
+    }
+
+    #[test_case]
+    fn test_segment_options() {
+        let mut seg = incoming(1, 2, SYN | ACK, b"");
+        let data = seg.build(REMOTE, LOCAL);
+        let header = Header { source: REMOTE, dest: LOCAL, protocol: PROTOCOL_TCP, ttl: 64, id: 0 };
+        assert_eq!(Segment::parse(&header, &data), Some(seg));
+        seg.mss = None;
+        seg.payload = b"xy";
+        assert_eq!(seg.len(), 3);
+    }
+
+    #[test_case]
+    fn test_connection() {
+        let mut tcb = tcb();
+        assert_eq!(sent(&mut tcb, 0), [(100, 0, SYN, Vec::new())]);
+        // The SYN is lost and resent
+        tcb.retransmit();
+        assert_eq!(sent(&mut tcb, 1000), [(100, 0, SYN, Vec::new())]);
+        tcb.input(&incoming(5000, 101, SYN | ACK, b""), 1100);
+        assert_eq!((tcb.state, tcb.mss), (State::Established, 1000));
+        assert_eq!(sent(&mut tcb, 1100), [(101, 5001, ACK, Vec::new())]);
+
+        // 1500 bytes go out as a full segment and the rest
+        tcb.tx.extend(&[7u8; 1500]);
+        let out = sent(&mut tcb, 1200);
+        assert_eq!(out.iter().map(|s| (s.0, s.3.len())).collect::<Vec<_>>(), [(101, 1000), (1101, 500)]);
+        tcb.input(&incoming(5001, 1101, ACK, b""), 1300);
+        assert_eq!((tcb.snd_una, tcb.tx.len()), (1101, 500));
+
+        // Data out of order is not taken; in order it is
+        tcb.input(&incoming(5004, 1601, ACK, b"def"), 1400);
+        assert!(tcb.rx.is_empty());
+        tcb.input(&incoming(5001, 1601, ACK, b"abc"), 1400);
+        assert_eq!(tcb.rx.iter().copied().collect::<Vec<_>>(), b"abc");
+        assert_eq!(tcb.tx.len(), 0);
+
+        // The peer closes, then we do
+        tcb.input(&incoming(5004, 1601, FIN | ACK, b""), 1500);
+        assert_eq!((tcb.state, tcb.rcv_nxt), (State::CloseWait, 5005));
+        tcb.closing = true;
+        assert_eq!(sent(&mut tcb, 1500), [(1601, 5005, FIN | ACK, Vec::new())]);
+        assert_eq!(tcb.state, State::LastAck);
+        tcb.input(&incoming(5005, 1602, ACK, b""), 1600);
+        assert_eq!(tcb.state, State::Closed);
+    }
+
+    #[test_case]
+    fn test_refused() {
+        let mut tcb = tcb();
+        sent(&mut tcb, 0);
+        tcb.input(&incoming(0, 101, RST | ACK, b""), 10);
+        assert_eq!((tcb.state, tcb.error), (State::Closed, Some(NetError::ConnectionRefused)));
+    }
+}
@@ -0,0 +1,197 @@
+// TFTP client
+//
+// Reads files from a TFTP server (RFC 1350) in octet mode, asking for
+// 1428-byte blocks so each fits one Ethernet frame (RFC 2348) and for the
+// file's size up front (RFC 2349). A server that ignores the options
+// answers with 512-byte blocks, which works just the same. The server
+// answers from a port of its own for the transfer; packets from anywhere
+// else are turned away with an error. Only reading is supported.
+
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use super::socket::UdpSocket;
+use super::{NetError, NetResult};
+
+pub const PORT: u16 = 69;
+const OP_RRQ: u16 = 1;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+const ERROR_NOT_FOUND: u16 = 1;
+const ERROR_ACCESS: u16 = 2;
+const ERROR_UNKNOWN_TID: u16 = 5;
+
+const DEFAULT_BLOCK_SIZE: usize = 512;
+/// Largest block that fits a 1500-byte frame with room to spare
+const BLOCK_SIZE: usize = 1428;
+const TIMEOUT_MS: u64 = 1000;
+/// Sends of one packet before the transfer is given up
+const ATTEMPTS: usize = 5;
+/// Files bigger than this are refused rather than run the heap dry
+pub const MAX_FILE_SIZE: usize = 64 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet<'a> {
+    Data { block: u16, data: &'a [u8] },
+    Ack(u16),
+    Error { code: u16, message: &'a str },
+    /// The options the server accepted, as name and value
+    Oack(Vec<(&'a str, &'a str)>),
+}
+
+/// A read request for `path`, asking for the block size and file size
+pub fn build_read_request(path: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(path.len() + 32);
+    packet.extend_from_slice(&OP_RRQ.to_be_bytes());
+    for field in [path, "octet", "blksize", "1428", "tsize", "0"] {
+        packet.extend_from_slice(field.as_bytes());
+        packet.push(0);
+    }
+    packet
+}
+
+pub fn build_ack(block: u16) -> [u8; 4] {
+    let mut packet = [0; 4];
+    packet[..2].copy_from_slice(&OP_ACK.to_be_bytes());
+    packet[2..].copy_from_slice(&block.to_be_bytes());
+    packet
+}
+
+fn build_error(code: u16, message: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(message.len() + 5);
+    packet.extend_from_slice(&OP_ERROR.to_be_bytes());
+    packet.extend_from_slice(&code.to_be_bytes());
+    packet.extend_from_slice(message.as_bytes());
+    packet.push(0);
+    packet
+}
+
+pub fn parse(packet: &[u8]) -> Option<Packet<'_>> {
+    let opcode = u16::from_be_bytes([*packet.first()?, *packet.get(1)?]);
+    let rest = &packet[2..];
+    let number = || rest.get(..2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    match opcode {
+        OP_DATA => Some(Packet::Data { block: number()?, data: &rest[2..] }),
+        OP_ACK => Some(Packet::Ack(number()?)),
+        OP_ERROR => {
+            let text = rest.get(2..)?;
+            let text = &text[..text.iter().position(|&b| b == 0).unwrap_or(text.len())];
+            Some(Packet::Error { code: number()?, message: core::str::from_utf8(text).unwrap_or("") })
+        }
+        OP_OACK => {
+            let mut fields = rest.split(|&b| b == 0).map(core::str::from_utf8);
+            let mut options = Vec::new();
+            while let Some(name) = fields.next() {
+                let name = name.ok()?;
+                if name.is_empty() {
+                    break;
+                }
+                options.push((name, fields.next()?.ok()?));
+            }
+            Some(Packet::Oack(options))
+        }
+        _ => None,
+    }
+}
+
+/// Fetch the whole of `path` from the server at `server`:`port`
+pub fn get(server: Ipv4Addr, port: u16, path: &str) -> NetResult<Vec<u8>> {
+    let socket = UdpSocket::bind(0)?;
+    let mut last = build_read_request(path);
+    // The transfer's port at the server, known once it answers
+    let mut peer = None;
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut expected: u16 = 1;
+    let mut data = Vec::new();
+    let mut attempts = 0;
+    socket.send_to(&last, server, port)?;
+    loop {
+        let (packet, from, from_port) = match socket.recv_from(TIMEOUT_MS) {
+            Ok(datagram) => datagram,
+            Err(NetError::Timeout) => {
+                attempts += 1;
+                if attempts >= ATTEMPTS {
+                    return Err(NetError::Timeout);
+                }
+                socket.send_to(&last, server, peer.unwrap_or(port))?;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if from != server || peer.map_or(false, |peer| peer != from_port) {
+            socket.send_to(&build_error(ERROR_UNKNOWN_TID, "unknown transfer ID"), from, from_port).ok();
+            continue;
+        }
+        match parse(&packet) {
+            Some(Packet::Oack(options)) if peer.is_none() => {
+                peer = Some(from_port);
+                for (name, value) in options {
+                    if name.eq_ignore_ascii_case("blksize") {
+                        block_size = value.parse().ok().filter(|size| (8..=BLOCK_SIZE).contains(size)).ok_or(NetError::BadResponse)?;
+                    } else if name.eq_ignore_ascii_case("tsize") {
+                        let size: usize = value.parse().map_err(|_| NetError::BadResponse)?;
+                        if size > MAX_FILE_SIZE {
+                            return Err(NetError::InvalidArgument);
+                        }
+                        data.reserve(size);
+                    }
+                }
+                last = build_ack(0).to_vec();
+            }
+            Some(Packet::Data { block, data: chunk }) if block == expected => {
+                peer = Some(from_port);
+                if data.len() + chunk.len() > MAX_FILE_SIZE {
+                    return Err(NetError::InvalidArgument);
+                }
+                data.extend_from_slice(chunk);
+                last = build_ack(block).to_vec();
+                if chunk.len() < block_size {
+                    // The final ACK is not answered; if it is lost the
+                    // server times out on its own
+                    socket.send_to(&last, server, from_port)?;
+                    return Ok(data);
+                }
+                expected = expected.wrapping_add(1);
+            }
+            // Our ACK was lost and the block sent again: ACK it again
+            Some(Packet::Data { block, .. }) if block == expected.wrapping_sub(1) => {}
+            Some(Packet::Error { code, .. }) => {
+                return Err(match code {
+                    ERROR_NOT_FOUND => NetError::FileNotFound,
+                    ERROR_ACCESS => NetError::AccessDenied,
+                    _ => NetError::BadResponse,
+                });
+            }
+            _ => continue,
+        }
+        attempts = 0;
+        socket.send_to(&last, server, from_port)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_packets() {
+        assert_eq!(build_read_request("bin/t"), b"\x00\x01bin/t\x00octet\x00blksize\x001428\x00tsize\x000\x00");
+        assert_eq!(build_ack(0x0102), [0, 4, 1, 2]);
+        assert_eq!(parse(&build_ack(7)), Some(Packet::Ack(7)));
+        assert_eq!(parse(b"\x00\x03\x00\x02abc"), Some(Packet::Data { block: 2, data: b"abc" }));
+        assert_eq!(parse(b"\x00\x03\x00\x02"), Some(Packet::Data { block: 2, data: b"" }));
+        assert_eq!(
+            parse(&build_error(ERROR_NOT_FOUND, "File not found")),
+            Some(Packet::Error { code: 1, message: "File not found" })
+        );
+        assert_eq!(
+            parse(b"\x00\x06blksize\x001428\x00tsize\x00300\x00"),
+            Some(Packet::Oack(alloc::vec![("blksize", "1428"), ("tsize", "300")]))
+        );
+        assert_eq!(parse(b"\x00\x06blksize"), None);
+        assert_eq!(parse(b"\x00\x09"), None);
+        assert_eq!(parse(b"\x00"), None);
+    }
+}
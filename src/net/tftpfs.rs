@@ -0,0 +1,304 @@
+// Read-only filesystem over TFTP
+//
+// Lets a development host export files to the VM: whatever the host's TFTP
+// server serves shows up under /net. TFTP cannot list directories, so the
+// server has to serve an index too, one path per line, which on the host
+// is as simple as
+//
+//     find . -type f ! -name index | sed 's|^\./||' > index
+//
+// The tree is built from the index when the filesystem is mounted. Reading
+// a file from its start fetches it again, so an edit on the host is seen
+// by the next cat or exec; reads further on come from that copy. Files
+// added on the host appear after `netmount -r`, which reads the index
+// again. Sizes are not known before a file is read, so they show as 0.
+//
+// tftp.server=ADDR[:PORT] on the command line mounts at boot.
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use spin::{Mutex, RwLock};
+use crate::fs::mount::{self, MountFlags};
+use crate::fs::vfs::{DirEntry, Filesystem, InodeNumber, VfsNode, VFS};
+use crate::fs::{FileMode, FileStat, FileType, FsError, FsResult};
+use super::{resolver, tftp, NetError};
+
+pub const MOUNT_POINT: &str = "/net";
+pub const INDEX: &str = "index";
+/// Longest path taken from the index
+const MAX_PATH: usize = 255;
+
+struct Node {
+    name: String,
+    /// Path on the server, empty for the root
+    path: String,
+    parent: InodeNumber,
+    /// Inode numbers of the entries of a directory; None for a file
+    children: Option<Vec<InodeNumber>>,
+}
+
+pub struct TftpFilesystem {
+    server: Ipv4Addr,
+    port: u16,
+    /// Inode n is nodes[n - 1]; the root is 1
+    nodes: Vec<Node>,
+    /// What was fetched of each file
+    cache: Mutex<BTreeMap<InodeNumber, Vec<u8>>>,
+}
+
+impl TftpFilesystem {
+    /// The tree an index describes; blank lines, comments and paths that
+    /// would climb out of the export are skipped
+    pub fn from_index(server: Ipv4Addr, port: u16, index: &str) -> TftpFilesystem {
+        let root = Node { name: String::from("/"), path: String::new(), parent: 1, children: Some(Vec::new()) };
+        let mut fs = TftpFilesystem { server, port, nodes: alloc::vec![root], cache: Mutex::new(BTreeMap::new()) };
+        for line in index.lines() {
+            let path = line.trim().trim_start_matches("./").trim_start_matches('/');
+            if path.is_empty() || path.starts_with('#') || path.len() > MAX_PATH {
+                continue;
+            }
+            let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty() && *c != ".").collect();
+            if components.contains(&"..") {
+                continue;
+            }
+            let mut dir = 1;
+            for (i, &name) in components.iter().enumerate() {
+                let last = i + 1 == components.len();
+                dir = match fs.child(dir, name) {
+                    // A name listed both as a file and a directory keeps
+                    // whichever came first
+                    Some(inode) if last || fs.node(inode).children.is_some() => inode,
+                    Some(_) => break,
+                    None => {
+                        let path = components[..=i].join("/");
+                        let children = if last { None } else { Some(Vec::new()) };
+                        fs.add(dir, Node { name: name.to_string(), path, parent: dir, children })
+                    }
+                };
+            }
+        }
+        fs
+    }
+
+    fn node(&self, inode: InodeNumber) -> &Node {
+        &self.nodes[inode as usize - 1]
+    }
+
+    fn get(&self, inode: InodeNumber) -> FsResult<&Node> {
+        inode.checked_sub(1).and_then(|i| self.nodes.get(i as usize)).ok_or(FsError::NotFound)
+    }
+
+    fn child(&self, dir: InodeNumber, name: &str) -> Option<InodeNumber> {
+        let children = self.node(dir).children.as_ref()?;
+        children.iter().copied().find(|&inode| self.node(inode).name == name)
+    }
+
+    fn add(&mut self, dir: InodeNumber, node: Node) -> InodeNumber {
+        self.nodes.push(node);
+        let inode = self.nodes.len() as InodeNumber;
+        if let Some(children) = self.nodes[dir as usize - 1].children.as_mut() {
+            children.push(inode);
+        }
+        inode
+    }
+
+    fn vfs_node(&self, inode: InodeNumber) -> FsResult<VfsNode> {
+        let node = self.get(inode)?;
+        Ok(match node.children {
+            Some(_) => VfsNode::new_directory(node.name.clone(), inode, 0o555),
+            None => {
+                let mut file = VfsNode::new_file(node.name.clone(), inode, 0o444);
+                file.size = self.cache.lock().get(&inode).map_or(0, |data| data.len() as u64);
+                file
+            }
+        })
+    }
+}
+
+fn fs_error(error: NetError) -> FsError {
+    match error {
+        NetError::FileNotFound => FsError::NotFound,
+        NetError::AccessDenied => FsError::PermissionDenied,
+        NetError::InvalidArgument => FsError::NoSpace,
+        _ => FsError::IoError,
+    }
+}
+
+impl Filesystem for TftpFilesystem {
+    fn name(&self) -> &str {
+        "tftp"
+    }
+
+    fn root(&self) -> FsResult<VfsNode> {
+        self.vfs_node(1)
+    }
+
+    fn lookup(&self, parent: InodeNumber, name: &str) -> FsResult<VfsNode> {
+        if self.get(parent)?.children.is_none() {
+            return Err(FsError::NotDirectory);
+        }
+        let inode = match name {
+            "." => parent,
+            ".." => self.node(parent).parent,
+            _ => self.child(parent, name).ok_or(FsError::NotFound)?,
+        };
+        self.vfs_node(inode)
+    }
+
+    fn read(&self, inode: InodeNumber, offset: u64, buf: &mut [u8]) -> FsResult<usize> {
+        let node = self.get(inode)?;
+        if node.children.is_some() {
+            return Err(FsError::IsDirectory);
+        }
+        let mut cache = self.cache.lock();
+        if offset == 0 || !cache.contains_key(&inode) {
+            let data = tftp::get(self.server, self.port, &node.path).map_err(fs_error)?;
+            cache.insert(inode, data);
+        }
+        let data = &cache[&inode];
+        let start = (offset as usize).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+
+    fn write(&mut self, _inode: InodeNumber, _offset: u64, _buf: &[u8]) -> FsResult<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn create(&mut self, _parent: InodeNumber, _name: &str, _mode: FileMode) -> FsResult<VfsNode> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn mkdir(&mut self, _parent: InodeNumber, _name: &str, _mode: FileMode) -> FsResult<VfsNode> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&mut self, _parent: InodeNumber, _name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rmdir(&mut self, _parent: InodeNumber, _name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn rename(&mut self, _old_parent: InodeNumber, _old_name: &str, _new_parent: InodeNumber, _new_name: &str) -> FsResult<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn stat(&self, inode: InodeNumber) -> FsResult<FileStat> {
+        Ok(self.vfs_node(inode)?.stat())
+    }
+
+    fn readdir(&self, inode: InodeNumber) -> FsResult<Vec<DirEntry>> {
+        let node = self.get(inode)?;
+        let children = node.children.as_ref().ok_or(FsError::NotDirectory)?;
+        let mut entries = alloc::vec![
+            DirEntry::new(String::from("."), inode, FileType::Directory),
+            DirEntry::new(String::from(".."), node.parent, FileType::Directory),
+        ];
+        for &child in children {
+            let kind = if self.node(child).children.is_some() { FileType::Directory } else { FileType::Regular };
+            entries.push(DirEntry::new(self.node(child).name.clone(), child, kind));
+        }
+        Ok(entries)
+    }
+
+    fn sync(&mut self) -> FsResult<()> {
+        Ok(())
+    }
+}
+
+/// The server /net is mounted from
+static MOUNTED: Mutex<Option<(Ipv4Addr, u16)>> = Mutex::new(None);
+
+/// `ADDR[:PORT]`, where ADDR may be a name
+pub fn parse_server(spec: &str) -> Result<(Ipv4Addr, u16), &'static str> {
+    let (host, port) = match spec.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| "bad port")?),
+        None => (spec, tftp::PORT),
+    };
+    Ok((resolver::resolve(host).map_err(|e| e.message())?, port))
+}
+
+/// Mount the files `server`:`port` lists in its index on /net, replacing
+/// what is there; the number of files on success
+pub fn mount(server: Ipv4Addr, port: u16) -> Result<usize, &'static str> {
+    let index = tftp::get(server, port, INDEX).map_err(|e| match e {
+        NetError::FileNotFound => "the server has no index",
+        e => e.message(),
+    })?;
+    let index = core::str::from_utf8(&index).map_err(|_| "the index is not text")?;
+    let fs = TftpFilesystem::from_index(server, port, index);
+    let files = fs.nodes.iter().filter(|node| node.children.is_none()).count();
+    let fs: Arc<RwLock<dyn Filesystem + Send + Sync>> = Arc::new(RwLock::new(fs));
+    {
+        let mut vfs = VFS.lock();
+        vfs.create_directory(MOUNT_POINT, FileMode::new(0o555)).ok();
+        vfs.graft(MOUNT_POINT, fs.clone()).map_err(|_| "cannot graft the tree")?;
+    }
+    mount::umount(MOUNT_POINT).ok();
+    let source = format!("tftp://{}:{}", server, port);
+    mount::mount(&source, MOUNT_POINT, "tftp", MountFlags::RDONLY, fs).map_err(|_| "cannot mount")?;
+    *MOUNTED.lock() = Some((server, port));
+    Ok(files)
+}
+
+/// Read the index again
+pub fn remount() -> Result<usize, &'static str> {
+    let (server, port) = MOUNTED.lock().ok_or("nothing is mounted")?;
+    mount(server, port)
+}
+
+pub fn unmount() -> Result<(), &'static str> {
+    MOUNTED.lock().take().ok_or("nothing is mounted")?;
+    mount::umount(MOUNT_POINT).ok();
+    VFS.lock().ungraft(MOUNT_POINT).map_err(|_| "cannot empty /net")
+}
+
+/// The server /net is mounted from, if any
+pub fn mounted() -> Option<(Ipv4Addr, u16)> {
+    *MOUNTED.lock()
+}
+
+/// Mount the server named by tftp.server=, if any
+pub fn init() {
+    let spec = match crate::kernel::get_param("tftp.server") {
+        Some(spec) => spec,
+        None => return,
+    };
+    let result = parse_server(&spec).and_then(|(server, port)| mount(server, port));
+    match result {
+        Ok(files) => crate::println!("  [NET] Mounted tftp://{} on {} ({} files)", spec, MOUNT_POINT, files),
+        Err(e) => crate::println!("  [NET] tftp.server={}: {}", spec, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_index() {
+        let index = "# exported\n./bin/hello\nbin/hello\n/etc/motd\n\n../secret\nbin/hello/x\nbin\n";
+        let fs = TftpFilesystem::from_index(Ipv4Addr::LOCALHOST, tftp::PORT, index);
+        let root = fs.root().unwrap();
+        let names: Vec<String> = fs.readdir(root.inode).unwrap().into_iter().map(|e| e.name).collect();
+        assert_eq!(names, [".", "..", "bin", "etc"]);
+
+        let bin = fs.lookup(root.inode, "bin").unwrap();
+        assert!(bin.is_dir());
+        let hello = fs.lookup(bin.inode, "hello").unwrap();
+        assert!(hello.is_file());
+        assert_eq!(fs.node(hello.inode).path, "bin/hello");
+        assert_eq!(fs.lookup(hello.inode, "x").err(), Some(FsError::NotDirectory));
+        assert_eq!(fs.lookup(bin.inode, "..").unwrap().inode, root.inode);
+        assert_eq!(fs.readdir(bin.inode).unwrap().len(), 3);
+        assert_eq!(fs.lookup(root.inode, "secret").err(), Some(FsError::NotFound));
+        assert_eq!(fs.stat(hello.inode).unwrap().mode.0, FileMode::S_IFREG | 0o444);
+    }
+}
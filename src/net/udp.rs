@@ -0,0 +1,175 @@
+// UDP
+//
+// Each bound port keeps a short queue of received datagrams; datagrams
+// for ports nobody has bound are answered with ICMP port-unreachable. An
+// ICMP error about a datagram a socket sent is kept on the socket and
+// handed to its next receive once the queue is empty.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use super::ipv4::{self, Header, PROTOCOL_UDP};
+use super::{NetError, NetResult, Stack};
+
+pub const HEADER_LEN: usize = 8;
+/// Datagrams queued per socket before new ones are dropped
+const QUEUE_LIMIT: usize = 32;
+pub const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+pub type SocketId = u32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub source: Ipv4Addr,
+    pub port: u16,
+    pub data: Vec<u8>,
+}
+
+struct Socket {
+    id: SocketId,
+    port: u16,
+    queue: VecDeque<Datagram>,
+    error: Option<NetError>,
+}
+
+pub struct Sockets {
+    sockets: Vec<Socket>,
+    next_id: SocketId,
+    next_port: u16,
+}
+
+impl Sockets {
+    pub fn new() -> Sockets {
+        Sockets { sockets: Vec::new(), next_id: 1, next_port: *EPHEMERAL_PORTS.start() }
+    }
+
+    fn in_use(&self, port: u16) -> bool {
+        self.sockets.iter().any(|s| s.port == port)
+    }
+
+    /// Bind `port`, or a free ephemeral port if it is 0
+    pub fn bind(&mut self, port: u16) -> NetResult<(SocketId, u16)> {
+        let port = if port != 0 {
+            if self.in_use(port) {
+                return Err(NetError::AddrInUse);
+            }
+            port
+        } else {
+            let count = EPHEMERAL_PORTS.len();
+            let free = (0..count).map(|_| {
+                let port = self.next_port;
+                self.next_port = if port == *EPHEMERAL_PORTS.end() { *EPHEMERAL_PORTS.start() } else { port + 1 };
+                port
+            }).find(|&port| !self.sockets.iter().any(|s| s.port == port));
+            free.ok_or(NetError::AddrInUse)?
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sockets.push(Socket { id, port, queue: VecDeque::new(), error: None });
+        Ok((id, port))
+    }
+
+    pub fn close(&mut self, id: SocketId) {
+        self.sockets.retain(|s| s.id != id);
+    }
+
+    pub fn port(&self, id: SocketId) -> Option<u16> {
+        self.sockets.iter().find(|s| s.id == id).map(|s| s.port)
+    }
+
+    /// The next datagram for socket `id`, or the error an ICMP message
+    /// reported once none is left
+    pub fn receive(&mut self, id: SocketId) -> NetResult<Option<Datagram>> {
+        let socket = self.sockets.iter_mut().find(|s| s.id == id).ok_or(NetError::NotConnected)?;
+        match socket.queue.pop_front() {
+            Some(datagram) => Ok(Some(datagram)),
+            None => socket.error.take().map_or(Ok(None), Err),
+        }
+    }
+
+    /// Keep `error` for the socket bound to `port`
+    pub fn report(&mut self, port: u16, error: NetError) {
+        if let Some(socket) = self.sockets.iter_mut().find(|s| s.port == port) {
+            socket.error = Some(error);
+        }
+    }
+}
+
+pub fn build(source: Ipv4Addr, dest: Ipv4Addr, source_port: u16, dest_port: u16, data: &[u8]) -> Vec<u8> {
+    let len = HEADER_LEN + data.len();
+    let mut datagram = Vec::with_capacity(len);
+    datagram.extend_from_slice(&source_port.to_be_bytes());
+    datagram.extend_from_slice(&dest_port.to_be_bytes());
+    datagram.extend_from_slice(&(len as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(data);
+    let sum = match ipv4::checksum(&datagram, ipv4::pseudo_header(source, dest, PROTOCOL_UDP, len)) {
+        // Zero means no checksum; all ones is the same sum
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    datagram
+}
+
+/// Source port, destination port and data, None if malformed
+pub fn parse<'a>(header: &Header, data: &'a [u8]) -> Option<(u16, u16, &'a [u8])> {
+    if data.len() < HEADER_LEN {
+        return None;
+    }
+    let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+    if len < HEADER_LEN || len > data.len() {
+        return None;
+    }
+    let sum = u16::from_be_bytes([data[6], data[7]]);
+    if sum != 0 && ipv4::checksum(&data[..len], ipv4::pseudo_header(header.source, header.dest, PROTOCOL_UDP, len)) != 0 {
+        return None;
+    }
+    let source_port = u16::from_be_bytes([data[0], data[1]]);
+    let dest_port = u16::from_be_bytes([data[2], data[3]]);
+    Some((source_port, dest_port, &data[HEADER_LEN..len]))
+}
+
+/// Queue a datagram for its socket; false if nobody has bound its port
+pub fn handle(stack: &mut Stack, header: &Header, data: &[u8]) -> bool {
+    let (source_port, dest_port, payload) = match parse(header, data) {
+        Some(parsed) => parsed,
+        None => return true,
+    };
+    let socket = match stack.udp.sockets.iter_mut().find(|s| s.port == dest_port) {
+        Some(socket) => socket,
+        None => return false,
+    };
+    if socket.queue.len() < QUEUE_LIMIT {
+        socket.queue.push_back(Datagram { source: header.source, port: source_port, data: payload.to_vec() });
+    }
+    true
+}
+
+/// Send `data` from socket `id` to `dest`:`port`
+pub fn send(stack: &mut Stack, id: SocketId, dest: Ipv4Addr, port: u16, data: &[u8], now: u64) -> NetResult<()> {
+    let source_port = stack.udp.port(id).ok_or(NetError::NotConnected)?;
+    let datagram = build(stack.local_addr(dest), dest, source_port, port, data);
+    ipv4::send(stack, dest, PROTOCOL_UDP, &datagram, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_datagram() {
+        let (a, b) = (Ipv4Addr::new(10, 0, 2, 15), Ipv4Addr::new(10, 0, 2, 3));
+        let datagram = build(a, b, 49152, 53, b"query");
+        let header = Header { source: a, dest: b, protocol: PROTOCOL_UDP, ttl: 64, id: 0 };
+        assert_eq!(parse(&header, &datagram), Some((49152, 53, &b"query"[..])));
+        let mut bad = datagram.clone();
+        bad[9] ^= 1;
+        assert_eq!(parse(&header, &bad), None);
+
+        let mut sockets = Sockets::new();
+        assert_eq!(sockets.bind(53).map(|(_, port)| port), Ok(53));
+        assert_eq!(sockets.bind(53), Err(NetError::AddrInUse));
+        assert_eq!(sockets.bind(0).map(|(_, port)| port), Ok(49152));
+    }
+}
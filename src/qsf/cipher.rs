@@ -0,0 +1,288 @@
+// Block ciphers: AES (FIPS 197) with 128, 192 and 256-bit keys, and
+// XTS-AES (IEEE 1619) for encrypting disk sectors, as dm-crypt volumes
+// use it. The S-boxes are computed at compile time from the field inverse
+// rather than written out. This is a plain byte-wise implementation: it is
+// not constant time, and round keys are wiped when a key is dropped.
+
+const fn gmul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+const fn sbox() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut x = 0;
+    while x < 256 {
+        // x^254 is the inverse of x in GF(2^8), and 0 maps to 0
+        let mut inverse = 1u8;
+        let mut i = 0;
+        while i < 254 {
+            inverse = gmul(inverse, x as u8);
+            i += 1;
+        }
+        let b = if x == 0 { 0 } else { inverse };
+        table[x] = b ^ b.rotate_left(1) ^ b.rotate_left(2) ^ b.rotate_left(3) ^ b.rotate_left(4) ^ 0x63;
+        x += 1;
+    }
+    table
+}
+
+const fn invert(table: [u8; 256]) -> [u8; 256] {
+    let mut inverse = [0u8; 256];
+    let mut x = 0;
+    while x < 256 {
+        inverse[table[x] as usize] = x as u8;
+        x += 1;
+    }
+    inverse
+}
+
+const SBOX: [u8; 256] = sbox();
+const INV_SBOX: [u8; 256] = invert(SBOX);
+
+pub const BLOCK_SIZE: usize = 16;
+
+/// An expanded AES key
+pub struct Aes {
+    round_keys: [[u8; BLOCK_SIZE]; 15],
+    rounds: usize,
+}
+
+impl Aes {
+    /// Expand a 16, 24 or 32-byte key; None for any other length
+    pub fn new(key: &[u8]) -> Option<Aes> {
+        if !matches!(key.len(), 16 | 24 | 32) {
+            return None;
+        }
+        let nk = key.len() / 4;
+        let rounds = nk + 6;
+        let mut words = [[0u8; 4]; 60];
+        for (word, chunk) in words.iter_mut().zip(key.chunks_exact(4)) {
+            word.copy_from_slice(chunk);
+        }
+        let mut rcon = 1u8;
+        for i in nk..4 * (rounds + 1) {
+            let mut temp = words[i - 1];
+            if i % nk == 0 {
+                temp = [SBOX[temp[1] as usize] ^ rcon, SBOX[temp[2] as usize], SBOX[temp[3] as usize], SBOX[temp[0] as usize]];
+                rcon = gmul(rcon, 2);
+            } else if nk > 6 && i % nk == 4 {
+                temp = temp.map(|b| SBOX[b as usize]);
+            }
+            for (j, byte) in temp.iter().enumerate() {
+                words[i][j] = words[i - nk][j] ^ byte;
+            }
+        }
+
+        let mut aes = Aes { round_keys: [[0; BLOCK_SIZE]; 15], rounds };
+        for (round, key) in aes.round_keys.iter_mut().enumerate().take(rounds + 1) {
+            for (column, word) in words[round * 4..round * 4 + 4].iter().enumerate() {
+                key[column * 4..column * 4 + 4].copy_from_slice(word);
+            }
+        }
+        words.fill([0; 4]);
+        Some(aes)
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; BLOCK_SIZE]) {
+        add_round_key(block, &self.round_keys[0]);
+        for round in 1..=self.rounds {
+            for byte in block.iter_mut() {
+                *byte = SBOX[*byte as usize];
+            }
+            shift_rows(block);
+            if round != self.rounds {
+                mix_columns(block);
+            }
+            add_round_key(block, &self.round_keys[round]);
+        }
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8; BLOCK_SIZE]) {
+        add_round_key(block, &self.round_keys[self.rounds]);
+        for round in (0..self.rounds).rev() {
+            inv_shift_rows(block);
+            for byte in block.iter_mut() {
+                *byte = INV_SBOX[*byte as usize];
+            }
+            add_round_key(block, &self.round_keys[round]);
+            if round != 0 {
+                inv_mix_columns(block);
+            }
+        }
+    }
+}
+
+impl Drop for Aes {
+    fn drop(&mut self) {
+        for key in self.round_keys.iter_mut() {
+            for byte in key.iter_mut() {
+                // Volatile, so wiping a key about to be freed is not
+                // optimised away
+                unsafe { core::ptr::write_volatile(byte, 0) };
+            }
+        }
+    }
+}
+
+fn add_round_key(block: &mut [u8; BLOCK_SIZE], key: &[u8; BLOCK_SIZE]) {
+    for (byte, key) in block.iter_mut().zip(key) {
+        *byte ^= key;
+    }
+}
+
+/// The state is stored column by column, so row r of column c is at 4c + r
+fn shift_rows(block: &mut [u8; BLOCK_SIZE]) {
+    let state = *block;
+    for c in 0..4 {
+        for r in 0..4 {
+            block[4 * c + r] = state[4 * ((c + r) % 4) + r];
+        }
+    }
+}
+
+fn inv_shift_rows(block: &mut [u8; BLOCK_SIZE]) {
+    let state = *block;
+    for c in 0..4 {
+        for r in 0..4 {
+            block[4 * ((c + r) % 4) + r] = state[4 * c + r];
+        }
+    }
+}
+
+fn mix_columns(block: &mut [u8; BLOCK_SIZE]) {
+    for column in block.chunks_exact_mut(4) {
+        let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+        column[0] = gmul(a, 2) ^ gmul(b, 3) ^ c ^ d;
+        column[1] = a ^ gmul(b, 2) ^ gmul(c, 3) ^ d;
+        column[2] = a ^ b ^ gmul(c, 2) ^ gmul(d, 3);
+        column[3] = gmul(a, 3) ^ b ^ c ^ gmul(d, 2);
+    }
+}
+
+fn inv_mix_columns(block: &mut [u8; BLOCK_SIZE]) {
+    for column in block.chunks_exact_mut(4) {
+        let [a, b, c, d] = [column[0], column[1], column[2], column[3]];
+        column[0] = gmul(a, 14) ^ gmul(b, 11) ^ gmul(c, 13) ^ gmul(d, 9);
+        column[1] = gmul(a, 9) ^ gmul(b, 14) ^ gmul(c, 11) ^ gmul(d, 13);
+        column[2] = gmul(a, 13) ^ gmul(b, 9) ^ gmul(c, 14) ^ gmul(d, 11);
+        column[3] = gmul(a, 11) ^ gmul(b, 13) ^ gmul(c, 9) ^ gmul(d, 14);
+    }
+}
+
+/// XTS-AES: a key twice the AES key size, the first half encrypting the
+/// data and the second the tweak. Each data unit - a disk sector - is
+/// encrypted under its own number, so equal sectors encrypt differently.
+pub struct Xts {
+    data: Aes,
+    tweak: Aes,
+}
+
+impl Xts {
+    /// A 32 or 64-byte key, for XTS-AES-128 or XTS-AES-256
+    pub fn new(key: &[u8]) -> Option<Xts> {
+        if !matches!(key.len(), 32 | 64) {
+            return None;
+        }
+        let (data, tweak) = key.split_at(key.len() / 2);
+        Some(Xts { data: Aes::new(data)?, tweak: Aes::new(tweak)? })
+    }
+
+    /// Bits of key, counting both halves
+    pub fn key_bits(&self) -> usize {
+        (self.data.rounds - 6) * 64
+    }
+
+    /// Encrypt data unit `unit` in place; its length must be a multiple of
+    /// the block size
+    pub fn encrypt(&self, unit: u64, data: &mut [u8]) {
+        self.crypt(unit, data, Aes::encrypt_block);
+    }
+
+    pub fn decrypt(&self, unit: u64, data: &mut [u8]) {
+        self.crypt(unit, data, Aes::decrypt_block);
+    }
+
+    fn crypt(&self, unit: u64, data: &mut [u8], cipher: fn(&Aes, &mut [u8; BLOCK_SIZE])) {
+        debug_assert!(data.len() % BLOCK_SIZE == 0, "XTS data unit is not whole blocks");
+        let mut tweak = [0u8; BLOCK_SIZE];
+        tweak[..8].copy_from_slice(&unit.to_le_bytes());
+        self.tweak.encrypt_block(&mut tweak);
+        for chunk in data.chunks_exact_mut(BLOCK_SIZE) {
+            let mut block = [0u8; BLOCK_SIZE];
+            for ((out, byte), t) in block.iter_mut().zip(chunk.iter()).zip(&tweak) {
+                *out = byte ^ t;
+            }
+            cipher(&self.data, &mut block);
+            for ((byte, out), t) in chunk.iter_mut().zip(&block).zip(&tweak) {
+                *byte = out ^ t;
+            }
+            double(&mut tweak);
+        }
+    }
+}
+
+/// Multiply the tweak by x in GF(2^128), little-endian as XTS has it
+fn double(tweak: &mut [u8; BLOCK_SIZE]) {
+    let mut carry = 0;
+    for byte in tweak.iter_mut() {
+        let next = *byte >> 7;
+        *byte = (*byte << 1) | carry;
+        carry = next;
+    }
+    if carry != 0 {
+        tweak[0] ^= 0x87;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qsf::crypto::{from_hex, to_hex};
+
+    #[test_case]
+    fn test_aes_vectors() {
+        // FIPS 197 appendix C
+        let plain = from_hex("00112233445566778899aabbccddeeff").unwrap();
+        for (key, expected) in [
+            ("000102030405060708090a0b0c0d0e0f", "69c4e0d86a7b0430d8cdb78070b4c55a"),
+            ("000102030405060708090a0b0c0d0e0f1011121314151617", "dda97ca4864cdfe06eaf70a0ec0d7191"),
+            ("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f", "8ea2b7ca516745bfeafc49904b496089"),
+        ] {
+            let aes = Aes::new(&from_hex(key).unwrap()).unwrap();
+            let mut block = [0u8; BLOCK_SIZE];
+            block.copy_from_slice(&plain);
+            aes.encrypt_block(&mut block);
+            assert_eq!(to_hex(&block), expected);
+            aes.decrypt_block(&mut block);
+            assert_eq!(&block[..], &plain[..]);
+        }
+        assert!(Aes::new(&[0; 20]).is_none());
+    }
+
+    #[test_case]
+    fn test_xts_vectors() {
+        // IEEE 1619 vectors 1 and 2
+        let xts = Xts::new(&[0; 32]).unwrap();
+        let mut data = [0u8; 32];
+        xts.encrypt(0, &mut data);
+        assert_eq!(to_hex(&data), "917cf69ebd68b2ec9b9fe9a3eadda692cd43d2f59598ed858c02c2652fbf922e");
+        xts.decrypt(0, &mut data);
+        assert_eq!(data, [0; 32]);
+
+        let mut key = [0x11u8; 32];
+        key[16..].fill(0x22);
+        let xts = Xts::new(&key).unwrap();
+        let mut data = [0x44u8; 32];
+        xts.encrypt(0x3333333333, &mut data);
+        assert_eq!(to_hex(&data), "c454185e6a16936e39334038acef838bfb186fff7480adc4289382ecd6d394f0");
+        assert_eq!(xts.key_bits(), 256);
+    }
+}
@@ -0,0 +1,122 @@
+// QSF lockdown: once engaged, security configuration is frozen until reboot.
+// Policy can then only change through an authenticated update, and every
+// configuration that was locked in is recorded in a measurement log.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use super::crypto;
+use super::policies::{self, SecurityPolicy};
+
+/// Kernel command line flag that engages lockdown during QSF init.
+pub const LOCKDOWN_PARAM: &str = "qsf.lockdown";
+
+/// Key for authenticating policy updates, fixed at build time. Without it
+/// no policy update is accepted once locked down.
+const POLICY_KEY: Option<&str> = option_env!("QSF_POLICY_KEY");
+
+/// Operations refused while locked down, even for root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockdownFeature {
+    SecurityLevel,
+    PolicyChange,
+    CapabilityGrant,
+    Confinement,
+    IntegrityDatabase,
+    AuditLog,
+    RawDeviceAccess,
+    ModuleLoad,
+}
+
+impl LockdownFeature {
+    pub fn name(self) -> &'static str {
+        match self {
+            LockdownFeature::SecurityLevel => "security level change",
+            LockdownFeature::PolicyChange => "policy modification",
+            LockdownFeature::CapabilityGrant => "capability grant",
+            LockdownFeature::Confinement => "confinement change",
+            LockdownFeature::IntegrityDatabase => "integrity database change",
+            LockdownFeature::AuditLog => "audit log tampering",
+            LockdownFeature::RawDeviceAccess => "raw device access",
+            LockdownFeature::ModuleLoad => "kernel code loading",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QsfError {
+    Locked(LockdownFeature),
+    NotFound,
+    NoUpdateKey,
+    BadSignature,
+    InvalidPolicy(&'static str),
+}
+
+pub type QsfResult<T> = Result<T, QsfError>;
+
+/// Devices that expose raw storage or memory and would let root bypass
+/// every file-level rule.
+const RAW_DEVICES: &[&str] = &[
+    "/dev/sd*", "/dev/hd*", "/dev/nvme*", "/dev/mmcblk*", "/dev/mem", "/dev/kmem", "/dev/port",
+];
+
+pub fn is_raw_device(path: &str) -> bool {
+    RAW_DEVICES.iter().any(|pattern| policies::pattern::matches(pattern, path))
+}
+
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub what: String,
+    pub digest: [u8; 32],
+}
+
+/// Append-only measurement log with a TPM-style extend register:
+/// `pcr = sha256(pcr || digest)`.
+pub struct MeasurementLog {
+    pcr: [u8; 32],
+    entries: Vec<Measurement>,
+}
+
+impl MeasurementLog {
+    pub fn new() -> Self {
+        MeasurementLog {
+            pcr: [0; 32],
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn extend(&mut self, what: &str, data: &[u8]) {
+        let digest = crypto::sha256(data);
+        let mut hasher = crypto::Sha256::new();
+        hasher.update(&self.pcr);
+        hasher.update(&digest);
+        self.pcr = hasher.finalize();
+        self.entries.push(Measurement {
+            what: String::from(what),
+            digest,
+        });
+    }
+
+    pub fn pcr(&self) -> [u8; 32] {
+        self.pcr
+    }
+
+    pub fn entries(&self) -> &[Measurement] {
+        &self.entries
+    }
+}
+
+pub fn update_key_present() -> bool {
+    POLICY_KEY.is_some()
+}
+
+/// Verifies an HMAC-SHA256 tag over a textual policy and parses it.
+pub fn verify_policy_update(text: &[u8], tag: &[u8]) -> QsfResult<SecurityPolicy> {
+    let key = POLICY_KEY.ok_or(QsfError::NoUpdateKey)?;
+    let expected = crypto::hmac_sha256(key.as_bytes(), text);
+    if !crypto::digest_eq(&expected, tag) {
+        return Err(QsfError::BadSignature);
+    }
+
+    let text = core::str::from_utf8(text).map_err(|_| QsfError::InvalidPolicy("policy is not UTF-8"))?;
+    policies::parse_policy(text).map_err(QsfError::InvalidPolicy)
+}
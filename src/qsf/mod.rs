@@ -1,13 +1,20 @@
+pub mod cipher;
+pub mod crypto;
+pub mod lockdown;
 pub mod modules;
 pub mod policies;
 pub mod qsf;
 
 pub use qsf::*;
+pub use lockdown::{LockdownFeature, QsfError, QsfResult};
 
 use crate::println;
 
 pub fn init() {
     println!("  [QSF] Initializing Qunix Security Framework...");
     qsf::init_qsf();
+    if QSF.lock().is_locked() {
+        println!("  [QSF] Lockdown engaged");
+    }
     println!("  [QSF] Security framework initialized");
 }
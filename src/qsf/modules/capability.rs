@@ -31,6 +31,7 @@ impl CapabilityModule {
         root_caps.insert(Capability::CapSysChroot);
         root_caps.insert(Capability::CapSysModule);
         root_caps.insert(Capability::CapSysRawio);
+        root_caps.insert(Capability::CapSysTime);
         root_caps.insert(Capability::CapMknod);
         module.user_capabilities.insert(0, root_caps);
         
@@ -1,10 +1,15 @@
-use alloc::collections::BTreeMap;
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::String;
 use alloc::vec::Vec;
 use alloc::vec;
+use crate::qsf::policies::pattern;
+use crate::qsf::qsf::Capability;
 
 pub struct ConfinementModule {
     process_confinements: BTreeMap<u32, ProcessConfinement>,
+    profiles: BTreeMap<String, ConfinementProfile>,
+    /// Executable path pattern -> profile name.
+    bindings: Vec<(String, String)>,
     enabled: bool,
 }
 
@@ -20,6 +25,62 @@ pub struct ProcessConfinement {
     pub can_exec: bool,
     pub max_memory: Option<usize>,
     pub max_files: Option<usize>,
+    /// Capability bounding set; `None` leaves capabilities unrestricted.
+    pub capabilities: Option<BTreeSet<Capability>>,
+    /// Profile this confinement was built from, if any.
+    pub profile: Option<String>,
+}
+
+/// What happens to a confined process's profile when it execs a program
+/// that has no profile bound to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecTransition {
+    /// Keep the current profile.
+    Inherit,
+    /// Switch to the named profile.
+    Transition(String),
+    /// Drop confinement.
+    Unconfined,
+}
+
+/// A named confinement profile, bound to executables rather than pids.
+#[derive(Debug, Clone)]
+pub struct ConfinementProfile {
+    pub name: String,
+    pub allowed_paths: Vec<String>,
+    pub denied_paths: Vec<String>,
+    pub network_allowed: bool,
+    pub capabilities: Option<BTreeSet<Capability>>,
+    pub can_fork: bool,
+    pub can_exec: bool,
+    pub on_exec: ExecTransition,
+}
+
+impl ConfinementProfile {
+    pub fn new(name: &str) -> Self {
+        ConfinementProfile {
+            name: String::from(name),
+            allowed_paths: Vec::new(),
+            denied_paths: Vec::new(),
+            network_allowed: true,
+            capabilities: None,
+            can_fork: true,
+            can_exec: true,
+            on_exec: ExecTransition::Inherit,
+        }
+    }
+    
+    pub fn to_confinement(&self, pid: u32) -> ProcessConfinement {
+        let mut c = ProcessConfinement::new(pid);
+        c.allowed_paths = self.allowed_paths.clone();
+        c.denied_paths = self.denied_paths.clone();
+        c.network_allowed = self.network_allowed;
+        c.capabilities = self.capabilities.clone();
+        c.can_fork = self.can_fork;
+        c.can_exec = self.can_exec;
+        c.profile = Some(self.name.clone());
+        c
+    }
 }
 
 impl ProcessConfinement {
@@ -35,6 +96,8 @@ impl ProcessConfinement {
             can_exec: true,
             max_memory: None,
             max_files: None,
+            capabilities: None,
+            profile: None,
         }
     }
     
@@ -59,6 +122,8 @@ impl ProcessConfinement {
             can_exec: false,
             max_memory: Some(64 * 1024 * 1024),
             max_files: Some(16),
+            capabilities: Some(BTreeSet::new()),
+            profile: None,
         }
     }
     
@@ -91,10 +156,108 @@ impl ConfinementModule {
     pub fn new() -> Self {
         ConfinementModule {
             process_confinements: BTreeMap::new(),
+            profiles: BTreeMap::new(),
+            bindings: Vec::new(),
             enabled: true,
         }
     }
     
+    pub fn add_profile(&mut self, profile: ConfinementProfile) {
+        self.profiles.insert(profile.name.clone(), profile);
+    }
+    
+    pub fn remove_profile(&mut self, name: &str) -> bool {
+        self.bindings.retain(|(_, profile)| profile != name);
+        self.profiles.remove(name).is_some()
+    }
+    
+    pub fn profiles(&self) -> impl Iterator<Item = &ConfinementProfile> {
+        self.profiles.values()
+    }
+    
+    /// Binds an executable path pattern to a profile; later bindings for the
+    /// same pattern replace earlier ones.
+    pub fn bind_profile(&mut self, exec_pattern: &str, profile: &str) -> bool {
+        if !self.profiles.contains_key(profile) {
+            return false;
+        }
+        self.bindings.retain(|(pattern, _)| pattern != exec_pattern);
+        self.bindings.push((String::from(exec_pattern), String::from(profile)));
+        true
+    }
+    
+    pub fn unbind_profile(&mut self, exec_pattern: &str) -> bool {
+        let before = self.bindings.len();
+        self.bindings.retain(|(pattern, _)| pattern != exec_pattern);
+        self.bindings.len() != before
+    }
+    
+    pub fn bindings(&self) -> &[(String, String)] {
+        &self.bindings
+    }
+    
+    pub fn profile_of(&self, pid: u32) -> Option<&str> {
+        self.process_confinements.get(&pid).and_then(|c| c.profile.as_deref())
+    }
+    
+    /// Applies profile rules when `pid` execs `path`: a profile bound to the
+    /// executable wins, otherwise the current profile's exec transition
+    /// decides. A transition to a profile that no longer exists keeps the
+    /// confinement the process has. Returns the profile now in effect.
+    pub fn apply_exec(&mut self, pid: u32, path: &str) -> Option<String> {
+        let bound = self.bindings
+            .iter()
+            .rev()
+            .find(|(pattern, _)| pattern::matches(pattern, path))
+            .map(|(_, profile)| profile.clone());
+
+        let next = match bound {
+            Some(profile) => Some(profile),
+            None => {
+                let current = self.profile_of(pid).and_then(|name| self.profiles.get(name));
+                match current.map(|p| &p.on_exec) {
+                    None => return self.profile_of(pid).map(String::from),
+                    Some(ExecTransition::Inherit) => current.map(|p| p.name.clone()),
+                    Some(ExecTransition::Transition(name)) => Some(name.clone()),
+                    Some(ExecTransition::Unconfined) => None,
+                }
+            }
+        };
+
+        match next {
+            Some(name) => {
+                if let Some(profile) = self.profiles.get(&name) {
+                    let confinement = profile.to_confinement(pid);
+                    self.process_confinements.insert(pid, confinement);
+                }
+            }
+            None => {
+                self.process_confinements.remove(&pid);
+            }
+        }
+        self.profile_of(pid).map(String::from)
+    }
+    
+    /// Children start with a copy of their parent's confinement.
+    pub fn inherit(&mut self, parent: u32, child: u32) {
+        if let Some(parent_confinement) = self.process_confinements.get(&parent) {
+            let mut c = parent_confinement.clone();
+            c.pid = child;
+            self.process_confinements.insert(child, c);
+        }
+    }
+    
+    pub fn capability_allowed(&self, pid: u32, cap: Capability) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        
+        self.process_confinements
+            .get(&pid)
+            .and_then(|c| c.capabilities.as_ref())
+            .map_or(true, |set| set.contains(&cap))
+    }
+    
     pub fn confine(&mut self, pid: u32, paths: Vec<String>, network: bool) {
         let mut confinement = ProcessConfinement::new(pid);
         confinement.allowed_paths = paths;
@@ -106,6 +269,19 @@ impl ConfinementModule {
         self.process_confinements.insert(pid, confinement);
     }
     
+    /// Confine `pid` by the named profile; false if there is no such
+    /// profile
+    pub fn apply_profile(&mut self, pid: u32, profile: &str) -> bool {
+        match self.profiles.get(profile) {
+            Some(profile) => {
+                let confinement = profile.to_confinement(pid);
+                self.process_confinements.insert(pid, confinement);
+                true
+            }
+            None => false,
+        }
+    }
+    
     pub fn unconfine(&mut self, pid: u32) {
         self.process_confinements.remove(&pid);
     }
@@ -125,7 +301,7 @@ impl ConfinementModule {
         };
         
         for denied in &confinement.denied_paths {
-            if path.starts_with(denied) {
+            if pattern::matches_tree(denied, path) {
                 return false;
             }
         }
@@ -135,7 +311,7 @@ impl ConfinementModule {
         }
         
         for allowed in &confinement.allowed_paths {
-            if path.starts_with(allowed) {
+            if pattern::matches_tree(allowed, path) {
                 return true;
             }
         }
@@ -242,3 +418,80 @@ impl SandboxProfile {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module() -> ConfinementModule {
+        let mut module = ConfinementModule::new();
+        let mut shell = ConfinementProfile::new("shell");
+        shell.allowed_paths = vec![String::from("/home/")];
+        module.add_profile(shell);
+        module.add_profile(ConfinementProfile::new("viewer"));
+        module
+    }
+
+    fn set_on_exec(module: &mut ConfinementModule, profile: &str, on_exec: ExecTransition) {
+        module.profiles.get_mut(profile).unwrap().on_exec = on_exec;
+    }
+
+    #[test_case]
+    fn test_bind_profile() {
+        let mut module = module();
+        assert!(!module.bind_profile("/bin/*", "missing"));
+        assert!(module.bind_profile("/bin/*", "shell"));
+        assert!(module.bind_profile("/bin/*", "viewer"));
+        assert_eq!(module.bindings().len(), 1);
+
+        assert_eq!(module.apply_exec(7, "/bin/less").as_deref(), Some("viewer"));
+        assert_eq!(module.apply_exec(8, "/sbin/init"), None);
+        assert!(!module.is_confined(8));
+
+        assert!(module.remove_profile("viewer"));
+        assert!(module.bindings().is_empty());
+    }
+
+    #[test_case]
+    fn test_exec_inherit() {
+        let mut module = module();
+        module.apply_profile(7, "shell");
+        assert_eq!(module.apply_exec(7, "/bin/ls").as_deref(), Some("shell"));
+        assert!(!module.check_path_access(7, "/etc/passwd"));
+    }
+
+    #[test_case]
+    fn test_exec_transition() {
+        let mut module = module();
+        set_on_exec(&mut module, "shell", ExecTransition::Transition(String::from("viewer")));
+        module.apply_profile(7, "shell");
+        assert_eq!(module.apply_exec(7, "/bin/ls").as_deref(), Some("viewer"));
+        assert!(module.check_path_access(7, "/etc/passwd"));
+    }
+
+    #[test_case]
+    fn test_exec_unconfined() {
+        let mut module = module();
+        set_on_exec(&mut module, "shell", ExecTransition::Unconfined);
+        module.apply_profile(7, "shell");
+        assert_eq!(module.apply_exec(7, "/bin/ls"), None);
+        assert!(!module.is_confined(7));
+    }
+
+    #[test_case]
+    fn test_exec_into_missing_profile() {
+        let mut module = module();
+        set_on_exec(&mut module, "shell", ExecTransition::Transition(String::from("gone")));
+        module.apply_profile(7, "shell");
+        assert_eq!(module.apply_exec(7, "/bin/ls").as_deref(), Some("shell"));
+        assert!(!module.check_path_access(7, "/etc/passwd"));
+
+        // So does a profile removed after the process was confined by it
+        module.apply_profile(8, "viewer");
+        set_on_exec(&mut module, "shell", ExecTransition::Transition(String::from("viewer")));
+        module.remove_profile("viewer");
+        assert_eq!(module.apply_exec(7, "/bin/ls").as_deref(), Some("shell"));
+        assert_eq!(module.apply_exec(8, "/bin/ls").as_deref(), Some("viewer"));
+        assert!(module.is_confined(8));
+    }
+}
@@ -1,7 +1,9 @@
 pub mod integrity;
 pub mod capability;
 pub mod confinement;
+pub mod trace;
 
 pub use integrity::IntegrityModule;
 pub use capability::CapabilityModule;
 pub use confinement::ConfinementModule;
+pub use trace::SyscallTraceModule;
@@ -0,0 +1,120 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Records allowed per traced process in one rate-limit window.
+pub const TRACE_BURST: u32 = 64;
+/// Rate-limit window length in PIT ticks (1 kHz, so one second).
+pub const TRACE_WINDOW_TICKS: u64 = 1000;
+
+/// Number of traced processes, readable without taking the QSF lock so the
+/// syscall fast path can skip tracing entirely when nobody is traced.
+static TRACED_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn tracing_active() -> bool {
+    TRACED_COUNT.load(Ordering::Relaxed) != 0
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TraceState {
+    pub window_start: u64,
+    pub recorded_in_window: u32,
+    pub suppressed: u64,
+    pub total: u64,
+}
+
+/// Outcome of asking whether a syscall for a traced pid may be logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceVerdict {
+    NotTraced,
+    Record,
+    /// Record, but first note how many entries the previous window dropped.
+    RecordAfterSuppressed(u64),
+    Suppress,
+}
+
+pub struct SyscallTraceModule {
+    traced: BTreeMap<u32, TraceState>,
+}
+
+impl SyscallTraceModule {
+    pub fn new() -> Self {
+        SyscallTraceModule {
+            traced: BTreeMap::new(),
+        }
+    }
+
+    pub fn enable(&mut self, pid: u32, now: u64) -> bool {
+        if self.traced.contains_key(&pid) {
+            return false;
+        }
+        self.traced.insert(pid, TraceState {
+            window_start: now,
+            recorded_in_window: 0,
+            suppressed: 0,
+            total: 0,
+        });
+        TRACED_COUNT.store(self.traced.len(), Ordering::Relaxed);
+        true
+    }
+
+    pub fn disable(&mut self, pid: u32) -> bool {
+        let removed = self.traced.remove(&pid).is_some();
+        TRACED_COUNT.store(self.traced.len(), Ordering::Relaxed);
+        removed
+    }
+
+    pub fn is_traced(&self, pid: u32) -> bool {
+        self.traced.contains_key(&pid)
+    }
+
+    pub fn traced(&self) -> Vec<(u32, TraceState)> {
+        self.traced.iter().map(|(pid, state)| (*pid, *state)).collect()
+    }
+
+    pub fn admit(&mut self, pid: u32, now: u64) -> TraceVerdict {
+        let state = match self.traced.get_mut(&pid) {
+            Some(s) => s,
+            None => return TraceVerdict::NotTraced,
+        };
+
+        let mut carried = 0;
+        if now.wrapping_sub(state.window_start) >= TRACE_WINDOW_TICKS {
+            state.window_start = now;
+            state.recorded_in_window = 0;
+            carried = state.suppressed;
+            state.suppressed = 0;
+        }
+
+        if state.recorded_in_window >= TRACE_BURST {
+            state.suppressed += 1;
+            return TraceVerdict::Suppress;
+        }
+
+        state.recorded_in_window += 1;
+        state.total += 1;
+        if carried > 0 {
+            TraceVerdict::RecordAfterSuppressed(carried)
+        } else {
+            TraceVerdict::Record
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_trace_rate_limit() {
+        let mut trace = SyscallTraceModule::new();
+        assert_eq!(trace.admit(7, 0), TraceVerdict::NotTraced);
+        trace.enable(7, 0);
+        for _ in 0..TRACE_BURST {
+            assert_eq!(trace.admit(7, 10), TraceVerdict::Record);
+        }
+        assert_eq!(trace.admit(7, 10), TraceVerdict::Suppress);
+        assert_eq!(trace.admit(7, TRACE_WINDOW_TICKS), TraceVerdict::RecordAfterSuppressed(1));
+        trace.disable(7);
+    }
+}
@@ -1,5 +1,9 @@
+pub mod pattern;
+
 use alloc::string::String;
 use alloc::vec::Vec;
+use core::fmt;
+use core::net::Ipv4Addr;
 
 #[derive(Debug, Clone)]
 pub struct SecurityPolicy {
@@ -7,6 +11,18 @@ pub struct SecurityPolicy {
     pub version: String,
     pub rules: Vec<PolicyRule>,
     pub enabled: bool,
+    pub resolution: ConflictResolution,
+}
+
+/// How a policy decides when several rules match the same request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// Any matching deny rule wins, regardless of priority.
+    DenyOverrides,
+    /// The highest-priority matching rule wins; ties go to the earlier rule.
+    FirstMatch,
+    /// Any matching allow rule wins, regardless of priority.
+    AllowOverrides,
 }
 
 #[derive(Debug, Clone)]
@@ -15,6 +31,53 @@ pub struct PolicyRule {
     pub object: Object,
     pub permissions: Permissions,
     pub action: PolicyAction,
+    /// Higher priorities are evaluated first.
+    pub priority: i32,
+}
+
+impl PolicyRule {
+    pub fn new(subject: Subject, object: Object, permissions: Permissions, action: PolicyAction) -> Self {
+        PolicyRule {
+            subject,
+            object,
+            permissions,
+            action,
+            priority: 0,
+        }
+    }
+    
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+impl ConflictResolution {
+    pub fn parse(s: &str) -> Option<ConflictResolution> {
+        match s {
+            "deny-overrides" => Some(ConflictResolution::DenyOverrides),
+            "first-match" => Some(ConflictResolution::FirstMatch),
+            "allow-overrides" => Some(ConflictResolution::AllowOverrides),
+            _ => None,
+        }
+    }
+    
+    pub fn name(self) -> &'static str {
+        match self {
+            ConflictResolution::DenyOverrides => "deny-overrides",
+            ConflictResolution::FirstMatch => "first-match",
+            ConflictResolution::AllowOverrides => "allow-overrides",
+        }
+    }
+}
+
+/// Result of evaluating a request against one policy.
+#[derive(Debug, Clone)]
+pub struct PolicyDecision {
+    pub action: PolicyAction,
+    /// Index into `SecurityPolicy::rules` of the rule that decided, if any.
+    pub rule: Option<usize>,
+    pub reason: &'static str,
 }
 
 #[derive(Debug, Clone)]
@@ -96,6 +159,32 @@ impl Permissions {
             ..Default::default()
         }
     }
+    
+    pub fn has(&self, permission: &str) -> bool {
+        match permission {
+            "read" => self.read,
+            "write" => self.write,
+            "execute" => self.execute,
+            "append" => self.append,
+            "create" => self.create,
+            "delete" => self.delete,
+            "setattr" => self.setattr,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bits = [
+            (self.read, 'r'), (self.write, 'w'), (self.execute, 'x'), (self.append, 'a'),
+            (self.create, 'c'), (self.delete, 'd'), (self.setattr, 's'),
+        ];
+        for (set, c) in bits {
+            write!(f, "{}", if set { c } else { '-' })?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -107,6 +196,76 @@ pub enum PolicyAction {
     AuditDeny,
 }
 
+impl PolicyAction {
+    pub fn is_deny(self) -> bool {
+        matches!(self, PolicyAction::Deny | PolicyAction::AuditDeny)
+    }
+}
+
+impl Subject {
+    /// Parses `user:N`, `group:N`, `pid:N`, `role:NAME` or `any`.
+    pub fn parse(s: &str) -> Option<Subject> {
+        if s == "any" {
+            return Some(Subject::Any);
+        }
+        let (kind, value) = s.split_once(':')?;
+        match kind {
+            "user" | "uid" => value.parse().ok().map(Subject::User),
+            "group" | "gid" => value.parse().ok().map(Subject::Group),
+            "pid" => value.parse().ok().map(Subject::Process),
+            "role" => Some(Subject::Role(String::from(value))),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Subject::User(uid) => write!(f, "user:{}", uid),
+            Subject::Group(gid) => write!(f, "group:{}", gid),
+            Subject::Process(pid) => write!(f, "pid:{}", pid),
+            Subject::Role(role) => write!(f, "role:{}", role),
+            Subject::Any => write!(f, "any"),
+        }
+    }
+}
+
+impl Object {
+    /// Parses `file:PATH`, `dir:PATH`, `pid:N`, `net:ADDR:PORT`, `cap:NAME` or `any`.
+    /// ADDR may be `*` or a network as ADDR/PREFIX, and port 0 means any.
+    pub fn parse(s: &str) -> Option<Object> {
+        if s == "any" {
+            return Some(Object::Any);
+        }
+        let (kind, value) = s.split_once(':')?;
+        match kind {
+            "file" => Some(Object::File(String::from(value))),
+            "dir" => Some(Object::Directory(String::from(value))),
+            "pid" => value.parse().ok().map(Object::Process),
+            "net" => {
+                let (addr, port) = value.rsplit_once(':')?;
+                Some(Object::Network(String::from(addr), port.parse().ok()?))
+            }
+            "cap" => Some(Object::Capability(String::from(value))),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Object::File(path) => write!(f, "file:{}", path),
+            Object::Directory(path) => write!(f, "dir:{}", path),
+            Object::Process(pid) => write!(f, "pid:{}", pid),
+            Object::Network(addr, port) => write!(f, "net:{}:{}", addr, port),
+            Object::Capability(cap) => write!(f, "cap:{}", cap),
+            Object::Any => write!(f, "any"),
+        }
+    }
+}
+
 impl SecurityPolicy {
     pub fn new(name: &str, version: &str) -> Self {
         SecurityPolicy {
@@ -114,6 +273,7 @@ impl SecurityPolicy {
             version: String::from(version),
             rules: Vec::new(),
             enabled: true,
+            resolution: ConflictResolution::DenyOverrides,
         }
     }
     
@@ -137,31 +297,84 @@ impl SecurityPolicy {
         self.enabled = false;
     }
     
+    pub fn set_resolution(&mut self, resolution: ConflictResolution) {
+        self.resolution = resolution;
+    }
+    
     pub fn check(&self, subject: &Subject, object: &Object, permission: &str) -> PolicyAction {
+        self.evaluate(subject, object, permission).action
+    }
+    
+    /// Evaluates a request and reports which rule decided it.
+    pub fn evaluate(&self, subject: &Subject, object: &Object, permission: &str) -> PolicyDecision {
         if !self.enabled {
-            return PolicyAction::Allow;
+            return PolicyDecision { action: PolicyAction::Allow, rule: None, reason: "policy disabled" };
         }
         
-        for rule in &self.rules {
-            if matches_subject(&rule.subject, subject) && matches_object(&rule.object, object) {
-                let has_permission = match permission {
-                    "read" => rule.permissions.read,
-                    "write" => rule.permissions.write,
-                    "execute" => rule.permissions.execute,
-                    "append" => rule.permissions.append,
-                    "create" => rule.permissions.create,
-                    "delete" => rule.permissions.delete,
-                    "setattr" => rule.permissions.setattr,
-                    _ => false,
-                };
-                
-                if has_permission {
-                    return rule.action;
-                }
-            }
+        // Matching rules in evaluation order: priority descending, then
+        // declaration order.
+        match self.choose(subject, object, permission, |_| true) {
+            Some(i) => PolicyDecision {
+                action: self.rules[i].action,
+                rule: Some(i),
+                reason: self.resolution.name(),
+            },
+            None => PolicyDecision { action: PolicyAction::Deny, rule: None, reason: "no matching rule (default deny)" },
         }
+    }
+    
+    /// Like `evaluate`, but only `net:` rules take part and a request none
+    /// of them matches is left undecided rather than denied. The packet
+    /// filter asks this, so the same rules govern packets and sockets.
+    pub fn evaluate_network(&self, subject: &Subject, object: &Object, permission: &str) -> Option<PolicyDecision> {
+        if !self.enabled {
+            return None;
+        }
+        let i = self.choose(subject, object, permission, |rule| matches!(rule.object, Object::Network(..)))?;
+        Some(PolicyDecision { action: self.rules[i].action, rule: Some(i), reason: self.resolution.name() })
+    }
+    
+    /// Like `evaluate_network`, for rules that can be about a file: file,
+    /// directory and `any` rules. File access asks this, so a request no
+    /// rule is about is left to the other checks.
+    pub fn evaluate_file(&self, subject: &Subject, object: &Object, permission: &str) -> Option<PolicyDecision> {
+        if !self.enabled {
+            return None;
+        }
+        let about_files = |rule: &PolicyRule| matches!(rule.object, Object::File(_) | Object::Directory(_) | Object::Any);
+        let i = self.choose(subject, object, permission, about_files)?;
+        Some(PolicyDecision { action: self.rules[i].action, rule: Some(i), reason: self.resolution.name() })
+    }
+    
+    /// Whether any rule is about network traffic
+    pub fn has_network_rules(&self) -> bool {
+        self.enabled && self.rules.iter().any(|rule| matches!(rule.object, Object::Network(..)))
+    }
+    
+    /// Index of the rule that decides a request, among those `eligible`
+    fn choose(&self, subject: &Subject, object: &Object, permission: &str, eligible: impl Fn(&PolicyRule) -> bool) -> Option<usize> {
+        let mut matching: Vec<usize> = (0..self.rules.len())
+            .filter(|&i| {
+                let rule = &self.rules[i];
+                eligible(rule)
+                    && matches_subject(&rule.subject, subject)
+                    && matches_object(&rule.object, object)
+                    && rule.permissions.has(permission)
+            })
+            .collect();
+        matching.sort_by(|&a, &b| self.rules[b].priority.cmp(&self.rules[a].priority));
         
-        PolicyAction::Deny
+        match self.resolution {
+            ConflictResolution::FirstMatch => matching.first().copied(),
+            ConflictResolution::DenyOverrides => matching.iter()
+                .copied()
+                .find(|&i| self.rules[i].action.is_deny())
+                .or_else(|| matching.first().copied()),
+            ConflictResolution::AllowOverrides => matching.iter()
+                .copied()
+                .find(|&i| !self.rules[i].action.is_deny())
+                .or_else(|| matching.first().copied()),
+        }
     }
 }
 
@@ -179,40 +392,211 @@ fn matches_subject(pattern: &Subject, subject: &Subject) -> bool {
 fn matches_object(pattern: &Object, object: &Object) -> bool {
     match (pattern, object) {
         (Object::Any, _) => true,
-        (Object::File(p), Object::File(o)) => o.starts_with(p) || p == "*",
-        (Object::Directory(p), Object::Directory(o)) => o.starts_with(p) || p == "*",
+        (Object::File(p), Object::File(o)) => pattern::matches(p, o),
+        // A directory rule covers the whole tree below it
+        (Object::Directory(p), Object::Directory(o)) => pattern::matches_tree(p, o),
+        (Object::Directory(p), Object::File(o)) => pattern::matches_tree(p, o),
         (Object::Process(p), Object::Process(o)) => p == o,
         (Object::Network(pa, pp), Object::Network(oa, op)) => {
-            (pa == "*" || pa == oa) && (pp == &0 || pp == op)
+            matches_address(pa, oa) && (pp == &0 || pp == op)
         }
         (Object::Capability(p), Object::Capability(o)) => p == o || p == "*",
         _ => false,
     }
 }
 
+/// Whether `addr` is covered by `pattern`: `*`, an address, or a network
+/// written ADDR/PREFIX
+fn matches_address(pattern: &str, addr: &str) -> bool {
+    if pattern == "*" || pattern == addr {
+        return true;
+    }
+    let (network, prefix) = match pattern.split_once('/') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    match (network.parse::<Ipv4Addr>(), prefix.parse::<u32>(), addr.parse::<Ipv4Addr>()) {
+        (Ok(network), Ok(prefix), Ok(addr)) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(network) & mask == u32::from(addr) & mask
+        }
+        _ => false,
+    }
+}
+
+impl PolicyAction {
+    pub fn parse(s: &str) -> Option<PolicyAction> {
+        match s {
+            "allow" => Some(PolicyAction::Allow),
+            "deny" => Some(PolicyAction::Deny),
+            "audit" => Some(PolicyAction::Audit),
+            "audit-allow" => Some(PolicyAction::AuditAllow),
+            "audit-deny" => Some(PolicyAction::AuditDeny),
+            _ => None,
+        }
+    }
+}
+
+impl Permissions {
+    /// Parses `all`, `none` or a set of letters from `rwxacds`.
+    pub fn parse(s: &str) -> Option<Permissions> {
+        match s {
+            "all" => return Some(Permissions::all()),
+            "none" | "-" => return Some(Permissions::default()),
+            _ => {}
+        }
+        let mut perms = Permissions::default();
+        for c in s.chars() {
+            match c {
+                'r' => perms.read = true,
+                'w' => perms.write = true,
+                'x' => perms.execute = true,
+                'a' => perms.append = true,
+                'c' => perms.create = true,
+                'd' => perms.delete = true,
+                's' => perms.setattr = true,
+                '-' => {}
+                _ => return None,
+            }
+        }
+        Some(perms)
+    }
+}
+
+/// Parses the textual policy format used for policy updates:
+///
+/// ```text
+/// # comment
+/// policy NAME VERSION [deny-overrides|first-match|allow-overrides]
+/// rule ACTION SUBJECT OBJECT PERMS [PRIORITY]
+/// ```
+pub fn parse_policy(text: &str) -> Result<SecurityPolicy, &'static str> {
+    let mut policy: Option<SecurityPolicy> = None;
+    
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields[0] {
+            "policy" => {
+                if policy.is_some() {
+                    return Err("duplicate policy header");
+                }
+                if fields.len() < 3 {
+                    return Err("policy header needs a name and version");
+                }
+                let mut p = SecurityPolicy::new(fields[1], fields[2]);
+                if let Some(strategy) = fields.get(3) {
+                    p.resolution = ConflictResolution::parse(strategy).ok_or("unknown resolution strategy")?;
+                }
+                policy = Some(p);
+            }
+            "rule" => {
+                let p = policy.as_mut().ok_or("rule before policy header")?;
+                if fields.len() < 5 {
+                    return Err("rule needs action, subject, object and permissions");
+                }
+                let action = PolicyAction::parse(fields[1]).ok_or("unknown action")?;
+                let subject = Subject::parse(fields[2]).ok_or("invalid subject")?;
+                let object = Object::parse(fields[3]).ok_or("invalid object")?;
+                let permissions = Permissions::parse(fields[4]).ok_or("invalid permissions")?;
+                let priority = match fields.get(5) {
+                    Some(prio) => prio.parse().map_err(|_| "invalid priority")?,
+                    None => 0,
+                };
+                p.add_rule(PolicyRule::new(subject, object, permissions, action).with_priority(priority));
+            }
+            _ => return Err("unknown directive"),
+        }
+    }
+    
+    policy.ok_or("missing policy header")
+}
+
 pub fn default_policy() -> SecurityPolicy {
     let mut policy = SecurityPolicy::new("default", "1.0");
     
-    policy.add_rule(PolicyRule {
-        subject: Subject::User(0),
-        object: Object::Any,
-        permissions: Permissions::all(),
-        action: PolicyAction::Allow,
-    });
-    
-    policy.add_rule(PolicyRule {
-        subject: Subject::Any,
-        object: Object::File(String::from("/etc/shadow")),
-        permissions: Permissions::default(),
-        action: PolicyAction::Deny,
-    });
-    
-    policy.add_rule(PolicyRule {
-        subject: Subject::Any,
-        object: Object::Directory(String::from("/tmp")),
-        permissions: Permissions::all(),
-        action: PolicyAction::Allow,
-    });
+    // Root is trusted, so this policy resolves by priority rather than
+    // letting the shadow deny override it.
+    policy.set_resolution(ConflictResolution::FirstMatch);
+    
+    policy.add_rule(PolicyRule::new(
+        Subject::User(0),
+        Object::Any,
+        Permissions::all(),
+        PolicyAction::Allow,
+    ).with_priority(100));
+    
+    policy.add_rule(PolicyRule::new(
+        Subject::Any,
+        Object::File(String::from("/etc/shadow")),
+        Permissions::all(),
+        PolicyAction::Deny,
+    ).with_priority(50));
+    
+    policy.add_rule(PolicyRule::new(
+        Subject::Any,
+        Object::Directory(String::from("/tmp")),
+        Permissions::all(),
+        PolicyAction::Allow,
+    ));
     
     policy
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_conflict_resolution() {
+        let mut policy = SecurityPolicy::new("test", "1.0");
+        policy.add_rule(PolicyRule::new(Subject::Any, Object::Any, Permissions::all(), PolicyAction::Allow).with_priority(10));
+        policy.add_rule(PolicyRule::new(Subject::Any, Object::File(String::from("/etc")), Permissions::read_only(), PolicyAction::Deny));
+        let subject = Subject::User(1000);
+        let object = Object::File(String::from("/etc/passwd"));
+
+        assert_eq!(policy.check(&subject, &object, "read"), PolicyAction::Allow);
+        policy.rules[1].object = Object::File(String::from("/etc/"));
+        assert_eq!(policy.check(&subject, &object, "read"), PolicyAction::Deny);
+        policy.set_resolution(ConflictResolution::FirstMatch);
+        assert_eq!(policy.evaluate(&subject, &object, "read").rule, Some(0));
+        policy.set_resolution(ConflictResolution::AllowOverrides);
+        assert_eq!(policy.check(&subject, &object, "read"), PolicyAction::Allow);
+        assert_eq!(policy.check(&subject, &Object::Process(1), "bogus"), PolicyAction::Deny);
+    }
+
+    #[test_case]
+    fn test_network_rules() {
+        let policy = parse_policy(
+            "policy net 1.0 first-match\n\
+             rule allow any net:10.0.2.2:53 rw 10\n\
+             rule deny any net:10.0.2.0/24:0 w\n\
+             rule allow any any rwx\n",
+        ).unwrap();
+        let net = |addr: &str, port| Object::Network(String::from(addr), port);
+        assert!(policy.has_network_rules());
+        assert_eq!(policy.evaluate_network(&Subject::Any, &net("10.0.2.2", 53), "write").map(|d| d.rule), Some(Some(0)));
+        assert_eq!(policy.evaluate_network(&Subject::Any, &net("10.0.2.2", 80), "write").map(|d| d.action), Some(PolicyAction::Deny));
+        assert!(policy.evaluate_network(&Subject::Any, &net("10.0.2.2", 80), "read").is_none());
+        assert!(policy.evaluate_network(&Subject::Any, &net("10.0.3.1", 80), "write").is_none());
+        assert_eq!(policy.check(&Subject::Any, &net("10.0.3.1", 80), "write"), PolicyAction::Allow);
+        assert!(matches_address("0.0.0.0/0", "192.168.1.1"));
+        assert!(!matches_address("10.0.2.0/33", "10.0.2.1"));
+        assert!(!matches_address("10.0.2.1", "10.0.2.10"));
+    }
+
+    #[test_case]
+    fn test_file_rules() {
+        let policy = default_policy();
+        let shadow = Object::File(String::from("/etc/shadow"));
+        let decide = |uid, object: &Object| policy.evaluate_file(&Subject::User(uid), object, "read").map(|d| d.action);
+        assert_eq!(decide(1000, &shadow), Some(PolicyAction::Deny));
+        assert_eq!(decide(0, &shadow), Some(PolicyAction::Allow));
+        assert_eq!(decide(1000, &Object::File(String::from("/tmp/x"))), Some(PolicyAction::Allow));
+        // What no rule is about is left undecided, not denied
+        assert_eq!(decide(1000, &Object::File(String::from("/etc/passwd"))), None);
+    }
+}
@@ -0,0 +1,92 @@
+// Path patterns for QSF objects.
+//
+// Patterns are matched component by component against canonical absolute
+// paths, so `/etc` never matches `/etcetera`:
+//
+//   /etc/passwd   exactly that path
+//   /etc/         /etc itself and everything beneath it
+//   /home/*/.ssh  `*` and `?` match within a single component
+//   /var/**/log   `**` matches zero or more whole components
+//   *             any path (kept for existing rules)
+
+use alloc::vec::Vec;
+
+pub fn matches(pattern: &str, path: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+
+    let mut pat: Vec<&str> = components(pattern);
+    if pattern.len() > 1 && pattern.ends_with('/') {
+        pat.push("**");
+    }
+    match_components(&pat, &components(path))
+}
+
+/// Like `matches`, but a pattern naming a directory also covers everything
+/// beneath it, as if it were written with a trailing slash.
+pub fn matches_tree(pattern: &str, path: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+
+    let mut pat: Vec<&str> = components(pattern);
+    if pat.last() != Some(&"**") {
+        pat.push("**");
+    }
+    match_components(&pat, &components(path))
+}
+
+fn components(path: &str) -> Vec<&str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".").collect()
+}
+
+fn match_components(pat: &[&str], path: &[&str]) -> bool {
+    match pat.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => {
+            (0..=path.len()).any(|skip| match_components(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((component, path_rest)) => {
+                match_component(first.as_bytes(), component.as_bytes()) && match_components(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_component(pat: &[u8], name: &[u8]) -> bool {
+    match pat.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| match_component(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && match_component(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && match_component(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_component_boundaries() {
+        assert!(matches("/etc", "/etc"));
+        assert!(!matches("/etc", "/etcetera"));
+        assert!(!matches("/etc", "/etc/passwd"));
+        assert!(matches("/etc/", "/etc/passwd"));
+        assert!(matches_tree("/etc", "/etc/ssh/sshd_config"));
+        assert!(!matches_tree("/etc", "/etcetera/x"));
+    }
+
+    #[test_case]
+    fn test_globs() {
+        assert!(matches("/home/*/.ssh", "/home/alice/.ssh"));
+        assert!(!matches("/home/*/.ssh", "/home/alice/x/.ssh"));
+        assert!(matches("/var/**/log", "/var/log"));
+        assert!(matches("/var/**/log", "/var/a/b/log"));
+        assert!(matches("/tmp/*.tmp", "/tmp/x.tmp"));
+        assert!(matches("/dev/tty?", "/dev/tty1"));
+        assert!(matches("*", "/anything"));
+    }
+}
@@ -1,9 +1,12 @@
-use spin::Mutex;
+use crate::hal::lockdep::{Class, Mutex};
 use lazy_static::lazy_static;
 use alloc::vec::Vec;
 use alloc::string::String;
-use super::modules::{IntegrityModule, CapabilityModule, ConfinementModule};
-use super::policies::SecurityPolicy;
+use super::modules::{IntegrityModule, CapabilityModule, ConfinementModule, SyscallTraceModule};
+use super::modules::trace::{TraceState, TraceVerdict};
+use super::modules::confinement::{ConfinementProfile, ExecTransition};
+use super::policies::{SecurityPolicy, Subject, Object, PolicyDecision};
+use super::lockdown::{self, LockdownFeature, MeasurementLog, QsfError, QsfResult};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SecurityLevel {
@@ -19,8 +22,28 @@ pub enum AccessDecision {
     Audit,
 }
 
+/// Which way network traffic goes. A `net:` rule's read permission covers
+/// what comes in, write what goes out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetDirection {
+    Inbound,
+    Outbound,
+}
+
+impl NetDirection {
+    pub fn permission(self) -> &'static str {
+        match self {
+            NetDirection::Inbound => "read",
+            NetDirection::Outbound => "write",
+        }
+    }
+}
+
+/// Ports below this are bound only with CAP_NET_BIND_SERVICE
+pub const PRIVILEGED_PORTS: u16 = 1024;
+
 lazy_static! {
-    pub static ref QSF: Mutex<QunixSecurityFramework> = Mutex::new(QunixSecurityFramework::new());
+    pub static ref QSF: Mutex<QunixSecurityFramework> = Mutex::new(Class::Qsf, QunixSecurityFramework::new());
 }
 
 pub struct QunixSecurityFramework {
@@ -28,8 +51,11 @@ pub struct QunixSecurityFramework {
     integrity: IntegrityModule,
     capability: CapabilityModule,
     confinement: ConfinementModule,
+    trace: SyscallTraceModule,
     policies: Vec<SecurityPolicy>,
     audit_log: Vec<AuditEntry>,
+    locked: bool,
+    measurements: MeasurementLog,
 }
 
 #[derive(Debug, Clone)]
@@ -50,13 +76,92 @@ impl QunixSecurityFramework {
             integrity: IntegrityModule::new(),
             capability: CapabilityModule::new(),
             confinement: ConfinementModule::new(),
+            trace: SyscallTraceModule::new(),
             policies: Vec::new(),
             audit_log: Vec::new(),
+            locked: false,
+            measurements: MeasurementLog::new(),
         }
     }
     
-    pub fn set_level(&mut self, level: SecurityLevel) {
+    pub fn set_level(&mut self, level: SecurityLevel) -> QsfResult<()> {
+        self.check_lockdown(LockdownFeature::SecurityLevel)?;
         self.level = level;
+        Ok(())
+    }
+    
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+    
+    /// Refuses `feature` if the kernel is locked down, auditing the attempt.
+    pub fn check_lockdown(&mut self, feature: LockdownFeature) -> QsfResult<()> {
+        if !self.locked {
+            return Ok(());
+        }
+        self.audit(0, 0, "lockdown", feature.name(), AccessDecision::Deny, "kernel is locked down");
+        Err(QsfError::Locked(feature))
+    }
+    
+    /// Switches to Enforcing and freezes the security configuration until
+    /// reboot. The configuration being locked in is measured first.
+    pub fn lock_down(&mut self) {
+        if self.locked {
+            return;
+        }
+        
+        self.measurements.extend("kernel", crate::kernel::version_string().as_bytes());
+        self.measurements.extend("cmdline", crate::kernel::config::BUILTIN_CMDLINE.as_bytes());
+        for policy in &self.policies {
+            let what = alloc::format!("policy {}", policy.name);
+            self.measurements.extend(&what, alloc::format!("{:?}", policy).as_bytes());
+        }
+        for profile in self.confinement.profiles() {
+            let what = alloc::format!("profile {}", profile.name);
+            self.measurements.extend(&what, alloc::format!("{:?}", profile).as_bytes());
+        }
+        
+        self.level = SecurityLevel::Enforcing;
+        self.locked = true;
+        self.audit(0, 0, "lockdown", "qsf", AccessDecision::Audit, "lockdown engaged");
+    }
+    
+    pub fn measurements(&self) -> &MeasurementLog {
+        &self.measurements
+    }
+    
+    /// Raw block and memory devices are off limits once locked down.
+    pub fn check_raw_device(&mut self, pid: u32, uid: u32, path: &str) -> QsfResult<()> {
+        if !self.locked {
+            return Ok(());
+        }
+        let path = canonical_path(path).unwrap_or_else(|| String::from(path));
+        if lockdown::is_raw_device(&path) {
+            self.audit(pid, uid, "open", &path, AccessDecision::Deny, "raw device access under lockdown");
+            return Err(QsfError::Locked(LockdownFeature::RawDeviceAccess));
+        }
+        Ok(())
+    }
+    
+    /// The one policy change path that stays open under lockdown: a policy
+    /// text authenticated with the build-time update key. Replaces the
+    /// loaded policy of the same name and returns that name.
+    pub fn apply_policy_update(&mut self, text: &[u8], tag: &[u8]) -> QsfResult<String> {
+        let policy = match lockdown::verify_policy_update(text, tag) {
+            Ok(policy) => policy,
+            Err(e) => {
+                self.audit(0, 0, "policy_update", "qsf", AccessDecision::Deny, "update rejected");
+                return Err(e);
+            }
+        };
+        
+        let name = policy.name.clone();
+        let what = alloc::format!("policy update {}", name);
+        self.measurements.extend(&what, text);
+        self.policies.retain(|p| p.name != name);
+        self.policies.push(policy);
+        self.audit(0, 0, "policy_update", &name, AccessDecision::Audit, "signed update applied");
+        Ok(name)
     }
     
     pub fn get_level(&self) -> SecurityLevel {
@@ -72,6 +177,17 @@ impl QunixSecurityFramework {
             return AccessDecision::Allow;
         }
         
+        // Rules are written against canonical paths; check what the path
+        // really names so symlinks and `..` cannot sidestep them.
+        let path = match canonical_path(path) {
+            Some(p) => p,
+            None => {
+                self.audit(pid, uid, "file_access", path, AccessDecision::Deny, "path resolution failed");
+                return if self.is_enforcing() { AccessDecision::Deny } else { AccessDecision::Audit };
+            }
+        };
+        let path = path.as_str();
+        
         if !self.integrity.verify_path(path) {
             self.audit(pid, uid, "file_access", path, AccessDecision::Deny, "integrity violation");
             return if self.is_enforcing() { AccessDecision::Deny } else { AccessDecision::Audit };
@@ -87,9 +203,50 @@ impl QunixSecurityFramework {
             return if self.is_enforcing() { AccessDecision::Deny } else { AccessDecision::Audit };
         }
         
+        if self.file_policy_denies(uid, path, mode) {
+            self.audit(pid, uid, "file_access", path, AccessDecision::Deny, "policy denied");
+            return if self.is_enforcing() { AccessDecision::Deny } else { AccessDecision::Audit };
+        }
+        
         AccessDecision::Allow
     }
     
+    /// Whether the file rules of a loaded policy deny the user any of the
+    /// access `mode` asks for; a deny in any policy wins, and what no rule
+    /// is about is not denied
+    fn file_policy_denies(&self, uid: u32, path: &str, mode: u32) -> bool {
+        let (subject, object) = (Subject::User(uid), Object::File(String::from(path)));
+        [(0o400, "read"), (0o200, "write"), (0o100, "execute")]
+            .into_iter()
+            .filter(|&(bit, _)| mode & bit != 0)
+            .any(|(_, permission)| {
+                self.policies.iter().any(|policy| {
+                    policy.evaluate_file(&subject, &object, permission).is_some_and(|d| d.action.is_deny())
+                })
+            })
+    }
+    
+    /// Whether a device node may be mapped with file access `mode`. Raw
+    /// memory is never mapped under lockdown, and otherwise only by root
+    /// holding CAP_SYS_RAWIO, permissive or not; then the file rules apply.
+    pub fn check_device_mmap(&mut self, pid: u32, uid: u32, path: &str, mode: u32) -> AccessDecision {
+        if self.level == SecurityLevel::Disabled {
+            return AccessDecision::Allow;
+        }
+        let canonical = canonical_path(path).unwrap_or_else(|| String::from(path));
+        if lockdown::is_raw_device(&canonical) {
+            if self.locked {
+                self.audit(pid, uid, "mmap", &canonical, AccessDecision::Deny, "raw device access under lockdown");
+                return AccessDecision::Deny;
+            }
+            if uid != 0 || !self.check_process_capability(pid, uid, Capability::CapSysRawio) {
+                self.audit(pid, uid, "mmap", &canonical, AccessDecision::Deny, "raw memory without CAP_SYS_RAWIO");
+                return AccessDecision::Deny;
+            }
+        }
+        self.check_file_access(pid, uid, path, mode)
+    }
+    
     pub fn check_process_exec(&mut self, pid: u32, uid: u32, path: &str) -> AccessDecision {
         if self.level == SecurityLevel::Disabled {
             return AccessDecision::Allow;
@@ -108,7 +265,9 @@ impl QunixSecurityFramework {
         AccessDecision::Allow
     }
     
-    pub fn check_network_access(&mut self, pid: u32, uid: u32, addr: &str, port: u16) -> AccessDecision {
+    /// Whether a socket may exchange traffic with `addr`:`port`: the
+    /// process's confinement, then the `net:` rules for its user.
+    pub fn check_network_access(&mut self, pid: u32, uid: u32, addr: &str, port: u16, direction: NetDirection) -> AccessDecision {
         if self.level == SecurityLevel::Disabled {
             return AccessDecision::Allow;
         }
@@ -120,9 +279,57 @@ impl QunixSecurityFramework {
             return if self.is_enforcing() { AccessDecision::Deny } else { AccessDecision::Audit };
         }
         
+        let object = Object::Network(String::from(addr), port);
+        if self.network_policy(&Subject::User(uid), &object, direction) == Some(false) {
+            self.audit(pid, uid, "network", &resource, AccessDecision::Deny, "network policy");
+            return if self.is_enforcing() { AccessDecision::Deny } else { AccessDecision::Audit };
+        }
+        
         AccessDecision::Allow
     }
     
+    /// Whether a socket may be bound to `port` to take traffic on it. A
+    /// port below PRIVILEGED_PORTS needs CAP_NET_BIND_SERVICE even when
+    /// permissive, as capabilities always do; then the port is checked as
+    /// inbound traffic from any address.
+    pub fn check_network_bind(&mut self, pid: u32, uid: u32, port: u16) -> AccessDecision {
+        if self.level == SecurityLevel::Disabled {
+            return AccessDecision::Allow;
+        }
+        if port < PRIVILEGED_PORTS && !self.check_process_capability(pid, uid, Capability::CapNetBindService) {
+            let resource = alloc::format!("port {}", port);
+            self.audit(pid, uid, "bind", &resource, AccessDecision::Deny, "privileged port without CAP_NET_BIND_SERVICE");
+            return AccessDecision::Deny;
+        }
+        self.check_network_access(pid, uid, "0.0.0.0", port, NetDirection::Inbound)
+    }
+    
+    /// What the `net:` rules of the loaded policies say about a packet to
+    /// or from `addr`:`port`, None if none of them matches. A packet has no
+    /// process behind it, so only rules for any subject apply, and they
+    /// only drop packets when enforcing: auditing every packet would bury
+    /// the log.
+    pub fn check_packet(&self, addr: core::net::Ipv4Addr, port: u16, direction: NetDirection) -> Option<bool> {
+        if !self.is_enforcing() || !self.policies.iter().any(|p| p.has_network_rules()) {
+            return None;
+        }
+        let object = Object::Network(alloc::format!("{}", addr), port);
+        self.network_policy(&Subject::Any, &object, direction)
+    }
+    
+    /// Whether the `net:` rules allow a request; a deny in any policy wins
+    fn network_policy(&self, subject: &Subject, object: &Object, direction: NetDirection) -> Option<bool> {
+        let mut verdict = None;
+        for policy in &self.policies {
+            match policy.evaluate_network(subject, object, direction.permission()) {
+                Some(decision) if decision.action.is_deny() => return Some(false),
+                Some(_) => verdict = Some(true),
+                None => {}
+            }
+        }
+        verdict
+    }
+    
     pub fn check_capability(&self, uid: u32, cap: Capability) -> bool {
         if self.level == SecurityLevel::Disabled {
             return true;
@@ -149,24 +356,87 @@ impl QunixSecurityFramework {
         }
     }
     
+    /// Starts recording every syscall made by `pid` into the audit log.
+    pub fn trace_process(&mut self, pid: u32) -> bool {
+        self.trace.enable(pid, crate::hal::drivers::pit::get_ticks())
+    }
+    
+    pub fn untrace_process(&mut self, pid: u32) -> bool {
+        self.trace.disable(pid)
+    }
+    
+    pub fn is_traced(&self, pid: u32) -> bool {
+        self.trace.is_traced(pid)
+    }
+    
+    pub fn traced_processes(&self) -> Vec<(u32, TraceState)> {
+        self.trace.traced()
+    }
+    
+    /// Logs one traced syscall. Tracing is an explicit per-process request,
+    /// so it records regardless of the current security level.
+    pub fn trace_syscall(&mut self, pid: u32, uid: u32, call: &str, result: &str) {
+        let now = crate::hal::drivers::pit::get_ticks();
+        match self.trace.admit(pid, now) {
+            TraceVerdict::NotTraced | TraceVerdict::Suppress => return,
+            TraceVerdict::RecordAfterSuppressed(dropped) => {
+                let reason = alloc::format!("{} records suppressed", dropped);
+                self.audit(pid, uid, "syscall", "(rate limited)", AccessDecision::Audit, &reason);
+            }
+            TraceVerdict::Record => {}
+        }
+        self.audit(pid, uid, "syscall", call, AccessDecision::Audit, result);
+    }
+    
     pub fn get_audit_log(&self) -> &[AuditEntry] {
         &self.audit_log
     }
     
-    pub fn clear_audit_log(&mut self) {
+    pub fn clear_audit_log(&mut self) -> QsfResult<()> {
+        self.check_lockdown(LockdownFeature::AuditLog)?;
         self.audit_log.clear();
+        Ok(())
     }
     
-    pub fn load_policy(&mut self, policy: SecurityPolicy) {
+    pub fn load_policy(&mut self, policy: SecurityPolicy) -> QsfResult<()> {
+        self.check_lockdown(LockdownFeature::PolicyChange)?;
         self.policies.push(policy);
+        Ok(())
+    }
+    
+    /// Evaluates a request against every loaded policy, canonicalizing
+    /// file and directory objects first.
+    pub fn evaluate_policies(&self, subject: &Subject, object: &Object, permission: &str) -> Vec<(String, PolicyDecision)> {
+        let object = match object {
+            Object::File(path) => Object::File(canonical_path(path).unwrap_or_else(|| path.clone())),
+            Object::Directory(path) => Object::Directory(canonical_path(path).unwrap_or_else(|| path.clone())),
+            other => other.clone(),
+        };
+        self.policies
+            .iter()
+            .map(|policy| (policy.name.clone(), policy.evaluate(subject, &object, permission)))
+            .collect()
+    }
+    
+    pub fn policies(&self) -> &[SecurityPolicy] {
+        &self.policies
     }
     
-    pub fn add_integrity_hash(&mut self, path: &str, hash: [u8; 32]) {
+    pub fn policy_mut(&mut self, name: &str) -> QsfResult<&mut SecurityPolicy> {
+        self.check_lockdown(LockdownFeature::PolicyChange)?;
+        self.policies.iter_mut().find(|p| p.name == name).ok_or(QsfError::NotFound)
+    }
+    
+    pub fn add_integrity_hash(&mut self, path: &str, hash: [u8; 32]) -> QsfResult<()> {
+        self.check_lockdown(LockdownFeature::IntegrityDatabase)?;
         self.integrity.add_hash(path, hash);
+        Ok(())
     }
     
-    pub fn grant_capability(&mut self, uid: u32, cap: Capability) {
+    pub fn grant_capability(&mut self, uid: u32, cap: Capability) -> QsfResult<()> {
+        self.check_lockdown(LockdownFeature::CapabilityGrant)?;
         self.capability.grant(uid, cap);
+        Ok(())
     }
     
     pub fn revoke_capability(&mut self, uid: u32, cap: Capability) {
@@ -177,8 +447,94 @@ impl QunixSecurityFramework {
         self.confinement.confine(pid, paths, network);
     }
     
-    pub fn unconfine_process(&mut self, pid: u32) {
+    pub fn unconfine_process(&mut self, pid: u32) -> QsfResult<()> {
+        self.check_lockdown(LockdownFeature::Confinement)?;
         self.confinement.unconfine(pid);
+        Ok(())
+    }
+    
+    pub fn confinement(&self) -> &ConfinementModule {
+        &self.confinement
+    }
+    
+    pub fn add_profile(&mut self, profile: ConfinementProfile) -> QsfResult<()> {
+        self.check_lockdown(LockdownFeature::Confinement)?;
+        self.confinement.add_profile(profile);
+        Ok(())
+    }
+    
+    pub fn bind_profile(&mut self, exec_pattern: &str, profile: &str) -> QsfResult<()> {
+        self.check_lockdown(LockdownFeature::Confinement)?;
+        if self.confinement.bind_profile(exec_pattern, profile) {
+            Ok(())
+        } else {
+            Err(QsfError::NotFound)
+        }
+    }
+    
+    pub fn unbind_profile(&mut self, exec_pattern: &str) -> QsfResult<()> {
+        self.check_lockdown(LockdownFeature::Confinement)?;
+        if self.confinement.unbind_profile(exec_pattern) {
+            Ok(())
+        } else {
+            Err(QsfError::NotFound)
+        }
+    }
+    
+    /// Called on execve: moves the process into the profile bound to the
+    /// new executable, or applies its current profile's exec transition.
+    pub fn on_exec(&mut self, pid: u32, uid: u32, path: &str) {
+        let path = canonical_path(path).unwrap_or_else(|| String::from(path));
+        let before = self.confinement.profile_of(pid).map(String::from);
+        let after = self.confinement.apply_exec(pid, &path);
+        if before != after {
+            let reason = alloc::format!("profile {} -> {}",
+                before.as_deref().unwrap_or("unconfined"), after.as_deref().unwrap_or("unconfined"));
+            self.audit(pid, uid, "exec", &path, AccessDecision::Audit, &reason);
+        }
+    }
+    
+    /// Called when qpkg installs or removes a package, or refuses a
+    /// bundle, so what was put on the system and by whom is on record.
+    pub fn on_package(&mut self, pid: u32, uid: u32, action: &str, package: &str, decision: AccessDecision, reason: &str) {
+        self.audit(pid, uid, action, package, decision, reason);
+    }
+    
+    /// Called when someone logs in on a terminal, is refused or is logged
+    /// out, so who used the system and when is on record.
+    pub fn on_login(&mut self, pid: u32, uid: u32, action: &str, tty: &str, decision: AccessDecision, reason: &str) {
+        self.audit(pid, uid, action, tty, decision, reason);
+    }
+    
+    pub fn on_fork(&mut self, parent: u32, child: u32) {
+        self.confinement.inherit(parent, child);
+    }
+    
+    /// Confines `pid` by a named profile. Tightening a process is allowed
+    /// even when locked down.
+    pub fn confine_to_profile(&mut self, pid: u32, profile: &str) -> QsfResult<()> {
+        if self.confinement.apply_profile(pid, profile) {
+            Ok(())
+        } else {
+            Err(QsfError::NotFound)
+        }
+    }
+    
+    /// Called when a task is reaped, so its pid starts out unconfined and
+    /// untraced if it is ever reused.
+    pub fn on_exit(&mut self, pid: u32) {
+        self.confinement.unconfine(pid);
+        self.trace.disable(pid);
+    }
+    
+    /// Capability check for a process: the user must hold the capability
+    /// and the process's confinement bounding set must include it.
+    pub fn check_process_capability(&self, pid: u32, uid: u32, cap: Capability) -> bool {
+        if self.level == SecurityLevel::Disabled {
+            return true;
+        }
+        
+        self.capability.has_capability(uid, cap) && self.confinement.capability_allowed(pid, cap)
     }
 }
 
@@ -227,22 +583,82 @@ pub enum Capability {
 pub fn init_qsf() {
     let mut qsf = QSF.lock();
     
-    qsf.set_level(SecurityLevel::Permissive);
+    // Boot-time configuration goes straight to the modules: nothing can be
+    // locked down yet.
+    qsf.level = crate::kernel::config::DEFAULT_SECURITY_LEVEL;
+    
+    qsf.capability.grant(0, Capability::CapSysAdmin);
+    qsf.capability.grant(0, Capability::CapDacOverride);
+    qsf.capability.grant(0, Capability::CapKill);
+    qsf.capability.grant(0, Capability::CapSetuid);
+    qsf.capability.grant(0, Capability::CapSetgid);
+    qsf.capability.grant(0, Capability::CapSysBoot);
+    qsf.capability.grant(0, Capability::CapNetAdmin);
+    qsf.capability.grant(0, Capability::CapNetBindService);
+    
+    qsf.policies.push(super::policies::default_policy());
+    
+    let mut sandbox = ConfinementProfile::new("sandbox");
+    sandbox.allowed_paths = alloc::vec![
+        String::from("/tmp/"),
+        String::from("/dev/null"),
+        String::from("/dev/zero"),
+    ];
+    sandbox.denied_paths = alloc::vec![String::from("/etc/shadow"), String::from("/root/")];
+    sandbox.network_allowed = false;
+    sandbox.capabilities = Some(alloc::collections::BTreeSet::new());
+    sandbox.on_exec = ExecTransition::Inherit;
+    qsf.confinement.add_profile(sandbox);
+    
+    // Default for `qcontainer run`: the chroot does the path confinement,
+    // and without CAP_SYS_ADMIN or CAP_SYS_CHROOT nothing inside can
+    // unshare or chroot its way back out.
+    let mut container = ConfinementProfile::new("container");
+    container.network_allowed = false;
+    container.capabilities = Some([
+        Capability::CapChown,
+        Capability::CapDacOverride,
+        Capability::CapFowner,
+        Capability::CapKill,
+        Capability::CapSetuid,
+        Capability::CapSetgid,
+        Capability::CapNetBindService,
+    ].into_iter().collect());
+    container.on_exec = ExecTransition::Inherit;
+    qsf.confinement.add_profile(container);
+    
+    // Policies shipped as boot modules go in before lockdown, so they are
+    // measured with the built-in ones
+    for (name, text) in crate::kernel::modules::policies() {
+        match super::policies::parse_policy(&text) {
+            Ok(policy) => {
+                crate::println!("  [QSF] Loaded policy {} from module {}", policy.name, name);
+                qsf.policies.retain(|p| p.name != policy.name);
+                qsf.policies.push(policy);
+            }
+            Err(why) => crate::println!("  [QSF] Module {}: invalid policy: {}", name, why),
+        }
+    }
     
-    qsf.grant_capability(0, Capability::CapSysAdmin);
-    qsf.grant_capability(0, Capability::CapDacOverride);
-    qsf.grant_capability(0, Capability::CapKill);
-    qsf.grant_capability(0, Capability::CapSetuid);
-    qsf.grant_capability(0, Capability::CapSetgid);
-    qsf.grant_capability(0, Capability::CapSysBoot);
-    qsf.grant_capability(0, Capability::CapNetAdmin);
-    qsf.grant_capability(0, Capability::CapNetBindService);
+    if crate::kernel::has_param(lockdown::LOCKDOWN_PARAM) {
+        qsf.lock_down();
+    }
+}
+
+/// Canonical form of `path` for policy evaluation, or `None` if it cannot
+/// be resolved (e.g. a symlink loop).
+pub fn canonical_path(path: &str) -> Option<String> {
+    crate::fs::vfs::vfs::VFS.lock().canonicalize(path).ok()
 }
 
 pub fn check_access(pid: u32, uid: u32, path: &str, mode: u32) -> AccessDecision {
     QSF.lock().check_file_access(pid, uid, path, mode)
 }
 
+pub fn check_mmap(pid: u32, uid: u32, path: &str, mode: u32) -> AccessDecision {
+    QSF.lock().check_device_mmap(pid, uid, path, mode)
+}
+
 pub fn check_exec(pid: u32, uid: u32, path: &str) -> AccessDecision {
     QSF.lock().check_process_exec(pid, uid, path)
 }
@@ -250,3 +666,32 @@ pub fn check_exec(pid: u32, uid: u32, path: &str) -> AccessDecision {
 pub fn has_capability(uid: u32, cap: Capability) -> bool {
     QSF.lock().check_capability(uid, cap)
 }
+
+pub fn check_network(pid: u32, uid: u32, addr: core::net::Ipv4Addr, port: u16, direction: NetDirection) -> AccessDecision {
+    QSF.lock().check_network_access(pid, uid, &alloc::format!("{}", addr), port, direction)
+}
+
+pub fn check_bind(pid: u32, uid: u32, port: u16) -> AccessDecision {
+    QSF.lock().check_network_bind(pid, uid, port)
+}
+
+pub fn check_packet(addr: core::net::Ipv4Addr, port: u16, direction: NetDirection) -> Option<bool> {
+    QSF.lock().check_packet(addr, port, direction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_privileged_port_bind() {
+        let mut qsf = QunixSecurityFramework::new();
+        qsf.grant_capability(0, Capability::CapNetBindService).unwrap();
+
+        // Denied even when permissive, as capabilities always are
+        assert_eq!(qsf.check_network_bind(7, 1000, 80), AccessDecision::Deny);
+        assert_eq!(qsf.check_network_bind(7, 0, 80), AccessDecision::Allow);
+        assert_eq!(qsf.check_network_bind(7, 1000, PRIVILEGED_PORTS), AccessDecision::Allow);
+        assert_eq!(qsf.check_network_bind(7, 1000, 8080), AccessDecision::Allow);
+    }
+}
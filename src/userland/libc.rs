@@ -4,6 +4,7 @@
 
 use core::ffi::c_char;
 use core::ptr;
+use core::sync::atomic::{AtomicI32, Ordering};
 
 // ============== Syscall numbers (x86_64) ==============
 pub const SYS_READ: u64 = 0;
@@ -13,9 +14,11 @@ pub const SYS_CLOSE: u64 = 3;
 pub const SYS_STAT: u64 = 4;
 pub const SYS_FSTAT: u64 = 5;
 pub const SYS_LSEEK: u64 = 8;
+pub const SYS_IOCTL: u64 = 16;
 pub const SYS_PIPE: u64 = 22;
 pub const SYS_GETPID: u64 = 39;
 pub const SYS_FORK: u64 = 57;
+pub const SYS_VFORK: u64 = 58;
 pub const SYS_EXECVE: u64 = 59;
 pub const SYS_EXIT: u64 = 60;
 pub const SYS_WAIT4: u64 = 61;
@@ -26,7 +29,12 @@ pub const SYS_GETUID: u64 = 102;
 pub const SYS_GETGID: u64 = 104;
 pub const SYS_SETUID: u64 = 105;
 pub const SYS_SETGID: u64 = 106;
+pub const SYS_SETPGID: u64 = 109;
 pub const SYS_GETPPID: u64 = 110;
+pub const SYS_GETPGRP: u64 = 111;
+pub const SYS_SETSID: u64 = 112;
+pub const SYS_GETPGID: u64 = 121;
+pub const SYS_GETSID: u64 = 124;
 pub const SYS_DUP: u64 = 32;
 pub const SYS_DUP2: u64 = 33;
 pub const SYS_GETCWD: u64 = 79;
@@ -34,6 +42,22 @@ pub const SYS_CHDIR: u64 = 80;
 pub const SYS_MKDIR: u64 = 83;
 pub const SYS_RMDIR: u64 = 84;
 pub const SYS_UNLINK: u64 = 87;
+pub const SYS_READLINK: u64 = 89;
+pub const SYS_ACCESS: u64 = 21;
+pub const SYS_FACCESSAT: u64 = 269;
+pub use crate::kernel::sys::syscalls::{AT_EACCESS, AT_FDCWD};
+pub use crate::kernel::sys::syscalls::{SpawnAction, SPAWN_CLOSE, SPAWN_DUP2, SPAWN_OPEN, SYS_POSIX_SPAWN};
+pub use crate::kernel::sys::syscalls::{TIOCGPGRP, TIOCSPGRP};
+pub use crate::kernel::sys::syscalls::{SerialConfig, TIOCGSERCONF, TIOCSSERCONF};
+pub use crate::kernel::sys::syscalls::{KeyEvent, KeyboardState, KBDGSTATE, KEY_PRESSED, KEY_RELEASED, KEY_REPEATED};
+pub use crate::kernel::sys::syscalls::{
+    KEYMOD_ALT, KEYMOD_CAPSLOCK, KEYMOD_CTRL, KEYMOD_META, KEYMOD_NUMLOCK, KEYMOD_SCROLLLOCK, KEYMOD_SHIFT,
+};
+pub use crate::kernel::sys::syscalls::{PR_GET_CHILD_SUBREAPER, PR_SET_CHILD_SUBREAPER, SYS_PRCTL};
+pub use crate::kernel::sys::syscalls::{PR_GET_WX_ALLOWED, PR_SET_WX_ALLOWED};
+pub use crate::kernel::sys::syscalls::{Rlimit, RLIMIT_NOFILE, RLIM_INFINITY, SYS_GETRLIMIT, SYS_SETRLIMIT};
+pub use crate::kernel::sys::syscalls::SYS_QUNIX_FEATURES;
+pub use crate::kernel::sys::abi::QunixFeatures;
 
 // File descriptor constants
 pub const STDIN_FILENO: i32 = 0;
@@ -49,25 +73,21 @@ pub const O_EXCL: i32 = 0o200;
 pub const O_TRUNC: i32 = 0o1000;
 pub const O_APPEND: i32 = 0o2000;
 
-// Error constants (POSIX errno values)
-pub const EPERM: i32 = 1;
-pub const ENOENT: i32 = 2;
-pub const ESRCH: i32 = 3;
-pub const EINTR: i32 = 4;
-pub const EIO: i32 = 5;
-pub const EBADF: i32 = 9;
-pub const EAGAIN: i32 = 11;
-pub const ENOMEM: i32 = 12;
-pub const EACCES: i32 = 13;
-pub const EFAULT: i32 = 14;
-pub const EBUSY: i32 = 16;
-pub const EEXIST: i32 = 17;
-pub const EINVAL: i32 = 22;
-pub const ENFILE: i32 = 23;
-pub const EMFILE: i32 = 24;
-pub const ENOSPC: i32 = 28;
-pub const ENOSYS: i32 = 38;
-pub const ECHILD: i32 = 10;
+// access() modes
+pub const F_OK: i32 = 0;
+pub const X_OK: i32 = 1;
+pub const W_OK: i32 = 2;
+pub const R_OK: i32 = 4;
+
+// Error constants (POSIX errno values), shared with the kernel so both sides
+// of the syscall ABI agree on every number.
+pub use crate::kernel::sys::errno::{
+    Errno, EPERM, ENOENT, ESRCH, EINTR, EIO, EBADF, ECHILD, EAGAIN, ENOMEM, EACCES,
+    EFAULT, EBUSY, EEXIST, ENOTDIR, EISDIR, EINVAL, ENFILE, EMFILE, ENOSPC, ERANGE,
+    ENAMETOOLONG, ENOSYS, ENOTEMPTY,
+};
+
+static ERRNO: AtomicI32 = AtomicI32::new(0);
 
 // Exit codes
 pub const EXIT_SUCCESS: i32 = 0;
@@ -158,30 +178,86 @@ pub unsafe fn syscall4(num: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64) ->
     ret
 }
 
+// ============== errno handling ==============
+
+/// Last error reported by a libc wrapper.
+pub fn errno() -> i32 {
+    ERRNO.load(Ordering::Relaxed)
+}
+
+pub fn set_errno(value: i32) {
+    ERRNO.store(value, Ordering::Relaxed);
+}
+
+pub fn strerror(errnum: i32) -> &'static str {
+    Errno::from_raw(errnum).map_or("Unknown error", Errno::description)
+}
+
+/// Decodes a raw syscall return: on error stores errno and returns -1.
+fn check(ret: i64) -> i64 {
+    match Errno::from_syscall_ret(ret) {
+        Ok(_) => ret,
+        Err(e) => {
+            set_errno(e.as_i32());
+            -1
+        }
+    }
+}
+
 // ============== POSIX syscall wrappers ==============
 
 pub fn read(fd: i32, buf: *mut u8, count: usize) -> i64 {
-    unsafe { syscall3(SYS_READ, fd as u64, buf as u64, count as u64) }
+    check(unsafe { syscall3(SYS_READ, fd as u64, buf as u64, count as u64) })
 }
 
 pub fn write(fd: i32, buf: *const u8, count: usize) -> i64 {
-    unsafe { syscall3(SYS_WRITE, fd as u64, buf as u64, count as u64) }
+    check(unsafe { syscall3(SYS_WRITE, fd as u64, buf as u64, count as u64) })
 }
 
 pub fn open(pathname: *const c_char, flags: i32, mode: u32) -> i32 {
-    unsafe { syscall3(SYS_OPEN, pathname as u64, flags as u64, mode as u64) as i32 }
+    check(unsafe { syscall3(SYS_OPEN, pathname as u64, flags as u64, mode as u64) }) as i32
 }
 
 pub fn close(fd: i32) -> i32 {
-    unsafe { syscall1(SYS_CLOSE, fd as u64) as i32 }
+    check(unsafe { syscall1(SYS_CLOSE, fd as u64) }) as i32
 }
 
 pub fn fork() -> i32 {
-    unsafe { syscall0(SYS_FORK) as i32 }
+    check(unsafe { syscall0(SYS_FORK) }) as i32
+}
+
+pub fn vfork() -> i32 {
+    check(unsafe { syscall0(SYS_VFORK) }) as i32
+}
+
+/// Start `path` in a new child after applying `actions` to the
+/// descriptors it inherits, storing its pid in `pid`. Returns 0 or, as
+/// POSIX has it, the error number rather than setting errno.
+pub fn posix_spawn(pid: *mut i32, path: *const c_char, actions: &[SpawnAction]) -> i32 {
+    let ret = unsafe { syscall4(SYS_POSIX_SPAWN, pid as u64, path as u64, actions.as_ptr() as u64, actions.len() as u64) };
+    match Errno::from_syscall_ret(ret) {
+        Ok(_) => 0,
+        Err(e) => e.as_i32(),
+    }
+}
+
+pub fn getrlimit(resource: u32, rlim: &mut Rlimit) -> i32 {
+    check(unsafe { syscall2(SYS_GETRLIMIT, resource as u64, rlim as *mut _ as u64) }) as i32
+}
+
+pub fn setrlimit(resource: u32, rlim: &Rlimit) -> i32 {
+    check(unsafe { syscall2(SYS_SETRLIMIT, resource as u64, rlim as *const _ as u64) }) as i32
+}
+
+/// Fill in `features` with what the kernel has. Returns its ABI version,
+/// or -1 with errno set on a kernel too old to say.
+pub fn qunix_features(features: &mut QunixFeatures) -> i32 {
+    let size = core::mem::size_of::<QunixFeatures>();
+    check(unsafe { syscall2(SYS_QUNIX_FEATURES, features as *mut _ as u64, size as u64) }) as i32
 }
 
 pub fn execve(filename: *const c_char, argv: *const *const c_char, envp: *const *const c_char) -> i32 {
-    unsafe { syscall3(SYS_EXECVE, filename as u64, argv as u64, envp as u64) as i32 }
+    check(unsafe { syscall3(SYS_EXECVE, filename as u64, argv as u64, envp as u64) }) as i32
 }
 
 pub fn exit(status: i32) -> ! {
@@ -192,27 +268,66 @@ pub fn exit(status: i32) -> ! {
 }
 
 pub fn waitpid(pid: i32, status: *mut i32, options: i32) -> i32 {
-    unsafe { syscall4(SYS_WAIT4, pid as u64, status as u64, options as u64, 0) as i32 }
+    check(unsafe { syscall4(SYS_WAIT4, pid as u64, status as u64, options as u64, 0) }) as i32
 }
 
 pub fn getpid() -> i32 {
-    unsafe { syscall0(SYS_GETPID) as i32 }
+    check(unsafe { syscall0(SYS_GETPID) }) as i32
 }
 
 pub fn getppid() -> i32 {
-    unsafe { syscall0(SYS_GETPPID) as i32 }
+    check(unsafe { syscall0(SYS_GETPPID) }) as i32
+}
+
+pub fn setpgid(pid: i32, pgid: i32) -> i32 {
+    check(unsafe { syscall2(SYS_SETPGID, pid as u64, pgid as u64) }) as i32
+}
+
+pub fn getpgid(pid: i32) -> i32 {
+    check(unsafe { syscall1(SYS_GETPGID, pid as u64) }) as i32
+}
+
+pub fn getpgrp() -> i32 {
+    check(unsafe { syscall0(SYS_GETPGRP) }) as i32
+}
+
+pub fn setsid() -> i32 {
+    check(unsafe { syscall0(SYS_SETSID) }) as i32
+}
+
+pub fn getsid(pid: i32) -> i32 {
+    check(unsafe { syscall1(SYS_GETSID, pid as u64) }) as i32
+}
+
+/// The foreground process group of the terminal `fd`
+pub fn tcgetpgrp(fd: i32) -> i32 {
+    let mut pgid: i32 = 0;
+    match check(unsafe { syscall3(SYS_IOCTL, fd as u64, TIOCGPGRP, &mut pgid as *mut i32 as u64) }) {
+        -1 => -1,
+        _ => pgid,
+    }
+}
+
+pub fn tcsetpgrp(fd: i32, pgid: i32) -> i32 {
+    check(unsafe { syscall3(SYS_IOCTL, fd as u64, TIOCSPGRP, &pgid as *const i32 as u64) }) as i32
+}
+
+/// prctl with one argument, as PR_SET_CHILD_SUBREAPER and
+/// PR_GET_CHILD_SUBREAPER take
+pub fn prctl(option: u64, arg: u64) -> i32 {
+    check(unsafe { syscall2(SYS_PRCTL, option, arg) }) as i32
 }
 
 pub fn getuid() -> u32 {
-    unsafe { syscall0(SYS_GETUID) as u32 }
+    check(unsafe { syscall0(SYS_GETUID) }) as u32
 }
 
 pub fn getgid() -> u32 {
-    unsafe { syscall0(SYS_GETGID) as u32 }
+    check(unsafe { syscall0(SYS_GETGID) }) as u32
 }
 
 pub fn getcwd(buf: *mut c_char, size: usize) -> *mut c_char {
-    let result = unsafe { syscall2(SYS_GETCWD, buf as u64, size as u64) };
+    let result = check(unsafe { syscall2(SYS_GETCWD, buf as u64, size as u64) });
     if result < 0 {
         ptr::null_mut()
     } else {
@@ -221,39 +336,57 @@ pub fn getcwd(buf: *mut c_char, size: usize) -> *mut c_char {
 }
 
 pub fn chdir(path: *const c_char) -> i32 {
-    unsafe { syscall1(SYS_CHDIR, path as u64) as i32 }
+    check(unsafe { syscall1(SYS_CHDIR, path as u64) }) as i32
 }
 
 pub fn mkdir(pathname: *const c_char, mode: u32) -> i32 {
-    unsafe { syscall2(SYS_MKDIR, pathname as u64, mode as u64) as i32 }
+    check(unsafe { syscall2(SYS_MKDIR, pathname as u64, mode as u64) }) as i32
 }
 
 pub fn unlink(pathname: *const c_char) -> i32 {
-    unsafe { syscall1(SYS_UNLINK, pathname as u64) as i32 }
+    check(unsafe { syscall1(SYS_UNLINK, pathname as u64) }) as i32
 }
 
 pub fn rmdir(pathname: *const c_char) -> i32 {
-    unsafe { syscall1(SYS_RMDIR, pathname as u64) as i32 }
+    check(unsafe { syscall1(SYS_RMDIR, pathname as u64) }) as i32
+}
+
+/// Whether the real user may access `pathname` as `mode` (R_OK, W_OK,
+/// X_OK or F_OK) says: 0 if so
+pub fn access(pathname: *const c_char, mode: i32) -> i32 {
+    check(unsafe { syscall2(SYS_ACCESS, pathname as u64, mode as u64) }) as i32
+}
+
+/// access() relative to the directory open as `dirfd` (or AT_FDCWD);
+/// AT_EACCESS in `flags` checks the effective ids instead
+pub fn faccessat(dirfd: i32, pathname: *const c_char, mode: i32, flags: i32) -> i32 {
+    check(unsafe { syscall4(SYS_FACCESSAT, dirfd as u64, pathname as u64, mode as u64, flags as u64) }) as i32
+}
+
+/// The target of the symlink `pathname`, cut to `bufsiz` bytes and not
+/// NUL-terminated; returns its length
+pub fn readlink(pathname: *const c_char, buf: *mut c_char, bufsiz: usize) -> i64 {
+    check(unsafe { syscall3(SYS_READLINK, pathname as u64, buf as u64, bufsiz as u64) })
 }
 
 pub fn kill(pid: i32, sig: i32) -> i32 {
-    unsafe { syscall2(SYS_KILL, pid as u64, sig as u64) as i32 }
+    check(unsafe { syscall2(SYS_KILL, pid as u64, sig as u64) }) as i32
 }
 
 pub fn chmod(path: *const c_char, mode: u32) -> i32 {
-    unsafe { syscall2(SYS_CHMOD, path as u64, mode as u64) as i32 }
+    check(unsafe { syscall2(SYS_CHMOD, path as u64, mode as u64) }) as i32
 }
 
 pub fn dup(oldfd: i32) -> i32 {
-    unsafe { syscall1(SYS_DUP, oldfd as u64) as i32 }
+    check(unsafe { syscall1(SYS_DUP, oldfd as u64) }) as i32
 }
 
 pub fn dup2(oldfd: i32, newfd: i32) -> i32 {
-    unsafe { syscall2(SYS_DUP2, oldfd as u64, newfd as u64) as i32 }
+    check(unsafe { syscall2(SYS_DUP2, oldfd as u64, newfd as u64) }) as i32
 }
 
 pub fn pipe(pipefd: *mut i32) -> i32 {
-    unsafe { syscall1(SYS_PIPE, pipefd as u64) as i32 }
+    check(unsafe { syscall1(SYS_PIPE, pipefd as u64) }) as i32
 }
 
 // ============== Standard string/memory functions ==============
@@ -314,3 +447,126 @@ pub fn printf(format: *const c_char) -> i32 {
     write(STDOUT_FILENO, format as *const u8, len) as i32
 }
 
+
+// ============== Name resolution ==============
+
+pub const AF_INET: i32 = 2;
+
+// h_errno values
+pub const HOST_NOT_FOUND: i32 = 1;
+pub const TRY_AGAIN: i32 = 2;
+pub const NO_RECOVERY: i32 = 3;
+pub const NO_DATA: i32 = 4;
+
+/// struct hostent
+#[repr(C)]
+pub struct Hostent {
+    pub h_name: *mut c_char,
+    pub h_aliases: *mut *mut c_char,
+    pub h_addrtype: i32,
+    pub h_length: i32,
+    /// Addresses in network byte order, null-terminated
+    pub h_addr_list: *mut *mut c_char,
+}
+
+const MAX_HOST_NAME: usize = 255;
+#[cfg(feature = "net")]
+const MAX_HOST_ADDRS: usize = 8;
+
+/// The static result gethostbyname() overwrites on every call
+#[cfg(feature = "net")]
+struct HostStorage {
+    entry: Hostent,
+    name: [u8; MAX_HOST_NAME + 1],
+    addrs: [[u8; 4]; MAX_HOST_ADDRS],
+    addr_list: [*mut c_char; MAX_HOST_ADDRS + 1],
+    aliases: [*mut c_char; 1],
+}
+
+#[cfg(feature = "net")]
+static mut HOST: HostStorage = HostStorage {
+    entry: Hostent {
+        h_name: ptr::null_mut(),
+        h_aliases: ptr::null_mut(),
+        h_addrtype: AF_INET,
+        h_length: 4,
+        h_addr_list: ptr::null_mut(),
+    },
+    name: [0; MAX_HOST_NAME + 1],
+    addrs: [[0; 4]; MAX_HOST_ADDRS],
+    addr_list: [ptr::null_mut(); MAX_HOST_ADDRS + 1],
+    aliases: [ptr::null_mut(); 1],
+};
+
+static H_ERRNO: AtomicI32 = AtomicI32::new(0);
+
+/// Error of the last failed gethostbyname()
+pub fn h_errno() -> i32 {
+    H_ERRNO.load(Ordering::Relaxed)
+}
+
+pub fn hstrerror(err: i32) -> &'static str {
+    match err {
+        HOST_NOT_FOUND => "Unknown host",
+        TRY_AGAIN => "Host name lookup failure",
+        NO_RECOVERY => "Unknown server error",
+        NO_DATA => "No address associated with name",
+        _ => "Unknown resolver error",
+    }
+}
+
+/// Look up the IPv4 addresses of `name` with the kernel's resolver
+/// (/etc/hosts, then DNS). The result is static and overwritten by the
+/// next call; on failure it is null and h_errno says why.
+pub fn gethostbyname(name: *const c_char) -> *mut Hostent {
+    if name.is_null() {
+        H_ERRNO.store(HOST_NOT_FOUND, Ordering::Relaxed);
+        return ptr::null_mut();
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(name as *const u8, strlen(name)) };
+    let host = match core::str::from_utf8(bytes) {
+        Ok(host) if host.len() <= MAX_HOST_NAME => host,
+        _ => {
+            H_ERRNO.store(HOST_NOT_FOUND, Ordering::Relaxed);
+            return ptr::null_mut();
+        }
+    };
+    #[cfg(feature = "net")]
+    {
+        use crate::net::{resolver, NetError};
+        let answer = match resolver::lookup(host) {
+            Ok(answer) => answer,
+            Err(e) => {
+                let err = match e {
+                    NetError::NameNotFound | NetError::InvalidArgument => HOST_NOT_FOUND,
+                    NetError::Timeout | NetError::NoDevice | NetError::HostUnreachable => TRY_AGAIN,
+                    _ => NO_RECOVERY,
+                };
+                H_ERRNO.store(err, Ordering::Relaxed);
+                return ptr::null_mut();
+            }
+        };
+        unsafe {
+            let host_storage = &mut *ptr::addr_of_mut!(HOST);
+            host_storage.name[..host.len()].copy_from_slice(host.as_bytes());
+            host_storage.name[host.len()] = 0;
+            let count = answer.addrs.len().min(MAX_HOST_ADDRS);
+            for (i, addr) in answer.addrs.iter().take(count).enumerate() {
+                host_storage.addrs[i] = addr.octets();
+                host_storage.addr_list[i] = host_storage.addrs[i].as_mut_ptr() as *mut c_char;
+            }
+            host_storage.addr_list[count] = ptr::null_mut();
+            host_storage.aliases[0] = ptr::null_mut();
+            host_storage.entry.h_name = host_storage.name.as_mut_ptr() as *mut c_char;
+            host_storage.entry.h_aliases = host_storage.aliases.as_mut_ptr();
+            host_storage.entry.h_addr_list = host_storage.addr_list.as_mut_ptr();
+            &mut host_storage.entry
+        }
+    }
+    #[cfg(not(feature = "net"))]
+    {
+        let _ = host;
+        H_ERRNO.store(NO_RECOVERY, Ordering::Relaxed);
+        ptr::null_mut()
+    }
+}
@@ -6,6 +6,7 @@ pub mod libc;
 pub mod shell;
 pub mod utils;
 pub mod qutils;
+pub mod users;
 
 pub use libc::*;
 pub use qutils::*;
@@ -0,0 +1,111 @@
+// getopt-style option parsing for shell commands
+//
+// `parse(args, "an:")` accepts the single-letter options in the spec; a
+// letter followed by ':' takes a value, given either attached (`-n5`) or
+// as the next argument (`-n 5`). Flags combine (`-la`), options may follow
+// operands, `--` ends option parsing and a lone `-` is an operand.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgError {
+    UnknownOption(char),
+    MissingValue(char),
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgError::UnknownOption(c) => write!(f, "invalid option -- '{}'", c),
+            ArgError::MissingValue(c) => write!(f, "option requires an argument -- '{}'", c),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Opts<'a> {
+    options: Vec<(char, Option<&'a str>)>,
+    pub operands: Vec<&'a str>,
+}
+
+impl<'a> Opts<'a> {
+    /// Whether option `c` was given
+    pub fn has(&self, c: char) -> bool {
+        self.options.iter().any(|&(o, _)| o == c)
+    }
+
+    /// Value of option `c`; the last one wins if it was repeated
+    pub fn value(&self, c: char) -> Option<&'a str> {
+        self.options.iter().rev().find(|&&(o, _)| o == c).and_then(|&(_, v)| v)
+    }
+}
+
+/// Takes a value if `c` is followed by ':' in `spec`; None if unknown
+fn lookup(spec: &str, c: char) -> Option<bool> {
+    if c == ':' {
+        return None;
+    }
+    let at = spec.find(c)?;
+    Some(spec[at + c.len_utf8()..].starts_with(':'))
+}
+
+pub fn parse<'a>(args: &[&'a str], spec: &str) -> Result<Opts<'a>, ArgError> {
+    let mut opts = Opts::default();
+    let mut iter = args.iter();
+
+    while let Some(&arg) = iter.next() {
+        if arg == "--" {
+            opts.operands.extend(iter.by_ref().copied());
+            break;
+        }
+        if arg.len() < 2 || !arg.starts_with('-') {
+            opts.operands.push(arg);
+            continue;
+        }
+
+        let flags = &arg[1..];
+        for (i, c) in flags.char_indices() {
+            match lookup(spec, c) {
+                None => return Err(ArgError::UnknownOption(c)),
+                Some(false) => opts.options.push((c, None)),
+                Some(true) => {
+                    let rest = &flags[i + c.len_utf8()..];
+                    let value = if !rest.is_empty() {
+                        rest
+                    } else {
+                        *iter.next().ok_or(ArgError::MissingValue(c))?
+                    };
+                    opts.options.push((c, Some(value)));
+                    break;
+                }
+            }
+        }
+    }
+    Ok(opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_flags_and_operands() {
+        let opts = parse(&["-la", "dir", "-n", "5", "file"], "aln:").unwrap();
+        assert!(opts.has('l') && opts.has('a'));
+        assert_eq!(opts.value('n'), Some("5"));
+        assert_eq!(opts.operands, ["dir", "file"]);
+
+        let opts = parse(&["-n10", "-", "--", "-a"], "an:").unwrap();
+        assert_eq!(opts.value('n'), Some("10"));
+        assert!(!opts.has('a'));
+        assert_eq!(opts.operands, ["-", "-a"]);
+    }
+
+    #[test_case]
+    fn test_errors() {
+        assert_eq!(parse(&["-x"], "a").err(), Some(ArgError::UnknownOption('x')));
+        assert_eq!(parse(&["-an"], "an:").err(), Some(ArgError::MissingValue('n')));
+        assert_eq!(parse(&["-:"], "n:").err(), Some(ArgError::UnknownOption(':')));
+    }
+}
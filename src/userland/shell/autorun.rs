@@ -0,0 +1,110 @@
+// Scripted boot for CI
+//
+// With `autorun=PATH` on the kernel command line init runs the script at
+// PATH instead of the interactive shell, and with a bare `autorun` the
+// built-in smoke test below. Each line is run as if typed at the prompt,
+// with its output captured, and reported on the serial port one record to
+// a line, numbered by script line:
+//
+//   AUTORUN BEGIN <script>
+//   AUTORUN CMD <n> <command line>
+//   AUTORUN OUT <n> <a line of its output>
+//   AUTORUN EXIT <n> <status> PASS|FAIL
+//   AUTORUN END <passed> <failed>
+//
+// with `AUTORUN ERROR <script>: <error>` before the end if the script
+// cannot be read.
+//
+// A line passes when it exits 0, or, if it starts with `!`, when it does
+// not. Blank lines and `#` comments are skipped. At the end QEMU is told
+// through isa-debug-exit whether everything passed, so it exits with 33
+// or 35 respectively.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::fs::vfs::api as vfs_api;
+use crate::{serial_println, QemuExitCode};
+use super::{apply_redirect, restore_fds, shell_pid, Redirect};
+
+/// Where a command's stdout and stderr go while it runs
+const OUTPUT: &str = "/tmp/.autorun.out";
+
+/// Run by a bare `autorun`: the shell, the VFS and the syscalls behind them
+const BUILT_IN: &str = "\
+uname
+id
+pwd
+echo autorun > /tmp/autorun.txt
+echo again >> /tmp/autorun.txt
+cat /tmp/autorun.txt
+cmp /tmp/autorun.txt /tmp/autorun.txt
+rm /tmp/autorun.txt
+! cat /tmp/autorun.txt
+ps
+";
+
+/// Run `line` with fds 1 and 2 on the capture file, returning its status
+/// and what it wrote
+fn capture(line: &str) -> (i32, String) {
+    let pid = shell_pid();
+    if vfs_api::write_file(OUTPUT, b"", 0o600).is_err() {
+        return (super::run_line(line), String::new());
+    }
+    let mut saved = Vec::new();
+    for fd in [1, 2] {
+        if let Ok(previous) = apply_redirect(pid, &Redirect { fd, path: String::from(OUTPUT), append: true }) {
+            saved.push((fd, previous));
+        }
+    }
+    let status = super::run_line(line);
+    restore_fds(pid, saved);
+    let output = vfs_api::read_file(OUTPUT).unwrap_or_default();
+    (status, String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Run the script at `path`, or the built-in one if `path` is empty, then
+/// power off
+pub fn run(path: &str) -> ! {
+    let script = match path {
+        "" => Ok(String::from(BUILT_IN)),
+        path => vfs_api::read_file(path).map(|bytes| String::from_utf8_lossy(&bytes).into_owned()),
+    };
+    serial_println!("AUTORUN BEGIN {}", if path.is_empty() { "(built-in)" } else { path });
+    let script = match script {
+        Ok(script) => script,
+        Err(e) => {
+            serial_println!("AUTORUN ERROR {}: {:?}", path, e);
+            serial_println!("AUTORUN END 0 1");
+            crate::exit_qemu(QemuExitCode::Failed);
+            crate::hlt_loop();
+        }
+    };
+
+    let (mut passed, mut failed) = (0, 0);
+    for (n, line) in script.lines().enumerate().map(|(i, line)| (i + 1, line.trim())) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (negated, command) = match line.strip_prefix('!') {
+            Some(command) => (true, command.trim_start()),
+            None => (false, line),
+        };
+        serial_println!("AUTORUN CMD {} {}", n, line);
+        let (status, output) = capture(command);
+        for out in output.lines() {
+            serial_println!("AUTORUN OUT {} {}", n, out);
+        }
+        let pass = (status == 0) != negated;
+        if pass {
+            passed += 1;
+        } else {
+            failed += 1;
+        }
+        serial_println!("AUTORUN EXIT {} {} {}", n, status, if pass { "PASS" } else { "FAIL" });
+    }
+    vfs_api::unlink(OUTPUT).ok();
+
+    serial_println!("AUTORUN END {} {}", passed, failed);
+    crate::exit_qemu(if failed == 0 { QemuExitCode::Success } else { QemuExitCode::Failed });
+    crate::hlt_loop();
+}
@@ -0,0 +1,136 @@
+// Command interface shared by all shell commands, and the writers that
+// carry their output to the shell's stdout and stderr descriptors.
+
+use alloc::vec::Vec;
+use core::fmt;
+use spin::Mutex;
+use lazy_static::lazy_static;
+use crate::kernel::scheduler::Pid;
+use crate::kernel::sys::syscalls;
+
+/// Successful completion
+pub const EXIT_SUCCESS: i32 = 0;
+/// General failure
+pub const EXIT_FAILURE: i32 = 1;
+/// Invalid usage: unknown option, missing operand
+pub const EXIT_USAGE: i32 = 2;
+/// No command of that name
+pub const EXIT_NOT_FOUND: i32 = 127;
+
+pub trait Command: Sync {
+    /// Name the command is invoked by
+    fn name(&self) -> &'static str;
+
+    /// Arguments as shown by `help`, e.g. "[-a] FILE..."
+    fn synopsis(&self) -> &'static str {
+        ""
+    }
+
+    /// One-line description shown by `help`
+    fn description(&self) -> &'static str;
+
+    /// Run with `args` (not including the command name), writing normal
+    /// output to `out` and diagnostics with `eprintln!`. Returns the exit
+    /// status.
+    fn run(&self, args: &[&str], out: &mut dyn fmt::Write) -> i32;
+
+    /// Print the usage line to stderr and return `EXIT_USAGE`
+    fn usage(&self) -> i32 {
+        match self.synopsis() {
+            "" => crate::eprintln!("Usage: {}", self.name()),
+            synopsis => crate::eprintln!("Usage: {} {}", self.name(), synopsis),
+        }
+        EXIT_USAGE
+    }
+}
+
+/// Output stream bound to a file descriptor of the shell's task.
+///
+/// Console output is written immediately. Output to anything else is held
+/// until `flush`, which the shell calls after the command returns: commands
+/// may write while holding the VFS lock, and writing a file takes it too.
+pub struct FdWriter {
+    pid: Option<Pid>,
+    fd: i32,
+    console: bool,
+    pending: Vec<u8>,
+}
+
+impl FdWriter {
+    pub fn new(pid: Option<Pid>, fd: i32) -> Self {
+        FdWriter {
+            pid,
+            fd,
+            console: syscalls::fd_is_console(pid, fd),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        if let Err(e) = syscalls::write_fd(self.pid, self.fd, &self.pending) {
+            crate::serial_println!("sh: write error on fd {}: {:?}", self.fd, e);
+        }
+        self.pending.clear();
+    }
+}
+
+impl fmt::Write for FdWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.console {
+            crate::hal::drivers::tty::write_console(s.as_bytes());
+        } else {
+            self.pending.extend_from_slice(s.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FdWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+lazy_static! {
+    /// stderr of the running command; None between commands
+    static ref STDERR: Mutex<Option<FdWriter>> = Mutex::new(None);
+}
+
+/// Bind stderr to `fd` of `pid` for the next command.
+pub fn open_stderr(pid: Option<Pid>, fd: i32) {
+    *STDERR.lock() = Some(FdWriter::new(pid, fd));
+}
+
+/// Flush and unbind stderr once the command has finished.
+pub fn close_stderr() {
+    let writer = STDERR.lock().take();
+    drop(writer);
+}
+
+#[doc(hidden)]
+pub fn _eprint(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let mut stderr = STDERR.lock();
+    match stderr.as_mut() {
+        Some(writer) => {
+            writer.write_fmt(args).ok();
+        }
+        None => crate::hal::drivers::tty::write_console(alloc::format!("{}", args).as_bytes()),
+    }
+}
+
+/// Print a diagnostic to the running command's stderr
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::userland::shell::command::_eprint(format_args!($($arg)*)));
+}
+
+/// Print a diagnostic line to the running command's stderr
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,3 @@
+// Editors: qed
+
+pub mod qed;
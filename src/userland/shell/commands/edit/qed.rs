@@ -0,0 +1,522 @@
+// qed - Line-oriented text editor
+//
+// A small ed(1): commands take line addresses (`N`, `.`, `$`, `+N`, `-N`,
+// `/text/`, `?text?`, `A,B`, `,` for the whole buffer) and act on whole
+// lines. Searches and `s` match plain text rather than regular
+// expressions. Input comes from the raw console reader, since the
+// shell's line reader has no end-of-file. Errors print "? reason".
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::FsError;
+use crate::fs::vfs::api as vfs_api;
+use crate::hal::drivers::serial;
+use crate::userland::shell::args;
+use crate::userland::shell::console::Console;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+const DEFAULT_PROMPT: &str = "*";
+
+pub struct Qed;
+
+impl Command for Qed {
+    fn name(&self) -> &'static str {
+        "qed"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-s] [-p PROMPT] [FILE]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Edit a text file with ed-style commands (-s: no byte counts)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "sp:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("qed: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.operands.len() > 1 {
+            return self.usage();
+        }
+        let prompt = opts.value('p').unwrap_or(DEFAULT_PROMPT);
+
+        let mut editor = Editor::new(opts.has('s'));
+        if let Some(&path) = opts.operands.first() {
+            editor.open(path, out);
+        }
+
+        let mut console = Console::default();
+        loop {
+            serial::write_string(prompt);
+            let line = match console.read_line() {
+                Some(line) => line,
+                None => break,
+            };
+            let mut input = || console.read_line();
+            if let Flow::Quit = editor.execute(&line, &mut input, out) {
+                break;
+            }
+        }
+        EXIT_SUCCESS
+    }
+}
+
+type EdResult<T> = Result<T, &'static str>;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    Quit,
+}
+
+struct Editor {
+    lines: Vec<String>,
+    /// Current line, 1-based; 0 only when the buffer is empty
+    current: usize,
+    path: Option<String>,
+    dirty: bool,
+    /// Buffer and current line before the last change
+    undo: Option<(Vec<String>, usize)>,
+    /// The previous command was refused for discarding unsaved changes
+    warned: bool,
+    quiet: bool,
+}
+
+fn digits(s: &mut &str) -> Option<usize> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, rest) = s.split_at(end);
+    *s = rest;
+    number.parse().ok()
+}
+
+/// Take text up to the next `delim` (or the end), consuming the delimiter
+fn until(s: &mut &str, delim: char) -> String {
+    match s.find(delim) {
+        Some(end) => {
+            let text = s[..end].to_string();
+            *s = &s[end + delim.len_utf8()..];
+            text
+        }
+        None => {
+            let text = s.to_string();
+            *s = "";
+            text
+        }
+    }
+}
+
+impl Editor {
+    fn new(quiet: bool) -> Self {
+        Editor {
+            lines: Vec::new(),
+            current: 0,
+            path: None,
+            dirty: false,
+            undo: None,
+            warned: false,
+            quiet,
+        }
+    }
+
+    /// Start editing `path`; a file that does not exist yet is fine
+    fn open(&mut self, path: &str, out: &mut dyn Write) {
+        self.path = Some(path.to_string());
+        match vfs_api::read_file(path) {
+            Ok(data) => self.replace_buffer(&data, out),
+            Err(FsError::NotFound) => crate::eprintln!("{}: new file", path),
+            Err(e) => crate::eprintln!("qed: {}: {:?}", path, e),
+        }
+    }
+
+    fn replace_buffer(&mut self, data: &[u8], out: &mut dyn Write) {
+        self.lines = String::from_utf8_lossy(data).split_terminator('\n').map(String::from).collect();
+        self.current = self.lines.len();
+        self.dirty = false;
+        self.undo = None;
+        if !self.quiet {
+            writeln!(out, "{}", data.len()).ok();
+        }
+    }
+
+    /// Run one command line, reading any text it needs through `input`
+    fn execute(&mut self, line: &str, input: &mut dyn FnMut() -> Option<String>, out: &mut dyn Write) -> Flow {
+        let warned = core::mem::take(&mut self.warned);
+        match self.command(line, warned, input, out) {
+            Ok(flow) => flow,
+            Err(reason) => {
+                writeln!(out, "? {}", reason).ok();
+                Flow::Continue
+            }
+        }
+    }
+
+    /// Remember the buffer so `u` can restore it, and mark it modified
+    fn checkpoint(&mut self) {
+        self.undo = Some((self.lines.clone(), self.current));
+        self.dirty = true;
+    }
+
+    fn search(&self, pattern: &str, forward: bool) -> EdResult<usize> {
+        let len = self.lines.len();
+        if pattern.is_empty() {
+            return Err("empty search pattern");
+        }
+        (1..=len)
+            .map(|step| if forward { (self.current + step - 1) % len + 1 } else { (self.current + len - step - 1) % len + 1 })
+            .find(|&n| self.lines[n - 1].contains(pattern))
+            .ok_or("no match")
+    }
+
+    /// One address, if `s` starts with one
+    fn address(&self, s: &mut &str) -> EdResult<Option<usize>> {
+        let mut addr = match s.chars().next() {
+            Some('.') => {
+                *s = &s[1..];
+                Some(self.current as isize)
+            }
+            Some('$') => {
+                *s = &s[1..];
+                Some(self.lines.len() as isize)
+            }
+            Some(c) if c.is_ascii_digit() => digits(s).map(|n| n as isize),
+            Some(c @ ('/' | '?')) => {
+                *s = &s[1..];
+                let pattern = until(s, c);
+                Some(self.search(&pattern, c == '/')? as isize)
+            }
+            _ => None,
+        };
+        while let Some(sign @ ('+' | '-')) = s.chars().next() {
+            *s = &s[1..];
+            let offset = digits(s).unwrap_or(1) as isize;
+            let base = addr.unwrap_or(self.current as isize);
+            addr = Some(if sign == '+' { base + offset } else { base - offset });
+        }
+        match addr {
+            Some(n) if n < 0 || n as usize > self.lines.len() => Err("invalid address"),
+            addr => Ok(addr.map(|n| n as usize)),
+        }
+    }
+
+    /// Up to two addresses separated by `,` or `;`
+    fn range(&mut self, s: &mut &str) -> EdResult<(Option<usize>, Option<usize>)> {
+        let len = self.lines.len();
+        if let Some(sep @ (',' | '%' | ';')) = s.chars().next() {
+            *s = &s[1..];
+            let first = if sep == ';' { self.current } else { 1.min(len) };
+            let second = self.address(s)?.unwrap_or(len);
+            return Ok((Some(first), Some(second)));
+        }
+        let first = self.address(s)?;
+        match s.chars().next() {
+            Some(sep @ (',' | ';')) => {
+                *s = &s[1..];
+                let first = first.unwrap_or(self.current);
+                if sep == ';' {
+                    self.current = first;
+                }
+                let second = self.address(s)?.unwrap_or(first);
+                Ok((Some(first), Some(second)))
+            }
+            _ => Ok((first, first)),
+        }
+    }
+
+    /// The addressed lines, or `default` when none were given; all must
+    /// exist
+    fn lines_in(&self, range: (Option<usize>, Option<usize>), default: (usize, usize)) -> EdResult<(usize, usize)> {
+        let (first, last) = match range {
+            (Some(first), Some(last)) => (first, last),
+            _ => default,
+        };
+        if first == 0 || first > last || last > self.lines.len() {
+            return Err("invalid address");
+        }
+        Ok((first, last))
+    }
+
+    /// Text lines up to a lone "."
+    fn read_text(input: &mut dyn FnMut() -> Option<String>) -> Vec<String> {
+        let mut text = Vec::new();
+        while let Some(line) = input() {
+            if line == "." {
+                break;
+            }
+            text.push(line);
+        }
+        text
+    }
+
+    /// Insert `text` after line `after` (0 for the top) and make the last
+    /// inserted line current
+    fn insert(&mut self, after: usize, text: Vec<String>) {
+        let count = text.len();
+        self.lines.splice(after..after, text);
+        self.current = if count > 0 { after + count } else { after.max(1).min(self.lines.len()) };
+    }
+
+    fn delete(&mut self, first: usize, last: usize) {
+        self.lines.drain(first - 1..last);
+        self.current = first.min(self.lines.len());
+    }
+
+    fn print(&mut self, out: &mut dyn Write, first: usize, last: usize, numbered: bool) {
+        for n in first..=last {
+            if numbered {
+                writeln!(out, "{}\t{}", n, self.lines[n - 1]).ok();
+            } else {
+                writeln!(out, "{}", self.lines[n - 1]).ok();
+            }
+        }
+        self.current = last;
+    }
+
+    fn filename(&self, arg: &str) -> EdResult<String> {
+        match (arg, &self.path) {
+            ("", Some(path)) => Ok(path.clone()),
+            ("", None) => Err("no current filename"),
+            (arg, _) => Ok(arg.to_string()),
+        }
+    }
+
+    fn command(
+        &mut self,
+        line: &str,
+        warned: bool,
+        input: &mut dyn FnMut() -> Option<String>,
+        out: &mut dyn Write,
+    ) -> EdResult<Flow> {
+        let mut rest = line.trim_start();
+        let range = self.range(&mut rest)?;
+        let mut chars = rest.chars();
+        let command = chars.next();
+        let rest = chars.as_str();
+        let arg = rest.trim();
+        let len = self.lines.len();
+        let current = (self.current, self.current);
+
+        match command {
+            None => {
+                let target = match range.1 {
+                    Some(n) => n,
+                    None => self.current + 1,
+                };
+                let (n, _) = self.lines_in((Some(target), Some(target)), (target, target))?;
+                self.print(out, n, n, false);
+            }
+            Some(c @ ('p' | 'n')) => {
+                let (first, last) = self.lines_in(range, current)?;
+                self.print(out, first, last, c == 'n');
+            }
+            Some('=') => {
+                writeln!(out, "{}", range.1.unwrap_or(len)).ok();
+            }
+            Some(c @ ('a' | 'i' | 'c')) => {
+                let (first, last) = match c {
+                    'c' => self.lines_in(range, current)?,
+                    _ => {
+                        let n = range.1.unwrap_or(self.current);
+                        (n, n)
+                    }
+                };
+                let text = Self::read_text(input);
+                self.checkpoint();
+                match c {
+                    'a' => self.insert(last, text),
+                    'i' => self.insert(first.saturating_sub(1), text),
+                    _ => {
+                        self.delete(first, last);
+                        self.insert(first - 1, text);
+                    }
+                }
+            }
+            Some('d') => {
+                let (first, last) = self.lines_in(range, current)?;
+                self.checkpoint();
+                self.delete(first, last);
+            }
+            Some('j') => {
+                let (first, last) = self.lines_in(range, (self.current, self.current + 1))?;
+                if first < last {
+                    self.checkpoint();
+                    let joined: String = self.lines.drain(first - 1..last).collect();
+                    self.lines.insert(first - 1, joined);
+                }
+                self.current = first;
+            }
+            Some(c @ ('m' | 't')) => {
+                let (first, last) = self.lines_in(range, current)?;
+                let mut dest_text = arg;
+                let dest = self.address(&mut dest_text)?.ok_or("destination expected")?;
+                if c == 'm' && dest >= first && dest < last {
+                    return Err("invalid destination");
+                }
+                self.checkpoint();
+                let moved: Vec<String> = self.lines[first - 1..last].to_vec();
+                let count = moved.len();
+                let dest = if c == 'm' {
+                    self.lines.drain(first - 1..last);
+                    if dest >= last { dest - count } else { dest }
+                } else {
+                    dest
+                };
+                self.insert(dest, moved);
+            }
+            Some('s') => {
+                let (first, last) = self.lines_in(range, current)?;
+                let mut spec = rest;
+                let delim = spec.chars().next().filter(|c| !c.is_whitespace()).ok_or("missing pattern delimiter")?;
+                spec = &spec[delim.len_utf8()..];
+                let pattern = until(&mut spec, delim);
+                let replacement = until(&mut spec, delim);
+                if pattern.is_empty() {
+                    return Err("empty search pattern");
+                }
+                let (global, show) = (spec.contains('g'), spec.contains('p'));
+                if spec.trim().chars().any(|c| c != 'g' && c != 'p') {
+                    return Err("unknown command suffix");
+                }
+
+                let mut changed = Vec::new();
+                for n in first..=last {
+                    let line = &self.lines[n - 1];
+                    if line.contains(pattern.as_str()) {
+                        let new = if global {
+                            line.replace(pattern.as_str(), &replacement)
+                        } else {
+                            line.replacen(pattern.as_str(), &replacement, 1)
+                        };
+                        changed.push((n, new));
+                    }
+                }
+                let last_changed = changed.last().ok_or("no match")?.0;
+                self.checkpoint();
+                for (n, new) in changed {
+                    self.lines[n - 1] = new;
+                }
+                self.current = last_changed;
+                if show {
+                    self.print(out, last_changed, last_changed, false);
+                }
+            }
+            Some('u') => {
+                let (lines, current) = self.undo.take().ok_or("nothing to undo")?;
+                let previous = core::mem::replace(&mut self.lines, lines);
+                self.undo = Some((previous, self.current));
+                self.current = current;
+                self.dirty = true;
+            }
+            Some('w') => {
+                let (quit, arg) = match rest.strip_prefix('q') {
+                    Some(after) => (true, after.trim()),
+                    None => (false, arg),
+                };
+                let (first, last) = if len == 0 { (1, 0) } else { self.lines_in(range, (1, len))? };
+                let path = self.filename(arg)?;
+                let mut data = String::new();
+                for line in &self.lines[first - 1..last] {
+                    data.push_str(line);
+                    data.push('\n');
+                }
+                vfs_api::write_file(&path, data.as_bytes(), 0o644).map_err(|_| "cannot write file")?;
+                if self.path.is_none() {
+                    self.path = Some(path);
+                }
+                if !self.quiet {
+                    writeln!(out, "{}", data.len()).ok();
+                }
+                if (first, last) == (1, len) {
+                    self.dirty = false;
+                }
+                if quit {
+                    return Ok(Flow::Quit);
+                }
+            }
+            Some(c @ ('q' | 'Q')) => {
+                if c == 'q' && self.dirty && !warned {
+                    self.warned = true;
+                    return Err("buffer modified; w to save, q again to discard");
+                }
+                return Ok(Flow::Quit);
+            }
+            Some(c @ ('e' | 'E')) => {
+                if c == 'e' && self.dirty && !warned {
+                    self.warned = true;
+                    return Err("buffer modified; w to save, e again to discard");
+                }
+                let path = self.filename(arg)?;
+                let data = vfs_api::read_file(&path).map_err(|_| "cannot open input file")?;
+                self.path = Some(path);
+                self.replace_buffer(&data, out);
+            }
+            Some('r') => {
+                let after = range.1.unwrap_or(len);
+                let path = self.filename(arg)?;
+                let data = vfs_api::read_file(&path).map_err(|_| "cannot open input file")?;
+                let text: Vec<String> = String::from_utf8_lossy(&data).split_terminator('\n').map(String::from).collect();
+                self.checkpoint();
+                self.insert(after, text);
+                if self.path.is_none() {
+                    self.path = Some(path);
+                }
+                if !self.quiet {
+                    writeln!(out, "{}", data.len()).ok();
+                }
+            }
+            Some('f') => {
+                if !arg.is_empty() {
+                    self.path = Some(arg.to_string());
+                }
+                let path = self.path.as_deref().ok_or("no current filename")?;
+                writeln!(out, "{}", path).ok();
+            }
+            Some('h') => {
+                writeln!(out, "{}", HELP).ok();
+            }
+            Some(_) => return Err("unknown command"),
+        }
+        Ok(Flow::Continue)
+    }
+}
+
+const HELP: &str = "\
+addresses: N . $ +N -N /text/ ?text? A,B , (all) ;
+(.)a (.)i (.,.)c     add after, insert before or change lines; end text with .
+(.,.)d (.,.+1)j      delete or join lines
+(.,.)m A (.,.)t A    move or copy lines after A
+(.,.)s/old/new/[g][p] replace text
+(.,.)p (.,.)n ($)=   print, print numbered, line number
+u                    undo the last change
+(1,$)w [FILE] wq     write (and quit); e FILE, r FILE, f [FILE]
+q Q                  quit, quit discarding changes";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(editor: &mut Editor, commands: &[&str]) -> String {
+        let mut out = String::new();
+        let mut lines = commands.iter().map(|s| s.to_string());
+        while let Some(line) = lines.next() {
+            let mut input = || lines.next();
+            editor.execute(&line, &mut input, &mut out);
+        }
+        out
+    }
+
+    #[test_case]
+    fn test_edit_commands() {
+        let mut editor = Editor::new(true);
+        assert_eq!(run(&mut editor, &["a", "one", "two", "three", ".", ",n"]), "1\tone\n2\ttwo\n3\tthree\n");
+        assert_eq!(run(&mut editor, &["2", "s/w/ww/p", "1i", "zero", ".", "$=", ".="]), "two\ntwwo\n4\n1\n");
+        assert_eq!(run(&mut editor, &["/three/d", ",p", "u", "$p"]), "zero\none\ntwwo\nthree\n");
+        assert_eq!(run(&mut editor, &["1m$", ",p", "1,2j", "p"]), "one\ntwwo\nthree\nzero\nonetwwo\n");
+        assert_eq!(run(&mut editor, &["9p", "s/nope/x/", "?o?", "q", "Q"]), "? invalid address\n? no match\nzero\n? buffer modified; w to save, q again to discard\n");
+    }
+}
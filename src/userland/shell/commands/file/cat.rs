@@ -1,36 +1,108 @@
 // cat - Display file contents
 
-pub fn run(args: &[&str]) {
-    if args.is_empty() {
-        crate::serial_println!("Usage: cat <file>");
-        return;
+use core::fmt::Write;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::shell::stream::FileReader;
+
+pub struct Cat;
+
+impl Command for Cat {
+    fn name(&self) -> &'static str {
+        "cat"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[FILE]..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Display file contents, or standard input for - or none"
     }
-    
-    let vfs = crate::fs::vfs::VFS.lock();
-    
-    for filename in args {
-        match vfs.lookup_path(filename) {
-            Ok(node) => {
-                if node.is_file() {
-                    let mut buf = [0u8; 4096];
-                    match node.read(0, &mut buf) {
-                        Ok(len) => {
-                            if let Ok(s) = core::str::from_utf8(&buf[..len]) {
-                                crate::serial_print!("{}", s);
-                            } else {
-                                crate::serial_println!("(binary data)");
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("cat: {}", e);
+                return self.usage();
+            }
+        };
+        let operands = match opts.operands.is_empty() {
+            true => alloc::vec!["-"],
+            false => opts.operands,
+        };
+        let mut status = EXIT_SUCCESS;
+
+        for filename in &operands {
+            if *filename == "-" {
+                if !cat_stdin(out) {
+                    status = EXIT_FAILURE;
+                }
+                continue;
+            }
+            let vfs = crate::fs::vfs::VFS.lock();
+            match vfs.lookup_path(filename) {
+                Ok(node) => {
+                    if !node.is_dir() {
+                        // Device nodes such as /dev/hotplug have no size,
+                        // so read until they run dry
+                        let mut buf = [0u8; 4096];
+                        let mut offset = 0;
+                        loop {
+                            match node.read(offset, &mut buf) {
+                                Ok(0) => break,
+                                Ok(len) => {
+                                    if let Ok(s) = core::str::from_utf8(&buf[..len]) {
+                                        write!(out, "{}", s).ok();
+                                    } else {
+                                        writeln!(out, "(binary data)").ok();
+                                        break;
+                                    }
+                                    offset += len as u64;
+                                }
+                                Err(e) => {
+                                    crate::eprintln!("cat: error reading '{}': {:?}", filename, e);
+                                    status = EXIT_FAILURE;
+                                    break;
+                                }
                             }
                         }
-                        Err(e) => {
-                            crate::serial_println!("cat: error reading '{}': {:?}", filename, e);
-                        }
+                    } else {
+                        crate::eprintln!("cat: '{}': Is a directory", filename);
+                        status = EXIT_FAILURE;
                     }
-                } else {
-                    crate::serial_println!("cat: '{}': Is a directory", filename);
                 }
+                Err(e) => {
+                    crate::eprintln!("cat: cannot open '{}': {:?}", filename, e);
+                    status = EXIT_FAILURE;
+                }
+            }
+        }
+        status
+    }
+}
+
+/// Copy standard input to `out`; false if it could not be read
+fn cat_stdin(out: &mut dyn Write) -> bool {
+    let mut stdin = match FileReader::open("-") {
+        Ok(stdin) => stdin,
+        Err(e) => {
+            crate::eprintln!("cat: -: {:?}", e);
+            return false;
+        }
+    };
+    let mut line = alloc::vec::Vec::new();
+    loop {
+        line.clear();
+        match stdin.read_line(&mut line) {
+            Ok(true) => {
+                write!(out, "{}", alloc::string::String::from_utf8_lossy(&line)).ok();
             }
+            Ok(false) => return true,
             Err(e) => {
-                crate::serial_println!("cat: cannot open '{}': {:?}", filename, e);
+                crate::eprintln!("cat: -: {:?}", e);
+                return false;
             }
         }
     }
@@ -1,15 +1,44 @@
 // cd - Change directory
 
-pub fn run(args: &[&str]) {
-    let target = if args.is_empty() { "/root" } else { args[0] };
-    
-    let mut vfs = crate::fs::vfs::VFS.lock();
-    match vfs.set_cwd(target) {
-        Ok(_) => {
+use alloc::string::String;
+use core::fmt::Write;
+use crate::userland::shell::{args, vars};
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Cd;
+
+impl Command for Cd {
+    fn name(&self) -> &'static str {
+        "cd"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[DIR]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Change directory"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("cd: {}", e);
+                return self.usage();
+            }
+        };
+        let home = vars::get("HOME").unwrap_or_else(|| String::from("/root"));
+        let target = opts.operands.first().copied().unwrap_or(&home);
+
+        let mut vfs = crate::fs::vfs::VFS.lock();
+        match vfs.set_cwd(target) {
             // Silent on success like real cd
-        }
-        Err(e) => {
-            crate::serial_println!("cd: error changing to '{}': {:?}", target, e);
+            Ok(_) => EXIT_SUCCESS,
+            Err(e) => {
+                crate::eprintln!("cd: error changing to '{}': {:?}", target, e);
+                EXIT_FAILURE
+            }
         }
     }
 }
@@ -1,30 +1,55 @@
 // chmod - Change file permissions
 
-pub fn run(args: &[&str]) {
-    if args.len() < 2 {
-        crate::serial_println!("Usage: chmod <mode> <file>");
-        return;
+use core::fmt::Write;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Chmod;
+
+impl Command for Chmod {
+    fn name(&self) -> &'static str {
+        "chmod"
     }
-    
-    let mode_str = args[0];
-    let path = args[1];
-    
-    let mode = if mode_str.starts_with('0') || mode_str.starts_with('1') || 
-                  mode_str.starts_with('2') || mode_str.starts_with('3') ||
-                  mode_str.starts_with('4') || mode_str.starts_with('5') ||
-                  mode_str.starts_with('6') || mode_str.starts_with('7') {
-        u16::from_str_radix(mode_str, 8).unwrap_or(0o644)
-    } else {
-        0o644
-    };
-    
-    let mut vfs = crate::fs::vfs::VFS.lock();
-    match vfs.chmod(path, mode) {
-        Ok(_) => {
-            crate::serial_println!("Changed permissions of '{}' to {:o}", path, mode);
-        }
-        Err(e) => {
-            crate::serial_println!("chmod: error changing '{}': {:?}", path, e);
+
+    fn synopsis(&self) -> &'static str {
+        "MODE FILE"
+    }
+
+    fn description(&self) -> &'static str {
+        "Change file permissions"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("chmod: {}", e);
+                return self.usage();
+            }
+        };
+        let (mode_str, path) = match opts.operands[..] {
+            [mode, path] => (mode, path),
+            _ => return self.usage(),
+        };
+
+        let mode = match u16::from_str_radix(mode_str, 8) {
+            Ok(mode) if mode <= 0o7777 => mode,
+            _ => {
+                crate::eprintln!("chmod: invalid mode: '{}'", mode_str);
+                return EXIT_FAILURE;
+            }
+        };
+
+        let mut vfs = crate::fs::vfs::VFS.lock();
+        match vfs.chmod(path, mode) {
+            Ok(_) => {
+                writeln!(out, "Changed permissions of '{}' to {:o}", path, mode).ok();
+                EXIT_SUCCESS
+            }
+            Err(e) => {
+                crate::eprintln!("chmod: error changing '{}': {:?}", path, e);
+                EXIT_FAILURE
+            }
         }
     }
 }
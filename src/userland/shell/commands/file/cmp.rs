@@ -0,0 +1,79 @@
+// cmp - Compare two files byte by byte
+
+use core::fmt::Write;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+use crate::userland::shell::stream::FileReader;
+
+pub struct Cmp;
+
+impl Command for Cmp {
+    fn name(&self) -> &'static str {
+        "cmp"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-s] FILE1 FILE2"
+    }
+
+    fn description(&self) -> &'static str {
+        "Report the first byte and line where two files differ"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "s") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("cmp: {}", e);
+                return self.usage();
+            }
+        };
+        let (name1, name2) = match opts.operands[..] {
+            [name1, name2] => (name1, name2),
+            _ => return self.usage(),
+        };
+        let silent = opts.has('s');
+
+        let mut readers = [name1, name2].map(|name| {
+            FileReader::open(name).map_err(|e| crate::eprintln!("cmp: {}: {:?}", name, e))
+        });
+        let (file1, file2) = match &mut readers {
+            [Ok(file1), Ok(file2)] => (file1, file2),
+            // Trouble is status 2, as for a usage error
+            _ => return EXIT_USAGE,
+        };
+
+        let (mut byte, mut line) = (1u64, 1u64);
+        loop {
+            let (b1, b2) = match (file1.next_byte(), file2.next_byte()) {
+                (Ok(b1), Ok(b2)) => (b1, b2),
+                (Err(e), _) | (_, Err(e)) => {
+                    crate::eprintln!("cmp: read error: {:?}", e);
+                    return EXIT_USAGE;
+                }
+            };
+            match (b1, b2) {
+                (None, None) => return EXIT_SUCCESS,
+                (None, Some(_)) | (Some(_), None) => {
+                    if !silent {
+                        let shorter = if b1.is_none() { name1 } else { name2 };
+                        crate::eprintln!("cmp: EOF on {} after byte {}, line {}", shorter, byte - 1, line);
+                    }
+                    return EXIT_FAILURE;
+                }
+                (Some(b1), Some(b2)) if b1 != b2 => {
+                    if !silent {
+                        writeln!(out, "{} {} differ: byte {}, line {}", name1, name2, byte, line).ok();
+                    }
+                    return EXIT_FAILURE;
+                }
+                (Some(b1), Some(_)) => {
+                    byte += 1;
+                    if b1 == b'\n' {
+                        line += 1;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// diff - Compare files line by line
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::FsResult;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+use crate::userland::shell::stream::FileReader;
+
+const DEFAULT_CONTEXT: usize = 3;
+
+pub struct Diff;
+
+impl Command for Diff {
+    fn name(&self) -> &'static str {
+        "diff"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-u] [-U NUM] FILE1 FILE2"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show line differences in unified format"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "uU:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("diff: {}", e);
+                return self.usage();
+            }
+        };
+        let (name1, name2) = match opts.operands[..] {
+            [name1, name2] => (name1, name2),
+            _ => return self.usage(),
+        };
+        let context = match opts.value('U').map(str::parse::<usize>) {
+            None => DEFAULT_CONTEXT,
+            Some(Ok(context)) => context,
+            Some(Err(_)) => {
+                crate::eprintln!("diff: invalid context length '{}'", opts.value('U').unwrap_or(""));
+                return EXIT_USAGE;
+            }
+        };
+
+        let mut files = [name1, name2].map(|name| {
+            read_lines(name).map_err(|e| crate::eprintln!("diff: {}: {:?}", name, e))
+        });
+        let (a, b) = match &mut files {
+            [Ok(a), Ok(b)] => (a, b),
+            // Trouble is status 2, as for a usage error
+            _ => return EXIT_USAGE,
+        };
+
+        let ops = diff_lines(a, b);
+        if ops.iter().all(|op| matches!(op, Op::Equal(..))) {
+            return EXIT_SUCCESS;
+        }
+        writeln!(out, "--- {}", name1).ok();
+        writeln!(out, "+++ {}", name2).ok();
+        write_hunks(out, a, b, &ops, context);
+        EXIT_FAILURE
+    }
+}
+
+fn read_lines(path: &str) -> FsResult<Vec<Vec<u8>>> {
+    let mut file = FileReader::open(path)?;
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    while file.read_line(&mut line)? {
+        lines.push(core::mem::take(&mut line));
+    }
+    Ok(lines)
+}
+
+/// One step of an edit script, by line index into the old and new file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Shortest edit script from `a` to `b` (Myers' O(ND) algorithm)
+fn diff_lines<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Op> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let index = |k: isize| (k + max + 1) as usize;
+
+    // trace[d] is the furthest x reached on each diagonal k = x - y before
+    // round d
+    let mut v = alloc::vec![0isize; 2 * max as usize + 3];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) { k + 1 } else { k - 1 };
+        let prev_x = v[index(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(Op::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                ops.push(Op::Insert(prev_y as usize));
+            } else {
+                ops.push(Op::Delete(prev_x as usize));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    ops
+}
+
+/// `start,len` as in a hunk header; the length is left out when it is 1
+fn range(before: usize, len: usize) -> String {
+    match len {
+        0 => alloc::format!("{},0", before),
+        1 => alloc::format!("{}", before + 1),
+        _ => alloc::format!("{},{}", before + 1, len),
+    }
+}
+
+fn write_line(out: &mut dyn Write, prefix: char, line: &[u8]) {
+    let text = String::from_utf8_lossy(line);
+    match text.strip_suffix('\n') {
+        Some(text) => writeln!(out, "{}{}", prefix, text),
+        None => writeln!(out, "{}{}\n\\ No newline at end of file", prefix, text),
+    }
+    .ok();
+}
+
+fn write_hunks(out: &mut dyn Write, a: &[Vec<u8>], b: &[Vec<u8>], ops: &[Op], context: usize) {
+    let changes: Vec<usize> = ops.iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(..)))
+        .map(|(i, _)| i)
+        .collect();
+
+    let mut next = 0;
+    while next < changes.len() {
+        // Merge changes whose context would touch or overlap
+        let first = changes[next];
+        let mut last = first;
+        next += 1;
+        while next < changes.len() && changes[next] - last <= 2 * context + 1 {
+            last = changes[next];
+            next += 1;
+        }
+        let start = first.saturating_sub(context);
+        let end = (last + context + 1).min(ops.len());
+        let hunk = &ops[start..end];
+
+        let old_before = ops[..start].iter().filter(|op| !matches!(op, Op::Insert(_))).count();
+        let new_before = ops[..start].iter().filter(|op| !matches!(op, Op::Delete(_))).count();
+        let old_len = hunk.iter().filter(|op| !matches!(op, Op::Insert(_))).count();
+        let new_len = hunk.iter().filter(|op| !matches!(op, Op::Delete(_))).count();
+        writeln!(out, "@@ -{} +{} @@", range(old_before, old_len), range(new_before, new_len)).ok();
+
+        for op in hunk {
+            match *op {
+                Op::Equal(i, _) => write_line(out, ' ', &a[i]),
+                Op::Delete(i) => write_line(out, '-', &a[i]),
+                Op::Insert(j) => write_line(out, '+', &b[j]),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<Vec<u8>> {
+        text.split_inclusive('\n').map(|line| line.as_bytes().to_vec()).collect()
+    }
+
+    #[test_case]
+    fn test_unified_hunks() {
+        let a = lines("a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n");
+        let b = lines("a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk");
+        let ops = diff_lines(&a, &b);
+        assert_eq!(ops.iter().filter(|op| !matches!(op, Op::Equal(..))).count(), 3);
+
+        let mut out = String::new();
+        write_hunks(&mut out, &a, &b, &ops, 1);
+        assert_eq!(
+            out,
+            "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n@@ -10 +10,2 @@\n j\n+k\n\\ No newline at end of file\n"
+        );
+
+        let mut out = String::new();
+        write_hunks(&mut out, &a, &b, &ops, 3);
+        assert!(out.starts_with("@@ -1,5 +1,5 @@\n") && out.contains("@@ -8,3 +8,4 @@\n"));
+    }
+}
@@ -1,11 +1,38 @@
 // echo - Print text
 
-pub fn run(args: &[&str]) {
-    for (i, arg) in args.iter().enumerate() {
-        if i > 0 { 
-            crate::serial_print!(" "); 
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Echo;
+
+impl Command for Echo {
+    fn name(&self) -> &'static str {
+        "echo"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-n] TEXT..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Echo text to terminal"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        // Only a leading -n is an option; everything else is printed as is
+        let (newline, args) = match args.split_first() {
+            Some((&"-n", rest)) => (false, rest),
+            _ => (true, args),
+        };
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                write!(out, " ").ok();
+            }
+            write!(out, "{}", arg).ok();
+        }
+        if newline {
+            writeln!(out).ok();
         }
-        crate::serial_print!("{}", arg);
+        EXIT_SUCCESS
     }
-    crate::serial_println!();
 }
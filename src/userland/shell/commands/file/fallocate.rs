@@ -0,0 +1,80 @@
+// fallocate - Preallocate or deallocate space in a file
+//
+// Allocates LENGTH bytes at OFFSET (0 by default), growing the file to
+// cover them unless -n keeps its size; FILE is created if missing. -p
+// punches a hole instead: the range reads as zeros and its pages are
+// freed, the size staying as it was. Sizes take a K, M or G suffix.
+//
+//   fallocate -l 64M disk.img
+//   fallocate -p -o 4K -l 1M disk.img
+//   ls -l disk.img
+
+use core::fmt::Write;
+use crate::fs::vfs::api::{self as vfs_api, FallocateFlags, OpenFlags};
+use crate::kernel::sys::errno::Errno;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Fallocate;
+
+impl Command for Fallocate {
+    fn name(&self) -> &'static str {
+        "fallocate"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-n] [-p] [-o OFFSET] -l LENGTH FILE"
+    }
+
+    fn description(&self) -> &'static str {
+        "Preallocate or punch holes in file space"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "npo:l:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("fallocate: {}", e);
+                return self.usage();
+            }
+        };
+        let [file] = opts.operands[..] else {
+            return self.usage();
+        };
+        let Some(len) = opts.value('l').and_then(bytes) else {
+            return self.usage();
+        };
+        let Some(offset) = opts.value('o').map_or(Some(0), bytes) else {
+            crate::eprintln!("fallocate: invalid offset");
+            return EXIT_FAILURE;
+        };
+        let mut mode = FallocateFlags::empty();
+        if opts.has('n') {
+            mode |= FallocateFlags::KEEP_SIZE;
+        }
+        if opts.has('p') {
+            mode |= FallocateFlags::PUNCH_HOLE | FallocateFlags::KEEP_SIZE;
+        }
+
+        let result = vfs_api::open(file, OpenFlags::O_WRONLY | OpenFlags::O_CREAT, 0o644)
+            .and_then(|fd| vfs_api::fallocate(&fd, mode, offset, len));
+        match result {
+            Ok(()) => EXIT_SUCCESS,
+            Err(e) => {
+                crate::eprintln!("fallocate: {}: {}", file, Errno::from(e).description());
+                EXIT_FAILURE
+            }
+        }
+    }
+}
+
+/// Bytes: a plain number, or one with a K, M or G suffix
+fn bytes(text: &str) -> Option<u64> {
+    let (number, shift) = match text.char_indices().last()? {
+        (at, 'K' | 'k') => (&text[..at], 10),
+        (at, 'M' | 'm') => (&text[..at], 20),
+        (at, 'G' | 'g') => (&text[..at], 30),
+        _ => (text, 0),
+    };
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
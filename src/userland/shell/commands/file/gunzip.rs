@@ -0,0 +1,85 @@
+// gunzip - Decompress gzip files
+
+use alloc::string::String;
+use core::fmt::Write;
+use crate::fs::archive::gzip;
+use crate::fs::vfs::api::{self as vfs_api, write_file};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use super::tar::UNPACK_LIMIT;
+
+pub struct Gunzip;
+
+impl Command for Gunzip {
+    fn name(&self) -> &'static str {
+        "gunzip"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-ck] FILE..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Decompress FILE.gz to FILE (-c: to stdout, -k: keep FILE.gz)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "ck") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("gunzip: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.operands.is_empty() {
+            return self.usage();
+        }
+        let (to_stdout, keep) = (opts.has('c'), opts.has('k'));
+
+        let mut status = EXIT_SUCCESS;
+        for &path in &opts.operands {
+            let target = match path.strip_suffix(".gz") {
+                Some(target) if !target.is_empty() => target,
+                _ if to_stdout => path,
+                _ => {
+                    crate::eprintln!("gunzip: {}: unknown suffix -- ignored", path);
+                    status = EXIT_FAILURE;
+                    continue;
+                }
+            };
+            let data = vfs_api::read_file(path).and_then(|data| {
+                if !gzip::is_gzip(&data) {
+                    crate::eprintln!("gunzip: {}: not in gzip format", path);
+                    return Ok(None);
+                }
+                gzip::decompress(&data, UNPACK_LIMIT).map(Some)
+            });
+            let data = match data {
+                Ok(Some(data)) => data,
+                Ok(None) => {
+                    status = EXIT_FAILURE;
+                    continue;
+                }
+                Err(e) => {
+                    crate::eprintln!("gunzip: {}: {:?}", path, e);
+                    status = EXIT_FAILURE;
+                    continue;
+                }
+            };
+
+            if to_stdout {
+                write!(out, "{}", String::from_utf8_lossy(&data)).ok();
+                continue;
+            }
+            let mode = vfs_api::stat(path).map(|s| s.mode.permissions()).unwrap_or(0o644);
+            let written = write_file(target, &data, mode).and_then(|_| {
+                if keep { Ok(()) } else { vfs_api::unlink(path) }
+            });
+            if let Err(e) = written {
+                crate::eprintln!("gunzip: {}: {:?}", target, e);
+                status = EXIT_FAILURE;
+            }
+        }
+        status
+    }
+}
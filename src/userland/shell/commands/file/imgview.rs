@@ -0,0 +1,446 @@
+// imgview - Show BMP and PPM images on the framebuffer
+//
+// Keys, on the PS/2 keyboard or the serial console: n or Space for the
+// next file, p or Backspace for the previous one, q or Esc to quit.
+// Images are decoded a row at a time straight from the file and scaled
+// onto the screen as the rows arrive, so an image never has to fit in
+// memory whole. Uncompressed BMP at 1, 4, 8, 24 and 32 bits per pixel and
+// binary (P6) or plain (P3) PPM are understood.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::ops::Range;
+use crate::hal::drivers::framebuffer::{self, Framebuffer, Mode};
+use crate::hal::drivers::gfx::{rgb, Canvas, Color, Font, Rect, BLACK, GLYPH_HEIGHT, WHITE};
+use crate::hal::drivers::{keyboard, serial};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::shell::stream::FileReader;
+
+const MODE: Mode = Mode { width: 800, height: 600 };
+/// Room for the file name under the picture
+const CAPTION_HEIGHT: u32 = GLYPH_HEIGHT + 4;
+/// Larger images are refused rather than read for minutes
+const MAX_DIMENSION: u32 = 16384;
+
+pub struct Imgview;
+
+impl Command for Imgview {
+    fn name(&self) -> &'static str {
+        "imgview"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "FILE..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Show BMP/PPM images full screen (n/p: next/previous, q: quit)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("imgview: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.operands.is_empty() {
+            return self.usage();
+        }
+        let fb = match framebuffer::enter(MODE) {
+            Ok(fb) => fb,
+            Err(e) => {
+                crate::eprintln!("imgview: {}", e);
+                return EXIT_FAILURE;
+            }
+        };
+        let font = match framebuffer::font() {
+            Some(font) => font,
+            None => {
+                framebuffer::leave();
+                crate::eprintln!("imgview: no font");
+                return EXIT_FAILURE;
+            }
+        };
+        keyboard::clear_buffer();
+
+        let mut screen = Canvas::new(MODE.width, MODE.height, BLACK);
+        let mut failed = Vec::new();
+        let mut index = 0;
+        loop {
+            let path = opts.operands[index];
+            let caption = match show(&mut screen, path) {
+                Ok((width, height)) => format!("{}  {}x{}  [{}/{}]", path, width, height, index + 1, opts.operands.len()),
+                Err(e) => {
+                    if !failed.contains(&path) {
+                        failed.push(path);
+                    }
+                    format!("{}: {}  [{}/{}]", path, e, index + 1, opts.operands.len())
+                }
+            };
+            draw_caption(&mut screen, &caption, &font);
+            present(&fb, &screen);
+
+            match wait_key() {
+                b'n' | b' ' => index = (index + 1) % opts.operands.len(),
+                b'p' | 0x08 | 0x7F => index = (index + opts.operands.len() - 1) % opts.operands.len(),
+                _ => break,
+            }
+        }
+        framebuffer::leave();
+        for path in &failed {
+            writeln!(out, "imgview: {}: could not be shown", path).ok();
+        }
+        if failed.is_empty() { EXIT_SUCCESS } else { EXIT_FAILURE }
+    }
+}
+
+/// The next navigation key from either keyboard, q for quit
+fn wait_key() -> u8 {
+    loop {
+        let key = keyboard::read_char()
+            .and_then(|c| u8::try_from(c).ok())
+            .or_else(serial::read_byte);
+        match key {
+            Some(b'n' | b' ' | b'p' | 0x08 | 0x7F) => return key.unwrap_or(b'q'),
+            Some(b'q' | 0x1B) => return b'q',
+            _ => x86_64::instructions::hlt(),
+        }
+    }
+}
+
+fn present(fb: &Framebuffer, screen: &Canvas) {
+    fb.present(screen, screen.bounds());
+}
+
+fn draw_caption(screen: &mut Canvas, caption: &str, font: &Font) {
+    let bar = Rect::new(0, (screen.height() - CAPTION_HEIGHT) as i32, screen.width(), CAPTION_HEIGHT);
+    screen.fill_rect(bar, rgb(0x20, 0x20, 0x20));
+    screen.draw_text(4, bar.y + 2, caption, WHITE, None, font);
+}
+
+/// Decode `path` onto the screen above the caption bar, returning the
+/// image size
+fn show(screen: &mut Canvas, path: &str) -> Result<(u32, u32), &'static str> {
+    screen.clear(BLACK);
+    let file = FileReader::open(path).map_err(|_| "cannot open")?;
+    let mut decoder = Decoder::new(file)?;
+    let area = Rect::new(0, 0, screen.width(), screen.height() - CAPTION_HEIGHT);
+    let (width, height) = (decoder.width, decoder.height);
+    let dest = fit(width, height, area);
+    let mut scaled = vec![BLACK; dest.width as usize];
+    while let Some((y, row)) = decoder.next_row()? {
+        let rows = dest_rows(y, height, dest.height);
+        if rows.is_empty() {
+            continue;
+        }
+        for (x, pixel) in scaled.iter_mut().enumerate() {
+            *pixel = row[x * width as usize / dest.width as usize];
+        }
+        for dy in rows {
+            for (x, &pixel) in scaled.iter().enumerate() {
+                screen.set_pixel(dest.x + x as i32, dest.y + dy as i32, pixel);
+            }
+        }
+    }
+    Ok((width, height))
+}
+
+/// The largest rectangle of the image's shape that fits centred in `area`
+fn fit(width: u32, height: u32, area: Rect) -> Rect {
+    let (w, h) = if width as u64 * area.height as u64 > height as u64 * area.width as u64 {
+        (area.width, (height as u64 * area.width as u64 / width as u64).max(1) as u32)
+    } else {
+        ((width as u64 * area.height as u64 / height as u64).max(1) as u32, area.height)
+    };
+    Rect::new(area.x + ((area.width - w) / 2) as i32, area.y + ((area.height - h) / 2) as i32, w, h)
+}
+
+/// The rows of a `dest`-row picture drawn from row `src_y` of a
+/// `src`-row image, nearest row first: dest row d shows row d*src/dest
+fn dest_rows(src_y: u32, src: u32, dest: u32) -> Range<u32> {
+    let first = |y: u32| ((y as u64 * dest as u64).div_ceil(src as u64)) as u32;
+    first(src_y)..first(src_y + 1).min(dest)
+}
+
+/// Where image bytes come from
+trait Source {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), &'static str>;
+
+    fn byte(&mut self) -> Result<u8, &'static str> {
+        let mut b = [0u8];
+        self.read_exact(&mut b)?;
+        Ok(b[0])
+    }
+
+    fn skip(&mut self, mut n: usize) -> Result<(), &'static str> {
+        let mut buf = [0u8; 256];
+        while n > 0 {
+            let chunk = n.min(buf.len());
+            self.read_exact(&mut buf[..chunk])?;
+            n -= chunk;
+        }
+        Ok(())
+    }
+}
+
+impl Source for FileReader {
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), &'static str> {
+        let mut done = 0;
+        while done < buf.len() {
+            match self.read(&mut buf[done..]) {
+                Ok(0) => return Err("file is truncated"),
+                Ok(n) => done += n,
+                Err(_) => return Err("read error"),
+            }
+        }
+        Ok(())
+    }
+}
+
+enum Format {
+    Bmp { bits: u16, top_down: bool, palette: Vec<Color>, stride: usize },
+    Ppm { plain: bool, maxval: u16 },
+}
+
+struct Decoder<S: Source> {
+    src: S,
+    format: Format,
+    width: u32,
+    height: u32,
+    /// Rows handed out so far
+    rows: u32,
+    raw: Vec<u8>,
+    row: Vec<Color>,
+}
+
+fn le16(b: &[u8]) -> u16 {
+    u16::from_le_bytes([b[0], b[1]])
+}
+
+fn le32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+impl<S: Source> Decoder<S> {
+    fn new(mut src: S) -> Result<Self, &'static str> {
+        let mut magic = [0u8; 2];
+        src.read_exact(&mut magic)?;
+        let (format, width, height) = match &magic {
+            b"BM" => Self::bmp_header(&mut src)?,
+            b"P6" | b"P3" => {
+                let width = ppm_number(&mut src)?;
+                let height = ppm_number(&mut src)?;
+                let maxval = ppm_number(&mut src)?;
+                if maxval == 0 || maxval > u16::MAX as u32 {
+                    return Err("bad PPM maximum value");
+                }
+                (Format::Ppm { plain: magic[1] == b'3', maxval: maxval as u16 }, width, height)
+            }
+            _ => return Err("not a BMP or PPM file"),
+        };
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err("unsupported image size");
+        }
+        let raw = match &format {
+            Format::Bmp { stride, .. } => vec![0; *stride],
+            Format::Ppm { plain: false, maxval } => vec![0; width as usize * if *maxval > 255 { 6 } else { 3 }],
+            Format::Ppm { plain: true, .. } => Vec::new(),
+        };
+        Ok(Decoder { src, format, width, height, rows: 0, raw, row: vec![BLACK; width as usize] })
+    }
+
+    /// Everything after "BM" up to the pixels
+    fn bmp_header(src: &mut S) -> Result<(Format, u32, u32), &'static str> {
+        let mut file = [0u8; 12];
+        src.read_exact(&mut file)?;
+        let offset = le32(&file[8..]) as usize;
+        let mut info = [0u8; 40];
+        src.read_exact(&mut info[..4])?;
+        let info_size = le32(&info) as usize;
+        if info_size < info.len() {
+            return Err("unsupported BMP header");
+        }
+        src.read_exact(&mut info[4..])?;
+        src.skip(info_size - info.len())?;
+
+        let width = le32(&info[4..]) as i32;
+        let height = le32(&info[8..]) as i32;
+        let bits = le16(&info[14..]);
+        if le32(&info[16..]) != 0 {
+            return Err("compressed BMP not supported");
+        }
+        if !matches!(bits, 1 | 4 | 8 | 24 | 32) || width <= 0 || height == 0 || height == i32::MIN {
+            return Err("unsupported BMP format");
+        }
+        let mut palette = Vec::new();
+        if bits <= 8 {
+            let used = le32(&info[32..]) as usize;
+            let count = if used == 0 || used > 1 << bits { 1 << bits } else { used };
+            for _ in 0..count {
+                let mut entry = [0u8; 4];
+                src.read_exact(&mut entry)?;
+                palette.push(rgb(entry[2], entry[1], entry[0]));
+            }
+        }
+        let read = 2 + file.len() + info_size + palette.len() * 4;
+        if offset < read {
+            return Err("bad BMP pixel offset");
+        }
+        src.skip(offset - read)?;
+        let stride = (width as usize * bits as usize).div_ceil(32) * 4;
+        Ok((Format::Bmp { bits, top_down: height < 0, palette, stride }, width as u32, height.unsigned_abs()))
+    }
+
+    /// The next row as it appears in the file, with its y from the top
+    fn next_row(&mut self) -> Result<Option<(u32, &[Color])>, &'static str> {
+        if self.rows == self.height {
+            return Ok(None);
+        }
+        let y = match &self.format {
+            Format::Bmp { bits, top_down, palette, .. } => {
+                self.src.read_exact(&mut self.raw)?;
+                for (x, pixel) in self.row.iter_mut().enumerate() {
+                    *pixel = match bits {
+                        32 | 24 => {
+                            let at = x * *bits as usize / 8;
+                            rgb(self.raw[at + 2], self.raw[at + 1], self.raw[at])
+                        }
+                        _ => {
+                            let bit = x * *bits as usize;
+                            let byte = self.raw[bit / 8];
+                            let index = (byte >> (8 - *bits as usize - bit % 8)) & ((1 << *bits) - 1) as u8;
+                            palette.get(index as usize).copied().unwrap_or(BLACK)
+                        }
+                    };
+                }
+                if *top_down { self.rows } else { self.height - 1 - self.rows }
+            }
+            Format::Ppm { plain, maxval } => {
+                let (plain, maxval) = (*plain, *maxval as u32);
+                let scale = |v: u32| (v.min(maxval) * 255 / maxval) as u8;
+                if !plain {
+                    self.src.read_exact(&mut self.raw)?;
+                }
+                let wide = maxval > 255;
+                for (x, pixel) in self.row.iter_mut().enumerate() {
+                    let mut sample = [0u32; 3];
+                    for (i, s) in sample.iter_mut().enumerate() {
+                        *s = if plain {
+                            ppm_number(&mut self.src)?
+                        } else if wide {
+                            let at = (x * 3 + i) * 2;
+                            (self.raw[at] as u32) << 8 | self.raw[at + 1] as u32
+                        } else {
+                            self.raw[x * 3 + i] as u32
+                        };
+                    }
+                    *pixel = rgb(scale(sample[0]), scale(sample[1]), scale(sample[2]));
+                }
+                self.rows
+            }
+        };
+        self.rows += 1;
+        Ok(Some((y, &self.row)))
+    }
+}
+
+/// A decimal number in a PPM header or plain raster, after any
+/// whitespace and comments. The byte ending it is consumed, which for
+/// the maximum value is the single byte before a binary raster.
+fn ppm_number<S: Source>(src: &mut S) -> Result<u32, &'static str> {
+    let mut b = src.byte()?;
+    loop {
+        match b {
+            b'#' => {
+                while b != b'\n' {
+                    b = src.byte()?;
+                }
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => b = src.byte()?,
+            _ => break,
+        }
+    }
+    let mut n: u32 = 0;
+    let mut digits = String::new();
+    while b.is_ascii_digit() {
+        digits.push(b as char);
+        n = n.checked_mul(10).and_then(|n| n.checked_add((b - b'0') as u32)).ok_or("bad PPM number")?;
+        b = match src.byte() {
+            Ok(b) => b,
+            // A plain raster may end right after its last digit
+            Err(_) => break,
+        };
+    }
+    if digits.is_empty() {
+        return Err("bad PPM header");
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Source for &[u8] {
+        fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), &'static str> {
+            if self.len() < buf.len() {
+                return Err("file is truncated");
+            }
+            buf.copy_from_slice(&self[..buf.len()]);
+            *self = &self[buf.len()..];
+            Ok(())
+        }
+    }
+
+    fn rows(data: &[u8]) -> Result<Vec<(u32, Vec<Color>)>, &'static str> {
+        let mut decoder = Decoder::new(data)?;
+        let mut rows = Vec::new();
+        while let Some((y, row)) = decoder.next_row()? {
+            rows.push((y, row.to_vec()));
+        }
+        Ok(rows)
+    }
+
+    #[test_case]
+    fn test_ppm() {
+        let red = rgb(0xFF, 0, 0);
+        let binary = b"P6\n# two by one\n2 1\n255\n\xFF\x00\x00\x00\x00\xFF";
+        assert_eq!(rows(binary), Ok(vec![(0, vec![red, rgb(0, 0, 0xFF)])]));
+        let plain = b"P3 1 2 15\n15 0 0\n0 15 0";
+        assert_eq!(rows(plain), Ok(vec![(0, vec![red]), (1, vec![rgb(0, 0xFF, 0)])]));
+        assert_eq!(rows(b"P6 2 1 255\n\xFF").err(), Some("file is truncated"));
+    }
+
+    #[test_case]
+    fn test_bmp() {
+        // 2x2 at 24 bits, bottom-up, rows padded to 8 bytes
+        let mut bmp = vec![b'B', b'M'];
+        bmp.extend_from_slice(&[0; 8]);
+        bmp.extend_from_slice(&54u32.to_le_bytes());
+        let mut info = [0u8; 40];
+        info[..4].copy_from_slice(&40u32.to_le_bytes());
+        info[4..8].copy_from_slice(&2u32.to_le_bytes());
+        info[8..12].copy_from_slice(&2u32.to_le_bytes());
+        info[14] = 24;
+        bmp.extend_from_slice(&info);
+        bmp.extend_from_slice(&[0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0]);
+        bmp.extend_from_slice(&[0xFF, 0, 0, 0, 0xFF, 0, 0, 0]);
+        let rows = rows(&bmp).unwrap();
+        assert_eq!(rows[0], (1, vec![rgb(0xFF, 0, 0), WHITE]));
+        assert_eq!(rows[1], (0, vec![rgb(0, 0, 0xFF), rgb(0, 0xFF, 0)]));
+    }
+
+    #[test_case]
+    fn test_scaling() {
+        assert_eq!(fit(200, 100, Rect::new(0, 0, 100, 100)), Rect::new(0, 25, 100, 50));
+        // Shrinking 4 rows to 2 keeps rows 0 and 2; growing 2 to 4 doubles
+        assert_eq!([0, 1, 2, 3].map(|y| dest_rows(y, 4, 2)), [0..1, 1..1, 1..2, 2..2]);
+        assert_eq!([0, 1].map(|y| dest_rows(y, 2, 4)), [0..2, 2..4]);
+    }
+}
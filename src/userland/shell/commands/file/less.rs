@@ -0,0 +1,262 @@
+// less - Page through a file
+//
+// Keys: q quits; j, k, Enter, Up and Down move a line; Space, b, PageDown
+// and PageUp a screen; d and u half a screen; g, G, Home and End go to the
+// ends; Left and Right scroll sideways (long lines are cut, not wrapped);
+// /text and ?text search forwards and backwards for plain text, n and N
+// repeat the search. When stdout is not the console the file is copied
+// through unpaged.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::FsResult;
+use crate::hal::drivers::{serial, vga};
+use crate::userland::shell::{self, args};
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::shell::console::{Console, Key};
+use crate::userland::shell::stream::FileReader;
+
+/// Screen size of the VGA console; the bottom row is the status line
+const ROWS: usize = 25;
+/// Printed columns; one short of the screen so no terminal wraps early
+const COLS: usize = 79;
+const TAB_WIDTH: usize = 8;
+
+pub struct Less;
+
+impl Command for Less {
+    fn name(&self) -> &'static str {
+        "less"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "FILE"
+    }
+
+    fn description(&self) -> &'static str {
+        "View a file a screen at a time, with scrolling and search"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("less: {}", e);
+                return self.usage();
+            }
+        };
+        let path = match opts.operands[..] {
+            [path] => path,
+            _ => return self.usage(),
+        };
+        let lines = match read_lines(path) {
+            Ok(lines) => lines,
+            Err(e) => {
+                crate::eprintln!("less: {}: {:?}", path, e);
+                return EXIT_FAILURE;
+            }
+        };
+
+        if !shell::isatty(1) {
+            for line in &lines {
+                writeln!(out, "{}", line).ok();
+            }
+            return EXIT_SUCCESS;
+        }
+
+        let mut pager = Pager::new(lines, ROWS - 1, path);
+        let mut console = Console::default();
+        loop {
+            clear_screen();
+            pager.render(out);
+            match console.read_key() {
+                Key::Char(c @ ('/' | '?')) => {
+                    serial::write_byte(c as u8);
+                    if let Some(pattern) = console.read_line() {
+                        pager.search(&pattern, c == '/');
+                    }
+                }
+                key => {
+                    if !pager.key(key) {
+                        break;
+                    }
+                }
+            }
+        }
+        clear_screen();
+        EXIT_SUCCESS
+    }
+}
+
+fn read_lines(path: &str) -> FsResult<Vec<String>> {
+    let mut file = FileReader::open(path)?;
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    while file.read_line(&mut line)? {
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        lines.push(String::from_utf8_lossy(&line).into_owned());
+        line.clear();
+    }
+    Ok(lines)
+}
+
+/// The serial terminal is cleared with an escape sequence; the VGA text
+/// console does not understand those and is cleared directly
+fn clear_screen() {
+    serial::write_string("\x1b[H\x1b[2J");
+    vga::clear_screen();
+}
+
+/// Columns `left..left + COLS` of `line` with tabs expanded
+fn visible(line: &str, left: usize) -> String {
+    let mut shown = String::new();
+    let mut col = 0;
+    for c in line.chars() {
+        let width = if c == '\t' { TAB_WIDTH - col % TAB_WIDTH } else { 1 };
+        for _ in 0..width {
+            if col >= left && col < left + COLS {
+                shown.push(if c == '\t' { ' ' } else { c });
+            }
+            col += 1;
+        }
+        if col >= left + COLS {
+            break;
+        }
+    }
+    shown
+}
+
+struct Pager<'a> {
+    lines: Vec<String>,
+    name: &'a str,
+    /// First line on screen
+    top: usize,
+    /// First column on screen
+    left: usize,
+    /// Lines of text per screen
+    rows: usize,
+    pattern: Option<String>,
+    /// Shown in place of the status line once
+    message: Option<&'static str>,
+}
+
+impl<'a> Pager<'a> {
+    fn new(lines: Vec<String>, rows: usize, name: &'a str) -> Self {
+        Pager { lines, name, top: 0, left: 0, rows, pattern: None, message: None }
+    }
+
+    fn max_top(&self) -> usize {
+        self.lines.len().saturating_sub(self.rows)
+    }
+
+    fn scroll(&mut self, delta: isize) {
+        let top = self.top as isize + delta;
+        self.top = (top.max(0) as usize).min(self.max_top());
+    }
+
+    /// Act on a key; false to quit
+    fn key(&mut self, key: Key) -> bool {
+        let page = self.rows as isize;
+        match key {
+            Key::Char('q' | 'Q') => return false,
+            Key::Down | Key::Enter | Key::Char('j' | 'e') => self.scroll(1),
+            Key::Up | Key::Char('k' | 'y') => self.scroll(-1),
+            // ^F and ^B as well, as in vi
+            Key::PageDown | Key::Char(' ' | 'f' | '\x06') => self.scroll(page),
+            Key::PageUp | Key::Char('b' | '\x02') => self.scroll(-page),
+            Key::Char('d') => self.scroll(page / 2),
+            Key::Char('u') => self.scroll(-page / 2),
+            Key::Home | Key::Char('g' | '<') => self.top = 0,
+            Key::End | Key::Char('G' | '>') => self.top = self.max_top(),
+            Key::Left => self.left = self.left.saturating_sub(COLS / 2),
+            Key::Right => self.left += COLS / 2,
+            Key::Char(c @ ('n' | 'N')) => match self.pattern.clone() {
+                Some(pattern) => self.find(&pattern, c == 'n'),
+                None => self.message = Some("No previous search pattern"),
+            },
+            _ => {}
+        }
+        true
+    }
+
+    /// Search for `pattern`, or the last one if it is empty
+    fn search(&mut self, pattern: &str, forward: bool) {
+        if !pattern.is_empty() {
+            self.pattern = Some(String::from(pattern));
+        }
+        match self.pattern.clone() {
+            Some(pattern) => self.find(&pattern, forward),
+            None => self.message = Some("No previous search pattern"),
+        }
+    }
+
+    /// Bring the next line containing `pattern` after (or before) the top
+    /// one to the top of the screen
+    fn find(&mut self, pattern: &str, forward: bool) {
+        self.message = None;
+        let found = if forward {
+            (self.top + 1..self.lines.len()).find(|&n| self.lines[n].contains(pattern))
+        } else {
+            (0..self.top).rev().find(|&n| self.lines[n].contains(pattern))
+        };
+        match found {
+            Some(n) => self.top = n.min(self.max_top()),
+            None => self.message = Some("Pattern not found"),
+        }
+    }
+
+    fn render(&mut self, out: &mut dyn Write) {
+        let mut screen = String::new();
+        for row in 0..self.rows {
+            match self.lines.get(self.top + row) {
+                Some(line) => screen.push_str(&visible(line, self.left)),
+                None => screen.push('~'),
+            }
+            screen.push('\n');
+        }
+        let status = match self.message.take() {
+            Some(message) => String::from(message),
+            None if self.top >= self.max_top() => String::from("(END)"),
+            None => {
+                let shown = (self.top + self.rows).min(self.lines.len());
+                format!("{} {}%", self.name, shown * 100 / self.lines.len().max(1))
+            }
+        };
+        screen.push_str(&status);
+        out.write_str(&screen).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_pager_scrolls_and_searches() {
+        let lines: Vec<String> = (1..=10).map(|n| format!("line {}", n)).collect();
+        let mut pager = Pager::new(lines, 4, "f");
+        pager.key(Key::PageDown);
+        pager.key(Key::PageDown);
+        assert_eq!(pager.top, 6);
+        pager.key(Key::Up);
+        assert_eq!(pager.top, 5);
+
+        pager.search("line 2", false);
+        assert_eq!(pager.top, 1);
+        pager.search("nowhere", true);
+        assert_eq!((pager.top, pager.message), (1, Some("Pattern not found")));
+        pager.search("line", true);
+        pager.key(Key::Char('n'));
+        assert_eq!(pager.top, 3);
+
+        let mut screen = String::new();
+        pager.render(&mut screen);
+        assert_eq!(screen, "line 4\nline 5\nline 6\nline 7\nf 70%");
+        assert_eq!(visible("\tab", 6), "  ab");
+        assert!(!pager.key(Key::Char('q')));
+    }
+}
@@ -1,31 +1,164 @@
 // ls - List directory contents
+//
+// -l lists one entry per line with its type and permissions, link count,
+// owner, group, size and modification time in the local time zone, and a
+// symlink with what it points to (`link -> target`). The time has the
+// year instead of the hour and minute if it is half a year or more away.
 
-pub fn run(args: &[&str]) {
-    let dir = if args.is_empty() { "." } else { args[0] };
-    
-    let vfs = crate::fs::vfs::VFS.lock();
-    match vfs.lookup_path(dir) {
-        Ok(node) => {
-            if node.is_dir() {
-                match node.readdir() {
-                    Ok(entries) => {
-                        for entry in entries {
-                            if entry.name == "." || entry.name == ".." {
-                                continue;
-                            }
-                            crate::serial_println!("{}", entry.name);
-                        }
-                    }
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::vfs::VfsNode;
+use crate::fs::{FileMode, FileType};
+use crate::kernel::time::{self, MONTHS};
+use crate::kernel::tz::Zone;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::users;
+
+pub struct Ls;
+
+impl Command for Ls {
+    fn name(&self) -> &'static str {
+        "ls"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-a] [-l] [PATH]"
+    }
+
+    fn description(&self) -> &'static str {
+        "List directory contents"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "al") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("ls: {}", e);
+                return self.usage();
+            }
+        };
+        let all = opts.has('a');
+        let dir = opts.operands.first().copied().unwrap_or(".");
+
+        // Owner names are read from /etc/passwd, so not under the VFS lock
+        let listed = {
+            let vfs = crate::fs::vfs::VFS.lock();
+            match vfs.lookup_path(dir) {
+                Ok(node) if node.is_dir() => match node.readdir() {
+                    Ok(entries) => entries
+                        .iter()
+                        .filter(|entry| all || !entry.name.starts_with('.'))
+                        .filter_map(|entry| vfs.get_node(entry.inode).ok().map(|node| Listed::new(&entry.name, node)))
+                        .collect::<Vec<_>>(),
                     Err(e) => {
-                        crate::serial_println!("Error reading directory: {:?}", e);
+                        crate::eprintln!("ls: error reading directory: {:?}", e);
+                        return EXIT_FAILURE;
                     }
+                },
+                Ok(node) => alloc::vec![Listed::new(dir, node)],
+                Err(e) => {
+                    crate::eprintln!("ls: cannot access '{}': {:?}", dir, e);
+                    return EXIT_FAILURE;
                 }
+            }
+        };
+
+        let (zone, now) = (crate::kernel::tz::current(), time::now());
+        for entry in &listed {
+            if opts.has('l') {
+                entry.write_long(out, &zone, now);
             } else {
-                crate::serial_println!("ls: cannot access '{}': Not a directory", dir);
+                writeln!(out, "{}", entry.name).ok();
             }
         }
-        Err(e) => {
-            crate::serial_println!("ls: cannot access '{}': {:?}", dir, e);
+        EXIT_SUCCESS
+    }
+}
+
+/// What ls shows of one entry
+struct Listed {
+    name: String,
+    mode: FileMode,
+    nlink: u64,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    mtime: u64,
+    target: Option<String>,
+}
+
+/// Half a Gregorian year
+const HALF_YEAR: u64 = 31_556_952 / 2;
+
+impl Listed {
+    fn new(name: &str, node: &VfsNode) -> Self {
+        let target = match &node.data {
+            crate::fs::vfs::VfsNodeData::Symlink(target) => Some(target.clone()),
+            _ => None,
+        };
+        Listed {
+            name: String::from(name),
+            mode: node.mode,
+            nlink: node.nlink,
+            uid: node.uid,
+            gid: node.gid,
+            size: node.size,
+            mtime: node.mtime,
+            target,
+        }
+    }
+
+    fn write_long(&self, out: &mut dyn Write, zone: &Zone, now: u64) {
+        let kind = match self.mode.file_type() {
+            FileType::Directory => 'd',
+            FileType::Symlink => 'l',
+            FileType::CharDevice => 'c',
+            FileType::BlockDevice => 'b',
+            FileType::Fifo => 'p',
+            FileType::Socket => 's',
+            FileType::Regular => '-',
+        };
+        let mut perms = String::new();
+        for shift in [6, 3, 0] {
+            let bits = self.mode.permissions() >> shift;
+            perms.push(if bits & 4 != 0 { 'r' } else { '-' });
+            perms.push(if bits & 2 != 0 { 'w' } else { '-' });
+            perms.push(if bits & 1 != 0 { 'x' } else { '-' });
+        }
+        let (user, group) = (users::user_name(self.uid), users::group_name(self.gid));
+        let t = zone.local(self.mtime).time;
+        let month = MONTHS[(t.month as usize).saturating_sub(1) % 12];
+        let when = if now.abs_diff(self.mtime) < HALF_YEAR {
+            alloc::format!("{} {:>2} {:02}:{:02}", month, t.day, t.hour, t.minute)
+        } else {
+            alloc::format!("{} {:>2}  {:>4}", month, t.day, t.year)
+        };
+        write!(out, "{}{} {:>2} {:<8} {:<8} {:>8} {} {}", kind, perms, self.nlink, user, group, self.size, when, self.name).ok();
+        match &self.target {
+            Some(target) => writeln!(out, " -> {}", target),
+            None => writeln!(out),
+        }
+        .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::vfs::VFS;
+
+    #[test_case]
+    fn test_long_listing_shows_link_targets() {
+        {
+            let mut vfs = VFS.lock();
+            vfs.create_directory("/tmp/ls", FileMode::new(0o755)).unwrap();
+            vfs.create_symlink("/tmp/ls/link", "/etc/passwd").unwrap();
         }
+        let mut out = String::new();
+        assert_eq!(Ls.run(&["-l", "/tmp/ls"], &mut out), EXIT_SUCCESS);
+        assert!(out.starts_with("lrwxrwxrwx  1 root     root"));
+        assert!(out.ends_with(" link -> /etc/passwd\n"));
     }
 }
@@ -1,21 +1,50 @@
 // mkdir - Create directory
 
-pub fn run(args: &[&str]) {
-    if args.is_empty() {
-        crate::serial_println!("Usage: mkdir <directory>");
-        return;
+use core::fmt::Write;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Mkdir;
+
+impl Command for Mkdir {
+    fn name(&self) -> &'static str {
+        "mkdir"
     }
-    
-    let mut vfs = crate::fs::vfs::VFS.lock();
-    
-    for dirname in args {
-        match vfs.create_directory(dirname, crate::fs::FileMode::new(0o755)) {
-            Ok(_) => {
-                crate::serial_println!("Created directory: {}", dirname);
-            }
+
+    fn synopsis(&self) -> &'static str {
+        "DIR..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Create directory"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
             Err(e) => {
-                crate::serial_println!("mkdir: error creating '{}': {:?}", dirname, e);
+                crate::eprintln!("mkdir: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.operands.is_empty() {
+            return self.usage();
+        }
+
+        let mut vfs = crate::fs::vfs::VFS.lock();
+        let mut status = EXIT_SUCCESS;
+
+        for dirname in &opts.operands {
+            match vfs.create_directory(dirname, crate::fs::FileMode::new(0o755)) {
+                Ok(_) => {
+                    writeln!(out, "Created directory: {}", dirname).ok();
+                }
+                Err(e) => {
+                    crate::eprintln!("mkdir: error creating '{}': {:?}", dirname, e);
+                    status = EXIT_FAILURE;
+                }
             }
         }
+        status
     }
 }
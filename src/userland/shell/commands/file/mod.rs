@@ -1,4 +1,6 @@
-// File operation commands: echo, cat, ls, touch, mkdir, rm, cd, chmod
+// File operation commands: echo, cat, ls, touch, mkdir, rm, cd, chmod, cmp, diff, tar,
+// gunzip, sha256sum, md5sum, less, imgview, play, fallocate, readlink,
+// printf
 
 pub mod echo;
 pub mod cat;
@@ -8,4 +10,16 @@ pub mod mkdir;
 pub mod rm;
 pub mod cd;
 pub mod chmod;
+pub mod cmp;
+pub mod diff;
+pub mod tar;
+pub mod gunzip;
+pub mod sum;
+pub mod less;
+#[cfg(feature = "framebuffer")]
+pub mod imgview;
+pub mod play;
+pub mod fallocate;
+pub mod readlink;
+pub mod printf;
 
@@ -0,0 +1,236 @@
+// play - Play WAV files through /dev/dsp
+//
+// Uncompressed PCM only: 8 or 16 bits, mono or stereo, any rate the audio
+// layer can resample. 8-bit samples are widened here since /dev/dsp takes
+// 16-bit ones. q on the serial console stops playback.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::vfs::api::{self as vfs_api, OpenFlags};
+use crate::hal::drivers::audio::{self, Format};
+use crate::hal::drivers::serial;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::shell::stream::FileReader;
+
+const DSP: &str = "/dev/dsp";
+const FORMAT_PCM: u16 = 1;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// Bytes read per write to /dev/dsp, about 23 ms of CD audio
+const CHUNK: usize = 4096;
+
+pub struct Play;
+
+impl Command for Play {
+    fn name(&self) -> &'static str {
+        "play"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "FILE..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Play PCM WAV files on the sound device (q stops)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("play: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.operands.is_empty() {
+            return self.usage();
+        }
+        let (device, _) = match audio::info() {
+            Some(info) => info,
+            None => {
+                crate::eprintln!("play: no audio device");
+                return EXIT_FAILURE;
+            }
+        };
+        let mut status = EXIT_SUCCESS;
+        for path in &opts.operands {
+            match play(path, device, out) {
+                Ok(true) => {}
+                Ok(false) => {
+                    writeln!(out, "play: stopped").ok();
+                    break;
+                }
+                Err(e) => {
+                    crate::eprintln!("play: {}: {}", path, e);
+                    status = EXIT_FAILURE;
+                }
+            }
+        }
+        status
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WavFormat {
+    channels: u16,
+    rate: u32,
+    bits: u16,
+}
+
+impl WavFormat {
+    /// The body of a "fmt " chunk
+    fn parse(data: &[u8]) -> Result<WavFormat, &'static str> {
+        if data.len() < 16 {
+            return Err("short fmt chunk");
+        }
+        let le16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+        let mut tag = le16(0);
+        // WAVE_FORMAT_EXTENSIBLE names the real format in its sub-format GUID
+        if tag == FORMAT_EXTENSIBLE && data.len() >= 26 {
+            tag = le16(24);
+        }
+        let format = WavFormat {
+            channels: le16(2),
+            rate: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+            bits: le16(14),
+        };
+        if tag != FORMAT_PCM {
+            return Err("not PCM");
+        }
+        if !matches!(format.bits, 8 | 16) || !(1..=2).contains(&format.channels) {
+            return Err("only 8 or 16-bit mono or stereo is supported");
+        }
+        Ok(format)
+    }
+
+    fn bytes_per_second(&self) -> u32 {
+        self.rate * self.channels as u32 * self.bits as u32 / 8
+    }
+}
+
+/// Samples as /dev/dsp takes them: 8-bit WAV is unsigned, 16-bit signed
+fn to_s16(bits: u16, data: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    if bits == 16 {
+        out.extend_from_slice(data);
+        return;
+    }
+    for &sample in data {
+        out.extend_from_slice(&(((sample as i16) - 0x80) << 8).to_le_bytes());
+    }
+}
+
+fn read_exact(file: &mut FileReader, buf: &mut [u8]) -> Result<(), &'static str> {
+    let mut done = 0;
+    while done < buf.len() {
+        match file.read(&mut buf[done..]) {
+            Ok(0) => return Err("file is truncated"),
+            Ok(n) => done += n,
+            Err(_) => return Err("read error"),
+        }
+    }
+    Ok(())
+}
+
+fn skip(file: &mut FileReader, n: usize) -> Result<(), &'static str> {
+    let mut buf = [0u8; 256];
+    let mut left = n;
+    while left > 0 {
+        let step = left.min(buf.len());
+        read_exact(file, &mut buf[..step])?;
+        left -= step;
+    }
+    Ok(())
+}
+
+/// Walk the RIFF chunks up to the samples, returning their format and
+/// length
+fn open_wav(file: &mut FileReader) -> Result<(WavFormat, u32), &'static str> {
+    let mut riff = [0u8; 12];
+    read_exact(file, &mut riff)?;
+    if &riff[..4] != b"RIFF" || &riff[8..] != b"WAVE" {
+        return Err("not a WAV file");
+    }
+    let mut format = None;
+    loop {
+        let mut header = [0u8; 8];
+        read_exact(file, &mut header)?;
+        let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        match &header[..4] {
+            b"fmt " => {
+                let mut body = vec![0u8; size.min(64) as usize];
+                read_exact(file, &mut body)?;
+                format = Some(WavFormat::parse(&body)?);
+                skip(file, (size as usize - body.len()) + (size & 1) as usize)?;
+            }
+            b"data" => return Ok((format.ok_or("no fmt chunk before the data")?, size)),
+            // Chunks are padded to an even length
+            _ => skip(file, size as usize + (size & 1) as usize)?,
+        }
+    }
+}
+
+/// Play one file; false if the user stopped it
+fn play(path: &str, device: &str, out: &mut dyn Write) -> Result<bool, &'static str> {
+    let mut file = FileReader::open(path).map_err(|_| "cannot open")?;
+    let (format, size) = open_wav(&mut file)?;
+    let tenths = size as u64 * 10 / format.bytes_per_second().max(1) as u64;
+    writeln!(
+        out,
+        "{}: {} Hz, {}-bit, {}, {}.{} s on {}",
+        path,
+        format.rate,
+        format.bits,
+        if format.channels == 2 { "stereo" } else { "mono" },
+        tenths / 10,
+        tenths % 10,
+        device
+    ).ok();
+
+    audio::set_format(Format { rate: format.rate, channels: format.channels })?;
+    let mut dsp = vfs_api::open(DSP, OpenFlags::O_WRONLY, 0).map_err(|_| "cannot open /dev/dsp")?;
+    let mut buf = vec![0u8; CHUNK];
+    let mut samples = Vec::with_capacity(2 * CHUNK);
+    let mut left = size as usize;
+    while left > 0 {
+        if serial::read_byte() == Some(b'q') {
+            audio::stop();
+            return Ok(false);
+        }
+        let n = match file.read(&mut buf[..left.min(CHUNK)]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(_) => return Err("read error"),
+        };
+        left -= n;
+        to_s16(format.bits, &buf[..n], &mut samples);
+        if vfs_api::write(&mut dsp, &samples).is_err() {
+            audio::stop();
+            return Err("write to /dev/dsp failed");
+        }
+    }
+    audio::drain();
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_wav_format() {
+        let mut fmt = [0u8; 16];
+        fmt[0] = 1;
+        fmt[2] = 2;
+        fmt[4..8].copy_from_slice(&22050u32.to_le_bytes());
+        fmt[14] = 16;
+        assert_eq!(WavFormat::parse(&fmt), Ok(WavFormat { channels: 2, rate: 22050, bits: 16 }));
+        fmt[14] = 24;
+        assert!(WavFormat::parse(&fmt).is_err());
+
+        let mut samples = Vec::new();
+        to_s16(8, &[0x80, 0xFF, 0x00], &mut samples);
+        assert_eq!(samples, [0x00, 0x00, 0x00, 0x7F, 0x00, 0x80]);
+    }
+}
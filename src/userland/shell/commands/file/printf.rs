@@ -0,0 +1,342 @@
+// printf - Print formatted text
+//
+// FORMAT is printed with its backslash escapes (\n \t \\ \a \b \f \r \v
+// \" and \NNN in octal) replaced, and each conversion replaced by the next
+// ARGUMENT:
+//
+//   %s %b        the argument; %b replaces its escapes too, \c in it
+//                stopping all output
+//   %c           its first character
+//   %d %i        a signed integer; %u %o %x %X unsigned, in decimal,
+//                octal and hex; 'c or "c is the code of c
+//   %%           a single %
+//
+// with optional flags `-` (left-justify), `0` (pad with zeros), `+`, space
+// and `#`, a field width and a precision, either of which may be `*` to
+// take it from the arguments. The format is used again while arguments
+// remain; missing ones are empty, or 0. An argument that is not a number
+// is an error, printed as 0.
+//
+//   printf '%-8s|%5.2s|\n' name value
+//   printf '%d items at %#x\n' 3 255
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Printf;
+
+impl Command for Printf {
+    fn name(&self) -> &'static str {
+        "printf"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "FORMAT [ARGUMENT...]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Print arguments by a format"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let Some((format, args)) = args.split_first() else {
+            return self.usage();
+        };
+        let mut printer = Printer { args, next: 0, failed: false };
+        let text = printer.print(format);
+        write!(out, "{}", text).ok();
+        if printer.failed { EXIT_FAILURE } else { EXIT_SUCCESS }
+    }
+}
+
+struct Printer<'a> {
+    args: &'a [&'a str],
+    next: usize,
+    failed: bool,
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// Replace the escape at the start of `chars`, after its backslash; None
+/// for `\c`, which ends the output, if `stop` allows it
+fn escape(chars: &mut core::iter::Peekable<core::str::Chars>, stop: bool, into: &mut String) -> Option<()> {
+    let c = match chars.next() {
+        None => '\\',
+        Some('n') => '\n',
+        Some('t') => '\t',
+        Some('r') => '\r',
+        Some('a') => '\x07',
+        Some('b') => '\x08',
+        Some('f') => '\x0c',
+        Some('v') => '\x0b',
+        Some('\\') => '\\',
+        Some('"') => '"',
+        Some('\'') => '\'',
+        Some('c') if stop => return None,
+        // `%b` takes \0NNN, the format \NNN; both up to three digits
+        Some(d @ '0'..='7') => {
+            let more = if stop && d == '0' { 3 } else { 2 };
+            let mut value = d as u32 - '0' as u32;
+            for _ in 0..more {
+                match chars.next_if(|c| ('0'..='7').contains(c)) {
+                    Some(d) => value = value * 8 + (d as u32 - '0' as u32),
+                    None => break,
+                }
+            }
+            char::from_u32(value & 0xff).unwrap_or('\0')
+        }
+        Some(other) => {
+            into.push('\\');
+            other
+        }
+    };
+    into.push(c);
+    Some(())
+}
+
+/// Pad `body` to the width, after any sign or prefix if padding with zeros
+fn pad(spec: &Spec, prefix: &str, body: &str, numeric: bool) -> String {
+    let len = prefix.chars().count() + body.chars().count();
+    let fill = spec.width.saturating_sub(len);
+    let mut s = String::with_capacity(len + fill);
+    if spec.left {
+        s.push_str(prefix);
+        s.push_str(body);
+        s.extend(core::iter::repeat_n(' ', fill));
+    } else if spec.zero && numeric && spec.precision.is_none() {
+        s.push_str(prefix);
+        s.extend(core::iter::repeat_n('0', fill));
+        s.push_str(body);
+    } else {
+        s.extend(core::iter::repeat_n(' ', fill));
+        s.push_str(prefix);
+        s.push_str(body);
+    }
+    s
+}
+
+impl Printer<'_> {
+    fn arg(&mut self) -> Option<&str> {
+        let arg = self.args.get(self.next).copied();
+        self.next += 1;
+        arg
+    }
+
+    /// The next argument as an integer
+    fn integer(&mut self) -> i128 {
+        let Some(arg) = self.arg() else {
+            return 0;
+        };
+        let mut chars = arg.chars();
+        if let Some('\'' | '"') = chars.next() {
+            return chars.next().map_or(0, |c| c as i128);
+        }
+        let text = arg.trim();
+        if text.is_empty() {
+            return 0;
+        }
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, text.strip_prefix('+').unwrap_or(text)),
+        };
+        let parsed = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            i128::from_str_radix(hex, 16)
+        } else if digits.len() > 1 && digits.starts_with('0') {
+            i128::from_str_radix(&digits[1..], 8)
+        } else {
+            digits.parse::<i128>()
+        };
+        match parsed {
+            Ok(n) if !digits.starts_with(['+', '-']) => if negative { -n } else { n },
+            _ => {
+                crate::eprintln!("printf: {}: invalid number", arg);
+                self.failed = true;
+                0
+            }
+        }
+    }
+
+    /// A width or precision given as `*`
+    fn star(&mut self) -> usize {
+        self.integer().clamp(0, 4096) as usize
+    }
+
+    /// The output for `format` and all the arguments
+    fn print(&mut self, format: &str) -> String {
+        let mut out = String::new();
+        loop {
+            let used = self.next;
+            if self.once(format, &mut out).is_none() {
+                break;
+            }
+            // Again only if this pass took arguments and some are left
+            if self.next == used || self.next >= self.args.len() {
+                break;
+            }
+        }
+        out
+    }
+
+    /// One pass over `format`; None if `\c` stopped the output
+    fn once(&mut self, format: &str, out: &mut String) -> Option<()> {
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => escape(&mut chars, false, out)?,
+                '%' => self.conversion(&mut chars, out)?,
+                _ => out.push(c),
+            }
+        }
+        Some(())
+    }
+
+    fn conversion(&mut self, chars: &mut core::iter::Peekable<core::str::Chars>, out: &mut String) -> Option<()> {
+        let mut spec = Spec::default();
+        while let Some(flag) = chars.next_if(|c| "-0+ #".contains(*c)) {
+            match flag {
+                '-' => spec.left = true,
+                '0' => spec.zero = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                _ => spec.alternate = true,
+            }
+        }
+        if chars.next_if_eq(&'*').is_some() {
+            spec.width = self.star();
+        } else {
+            while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                spec.width = (spec.width * 10 + (d as usize - '0' as usize)).min(4096);
+            }
+        }
+        if chars.next_if_eq(&'.').is_some() {
+            let mut precision = 0;
+            if chars.next_if_eq(&'*').is_some() {
+                precision = self.star();
+            } else {
+                while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                    precision = (precision * 10 + (d as usize - '0' as usize)).min(4096);
+                }
+            }
+            spec.precision = Some(precision);
+        }
+
+        let text = match chars.next() {
+            Some('%') => String::from("%"),
+            Some('s') => {
+                let arg = self.arg().unwrap_or("");
+                let arg: String = arg.chars().take(spec.precision.unwrap_or(usize::MAX)).collect();
+                pad(&spec, "", &arg, false)
+            }
+            Some('b') => {
+                let arg = self.arg().unwrap_or("");
+                let mut expanded = String::new();
+                let mut arg_chars = arg.chars().peekable();
+                let mut stopped = false;
+                while let Some(c) = arg_chars.next() {
+                    if c != '\\' {
+                        expanded.push(c);
+                    } else if escape(&mut arg_chars, true, &mut expanded).is_none() {
+                        stopped = true;
+                        break;
+                    }
+                }
+                let expanded: String = expanded.chars().take(spec.precision.unwrap_or(usize::MAX)).collect();
+                out.push_str(&pad(&spec, "", &expanded, false));
+                return if stopped { None } else { Some(()) };
+            }
+            Some('c') => {
+                let arg = self.arg().unwrap_or("");
+                let c: String = arg.chars().take(1).collect();
+                pad(&spec, "", &c, false)
+            }
+            Some('d' | 'i') => {
+                let n = self.integer();
+                let sign = if n < 0 { "-" } else if spec.plus { "+" } else if spec.space { " " } else { "" };
+                let digits = with_precision(&spec, format!("{}", n.unsigned_abs()));
+                pad(&spec, sign, &digits, true)
+            }
+            Some(conv @ ('u' | 'o' | 'x' | 'X')) => {
+                // Negative numbers wrap, as they do in C
+                let n = self.integer() as u64;
+                let (digits, prefix) = match conv {
+                    'u' => (format!("{}", n), ""),
+                    'o' => (format!("{:o}", n), if spec.alternate { "0" } else { "" }),
+                    'x' => (format!("{:x}", n), if spec.alternate && n != 0 { "0x" } else { "" }),
+                    _ => (format!("{:X}", n), if spec.alternate && n != 0 { "0X" } else { "" }),
+                };
+                let digits = with_precision(&spec, digits);
+                let prefix = if conv == 'o' && digits.starts_with('0') { "" } else { prefix };
+                pad(&spec, prefix, &digits, true)
+            }
+            Some(other) => {
+                crate::eprintln!("printf: %{}: invalid conversion", other);
+                self.failed = true;
+                return None;
+            }
+            None => {
+                crate::eprintln!("printf: missing conversion after %");
+                self.failed = true;
+                return None;
+            }
+        };
+        out.push_str(&text);
+        Some(())
+    }
+}
+
+/// At least as many digits as the precision asks for
+fn with_precision(spec: &Spec, digits: String) -> String {
+    match spec.precision {
+        Some(0) if digits == "0" => String::new(),
+        Some(p) if p > digits.len() => {
+            let mut padded: String = core::iter::repeat_n('0', p - digits.len()).collect();
+            padded.push_str(&digits);
+            padded
+        }
+        _ => digits,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printf(args: &[&str]) -> (i32, String) {
+        let mut out = String::new();
+        let status = Printf.run(args, &mut out);
+        (status, out)
+    }
+
+    #[test_case]
+    fn test_conversions() {
+        assert_eq!(printf(&["%s=%d\\n", "x", "42"]), (EXIT_SUCCESS, String::from("x=42\n")));
+        assert_eq!(printf(&["[%-5s|%5.2s]", "ab", "xyz"]).1, "[ab   |   xy]");
+        assert_eq!(printf(&["%05d %+d %x %#X %o", "-42", "7", "255", "255", "8"]).1, "-0042 +7 ff 0XFF 10");
+        assert_eq!(printf(&["%*d|%.3d", "4", "1", "5"]).1, "   1|005");
+        assert_eq!(printf(&["%c%c %d", "hello", "i", "'A"]).1, "hi 65");
+        assert_eq!(printf(&["100%%\\t\\101"]).1, "100%\tA");
+        assert_eq!(printf(&["%b|%s", "a\\tb", "a\\tb"]).1, "a\tb|a\\tb");
+        assert_eq!(printf(&["%b.", "x\\cy"]).1, "x");
+    }
+
+    #[test_case]
+    fn test_reuse_and_errors() {
+        assert_eq!(printf(&["%s,", "a", "b", "c"]).1, "a,b,c,");
+        assert_eq!(printf(&["%s-%s\\n", "a"]).1, "a-\n");
+        assert_eq!(printf(&["%d %d\\n", "0x10", "010"]).1, "16 8\n");
+        assert_eq!(printf(&["%d\\n", "12abc"]), (EXIT_FAILURE, String::from("0\n")));
+        assert_eq!(printf(&["%q"]).0, EXIT_FAILURE);
+        assert_eq!(printf(&[]).0, crate::userland::shell::command::EXIT_USAGE);
+    }
+}
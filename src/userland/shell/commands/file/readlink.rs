@@ -0,0 +1,57 @@
+// readlink - Print the target of a symbolic link
+//
+// With -f, prints the path FILE really names instead, every symlink along
+// it followed.
+
+use core::fmt::Write;
+use crate::fs::vfs::api as vfs_api;
+use crate::kernel::sys::errno::Errno;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Readlink;
+
+impl Command for Readlink {
+    fn name(&self) -> &'static str {
+        "readlink"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-f] FILE..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Print the target of a symbolic link"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "f") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("readlink: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.operands.is_empty() {
+            return self.usage();
+        }
+
+        let mut status = EXIT_SUCCESS;
+        for file in &opts.operands {
+            let target = match opts.has('f') {
+                true => crate::fs::vfs::VFS.lock().canonicalize(file),
+                false => vfs_api::readlink(file),
+            };
+            match target {
+                Ok(target) => {
+                    writeln!(out, "{}", target).ok();
+                }
+                Err(e) => {
+                    crate::eprintln!("readlink: {}: {}", file, Errno::from(e).description());
+                    status = EXIT_FAILURE;
+                }
+            }
+        }
+        status
+    }
+}
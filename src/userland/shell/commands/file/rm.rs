@@ -1,21 +1,53 @@
 // rm - Remove file
 
-pub fn run(args: &[&str]) {
-    if args.is_empty() {
-        crate::serial_println!("Usage: rm <file>");
-        return;
+use core::fmt::Write;
+use crate::fs::FsError;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Rm;
+
+impl Command for Rm {
+    fn name(&self) -> &'static str {
+        "rm"
     }
-    
-    let mut vfs = crate::fs::vfs::VFS.lock();
-    
-    for filename in args {
-        match vfs.remove_file(filename) {
-            Ok(_) => {
-                crate::serial_println!("Removed: {}", filename);
-            }
+
+    fn synopsis(&self) -> &'static str {
+        "[-f] FILE..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Remove file"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "f") {
+            Ok(opts) => opts,
             Err(e) => {
-                crate::serial_println!("rm: error removing '{}': {:?}", filename, e);
+                crate::eprintln!("rm: {}", e);
+                return self.usage();
+            }
+        };
+        let force = opts.has('f');
+        if opts.operands.is_empty() && !force {
+            return self.usage();
+        }
+
+        let mut vfs = crate::fs::vfs::VFS.lock();
+        let mut status = EXIT_SUCCESS;
+
+        for filename in &opts.operands {
+            match vfs.remove_file(filename) {
+                Ok(_) => {
+                    writeln!(out, "Removed: {}", filename).ok();
+                }
+                Err(FsError::NotFound) if force => {}
+                Err(e) => {
+                    crate::eprintln!("rm: error removing '{}': {:?}", filename, e);
+                    status = EXIT_FAILURE;
+                }
             }
         }
+        status
     }
 }
@@ -0,0 +1,216 @@
+// sha256sum, md5sum - Print or check file digests
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::FsResult;
+use crate::qsf::crypto::{self, Md5, Sha256};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::shell::stream::FileReader;
+
+#[derive(Clone, Copy)]
+enum Algorithm {
+    Sha256,
+    Md5,
+}
+
+impl Algorithm {
+    fn command(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256sum",
+            Algorithm::Md5 => "md5sum",
+        }
+    }
+
+    fn hex_len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 64,
+            Algorithm::Md5 => 32,
+        }
+    }
+
+    /// Digest of a file, read a block at a time
+    fn digest(self, path: &str) -> FsResult<Vec<u8>> {
+        let mut file = FileReader::open(path)?;
+        let mut buf = [0u8; 512];
+        match self {
+            Algorithm::Sha256 => {
+                let mut hasher = Sha256::new();
+                loop {
+                    match file.read(&mut buf)? {
+                        0 => return Ok(hasher.finalize().to_vec()),
+                        n => hasher.update(&buf[..n]),
+                    }
+                }
+            }
+            Algorithm::Md5 => {
+                let mut hasher = Md5::new();
+                loop {
+                    match file.read(&mut buf)? {
+                        0 => return Ok(hasher.finalize().to_vec()),
+                        n => hasher.update(&buf[..n]),
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct Sha256sum;
+pub struct Md5sum;
+
+impl Command for Sha256sum {
+    fn name(&self) -> &'static str {
+        "sha256sum"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-c] [FILE]..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Print SHA-256 digests, or check them against -c FILEs"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        run(Algorithm::Sha256, self, args, out)
+    }
+}
+
+impl Command for Md5sum {
+    fn name(&self) -> &'static str {
+        "md5sum"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-c] [FILE]..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Print MD5 digests, or check them against -c FILEs"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        run(Algorithm::Md5, self, args, out)
+    }
+}
+
+fn run(algorithm: Algorithm, command: &dyn Command, args: &[&str], out: &mut dyn Write) -> i32 {
+    let opts = match args::parse(args, "c") {
+        Ok(opts) => opts,
+        Err(e) => {
+            crate::eprintln!("{}: {}", algorithm.command(), e);
+            return command.usage();
+        }
+    };
+    // Standard input with no FILE, as with -
+    let operands = match opts.operands.is_empty() {
+        true => alloc::vec!["-"],
+        false => opts.operands.clone(),
+    };
+
+    let mut status = EXIT_SUCCESS;
+    for &path in &operands {
+        let ok = if opts.has('c') {
+            check(algorithm, path, out)
+        } else {
+            match algorithm.digest(path) {
+                Ok(digest) => {
+                    writeln!(out, "{}  {}", crypto::to_hex(&digest), path).ok();
+                    true
+                }
+                Err(e) => {
+                    crate::eprintln!("{}: {}: {:?}", algorithm.command(), path, e);
+                    false
+                }
+            }
+        };
+        if !ok {
+            status = EXIT_FAILURE;
+        }
+    }
+    status
+}
+
+/// Split a "DIGEST  NAME" line as written above (or "DIGEST *NAME", the
+/// binary-mode form other systems produce)
+fn parse_line(algorithm: Algorithm, line: &str) -> Option<(Vec<u8>, &str)> {
+    let hex = line.get(..algorithm.hex_len())?;
+    let name = line[hex.len()..].strip_prefix(' ')?;
+    let name = name.strip_prefix(' ').or_else(|| name.strip_prefix('*'))?;
+    if name.is_empty() {
+        return None;
+    }
+    Some((crypto::from_hex(hex)?, name))
+}
+
+/// Verify every file listed in the sums file `list`; false if any check
+/// failed or the list could not be read
+fn check(algorithm: Algorithm, list: &str, out: &mut dyn Write) -> bool {
+    let command = algorithm.command();
+    let mut file = match FileReader::open(list) {
+        Ok(file) => file,
+        Err(e) => {
+            crate::eprintln!("{}: {}: {:?}", command, list, e);
+            return false;
+        }
+    };
+
+    let (mut checked, mut mismatched, mut unreadable, mut malformed) = (0, 0, 0, 0);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match file.read_line(&mut line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                crate::eprintln!("{}: {}: {:?}", command, list, e);
+                return false;
+            }
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(&['\n', '\r'][..]);
+        if text.is_empty() {
+            continue;
+        }
+        let (expected, name) = match parse_line(algorithm, text) {
+            Some(parsed) => parsed,
+            None => {
+                malformed += 1;
+                continue;
+            }
+        };
+        checked += 1;
+        match algorithm.digest(name) {
+            Ok(digest) if crypto::digest_eq(&digest, &expected) => {
+                writeln!(out, "{}: OK", name).ok();
+            }
+            Ok(_) => {
+                writeln!(out, "{}: FAILED", name).ok();
+                mismatched += 1;
+            }
+            Err(e) => {
+                crate::eprintln!("{}: {}: {:?}", command, name, e);
+                writeln!(out, "{}: FAILED open or read", name).ok();
+                unreadable += 1;
+            }
+        }
+    }
+
+    if checked == 0 {
+        crate::eprintln!("{}: {}: no properly formatted checksum lines found", command, list);
+        return false;
+    }
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    if malformed > 0 {
+        crate::eprintln!("{}: WARNING: {} line{} improperly formatted", command, malformed, plural(malformed));
+    }
+    if unreadable > 0 {
+        crate::eprintln!("{}: WARNING: {} listed file{} could not be read", command, unreadable, plural(unreadable));
+    }
+    if mismatched > 0 {
+        crate::eprintln!("{}: WARNING: {} computed checksum{} did NOT match", command, mismatched, plural(mismatched));
+    }
+    mismatched == 0 && unreadable == 0
+}
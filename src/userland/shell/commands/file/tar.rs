@@ -0,0 +1,154 @@
+// tar - Create, extract or list tar archives
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::archive::{gzip, tar};
+use crate::fs::archive::tar::{Entry, EntryKind, Reader};
+use crate::fs::vfs::api::{self as vfs_api, write_file};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+/// Most a compressed archive may expand to; it is held in memory whole
+pub const UNPACK_LIMIT: usize = 4 << 20;
+
+pub struct Tar;
+
+impl Command for Tar {
+    fn name(&self) -> &'static str {
+        "tar"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "-c|-x|-t [-vz] -f ARCHIVE [-C DIR] [PATH...]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Create, extract (into DIR) or list ustar archives; -z reads gzip"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "cxtvzf:C:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("tar: {}", e);
+                return self.usage();
+            }
+        };
+        let archive = match opts.value('f') {
+            Some(archive) => archive,
+            None => {
+                crate::eprintln!("tar: an archive must be given with -f");
+                return self.usage();
+            }
+        };
+        let verbose = opts.has('v');
+
+        match (opts.has('c'), opts.has('x'), opts.has('t')) {
+            (true, false, false) => {
+                if opts.has('z') {
+                    crate::eprintln!("tar: creating compressed archives is not supported");
+                    return EXIT_FAILURE;
+                }
+                if opts.operands.is_empty() {
+                    crate::eprintln!("tar: refusing to create an empty archive");
+                    return self.usage();
+                }
+                let created = tar::create(&opts.operands, |path| {
+                    if verbose {
+                        writeln!(out, "{}", path).ok();
+                    }
+                });
+                match created.and_then(|data| write_file(archive, &data, 0o644)) {
+                    Ok(()) => EXIT_SUCCESS,
+                    Err(e) => {
+                        crate::eprintln!("tar: {}: {:?}", archive, e);
+                        EXIT_FAILURE
+                    }
+                }
+            }
+            (false, true, false) => {
+                let data = match load(archive, opts.has('z')) {
+                    Some(data) => data,
+                    None => return EXIT_FAILURE,
+                };
+                let dest = opts.value('C').unwrap_or(".");
+                let extracted = tar::extract(&data, dest, |entry| {
+                    if verbose {
+                        writeln!(out, "{}", entry.path).ok();
+                    }
+                });
+                match extracted {
+                    Ok(_) => EXIT_SUCCESS,
+                    Err(e) => {
+                        crate::eprintln!("tar: {}: {:?}", archive, e);
+                        EXIT_FAILURE
+                    }
+                }
+            }
+            (false, false, true) => {
+                let data = match load(archive, opts.has('z')) {
+                    Some(data) => data,
+                    None => return EXIT_FAILURE,
+                };
+                for entry in Reader::new(&data) {
+                    match entry {
+                        Ok(entry) if verbose => list_long(out, &entry),
+                        Ok(entry) => {
+                            writeln!(out, "{}", entry.path).ok();
+                        }
+                        Err(e) => {
+                            crate::eprintln!("tar: {}: {:?}", archive, e);
+                            return EXIT_FAILURE;
+                        }
+                    }
+                }
+                EXIT_SUCCESS
+            }
+            _ => {
+                crate::eprintln!("tar: exactly one of -c, -x and -t is required");
+                self.usage()
+            }
+        }
+    }
+}
+
+/// Read an archive, decompressing it if it is gzipped. Compression is
+/// recognised without -z; with it, an uncompressed archive is refused.
+fn load(path: &str, gzipped: bool) -> Option<Vec<u8>> {
+    let data = vfs_api::read_file(path).map_err(|e| crate::eprintln!("tar: {}: {:?}", path, e)).ok()?;
+    if !gzip::is_gzip(&data) {
+        if gzipped {
+            crate::eprintln!("tar: {}: not in gzip format", path);
+            return None;
+        }
+        return Some(data);
+    }
+    gzip::decompress(&data, UNPACK_LIMIT)
+        .map_err(|e| crate::eprintln!("tar: {}: {:?}", path, e))
+        .ok()
+}
+
+fn list_long(out: &mut dyn Write, entry: &Entry) {
+    let kind = match entry.kind {
+        EntryKind::Directory => 'd',
+        EntryKind::Symlink(_) => 'l',
+        EntryKind::HardLink(_) => 'h',
+        EntryKind::File => '-',
+        EntryKind::Other(_) => '?',
+    };
+    let mut perms = String::new();
+    for shift in [6, 3, 0] {
+        let bits = entry.mode >> shift;
+        perms.push(if bits & 4 != 0 { 'r' } else { '-' });
+        perms.push(if bits & 2 != 0 { 'w' } else { '-' });
+        perms.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    write!(out, "{}{} {}/{} {:>8} {}", kind, perms, entry.uid, entry.gid, entry.data.len(), entry.path).ok();
+    match &entry.kind {
+        EntryKind::Symlink(target) => writeln!(out, " -> {}", target),
+        EntryKind::HardLink(target) => writeln!(out, " link to {}", target),
+        _ => writeln!(out),
+    }
+    .ok();
+}
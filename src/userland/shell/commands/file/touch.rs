@@ -1,21 +1,50 @@
 // touch - Create empty file
 
-pub fn run(args: &[&str]) {
-    if args.is_empty() {
-        crate::serial_println!("Usage: touch <file>");
-        return;
+use core::fmt::Write;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Touch;
+
+impl Command for Touch {
+    fn name(&self) -> &'static str {
+        "touch"
     }
-    
-    let mut vfs = crate::fs::vfs::VFS.lock();
-    
-    for filename in args {
-        match vfs.create_file(filename, crate::fs::FileMode::new(0o644)) {
-            Ok(_) => {
-                crate::serial_println!("Created: {}", filename);
-            }
+
+    fn synopsis(&self) -> &'static str {
+        "FILE..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Create empty file"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
             Err(e) => {
-                crate::serial_println!("touch: error creating '{}': {:?}", filename, e);
+                crate::eprintln!("touch: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.operands.is_empty() {
+            return self.usage();
+        }
+
+        let mut vfs = crate::fs::vfs::VFS.lock();
+        let mut status = EXIT_SUCCESS;
+
+        for filename in &opts.operands {
+            match vfs.create_file(filename, crate::fs::FileMode::new(0o644)) {
+                Ok(_) => {
+                    writeln!(out, "Created: {}", filename).ok();
+                }
+                Err(e) => {
+                    crate::eprintln!("touch: error creating '{}': {:?}", filename, e);
+                    status = EXIT_FAILURE;
+                }
             }
         }
+        status
     }
 }
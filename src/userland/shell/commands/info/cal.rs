@@ -0,0 +1,95 @@
+// cal - Display a calendar
+
+use core::fmt::Write;
+use crate::kernel::time::{self, days_in_month, DateTime};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+pub struct Cal;
+
+impl Command for Cal {
+    fn name(&self) -> &'static str {
+        "cal"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[[MONTH] YEAR]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Display a calendar for a month or year"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("cal: {}", e);
+                return self.usage();
+            }
+        };
+        let today = DateTime::from_unix(time::now());
+
+        let parse_year = |s: &str| s.parse::<u32>().ok().filter(|y| (1970..=9999).contains(y));
+        let parse_month = |s: &str| s.parse::<u8>().ok().filter(|m| (1..=12).contains(m));
+        let (month, year) = match opts.operands[..] {
+            [] => (Some(today.month), Some(today.year)),
+            [year] => (None, parse_year(year)),
+            [month, year] => (parse_month(month), parse_year(year)),
+            _ => return self.usage(),
+        };
+        let year = match year {
+            Some(year) => year,
+            None => {
+                crate::eprintln!("cal: year must be between 1970 and 9999");
+                return EXIT_FAILURE;
+            }
+        };
+
+        match (month, opts.operands.len()) {
+            (Some(month), _) => print_month(out, year, month, today),
+            (None, 1) => {
+                writeln!(out, "{:^20}", year).ok();
+                for month in 1..=12 {
+                    writeln!(out).ok();
+                    print_month(out, year, month, today);
+                }
+            }
+            (None, _) => {
+                crate::eprintln!("cal: month must be between 1 and 12");
+                return EXIT_FAILURE;
+            }
+        }
+        EXIT_SUCCESS
+    }
+}
+
+/// Print one month; `today` is marked with a `*` after the day
+fn print_month(out: &mut dyn Write, year: u32, month: u8, today: DateTime) {
+    let title = alloc::format!("{} {}", MONTH_NAMES[month as usize - 1], year);
+    writeln!(out, "{:^20}", title).ok();
+    writeln!(out, "Su Mo Tu We Th Fr Sa").ok();
+
+    let first = DateTime { year, month, day: 1, hour: 0, minute: 0, second: 0 };
+    let mut column = first.weekday();
+    for _ in 0..column {
+        write!(out, "   ").ok();
+    }
+    for day in 1..=days_in_month(year, month) {
+        let is_today = today.year == year && today.month == month && today.day == day;
+        write!(out, "{:>2}{}", day, if is_today { "*" } else { " " }).ok();
+        column += 1;
+        if column == 7 {
+            writeln!(out).ok();
+            column = 0;
+        }
+    }
+    if column != 0 {
+        writeln!(out).ok();
+    }
+}
@@ -0,0 +1,136 @@
+// date - Print or set the system date and time
+//
+// Times are shown and read in the local time zone (see kernel::tz), or in
+// UTC with -u; `@SECS` is always seconds since the epoch.
+
+use core::fmt::Write;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::kernel::time::{self, DateTime, MONTHS, WEEKDAYS};
+use crate::kernel::tz::{self, Zone};
+use crate::qsf::Capability;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Date;
+
+impl Command for Date {
+    fn name(&self) -> &'static str {
+        "date"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-u] [-s 'YYYY-MM-DD HH:MM[:SS]'|@SECS] [+FORMAT]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Print or set the system date and time"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "us:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("date: {}", e);
+                return self.usage();
+            }
+        };
+        let format = match opts.operands[..] {
+            [] => None,
+            [format] if format.starts_with('+') => Some(&format[1..]),
+            _ => return self.usage(),
+        };
+
+        let zone = if opts.has('u') { Zone::utc() } else { tz::current() };
+
+        if let Some(spec) = opts.value('s') {
+            let secs = match parse_date(spec, &zone) {
+                Some(secs) => secs,
+                None => {
+                    crate::eprintln!("date: invalid date '{}'", spec);
+                    return EXIT_FAILURE;
+                }
+            };
+            let pid = crate::userland::shell::shell_pid();
+            let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+            if !crate::qsf::has_capability(uid, Capability::CapSysTime) {
+                crate::eprintln!("date: cannot set date: Operation not permitted");
+                return EXIT_FAILURE;
+            }
+            time::set(secs);
+        }
+
+        let now = time::now();
+        match format {
+            Some(format) => print_formatted(out, format, now, &zone),
+            None => {
+                writeln!(out, "{}", zone.local(now)).ok();
+            }
+        }
+        EXIT_SUCCESS
+    }
+}
+
+/// `@SECS`, or `YYYY-MM-DD HH:MM[:SS]` or `YYYY-MM-DD` in `zone`
+fn parse_date(spec: &str, zone: &Zone) -> Option<u64> {
+    if let Some(secs) = spec.strip_prefix('@') {
+        return secs.parse().ok();
+    }
+    let (date, clock) = match spec.split_once(|c| c == ' ' || c == 'T') {
+        Some((date, clock)) => (date, clock.trim()),
+        None => (spec, "00:00"),
+    };
+
+    let mut ymd = date.split('-').map(|part| part.parse::<u32>().ok());
+    let (year, month, day) = (ymd.next()??, ymd.next()??, ymd.next()??);
+    let mut hms = clock.split(':').map(|part| part.parse::<u8>().ok());
+    let (hour, minute) = (hms.next()??, hms.next()??);
+    let second = match hms.next() {
+        Some(second) => second?,
+        None => 0,
+    };
+    if ymd.next().is_some() || hms.next().is_some() || month > 12 || day > 31 {
+        return None;
+    }
+
+    let time = DateTime { year, month: month as u8, day: day as u8, hour, minute, second };
+    time.is_valid().then(|| zone.to_utc(time.to_unix()))
+}
+
+/// Expand a strftime-style format: %Y %m %d %e %H %M %S %j %a %b %s %F %T
+/// %Z %z %%
+fn print_formatted(out: &mut dyn Write, format: &str, secs: u64, zone: &Zone) {
+    let local = zone.local(secs);
+    let t = local.time;
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            write!(out, "{}", c).ok();
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => write!(out, "{}", t.year),
+            Some('m') => write!(out, "{:02}", t.month),
+            Some('d') => write!(out, "{:02}", t.day),
+            Some('e') => write!(out, "{:>2}", t.day),
+            Some('H') => write!(out, "{:02}", t.hour),
+            Some('M') => write!(out, "{:02}", t.minute),
+            Some('S') => write!(out, "{:02}", t.second),
+            Some('j') => {
+                let jan1 = DateTime { month: 1, day: 1, hour: 0, minute: 0, second: 0, ..t };
+                write!(out, "{:03}", (t.to_unix() - jan1.to_unix()) / 86400 + 1)
+            }
+            Some('a') => write!(out, "{}", WEEKDAYS[t.weekday()]),
+            Some('b') => write!(out, "{}", MONTHS[t.month as usize - 1]),
+            Some('s') => write!(out, "{}", secs),
+            Some('F') => write!(out, "{}-{:02}-{:02}", t.year, t.month, t.day),
+            Some('T') => write!(out, "{:02}:{:02}:{:02}", t.hour, t.minute, t.second),
+            Some('Z') => write!(out, "{}", local.abbr),
+            Some('z') => write!(out, "{}", tz::format_offset(local.offset)),
+            Some('%') => write!(out, "%"),
+            Some(other) => write!(out, "%{}", other),
+            None => write!(out, "%"),
+        }
+        .ok();
+    }
+    writeln!(out).ok();
+}
@@ -1,5 +1,21 @@
 // id - Print user ID information
 
-pub fn run() {
-    crate::serial_println!("uid=0(root) gid=0(root) groups=0(root)");
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Id;
+
+impl Command for Id {
+    fn name(&self) -> &'static str {
+        "id"
+    }
+
+    fn description(&self) -> &'static str {
+        "Print user ID information"
+    }
+
+    fn run(&self, _args: &[&str], out: &mut dyn Write) -> i32 {
+        writeln!(out, "uid=0(root) gid=0(root) groups=0(root)").ok();
+        EXIT_SUCCESS
+    }
 }
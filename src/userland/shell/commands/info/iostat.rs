@@ -0,0 +1,30 @@
+// iostat - Show block request queue statistics
+
+use core::fmt::Write;
+use crate::fs::iosched;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Iostat;
+
+impl Command for Iostat {
+    fn name(&self) -> &'static str {
+        "iostat"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show reads, writes, merges and queued blocks per disk"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        if !args.is_empty() {
+            return self.usage();
+        }
+        writeln!(out, "{:<8} {:>8} {:>8} {:>8} {:>10} {:>7} {:>7}",
+            "DEVICE", "READS", "WRITES", "MERGED", "DISPATCHED", "QUEUED", "HITS").ok();
+        for (name, stats) in iosched::all_stats() {
+            writeln!(out, "{:<8} {:>8} {:>8} {:>8} {:>10} {:>7} {:>7}", name, stats.reads, stats.writes,
+                stats.merges, stats.dispatches, stats.queued_blocks, stats.read_hits).ok();
+        }
+        EXIT_SUCCESS
+    }
+}
@@ -0,0 +1,119 @@
+// lsdev, lsblk - List registered character and block devices
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+use crate::fs::mount::{self, MountPoint};
+use crate::hal::drivers::device::{self, DeviceInfo, DeviceKind};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Lsdev;
+
+impl Command for Lsdev {
+    fn name(&self) -> &'static str {
+        "lsdev"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-b|-c]"
+    }
+
+    fn description(&self) -> &'static str {
+        "List devices with size and mount status (-b: block, -c: char)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "bc") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("lsdev: {}", e);
+                return self.usage();
+            }
+        };
+        if !opts.operands.is_empty() {
+            return self.usage();
+        }
+
+        let (block, chr) = match (opts.has('b'), opts.has('c')) {
+            (false, false) => (true, true),
+            (b, c) => (b, c),
+        };
+        list(out, |d| match d.kind {
+            DeviceKind::Block => block,
+            DeviceKind::Char => chr,
+        });
+        EXIT_SUCCESS
+    }
+}
+
+pub struct Lsblk;
+
+impl Command for Lsblk {
+    fn name(&self) -> &'static str {
+        "lsblk"
+    }
+
+    fn description(&self) -> &'static str {
+        "List block devices (same as lsdev -b)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        if !args.is_empty() {
+            return self.usage();
+        }
+        list(out, |d| d.kind == DeviceKind::Block);
+        EXIT_SUCCESS
+    }
+}
+
+fn list(out: &mut dyn Write, filter: impl Fn(&DeviceInfo) -> bool) {
+    let mounts = mount::get_mount_table();
+    writeln!(out, "{:<10} {:<5} {:>7} {:>7}  {:<8} MOUNTPOINT", "NAME", "TYPE", "MAJ:MIN", "SIZE", "DRIVER").ok();
+    for dev in device::list().iter().filter(|d| filter(d)) {
+        let kind = match dev.kind {
+            DeviceKind::Block => "block",
+            DeviceKind::Char => "char",
+        };
+        let size = dev.size.map_or_else(|| String::from("-"), human_size);
+        let mount_point = mounted_at(&mounts, &dev.name).unwrap_or("");
+        writeln!(
+            out,
+            "{:<10} {:<5} {:>7} {:>7}  {:<8} {}",
+            dev.name,
+            kind,
+            format!("{}:{}", dev.id.major, dev.id.minor),
+            size,
+            dev.driver,
+            mount_point
+        )
+        .ok();
+    }
+}
+
+/// Where the device `name` is mounted, matching "/dev/name" or a bare "name"
+fn mounted_at<'a>(mounts: &'a [MountPoint], name: &str) -> Option<&'a str> {
+    mounts
+        .iter()
+        .find(|m| m.device.strip_prefix("/dev/").unwrap_or(&m.device) == name)
+        .map(|m| m.path.as_str())
+}
+
+/// lsblk-style size: "512B", "64K", "7.5G"
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut unit = 0;
+    let mut scaled = bytes;
+    let mut rem = 0;
+    while scaled >= 1024 && unit + 1 < UNITS.len() {
+        rem = scaled % 1024;
+        scaled /= 1024;
+        unit += 1;
+    }
+    let tenths = rem * 10 / 1024;
+    if scaled < 10 && tenths != 0 {
+        format!("{}.{}{}", scaled, tenths, UNITS[unit])
+    } else {
+        format!("{}{}", scaled, UNITS[unit])
+    }
+}
@@ -0,0 +1,103 @@
+// lspci - List PCI devices
+
+use core::fmt::Write;
+use crate::hal::drivers::hotplug;
+use crate::hal::drivers::pci::{self, PciDevice};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Lspci;
+
+impl Command for Lspci {
+    fn name(&self) -> &'static str {
+        "lspci"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-r] [-v]"
+    }
+
+    fn description(&self) -> &'static str {
+        "List PCI devices (-r: rescan the bus first, -v: BARs and IRQ)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "rv") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("lspci: {}", e);
+                return self.usage();
+            }
+        };
+        if !opts.operands.is_empty() {
+            return self.usage();
+        }
+        if opts.has('r') {
+            let changed = pci::rescan();
+            hotplug::process();
+            writeln!(out, "{} device(s) added or removed", changed).ok();
+        }
+
+        for dev in pci::get_devices() {
+            let addr = dev.address;
+            write!(
+                out,
+                "{:02x}:{:02x}.{} {} [{:02x}{:02x}]: {:04x}:{:04x}",
+                addr.bus, addr.device, addr.function, dev.class_name(),
+                dev.class_code, dev.subclass, dev.vendor_id, dev.device_id
+            )
+            .ok();
+            if dev.revision_id != 0 {
+                write!(out, " (rev {:02x})", dev.revision_id).ok();
+            }
+            writeln!(out).ok();
+
+            if opts.has('v') {
+                print_verbose(out, &dev);
+                writeln!(out).ok();
+            }
+        }
+        EXIT_SUCCESS
+    }
+}
+
+fn print_verbose(out: &mut dyn Write, dev: &PciDevice) {
+    writeln!(out, "\tProg-if: {:02x}, header type {:02x}", dev.prog_if, dev.header_type & 0x7F).ok();
+    match dev.interrupt_pin {
+        0 => writeln!(out, "\tInterrupt: none"),
+        pin @ 1..=4 => writeln!(
+            out,
+            "\tInterrupt: pin {} routed to IRQ {}",
+            (b'A' + pin - 1) as char,
+            dev.interrupt_line
+        ),
+        pin => writeln!(out, "\tInterrupt: invalid pin {}", pin),
+    }
+    .ok();
+
+    // Bridges (header type 1) only have BAR0 and BAR1
+    let bars = if dev.header_type & 0x7F == 0 { 6 } else { 2 };
+    let mut i = 0;
+    while i < bars {
+        let bar = dev.bar[i];
+        let region = i;
+        i += 1;
+        if bar == 0 {
+            continue;
+        }
+        if pci::is_bar_io(bar) {
+            writeln!(out, "\tRegion {}: I/O ports at {:x}", region, pci::get_bar_address(bar)).ok();
+            continue;
+        }
+
+        let prefetchable = if bar & 0x08 != 0 { "prefetchable" } else { "non-prefetchable" };
+        if (bar >> 1) & 0x03 == 0x02 && i < bars {
+            // 64-bit BAR: the next one holds the high half
+            let address = pci::get_bar_address(bar) | (dev.bar[i] as u64) << 32;
+            i += 1;
+            writeln!(out, "\tRegion {}: Memory at {:x} (64-bit, {})", region, address, prefetchable).ok();
+        } else {
+            writeln!(out, "\tRegion {}: Memory at {:x} (32-bit, {})", region, pci::get_bar_address(bar), prefetchable).ok();
+        }
+    }
+}
@@ -1,7 +1,16 @@
-// Info commands: whoami, id, uname, pwd
+// Info commands: whoami, id, uname, pwd, uptime, date, cal, lspci, lsdev, lsblk,
+// iostat, smartctl, quota
 
 pub mod whoami;
 pub mod id;
 pub mod uname;
 pub mod pwd;
+pub mod uptime;
+pub mod date;
+pub mod cal;
+pub mod lspci;
+pub mod lsdev;
+pub mod iostat;
+pub mod smartctl;
+pub mod quota;
 pub use pwd::*;
@@ -1,7 +1,23 @@
 // pwd - Print working directory
 
-pub fn run() {
-    let vfs = crate::fs::vfs::VFS.lock();
-    let cwd = vfs.get_cwd();
-    crate::serial_println!("{}", cwd);
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Pwd;
+
+impl Command for Pwd {
+    fn name(&self) -> &'static str {
+        "pwd"
+    }
+
+    fn description(&self) -> &'static str {
+        "Print working directory"
+    }
+
+    fn run(&self, _args: &[&str], out: &mut dyn Write) -> i32 {
+        let vfs = crate::fs::vfs::VFS.lock();
+        let cwd = vfs.get_cwd();
+        writeln!(out, "{}", cwd).ok();
+        EXIT_SUCCESS
+    }
 }
@@ -0,0 +1,142 @@
+// quota - Show disk usage and limits
+//
+// Without arguments, the usage of the shell's user (or group, with -g)
+// against their limits, in 1 KiB blocks and files; `quota NAME` shows
+// another's and `-a` every user's or group's that has usage or limits,
+// as repquota does. A '*' marks a count over its soft limit, and the
+// grace column shows how long is left before that limit holds.
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+use crate::fs::quota::{Dquot, QuotaType};
+use crate::fs::vfs::VFS;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::users;
+
+pub struct Quota;
+
+impl Command for Quota {
+    fn name(&self) -> &'static str {
+        "quota"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-g] [-a | NAME]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show disk usage and quota limits"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "ga") {
+            Ok(opts) if opts.operands.len() <= 1 && (!opts.has('a') || opts.operands.is_empty()) => opts,
+            Ok(_) => return self.usage(),
+            Err(e) => {
+                crate::eprintln!("quota: {}", e);
+                return self.usage();
+            }
+        };
+        let kind = if opts.has('g') { QuotaType::Group } else { QuotaType::User };
+        let (label, name_of): (&str, fn(u32) -> String) = match kind {
+            QuotaType::User => ("user", users::user_name),
+            QuotaType::Group => ("group", users::group_name),
+        };
+
+        if opts.has('a') {
+            let (report, (block_grace, inode_grace), now) = {
+                let vfs = VFS.lock();
+                let quotas = vfs.quotas();
+                (quotas.report(kind), quotas.grace(), quotas.now())
+            };
+            writeln!(out, "Block grace time: {}; Inode grace time: {}", period(block_grace), period(inode_grace)).ok();
+            writeln!(out, "{:<12}{}", if kind == QuotaType::User { "User" } else { "Group" }, HEADER).ok();
+            for (id, dquot) in report {
+                writeln!(out, "{:<12}{}", name_of(id), row(&dquot, now)).ok();
+            }
+            return EXIT_SUCCESS;
+        }
+
+        let id = match opts.operands.first() {
+            Some(name) => {
+                let id = match kind {
+                    QuotaType::User => users::uid(name),
+                    QuotaType::Group => users::gid(name),
+                };
+                match id {
+                    Some(id) => id,
+                    None => {
+                        crate::eprintln!("quota: no such {}: {}", label, name);
+                        return EXIT_FAILURE;
+                    }
+                }
+            }
+            None => {
+                let pid = crate::userland::shell::shell_pid();
+                let scheduler = SCHEDULER.lock();
+                let task = scheduler.get_task(pid);
+                match kind {
+                    QuotaType::User => task.map_or(0, |task| task.euid),
+                    QuotaType::Group => task.map_or(0, |task| task.egid),
+                }
+            }
+        };
+        let (dquot, now) = {
+            let vfs = VFS.lock();
+            (vfs.quotas().get(kind, id), vfs.quotas().now())
+        };
+        writeln!(out, "Disk quotas for {} {} ({}id {}):", label, name_of(id), &label[..1], id).ok();
+        writeln!(out, "{:<12}{}", "", HEADER).ok();
+        writeln!(out, "{:<12}{}", "", row(&dquot, now)).ok();
+        EXIT_SUCCESS
+    }
+}
+
+const HEADER: &str = "    blocks     quota     limit   grace     files     quota     limit   grace";
+
+/// One line of usage, limits and grace: blocks, then files
+fn row(dquot: &Dquot, now: u64) -> String {
+    let limits = &dquot.limits;
+    let used = |count: u64, soft: u64| format!("{}{}", count, if soft != 0 && count > soft { "*" } else { "" });
+    format!(
+        "{:>10}{:>10}{:>10}{:>8}{:>10}{:>10}{:>10}{:>8}",
+        used(dquot.blocks, limits.block_soft),
+        limits.block_soft,
+        limits.block_hard,
+        grace(dquot.block_grace, now),
+        used(dquot.inodes, limits.inode_soft),
+        limits.inode_soft,
+        limits.inode_hard,
+        grace(dquot.inode_grace, now)
+    )
+}
+
+/// Time left of a running grace period: "6days", "23:59", or "none" once
+/// it is over
+fn grace(end: Option<u64>, now: u64) -> String {
+    match end {
+        None => String::new(),
+        Some(end) if end <= now => String::from("none"),
+        Some(end) => {
+            let left = end - now;
+            if left >= 24 * 60 * 60 {
+                format!("{}days", left / (24 * 60 * 60))
+            } else {
+                let minutes = left.div_ceil(60);
+                format!("{:02}:{:02}", minutes / 60, minutes % 60)
+            }
+        }
+    }
+}
+
+fn period(seconds: u64) -> String {
+    match seconds {
+        s if s % (24 * 60 * 60) == 0 && s != 0 => format!("{}days", s / (24 * 60 * 60)),
+        s if s % 3600 == 0 && s != 0 => format!("{}hours", s / 3600),
+        s if s % 60 == 0 && s != 0 => format!("{}minutes", s / 60),
+        s => format!("{}seconds", s),
+    }
+}
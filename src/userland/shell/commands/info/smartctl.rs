@@ -0,0 +1,112 @@
+// smartctl - Show disk identity and S.M.A.R.T. health
+
+use core::fmt::Write;
+use crate::hal::drivers::ata::{self, Drive};
+use crate::hal::drivers::smart::Health;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::Capability;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Smartctl;
+
+impl Command for Smartctl {
+    fn name(&self) -> &'static str {
+        "smartctl"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-i] [-H] [-A] DEVICE"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show a disk's identity (-i), health (-H) and SMART attributes (-A)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "iHA") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("smartctl: {}", e);
+                return self.usage();
+            }
+        };
+        let name = match opts.operands.as_slice() {
+            [name] => name.strip_prefix("/dev/").unwrap_or(*name),
+            _ => return self.usage(),
+        };
+        let drive = match Drive::from_device_name(name).or_else(|| Drive::from_name(name)) {
+            Some(drive) => drive,
+            None => {
+                crate::eprintln!("smartctl: {}: not an IDE disk (hda-hdd)", name);
+                return EXIT_FAILURE;
+            }
+        };
+        let identity = match ata::identities().into_iter().find(|(d, _)| *d == drive) {
+            Some((_, identity)) => identity,
+            None => {
+                crate::eprintln!("smartctl: {}: no such disk", name);
+                return EXIT_FAILURE;
+            }
+        };
+        let (info, health, attrs) = match (opts.has('i'), opts.has('H'), opts.has('A')) {
+            (false, false, false) => (true, true, true),
+            flags => flags,
+        };
+
+        if info {
+            writeln!(out, "Device:    {}", drive.device_name()).ok();
+            writeln!(out, "Model:     {}", identity.model).ok();
+            writeln!(out, "Serial:    {}", identity.serial).ok();
+            writeln!(out, "Firmware:  {}", identity.firmware).ok();
+            writeln!(out, "Capacity:  {} sectors, {} bytes", identity.sectors,
+                identity.sectors * ata::SECTOR_SIZE as u64).ok();
+            writeln!(out, "Features:  {}", identity.features().join(" ")).ok();
+        }
+        if !health && !attrs {
+            return EXIT_SUCCESS;
+        }
+        if !identity.smart_supported || !identity.smart_enabled {
+            writeln!(out, "SMART:     {}", if identity.smart_supported { "disabled" } else { "unsupported" }).ok();
+            return EXIT_FAILURE;
+        }
+
+        let uid = SCHEDULER.lock().get_task(crate::userland::shell::shell_pid()).map_or(0, |t| t.uid);
+        if !crate::qsf::has_capability(uid, Capability::CapSysRawio) {
+            crate::eprintln!("smartctl: {}: Operation not permitted", name);
+            return EXIT_FAILURE;
+        }
+        let (status, table) = match ata::smart(drive) {
+            Ok(result) => result,
+            Err(e) => {
+                crate::eprintln!("smartctl: {}: {}", name, e.message());
+                return EXIT_FAILURE;
+            }
+        };
+        if health {
+            let verdict = match status {
+                Health::Passed => "PASSED",
+                Health::Failing => "FAILING",
+                Health::Unknown => "UNKNOWN",
+            };
+            writeln!(out, "Health:    {}", verdict).ok();
+        }
+        if attrs {
+            let table = match table {
+                Some(table) => table,
+                None => {
+                    crate::eprintln!("smartctl: {}: bad checksum on SMART data", name);
+                    return EXIT_FAILURE;
+                }
+            };
+            writeln!(out, "{:>3} {:<24} {:<8} {:>5} {:>5} {:>6} {:>12}",
+                "ID", "ATTRIBUTE", "TYPE", "VALUE", "WORST", "THRESH", "RAW").ok();
+            for attr in &table {
+                writeln!(out, "{:>3} {:<24} {:<8} {:>5} {:>5} {:>6} {:>12}{}", attr.id, attr.name(),
+                    if attr.prefail() { "Pre-fail" } else { "Old_age" }, attr.current, attr.worst,
+                    attr.threshold, attr.raw, if attr.failing() { "  FAILING" } else { "" }).ok();
+            }
+        }
+        if status == Health::Failing { EXIT_FAILURE } else { EXIT_SUCCESS }
+    }
+}
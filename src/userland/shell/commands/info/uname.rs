@@ -1,5 +1,21 @@
 // uname - Print system information
 
-pub fn run() {
-    crate::serial_println!("Qunix 1.0 x86_64");
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Uname;
+
+impl Command for Uname {
+    fn name(&self) -> &'static str {
+        "uname"
+    }
+
+    fn description(&self) -> &'static str {
+        "Print system information"
+    }
+
+    fn run(&self, _args: &[&str], out: &mut dyn Write) -> i32 {
+        writeln!(out, "Qunix 1.0 x86_64").ok();
+        EXIT_SUCCESS
+    }
 }
@@ -0,0 +1,56 @@
+// uptime - Show how long the system has been running
+
+use core::fmt::Write;
+use crate::kernel::scheduler::{loadavg, TaskState, SCHEDULER};
+use crate::kernel::time;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Uptime;
+
+impl Command for Uptime {
+    fn name(&self) -> &'static str {
+        "uptime"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show time since boot, tasks, run queue depth and load average"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        if !args.is_empty() {
+            return self.usage();
+        }
+
+        let up = crate::hal::drivers::pit::get_uptime_seconds();
+        let now = crate::kernel::tz::local(time::now()).time;
+        write!(out, " {:02}:{:02}:{:02} up ", now.hour, now.minute, now.second).ok();
+        let days = up / 86400;
+        if days > 0 {
+            write!(out, "{} day{}, ", days, if days == 1 { "" } else { "s" }).ok();
+        }
+        write!(out, "{:>2}:{:02}", up / 3600 % 24, up / 60 % 60).ok();
+
+        let scheduler = SCHEDULER.lock();
+        let (mut running, mut sleeping, mut zombies) = (0, 0, 0);
+        for task in scheduler.get_tasks() {
+            match task.state {
+                TaskState::Ready | TaskState::Running => running += 1,
+                TaskState::Zombie => zombies += 1,
+                _ => sleeping += 1,
+            }
+        }
+        let queued = scheduler.ready_count();
+        writeln!(
+            out,
+            ",  {} tasks: {} runnable, {} sleeping, {} zombie,  run queue: {},  load average: {}",
+            scheduler.get_tasks().len(),
+            running,
+            sleeping,
+            zombies,
+            queued,
+            loadavg::describe(scheduler.load.averages())
+        )
+        .ok();
+        EXIT_SUCCESS
+    }
+}
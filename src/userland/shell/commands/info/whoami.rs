@@ -1,5 +1,21 @@
 // whoami - Print current user
 
-pub fn run() {
-    crate::serial_println!("root");
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Whoami;
+
+impl Command for Whoami {
+    fn name(&self) -> &'static str {
+        "whoami"
+    }
+
+    fn description(&self) -> &'static str {
+        "Print current user"
+    }
+
+    fn run(&self, _args: &[&str], out: &mut dyn Write) -> i32 {
+        writeln!(out, "root").ok();
+        EXIT_SUCCESS
+    }
 }
@@ -5,69 +5,139 @@ pub mod system;
 pub mod file;
 pub mod process;
 pub mod info;
+pub mod edit;
+#[cfg(feature = "net")]
+pub mod net;
 
-// Don't re-export everything due to naming conflicts
-// Instead, access commands directly or through the execute function
+use core::fmt::Write;
+use super::command::{Command, EXIT_NOT_FOUND};
 
-/// Execute a shell command with arguments
-pub fn execute(command: &str, args: &[&str]) {
-    use crate::serial_println;
-    
-    match command {
-        // System commands
-        "help" => {
-            serial_println!("Qunix Shell - Available Commands:");
-            serial_println!();
-            serial_println!("System Info:");
-            serial_println!("  help      - Show this help message");
-            serial_println!("  whoami    - Print current user");
-            serial_println!("  uname     - Print system information");
-            serial_println!("  id        - Print user ID information");
-            serial_println!("  pwd       - Print working directory");
-            serial_println!();
-            serial_println!("File Operations:");
-            serial_println!("  echo TEXT - Echo text to terminal");
-            serial_println!("  cat FILE  - Display file contents");
-            serial_println!("  ls [DIR]  - List directory contents");
-            serial_println!("  touch FILE- Create empty file");
-            serial_println!("  mkdir DIR - Create directory");
-            serial_println!("  rm FILE   - Remove file");
-            serial_println!("  cd DIR    - Change directory");
-            serial_println!("  chmod MODE FILE - Change file permissions");
-            serial_println!();
-            serial_println!("System:");
-            serial_println!("  clear     - Clear the screen");
-            serial_println!("  ps        - List running processes");
-            serial_println!("  fork      - Test fork syscall");
-            serial_println!("  exit      - Exit shell (disabled in init)");
-        },
-        "clear" => crate::hal::drivers::vga::clear_screen(),
-        "exit" => {
-            serial_println!("Cannot exit from init shell. Use 'reboot' to restart.");
-        },
-        
-        // Info commands
-        "whoami" => info::whoami::run(),
-        "id" => info::id::run(),
-        "uname" => info::uname::run(),
-        "pwd" => info::pwd::run(),
-        
-        // File commands
-        "echo" => file::echo::run(args),
-        "cat" => file::cat::run(args),
-        "ls" => file::ls::run(args),
-        "touch" => file::touch::run(args),
-        "mkdir" => file::mkdir::run(args),
-        "rm" => file::rm::run(args),
-        "cd" => file::cd::run(args),
-        "chmod" => file::chmod::run(args),
-        
-        // Process commands
-        "ps" => process::ps::run(),
-        "fork" => process::fork::run(),
-        
-        _ => {
-            serial_println!("command not found: {}", command);
-        },
+/// A group of commands as listed by `help`
+pub struct Section {
+    pub title: &'static str,
+    pub commands: &'static [&'static dyn Command],
+}
+
+/// Every built-in command, in `help` order
+pub static SECTIONS: &[Section] = &[
+    Section {
+        title: "System Info",
+        commands: &[
+            &system::help::Help,
+            &info::whoami::Whoami,
+            &info::uname::Uname,
+            &info::id::Id,
+            &info::pwd::Pwd,
+            &info::uptime::Uptime,
+            &info::date::Date,
+            &info::cal::Cal,
+            &info::lspci::Lspci,
+            &info::lsdev::Lsdev,
+            &info::lsdev::Lsblk,
+            &info::iostat::Iostat,
+            &info::smartctl::Smartctl,
+            &info::quota::Quota,
+        ],
+    },
+    Section {
+        title: "File Operations",
+        commands: &[
+            &file::echo::Echo,
+            &file::printf::Printf,
+            &file::cat::Cat,
+            &file::less::Less,
+            &file::ls::Ls,
+            &file::touch::Touch,
+            &file::mkdir::Mkdir,
+            &file::rm::Rm,
+            &file::cd::Cd,
+            &file::chmod::Chmod,
+            &file::cmp::Cmp,
+            &file::diff::Diff,
+            &file::tar::Tar,
+            &file::gunzip::Gunzip,
+            &file::sum::Sha256sum,
+            &file::sum::Md5sum,
+            #[cfg(feature = "framebuffer")]
+            &file::imgview::Imgview,
+            &file::play::Play,
+            &file::fallocate::Fallocate,
+            &file::readlink::Readlink,
+        ],
+    },
+    Section {
+        title: "System",
+        commands: &[
+            &system::clear::Clear,
+            &process::ps::Ps,
+            &process::top::Top,
+            &process::lsof::Lsof,
+            &process::fork::Fork,
+            &system::exit::Exit,
+            &system::reboot::Reboot,
+            &system::hibernate::Hibernate,
+            &system::kexec::Kexec,
+            &system::set::Set,
+            &system::which::Which,
+            &system::type_::Type,
+            &system::test::Test,
+            &system::test::Bracket,
+            &system::source::Dot,
+            &system::source::Source,
+            &system::read::Read,
+            &system::tzselect::Tzselect,
+            &system::theme::Theme,
+            &system::heapdbg::Heapdbg,
+            &system::memtop::Memtop,
+            &system::crashdump::Crashdump,
+            &system::screendump::Screendump,
+            &system::qbench::Qbench,
+            &system::trace::Trace,
+            &system::dmsetup::Dmsetup,
+            &system::mdadm::Mdadm,
+            &system::edquota::Edquota,
+            &system::mount::Mount,
+            &system::mount::Umount,
+            &system::overlay::Overlay,
+            &system::vfs_snapshot::VfsSnapshot,
+            &system::vfs_restore::VfsRestore,
+            &system::qpkg::Qpkg,
+            &system::sysupdate::Sysupdate,
+            #[cfg(feature = "framebuffer")]
+            &system::desktop::Desktop,
+        ],
+    },
+    #[cfg(feature = "net")]
+    Section {
+        title: "Network",
+        commands: &[&net::wget::Wget, &net::nslookup::Nslookup, &net::timedatectl::Timedatectl, &net::netmount::Netmount, &net::qfw::Qfw, &net::arp::Arp],
+    },
+    Section {
+        title: "Editing",
+        commands: &[&edit::qed::Qed],
+    },
+    Section {
+        title: "Security",
+        commands: &[&system::qsfctl::Qsfctl, &system::qcontainer::Qcontainer],
+    },
+];
+
+/// Look up a built-in command by name
+pub fn find(name: &str) -> Option<&'static dyn Command> {
+    SECTIONS
+        .iter()
+        .flat_map(|section| section.commands.iter())
+        .find(|command| command.name() == name)
+        .copied()
+}
+
+/// Execute a shell command with arguments, returning its exit status
+pub fn execute(command: &str, args: &[&str], out: &mut dyn Write) -> i32 {
+    match find(command) {
+        Some(cmd) => cmd.run(args, out),
+        None => {
+            crate::eprintln!("{}: command not found", command);
+            EXIT_NOT_FOUND
+        }
     }
 }
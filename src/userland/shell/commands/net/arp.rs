@@ -0,0 +1,87 @@
+// arp - Show or change the ARP cache
+//
+// Lists the addresses the stack has resolved or is resolving, with their
+// hardware address, state and age, and how many packets wait on those not
+// answered yet. -d ADDR forgets one entry and -f all of them; both need
+// CAP_NET_ADMIN.
+
+use core::fmt::Write;
+use core::net::Ipv4Addr;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::net::arp::{self, State};
+use crate::qsf::Capability;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Arp;
+
+impl Command for Arp {
+    fn name(&self) -> &'static str {
+        "arp"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-d ADDR | -f]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show the ARP cache, or delete an entry (-d) or all of them (-f)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "d:f") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("arp: {}", e);
+                return self.usage();
+            }
+        };
+        if !opts.operands.is_empty() || (opts.has('d') && opts.has('f')) {
+            return self.usage();
+        }
+        if !opts.has('d') && !opts.has('f') {
+            list(out);
+            return EXIT_SUCCESS;
+        }
+
+        let pid = crate::userland::shell::shell_pid();
+        let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+        if !crate::qsf::has_capability(uid, Capability::CapNetAdmin) {
+            crate::eprintln!("arp: Operation not permitted");
+            return EXIT_FAILURE;
+        }
+        match opts.value('d') {
+            Some(addr) => match addr.parse::<Ipv4Addr>() {
+                Ok(ip) if arp::remove(ip) => EXIT_SUCCESS,
+                Ok(_) => {
+                    crate::eprintln!("arp: {}: no entry", addr);
+                    EXIT_FAILURE
+                }
+                Err(_) => {
+                    crate::eprintln!("arp: {}: not an IPv4 address", addr);
+                    EXIT_FAILURE
+                }
+            },
+            None => {
+                arp::flush();
+                EXIT_SUCCESS
+            }
+        }
+    }
+}
+
+fn list(out: &mut dyn Write) {
+    writeln!(out, "{:<16} {:<18} {:<11} {:>6}", "Address", "HWaddress", "State", "Age").ok();
+    for entry in arp::entries() {
+        let mac = match entry.state {
+            State::Reachable(mac) => alloc::format!("{}", mac),
+            _ => alloc::string::String::from("-"),
+        };
+        let ip = alloc::format!("{}", entry.ip);
+        write!(out, "{:<16} {:<18} {:<11} {:>5}s", ip, mac, entry.state.name(), entry.age_ms / 1000).ok();
+        match entry.queued {
+            0 => writeln!(out).ok(),
+            n => writeln!(out, "  ({} queued)", n).ok(),
+        };
+    }
+}
@@ -0,0 +1,8 @@
+// Network commands: wget, nslookup, timedatectl, netmount, qfw, arp
+
+pub mod wget;
+pub mod nslookup;
+pub mod timedatectl;
+pub mod netmount;
+pub mod qfw;
+pub mod arp;
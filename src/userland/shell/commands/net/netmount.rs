@@ -0,0 +1,74 @@
+// netmount - Mount a TFTP server's files on /net
+//
+// With a server it fetches the server's index and mirrors the files it
+// lists under /net, read-only. -r reads the index again to pick up files
+// added on the host, -u unmounts, and no arguments say what is mounted.
+
+use core::fmt::Write;
+use crate::net::tftpfs::{self, MOUNT_POINT};
+use crate::qsf::Capability;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Netmount;
+
+impl Command for Netmount {
+    fn name(&self) -> &'static str {
+        "netmount"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-r | -u | SERVER[:PORT]]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Mount a TFTP server's files on /net, re-read its index (-r) or unmount (-u)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "ru") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("netmount: {}", e);
+                return self.usage();
+            }
+        };
+        let (remount, unmount) = (opts.has('r'), opts.has('u'));
+        let server = match (remount, unmount, &opts.operands[..]) {
+            (false, false, []) => {
+                match tftpfs::mounted() {
+                    Some((server, port)) => writeln!(out, "tftp://{}:{} on {} (ro)", server, port, MOUNT_POINT).ok(),
+                    None => writeln!(out, "nothing mounted on {}", MOUNT_POINT).ok(),
+                };
+                return EXIT_SUCCESS;
+            }
+            (false, false, [server]) => Some(*server),
+            (true, false, []) | (false, true, []) => None,
+            _ => return self.usage(),
+        };
+
+        let pid = crate::userland::shell::shell_pid();
+        let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+        if !crate::qsf::has_capability(uid, Capability::CapSysAdmin) {
+            crate::eprintln!("netmount: Operation not permitted");
+            return EXIT_FAILURE;
+        }
+        let result = match server {
+            Some(server) => tftpfs::parse_server(server).and_then(|(server, port)| tftpfs::mount(server, port)),
+            None if remount => tftpfs::remount(),
+            None => tftpfs::unmount().map(|()| 0),
+        };
+        match result {
+            Ok(_) if unmount => EXIT_SUCCESS,
+            Ok(files) => {
+                writeln!(out, "{} files on {}", files, MOUNT_POINT).ok();
+                EXIT_SUCCESS
+            }
+            Err(e) => {
+                crate::eprintln!("netmount: {}", e);
+                EXIT_FAILURE
+            }
+        }
+    }
+}
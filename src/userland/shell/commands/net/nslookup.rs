@@ -0,0 +1,94 @@
+// nslookup - Look up the addresses of a host name
+//
+// With one name it asks the resolver, so /etc/hosts and the cache count,
+// and says where the answer came from. With a server as well it sends the
+// query straight to that server. -c lists the cache and -f empties it.
+
+use core::fmt::Write;
+use core::net::Ipv4Addr;
+use crate::net::resolver::{self, Source};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Nslookup;
+
+impl Command for Nslookup {
+    fn name(&self) -> &'static str {
+        "nslookup"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "NAME [SERVER] | -c | -f"
+    }
+
+    fn description(&self) -> &'static str {
+        "Look up a host name, or list (-c) or flush (-f) the resolver cache"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "cf") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("nslookup: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.has('f') {
+            resolver::flush_cache();
+            return EXIT_SUCCESS;
+        }
+        if opts.has('c') {
+            for (name, addrs, ttl) in resolver::cache_entries() {
+                match addrs.is_empty() {
+                    true => writeln!(out, "{:<32} {:>5}s  NXDOMAIN", name, ttl).ok(),
+                    false => writeln!(out, "{:<32} {:>5}s  {}", name, ttl, join(&addrs)).ok(),
+                };
+            }
+            return EXIT_SUCCESS;
+        }
+
+        let (name, result) = match opts.operands[..] {
+            [name] => (name, resolver::lookup(name)),
+            [name, server] => match server.parse::<Ipv4Addr>() {
+                Ok(server) => {
+                    let addrs = resolver::query(server, name);
+                    (name, addrs.map(|addrs| resolver::Answer { addrs, source: Source::Server(server) }))
+                }
+                Err(_) => {
+                    crate::eprintln!("nslookup: {}: not an IPv4 address", server);
+                    return EXIT_FAILURE;
+                }
+            },
+            _ => return self.usage(),
+        };
+        match result {
+            Ok(answer) => {
+                match answer.source {
+                    Source::Server(server) => writeln!(out, "Server:  {}\n", server).ok(),
+                    Source::Hosts => writeln!(out, "From {}\n", resolver::HOSTS).ok(),
+                    Source::Cache => writeln!(out, "Non-authoritative answer (cached):").ok(),
+                    Source::Literal => None,
+                };
+                for addr in answer.addrs {
+                    writeln!(out, "Name:    {}\nAddress: {}", name, addr).ok();
+                }
+                EXIT_SUCCESS
+            }
+            Err(e) => {
+                crate::eprintln!("nslookup: {}: {}", name, e.message());
+                EXIT_FAILURE
+            }
+        }
+    }
+}
+
+fn join(addrs: &[Ipv4Addr]) -> alloc::string::String {
+    let mut text = alloc::string::String::new();
+    for (i, addr) in addrs.iter().enumerate() {
+        if i > 0 {
+            text.push(' ');
+        }
+        write!(text, "{}", addr).ok();
+    }
+    text
+}
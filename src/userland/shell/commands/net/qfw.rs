@@ -0,0 +1,178 @@
+// qfw - Manage the packet filter
+//
+// With no options, or -L, it lists both chains with their rules and
+// counters, followed by the QSF net: rules that packets no rule matches
+// fall through to. -A appends a rule, -I inserts one at the top or before
+// the rule numbered by the operand, -D deletes one, -P sets a chain's
+// default policy, -F flushes and -Z zeroes the counters. Changes need
+// CAP_NET_ADMIN and are refused under lockdown.
+
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::net::firewall::{self, Chain, Counter, Rule, Verdict};
+use crate::qsf::policies::Object;
+use crate::qsf::{Capability, QSF};
+use crate::userland::shell::args::{self, Opts};
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Qfw;
+
+impl Command for Qfw {
+    fn name(&self) -> &'static str {
+        "qfw"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-L] | -A|-I CHAIN [-p PROTO] [-a ADDR[/PREFIX]] [-d PORT[:LAST]] -j accept|drop [N] | -D CHAIN N | -P CHAIN accept|drop | -F|-Z [CHAIN]"
+    }
+
+    fn description(&self) -> &'static str {
+        "List or change the firewall's input and output chains"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "LA:I:D:P:FZp:a:d:j:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("qfw: {}", e);
+                return self.usage();
+            }
+        };
+        let commands: Vec<char> = "LAIDPFZ".chars().filter(|&c| opts.has(c)).collect();
+        let command = match commands[..] {
+            [] => 'L',
+            [command] => command,
+            _ => return self.usage(),
+        };
+        if command == 'L' {
+            if !opts.operands.is_empty() {
+                return self.usage();
+            }
+            list(out);
+            return EXIT_SUCCESS;
+        }
+
+        let pid = crate::userland::shell::shell_pid();
+        let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+        if !crate::qsf::has_capability(uid, Capability::CapNetAdmin) {
+            crate::eprintln!("qfw: Operation not permitted");
+            return EXIT_FAILURE;
+        }
+        let result = match command {
+            'A' | 'I' => {
+                let (chain, rule) = match (chain_of(opts.value(command)), parse_rule(&opts)) {
+                    (Ok(chain), Ok(rule)) => (chain, rule),
+                    (Err(e), _) | (_, Err(e)) => {
+                        crate::eprintln!("qfw: {}", e);
+                        return self.usage();
+                    }
+                };
+                let position = match (command, &opts.operands[..]) {
+                    ('A', []) => None,
+                    ('I', []) => Some(1),
+                    ('I', [n]) => match n.parse() {
+                        Ok(n) => Some(n),
+                        Err(_) => return self.usage(),
+                    },
+                    _ => return self.usage(),
+                };
+                firewall::insert(chain, position, rule)
+            }
+            'D' => match (chain_of(opts.value('D')), &opts.operands[..]) {
+                (Ok(chain), [n]) => match n.parse() {
+                    Ok(n) => firewall::delete(chain, n).map(|_| ()),
+                    Err(_) => return self.usage(),
+                },
+                _ => return self.usage(),
+            },
+            'P' => match (chain_of(opts.value('P')), &opts.operands[..]) {
+                (Ok(chain), [verdict]) => match Verdict::parse(verdict) {
+                    Some(verdict) => firewall::set_policy(chain, verdict),
+                    None => return self.usage(),
+                },
+                _ => return self.usage(),
+            },
+            _ => {
+                let chains = match &opts.operands[..] {
+                    [] => Chain::ALL.to_vec(),
+                    [name] => match Chain::parse(name) {
+                        Some(chain) => alloc::vec![chain],
+                        None => return self.usage(),
+                    },
+                    _ => return self.usage(),
+                };
+                chains.iter().try_for_each(|&chain| match command {
+                    'F' => firewall::flush(chain),
+                    _ => {
+                        firewall::zero(chain);
+                        Ok(())
+                    }
+                })
+            }
+        };
+        match result {
+            Ok(()) => EXIT_SUCCESS,
+            Err(e) => {
+                crate::eprintln!("qfw: {}", e);
+                EXIT_FAILURE
+            }
+        }
+    }
+}
+
+fn chain_of(name: Option<&str>) -> Result<Chain, &'static str> {
+    name.and_then(Chain::parse).ok_or("the chain is input or output")
+}
+
+/// The rule -p, -a, -d and -j describe
+fn parse_rule(opts: &Opts) -> Result<Rule, &'static str> {
+    let verdict = opts.value('j').ok_or("-j accept|drop is required")?;
+    let mut rule = Rule::new(Verdict::parse(verdict).ok_or("the verdict is accept or drop")?);
+    if let Some(protocol) = opts.value('p') {
+        rule.protocol = firewall::parse_protocol(protocol).ok_or("unknown protocol")?;
+    }
+    if let Some(addr) = opts.value('a') {
+        (rule.network, rule.prefix) = firewall::parse_network(addr).ok_or("bad address")?;
+    }
+    if let Some(ports) = opts.value('d') {
+        rule.ports = Some(firewall::parse_ports(ports).ok_or("bad port")?);
+    }
+    Ok(rule)
+}
+
+fn write_counter(out: &mut dyn Write, counter: Counter) {
+    write!(out, "{:>9} {:>11}", counter.packets, counter.bytes).ok();
+}
+
+fn list(out: &mut dyn Write) {
+    for chain in Chain::ALL {
+        let rules = firewall::chain(chain);
+        writeln!(out, "Chain {} (policy {})", chain.name(), rules.policy.name()).ok();
+        writeln!(out, "  num   packets       bytes  rule").ok();
+        for (i, rule) in rules.rules.iter().enumerate() {
+            write!(out, "{:>5} ", i + 1).ok();
+            write_counter(out, rule.counter);
+            writeln!(out, "  {}", rule).ok();
+        }
+        write!(out, "      ").ok();
+        write_counter(out, rules.qsf_counter);
+        writeln!(out, "  (QSF net: rules)").ok();
+        write!(out, "      ").ok();
+        write_counter(out, rules.policy_counter);
+        writeln!(out, "  (policy {})", rules.policy.name()).ok();
+    }
+
+    let qsf = QSF.lock();
+    let mut header = false;
+    for policy in qsf.policies().iter().filter(|policy| policy.has_network_rules()) {
+        for rule in policy.rules.iter().filter(|rule| matches!(rule.object, Object::Network(..))) {
+            if !header {
+                writeln!(out, "QSF net: rules (read = input, write = output)").ok();
+                header = true;
+            }
+            writeln!(out, "  {}: prio={} {:?} {} {} {}",
+                policy.name, rule.priority, rule.action, rule.subject, rule.object, rule.permissions).ok();
+        }
+    }
+}
@@ -0,0 +1,148 @@
+// timedatectl - Show and control the clock and its NTP synchronization
+//
+// With no arguments it shows the local, UTC and RTC times, the time zone
+// and whether the clock is synchronized; timesync-status shows the NTP
+// client's server, last offset and delay, and the frequency correction it
+// has arrived at.
+// The set-* subcommands and sync need CAP_SYS_TIME.
+
+use core::fmt::Write;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::kernel::time::{self, DateTime, WEEKDAYS};
+use crate::kernel::tz;
+use crate::net::ntp;
+use crate::qsf::Capability;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Timedatectl;
+
+impl Command for Timedatectl {
+    fn name(&self) -> &'static str {
+        "timedatectl"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[status|timesync-status|sync|set-ntp on|off|set-ntp-server NAME|set-rtc-sync on|off]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show the clock and NTP status, or configure time synchronization"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        match args {
+            [] | ["status"] => {
+                show_status(out);
+                return EXIT_SUCCESS;
+            }
+            ["timesync-status"] => {
+                show_timesync(out);
+                return EXIT_SUCCESS;
+            }
+            ["sync"] | ["set-ntp", _] | ["set-ntp-server", _] | ["set-rtc-sync", _] => {}
+            _ => return self.usage(),
+        }
+
+        let pid = crate::userland::shell::shell_pid();
+        let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+        if !crate::qsf::has_capability(uid, Capability::CapSysTime) {
+            crate::eprintln!("timedatectl: Operation not permitted");
+            return EXIT_FAILURE;
+        }
+        match args {
+            ["sync"] => match ntp::sync() {
+                Ok(sample) => {
+                    writeln!(out, "offset {:+}ms, delay {}ms", sample.offset_ms, sample.delay_ms).ok();
+                }
+                Err(e) => {
+                    crate::eprintln!("timedatectl: {}: {}", ntp::status().server, e.message());
+                    return EXIT_FAILURE;
+                }
+            },
+            ["set-ntp-server", server] => ntp::set_server(server),
+            [command, value] => {
+                let on = match *value {
+                    "on" | "yes" | "1" | "true" => true,
+                    "off" | "no" | "0" | "false" => false,
+                    _ => return self.usage(),
+                };
+                match *command {
+                    "set-ntp" => ntp::set_enabled(on),
+                    _ => ntp::set_rtc_sync(on),
+                }
+            }
+            _ => unreachable!(),
+        }
+        EXIT_SUCCESS
+    }
+}
+
+/// "Fri 2026-10-16 12:00:00"
+fn format_time(secs: u64) -> alloc::string::String {
+    let t = DateTime::from_unix(secs);
+    alloc::format!(
+        "{} {}-{:02}-{:02} {:02}:{:02}:{:02}",
+        WEEKDAYS[t.weekday()],
+        t.year,
+        t.month,
+        t.day,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+fn show_status(out: &mut dyn Write) {
+    let status = ntp::status();
+    let (now, zone) = (time::now(), tz::current());
+    let local = zone.local(now);
+    writeln!(out, "               Local time: {} {}", format_time(local.time.to_unix()), local.abbr).ok();
+    writeln!(out, "           Universal time: {} UTC", format_time(now)).ok();
+    match time::read_rtc() {
+        Some(rtc) => writeln!(out, "                 RTC time: {}", format_time(rtc)).ok(),
+        None => writeln!(out, "                 RTC time: n/a").ok(),
+    };
+    writeln!(out, "                Time zone: {} ({}, {})", zone.name, local.abbr, tz::format_offset(local.offset)).ok();
+    writeln!(out, "System clock synchronized: {}", yes_no(status.last_sync.is_some())).ok();
+    writeln!(out, "              NTP service: {}", if status.enabled { "active" } else { "inactive" }).ok();
+    writeln!(out, "                 RTC sync: {}", yes_no(status.rtc_sync)).ok();
+}
+
+fn show_timesync(out: &mut dyn Write) {
+    let status = ntp::status();
+    match status.addr {
+        Some(addr) => writeln!(out, "       Server: {} ({})", addr, status.server).ok(),
+        None => writeln!(out, "       Server: {}", status.server).ok(),
+    };
+    writeln!(out, "Poll interval: {}s", status.interval_s).ok();
+    if let Some(sample) = status.last {
+        let leap = match status.leap {
+            0 => "normal",
+            1 => "insert second",
+            _ => "delete second",
+        };
+        writeln!(out, "         Leap: {}", leap).ok();
+        writeln!(out, "      Stratum: {}", status.stratum).ok();
+        writeln!(out, "       Offset: {:+}ms", sample.offset_ms).ok();
+        writeln!(out, "        Delay: {}ms", sample.delay_ms).ok();
+    }
+    let ppb = time::frequency();
+    let sign = if ppb < 0 { '-' } else { '+' };
+    writeln!(out, "    Frequency: {}{}.{:03}ppm", sign, ppb.abs() / 1000, ppb.abs() % 1000).ok();
+    writeln!(out, "   Adjustment: {:+}ms pending", time::pending_adjustment()).ok();
+    if let Some(last_sync) = status.last_sync {
+        writeln!(out, "    Last sync: {} UTC", format_time(last_sync)).ok();
+    }
+    writeln!(out, "      Packets: {} ({} failed, {} steps)", status.packets, status.failures, status.steps).ok();
+    if let Some(error) = status.error {
+        writeln!(out, "   Last error: {}", error.message()).ok();
+    }
+}
@@ -0,0 +1,395 @@
+// wget - Download a URL over HTTP to a file
+//
+// HTTP/1.1 GET with a Host header and Connection: close. The body is
+// sized by Content-Length, sent chunked, or runs until the server closes
+// the connection, and is written to the file as it arrives. Redirects are
+// followed a few times. There is no TLS, so https URLs are refused.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::vfs::api::{self as vfs_api, FileDescriptor, OpenFlags};
+use crate::net::resolver;
+use crate::net::socket::{TcpStream, DEFAULT_TIMEOUT_MS};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+const MAX_REDIRECTS: usize = 5;
+/// Longest status line and headers accepted
+const MAX_HEAD: usize = 16 * 1024;
+const BUFFER: usize = 4096;
+
+pub struct Wget;
+
+impl Command for Wget {
+    fn name(&self) -> &'static str {
+        "wget"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-O FILE] URL"
+    }
+
+    fn description(&self) -> &'static str {
+        "Download a URL over HTTP to a file"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "O:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("wget: {}", e);
+                return self.usage();
+            }
+        };
+        let [url] = opts.operands[..] else {
+            return self.usage();
+        };
+        let mut url = match Url::parse(url) {
+            Ok(url) => url,
+            Err(e) => {
+                crate::eprintln!("wget: {}: {}", url, e);
+                return EXIT_FAILURE;
+            }
+        };
+        let path = opts.value('O').map_or_else(|| url.file_name().to_string(), |path| path.to_string());
+
+        for _ in 0..=MAX_REDIRECTS {
+            match fetch(&url, &path, out) {
+                Ok(Fetched::Saved(bytes)) => {
+                    writeln!(out, "saved {} bytes to {}", bytes, path).ok();
+                    return EXIT_SUCCESS;
+                }
+                Ok(Fetched::Redirect(location)) => {
+                    url = match url.join(&location) {
+                        Ok(next) => next,
+                        Err(e) => {
+                            crate::eprintln!("wget: redirect to {}: {}", location, e);
+                            return EXIT_FAILURE;
+                        }
+                    };
+                    writeln!(out, "redirected to {}", url).ok();
+                }
+                Err(e) => {
+                    crate::eprintln!("wget: {}", e);
+                    return EXIT_FAILURE;
+                }
+            }
+        }
+        crate::eprintln!("wget: too many redirects");
+        EXIT_FAILURE
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Url {
+    host: String,
+    port: u16,
+    /// From the first '/', query included
+    path: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Url, &'static str> {
+        let rest = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some((scheme, _)) if scheme.eq_ignore_ascii_case("https") => return Err("https is not supported"),
+            Some(_) => return Err("unsupported scheme"),
+            None => url,
+        };
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(at) => (&rest[..at], &rest[at..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| "bad port")?),
+            None => (authority, 80),
+        };
+        if host.is_empty() || host.contains('@') {
+            return Err("bad host");
+        }
+        let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+        Ok(Url { host: host.to_string(), port, path })
+    }
+
+    /// Where a Location header points, taken relative to this URL
+    fn join(&self, location: &str) -> Result<Url, &'static str> {
+        if location.contains("://") {
+            Url::parse(location)
+        } else if location.starts_with('/') {
+            Ok(Url { path: location.to_string(), ..self.clone() })
+        } else {
+            let dir = &self.path[..self.path.rfind('/').map_or(0, |at| at + 1)];
+            Ok(Url { path: format!("{}{}", dir, location), ..self.clone() })
+        }
+    }
+
+    /// The last path segment, or index.html for a directory
+    fn file_name(&self) -> &str {
+        let path = self.path.split(['?', '#']).next().unwrap_or("");
+        match path.rsplit('/').next() {
+            Some("") | None => "index.html",
+            Some(name) => name,
+        }
+    }
+}
+
+impl core::fmt::Display for Url {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.port {
+            80 => write!(f, "http://{}{}", self.host, self.path),
+            port => write!(f, "http://{}:{}{}", self.host, port, self.path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Head {
+    status: u16,
+    reason: String,
+    length: Option<u64>,
+    chunked: bool,
+    location: Option<String>,
+}
+
+impl Head {
+    /// The status line and headers, without the blank line ending them
+    fn parse(text: &str) -> Result<Head, &'static str> {
+        let mut lines = text.split("\r\n");
+        let status_line = lines.next().unwrap_or("");
+        let mut parts = status_line.splitn(3, ' ');
+        if !parts.next().is_some_and(|version| version.starts_with("HTTP/1.")) {
+            return Err("not an HTTP response");
+        }
+        let status = parts.next().and_then(|s| s.parse().ok()).ok_or("bad status line")?;
+        let mut head = Head {
+            status,
+            reason: parts.next().unwrap_or("").to_string(),
+            length: None,
+            chunked: false,
+            location: None,
+        };
+        for line in lines {
+            let Some((name, value)) = line.split_once(':') else { continue };
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                head.length = Some(value.parse().map_err(|_| "bad Content-Length")?);
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                head.chunked = value.rsplit(',').next().is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"));
+            } else if name.eq_ignore_ascii_case("location") {
+                head.location = Some(value.to_string());
+            }
+        }
+        Ok(head)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Chunk {
+    /// Reading the size line, hex digits so far
+    Size(u64),
+    /// Skipping an extension after the size
+    Extension(u64),
+    /// Bytes of chunk data left
+    Data(u64),
+    /// Expecting the line break after the data
+    DataEnd,
+    /// Reading trailer lines; whether the current one is empty so far
+    Trailer(bool),
+    Done,
+}
+
+/// Undoes chunked transfer coding as bytes arrive
+struct ChunkedDecoder {
+    state: Chunk,
+}
+
+impl ChunkedDecoder {
+    fn new() -> ChunkedDecoder {
+        ChunkedDecoder { state: Chunk::Size(0) }
+    }
+
+    fn is_done(&self) -> bool {
+        self.state == Chunk::Done
+    }
+
+    /// Append the data in `input` to `out`
+    fn feed(&mut self, mut input: &[u8], out: &mut Vec<u8>) -> Result<(), &'static str> {
+        while let Some(&byte) = input.first() {
+            if let Chunk::Data(left) = self.state {
+                let n = (left as usize).min(input.len());
+                out.extend_from_slice(&input[..n]);
+                input = &input[n..];
+                self.state = if left == n as u64 { Chunk::DataEnd } else { Chunk::Data(left - n as u64) };
+                continue;
+            }
+            input = &input[1..];
+            self.state = match (self.state, byte) {
+                (Chunk::Size(size), b'\n') | (Chunk::Extension(size), b'\n') => match size {
+                    0 => Chunk::Trailer(true),
+                    size => Chunk::Data(size),
+                },
+                (Chunk::Size(size), b) if b.is_ascii_hexdigit() => {
+                    let digit = (b as char).to_digit(16).unwrap_or(0) as u64;
+                    Chunk::Size(size.checked_mul(16).ok_or("chunk too large")? + digit)
+                }
+                (Chunk::Size(size), b';' | b' ' | b'\t' | b'\r') => Chunk::Extension(size),
+                (Chunk::Size(_), _) => return Err("bad chunk size"),
+                (state @ Chunk::Extension(_), _) => state,
+                (Chunk::DataEnd, b'\r') => Chunk::DataEnd,
+                (Chunk::DataEnd, b'\n') => Chunk::Size(0),
+                (Chunk::DataEnd, _) => return Err("chunk overruns its size"),
+                (Chunk::Trailer(true), b'\n') => Chunk::Done,
+                (Chunk::Trailer(_), b'\n') => Chunk::Trailer(true),
+                (Chunk::Trailer(empty), b'\r') => Chunk::Trailer(empty),
+                (Chunk::Trailer(_), _) => Chunk::Trailer(false),
+                (Chunk::Done, _) => Chunk::Done,
+                (Chunk::Data(_), _) => unreachable!(),
+            };
+        }
+        Ok(())
+    }
+}
+
+enum Fetched {
+    Saved(u64),
+    Redirect(String),
+}
+
+fn write_all(file: &mut FileDescriptor, mut data: &[u8]) -> Result<(), String> {
+    while !data.is_empty() {
+        match vfs_api::write(file, data) {
+            Ok(0) | Err(_) => return Err("write error".to_string()),
+            Ok(n) => data = &data[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Request `url`, saving a successful response's body to `path`
+fn fetch(url: &Url, path: &str, out: &mut dyn Write) -> Result<Fetched, String> {
+    let addr = resolver::resolve(&url.host).map_err(|e| format!("{}: {}", url.host, e.message()))?;
+    writeln!(out, "connecting to {} ({}:{})", url.host, addr, url.port).ok();
+    let mut stream = TcpStream::connect(addr, url.port, DEFAULT_TIMEOUT_MS).map_err(|e| e.message().to_string())?;
+    let host = match url.port {
+        80 => url.host.clone(),
+        port => format!("{}:{}", url.host, port),
+    };
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: qunix-wget\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        url.path, host
+    );
+    stream.write_all(request.as_bytes()).map_err(|e| e.message().to_string())?;
+
+    let mut buf = alloc::vec![0u8; BUFFER];
+    let mut received = Vec::new();
+    let end = loop {
+        if let Some(at) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+            break at;
+        }
+        if received.len() > MAX_HEAD {
+            return Err("response header too long".to_string());
+        }
+        match stream.read(&mut buf).map_err(|e| e.message().to_string())? {
+            0 => return Err("connection closed before the response".to_string()),
+            n => received.extend_from_slice(&buf[..n]),
+        }
+    };
+    let head = core::str::from_utf8(&received[..end]).map_err(|_| "bad response header".to_string())?;
+    let head = Head::parse(head)?;
+    writeln!(out, "HTTP {} {}", head.status, head.reason).ok();
+    if matches!(head.status, 301 | 302 | 303 | 307 | 308) {
+        return head.location.map(Fetched::Redirect).ok_or_else(|| "redirect without a Location".to_string());
+    }
+    if head.status != 200 {
+        return Err(format!("server returned {} {}", head.status, head.reason));
+    }
+
+    let flags = OpenFlags::O_WRONLY | OpenFlags::O_CREAT | OpenFlags::O_TRUNC;
+    let mut file = vfs_api::open(path, flags, 0o644).map_err(|e| format!("{}: {:?}", path, e))?;
+    let mut body = received.split_off(end + 4);
+    let mut chunked = head.chunked.then(ChunkedDecoder::new);
+    let mut decoded = Vec::new();
+    let mut saved = 0u64;
+    loop {
+        let data = match chunked.as_mut() {
+            Some(decoder) => {
+                decoded.clear();
+                decoder.feed(&body, &mut decoded)?;
+                &decoded[..]
+            }
+            None => {
+                let left = head.length.map_or(body.len(), |length| (length - saved) as usize);
+                &body[..body.len().min(left)]
+            }
+        };
+        write_all(&mut file, data)?;
+        saved += data.len() as u64;
+        let complete = match &chunked {
+            Some(decoder) => decoder.is_done(),
+            None => head.length.is_some_and(|length| saved >= length),
+        };
+        if complete {
+            return Ok(Fetched::Saved(saved));
+        }
+        let n = stream.read(&mut buf).map_err(|e| e.message().to_string())?;
+        if n == 0 {
+            // Only a body without a length may end with the connection
+            return match (head.length, &chunked) {
+                (None, None) => Ok(Fetched::Saved(saved)),
+                _ => Err(format!("connection closed after {} bytes", saved)),
+            };
+        }
+        body.clear();
+        body.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_url() {
+        let url = Url::parse("http://example.com:8080/a/b.txt?x=1").unwrap();
+        assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("example.com", 8080, "/a/b.txt?x=1"));
+        assert_eq!(url.file_name(), "b.txt");
+        let url = Url::parse("example.com").unwrap();
+        assert_eq!((url.port, url.path.as_str(), url.file_name()), (80, "/", "index.html"));
+        assert_eq!(Url::parse("https://example.com/"), Err("https is not supported"));
+        assert_eq!(Url::parse("http://:80/"), Err("bad host"));
+
+        let base = Url::parse("http://example.com/dir/page").unwrap();
+        assert_eq!(base.join("other").unwrap().path, "/dir/other");
+        assert_eq!(base.join("/top").unwrap().path, "/top");
+        assert_eq!(base.join("http://mirror.org/x").unwrap().host, "mirror.org");
+    }
+
+    #[test_case]
+    fn test_head() {
+        let head = Head::parse("HTTP/1.1 200 OK\r\nContent-Length: 12\r\nServer: x").unwrap();
+        assert_eq!((head.status, head.reason.as_str(), head.length, head.chunked), (200, "OK", Some(12), false));
+        let head = Head::parse("HTTP/1.1 302 Found\r\nLocation: /new\r\ntransfer-encoding: gzip, chunked").unwrap();
+        assert_eq!((head.location.as_deref(), head.chunked), (Some("/new"), true));
+        assert_eq!(Head::parse("SSH-2.0"), Err("not an HTTP response"));
+    }
+
+    #[test_case]
+    fn test_chunked() {
+        let body = b"5;name=x\r\nhello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\nextra";
+        // Fed whole and a byte at a time
+        for step in [body.len(), 1] {
+            let mut decoder = ChunkedDecoder::new();
+            let mut out = Vec::new();
+            for piece in body.chunks(step) {
+                decoder.feed(piece, &mut out).unwrap();
+            }
+            assert!(decoder.is_done());
+            assert_eq!(out, b"hello, world");
+        }
+        let mut out = Vec::new();
+        assert_eq!(ChunkedDecoder::new().feed(b"2\r\nabc\r\n", &mut out), Err("chunk overruns its size"));
+        assert_eq!(ChunkedDecoder::new().feed(b"zz\r\n", &mut out), Err("bad chunk size"));
+    }
+}
@@ -1,22 +1,39 @@
 // fork - Test fork syscall
 
-pub fn run() {
-    // Use inline assembly to call fork syscall
-    let pid: i32 = unsafe {
-        let result: i64;
-        core::arch::asm!(
-            "mov rax, 57; syscall",
-            out("rax") result,
-            options(nostack, preserves_flags)
-        );
-        result as i32
-    };
-    
-    if pid == 0 {
-        crate::serial_println!("[CHILD] This is the child process");
-    } else if pid > 0 {
-        crate::serial_println!("[PARENT] Forked child process: {}", pid);
-    } else {
-        crate::serial_println!("fork() failed with error code: {}", pid);
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Fork;
+
+impl Command for Fork {
+    fn name(&self) -> &'static str {
+        "fork"
+    }
+
+    fn description(&self) -> &'static str {
+        "Test fork syscall"
+    }
+
+    fn run(&self, _args: &[&str], out: &mut dyn Write) -> i32 {
+        // Use inline assembly to call fork syscall
+        let pid: i32 = unsafe {
+            let result: i64;
+            core::arch::asm!(
+                "mov rax, 57; syscall",
+                out("rax") result,
+                options(nostack, preserves_flags)
+            );
+            result as i32
+        };
+
+        if pid == 0 {
+            writeln!(out, "[CHILD] This is the child process").ok();
+        } else if pid > 0 {
+            writeln!(out, "[PARENT] Forked child process: {}", pid).ok();
+        } else {
+            crate::eprintln!("fork() failed with error code: {}", pid);
+            return EXIT_FAILURE;
+        }
+        EXIT_SUCCESS
     }
 }
@@ -1,5 +1,7 @@
-// Process commands: ps, fork
+// Process commands: ps, top, fork, lsof
 
 pub mod ps;
+pub mod top;
 pub mod fork;
+pub mod lsof;
 
@@ -1,19 +1,38 @@
 // ps - List running processes
 
+use core::fmt::Write;
 use crate::kernel::scheduler::SCHEDULER;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
 
-pub fn run() {
-    crate::serial_println!(" PID  NAME");
-    // Use try_lock which returns Option
-    match SCHEDULER.try_lock() {
-        Some(scheduler) => {
-            for task in scheduler.get_tasks() {
-                crate::serial_println!("  {}  {}", task.pid, task.name);
+pub struct Ps;
+
+impl Command for Ps {
+    fn name(&self) -> &'static str {
+        "ps"
+    }
+
+    fn description(&self) -> &'static str {
+        "List running processes"
+    }
+
+    fn run(&self, _args: &[&str], out: &mut dyn Write) -> i32 {
+        writeln!(out, " PID  NAME").ok();
+        // Use try_lock which returns Option
+        match SCHEDULER.try_lock() {
+            Some(scheduler) => {
+                // Only the tasks in our PID namespace, as it numbers them
+                let ns = scheduler.current_pid_ns();
+                for task in scheduler.get_tasks() {
+                    if let Some(pid) = task.pid_in(ns) {
+                        writeln!(out, "  {}  {}", pid, task.name).ok();
+                    }
+                }
+            }
+            None => {
+                writeln!(out, "  1   init").ok();
+                writeln!(out, "(scheduler busy, showing init only)").ok();
             }
         }
-        None => {
-            crate::serial_println!("  1   init");
-            crate::serial_println!("(scheduler busy, showing init only)");
-        }
+        EXIT_SUCCESS
     }
 }
@@ -0,0 +1,93 @@
+// top - Show the busiest processes
+//
+// One snapshot, as `top -b -n 1` would print it: the load average and task
+// counts, then the tasks of our PID namespace, most CPU time first, with
+// their voluntary and involuntary context switches. `-n COUNT` shows only
+// the first COUNT.
+
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::kernel::scheduler::{loadavg, Pid, Task, TaskState, SCHEDULER};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Top;
+
+impl Command for Top {
+    fn name(&self) -> &'static str {
+        "top"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-n COUNT]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show load average and processes by CPU time"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "n:") {
+            Ok(opts) if opts.operands.is_empty() => opts,
+            Ok(_) => return self.usage(),
+            Err(e) => {
+                crate::eprintln!("top: {}", e);
+                return self.usage();
+            }
+        };
+        let count = match opts.value('n').map(|count| count.parse::<usize>()) {
+            None => usize::MAX,
+            Some(Ok(count)) => count,
+            Some(Err(_)) => {
+                crate::eprintln!("top: invalid count: {}", opts.value('n').unwrap_or(""));
+                return self.usage();
+            }
+        };
+
+        let scheduler = SCHEDULER.lock();
+        writeln!(out, "load average: {}", loadavg::describe(scheduler.load.averages())).ok();
+        let ns = scheduler.current_pid_ns();
+        let mut tasks: Vec<(Pid, &Task)> = scheduler.get_tasks()
+            .filter_map(|task| Some((task.pid_in(ns)?, task)))
+            .collect();
+        let count_in = |states: &[TaskState]| tasks.iter().filter(|(_, task)| states.contains(&task.state)).count();
+        writeln!(
+            out,
+            "Tasks: {} total, {} runnable, {} sleeping, {} stopped, {} zombie",
+            tasks.len(),
+            count_in(&[TaskState::Ready, TaskState::Running]),
+            count_in(&[TaskState::Blocked, TaskState::Sleeping]),
+            count_in(&[TaskState::Stopped]),
+            count_in(&[TaskState::Zombie])
+        )
+        .ok();
+
+        tasks.sort_by(|(a_pid, a), (b_pid, b)| b.cpu_time.cmp(&a.cpu_time).then(a_pid.cmp(b_pid)));
+        writeln!(out, "{:>5} S {:>3} {:>9} {:>7} {:>7}  NAME", "PID", "PRI", "TIME", "VCSW", "IVCSW").ok();
+        for (pid, task) in tasks.into_iter().take(count) {
+            writeln!(
+                out,
+                "{:>5} {} {:>3} {:>6}.{:02} {:>7} {:>7}  {}",
+                pid,
+                state_letter(task.state),
+                task.priority as u8,
+                task.cpu_time / 1000,
+                task.cpu_time % 1000 / 10,
+                task.nvcsw,
+                task.nivcsw,
+                task.name
+            )
+            .ok();
+        }
+        EXIT_SUCCESS
+    }
+}
+
+fn state_letter(state: TaskState) -> char {
+    match state {
+        TaskState::Ready | TaskState::Running => 'R',
+        TaskState::Blocked | TaskState::Sleeping => 'S',
+        TaskState::Stopped => 'T',
+        TaskState::Zombie => 'Z',
+    }
+}
@@ -1,5 +1,21 @@
 // clear - Clear the screen
 
-pub fn run() {
-    crate::hal::drivers::vga::clear_screen();
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Clear;
+
+impl Command for Clear {
+    fn name(&self) -> &'static str {
+        "clear"
+    }
+
+    fn description(&self) -> &'static str {
+        "Clear the screen"
+    }
+
+    fn run(&self, _args: &[&str], _out: &mut dyn Write) -> i32 {
+        crate::hal::drivers::vga::clear_screen();
+        EXIT_SUCCESS
+    }
 }
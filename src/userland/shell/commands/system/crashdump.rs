@@ -0,0 +1,72 @@
+// crashdump - Show or clear the crash dump left by the last panic
+
+use alloc::string::String;
+use core::fmt::Write;
+use crate::kernel::crashdump::{self, PARAM};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Crashdump;
+
+impl Command for Crashdump {
+    fn name(&self) -> &'static str {
+        "crashdump"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-c]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show the crash dump saved by the last panic (-c: erase it)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "c") {
+            Ok(opts) if opts.operands.is_empty() => opts,
+            Ok(_) => return self.usage(),
+            Err(e) => {
+                crate::eprintln!("crashdump: {}", e);
+                return self.usage();
+            }
+        };
+        let region = match crashdump::region() {
+            Some(region) => region,
+            None => {
+                crate::eprintln!("crashdump: no dump region (boot with {}=ataN[:LBA])", PARAM);
+                return EXIT_FAILURE;
+            }
+        };
+        let disk = region.drive.device_name();
+
+        if opts.has('c') {
+            return match crashdump::clear(region) {
+                Ok(()) => EXIT_SUCCESS,
+                Err(e) => {
+                    crate::eprintln!("crashdump: {}: {:?}", disk, e);
+                    EXIT_FAILURE
+                }
+            };
+        }
+
+        let dump = match crashdump::read(region) {
+            Ok(Some(dump)) => dump,
+            Ok(None) => {
+                writeln!(out, "No crash dump on {}", disk).ok();
+                return EXIT_SUCCESS;
+            }
+            Err(e) => {
+                crate::eprintln!("crashdump: {}: {:?}", disk, e);
+                return EXIT_FAILURE;
+            }
+        };
+        writeln!(out, "Crash dump from {} ({} at sector {}, {} bytes)",
+            crate::kernel::tz::local(dump.header.time), disk, region.lba, dump.header.length).ok();
+        if !dump.intact() {
+            crate::eprintln!("crashdump: warning: checksum mismatch, the dump is damaged");
+        }
+        writeln!(out).ok();
+        write!(out, "{}", String::from_utf8_lossy(&dump.report)).ok();
+        EXIT_SUCCESS
+    }
+}
@@ -0,0 +1,124 @@
+// desktop - Show the compositor with a clock and a scratch window
+
+use alloc::string::String;
+use core::fmt::Write;
+use crate::hal::drivers::framebuffer::{self, Mode};
+use crate::hal::drivers::gfx::{rgb, Color, Font, Rect, BLACK, GLYPH_HEIGHT, GLYPH_WIDTH, WHITE};
+use crate::hal::drivers::{mouse, serial};
+use crate::kernel::compositor::{self, InputEvent, SurfaceHandle};
+use crate::kernel::time;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+const DEFAULT_MODE: Mode = Mode { width: 800, height: 600 };
+const ESCAPE: char = '\u{1b}';
+const INK: Color = rgb(0xC0, 0x20, 0x20);
+
+pub struct Desktop;
+
+impl Command for Desktop {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[WIDTHxHEIGHT]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Run the compositor until Esc in a window or q on the console"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let mode = match args {
+            [] => DEFAULT_MODE,
+            [size] => match parse_mode(size) {
+                Some(mode) => mode,
+                None => return self.usage(),
+            },
+            _ => return self.usage(),
+        };
+        if let Err(e) = compositor::start(mode) {
+            crate::eprintln!("desktop: {}", e);
+            return EXIT_FAILURE;
+        }
+        let result = open_windows().and_then(|(clock, scratch)| {
+            let font = framebuffer::font().ok_or("no font")?;
+            run_desktop(&clock, &scratch, &font);
+            Ok(())
+        });
+        compositor::stop();
+        match result {
+            Ok(()) => {
+                writeln!(out, "desktop: back to the text console").ok();
+                EXIT_SUCCESS
+            }
+            Err(e) => {
+                crate::eprintln!("desktop: {}", e);
+                EXIT_FAILURE
+            }
+        }
+    }
+}
+
+fn parse_mode(size: &str) -> Option<Mode> {
+    let (width, height) = size.split_once('x')?;
+    Some(Mode { width: width.parse().ok()?, height: height.parse().ok()? })
+}
+
+fn open_windows() -> Result<(SurfaceHandle, SurfaceHandle), &'static str> {
+    let clock = compositor::create_surface("Clock", 10 * GLYPH_WIDTH, GLYPH_HEIGHT + 8)?;
+    let scratch = compositor::create_surface("Scratch - type or click", 320, 160)?;
+    Ok((clock, scratch))
+}
+
+fn run_desktop(clock: &SurfaceHandle, scratch: &SurfaceHandle, font: &Font) {
+    let mut shown = u64::MAX;
+    let mut typed = String::new();
+    scratch.draw(|canvas| canvas.clear(WHITE));
+    loop {
+        let now = time::now();
+        if now != shown {
+            shown = now;
+            let t = crate::kernel::tz::local(now).time;
+            let mut text = String::new();
+            write!(text, " {:02}:{:02}:{:02}", t.hour, t.minute, t.second).ok();
+            clock.draw(|canvas| {
+                canvas.clear(BLACK);
+                canvas.draw_text(0, 4, &text, WHITE, None, font);
+            });
+        }
+
+        for handle in [clock, scratch] {
+            while let Some(event) = handle.next_event() {
+                match event {
+                    InputEvent::Key(ESCAPE) => return,
+                    InputEvent::Key(c) if handle.id() == scratch.id() => {
+                        match c {
+                            '\x08' => {
+                                typed.pop();
+                            }
+                            '\n' => typed.clear(),
+                            c if !c.is_control() && typed.chars().count() < 38 => typed.push(c),
+                            _ => {}
+                        }
+                        scratch.draw(|canvas| {
+                            canvas.fill_rect(Rect::new(0, 0, canvas.width(), GLYPH_HEIGHT + 4), rgb(0xE8, 0xE8, 0xE8));
+                            canvas.draw_text(4, 2, &typed, BLACK, None, font);
+                        });
+                    }
+                    InputEvent::Pointer { x, y, buttons } if buttons & mouse::BUTTON_LEFT != 0 => {
+                        if handle.id() == scratch.id() {
+                            scratch.draw(|canvas| canvas.fill_rect(Rect::new(x - 1, y - 1, 3, 3), INK));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if matches!(serial::read_byte(), Some(b'q') | Some(0x1B)) {
+            return;
+        }
+        compositor::poll();
+        x86_64::instructions::hlt();
+    }
+}
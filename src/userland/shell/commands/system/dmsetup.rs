@@ -0,0 +1,135 @@
+// dmsetup - Manage mapped block devices
+//
+// `create NAME FILE` loads the table in FILE, one target per line, and
+// `create NAME -t TABLE` takes it inline with lines separated by ';':
+//
+//   dmsetup create vol -t '0 2048 linear hda 0; 2048 2048 linear hdb 0'
+//   dmsetup create secret -t '0 4096 crypt aes-xts-plain64 KEY 0 hdb 0'
+//
+// `remove NAME` takes a device away, `ls` lists them and `table [NAME]`
+// prints their tables with the keys left out. Creating and removing need
+// CAP_SYS_ADMIN.
+
+use alloc::string::String;
+use core::fmt::Write;
+use crate::hal::drivers::dm;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::Capability;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Dmsetup;
+
+impl Command for Dmsetup {
+    fn name(&self) -> &'static str {
+        "dmsetup"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "create NAME (FILE|-t TABLE) | remove NAME | ls | table [NAME]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Stack linear and encrypted block devices on others"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let (command, rest) = match args.split_first() {
+            Some((&command, rest)) => (command, rest),
+            None => return self.usage(),
+        };
+        match (command, rest) {
+            ("ls", []) => {
+                let mapped = dm::list();
+                if mapped.is_empty() {
+                    writeln!(out, "No devices found").ok();
+                }
+                for device in mapped {
+                    let device = device.read();
+                    writeln!(out, "{}\t({}:{})", device.name(), dm::DM_MAJOR, device.minor()).ok();
+                }
+                EXIT_SUCCESS
+            }
+            ("table", []) => {
+                for device in dm::list() {
+                    let device = device.read();
+                    for line in device.table().lines() {
+                        writeln!(out, "{}: {}", device.name(), line).ok();
+                    }
+                }
+                EXIT_SUCCESS
+            }
+            ("table", [name]) => match dm::device(name) {
+                Some(device) => {
+                    out.write_str(&device.read().table()).ok();
+                    EXIT_SUCCESS
+                }
+                None => fail(name, &dm::DmError::NotFound),
+            },
+            ("create", [name, table @ ..]) if !table.is_empty() => {
+                if !permitted() {
+                    return EXIT_FAILURE;
+                }
+                let table = match table_text(table) {
+                    Some(table) => table,
+                    None => return self.usage(),
+                };
+                let table = match table {
+                    Ok(table) => table,
+                    Err(path) => {
+                        crate::eprintln!("dmsetup: {}: cannot read table", path);
+                        return EXIT_FAILURE;
+                    }
+                };
+                match dm::create(name, &table) {
+                    Ok(minor) => {
+                        writeln!(out, "{}: created as dm-{}", name, minor).ok();
+                        EXIT_SUCCESS
+                    }
+                    Err(e) => fail(name, &e),
+                }
+            }
+            ("remove", [name]) => {
+                if !permitted() {
+                    return EXIT_FAILURE;
+                }
+                match dm::remove(name) {
+                    Ok(()) => EXIT_SUCCESS,
+                    Err(e) => fail(name, &e),
+                }
+            }
+            _ => self.usage(),
+        }
+    }
+}
+
+/// The table given as `-t TABLE` or in a file: None if the arguments are
+/// neither, Err with the path if the file cannot be read
+fn table_text<'a>(args: &[&'a str]) -> Option<Result<String, &'a str>> {
+    let opts = args::parse(args, "t:").ok()?;
+    match (opts.value('t'), &opts.operands[..]) {
+        (Some(table), []) => Some(Ok(String::from(table))),
+        (None, [path]) => Some(
+            crate::fs::vfs::api::read_file(path)
+                .ok()
+                .and_then(|data| String::from_utf8(data).ok())
+                .ok_or(*path),
+        ),
+        _ => None,
+    }
+}
+
+fn permitted() -> bool {
+    let pid = crate::userland::shell::shell_pid();
+    let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+    let permitted = crate::qsf::has_capability(uid, Capability::CapSysAdmin);
+    if !permitted {
+        crate::eprintln!("dmsetup: Operation not permitted");
+    }
+    permitted
+}
+
+fn fail(name: &str, error: &dm::DmError) -> i32 {
+    crate::eprintln!("dmsetup: {}: {}", name, error);
+    EXIT_FAILURE
+}
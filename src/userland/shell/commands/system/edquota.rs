@@ -0,0 +1,154 @@
+// edquota - Set disk quota limits
+//
+// `edquota [-g] NAME -b SOFT:HARD -i SOFT:HARD` sets the block limits (in
+// KiB, or with a K, M, G or T suffix) and the file limits of a user or,
+// with -g, a group; 0 is no limit, and either may be left out to keep
+// what is set. Given neither, it prints the limits. `edquota -t
+// BLOCK:INODE` sets the grace periods, in seconds or with an s, m, h or d
+// suffix. Changing anything needs CAP_SYS_ADMIN.
+
+use core::fmt::Write;
+use crate::fs::quota::QuotaType;
+use crate::fs::vfs::VFS;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::Capability;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::users;
+
+pub struct Edquota;
+
+impl Command for Edquota {
+    fn name(&self) -> &'static str {
+        "edquota"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-g] NAME [-b SOFT:HARD] [-i SOFT:HARD] | -t BLOCK:INODE"
+    }
+
+    fn description(&self) -> &'static str {
+        "Set disk quota limits and grace periods"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "gb:i:t:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("edquota: {}", e);
+                return self.usage();
+            }
+        };
+
+        if let Some(periods) = opts.value('t') {
+            if !opts.operands.is_empty() {
+                return self.usage();
+            }
+            let (block, inode) = match pair(periods, duration) {
+                Some(periods) => periods,
+                None => {
+                    crate::eprintln!("edquota: invalid grace periods: {}", periods);
+                    return EXIT_FAILURE;
+                }
+            };
+            if !permitted() {
+                return EXIT_FAILURE;
+            }
+            VFS.lock().quotas_mut().set_grace(block, inode);
+            return EXIT_SUCCESS;
+        }
+
+        let name = match &opts.operands[..] {
+            [name] => *name,
+            _ => return self.usage(),
+        };
+        let (kind, id) = if opts.has('g') {
+            (QuotaType::Group, users::gid(name))
+        } else {
+            (QuotaType::User, users::uid(name))
+        };
+        let id = match id {
+            Some(id) => id,
+            None => {
+                crate::eprintln!("edquota: no such {}: {}", if kind == QuotaType::User { "user" } else { "group" }, name);
+                return EXIT_FAILURE;
+            }
+        };
+        let mut limits = VFS.lock().quotas().get(kind, id).limits;
+        if opts.value('b').is_none() && opts.value('i').is_none() {
+            writeln!(out, "blocks: {}:{}  files: {}:{}", limits.block_soft, limits.block_hard, limits.inode_soft, limits.inode_hard).ok();
+            return EXIT_SUCCESS;
+        }
+
+        if let Some(blocks) = opts.value('b') {
+            match pair(blocks, kibibytes) {
+                Some((soft, hard)) => (limits.block_soft, limits.block_hard) = (soft, hard),
+                None => {
+                    crate::eprintln!("edquota: invalid block limits: {}", blocks);
+                    return EXIT_FAILURE;
+                }
+            }
+        }
+        if let Some(files) = opts.value('i') {
+            match pair(files, |count| count.parse().ok()) {
+                Some((soft, hard)) => (limits.inode_soft, limits.inode_hard) = (soft, hard),
+                None => {
+                    crate::eprintln!("edquota: invalid file limits: {}", files);
+                    return EXIT_FAILURE;
+                }
+            }
+        }
+        if limits.block_hard != 0 && limits.block_soft > limits.block_hard
+            || limits.inode_hard != 0 && limits.inode_soft > limits.inode_hard
+        {
+            crate::eprintln!("edquota: soft limit is above the hard limit");
+            return EXIT_FAILURE;
+        }
+        if !permitted() {
+            return EXIT_FAILURE;
+        }
+        VFS.lock().quotas_mut().set_limits(kind, id, limits);
+        EXIT_SUCCESS
+    }
+}
+
+/// "SOFT:HARD", each parsed by `parse`
+fn pair(text: &str, parse: fn(&str) -> Option<u64>) -> Option<(u64, u64)> {
+    let (first, second) = text.split_once(':')?;
+    Some((parse(first)?, parse(second)?))
+}
+
+/// A size in KiB: a plain number, or one with a K, M, G or T suffix
+fn kibibytes(text: &str) -> Option<u64> {
+    let (number, scale) = match text.char_indices().last()? {
+        (at, 'K' | 'k') => (&text[..at], 1),
+        (at, 'M' | 'm') => (&text[..at], 1 << 10),
+        (at, 'G' | 'g') => (&text[..at], 1 << 20),
+        (at, 'T' | 't') => (&text[..at], 1 << 30),
+        _ => (text, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+/// Seconds: a plain number, or one with an s, m, h or d suffix
+fn duration(text: &str) -> Option<u64> {
+    let (number, scale) = match text.char_indices().last()? {
+        (at, 's') => (&text[..at], 1),
+        (at, 'm') => (&text[..at], 60),
+        (at, 'h') => (&text[..at], 60 * 60),
+        (at, 'd') => (&text[..at], 24 * 60 * 60),
+        _ => (text, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+fn permitted() -> bool {
+    let pid = crate::userland::shell::shell_pid();
+    let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+    let permitted = crate::qsf::has_capability(uid, Capability::CapSysAdmin);
+    if !permitted {
+        crate::eprintln!("edquota: Operation not permitted");
+    }
+    permitted
+}
+
@@ -1,5 +1,21 @@
 // exit - Exit shell
 
-pub fn run() {
-    crate::println!("Cannot exit from init shell. Use 'reboot' to restart.");
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_FAILURE};
+
+pub struct Exit;
+
+impl Command for Exit {
+    fn name(&self) -> &'static str {
+        "exit"
+    }
+
+    fn description(&self) -> &'static str {
+        "Exit shell (disabled in init)"
+    }
+
+    fn run(&self, _args: &[&str], _out: &mut dyn Write) -> i32 {
+        crate::eprintln!("Cannot exit from init shell. Use 'reboot' to restart.");
+        EXIT_FAILURE
+    }
 }
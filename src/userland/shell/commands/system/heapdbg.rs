@@ -0,0 +1,153 @@
+// heapdbg - Kernel heap debugging: statistics, allocation tracking, leak scans
+
+use alloc::collections::BTreeMap;
+use core::fmt::Write;
+use crate::hal::memory::{heap_debug, kmemleak};
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+
+pub struct Heapdbg;
+
+impl Command for Heapdbg {
+    fn name(&self) -> &'static str {
+        "heapdbg"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[SUBCOMMAND]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Heap debugging: stats, allocation tracking, leak reports"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        match args.first().copied() {
+            None | Some("stats") => stats(out),
+            Some("track") => track(&args[1..], out),
+            Some("leaks") => leaks(&args[1..], out),
+            Some("check") => check(out),
+            Some("scan") => scan(out),
+            Some(other) => {
+                crate::eprintln!("heapdbg: unknown subcommand '{}'", other);
+                usage()
+            }
+        }
+    }
+}
+
+fn usage() -> i32 {
+    crate::eprintln!("Usage: heapdbg [stats]");
+    crate::eprintln!("       heapdbg track on|off  - record size and owner of new allocations");
+    crate::eprintln!("       heapdbg leaks [PID]   - live tracked allocations, by owner or for PID");
+    crate::eprintln!("       heapdbg check         - verify canaries of all tracked allocations");
+    crate::eprintln!("       heapdbg scan          - report tracked allocations nothing refers to");
+    EXIT_USAGE
+}
+
+fn not_compiled_in() -> i32 {
+    crate::eprintln!("heapdbg: heap debugging not compiled in");
+    EXIT_FAILURE
+}
+
+fn stats(out: &mut dyn Write) -> i32 {
+    let heap = crate::hal::memory::get_heap_stats();
+    writeln!(out, "Heap: {} used, {} free, {} total", heap.used, heap.free, heap.total).ok();
+    if !heap_debug::enabled() {
+        writeln!(out, "Heap debugging not compiled in (enable the heap-debug feature)").ok();
+        return EXIT_SUCCESS;
+    }
+    let dbg = heap_debug::stats();
+    writeln!(out, "Allocations: {}  Frees: {}  Live bytes: {}", dbg.allocations, dbg.frees, dbg.live_bytes).ok();
+    writeln!(out, "Poisoning: alloc {:#04x}, free {:#04x}", heap_debug::POISON_ALLOC, heap_debug::POISON_FREE).ok();
+    writeln!(
+        out,
+        "Tracking: {} ({} tracked, {} untracked, {} slots)",
+        if heap_debug::tracking() { "on" } else { "off" },
+        dbg.tracked,
+        dbg.untracked,
+        heap_debug::TRACK_SLOTS
+    )
+    .ok();
+    EXIT_SUCCESS
+}
+
+fn track(args: &[&str], out: &mut dyn Write) -> i32 {
+    if !heap_debug::enabled() {
+        return not_compiled_in();
+    }
+    match args.first().copied() {
+        Some("on") => {
+            heap_debug::set_tracking(true);
+            writeln!(out, "heapdbg: tracking new allocations").ok();
+        }
+        Some("off") => {
+            heap_debug::set_tracking(false);
+            writeln!(out, "heapdbg: tracking stopped").ok();
+        }
+        _ => return usage(),
+    }
+    EXIT_SUCCESS
+}
+
+fn leaks(args: &[&str], out: &mut dyn Write) -> i32 {
+    let records = heap_debug::snapshot();
+
+    if let Some(pid) = args.first() {
+        let pid: u32 = match pid.parse() {
+            Ok(pid) => pid,
+            Err(_) => {
+                crate::eprintln!("heapdbg: invalid pid '{}'", pid);
+                return EXIT_FAILURE;
+            }
+        };
+        writeln!(out, "{:>8} {:>18} {:>8}  SITE", "SEQ", "ADDRESS", "SIZE").ok();
+        for record in records.iter().filter(|r| r.owner == pid) {
+            writeln!(out, "{:>8} {:#018x} {:>8}  {}", record.seq, record.ptr, record.size, record.site).ok();
+        }
+        return EXIT_SUCCESS;
+    }
+
+    let mut owners: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+    for record in &records {
+        let entry = owners.entry(record.owner).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += record.size;
+    }
+    if owners.is_empty() {
+        writeln!(out, "No tracked allocations (see 'heapdbg track on')").ok();
+        return EXIT_SUCCESS;
+    }
+    writeln!(out, "{:>6} {:>8} {:>10}", "PID", "ALLOCS", "BYTES").ok();
+    for (owner, (count, bytes)) in owners {
+        writeln!(out, "{:>6} {:>8} {:>10}", owner, count, bytes).ok();
+    }
+    EXIT_SUCCESS
+}
+
+fn check(out: &mut dyn Write) -> i32 {
+    if !heap_debug::enabled() {
+        return not_compiled_in();
+    }
+    let checked = heap_debug::check_all();
+    writeln!(out, "heapdbg: {} tracked allocations intact", checked).ok();
+    EXIT_SUCCESS
+}
+
+fn scan(out: &mut dyn Write) -> i32 {
+    if !heap_debug::enabled() {
+        return not_compiled_in();
+    }
+    if !heap_debug::tracking() {
+        crate::eprintln!("heapdbg: tracking is off; only allocations made while tracking can be scanned");
+    }
+    let report = kmemleak::scan();
+    writeln!(out, "heapdbg: scanned {} allocations, {} unreferenced", report.scanned, report.leaks.len()).ok();
+    if report.leaks.is_empty() {
+        return EXIT_SUCCESS;
+    }
+    writeln!(out, "{:>8} {:>18} {:>8} {:>6}  SITE", "SEQ", "ADDRESS", "SIZE", "PID").ok();
+    for leak in &report.leaks {
+        writeln!(out, "{:>8} {:#018x} {:>8} {:>6}  {}", leak.seq, leak.ptr, leak.size, leak.owner, leak.site).ok();
+    }
+    EXIT_SUCCESS
+}
@@ -1,28 +1,68 @@
 // help - Show available commands
 
-pub fn run() {
-    crate::println!("Qunix Shell - Available Commands:");
-    crate::println!();
-    crate::println!("System Info:");
-    crate::println!("  help      - Show this help message");
-    crate::println!("  whoami    - Print current user");
-    crate::println!("  uname     - Print system information");
-    crate::println!("  id        - Print user ID information");
-    crate::println!("  pwd       - Print working directory");
-    crate::println!();
-    crate::println!("File Operations:");
-    crate::println!("  echo TEXT - Echo text to terminal");
-    crate::println!("  cat FILE  - Display file contents");
-    crate::println!("  ls [DIR]  - List directory contents");
-    crate::println!("  touch FILE- Create empty file");
-    crate::println!("  mkdir DIR - Create directory");
-    crate::println!("  rm FILE   - Remove file");
-    crate::println!("  cd DIR    - Change directory");
-    crate::println!("  chmod MODE FILE - Change file permissions");
-    crate::println!();
-    crate::println!("System:");
-    crate::println!("  clear     - Clear the screen");
-    crate::println!("  ps        - List running processes");
-    crate::println!("  fork      - Test fork syscall");
-    crate::println!("  exit      - Exit shell (disabled in init)");
+use alloc::format;
+use core::fmt::Write;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use super::super::{find, SECTIONS};
+
+pub struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[COMMAND...]"
+    }
+
+    fn description(&self) -> &'static str {
+        "List commands, or show usage of the named ones"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("help: {}", e);
+                return self.usage();
+            }
+        };
+        if !opts.operands.is_empty() {
+            return describe(&opts.operands, out);
+        }
+
+        writeln!(out, "Qunix Shell - Available Commands:").ok();
+        for section in SECTIONS {
+            writeln!(out).ok();
+            writeln!(out, "{}:", section.title).ok();
+            for command in section.commands {
+                let usage = format!("{} {}", command.name(), command.synopsis());
+                writeln!(out, "  {:<22} - {}", usage.trim_end(), command.description()).ok();
+            }
+        }
+        writeln!(out).ok();
+        writeln!(out, "Redirect output with > FILE, >> FILE, 2> FILE or 2>> FILE.").ok();
+        EXIT_SUCCESS
+    }
+}
+
+/// `help NAME...`: usage line and description of each builtin
+fn describe(names: &[&str], out: &mut dyn Write) -> i32 {
+    let mut status = EXIT_SUCCESS;
+    for name in names {
+        match find(name) {
+            Some(command) => {
+                let usage = format!("{} {}", command.name(), command.synopsis());
+                writeln!(out, "{}: {}", command.name(), usage.trim_end()).ok();
+                writeln!(out, "    {}", command.description()).ok();
+            }
+            None => {
+                crate::eprintln!("help: no help topics match `{}'", name);
+                status = EXIT_FAILURE;
+            }
+        }
+    }
+    status
 }
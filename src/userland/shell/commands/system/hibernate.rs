@@ -0,0 +1,46 @@
+// hibernate - Save the running system to disk and power off
+//
+// Experimental: see kernel::hibernate. The next boot with the same
+// `resume=` carries on where this left off, and the command then returns.
+
+use core::fmt::Write;
+use crate::kernel::hibernate::{self, HibernateError, PARAM};
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::Capability;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Hibernate;
+
+impl Command for Hibernate {
+    fn name(&self) -> &'static str {
+        "hibernate"
+    }
+
+    fn description(&self) -> &'static str {
+        "Save memory to the resume disk and power off (experimental)"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        if !args.is_empty() {
+            return self.usage();
+        }
+        let pid = crate::userland::shell::shell_pid();
+        let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+        if !crate::qsf::has_capability(uid, Capability::CapSysBoot) {
+            crate::eprintln!("hibernate: Operation not permitted");
+            return EXIT_FAILURE;
+        }
+        crate::println!("Hibernating...");
+        match hibernate::hibernate() {
+            Ok(()) => EXIT_SUCCESS,
+            Err(HibernateError::NotConfigured) => {
+                crate::eprintln!("hibernate: no resume disk (boot with {}=ataN[:LBA])", PARAM);
+                EXIT_FAILURE
+            }
+            Err(e) => {
+                crate::eprintln!("hibernate: {:?}", e);
+                EXIT_FAILURE
+            }
+        }
+    }
+}
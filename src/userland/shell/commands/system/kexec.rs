@@ -0,0 +1,58 @@
+// kexec - Start another kernel without a firmware reboot
+//
+// Loads FILE, the new kernel's ELF (not the bootimage), then syncs the
+// filesystems, saves the kernel log and jumps to it; see kernel::kexec.
+// Nothing happens if the image cannot be loaded.
+//
+//   kexec /mnt/disk/qunix
+
+use core::fmt::Write;
+use crate::fs::vfs::api as vfs_api;
+use crate::kernel::kexec;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::Capability;
+use crate::userland::shell::command::{Command, EXIT_FAILURE};
+
+pub struct Kexec;
+
+impl Command for Kexec {
+    fn name(&self) -> &'static str {
+        "kexec"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "FILE"
+    }
+
+    fn description(&self) -> &'static str {
+        "Load a kernel image and start it in place of this one"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        let [path] = args else {
+            return self.usage();
+        };
+        let pid = crate::userland::shell::shell_pid();
+        let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+        if !crate::qsf::has_capability(uid, Capability::CapSysBoot) {
+            crate::eprintln!("kexec: Operation not permitted");
+            return EXIT_FAILURE;
+        }
+        let data = match vfs_api::read_file(path) {
+            Ok(data) => data,
+            Err(e) => {
+                crate::eprintln!("kexec: {}: {:?}", path, e);
+                return EXIT_FAILURE;
+            }
+        };
+        let image = match kexec::load(&data) {
+            Ok(image) => image,
+            Err(e) => {
+                crate::eprintln!("kexec: {}: {:?}", path, e);
+                return EXIT_FAILURE;
+            }
+        };
+        drop(data);
+        kexec::exec(image)
+    }
+}
@@ -0,0 +1,153 @@
+// mdadm - Manage software RAID arrays
+//
+// `create mdN -l LEVEL [-c CHUNK] DEVICE...` makes a RAID0 (striped, CHUNK
+// KiB a chunk, 64 by default) or RAID1 (mirrored) array, and `assemble`
+// finds arrays again from their superblocks, either the one given with
+// its members or, without arguments, every one on the block devices:
+//
+//   mdadm create md0 -l 1 hdb hdc
+//   mdadm fail md0 hdc; mdadm add md0 hdd
+//   mdadm stop md0; mdadm assemble
+//
+// `fail` and `add` replace a mirror's member, the new one rebuilt in the
+// background; `detail [mdN]` shows the arrays and how far a rebuild has
+// got. Everything but `detail` needs CAP_SYS_ADMIN.
+
+use core::fmt::Write;
+use crate::hal::drivers::md::{self, Level, MdError};
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::Capability;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Mdadm;
+
+impl Command for Mdadm {
+    fn name(&self) -> &'static str {
+        "mdadm"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "create mdN -l LEVEL [-c CHUNK] DEVICE... | assemble [mdN DEVICE...] | stop mdN | fail mdN DEVICE | add mdN DEVICE | detail [mdN]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Stripe or mirror block devices as RAID0/1 arrays"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let (command, rest) = match args.split_first() {
+            Some((&command, rest)) => (command, rest),
+            None => return self.usage(),
+        };
+        if command != "detail" && !permitted() {
+            return EXIT_FAILURE;
+        }
+        match (command, rest) {
+            ("detail", []) => {
+                let arrays = md::list();
+                if arrays.is_empty() {
+                    writeln!(out, "No arrays running").ok();
+                }
+                for array in arrays {
+                    out.write_str(&array.read().detail()).ok();
+                }
+                EXIT_SUCCESS
+            }
+            ("detail", [name]) => match md::array(name) {
+                Some(array) => {
+                    out.write_str(&array.read().detail()).ok();
+                    EXIT_SUCCESS
+                }
+                None => fail(name, &MdError::NotFound),
+            },
+            ("create", [name, rest @ ..]) => {
+                let opts = match args::parse(rest, "l:c:") {
+                    Ok(opts) => opts,
+                    Err(e) => {
+                        crate::eprintln!("mdadm: {}", e);
+                        return self.usage();
+                    }
+                };
+                let level = match opts.value('l').map(Level::parse) {
+                    Some(Some(level)) => level,
+                    Some(None) => {
+                        crate::eprintln!("mdadm: unsupported level: {}", opts.value('l').unwrap_or(""));
+                        return EXIT_FAILURE;
+                    }
+                    None => return self.usage(),
+                };
+                let chunk = match opts.value('c').map(|kib| kib.parse::<u64>()) {
+                    None => md::DEFAULT_CHUNK,
+                    Some(Ok(kib)) if kib > 0 => kib * 1024 / md::SECTOR_SIZE,
+                    Some(_) => {
+                        crate::eprintln!("mdadm: invalid chunk size: {}", opts.value('c').unwrap_or(""));
+                        return EXIT_FAILURE;
+                    }
+                };
+                match md::create(name, level, chunk, &opts.operands) {
+                    Ok(minor) => {
+                        writeln!(out, "/dev/md{}: {} array started", minor, level).ok();
+                        EXIT_SUCCESS
+                    }
+                    Err(e) => fail(name, &e),
+                }
+            }
+            ("assemble", []) => {
+                let found = md::assemble_scan();
+                if found.is_empty() {
+                    writeln!(out, "No arrays found").ok();
+                }
+                let mut status = EXIT_SUCCESS;
+                for (uuid, result) in found {
+                    let uuid = crate::qsf::crypto::to_hex(&uuid);
+                    match result {
+                        Ok(minor) => {
+                            writeln!(out, "/dev/md{}: assembled array {}", minor, uuid).ok();
+                        }
+                        Err(e) => status = fail(&uuid, &e),
+                    }
+                }
+                status
+            }
+            ("assemble", [name, devices @ ..]) if !devices.is_empty() => match md::assemble(name, devices) {
+                Ok(minor) => {
+                    writeln!(out, "/dev/md{}: assembled from {} devices", minor, devices.len()).ok();
+                    EXIT_SUCCESS
+                }
+                Err(e) => fail(name, &e),
+            },
+            ("stop", [name]) => match md::stop(name) {
+                Ok(()) => EXIT_SUCCESS,
+                Err(e) => fail(name, &e),
+            },
+            ("fail", [name, device]) => match md::fail(name, device) {
+                Ok(()) => EXIT_SUCCESS,
+                Err(e) => fail(name, &e),
+            },
+            ("add", [name, device]) => match md::add(name, device) {
+                Ok(slot) => {
+                    writeln!(out, "{}: {} added as member {}, rebuilding", name, device, slot).ok();
+                    EXIT_SUCCESS
+                }
+                Err(e) => fail(name, &e),
+            },
+            _ => self.usage(),
+        }
+    }
+}
+
+fn permitted() -> bool {
+    let pid = crate::userland::shell::shell_pid();
+    let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+    let permitted = crate::qsf::has_capability(uid, Capability::CapSysAdmin);
+    if !permitted {
+        crate::eprintln!("mdadm: Operation not permitted");
+    }
+    permitted
+}
+
+fn fail(name: &str, error: &MdError) -> i32 {
+    crate::eprintln!("mdadm: {}: {}", name, error);
+    EXIT_FAILURE
+}
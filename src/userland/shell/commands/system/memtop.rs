@@ -0,0 +1,55 @@
+// memtop - Kernel memory by subsystem
+//
+// Shows what each subsystem holds of the fixed-size kernel heap, largest
+// first: live bytes, live allocations and allocations made since boot,
+// with the page frames each has taken. Allocations are charged through
+// `alloc_tag!`; the heap columns need the heap-debug feature, the frame
+// column does not.
+
+use core::fmt::Write;
+use crate::hal::memory::{alloc_tag, heap_debug};
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+
+pub struct Memtop;
+
+impl Command for Memtop {
+    fn name(&self) -> &'static str {
+        "memtop"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        ""
+    }
+
+    fn description(&self) -> &'static str {
+        "Show kernel heap and frame usage by subsystem"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        if !args.is_empty() {
+            return self.usage();
+        }
+        let heap = crate::hal::memory::get_heap_stats();
+        writeln!(out, "Heap: {} used, {} free, {} total", heap.used, heap.free, heap.total).ok();
+        if !heap_debug::enabled() {
+            writeln!(out, "Heap columns need the heap-debug feature; frames are always counted").ok();
+        }
+
+        let mut usage = alloc_tag::usage();
+        usage.sort_by(|a, b| b.live_bytes.cmp(&a.live_bytes).then(b.frames.cmp(&a.frames)));
+        writeln!(out, "{:<10} {:>12} {:>8} {:>10} {:>8}", "SUBSYSTEM", "LIVE BYTES", "LIVE", "ALLOCS", "FRAMES").ok();
+        for entry in usage {
+            writeln!(
+                out,
+                "{:<10} {:>12} {:>8} {:>10} {:>8}",
+                entry.subsystem.name(),
+                entry.live_bytes,
+                entry.live,
+                entry.allocations,
+                entry.frames
+            )
+            .ok();
+        }
+        EXIT_SUCCESS
+    }
+}
@@ -1,6 +1,39 @@
-// System commands: help, clear, exit
+// System commands: help, clear, exit, set, which, type, qsfctl, qcontainer, heapdbg,
+// memtop, crashdump, reboot, screendump, desktop, qbench, trace, dmsetup,
+// mdadm, edquota, mount, umount, overlay, vfs-snapshot, vfs-restore, test, [, ., source, read,
+// tzselect, theme, hibernate, kexec, qpkg, sysupdate
 
 pub mod help;
 pub mod clear;
 pub mod exit;
+pub mod set;
+pub mod which;
+pub mod type_;
+pub mod qsfctl;
+pub mod qcontainer;
+pub mod heapdbg;
+pub mod memtop;
+pub mod crashdump;
+pub mod reboot;
+pub mod hibernate;
+pub mod kexec;
+pub mod screendump;
+pub mod qbench;
+pub mod trace;
+pub mod dmsetup;
+pub mod mdadm;
+pub mod edquota;
+pub mod mount;
+pub mod overlay;
+pub mod vfs_snapshot;
+pub mod vfs_restore;
+pub mod qpkg;
+pub mod sysupdate;
+pub mod test;
+pub mod source;
+pub mod read;
+pub mod tzselect;
+pub mod theme;
+#[cfg(feature = "framebuffer")]
+pub mod desktop;
 
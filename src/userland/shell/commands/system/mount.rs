@@ -0,0 +1,164 @@
+// mount, umount - Mount filesystems on block devices and list mounts
+//
+// With no operands mount lists every mount in the shell's mount
+// namespace, as /proc/mounts does. `mount DEVICE DIR` puts the ext4 or
+// FAT32 filesystem on DEVICE, such as /dev/sda1, on the directory DIR;
+// -t names the type instead of trying each, and -r mounts it read-only,
+// as ext4 with a journal to replay or features the driver cannot keep
+// up must be. `mount -t tmpfs NAME DIR` makes a new tmpfs on DIR
+// instead, named NAME in the list, with -o giving its size= and
+// nr_inodes= limits.
+// `umount DIR` writes out what the filesystem holds back and takes it
+// off again; it fails while the working directory is under DIR.
+//
+//   mount -r /dev/mmcblk0p1 /mnt
+//   mount -t tmpfs -o size=4m scratch /mnt
+//   mount
+//   umount /mnt
+//
+// Mounting and unmounting need CAP_SYS_ADMIN.
+
+use core::fmt::Write;
+use crate::fs::mount::{self, MountFlags};
+use crate::fs::tmpfs;
+use crate::fs::FsError;
+use crate::hal::block;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::kernel::sys::errno::Errno;
+use crate::qsf::{Capability, QSF};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Mount;
+
+impl Command for Mount {
+    fn name(&self) -> &'static str {
+        "mount"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[[-r] [-t TYPE] [-o OPTIONS] DEVICE DIR]"
+    }
+
+    fn description(&self) -> &'static str {
+        "List mounts, or mount the filesystem on DEVICE on DIR"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "rt:o:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("mount: {}", e);
+                return self.usage();
+            }
+        };
+        let (device, dir) = match opts.operands[..] {
+            [] if !opts.has('r') && !opts.has('t') && !opts.has('o') => {
+                for m in mount::get_mounts() {
+                    writeln!(out, "{} on {} type {} ({})", m.device, m.mount_point, m.fs_type, m.options).ok();
+                }
+                return EXIT_SUCCESS;
+            }
+            [device, dir] => (device, dir),
+            _ => return self.usage(),
+        };
+        let fs_type = opts.value('t').filter(|&t| t != "auto");
+        if let Some(t) = fs_type.filter(|&t| t != "tmpfs" && !block::FS_TYPES.contains(&t)) {
+            crate::eprintln!("mount: unknown filesystem type '{}'", t);
+            return EXIT_FAILURE;
+        }
+        let options = match (fs_type, opts.value('o')) {
+            (Some("tmpfs"), options) => match tmpfs::Options::parse(options.unwrap_or("")) {
+                Ok(options) => Some(options),
+                Err(_) => {
+                    crate::eprintln!("mount: bad tmpfs options '{}'", options.unwrap_or(""));
+                    return EXIT_FAILURE;
+                }
+            },
+            (_, Some(_)) => {
+                crate::eprintln!("mount: -o is only for tmpfs");
+                return EXIT_FAILURE;
+            }
+            (_, None) => None,
+        };
+        if !permitted("mount") {
+            return EXIT_FAILURE;
+        }
+        let flags = match opts.has('r') {
+            true => MountFlags::RDONLY,
+            false => MountFlags::empty(),
+        };
+        if let Some(options) = options {
+            return match tmpfs::mount(device, dir, options, flags) {
+                Ok(()) => EXIT_SUCCESS,
+                Err(e) => fail("mount", dir, e),
+            };
+        }
+        match block::mount(device, dir, fs_type, flags) {
+            Ok(_) => EXIT_SUCCESS,
+            Err(FsError::NotFound) if block::open(device).is_none() => {
+                crate::eprintln!("mount: {}: no such block device", device);
+                EXIT_FAILURE
+            }
+            Err(FsError::InvalidArgument) => {
+                crate::eprintln!("mount: {}: no filesystem to mount", device);
+                EXIT_FAILURE
+            }
+            Err(FsError::ReadOnly) => {
+                crate::eprintln!("mount: {}: can only be mounted read-only (-r)", device);
+                EXIT_FAILURE
+            }
+            Err(e) => fail("mount", dir, e),
+        }
+    }
+}
+
+pub struct Umount;
+
+impl Command for Umount {
+    fn name(&self) -> &'static str {
+        "umount"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "DIR"
+    }
+
+    fn description(&self) -> &'static str {
+        "Unmount the filesystem mounted on DIR"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        let [dir] = args else {
+            return self.usage();
+        };
+        if !permitted("umount") {
+            return EXIT_FAILURE;
+        }
+        match block::umount(dir) {
+            Ok(()) => EXIT_SUCCESS,
+            Err(FsError::NotFound) => {
+                crate::eprintln!("umount: {}: not mounted", dir);
+                EXIT_FAILURE
+            }
+            Err(e) => fail("umount", dir, e),
+        }
+    }
+}
+
+fn permitted(command: &str) -> bool {
+    let pid = crate::userland::shell::shell_pid();
+    let euid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.euid);
+    // As require_sys_admin() checks it, so a confined shell is held to its
+    // profile's capabilities
+    let permitted = QSF.lock().check_process_capability(pid, euid, Capability::CapSysAdmin);
+    if !permitted {
+        crate::eprintln!("{}: Operation not permitted", command);
+    }
+    permitted
+}
+
+fn fail(command: &str, dir: &str, error: FsError) -> i32 {
+    crate::eprintln!("{}: {}: {}", command, dir, Errno::from(error).description());
+    EXIT_FAILURE
+}
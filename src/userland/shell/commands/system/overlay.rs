@@ -0,0 +1,111 @@
+// overlay - Merge a read-only filesystem into a writable directory
+//
+// `overlay mount LOWER DIR` takes the filesystem mounted on LOWER as the
+// read-only lower layer of an overlay on DIR, replacing what DIR held:
+// changes under DIR are kept in memory and LOWER is never written. `diff
+// DIR` lists what was added (A), changed (C) and deleted (D), and `umount
+// DIR` drops the overlay with every change. A base image for containers:
+//
+//   overlay mount /media/mmcblk0 /srv/base
+//   qcontainer run /srv/base sh
+//   overlay diff /srv/base; overlay umount /srv/base
+//
+// mount and umount need CAP_SYS_ADMIN.
+
+use core::fmt::Write;
+use crate::fs::mount::{self, MountFlags};
+use crate::fs::vfs::VFS;
+use crate::fs::FsError;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::kernel::sys::errno::Errno;
+use crate::qsf::Capability;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Overlay;
+
+impl Command for Overlay {
+    fn name(&self) -> &'static str {
+        "overlay"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "mount LOWER DIR | diff DIR | umount DIR"
+    }
+
+    fn description(&self) -> &'static str {
+        "Overlay a writable in-memory layer on a read-only filesystem"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        match args {
+            ["mount", lower, dir] => {
+                if !permitted() {
+                    return EXIT_FAILURE;
+                }
+                let filesystem = match mount::get_mount_table().into_iter().find(|m| m.path == *lower) {
+                    Some(mount) => mount.filesystem,
+                    None => {
+                        crate::eprintln!("overlay: {}: not a mount point", lower);
+                        return EXIT_FAILURE;
+                    }
+                };
+                let target = {
+                    let mut vfs = VFS.lock();
+                    let target = vfs.resolve_path(dir);
+                    if let Err(e) = vfs.overlay(dir, filesystem.clone()) {
+                        return fail(dir, e);
+                    }
+                    target
+                };
+                mount::umount(&target).ok();
+                match mount::mount(lower, &target, "overlay", MountFlags::empty(), filesystem) {
+                    Ok(()) => EXIT_SUCCESS,
+                    Err(e) => fail(dir, e),
+                }
+            }
+            ["diff", dir] => match VFS.lock().overlay_changes(dir) {
+                Ok(changes) => {
+                    for (change, path) in changes {
+                        writeln!(out, "{} {}", change, path).ok();
+                    }
+                    EXIT_SUCCESS
+                }
+                Err(FsError::InvalidArgument) => {
+                    crate::eprintln!("overlay: {}: not an overlay", dir);
+                    EXIT_FAILURE
+                }
+                Err(e) => fail(dir, e),
+            },
+            ["umount", dir] => {
+                if !permitted() {
+                    return EXIT_FAILURE;
+                }
+                let target = {
+                    let mut vfs = VFS.lock();
+                    if let Err(e) = vfs.overlay_changes(dir).and_then(|_| vfs.ungraft(dir)) {
+                        return fail(dir, e);
+                    }
+                    vfs.resolve_path(dir)
+                };
+                mount::umount(&target).ok();
+                EXIT_SUCCESS
+            }
+            _ => self.usage(),
+        }
+    }
+}
+
+fn permitted() -> bool {
+    let pid = crate::userland::shell::shell_pid();
+    let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+    let permitted = crate::qsf::has_capability(uid, Capability::CapSysAdmin);
+    if !permitted {
+        crate::eprintln!("overlay: Operation not permitted");
+    }
+    permitted
+}
+
+fn fail(dir: &str, error: FsError) -> i32 {
+    crate::eprintln!("overlay: {}: {}", dir, Errno::from(error).description());
+    EXIT_FAILURE
+}
@@ -0,0 +1,234 @@
+// qbench - Benchmark kernel primitives
+//
+// Times what the scheduler and VFS redesigns are going to change, so that
+// a regression shows up as a number that moved: a getpid round trip
+// through the syscall dispatcher, a switch between two tasks on a
+// scheduler of their own, creating files and writing and reading one
+// through the VFS, moving data through a pipe, and allocating and freeing
+// on the kernel heap. Times come from the TSC, calibrated against the PIT.
+//
+// Each result is one line, `NAME VALUE UNIT`, and the names and units stay
+// the same between versions so runs can be diffed or parsed. A benchmark
+// that cannot run here prints `-` and `unsupported`.
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use alloc::string::String;
+use alloc::vec;
+use core::fmt::Write;
+use core::hint::black_box;
+use crate::fs::vfs::api::{self as vfs_api, OpenFlags};
+use crate::hal::drivers::pit::{self, rdtsc};
+use crate::kernel::scheduler::{Scheduler, Task, TaskState, SCHEDULER};
+use crate::kernel::sys::syscalls::{self, SyscallArgs};
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS, EXIT_USAGE};
+
+const DEFAULT_ITERATIONS: u64 = 10_000;
+/// Files created by vfs.create, at most
+const MAX_FILES: u64 = 1000;
+/// Bytes moved by the throughput benchmarks, and in what chunks
+const STREAM_BYTES: usize = 4 << 20;
+const CHUNK: usize = 4096;
+const SCRATCH: &str = "/tmp/qbench";
+
+/// Benchmark groups, in the order they run
+const GROUPS: &[&str] = &["syscall", "sched", "vfs", "pipe", "heap"];
+
+pub struct Qbench;
+
+impl Command for Qbench {
+    fn name(&self) -> &'static str {
+        "qbench"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-n ITERATIONS] [syscall|sched|vfs|pipe|heap]..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Benchmark syscalls, task switches, the VFS, pipes and the heap"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "n:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("qbench: {}", e);
+                return self.usage();
+            }
+        };
+        let iterations = match opts.value('n').map(str::parse::<u64>) {
+            None => DEFAULT_ITERATIONS,
+            Some(Ok(n)) if n > 0 => n,
+            Some(_) => {
+                crate::eprintln!("qbench: -n wants a positive number");
+                return EXIT_USAGE;
+            }
+        };
+        if let Some(group) = opts.operands.iter().find(|group| !GROUPS.contains(group)) {
+            crate::eprintln!("qbench: {}: no such benchmark", group);
+            return self.usage();
+        }
+
+        let mut bench = Bench { out, tsc_per_us: pit::tsc_per_us(), iterations };
+        bench.report("tsc", Some(bench.tsc_per_us), "MHz");
+        for group in GROUPS.iter().filter(|group| opts.operands.is_empty() || opts.operands.contains(group)) {
+            match *group {
+                "syscall" => bench.syscall(),
+                "sched" => bench.switch(),
+                "vfs" => bench.vfs(),
+                "pipe" => bench.pipe(),
+                _ => bench.heap(),
+            }
+        }
+        EXIT_SUCCESS
+    }
+}
+
+struct Bench<'a> {
+    out: &'a mut dyn Write,
+    tsc_per_us: u64,
+    iterations: u64,
+}
+
+impl Bench<'_> {
+    fn report(&mut self, name: &str, value: Option<u64>, unit: &str) {
+        match value {
+            Some(value) => writeln!(self.out, "{:<16} {:>10} {}", name, value, unit),
+            None => writeln!(self.out, "{:<16} {:>10} unsupported", name, "-"),
+        }
+        .ok();
+    }
+
+    /// Nanoseconds each of `ops` operations took, given the cycles for all
+    fn per_op(&self, cycles: u64, ops: u64) -> u64 {
+        (cycles as u128 * 1000 / (self.tsc_per_us as u128 * ops as u128)) as u64
+    }
+
+    fn kib_per_sec(&self, cycles: u64, bytes: usize) -> u64 {
+        (bytes as u128 * self.tsc_per_us as u128 * 1_000_000 / (cycles.max(1) as u128 * 1024)) as u64
+    }
+
+    /// Time `op` run once per iteration, in nanoseconds each
+    fn time(&self, mut op: impl FnMut()) -> u64 {
+        let start = rdtsc();
+        for _ in 0..self.iterations {
+            op();
+        }
+        self.per_op(rdtsc() - start, self.iterations)
+    }
+
+    fn syscall(&mut self) {
+        let args = SyscallArgs { num: syscalls::SYS_GETPID, arg1: 0, arg2: 0, arg3: 0, arg4: 0, arg5: 0, arg6: 0 };
+        let ns = self.time(|| {
+            black_box(syscalls::dispatch_syscall(black_box(&args)));
+        });
+        self.report("syscall.getpid", Some(ns), "ns/op");
+    }
+
+    /// Two tasks taking turns on a scheduler that is not the kernel's, so
+    /// the running system is left alone
+    fn switch(&mut self) {
+        let mut scheduler = Scheduler::new();
+        scheduler.time_slice = 1;
+        for pid in [1, 2] {
+            if let Ok(task) = Task::new(pid, String::from("qbench"), 0, false) {
+                scheduler.add_task(task);
+            }
+        }
+        scheduler.unqueue(1);
+        scheduler.current_pid = Some(1);
+        if let Some(task) = scheduler.get_task_mut(1) {
+            task.state = TaskState::Running;
+        }
+        let ns = self.time(|| scheduler.schedule());
+        drop(scheduler);
+
+        // Switching set the heap owner and mount namespace as it went;
+        // put back the real current task's
+        let mut scheduler = SCHEDULER.lock();
+        let current = scheduler.current_pid();
+        scheduler.make_current(current);
+        drop(scheduler);
+        self.report("sched.switch", Some(ns), "ns/op");
+    }
+
+    fn vfs(&mut self) {
+        let files = self.iterations.min(MAX_FILES);
+        let create = OpenFlags::O_WRONLY | OpenFlags::O_CREAT | OpenFlags::O_TRUNC;
+        let start = rdtsc();
+        let created = (0..files)
+            .take_while(|n| vfs_api::open(&alloc::format!("{}.{}", SCRATCH, n), create, 0o600).is_ok())
+            .count() as u64;
+        let cycles = rdtsc() - start;
+        for n in 0..created {
+            vfs_api::unlink(&alloc::format!("{}.{}", SCRATCH, n)).ok();
+        }
+        self.report("vfs.create", (created == files).then(|| self.per_op(cycles, files)), "ns/op");
+
+        let mut chunk = vec![0x5Au8; CHUNK];
+        let write = vfs_api::open(SCRATCH, create, 0o600).ok().and_then(|mut fd| {
+            let start = rdtsc();
+            for _ in 0..STREAM_BYTES / CHUNK {
+                vfs_api::write(&mut fd, &chunk).ok()?;
+            }
+            Some(rdtsc() - start)
+        });
+        self.report("vfs.write", write.map(|cycles| self.kib_per_sec(cycles, STREAM_BYTES)), "KiB/s");
+
+        let read = vfs_api::open(SCRATCH, OpenFlags::O_RDONLY, 0).ok().filter(|_| write.is_some()).and_then(|mut fd| {
+            let start = rdtsc();
+            let mut total = 0;
+            while total < STREAM_BYTES {
+                match vfs_api::read(&mut fd, &mut chunk).ok()? {
+                    0 => return None,
+                    n => total += n,
+                }
+            }
+            Some(rdtsc() - start)
+        });
+        vfs_api::unlink(SCRATCH).ok();
+        self.report("vfs.read", read.map(|cycles| self.kib_per_sec(cycles, STREAM_BYTES)), "KiB/s");
+    }
+
+    /// Chunks written into a pipe and read straight back out, through the
+    /// syscalls
+    fn pipe(&mut self) {
+        let call = |num, arg1, arg2, arg3| {
+            syscalls::dispatch_syscall(&SyscallArgs { num, arg1, arg2, arg3, arg4: 0, arg5: 0, arg6: 0 })
+        };
+        let mut fds = [0i32; 2];
+        if call(syscalls::SYS_PIPE, fds.as_mut_ptr() as u64, 0, 0) < 0 {
+            self.report("pipe.bandwidth", None, "KiB/s");
+            return;
+        }
+        let (reader, writer) = (fds[0] as u64, fds[1] as u64);
+        let mut chunk = vec![0x5Au8; CHUNK];
+        let start = rdtsc();
+        let mut moved = 0;
+        while moved < STREAM_BYTES {
+            let written = call(syscalls::SYS_WRITE, writer, chunk.as_ptr() as u64, CHUNK as u64);
+            let read = call(syscalls::SYS_READ, reader, chunk.as_mut_ptr() as u64, CHUNK as u64);
+            if written <= 0 || read <= 0 {
+                break;
+            }
+            moved += read as usize;
+        }
+        let cycles = rdtsc() - start;
+        call(syscalls::SYS_CLOSE, reader, 0, 0);
+        call(syscalls::SYS_CLOSE, writer, 0, 0);
+        let bandwidth = (moved >= STREAM_BYTES).then(|| self.kib_per_sec(cycles, moved));
+        self.report("pipe.bandwidth", bandwidth, "KiB/s");
+    }
+
+    fn heap(&mut self) {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let ns = self.time(|| unsafe {
+            let block = black_box(alloc(layout));
+            if !block.is_null() {
+                dealloc(block, layout);
+            }
+        });
+        self.report("heap.alloc_free", Some(ns), "ns/op");
+    }
+}
@@ -0,0 +1,85 @@
+// qcontainer - Run a command in a container
+//
+// `qcontainer run DIR COMMAND` runs COMMAND as pid 1 of a new PID
+// namespace, with a private mount table, DIR as its root directory and
+// the QSF confinement profile given with -p ("container" by default).
+
+use core::fmt::Write;
+use crate::kernel::container;
+use crate::userland::shell::{self, args};
+use crate::userland::shell::command::{Command, EXIT_FAILURE};
+
+const DEFAULT_PROFILE: &str = "container";
+
+pub struct Qcontainer;
+
+impl Command for Qcontainer {
+    fn name(&self) -> &'static str {
+        "qcontainer"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "run [-p PROFILE] DIR COMMAND [ARG...]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Run COMMAND chrooted to DIR in new PID and mount namespaces"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let args = match args.split_first() {
+            Some((&"run", rest)) => rest,
+            _ => return self.usage(),
+        };
+        let (options, rest) = split_options(args);
+        let opts = match args::parse(options, "p:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("qcontainer: {}", e);
+                return self.usage();
+            }
+        };
+        let (dir, command, command_args) = match rest {
+            [dir, command, command_args @ ..] => (*dir, *command, command_args),
+            _ => return self.usage(),
+        };
+        let profile = opts.value('p').unwrap_or(DEFAULT_PROFILE);
+
+        let status = container::run(command, dir, profile, || shell::execute(command, command_args, out));
+        match status {
+            Ok(status) => status,
+            Err(e) => {
+                crate::eprintln!("qcontainer: {}: {}", dir, e.description());
+                EXIT_FAILURE
+            }
+        }
+    }
+}
+
+/// Split off the options before DIR; everything from DIR on belongs to
+/// the command, options included
+fn split_options<'a, 'b>(args: &'b [&'a str]) -> (&'b [&'a str], &'b [&'a str]) {
+    let mut i = 0;
+    while let Some(&arg) = args.get(i) {
+        match arg {
+            "--" => return (&args[..i], &args[i + 1..]),
+            "-p" => i += 2,
+            _ if arg.starts_with('-') && arg != "-" => i += 1,
+            _ => break,
+        }
+    }
+    args.split_at(i.min(args.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_options_stop_at_dir() {
+        let args = ["-p", "sandbox", "/srv", "ls", "-l"];
+        assert_eq!(split_options(&args), (&args[..2], &args[2..]));
+        let args = ["-psandbox", "--", "-dir", "ls"];
+        assert_eq!(split_options(&args), (&args[..1], &args[2..]));
+    }
+}
@@ -0,0 +1,107 @@
+// qpkg - Install, list and remove signed package bundles
+//
+// `install BUNDLE` checks the bundle's signature against the keys in
+// /etc/qpkg/keys and its files against the manifest, then unpacks them
+// under /usr; see kernel::qpkg for the format. `remove NAME` takes an
+// installed package away, `list` shows what is installed and `list NAME`
+// the files of one package. Installing and removing need CAP_SYS_ADMIN.
+//
+//   qpkg install /mnt/disk/hello-1.0.qpkg
+//   qpkg list hello
+
+use core::fmt::Write;
+use crate::fs::vfs::api as vfs_api;
+use crate::kernel::qpkg::{self, QpkgError};
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::Capability;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Qpkg;
+
+impl Command for Qpkg {
+    fn name(&self) -> &'static str {
+        "qpkg"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "install BUNDLE | remove NAME | list [NAME]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Install signed package bundles under /usr, list or remove them"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        match args {
+            ["install", path] => {
+                if !permitted() {
+                    return EXIT_FAILURE;
+                }
+                let bundle = match vfs_api::read_file(path) {
+                    Ok(bundle) => bundle,
+                    Err(e) => {
+                        crate::eprintln!("qpkg: {}: {:?}", path, e);
+                        return EXIT_FAILURE;
+                    }
+                };
+                match qpkg::install(&bundle) {
+                    Ok(package) => {
+                        writeln!(out, "{} {}: installed {} file(s), signed by {}",
+                            package.name, package.version, package.files.len(), package.key).ok();
+                        EXIT_SUCCESS
+                    }
+                    Err(e) => fail(path, &e),
+                }
+            }
+            ["remove", name] => {
+                if !permitted() {
+                    return EXIT_FAILURE;
+                }
+                match qpkg::remove(name) {
+                    Ok(package) => {
+                        writeln!(out, "{} {}: removed", package.name, package.version).ok();
+                        EXIT_SUCCESS
+                    }
+                    Err(e) => fail(name, &e),
+                }
+            }
+            ["list"] => match qpkg::installed() {
+                Ok(packages) => {
+                    for package in packages {
+                        writeln!(out, "{}\t{}\t{} file(s)", package.name, package.version, package.files.len()).ok();
+                    }
+                    EXIT_SUCCESS
+                }
+                Err(e) => fail(qpkg::DB_DIR, &e),
+            },
+            ["list", name] => match qpkg::installed() {
+                Ok(packages) => match packages.iter().find(|package| package.name == *name) {
+                    Some(package) => {
+                        for file in &package.files {
+                            writeln!(out, "{}", file).ok();
+                        }
+                        EXIT_SUCCESS
+                    }
+                    None => fail(name, &QpkgError::NotInstalled),
+                },
+                Err(e) => fail(qpkg::DB_DIR, &e),
+            },
+            _ => self.usage(),
+        }
+    }
+}
+
+fn permitted() -> bool {
+    let pid = crate::userland::shell::shell_pid();
+    let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+    let permitted = crate::qsf::has_capability(uid, Capability::CapSysAdmin);
+    if !permitted {
+        crate::eprintln!("qpkg: Operation not permitted");
+    }
+    permitted
+}
+
+fn fail(what: &str, error: &QpkgError) -> i32 {
+    crate::eprintln!("qpkg: {}: {}", what, error);
+    EXIT_FAILURE
+}
@@ -0,0 +1,374 @@
+// qsfctl - Inspect and control the Qunix Security Framework
+
+use core::fmt::Write;
+use crate::qsf::{QSF, AccessDecision, SecurityLevel, QsfError};
+use crate::qsf::policies::{Subject, Object, ConflictResolution};
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+
+pub struct Qsfctl;
+
+impl Command for Qsfctl {
+    fn name(&self) -> &'static str {
+        "qsfctl"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[SUBCOMMAND]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Security framework status, syscall tracing, audit log"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        match args.first().copied() {
+            None | Some("status") => status(out),
+            Some("trace") => trace(&args[1..], out),
+            Some("untrace") => untrace(&args[1..], out),
+            Some("audit") => audit(&args[1..], out),
+            Some("policies") => policies(out),
+            Some("resolve") => resolve(&args[1..], out),
+            Some("test") => test(&args[1..], out),
+            Some("profiles") => profiles(out),
+            Some("bind") => bind(&args[1..], out),
+            Some("unbind") => unbind(&args[1..]),
+            Some("level") => level(&args[1..], out),
+            Some("lockdown") => lockdown(out),
+            Some("measure") => measure(out),
+            Some("update") => update(&args[1..], out),
+            Some(other) => {
+                crate::eprintln!("qsfctl: unknown subcommand '{}'", other);
+                usage()
+            }
+        }
+    }
+}
+
+fn usage() -> i32 {
+    crate::eprintln!("Usage: qsfctl [status]");
+    crate::eprintln!("       qsfctl trace [PID]     - trace syscalls of PID (no PID: list traced)");
+    crate::eprintln!("       qsfctl untrace PID     - stop tracing PID");
+    crate::eprintln!("       qsfctl audit [COUNT]   - show the last COUNT audit entries");
+    crate::eprintln!("       qsfctl policies        - list loaded policies and their rules");
+    crate::eprintln!("       qsfctl resolve POLICY deny-overrides|first-match|allow-overrides");
+    crate::eprintln!("       qsfctl test SUBJECT OBJECT PERM - dry-run a policy decision");
+    crate::eprintln!("       qsfctl profiles        - list confinement profiles and bindings");
+    crate::eprintln!("       qsfctl bind EXEC PROFILE - confine EXEC (path glob) with PROFILE on exec");
+    crate::eprintln!("       qsfctl unbind EXEC");
+    crate::eprintln!("       qsfctl level disabled|permissive|enforcing");
+    crate::eprintln!("       qsfctl lockdown        - enforce and freeze QSF until reboot");
+    crate::eprintln!("       qsfctl measure         - show the lockdown measurement log");
+    crate::eprintln!("       qsfctl update FILE TAGFILE - apply an HMAC-authenticated policy");
+    crate::eprintln!("         SUBJECT: user:N group:N pid:N role:NAME any");
+    crate::eprintln!("         OBJECT:  file:PATH dir:PATH pid:N net:ADDR[/PREFIX]:PORT cap:NAME any");
+    EXIT_USAGE
+}
+
+fn report(err: QsfError) -> i32 {
+    match err {
+        QsfError::Locked(feature) => {
+            crate::eprintln!("qsfctl: refused: {} is disabled under lockdown", feature.name());
+        }
+        QsfError::NotFound => {
+            crate::eprintln!("qsfctl: not found");
+        }
+        QsfError::NoUpdateKey => {
+            crate::eprintln!("qsfctl: kernel was built without a policy update key");
+        }
+        QsfError::BadSignature => {
+            crate::eprintln!("qsfctl: policy authentication failed");
+        }
+        QsfError::InvalidPolicy(why) => {
+            crate::eprintln!("qsfctl: invalid policy: {}", why);
+        }
+    }
+    EXIT_FAILURE
+}
+
+fn parse_pid(arg: Option<&&str>) -> Option<u32> {
+    arg.and_then(|s| s.parse::<u32>().ok())
+}
+
+fn status(out: &mut dyn Write) -> i32 {
+    let qsf = QSF.lock();
+    writeln!(out, "QSF level:      {:?}", qsf.get_level()).ok();
+    writeln!(out, "Lockdown:       {}", if qsf.is_locked() { "engaged" } else { "off" }).ok();
+    writeln!(out, "Audit entries:  {}", qsf.get_audit_log().len()).ok();
+    writeln!(out, "Traced pids:    {}", qsf.traced_processes().len()).ok();
+    EXIT_SUCCESS
+}
+
+fn trace(args: &[&str], out: &mut dyn Write) -> i32 {
+    if args.is_empty() {
+        let qsf = QSF.lock();
+        let traced = qsf.traced_processes();
+        if traced.is_empty() {
+            writeln!(out, "No processes are being traced").ok();
+            return EXIT_SUCCESS;
+        }
+        writeln!(out, " PID  RECORDED  SUPPRESSED").ok();
+        for (pid, state) in traced {
+            writeln!(out, "  {}  {}  {}", pid, state.total, state.suppressed).ok();
+        }
+        return EXIT_SUCCESS;
+    }
+
+    let pid = match parse_pid(args.first()) {
+        Some(pid) => pid,
+        None => return usage(),
+    };
+    if crate::kernel::scheduler::SCHEDULER.lock().get_task(pid).is_none() {
+        crate::eprintln!("qsfctl: no such process: {}", pid);
+        return EXIT_FAILURE;
+    }
+    if QSF.lock().trace_process(pid) {
+        writeln!(out, "Tracing syscalls of pid {}", pid).ok();
+    } else {
+        writeln!(out, "pid {} is already traced", pid).ok();
+    }
+    EXIT_SUCCESS
+}
+
+fn untrace(args: &[&str], out: &mut dyn Write) -> i32 {
+    let pid = match parse_pid(args.first()) {
+        Some(pid) => pid,
+        None => return usage(),
+    };
+    if QSF.lock().untrace_process(pid) {
+        writeln!(out, "Stopped tracing pid {}", pid).ok();
+        EXIT_SUCCESS
+    } else {
+        crate::eprintln!("pid {} is not traced", pid);
+        EXIT_FAILURE
+    }
+}
+
+fn audit(args: &[&str], out: &mut dyn Write) -> i32 {
+    let count = args.first().and_then(|s| s.parse::<usize>().ok()).unwrap_or(20);
+    let qsf = QSF.lock();
+    let log = qsf.get_audit_log();
+    let start = log.len().saturating_sub(count);
+    for entry in &log[start..] {
+        let decision = match entry.decision {
+            AccessDecision::Allow => "allow",
+            AccessDecision::Deny => "deny",
+            AccessDecision::Audit => "audit",
+        };
+        writeln!(out, "[{}] pid={} uid={} {} {} {} {}",
+            entry.timestamp, entry.pid, entry.uid, decision, entry.action, entry.resource, entry.reason).ok();
+    }
+    EXIT_SUCCESS
+}
+
+fn policies(out: &mut dyn Write) -> i32 {
+    let qsf = QSF.lock();
+    for policy in qsf.policies() {
+        writeln!(out, "{} v{} ({}, {})", policy.name, policy.version,
+            if policy.enabled { "enabled" } else { "disabled" }, policy.resolution.name()).ok();
+        for (i, rule) in policy.rules.iter().enumerate() {
+            writeln!(out, "  #{} prio={} {:?} {} {} {}",
+                i, rule.priority, rule.action, rule.subject, rule.object, rule.permissions).ok();
+        }
+    }
+    EXIT_SUCCESS
+}
+
+fn resolve(args: &[&str], out: &mut dyn Write) -> i32 {
+    if args.len() < 2 {
+        return usage();
+    }
+    let resolution = match ConflictResolution::parse(args[1]) {
+        Some(r) => r,
+        None => {
+            crate::eprintln!("qsfctl: unknown strategy '{}'", args[1]);
+            return EXIT_FAILURE;
+        }
+    };
+    match QSF.lock().policy_mut(args[0]) {
+        Ok(policy) => {
+            policy.set_resolution(resolution);
+            writeln!(out, "Policy '{}' now uses {}", args[0], resolution.name()).ok();
+            EXIT_SUCCESS
+        }
+        Err(QsfError::NotFound) => {
+            crate::eprintln!("qsfctl: no such policy '{}'", args[0]);
+            EXIT_FAILURE
+        }
+        Err(e) => report(e),
+    }
+}
+
+fn test(args: &[&str], out: &mut dyn Write) -> i32 {
+    if args.len() < 3 {
+        return usage();
+    }
+    let subject = match Subject::parse(args[0]) {
+        Some(s) => s,
+        None => {
+            crate::eprintln!("qsfctl: invalid subject '{}'", args[0]);
+            return EXIT_FAILURE;
+        }
+    };
+    let object = match Object::parse(args[1]) {
+        Some(o) => o,
+        None => {
+            crate::eprintln!("qsfctl: invalid object '{}'", args[1]);
+            return EXIT_FAILURE;
+        }
+    };
+    let permission = args[2];
+
+    let qsf = QSF.lock();
+    if qsf.policies().is_empty() {
+        writeln!(out, "No policies loaded").ok();
+        return EXIT_SUCCESS;
+    }
+    for (name, decision) in qsf.evaluate_policies(&subject, &object, permission) {
+        writeln!(out, "{}: {:?} ({})", name, decision.action, decision.reason).ok();
+        if let (Some(i), Some(policy)) = (decision.rule, qsf.policies().iter().find(|p| p.name == name)) {
+            let rule = &policy.rules[i];
+            writeln!(out, "  matched rule #{} prio={} {} {} {}",
+                i, rule.priority, rule.subject, rule.object, rule.permissions).ok();
+        }
+    }
+    EXIT_SUCCESS
+}
+
+fn profiles(out: &mut dyn Write) -> i32 {
+    let qsf = QSF.lock();
+    let confinement = qsf.confinement();
+    for profile in confinement.profiles() {
+        writeln!(out, "{} (network: {}, fork: {}, exec: {}, on exec: {:?})",
+            profile.name,
+            if profile.network_allowed { "yes" } else { "no" },
+            if profile.can_fork { "yes" } else { "no" },
+            if profile.can_exec { "yes" } else { "no" },
+            profile.on_exec).ok();
+        for path in &profile.allowed_paths {
+            writeln!(out, "  allow {}", path).ok();
+        }
+        for path in &profile.denied_paths {
+            writeln!(out, "  deny  {}", path).ok();
+        }
+        match &profile.capabilities {
+            None => {
+                writeln!(out, "  caps  (unrestricted)").ok();
+            }
+            Some(caps) => {
+                writeln!(out, "  caps  {:?}", caps).ok();
+            }
+        }
+    }
+    writeln!(out).ok();
+    writeln!(out, "Bindings:").ok();
+    for (exec, profile) in confinement.bindings() {
+        writeln!(out, "  {} -> {}", exec, profile).ok();
+    }
+    EXIT_SUCCESS
+}
+
+fn bind(args: &[&str], out: &mut dyn Write) -> i32 {
+    if args.len() < 2 {
+        return usage();
+    }
+    match QSF.lock().bind_profile(args[0], args[1]) {
+        Ok(()) => {
+            writeln!(out, "{} now runs under profile '{}'", args[0], args[1]).ok();
+            EXIT_SUCCESS
+        }
+        Err(QsfError::NotFound) => {
+            crate::eprintln!("qsfctl: no such profile '{}'", args[1]);
+            EXIT_FAILURE
+        }
+        Err(e) => report(e),
+    }
+}
+
+fn unbind(args: &[&str]) -> i32 {
+    if args.is_empty() {
+        return usage();
+    }
+    match QSF.lock().unbind_profile(args[0]) {
+        Ok(()) => EXIT_SUCCESS,
+        Err(QsfError::NotFound) => {
+            crate::eprintln!("qsfctl: {} is not bound", args[0]);
+            EXIT_FAILURE
+        }
+        Err(e) => report(e),
+    }
+}
+
+fn level(args: &[&str], out: &mut dyn Write) -> i32 {
+    let level = match args.first().copied() {
+        Some("disabled") => SecurityLevel::Disabled,
+        Some("permissive") => SecurityLevel::Permissive,
+        Some("enforcing") => SecurityLevel::Enforcing,
+        _ => return usage(),
+    };
+    match QSF.lock().set_level(level) {
+        Ok(()) => {
+            writeln!(out, "QSF level set to {:?}", level).ok();
+            EXIT_SUCCESS
+        }
+        Err(e) => report(e),
+    }
+}
+
+fn lockdown(out: &mut dyn Write) -> i32 {
+    let mut qsf = QSF.lock();
+    if qsf.is_locked() {
+        writeln!(out, "Lockdown is already engaged").ok();
+        return EXIT_SUCCESS;
+    }
+    qsf.lock_down();
+    writeln!(out, "Lockdown engaged: QSF is enforcing and frozen until reboot").ok();
+    EXIT_SUCCESS
+}
+
+fn measure(out: &mut dyn Write) -> i32 {
+    let qsf = QSF.lock();
+    let log = qsf.measurements();
+    if log.entries().is_empty() {
+        writeln!(out, "No measurements (lockdown not engaged)").ok();
+        return EXIT_SUCCESS;
+    }
+    for m in log.entries() {
+        writeln!(out, "{}  {}", crate::qsf::crypto::to_hex(&m.digest), m.what).ok();
+    }
+    writeln!(out, "PCR: {}", crate::qsf::crypto::to_hex(&log.pcr())).ok();
+    EXIT_SUCCESS
+}
+
+fn update(args: &[&str], out: &mut dyn Write) -> i32 {
+    if args.len() < 2 {
+        return usage();
+    }
+    let text = match crate::fs::vfs::api::read_file(args[0]) {
+        Ok(data) => data,
+        Err(e) => {
+            crate::eprintln!("qsfctl: cannot read '{}': {:?}", args[0], e);
+            return EXIT_FAILURE;
+        }
+    };
+    let tag = match crate::fs::vfs::api::read_file(args[1]) {
+        Ok(data) => core::str::from_utf8(&data).ok().and_then(crate::qsf::crypto::from_hex),
+        Err(e) => {
+            crate::eprintln!("qsfctl: cannot read '{}': {:?}", args[1], e);
+            return EXIT_FAILURE;
+        }
+    };
+    let tag = match tag {
+        Some(tag) => tag,
+        None => {
+            crate::eprintln!("qsfctl: '{}' is not a hex HMAC-SHA256 tag", args[1]);
+            return EXIT_FAILURE;
+        }
+    };
+    match QSF.lock().apply_policy_update(&text, &tag) {
+        Ok(name) => {
+            writeln!(out, "Policy '{}' updated", name).ok();
+            EXIT_SUCCESS
+        }
+        Err(e) => report(e),
+    }
+}
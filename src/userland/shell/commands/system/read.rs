@@ -0,0 +1,86 @@
+// read - Read a line into shell variables
+//
+// Reads a line from the console, printing PROMPT to stderr first if given.
+// The line is split into fields at spaces and tabs; each NAME gets the
+// next field and the last NAME the rest of the line, so `read line` keeps
+// it whole, bar leading and trailing blanks. Names left over are set
+// empty; with no NAME the line goes to REPLY. Backslashes are kept as
+// they are. Exits 1 at end of input (^D on an empty line).
+//
+//   read -p "Name: " first rest
+//   if read -p "Continue? " answer && test "$answer" = y; then echo ok; fi
+
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::shell::console::Console;
+use crate::userland::shell::vars;
+
+pub struct Read;
+
+impl Command for Read {
+    fn name(&self) -> &'static str {
+        "read"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-p PROMPT] [NAME...]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Read a line from the console into variables"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        let (prompt, names) = match args {
+            ["-p", prompt, names @ ..] => (Some(*prompt), names),
+            ["-p"] => return self.usage(),
+            [option, ..] if option.starts_with('-') && *option != "-" => return self.usage(),
+            names => (None, names),
+        };
+        if let Some(bad) = names.iter().find(|name| !vars::is_valid_name(name)) {
+            crate::eprintln!("read: `{}': not a valid identifier", bad);
+            return EXIT_FAILURE;
+        }
+
+        if let Some(prompt) = prompt {
+            crate::eprint!("{}", prompt);
+        }
+        let Some(line) = Console::default().read_line() else {
+            return EXIT_FAILURE;
+        };
+        let names = if names.is_empty() { &["REPLY"][..] } else { names };
+        for (name, value) in names.iter().zip(split(&line, names.len())) {
+            vars::set(name, value);
+        }
+        EXIT_SUCCESS
+    }
+}
+
+/// `line` in `count` fields, the last holding whatever the others leave;
+/// missing fields are empty
+fn split(line: &str, count: usize) -> impl Iterator<Item = &str> {
+    let blank = |c: char| c == ' ' || c == '\t';
+    let mut rest = line.trim_matches(blank);
+    (0..count).map(move |index| {
+        if index + 1 == count {
+            return core::mem::take(&mut rest);
+        }
+        let (field, after) = rest.split_once(blank).unwrap_or((rest, ""));
+        rest = after.trim_start_matches(blank);
+        field
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test_case]
+    fn test_split() {
+        let fields = |line, count| split(line, count).collect::<Vec<_>>();
+        assert_eq!(fields("  a b\tc  d ", 1), ["a b\tc  d"]);
+        assert_eq!(fields("a  b c", 2), ["a", "b c"]);
+        assert_eq!(fields("a", 3), ["a", "", ""]);
+    }
+}
@@ -0,0 +1,32 @@
+// reboot - Restart the machine
+
+use core::fmt::Write;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::qsf::Capability;
+use crate::userland::shell::command::{Command, EXIT_FAILURE};
+
+pub struct Reboot;
+
+impl Command for Reboot {
+    fn name(&self) -> &'static str {
+        "reboot"
+    }
+
+    fn description(&self) -> &'static str {
+        "Sync filesystems, save the kernel log and restart"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        if !args.is_empty() {
+            return self.usage();
+        }
+        let pid = crate::userland::shell::shell_pid();
+        let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+        if !crate::qsf::has_capability(uid, Capability::CapSysBoot) {
+            crate::eprintln!("reboot: Operation not permitted");
+            return EXIT_FAILURE;
+        }
+        crate::println!("Rebooting...");
+        crate::kernel::reboot()
+    }
+}
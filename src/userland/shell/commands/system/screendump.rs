@@ -0,0 +1,39 @@
+// screendump - Save the console screen to a file
+
+use core::fmt::Write;
+use crate::kernel::screendump::{self, DEFAULT_PATH};
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Screendump;
+
+impl Command for Screendump {
+    fn name(&self) -> &'static str {
+        "screendump"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[FILE]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Save the screen as text to FILE (default /tmp/screen.txt)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let path = match args {
+            [] => DEFAULT_PATH,
+            [path] => *path,
+            _ => return self.usage(),
+        };
+        match screendump::save(path) {
+            Ok(size) => {
+                writeln!(out, "{}: {} bytes", path, size).ok();
+                EXIT_SUCCESS
+            }
+            Err(e) => {
+                crate::eprintln!("screendump: {}: {:?}", path, e);
+                EXIT_FAILURE
+            }
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// set - List shell variables
+
+use core::fmt::Write;
+use crate::userland::shell::command::{Command, EXIT_SUCCESS};
+use crate::userland::shell::vars;
+
+pub struct Set;
+
+impl Command for Set {
+    fn name(&self) -> &'static str {
+        "set"
+    }
+
+    fn description(&self) -> &'static str {
+        "List shell variables (assign with NAME=value)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        if !args.is_empty() {
+            return self.usage();
+        }
+        for (name, value) in vars::snapshot() {
+            writeln!(out, "{}='{}'", name, value.replace('\'', "'\\''")).ok();
+        }
+        EXIT_SUCCESS
+    }
+}
@@ -0,0 +1,70 @@
+// ., source - Run a script in the current shell
+//
+// Runs each line of FILE as if it were typed at the prompt, so variables
+// it sets and directories it changes to are still in effect afterwards,
+// unlike a script run as a program. Each line is a command line of its
+// own: an `if` has to end with its `fi` on the same line. Blank lines and
+// `#` comments are skipped. Exits with the status of the last line run,
+// or 0 if none was. A login runs /etc/profile and ~/.profile this way.
+//
+//   echo 'PATH=/bin:/usr/bin:$HOME/bin' > ~/.profile
+//   . ~/.profile
+
+use core::fmt::Write;
+use crate::kernel::sys::errno::Errno;
+use crate::userland::shell::command::{Command, EXIT_FAILURE};
+
+pub struct Dot;
+pub struct Source;
+
+impl Command for Dot {
+    fn name(&self) -> &'static str {
+        "."
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "FILE"
+    }
+
+    fn description(&self) -> &'static str {
+        "Run the commands in FILE in this shell"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        match args {
+            [path] => source(".", path),
+            _ => self.usage(),
+        }
+    }
+}
+
+impl Command for Source {
+    fn name(&self) -> &'static str {
+        "source"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "FILE"
+    }
+
+    fn description(&self) -> &'static str {
+        "Same as ."
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        match args {
+            [path] => source("source", path),
+            _ => self.usage(),
+        }
+    }
+}
+
+fn source(command: &str, path: &str) -> i32 {
+    match crate::userland::shell::source(path) {
+        Ok(status) => status,
+        Err(e) => {
+            crate::eprintln!("{}: {}: {}", command, path, Errno::from(e).description());
+            EXIT_FAILURE
+        }
+    }
+}
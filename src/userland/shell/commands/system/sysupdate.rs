@@ -0,0 +1,96 @@
+// sysupdate - Install a signed kernel on the boot partition
+//
+// `sysupdate IMAGE` checks IMAGE, a kernel ELF, against IMAGE.sig and the
+// keys in /etc/sysupdate/keys, then writes it to the inactive slot of the
+// FAT32 volume named by `bootpart=`, keeping the active kernel as the
+// fallback; the next boot tries it. `status` shows the slots. See
+// kernel::sysupdate. Installing needs CAP_SYS_BOOT.
+//
+//   sysupdate /mnt/disk/qunix
+//   sysupdate status
+
+use core::fmt::Write;
+use alloc::format;
+use alloc::string::String;
+use crate::fs::vfs::api as vfs_api;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::kernel::sysupdate::{self, Slot, SysupdateError};
+use crate::qsf::crypto;
+use crate::qsf::Capability;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Sysupdate;
+
+impl Command for Sysupdate {
+    fn name(&self) -> &'static str {
+        "sysupdate"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "IMAGE | status"
+    }
+
+    fn description(&self) -> &'static str {
+        "Install a signed kernel image on the boot partition"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        match args {
+            ["status"] => match sysupdate::status() {
+                Ok(state) => {
+                    for slot in [Slot::A, Slot::B] {
+                        let role = if slot == state.active {
+                            format!("active, {}", state.status)
+                        } else if Some(slot) == state.fallback {
+                            String::from("fallback")
+                        } else {
+                            String::from("unused")
+                        };
+                        let digest = state.digest(slot).map_or(String::from("-"), |digest| crypto::to_hex(digest));
+                        writeln!(out, "{}\t{}\t{}", slot.file(), role, digest).ok();
+                    }
+                    EXIT_SUCCESS
+                }
+                Err(e) => fail(sysupdate::PARAM, &e),
+            },
+            [path] => {
+                let pid = crate::userland::shell::shell_pid();
+                let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+                if !crate::qsf::has_capability(uid, Capability::CapSysBoot) {
+                    crate::eprintln!("sysupdate: Operation not permitted");
+                    return EXIT_FAILURE;
+                }
+                let signature_path = format!("{}.sig", path);
+                let (image, signature) = match (vfs_api::read_file(path), vfs_api::read_file(&signature_path)) {
+                    (Ok(image), Ok(signature)) => (image, signature),
+                    (Err(e), _) => {
+                        crate::eprintln!("sysupdate: {}: {:?}", path, e);
+                        return EXIT_FAILURE;
+                    }
+                    (_, Err(e)) => {
+                        crate::eprintln!("sysupdate: {}: {:?}", signature_path, e);
+                        return EXIT_FAILURE;
+                    }
+                };
+                let signature = String::from_utf8_lossy(&signature);
+                match sysupdate::install(&image, &signature) {
+                    Ok((state, key)) => {
+                        write!(out, "{}: installed, signed by {}; starts at the next boot", state.active.file(), key).ok();
+                        match state.fallback {
+                            Some(fallback) => writeln!(out, ", with {} to fall back on", fallback.file()).ok(),
+                            None => writeln!(out).ok(),
+                        };
+                        EXIT_SUCCESS
+                    }
+                    Err(e) => fail(path, &e),
+                }
+            }
+            _ => self.usage(),
+        }
+    }
+}
+
+fn fail(what: &str, error: &SysupdateError) -> i32 {
+    crate::eprintln!("sysupdate: {}: {}", what, error);
+    EXIT_FAILURE
+}
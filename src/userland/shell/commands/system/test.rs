@@ -0,0 +1,180 @@
+// test, [ - Evaluate a condition
+//
+// Exits 0 if EXPRESSION is true, 1 if it is false and 2 if it cannot be
+// understood. `[` is the same command wanting `]` as its last argument.
+// EXPRESSION is read by how many arguments it has, as POSIX has it:
+//
+//   (none)              false
+//   STRING              STRING is not empty
+//   -n STRING           STRING is not empty
+//   -z STRING           STRING is empty
+//   -e|-f|-d|-s FILE    FILE exists; is a regular file; a directory; not empty
+//   -r|-w|-x FILE       the shell may read, write, execute/search FILE
+//   S1 = S2, S1 != S2   the strings are equal; differ
+//   N1 -eq N2           likewise -ne, -lt, -le, -gt, -ge, for integers
+//   ! EXPRESSION        EXPRESSION is false
+//   ( EXPRESSION )      EXPRESSION, of one or two arguments
+//
+//   [ -d /tmp ] && echo yes
+//   if test "$?" -ne 0; then echo failed; fi
+
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+use crate::fs::vfs::api::{self as vfs_api, AccessMode};
+use crate::fs::FileType;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+
+pub struct Test;
+pub struct Bracket;
+
+impl Command for Test {
+    fn name(&self) -> &'static str {
+        "test"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "EXPRESSION"
+    }
+
+    fn description(&self) -> &'static str {
+        "Check files, compare strings and integers"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        status("test", evaluate(args))
+    }
+}
+
+impl Command for Bracket {
+    fn name(&self) -> &'static str {
+        "["
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "EXPRESSION ]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Same as test, ended by ]"
+    }
+
+    fn run(&self, args: &[&str], _out: &mut dyn Write) -> i32 {
+        match args.split_last() {
+            Some((&"]", args)) => status("[", evaluate(args)),
+            _ => {
+                crate::eprintln!("[: missing `]'");
+                EXIT_USAGE
+            }
+        }
+    }
+}
+
+fn status(name: &str, result: Result<bool, String>) -> i32 {
+    match result {
+        Ok(true) => EXIT_SUCCESS,
+        Ok(false) => EXIT_FAILURE,
+        Err(e) => {
+            crate::eprintln!("{}: {}", name, e);
+            EXIT_USAGE
+        }
+    }
+}
+
+fn evaluate(args: &[&str]) -> Result<bool, String> {
+    match *args {
+        [] => Ok(false),
+        [s] => Ok(!s.is_empty()),
+        ["!", a] => evaluate(&[a]).map(|b| !b),
+        [op, operand] => unary(op, operand),
+        [a, op, b] if is_binary(op) => binary(a, op, b),
+        ["!", a, b] => evaluate(&[a, b]).map(|b| !b),
+        ["(", a, ")"] => evaluate(&[a]),
+        [_, op, _] => Err(format!("{}: binary operator expected", op)),
+        ["!", a, b, c] => evaluate(&[a, b, c]).map(|b| !b),
+        ["(", a, b, ")"] => evaluate(&[a, b]),
+        _ => Err(String::from("too many arguments")),
+    }
+}
+
+fn is_binary(op: &str) -> bool {
+    matches!(op, "=" | "!=" | "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge")
+}
+
+fn unary(op: &str, operand: &str) -> Result<bool, String> {
+    let access = |mode| {
+        let (uid, gid) = crate::kernel::scheduler::SCHEDULER.lock().current().map_or((0, 0), |task| (task.uid, task.gid));
+        vfs_api::access(operand, uid, gid, mode).is_ok()
+    };
+    let stat = || vfs_api::stat(operand).ok();
+    Ok(match op {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-e" => stat().is_some(),
+        "-f" => stat().is_some_and(|st| st.mode.file_type() == FileType::Regular),
+        "-d" => stat().is_some_and(|st| st.mode.file_type() == FileType::Directory),
+        "-s" => stat().is_some_and(|st| st.size > 0),
+        "-r" => access(AccessMode::R_OK),
+        "-w" => access(AccessMode::W_OK),
+        "-x" => access(AccessMode::X_OK),
+        _ => return Err(format!("{}: unary operator expected", op)),
+    })
+}
+
+fn binary(a: &str, op: &str, b: &str) -> Result<bool, String> {
+    let int = |s: &str| s.trim().parse::<i64>().map_err(|_| format!("{}: integer expression expected", s));
+    Ok(match op {
+        "=" => a == b,
+        "!=" => a != b,
+        "-eq" => int(a)? == int(b)?,
+        "-ne" => int(a)? != int(b)?,
+        "-lt" => int(a)? < int(b)?,
+        "-le" => int(a)? <= int(b)?,
+        "-gt" => int(a)? > int(b)?,
+        "-ge" => int(a)? >= int(b)?,
+        _ => unreachable!("not a binary operator: {}", op),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::vfs::VFS;
+    use crate::fs::FileMode;
+
+    fn test(args: &[&str]) -> i32 {
+        Test.run(args, &mut String::new())
+    }
+
+    #[test_case]
+    fn test_strings_and_integers() {
+        assert_eq!(test(&[]), EXIT_FAILURE);
+        assert_eq!(test(&["x"]), EXIT_SUCCESS);
+        assert_eq!(test(&["-z", ""]), EXIT_SUCCESS);
+        assert_eq!(test(&["a", "!=", "b"]), EXIT_SUCCESS);
+        assert_eq!(test(&["!", "a", "=", "a"]), EXIT_FAILURE);
+        assert_eq!(test(&["-3", "-lt", "2"]), EXIT_SUCCESS);
+        assert_eq!(test(&["10", "-le", "9"]), EXIT_FAILURE);
+        assert_eq!(test(&["1", "-eq", "one"]), EXIT_USAGE);
+        // A binary operator in the middle wins over the parentheses
+        assert_eq!(Bracket.run(&["(", "=", ")", "]"], &mut String::new()), EXIT_FAILURE);
+        assert_eq!(Bracket.run(&["(", "-n", "x", ")", "]"], &mut String::new()), EXIT_SUCCESS);
+        assert_eq!(Bracket.run(&["x"], &mut String::new()), EXIT_USAGE);
+    }
+
+    #[test_case]
+    fn test_files() {
+        {
+            let mut vfs = VFS.lock();
+            vfs.create_directory("/tmp/t", FileMode::new(0o755)).unwrap();
+            vfs.create_file("/tmp/t/empty", FileMode::new(0o644)).unwrap();
+        }
+        assert_eq!(test(&["-d", "/tmp/t"]), EXIT_SUCCESS);
+        assert_eq!(test(&["-f", "/tmp/t"]), EXIT_FAILURE);
+        assert_eq!(test(&["-f", "/tmp/t/empty"]), EXIT_SUCCESS);
+        assert_eq!(test(&["-s", "/tmp/t/empty"]), EXIT_FAILURE);
+        assert_eq!(test(&["-e", "/tmp/t/none"]), EXIT_FAILURE);
+        assert_eq!(test(&["-x", "/tmp/t/empty"]), EXIT_FAILURE);
+        assert_eq!(test(&["-w", "/tmp/t/empty"]), EXIT_SUCCESS);
+    }
+}
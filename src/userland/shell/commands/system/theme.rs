@@ -0,0 +1,82 @@
+// theme - Show or change the console colors and font size
+//
+// With no arguments it prints the theme and font in use and the themes
+// there are (see kernel::theme). THEME changes the colors of the whole
+// console at once, text already on it included; -f large shows it in
+// characters twice the size on the framebuffer, and -f normal goes back
+// to the text screen. Changing either needs CAP_SYS_TTY_CONFIG and is
+// written to /etc/console.conf, which is read at boot.
+//
+//   theme high-contrast
+//   theme -f large light
+
+use core::fmt::Write;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::kernel::theme::{self, FontSize, THEMES};
+use crate::qsf::Capability;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Theme;
+
+impl Command for Theme {
+    fn name(&self) -> &'static str {
+        "theme"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-f normal|large] [THEME]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show the console themes, or change the theme or font size"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "f:") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("theme: {}", e);
+                return self.usage();
+            }
+        };
+        let mut conf = theme::current();
+        match (&opts.operands[..], opts.value('f')) {
+            ([], None) => {
+                writeln!(out, "Theme: {}, {} font", conf.theme.name, conf.font.name()).ok();
+                writeln!(out).ok();
+                for t in THEMES {
+                    let mark = if t == conf.theme { '*' } else { ' ' };
+                    writeln!(out, "{} {:<14} {}", mark, t.name, t.description).ok();
+                }
+                return EXIT_SUCCESS;
+            }
+            ([name], _) => match theme::find(name) {
+                Some(t) => conf.theme = t,
+                None => {
+                    crate::eprintln!("theme: {}: no such theme", name);
+                    return EXIT_FAILURE;
+                }
+            },
+            ([], Some(_)) => {}
+            _ => return self.usage(),
+        }
+        if let Some(size) = opts.value('f') {
+            match FontSize::parse(size) {
+                Some(size) => conf.font = size,
+                None => return self.usage(),
+            }
+        }
+        let pid = crate::userland::shell::shell_pid();
+        let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+        if !crate::qsf::has_capability(uid, Capability::CapSysTtyConfig) {
+            crate::eprintln!("theme: Operation not permitted");
+            return EXIT_FAILURE;
+        }
+        if let Err(e) = theme::set_and_save(conf) {
+            crate::eprintln!("theme: {}", e);
+            return EXIT_FAILURE;
+        }
+        EXIT_SUCCESS
+    }
+}
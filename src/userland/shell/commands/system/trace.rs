@@ -0,0 +1,54 @@
+// trace - Control the kernel tracepoints
+//
+// `on` starts recording task switches, syscalls, page faults and disk I/O
+// into the per-CPU trace rings, `off` stops and `clear` empties them; all
+// three need CAP_SYS_ADMIN. `dump` prints what was recorded, as
+// /proc/trace does, and with no argument the state and counts are shown.
+
+use core::fmt::Write;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::kernel::trace;
+use crate::qsf::Capability;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Trace;
+
+impl Command for Trace {
+    fn name(&self) -> &'static str {
+        "trace"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[on|off|clear|dump]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Record kernel tracepoints (on/off/clear) or print them (dump)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        match args {
+            [] => {
+                let (held, lost) = trace::counts();
+                let state = if trace::enabled() { "on" } else { "off" };
+                writeln!(out, "tracing {}, {} records, {} lost", state, held, lost).ok();
+                EXIT_SUCCESS
+            }
+            ["dump"] => {
+                out.write_str(&trace::dump()).ok();
+                EXIT_SUCCESS
+            }
+            [command @ ("on" | "off" | "clear")] => {
+                let pid = crate::userland::shell::shell_pid();
+                let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+                if !crate::qsf::has_capability(uid, Capability::CapSysAdmin) {
+                    crate::eprintln!("trace: Operation not permitted");
+                    return EXIT_FAILURE;
+                }
+                trace::control(command).ok();
+                EXIT_SUCCESS
+            }
+            _ => self.usage(),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+// type - Describe how each name would be interpreted as a command
+
+use core::fmt::Write;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::shell::path;
+use super::super::find;
+
+pub struct Type;
+
+impl Command for Type {
+    fn name(&self) -> &'static str {
+        "type"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "NAME..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Tell whether each NAME is a builtin or a file in $PATH"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("type: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.operands.is_empty() {
+            return self.usage();
+        }
+
+        // The shell has no aliases or functions, so builtins come first,
+        // then $PATH
+        let mut status = EXIT_SUCCESS;
+        for name in &opts.operands {
+            if find(name).is_some() {
+                writeln!(out, "{} is a shell builtin", name).ok();
+            } else if let Some(file) = path::lookup(name) {
+                writeln!(out, "{} is {}", name, file).ok();
+            } else {
+                crate::eprintln!("type: {}: not found", name);
+                status = EXIT_FAILURE;
+            }
+        }
+        status
+    }
+}
@@ -0,0 +1,94 @@
+// tzselect - Show or change the time zone
+//
+// With no arguments it prints the zone in use and the tzdata names it
+// knows, each with its offset now; with -l only the names. ZONE is one of
+// those names or a POSIX TZ rule such as `EST5EDT,M3.2.0,M11.1.0` (see
+// kernel::tz). Changing the zone needs CAP_SYS_TIME and is written to
+// /etc/timezone, which is read at boot.
+//
+//   tzselect Europe/Berlin
+//   tzselect '<+0545>-5:45'
+
+use core::fmt::Write;
+use crate::kernel::scheduler::SCHEDULER;
+use crate::kernel::time;
+use crate::kernel::tz::{self, Zone, ZONES};
+use crate::qsf::Capability;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct Tzselect;
+
+impl Command for Tzselect {
+    fn name(&self) -> &'static str {
+        "tzselect"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-l|ZONE]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Show the time zones, or change the one in use"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let now = time::now();
+        match args {
+            [] => {
+                let current = tz::current();
+                let local = current.local(now);
+                writeln!(out, "Time zone: {} ({}, {})", current.name, local.abbr, tz::format_offset(local.offset)).ok();
+                writeln!(out).ok();
+                for (name, rule) in ZONES {
+                    if let Some(zone) = Zone::parse(name, rule) {
+                        let local = zone.local(now);
+                        writeln!(out, "  {:<22} {:<6} {}", name, local.abbr, tz::format_offset(local.offset)).ok();
+                    }
+                }
+            }
+            ["-l"] => {
+                for (name, _) in ZONES {
+                    writeln!(out, "{}", name).ok();
+                }
+            }
+            [spec] if !spec.starts_with('-') => {
+                let Some(zone) = Zone::lookup(spec) else {
+                    crate::eprintln!("tzselect: {}: unknown time zone", spec);
+                    return EXIT_FAILURE;
+                };
+                let pid = crate::userland::shell::shell_pid();
+                let uid = SCHEDULER.lock().get_task(pid).map_or(0, |task| task.uid);
+                if !crate::qsf::has_capability(uid, Capability::CapSysTime) {
+                    crate::eprintln!("tzselect: Operation not permitted");
+                    return EXIT_FAILURE;
+                }
+                let local = zone.local(now);
+                if let Err(e) = tz::set_and_save(zone) {
+                    crate::eprintln!("tzselect: {}: {:?}", tz::TIMEZONE_FILE, e);
+                    return EXIT_FAILURE;
+                }
+                writeln!(out, "{}", local).ok();
+            }
+            _ => return self.usage(),
+        }
+        EXIT_SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test_case]
+    fn test_select_persists() {
+        let mut out = String::new();
+        assert_eq!(Tzselect.run(&["Asia/Kolkata"], &mut out), EXIT_SUCCESS);
+        assert!(out.contains(" IST "));
+        assert_eq!(tz::current().name, "Asia/Kolkata");
+        let saved = crate::fs::vfs::api::read_file(tz::TIMEZONE_FILE).unwrap();
+        assert_eq!(saved, b"Asia/Kolkata\n");
+        assert_eq!(Tzselect.run(&["Nowhere/Else"], &mut String::new()), EXIT_FAILURE);
+        tz::set(Zone::utc());
+    }
+}
@@ -0,0 +1,42 @@
+// vfs-restore - Go back to a snapshot of the in-memory filesystem
+//
+// Puts back every node changed since `vfs-snapshot NAME`, and only those,
+// and drops the snapshots taken after it. The snapshot stays, so a test
+// can be run again and again from the same files.
+
+use core::fmt::Write;
+use crate::fs::vfs::snapshot;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct VfsRestore;
+
+impl Command for VfsRestore {
+    fn name(&self) -> &'static str {
+        "vfs-restore"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "NAME"
+    }
+
+    fn description(&self) -> &'static str {
+        "Restore the VFS to a snapshot taken with vfs-snapshot"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let name = match args {
+            [name] => *name,
+            _ => return self.usage(),
+        };
+        match snapshot::restore(name) {
+            Ok(nodes) => {
+                writeln!(out, "{}: {} nodes restored", name, nodes).ok();
+                EXIT_SUCCESS
+            }
+            Err(_) => {
+                crate::eprintln!("vfs-restore: {}: no such snapshot", name);
+                EXIT_FAILURE
+            }
+        }
+    }
+}
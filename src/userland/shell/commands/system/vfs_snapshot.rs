@@ -0,0 +1,58 @@
+// vfs-snapshot - Snapshot the in-memory filesystem
+//
+// `vfs-snapshot NAME` saves the VFS as it is, for `vfs-restore NAME` to go
+// back to: nodes are shared with the live tree until one side changes
+// them, so a snapshot is cheap to take and to keep. Without arguments it
+// lists the snapshots; -d drops one. A debugging aid: grafted and device
+// files come back as nodes, not with what their filesystem or driver held.
+
+use core::fmt::Write;
+use crate::fs::vfs::snapshot;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+
+pub struct VfsSnapshot;
+
+impl Command for VfsSnapshot {
+    fn name(&self) -> &'static str {
+        "vfs-snapshot"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "[-d] [NAME]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Snapshot the VFS for vfs-restore, list snapshots or drop one (-d)"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "d") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("vfs-snapshot: {}", e);
+                return self.usage();
+            }
+        };
+        match (opts.has('d'), &opts.operands[..]) {
+            (false, []) => {
+                for name in snapshot::names() {
+                    writeln!(out, "{}", name).ok();
+                }
+                EXIT_SUCCESS
+            }
+            (false, [name]) => {
+                snapshot::take(name);
+                EXIT_SUCCESS
+            }
+            (true, [name]) => match snapshot::discard(name) {
+                Ok(()) => EXIT_SUCCESS,
+                Err(_) => {
+                    crate::eprintln!("vfs-snapshot: {}: no such snapshot", name);
+                    EXIT_FAILURE
+                }
+            },
+            _ => self.usage(),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// which - Locate the file a command name would execute
+
+use core::fmt::Write;
+use crate::userland::shell::args;
+use crate::userland::shell::command::{Command, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::userland::shell::path;
+
+pub struct Which;
+
+impl Command for Which {
+    fn name(&self) -> &'static str {
+        "which"
+    }
+
+    fn synopsis(&self) -> &'static str {
+        "NAME..."
+    }
+
+    fn description(&self) -> &'static str {
+        "Print the $PATH file each NAME would run"
+    }
+
+    fn run(&self, args: &[&str], out: &mut dyn Write) -> i32 {
+        let opts = match args::parse(args, "") {
+            Ok(opts) => opts,
+            Err(e) => {
+                crate::eprintln!("which: {}", e);
+                return self.usage();
+            }
+        };
+        if opts.operands.is_empty() {
+            return self.usage();
+        }
+
+        let mut status = EXIT_SUCCESS;
+        for name in &opts.operands {
+            match path::lookup(name) {
+                Some(file) => {
+                    writeln!(out, "{}", file).ok();
+                }
+                None => status = EXIT_FAILURE,
+            }
+        }
+        status
+    }
+}
@@ -0,0 +1,162 @@
+// Raw console input for interactive commands
+//
+// The shell's own line reader is cooked by the serial driver and has no
+// end-of-file or cursor keys. Editors and pagers read the serial port a
+// byte at a time through this instead: whole lines with their own editing
+// and echo, or single keys with VT100 escape sequences and UTF-8 decoded.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::hal::drivers::serial;
+use crate::hal::drivers::tty::Utf8Decoder;
+
+/// How long to wait for the rest of an escape sequence, in polls of the
+/// serial status port (each a microsecond or so)
+const ESCAPE_POLLS: usize = 50_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    /// Escape on its own
+    Escape,
+    /// A sequence or byte with no meaning here
+    Other,
+}
+
+/// Line and key input from the serial console
+#[derive(Default)]
+pub struct Console {
+    /// The last line ended in CR, so an LF right after it is part of it
+    after_cr: bool,
+}
+
+/// Remove the last UTF-8 character; false if there was none
+fn pop_char(line: &mut Vec<u8>) -> bool {
+    while let Some(byte) = line.pop() {
+        if byte & 0xC0 != 0x80 {
+            return true;
+        }
+    }
+    false
+}
+
+/// The next byte if one arrives shortly
+fn read_byte_soon() -> Option<u8> {
+    (0..ESCAPE_POLLS).find_map(|_| serial::read_byte())
+}
+
+impl Console {
+    /// Read a line with echo, backspace, ^U to erase it and ^C to abandon
+    /// it. Returns None for ^D on an empty line.
+    pub fn read_line(&mut self) -> Option<String> {
+        let mut line: Vec<u8> = Vec::new();
+        loop {
+            let byte = serial::read_byte_blocking();
+            let after_cr = core::mem::take(&mut self.after_cr);
+            match byte {
+                b'\n' if after_cr && line.is_empty() => {}
+                b'\r' | b'\n' => {
+                    self.after_cr = byte == b'\r';
+                    serial::write_string("\n");
+                    return Some(String::from_utf8_lossy(&line).into_owned());
+                }
+                0x04 if line.is_empty() => return None,
+                0x03 => {
+                    serial::write_string("^C\n");
+                    line.clear();
+                }
+                0x15 => {
+                    while pop_char(&mut line) {
+                        serial::write_string("\x08 \x08");
+                    }
+                }
+                0x08 | 0x7F => {
+                    if pop_char(&mut line) {
+                        serial::write_string("\x08 \x08");
+                    }
+                }
+                b'\t' | 0x20.. => {
+                    line.push(byte);
+                    serial::write_byte(byte);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Read one key press without echo
+    pub fn read_key(&mut self) -> Key {
+        loop {
+            let byte = serial::read_byte_blocking();
+            let after_cr = core::mem::take(&mut self.after_cr);
+            return match byte {
+                b'\n' if after_cr => continue,
+                b'\r' | b'\n' => {
+                    self.after_cr = byte == b'\r';
+                    Key::Enter
+                }
+                0x1B => Self::escape(),
+                0x00..=0x7F => Key::Char(byte as char),
+                _ => Self::utf8(byte),
+            };
+        }
+    }
+
+    /// Finish a multi-byte UTF-8 character that starts with `lead`
+    fn utf8(lead: u8) -> Key {
+        let mut decoder = Utf8Decoder::new();
+        let mut decoded = None;
+        decoder.push(lead, &mut |c| decoded = decoded.or(Some(c)));
+        while decoded.is_none() {
+            match read_byte_soon() {
+                Some(byte) => decoder.push(byte, &mut |c| decoded = decoded.or(Some(c))),
+                None => return Key::Other,
+            }
+        }
+        match decoded {
+            Some(char::REPLACEMENT_CHARACTER) | None => Key::Other,
+            Some(c) => Key::Char(c),
+        }
+    }
+
+    /// Decode what follows ESC: `[` or `O`, optional digits, then a final
+    /// letter or `~`
+    fn escape() -> Key {
+        match read_byte_soon() {
+            Some(b'[') | Some(b'O') => {}
+            None => return Key::Escape,
+            Some(_) => return Key::Other,
+        }
+        let mut number = 0u32;
+        loop {
+            match read_byte_soon() {
+                Some(digit @ b'0'..=b'9') => number = number.saturating_mul(10).saturating_add((digit - b'0') as u32),
+                Some(b'A') => return Key::Up,
+                Some(b'B') => return Key::Down,
+                Some(b'C') => return Key::Right,
+                Some(b'D') => return Key::Left,
+                Some(b'H') => return Key::Home,
+                Some(b'F') => return Key::End,
+                Some(b'~') => {
+                    return match number {
+                        1 | 7 => Key::Home,
+                        4 | 8 => Key::End,
+                        5 => Key::PageUp,
+                        6 => Key::PageDown,
+                        _ => Key::Other,
+                    }
+                }
+                _ => return Key::Other,
+            }
+        }
+    }
+}
@@ -0,0 +1,227 @@
+// Pathname expansion: `*`, `?` and `[...]` matched against the VFS
+//
+// Patterns come from the tokenizer with quoted characters escaped by a
+// backslash, so `\*` is a literal star. A pattern that matches nothing is
+// passed on as written, minus the escapes, as POSIX sh does.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::vec;
+use crate::fs::vfs::VFS;
+
+/// Whether `pattern` has an unescaped wildcard
+pub fn has_magic(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Drop the escaping backslashes from `pattern`
+pub fn unescape(pattern: &str) -> String {
+    let mut out = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Match `c` against the bracket expression opening at `p[start]`.
+/// Returns whether it matched and the index past the closing `]`, or None
+/// if the bracket is never closed (it is then an ordinary character).
+fn match_class(p: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = matches!(p.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let lo = match p.get(i)? {
+            ']' if !first => break,
+            '\\' => {
+                i += 1;
+                *p.get(i)?
+            }
+            &ch => ch,
+        };
+        first = false;
+        i += 1;
+        let hi = if p.get(i) == Some(&'-') && p.get(i + 1).map_or(false, |&ch| ch != ']') {
+            i += 1;
+            let hi = match p[i] {
+                '\\' => {
+                    i += 1;
+                    *p.get(i)?
+                }
+                ch => ch,
+            };
+            i += 1;
+            hi
+        } else {
+            lo
+        };
+        if lo <= c && c <= hi {
+            matched = true;
+        }
+    }
+    Some((matched != negate, i + 1))
+}
+
+/// If the single-character pattern element at `p[pi]` matches `c`,
+/// the index of the next element.
+fn match_one(p: &[char], pi: usize, c: char) -> Option<usize> {
+    match p[pi] {
+        '?' => Some(pi + 1),
+        '[' => match match_class(p, pi, c) {
+            Some((true, next)) => Some(next),
+            Some((false, _)) => None,
+            None => (c == '[').then(|| pi + 1),
+        },
+        '\\' if pi + 1 < p.len() => (p[pi + 1] == c).then(|| pi + 2),
+        ch => (ch == c).then(|| pi + 1),
+    }
+}
+
+/// Whether `name` matches the single path component `pattern`
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    let (mut pi, mut ni) = (0, 0);
+    // Where to resume after the last `*`: pattern index, name index
+    let mut star: Option<(usize, usize)> = None;
+
+    while ni < n.len() {
+        if pi < p.len() && p[pi] == '*' {
+            pi += 1;
+            star = Some((pi, ni));
+            continue;
+        }
+        if pi < p.len() {
+            if let Some(next) = match_one(&p, pi, n[ni]) {
+                pi = next;
+                ni += 1;
+                continue;
+            }
+        }
+        match star {
+            Some((sp, sn)) => {
+                pi = sp;
+                ni = sn + 1;
+                star = Some((sp, sn + 1));
+            }
+            None => return false,
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+fn join(base: &str, name: &str) -> String {
+    let mut path = String::from(base);
+    if !path.is_empty() && !path.ends_with('/') {
+        path.push('/');
+    }
+    path.push_str(name);
+    path
+}
+
+/// Names in directory `dir`, without `.` and `..`
+fn list(dir: &str) -> Vec<String> {
+    let vfs = VFS.lock();
+    match vfs.lookup_path(dir).and_then(|node| node.readdir()) {
+        Ok(entries) => entries
+            .iter()
+            .filter(|e| e.name != "." && e.name != "..")
+            .map(|e| e.name.clone())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn is_dir(path: &str) -> bool {
+    VFS.lock().lookup_path(path).map_or(false, |node| node.is_dir())
+}
+
+/// Expand `pattern` to the sorted paths it matches, or to the unescaped
+/// pattern itself if it has no wildcards or matches nothing.
+pub fn expand(pattern: &str) -> Vec<String> {
+    if !has_magic(pattern) {
+        return vec![unescape(pattern)];
+    }
+
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![String::from("/")], rest),
+        None => (vec![String::new()], pattern),
+    };
+    for component in rest.split('/').filter(|c| !c.is_empty()) {
+        let mut next = Vec::new();
+        for base in &paths {
+            if has_magic(component) {
+                let dir = if base.is_empty() { "." } else { base.as_str() };
+                // Leading dots must be matched explicitly
+                let dotted = component.starts_with('.');
+                for name in list(dir) {
+                    if (dotted || !name.starts_with('.')) && matches(component, &name) {
+                        next.push(join(base, &name));
+                    }
+                }
+            } else {
+                let path = join(base, &unescape(component));
+                if VFS.lock().lookup_path(&path).is_ok() {
+                    next.push(path);
+                }
+            }
+        }
+        paths = next;
+    }
+
+    if pattern.ends_with('/') {
+        paths.retain(|path| is_dir(path));
+        for path in paths.iter_mut() {
+            path.push('/');
+        }
+    }
+    if paths.is_empty() {
+        return vec![unescape(pattern)];
+    }
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_wildcards() {
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(!matches("*.txt", "notes.txt.bak"));
+        assert!(matches("a*b*c", "aXbYbc"));
+        assert!(matches("?", "x") && !matches("?", ""));
+        assert!(matches("*", ""));
+        assert!(matches("file[0-9]", "file7"));
+        assert!(!matches("file[!0-9]", "file7"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[abc", "[abc"));
+    }
+
+    #[test_case]
+    fn test_escapes() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "x"));
+        assert!(!has_magic("a\\*b"));
+        assert_eq!(unescape("a\\*b\\\\"), "a*b\\");
+    }
+}
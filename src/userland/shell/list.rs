@@ -0,0 +1,241 @@
+// Command lists and conditionals
+//
+// A line is a list of and-or lists separated by `;`. An and-or list runs
+// its first pipeline, then each pipeline after `&&` if the status so far
+// is 0 and each after `||` if it is not; its status is that of the last
+// pipeline that ran. A pipeline is commands joined by `|`, each reading
+// what the one before it wrote, with the status of the last. A command is
+// a simple command, or
+//
+//   if LIST; then LIST; [elif LIST; then LIST;]... [else LIST;] fi
+//
+// all on the line, which runs the LIST after the first `then` whose
+// condition exits 0, else the one after `else`. `if`, `then`, `elif`,
+// `else` and `fi` are only reserved where a command may start.
+//
+// Variables are expanded as the line is read, so `$?` is the status of
+// the line before, whatever ran earlier on this one.
+
+use alloc::string::String;
+use alloc::vec::{IntoIter, Vec};
+use core::iter::Peekable;
+use super::parser::Token;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Words, assignments and redirections
+    Simple(Vec<Token>),
+    If { branches: Vec<(List, List)>, otherwise: Option<List> },
+    /// Two or more commands joined by `|`
+    Pipeline(Vec<Command>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndOr {
+    pub first: Command,
+    pub rest: Vec<(Connector, Command)>,
+}
+
+pub type List = Vec<AndOr>;
+
+const RESERVED: &[&str] = &["if", "then", "elif", "else", "fi"];
+
+type Tokens = Peekable<IntoIter<Token>>;
+
+/// How a token reads in a syntax error
+fn shown(token: Option<&Token>) -> String {
+    String::from(match token {
+        None => "newline",
+        Some(Token::Pipe) => "|",
+        Some(Token::AndIf) => "&&",
+        Some(Token::OrIf) => "||",
+        Some(Token::Semi) => ";",
+        Some(Token::Redirect { append: true, .. }) => ">>",
+        Some(Token::Redirect { append: false, .. }) => ">",
+        Some(Token::Word(word)) => return word.clone(),
+        Some(Token::Assign(name, value)) => return alloc::format!("{}={}", name, value),
+    })
+}
+
+/// The reserved word `tokens` is at, if any
+fn reserved(tokens: &mut Tokens) -> Option<&'static str> {
+    match tokens.peek() {
+        Some(Token::Word(word)) => RESERVED.iter().find(|&&r| r == word).copied(),
+        _ => None,
+    }
+}
+
+/// Parse a tokenized line, or return the token a syntax error is near
+pub fn parse(tokens: Vec<Token>) -> Result<List, String> {
+    let mut tokens = tokens.into_iter().peekable();
+    let list = list(&mut tokens, &[])?;
+    match tokens.peek() {
+        None => Ok(list),
+        token => Err(shown(token)),
+    }
+}
+
+/// And-or lists up to one of the reserved words `end` or the end of the
+/// line; there must be at least one
+fn list(tokens: &mut Tokens, end: &[&str]) -> Result<List, String> {
+    let mut list = Vec::new();
+    loop {
+        list.push(and_or(tokens)?);
+        let at_end = |tokens: &mut Tokens| {
+            tokens.peek().is_none() || reserved(tokens).is_some_and(|word| end.contains(&word))
+        };
+        if at_end(tokens) {
+            return Ok(list);
+        }
+        if tokens.next_if_eq(&Token::Semi).is_none() {
+            return Err(shown(tokens.peek()));
+        }
+        if at_end(tokens) {
+            return Ok(list);
+        }
+    }
+}
+
+fn and_or(tokens: &mut Tokens) -> Result<AndOr, String> {
+    let first = pipeline(tokens)?;
+    let mut rest = Vec::new();
+    loop {
+        let connector = match tokens.peek() {
+            Some(Token::AndIf) => Connector::And,
+            Some(Token::OrIf) => Connector::Or,
+            _ => return Ok(AndOr { first, rest }),
+        };
+        tokens.next();
+        rest.push((connector, pipeline(tokens)?));
+    }
+}
+
+/// A command, or a pipeline of them if there is a `|`
+fn pipeline(tokens: &mut Tokens) -> Result<Command, String> {
+    let first = command(tokens)?;
+    if tokens.peek() != Some(&Token::Pipe) {
+        return Ok(first);
+    }
+    let mut stages = alloc::vec![first];
+    while tokens.next_if_eq(&Token::Pipe).is_some() {
+        stages.push(command(tokens)?);
+    }
+    Ok(Command::Pipeline(stages))
+}
+
+fn command(tokens: &mut Tokens) -> Result<Command, String> {
+    match reserved(tokens) {
+        Some("if") => {
+            tokens.next();
+            return conditional(tokens);
+        }
+        Some(_) => return Err(shown(tokens.peek())),
+        None => {}
+    }
+    let mut words = Vec::new();
+    while let Some(token) = tokens.next_if(|token| !token.is_operator()) {
+        words.push(token);
+    }
+    if words.is_empty() {
+        return Err(shown(tokens.peek()));
+    }
+    Ok(Command::Simple(words))
+}
+
+/// The rest of an `if`, after the word itself
+fn conditional(tokens: &mut Tokens) -> Result<Command, String> {
+    let mut branches = Vec::new();
+    loop {
+        let condition = list(tokens, &["then"])?;
+        expect(tokens, "then")?;
+        let body = list(tokens, &["elif", "else", "fi"])?;
+        branches.push((condition, body));
+        match reserved(tokens) {
+            Some("elif") => {
+                tokens.next();
+            }
+            Some("else") => {
+                tokens.next();
+                let otherwise = list(tokens, &["fi"])?;
+                expect(tokens, "fi")?;
+                return Ok(Command::If { branches, otherwise: Some(otherwise) });
+            }
+            _ => {
+                expect(tokens, "fi")?;
+                return Ok(Command::If { branches, otherwise: None });
+            }
+        }
+    }
+}
+
+fn expect(tokens: &mut Tokens, word: &str) -> Result<(), String> {
+    match reserved(tokens) {
+        Some(found) if found == word => {
+            tokens.next();
+            Ok(())
+        }
+        _ => Err(shown(tokens.peek())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::userland::shell::parser;
+
+    fn parse_line(line: &str) -> Result<List, String> {
+        parse(parser::tokenize_with(line, &|_| None).unwrap())
+    }
+
+    fn simple(words: &[&str]) -> Command {
+        Command::Simple(words.iter().map(|w| Token::Word(String::from(*w))).collect())
+    }
+
+    #[test_case]
+    fn test_and_or_lists() {
+        let list = parse_line("a && b || c; d").unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].first, simple(&["a"]));
+        assert_eq!(list[0].rest, alloc::vec![(Connector::And, simple(&["b"])), (Connector::Or, simple(&["c"]))]);
+        assert_eq!(list[1].first, simple(&["d"]));
+        assert!(parse_line("a;").is_ok());
+        assert_eq!(parse_line("&& a"), Err(String::from("&&")));
+        assert_eq!(parse_line("a ||"), Err(String::from("newline")));
+        assert_eq!(parse_line("a;; b"), Err(String::from(";")));
+    }
+
+    #[test_case]
+    fn test_conditionals() {
+        let list = parse_line("if a; then b; elif c; then d; else e fi; fi && f").unwrap();
+        let Command::If { branches, otherwise } = &list[0].first else {
+            panic!("not an if: {:?}", list[0].first);
+        };
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[1].0[0].first, simple(&["c"]));
+        // `fi` is an argument where a command cannot start
+        assert_eq!(otherwise.as_ref().unwrap()[0].first, simple(&["e", "fi"]));
+        assert_eq!(list[0].rest[0].1, simple(&["f"]));
+        assert_eq!(parse_line("if a; then b"), Err(String::from("newline")));
+        assert_eq!(parse_line("if a; fi"), Err(String::from("fi")));
+        assert_eq!(parse_line("then"), Err(String::from("then")));
+        assert_eq!(parse_line("if a; then b; fi c"), Err(String::from("c")));
+    }
+
+    #[test_case]
+    fn test_pipelines() {
+        let list = parse_line("a x | b | c && d").unwrap();
+        assert_eq!(list[0].first, Command::Pipeline(alloc::vec![simple(&["a", "x"]), simple(&["b"]), simple(&["c"])]));
+        assert_eq!(list[0].rest, alloc::vec![(Connector::And, simple(&["d"]))]);
+        let list = parse_line("if a; then b; fi | c").unwrap();
+        assert!(matches!(&list[0].first, Command::Pipeline(stages) if stages.len() == 2));
+        assert_eq!(parse_line("| a"), Err(String::from("|")));
+        assert_eq!(parse_line("a |"), Err(String::from("newline")));
+        assert_eq!(parse_line("a | ; b"), Err(String::from(";")));
+    }
+}
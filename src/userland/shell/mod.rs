@@ -1,6 +1,259 @@
 // Shell module with command-based structure
 // Organized like GNU coreutils - POSIX compatible
 
+pub mod args;
+pub mod autorun;
+pub mod command;
 pub mod commands;
+pub mod console;
+pub mod glob;
+pub mod list;
+pub mod parser;
+pub mod path;
+pub mod stream;
+pub mod vars;
 
 pub use commands::execute;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicI32, Ordering};
+use crate::fs::vfs::api::{self as vfs_api, OpenFlags};
+use crate::fs::{FileType, FsError, FsResult};
+use crate::kernel::file::OpenFile;
+use crate::kernel::pipe;
+use crate::kernel::scheduler::task::FileDescriptor;
+use crate::kernel::scheduler::{Pid, SCHEDULER};
+use command::{FdWriter, EXIT_FAILURE, EXIT_SUCCESS, EXIT_USAGE};
+use parser::Token;
+
+/// The shell runs in init's context, which may not have been made the
+/// current task yet when it starts.
+const SHELL_PID: Pid = 1;
+
+static LAST_STATUS: AtomicI32 = AtomicI32::new(0);
+
+/// Exit status of the last command line
+pub fn last_status() -> i32 {
+    LAST_STATUS.load(Ordering::Relaxed)
+}
+
+fn shell_pid() -> Pid {
+    SCHEDULER.lock().current_pid().unwrap_or(SHELL_PID)
+}
+
+/// Whether the shell's descriptor `fd` is the console, as isatty(3)
+pub fn isatty(fd: i32) -> bool {
+    crate::kernel::sys::syscalls::fd_is_console(Some(shell_pid()), fd)
+}
+
+struct Redirect {
+    fd: i32,
+    path: String,
+    append: bool,
+}
+
+/// Point `redirect.fd` of task `pid` at the target file, returning the
+/// entry it replaces.
+fn apply_redirect(pid: Pid, redirect: &Redirect) -> FsResult<Option<FileDescriptor>> {
+    let flags = OpenFlags::O_WRONLY
+        | OpenFlags::O_CREAT
+        | if redirect.append { OpenFlags::O_APPEND } else { OpenFlags::O_TRUNC };
+    let opened = vfs_api::open(&redirect.path, flags, 0o644)?;
+    if opened.mode.file_type() == FileType::Directory {
+        return Err(FsError::IsDirectory);
+    }
+    let path = crate::fs::vfs::VFS.lock().resolve_path(&redirect.path);
+    let file = OpenFile::open(opened.inode, path, flags.bits(), false);
+
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler.get_task_mut(pid).ok_or(FsError::NotSupported)?;
+    Ok(task.fds.insert(redirect.fd, FileDescriptor::new(redirect.fd, file, flags.bits())))
+}
+
+/// Point `fd` of task `pid` at the pipe end `end`, returning the entry it
+/// replaces
+fn pipe_fd(pid: Pid, fd: i32, flags: OpenFlags, end: pipe::End) -> Option<FileDescriptor> {
+    let mut scheduler = SCHEDULER.lock();
+    let task = scheduler.get_task_mut(pid)?;
+    task.fds.insert(fd, FileDescriptor::new(fd, OpenFile::pipe(end, flags.bits()), flags.bits()))
+}
+
+fn restore_fds(pid: Pid, saved: Vec<(i32, Option<FileDescriptor>)>) {
+    let mut scheduler = SCHEDULER.lock();
+    if let Some(task) = scheduler.get_task_mut(pid) {
+        for (fd, entry) in saved.into_iter().rev() {
+            match entry {
+                Some(entry) => task.fds.insert(fd, entry),
+                None => task.fds.remove(&fd),
+            };
+        }
+    }
+}
+
+fn syntax_error(near: &str) -> i32 {
+    crate::eprintln!("sh: syntax error near unexpected token `{}'", near);
+    LAST_STATUS.store(EXIT_USAGE, Ordering::Relaxed);
+    EXIT_USAGE
+}
+
+/// Run one command line: simple commands joined by `|`, `&&`, `||` and
+/// `;`, and `if ... fi` (see `list`). A simple command is `NAME ARGS...` with
+/// optional `>`, `>>`, `2>` and `2>>` redirections and leading
+/// `NAME=value` assignments. Quoting follows sh (see `parser`); unquoted
+/// `*`, `?` and `[...]` in words are expanded against the filesystem.
+/// Returns the exit status.
+pub fn run_line(line: &str) -> i32 {
+    let tokens = match parser::tokenize(line) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            crate::eprintln!("sh: {}", e);
+            LAST_STATUS.store(EXIT_USAGE, Ordering::Relaxed);
+            return EXIT_USAGE;
+        }
+    };
+
+    if tokens.is_empty() {
+        return last_status();
+    }
+
+    match list::parse(tokens) {
+        Ok(list) => run_list(&list),
+        Err(near) => syntax_error(&near),
+    }
+}
+
+/// Run the script at `path` in this shell, as `.` does: each line as if
+/// typed at the prompt, so the variables and working directory it sets
+/// stay set. Blank lines and `#` comments are skipped. Returns the
+/// status of the last line run.
+pub fn source(path: &str) -> FsResult<i32> {
+    let script = vfs_api::read_file(path)?;
+    let mut status = EXIT_SUCCESS;
+    for line in String::from_utf8_lossy(&script).lines() {
+        let line = line.trim();
+        if !line.is_empty() && !line.starts_with('#') {
+            status = run_line(line);
+        }
+    }
+    Ok(status)
+}
+
+fn run_list(list: &list::List) -> i32 {
+    let mut status = last_status();
+    for and_or in list {
+        status = run_command(&and_or.first);
+        for (connector, command) in &and_or.rest {
+            let run = match connector {
+                list::Connector::And => status == EXIT_SUCCESS,
+                list::Connector::Or => status != EXIT_SUCCESS,
+            };
+            if run {
+                status = run_command(command);
+            }
+        }
+    }
+    status
+}
+
+fn run_command(command: &list::Command) -> i32 {
+    match command {
+        list::Command::Simple(tokens) => run_simple(tokens),
+        list::Command::If { branches, otherwise } => {
+            let status = match branches.iter().find(|(condition, _)| run_list(condition) == EXIT_SUCCESS) {
+                Some((_, body)) => run_list(body),
+                None => otherwise.as_ref().map_or(EXIT_SUCCESS, run_list),
+            };
+            LAST_STATUS.store(status, Ordering::Relaxed);
+            status
+        }
+        list::Command::Pipeline(stages) => run_pipeline(stages),
+    }
+}
+
+/// Run the stages of a pipeline one after another, each with fd 1 the
+/// write end of a pipe the next has as fd 0. As none runs alongside
+/// another the pipes have no limit, so a stage never waits on the next;
+/// a stage's own redirections still win. The status is the last stage's.
+fn run_pipeline(stages: &[list::Command]) -> i32 {
+    let pid = shell_pid();
+    let mut input = None;
+    let mut status = EXIT_SUCCESS;
+    for (index, stage) in stages.iter().enumerate() {
+        let mut saved = Vec::new();
+        if let Some(reader) = input.take() {
+            saved.push((0, pipe_fd(pid, 0, OpenFlags::O_RDONLY, reader)));
+        }
+        if index + 1 < stages.len() {
+            let (reader, writer) = pipe::pipe(usize::MAX);
+            saved.push((1, pipe_fd(pid, 1, OpenFlags::O_WRONLY, writer)));
+            input = Some(reader);
+        }
+        status = run_command(stage);
+        // Putting fd 1 back closes the write end, so the next stage
+        // reads to the end of what this one wrote
+        restore_fds(pid, saved);
+    }
+    status
+}
+
+/// Run a simple command, returning its status
+fn run_simple(tokens: &[Token]) -> i32 {
+    let mut words: Vec<String> = Vec::new();
+    let mut redirects = Vec::new();
+    let mut tokens = tokens.iter().cloned();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Word(pattern) => words.extend(glob::expand(&pattern)),
+            // Built-ins share the shell's variables, so an assignment
+            // before a command outlives it
+            Token::Assign(name, value) => vars::set(&name, &value),
+            Token::Redirect { fd, append } => match tokens.next() {
+                Some(Token::Word(target)) => {
+                    redirects.push(Redirect { fd, path: glob::unescape(&target), append });
+                }
+                Some(Token::Redirect { append, .. }) => {
+                    return syntax_error(if append { ">>" } else { ">" });
+                }
+                Some(Token::Assign(..)) => unreachable!("assignments never follow a redirection"),
+                Some(_) | None => return syntax_error("newline"),
+            },
+            Token::Pipe | Token::AndIf | Token::OrIf | Token::Semi => unreachable!("operators end a simple command"),
+        }
+    }
+
+    let pid = shell_pid();
+    let mut saved = Vec::new();
+    for redirect in &redirects {
+        match apply_redirect(pid, redirect) {
+            Ok(previous) => saved.push((redirect.fd, previous)),
+            Err(e) => {
+                restore_fds(pid, saved);
+                crate::eprintln!("sh: {}: {:?}", redirect.path, e);
+                LAST_STATUS.store(EXIT_FAILURE, Ordering::Relaxed);
+                return EXIT_FAILURE;
+            }
+        }
+    }
+
+    let args: Vec<&str> = words.iter().map(String::as_str).collect();
+    let status = match args.split_first() {
+        Some((name, args)) => {
+            // Built-ins read the VFS directly, so /proc has to be current
+            // before they run, as do /dev and the list of SD cards
+            crate::hal::drivers::hotplug::poll();
+            crate::kernel::procfs::refresh();
+            command::open_stderr(Some(pid), 2);
+            let mut out = FdWriter::new(Some(pid), 1);
+            let status = execute(name, args, &mut out);
+            out.flush();
+            command::close_stderr();
+            status
+        }
+        None => EXIT_SUCCESS,
+    };
+
+    restore_fds(pid, saved);
+    LAST_STATUS.store(status, Ordering::Relaxed);
+    status
+}
@@ -0,0 +1,342 @@
+// Command line tokenizer
+//
+// Splits a line into words, variable assignments, redirection operators
+// and the `|`, `&&`, `||` and `;` that join commands, following sh quoting
+// rules:
+//
+//   'text'   everything literal
+//   "text"   literal except `$VAR`, `${VAR}`, `$?`, `$$`, and `\` before
+//            one of  $ " \ `
+//   \c       c literal, outside quotes
+//
+// Each word is returned as a glob pattern: characters that were quoted or
+// escaped have their wildcards escaped with a backslash, so expansion
+// leaves them alone. Unquoted expansions are not split into fields.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::Peekable;
+use core::str::Chars;
+use super::glob;
+use super::vars;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Word(String),
+    /// `NAME=value` before the first word; the value is unescaped
+    Assign(String, String),
+    /// `>`, `>>`, `N>` or `N>>`; the target is the next word
+    Redirect { fd: i32, append: bool },
+    /// `|`
+    Pipe,
+    /// `&&`
+    AndIf,
+    /// `||`
+    OrIf,
+    /// `;`
+    Semi,
+}
+
+impl Token {
+    /// Whether this ends a command, so another may follow
+    pub fn is_operator(&self) -> bool {
+        matches!(self, Token::Pipe | Token::AndIf | Token::OrIf | Token::Semi)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    UnterminatedQuote(char),
+    BadSubstitution,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnterminatedQuote(q) => {
+                write!(f, "unexpected EOF while looking for matching `{}'", q)
+            }
+            ParseError::BadSubstitution => write!(f, "bad substitution"),
+        }
+    }
+}
+
+/// Append `c` to a pattern so that it only ever matches itself
+fn push_literal(word: &mut String, c: char) {
+    if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+        word.push('\\');
+    }
+    word.push(c);
+}
+
+struct Lexer<'v> {
+    tokens: Vec<Token>,
+    word: String,
+    /// A word has started, even if empty (`''`)
+    in_word: bool,
+    /// Length of `word` when quoting or escaping first occurred in it
+    quoted_at: Option<usize>,
+    lookup: &'v dyn Fn(&str) -> Option<String>,
+}
+
+impl<'v> Lexer<'v> {
+    fn mark_quoted(&mut self) {
+        self.in_word = true;
+        self.quoted_at.get_or_insert(self.word.len());
+    }
+
+    fn finish_word(&mut self) {
+        // An unquoted expansion to nothing is no word at all
+        if self.in_word && !(self.word.is_empty() && self.quoted_at.is_none()) {
+            let word = core::mem::take(&mut self.word);
+            let token = match self.assignment(&word) {
+                Some((name, value)) => Token::Assign(String::from(name), glob::unescape(value)),
+                None => Token::Word(word),
+            };
+            self.tokens.push(token);
+        }
+        self.in_word = false;
+        self.quoted_at = None;
+    }
+
+    /// `NAME=value` with an unquoted name, while no word of this command
+    /// has been seen (and not as a redirection target)
+    fn assignment<'w>(&self, word: &'w str) -> Option<(&'w str, &'w str)> {
+        let start = self.tokens.iter().rposition(Token::is_operator).map_or(0, |at| at + 1);
+        if self.tokens[start..].iter().any(|t| matches!(t, Token::Word(_)))
+            || matches!(self.tokens.last(), Some(Token::Redirect { .. }))
+        {
+            return None;
+        }
+        let eq = word.find('=')?;
+        let name = &word[..eq];
+        if self.quoted_at.map_or(false, |at| at <= eq) || !vars::is_valid_name(name) {
+            return None;
+        }
+        Some((name, &word[eq + 1..]))
+    }
+
+    /// Expand the variable reference following a `$`. Quoted expansions
+    /// are taken literally; unquoted ones may still glob.
+    fn expand_var(&mut self, chars: &mut Peekable<Chars>, quoted: bool) -> Result<(), ParseError> {
+        let mut name = String::new();
+        match chars.peek().copied() {
+            Some('{') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(ParseError::BadSubstitution),
+                    }
+                }
+                if !vars::is_valid_name(&name) && name != "?" && name != "$" {
+                    return Err(ParseError::BadSubstitution);
+                }
+            }
+            Some(c @ ('?' | '$')) => {
+                chars.next();
+                name.push(c);
+            }
+            Some(c) if c == '_' || c.is_ascii_alphabetic() => {
+                while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+            }
+            // Not a reference: a lone `$` is literal
+            _ => {
+                self.in_word = true;
+                self.word.push('$');
+                return Ok(());
+            }
+        }
+
+        self.in_word = true;
+        let value = (self.lookup)(&name).unwrap_or_default();
+        if quoted {
+            self.quoted_at.get_or_insert(self.word.len());
+            for c in value.chars() {
+                push_literal(&mut self.word, c);
+            }
+        } else {
+            self.word.push_str(&value);
+        }
+        Ok(())
+    }
+}
+
+/// Tokenize `line`, resolving variable references with `lookup`
+pub fn tokenize_with(line: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Vec<Token>, ParseError> {
+    let mut lx = Lexer { tokens: Vec::new(), word: String::new(), in_word: false, quoted_at: None, lookup };
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => lx.finish_word(),
+            '\'' => {
+                lx.mark_quoted();
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(ch) => push_literal(&mut lx.word, ch),
+                        None => return Err(ParseError::UnterminatedQuote('\'')),
+                    }
+                }
+            }
+            '"' => {
+                lx.mark_quoted();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next_if(|&c| matches!(c, '$' | '"' | '\\' | '`')) {
+                            Some(escaped) => push_literal(&mut lx.word, escaped),
+                            None => push_literal(&mut lx.word, '\\'),
+                        },
+                        Some('$') => lx.expand_var(&mut chars, true)?,
+                        Some(ch) => push_literal(&mut lx.word, ch),
+                        None => return Err(ParseError::UnterminatedQuote('"')),
+                    }
+                }
+            }
+            '\\' => {
+                lx.mark_quoted();
+                // A trailing backslash stands for itself
+                push_literal(&mut lx.word, chars.next().unwrap_or('\\'));
+            }
+            '$' => lx.expand_var(&mut chars, false)?,
+            '>' => {
+                // A bare 1 or 2 right before the operator names the fd
+                let fd = match lx.word.as_str() {
+                    "1" | "2" if lx.quoted_at.is_none() => {
+                        let fd = if lx.word == "1" { 1 } else { 2 };
+                        lx.word.clear();
+                        lx.in_word = false;
+                        fd
+                    }
+                    _ => 1,
+                };
+                lx.finish_word();
+                let append = chars.next_if_eq(&'>').is_some();
+                lx.tokens.push(Token::Redirect { fd, append });
+            }
+            // A lone `&` is still an ordinary character
+            '&' if chars.next_if_eq(&'&').is_some() => {
+                lx.finish_word();
+                lx.tokens.push(Token::AndIf);
+            }
+            '|' => {
+                lx.finish_word();
+                let token = if chars.next_if_eq(&'|').is_some() { Token::OrIf } else { Token::Pipe };
+                lx.tokens.push(token);
+            }
+            ';' => {
+                lx.finish_word();
+                lx.tokens.push(Token::Semi);
+            }
+            c => {
+                lx.in_word = true;
+                lx.word.push(c);
+            }
+        }
+    }
+    lx.finish_word();
+    Ok(lx.tokens)
+}
+
+/// Tokenize `line` against the shell's variables
+pub fn tokenize(line: &str) -> Result<Vec<Token>, ParseError> {
+    tokenize_with(line, &vars::get)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn word(s: &str) -> Token {
+        Token::Word(String::from(s))
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some(String::from("/root")),
+            "STAR" => Some(String::from("*")),
+            "?" => Some(String::from("0")),
+            _ => None,
+        }
+    }
+
+    fn lex(line: &str) -> Result<Vec<Token>, ParseError> {
+        tokenize_with(line, &lookup)
+    }
+
+    #[test_case]
+    fn test_words_and_redirects() {
+        assert_eq!(lex("  ls   -a  ").unwrap(), vec![word("ls"), word("-a")]);
+        assert_eq!(
+            lex("cat a 2>>err >out").unwrap(),
+            vec![
+                word("cat"),
+                word("a"),
+                Token::Redirect { fd: 2, append: true },
+                word("err"),
+                Token::Redirect { fd: 1, append: false },
+                word("out"),
+            ]
+        );
+        assert_eq!(lex("echo a2>b").unwrap()[1], word("a2"));
+    }
+
+    #[test_case]
+    fn test_quotes_suppress_globbing() {
+        assert_eq!(lex("rm '*.txt' \"a b\" x*").unwrap(), vec![word("rm"), word("\\*.txt"), word("a b"), word("x*")]);
+        assert_eq!(lex("echo '' '>' \\>").unwrap(), vec![word("echo"), word(""), word(">"), word(">")]);
+        assert_eq!(lex("echo 'oops"), Err(ParseError::UnterminatedQuote('\'')));
+        assert_eq!(lex("echo \"oops"), Err(ParseError::UnterminatedQuote('"')));
+    }
+
+    #[test_case]
+    fn test_escapes() {
+        assert_eq!(lex("touch my\\ file \\*").unwrap(), vec![word("touch"), word("my file"), word("\\*")]);
+        assert_eq!(lex(r#"echo "a\"b" "c\d" 'e\f'"#).unwrap(), vec![word("echo"), word("a\"b"), word("c\\\\d"), word("e\\\\f")]);
+        assert_eq!(lex("echo \"\\2\">x").unwrap()[1], word("\\\\2"));
+    }
+
+    #[test_case]
+    fn test_variables() {
+        assert_eq!(lex("echo $HOME/x \"${HOME}y\" '$HOME' $? $NOPE.").unwrap(),
+            vec![word("echo"), word("/root/x"), word("/rooty"), word("$HOME"), word("0"), word(".")]);
+        // Quoted expansions never glob; unquoted ones may
+        assert_eq!(lex("echo \"$STAR\" $STAR $ \"$\"").unwrap(), vec![word("echo"), word("\\*"), word("*"), word("$"), word("$")]);
+        assert_eq!(lex("echo ${HOME"), Err(ParseError::BadSubstitution));
+        assert_eq!(lex("echo ${1x}"), Err(ParseError::BadSubstitution));
+    }
+
+    #[test_case]
+    fn test_assignments() {
+        assert_eq!(lex("A=1 B='x y'").unwrap(), vec![
+            Token::Assign(String::from("A"), String::from("1")),
+            Token::Assign(String::from("B"), String::from("x y")),
+        ]);
+        assert_eq!(lex("echo A=1").unwrap()[1], word("A=1"));
+        assert_eq!(lex("'A'=1").unwrap(), vec![word("A=1")]);
+        assert_eq!(lex("1A=x").unwrap(), vec![word("1A=x")]);
+        assert_eq!(lex(">A=1").unwrap()[1], word("A=1"));
+        assert_eq!(lex("true; A=1").unwrap()[2], Token::Assign(String::from("A"), String::from("1")));
+    }
+
+    #[test_case]
+    fn test_operators() {
+        assert_eq!(lex("a&&b || c;d").unwrap(),
+            vec![word("a"), Token::AndIf, word("b"), Token::OrIf, word("c"), Token::Semi, word("d")]);
+        assert_eq!(lex("echo a&b 'x;y' \\;").unwrap(), vec![word("echo"), word("a&b"), word("x;y"), word(";")]);
+        assert_eq!(lex("ls|A=1 wc '|'").unwrap(), vec![
+            word("ls"),
+            Token::Pipe,
+            Token::Assign(String::from("A"), String::from("1")),
+            word("wc"),
+            word("|"),
+        ]);
+    }
+}
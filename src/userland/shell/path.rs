@@ -0,0 +1,33 @@
+// $PATH search
+//
+// A name containing a `/` is taken as a path as-is; anything else is
+// looked up in each $PATH directory in turn. Only regular files with an
+// execute bit count.
+
+use alloc::format;
+use alloc::string::String;
+use crate::fs::vfs::VFS;
+use super::vars;
+
+fn is_executable(path: &str) -> bool {
+    VFS.lock()
+        .lookup_path(path)
+        .map_or(false, |node| node.is_file() && node.mode.0 & 0o111 != 0)
+}
+
+/// The file that running `name` as an external command would execute
+pub fn lookup(name: &str) -> Option<String> {
+    if name.is_empty() {
+        return None;
+    }
+    if name.contains('/') {
+        return is_executable(name).then(|| String::from(name));
+    }
+
+    let path = vars::get("PATH").unwrap_or_default();
+    path.split(':')
+        // An empty entry means the current directory
+        .map(|dir| if dir.is_empty() { "." } else { dir })
+        .map(|dir| format!("{}/{}", dir.trim_end_matches('/'), name))
+        .find(|candidate| is_executable(candidate))
+}
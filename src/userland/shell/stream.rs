@@ -0,0 +1,119 @@
+// Buffered file input for commands
+//
+// Files are read through the VFS a block at a time rather than copied into
+// memory whole, so commands can work through files larger than they want
+// to hold. Each refill takes the VFS lock briefly; none is held between
+// calls.
+//
+// `-` is the shell's standard input instead: what the command before it in
+// a pipeline writes, or lines typed at the console, up to ^D.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::fs::{FsError, FsResult};
+use crate::fs::vfs::api::{self as vfs_api, FileDescriptor, OpenFlags};
+use super::console::Console;
+
+const BLOCK_SIZE: usize = 4096;
+
+enum Source {
+    File(FileDescriptor),
+    /// The shell's descriptor 0, read as a pipe or file
+    Stdin,
+    /// The shell's descriptor 0 when it is the console; done after ^D
+    Console { done: bool },
+}
+
+pub struct FileReader {
+    source: Source,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+}
+
+impl FileReader {
+    /// Open `path` for reading, or standard input for `-`
+    pub fn open(path: &str) -> FsResult<Self> {
+        let source = if path == "-" {
+            match super::isatty(0) {
+                true => Source::Console { done: false },
+                false => Source::Stdin,
+            }
+        } else {
+            let fd = vfs_api::open(path, OpenFlags::O_RDONLY, 0)?;
+            if fd.mode.is_dir() {
+                return Err(FsError::IsDirectory);
+            }
+            Source::File(fd)
+        };
+        Ok(FileReader { source, buf: vec![0; BLOCK_SIZE], pos: 0, len: 0 })
+    }
+
+    /// Refill the buffer once it is used up; false at end of file
+    fn fill(&mut self) -> FsResult<bool> {
+        if self.pos == self.len {
+            self.len = match &mut self.source {
+                Source::File(fd) => vfs_api::read(fd, &mut self.buf)?,
+                Source::Stdin => {
+                    crate::kernel::sys::syscalls::read_fd(Some(super::shell_pid()), 0, &mut self.buf)
+                        .map_err(|_| FsError::IoError)?
+                }
+                Source::Console { done: true } => 0,
+                Source::Console { done } => match Console::default().read_line() {
+                    Some(line) => {
+                        self.buf = line.into_bytes();
+                        self.buf.push(b'\n');
+                        self.buf.len()
+                    }
+                    None => {
+                        *done = true;
+                        0
+                    }
+                },
+            };
+            self.pos = 0;
+        }
+        Ok(self.len > 0)
+    }
+
+    /// Read up to `out.len()` bytes, returning 0 at end of file
+    pub fn read(&mut self, out: &mut [u8]) -> FsResult<usize> {
+        if !self.fill()? {
+            return Ok(0);
+        }
+        let n = out.len().min(self.len - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+    pub fn next_byte(&mut self) -> FsResult<Option<u8>> {
+        if !self.fill()? {
+            return Ok(None);
+        }
+        self.pos += 1;
+        Ok(Some(self.buf[self.pos - 1]))
+    }
+
+    /// Append the next line, including its `\n` if it has one, to `line`.
+    /// Returns false at end of file.
+    pub fn read_line(&mut self, line: &mut Vec<u8>) -> FsResult<bool> {
+        let mut read_any = false;
+        while self.fill()? {
+            read_any = true;
+            let chunk = &self.buf[self.pos..self.len];
+            match chunk.iter().position(|&b| b == b'\n') {
+                Some(end) => {
+                    line.extend_from_slice(&chunk[..=end]);
+                    self.pos += end + 1;
+                    return Ok(true);
+                }
+                None => {
+                    line.extend_from_slice(chunk);
+                    self.pos = self.len;
+                }
+            }
+        }
+        Ok(read_any)
+    }
+}
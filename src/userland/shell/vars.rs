@@ -0,0 +1,50 @@
+// Shell variables
+//
+// Set with `NAME=value` on a line of its own and expanded by the tokenizer.
+// `$?` (last exit status), `$$` (shell pid) and `$PWD` are computed.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use spin::Mutex;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref VARS: Mutex<BTreeMap<String, String>> = {
+        let mut vars = BTreeMap::new();
+        for (name, value) in [("HOME", "/root"), ("PATH", "/bin:/usr/bin"), ("SHELL", "/bin/sh"), ("USER", "root")] {
+            vars.insert(String::from(name), String::from(value));
+        }
+        Mutex::new(vars)
+    };
+}
+
+/// Whether `name` can be used as a variable name
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+pub fn get(name: &str) -> Option<String> {
+    match name {
+        "?" => Some(super::last_status().to_string()),
+        "$" => Some(super::shell_pid().to_string()),
+        "PWD" => Some(String::from(crate::fs::vfs::VFS.lock().get_cwd())),
+        _ => VARS.lock().get(name).cloned(),
+    }
+}
+
+pub fn set(name: &str, value: &str) {
+    VARS.lock().insert(String::from(name), String::from(value));
+}
+
+/// All variables, sorted by name
+pub fn snapshot() -> BTreeMap<String, String> {
+    VARS.lock().clone()
+}
+
+/// Put `vars` in place of every variable, returning the ones there were,
+/// for a login session to run with its own
+pub fn replace(vars: BTreeMap<String, String>) -> BTreeMap<String, String> {
+    core::mem::replace(&mut *VARS.lock(), vars)
+}
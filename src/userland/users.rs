@@ -0,0 +1,221 @@
+// User and group names
+//
+// Accounts are the lines of /etc/passwd (`name:password:uid:gid:gecos:
+// home:shell`) and groups those of /etc/group (`name:password:gid:
+// members`). Commands that take or show a user or group go through here,
+// so a name works wherever an id does and an id without an entry shows as
+// its number. root is uid and gid 0 whether or not the files list it.
+//
+// The password field of an account is the SHA-256 of its password in hex,
+// or empty for none; anything else, such as `x` or `*`, matches no
+// password and so locks the account.
+//
+// An account's home directory is made the first time it logs in, as a
+// copy of /etc/skel owned by the account, which is where a default
+// .profile for new users goes.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::fs::vfs::api as vfs_api;
+use crate::fs::{FileType, FsResult};
+use crate::qsf::crypto;
+
+pub const PASSWD: &str = "/etc/passwd";
+pub const GROUP: &str = "/etc/group";
+pub const SKEL: &str = "/etc/skel";
+/// Mode of a home directory made at login
+const HOME_MODE: u16 = 0o700;
+
+/// The names and ids in a passwd or group file: the first and third
+/// fields of each line, skipping lines that have no number there
+pub fn entries(text: &str) -> Vec<(String, u32)> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next().filter(|name| !name.is_empty())?;
+            let id = fields.nth(1)?.trim().parse().ok()?;
+            Some((name.to_string(), id))
+        })
+        .collect()
+}
+
+fn read(path: &str) -> Vec<(String, u32)> {
+    crate::fs::vfs::api::read_file(path)
+        .ok()
+        .and_then(|data| String::from_utf8(data).ok())
+        .map_or_else(Vec::new, |text| entries(&text))
+}
+
+fn id_of(path: &str, name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    if name == "root" {
+        return Some(0);
+    }
+    read(path).into_iter().find(|(entry, _)| entry == name).map(|(_, id)| id)
+}
+
+fn name_of(path: &str, id: u32) -> String {
+    match read(path).into_iter().find(|&(_, entry)| entry == id) {
+        Some((name, _)) => name,
+        None if id == 0 => String::from("root"),
+        None => id.to_string(),
+    }
+}
+
+/// The uid named or numbered `name`
+pub fn uid(name: &str) -> Option<u32> {
+    id_of(PASSWD, name)
+}
+
+/// The gid named or numbered `name`
+pub fn gid(name: &str) -> Option<u32> {
+    id_of(GROUP, name)
+}
+
+/// The user name of `uid`, or the number if it has none
+pub fn user_name(uid: u32) -> String {
+    name_of(PASSWD, uid)
+}
+
+/// The group name of `gid`, or the number if it has none
+pub fn group_name(gid: u32) -> String {
+    name_of(GROUP, gid)
+}
+
+/// An account to log in as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub name: String,
+    pub password: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub home: String,
+}
+
+impl Account {
+    /// Whether `password` is the account's
+    pub fn check_password(&self, password: &str) -> bool {
+        if self.password.is_empty() {
+            return true;
+        }
+        crypto::from_hex(&self.password).is_some_and(|hash| crypto::digest_eq(&crypto::sha256(password.as_bytes()), &hash))
+    }
+}
+
+/// The account on one line of a passwd file
+pub fn parse_account(line: &str) -> Option<Account> {
+    let fields: Vec<&str> = line.split(':').collect();
+    match fields[..] {
+        [name, password, uid, gid, _, home, ..] if !name.is_empty() && !line.starts_with('#') => Some(Account {
+            name: name.to_string(),
+            password: password.to_string(),
+            uid: uid.trim().parse().ok()?,
+            gid: gid.trim().parse().ok()?,
+            home: home.to_string(),
+        }),
+        _ => None,
+    }
+}
+
+/// The account `name`, from /etc/passwd; root has one without a password
+/// until the file gives it another
+pub fn account(name: &str) -> Option<Account> {
+    let text = crate::fs::vfs::api::read_file(PASSWD).ok().and_then(|data| String::from_utf8(data).ok());
+    let found = text.as_deref().unwrap_or("").lines().filter_map(parse_account).find(|account| account.name == name);
+    match found {
+        None if name == "root" => Some(Account {
+            name: name.to_string(),
+            password: String::new(),
+            uid: 0,
+            gid: 0,
+            home: String::from("/root"),
+        }),
+        found => found,
+    }
+}
+
+/// Make the home directory of `account` from /etc/skel if it has none
+/// yet, returning whether it did
+pub fn create_home(account: &Account) -> FsResult<bool> {
+    if vfs_api::stat(&account.home).is_ok() {
+        return Ok(false);
+    }
+    vfs_api::mkdir(&account.home, HOME_MODE)?;
+    vfs_api::chown(&account.home, account.uid, account.gid)?;
+    if vfs_api::stat(SKEL).is_ok_and(|stat| stat.mode.file_type() == FileType::Directory) {
+        copy_tree(SKEL, &account.home, account.uid, account.gid)?;
+    }
+    Ok(true)
+}
+
+/// Copy what is in the directory `from` into the directory `to`, with the
+/// same modes but owned by `uid` and `gid`
+fn copy_tree(from: &str, to: &str, uid: u32, gid: u32) -> FsResult<()> {
+    for entry in vfs_api::readdir(from)?.into_iter().filter(|e| e.name != "." && e.name != "..") {
+        let (source, target) = (format!("{}/{}", from, entry.name), format!("{}/{}", to, entry.name));
+        let perms = vfs_api::stat(&source)?.mode.0 & 0o7777;
+        match entry.file_type {
+            FileType::Directory => {
+                vfs_api::mkdir(&target, perms)?;
+                copy_tree(&source, &target, uid, gid)?;
+            }
+            FileType::Regular => vfs_api::write_file(&target, &vfs_api::read_file(&source)?, perms)?,
+            FileType::Symlink => vfs_api::symlink(&vfs_api::readlink(&source)?, &target)?,
+            // Devices, pipes and sockets have no place in a home
+            _ => continue,
+        }
+        if entry.file_type != FileType::Symlink {
+            vfs_api::chmod(&target, perms)?;
+        }
+        vfs_api::chown(&target, uid, gid)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test_case]
+    fn test_entries() {
+        let passwd = "root:x:0:0:root:/root:/bin/sh\n# comment\nalice:x:1000:1000::/home/alice:/bin/sh\nbroken:x\n:x:5:5\n";
+        assert_eq!(entries(passwd), [("root".to_string(), 0), ("alice".to_string(), 1000)]);
+        assert_eq!(entries("staff:x:50:alice,bob"), [("staff".to_string(), 50)]);
+    }
+
+    #[test_case]
+    fn test_accounts() {
+        let hash = crypto::to_hex(&crypto::sha256(b"secret"));
+        let alice = parse_account(&format!("alice:{}:1000:100::/home/alice:/bin/sh", hash)).unwrap();
+        assert_eq!((alice.uid, alice.gid, alice.home.as_str()), (1000, 100, "/home/alice"));
+        assert!(alice.check_password("secret") && !alice.check_password("Secret"));
+        assert!(!parse_account("bob:x:1001:100::/home/bob:/bin/sh").unwrap().check_password(""));
+        assert!(parse_account("carol::1002:100::/:/bin/sh").unwrap().check_password(""));
+        assert_eq!(parse_account("broken:x:1003"), None);
+    }
+
+    #[test_case]
+    fn test_home_from_skel() {
+        vfs_api::mkdir("/tmp/skel", 0o755).unwrap();
+        vfs_api::mkdir("/tmp/skel/bin", 0o750).unwrap();
+        vfs_api::write_file("/tmp/skel/.profile", b"PATH=$HOME/bin:$PATH\n", 0o644).unwrap();
+        vfs_api::symlink(".profile", "/tmp/skel/.bashrc").unwrap();
+        vfs_api::mkdir("/tmp/alice", HOME_MODE).unwrap();
+        copy_tree("/tmp/skel", "/tmp/alice", 1000, 100).unwrap();
+
+        let profile = vfs_api::stat("/tmp/alice/.profile").unwrap();
+        assert_eq!((profile.uid, profile.gid, profile.mode.0 & 0o7777), (1000, 100, 0o644));
+        assert_eq!(vfs_api::read_file("/tmp/alice/.profile").unwrap(), b"PATH=$HOME/bin:$PATH\n");
+        let bin = vfs_api::stat("/tmp/alice/bin").unwrap();
+        assert_eq!((bin.mode.file_type(), bin.mode.0 & 0o7777, bin.uid), (FileType::Directory, 0o750, 1000));
+        assert_eq!(vfs_api::readlink("/tmp/alice/.bashrc").unwrap(), ".profile");
+
+        let alice = Account { home: String::from("/tmp/alice"), ..parse_account("alice::1000:100::/:/bin/sh").unwrap() };
+        assert_eq!(create_home(&alice), Ok(false));
+    }
+}